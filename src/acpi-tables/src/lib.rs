@@ -11,6 +11,7 @@ pub mod dsdt;
 pub mod fadt;
 pub mod madt;
 pub mod rsdp;
+pub mod tpm2;
 pub mod xsdt;
 
 pub use aml::Aml;
@@ -18,6 +19,7 @@ pub use dsdt::Dsdt;
 pub use fadt::Fadt;
 pub use madt::Madt;
 pub use rsdp::Rsdp;
+pub use tpm2::Tpm2;
 pub use xsdt::Xsdt;
 use zerocopy::little_endian::{U32, U64};
 use zerocopy::AsBytes;
@@ -10,6 +10,7 @@ use std::sync::{Arc, Mutex};
 
 use vmm::builder::{build_microvm_for_boot, StartMicrovmError};
 use vmm::cpu_config::templates::{CustomCpuTemplate, Numeric};
+use vmm::landlock::set_landlock;
 use vmm::resources::VmResources;
 use vmm::seccomp_filters::get_empty_filters;
 use vmm::vmm_config::instance_info::{InstanceInfo, VmState};
@@ -125,11 +126,13 @@ pub fn build_microvm_from_config(
     }
     let mut event_manager = EventManager::new().unwrap();
     let seccomp_filters = get_empty_filters();
+    // The microVM is built on the main thread, which writes the output files afterwards.
+    set_landlock(false);
 
     // Build a microVM.
     let vmm = build_microvm_for_boot(
         &instance_info,
-        &vm_resources,
+        &mut vm_resources,
         &mut event_manager,
         &seccomp_filters,
     )?;
@@ -0,0 +1,411 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Provides a remote device backend emulating a virtio console which echoes
+//! back to the guest everything the guest writes to it.
+//!
+//! The backend listens on the Unix domain socket given as argument and serves
+//! a single Firecracker instance. It is meant to be attached as a remote device
+//! of type 3 (console) with 2 queues and a 12 bytes config space.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+
+use utils::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
+use utils::u64_to_usize;
+use vhost::vhost_user::message::{
+    VhostTransferStateDirection, VhostTransferStatePhase, VhostUserConfigFlags, VhostUserInflight,
+    VhostUserLog, VhostUserMemoryRegion, VhostUserSingleMemoryRegion, VhostUserVringAddrFlags,
+    VhostUserVringState,
+};
+use vhost::vhost_user::{
+    BackendListener, Error, Listener, Result, VhostUserBackendReqHandlerMut,
+    VhostUserProtocolFeatures, VhostUserVirtioFeatures,
+};
+use vmm::devices::virtio::queue::Queue;
+use vmm::vstate::memory::{Bytes, FileOffset, GuestAddress, GuestMemoryExtension, GuestMemoryMmap};
+
+/// Queue the backend puts the echoed data in.
+const RX_QUEUE: usize = 0;
+/// Queue the guest writes data to.
+const TX_QUEUE: usize = 1;
+const NUM_QUEUES: usize = 2;
+const QUEUE_SIZE: u16 = 256;
+/// Epoll data of the vhost-user socket. The kick events use their queue index.
+const SOCKET_EVENT: u64 = NUM_QUEUES as u64;
+/// Size of `struct virtio_console_config`.
+const CONFIG_SIZE: usize = 12;
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+#[derive(Debug)]
+struct Vring {
+    queue: Queue,
+    kick: Option<File>,
+    call: Option<File>,
+    enabled: bool,
+}
+
+/// Memory region of the frontend, used to translate the queue addresses.
+#[derive(Debug)]
+struct MemoryRegion {
+    guest_phys_addr: u64,
+    memory_size: u64,
+    user_addr: u64,
+}
+
+#[derive(Debug)]
+struct EchoBackend {
+    epoll: Arc<Epoll>,
+    acked_features: u64,
+    regions: Vec<MemoryRegion>,
+    mem: Option<GuestMemoryMmap>,
+    vrings: Vec<Vring>,
+    config: [u8; CONFIG_SIZE],
+    // Data written by the guest which doesn't fit in the RX buffers yet.
+    pending: Vec<u8>,
+}
+
+impl EchoBackend {
+    fn new(epoll: Arc<Epoll>) -> Self {
+        let vrings = (0..NUM_QUEUES)
+            .map(|_| Vring {
+                queue: Queue::new(QUEUE_SIZE),
+                kick: None,
+                call: None,
+                enabled: false,
+            })
+            .collect();
+        Self {
+            epoll,
+            acked_features: 0,
+            regions: vec![],
+            mem: None,
+            vrings,
+            config: [0; CONFIG_SIZE],
+            pending: vec![],
+        }
+    }
+
+    fn vring(&mut self, index: u32) -> Result<&mut Vring> {
+        self.vrings
+            .get_mut(u64_to_usize(u64::from(index)))
+            .ok_or(Error::InvalidParam)
+    }
+
+    /// Translates an address in the frontend address space to a guest physical address.
+    fn translate(&self, addr: u64) -> Result<GuestAddress> {
+        self.regions
+            .iter()
+            .find(|region| region.user_addr <= addr && addr - region.user_addr < region.memory_size)
+            .map(|region| GuestAddress(region.guest_phys_addr + (addr - region.user_addr)))
+            .ok_or(Error::InvalidParam)
+    }
+
+    /// Moves the data of the TX queue to the RX queue.
+    fn process_queues(&mut self) {
+        let Self {
+            mem,
+            vrings,
+            pending,
+            ..
+        } = self;
+        let Some(mem) = mem.as_ref() else {
+            return;
+        };
+
+        let tx = &mut vrings[TX_QUEUE];
+        if tx.enabled {
+            while let Some(head) = tx.queue.pop(mem) {
+                let index = head.index;
+                let mut desc = Some(head);
+                while let Some(d) = desc {
+                    if !d.is_write_only() {
+                        let mut buf = vec![0u8; u64_to_usize(u64::from(d.len))];
+                        mem.read_slice(&mut buf, d.addr)
+                            .expect("Invalid TX descriptor");
+                        pending.extend(buf);
+                    }
+                    desc = d.next_descriptor();
+                }
+                tx.queue.add_used(mem, index, 0).unwrap();
+            }
+            signal(tx);
+        }
+
+        let rx = &mut vrings[RX_QUEUE];
+        if rx.enabled && !pending.is_empty() {
+            while !pending.is_empty() {
+                let Some(head) = rx.queue.pop(mem) else {
+                    break;
+                };
+                let index = head.index;
+                let mut written = 0;
+                let mut desc = Some(head);
+                while let Some(d) = desc {
+                    if d.is_write_only() {
+                        let len = pending.len().min(u64_to_usize(u64::from(d.len)));
+                        let data: Vec<u8> = pending.drain(..len).collect();
+                        mem.write_slice(&data, d.addr)
+                            .expect("Invalid RX descriptor");
+                        written += len;
+                    }
+                    desc = d.next_descriptor();
+                }
+                rx.queue
+                    .add_used(mem, index, u32::try_from(written).unwrap())
+                    .unwrap();
+            }
+            signal(rx);
+        }
+    }
+}
+
+fn signal(vring: &mut Vring) {
+    if let Some(call) = vring.call.as_mut() {
+        call.write_all(&1u64.to_ne_bytes())
+            .expect("Failed to signal the guest");
+    }
+}
+
+impl VhostUserBackendReqHandlerMut for EchoBackend {
+    fn set_owner(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn reset_owner(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_features(&mut self) -> Result<u64> {
+        Ok(VIRTIO_F_VERSION_1 | VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits())
+    }
+
+    fn set_features(&mut self, features: u64) -> Result<()> {
+        self.acked_features = features;
+        Ok(())
+    }
+
+    fn set_mem_table(&mut self, ctx: &[VhostUserMemoryRegion], files: Vec<File>) -> Result<()> {
+        let regions = ctx
+            .iter()
+            .zip(files)
+            .map(|(region, file)| {
+                (
+                    FileOffset::new(file, region.mmap_offset),
+                    GuestAddress(region.guest_phys_addr),
+                    u64_to_usize(region.memory_size),
+                )
+            })
+            .collect();
+        let mem = GuestMemoryMmap::from_raw_regions_file(regions, false, true)
+            .map_err(|_| Error::InvalidParam)?;
+        self.regions = ctx
+            .iter()
+            .map(|region| MemoryRegion {
+                guest_phys_addr: region.guest_phys_addr,
+                memory_size: region.memory_size,
+                user_addr: region.user_addr,
+            })
+            .collect();
+        self.mem = Some(mem);
+        Ok(())
+    }
+
+    fn set_vring_num(&mut self, index: u32, num: u32) -> Result<()> {
+        self.vring(index)?.queue.size = u16::try_from(num).map_err(|_| Error::InvalidParam)?;
+        Ok(())
+    }
+
+    fn set_vring_addr(
+        &mut self,
+        index: u32,
+        _flags: VhostUserVringAddrFlags,
+        descriptor: u64,
+        used: u64,
+        available: u64,
+        _log: u64,
+    ) -> Result<()> {
+        let desc_table = self.translate(descriptor)?;
+        let used_ring = self.translate(used)?;
+        let avail_ring = self.translate(available)?;
+        let queue = &mut self.vring(index)?.queue;
+        queue.desc_table = desc_table;
+        queue.used_ring = used_ring;
+        queue.avail_ring = avail_ring;
+        Ok(())
+    }
+
+    fn set_vring_base(&mut self, index: u32, base: u32) -> Result<()> {
+        // The queues are only ever started from scratch.
+        self.vring(index)?;
+        if base != 0 {
+            return Err(Error::InvalidParam);
+        }
+        Ok(())
+    }
+
+    fn get_vring_base(&mut self, index: u32) -> Result<VhostUserVringState> {
+        self.vring(index)?.enabled = false;
+        Ok(VhostUserVringState::new(index, 0))
+    }
+
+    fn set_vring_kick(&mut self, index: u8, fd: Option<File>) -> Result<()> {
+        // Polling the queues is not supported.
+        let fd = fd.ok_or(Error::InvalidParam)?;
+        self.epoll
+            .ctl(
+                ControlOperation::Add,
+                fd.as_raw_fd(),
+                EpollEvent::new(EventSet::IN, u64::from(index)),
+            )
+            .map_err(Error::ReqHandlerError)?;
+        self.vring(u32::from(index))?.kick = Some(fd);
+        Ok(())
+    }
+
+    fn set_vring_call(&mut self, index: u8, fd: Option<File>) -> Result<()> {
+        self.vring(u32::from(index))?.call = fd;
+        Ok(())
+    }
+
+    fn set_vring_err(&mut self, _index: u8, _fd: Option<File>) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_protocol_features(&mut self) -> Result<VhostUserProtocolFeatures> {
+        Ok(VhostUserProtocolFeatures::CONFIG)
+    }
+
+    fn set_protocol_features(&mut self, _features: u64) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_queue_num(&mut self) -> Result<u64> {
+        Ok(NUM_QUEUES as u64)
+    }
+
+    fn set_vring_enable(&mut self, index: u32, enable: bool) -> Result<()> {
+        let vring = self.vring(index)?;
+        vring.enabled = enable;
+        vring.queue.ready = enable;
+        // The guest may have written data before the queues were enabled.
+        self.process_queues();
+        Ok(())
+    }
+
+    fn get_config(
+        &mut self,
+        offset: u32,
+        size: u32,
+        _flags: VhostUserConfigFlags,
+    ) -> Result<Vec<u8>> {
+        let start = u64_to_usize(u64::from(offset));
+        let end = start + u64_to_usize(u64::from(size));
+        self.config
+            .get(start..end)
+            .map(<[u8]>::to_vec)
+            .ok_or(Error::InvalidParam)
+    }
+
+    fn set_config(&mut self, offset: u32, buf: &[u8], _flags: VhostUserConfigFlags) -> Result<()> {
+        let start = u64_to_usize(u64::from(offset));
+        self.config
+            .get_mut(start..start + buf.len())
+            .ok_or(Error::InvalidParam)?
+            .copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn get_inflight_fd(
+        &mut self,
+        _inflight: &VhostUserInflight,
+    ) -> Result<(VhostUserInflight, File)> {
+        Err(Error::InvalidOperation("get_inflight_fd"))
+    }
+
+    fn set_inflight_fd(&mut self, _inflight: &VhostUserInflight, _file: File) -> Result<()> {
+        Err(Error::InvalidOperation("set_inflight_fd"))
+    }
+
+    fn get_max_mem_slots(&mut self) -> Result<u64> {
+        Err(Error::InvalidOperation("get_max_mem_slots"))
+    }
+
+    fn add_mem_region(&mut self, _region: &VhostUserSingleMemoryRegion, _fd: File) -> Result<()> {
+        Err(Error::InvalidOperation("add_mem_region"))
+    }
+
+    fn remove_mem_region(&mut self, _region: &VhostUserSingleMemoryRegion) -> Result<()> {
+        Err(Error::InvalidOperation("remove_mem_region"))
+    }
+
+    fn set_device_state_fd(
+        &mut self,
+        _direction: VhostTransferStateDirection,
+        _phase: VhostTransferStatePhase,
+        _fd: File,
+    ) -> Result<Option<File>> {
+        Err(Error::InvalidOperation("set_device_state_fd"))
+    }
+
+    fn check_device_state(&mut self) -> Result<()> {
+        Err(Error::InvalidOperation("check_device_state"))
+    }
+
+    fn set_log_base(&mut self, _log: &VhostUserLog, _file: File) -> Result<()> {
+        Err(Error::InvalidOperation("set_log_base"))
+    }
+}
+
+fn main() {
+    let socket_path = std::env::args().nth(1).expect("No socket path given");
+
+    let epoll = Arc::new(Epoll::new().expect("Cannot create epoll"));
+    let backend = Arc::new(Mutex::new(EchoBackend::new(epoll.clone())));
+
+    let listener = Listener::new(socket_path, true).expect("Cannot bind to socket path");
+    let mut listener = BackendListener::new(listener, backend.clone()).unwrap();
+    let mut handler = loop {
+        if let Some(handler) = listener.accept().expect("Cannot accept connection") {
+            break handler;
+        }
+    };
+    epoll
+        .ctl(
+            ControlOperation::Add,
+            handler.as_raw_fd(),
+            EpollEvent::new(EventSet::IN, SOCKET_EVENT),
+        )
+        .expect("Cannot register the vhost-user socket");
+
+    let mut events = vec![EpollEvent::default(); NUM_QUEUES + 1];
+    loop {
+        let num_events = match epoll.wait(-1, &mut events) {
+            Ok(num_events) => num_events,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => panic!("Failed to wait for events: {err}"),
+        };
+
+        for event in &events[..num_events] {
+            if event.data() == SOCKET_EVENT {
+                match handler.handle_request() {
+                    Ok(()) => (),
+                    // Firecracker exited.
+                    Err(Error::Disconnected | Error::PartialMessage) => return,
+                    Err(err) => panic!("Failed to handle vhost-user request: {err}"),
+                }
+            } else {
+                let mut backend = backend.lock().unwrap();
+                let index = u64_to_usize(event.data());
+                if let Some(kick) = backend.vrings[index].kick.as_mut() {
+                    let mut buf = [0u8; 8];
+                    kick.read_exact(&mut buf)
+                        .expect("Failed to read kick event");
+                }
+                backend.process_queues();
+            }
+        }
+    }
+}
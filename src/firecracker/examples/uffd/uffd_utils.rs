@@ -4,11 +4,15 @@
 // Not everything is used by both binaries
 #![allow(dead_code)]
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
 use std::os::unix::net::UnixStream;
 use std::ptr;
+use std::rc::Rc;
 
 use serde::{Deserialize, Serialize};
 use userfaultfd::{Error, Event, Uffd};
@@ -34,6 +38,167 @@ pub struct GuestRegionUffdMapping {
     pub page_size_kib: usize,
 }
 
+/// Source of the guest memory contents served on page faults.
+pub trait PageSource: std::fmt::Debug {
+    /// Size in bytes of the guest memory contents.
+    fn size(&self) -> usize;
+
+    /// Returns `len` bytes of guest memory contents, starting at `offset`.
+    fn read_pages(&self, offset: u64, len: usize) -> io::Result<Cow<'_, [u8]>>;
+}
+
+/// Serves pages from a memory file mapped in the handler address space.
+#[derive(Debug)]
+pub struct FilePageSource {
+    _file: File,
+    memory: *const u8,
+    size: usize,
+}
+
+impl FilePageSource {
+    pub fn new(file: File) -> Self {
+        let size = file
+            .metadata()
+            .expect("can not get backing file metadata")
+            .len() as usize;
+        // # Safety:
+        // File size and fd are valid
+        let ret = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ret == libc::MAP_FAILED {
+            panic!("mmap on backing file failed");
+        }
+
+        Self {
+            _file: file,
+            memory: ret.cast(),
+            size,
+        }
+    }
+}
+
+impl PageSource for FilePageSource {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn read_pages(&self, offset: u64, len: usize) -> io::Result<Cow<'_, [u8]>> {
+        let offset = usize::try_from(offset)
+            .ok()
+            .filter(|offset| offset.checked_add(len).is_some_and(|end| end <= self.size))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "range outside of memory file")
+            })?;
+        // # Safety:
+        // The range was checked against the size of the mapping.
+        Ok(Cow::Borrowed(unsafe {
+            std::slice::from_raw_parts(self.memory.add(offset), len)
+        }))
+    }
+}
+
+/// Fetches pages with HTTP range requests, e.g. from an object store.
+///
+/// This is a minimal HTTP/1.1 client: every fetch opens a new connection, which the server
+/// closes after sending a `206 Partial Content` response.
+#[derive(Debug)]
+pub struct HttpPageSource {
+    addr: String,
+    host: String,
+    path: String,
+    size: usize,
+}
+
+impl HttpPageSource {
+    /// Creates a source for an `http://host[:port]/path` URL.
+    pub fn new(url: &str) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| invalid("only http:// URLs are supported"))?;
+        let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let mut source = Self {
+            addr: if host.contains(':') {
+                host.to_string()
+            } else {
+                format!("{host}:80")
+            },
+            host: host.to_string(),
+            path: if path.is_empty() { "/" } else { path }.to_string(),
+            size: 0,
+        };
+
+        // The total size is reported in the `Content-Range` header of every range response.
+        let (headers, _) = source.fetch(0, 1)?;
+        source.size = http_header(&headers, "content-range")
+            .and_then(|range| range.rsplit('/').next())
+            .and_then(|size| size.parse().ok())
+            .ok_or_else(|| invalid("missing total size in Content-Range header"))?;
+        Ok(source)
+    }
+
+    fn fetch(&self, offset: u64, len: usize) -> io::Result<(String, Vec<u8>)> {
+        let mut stream = TcpStream::connect(&self.addr)?;
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: {}\r\nRange: bytes={}-{}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.host,
+            offset,
+            offset + len as u64 - 1
+        )?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+
+        let header_end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response"))?;
+        let headers = String::from_utf8_lossy(&response[..header_end]).into_owned();
+        if headers.split(' ').nth(1) != Some("206") {
+            return Err(io::Error::other(format!(
+                "unexpected HTTP response: {}",
+                headers.lines().next().unwrap_or_default()
+            )));
+        }
+        let body = response.split_off(header_end + 4);
+        if body.len() != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short HTTP range response",
+            ));
+        }
+        Ok((headers, body))
+    }
+}
+
+impl PageSource for HttpPageSource {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn read_pages(&self, offset: u64, len: usize) -> io::Result<Cow<'_, [u8]>> {
+        self.fetch(offset, len).map(|(_, body)| Cow::Owned(body))
+    }
+}
+
+fn http_header<'a>(headers: &'a str, name: &str) -> Option<&'a str> {
+    headers.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then_some(value.trim())
+    })
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum MemPageState {
     Uninitialized,
@@ -52,12 +217,12 @@ pub struct MemRegion {
 pub struct UffdHandler {
     pub mem_regions: Vec<MemRegion>,
     pub page_size: usize,
-    backing_buffer: *const u8,
+    source: Rc<dyn PageSource>,
     uffd: Uffd,
 }
 
 impl UffdHandler {
-    pub fn from_unix_stream(stream: &UnixStream, backing_buffer: *const u8, size: usize) -> Self {
+    pub fn from_unix_stream(stream: &UnixStream, source: Rc<dyn PageSource>) -> Self {
         let mut message_buf = vec![0u8; 1024];
         let (bytes_read, file) = stream
             .recv_with_fd(&mut message_buf[..])
@@ -74,7 +239,7 @@ impl UffdHandler {
         let page_size = mappings.first().unwrap().page_size_kib;
 
         // Make sure memory size matches backing data size.
-        assert_eq!(memsize, size);
+        assert_eq!(memsize, source.size());
         assert!(page_size.is_power_of_two());
 
         let uffd = unsafe { Uffd::from_raw_fd(file.into_raw_fd()) };
@@ -84,7 +249,7 @@ impl UffdHandler {
         Self {
             mem_regions,
             page_size,
-            backing_buffer,
+            source,
             uffd,
         }
     }
@@ -140,12 +305,15 @@ impl UffdHandler {
     }
 
     fn populate_from_file(&self, region: &MemRegion, dst: u64, len: usize) -> (u64, u64) {
-        let offset = dst - region.mapping.base_host_virt_addr;
-        let src = self.backing_buffer as u64 + region.mapping.offset + offset;
+        let offset = region.mapping.offset + dst - region.mapping.base_host_virt_addr;
+        let pages = self
+            .source
+            .read_pages(offset, len)
+            .expect("Cannot read guest memory contents");
 
         let ret = unsafe {
             self.uffd
-                .copy(src as *const _, dst as *mut _, len, true)
+                .copy(pages.as_ptr().cast(), dst as *mut _, len, true)
                 .expect("Uffd copy failed")
         };
 
@@ -171,39 +339,19 @@ impl UffdHandler {
 #[derive(Debug)]
 pub struct Runtime {
     stream: UnixStream,
-    backing_file: File,
-    backing_memory: *mut u8,
-    backing_memory_size: usize,
+    source: Rc<dyn PageSource>,
     uffds: HashMap<i32, UffdHandler>,
 }
 
 impl Runtime {
     pub fn new(stream: UnixStream, backing_file: File) -> Self {
-        let file_meta = backing_file
-            .metadata()
-            .expect("can not get backing file metadata");
-        let backing_memory_size = file_meta.len() as usize;
-        // # Safety:
-        // File size and fd are valid
-        let ret = unsafe {
-            libc::mmap(
-                ptr::null_mut(),
-                backing_memory_size,
-                libc::PROT_READ,
-                libc::MAP_PRIVATE,
-                backing_file.as_raw_fd(),
-                0,
-            )
-        };
-        if ret == libc::MAP_FAILED {
-            panic!("mmap on backing file failed");
-        }
+        Self::with_page_source(stream, Rc::new(FilePageSource::new(backing_file)))
+    }
 
+    pub fn with_page_source(stream: UnixStream, source: Rc<dyn PageSource>) -> Self {
         Self {
             stream,
-            backing_file,
-            backing_memory: ret.cast(),
-            backing_memory_size,
+            source,
             uffds: HashMap::default(),
         }
     }
@@ -246,11 +394,8 @@ impl Runtime {
                     nready -= 1;
                     if pollfds[i].fd == self.stream.as_raw_fd() {
                         // Handle new uffd from stream
-                        let handler = UffdHandler::from_unix_stream(
-                            &self.stream,
-                            self.backing_memory,
-                            self.backing_memory_size,
-                        );
+                        let handler =
+                            UffdHandler::from_unix_stream(&self.stream, self.source.clone());
                         pollfds.push(libc::pollfd {
                             fd: handler.uffd.as_raw_fd(),
                             events: libc::POLLIN,
@@ -296,7 +441,9 @@ fn create_mem_regions(mappings: &Vec<GuestRegionUffdMapping>, page_size: usize)
 
 #[cfg(test)]
 mod tests {
+    use std::io::{BufRead, BufReader};
     use std::mem::MaybeUninit;
+    use std::net::TcpListener;
     use std::os::unix::net::UnixListener;
 
     use utils::tempdir::TempDir;
@@ -306,6 +453,83 @@ mod tests {
 
     unsafe impl Send for Runtime {}
 
+    #[test]
+    fn test_file_page_source() {
+        let tmp_file = TempFile::new().unwrap();
+        let contents: Vec<u8> = (0..0x2000).map(|i| (i % 251) as u8).collect();
+        tmp_file.as_file().write_all(&contents).unwrap();
+
+        let source = FilePageSource::new(File::open(tmp_file.as_path()).unwrap());
+        assert_eq!(source.size(), 0x2000);
+        assert_eq!(
+            &*source.read_pages(0x1000, 0x1000).unwrap(),
+            &contents[0x1000..]
+        );
+        source.read_pages(0x1000, 0x1001).unwrap_err();
+        source.read_pages(u64::MAX, 1).unwrap_err();
+    }
+
+    /// Serves range requests for `contents` on `connections` successive connections.
+    fn http_range_server(contents: Vec<u8>, connections: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/snapshot/mem", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let mut stream = stream.unwrap();
+                let mut range = None;
+                for line in BufReader::new(&stream).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(bytes) = line.strip_prefix("Range: bytes=") {
+                        let (start, end) = bytes.split_once('-').unwrap();
+                        range = Some((
+                            start.parse::<usize>().unwrap(),
+                            end.parse::<usize>().unwrap(),
+                        ));
+                    }
+                }
+                let (start, end) = range.unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    start,
+                    end,
+                    contents.len()
+                )
+                .unwrap();
+                stream.write_all(&contents[start..=end]).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_http_page_source() {
+        let contents: Vec<u8> = (0..0x2000).map(|i| (i % 251) as u8).collect();
+        let url = http_range_server(contents.clone(), 2);
+
+        let source = HttpPageSource::new(&url).unwrap();
+        assert_eq!(source.size(), 0x2000);
+        assert_eq!(
+            &*source.read_pages(0x1000, 0x800).unwrap(),
+            &contents[0x1000..0x1800]
+        );
+
+        HttpPageSource::new("https://localhost/mem").unwrap_err();
+    }
+
+    #[test]
+    fn test_http_header() {
+        let headers = "HTTP/1.1 206 Partial Content\r\ncontent-range: bytes 0-0/4096\r\nX: y";
+        assert_eq!(
+            http_header(headers, "Content-Range"),
+            Some("bytes 0-0/4096")
+        );
+        assert_eq!(http_header(headers, "Content-Length"), None);
+    }
+
     #[test]
     fn test_runtime() {
         let tmp_dir = TempDir::new().unwrap();
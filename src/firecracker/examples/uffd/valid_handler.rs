@@ -4,26 +4,35 @@
 //! Provides functionality for a userspace page fault handler
 //! which loads the whole region from the backing memory file
 //! when a page fault occurs.
+//!
+//! The memory contents are read from a local file, or fetched with
+//! HTTP range requests when an `http://` URL is given instead.
 
 mod uffd_utils;
 
 use std::fs::File;
 use std::os::unix::net::UnixListener;
+use std::rc::Rc;
 
-use uffd_utils::{MemPageState, Runtime, UffdHandler};
+use uffd_utils::{FilePageSource, HttpPageSource, MemPageState, PageSource, Runtime, UffdHandler};
 
 fn main() {
     let mut args = std::env::args();
     let uffd_sock_path = args.nth(1).expect("No socket path given");
     let mem_file_path = args.next().expect("No memory file given");
 
-    let file = File::open(mem_file_path).expect("Cannot open memfile");
+    let source: Rc<dyn PageSource> = if mem_file_path.starts_with("http://") {
+        Rc::new(HttpPageSource::new(&mem_file_path).expect("Cannot fetch memory from URL"))
+    } else {
+        let file = File::open(mem_file_path).expect("Cannot open memfile");
+        Rc::new(FilePageSource::new(file))
+    };
 
     // Get Uffd from UDS. We'll use the uffd to handle PFs for Firecracker.
     let listener = UnixListener::bind(uffd_sock_path).expect("Cannot bind to socket path");
     let (stream, _) = listener.accept().expect("Cannot listen on UDS socket");
 
-    let mut runtime = Runtime::new(stream, file);
+    let mut runtime = Runtime::with_page_source(stream, source);
     runtime.run(|uffd_handler: &mut UffdHandler| {
         // Read an event from the userfaultfd.
         let event = uffd_handler
@@ -17,6 +17,7 @@ use parsed_request::{ParsedRequest, RequestAction};
 use seccompiler::BpfProgramRef;
 use serde_json::json;
 use utils::eventfd::EventFd;
+use vmm::api_error::ApiError;
 use vmm::logger::{
     debug, error, info, update_metric_with_elapsed_time, warn, ProcessTimeReporter, METRICS,
 };
@@ -162,8 +163,8 @@ impl ApiServer {
             VmmAction::LoadSnapshot(_) => {
                 Some((&METRICS.latencies_us.load_snapshot, "load snapshot"))
             }
-            VmmAction::Pause => Some((&METRICS.latencies_us.pause_vm, "pause vm")),
-            VmmAction::Resume => Some((&METRICS.latencies_us.resume_vm, "resume vm")),
+            VmmAction::Pause(_) => Some((&METRICS.latencies_us.pause_vm, "pause vm")),
+            VmmAction::Resume(_) => Some((&METRICS.latencies_us.resume_vm, "resume vm")),
             _ => None,
         };
 
@@ -191,8 +192,21 @@ impl ApiServer {
         response
     }
 
-    fn json_fault_message<T: AsRef<str> + serde::Serialize + Debug>(msg: T) -> String {
-        json!({ "fault_message": msg }).to_string()
+    /// The body of an error response. `fault_message` duplicates `message`, for the clients that
+    /// predate the structured errors.
+    fn json_fault<E: ApiError + Debug>(err: &E) -> String {
+        let message = err.to_string();
+        let mut fault = json!({
+            "fault_message": message,
+            "code": err.code(),
+            "subsystem": err.subsystem(),
+            "message": message,
+        });
+        let details = err.details();
+        if !details.is_empty() {
+            fault["details"] = json!(details);
+        }
+        fault.to_string()
     }
 }
 
@@ -212,7 +226,7 @@ mod tests {
     use vmm::rpc_interface::{VmmActionError, VmmData};
     use vmm::seccomp_filters::get_empty_filters;
     use vmm::vmm_config::instance_info::InstanceInfo;
-    use vmm::vmm_config::snapshot::CreateSnapshotParams;
+    use vmm::vmm_config::snapshot::{CreateSnapshotParams, PauseMode};
 
     use super::request::cpu_configuration::parse_put_cpu_config;
     use super::*;
@@ -262,8 +276,8 @@ mod tests {
         let start_time_us = utils::time::get_time_us(ClockType::Monotonic) - 1;
         assert_eq!(METRICS.latencies_us.pause_vm.fetch(), 0);
         to_api.send(Box::new(Ok(VmmData::Empty))).unwrap();
-        let response =
-            api_server.serve_vmm_action_request(Box::new(VmmAction::Pause), start_time_us);
+        let response = api_server
+            .serve_vmm_action_request(Box::new(VmmAction::Pause(PauseMode::Vcpus)), start_time_us);
         assert_eq!(response.status(), StatusCode::NoContent);
         assert_ne!(METRICS.latencies_us.pause_vm.fetch(), 0);
 
@@ -276,6 +290,10 @@ mod tests {
                 snapshot_type: SnapshotType::Diff,
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
+                encryption: None,
+                manifest_path: None,
+                sparse: false,
+                snapshot_version: None,
             })),
             start_time_us,
         );
@@ -289,6 +307,10 @@ mod tests {
                 snapshot_type: SnapshotType::Diff,
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
+                encryption: None,
+                manifest_path: None,
+                sparse: false,
+                snapshot_version: None,
             })),
             start_time_us,
         );
@@ -6,15 +6,23 @@ use std::fmt::Debug;
 use micro_http::{Body, Method, Request, Response, StatusCode, Version};
 use serde::ser::Serialize;
 use serde_json::Value;
+use vmm::api_error::ApiError;
 use vmm::logger::{error, info, log_enabled, Level};
 use vmm::rpc_interface::{VmmAction, VmmActionError, VmmData};
+use vmm::vmm_config::device_id::{validate_device_id, DeviceIdError};
 
 use super::request::actions::parse_put_actions;
-use super::request::balloon::{parse_get_balloon, parse_patch_balloon, parse_put_balloon};
+use super::request::balloon::{
+    parse_get_balloon, parse_patch_balloon, parse_put_balloon, parse_put_memory_pressure_policy,
+};
 use super::request::boot_source::parse_put_boot_source;
+use super::request::config_batch::parse_put_config_batch;
 use super::request::cpu_configuration::parse_put_cpu_config;
+use super::request::crypto::parse_put_crypto;
+use super::request::devices::parse_get_devices;
 use super::request::drive::{parse_patch_drive, parse_put_drive};
-use super::request::entropy::parse_put_entropy;
+use super::request::entropy::{parse_patch_entropy, parse_put_entropy};
+use super::request::fault_injection::parse_put_fault_injection;
 use super::request::instance_info::parse_get_instance_info;
 use super::request::logger::parse_put_logger;
 use super::request::machine_configuration::{
@@ -22,9 +30,19 @@ use super::request::machine_configuration::{
 };
 use super::request::metrics::parse_put_metrics;
 use super::request::mmds::{parse_get_mmds, parse_patch_mmds, parse_put_mmds};
-use super::request::net::{parse_patch_net, parse_put_net};
+use super::request::mmio_trace::parse_put_mmio_trace;
+use super::request::net::{
+    parse_get_net_rate_limiters, parse_patch_net, parse_patch_net_link, parse_put_net,
+    parse_put_net_dhcp,
+};
+use super::request::port_forward::parse_put_port_forwards;
+use super::request::remote_device::parse_put_remote_device;
+use super::request::replay::parse_put_replay;
 use super::request::snapshot::{parse_patch_vm_state, parse_put_snapshot};
+use super::request::tpm::parse_put_tpm;
+use super::request::vcpu::parse_get_vcpu_registers;
 use super::request::version::parse_get_version;
+use super::request::vmcore::parse_put_vmcore;
 use super::request::vsock::parse_put_vsock;
 use super::ApiServer;
 
@@ -77,35 +95,95 @@ impl TryFrom<&Request> for ParsedRequest {
         match (request.method(), path, request.body.as_ref()) {
             (Method::Get, "", None) => parse_get_instance_info(),
             (Method::Get, "balloon", None) => parse_get_balloon(path_tokens.next()),
+            (Method::Get, "devices", None) => parse_get_devices(path_tokens.next()),
             (Method::Get, "version", None) => parse_get_version(),
-            (Method::Get, "vm", None) if path_tokens.next() == Some("config") => {
-                Ok(ParsedRequest::new_sync(VmmAction::GetFullVmConfig))
-            }
-            (Method::Get, "machine-config", None) => parse_get_machine_config(),
+            (Method::Get, "vm", None) => match path_tokens.next() {
+                Some("config") => Ok(ParsedRequest::new_sync(VmmAction::GetFullVmConfig)),
+                Some("vcpus") => parse_get_vcpu_registers(path_tokens),
+                _ => Err(RequestError::InvalidPathMethod(
+                    path.to_string(),
+                    Method::Get,
+                )),
+            },
+            (Method::Get, "vmm", None) => match path_tokens.next() {
+                Some("info") => Ok(ParsedRequest::new_sync(VmmAction::GetVmmInfo)),
+                _ => Err(RequestError::InvalidPathMethod(
+                    path.to_string(),
+                    Method::Get,
+                )),
+            },
+            (Method::Get, "machine-config", None) => parse_get_machine_config(path_tokens.next()),
             (Method::Get, "mmds", None) => parse_get_mmds(),
+            (Method::Get, "network-interfaces", None) => {
+                let id_from_path = path_tokens.next();
+                match path_tokens.next() {
+                    Some("rate-limiters") => parse_get_net_rate_limiters(id_from_path),
+                    _ => Err(RequestError::InvalidPathMethod(
+                        path.to_string(),
+                        Method::Get,
+                    )),
+                }
+            }
             (Method::Get, _, Some(_)) => method_to_error(Method::Get),
             (Method::Put, "actions", Some(body)) => parse_put_actions(body),
             (Method::Put, "balloon", Some(body)) => parse_put_balloon(body),
             (Method::Put, "boot-source", Some(body)) => parse_put_boot_source(body),
             (Method::Put, "cpu-config", Some(body)) => parse_put_cpu_config(body),
             (Method::Put, "drives", Some(body)) => parse_put_drive(body, path_tokens.next()),
+            (Method::Put, "fault-injection", Some(body)) => parse_put_fault_injection(body),
             (Method::Put, "logger", Some(body)) => parse_put_logger(body),
             (Method::Put, "machine-config", Some(body)) => parse_put_machine_config(body),
+            (Method::Put, "memory-pressure-policy", Some(body)) => {
+                parse_put_memory_pressure_policy(body)
+            }
             (Method::Put, "metrics", Some(body)) => parse_put_metrics(body),
             (Method::Put, "mmds", Some(body)) => parse_put_mmds(body, path_tokens.next()),
             (Method::Put, "network-interfaces", Some(body)) => {
-                parse_put_net(body, path_tokens.next())
+                let id_from_path = path_tokens.next();
+                match path_tokens.next() {
+                    None => parse_put_net(body, id_from_path),
+                    Some("dhcp") => parse_put_net_dhcp(body, id_from_path),
+                    Some(_) => Err(RequestError::InvalidPathMethod(
+                        path.to_string(),
+                        Method::Put,
+                    )),
+                }
+            }
+            (Method::Put, "remote-devices", Some(body)) => {
+                parse_put_remote_device(body, path_tokens.next())
             }
             (Method::Put, "snapshot", Some(body)) => parse_put_snapshot(body, path_tokens.next()),
-            (Method::Put, "vsock", Some(body)) => parse_put_vsock(body),
+            (Method::Put, "vm", Some(body)) => match path_tokens.next() {
+                Some("config-batch") => parse_put_config_batch(body),
+                _ => Err(RequestError::InvalidPathMethod(
+                    path.to_string(),
+                    Method::Put,
+                )),
+            },
+            (Method::Put, "vmcore", Some(body)) => parse_put_vmcore(body),
+            (Method::Put, "vsock", Some(body)) => parse_put_vsock(body, path_tokens.next()),
             (Method::Put, "entropy", Some(body)) => parse_put_entropy(body),
+            (Method::Put, "crypto", Some(body)) => parse_put_crypto(body),
+            (Method::Put, "tpm", Some(body)) => parse_put_tpm(body),
+            (Method::Put, "replay", Some(body)) => parse_put_replay(body),
+            (Method::Put, "mmio-trace", Some(body)) => parse_put_mmio_trace(body),
+            (Method::Put, "port-forwards", Some(body)) => parse_put_port_forwards(body),
             (Method::Put, _, None) => method_to_error(Method::Put),
             (Method::Patch, "balloon", Some(body)) => parse_patch_balloon(body, path_tokens.next()),
             (Method::Patch, "drives", Some(body)) => parse_patch_drive(body, path_tokens.next()),
+            (Method::Patch, "entropy", Some(body)) => parse_patch_entropy(body),
             (Method::Patch, "machine-config", Some(body)) => parse_patch_machine_config(body),
             (Method::Patch, "mmds", Some(body)) => parse_patch_mmds(body),
             (Method::Patch, "network-interfaces", Some(body)) => {
-                parse_patch_net(body, path_tokens.next())
+                let id_from_path = path_tokens.next();
+                match path_tokens.next() {
+                    None => parse_patch_net(body, id_from_path),
+                    Some("link") => parse_patch_net_link(body, id_from_path),
+                    Some(_) => Err(RequestError::InvalidPathMethod(
+                        path.to_string(),
+                        Method::Patch,
+                    )),
+                }
             }
             (Method::Patch, "vm", Some(body)) => parse_patch_vm_state(body),
             (Method::Patch, _, None) => method_to_error(Method::Patch),
@@ -166,16 +244,26 @@ impl ParsedRequest {
                 VmmData::MachineConfiguration(vm_config) => {
                     Self::success_response_with_data(vm_config)
                 }
+                VmmData::MemoryMap(map) => Self::success_response_with_data(map),
                 VmmData::MmdsValue(value) => Self::success_response_with_mmds_value(value),
+                VmmData::NetworkInterfaceRateLimiters(stats) => {
+                    Self::success_response_with_data(stats)
+                }
                 VmmData::BalloonConfig(balloon_config) => {
                     Self::success_response_with_data(balloon_config)
                 }
                 VmmData::BalloonStats(stats) => Self::success_response_with_data(stats),
+                VmmData::BalloonStatsHistory(history) => Self::success_response_with_data(history),
+                VmmData::DeviceFeatures(features) => Self::success_response_with_data(features),
+                VmmData::DeviceRegions(regions) => Self::success_response_with_data(regions),
                 VmmData::InstanceInformation(info) => Self::success_response_with_data(info),
                 VmmData::VmmVersion(version) => Self::success_response_with_data(
                     &serde_json::json!({ "firecracker_version": version.as_str() }),
                 ),
                 VmmData::FullVmConfig(config) => Self::success_response_with_data(config),
+                VmmData::VcpuRegisters(registers) => Self::success_response_with_data(registers),
+                VmmData::VirtioTrace(spans) => Self::success_response_with_data(spans),
+                VmmData::VmmInfo(info) => Self::success_response_with_data(info),
             },
             Err(vmm_action_error) => {
                 let mut response = match vmm_action_error {
@@ -194,9 +282,7 @@ impl ParsedRequest {
                         Response::new(Version::Http11, StatusCode::BadRequest)
                     }
                 };
-                response.set_body(Body::new(ApiServer::json_fault_message(
-                    vmm_action_error.to_string(),
-                )));
+                response.set_body(Body::new(ApiServer::json_fault(vmm_action_error)));
                 response
             }
         }
@@ -283,10 +369,28 @@ pub(crate) enum RequestError {
     SerdeJson(#[from] serde_json::Error),
 }
 
+impl ApiError for RequestError {
+    fn subsystem(&self) -> &'static str {
+        "api"
+    }
+
+    // The wrapped values are not errors of their own, so only the outer variant is reported.
+    fn code(&self) -> String {
+        match self {
+            RequestError::EmptyID => "EmptyID",
+            RequestError::Generic(_, _) => "Generic",
+            RequestError::InvalidID => "InvalidID",
+            RequestError::InvalidPathMethod(_, _) => "InvalidPathMethod",
+            RequestError::SerdeJson(_) => "SerdeJson",
+        }
+        .to_string()
+    }
+}
+
 // It's convenient to turn errors into HTTP responses directly.
 impl From<RequestError> for Response {
     fn from(err: RequestError) -> Self {
-        let msg = ApiServer::json_fault_message(format!("{}", err));
+        let msg = ApiServer::json_fault(&err);
         match err {
             RequestError::Generic(status, _) => ApiServer::json_response(status, msg),
             RequestError::EmptyID
@@ -297,19 +401,17 @@ impl From<RequestError> for Response {
     }
 }
 
-// This function is supposed to do id validation for requests.
+// Validates the ID of a device in a request path, following the rules of the VMM for device IDs.
 pub(crate) fn checked_id(id: &str) -> Result<&str, RequestError> {
-    // todo: are there any checks we want to do on id's?
-    // not allow them to be empty strings maybe?
-    // check: ensure string is not empty
-    if id.is_empty() {
-        return Err(RequestError::EmptyID);
-    }
-    // check: ensure string is alphanumeric
-    if !id.chars().all(|c| c == '_' || c.is_alphanumeric()) {
-        return Err(RequestError::InvalidID);
+    match validate_device_id(id) {
+        Ok(()) => Ok(id),
+        Err(DeviceIdError::Empty) => Err(RequestError::EmptyID),
+        Err(DeviceIdError::InvalidCharacters(_)) => Err(RequestError::InvalidID),
+        Err(err) => Err(RequestError::Generic(
+            StatusCode::BadRequest,
+            err.to_string(),
+        )),
     }
-    Ok(id)
 }
 
 #[cfg(test)]
@@ -321,11 +423,18 @@ pub mod tests {
     use micro_http::HttpConnection;
     use vmm::builder::StartMicrovmError;
     use vmm::cpu_config::templates::test_utils::build_test_template;
+    use vmm::devices::virtio::features::VirtioDeviceFeatures;
+    use vmm::devices::virtio::mmio::DeviceActivation;
+    use vmm::devices::virtio::trace::TraceSpan;
+    use vmm::devices::{BusRegion, DeviceRegions};
     use vmm::resources::VmmConfig;
     use vmm::rpc_interface::VmmActionError;
-    use vmm::vmm_config::balloon::{BalloonDeviceConfig, BalloonStats};
+    use vmm::vmm_config::balloon::{BalloonDeviceConfig, BalloonStats, BalloonStatsSample};
+    use vmm::vmm_config::device_id::MAX_DEVICE_ID_LEN;
     use vmm::vmm_config::instance_info::InstanceInfo;
     use vmm::vmm_config::machine_config::MachineConfig;
+    use vmm::vmm_config::vmm_info::VmmInfo;
+    use vmm::vstate::vcpu::VcpuRegisters;
 
     use super::*;
 
@@ -422,6 +531,11 @@ pub mod tests {
             format!("{}", checked_id("dummy!!").unwrap_err()),
             "API Resource IDs can only contain alphanumeric characters and underscores."
         );
+        let long_id = "a".repeat(MAX_DEVICE_ID_LEN + 1);
+        assert_eq!(
+            format!("{}", checked_id(&long_id).unwrap_err()),
+            format!("The device ID {long_id} is longer than 64 characters.")
+        );
     }
 
     #[test]
@@ -472,6 +586,19 @@ pub mod tests {
         ));
     }
 
+    fn api_fault(code: &str, message: &str, details: &[&str]) -> String {
+        let mut fault = serde_json::json!({
+            "fault_message": message,
+            "code": code,
+            "subsystem": "api",
+            "message": message,
+        });
+        if !details.is_empty() {
+            fault["details"] = serde_json::json!(details);
+        }
+        fault.to_string()
+    }
+
     #[test]
     fn test_error_into_response() {
         // Generic error.
@@ -479,7 +606,7 @@ pub mod tests {
         let response: Response =
             RequestError::Generic(StatusCode::BadRequest, "message".to_string()).into();
         response.write_all(&mut buf).unwrap();
-        let body = ApiServer::json_fault_message("message");
+        let body = api_fault("Generic", "message", &[]);
         let expected_response = http_response(&body, 400);
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
 
@@ -487,7 +614,7 @@ pub mod tests {
         let mut buf = Cursor::new(vec![0]);
         let response: Response = RequestError::EmptyID.into();
         response.write_all(&mut buf).unwrap();
-        let body = ApiServer::json_fault_message("The ID cannot be empty.");
+        let body = api_fault("EmptyID", "The ID cannot be empty.", &[]);
         let expected_response = http_response(&body, 400);
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
 
@@ -495,8 +622,10 @@ pub mod tests {
         let mut buf = Cursor::new(vec![0]);
         let response: Response = RequestError::InvalidID.into();
         response.write_all(&mut buf).unwrap();
-        let body = ApiServer::json_fault_message(
+        let body = api_fault(
+            "InvalidID",
             "API Resource IDs can only contain alphanumeric characters and underscores.",
+            &[],
         );
         let expected_response = http_response(&body, 400);
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
@@ -506,11 +635,15 @@ pub mod tests {
         let response: Response =
             RequestError::InvalidPathMethod("path".to_string(), Method::Get).into();
         response.write_all(&mut buf).unwrap();
-        let body = ApiServer::json_fault_message(format!(
-            "Invalid request method and/or path: {} {}.",
-            std::str::from_utf8(Method::Get.raw()).unwrap(),
-            "path"
-        ));
+        let body = api_fault(
+            "InvalidPathMethod",
+            &format!(
+                "Invalid request method and/or path: {} {}.",
+                std::str::from_utf8(Method::Get.raw()).unwrap(),
+                "path"
+            ),
+            &[],
+        );
         let expected_response = http_response(&body, 400);
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
 
@@ -519,9 +652,11 @@ pub mod tests {
         let serde_error = serde_json::Value::from_str("").unwrap_err();
         let response: Response = RequestError::SerdeJson(serde_error).into();
         response.write_all(&mut buf).unwrap();
-        let body = ApiServer::json_fault_message(
+        let body = api_fault(
+            "SerdeJson",
             "An error occurred when deserializing the json body of a request: EOF while parsing a \
              value at line 1 column 0.",
+            &["EOF while parsing a value at line 1 column 0"],
         );
         let expected_response = http_response(&body, 400);
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
@@ -555,6 +690,15 @@ pub mod tests {
                 VmmData::BalloonStats(stats) => {
                     http_response(&serde_json::to_string(stats).unwrap(), 200)
                 }
+                VmmData::BalloonStatsHistory(history) => {
+                    http_response(&serde_json::to_string(history).unwrap(), 200)
+                }
+                VmmData::DeviceFeatures(features) => {
+                    http_response(&serde_json::to_string(features).unwrap(), 200)
+                }
+                VmmData::DeviceRegions(regions) => {
+                    http_response(&serde_json::to_string(regions).unwrap(), 200)
+                }
                 VmmData::Empty => http_response("", 204),
                 VmmData::FullVmConfig(cfg) => {
                     http_response(&serde_json::to_string(cfg).unwrap(), 200)
@@ -562,16 +706,27 @@ pub mod tests {
                 VmmData::MachineConfiguration(cfg) => {
                     http_response(&serde_json::to_string(cfg).unwrap(), 200)
                 }
+                VmmData::MemoryMap(map) => http_response(&serde_json::to_string(map).unwrap(), 200),
                 VmmData::MmdsValue(value) => {
                     http_response(&serde_json::to_string(value).unwrap(), 200)
                 }
+                VmmData::NetworkInterfaceRateLimiters(stats) => {
+                    http_response(&serde_json::to_string(stats).unwrap(), 200)
+                }
                 VmmData::InstanceInformation(info) => {
                     http_response(&serde_json::to_string(info).unwrap(), 200)
                 }
+                VmmData::VcpuRegisters(registers) => {
+                    http_response(&serde_json::to_string(registers).unwrap(), 200)
+                }
+                VmmData::VirtioTrace(spans) => {
+                    http_response(&serde_json::to_string(spans).unwrap(), 200)
+                }
                 VmmData::VmmVersion(version) => http_response(
                     &serde_json::json!({ "firecracker_version": version.as_str() }).to_string(),
                     200,
                 ),
+                VmmData::VmmInfo(info) => http_response(&serde_json::to_string(info).unwrap(), 200),
             };
             let response = ParsedRequest::convert_to_response(&data);
             response.write_all(&mut buf).unwrap();
@@ -584,17 +739,55 @@ pub mod tests {
             swap_out: Some(1),
             ..Default::default()
         }));
+        verify_ok_response_with(VmmData::BalloonStatsHistory(vec![BalloonStatsSample {
+            timestamp_us: 1,
+            stats: BalloonStats {
+                free_memory: Some(1),
+                ..Default::default()
+            },
+        }]));
+        verify_ok_response_with(VmmData::DeviceFeatures(VirtioDeviceFeatures {
+            id: "rootfs".to_string(),
+            device_type: "block".to_string(),
+            negotiated: true,
+            avail_features: 1 << 32,
+            acked_features: 1 << 32,
+            acked_feature_names: vec!["VIRTIO_F_VERSION_1".to_string()],
+        }));
+        verify_ok_response_with(VmmData::DeviceRegions(DeviceRegions {
+            mmio: vec![BusRegion {
+                base: 0xd000_0000,
+                len: 0x1000,
+                device: "virtio-block/rootfs".to_string(),
+                activation: Some(DeviceActivation::Activated { timestamp_us: 1 }),
+            }],
+            pio: Vec::new(),
+        }));
         verify_ok_response_with(VmmData::Empty);
         verify_ok_response_with(VmmData::FullVmConfig(VmmConfig::default()));
         verify_ok_response_with(VmmData::MachineConfiguration(MachineConfig::default()));
         verify_ok_response_with(VmmData::MmdsValue(serde_json::from_str("{}").unwrap()));
         verify_ok_response_with(VmmData::InstanceInformation(InstanceInfo::default()));
+        verify_ok_response_with(VmmData::VcpuRegisters(VcpuRegisters::default()));
+        verify_ok_response_with(VmmData::VirtioTrace(vec![TraceSpan {
+            span_id: 1,
+            pop_us: 1,
+            ..Default::default()
+        }]));
         verify_ok_response_with(VmmData::VmmVersion(String::default()));
+        verify_ok_response_with(VmmData::VmmInfo(VmmInfo::default()));
 
         // Error.
         let error = VmmActionError::StartMicrovm(StartMicrovmError::MissingKernelConfig);
         let mut buf = Cursor::new(vec![0]);
-        let json = ApiServer::json_fault_message(error.to_string());
+        let json = serde_json::json!({
+            "fault_message": error.to_string(),
+            "code": "StartMicrovm.MissingKernelConfig",
+            "subsystem": "vmm",
+            "message": error.to_string(),
+            "details": [StartMicrovmError::MissingKernelConfig.to_string()],
+        })
+        .to_string();
         let response = ParsedRequest::convert_to_response(&Err(error));
         response.write_all(&mut buf).unwrap();
 
@@ -636,6 +829,16 @@ pub mod tests {
         connection.try_read().unwrap();
         let req = connection.pop_parsed_request().unwrap();
         ParsedRequest::try_from(&req).unwrap();
+
+        sender
+            .write_all(http_request("GET", "/balloon/statistics?history=5", None).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        assert_eq!(
+            vmm_action_from_request(ParsedRequest::try_from(&req).unwrap()),
+            VmmAction::GetBalloonStatsHistory(5)
+        );
     }
 
     #[test]
@@ -650,6 +853,18 @@ pub mod tests {
         ParsedRequest::try_from(&req).unwrap();
     }
 
+    #[test]
+    fn test_try_from_get_memory_map() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/machine-config/memory-map", None).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+    }
+
     #[test]
     fn test_try_from_get_mmds() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -662,6 +877,53 @@ pub mod tests {
         ParsedRequest::try_from(&req).unwrap();
     }
 
+    #[test]
+    fn test_try_from_get_devices() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/devices", None).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+    }
+
+    #[test]
+    fn test_try_from_get_device_features() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/devices/rootfs", None).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+    }
+
+    #[test]
+    fn test_try_from_get_net_rate_limiters() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                http_request("GET", "/network-interfaces/eth0/rate-limiters", None).as_bytes(),
+            )
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/network-interfaces/eth0", None).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap_err();
+    }
+
     #[test]
     fn test_try_from_get_version() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -674,6 +936,54 @@ pub mod tests {
         ParsedRequest::try_from(&req).unwrap();
     }
 
+    #[test]
+    fn test_try_from_get_vmm_info() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/vmm/info", None).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        match ParsedRequest::try_from(&req).unwrap().into_parts() {
+            (RequestAction::Sync(action), _) if *action == VmmAction::GetVmmInfo => {}
+            _ => panic!("Test failed."),
+        }
+
+        sender
+            .write_all(http_request("GET", "/vmm", None).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap_err();
+    }
+
+    #[test]
+    fn test_try_from_get_vcpu_registers() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/vm/vcpus/0/registers", None).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+
+        sender
+            .write_all(http_request("GET", "/vm/vcpus", None).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap_err();
+
+        sender
+            .write_all(http_request("GET", "/vm", None).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap_err();
+    }
+
     #[test]
     fn test_try_from_put_actions() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -687,6 +997,20 @@ pub mod tests {
         ParsedRequest::try_from(&req).unwrap();
     }
 
+    #[test]
+    fn test_try_from_put_memory_pressure_policy() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body =
+            "{ \"inflate_step_mib\": 64, \"max_balloon_mib\": 512, \"deflate_interval_s\": 10 }";
+        sender
+            .write_all(http_request("PUT", "/memory-pressure-policy", Some(body)).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+    }
+
     #[test]
     fn test_try_from_put_balloon() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -716,6 +1040,115 @@ pub mod tests {
         ParsedRequest::try_from(&req).unwrap();
     }
 
+    #[test]
+    fn test_try_from_put_crypto() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body =
+            "{ \"rate_limiter\": { \"bandwidth\" : { \"size\": 1048576,                     \
+             \"refill_time\": 1000 } } }";
+        sender
+            .write_all(http_request("PUT", "/crypto", Some(body)).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+    }
+
+    #[test]
+    fn test_try_from_put_port_forwards() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "[{ \"iface_id\": \"eth0\", \"host_port\": 8080, \"guest_address\": \
+                    \"192.168.0.2\", \"guest_port\": 80 }]";
+        sender
+            .write_all(http_request("PUT", "/port-forwards", Some(body)).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+    }
+
+    #[test]
+    fn test_try_from_put_tpm() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"socket\": \"/tmp/swtpm.sock\" }";
+        sender
+            .write_all(http_request("PUT", "/tpm", Some(body)).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+    }
+
+    #[test]
+    fn test_try_from_put_config_batch() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"devices\": [{ \"entropy\": {} }] }";
+        sender
+            .write_all(http_request("PUT", "/vm/config-batch", Some(body)).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+
+        sender
+            .write_all(http_request("PUT", "/vm/config", Some(body)).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap_err();
+    }
+
+    #[test]
+    fn test_try_from_put_replay() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"mode\": \"Record\", \"log_path\": \"/tmp/replay.log\" }";
+        sender
+            .write_all(http_request("PUT", "/replay", Some(body)).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+    }
+
+    #[test]
+    fn test_try_from_put_mmio_trace() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"device_ids\": [\"rootfs\"] }";
+        sender
+            .write_all(http_request("PUT", "/mmio-trace", Some(body)).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+    }
+
+    #[test]
+    fn test_try_from_patch_entropy() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"rate_limiter\": { \"ops\": { \"size\": 100, \"refill_time\": 1000 } } }";
+        sender
+            .write_all(http_request("PATCH", "/entropy", Some(body)).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+
+        // A PATCH request without a body is rejected like the ones of the other resources.
+        sender
+            .write_all(http_request("PATCH", "/entropy", None).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap_err();
+    }
+
     #[test]
     fn test_try_from_put_boot() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -834,6 +1267,23 @@ pub mod tests {
         connection.try_read().unwrap();
         let req = connection.pop_parsed_request().unwrap();
         ParsedRequest::try_from(&req).unwrap();
+
+        let body = "{ \"ip_address\": \"192.168.0.2\", \"prefix_length\": 24 }";
+        sender
+            .write_all(
+                http_request("PUT", "/network-interfaces/string/dhcp", Some(body)).as_bytes(),
+            )
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+
+        sender
+            .write_all(http_request("PUT", "/network-interfaces/string/foo", Some(body)).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap_err();
     }
 
     #[test]
@@ -893,6 +1343,14 @@ pub mod tests {
         connection.try_read().unwrap();
         let req = connection.pop_parsed_request().unwrap();
         ParsedRequest::try_from(&req).unwrap();
+
+        let body = "{ \"guest_cid\": 0, \"uds_path\": \"string\" }";
+        sender
+            .write_all(http_request("PUT", "/vsock/string", Some(body)).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
     }
 
     #[test]
@@ -996,5 +1454,24 @@ pub mod tests {
         connection.try_read().unwrap();
         let req = connection.pop_parsed_request().unwrap();
         ParsedRequest::try_from(&req).unwrap();
+
+        let body = "{ \"state\": \"Down\" }";
+        sender
+            .write_all(
+                http_request("PATCH", "/network-interfaces/string/link", Some(body)).as_bytes(),
+            )
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+
+        sender
+            .write_all(
+                http_request("PATCH", "/network-interfaces/string/foo", Some(body)).as_bytes(),
+            )
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap_err();
     }
 }
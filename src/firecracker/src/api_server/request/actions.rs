@@ -1,23 +1,27 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 use vmm::logger::{IncMetric, METRICS};
 use vmm::rpc_interface::VmmAction;
+use vmm::DEFAULT_SHUTDOWN_TIMEOUT;
 
 use super::super::parsed_request::{ParsedRequest, RequestError};
-use super::Body;
-#[cfg(target_arch = "aarch64")]
-use super::StatusCode;
+use super::{Body, StatusCode};
 
 // The names of the members from this enum must precisely correspond (as a string) to the possible
 // values of "action_type" from the json request body. This is useful to get a strongly typed
 // struct from the Serde deserialization process.
 #[derive(Debug, Deserialize, Serialize)]
 enum ActionType {
+    DumpVirtioTrace,
     FlushMetrics,
     InstanceStart,
+    ResetNetMetrics,
     SendCtrlAltDel,
+    Shutdown,
 }
 
 // The model of the json body from a sync request. We use Serde to transform each associated
@@ -26,6 +30,8 @@ enum ActionType {
 #[serde(deny_unknown_fields)]
 struct ActionBody {
     action_type: ActionType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timeout_ms: Option<u64>,
 }
 
 pub(crate) fn parse_put_actions(body: &Body) -> Result<ParsedRequest, RequestError> {
@@ -35,9 +41,20 @@ pub(crate) fn parse_put_actions(body: &Body) -> Result<ParsedRequest, RequestErr
         err
     })?;
 
+    if action_body.timeout_ms.is_some() && !matches!(action_body.action_type, ActionType::Shutdown)
+    {
+        METRICS.put_api_requests.actions_fails.inc();
+        return Err(RequestError::Generic(
+            StatusCode::BadRequest,
+            "timeout_ms is only supported by the Shutdown action.".to_string(),
+        ));
+    }
+
     match action_body.action_type {
+        ActionType::DumpVirtioTrace => Ok(ParsedRequest::new_sync(VmmAction::DumpVirtioTrace)),
         ActionType::FlushMetrics => Ok(ParsedRequest::new_sync(VmmAction::FlushMetrics)),
         ActionType::InstanceStart => Ok(ParsedRequest::new_sync(VmmAction::StartMicroVm)),
+        ActionType::ResetNetMetrics => Ok(ParsedRequest::new_sync(VmmAction::ResetNetMetrics)),
         ActionType::SendCtrlAltDel => {
             // SendCtrlAltDel not supported on aarch64.
             #[cfg(target_arch = "aarch64")]
@@ -49,6 +66,12 @@ pub(crate) fn parse_put_actions(body: &Body) -> Result<ParsedRequest, RequestErr
             #[cfg(target_arch = "x86_64")]
             Ok(ParsedRequest::new_sync(VmmAction::SendCtrlAltDel))
         }
+        ActionType::Shutdown => {
+            let timeout = action_body
+                .timeout_ms
+                .map_or(DEFAULT_SHUTDOWN_TIMEOUT, Duration::from_millis);
+            Ok(ParsedRequest::new_sync(VmmAction::Shutdown(timeout)))
+        }
     }
 }
 
@@ -100,5 +123,54 @@ mod tests {
             let result = parse_put_actions(&Body::new(json));
             assert_eq!(result.unwrap(), req);
         }
+
+        {
+            let json = r#"{
+                "action_type": "ResetNetMetrics"
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::ResetNetMetrics);
+            let result = parse_put_actions(&Body::new(json));
+            assert_eq!(result.unwrap(), req);
+        }
+
+        {
+            let json = r#"{
+                "action_type": "DumpVirtioTrace"
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::DumpVirtioTrace);
+            let result = parse_put_actions(&Body::new(json));
+            assert_eq!(result.unwrap(), req);
+        }
+
+        {
+            let json = r#"{
+                "action_type": "Shutdown"
+            }"#;
+
+            let req: ParsedRequest =
+                ParsedRequest::new_sync(VmmAction::Shutdown(DEFAULT_SHUTDOWN_TIMEOUT));
+            let result = parse_put_actions(&Body::new(json));
+            assert_eq!(result.unwrap(), req);
+
+            let json = r#"{
+                "action_type": "Shutdown",
+                "timeout_ms": 200
+            }"#;
+
+            let req: ParsedRequest =
+                ParsedRequest::new_sync(VmmAction::Shutdown(Duration::from_millis(200)));
+            let result = parse_put_actions(&Body::new(json));
+            assert_eq!(result.unwrap(), req);
+
+            // The timeout is only accepted by the Shutdown action.
+            let json = r#"{
+                "action_type": "FlushMetrics",
+                "timeout_ms": 200
+            }"#;
+
+            parse_put_actions(&Body::new(json)).unwrap_err();
+        }
     }
 }
@@ -4,7 +4,7 @@
 use micro_http::StatusCode;
 use vmm::rpc_interface::VmmAction;
 use vmm::vmm_config::balloon::{
-    BalloonDeviceConfig, BalloonUpdateConfig, BalloonUpdateStatsConfig,
+    BalloonDeviceConfig, BalloonUpdateConfig, BalloonUpdateStatsConfig, MemoryPressurePolicyConfig,
 };
 
 use super::super::parsed_request::{ParsedRequest, RequestError};
@@ -14,8 +14,11 @@ pub(crate) fn parse_get_balloon(
     path_second_token: Option<&str>,
 ) -> Result<ParsedRequest, RequestError> {
     match path_second_token {
-        Some(stats_path) => match stats_path {
-            "statistics" => Ok(ParsedRequest::new_sync(VmmAction::GetBalloonStats)),
+        Some(stats_path) => match stats_path.split_once('?') {
+            None if stats_path == "statistics" => {
+                Ok(ParsedRequest::new_sync(VmmAction::GetBalloonStats))
+            }
+            Some(("statistics", query)) => parse_get_balloon_stats_history(query),
             _ => Err(RequestError::Generic(
                 StatusCode::BadRequest,
                 format!("Unrecognized GET request path `{}`.", stats_path),
@@ -25,12 +28,37 @@ pub(crate) fn parse_get_balloon(
     }
 }
 
+fn parse_get_balloon_stats_history(query: &str) -> Result<ParsedRequest, RequestError> {
+    let count = query
+        .strip_prefix("history=")
+        .and_then(|count| count.parse::<usize>().ok())
+        .filter(|count| *count > 0)
+        .ok_or_else(|| {
+            RequestError::Generic(
+                StatusCode::BadRequest,
+                format!(
+                    "Invalid query `{}`, expected `history=N` with N a positive integer.",
+                    query
+                ),
+            )
+        })?;
+    Ok(ParsedRequest::new_sync(VmmAction::GetBalloonStatsHistory(
+        count,
+    )))
+}
+
 pub(crate) fn parse_put_balloon(body: &Body) -> Result<ParsedRequest, RequestError> {
     Ok(ParsedRequest::new_sync(VmmAction::SetBalloonDevice(
         serde_json::from_slice::<BalloonDeviceConfig>(body.raw())?,
     )))
 }
 
+pub(crate) fn parse_put_memory_pressure_policy(body: &Body) -> Result<ParsedRequest, RequestError> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetMemoryPressurePolicy(
+        serde_json::from_slice::<MemoryPressurePolicyConfig>(body.raw())?,
+    )))
+}
+
 pub(crate) fn parse_patch_balloon(
     body: &Body,
     path_second_token: Option<&str>,
@@ -63,6 +91,16 @@ mod tests {
         parse_get_balloon(Some("unrelated")).unwrap_err();
 
         parse_get_balloon(Some("statistics")).unwrap();
+
+        assert_eq!(
+            vmm_action_from_request(parse_get_balloon(Some("statistics?history=10")).unwrap()),
+            VmmAction::GetBalloonStatsHistory(10)
+        );
+        parse_get_balloon(Some("statistics?history=0")).unwrap_err();
+        parse_get_balloon(Some("statistics?history=-1")).unwrap_err();
+        parse_get_balloon(Some("statistics?history=")).unwrap_err();
+        parse_get_balloon(Some("statistics?foo=1")).unwrap_err();
+        parse_get_balloon(Some("unrelated?history=1")).unwrap_err();
     }
 
     #[test]
@@ -168,4 +206,31 @@ mod tests {
         }"#;
         parse_put_balloon(&Body::new(body)).unwrap();
     }
+
+    #[test]
+    fn test_parse_put_memory_pressure_policy_request() {
+        parse_put_memory_pressure_policy(&Body::new("invalid_payload")).unwrap_err();
+
+        // PUT with missing fields.
+        let body = r#"{
+            "inflate_step_mib": 64
+        }"#;
+        parse_put_memory_pressure_policy(&Body::new(body)).unwrap_err();
+
+        // PUT with valid input fields.
+        let body = r#"{
+            "inflate_step_mib": 64,
+            "max_balloon_mib": 512,
+            "deflate_interval_s": 10
+        }"#;
+        let expected_config = MemoryPressurePolicyConfig {
+            inflate_step_mib: 64,
+            max_balloon_mib: 512,
+            deflate_interval_s: 10,
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_memory_pressure_policy(&Body::new(body)).unwrap()),
+            VmmAction::SetMemoryPressurePolicy(expected_config)
+        );
+    }
 }
@@ -35,6 +35,7 @@ mod tests {
             kernel_image_path: String::from("/foo/bar"),
             initrd_path: Some(String::from("/bar/foo")),
             boot_args: Some(String::from("foobar")),
+            crash_kernel_size_mib: None,
         };
         let parsed_req = parse_put_boot_source(&Body::new(body)).unwrap();
 
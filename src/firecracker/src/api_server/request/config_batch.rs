@@ -0,0 +1,43 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::config_batch::ConfigBatch;
+
+use super::super::parsed_request::{ParsedRequest, RequestError};
+use super::Body;
+
+pub(crate) fn parse_put_config_batch(body: &Body) -> Result<ParsedRequest, RequestError> {
+    let batch = serde_json::from_slice::<ConfigBatch>(body.raw())?;
+    Ok(ParsedRequest::new_sync(VmmAction::ApplyConfigBatch(batch)))
+}
+
+#[cfg(test)]
+mod tests {
+    use vmm::vmm_config::config_batch::DeviceConfig;
+    use vmm::vmm_config::entropy::EntropyDeviceConfig;
+
+    use super::*;
+    use crate::api_server::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_config_batch_request() {
+        parse_put_config_batch(&Body::new("invalid_payload")).unwrap_err();
+
+        // PUT with an unknown kind of device.
+        let body = r#"{
+            "devices": [{"machine-config": {"vcpu_count": 2}}]
+        }"#;
+        parse_put_config_batch(&Body::new(body)).unwrap_err();
+
+        let body = r#"{
+            "devices": [{"entropy": {}}]
+        }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_config_batch(&Body::new(body)).unwrap()),
+            VmmAction::ApplyConfigBatch(ConfigBatch {
+                devices: vec![DeviceConfig::Entropy(EntropyDeviceConfig::default())],
+            })
+        );
+    }
+}
@@ -0,0 +1,45 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::crypto::CryptoDeviceConfig;
+
+use super::super::parsed_request::{ParsedRequest, RequestError};
+use super::Body;
+
+pub(crate) fn parse_put_crypto(body: &Body) -> Result<ParsedRequest, RequestError> {
+    let cfg = serde_json::from_slice::<CryptoDeviceConfig>(body.raw())?;
+    Ok(ParsedRequest::new_sync(VmmAction::SetCryptoDevice(cfg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_server::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_crypto_request() {
+        parse_put_crypto(&Body::new("invalid_payload")).unwrap_err();
+
+        // PUT with invalid fields.
+        let body = r#"{
+            "deterministic_seed": 4
+        }"#;
+        parse_put_crypto(&Body::new(body)).unwrap_err();
+
+        // PUT with valid fields.
+        let body = r#"{
+            "rate_limiter": {
+                "ops": {
+                    "size": 100,
+                    "refill_time": 1000
+                }
+            }
+        }"#;
+        let expected_config = serde_json::from_str::<CryptoDeviceConfig>(body).unwrap();
+        assert_eq!(
+            vmm_action_from_request(parse_put_crypto(&Body::new(body)).unwrap()),
+            VmmAction::SetCryptoDevice(expected_config)
+        );
+    }
+}
@@ -0,0 +1,35 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+
+use super::super::parsed_request::{ParsedRequest, RequestError};
+
+pub(crate) fn parse_get_devices(device_id: Option<&str>) -> Result<ParsedRequest, RequestError> {
+    match device_id {
+        Some(id) => Ok(ParsedRequest::new_sync(VmmAction::GetDeviceFeatures(
+            id.to_string(),
+        ))),
+        None => Ok(ParsedRequest::new_sync(VmmAction::GetDevices)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::parsed_request::RequestAction;
+    use super::*;
+
+    #[test]
+    fn test_parse_get_devices_request() {
+        match parse_get_devices(None).unwrap().into_parts() {
+            (RequestAction::Sync(action), _) if *action == VmmAction::GetDevices => {}
+            _ => panic!("Test failed."),
+        }
+
+        match parse_get_devices(Some("rootfs")).unwrap().into_parts() {
+            (RequestAction::Sync(action), _)
+                if *action == VmmAction::GetDeviceFeatures(String::from("rootfs")) => {}
+            _ => panic!("Test failed."),
+        }
+    }
+}
@@ -71,6 +71,8 @@ pub(crate) fn parse_patch_drive(
 
 #[cfg(test)]
 mod tests {
+    use vmm::vmm_config::drive::FileEngineType;
+
     use super::*;
     use crate::api_server::parsed_request::tests::vmm_action_from_request;
 
@@ -139,6 +141,7 @@ mod tests {
             drive_id: "foo".to_string(),
             path_on_host: Some("dummy".to_string()),
             rate_limiter: None,
+            file_engine_type: None,
         };
         assert_eq!(
             vmm_action_from_request(parse_patch_drive(&Body::new(body), Some("foo")).unwrap()),
@@ -196,6 +199,28 @@ mod tests {
         }"#;
         // Validate that parse_patch_drive fails for invalid rate limiter cfg.
         parse_patch_drive(&Body::new(body), Some("foo")).unwrap_err();
+
+        let body = r#"{
+            "drive_id": "foo",
+            "io_engine": "Async"
+        }"#;
+        // Validate that updating just the io engine works.
+        let expected_config = BlockDeviceUpdateConfig {
+            drive_id: "foo".to_string(),
+            file_engine_type: Some(FileEngineType::Async),
+            ..Default::default()
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_patch_drive(&Body::new(body), Some("foo")).unwrap()),
+            VmmAction::UpdateBlockDevice(expected_config)
+        );
+
+        let body = r#"{
+            "drive_id": "foo",
+            "io_engine": "Uring"
+        }"#;
+        // Validate that parse_patch_drive fails for an unknown io engine.
+        parse_patch_drive(&Body::new(body), Some("foo")).unwrap_err();
     }
 
     #[test]
@@ -0,0 +1,49 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::fault_injection::FaultInjectionConfig;
+
+use super::super::parsed_request::{ParsedRequest, RequestError};
+use super::Body;
+
+pub(crate) fn parse_put_fault_injection(body: &Body) -> Result<ParsedRequest, RequestError> {
+    let cfg = serde_json::from_slice::<FaultInjectionConfig>(body.raw())?;
+    Ok(ParsedRequest::new_sync(VmmAction::InjectFault(cfg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use vmm::vmm_config::fault_injection::FaultType;
+
+    use super::*;
+    use crate::api_server::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_fault_injection_request() {
+        parse_put_fault_injection(&Body::new("invalid_payload")).unwrap_err();
+
+        // PUT with an unknown fault type.
+        let body = r#"{
+            "device_id": "eth0",
+            "fault_type": "NetRx",
+            "enabled": true
+        }"#;
+        parse_put_fault_injection(&Body::new(body)).unwrap_err();
+
+        // PUT with valid fields.
+        let body = r#"{
+            "device_id": "eth0",
+            "fault_type": "NetTx",
+            "enabled": true
+        }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_fault_injection(&Body::new(body)).unwrap()),
+            VmmAction::InjectFault(FaultInjectionConfig {
+                device_id: String::from("eth0"),
+                fault_type: FaultType::NetTx,
+                enabled: true,
+            })
+        );
+    }
+}
@@ -6,11 +6,20 @@ use vmm::rpc_interface::VmmAction;
 use vmm::vmm_config::machine_config::{MachineConfig, MachineConfigUpdate};
 
 use super::super::parsed_request::{method_to_error, ParsedRequest, RequestError};
-use super::{Body, Method};
+use super::{Body, Method, StatusCode};
 
-pub(crate) fn parse_get_machine_config() -> Result<ParsedRequest, RequestError> {
+pub(crate) fn parse_get_machine_config(
+    path_second_token: Option<&str>,
+) -> Result<ParsedRequest, RequestError> {
     METRICS.get_api_requests.machine_cfg_count.inc();
-    Ok(ParsedRequest::new_sync(VmmAction::GetVmMachineConfig))
+    match path_second_token {
+        None => Ok(ParsedRequest::new_sync(VmmAction::GetVmMachineConfig)),
+        Some("memory-map") => Ok(ParsedRequest::new_sync(VmmAction::GetMemoryMap)),
+        Some(path) => Err(RequestError::Generic(
+            StatusCode::BadRequest,
+            format!("Unrecognized GET request path `{}`.", path),
+        )),
+    }
 }
 
 pub(crate) fn parse_put_machine_config(body: &Body) -> Result<ParsedRequest, RequestError> {
@@ -74,15 +83,27 @@ pub(crate) fn parse_patch_machine_config(body: &Body) -> Result<ParsedRequest, R
 #[cfg(test)]
 mod tests {
     use vmm::cpu_config::templates::StaticCpuTemplate;
-    use vmm::vmm_config::machine_config::HugePageConfig;
+    use vmm::vmm_config::machine_config::{
+        DirtyTrackingMode, GuestMemoryRange, HugePageConfig, MemoryBackendType, MemoryLayoutConfig,
+        VirtioFeaturePolicy,
+    };
 
     use super::*;
     use crate::api_server::parsed_request::tests::{depr_action_from_req, vmm_action_from_request};
 
     #[test]
     fn test_parse_get_machine_config_request() {
-        parse_get_machine_config().unwrap();
+        assert_eq!(
+            vmm_action_from_request(parse_get_machine_config(None).unwrap()),
+            VmmAction::GetVmMachineConfig
+        );
         assert!(METRICS.get_api_requests.machine_cfg_count.count() > 0);
+
+        assert_eq!(
+            vmm_action_from_request(parse_get_machine_config(Some("memory-map")).unwrap()),
+            VmmAction::GetMemoryMap
+        );
+        parse_get_machine_config(Some("invalid")).unwrap_err();
     }
 
     #[test]
@@ -122,7 +143,18 @@ mod tests {
                 smt: Some(false),
                 cpu_template: None,
                 track_dirty_pages: Some(false),
+                dirty_tracking_mode: Some(DirtyTrackingMode::Auto),
                 huge_pages: Some(expected),
+                memory_backend: Some(MemoryBackendType::Auto),
+                mem_populate: Some(false),
+                mem_prefault_ranges: Some(vec![]),
+                mem_mergeable: Some(false),
+                mem_mergeable_ranges: Some(vec![]),
+                confidential: None,
+                memory_layout: Some(MemoryLayoutConfig::default()),
+                virtio_feature_policy: Some(VirtioFeaturePolicy::default()),
+                vcpu_threads: Some(Vec::new()),
+                pmu: Some(false),
             };
             assert_eq!(
                 vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()),
@@ -141,7 +173,18 @@ mod tests {
             smt: Some(false),
             cpu_template: Some(StaticCpuTemplate::None),
             track_dirty_pages: Some(false),
+            dirty_tracking_mode: Some(DirtyTrackingMode::Auto),
             huge_pages: Some(HugePageConfig::None),
+            memory_backend: Some(MemoryBackendType::Auto),
+            mem_populate: Some(false),
+            mem_prefault_ranges: Some(vec![]),
+            mem_mergeable: Some(false),
+            mem_mergeable_ranges: Some(vec![]),
+            confidential: None,
+            memory_layout: Some(MemoryLayoutConfig::default()),
+            virtio_feature_policy: Some(VirtioFeaturePolicy::default()),
+            vcpu_threads: Some(Vec::new()),
+            pmu: Some(false),
         };
         assert_eq!(
             vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()),
@@ -160,7 +203,18 @@ mod tests {
             smt: Some(false),
             cpu_template: None,
             track_dirty_pages: Some(true),
+            dirty_tracking_mode: Some(DirtyTrackingMode::Auto),
             huge_pages: Some(HugePageConfig::None),
+            memory_backend: Some(MemoryBackendType::Auto),
+            mem_populate: Some(false),
+            mem_prefault_ranges: Some(vec![]),
+            mem_mergeable: Some(false),
+            mem_mergeable_ranges: Some(vec![]),
+            confidential: None,
+            memory_layout: Some(MemoryLayoutConfig::default()),
+            virtio_feature_policy: Some(VirtioFeaturePolicy::default()),
+            vcpu_threads: Some(Vec::new()),
+            pmu: Some(false),
         };
         assert_eq!(
             vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()),
@@ -183,7 +237,18 @@ mod tests {
                 smt: Some(false),
                 cpu_template: Some(StaticCpuTemplate::T2),
                 track_dirty_pages: Some(true),
+                dirty_tracking_mode: Some(DirtyTrackingMode::Auto),
                 huge_pages: Some(HugePageConfig::None),
+                memory_backend: Some(MemoryBackendType::Auto),
+                mem_populate: Some(false),
+                mem_prefault_ranges: Some(vec![]),
+                mem_mergeable: Some(false),
+                mem_mergeable_ranges: Some(vec![]),
+                confidential: None,
+                memory_layout: Some(MemoryLayoutConfig::default()),
+                virtio_feature_policy: Some(VirtioFeaturePolicy::default()),
+                vcpu_threads: Some(Vec::new()),
+                pmu: Some(false),
             };
             assert_eq!(
                 vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()),
@@ -208,14 +273,63 @@ mod tests {
             smt: Some(true),
             cpu_template: None,
             track_dirty_pages: Some(true),
+            dirty_tracking_mode: Some(DirtyTrackingMode::Auto),
+            huge_pages: Some(HugePageConfig::None),
+            memory_backend: Some(MemoryBackendType::Auto),
+            mem_populate: Some(false),
+            mem_prefault_ranges: Some(vec![]),
+            mem_mergeable: Some(false),
+            mem_mergeable_ranges: Some(vec![]),
+            confidential: None,
+            memory_layout: Some(MemoryLayoutConfig::default()),
+            virtio_feature_policy: Some(VirtioFeaturePolicy::default()),
+            vcpu_threads: Some(Vec::new()),
+            pmu: Some(false),
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()),
+            VmmAction::UpdateVmConfiguration(expected_config)
+        );
+
+        // 6. Test that the memory pre-fault and KSM options are parsed
+        let body = r#"{
+            "vcpu_count": 8,
+            "mem_size_mib": 1024,
+            "mem_populate": true,
+            "mem_prefault_ranges": [{ "guest_addr": 4096, "len": 8192 }],
+            "mem_mergeable_ranges": [{ "guest_addr": 0, "len": 4096 }]
+        }"#;
+        let expected_config = MachineConfigUpdate {
+            vcpu_count: Some(8),
+            mem_size_mib: Some(1024),
+            smt: Some(false),
+            cpu_template: None,
+            track_dirty_pages: Some(false),
+            dirty_tracking_mode: Some(DirtyTrackingMode::Auto),
             huge_pages: Some(HugePageConfig::None),
+            memory_backend: Some(MemoryBackendType::Auto),
+            mem_populate: Some(true),
+            mem_prefault_ranges: Some(vec![GuestMemoryRange {
+                guest_addr: 4096,
+                len: 8192,
+            }]),
+            mem_mergeable: Some(false),
+            mem_mergeable_ranges: Some(vec![GuestMemoryRange {
+                guest_addr: 0,
+                len: 4096,
+            }]),
+            confidential: None,
+            memory_layout: Some(MemoryLayoutConfig::default()),
+            virtio_feature_policy: Some(VirtioFeaturePolicy::default()),
+            vcpu_threads: Some(Vec::new()),
+            pmu: Some(false),
         };
         assert_eq!(
             vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()),
             VmmAction::UpdateVmConfiguration(expected_config)
         );
 
-        // 6. Test nonsense values for huge page size
+        // 7. Test nonsense values for huge page size
         let body = r#"{
             "vcpu_count": 8,
             "mem_size_mib": 1024,
@@ -0,0 +1,35 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::mmio_trace::MmioTraceConfig;
+
+use super::super::parsed_request::{ParsedRequest, RequestError};
+use super::Body;
+
+pub(crate) fn parse_put_mmio_trace(body: &Body) -> Result<ParsedRequest, RequestError> {
+    let cfg = serde_json::from_slice::<MmioTraceConfig>(body.raw())?;
+    Ok(ParsedRequest::new_sync(VmmAction::SetMmioTrace(cfg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_server::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_mmio_trace_request() {
+        parse_put_mmio_trace(&Body::new("invalid_payload")).unwrap_err();
+
+        // PUT without the device IDs.
+        parse_put_mmio_trace(&Body::new("{}")).unwrap_err();
+
+        let body = r#"{"device_ids": ["rootfs"]}"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_mmio_trace(&Body::new(body)).unwrap()),
+            VmmAction::SetMmioTrace(MmioTraceConfig {
+                device_ids: vec![String::from("rootfs")],
+            })
+        );
+    }
+}
@@ -4,16 +4,27 @@
 pub mod actions;
 pub mod balloon;
 pub mod boot_source;
+pub mod config_batch;
 pub mod cpu_configuration;
+pub mod crypto;
+pub mod devices;
 pub mod drive;
 pub mod entropy;
+pub mod fault_injection;
 pub mod instance_info;
 pub mod logger;
 pub mod machine_configuration;
 pub mod metrics;
 pub mod mmds;
+pub mod mmio_trace;
 pub mod net;
+pub mod port_forward;
+pub mod remote_device;
+pub mod replay;
 pub mod snapshot;
+pub mod tpm;
+pub mod vcpu;
 pub mod version;
+pub mod vmcore;
 pub mod vsock;
 pub use micro_http::{Body, Method, StatusCode};
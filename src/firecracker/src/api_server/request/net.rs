@@ -1,9 +1,12 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use vmm::devices::virtio::net::dhcp::DhcpConfig;
 use vmm::logger::{IncMetric, METRICS};
 use vmm::rpc_interface::VmmAction;
-use vmm::vmm_config::net::{NetworkInterfaceConfig, NetworkInterfaceUpdateConfig};
+use vmm::vmm_config::net::{
+    NetworkInterfaceConfig, NetworkInterfaceLinkConfig, NetworkInterfaceUpdateConfig,
+};
 
 use super::super::parsed_request::{checked_id, ParsedRequest, RequestError};
 use super::{Body, StatusCode};
@@ -40,6 +43,42 @@ pub(crate) fn parse_put_net(
     )))
 }
 
+pub(crate) fn parse_put_net_dhcp(
+    body: &Body,
+    id_from_path: Option<&str>,
+) -> Result<ParsedRequest, RequestError> {
+    METRICS.put_api_requests.network_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.put_api_requests.network_fails.inc();
+        return Err(RequestError::EmptyID);
+    };
+
+    let config = serde_json::from_slice::<DhcpConfig>(body.raw()).map_err(|err| {
+        METRICS.put_api_requests.network_fails.inc();
+        err
+    })?;
+    Ok(ParsedRequest::new_sync(VmmAction::SetNetworkInterfaceDhcp(
+        id.to_string(),
+        config,
+    )))
+}
+
+pub(crate) fn parse_get_net_rate_limiters(
+    id_from_path: Option<&str>,
+) -> Result<ParsedRequest, RequestError> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(RequestError::EmptyID);
+    };
+
+    Ok(ParsedRequest::new_sync(
+        VmmAction::GetNetworkInterfaceRateLimiters(id.to_string()),
+    ))
+}
+
 pub(crate) fn parse_patch_net(
     body: &Body,
     id_from_path: Option<&str>,
@@ -73,8 +112,32 @@ pub(crate) fn parse_patch_net(
     )))
 }
 
+pub(crate) fn parse_patch_net_link(
+    body: &Body,
+    id_from_path: Option<&str>,
+) -> Result<ParsedRequest, RequestError> {
+    METRICS.patch_api_requests.network_count.inc();
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        METRICS.patch_api_requests.network_fails.inc();
+        return Err(RequestError::EmptyID);
+    };
+
+    let config =
+        serde_json::from_slice::<NetworkInterfaceLinkConfig>(body.raw()).map_err(|err| {
+            METRICS.patch_api_requests.network_fails.inc();
+            err
+        })?;
+    Ok(ParsedRequest::new_sync(
+        VmmAction::UpdateNetworkInterfaceLink(id.to_string(), config),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
+    use vmm::vmm_config::net::LinkState;
+
     use super::*;
     use crate::api_server::parsed_request::tests::vmm_action_from_request;
 
@@ -116,6 +179,45 @@ mod tests {
         parse_put_net(&Body::new(body), Some("foo")).unwrap_err();
     }
 
+    #[test]
+    fn test_parse_get_net_rate_limiters_request() {
+        // 1. The `id_from_path` cannot be None.
+        parse_get_net_rate_limiters(None).unwrap_err();
+
+        // 2. Success case.
+        assert_eq!(
+            vmm_action_from_request(parse_get_net_rate_limiters(Some("foo")).unwrap()),
+            VmmAction::GetNetworkInterfaceRateLimiters(String::from("foo"))
+        );
+    }
+
+    #[test]
+    fn test_parse_put_net_dhcp_request() {
+        let body = r#"{
+            "ip_address": "192.168.0.2",
+            "prefix_length": 24,
+            "gateway": "192.168.0.1",
+            "nameservers": ["1.1.1.1"]
+        }"#;
+        // 1. The `id_from_path` cannot be None.
+        parse_put_net_dhcp(&Body::new(body), None).unwrap_err();
+
+        // 2. Success case.
+        let expected_config = serde_json::from_str::<DhcpConfig>(body).unwrap();
+        assert_eq!(
+            vmm_action_from_request(parse_put_net_dhcp(&Body::new(body), Some("foo")).unwrap()),
+            VmmAction::SetNetworkInterfaceDhcp(String::from("foo"), expected_config)
+        );
+
+        // 3. Serde error for unknown field.
+        let body = r#"{
+            "ip_address": "192.168.0.2",
+            "prefix_length": 24,
+            "lease_time": 3600
+        }"#;
+        parse_put_net_dhcp(&Body::new(body), Some("foo")).unwrap_err();
+    }
+
     #[test]
     fn test_parse_patch_net_request() {
         let body = r#"{
@@ -152,5 +254,39 @@ mod tests {
             }
         }"#;
         parse_patch_net(&Body::new(body), Some("foo")).unwrap_err();
+
+        // 5. Guest MAC update.
+        let body = r#"{
+            "iface_id": "foo",
+            "guest_mac": "12:34:56:78:9A:BC"
+        }"#;
+        let expected_config = serde_json::from_str::<NetworkInterfaceUpdateConfig>(body).unwrap();
+        assert!(expected_config.guest_mac.is_some());
+        assert_eq!(
+            vmm_action_from_request(parse_patch_net(&Body::new(body), Some("foo")).unwrap()),
+            VmmAction::UpdateNetworkInterface(expected_config)
+        );
+    }
+
+    #[test]
+    fn test_parse_patch_net_link_request() {
+        // 1. The `id_from_path` cannot be None.
+        let body = r#"{ "state": "Down" }"#;
+        parse_patch_net_link(&Body::new(body), None).unwrap_err();
+
+        // 2. Success case.
+        assert_eq!(
+            vmm_action_from_request(parse_patch_net_link(&Body::new(body), Some("foo")).unwrap()),
+            VmmAction::UpdateNetworkInterfaceLink(
+                String::from("foo"),
+                NetworkInterfaceLinkConfig {
+                    state: LinkState::Down
+                }
+            )
+        );
+
+        // 3. Serde error for an unknown state.
+        let body = r#"{ "state": "Flapping" }"#;
+        parse_patch_net_link(&Body::new(body), Some("foo")).unwrap_err();
     }
 }
@@ -0,0 +1,82 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::port_forward::PortForwardConfig;
+
+use super::super::parsed_request::{ParsedRequest, RequestError};
+use super::Body;
+
+pub(crate) fn parse_put_port_forwards(body: &Body) -> Result<ParsedRequest, RequestError> {
+    let configs = serde_json::from_slice::<Vec<PortForwardConfig>>(body.raw())?;
+    Ok(ParsedRequest::new_sync(VmmAction::SetPortForwards(configs)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_server::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_port_forwards_request() {
+        parse_put_port_forwards(&Body::new("invalid_payload")).unwrap_err();
+
+        // PUT with a single object instead of a list.
+        let body = r#"{
+            "iface_id": "eth0",
+            "host_port": 8080,
+            "guest_address": "192.168.0.2",
+            "guest_port": 80
+        }"#;
+        parse_put_port_forwards(&Body::new(body)).unwrap_err();
+
+        // PUT with invalid fields.
+        let body = r#"[{
+            "iface_id": "eth0",
+            "host_port": 8080,
+            "guest_address": "192.168.0.2",
+            "guest_port": 80,
+            "protocol": "udp"
+        }]"#;
+        parse_put_port_forwards(&Body::new(body)).unwrap_err();
+
+        // PUT with valid fields.
+        let body = r#"[{
+            "iface_id": "eth0",
+            "host_port": 8080,
+            "guest_address": "192.168.0.2",
+            "guest_port": 80
+        }, {
+            "iface_id": "eth0",
+            "host_address": "0.0.0.0",
+            "host_port": 2222,
+            "guest_address": "192.168.0.2",
+            "guest_port": 22
+        }]"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_port_forwards(&Body::new(body)).unwrap()),
+            VmmAction::SetPortForwards(vec![
+                PortForwardConfig {
+                    iface_id: String::from("eth0"),
+                    host_address: "127.0.0.1".parse().unwrap(),
+                    host_port: 8080,
+                    guest_address: "192.168.0.2".parse().unwrap(),
+                    guest_port: 80,
+                },
+                PortForwardConfig {
+                    iface_id: String::from("eth0"),
+                    host_address: "0.0.0.0".parse().unwrap(),
+                    host_port: 2222,
+                    guest_address: "192.168.0.2".parse().unwrap(),
+                    guest_port: 22,
+                },
+            ])
+        );
+
+        // An empty list removes the port forwards.
+        assert_eq!(
+            vmm_action_from_request(parse_put_port_forwards(&Body::new("[]")).unwrap()),
+            VmmAction::SetPortForwards(Vec::new())
+        );
+    }
+}
@@ -0,0 +1,76 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::remote_device::RemoteDeviceConfig;
+
+use super::super::parsed_request::{checked_id, ParsedRequest, RequestError};
+use super::{Body, StatusCode};
+
+pub(crate) fn parse_put_remote_device(
+    body: &Body,
+    id_from_path: Option<&str>,
+) -> Result<ParsedRequest, RequestError> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(RequestError::EmptyID);
+    };
+
+    let config = serde_json::from_slice::<RemoteDeviceConfig>(body.raw())?;
+    if id != config.id.as_str() {
+        return Err(RequestError::Generic(
+            StatusCode::BadRequest,
+            format!(
+                "The id from the path [{}] does not match the id from the body [{}]!",
+                id,
+                config.id.as_str()
+            ),
+        ));
+    }
+    Ok(ParsedRequest::new_sync(VmmAction::InsertRemoteDevice(
+        config,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_server::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_remote_device_request() {
+        let body = r#"{
+            "id": "foo",
+            "device_type": 3,
+            "socket": "/tmp/remote.sock"
+        }"#;
+        parse_put_remote_device(&Body::new(body), None).unwrap_err();
+        parse_put_remote_device(&Body::new(body), Some("bar")).unwrap_err();
+        parse_put_remote_device(&Body::new("invalid_payload"), Some("foo")).unwrap_err();
+
+        let expected_config = RemoteDeviceConfig {
+            id: String::from("foo"),
+            device_type: 3,
+            socket: String::from("/tmp/remote.sock"),
+            num_queues: 1,
+            queue_size: 256,
+            config_size: 0,
+        };
+        assert_eq!(
+            vmm_action_from_request(
+                parse_put_remote_device(&Body::new(body), Some("foo")).unwrap()
+            ),
+            VmmAction::InsertRemoteDevice(expected_config)
+        );
+
+        // PUT with invalid fields.
+        let body = r#"{
+            "id": "foo",
+            "device_type": 3,
+            "socket": "/tmp/remote.sock",
+            "some_field": 1
+        }"#;
+        parse_put_remote_device(&Body::new(body), Some("foo")).unwrap_err();
+    }
+}
@@ -0,0 +1,51 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::replay::ReplayConfig;
+
+use super::super::parsed_request::{ParsedRequest, RequestError};
+use super::Body;
+
+pub(crate) fn parse_put_replay(body: &Body) -> Result<ParsedRequest, RequestError> {
+    let cfg = serde_json::from_slice::<ReplayConfig>(body.raw())?;
+    Ok(ParsedRequest::new_sync(VmmAction::SetReplay(cfg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use vmm::vmm_config::replay::ReplayMode;
+
+    use super::*;
+    use crate::api_server::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_replay_request() {
+        parse_put_replay(&Body::new("invalid_payload")).unwrap_err();
+
+        // PUT without the log path.
+        parse_put_replay(&Body::new(r#"{"mode": "Record"}"#)).unwrap_err();
+
+        // PUT with an invalid mode.
+        let body = r#"{
+            "mode": "Rewind",
+            "log_path": "/tmp/replay.log"
+        }"#;
+        parse_put_replay(&Body::new(body)).unwrap_err();
+
+        // PUT with valid fields.
+        let body = r#"{
+            "mode": "Replay",
+            "log_path": "/tmp/replay.log"
+        }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_replay(&Body::new(body)).unwrap()),
+            VmmAction::SetReplay(ReplayConfig {
+                mode: ReplayMode::Replay,
+                log_path: PathBuf::from("/tmp/replay.log"),
+            })
+        );
+    }
+}
@@ -6,7 +6,7 @@ use vmm::logger::{IncMetric, METRICS};
 use vmm::rpc_interface::VmmAction;
 use vmm::vmm_config::snapshot::{
     CreateSnapshotParams, LoadSnapshotConfig, LoadSnapshotParams, MemBackendConfig, MemBackendType,
-    Vm, VmState,
+    PauseMode, Vm, VmState,
 };
 
 use super::super::parsed_request::{ParsedRequest, RequestError};
@@ -17,6 +17,11 @@ const LOAD_DEPRECATION_MESSAGE: &str = "PUT /snapshot/load: mem_file_path field
 /// None of the `mem_backend` or `mem_file_path` fields has been specified.
 pub const MISSING_FIELD: &str =
     "missing field: either `mem_backend` or `mem_file_path` is required";
+/// The `quiesce_devices` field has been specified for a resume.
+pub const QUIESCE_ON_RESUME: &str = "`quiesce_devices` is only supported when pausing the microVM";
+/// The `resume_time_handling` field has been specified for a pause.
+pub const TIME_HANDLING_ON_PAUSE: &str =
+    "`resume_time_handling` is only supported when resuming the microVM";
 /// Both the `mem_backend` and `mem_file_path` fields have been specified.
 /// Only specifying one of them is allowed.
 pub const TOO_MANY_FIELDS: &str =
@@ -45,9 +50,22 @@ pub(crate) fn parse_put_snapshot(
 pub(crate) fn parse_patch_vm_state(body: &Body) -> Result<ParsedRequest, RequestError> {
     let vm = serde_json::from_slice::<Vm>(body.raw())?;
 
-    match vm.state {
-        VmState::Paused => Ok(ParsedRequest::new_sync(VmmAction::Pause)),
-        VmState::Resumed => Ok(ParsedRequest::new_sync(VmmAction::Resume)),
+    match (vm.state, vm.quiesce_devices) {
+        (VmState::Paused, _) if vm.resume_time_handling.is_some() => Err(RequestError::Generic(
+            StatusCode::BadRequest,
+            TIME_HANDLING_ON_PAUSE.to_string(),
+        )),
+        (VmState::Paused, false) => Ok(ParsedRequest::new_sync(VmmAction::Pause(PauseMode::Vcpus))),
+        (VmState::Paused, true) => Ok(ParsedRequest::new_sync(VmmAction::Pause(
+            PauseMode::VcpusAndDevices,
+        ))),
+        (VmState::Resumed, false) => Ok(ParsedRequest::new_sync(VmmAction::Resume(
+            vm.resume_time_handling.unwrap_or_default(),
+        ))),
+        (VmState::Resumed, true) => Err(RequestError::Generic(
+            StatusCode::BadRequest,
+            QUIESCE_ON_RESUME.to_string(),
+        )),
     }
 }
 
@@ -105,6 +123,10 @@ fn parse_put_snapshot_load(body: &Body) -> Result<ParsedRequest, RequestError> {
         mem_backend,
         enable_diff_snapshots: snapshot_config.enable_diff_snapshots,
         resume_vm: snapshot_config.resume_vm,
+        verify: snapshot_config.verify,
+        encryption: snapshot_config.encryption,
+        allow_cpu_mismatch: snapshot_config.allow_cpu_mismatch,
+        overrides: snapshot_config.overrides,
     };
 
     // Construct the `ParsedRequest` object.
@@ -120,7 +142,10 @@ fn parse_put_snapshot_load(body: &Body) -> Result<ParsedRequest, RequestError> {
 
 #[cfg(test)]
 mod tests {
-    use vmm::vmm_config::snapshot::{MemBackendConfig, MemBackendType};
+    use vmm::vmm_config::snapshot::{
+        DriveOverride, MemBackendConfig, MemBackendType, NetworkInterfaceOverride,
+        ResumeTimeHandling, SnapshotOverrides, VsockOverride,
+    };
 
     use super::*;
     use crate::api_server::parsed_request::tests::{depr_action_from_req, vmm_action_from_request};
@@ -129,7 +154,7 @@ mod tests {
     fn test_parse_put_snapshot() {
         use std::path::PathBuf;
 
-        use vmm::vmm_config::snapshot::SnapshotType;
+        use vmm::vmm_config::snapshot::{SnapshotType, Version};
 
         let body = r#"{
             "snapshot_type": "Diff",
@@ -140,6 +165,10 @@ mod tests {
             snapshot_type: SnapshotType::Diff,
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
+            encryption: None,
+            manifest_path: None,
+            sparse: false,
+            snapshot_version: None,
         };
         assert_eq!(
             vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("create")).unwrap()),
@@ -154,6 +183,49 @@ mod tests {
             snapshot_type: SnapshotType::Full,
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
+            encryption: None,
+            manifest_path: None,
+            sparse: false,
+            snapshot_version: None,
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("create")).unwrap()),
+            VmmAction::CreateSnapshot(expected_config)
+        );
+
+        let body = r#"{
+            "snapshot_path": "foo",
+            "mem_file_path": "bar",
+            "manifest_path": "baz",
+            "sparse": true
+        }"#;
+        let expected_config = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: PathBuf::from("foo"),
+            mem_file_path: PathBuf::from("bar"),
+            encryption: None,
+            manifest_path: Some(PathBuf::from("baz")),
+            sparse: true,
+            snapshot_version: None,
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("create")).unwrap()),
+            VmmAction::CreateSnapshot(expected_config)
+        );
+
+        let body = r#"{
+            "snapshot_path": "foo",
+            "mem_file_path": "bar",
+            "snapshot_version": "2.0.0"
+        }"#;
+        let expected_config = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: PathBuf::from("foo"),
+            mem_file_path: PathBuf::from("bar"),
+            encryption: None,
+            manifest_path: None,
+            sparse: false,
+            snapshot_version: Some(Version::new(2, 0, 0)),
         };
         assert_eq!(
             vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("create")).unwrap()),
@@ -181,6 +253,10 @@ mod tests {
             },
             enable_diff_snapshots: false,
             resume_vm: false,
+            verify: false,
+            encryption: None,
+            allow_cpu_mismatch: false,
+            overrides: Default::default(),
         };
         let mut parsed_request = parse_put_snapshot(&Body::new(body), Some("load")).unwrap();
         assert!(parsed_request
@@ -208,6 +284,10 @@ mod tests {
             },
             enable_diff_snapshots: true,
             resume_vm: false,
+            verify: false,
+            encryption: None,
+            allow_cpu_mismatch: false,
+            overrides: Default::default(),
         };
         let mut parsed_request = parse_put_snapshot(&Body::new(body), Some("load")).unwrap();
         assert!(parsed_request
@@ -235,6 +315,10 @@ mod tests {
             },
             enable_diff_snapshots: false,
             resume_vm: true,
+            verify: false,
+            encryption: None,
+            allow_cpu_mismatch: false,
+            overrides: Default::default(),
         };
         let mut parsed_request = parse_put_snapshot(&Body::new(body), Some("load")).unwrap();
         assert!(parsed_request
@@ -246,6 +330,78 @@ mod tests {
             VmmAction::LoadSnapshot(expected_config)
         );
 
+        let body = r#"{
+            "snapshot_path": "foo",
+            "mem_backend": {
+                "backend_path": "bar",
+                "backend_type": "File"
+            },
+            "verify": true,
+            "allow_cpu_mismatch": true
+        }"#;
+        let expected_config = LoadSnapshotParams {
+            snapshot_path: PathBuf::from("foo"),
+            mem_backend: MemBackendConfig {
+                backend_path: PathBuf::from("bar"),
+                backend_type: MemBackendType::File,
+            },
+            enable_diff_snapshots: false,
+            resume_vm: false,
+            verify: true,
+            encryption: None,
+            allow_cpu_mismatch: true,
+            overrides: Default::default(),
+        };
+        let parsed_request = parse_put_snapshot(&Body::new(body), Some("load")).unwrap();
+        assert_eq!(
+            vmm_action_from_request(parsed_request),
+            VmmAction::LoadSnapshot(expected_config)
+        );
+
+        let body = r#"{
+            "snapshot_path": "foo",
+            "mem_backend": {
+                "backend_path": "bar",
+                "backend_type": "File"
+            },
+            "overrides": {
+                "drives": [{"drive_id": "rootfs", "path_on_host": "/srv/rootfs.ext4"}],
+                "network_interfaces": [{"iface_id": "eth0", "host_dev_name": "tap1"}],
+                "vsock_devices": [{"uds_path": "/srv/v.sock"}]
+            }
+        }"#;
+        let expected_config = LoadSnapshotParams {
+            snapshot_path: PathBuf::from("foo"),
+            mem_backend: MemBackendConfig {
+                backend_path: PathBuf::from("bar"),
+                backend_type: MemBackendType::File,
+            },
+            enable_diff_snapshots: false,
+            resume_vm: false,
+            verify: false,
+            encryption: None,
+            allow_cpu_mismatch: false,
+            overrides: SnapshotOverrides {
+                drives: vec![DriveOverride {
+                    drive_id: String::from("rootfs"),
+                    path_on_host: String::from("/srv/rootfs.ext4"),
+                }],
+                network_interfaces: vec![NetworkInterfaceOverride {
+                    iface_id: String::from("eth0"),
+                    host_dev_name: String::from("tap1"),
+                }],
+                vsock_devices: vec![VsockOverride {
+                    vsock_id: None,
+                    uds_path: String::from("/srv/v.sock"),
+                }],
+            },
+        };
+        let parsed_request = parse_put_snapshot(&Body::new(body), Some("load")).unwrap();
+        assert_eq!(
+            vmm_action_from_request(parsed_request),
+            VmmAction::LoadSnapshot(expected_config)
+        );
+
         let body = r#"{
             "snapshot_path": "foo",
             "mem_file_path": "bar",
@@ -259,6 +415,10 @@ mod tests {
             },
             enable_diff_snapshots: false,
             resume_vm: true,
+            verify: false,
+            encryption: None,
+            allow_cpu_mismatch: false,
+            overrides: Default::default(),
         };
         let parsed_request = parse_put_snapshot(&Body::new(body), Some("load")).unwrap();
         assert_eq!(
@@ -350,14 +510,58 @@ mod tests {
         }"#;
         assert!(parse_patch_vm_state(&Body::new(body))
             .unwrap()
-            .eq(&ParsedRequest::new_sync(VmmAction::Pause)));
+            .eq(&ParsedRequest::new_sync(VmmAction::Pause(PauseMode::Vcpus))));
+
+        let body = r#"{
+            "state": "Paused",
+            "quiesce_devices": true
+        }"#;
+        assert!(parse_patch_vm_state(&Body::new(body))
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::Pause(
+                PauseMode::VcpusAndDevices
+            ))));
 
         let body = r#"{
             "state": "Resumed"
         }"#;
         assert!(parse_patch_vm_state(&Body::new(body))
             .unwrap()
-            .eq(&ParsedRequest::new_sync(VmmAction::Resume)));
+            .eq(&ParsedRequest::new_sync(VmmAction::Resume(
+                ResumeTimeHandling::None
+            ))));
+
+        let body = r#"{
+            "state": "Resumed",
+            "resume_time_handling": "adjust"
+        }"#;
+        assert!(parse_patch_vm_state(&Body::new(body))
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::Resume(
+                ResumeTimeHandling::Adjust
+            ))));
+
+        let invalid_body = r#"{
+            "state": "Paused",
+            "resume_time_handling": "none"
+        }"#;
+        assert_eq!(
+            parse_patch_vm_state(&Body::new(invalid_body))
+                .unwrap_err()
+                .to_string(),
+            TIME_HANDLING_ON_PAUSE
+        );
+
+        let invalid_body = r#"{
+            "state": "Resumed",
+            "quiesce_devices": true
+        }"#;
+        assert_eq!(
+            parse_patch_vm_state(&Body::new(invalid_body))
+                .unwrap_err()
+                .to_string(),
+            QUIESCE_ON_RESUME
+        );
 
         let invalid_body = r#"{
             "invalid": "Paused"
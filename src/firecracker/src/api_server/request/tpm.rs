@@ -0,0 +1,45 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::tpm::TpmConfig;
+
+use super::super::parsed_request::{ParsedRequest, RequestError};
+use super::Body;
+
+pub(crate) fn parse_put_tpm(body: &Body) -> Result<ParsedRequest, RequestError> {
+    let cfg = serde_json::from_slice::<TpmConfig>(body.raw())?;
+    Ok(ParsedRequest::new_sync(VmmAction::SetTpm(cfg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_server::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_tpm_request() {
+        parse_put_tpm(&Body::new("invalid_payload")).unwrap_err();
+
+        // PUT without the socket.
+        parse_put_tpm(&Body::new("{}")).unwrap_err();
+
+        // PUT with invalid fields.
+        let body = r#"{
+            "socket": "/tmp/swtpm.sock",
+            "version": "2.0"
+        }"#;
+        parse_put_tpm(&Body::new(body)).unwrap_err();
+
+        // PUT with valid fields.
+        let body = r#"{
+            "socket": "/tmp/swtpm.sock"
+        }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_tpm(&Body::new(body)).unwrap()),
+            VmmAction::SetTpm(TpmConfig {
+                socket: String::from("/tmp/swtpm.sock"),
+            })
+        );
+    }
+}
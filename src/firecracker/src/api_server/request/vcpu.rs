@@ -0,0 +1,59 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+
+use super::super::parsed_request::{ParsedRequest, RequestError};
+use super::{Method, StatusCode};
+
+pub(crate) fn parse_get_vcpu_registers<'a, T>(
+    mut path_tokens: T,
+) -> Result<ParsedRequest, RequestError>
+where
+    T: Iterator<Item = &'a str>,
+{
+    let index = match path_tokens.next() {
+        Some(id) => id.parse::<u8>().map_err(|_| {
+            RequestError::Generic(StatusCode::BadRequest, format!("Invalid vCPU id: {}.", id))
+        })?,
+        None => return Err(RequestError::EmptyID),
+    };
+
+    match (path_tokens.next(), path_tokens.next()) {
+        (Some("registers"), None) => {
+            Ok(ParsedRequest::new_sync(VmmAction::GetVcpuRegisters(index)))
+        }
+        _ => Err(RequestError::InvalidPathMethod(
+            format!("/vm/vcpus/{}", index),
+            Method::Get,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_server::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_get_vcpu_registers_request() {
+        let tokens = "0/registers".split('/');
+        assert_eq!(
+            vmm_action_from_request(parse_get_vcpu_registers(tokens).unwrap()),
+            VmmAction::GetVcpuRegisters(0)
+        );
+
+        let tokens = "31/registers".split('/');
+        assert_eq!(
+            vmm_action_from_request(parse_get_vcpu_registers(tokens).unwrap()),
+            VmmAction::GetVcpuRegisters(31)
+        );
+
+        parse_get_vcpu_registers("".split_terminator('/')).unwrap_err();
+        parse_get_vcpu_registers("foo/registers".split('/')).unwrap_err();
+        parse_get_vcpu_registers("256/registers".split('/')).unwrap_err();
+        parse_get_vcpu_registers("0".split('/')).unwrap_err();
+        parse_get_vcpu_registers("0/regs".split('/')).unwrap_err();
+        parse_get_vcpu_registers("0/registers/foo".split('/')).unwrap_err();
+    }
+}
@@ -0,0 +1,44 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::vmcore::CreateVmcoreParams;
+
+use super::super::parsed_request::{ParsedRequest, RequestError};
+use super::Body;
+
+pub(crate) fn parse_put_vmcore(body: &Body) -> Result<ParsedRequest, RequestError> {
+    let cfg = serde_json::from_slice::<CreateVmcoreParams>(body.raw())?;
+    Ok(ParsedRequest::new_sync(VmmAction::CreateVmcore(cfg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::api_server::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_vmcore_request() {
+        parse_put_vmcore(&Body::new("invalid_payload")).unwrap_err();
+
+        // PUT with invalid fields.
+        let body = r#"{
+            "vmcore_path": "/tmp/vmcore",
+            "mem_file_path": "/tmp/mem"
+        }"#;
+        parse_put_vmcore(&Body::new(body)).unwrap_err();
+
+        // PUT with valid fields.
+        let body = r#"{
+            "vmcore_path": "/tmp/vmcore"
+        }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_vmcore(&Body::new(body)).unwrap()),
+            VmmAction::CreateVmcore(CreateVmcoreParams {
+                vmcore_path: PathBuf::from("/tmp/vmcore"),
+            })
+        );
+    }
+}
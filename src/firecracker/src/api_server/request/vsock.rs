@@ -5,20 +5,46 @@ use vmm::logger::{IncMetric, METRICS};
 use vmm::rpc_interface::VmmAction;
 use vmm::vmm_config::vsock::VsockDeviceConfig;
 
-use super::super::parsed_request::{ParsedRequest, RequestError};
-use super::Body;
+use super::super::parsed_request::{checked_id, ParsedRequest, RequestError};
+use super::{Body, StatusCode};
 
-pub(crate) fn parse_put_vsock(body: &Body) -> Result<ParsedRequest, RequestError> {
+pub(crate) fn parse_put_vsock(
+    body: &Body,
+    id_from_path: Option<&str>,
+) -> Result<ParsedRequest, RequestError> {
     METRICS.put_api_requests.vsock_count.inc();
-    let vsock_cfg = serde_json::from_slice::<VsockDeviceConfig>(body.raw()).map_err(|err| {
+    let mut vsock_cfg = serde_json::from_slice::<VsockDeviceConfig>(body.raw()).map_err(|err| {
         METRICS.put_api_requests.vsock_fails.inc();
         err
     })?;
 
+    // `PUT /vsock/{id}` configures the vsock device with the given ID.
+    if let Some(id) = id_from_path {
+        let id = checked_id(id).map_err(|err| {
+            METRICS.put_api_requests.vsock_fails.inc();
+            err
+        })?;
+        if vsock_cfg
+            .vsock_id
+            .as_deref()
+            .is_some_and(|body_id| body_id != id)
+        {
+            METRICS.put_api_requests.vsock_fails.inc();
+            return Err(RequestError::Generic(
+                StatusCode::BadRequest,
+                "The id from the path does not match the id from the body!".to_string(),
+            ));
+        }
+        vsock_cfg.vsock_id = Some(id.to_string());
+        return Ok(ParsedRequest::new_sync(VmmAction::SetVsockDevice(
+            vsock_cfg,
+        )));
+    }
+
     // Check for the presence of deprecated `vsock_id` field.
     let mut deprecation_message = None;
-    if vsock_cfg.vsock_id.is_some() {
-        // vsock_id field in request is deprecated.
+    if vsock_cfg.vsock_id.take().is_some() {
+        // vsock_id field in request is deprecated, `PUT /vsock` configures the default device.
         METRICS.deprecated_api.deprecated_http_api_calls.inc();
         deprecation_message = Some("PUT /vsock: vsock_id field is deprecated.");
     }
@@ -36,7 +62,7 @@ pub(crate) fn parse_put_vsock(body: &Body) -> Result<ParsedRequest, RequestError
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::api_server::parsed_request::tests::depr_action_from_req;
+    use crate::api_server::parsed_request::tests::{depr_action_from_req, vmm_action_from_request};
 
     #[test]
     fn test_parse_put_vsock_request() {
@@ -44,13 +70,53 @@ mod tests {
             "guest_cid": 42,
             "uds_path": "vsock.sock"
         }"#;
-        parse_put_vsock(&Body::new(body)).unwrap();
+        parse_put_vsock(&Body::new(body), None).unwrap();
 
         let body = r#"{
             "guest_cid": 42,
             "invalid_field": false
         }"#;
-        parse_put_vsock(&Body::new(body)).unwrap_err();
+        parse_put_vsock(&Body::new(body), None).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_put_vsock_with_id() {
+        let body = r#"{
+            "guest_cid": 42,
+            "uds_path": "vsock.sock"
+        }"#;
+        let expected_config = VsockDeviceConfig {
+            vsock_id: Some("data".to_string()),
+            guest_cid: 42,
+            uds_path: "vsock.sock".to_string(),
+            dma_ranges: None,
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_vsock(&Body::new(body), Some("data")).unwrap()),
+            VmmAction::SetVsockDevice(expected_config.clone())
+        );
+
+        let body = r#"{
+            "vsock_id": "data",
+            "guest_cid": 42,
+            "uds_path": "vsock.sock"
+        }"#;
+        let (action, mut parsing_info) = parse_put_vsock(&Body::new(body), Some("data"))
+            .unwrap()
+            .into_parts();
+        assert!(parsing_info.take_deprecation_message().is_none());
+        assert_eq!(
+            vmm_action_from_request(ParsedRequest::new(action)),
+            VmmAction::SetVsockDevice(expected_config)
+        );
+
+        let body = r#"{
+            "vsock_id": "control",
+            "guest_cid": 42,
+            "uds_path": "vsock.sock"
+        }"#;
+        parse_put_vsock(&Body::new(body), Some("data")).unwrap_err();
+        parse_put_vsock(&Body::new(body), Some("invalid id!")).unwrap_err();
     }
 
     #[test]
@@ -61,7 +127,7 @@ mod tests {
             "uds_path": "vsock.sock"
         }"#;
         depr_action_from_req(
-            parse_put_vsock(&Body::new(body)).unwrap(),
+            parse_put_vsock(&Body::new(body), None).unwrap(),
             Some("PUT /vsock: vsock_id field is deprecated.".to_string()),
         );
 
@@ -69,7 +135,9 @@ mod tests {
             "guest_cid": 42,
             "uds_path": "vsock.sock"
         }"#;
-        let (_, mut parsing_info) = parse_put_vsock(&Body::new(body)).unwrap().into_parts();
+        let (_, mut parsing_info) = parse_put_vsock(&Body::new(body), None)
+            .unwrap()
+            .into_parts();
         assert!(parsing_info.take_deprecation_message().is_none());
     }
 }
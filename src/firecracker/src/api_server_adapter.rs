@@ -7,16 +7,18 @@ use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
-use event_manager::{EventOps, Events, MutEventSubscriber, SubscriberOps};
+use event_manager::{EventOps, Events, MutEventSubscriber};
 use seccompiler::BpfThreadMap;
 use utils::epoll::EventSet;
 use utils::eventfd::EventFd;
+use vmm::event_loop::add_timed_subscriber;
 use vmm::logger::{error, warn, ProcessTimeReporter};
 use vmm::resources::VmResources;
 use vmm::rpc_interface::{
     ApiRequest, ApiResponse, BuildMicrovmFromRequestsError, PrebootApiController,
     RuntimeApiController, VmmAction,
 };
+use vmm::signal_handler::register_shutdown_signal_handler;
 use vmm::vmm_config::instance_info::InstanceInfo;
 use vmm::{EventManager, FcExitCode, Vmm};
 
@@ -61,7 +63,10 @@ impl ApiServerAdapter {
             to_api,
             controller: RuntimeApiController::new(vm_resources, vmm.clone()),
         }));
-        event_manager.add_subscriber(api_adapter);
+        add_timed_subscriber(event_manager, "api", api_adapter);
+        if let Err(err) = register_shutdown_signal_handler(vmm.lock().unwrap().shutdown_evt()) {
+            warn!("Failed to register the SIGTERM handler: {}", err);
+        }
         loop {
             event_manager
                 .run()
@@ -95,7 +100,7 @@ impl MutEventSubscriber for ApiServerAdapter {
             let _ = self.api_event_fd.read();
             match self.from_api.try_recv() {
                 Ok(api_request) => {
-                    let request_is_pause = *api_request == VmmAction::Pause;
+                    let request_is_pause = matches!(*api_request, VmmAction::Pause(_));
                     self.handle_request(*api_request);
 
                     // If the latest req is a pause request, temporarily switch to a mode where we
@@ -108,9 +113,10 @@ impl MutEventSubscriber for ApiServerAdapter {
                         // metric flush timerfd handling are frozen as well.
                         loop {
                             let req = self.from_api.recv().expect("Error receiving API request.");
-                            let req_is_resume = *req == VmmAction::Resume;
+                            let req_ends_pause =
+                                matches!(*req, VmmAction::Resume(_) | VmmAction::Shutdown(_));
                             self.handle_request(*req);
-                            if req_is_resume {
+                            if req_ends_pause {
                                 break;
                             }
                         }
@@ -146,6 +152,7 @@ pub(crate) fn run_with_api(
     api_payload_limit: usize,
     mmds_size_limit: usize,
     metadata_json: Option<&str>,
+    pressure_monitors: Vec<super::pressure::PressureMonitor>,
 ) -> Result<(), ApiServerError> {
     // FD to notify of API events. This is a blocking eventfd by design.
     // It is used in the config/pre-boot loop which is a simple blocking loop
@@ -201,7 +208,15 @@ pub(crate) fn run_with_api(
 
     // Create the firecracker metrics object responsible for periodically printing metrics.
     let firecracker_metrics = Arc::new(Mutex::new(super::metrics::PeriodicMetrics::new()));
-    event_manager.add_subscriber(firecracker_metrics.clone());
+    add_timed_subscriber(&mut event_manager, "metrics", firecracker_metrics.clone());
+
+    for monitor in pressure_monitors {
+        add_timed_subscriber(
+            &mut event_manager,
+            "pressure",
+            Arc::new(Mutex::new(monitor)),
+        );
+    }
 
     // Configure, build and start the microVM.
     let build_result = match config_json {
@@ -4,32 +4,43 @@
 mod api_server;
 mod api_server_adapter;
 mod metrics;
+mod pressure;
 mod seccomp;
 
 use std::fs::{self, File};
-use std::path::PathBuf;
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::{io, panic};
 
 use api_server_adapter::ApiServerError;
-use event_manager::SubscriberOps;
 use seccomp::FilterError;
 use seccompiler::BpfThreadMap;
 use utils::arg_parser::{ArgParser, Argument};
 use utils::terminal::Terminal;
+use utils::time::{get_time_ms, get_time_us, ClockType};
 use utils::validators::validate_instance_id;
 use vmm::builder::StartMicrovmError;
+use vmm::devices::virtio::balloon::set_memory_pressure_trigger;
+use vmm::event_loop::{add_timed_subscriber, set_event_loop_budget_us};
+use vmm::landlock::set_landlock;
+use vmm::lifecycle::{
+    add_lifecycle_fd, add_lifecycle_socket, notify_lifecycle_event, LifecycleError, LifecycleEvent,
+};
 use vmm::logger::{
-    debug, error, info, LoggerConfig, ProcessTimeReporter, StoreMetric, LOGGER, METRICS,
+    debug, error, info, warn, LoggerConfig, ProcessTimeReporter, StoreMetric, LOGGER, METRICS,
 };
 use vmm::persist::SNAPSHOT_VERSION;
 use vmm::resources::VmResources;
-use vmm::signal_handler::register_signal_handlers;
+use vmm::secret::set_secret_hardening;
+use vmm::signal_handler::{register_shutdown_signal_handler, register_signal_handlers};
+use vmm::snapshot::encryption::{add_key_fd, SnapshotEncryptionError};
 use vmm::snapshot::{Snapshot, SnapshotError};
 use vmm::vmm_config::instance_info::{InstanceInfo, VmState};
 use vmm::vmm_config::metrics::{init_metrics, MetricsConfig, MetricsConfigError};
+use vmm::vmm_config::vmm_info::{set_process_details, ProcessDetails, SeccompLevel};
 use vmm::{EventManager, FcExitCode, HTTP_MAX_PAYLOAD_SIZE};
 
 use crate::seccomp::SeccompConfig;
@@ -61,6 +72,14 @@ enum MainError {
     SeccompFilter(FilterError),
     /// Failed to resize fd table: {0}
     ResizeFdtable(ResizeFdTableError),
+    /// Failed to start the device worker spawner: {0}
+    WorkerSpawner(vmm::devices::virtio::worker::WorkerError),
+    /// Failed to set up the lifecycle notifications: {0}
+    Lifecycle(LifecycleError),
+    /// Invalid snapshot key file descriptor: {0}
+    SnapshotKeyFd(SnapshotEncryptionError),
+    /// Failed to duplicate the memory pressure trigger file descriptor: {0}
+    MemoryPressureFd(io::Error),
     /// RunWithApiError error: {0}
     RunWithApi(ApiServerError),
     /// RunWithoutApiError error: {0}
@@ -94,10 +113,13 @@ fn main() -> ExitCode {
     if let Err(err) = result {
         error!("{err}");
         eprintln!("Error: {err:?}");
-        let exit_code = FcExitCode::from(err) as u8;
+        let exit_code = FcExitCode::from(err);
+        notify_lifecycle_event(LifecycleEvent::Exit(exit_code));
+        let exit_code = exit_code as u8;
         error!("Firecracker exiting with error. exit_code={exit_code}");
         ExitCode::from(exit_code)
     } else {
+        notify_lifecycle_event(LifecycleEvent::Exit(FcExitCode::Ok));
         info!("Firecracker exiting successfully. exit_code=0");
         ExitCode::SUCCESS
     }
@@ -178,6 +200,36 @@ fn main_exec() -> Result<(), MainError> {
             .arg(Argument::new("parent-cpu-time-us").takes_value(true).help(
                 "Parent process CPU time (wall clock, microseconds). This parameter is optional.",
             ))
+            .arg(
+                Argument::new("cgroup-pressure-fd")
+                    .allow_multiple(true)
+                    .help(
+                        "File descriptor of a cgroup pressure stall trigger installed by the \
+                         jailer. This parameter is optional.",
+                    ),
+            )
+            .arg(Argument::new("memory-pressure-fd").takes_value(true).help(
+                "File descriptor of the memory pressure stall trigger installed by the jailer, \
+                 which drives the memory pressure policy of the balloon device. This parameter is \
+                 optional.",
+            ))
+            .arg(Argument::new("lifecycle-notify-fd").takes_value(true).help(
+                "File descriptor notified of the microVM state transitions, installed by the \
+                 jailer. This parameter is optional.",
+            ))
+            .arg(Argument::new("snapshot-key-fd").allow_multiple(true).help(
+                "File descriptor from which snapshot encryption keys can be read, when given as \
+                 `key_fd` in the snapshot API requests. This parameter is optional.",
+            ))
+            .arg(
+                Argument::new("lifecycle-notify-socket")
+                    .takes_value(true)
+                    .help(
+                        "Path to a Unix socket notified of the microVM state transitions with a \
+                         line of JSON each time the microVM starts running, is paused, starts \
+                         being snapshotted or exits.",
+                    ),
+            )
             .arg(
                 Argument::new("config-file")
                     .takes_value(true)
@@ -254,7 +306,23 @@ fn main_exec() -> Result<(), MainError> {
                 Argument::new("mmds-size-limit")
                     .takes_value(true)
                     .help("Mmds data store limit, in bytes."),
-            );
+            )
+            .arg(
+                Argument::new("event-loop-budget-us")
+                    .takes_value(true)
+                    .help(
+                        "Event handlers running for longer than this, in microseconds, are \
+                         reported as event loop stalls. Disabled if not set.",
+                    ),
+            )
+            .arg(Argument::new("secret-hardening").takes_value(false).help(
+                "Lock the buffers staging entropy and vsock data in memory, exclude them from \
+                 core dumps and zeroize them after use.",
+            ))
+            .arg(Argument::new("no-landlock").takes_value(false).help(
+                "Do not restrict the filesystem accesses of the microVM to its artifacts with \
+                 Landlock.",
+            ));
 
     arg_parser.parse_from_cmdline()?;
     let arguments = arg_parser.arguments();
@@ -308,6 +376,11 @@ fn main_exec() -> Result<(), MainError> {
         .map_err(MainError::LoggerInitialization)?;
     info!("Running Firecracker v{FIRECRACKER_VERSION}");
 
+    // The device workers are forked from a spawner, which is forked before any thread is started
+    // and before the signal handlers are registered.
+    // SAFETY: No thread was started yet.
+    unsafe { vmm::devices::virtio::worker::start_spawner() }.map_err(MainError::WorkerSpawner)?;
+
     register_signal_handlers().map_err(MainError::RegisterSignalHandlers)?;
 
     #[cfg(target_arch = "aarch64")]
@@ -338,6 +411,27 @@ fn main_exec() -> Result<(), MainError> {
         app_name: "Firecracker".to_string(),
     };
 
+    // The jailer passes the monotonic time at which it started, which is also when the process
+    // started from the user's point of view.
+    let now_mono_us = get_time_us(ClockType::Monotonic);
+    let start_mono_us = arguments
+        .single_value("start-time-us")
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(now_mono_us);
+    set_process_details(ProcessDetails {
+        start_time_utc_ms: get_time_ms(ClockType::Real)
+            .saturating_sub(now_mono_us.saturating_sub(start_mono_us) / 1000),
+        seccomp_level: if arguments.flag_present("no-seccomp") {
+            SeccompLevel::None
+        } else if arguments.single_value("seccomp-filter").is_some() {
+            SeccompLevel::Custom
+        } else {
+            SeccompLevel::Default
+        },
+        // Only the jailer passes the CPU time it spent.
+        jailed: arguments.single_value("parent-cpu-time-us").is_some(),
+    });
+
     if let Some(metrics_path) = arguments.single_value("metrics-path") {
         let metrics_config = MetricsConfig {
             metrics_path: PathBuf::from(metrics_path),
@@ -345,6 +439,27 @@ fn main_exec() -> Result<(), MainError> {
         init_metrics(metrics_config).map_err(MainError::MetricsInitialization)?;
     }
 
+    if let Some(fd) = arguments.single_value("lifecycle-notify-fd") {
+        let fd = fd
+            .parse::<i32>()
+            .expect("'lifecycle-notify-fd' parameter expected to be of 'i32' type.");
+        // SAFETY: The fd is inherited from the jailer and owned by nothing else.
+        unsafe { add_lifecycle_fd(fd) }.map_err(MainError::Lifecycle)?;
+    }
+    if let Some(path) = arguments.single_value("lifecycle-notify-socket") {
+        add_lifecycle_socket(Path::new(path)).map_err(MainError::Lifecycle)?;
+    }
+    for fd in arguments
+        .multiple_values("snapshot-key-fd")
+        .unwrap_or_default()
+    {
+        let fd = fd
+            .parse::<i32>()
+            .expect("'snapshot-key-fd' parameter expected to be of 'i32' type.");
+        // SAFETY: The fd is inherited from the parent process and owned by nothing else.
+        unsafe { add_key_fd(fd) }.map_err(MainError::SnapshotKeyFd)?;
+    }
+
     let mut seccomp_filters: BpfThreadMap = SeccompConfig::from_args(
         arguments.flag_present("no-seccomp"),
         arguments.single_value("seccomp-filter"),
@@ -385,6 +500,47 @@ fn main_exec() -> Result<(), MainError> {
         })
         .unwrap_or_else(|| api_payload_limit);
 
+    if let Some(budget_us) = arguments.single_value("event-loop-budget-us") {
+        set_event_loop_budget_us(
+            budget_us
+                .parse::<u64>()
+                .expect("'event-loop-budget-us' parameter expected to be of 'u64' type."),
+        );
+    }
+
+    if arguments.flag_present("secret-hardening") {
+        set_secret_hardening(true);
+    }
+
+    if arguments.flag_present("no-landlock") {
+        set_landlock(false);
+    }
+
+    let mut pressure_monitors = arguments
+        .multiple_values("cgroup-pressure-fd")
+        .unwrap_or_default()
+        .iter()
+        .map(|fd| {
+            let fd = fd
+                .parse::<i32>()
+                .expect("'cgroup-pressure-fd' parameter expected to be of 'i32' type.");
+            // SAFETY: The fd is inherited from the jailer and owned by nothing else.
+            unsafe { pressure::PressureMonitor::from_raw_fd(fd) }
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(fd) = arguments.single_value("memory-pressure-fd") {
+        let fd = fd
+            .parse::<i32>()
+            .expect("'memory-pressure-fd' parameter expected to be of 'i32' type.");
+        // SAFETY: The fd is inherited from the jailer and owned by nothing else.
+        let trigger = unsafe { File::from_raw_fd(fd) };
+        // The notifications are reported like the ones of the other cgroup pressure triggers.
+        let monitor_file = trigger.try_clone().map_err(MainError::MemoryPressureFd)?;
+        pressure_monitors.push(pressure::PressureMonitor::new(monitor_file));
+        set_memory_pressure_trigger(trigger);
+    }
+
     if api_enabled {
         let bind_path = arguments
             .single_value("api-sock")
@@ -419,6 +575,7 @@ fn main_exec() -> Result<(), MainError> {
             api_payload_limit,
             mmds_size_limit,
             metadata_json.as_deref(),
+            pressure_monitors,
         )
         .map_err(MainError::RunWithApi)
     } else {
@@ -433,6 +590,7 @@ fn main_exec() -> Result<(), MainError> {
             boot_timer_enabled,
             mmds_size_limit,
             metadata_json.as_deref(),
+            pressure_monitors,
         )
         .map_err(MainError::RunWithoutApiError)
     }
@@ -570,7 +728,7 @@ fn build_microvm_from_json(
     vm_resources.boot_timer = boot_timer_enabled;
     let vmm = vmm::builder::build_and_boot_microvm(
         &instance_info,
-        &vm_resources,
+        &mut vm_resources,
         event_manager,
         seccomp_filters,
     )
@@ -596,12 +754,21 @@ fn run_without_api(
     bool_timer_enabled: bool,
     mmds_size_limit: usize,
     metadata_json: Option<&str>,
+    pressure_monitors: Vec<pressure::PressureMonitor>,
 ) -> Result<(), RunWithoutApiError> {
     let mut event_manager = EventManager::new().expect("Unable to create EventManager");
 
     // Create the firecracker metrics object responsible for periodically printing metrics.
     let firecracker_metrics = Arc::new(Mutex::new(metrics::PeriodicMetrics::new()));
-    event_manager.add_subscriber(firecracker_metrics.clone());
+    add_timed_subscriber(&mut event_manager, "metrics", firecracker_metrics.clone());
+
+    for monitor in pressure_monitors {
+        add_timed_subscriber(
+            &mut event_manager,
+            "pressure",
+            Arc::new(Mutex::new(monitor)),
+        );
+    }
 
     // Build the microVm. We can ignore VmResources since it's not used without api.
     let (_, vmm) = build_microvm_from_json(
@@ -616,6 +783,10 @@ fn run_without_api(
     )
     .map_err(RunWithoutApiError::BuildMicroVMFromJson)?;
 
+    if let Err(err) = register_shutdown_signal_handler(vmm.lock().unwrap().shutdown_evt()) {
+        warn!("Failed to register the SIGTERM handler: {}", err);
+    }
+
     // Start the metrics.
     firecracker_metrics
         .lock()
@@ -8,6 +8,7 @@ use event_manager::{EventOps, Events, MutEventSubscriber};
 use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 use utils::epoll::EventSet;
 use vmm::logger::{error, warn, IncMetric, METRICS};
+use vmm::vstate::memory::update_ksm_metrics;
 
 /// Metrics reporting period.
 pub(crate) const WRITE_METRICS_PERIOD_MS: u64 = 60000;
@@ -47,6 +48,7 @@ impl PeriodicMetrics {
     }
 
     fn write_metrics(&mut self) {
+        update_ksm_metrics();
         if let Err(err) = METRICS.write() {
             METRICS.logger.missed_metrics_count.inc();
             error!("Failed to write metrics: {}", err);
@@ -0,0 +1,109 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::File;
+use std::os::unix::io::{FromRawFd, RawFd};
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use utils::epoll::EventSet;
+use vmm::logger::{error, warn, IncMetric, METRICS};
+
+/// Object to report the notifications of a cgroup pressure stall trigger.
+///
+/// The trigger is installed by the jailer, which passes the file it was written to
+/// through the `--cgroup-pressure-fd` argument. The kernel signals the file with
+/// `POLLPRI` whenever the configured stall threshold is exceeded.
+#[derive(Debug)]
+pub(crate) struct PressureMonitor {
+    file: File,
+}
+
+impl PressureMonitor {
+    /// Creates a `PressureMonitor` for the pressure trigger `file`.
+    pub(crate) fn new(file: File) -> Self {
+        PressureMonitor { file }
+    }
+
+    /// Creates a `PressureMonitor` owning the pressure trigger file `fd`.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open file descriptor not owned by anything else.
+    pub(crate) unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        PressureMonitor {
+            // SAFETY: Ownership of the fd is handed over by the caller.
+            file: unsafe { File::from_raw_fd(fd) },
+        }
+    }
+}
+
+impl MutEventSubscriber for PressureMonitor {
+    /// Handle a priority event (EPOLLPRI).
+    fn process(&mut self, event: Events, ops: &mut EventOps) {
+        let event_set = event.event_set();
+
+        // The trigger is signaled with EPOLLERR once the cgroup is removed.
+        if event_set.contains(EventSet::ERROR) {
+            warn!("Cgroup pressure trigger is no longer available.");
+            if let Err(err) = ops.remove(Events::new(&self.file, EventSet::PRIORITY)) {
+                error!("Failed to unregister cgroup pressure trigger: {}", err);
+            }
+            return;
+        }
+
+        if !event_set.contains(EventSet::PRIORITY) {
+            warn!(
+                "Received unknown event: {:?} from source: {:?}",
+                event_set,
+                event.fd()
+            );
+            return;
+        }
+
+        METRICS.vmm.cgroup_pressure_events.inc();
+        warn!("Cgroup pressure stall threshold exceeded.");
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(&self.file, EventSet::PRIORITY)) {
+            error!("Failed to register cgroup pressure trigger: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::io::{AsRawFd, IntoRawFd};
+    use std::sync::{Arc, Mutex};
+
+    use event_manager::SubscriberOps;
+    use vmm::EventManager;
+
+    use super::*;
+
+    #[test]
+    fn test_pressure_monitor() {
+        let mut event_manager = EventManager::new().unwrap();
+
+        // Out-of-band data on a TCP socket is signaled with EPOLLPRI as well, which makes it
+        // a stand-in for the pressure trigger file.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let sender = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (receiver, _) = listener.accept().unwrap();
+
+        // SAFETY: The fd is valid and its ownership is handed over.
+        let monitor = unsafe { PressureMonitor::from_raw_fd(receiver.into_raw_fd()) };
+        event_manager.add_subscriber(Arc::new(Mutex::new(monitor)));
+
+        let count = METRICS.vmm.cgroup_pressure_events.count();
+        assert_eq!(event_manager.run_with_timeout(50).unwrap(), 0);
+        assert_eq!(METRICS.vmm.cgroup_pressure_events.count(), count);
+
+        // SAFETY: Safe because the fd and buffer are valid, and we check the return value.
+        let ret = unsafe { libc::send(sender.as_raw_fd(), b"x".as_ptr().cast(), 1, libc::MSG_OOB) };
+        assert_eq!(ret, 1);
+        assert_eq!(event_manager.run_with_timeout(1000).unwrap(), 1);
+        assert_eq!(METRICS.vmm.cgroup_pressure_events.count(), count + 1);
+    }
+}
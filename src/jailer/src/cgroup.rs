@@ -4,12 +4,14 @@
 use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::collections::HashMap;
 use std::fmt::Debug;
-use std::fs::{self, File};
-use std::io::{BufRead, BufReader};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::process;
 
 use regex::Regex;
+use utils::syscall::SyscallReturnCode;
 
 use crate::{readln_special, writeln_special, JailerError};
 
@@ -19,6 +21,9 @@ const PROC_MOUNTS: &str = if cfg!(test) {
     "/proc/mounts"
 };
 
+// Resources whose pressure stall information is exposed by cgroupsv2
+const PRESSURE_RESOURCES: [&str; 4] = ["cpu", "io", "irq", "memory"];
+
 // Holds information on a cgroup mount point discovered on the system
 #[derive(Debug)]
 struct CgroupMountPoint {
@@ -133,6 +138,34 @@ impl CgroupBuilder {
         }
     }
 
+    // Creates a pressure stall trigger for the cgroup of the microVM and returns it
+    pub fn new_pressure_trigger(
+        &mut self,
+        file: String,
+        trigger: String,
+        id: &str,
+        parent_cg: &Path,
+    ) -> Result<CgroupPressureTrigger, JailerError> {
+        if self.version != 2 {
+            return Err(JailerError::CgroupPressureVersion);
+        }
+        if !file
+            .strip_suffix(".pressure")
+            .is_some_and(|resource| PRESSURE_RESOURCES.contains(&resource))
+        {
+            return Err(JailerError::CgroupInvalidFile(file));
+        }
+
+        let mut location = self.get_v2_hierarchy_path()?.clone();
+        location.push(parent_cg);
+        location.push(id);
+        Ok(CgroupPressureTrigger(CgroupBase {
+            file,
+            value: trigger,
+            location,
+        }))
+    }
+
     // Returns the path to the root of the hierarchy for the controller specified
     // Cgroups for a controller are arranged in a hierarchy; multiple controllers
     // may share the same hierarchy
@@ -189,6 +222,12 @@ pub struct CgroupV1 {
 #[derive(Debug)]
 pub struct CgroupV2(CgroupBase);
 
+// Pressure stall information (PSI) trigger of the cgroupsv2 cgroup of the microVM.
+// The kernel only keeps a trigger installed while the file it was written to is open,
+// so the file is handed over to the exec-ed binary, which polls it for notifications.
+#[derive(Debug)]
+pub struct CgroupPressureTrigger(CgroupBase);
+
 pub trait Cgroup: Debug {
     // Write the cgroup value into the cgroup property file.
     fn write_value(&self) -> Result<(), JailerError>;
@@ -442,6 +481,40 @@ impl Cgroup for CgroupV2 {
     }
 }
 
+impl CgroupPressureTrigger {
+    // Returns whether the trigger is installed on the memory pressure of the cgroup.
+    pub fn is_memory(&self) -> bool {
+        self.0.file == "memory.pressure"
+    }
+
+    // Installs the trigger and returns the file to poll for its notifications.
+    // The file is inherited by the exec-ed binary.
+    pub fn install(&self) -> Result<File, JailerError> {
+        fs::create_dir_all(&self.0.location)
+            .map_err(|err| JailerError::CreateDir(self.0.location.clone(), err))?;
+
+        // Only the processes of the cgroup contribute to its pressure.
+        writeln_special(&self.0.location.join("cgroup.procs"), process::id())?;
+
+        let path = self.0.location.join(&self.0.file);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(|err| JailerError::FileOpen(path.clone(), err))?;
+        // The trigger must be written at once, and the kernel discards its last byte.
+        file.write_all(format!("{}\0", self.0.value).as_bytes())
+            .map_err(|err| JailerError::Write(path, err))?;
+
+        // SAFETY: Safe because the fd is valid and we check the return value.
+        SyscallReturnCode(unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFD, 0) })
+            .into_empty_result()
+            .map_err(JailerError::UnsetPressureCloexec)?;
+
+        Ok(file)
+    }
+}
+
 #[cfg(test)]
 pub mod test_util {
     use std::fmt::Debug;
@@ -698,6 +771,97 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_pressure_trigger_build() {
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        mock_cgroups.add_v1_mounts().unwrap();
+        mock_cgroups.add_v2_mounts().unwrap();
+
+        let mut builder = CgroupBuilder::new(1).unwrap();
+        let trigger = builder.new_pressure_trigger(
+            "memory.pressure".to_string(),
+            "some 150000 1000000".to_string(),
+            "101",
+            Path::new("fc_test_cg"),
+        );
+        assert!(
+            matches!(trigger, Err(JailerError::CgroupPressureVersion)),
+            "{:?}",
+            trigger
+        );
+
+        let mut builder = CgroupBuilder::new(2).unwrap();
+        for file in ["memory.high", "memory", "foo.pressure", "cgroup.pressure"] {
+            let trigger = builder.new_pressure_trigger(
+                file.to_string(),
+                "some 150000 1000000".to_string(),
+                "101",
+                Path::new("fc_test_cg"),
+            );
+            assert!(
+                matches!(trigger, Err(JailerError::CgroupInvalidFile(_))),
+                "{:?}",
+                trigger
+            );
+        }
+        for file in [
+            "cpu.pressure",
+            "io.pressure",
+            "irq.pressure",
+            "memory.pressure",
+        ] {
+            let trigger = builder
+                .new_pressure_trigger(
+                    file.to_string(),
+                    "some 150000 1000000".to_string(),
+                    "101",
+                    Path::new("fc_test_cg"),
+                )
+                .unwrap();
+            assert_eq!(trigger.is_memory(), file == "memory.pressure");
+        }
+    }
+
+    #[test]
+    fn test_pressure_trigger_install() {
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        mock_cgroups.add_v2_mounts().unwrap();
+
+        let mut builder = CgroupBuilder::new(2).unwrap();
+        let trigger = builder
+            .new_pressure_trigger(
+                "memory.pressure".to_string(),
+                "some 150000 1000000".to_string(),
+                "101",
+                Path::new("fc_test_psi"),
+            )
+            .unwrap();
+
+        let cg_path = PathBuf::from(format!(
+            "{}/unified/fc_test_psi/101",
+            MockCgroupFs::MOCK_SYS_CGROUPS_DIR
+        ));
+
+        // The pressure file is created by the kernel along with the cgroup.
+        trigger.install().unwrap_err();
+
+        MockCgroupFs::create_file_with_contents(cg_path.join("memory.pressure"), "").unwrap();
+        let file = trigger.install().unwrap();
+
+        assert_eq!(
+            fs::read(cg_path.join("memory.pressure")).unwrap(),
+            b"some 150000 1000000\0"
+        );
+        assert_eq!(
+            read_first_line(cg_path.join("cgroup.procs")).unwrap(),
+            format!("{}\n", process::id())
+        );
+        // The file must be inherited by the exec-ed binary.
+        // SAFETY: Safe because the fd is valid.
+        let flags = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFD) };
+        assert_eq!(flags & libc::FD_CLOEXEC, 0);
+    }
+
     #[test]
     fn test_inherit_from_parent() {
         // 1. If parent file does not exist, return an error.
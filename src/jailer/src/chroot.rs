@@ -3,6 +3,7 @@
 
 use std::env;
 use std::ffi::CStr;
+use std::fs::{self, File};
 use std::path::Path;
 use std::ptr::null;
 
@@ -14,9 +15,36 @@ const OLD_ROOT_DIR_NAME_NUL_TERMINATED: &[u8] = b"old_root\0";
 const ROOT_DIR_NUL_TERMINATED: &[u8] = b"/\0";
 const CURRENT_DIR_NUL_TERMINATED: &[u8] = b".\0";
 
+// Bind mounts the host device node `dev` at the same path relative to the current directory.
+fn bind_mount_dev(dev: &str) -> Result<(), JailerError> {
+    let target = Path::new(dev.trim_start_matches('/'));
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|err| JailerError::CreateDir(parent.to_path_buf(), err))?;
+    }
+    File::create(target).map_err(|err| JailerError::FileOpen(target.to_path_buf(), err))?;
+
+    let source = to_cstring(dev)?;
+    let target = to_cstring(target)?;
+    // SAFETY: Safe because we provide valid parameters.
+    SyscallReturnCode(unsafe {
+        libc::mount(
+            source.as_ptr(),
+            target.as_ptr(),
+            null(),
+            libc::MS_BIND,
+            null(),
+        )
+    })
+    .into_empty_result()
+    .map_err(|err| JailerError::MountBindDev(err, dev.to_owned()))
+}
+
 // This uses switching to a new mount namespace + pivot_root(), together with the regular chroot,
 // to provide a hardened jail (at least compared to only relying on chroot).
-pub fn chroot(path: &Path) -> Result<(), JailerError> {
+// The host device nodes in `devices` are bind mounted inside the jail before the host root
+// becomes unreachable.
+pub fn chroot(path: &Path, devices: &[&str]) -> Result<(), JailerError> {
     // We unshare into a new mount namespace.
     // SAFETY: The call is safe because we're invoking a C library
     // function with valid parameters.
@@ -64,6 +92,8 @@ pub fn chroot(path: &Path) -> Result<(), JailerError> {
     // Change current dir to the chroot dir, so we only need to handle relative paths from now on.
     env::set_current_dir(path).map_err(JailerError::SetCurrentDir)?;
 
+    devices.iter().try_for_each(|dev| bind_mount_dev(dev))?;
+
     // We use the CStr conversion to make sure the contents of the byte slice would be a
     // valid C string (and for the as_ptr() method).
     let old_root_dir = CStr::from_bytes_with_nul(OLD_ROOT_DIR_NAME_NUL_TERMINATED)
@@ -3,9 +3,9 @@
 
 use std::ffi::{CString, OsString};
 use std::fs::{self, canonicalize, read_to_string, File, OpenOptions, Permissions};
-use std::io::Write;
+use std::io::{BufRead, BufReader, Write};
 use std::os::unix::fs::PermissionsExt;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::os::unix::process::CommandExt;
 use std::path::{Component, Path, PathBuf};
 use std::process::{exit, id, Command, Stdio};
@@ -15,10 +15,10 @@ use utils::arg_parser::UtilsArgParserError::MissingValue;
 use utils::syscall::SyscallReturnCode;
 use utils::{arg_parser, validators};
 
-use crate::cgroup::{Cgroup, CgroupBuilder};
+use crate::cgroup::{Cgroup, CgroupBuilder, CgroupPressureTrigger};
 use crate::chroot::chroot;
 use crate::resource_limits::{ResourceLimits, FSIZE_ARG, NO_FILE_ARG};
-use crate::JailerError;
+use crate::{writeln_special, JailerError};
 
 const STDIN_FILENO: libc::c_int = 0;
 const STDOUT_FILENO: libc::c_int = 1;
@@ -66,6 +66,11 @@ const FOLDER_PERMISSIONS: u32 = 0o700;
 // from jailer's and it is stored inside a dedicated file, prefixed with the below extension.
 const PID_FILE_EXTENSION: &str = ".pid";
 
+// Files setting up the id mappings of the user namespace of the jailer.
+const PROC_SELF_UID_MAP: &str = "/proc/self/uid_map";
+const PROC_SELF_GID_MAP: &str = "/proc/self/gid_map";
+const PROC_SELF_SETGROUPS: &str = "/proc/self/setgroups";
+
 // Helper function, since we'll use libc::dup2 a bunch of times for daemonization.
 fn dup2(old_fd: libc::c_int, new_fd: libc::c_int) -> Result<(), JailerError> {
     // SAFETY: This is safe because we are using a library function with valid parameters.
@@ -105,6 +110,56 @@ fn clone(child_stack: *mut libc::c_void, flags: libc::c_int) -> Result<libc::c_i
     .map_err(JailerError::Clone);
 }
 
+// Forks a process running `hook` for each lifecycle notification written to the returned pipe.
+fn spawn_lifecycle_hook_runner(hook: &Path) -> Result<File, JailerError> {
+    let mut fds: [libc::c_int; 2] = [0; 2];
+    // SAFETY: Safe because the array can hold the two fds and we check the return value.
+    SyscallReturnCode(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) })
+        .into_empty_result()
+        .map_err(JailerError::LifecycleHook)?;
+    // SAFETY: The fds were just created and are owned by nothing else.
+    let (events, notifier) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    // SAFETY: Safe because it's a library function.
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        return Err(JailerError::LifecycleHook(io::Error::last_os_error()));
+    }
+    if pid == 0 {
+        // The runner exits once the exec-ed binary, the only writer left, closes the pipe.
+        drop(notifier);
+        run_lifecycle_hooks(hook, events);
+        // SAFETY: Safe because it's a library function, which does not run the exit handlers
+        // of the forked jailer.
+        unsafe { libc::_exit(0) };
+    }
+    drop(events);
+
+    // SAFETY: Safe because the fd is valid and we check the return value.
+    SyscallReturnCode(unsafe { libc::fcntl(notifier.as_raw_fd(), libc::F_SETFD, 0) })
+        .into_empty_result()
+        .map_err(JailerError::LifecycleHook)?;
+    Ok(notifier)
+}
+
+// Runs `hook` for each line read from `events`, with the line written to its standard input.
+fn run_lifecycle_hooks(hook: &Path, events: File) {
+    for event in BufReader::new(events).lines() {
+        let Ok(event) = event else {
+            break;
+        };
+        match Command::new(hook).stdin(Stdio::piped()).spawn() {
+            Ok(mut child) => {
+                if let Some(mut stdin) = child.stdin.take() {
+                    let _ = writeln!(stdin, "{}", event);
+                }
+                let _ = child.wait();
+            }
+            Err(err) => eprintln!("Failed to run the lifecycle hook {:?}: {}", hook, err),
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 enum UserfaultfdParseError {
     #[error("Could not read /proc/misc: {0}")]
@@ -124,13 +179,19 @@ pub struct Env {
     netns: Option<String>,
     daemonize: bool,
     new_pid_ns: bool,
+    new_user_ns: bool,
     start_time_us: u64,
     start_time_cpu_us: u64,
     jailer_cpu_time_us: u64,
     extra_args: Vec<String>,
     cgroups: Vec<Box<dyn Cgroup>>,
+    pressure_triggers: Vec<CgroupPressureTrigger>,
+    pressure_files: Vec<File>,
+    memory_pressure_file: Option<File>,
     resource_limits: ResourceLimits,
     uffd_dev_minor: Option<u32>,
+    lifecycle_hook: Option<PathBuf>,
+    lifecycle_pipe: Option<File>,
 }
 
 impl fmt::Debug for Env {
@@ -144,6 +205,7 @@ impl fmt::Debug for Env {
             .field("netns", &self.netns)
             .field("daemonize", &self.daemonize)
             .field("new_pid_ns", &self.new_pid_ns)
+            .field("new_user_ns", &self.new_user_ns)
             .field("start_time_us", &self.start_time_us)
             .field("jailer_cpu_time_us", &self.jailer_cpu_time_us)
             .field("extra_args", &self.extra_args)
@@ -155,7 +217,12 @@ impl fmt::Debug for Env {
                     .map(|b| b as *const _)
                     .collect::<Vec<_>>(),
             )
+            .field("pressure_triggers", &self.pressure_triggers)
+            .field("pressure_files", &self.pressure_files)
+            .field("memory_pressure_file", &self.memory_pressure_file)
             .field("resource_limits", &self.resource_limits)
+            .field("lifecycle_hook", &self.lifecycle_hook)
+            .field("lifecycle_pipe", &self.lifecycle_pipe)
             .finish()
     }
 }
@@ -213,6 +280,8 @@ impl Env {
 
         let new_pid_ns = arguments.flag_present("new-pid-ns");
 
+        let new_user_ns = arguments.flag_present("new-user-ns");
+
         // Optional arguments.
         let mut cgroups: Vec<Box<dyn Cgroup>> = Vec::new();
         let parent_cgroup = match arguments.single_value("parent-cgroup") {
@@ -252,24 +321,33 @@ impl Env {
         if let Some(cgroups_args) = arguments.multiple_values("cgroup") {
             let mut builder = CgroupBuilder::new(cgroup_ver)?;
             for cg in cgroups_args {
-                let aux: Vec<&str> = cg.split('=').collect();
-                if aux.len() != 2 || aux[1].is_empty() {
-                    return Err(JailerError::CgroupFormat(cg.to_string()));
-                }
-                let file = Path::new(aux[0]);
-                if file.components().any(|c| {
-                    c == Component::CurDir || c == Component::ParentDir || c == Component::RootDir
-                }) {
-                    return Err(JailerError::CgroupInvalidFile(cg.to_string()));
-                }
+                // Values may contain '=' themselves (e.g io.max=8:0 rbps=1048576).
+                let (file, value) = Self::parse_cgroup_arg(cg)?;
+                let cgroup =
+                    builder.new_cgroup(file.to_string(), value.to_string(), id, parent_cgroup)?;
+                cgroups.push(cgroup);
+            }
+        }
 
-                let cgroup = builder.new_cgroup(
-                    aux[0].to_string(), // cgroup file
-                    aux[1].to_string(), // cgroup value
+        // cgroup pressure format: <resource>.pressure=<trigger>
+        let mut pressure_triggers = Vec::new();
+        if let Some(pressure_args) = arguments.multiple_values("cgroup-pressure") {
+            if cgroup_ver != 2 {
+                return Err(JailerError::CgroupPressureVersion);
+            }
+            let mut builder = CgroupBuilder::new(cgroup_ver)?;
+            for arg in pressure_args {
+                let (file, trigger) = Self::parse_cgroup_arg(arg)?;
+                pressure_triggers.push(builder.new_pressure_trigger(
+                    file.to_string(),
+                    trigger.to_string(),
                     id,
                     parent_cgroup,
-                )?;
-                cgroups.push(cgroup);
+                )?);
+            }
+            // The memory trigger drives the memory pressure policy of the balloon device.
+            if pressure_triggers.iter().filter(|t| t.is_memory()).count() > 1 {
+                return Err(JailerError::CgroupMemoryPressureDuplicate);
             }
         }
 
@@ -280,6 +358,18 @@ impl Env {
 
         let uffd_dev_minor = Self::get_userfaultfd_minor_dev_number().ok();
 
+        let lifecycle_hook = arguments
+            .single_value("lifecycle-hook")
+            .map(|hook| {
+                let hook_path = canonicalize(hook)
+                    .map_err(|err| JailerError::Canonicalize(PathBuf::from(hook), err))?;
+                if !hook_path.is_file() {
+                    return Err(JailerError::NotAFile(hook_path));
+                }
+                Ok(hook_path)
+            })
+            .transpose()?;
+
         Ok(Env {
             id: id.to_owned(),
             chroot_dir,
@@ -289,16 +379,36 @@ impl Env {
             netns,
             daemonize,
             new_pid_ns,
+            new_user_ns,
             start_time_us,
             start_time_cpu_us,
             jailer_cpu_time_us: 0,
             extra_args: arguments.extra_args(),
             cgroups,
+            pressure_triggers,
+            pressure_files: Vec::new(),
+            memory_pressure_file: None,
             resource_limits,
             uffd_dev_minor,
+            lifecycle_hook,
+            lifecycle_pipe: None,
         })
     }
 
+    fn parse_cgroup_arg(arg: &str) -> Result<(&str, &str), JailerError> {
+        let (file, value) = arg
+            .split_once('=')
+            .filter(|(_, value)| !value.is_empty())
+            .ok_or_else(|| JailerError::CgroupFormat(arg.to_string()))?;
+        if Path::new(file)
+            .components()
+            .any(|c| c == Component::CurDir || c == Component::ParentDir || c == Component::RootDir)
+        {
+            return Err(JailerError::CgroupInvalidFile(arg.to_string()));
+        }
+        Ok((file, value))
+    }
+
     pub fn chroot_dir(&self) -> &Path {
         self.chroot_dir.as_path()
     }
@@ -442,6 +552,40 @@ impl Env {
             })
     }
 
+    // Unshares into a new user namespace, in which the jailer holds all the capabilities it needs
+    // to set up the jail. Without privileges on the host, the jailer can only map its own
+    // effective ids, which the exec-ed binary then runs with.
+    fn enter_user_ns(&self) -> Result<(), JailerError> {
+        // SAFETY: These calls are always safe.
+        let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+
+        // SAFETY: Safe because we are passing valid parameters.
+        SyscallReturnCode(unsafe { libc::unshare(libc::CLONE_NEWUSER) })
+            .into_empty_result()
+            .map_err(JailerError::UnshareNewUserNs)?;
+
+        writeln_special(&PROC_SELF_UID_MAP, format!("{} {} 1", self.uid(), euid))?;
+        // setgroups() has to be disabled before an unprivileged process can map its gid.
+        writeln_special(&PROC_SELF_SETGROUPS, "deny")?;
+        writeln_special(&PROC_SELF_GID_MAP, format!("{} {} 1", self.gid(), egid))
+    }
+
+    // Returns the host device nodes to be bind mounted inside the jail, as device nodes cannot
+    // be created from a user namespace.
+    fn host_devices(&self) -> Vec<&'static str> {
+        let mut devices = vec![DEV_NET_TUN_WITH_NUL, DEV_KVM_WITH_NUL];
+        if Path::new(DEV_URANDOM_WITH_NUL).exists() {
+            devices.push(DEV_URANDOM_WITH_NUL);
+        } else {
+            println!("Warning! Could not find /dev/urandom device on the host.");
+            println!("MMDS version 2 will not be available to use.");
+        }
+        if self.uffd_dev_minor.is_some() {
+            devices.push(DEV_UFFD_PATH);
+        }
+        devices
+    }
+
     fn setup_jailed_folder(&self, folder: impl AsRef<Path>) -> Result<(), JailerError> {
         let folder_path = folder.as_ref();
         fs::create_dir_all(folder_path)
@@ -509,6 +653,24 @@ impl Env {
             .stderr(Stdio::inherit())
             .uid(self.uid())
             .gid(self.gid())
+            .args(self.pressure_files.iter().flat_map(|file| {
+                [
+                    "--cgroup-pressure-fd".to_string(),
+                    file.as_raw_fd().to_string(),
+                ]
+            }))
+            .args(self.memory_pressure_file.iter().flat_map(|file| {
+                [
+                    "--memory-pressure-fd".to_string(),
+                    file.as_raw_fd().to_string(),
+                ]
+            }))
+            .args(self.lifecycle_pipe.iter().flat_map(|pipe| {
+                [
+                    "--lifecycle-notify-fd".to_string(),
+                    pipe.as_raw_fd().to_string(),
+                ]
+            }))
             .args(&self.extra_args)
             .exec()
     }
@@ -606,11 +768,23 @@ impl Env {
         let exec_file_name = self.copy_exec_to_chroot()?;
         let chroot_exec_file = PathBuf::from("/").join(exec_file_name);
 
+        // The hook runner is forked before the jailer confines itself, so that the hooks run
+        // outside of the jail.
+        if let Some(ref hook) = self.lifecycle_hook {
+            self.lifecycle_pipe = Some(spawn_lifecycle_hook_runner(hook)?);
+        }
+
         // Join the specified network namespace, if applicable.
         if let Some(ref path) = self.netns {
             Env::join_netns(path)?;
         }
 
+        // The network namespace has to be joined first, as it is not owned by the new user
+        // namespace.
+        if self.new_user_ns {
+            self.enter_user_ns()?;
+        }
+
         // Set limits on resources.
         self.resource_limits.install()?;
 
@@ -627,6 +801,16 @@ impl Env {
             cgroup.attach_pid().unwrap();
         }
 
+        // The pressure files have to be opened before chrooting as well.
+        for trigger in &self.pressure_triggers {
+            let file = trigger.install()?;
+            if trigger.is_memory() {
+                self.memory_pressure_file = Some(file);
+            } else {
+                self.pressure_files.push(file);
+            }
+        }
+
         // If daemonization was requested, open /dev/null before chrooting.
         let dev_null = if self.daemonize {
             Some(File::open("/dev/null").map_err(JailerError::OpenDevNull)?)
@@ -639,7 +823,12 @@ impl Env {
         self.copy_midr_el1_info()?;
 
         // Jail self.
-        chroot(self.chroot_dir())?;
+        let devices = if self.new_user_ns {
+            self.host_devices()
+        } else {
+            Vec::new()
+        };
+        chroot(self.chroot_dir(), &devices)?;
 
         // This will not only create necessary directories, but will also change ownership
         // for all of them.
@@ -647,33 +836,36 @@ impl Env {
             .iter()
             .try_for_each(|f| self.setup_jailed_folder(f))?;
 
-        // Here we are creating the /dev/kvm and /dev/net/tun devices inside the jailer.
-        // Following commands can be translated into bash like this:
-        // $: mkdir -p $chroot_dir/dev/net
-        // $: dev_net_tun_path={$chroot_dir}/"tun"
-        // $: mknod $dev_net_tun_path c 10 200
-        // www.kernel.org/doc/Documentation/networking/tuntap.txt specifies 10 and 200 as the major
-        // and minor for the /dev/net/tun device.
-        self.mknod_and_own_dev(DEV_NET_TUN_WITH_NUL, DEV_NET_TUN_MAJOR, DEV_NET_TUN_MINOR)?;
-        // Do the same for /dev/kvm with (major, minor) = (10, 232).
-        self.mknod_and_own_dev(DEV_KVM_WITH_NUL, DEV_KVM_MAJOR, DEV_KVM_MINOR)?;
-        // And for /dev/urandom with (major, minor) = (1, 9).
-        // If the device is not accessible on the host, output a warning to inform user that MMDS
-        // version 2 will not be available to use.
-        let _ = self
-            .mknod_and_own_dev(DEV_URANDOM_WITH_NUL, DEV_URANDOM_MAJOR, DEV_URANDOM_MINOR)
-            .map_err(|err| {
-                println!(
-                    "Warning! Could not create /dev/urandom device inside jailer: {}.",
-                    err
-                );
-                println!("MMDS version 2 will not be available to use.");
-            });
-
-        // If we have a minor version for /dev/userfaultfd the device is present on the host.
-        // Expose the device in the jailed environment.
-        if let Some(minor) = self.uffd_dev_minor {
-            self.mknod_and_own_dev(DEV_UFFD_PATH, DEV_UFFD_MAJOR, minor)?;
+        // With a new user namespace, the devices were bind mounted from the host instead.
+        if !self.new_user_ns {
+            // Here we are creating the /dev/kvm and /dev/net/tun devices inside the jailer.
+            // Following commands can be translated into bash like this:
+            // $: mkdir -p $chroot_dir/dev/net
+            // $: dev_net_tun_path={$chroot_dir}/"tun"
+            // $: mknod $dev_net_tun_path c 10 200
+            // www.kernel.org/doc/Documentation/networking/tuntap.txt specifies 10 and 200 as the
+            // major and minor for the /dev/net/tun device.
+            self.mknod_and_own_dev(DEV_NET_TUN_WITH_NUL, DEV_NET_TUN_MAJOR, DEV_NET_TUN_MINOR)?;
+            // Do the same for /dev/kvm with (major, minor) = (10, 232).
+            self.mknod_and_own_dev(DEV_KVM_WITH_NUL, DEV_KVM_MAJOR, DEV_KVM_MINOR)?;
+            // And for /dev/urandom with (major, minor) = (1, 9).
+            // If the device is not accessible on the host, output a warning to inform user that
+            // MMDS version 2 will not be available to use.
+            let _ = self
+                .mknod_and_own_dev(DEV_URANDOM_WITH_NUL, DEV_URANDOM_MAJOR, DEV_URANDOM_MINOR)
+                .map_err(|err| {
+                    println!(
+                        "Warning! Could not create /dev/urandom device inside jailer: {}.",
+                        err
+                    );
+                    println!("MMDS version 2 will not be available to use.");
+                });
+
+            // If we have a minor version for /dev/userfaultfd the device is present on the host.
+            // Expose the device in the jailed environment.
+            if let Some(minor) = self.uffd_dev_minor {
+                self.mknod_and_own_dev(DEV_UFFD_PATH, DEV_UFFD_MAJOR, minor)?;
+            }
         }
 
         // Daemonize before exec, if so required (when the dev_null variable != None).
@@ -770,6 +962,7 @@ mod tests {
         pub netns: Option<&'a str>,
         pub daemonize: bool,
         pub new_pid_ns: bool,
+        pub new_user_ns: bool,
         pub cgroups: Vec<&'a str>,
         pub resource_limits: Vec<&'a str>,
         pub parent_cgroup: Option<&'a str>,
@@ -787,6 +980,7 @@ mod tests {
                 netns: Some("zzzns"),
                 daemonize: true,
                 new_pid_ns: true,
+                new_user_ns: true,
                 cgroups: vec!["cpu.shares=2", "cpuset.mems=0"],
                 resource_limits: vec!["no-file=1024", "fsize=1048575"],
                 parent_cgroup: None,
@@ -837,6 +1031,10 @@ mod tests {
             arg_vec.push("--new-pid-ns".to_string());
         }
 
+        if arg_vals.new_user_ns {
+            arg_vec.push("--new-user-ns".to_string());
+        }
+
         if let Some(parent_cg) = arg_vals.parent_cgroup {
             arg_vec.push("--parent-cgroup".to_string());
             arg_vec.push(parent_cg.to_string());
@@ -886,11 +1084,13 @@ mod tests {
         assert_eq!(good_env.netns, good_arg_vals.netns.map(String::from));
         assert!(good_env.daemonize);
         assert!(good_env.new_pid_ns);
+        assert!(good_env.new_user_ns);
 
         let another_good_arg_vals = ArgVals {
             netns: None,
             daemonize: false,
             new_pid_ns: false,
+            new_user_ns: false,
             ..good_arg_vals
         };
 
@@ -901,6 +1101,7 @@ mod tests {
             .expect("This another new environment should be created successfully.");
         assert!(!another_good_env.daemonize);
         assert!(!another_good_env.new_pid_ns);
+        assert!(!another_good_env.new_user_ns);
 
         let base_invalid_arg_vals = ArgVals {
             daemonize: true,
@@ -1153,6 +1354,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_enter_user_ns() {
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        mock_cgroups.add_v1_mounts().unwrap();
+        let env = create_env();
+
+        // unshare(CLONE_NEWUSER) requires a single threaded process, so run it in a child.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            let mapped = env.enter_user_ns().is_ok()
+                && unsafe { libc::getuid() } == env.uid()
+                && unsafe { libc::getgid() } == env.gid();
+            unsafe { libc::_exit(i32::from(!mapped)) };
+        }
+
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+
+    #[test]
+    fn test_host_devices() {
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        mock_cgroups.add_v1_mounts().unwrap();
+        let env = create_env();
+
+        let devices = env.host_devices();
+        assert_eq!(devices[..2], [DEV_NET_TUN_WITH_NUL, DEV_KVM_WITH_NUL]);
+        assert_eq!(
+            devices.contains(&DEV_URANDOM_WITH_NUL),
+            Path::new(DEV_URANDOM_WITH_NUL).exists()
+        );
+        assert_eq!(
+            devices.contains(&DEV_UFFD_PATH),
+            env.uffd_dev_minor.is_some()
+        );
+    }
+
     #[test]
     fn test_userfaultfd_dev() {
         let mut mock_cgroups = MockCgroupFs::new().unwrap();
@@ -1190,6 +1431,7 @@ mod tests {
             netns: Some("zzzns"),
             daemonize: false,
             new_pid_ns: false,
+            new_user_ns: false,
             cgroups: Vec::new(),
             resource_limits: Vec::new(),
             parent_cgroup: None,
@@ -1317,6 +1559,87 @@ mod tests {
         Env::new(&args, 0, 0).unwrap();
     }
 
+    #[test]
+    fn test_cgroups_v2_parsing() {
+        let arg_parser = build_arg_parser();
+        let good_arg_vals = ArgVals::new();
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        mock_cgroups.add_v2_mounts().unwrap();
+
+        let make_v2_args = |arg_vals: &ArgVals, pressure: &[&str]| {
+            let mut arg_vec = make_args(arg_vals);
+            arg_vec.extend(["--cgroup-version".to_string(), "2".to_string()]);
+            for trigger in pressure {
+                arg_vec.extend(["--cgroup-pressure".to_string(), trigger.to_string()]);
+            }
+            arg_vec
+        };
+
+        // Check value containing '='
+        let mut args = arg_parser.arguments().clone();
+        let cgroup_arg_vals = ArgVals {
+            cgroups: vec!["io.max=8:0 rbps=1048576 wiops=120", "io.weight=default 100"],
+            ..good_arg_vals.clone()
+        };
+        args.parse(&make_v2_args(&cgroup_arg_vals, &[])).unwrap();
+        Env::new(&args, 0, 0).unwrap();
+
+        // Check valid pressure triggers
+        let mut args = arg_parser.arguments().clone();
+        args.parse(&make_v2_args(
+            &good_arg_vals,
+            &[
+                "memory.pressure=some 150000 1000000",
+                "cpu.pressure=full 50000 1000000",
+            ],
+        ))
+        .unwrap();
+        let env = Env::new(&args, 0, 0).unwrap();
+        assert_eq!(env.pressure_triggers.len(), 2);
+
+        // Check more than one memory pressure trigger
+        let mut args = arg_parser.arguments().clone();
+        args.parse(&make_v2_args(
+            &good_arg_vals,
+            &[
+                "memory.pressure=some 150000 1000000",
+                "memory.pressure=full 50000 1000000",
+            ],
+        ))
+        .unwrap();
+        assert!(matches!(
+            Env::new(&args, 0, 0),
+            Err(JailerError::CgroupMemoryPressureDuplicate)
+        ));
+
+        // Check invalid pressure triggers
+        for trigger in [
+            "memory.pressure",
+            "memory.pressure=",
+            "memory.high=some 150000 1000000",
+            "../memory.pressure=some 150000 1000000",
+        ] {
+            let mut args = arg_parser.arguments().clone();
+            args.parse(&make_v2_args(&good_arg_vals, &[trigger]))
+                .unwrap();
+            Env::new(&args, 0, 0).unwrap_err();
+        }
+
+        // Check pressure triggers with cgroup v1
+        mock_cgroups.add_v1_mounts().unwrap();
+        let mut args = arg_parser.arguments().clone();
+        let mut arg_vec = make_args(&good_arg_vals);
+        arg_vec.extend([
+            "--cgroup-pressure".to_string(),
+            "memory.pressure=some 150000 1000000".to_string(),
+        ]);
+        args.parse(&arg_vec).unwrap();
+        assert!(matches!(
+            Env::new(&args, 0, 0),
+            Err(JailerError::CgroupPressureVersion)
+        ));
+    }
+
     #[test]
     fn test_parse_resource_limits() {
         let mut resource_limits = ResourceLimits::default();
@@ -1382,6 +1705,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_lifecycle_hook() {
+        let mut mock_cgroups = MockCgroupFs::new().unwrap();
+        mock_cgroups.add_v1_mounts().unwrap();
+        let arg_parser = build_arg_parser();
+        let tmp_dir = TempDir::new().unwrap();
+        let hook = tmp_dir.as_path().join("hook.sh");
+        let output = tmp_dir.as_path().join("events");
+
+        // The hook has to exist.
+        let mut args = arg_parser.arguments().clone();
+        let mut arg_vec = make_args(&ArgVals::new());
+        arg_vec.extend(["--lifecycle-hook".to_string(), hook.display().to_string()]);
+        args.parse(&arg_vec).unwrap();
+        assert!(matches!(
+            Env::new(&args, 0, 0),
+            Err(JailerError::Canonicalize(_, _))
+        ));
+
+        fs::write(&hook, format!("#!/bin/sh\ncat >> {}\n", output.display())).unwrap();
+        fs::set_permissions(&hook, Permissions::from_mode(0o700)).unwrap();
+        let env = Env::new(&args, 0, 0).unwrap();
+        assert_eq!(env.lifecycle_hook.as_deref(), Some(hook.as_path()));
+
+        // The hook is run once per event.
+        let mut fds: [libc::c_int; 2] = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let (events, mut notifier) =
+            unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        notifier
+            .write_all(b"{\"state\":\"Running\"}\n{\"state\":\"Exit\",\"exit_code\":0}\n")
+            .unwrap();
+        drop(notifier);
+        run_lifecycle_hooks(&hook, events);
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "{\"state\":\"Running\"}\n{\"state\":\"Exit\",\"exit_code\":0}\n"
+        );
+    }
+
     #[test]
     #[cfg(target_arch = "aarch64")]
     fn test_copy_cache_info() {
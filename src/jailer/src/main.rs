@@ -42,6 +42,10 @@ pub enum JailerError {
     CgroupInvalidVersion(String),
     #[error("Parent cgroup path is invalid. Path should not be absolute or contain '..' or '.'")]
     CgroupInvalidParentPath(),
+    #[error("Only one memory.pressure trigger can be installed")]
+    CgroupMemoryPressureDuplicate,
+    #[error("Cgroup pressure triggers are only supported by cgroup v2")]
+    CgroupPressureVersion,
     #[error("Failed to write to cgroups file: {0}")]
     CgroupWrite(io::Error),
     #[error("Failed to change owner for {0}: {1}")]
@@ -88,6 +92,8 @@ pub enum JailerError {
     Gid(String),
     #[error("Invalid instance ID: {0}")]
     InvalidInstanceId(validators::ValidatorError),
+    #[error("Failed to start the lifecycle hook runner: {0}")]
+    LifecycleHook(io::Error),
     #[error("{}", format!("File {:?} doesn't have a parent", .0).replace('\"', ""))]
     MissingParent(PathBuf),
     #[error("Failed to create the jail root directory before pivoting root: {0}")]
@@ -96,6 +102,8 @@ pub enum JailerError {
     MknodDev(io::Error, String),
     #[error("Failed to bind mount the jail root directory: {0}")]
     MountBind(io::Error),
+    #[error("Failed to bind mount {1} inside the jail: {0}")]
+    MountBindDev(io::Error, String),
     #[error("Failed to change the propagation type to slave: {0}")]
     MountPropagationSlave(io::Error),
     #[error("{}", format!("{:?} is not a file", .0).replace('\"', ""))]
@@ -138,8 +146,12 @@ pub enum JailerError {
     UnexpectedListenerFd(i32),
     #[error("Failed to unshare into new mount namespace: {0}")]
     UnshareNewNs(io::Error),
+    #[error("Failed to unshare into new user namespace: {0}")]
+    UnshareNewUserNs(io::Error),
     #[error("Failed to unset the O_CLOEXEC flag on the socket fd: {0}")]
     UnsetCloexec(io::Error),
+    #[error("Failed to unset the O_CLOEXEC flag on the cgroup pressure trigger fd: {0}")]
+    UnsetPressureCloexec(io::Error),
     #[error("Slice contains invalid UTF-8 data : {0}")]
     UTF8Parsing(std::str::Utf8Error),
     #[error("{}", format!("Failed to write to {:?}: {}", .0, .1).replace('\"', ""))]
@@ -194,11 +206,28 @@ pub fn build_arg_parser() -> ArgParser<'static> {
                 .takes_value(false)
                 .help("Exec into a new PID namespace."),
         )
+        .arg(Argument::new("new-user-ns").takes_value(false).help(
+            "Set up the jail from a new user namespace, mapping the uid and gid to the ones of \
+             the jailer, so that the jailer can be run without privileges.",
+        ))
         .arg(Argument::new("cgroup").allow_multiple(true).help(
             "Cgroup and value to be set by the jailer. It must follow this format: \
              <cgroup_file>=<value> (e.g cpu.shares=10). This argument can be used multiple times \
              to add multiple cgroups.",
         ))
+        .arg(Argument::new("cgroup-pressure").allow_multiple(true).help(
+            "Cgroup pressure stall trigger to be installed by the jailer for the microVM cgroup. \
+             It must follow this format: <resource>.pressure=<trigger> (e.g memory.pressure=some \
+             150000 1000000). The exec-ed binary is notified of the trigger through the \
+             --cgroup-pressure-fd argument. Requires cgroup v2. This argument can be used \
+             multiple times to install multiple triggers.",
+        ))
+        .arg(Argument::new("lifecycle-hook").takes_value(true).help(
+            "Path to a binary run outside of the jail each time the microVM starts running, is \
+             paused, starts being snapshotted or exits. The state transition is written as a line \
+             of JSON to its standard input. The exec-ed binary is notified of the transitions \
+             through the --lifecycle-notify-fd argument.",
+        ))
         .arg(Argument::new("resource-limit").allow_multiple(true).help(
             "Resource limit values to be set by the jailer. It must follow this format: \
              <resource>=<value> (e.g no-file=1024). This argument can be used multiple times to \
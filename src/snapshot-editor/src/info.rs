@@ -1,11 +1,11 @@
 // Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use clap::Subcommand;
 use semver::Version;
-use vmm::persist::MicrovmState;
+use vmm::persist::{describe_snapshot, MicrovmState, SnapshotStateFromFileError};
 
 use crate::utils::*;
 
@@ -13,6 +13,10 @@ use crate::utils::*;
 pub enum InfoVmStateError {
     /// {0}
     Utils(#[from] UtilsError),
+    /// Can not describe snapshot: {0}
+    Describe(#[from] SnapshotStateFromFileError),
+    /// Can not serialize snapshot description: {0}
+    Serialize(#[from] serde_json::Error),
 }
 
 #[derive(Debug, Subcommand)]
@@ -35,6 +39,12 @@ pub enum InfoVmStateSubCommand {
         #[arg(short, long)]
         vmstate_path: PathBuf,
     },
+    /// Print a JSON summary of the snapshot.
+    Describe {
+        /// Path to the vmstate file.
+        #[arg(short, long)]
+        vmstate_path: PathBuf,
+    },
 }
 
 pub fn info_vmstate_command(command: InfoVmStateSubCommand) -> Result<(), InfoVmStateError> {
@@ -44,6 +54,7 @@ pub fn info_vmstate_command(command: InfoVmStateSubCommand) -> Result<(), InfoVm
             info(&vmstate_path, info_vcpu_states)?
         }
         InfoVmStateSubCommand::VmState { vmstate_path } => info(&vmstate_path, info_vmstate)?,
+        InfoVmStateSubCommand::Describe { vmstate_path } => info_describe(&vmstate_path)?,
     }
     Ok(())
 }
@@ -74,3 +85,9 @@ fn info_vmstate(vmstate: &MicrovmState, _version: Version) -> Result<(), InfoVmS
     println!("{vmstate:#?}");
     Ok(())
 }
+
+fn info_describe(vmstate_path: &Path) -> Result<(), InfoVmStateError> {
+    let description = describe_snapshot(vmstate_path)?;
+    println!("{}", serde_json::to_string_pretty(&description)?);
+    Ok(())
+}
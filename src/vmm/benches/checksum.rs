@@ -0,0 +1,34 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Benchmarking cases:
+//   * One's complement sum of the payload of a minimum sized Ethernet frame
+//   * One's complement sum of the payload of a standard MTU Ethernet frame
+//   * One's complement sum of the payload of a maximum sized IPv4 packet
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use vmm::dumbo::pdu::ones_complement_sum;
+
+pub fn checksum_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ones_complement_sum");
+
+    for len in [46usize, 1500, 65535] {
+        let bytes: Vec<u8> = (0..len).map(|i| u8::try_from(i % 251).unwrap()).collect();
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(len), &bytes, |b, bytes| {
+            b.iter(|| ones_complement_sum(black_box(bytes)))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group! {
+    name = checksum_benches;
+    config = Criterion::default().sample_size(200).noise_threshold(0.05);
+    targets = checksum_benchmark
+}
+
+criterion_main! {
+    checksum_benches
+}
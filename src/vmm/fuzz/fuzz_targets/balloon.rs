@@ -0,0 +1,16 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vmm::devices::virtio::balloon::Balloon;
+use vmm_fuzz::{activate, guest_memory};
+
+// Processes the inflate and deflate queues of the balloon device.
+fuzz_target!(|data: &[u8]| {
+    let mut balloon = Balloon::new(0, false, 0, false).unwrap();
+    if activate(&mut balloon, guest_memory(data)) {
+        balloon.process_virtio_queues();
+    }
+});
@@ -0,0 +1,18 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vmm::devices::virtio::block::virtio::device::FileEngineType;
+use vmm::devices::virtio::block::virtio::test_utils::default_block;
+use vmm_fuzz::{activate, guest_memory};
+
+// Processes the request queue of a virtio block device backed by a temporary file, using the
+// synchronous IO engine.
+fuzz_target!(|data: &[u8]| {
+    let mut block = default_block(FileEngineType::Sync);
+    if activate(&mut block, guest_memory(data)) {
+        block.process_virtio_queues();
+    }
+});
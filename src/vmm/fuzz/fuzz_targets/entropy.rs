@@ -0,0 +1,17 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vmm::devices::virtio::rng::Entropy;
+use vmm::rate_limiter::RateLimiter;
+use vmm_fuzz::{activate, guest_memory};
+
+// Processes the request queue of the entropy device.
+fuzz_target!(|data: &[u8]| {
+    let mut entropy = Entropy::new(RateLimiter::default()).unwrap();
+    if activate(&mut entropy, guest_memory(data)) {
+        entropy.process_virtio_queues();
+    }
+});
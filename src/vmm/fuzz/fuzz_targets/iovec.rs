@@ -0,0 +1,35 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vmm::devices::virtio::iovec::{IoVecBuffer, IoVecBufferMut};
+use vmm_fuzz::{guest_memory, queue, MEM_SIZE, QUEUE_SIZE};
+
+// Parses every available descriptor chain both as a read-only and as a write-only buffer, and
+// copies data in and out of the buffers that could be parsed.
+fuzz_target!(|data: &[u8]| {
+    let mem = guest_memory(data);
+    let Some(mut queue) = queue(0, &mem) else {
+        return;
+    };
+    // Popping from both copies of the queue yields the same chains.
+    let mut queue_mut = queue.clone();
+
+    for _ in 0..QUEUE_SIZE {
+        let (Some(head), Some(head_mut)) = (queue.pop(&mem), queue_mut.pop(&mem)) else {
+            break;
+        };
+
+        if let Ok(iovec) = IoVecBuffer::from_descriptor_chain(head) {
+            let mut buf = vec![0u8; MEM_SIZE];
+            let _ = iovec.read_volatile_at(&mut buf.as_mut_slice(), 0, MEM_SIZE);
+        }
+
+        if let Ok(mut iovec) = IoVecBufferMut::from_descriptor_chain(head_mut) {
+            let buf = vec![0xa5u8; MEM_SIZE];
+            let _ = iovec.write_volatile_at(&mut buf.as_slice(), 0, MEM_SIZE);
+        }
+    }
+});
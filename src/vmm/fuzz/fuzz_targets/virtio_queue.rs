@@ -0,0 +1,29 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vmm_fuzz::{guest_memory, queue, QUEUE_SIZE};
+
+// Pops every available descriptor chain, walks it and returns it to the used ring.
+fuzz_target!(|data: &[u8]| {
+    let mem = guest_memory(data);
+    let Some(mut queue) = queue(0, &mem) else {
+        return;
+    };
+
+    for _ in 0..QUEUE_SIZE {
+        let Some(head) = queue.pop(&mem) else {
+            break;
+        };
+        let index = head.index;
+        let mut len = 0u32;
+        let mut desc = Some(head);
+        while let Some(d) = desc {
+            len = len.wrapping_add(d.len);
+            desc = d.next_descriptor();
+        }
+        let _ = queue.add_used(&mem, index, len);
+    }
+});
@@ -0,0 +1,64 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helpers shared by the virtio fuzz targets.
+//!
+//! The targets model a guest which fully controls its memory: the fuzzer input is copied to the
+//! start of guest memory, where the virtqueues live at fixed addresses. The descriptor tables,
+//! the available rings and the buffers the descriptors point to are all guest controlled.
+//!
+//! Queue `i` is laid out as follows, with `base = i * QUEUE_STRIDE`:
+//!
+//!  |--------------------|----------------------|
+//!  | base               | descriptor table     |
+//!  | base + 0x100       | available ring       |
+//!  | base + 0x200       | used ring            |
+//!  |--------------------|----------------------|
+
+use vmm::devices::virtio::device::VirtioDevice;
+use vmm::devices::virtio::queue::Queue;
+use vmm::utilities::test_utils::single_region_mem;
+use vmm::vstate::memory::{Bytes, GuestAddress, GuestMemoryMmap};
+
+/// Size of the guest memory.
+pub const MEM_SIZE: usize = 0x10000;
+/// Size of every queue.
+pub const QUEUE_SIZE: u16 = 16;
+/// Distance between the start of two consecutive queues.
+const QUEUE_STRIDE: u64 = 0x400;
+
+/// Creates the guest memory, initialized with the fuzzer input.
+pub fn guest_memory(data: &[u8]) -> GuestMemoryMmap {
+    let mem = single_region_mem(MEM_SIZE);
+    let len = data.len().min(MEM_SIZE);
+    mem.write_slice(&data[..len], GuestAddress(0)).unwrap();
+    mem
+}
+
+/// Creates the queue with the given index, if the guest left it in a valid state.
+///
+/// An available ring index more than a queue size ahead is rejected, as `Queue::pop` deliberately
+/// aborts the VMM on such a misbehaving driver.
+pub fn queue(index: usize, mem: &GuestMemoryMmap) -> Option<Queue> {
+    let base = QUEUE_STRIDE * u64::try_from(index).unwrap();
+    let mut queue = Queue::new(QUEUE_SIZE);
+    queue.size = QUEUE_SIZE;
+    queue.ready = true;
+    queue.desc_table = GuestAddress(base);
+    queue.avail_ring = GuestAddress(base + 0x100);
+    queue.used_ring = GuestAddress(base + 0x200);
+    queue.is_valid(mem).then_some(queue)
+}
+
+/// Sets up the queues of `device` and activates it. Returns `false` if any of the queues is not
+/// valid, in which case the input should be skipped.
+pub fn activate<D: VirtioDevice>(device: &mut D, mem: GuestMemoryMmap) -> bool {
+    for (index, device_queue) in device.queues_mut().iter_mut().enumerate() {
+        match queue(index, &mem) {
+            Some(queue) => *device_queue = queue,
+            None => return false,
+        }
+    }
+    device.activate(mem).unwrap();
+    true
+}
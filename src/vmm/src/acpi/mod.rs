@@ -2,16 +2,19 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use acpi_tables::fadt::{FADT_F_HW_REDUCED_ACPI, FADT_F_PWR_BUTTON, FADT_F_SLP_BUTTON};
-use acpi_tables::{Aml, Dsdt, Fadt, Madt, Rsdp, Sdt, Xsdt};
+use acpi_tables::tpm2::TPM2_START_METHOD_CRB;
+use acpi_tables::{Aml, Dsdt, Fadt, Madt, Rsdp, Sdt, Tpm2, Xsdt};
 use log::{debug, error};
 use vm_allocator::AllocPolicy;
 
 use crate::acpi::x86_64::{
     apic_addr, rsdp_addr, setup_arch_dsdt, setup_arch_fadt, setup_interrupt_controllers,
 };
+use crate::arch::DeviceType;
 use crate::device_manager::acpi::ACPIDeviceManager;
 use crate::device_manager::mmio::MMIODeviceManager;
 use crate::device_manager::resources::ResourceAllocator;
+use crate::devices::tpm::TPM_CRB_CTRL_AREA_OFFSET;
 use crate::vstate::memory::{GuestAddress, GuestMemoryMmap};
 use crate::Vcpu;
 
@@ -123,16 +126,25 @@ impl<'a> AcpiTableWriter<'a> {
         self.write_acpi_table(&mut madt)
     }
 
-    /// Build the XSDT table for the guest
+    /// Build the TPM2 table for the guest
     ///
-    /// Currently, we pass to the guest just FADT and MADT tables.
-    fn build_xsdt(&mut self, fadt_addr: u64, madt_addr: u64) -> Result<u64, AcpiError> {
-        let mut xsdt = Xsdt::new(
+    /// This describes the CRB interface of the TPM device whose MMIO region starts at `tpm_addr`
+    fn build_tpm2(&mut self, tpm_addr: u64) -> Result<u64, AcpiError> {
+        let mut tpm2 = Tpm2::new(
             OEM_ID,
-            *b"FCMVXSDT",
+            *b"FCVMTPM2",
             OEM_REVISION,
-            vec![fadt_addr, madt_addr],
+            tpm_addr + TPM_CRB_CTRL_AREA_OFFSET,
+            TPM2_START_METHOD_CRB,
         );
+        self.write_acpi_table(&mut tpm2)
+    }
+
+    /// Build the XSDT table for the guest
+    ///
+    /// We pass to the guest the FADT and MADT tables, and the TPM2 table if there is a TPM.
+    fn build_xsdt(&mut self, table_addrs: Vec<u64>) -> Result<u64, AcpiError> {
+        let mut xsdt = Xsdt::new(OEM_ID, *b"FCMVXSDT", OEM_REVISION, table_addrs);
         self.write_acpi_table(&mut xsdt)
     }
 
@@ -174,7 +186,14 @@ pub(crate) fn create_acpi_tables(
     let dsdt_addr = writer.build_dsdt(mmio_device_manager, acpi_device_manager)?;
     let fadt_addr = writer.build_fadt(dsdt_addr)?;
     let madt_addr = writer.build_madt(vcpus.len().try_into().unwrap())?;
-    let xsdt_addr = writer.build_xsdt(fadt_addr, madt_addr)?;
+    let mut table_addrs = vec![fadt_addr, madt_addr];
+    if let Some(tpm) = mmio_device_manager
+        .get_device_info()
+        .get(&(DeviceType::Tpm, DeviceType::Tpm.to_string()))
+    {
+        table_addrs.push(writer.build_tpm2(tpm.addr)?);
+    }
+    let xsdt_addr = writer.build_xsdt(table_addrs)?;
     writer.build_rsdp(xsdt_addr)
 }
 
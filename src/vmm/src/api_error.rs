@@ -0,0 +1,122 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::error::Error;
+use std::fmt::Debug;
+
+/// An error that is reported to the API clients along with a machine-readable classification.
+pub trait ApiError: Error {
+    /// Name of the component the error originates from, e.g. `block` or `snapshot`.
+    fn subsystem(&self) -> &'static str;
+
+    /// Stable identifier of the failure, built from the name of the error variant and, if it
+    /// wraps another error, the name of the wrapped variant (e.g.
+    /// `DriveConfig.InvalidBlockDevicePath`).
+    fn code(&self) -> String
+    where
+        Self: Debug,
+    {
+        variant_path(&format!("{:?}", self))
+    }
+
+    /// Messages of the errors that caused this one, outermost first.
+    fn details(&self) -> Vec<String> {
+        let mut details = Vec::new();
+        let mut source = self.source();
+        while let Some(err) = source {
+            details.push(err.to_string());
+            source = err.source();
+        }
+        details
+    }
+}
+
+/// Implements [`ApiError`](crate::api_error::ApiError) for an error enum, given the subsystem of
+/// each of its variants.
+///
+/// The generated match is exhaustive, so adding a variant to the enum without classifying it is
+/// a build error.
+#[macro_export]
+macro_rules! impl_api_error {
+    ($error:ty { $($variant:ident => $subsystem:literal),+ $(,)? }) => {
+        impl $crate::api_error::ApiError for $error {
+            fn subsystem(&self) -> &'static str {
+                match self {
+                    $(Self::$variant { .. } => $subsystem,)+
+                }
+            }
+        }
+    };
+}
+
+// Extracts the names of the outermost two enum variants from the derived `Debug` representation
+// of an error, e.g. `DriveConfig(InvalidBlockDevicePath("/foo"))` yields
+// `DriveConfig.InvalidBlockDevicePath`.
+fn variant_path(debug: &str) -> String {
+    let ident_len = |s: &str| {
+        s.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(s.len())
+    };
+
+    let (outer, rest) = debug.split_at(ident_len(debug));
+    match rest.strip_prefix('(') {
+        Some(inner) if inner.starts_with(|c: char| c.is_ascii_uppercase()) => {
+            format!("{}.{}", outer, &inner[..ident_len(inner)])
+        }
+        _ => outer.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, thiserror::Error, displaydoc::Display)]
+    enum InnerError {
+        /// Inner failure: {0}
+        Failure(String),
+    }
+
+    #[derive(Debug, thiserror::Error, displaydoc::Display)]
+    enum OuterError {
+        /// Outer error: {0}
+        Inner(#[from] InnerError),
+        /// Message: {0}
+        Message(String),
+        /// Not supported.
+        NotSupported,
+    }
+
+    crate::impl_api_error!(OuterError {
+        Inner => "inner",
+        Message => "outer",
+        NotSupported => "outer",
+    });
+
+    #[test]
+    fn test_variant_path() {
+        assert_eq!(variant_path("NotSupported"), "NotSupported");
+        assert_eq!(variant_path("Message(\"(Foo)\")"), "Message");
+        assert_eq!(variant_path("Inner(Failure(\"foo\"))"), "Inner.Failure");
+        assert_eq!(variant_path("Inner(Unit)"), "Inner.Unit");
+        assert_eq!(variant_path("Range { start: 0 }"), "Range");
+        assert_eq!(variant_path(""), "");
+    }
+
+    #[test]
+    fn test_api_error() {
+        let err = OuterError::from(InnerError::Failure("foo".to_string()));
+        assert_eq!(err.subsystem(), "inner");
+        assert_eq!(err.code(), "Inner.Failure");
+        assert_eq!(err.details(), vec!["Inner failure: foo".to_string()]);
+
+        let err = OuterError::Message("foo".to_string());
+        assert_eq!(err.subsystem(), "outer");
+        assert_eq!(err.code(), "Message");
+        assert!(err.details().is_empty());
+
+        let err = OuterError::NotSupported;
+        assert_eq!(err.code(), "NotSupported");
+        assert!(err.details().is_empty());
+    }
+}
@@ -9,13 +9,15 @@ use std::collections::HashMap;
 use std::ffi::CString;
 use std::fmt::Debug;
 
+use utils::u64_to_usize;
 use vm_fdt::{Error as VmFdtError, FdtWriter, FdtWriterNode};
 use vm_memory::GuestMemoryError;
 
-use super::super::{DeviceType, InitrdConfig};
+use super::super::{make_rng_seed, DeviceType, InitrdConfig, RNG_SEED_SIZE};
 use super::cache_info::{read_cache_config, CacheEntry};
 use super::get_fdt_addr;
 use super::gic::GICDevice;
+use super::layout::PMU_PPI;
 use crate::vstate::memory::{Address, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap};
 
 // This is a value for uniquely identifying the FDT node declaring the interrupt controller.
@@ -42,6 +44,16 @@ const GIC_FDT_IRQ_TYPE_PPI: u32 = 1;
 const IRQ_TYPE_EDGE_RISING: u32 = 1;
 const IRQ_TYPE_LEVEL_HI: u32 = 4;
 
+// Magic number and structure block tokens of the FDT, from
+// https://devicetree-specification.readthedocs.io/en/stable/flattened-format.html
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+// Size of the FDT header.
+const FDT_HEADER_SIZE: usize = 40;
+
 /// Trait for devices to be added to the Flattened Device Tree.
 pub trait DeviceInfoForFDT {
     /// Returns the address where this device will be loaded.
@@ -61,6 +73,8 @@ pub enum FdtError {
     ReadCacheInfo(String),
     /// Failure in writing FDT in memory.
     WriteFdtToMemory(#[from] GuestMemoryError),
+    /// Failed to generate the random seed of the guest kernel.
+    RngSeed,
 }
 
 /// Creates the flattened device tree for this aarch64 microVM.
@@ -71,6 +85,8 @@ pub fn create_fdt<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher
     device_info: &HashMap<(DeviceType, String), T, S>,
     gic_device: &GICDevice,
     initrd: &Option<InitrdConfig>,
+    pmu: bool,
+    rng_seed: Option<&[u8]>,
 ) -> Result<Vec<u8>, FdtError> {
     // Allocate stuff necessary for storing the blob.
     let mut fdt_writer = FdtWriter::new()?;
@@ -91,9 +107,12 @@ pub fn create_fdt<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher
     fdt_writer.property_u32("interrupt-parent", GIC_PHANDLE)?;
     create_cpu_nodes(&mut fdt_writer, &vcpu_mpidr)?;
     create_memory_node(&mut fdt_writer, guest_mem)?;
-    create_chosen_node(&mut fdt_writer, cmdline, initrd)?;
+    create_chosen_node(&mut fdt_writer, cmdline, initrd, rng_seed)?;
     create_gic_node(&mut fdt_writer, gic_device)?;
     create_timer_node(&mut fdt_writer)?;
+    if pmu {
+        create_pmu_node(&mut fdt_writer)?;
+    }
     create_clock_node(&mut fdt_writer)?;
     create_psci_node(&mut fdt_writer)?;
     create_devices_node(&mut fdt_writer, device_info)?;
@@ -236,6 +255,7 @@ fn create_chosen_node(
     fdt: &mut FdtWriter,
     cmdline: CString,
     initrd: &Option<InitrdConfig>,
+    rng_seed: Option<&[u8]>,
 ) -> Result<(), FdtError> {
     let chosen = fdt.begin_node("chosen")?;
     // Workaround to be able to reuse an existing property_*() method; in property_string() method,
@@ -253,6 +273,11 @@ fn create_chosen_node(
         )?;
     }
 
+    // Entropy for the kernel, which removes the property once it consumed it.
+    if let Some(seed) = rng_seed {
+        fdt.property("rng-seed", seed)?;
+    }
+
     fdt.end_node(chosen)?;
 
     Ok(())
@@ -321,6 +346,19 @@ fn create_timer_node(fdt: &mut FdtWriter) -> Result<(), FdtError> {
     Ok(())
 }
 
+fn create_pmu_node(fdt: &mut FdtWriter) -> Result<(), FdtError> {
+    // See
+    // https://github.com/torvalds/linux/blob/master/Documentation/devicetree/bindings/arm/pmu.yaml
+    let pmu = fdt.begin_node("pmu")?;
+    fdt.property_string("compatible", "arm,armv8-pmuv3")?;
+    fdt.property_array_u32(
+        "interrupts",
+        &[GIC_FDT_IRQ_TYPE_PPI, PMU_PPI, IRQ_TYPE_LEVEL_HI],
+    )?;
+    fdt.end_node(pmu)?;
+    Ok(())
+}
+
 fn create_psci_node(fdt: &mut FdtWriter) -> Result<(), FdtError> {
     let compatible = "arm,psci-0.2";
 
@@ -419,6 +457,71 @@ fn create_devices_node<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildH
     Ok(())
 }
 
+/// Replaces the `rng-seed` property of the FDT in the memory of a restored guest, so that the
+/// microVMs restored from the same snapshot don't share it.
+///
+/// The kernel removes the property once it consumed it, in which case nothing is written. A
+/// malformed FDT is not an error, as it is owned by the guest.
+pub fn reseed_rng(guest_mem: &GuestMemoryMmap) -> Result<(), FdtError> {
+    let fdt_address = GuestAddress(get_fdt_addr(guest_mem));
+    let mut header = [0u8; FDT_HEADER_SIZE];
+    guest_mem.read_slice(&mut header, fdt_address)?;
+    if be32(&header, 0) != Some(FDT_MAGIC) {
+        return Ok(());
+    }
+    let total_size = be32(&header, 4).map_or(0, |size| u64_to_usize(size.into()));
+    let mut fdt = vec![0u8; total_size.min(super::layout::FDT_MAX_SIZE)];
+    if guest_mem.read_slice(&mut fdt, fdt_address).is_err() {
+        return Ok(());
+    }
+
+    if let Some(offset) = find_rng_seed(&fdt) {
+        let seed = make_rng_seed().map_err(|_| FdtError::RngSeed)?;
+        guest_mem.write_slice(&seed, fdt_address.unchecked_add(offset as u64))?;
+    }
+    Ok(())
+}
+
+// Reads the big endian 32 bits value at `offset` in `blob`.
+fn be32(blob: &[u8], offset: usize) -> Option<u32> {
+    let bytes = blob.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+// Returns the offset in `fdt` of the value of the `rng-seed` property, if it holds a seed.
+fn find_rng_seed(fdt: &[u8]) -> Option<usize> {
+    let align = |offset: usize| offset.checked_add(3).map(|offset| offset & !3);
+    let strings_offset = u64_to_usize(be32(fdt, 12)?.into());
+    let mut offset = u64_to_usize(be32(fdt, 8)?.into());
+    loop {
+        // The token was read, so these offsets do not overflow.
+        let token = be32(fdt, offset)?;
+        offset += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name_len = fdt.get(offset..)?.iter().position(|&byte| byte == 0)?;
+                offset = align(offset + name_len + 1)?;
+            }
+            FDT_END_NODE | FDT_NOP => {}
+            FDT_PROP => {
+                let len = u64_to_usize(be32(fdt, offset)?.into());
+                let name_offset = u64_to_usize(be32(fdt, offset + 4)?.into());
+                let name = fdt
+                    .get(strings_offset.checked_add(name_offset)?..)?
+                    .split(|&byte| byte == 0)
+                    .next()?;
+                let value_offset = offset + 8;
+                if name == b"rng-seed" && len == RNG_SEED_SIZE && fdt.len() - value_offset >= len {
+                    return Some(value_offset);
+                }
+                offset = align(value_offset.checked_add(len)?)?;
+            }
+            // FDT_END, or an invalid token.
+            _ => return None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::ffi::CString;
@@ -492,8 +595,55 @@ mod tests {
             &dev_info,
             &gic,
             &None,
+            false,
+            None,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_reseed_rng() {
+        let mem = arch_mem(layout::FDT_MAX_SIZE + 0x1000);
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        let gic = create_gic(&vm, 1, None).unwrap();
+        let seed = make_rng_seed().unwrap();
+
+        let dtb = create_fdt(
+            &mem,
+            vec![0],
+            CString::new("console=tty0").unwrap(),
+            &HashMap::<(DeviceType, std::string::String), MMIODeviceInfo>::new(),
+            &gic,
+            &None,
+            false,
+            Some(&seed),
         )
         .unwrap();
+        let offset = find_rng_seed(&dtb).unwrap();
+        assert_eq!(dtb[offset..offset + RNG_SEED_SIZE], seed);
+
+        reseed_rng(&mem).unwrap();
+        let mut reseeded = [0u8; RNG_SEED_SIZE];
+        let fdt_address = GuestAddress(get_fdt_addr(&mem));
+        mem.read_slice(&mut reseeded, fdt_address.unchecked_add(offset as u64))
+            .unwrap();
+        assert_ne!(reseeded, seed);
+
+        // The kernel replaces the property with FDT_NOP tokens once it consumed the seed.
+        let nop = (offset - 12..offset + RNG_SEED_SIZE)
+            .step_by(4)
+            .flat_map(|_| FDT_NOP.to_be_bytes())
+            .collect::<Vec<_>>();
+        mem.write_slice(&nop, fdt_address.unchecked_add(offset as u64 - 12))
+            .unwrap();
+        let mut fdt = vec![0u8; dtb.len()];
+        mem.read_slice(&mut fdt, fdt_address).unwrap();
+        assert_eq!(find_rng_seed(&fdt), None);
+        reseed_rng(&mem).unwrap();
+        let mut consumed = vec![0u8; dtb.len()];
+        mem.read_slice(&mut consumed, fdt_address).unwrap();
+        assert_eq!(consumed, fdt);
     }
 
     #[test]
@@ -516,6 +666,8 @@ mod tests {
             &HashMap::<(DeviceType, std::string::String), MMIODeviceInfo>::new(),
             &gic,
             &None,
+            false,
+            None,
         )
         .unwrap();
 
@@ -552,6 +704,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_create_fdt_with_pmu() {
+        let mem = arch_mem(layout::FDT_MAX_SIZE + 0x1000);
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        let gic = create_gic(&vm, 1, None).unwrap();
+
+        let dtb = create_fdt(
+            &mem,
+            vec![0],
+            CString::new("console=tty0").unwrap(),
+            &HashMap::<(DeviceType, std::string::String), MMIODeviceInfo>::new(),
+            &gic,
+            &None,
+            true,
+            None,
+        )
+        .unwrap();
+        let fdt = device_tree::DeviceTree::load(&dtb).unwrap();
+        let pmu = fdt.find("/pmu").unwrap();
+        assert_eq!(pmu.prop_str("compatible").unwrap(), "arm,armv8-pmuv3");
+        let interrupts = pmu.prop_raw("interrupts").unwrap();
+        assert_eq!(interrupts[4..8], PMU_PPI.to_be_bytes());
+    }
+
     #[test]
     fn test_create_fdt_with_initrd() {
         let mem = arch_mem(layout::FDT_MAX_SIZE + 0x1000);
@@ -577,6 +754,8 @@ mod tests {
             &HashMap::<(DeviceType, std::string::String), MMIODeviceInfo>::new(),
             &gic,
             &Some(initrd),
+            false,
+            None,
         )
         .unwrap();
 
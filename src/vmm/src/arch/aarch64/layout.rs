@@ -51,9 +51,20 @@
 
 /// Start of RAM on 64 bit ARM.
 pub const DRAM_MEM_START: u64 = 0x8000_0000; // 2 GB.
-/// The maximum RAM size.
+/// The maximum RAM size, with the default guest physical address space size.
 pub const DRAM_MEM_MAX_SIZE: usize = 0x00FF_8000_0000; // 1024 - 2 = 1022G.
 
+/// Default size of the guest physical address space (IPA), in bits, which is the one KVM uses
+/// when the host doesn't support configuring it.
+pub const DEFAULT_IPA_SIZE: u8 = 40;
+/// Smallest size of the guest physical address space that KVM supports, in bits.
+pub const MIN_IPA_SIZE: u8 = 32;
+/// Largest size of the guest physical address space that the architecture supports, in bits.
+pub const MAX_IPA_SIZE: u8 = 52;
+
+/// Alignment of the crash kernel region, as required by the arm64 kernel.
+pub const CRASH_KERNEL_ALIGNMENT: u64 = 2 << 20; // 2 MB.
+
 /// Kernel command line maximum size.
 /// As per `arch/arm64/include/uapi/asm/setup.h`.
 pub const CMDLINE_MAX_SIZE: usize = 2048;
@@ -72,5 +83,9 @@ pub const IRQ_MAX: u32 = 128;
 /// First usable interrupt on aarch64.
 pub const IRQ_BASE: u32 = 32;
 
+/// Private peripheral interrupt raised by the overflow of a PMU counter, numbered from the first
+/// PPI (interrupt 16) as in the device tree.
+pub const PMU_PPI: u32 = 7;
+
 /// Below this address will reside the GIC, above this address will reside the MMIO devices.
 pub const MAPPED_IO_START: u64 = 1 << 30; // 1 GB
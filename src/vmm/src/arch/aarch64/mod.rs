@@ -17,9 +17,14 @@ use std::collections::HashMap;
 use std::ffi::CString;
 use std::fmt::Debug;
 
+use utils::u64_to_usize;
+
 pub use self::fdt::DeviceInfoForFDT;
 use self::gic::GICDevice;
-use crate::arch::DeviceType;
+use crate::arch::{
+    make_rng_seed, DeviceType, InitrdConfig, MemoryMap, MemoryMapRegion, MemoryRegionKind,
+};
+use crate::vmm_config::machine_config::MemoryLayoutConfig;
 use crate::vstate::memory::{Address, GuestAddress, GuestMemory, GuestMemoryMmap};
 
 /// Errors thrown while configuring aarch64 system.
@@ -29,6 +34,10 @@ pub enum ConfigurationError {
     SetupFDT(#[from] fdt::FdtError),
     /// Failed to compute the initrd address.
     InitrdAddress,
+    /// Failed to compute the crash kernel region address.
+    CrashKernelAddress,
+    /// Failed to generate the random seed of the guest kernel.
+    RngSeed,
 }
 
 /// The start of the memory area reserved for MMIO devices.
@@ -36,15 +45,62 @@ pub const MMIO_MEM_START: u64 = layout::MAPPED_IO_START;
 /// The size of the memory area reserved for MMIO devices.
 pub const MMIO_MEM_SIZE: u64 = layout::DRAM_MEM_START - layout::MAPPED_IO_START; //>> 1GB
 
+/// Returns the maximum RAM size fitting in a guest physical address space of `ipa_size` bits.
+pub fn dram_mem_max_size(ipa_size: u8) -> usize {
+    u64_to_usize((1u64 << ipa_size) - layout::DRAM_MEM_START)
+}
+
 /// Returns a Vec of the valid memory addresses for aarch64.
 /// See [`layout`](layout) module for a drawing of the specific memory model for this platform.
-pub fn arch_memory_regions(size: usize) -> Vec<(GuestAddress, usize)> {
-    let dram_size = min(size, layout::DRAM_MEM_MAX_SIZE);
+/// Only the size of the guest physical address space of `memory_layout` is configurable on
+/// aarch64, which bounds the size of the DRAM.
+pub fn arch_memory_regions(
+    size: usize,
+    memory_layout: &MemoryLayoutConfig,
+) -> Vec<(GuestAddress, usize)> {
+    let ipa_size = memory_layout.ipa_size.unwrap_or(layout::DEFAULT_IPA_SIZE);
+    let dram_size = min(size, dram_mem_max_size(ipa_size));
     vec![(GuestAddress(layout::DRAM_MEM_START), dram_size)]
 }
 
+/// Returns the MMIO windows, as `(start, size)` pairs. The aarch64 memory layout only has the
+/// fixed MMIO area below the DRAM.
+pub fn mmio_windows(
+    _layout: &MemoryLayoutConfig,
+    _last_addr: GuestAddress,
+) -> ((u64, u64), Option<(u64, u64)>) {
+    ((MMIO_MEM_START, MMIO_MEM_SIZE), None)
+}
+
+/// Returns the layout of the guest physical address space of a microVM with `size` bytes of
+/// memory laid out according to `memory_layout`.
+pub fn memory_map(size: usize, memory_layout: &MemoryLayoutConfig) -> MemoryMap {
+    let ram = arch_memory_regions(size, memory_layout);
+    // The FDT is placed at the end of the DRAM, as done by `get_fdt_addr()`.
+    let (dram_start, dram_size) = ram[0];
+    let fdt_addr = (dram_start.raw_value() + dram_size as u64)
+        .checked_sub(layout::FDT_MAX_SIZE as u64)
+        .filter(|addr| *addr >= dram_start.raw_value())
+        .unwrap_or(layout::DRAM_MEM_START);
+
+    let mut regions = MemoryMap::ram(&ram);
+    regions.extend([
+        MemoryMapRegion {
+            kind: MemoryRegionKind::Mmio,
+            base: MMIO_MEM_START,
+            len: MMIO_MEM_SIZE,
+        },
+        MemoryMapRegion {
+            kind: MemoryRegionKind::Fdt,
+            base: fdt_addr,
+            len: layout::FDT_MAX_SIZE as u64,
+        },
+    ]);
+    MemoryMap::new(regions)
+}
+
 /// Configures the system and should be called once per vm before starting vcpu threads.
-/// For aarch64, we only setup the FDT.
+/// For aarch64, we only setup the FDT, which also holds a random seed for the guest kernel.
 ///
 /// # Arguments
 ///
@@ -61,7 +117,9 @@ pub fn configure_system<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::Build
     device_info: &HashMap<(DeviceType, String), T, S>,
     gic_device: &GICDevice,
     initrd: &Option<super::InitrdConfig>,
+    pmu: bool,
 ) -> Result<(), ConfigurationError> {
+    let rng_seed = make_rng_seed().map_err(|_| ConfigurationError::RngSeed)?;
     fdt::create_fdt(
         guest_mem,
         vcpu_mpidr,
@@ -69,10 +127,18 @@ pub fn configure_system<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::Build
         device_info,
         gic_device,
         initrd,
+        pmu,
+        Some(&rng_seed),
     )?;
     Ok(())
 }
 
+/// Replaces the random seed of the kernel in the memory of a restored guest, if it was not
+/// consumed yet.
+pub fn reseed_rng(guest_mem: &GuestMemoryMmap) -> Result<(), ConfigurationError> {
+    fdt::reseed_rng(guest_mem).map_err(ConfigurationError::SetupFDT)
+}
+
 /// Returns the memory address where the kernel could be loaded.
 pub fn get_kernel_start() -> u64 {
     layout::DRAM_MEM_START
@@ -96,6 +162,27 @@ pub fn initrd_load_addr(
     }
 }
 
+/// Returns the memory address where a crash kernel region of `size` bytes could be reserved.
+/// The region sits below the initrd if there is one, or else below the device tree blob, and
+/// must not overlap the kernel image ending at `kernel_end`.
+pub fn crash_kernel_addr(
+    guest_mem: &GuestMemoryMmap,
+    kernel_end: u64,
+    initrd: Option<&InitrdConfig>,
+    size: usize,
+) -> Result<u64, ConfigurationError> {
+    let top = initrd.map_or(get_fdt_addr(guest_mem), |initrd| initrd.address.raw_value());
+
+    let address = top
+        .checked_sub(size as u64)
+        .ok_or(ConfigurationError::CrashKernelAddress)?
+        & !(layout::CRASH_KERNEL_ALIGNMENT - 1);
+    if address < kernel_end || !guest_mem.address_in_range(GuestAddress(address)) {
+        return Err(ConfigurationError::CrashKernelAddress);
+    }
+    Ok(address)
+}
+
 // Auxiliary function to get the address where the device tree blob is loaded.
 fn get_fdt_addr(mem: &GuestMemoryMmap) -> u64 {
     // If the memory allocated is smaller than the size allocated for the FDT,
@@ -118,7 +205,7 @@ mod tests {
 
     #[test]
     fn test_regions_lt_1024gb() {
-        let regions = arch_memory_regions(1usize << 29);
+        let regions = arch_memory_regions(1usize << 29, &MemoryLayoutConfig::default());
         assert_eq!(1, regions.len());
         assert_eq!(GuestAddress(super::layout::DRAM_MEM_START), regions[0].0);
         assert_eq!(1usize << 29, regions[0].1);
@@ -126,12 +213,50 @@ mod tests {
 
     #[test]
     fn test_regions_gt_1024gb() {
-        let regions = arch_memory_regions(1usize << 41);
+        let regions = arch_memory_regions(1usize << 41, &MemoryLayoutConfig::default());
         assert_eq!(1, regions.len());
         assert_eq!(GuestAddress(super::layout::DRAM_MEM_START), regions[0].0);
         assert_eq!(super::layout::DRAM_MEM_MAX_SIZE, regions[0].1);
     }
 
+    #[test]
+    fn test_memory_map() {
+        let map = memory_map(128 << 20, &MemoryLayoutConfig::default());
+        let regions: Vec<_> = map
+            .regions
+            .iter()
+            .map(|region| (region.kind, region.base, region.len))
+            .collect();
+        let fdt_addr = get_fdt_addr(&arch_mem(128 << 20));
+        assert_eq!(
+            regions,
+            vec![
+                (MemoryRegionKind::Mmio, MMIO_MEM_START, MMIO_MEM_SIZE),
+                (MemoryRegionKind::Ram, layout::DRAM_MEM_START, 128 << 20),
+                (MemoryRegionKind::Fdt, fdt_addr, layout::FDT_MAX_SIZE as u64),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_regions_ipa_size() {
+        assert_eq!(
+            dram_mem_max_size(layout::DEFAULT_IPA_SIZE),
+            layout::DRAM_MEM_MAX_SIZE
+        );
+        let memory_layout = MemoryLayoutConfig {
+            ipa_size: Some(44),
+            ..Default::default()
+        };
+        let regions = arch_memory_regions(1usize << 42, &memory_layout);
+        assert_eq!(
+            regions,
+            vec![(GuestAddress(super::layout::DRAM_MEM_START), 1usize << 42)]
+        );
+        let regions = arch_memory_regions(1usize << 45, &memory_layout);
+        assert_eq!(regions[0].1, (1usize << 44) - (2usize << 30));
+    }
+
     #[test]
     fn test_get_fdt_addr() {
         let mem = arch_mem(layout::FDT_MAX_SIZE - 0x1000);
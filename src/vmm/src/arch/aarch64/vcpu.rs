@@ -49,9 +49,8 @@ pub fn get_manufacturer_id_from_state(regs: &Aarch64RegisterVec) -> Result<u32,
     }
 }
 
-/// Extract the Manufacturer ID from the host.
-/// The ID is found between bits 24-31 of MIDR_EL1 register.
-pub fn get_manufacturer_id_from_host() -> Result<u32, VcpuError> {
+/// Read the MIDR_EL1 register of the host.
+pub fn get_midr_el1_from_host() -> Result<u32, VcpuError> {
     let midr_el1_path =
         &PathBuf::from("/sys/devices/system/cpu/cpu0/regs/identification/midr_el1".to_string());
 
@@ -59,10 +58,14 @@ pub fn get_manufacturer_id_from_host() -> Result<u32, VcpuError> {
         VcpuError::GetMidrEl1(format!("Failed to get MIDR_EL1 from host path: {err}"))
     })?;
     let midr_el1_trimmed = midr_el1.trim_end().trim_start_matches("0x");
-    let manufacturer_id = u32::from_str_radix(midr_el1_trimmed, 16)
-        .map_err(|err| VcpuError::GetMidrEl1(format!("Invalid MIDR_EL1 found on host: {err}",)))?;
+    u32::from_str_radix(midr_el1_trimmed, 16)
+        .map_err(|err| VcpuError::GetMidrEl1(format!("Invalid MIDR_EL1 found on host: {err}",)))
+}
 
-    Ok(manufacturer_id >> 24)
+/// Extract the Manufacturer ID from the host.
+/// The ID is found between bits 24-31 of MIDR_EL1 register.
+pub fn get_manufacturer_id_from_host() -> Result<u32, VcpuError> {
+    Ok(get_midr_el1_from_host()? >> 24)
 }
 
 /// Configure relevant boot registers for a given vCPU.
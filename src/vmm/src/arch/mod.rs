@@ -5,15 +5,17 @@ use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
+use crate::devices::replay::{self, ReplaySource};
+
 /// Module for aarch64 related functionality.
 #[cfg(target_arch = "aarch64")]
 pub mod aarch64;
 
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::{
-    arch_memory_regions, configure_system, get_kernel_start, initrd_load_addr,
-    layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE, layout::IRQ_MAX, ConfigurationError, MMIO_MEM_SIZE,
-    MMIO_MEM_START,
+    arch_memory_regions, configure_system, crash_kernel_addr, get_kernel_start, initrd_load_addr,
+    layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE, layout::IRQ_MAX, memory_map, mmio_windows,
+    reseed_rng, ConfigurationError, MMIO_MEM_SIZE, MMIO_MEM_START,
 };
 
 /// Module for x86_64 related functionality.
@@ -22,10 +24,10 @@ pub mod x86_64;
 
 #[cfg(target_arch = "x86_64")]
 pub use crate::arch::x86_64::{
-    arch_memory_regions, configure_system, get_kernel_start, initrd_load_addr, layout::APIC_ADDR,
-    layout::CMDLINE_MAX_SIZE, layout::IOAPIC_ADDR, layout::IRQ_BASE, layout::IRQ_MAX,
-    layout::SYSTEM_MEM_SIZE, layout::SYSTEM_MEM_START, ConfigurationError, MMIO_MEM_SIZE,
-    MMIO_MEM_START,
+    arch_memory_regions, configure_system, crash_kernel_addr, get_kernel_start, initrd_load_addr,
+    layout::APIC_ADDR, layout::CMDLINE_MAX_SIZE, layout::IOAPIC_ADDR, layout::IRQ_BASE,
+    layout::IRQ_MAX, layout::SYSTEM_MEM_SIZE, layout::SYSTEM_MEM_START, memory_map, mmio_windows,
+    reseed_rng, ConfigurationError, MMIO_MEM_SIZE, MMIO_MEM_START,
 };
 
 /// Types of devices that can get attached to this platform.
@@ -41,6 +43,9 @@ pub enum DeviceType {
     Rtc,
     /// Device Type: BootTimer.
     BootTimer,
+    /// Device Type: TPM.
+    #[cfg(target_arch = "x86_64")]
+    Tpm,
 }
 
 /// Type for passing information about the initrd in the guest memory.
@@ -52,9 +57,84 @@ pub struct InitrdConfig {
     pub size: usize,
 }
 
+/// Kind of a region of the guest physical address space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryRegionKind {
+    /// Guest memory.
+    Ram,
+    /// Window the MMIO address ranges of the devices are allocated from.
+    Mmio,
+    /// 64-bit window the large MMIO address ranges of the devices are allocated from.
+    Mmio64,
+    /// Guest memory reserved for the MP table, the ACPI tables and the boot setup data, on
+    /// x86_64.
+    System,
+    /// ACPI Root System Description Pointer, on x86_64.
+    AcpiRsdp,
+    /// I/O APIC registers, on x86_64.
+    Ioapic,
+    /// Local APIC registers, on x86_64.
+    Lapic,
+    /// Task state segment KVM needs for real mode, on x86_64.
+    KvmTss,
+    /// Flattened device tree handed to the kernel, on aarch64.
+    Fdt,
+}
+
+/// Region of the guest physical address space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct MemoryMapRegion {
+    /// What the region is used for.
+    pub kind: MemoryRegionKind,
+    /// Guest physical address where the region starts.
+    pub base: u64,
+    /// Length of the region, in bytes.
+    pub len: u64,
+}
+
+/// Layout of the guest physical address space, as computed by the VMM. The regions are sorted
+/// by base address, and the regions carved out of guest memory or of an MMIO window are listed
+/// along with it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct MemoryMap {
+    /// Regions of the guest physical address space.
+    pub regions: Vec<MemoryMapRegion>,
+}
+
+impl MemoryMap {
+    fn new(mut regions: Vec<MemoryMapRegion>) -> Self {
+        // Larger regions come first so that a region precedes the ones carved out of it.
+        regions.sort_by_key(|region| (region.base, std::cmp::Reverse(region.len)));
+        MemoryMap { regions }
+    }
+
+    fn ram(regions: &[(crate::vstate::memory::GuestAddress, usize)]) -> Vec<MemoryMapRegion> {
+        regions
+            .iter()
+            .map(|(base, len)| MemoryMapRegion {
+                kind: MemoryRegionKind::Ram,
+                base: base.0,
+                len: *len as u64,
+            })
+            .collect()
+    }
+}
+
 /// Default (smallest) memory page size for the supported architectures.
 pub const PAGE_SIZE: usize = 4096;
 
+/// Size of the random seed handed to the guest kernel at boot, to initialize its entropy pool
+/// before any hardware RNG driver is probed.
+pub const RNG_SEED_SIZE: usize = 32;
+
+/// Returns a new random seed for the guest kernel.
+pub fn make_rng_seed() -> Result<[u8; RNG_SEED_SIZE], aws_lc_rs::error::Unspecified> {
+    let mut seed = [0u8; RNG_SEED_SIZE];
+    replay::replay_or_fill(&ReplaySource::Entropy, &mut seed, aws_lc_rs::rand::fill)?;
+    Ok(seed)
+}
+
 impl fmt::Display for DeviceType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
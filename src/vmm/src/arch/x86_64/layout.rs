@@ -66,3 +66,23 @@ pub const SYSTEM_MEM_START: u64 = 0x9fc00;
 /// 257KiB is more than we need, however we reserve this space for potential future use of
 /// ACPI features (new tables and/or devices).
 pub const SYSTEM_MEM_SIZE: u64 = RSDP_ADDR - SYSTEM_MEM_START;
+
+/// Lowest start of the MMIO gap below 4 GiB, so that the kernel and the initrd fit in the guest
+/// memory below it.
+pub const MIN_MMIO_GAP_START: u64 = 1 << 30; // 1 GB.
+
+/// Smallest size of the MMIO gap below 4 GiB.
+pub const MIN_MMIO_GAP_SIZE: u64 = 256 << 20; // 256 MB.
+
+/// Alignment of the start and size of the MMIO gap, so that guest memory backed by huge pages
+/// can be split around it.
+pub const MMIO_GAP_ALIGNMENT: u64 = 2 << 20; // 2 MB.
+
+/// Alignment of the start and size of the 64-bit MMIO window placed above the guest memory.
+pub const MMIO64_ALIGNMENT: u64 = 1 << 30; // 1 GB.
+
+/// Largest size of the 64-bit MMIO window.
+pub const MAX_MMIO64_SIZE: u64 = 1 << 40; // 1 TB.
+
+/// Alignment of the crash kernel region, as required by the x86_64 kernel.
+pub const CRASH_KERNEL_ALIGNMENT: u64 = 16 << 20; // 16 MB.
@@ -22,11 +22,15 @@ use linux_loader::configurator::linux::LinuxBootConfigurator;
 use linux_loader::configurator::{BootConfigurator, BootParams};
 use linux_loader::loader::bootparam::boot_params;
 use utils::u64_to_usize;
+use vm_allocator::AllocPolicy;
 
-use crate::arch::InitrdConfig;
+use crate::arch::{
+    make_rng_seed, InitrdConfig, MemoryMap, MemoryMapRegion, MemoryRegionKind, RNG_SEED_SIZE,
+};
 use crate::device_manager::resources::ResourceAllocator;
+use crate::vmm_config::machine_config::MemoryLayoutConfig;
 use crate::vstate::memory::{
-    Address, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion,
+    Address, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion,
 };
 
 // Value taken from https://elixir.bootlin.com/linux/v5.10.68/source/arch/x86/include/uapi/asm/e820.h#L31
@@ -35,6 +39,16 @@ const E820_RAM: u32 = 1;
 // Reserved area that should be avoided during memory allocations
 const E820_RESERVED: u32 = 2;
 
+// Type of the setup_data entry holding a random seed for the kernel, taken from
+// https://elixir.bootlin.com/linux/v6.1/source/arch/x86/include/uapi/asm/bootparam.h#L14
+const SETUP_RNG_SEED: u32 = 9;
+// Offset of the `setup_data` pointer of the setup header in the zero page.
+const SETUP_DATA_PTR_OFFSET: u64 = 0x250;
+// Size of the header of a setup_data entry: the address of the next entry, its type and length.
+const SETUP_DATA_HEADER_SIZE: usize = 16;
+// Bound on the setup_data entries walked when reseeding, as the list lives in guest memory.
+const MAX_SETUP_DATA_ENTRIES: usize = 16;
+
 /// Errors thrown while configuring x86_64 system.
 #[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
 pub enum ConfigurationError {
@@ -46,6 +60,10 @@ pub enum ConfigurationError {
     ZeroPageSetup,
     /// Failed to compute initrd address.
     InitrdAddress,
+    /// Failed to compute the crash kernel region address.
+    CrashKernelAddress,
+    /// Failed to write the random seed of the guest kernel.
+    RngSeed,
 }
 
 const FIRST_ADDR_PAST_32BITS: u64 = 1 << 32;
@@ -60,21 +78,81 @@ pub const MMIO_MEM_SIZE: u64 = MEM_32BIT_GAP_SIZE;
 /// Returns a Vec of the valid memory addresses.
 /// These should be used to configure the GuestMemoryMmap structure for the platform.
 /// For x86_64 all addresses are valid from the start of the kernel except a
-/// carve out at the end of 32bit address space.
-pub fn arch_memory_regions(size: usize) -> Vec<(GuestAddress, usize)> {
-    // It's safe to cast MMIO_MEM_START to usize because it fits in a u32 variable
+/// carve out for the MMIO gap of `layout`, below the end of 32bit address space.
+pub fn arch_memory_regions(size: usize, layout: &MemoryLayoutConfig) -> Vec<(GuestAddress, usize)> {
+    // It's safe to cast the gap start to usize because it fits in a u32 variable
     // (It points to an address in the 32 bit space).
-    match size.checked_sub(usize::try_from(MMIO_MEM_START).unwrap()) {
+    let mmio_gap_start = usize::try_from(layout.mmio_gap_start).unwrap();
+    match size.checked_sub(mmio_gap_start) {
         // case1: guest memory fits before the gap
         None | Some(0) => vec![(GuestAddress(0), size)],
         // case2: guest memory extends beyond the gap
         Some(remaining) => vec![
-            (GuestAddress(0), usize::try_from(MMIO_MEM_START).unwrap()),
+            (GuestAddress(0), mmio_gap_start),
             (GuestAddress(FIRST_ADDR_PAST_32BITS), remaining),
         ],
     }
 }
 
+/// Returns the MMIO windows of `layout`, as `(start, size)` pairs: the gap below 4 GiB, and the
+/// 64-bit window placed above the guest memory ending at `last_addr`, if it has one.
+pub fn mmio_windows(
+    layout: &MemoryLayoutConfig,
+    last_addr: GuestAddress,
+) -> ((u64, u64), Option<(u64, u64)>) {
+    let mmio64 = (layout.mmio64_size > 0).then(|| {
+        let start = (last_addr.raw_value() + 1)
+            .max(FIRST_ADDR_PAST_32BITS)
+            .next_multiple_of(layout::MMIO64_ALIGNMENT);
+        (start, layout.mmio64_size)
+    });
+    ((layout.mmio_gap_start, layout.mmio_gap_size), mmio64)
+}
+
+/// Returns the layout of the guest physical address space of a microVM with `size` bytes of
+/// memory laid out around the MMIO windows of `layout`.
+pub fn memory_map(size: usize, layout: &MemoryLayoutConfig) -> MemoryMap {
+    let ram = arch_memory_regions(size, layout);
+    // The guest memory has at least one region.
+    let (last_base, last_len) = ram[ram.len() - 1];
+    let last_addr = last_base.unchecked_add(last_len as u64 - 1);
+    let ((mmio_start, mmio_size), mmio64) = mmio_windows(layout, last_addr);
+
+    let region = |kind, base, len| MemoryMapRegion { kind, base, len };
+    let mut regions = MemoryMap::ram(&ram);
+    regions.extend([
+        region(
+            MemoryRegionKind::System,
+            layout::SYSTEM_MEM_START,
+            layout::SYSTEM_MEM_SIZE,
+        ),
+        region(
+            MemoryRegionKind::AcpiRsdp,
+            layout::RSDP_ADDR,
+            std::mem::size_of::<acpi_tables::Rsdp>() as u64,
+        ),
+        region(MemoryRegionKind::Mmio, mmio_start, mmio_size),
+        region(
+            MemoryRegionKind::Ioapic,
+            u64::from(layout::IOAPIC_ADDR),
+            super::PAGE_SIZE as u64,
+        ),
+        region(
+            MemoryRegionKind::Lapic,
+            u64::from(layout::APIC_ADDR),
+            super::PAGE_SIZE as u64,
+        ),
+        // KVM uses three pages for the TSS.
+        region(
+            MemoryRegionKind::KvmTss,
+            layout::KVM_TSS_ADDRESS,
+            3 * super::PAGE_SIZE as u64,
+        ),
+    ]);
+    regions.extend(mmio64.map(|(start, size)| region(MemoryRegionKind::Mmio64, start, size)));
+    MemoryMap::new(regions)
+}
+
 /// Returns the memory address where the kernel could be loaded.
 pub fn get_kernel_start() -> u64 {
     layout::HIMEM_START
@@ -98,6 +176,30 @@ pub fn initrd_load_addr(
     Ok(align_to_pagesize(lowmem_size - initrd_size) as u64)
 }
 
+/// Returns the memory address where a crash kernel region of `size` bytes could be reserved.
+/// The region sits at the top of the low memory, below the initrd if there is one, and must not
+/// overlap the kernel image ending at `kernel_end`.
+pub fn crash_kernel_addr(
+    guest_mem: &GuestMemoryMmap,
+    kernel_end: u64,
+    initrd: Option<&InitrdConfig>,
+    size: usize,
+) -> Result<u64, ConfigurationError> {
+    let first_region = guest_mem
+        .find_region(GuestAddress::new(0))
+        .ok_or(ConfigurationError::CrashKernelAddress)?;
+    let top = initrd.map_or(first_region.len(), |initrd| initrd.address.raw_value());
+
+    let address = top
+        .checked_sub(size as u64)
+        .ok_or(ConfigurationError::CrashKernelAddress)?
+        & !(layout::CRASH_KERNEL_ALIGNMENT - 1);
+    if address < kernel_end {
+        return Err(ConfigurationError::CrashKernelAddress);
+    }
+    Ok(address)
+}
+
 /// Configures the system and should be called once per vm before starting vcpu threads.
 ///
 /// # Arguments
@@ -107,6 +209,7 @@ pub fn initrd_load_addr(
 /// * `cmdline_size` - Size of the kernel command line in bytes including the null terminator.
 /// * `initrd` - Information about where the ramdisk image was loaded in the `guest_mem`.
 /// * `num_cpus` - Number of virtual CPUs the guest will have.
+/// * `memory_layout` - Layout of the MMIO address space the guest memory is split around.
 pub fn configure_system(
     guest_mem: &GuestMemoryMmap,
     resource_allocator: &mut ResourceAllocator,
@@ -114,13 +217,14 @@ pub fn configure_system(
     cmdline_size: usize,
     initrd: &Option<InitrdConfig>,
     num_cpus: u8,
+    memory_layout: &MemoryLayoutConfig,
 ) -> Result<(), ConfigurationError> {
     const KERNEL_BOOT_FLAG_MAGIC: u16 = 0xaa55;
     const KERNEL_HDR_MAGIC: u32 = 0x5372_6448;
     const KERNEL_LOADER_OTHER: u8 = 0xff;
     const KERNEL_MIN_ALIGNMENT_BYTES: u32 = 0x0100_0000; // Must be non-zero.
     let first_addr_past_32bits = GuestAddress(FIRST_ADDR_PAST_32BITS);
-    let end_32bit_gap_start = GuestAddress(MMIO_MEM_START);
+    let end_32bit_gap_start = GuestAddress(memory_layout.mmio_gap_start);
 
     let himem_start = GuestAddress(layout::HIMEM_START);
 
@@ -143,6 +247,7 @@ pub fn configure_system(
         params.hdr.ramdisk_image = u32::try_from(initrd_config.address.raw_value()).unwrap();
         params.hdr.ramdisk_size = u32::try_from(initrd_config.size).unwrap();
     }
+    params.hdr.setup_data = setup_rng_seed(guest_mem, resource_allocator)?;
 
     // We mark first [0x0, SYSTEM_MEM_START) region as usable RAM and the subsequent
     // [SYSTEM_MEM_START, (SYSTEM_MEM_START + SYSTEM_MEM_SIZE)) as reserved (note
@@ -194,6 +299,69 @@ pub fn configure_system(
     .map_err(|_| ConfigurationError::ZeroPageSetup)
 }
 
+/// Writes a setup_data entry holding a random seed for the entropy pool of the kernel in the system
+/// memory, and returns its address.
+fn setup_rng_seed(
+    guest_mem: &GuestMemoryMmap,
+    resource_allocator: &mut ResourceAllocator,
+) -> Result<u64, ConfigurationError> {
+    let seed = make_rng_seed().map_err(|_| ConfigurationError::RngSeed)?;
+    let addr = resource_allocator
+        .allocate_system_memory(
+            (SETUP_DATA_HEADER_SIZE + RNG_SEED_SIZE) as u64,
+            8,
+            AllocPolicy::FirstMatch,
+        )
+        .map_err(|_| ConfigurationError::RngSeed)?;
+
+    let mut entry = Vec::with_capacity(SETUP_DATA_HEADER_SIZE + RNG_SEED_SIZE);
+    // This is the only entry of the list.
+    entry.extend_from_slice(&0u64.to_le_bytes());
+    entry.extend_from_slice(&SETUP_RNG_SEED.to_le_bytes());
+    entry.extend_from_slice(&u32::try_from(RNG_SEED_SIZE).unwrap().to_le_bytes());
+    entry.extend_from_slice(&seed);
+    guest_mem
+        .write_slice(&entry, GuestAddress(addr))
+        .map_err(|_| ConfigurationError::RngSeed)?;
+    Ok(addr)
+}
+
+/// Replaces the random seed of the kernel in the memory of a restored guest, so that the microVMs
+/// restored from the same snapshot don't share it.
+///
+/// The kernel zeroes the length of the seed once it consumed it, in which case nothing is written.
+/// A malformed setup_data list is not an error, as it is owned by the guest.
+pub fn reseed_rng(guest_mem: &GuestMemoryMmap) -> Result<(), ConfigurationError> {
+    let mut addr: u64 = guest_mem
+        .read_obj(GuestAddress(
+            layout::ZERO_PAGE_START + SETUP_DATA_PTR_OFFSET,
+        ))
+        .map_err(|_| ConfigurationError::RngSeed)?;
+    for _ in 0..MAX_SETUP_DATA_ENTRIES {
+        let mut header = [0u8; SETUP_DATA_HEADER_SIZE];
+        if addr == 0
+            || guest_mem
+                .read_slice(&mut header, GuestAddress(addr))
+                .is_err()
+        {
+            break;
+        }
+        let next = u64::from_le_bytes(header[0..8].try_into().unwrap());
+        let type_ = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let len = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        if type_ == SETUP_RNG_SEED && u64_to_usize(len.into()) == RNG_SEED_SIZE {
+            let seed = make_rng_seed().map_err(|_| ConfigurationError::RngSeed)?;
+            // The entry header was read, so this does not overflow.
+            let seed_addr = GuestAddress(addr + SETUP_DATA_HEADER_SIZE as u64);
+            guest_mem
+                .write_slice(&seed, seed_addr)
+                .map_err(|_| ConfigurationError::RngSeed)?;
+        }
+        addr = next;
+    }
+    Ok(())
+}
+
 /// Add an e820 region to the e820 map.
 /// Returns Ok(()) if successful, or an error if there is no space left in the map.
 fn add_e820_entry(
@@ -223,7 +391,7 @@ mod tests {
 
     #[test]
     fn regions_lt_4gb() {
-        let regions = arch_memory_regions(1usize << 29);
+        let regions = arch_memory_regions(1usize << 29, &MemoryLayoutConfig::default());
         assert_eq!(1, regions.len());
         assert_eq!(GuestAddress(0), regions[0].0);
         assert_eq!(1usize << 29, regions[0].1);
@@ -231,19 +399,141 @@ mod tests {
 
     #[test]
     fn regions_gt_4gb() {
-        let regions = arch_memory_regions((1usize << 32) + 0x8000);
+        let regions = arch_memory_regions((1usize << 32) + 0x8000, &MemoryLayoutConfig::default());
         assert_eq!(2, regions.len());
         assert_eq!(GuestAddress(0), regions[0].0);
         assert_eq!(GuestAddress(1u64 << 32), regions[1].0);
     }
 
+    #[test]
+    fn regions_custom_mmio_gap() {
+        let layout = MemoryLayoutConfig {
+            mmio_gap_start: 2 << 30,
+            mmio_gap_size: 2 << 30,
+            mmio64_size: 0,
+            ipa_size: None,
+        };
+        let regions = arch_memory_regions(3 << 30, &layout);
+        assert_eq!(
+            regions,
+            vec![
+                (GuestAddress(0), 2 << 30),
+                (GuestAddress(1u64 << 32), 1 << 30)
+            ]
+        );
+        let regions = arch_memory_regions(1 << 30, &layout);
+        assert_eq!(regions, vec![(GuestAddress(0), 1 << 30)]);
+    }
+
+    #[test]
+    fn test_mmio_windows() {
+        let layout = MemoryLayoutConfig::default();
+        assert_eq!(
+            mmio_windows(&layout, GuestAddress((128 << 20) - 1)),
+            ((MMIO_MEM_START, MMIO_MEM_SIZE), None)
+        );
+
+        let layout = MemoryLayoutConfig {
+            mmio_gap_start: 2 << 30,
+            mmio_gap_size: 2 << 30,
+            mmio64_size: 64 << 30,
+            ipa_size: None,
+        };
+        // The 64-bit window starts past 4 GiB, or past the guest memory above it.
+        assert_eq!(
+            mmio_windows(&layout, GuestAddress((1 << 30) - 1)),
+            ((2 << 30, 2 << 30), Some((4 << 30, 64 << 30)))
+        );
+        assert_eq!(
+            mmio_windows(&layout, GuestAddress((4 << 30) + (1 << 20) - 1)),
+            ((2 << 30, 2 << 30), Some((5 << 30, 64 << 30)))
+        );
+    }
+
+    #[test]
+    fn test_memory_map() {
+        use MemoryRegionKind::*;
+
+        let layout = MemoryLayoutConfig {
+            mmio_gap_start: 2 << 30,
+            mmio_gap_size: 2 << 30,
+            mmio64_size: 64 << 30,
+            ipa_size: None,
+        };
+        let map = memory_map(3 << 30, &layout);
+        let regions: Vec<_> = map
+            .regions
+            .iter()
+            .map(|region| (region.kind, region.base, region.len))
+            .collect();
+        assert_eq!(
+            regions,
+            vec![
+                (Ram, 0, 2 << 30),
+                (System, layout::SYSTEM_MEM_START, layout::SYSTEM_MEM_SIZE),
+                (AcpiRsdp, layout::RSDP_ADDR, 36),
+                (Mmio, 2 << 30, 2 << 30),
+                (Ioapic, 0xfec0_0000, 0x1000),
+                (Lapic, 0xfee0_0000, 0x1000),
+                (KvmTss, 0xfffb_d000, 0x3000),
+                (Ram, 4 << 30, 1 << 30),
+                (Mmio64, 5 << 30, 64 << 30),
+            ]
+        );
+
+        // There is no 64-bit MMIO window by default.
+        let map = memory_map(128 << 20, &MemoryLayoutConfig::default());
+        assert_eq!(map.regions[0].kind, Ram);
+        assert_eq!(map.regions[0].len, 128 << 20);
+        assert!(!map.regions.iter().any(|region| region.kind == Mmio64));
+    }
+
+    #[test]
+    fn test_crash_kernel_addr() {
+        let gm = single_region_mem(256 << 20);
+        let kernel_end = 32 << 20;
+
+        // Without an initrd, the region sits at the top of the low memory.
+        assert_eq!(
+            crash_kernel_addr(&gm, kernel_end, None, 64 << 20).unwrap(),
+            192 << 20
+        );
+
+        // With an initrd, the region sits below it, aligned down to 16 MiB.
+        let initrd = InitrdConfig {
+            address: GuestAddress((250 << 20) + 0x1000),
+            size: (6 << 20) - 0x1000,
+        };
+        assert_eq!(
+            crash_kernel_addr(&gm, kernel_end, Some(&initrd), 64 << 20).unwrap(),
+            176 << 20
+        );
+
+        // The region must not overlap the kernel image.
+        assert_eq!(
+            crash_kernel_addr(&gm, kernel_end, None, 240 << 20),
+            Err(ConfigurationError::CrashKernelAddress)
+        );
+        assert_eq!(
+            crash_kernel_addr(&gm, kernel_end, None, 512 << 20),
+            Err(ConfigurationError::CrashKernelAddress)
+        );
+    }
+
     #[test]
     fn test_system_configuration() {
         let no_vcpus = 4;
         let gm = single_region_mem(0x10000);
         let mut resource_allocator = ResourceAllocator::new().unwrap();
-        let config_err =
-            configure_system(&gm, &mut resource_allocator, GuestAddress(0), 0, &None, 1);
+        let config_err = configure_system(
+            &gm,
+            &mut resource_allocator,
+            GuestAddress(0),
+            0,
+            &None,
+            1,
+            &MemoryLayoutConfig::default(),
+        );
         assert_eq!(
             config_err.unwrap_err(),
             super::ConfigurationError::MpTableSetup(mptable::MptableError::NotEnoughMemory)
@@ -260,6 +550,7 @@ mod tests {
             0,
             &None,
             no_vcpus,
+            &MemoryLayoutConfig::default(),
         )
         .unwrap();
 
@@ -274,6 +565,7 @@ mod tests {
             0,
             &None,
             no_vcpus,
+            &MemoryLayoutConfig::default(),
         )
         .unwrap();
 
@@ -288,8 +580,58 @@ mod tests {
             0,
             &None,
             no_vcpus,
+            &MemoryLayoutConfig::default(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_rng_seed() {
+        let gm = arch_mem(128 << 20);
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        configure_system(
+            &gm,
+            &mut resource_allocator,
+            GuestAddress(0),
+            0,
+            &None,
+            1,
+            &MemoryLayoutConfig::default(),
         )
         .unwrap();
+
+        let addr: u64 = gm
+            .read_obj(GuestAddress(
+                layout::ZERO_PAGE_START + SETUP_DATA_PTR_OFFSET,
+            ))
+            .unwrap();
+        assert!(addr >= layout::SYSTEM_MEM_START);
+        let read_entry = || {
+            let mut entry = [0u8; SETUP_DATA_HEADER_SIZE + RNG_SEED_SIZE];
+            gm.read_slice(&mut entry, GuestAddress(addr)).unwrap();
+            entry
+        };
+        let entry = read_entry();
+        assert_eq!(entry[0..8], [0; 8]);
+        assert_eq!(entry[8..12], SETUP_RNG_SEED.to_le_bytes());
+        assert_eq!(entry[12..16], 32u32.to_le_bytes());
+
+        reseed_rng(&gm).unwrap();
+        let reseeded = read_entry();
+        assert_eq!(
+            entry[..SETUP_DATA_HEADER_SIZE],
+            reseeded[..SETUP_DATA_HEADER_SIZE]
+        );
+        assert_ne!(
+            entry[SETUP_DATA_HEADER_SIZE..],
+            reseeded[SETUP_DATA_HEADER_SIZE..]
+        );
+
+        // The guest consumed the seed.
+        gm.write_slice(&[0u8; RNG_SEED_SIZE + 4], GuestAddress(addr + 12))
+            .unwrap();
+        reseed_rng(&gm).unwrap();
+        assert_eq!(read_entry()[12..], [0u8; RNG_SEED_SIZE + 4]);
     }
 
     #[test]
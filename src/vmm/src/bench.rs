@@ -0,0 +1,372 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Programmatic measurement of the boot, snapshot and restore times of microVMs.
+//!
+//! Each iteration of a benchmark boots a microVM from the given artifacts, lets it run its
+//! [`Workload`], pauses it and creates a full snapshot, and then restores and resumes a new
+//! microVM from the snapshot. The snapshot files were just written, so they are still in the page
+//! cache and the restore is a warm one. This lets users run performance regression tests of the
+//! VMM against their own kernels and root filesystems:
+//!
+//! ```no_run
+//! use vmm::bench::{run, Artifacts, Workload};
+//!
+//! let artifacts = Artifacts {
+//!     kernel_image_path: "vmlinux".into(),
+//!     rootfs_path: Some("rootfs.ext4".into()),
+//!     boot_args: None,
+//! };
+//! let report = run(&artifacts, &Workload::SMALL, 10, "/tmp".as_ref()).unwrap();
+//! println!("{}", serde_json::to_string(&report).unwrap());
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+
+use crate::builder::{build_and_boot_microvm, StartMicrovmError};
+use crate::landlock::set_landlock;
+use crate::persist::{
+    create_snapshot, restore_from_snapshot, CreateSnapshotError, RestoreFromSnapshotError, VmInfo,
+};
+use crate::resources::{ResourcesError, VmResources};
+use crate::seccomp_filters::get_empty_filters;
+use crate::vmm_config::instance_info::{InstanceInfo, VmState};
+use crate::vmm_config::snapshot::{
+    CreateSnapshotParams, LoadSnapshotParams, MemBackendConfig, MemBackendType, SnapshotType,
+};
+use crate::{EventManager, FcExitCode, Vmm, VmmError, HTTP_MAX_PAYLOAD_SIZE};
+
+/// Boot arguments used when the artifacts don't specify any.
+pub const DEFAULT_BOOT_ARGS: &str = "console=ttyS0 reboot=k panic=1 pci=off";
+
+/// Errors of the benchmarks.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum BenchError {
+    /// Failed to configure the microVM: {0}
+    Resources(ResourcesError),
+    /// Failed to boot the microVM: {0}
+    Boot(StartMicrovmError),
+    /// Failed to run the event loop: {0}
+    EventLoop(event_manager::Error),
+    /// Failed to pause the microVM: {0}
+    Pause(VmmError),
+    /// Failed to create the snapshot: {0}
+    CreateSnapshot(CreateSnapshotError),
+    /// Failed to restore the snapshot: {0}
+    Restore(RestoreFromSnapshotError),
+    /// Failed to resume the microVM: {0}
+    Resume(VmmError),
+}
+
+/// Guest artifacts the microVMs are booted from.
+#[derive(Debug, Clone)]
+pub struct Artifacts {
+    /// Path of the kernel image.
+    pub kernel_image_path: PathBuf,
+    /// Path of the root filesystem, attached as a read-only root drive, if any.
+    pub rootfs_path: Option<PathBuf>,
+    /// Kernel command line, [`DEFAULT_BOOT_ARGS`] by default.
+    pub boot_args: Option<String>,
+}
+
+/// Shape of the benchmarked microVMs and what they run before being snapshotted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workload {
+    /// Name of the workload, reported with the measurements.
+    pub name: &'static str,
+    /// Number of vCPUs.
+    pub vcpu_count: u8,
+    /// Guest memory size, in MiB.
+    pub mem_size_mib: usize,
+    /// Time the guest runs after booting, before the microVM is paused and snapshotted.
+    pub run_time: Duration,
+}
+
+impl Workload {
+    /// Single vCPU microVM with 128 MiB of memory.
+    pub const SMALL: Workload = Workload {
+        name: "small",
+        vcpu_count: 1,
+        mem_size_mib: 128,
+        run_time: Duration::from_secs(1),
+    };
+    /// MicroVM with 2 vCPUs and 1 GiB of memory.
+    pub const MEDIUM: Workload = Workload {
+        name: "medium",
+        vcpu_count: 2,
+        mem_size_mib: 1024,
+        run_time: Duration::from_secs(2),
+    };
+    /// MicroVM with 8 vCPUs and 4 GiB of memory.
+    pub const LARGE: Workload = Workload {
+        name: "large",
+        vcpu_count: 8,
+        mem_size_mib: 4096,
+        run_time: Duration::from_secs(4),
+    };
+}
+
+/// Durations measured for one phase of the benchmark, one per iteration.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Measurements {
+    /// Durations, in microseconds.
+    pub samples_us: Vec<u64>,
+}
+
+impl Measurements {
+    fn push(&mut self, duration: Duration) {
+        self.samples_us
+            .push(u64::try_from(duration.as_micros()).unwrap_or(u64::MAX));
+    }
+
+    /// Shortest duration, in microseconds.
+    pub fn min_us(&self) -> u64 {
+        self.samples_us.iter().copied().min().unwrap_or_default()
+    }
+
+    /// Longest duration, in microseconds.
+    pub fn max_us(&self) -> u64 {
+        self.samples_us.iter().copied().max().unwrap_or_default()
+    }
+
+    /// Mean duration, in microseconds.
+    pub fn mean_us(&self) -> u64 {
+        match u64::try_from(self.samples_us.len()) {
+            Ok(0) | Err(_) => 0,
+            Ok(len) => self.samples_us.iter().sum::<u64>() / len,
+        }
+    }
+
+    /// Duration below which `percentile` percent of the samples fall, in microseconds.
+    pub fn percentile_us(&self, percentile: u8) -> u64 {
+        let mut samples = self.samples_us.clone();
+        samples.sort_unstable();
+        let rank = (samples.len() * usize::from(percentile.min(100))).div_ceil(100);
+        samples
+            .get(rank.saturating_sub(1))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Serialize for Measurements {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Measurements", 6)?;
+        state.serialize_field("min_us", &self.min_us())?;
+        state.serialize_field("max_us", &self.max_us())?;
+        state.serialize_field("mean_us", &self.mean_us())?;
+        state.serialize_field("p50_us", &self.percentile_us(50))?;
+        state.serialize_field("p99_us", &self.percentile_us(99))?;
+        state.serialize_field("samples_us", &self.samples_us)?;
+        state.end()
+    }
+}
+
+/// Results of a benchmark.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Report {
+    /// Name of the workload.
+    pub workload: String,
+    /// Time to configure, build and start the microVM.
+    pub boot: Measurements,
+    /// Time to pause the microVM and create a full snapshot.
+    pub snapshot: Measurements,
+    /// Time to restore and resume the microVM from the snapshot.
+    pub restore: Measurements,
+}
+
+fn instance_info() -> InstanceInfo {
+    InstanceInfo {
+        id: "bench".to_string(),
+        state: VmState::NotStarted,
+        vmm_version: env!("CARGO_PKG_VERSION").to_string(),
+        app_name: "bench".to_string(),
+    }
+}
+
+fn vm_resources(artifacts: &Artifacts, workload: &Workload) -> Result<VmResources, BenchError> {
+    let drives = match &artifacts.rootfs_path {
+        Some(rootfs_path) => serde_json::json!([{
+            "drive_id": "rootfs",
+            "path_on_host": rootfs_path,
+            "is_root_device": true,
+            "is_read_only": true,
+        }]),
+        None => serde_json::json!([]),
+    };
+    let config = serde_json::json!({
+        "boot-source": {
+            "kernel_image_path": artifacts.kernel_image_path,
+            "boot_args": artifacts.boot_args.as_deref().unwrap_or(DEFAULT_BOOT_ARGS),
+        },
+        "machine-config": {
+            "vcpu_count": workload.vcpu_count,
+            "mem_size_mib": workload.mem_size_mib,
+        },
+        "drives": drives,
+    });
+    VmResources::from_json(
+        &config.to_string(),
+        &instance_info(),
+        HTTP_MAX_PAYLOAD_SIZE,
+        None,
+    )
+    .map_err(BenchError::Resources)
+}
+
+// Handles the device events of the microVM for `duration`.
+fn run_event_loop(event_manager: &mut EventManager, duration: Duration) -> Result<(), BenchError> {
+    let deadline = Instant::now() + duration;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        let timeout = i32::try_from(remaining.as_millis()).unwrap_or(i32::MAX);
+        event_manager
+            .run_with_timeout(timeout)
+            .map_err(BenchError::EventLoop)?;
+    }
+    Ok(())
+}
+
+fn stop(vmm: Arc<Mutex<Vmm>>) {
+    vmm.lock().unwrap().stop(FcExitCode::Ok);
+}
+
+/// Runs `iterations` iterations of the benchmark of `workload`, booting the microVMs from
+/// `artifacts` and creating the snapshots in `work_dir`.
+///
+/// The microVMs are built on the calling thread and run without seccomp filters. Landlock is
+/// disabled, since the snapshots are created in `work_dir`.
+pub fn run(
+    artifacts: &Artifacts,
+    workload: &Workload,
+    iterations: usize,
+    work_dir: &Path,
+) -> Result<Report, BenchError> {
+    set_landlock(false);
+    let seccomp_filters = get_empty_filters();
+    let snapshot_path = work_dir.join("bench.snap");
+    let mem_file_path = work_dir.join("bench.mem");
+    let mut report = Report {
+        workload: workload.name.to_string(),
+        ..Default::default()
+    };
+
+    for _ in 0..iterations {
+        let mut event_manager = EventManager::new().map_err(BenchError::EventLoop)?;
+        let start = Instant::now();
+        let mut vm_resources = vm_resources(artifacts, workload)?;
+        let vmm = build_and_boot_microvm(
+            &instance_info(),
+            &mut vm_resources,
+            &mut event_manager,
+            &seccomp_filters,
+        )
+        .map_err(BenchError::Boot)?;
+        report.boot.push(start.elapsed());
+
+        run_event_loop(&mut event_manager, workload.run_time)?;
+
+        let start = Instant::now();
+        let result = vmm.lock().unwrap().pause_vm().map_err(BenchError::Pause);
+        let result = result.and_then(|()| {
+            create_snapshot(
+                &mut vmm.lock().unwrap(),
+                &VmInfo::from(&vm_resources),
+                &CreateSnapshotParams {
+                    snapshot_type: SnapshotType::Full,
+                    snapshot_path: snapshot_path.clone(),
+                    mem_file_path: mem_file_path.clone(),
+                    encryption: None,
+                    manifest_path: None,
+                    sparse: false,
+                    snapshot_version: None,
+                },
+            )
+            .map_err(BenchError::CreateSnapshot)
+        });
+        report.snapshot.push(start.elapsed());
+        stop(vmm);
+        result?;
+
+        let mut event_manager = EventManager::new().map_err(BenchError::EventLoop)?;
+        let start = Instant::now();
+        let vmm = restore_from_snapshot(
+            &instance_info(),
+            &mut event_manager,
+            &seccomp_filters,
+            &LoadSnapshotParams {
+                snapshot_path: snapshot_path.clone(),
+                mem_backend: MemBackendConfig {
+                    backend_path: mem_file_path.clone(),
+                    backend_type: MemBackendType::File,
+                },
+                enable_diff_snapshots: false,
+                resume_vm: false,
+                verify: false,
+                encryption: None,
+                allow_cpu_mismatch: false,
+                overrides: Default::default(),
+            },
+            &mut VmResources::default(),
+        )
+        .map_err(BenchError::Restore)?;
+        let result = vmm.lock().unwrap().resume_vm().map_err(BenchError::Resume);
+        report.restore.push(start.elapsed());
+        stop(vmm);
+        result?;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::tempdir::TempDir;
+
+    use super::*;
+    use crate::utilities::mock_resources::{kernel_image_path, NOISY_KERNEL_IMAGE};
+
+    #[test]
+    fn test_measurements() {
+        let measurements = Measurements {
+            samples_us: vec![40, 10, 30, 20],
+        };
+        assert_eq!(measurements.min_us(), 10);
+        assert_eq!(measurements.max_us(), 40);
+        assert_eq!(measurements.mean_us(), 25);
+        assert_eq!(measurements.percentile_us(50), 20);
+        assert_eq!(measurements.percentile_us(99), 40);
+        assert_eq!(
+            serde_json::to_string(&measurements).unwrap(),
+            r#"{"min_us":10,"max_us":40,"mean_us":25,"p50_us":20,"p99_us":40,"samples_us":[40,10,30,20]}"#
+        );
+
+        let measurements = Measurements::default();
+        assert_eq!(measurements.mean_us(), 0);
+        assert_eq!(measurements.percentile_us(50), 0);
+    }
+
+    #[test]
+    fn test_run() {
+        let work_dir = TempDir::new().unwrap();
+        let artifacts = Artifacts {
+            kernel_image_path: kernel_image_path(Some(NOISY_KERNEL_IMAGE)).into(),
+            rootfs_path: None,
+            boot_args: None,
+        };
+        let workload = Workload {
+            run_time: Duration::from_millis(100),
+            ..Workload::SMALL
+        };
+
+        let report = run(&artifacts, &workload, 2, work_dir.as_path()).unwrap();
+        assert_eq!(report.workload, "small");
+        assert_eq!(report.boot.samples_us.len(), 2);
+        assert_eq!(report.snapshot.samples_us.len(), 2);
+        assert_eq!(report.restore.samples_us.len(), 2);
+    }
+}
@@ -7,16 +7,18 @@
 use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::io::{self, Seek, SeekFrom};
+#[cfg(target_arch = "x86_64")]
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
-use event_manager::{MutEventSubscriber, SubscriberOps};
+use event_manager::MutEventSubscriber;
 use libc::EFD_NONBLOCK;
 use linux_loader::cmdline::Cmdline as LoaderKernelCmdline;
 #[cfg(target_arch = "x86_64")]
 use linux_loader::loader::elf::Elf as Loader;
 #[cfg(target_arch = "aarch64")]
 use linux_loader::loader::pe::PE as Loader;
-use linux_loader::loader::KernelLoader;
+use linux_loader::loader::{KernelLoader, KernelLoaderResult};
 use seccompiler::BpfThreadMap;
 use userfaultfd::Uffd;
 use utils::eventfd::EventFd;
@@ -53,24 +55,44 @@ use crate::devices::legacy::serial::SerialOut;
 #[cfg(target_arch = "aarch64")]
 use crate::devices::legacy::RTCDevice;
 use crate::devices::legacy::{EventFdTrigger, SerialEventsWrapper, SerialWrapper};
+use crate::devices::replay::{self, ReplayError};
+#[cfg(target_arch = "x86_64")]
+use crate::devices::tpm::SwtpmBackend;
 use crate::devices::virtio::balloon::Balloon;
 use crate::devices::virtio::block::device::Block;
+use crate::devices::virtio::crypto::Crypto;
 use crate::devices::virtio::device::VirtioDevice;
 use crate::devices::virtio::mmio::MmioTransport;
 use crate::devices::virtio::net::Net;
+use crate::devices::virtio::remote::RemoteDevice;
 use crate::devices::virtio::rng::Entropy;
 use crate::devices::virtio::vsock::{Vsock, VsockUnixBackend};
 use crate::devices::BusDevice;
-use crate::logger::{debug, error};
+use crate::event_loop::add_timed_subscriber;
+use crate::landlock::{landlock, Access, LandlockError, Ruleset};
+use crate::logger::{debug, error, warn};
+use crate::mmds::data_file::{MmdsDataFile, MmdsDataFileError};
+use crate::mmds::data_store::Mmds;
 use crate::persist::{MicrovmState, MicrovmStateError};
 use crate::resources::VmResources;
 use crate::snapshot::Persist;
 use crate::vmm_config::boot_source::BootConfig;
 use crate::vmm_config::instance_info::InstanceInfo;
-use crate::vmm_config::machine_config::{VmConfig, VmConfigError};
-use crate::vstate::memory::{GuestAddress, GuestMemory, GuestMemoryExtension, GuestMemoryMmap};
+use crate::vmm_config::machine_config::{
+    DirtyTrackingMode, MemoryBackendType, MemoryLayoutConfig, VmConfig, VmConfigError,
+};
+use crate::vmm_config::mmio_trace::MmioTraceError;
+use crate::vmm_config::replay::ReplayConfig;
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::tpm::TpmConfig;
+use crate::vmm_config::vmm_info::PROC_SELF_ENTRIES;
+use crate::vstate::confidential::ConfidentialVm;
+use crate::vstate::memory::{
+    AnonymousBackend, GuestAddress, GuestMemory, GuestMemoryExtension, GuestMemoryMmap,
+    HugetlbfsBackend, MemfdBackend, MemoryBackend, MemoryError,
+};
 use crate::vstate::vcpu::{Vcpu, VcpuConfig, VcpuError};
-use crate::vstate::vm::Vm;
+use crate::vstate::vm::{Vm, VmError};
 use crate::{device_manager, EventManager, Vmm, VmmError};
 
 /// Errors associated with starting the instance.
@@ -95,8 +117,15 @@ pub enum StartMicrovmError {
     /// Error creating VMGenID device: {0}
     #[cfg(target_arch = "x86_64")]
     CreateVMGenID(VmGenIdError),
+    /// Cannot create the TPM device: {0}
+    #[cfg(target_arch = "x86_64")]
+    CreateTpm(crate::devices::tpm::TpmError),
+    /// Cannot reserve the crash kernel region due to an invalid memory configuration.
+    CrashKernelReserve,
     /// Invalid Memory Configuration: {0}
     GuestMemory(crate::vstate::memory::MemoryError),
+    /// Vhost-user and remote devices cannot access private anonymous guest memory.
+    PrivateGuestMemory,
     /// Cannot load initrd due to an invalid memory configuration.
     InitrdLoad,
     /// Cannot load initrd due to an invalid image: {0}
@@ -109,12 +138,18 @@ pub enum StartMicrovmError {
     KernelCmdline(String),
     /// Cannot load kernel due to invalid memory configuration or invalid kernel image: {0}
     KernelLoader(linux_loader::loader::Error),
+    /// Failed to restrict the filesystem accesses: {0}
+    Landlock(LandlockError),
     /// Cannot load command line string: {0}
     LoadCommandline(linux_loader::loader::Error),
     /// Cannot start microvm without kernel configuration.
     MissingKernelConfig,
     /// Cannot start microvm without guest mem_size config.
     MissingMemSizeConfig,
+    /// Cannot load the MMDS data file: {0}
+    MmdsDataFile(MmdsDataFileError),
+    /// Cannot trace the MMIO register accesses: {0}
+    MmioTrace(MmioTraceError),
     /// No seccomp filter for thread category: {0}
     MissingSeccompFilters(String),
     /// The net device configuration is missing the tap device.
@@ -123,6 +158,8 @@ pub enum StartMicrovmError {
     OpenBlockDevice(io::Error),
     /// Cannot initialize a MMIO Device or add a device to the MMIO Bus or cmdline: {0}
     RegisterMmioDevice(#[from] device_manager::mmio::MmioError),
+    /// Cannot record or replay the inputs of the devices: {0}
+    Replay(ReplayError),
     /// Cannot restore microvm state: {0}
     RestoreMicrovmState(MicrovmStateError),
     /// Cannot set vm resources: {0}
@@ -145,22 +182,31 @@ impl std::convert::From<linux_loader::cmdline::Error> for StartMicrovmError {
 }
 
 #[cfg_attr(target_arch = "aarch64", allow(unused))]
+#[allow(clippy::too_many_arguments)]
 fn create_vmm_and_vcpus(
     instance_info: &InstanceInfo,
     event_manager: &mut EventManager,
     guest_memory: GuestMemoryMmap,
     uffd: Option<Uffd>,
     track_dirty_pages: bool,
+    dirty_tracking_mode: DirtyTrackingMode,
     vcpu_count: u8,
+    memory_layout: &MemoryLayoutConfig,
     kvm_capabilities: Vec<KvmCapability>,
+    confidential: Option<Box<dyn ConfidentialVm>>,
 ) -> Result<(Vmm, Vec<Vcpu>), StartMicrovmError> {
     use self::StartMicrovmError::*;
 
     // Set up Kvm Vm and register memory regions.
     // Build custom CPU config if a custom template is provided.
-    let mut vm = Vm::new(kvm_capabilities)
+    let mut vm = Vm::with_confidential(kvm_capabilities, confidential, memory_layout.ipa_size)
         .map_err(VmmError::Vm)
         .map_err(StartMicrovmError::Internal)?;
+    if track_dirty_pages {
+        vm.enable_dirty_ring(&guest_memory, dirty_tracking_mode)
+            .map_err(VmmError::Vm)
+            .map_err(StartMicrovmError::Internal)?;
+    }
     vm.memory_init(&guest_memory, track_dirty_pages)
         .map_err(VmmError::Vm)
         .map_err(StartMicrovmError::Internal)?;
@@ -169,7 +215,11 @@ fn create_vmm_and_vcpus(
         .map_err(VmmError::EventFd)
         .map_err(Internal)?;
 
-    let resource_allocator = ResourceAllocator::new()?;
+    let shutdown_evt = EventFd::new(libc::EFD_NONBLOCK)
+        .map_err(VmmError::EventFd)
+        .map_err(Internal)?;
+
+    let resource_allocator = ResourceAllocator::with_memory_layout(memory_layout, &guest_memory)?;
 
     // Instantiate the MMIO device manager.
     let mmio_device_manager = MMIODeviceManager::new();
@@ -224,11 +274,13 @@ fn create_vmm_and_vcpus(
         events_observer: Some(std::io::stdin()),
         instance_info: instance_info.clone(),
         shutdown_exit_code: None,
+        devices_quiesced: false,
         vm,
         guest_memory,
         uffd,
         vcpus_handles: Vec::new(),
         vcpus_exit_evt,
+        shutdown_evt,
         resource_allocator,
         mmio_device_manager,
         #[cfg(target_arch = "x86_64")]
@@ -240,6 +292,112 @@ fn create_vmm_and_vcpus(
     Ok((vmm, vcpus))
 }
 
+/// Pre-faults the guest memory selected by the `mem_populate` and `mem_prefault_ranges`
+/// machine configuration options.
+/// Returns the backend of the guest memory of a microVM, whose devices share the guest memory with
+/// other processes if `shared` is set.
+fn guest_memory_backend(
+    vm_config: &VmConfig,
+    shared: bool,
+) -> Result<Box<dyn MemoryBackend>, StartMicrovmError> {
+    let huge_pages = vm_config.huge_pages;
+    // Page faults are more expensive for shared memory mapping, including memfd. For this reason,
+    // the Auto backend only backs guest memory with a memfd if a vhost-user-blk, a remote device or
+    // a device worker is configured in the VM, otherwise it falls back to anonymous private memory.
+    let backend: Box<dyn MemoryBackend> = match (vm_config.memory_backend, shared) {
+        (MemoryBackendType::Anonymous, true) => return Err(StartMicrovmError::PrivateGuestMemory),
+        (MemoryBackendType::Memfd, _) | (MemoryBackendType::Auto, true) => {
+            Box::new(MemfdBackend { huge_pages })
+        }
+        _ if huge_pages.is_hugetlbfs() => Box::new(HugetlbfsBackend { huge_pages }),
+        _ => Box::new(AnonymousBackend),
+    };
+    Ok(backend)
+}
+
+pub(crate) fn prefault_guest_memory(
+    guest_memory: &GuestMemoryMmap,
+    vm_config: &VmConfig,
+) -> Result<(), MemoryError> {
+    let page_size = vm_config.huge_pages.page_size_kib();
+
+    if vm_config.mem_populate {
+        return guest_memory.populate(GuestAddress(0), u64::MAX, page_size);
+    }
+    for range in &vm_config.mem_prefault_ranges {
+        guest_memory.populate(GuestAddress(range.guest_addr), range.len, page_size)?;
+    }
+    Ok(())
+}
+
+/// Marks the guest memory selected by the `mem_mergeable` and `mem_mergeable_ranges` machine
+/// configuration options as mergeable by KSM.
+pub(crate) fn mark_guest_memory_mergeable(
+    guest_memory: &GuestMemoryMmap,
+    vm_config: &VmConfig,
+) -> Result<(), MemoryError> {
+    let page_size = vm_config.huge_pages.page_size_kib();
+
+    if vm_config.mem_mergeable {
+        return guest_memory.mark_mergeable(GuestAddress(0), u64::MAX, page_size);
+    }
+    for range in &vm_config.mem_mergeable_ranges {
+        guest_memory.mark_mergeable(GuestAddress(range.guest_addr), range.len, page_size)?;
+    }
+    Ok(())
+}
+
+// Returns the ruleset restricting the filesystem accesses to the artifacts of the devices of the
+// microVM. Files can also be created in the working directory, e.g. snapshots, which are never
+// read back.
+pub(crate) fn landlock_ruleset(vm_resources: &VmResources) -> Ruleset {
+    let mut ruleset = Ruleset::new();
+    ruleset
+        .allow(".", Access::CreateFiles)
+        // Opened again when dumping the CPU configuration.
+        .allow("/dev/kvm", Access::ReadWrite);
+
+    for config in vm_resources.block.configs() {
+        if let Some(socket) = config.socket {
+            ruleset.allow(socket, Access::ReadWrite);
+        }
+        if let Some(path) = config.path_on_host {
+            if config.is_read_only == Some(true) || config.overlay_path.is_some() {
+                ruleset.allow(path, Access::Read);
+            } else {
+                ruleset.allow(path, Access::ReadWrite);
+            }
+        }
+        if let Some(overlay_path) = config.overlay_path {
+            ruleset.allow(overlay_path, Access::ReadWrite);
+        }
+    }
+    for config in vm_resources.vsock.configs() {
+        ruleset.allow(config.uds_path, Access::ReadWrite);
+    }
+    for config in vm_resources.remote_devices.configs() {
+        ruleset.allow(config.socket, Access::ReadWrite);
+    }
+    // The directory is watched, and the file is read again whenever it is replaced.
+    if let Some(path) = vm_resources.mmds_data_file.as_ref() {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+        ruleset.allow(dir.unwrap_or(Path::new(".")), Access::Read);
+    }
+    // Connected to again after errors.
+    if let Some(config) = vm_resources.tpm.as_ref() {
+        ruleset.allow(config.socket.clone(), Access::ReadWrite);
+    }
+
+    // Read when flushing the metrics, and when collecting the information about the process.
+    const KSM_STAT: &str = "/proc/self/ksm_stat";
+    for path in std::iter::once(KSM_STAT).chain(PROC_SELF_ENTRIES) {
+        if std::path::Path::new(path).exists() {
+            ruleset.allow(path, Access::Read);
+        }
+    }
+    ruleset
+}
+
 /// Builds and starts a microVM based on the current Firecracker VmResources configuration.
 ///
 /// The built microVM and all the created vCPUs start off in the paused state.
@@ -247,7 +405,7 @@ fn create_vmm_and_vcpus(
 /// called.
 pub fn build_microvm_for_boot(
     instance_info: &InstanceInfo,
-    vm_resources: &super::resources::VmResources,
+    vm_resources: &mut super::resources::VmResources,
     event_manager: &mut EventManager,
     seccomp_filters: &BpfThreadMap,
 ) -> Result<Arc<Mutex<Vmm>>, StartMicrovmError> {
@@ -256,6 +414,10 @@ pub fn build_microvm_for_boot(
     // Timestamp for measuring microVM boot duration.
     let request_ts = TimestampUs::default();
 
+    // Size the MMIO windows for the devices before allocating anything, so that a configuration
+    // with more devices than the microVM can hold fails before any of them is attached.
+    vm_resources.fit_memory_layout().map_err(SetVmResources)?;
+
     let boot_config = vm_resources
         .boot_source_builder()
         .ok_or(MissingKernelConfig)?;
@@ -266,50 +428,64 @@ pub fn build_microvm_for_boot(
         .block
         .devices
         .iter()
-        .any(|b| b.lock().expect("Poisoned lock").is_vhost_user());
+        .any(|b| b.lock().expect("Poisoned lock").is_vhost_user())
+        || vm_resources
+            .net_builder
+            .iter()
+            .any(|net| net.lock().expect("Poisoned lock").is_worker())
+        || !vm_resources.remote_devices.is_empty();
 
-    // Page faults are more expensive for shared memory mapping, including  memfd.
-    // For this reason, we only back guest memory with a memfd
-    // if a vhost-user-blk device is configured in the VM, otherwise we fall back to
-    // an anonymous private memory.
-    //
     // The vhost-user-blk branch is not currently covered by integration tests in Rust,
     // because that would require running a backend process. If in the future we converge to
     // a single way of backing guest memory for vhost-user and non-vhost-user cases,
     // that would not be worth the effort.
-    let guest_memory = if vhost_user_device_used {
-        GuestMemoryMmap::memfd_backed(
-            vm_resources.vm_config.mem_size_mib,
-            track_dirty_pages,
-            vm_resources.vm_config.huge_pages,
-        )
-        .map_err(StartMicrovmError::GuestMemory)?
-    } else {
-        let regions = crate::arch::arch_memory_regions(vm_resources.vm_config.mem_size_mib << 20);
-        GuestMemoryMmap::from_raw_regions(
-            &regions,
-            track_dirty_pages,
-            vm_resources.vm_config.huge_pages,
-        )
-        .map_err(StartMicrovmError::GuestMemory)?
-    };
-
-    let entry_addr = load_kernel(boot_config, &guest_memory)?;
+    let regions = crate::arch::arch_memory_regions(
+        vm_resources.vm_config.mem_size_mib << 20,
+        &vm_resources.vm_config.memory_layout,
+    );
+    let guest_memory = guest_memory_backend(&vm_resources.vm_config, vhost_user_device_used)?
+        .create(&regions, track_dirty_pages)
+        .map_err(StartMicrovmError::GuestMemory)?;
+    mark_guest_memory_mergeable(&guest_memory, &vm_resources.vm_config)
+        .map_err(StartMicrovmError::GuestMemory)?;
+    prefault_guest_memory(&guest_memory, &vm_resources.vm_config)
+        .map_err(StartMicrovmError::GuestMemory)?;
+
+    let kernel = load_kernel(boot_config, &guest_memory)?;
     let initrd = load_initrd_from_config(boot_config, &guest_memory)?;
     // Clone the command-line so that a failed boot doesn't pollute the original.
     #[allow(unused_mut)]
     let mut boot_cmdline = boot_config.cmdline.clone();
+    if let Some(size_mib) = boot_config.crash_kernel_size_mib {
+        reserve_crash_kernel(
+            &guest_memory,
+            kernel.kernel_end,
+            initrd.as_ref(),
+            size_mib,
+            &mut boot_cmdline,
+        )?;
+    }
 
     let cpu_template = vm_resources.vm_config.cpu_template.get_cpu_template()?;
 
+    let confidential = vm_resources
+        .vm_config
+        .confidential
+        .map(|config| config.create())
+        .transpose()
+        .map_err(|err| Internal(VmmError::Vm(VmError::Confidential(err))))?;
+
     let (mut vmm, mut vcpus) = create_vmm_and_vcpus(
         instance_info,
         event_manager,
         guest_memory,
         None,
         track_dirty_pages,
+        vm_resources.vm_config.dirty_tracking_mode,
         vm_resources.vm_config.vcpu_count,
+        &vm_resources.vm_config.memory_layout,
         cpu_template.kvm_capabilities.clone(),
+        confidential,
     )?;
 
     // The boot timer device needs to be the first device attached in order
@@ -319,6 +495,12 @@ pub fn build_microvm_for_boot(
         attach_boot_timer_device(&mut vmm, request_ts)?;
     }
 
+    apply_virtio_feature_policy(vm_resources);
+
+    if let Some(replay_config) = vm_resources.replay.as_ref() {
+        start_replay(vm_resources, replay_config).map_err(Replay)?;
+    }
+
     if let Some(balloon) = vm_resources.balloon.get() {
         attach_balloon_device(&mut vmm, &mut boot_cmdline, balloon, event_manager)?;
     }
@@ -329,6 +511,15 @@ pub fn build_microvm_for_boot(
         vm_resources.block.devices.iter(),
         event_manager,
     )?;
+    // A microVM doesn't need any drive when the kernel and its initramfs are the entire system,
+    // or when the boot arguments mount the root filesystem from elsewhere, e.g. a pmem device.
+    if !vm_resources.block.has_root_device() && initrd.is_none() && !cmdline_has_root(&boot_cmdline)
+    {
+        warn!(
+            "There is no root block device, initrd or root= boot argument, the guest kernel will \
+             fail to mount a root filesystem."
+        );
+    }
     attach_net_devices(
         &mut vmm,
         &mut boot_cmdline,
@@ -336,7 +527,7 @@ pub fn build_microvm_for_boot(
         event_manager,
     )?;
 
-    if let Some(unix_vsock) = vm_resources.vsock.get() {
+    for unix_vsock in vm_resources.vsock.iter() {
         attach_unixsock_vsock_device(&mut vmm, &mut boot_cmdline, unix_vsock, event_manager)?;
     }
 
@@ -344,23 +535,73 @@ pub fn build_microvm_for_boot(
         attach_entropy_device(&mut vmm, &mut boot_cmdline, entropy, event_manager)?;
     }
 
+    if let Some(crypto) = vm_resources.crypto.get() {
+        attach_crypto_device(&mut vmm, &mut boot_cmdline, crypto, event_manager)?;
+    }
+
+    attach_remote_devices(
+        &mut vmm,
+        &mut boot_cmdline,
+        vm_resources.remote_devices.iter(),
+        event_manager,
+    )?;
+
+    // The guest drivers access the registers as soon as they probe the devices.
+    vmm.set_mmio_trace(&vm_resources.mmio_trace)
+        .map_err(MmioTrace)?;
+
     #[cfg(target_arch = "aarch64")]
     attach_legacy_devices_aarch64(event_manager, &mut vmm, &mut boot_cmdline).map_err(Internal)?;
 
     #[cfg(target_arch = "x86_64")]
     attach_vmgenid_device(&mut vmm)?;
 
+    #[cfg(target_arch = "x86_64")]
+    if let Some(tpm) = vm_resources.tpm.as_ref() {
+        attach_tpm_device(&mut vmm, tpm, event_manager)?;
+    }
+
+    // The data store exists once MMDS is configured with a data file.
+    if let (Some(path), Some(mmds)) = (&vm_resources.mmds_data_file, &vm_resources.mmds) {
+        watch_mmds_data_file(path, mmds.clone(), event_manager)?;
+    }
+
     configure_system_for_boot(
         &mut vmm,
         vcpus.as_mut(),
         &vm_resources.vm_config,
         &cpu_template,
-        entry_addr,
+        kernel.kernel_load,
         &initrd,
         boot_cmdline,
     )?;
 
+    // The guest payload is in place and the vCPUs are configured, so the launch of a confidential
+    // guest can be completed.
+    #[cfg(target_arch = "x86_64")]
+    let payload = confidential_launch_payload(kernel.kernel_end, initrd.as_ref());
+    // Confidential guests are only supported on x86_64.
+    #[cfg(target_arch = "aarch64")]
+    let payload = Vec::new();
+    vmm.vm
+        .confidential_launch(&vmm.guest_memory, &payload)
+        .map_err(VmmError::Vm)
+        .map_err(Internal)?;
+
+    // Restrict the filesystem accesses before spawning the vCPU threads, which inherit the
+    // restriction.
+    if landlock() {
+        let mut ruleset = landlock_ruleset(vm_resources);
+        let boot_source = vm_resources.boot_source_config();
+        ruleset.allow(&boot_source.kernel_image_path, Access::Read);
+        if let Some(initrd_path) = &boot_source.initrd_path {
+            ruleset.allow(initrd_path, Access::Read);
+        }
+        ruleset.restrict_self().map_err(Landlock)?;
+    }
+
     // Move vcpus to their own threads and start their state machine in the 'Paused' state.
+    configure_vcpu_threads(&mut vcpus, vm_resources);
     vmm.start_vcpus(
         vcpus,
         seccomp_filters
@@ -384,7 +625,7 @@ pub fn build_microvm_for_boot(
     .map_err(Internal)?;
 
     let vmm = Arc::new(Mutex::new(vmm));
-    event_manager.add_subscriber(vmm.clone());
+    add_timed_subscriber(event_manager, "vmm", vmm.clone());
 
     Ok(vmm)
 }
@@ -398,7 +639,7 @@ pub fn build_microvm_for_boot(
 /// is returned.
 pub fn build_and_boot_microvm(
     instance_info: &InstanceInfo,
-    vm_resources: &super::resources::VmResources,
+    vm_resources: &mut super::resources::VmResources,
     event_manager: &mut EventManager,
     seccomp_filters: &BpfThreadMap,
 ) -> Result<Arc<Mutex<Vmm>>, StartMicrovmError> {
@@ -453,6 +694,10 @@ pub enum BuildMicrovmFromSnapshotError {
     ACPIDeviManager(#[from] ACPIDeviceManagerRestoreError),
     /// VMGenID update failed: {0}
     VMGenIDUpdate(std::io::Error),
+    /// Failed to reseed the guest kernel: {0}
+    ReseedRng(crate::arch::ConfigurationError),
+    /// Failed to restrict the filesystem accesses: {0}
+    Landlock(#[from] LandlockError),
 }
 
 /// Builds and starts a microVM based on the provided MicrovmState.
@@ -477,8 +722,11 @@ pub fn build_microvm_from_snapshot(
         guest_memory.clone(),
         uffd,
         vm_resources.vm_config.track_dirty_pages,
+        vm_resources.vm_config.dirty_tracking_mode,
         vm_resources.vm_config.vcpu_count,
+        &vm_resources.vm_config.memory_layout,
         microvm_state.vm_state.kvm_cap_modifiers.clone(),
+        None,
     )?;
 
     #[cfg(target_arch = "x86_64")]
@@ -551,7 +799,17 @@ pub fn build_microvm_from_snapshot(
             .map_err(BuildMicrovmFromSnapshotError::VMGenIDUpdate)?;
     }
 
+    // Give a fresh boot seed to the guest kernel, in case it did not consume its own yet.
+    crate::arch::reseed_rng(&guest_memory).map_err(BuildMicrovmFromSnapshotError::ReseedRng)?;
+
+    // Restrict the filesystem accesses before spawning the vCPU threads, which inherit the
+    // restriction.
+    if landlock() {
+        landlock_ruleset(vm_resources).restrict_self()?;
+    }
+
     // Move vcpus to their own threads and start their state machine in the 'Paused' state.
+    configure_vcpu_threads(&mut vcpus, vm_resources);
     vmm.start_vcpus(
         vcpus,
         seccomp_filters
@@ -561,7 +819,7 @@ pub fn build_microvm_from_snapshot(
     )?;
 
     let vmm = Arc::new(Mutex::new(vmm));
-    event_manager.add_subscriber(vmm.clone());
+    add_timed_subscriber(event_manager, "vmm", vmm.clone());
 
     // Load seccomp filters for the VMM thread.
     // Keep this as the last step of the building process.
@@ -578,7 +836,7 @@ pub fn build_microvm_from_snapshot(
 fn load_kernel(
     boot_config: &BootConfig,
     guest_memory: &GuestMemoryMmap,
-) -> Result<GuestAddress, StartMicrovmError> {
+) -> Result<KernelLoaderResult, StartMicrovmError> {
     let mut kernel_file = boot_config
         .kernel_file
         .try_clone()
@@ -602,7 +860,7 @@ fn load_kernel(
     )
     .map_err(StartMicrovmError::KernelLoader)?;
 
-    Ok(entry_addr.kernel_load)
+    Ok(entry_addr)
 }
 
 fn load_initrd_from_config(
@@ -668,6 +926,43 @@ where
     })
 }
 
+/// The ranges of guest memory written before the launch of a confidential guest, which are
+/// measured: the low memory holding the boot parameters, page tables and system tables, the kernel
+/// loaded right above it, and the initrd.
+#[cfg(target_arch = "x86_64")]
+fn confidential_launch_payload(
+    kernel_end: u64,
+    initrd: Option<&InitrdConfig>,
+) -> Vec<(GuestAddress, u64)> {
+    let page_size = u64::try_from(crate::arch::PAGE_SIZE).unwrap();
+    let mut payload = vec![(GuestAddress(0), kernel_end.next_multiple_of(page_size))];
+    if let Some(initrd) = initrd {
+        payload.push((
+            initrd.address,
+            u64::try_from(initrd.size)
+                .unwrap()
+                .next_multiple_of(page_size),
+        ));
+    }
+    payload
+}
+
+/// Reserves a crash kernel region of `size_mib` MiB in the guest memory, and advertises it to
+/// the guest kernel through the `crashkernel` boot argument.
+fn reserve_crash_kernel(
+    vm_memory: &GuestMemoryMmap,
+    kernel_end: u64,
+    initrd: Option<&InitrdConfig>,
+    size_mib: u32,
+    cmdline: &mut LoaderKernelCmdline,
+) -> Result<(), StartMicrovmError> {
+    let size = u64_to_usize(u64::from(size_mib) << 20);
+    let address = crate::arch::crash_kernel_addr(vm_memory, kernel_end, initrd, size)
+        .map_err(|_| StartMicrovmError::CrashKernelReserve)?;
+    cmdline.insert("crashkernel", &format!("{}M@{:#x}", size_mib, address))?;
+    Ok(())
+}
+
 /// Sets up the irqchip for a x86_64 microVM.
 #[cfg(target_arch = "x86_64")]
 pub fn setup_interrupt_controller(vm: &mut Vm) -> Result<(), StartMicrovmError> {
@@ -703,7 +998,7 @@ pub fn setup_serial_device(
         ),
         input: Some(input),
     })));
-    event_manager.add_subscriber(serial.clone());
+    add_timed_subscriber(event_manager, "serial", serial.clone());
     Ok(serial)
 }
 
@@ -784,6 +1079,12 @@ pub fn configure_system_for_boot(
         use crate::arch::aarch64::vcpu::get_registers;
 
         for vcpu in vcpus.iter_mut() {
+            if vm_config.pmu {
+                vcpu.kvm_vcpu
+                    .enable_pmu(&vmm.vm)
+                    .map_err(VmmError::VcpuInit)
+                    .map_err(Internal)?;
+            }
             vcpu.kvm_vcpu
                 .init(&cpu_template.vcpu_features)
                 .map_err(VmmError::VcpuInit)
@@ -802,6 +1103,7 @@ pub fn configure_system_for_boot(
     let vcpu_config = VcpuConfig {
         vcpu_count: vm_config.vcpu_count,
         smt: vm_config.smt,
+        pmu: vm_config.pmu,
         cpu_config,
     };
 
@@ -834,6 +1136,7 @@ pub fn configure_system_for_boot(
             cmdline_size,
             initrd,
             vcpu_config.vcpu_count,
+            &vm_config.memory_layout,
         )
         .map_err(ConfigureSystem)?;
 
@@ -861,12 +1164,68 @@ pub fn configure_system_for_boot(
             vmm.mmio_device_manager.get_device_info(),
             vmm.vm.get_irqchip(),
             initrd,
+            vm_config.pmu,
         )
         .map_err(ConfigureSystem)?;
     }
     Ok(())
 }
 
+/// Hands the host scheduling settings of the vCPU threads to the vCPUs, which apply them when
+/// their threads are spawned.
+fn configure_vcpu_threads(vcpus: &mut [Vcpu], vm_resources: &VmResources) {
+    for config in &vm_resources.vm_config.vcpu_threads {
+        // The vCPU indexes are validated against the vCPU count of the configuration.
+        vcpus[usize::from(config.vcpu)].set_thread_config(config.clone());
+    }
+}
+
+/// Withholds the virtio features denied by the feature policy of the microVM from the features
+/// offered by its devices, before they are attached.
+fn apply_virtio_feature_policy(vm_resources: &VmResources) {
+    let policy = &vm_resources.vm_config.virtio_feature_policy;
+    let apply = |device: &mut dyn VirtioDevice, id: &str| {
+        let avail_features = device.avail_features() & !policy.denied_features(id);
+        device.set_avail_features(avail_features);
+    };
+
+    if let Some(balloon) = vm_resources.balloon.get() {
+        let mut balloon = balloon.lock().expect("Poisoned lock");
+        let id = balloon.id().to_string();
+        apply(&mut *balloon, &id);
+    }
+    for block in vm_resources.block.devices.iter() {
+        let mut block = block.lock().expect("Poisoned lock");
+        let id = block.id().to_string();
+        apply(&mut *block, &id);
+    }
+    for net in vm_resources.net_builder.iter() {
+        let mut net = net.lock().expect("Poisoned lock");
+        let id = net.id().clone();
+        apply(&mut *net, &id);
+    }
+    for vsock in vm_resources.vsock.iter() {
+        let mut vsock = vsock.lock().expect("Poisoned lock");
+        let id = vsock.id().to_string();
+        apply(&mut *vsock, &id);
+    }
+    if let Some(entropy) = vm_resources.entropy.get() {
+        let mut entropy = entropy.lock().expect("Poisoned lock");
+        let id = entropy.id().to_string();
+        apply(&mut *entropy, &id);
+    }
+    if let Some(crypto) = vm_resources.crypto.get() {
+        let mut crypto = crypto.lock().expect("Poisoned lock");
+        let id = crypto.id().to_string();
+        apply(&mut *crypto, &id);
+    }
+    for remote_device in vm_resources.remote_devices.iter() {
+        let mut remote_device = remote_device.lock().expect("Poisoned lock");
+        let id = remote_device.id().to_string();
+        apply(&mut *remote_device, &id);
+    }
+}
+
 /// Attaches a VirtioDevice device to the device manager and event manager.
 fn attach_virtio_device<T: 'static + VirtioDevice + MutEventSubscriber + Debug>(
     event_manager: &mut EventManager,
@@ -878,7 +1237,13 @@ fn attach_virtio_device<T: 'static + VirtioDevice + MutEventSubscriber + Debug>(
 ) -> Result<(), StartMicrovmError> {
     use self::StartMicrovmError::*;
 
-    event_manager.add_subscriber(device.clone());
+    let device_type = device.lock().expect("Poisoned lock").device_type();
+    vmm.mmio_device_manager.add_virtio_subscriber(
+        event_manager,
+        device_type,
+        id.clone(),
+        device.clone(),
+    );
 
     // The device mutex mustn't be locked here otherwise it will deadlock.
     let device = MmioTransport::new(vmm.guest_memory().clone(), device, is_vhost_user);
@@ -909,6 +1274,38 @@ pub(crate) fn attach_boot_timer_device(
     Ok(())
 }
 
+fn watch_mmds_data_file(
+    path: &Path,
+    mmds: Arc<Mutex<Mmds>>,
+    event_manager: &mut EventManager,
+) -> Result<(), StartMicrovmError> {
+    let data_file =
+        MmdsDataFile::new(path.to_path_buf(), mmds).map_err(StartMicrovmError::MmdsDataFile)?;
+    // The file is reloaded from the VMM thread.
+    add_timed_subscriber(
+        event_manager,
+        "mmds_data_file",
+        Arc::new(Mutex::new(data_file)),
+    );
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn attach_tpm_device(
+    vmm: &mut Vmm,
+    tpm_config: &TpmConfig,
+    event_manager: &mut EventManager,
+) -> Result<(), StartMicrovmError> {
+    let backend = SwtpmBackend::new(PathBuf::from(&tpm_config.socket))
+        .map_err(StartMicrovmError::CreateTpm)?;
+    let tpm = vmm
+        .mmio_device_manager
+        .register_mmio_tpm(&mut vmm.resource_allocator, Box::new(backend))?;
+    // The commands of the guest are executed from the VMM thread.
+    add_timed_subscriber(event_manager, "tpm", tpm);
+    Ok(())
+}
+
 #[cfg(target_arch = "x86_64")]
 fn attach_vmgenid_device(vmm: &mut Vmm) -> Result<(), StartMicrovmError> {
     let vmgenid = VmGenId::new(&vmm.guest_memory, &mut vmm.resource_allocator)
@@ -943,6 +1340,38 @@ fn attach_entropy_device(
     )
 }
 
+fn attach_crypto_device(
+    vmm: &mut Vmm,
+    cmdline: &mut LoaderKernelCmdline,
+    crypto_device: &Arc<Mutex<Crypto>>,
+    event_manager: &mut EventManager,
+) -> Result<(), StartMicrovmError> {
+    let id = crypto_device
+        .lock()
+        .expect("Poisoned lock")
+        .id()
+        .to_string();
+
+    attach_virtio_device(
+        event_manager,
+        vmm,
+        id,
+        crypto_device.clone(),
+        cmdline,
+        false,
+    )
+}
+
+/// Specifies whether the command line names the root filesystem of the guest.
+fn cmdline_has_root(cmdline: &LoaderKernelCmdline) -> bool {
+    cmdline.as_cstring().is_ok_and(|cmdline| {
+        cmdline
+            .to_string_lossy()
+            .split_whitespace()
+            .any(|arg| arg.starts_with("root="))
+    })
+}
+
 fn attach_block_devices<'a, I: Iterator<Item = &'a Arc<Mutex<Block>>> + Debug>(
     vmm: &mut Vmm,
     cmdline: &mut LoaderKernelCmdline,
@@ -979,6 +1408,21 @@ fn attach_block_devices<'a, I: Iterator<Item = &'a Arc<Mutex<Block>>> + Debug>(
     Ok(())
 }
 
+// Starts recording or replaying the inputs of the devices, which is only supported on microVMs
+// with a single vCPU and without devices emulated by worker processes.
+fn start_replay(vm_resources: &VmResources, config: &ReplayConfig) -> Result<(), ReplayError> {
+    if vm_resources.vm_config.vcpu_count != 1 {
+        return Err(ReplayError::MultipleVcpus);
+    }
+    for net in vm_resources.net_builder.iter() {
+        let net = net.lock().expect("Poisoned lock");
+        if net.is_worker() {
+            return Err(ReplayError::WorkerDevice(net.id().clone()));
+        }
+    }
+    replay::start(config)
+}
+
 fn attach_net_devices<'a, I: Iterator<Item = &'a Arc<Mutex<Net>>> + Debug>(
     vmm: &mut Vmm,
     cmdline: &mut LoaderKernelCmdline,
@@ -986,9 +1430,42 @@ fn attach_net_devices<'a, I: Iterator<Item = &'a Arc<Mutex<Net>>> + Debug>(
     event_manager: &mut EventManager,
 ) -> Result<(), StartMicrovmError> {
     for net_device in net_devices {
-        let id = net_device.lock().expect("Poisoned lock").id().clone();
+        let (id, is_worker) = {
+            let mut locked = net_device.lock().expect("Poisoned lock");
+            if replay::is_replaying() {
+                locked
+                    .enable_replay()
+                    .map_err(StartMicrovmError::CreateNetDevice)?;
+            }
+            (locked.id().clone(), locked.is_worker())
+        };
+        // The device mutex mustn't be locked here otherwise it will deadlock.
+        attach_virtio_device(
+            event_manager,
+            vmm,
+            id,
+            net_device.clone(),
+            cmdline,
+            is_worker,
+        )?;
+    }
+    Ok(())
+}
+
+fn attach_remote_devices<'a, I: Iterator<Item = &'a Arc<Mutex<RemoteDevice>>> + Debug>(
+    vmm: &mut Vmm,
+    cmdline: &mut LoaderKernelCmdline,
+    remote_devices: I,
+    event_manager: &mut EventManager,
+) -> Result<(), StartMicrovmError> {
+    for remote_device in remote_devices {
+        let id = remote_device
+            .lock()
+            .expect("Poisoned lock")
+            .id()
+            .to_string();
         // The device mutex mustn't be locked here otherwise it will deadlock.
-        attach_virtio_device(event_manager, vmm, id, net_device.clone(), cmdline, false)?;
+        attach_virtio_device(event_manager, vmm, id, remote_device.clone(), cmdline, true)?;
     }
     Ok(())
 }
@@ -1032,6 +1509,7 @@ pub(crate) fn set_stdout_nonblocking() {
 #[cfg(test)]
 pub mod tests {
     use std::io::Write;
+    use std::time::Duration;
 
     use linux_loader::cmdline::Cmdline;
     use utils::tempfile::TempFile;
@@ -1040,19 +1518,26 @@ pub mod tests {
     use crate::arch::DeviceType;
     use crate::device_manager::resources::ResourceAllocator;
     use crate::devices::virtio::block::CacheType;
+    use crate::devices::virtio::crypto::device::CRYPTO_DEV_ID;
+    use crate::devices::virtio::gen::virtio_net::VIRTIO_NET_F_CSUM;
+    use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
     use crate::devices::virtio::rng::device::ENTROPY_DEV_ID;
-    use crate::devices::virtio::vsock::{TYPE_VSOCK, VSOCK_DEV_ID};
-    use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_RNG};
+    use crate::devices::virtio::vsock::TYPE_VSOCK;
+    use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_CRYPTO, TYPE_RNG};
+    use crate::logger::{IncMetric, METRICS};
     use crate::mmds::data_store::{Mmds, MmdsVersion};
     use crate::mmds::ns::MmdsNetworkStack;
     use crate::utilities::test_utils::{arch_mem, single_region_mem, single_region_mem_at};
     use crate::vmm_config::balloon::{BalloonBuilder, BalloonDeviceConfig, BALLOON_DEV_ID};
     use crate::vmm_config::boot_source::DEFAULT_KERNEL_CMDLINE;
+    use crate::vmm_config::crypto::{CryptoDeviceBuilder, CryptoDeviceConfig};
     use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig};
     use crate::vmm_config::entropy::{EntropyDeviceBuilder, EntropyDeviceConfig};
+    use crate::vmm_config::machine_config::HugePageConfig;
     use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vmm_config::vsock::{VsockBuilder, VsockDeviceConfig};
+    use crate::FcExitCode;
 
     #[derive(Debug)]
     pub(crate) struct CustomBlockConfig {
@@ -1150,11 +1635,13 @@ pub mod tests {
             events_observer: Some(std::io::stdin()),
             instance_info: InstanceInfo::default(),
             shutdown_exit_code: None,
+            devices_quiesced: false,
             vm,
             guest_memory,
             uffd: None,
             vcpus_handles: Vec::new(),
             vcpus_exit_evt,
+            shutdown_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
             resource_allocator: ResourceAllocator::new().unwrap(),
             mmio_device_manager,
             #[cfg(target_arch = "x86_64")]
@@ -1193,8 +1680,11 @@ pub mod tests {
                 ),
                 rate_limiter: None,
                 file_engine_type: None,
+                overlay_path: None,
 
                 socket: None,
+                reconnect: None,
+                worker: false,
             };
 
             block_dev_configs.insert(block_device_config).unwrap();
@@ -1249,7 +1739,7 @@ pub mod tests {
         event_manager: &mut EventManager,
         vsock_config: VsockDeviceConfig,
     ) {
-        let vsock_dev_id = VSOCK_DEV_ID.to_owned();
+        let vsock_dev_id = vsock_config.id().to_owned();
         let vsock = VsockBuilder::create_unixsock_vsock(vsock_config).unwrap();
         let vsock = Arc::new(Mutex::new(vsock));
 
@@ -1278,6 +1768,40 @@ pub mod tests {
             .is_some());
     }
 
+    pub(crate) fn insert_crypto_device(
+        vmm: &mut Vmm,
+        cmdline: &mut Cmdline,
+        event_manager: &mut EventManager,
+        crypto_config: CryptoDeviceConfig,
+    ) {
+        let mut builder = CryptoDeviceBuilder::new();
+        let crypto = builder.build(crypto_config).unwrap();
+
+        attach_crypto_device(vmm, cmdline, &crypto, event_manager).unwrap();
+
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_CRYPTO), CRYPTO_DEV_ID)
+            .is_some());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub(crate) fn insert_tpm_device(
+        vmm: &mut Vmm,
+        event_manager: &mut EventManager,
+        socket: &std::path::Path,
+    ) {
+        let tpm_config = TpmConfig {
+            socket: socket.to_str().unwrap().to_string(),
+        };
+        attach_tpm_device(vmm, &tpm_config, event_manager).unwrap();
+
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Tpm, &DeviceType::Tpm.to_string())
+            .is_some());
+    }
+
     #[cfg(target_arch = "x86_64")]
     pub(crate) fn insert_vmgenid_device(vmm: &mut Vmm) {
         attach_vmgenid_device(vmm).unwrap();
@@ -1363,6 +1887,47 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_reserve_crash_kernel() {
+        let gm = arch_mem(256 << 20);
+        let kernel_end = crate::arch::get_kernel_start() + (16 << 20);
+        let mut cmdline = LoaderKernelCmdline::new(crate::arch::CMDLINE_MAX_SIZE).unwrap();
+
+        reserve_crash_kernel(&gm, kernel_end, None, 64, &mut cmdline).unwrap();
+        let cmdline = cmdline.as_cstring().unwrap().into_string().unwrap();
+        assert!(cmdline.starts_with("crashkernel=64M@0x"), "{}", cmdline);
+
+        let mut cmdline = LoaderKernelCmdline::new(crate::arch::CMDLINE_MAX_SIZE).unwrap();
+        let res = reserve_crash_kernel(&gm, kernel_end, None, 512, &mut cmdline);
+        assert!(
+            matches!(res, Err(StartMicrovmError::CrashKernelReserve)),
+            "{:?}",
+            res
+        );
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_confidential_launch_payload() {
+        let kernel_end = crate::arch::get_kernel_start() + 0x1234;
+        assert_eq!(
+            confidential_launch_payload(kernel_end, None),
+            vec![(GuestAddress(0), crate::arch::get_kernel_start() + 0x2000)]
+        );
+
+        let initrd = InitrdConfig {
+            address: GuestAddress(0x800_0000),
+            size: 0x1001,
+        };
+        assert_eq!(
+            confidential_launch_payload(kernel_end, Some(&initrd)),
+            vec![
+                (GuestAddress(0), crate::arch::get_kernel_start() + 0x2000),
+                (GuestAddress(0x800_0000), 0x2000),
+            ]
+        );
+    }
+
     #[test]
     fn test_create_vcpus() {
         let vcpu_count = 2;
@@ -1391,6 +1956,13 @@ pub mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            peer: None,
+            dma_ranges: None,
+            rx_interrupt_coalescing: None,
+            tx_interrupt_coalescing: None,
+            worker: false,
+            dhcp: None,
+            xdp: None,
         };
 
         let mut cmdline = default_kernel_cmdline();
@@ -1406,6 +1978,102 @@ pub mod tests {
         net_builder.build(network_interface).unwrap_err();
     }
 
+    #[test]
+    fn test_guest_memory_backend() {
+        let backend_name = |vm_config: &VmConfig, shared| {
+            format!("{:?}", guest_memory_backend(vm_config, shared).unwrap())
+        };
+        let mut vm_config = VmConfig::default();
+
+        assert_eq!(backend_name(&vm_config, false), "AnonymousBackend");
+        assert!(backend_name(&vm_config, true).starts_with("MemfdBackend"));
+        vm_config.huge_pages = HugePageConfig::Hugetlbfs2M;
+        assert!(backend_name(&vm_config, false).starts_with("HugetlbfsBackend"));
+
+        vm_config.memory_backend = MemoryBackendType::Memfd;
+        assert!(backend_name(&vm_config, false).starts_with("MemfdBackend"));
+
+        vm_config.memory_backend = MemoryBackendType::Anonymous;
+        assert!(backend_name(&vm_config, false).starts_with("HugetlbfsBackend"));
+        vm_config.huge_pages = HugePageConfig::None;
+        assert_eq!(backend_name(&vm_config, false), "AnonymousBackend");
+        assert!(matches!(
+            guest_memory_backend(&vm_config, true),
+            Err(StartMicrovmError::PrivateGuestMemory)
+        ));
+    }
+
+    #[test]
+    fn test_apply_virtio_feature_policy() {
+        let mut vm_resources = VmResources::default();
+        for iface_id in ["net0", "net1"] {
+            vm_resources
+                .build_net_device(NetworkInterfaceConfig {
+                    iface_id: String::from(iface_id),
+                    host_dev_name: String::from("net-device%d"),
+                    guest_mac: None,
+                    rx_rate_limiter: None,
+                    tx_rate_limiter: None,
+                    peer: None,
+                    dma_ranges: None,
+                    rx_interrupt_coalescing: None,
+                    tx_interrupt_coalescing: None,
+                    worker: false,
+                    dhcp: None,
+                    xdp: None,
+                })
+                .unwrap();
+        }
+        vm_resources
+            .build_entropy_device(EntropyDeviceConfig::default())
+            .unwrap();
+        let net_features = vm_resources
+            .net_builder
+            .iter()
+            .next()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .avail_features();
+        let entropy_features = vm_resources
+            .entropy
+            .get()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .avail_features();
+
+        let policy = &mut vm_resources.vm_config.virtio_feature_policy;
+        policy.denied_features = vec![VIRTIO_RING_F_EVENT_IDX];
+        policy
+            .devices
+            .insert(String::from("net0"), vec![VIRTIO_NET_F_CSUM]);
+        apply_virtio_feature_policy(&vm_resources);
+
+        let nets: Vec<_> = vm_resources
+            .net_builder
+            .iter()
+            .map(|net| net.lock().unwrap().avail_features())
+            .collect();
+        assert_eq!(
+            nets,
+            [
+                net_features & !(1 << VIRTIO_RING_F_EVENT_IDX | 1 << VIRTIO_NET_F_CSUM),
+                net_features & !(1 << VIRTIO_RING_F_EVENT_IDX),
+            ]
+        );
+        assert_eq!(
+            vm_resources
+                .entropy
+                .get()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .avail_features(),
+            entropy_features & !(1 << VIRTIO_RING_F_EVENT_IDX)
+        );
+    }
+
     #[test]
     fn test_attach_block_devices() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
@@ -1452,6 +2120,11 @@ pub mod tests {
 
         // Use case 3: root block device is not added at all.
         {
+            let mut cmdline = default_kernel_cmdline();
+            assert!(!cmdline_has_root(&cmdline));
+            cmdline.insert_str("root=/dev/pmem0").unwrap();
+            assert!(cmdline_has_root(&cmdline));
+
             let drive_id = String::from("non_root");
             let block_configs = vec![CustomBlockConfig::new(
                 drive_id.clone(),
@@ -1584,6 +2257,33 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_graceful_shutdown() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let block_configs = vec![
+            CustomBlockConfig::new(String::from("root"), true, None, true, CacheType::Unsafe),
+            CustomBlockConfig::new(
+                String::from("data"),
+                false,
+                None,
+                false,
+                CacheType::Writeback,
+            ),
+        ];
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+        insert_block_devices(&mut vmm, &mut cmdline, &mut event_manager, block_configs);
+
+        // With no time budget, none of the block devices gets drained.
+        let drain_timeouts = METRICS.vmm.shutdown_drain_timeouts.count();
+        vmm.graceful_shutdown(Duration::ZERO);
+        assert_eq!(vmm.shutdown_exit_code(), Some(FcExitCode::Ok));
+        assert_eq!(
+            METRICS.vmm.shutdown_drain_timeouts.count(),
+            drain_timeouts + 2
+        );
+    }
+
     #[test]
     fn test_attach_boot_timer_device() {
         let mut vmm = default_vmm();
@@ -1597,6 +2297,32 @@ pub mod tests {
             .is_some());
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_attach_tpm_device() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        let dir = utils::tempdir::TempDir::new().unwrap();
+        let socket = dir.as_path().join("swtpm.sock");
+
+        let tpm_config = TpmConfig {
+            socket: socket.to_str().unwrap().to_string(),
+        };
+        assert!(matches!(
+            attach_tpm_device(&mut vmm, &tpm_config, &mut event_manager),
+            Err(StartMicrovmError::CreateTpm(_))
+        ));
+
+        let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+        insert_tpm_device(&mut vmm, &mut event_manager, &socket);
+        // The device is described to the guest in the DSDT.
+        assert!(vmm
+            .mmio_device_manager
+            .dsdt_data
+            .windows(8)
+            .any(|window| window == b"MSFT0101"));
+    }
+
     #[test]
     fn test_attach_balloon_device() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
@@ -1618,6 +2344,23 @@ pub mod tests {
         ));
     }
 
+    #[test]
+    fn test_attach_crypto_device() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+
+        let crypto_config = CryptoDeviceConfig::default();
+
+        let mut cmdline = default_kernel_cmdline();
+        insert_crypto_device(&mut vmm, &mut cmdline, &mut event_manager, crypto_config);
+        // Check if the crypto device is described in kernel_cmdline.
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        assert!(cmdline_contains(
+            &cmdline,
+            "virtio_mmio.device=4K@0xd0000000:5"
+        ));
+    }
+
     #[test]
     fn test_attach_entropy_device() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
@@ -0,0 +1,225 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fingerprint of the host CPU, recorded in snapshots to check that they are restored on a
+//! compatible host.
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(target_arch = "aarch64")]
+use crate::arch::aarch64::vcpu::get_midr_el1_from_host;
+#[cfg(target_arch = "x86_64")]
+use crate::cpu_config::x86_64::cpuid::common::{get_cpuid, get_vendor_id_from_host};
+
+/// Registers holding the feature flags of the CPU, as (leaf, subleaf, register) where the
+/// register is the index of eax, ebx, ecx or edx.
+#[cfg(target_arch = "x86_64")]
+const FEATURE_REGISTERS: [(u32, u32, usize); 9] = [
+    (0x1, 0, 2),
+    (0x1, 0, 3),
+    (0x7, 0, 1),
+    (0x7, 0, 2),
+    (0x7, 0, 3),
+    (0x7, 1, 0),
+    (0xd, 1, 0),
+    (0x8000_0001, 0, 2),
+    (0x8000_0001, 0, 3),
+];
+
+/// Error type for [`CpuFingerprint::check_compatible`].
+#[derive(Debug, thiserror::Error, displaydoc::Display, PartialEq, Eq)]
+pub enum CpuFingerprintError {
+    /// Host CPU vendor {host} differs from the snapshot CPU vendor {snapshot}.
+    Vendor {
+        /// Vendor of the host CPU.
+        host: String,
+        /// Vendor of the CPU the snapshot was taken on.
+        snapshot: String,
+    },
+    /// Host CPU lacks features of the snapshot CPU: {0}
+    MissingFeatures(String),
+}
+
+/// Identity and features of a CPU.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuFingerprint {
+    /// CPU vendor: the CPUID vendor string on x86_64, the MIDR_EL1 implementer on aarch64.
+    pub vendor: String,
+    /// CPU model: the CPUID signature on x86_64, MIDR_EL1 on aarch64.
+    pub model: u32,
+    /// Feature flags: the CPUID feature registers on x86_64, the hwcaps on aarch64.
+    pub features: Vec<u64>,
+}
+
+impl CpuFingerprint {
+    /// Fingerprint of the host CPU.
+    #[cfg(target_arch = "x86_64")]
+    pub fn host() -> Self {
+        let vendor = get_vendor_id_from_host()
+            .map(|vendor| String::from_utf8_lossy(&vendor).into_owned())
+            .unwrap_or_default();
+        let model = get_cpuid(0x1, 0).map(|entry| entry.eax).unwrap_or_default();
+        Self {
+            vendor,
+            model,
+            features: Self::feature_words(|leaf, subleaf| {
+                get_cpuid(leaf, subleaf)
+                    .ok()
+                    .map(|entry| [entry.eax, entry.ebx, entry.ecx, entry.edx])
+            }),
+        }
+    }
+
+    /// Fingerprint of the host CPU.
+    #[cfg(target_arch = "aarch64")]
+    pub fn host() -> Self {
+        let model = get_midr_el1_from_host().unwrap_or_default();
+        // SAFETY: `getauxval` has no preconditions, and returns 0 for unknown entries.
+        let (hwcap, hwcap2) = unsafe {
+            (
+                libc::getauxval(libc::AT_HWCAP),
+                libc::getauxval(libc::AT_HWCAP2),
+            )
+        };
+        Self {
+            vendor: format!("{:#04x}", model >> 24),
+            model,
+            features: vec![hwcap, hwcap2],
+        }
+    }
+
+    /// Feature flags of the CPU described by `cpuid`, which returns the registers of a leaf.
+    #[cfg(target_arch = "x86_64")]
+    pub fn feature_words(cpuid: impl Fn(u32, u32) -> Option<[u32; 4]>) -> Vec<u64> {
+        FEATURE_REGISTERS
+            .iter()
+            .map(|&(leaf, subleaf, register)| {
+                cpuid(leaf, subleaf).map_or(0, |registers| u64::from(registers[register]))
+            })
+            .collect()
+    }
+
+    /// Only keeps the feature flags which are also set in `features`.
+    pub fn mask_features(&mut self, features: &[u64]) {
+        for (word, mask) in self.features.iter_mut().zip(features.iter()) {
+            *word &= mask;
+        }
+    }
+
+    /// Checks that a guest running on the CPU described by `snapshot` can run on this CPU,
+    /// which must then have the same vendor and all of its features.
+    pub fn check_compatible(&self, snapshot: &CpuFingerprint) -> Result<(), CpuFingerprintError> {
+        if self.vendor != snapshot.vendor {
+            return Err(CpuFingerprintError::Vendor {
+                host: self.vendor.clone(),
+                snapshot: snapshot.vendor.clone(),
+            });
+        }
+
+        let missing: Vec<String> = snapshot
+            .features
+            .iter()
+            .enumerate()
+            .filter_map(|(index, word)| {
+                let missing = word & !self.features.get(index).copied().unwrap_or_default();
+                (missing != 0).then(|| format!("{} {missing:#x}", Self::feature_word_name(index)))
+            })
+            .collect();
+        if !missing.is_empty() {
+            return Err(CpuFingerprintError::MissingFeatures(missing.join(", ")));
+        }
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn feature_word_name(index: usize) -> String {
+        match FEATURE_REGISTERS.get(index) {
+            Some(&(leaf, subleaf, register)) => {
+                let register = ["eax", "ebx", "ecx", "edx"][register];
+                format!("CPUID {leaf:#x}.{subleaf} {register}")
+            }
+            None => format!("feature word {index}"),
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn feature_word_name(index: usize) -> String {
+        match index {
+            0 => "HWCAP".to_string(),
+            1 => "HWCAP2".to_string(),
+            _ => format!("feature word {index}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(vendor: &str, features: Vec<u64>) -> CpuFingerprint {
+        CpuFingerprint {
+            vendor: vendor.to_string(),
+            model: 0,
+            features,
+        }
+    }
+
+    #[test]
+    fn test_host() {
+        let host = CpuFingerprint::host();
+        assert!(!host.vendor.is_empty());
+        host.check_compatible(&host).unwrap();
+    }
+
+    #[test]
+    fn test_check_compatible() {
+        let host = fingerprint("vendor", vec![0b1011, 0b1]);
+
+        // A snapshot taken on a CPU with a subset of the host features can be loaded.
+        host.check_compatible(&fingerprint("vendor", vec![0b11, 0b1]))
+            .unwrap();
+        host.check_compatible(&fingerprint("vendor", vec![0b11]))
+            .unwrap();
+
+        assert_eq!(
+            host.check_compatible(&fingerprint("other", vec![0b11, 0b1])),
+            Err(CpuFingerprintError::Vendor {
+                host: "vendor".to_string(),
+                snapshot: "other".to_string(),
+            })
+        );
+
+        let err = host
+            .check_compatible(&fingerprint("vendor", vec![0b111, 0b1, 0b1]))
+            .unwrap_err();
+        let CpuFingerprintError::MissingFeatures(missing) = err else {
+            panic!("unexpected error: {err}");
+        };
+        assert_eq!(
+            missing,
+            format!(
+                "{} 0x4, {} 0x1",
+                CpuFingerprint::feature_word_name(0),
+                CpuFingerprint::feature_word_name(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_mask_features() {
+        let mut snapshot = fingerprint("vendor", vec![0b1011, 0b11]);
+        snapshot.mask_features(&[0b0110]);
+        assert_eq!(snapshot.features, vec![0b0010, 0b11]);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_feature_words() {
+        let words = CpuFingerprint::feature_words(|leaf, subleaf| match (leaf, subleaf) {
+            (0x1, 0) => Some([1, 2, 3, 4]),
+            (0x7, 1) => Some([5, 6, 7, 8]),
+            _ => None,
+        });
+        assert_eq!(words, vec![3, 4, 0, 0, 0, 5, 0, 0, 0]);
+    }
+}
@@ -134,6 +134,43 @@ pub(crate) fn msrs_to_save_by_cpuid(cpuid: &kvm_bindings::CpuId) -> std::collect
     // The number of emulated MCE banks can be configured via KVM_X86_SETUP_MCE.
     cpuid_msr_dep!(0x1, 0, edx, MCE_BITINDEX, 0x400..0x480);
 
+    // Architectural performance monitoring MSRs, present when the virtual PMU is exposed through
+    // leaf 0xA. The counters KVM reports in leaf 0xA are all backed by the host PMU.
+    if let Some(leaf_a) = cpuid
+        .as_slice()
+        .iter()
+        .find(|entry| entry.function == 0xA && entry.index == 0)
+    {
+        use crate::arch_gen::x86::msr_index::{
+            MSR_CORE_PERF_FIXED_CTR_CTRL, MSR_CORE_PERF_GLOBAL_CTRL, MSR_CORE_PERF_GLOBAL_OVF_CTRL,
+            MSR_CORE_PERF_GLOBAL_STATUS,
+        };
+        use crate::arch_gen::x86::perf_event::{
+            MSR_ARCH_PERFMON_EVENTSEL0, MSR_ARCH_PERFMON_FIXED_CTR0, MSR_ARCH_PERFMON_PERFCTR0,
+        };
+
+        let version = leaf_a.eax & 0xff;
+        if version > 0 {
+            let gp_counters = (leaf_a.eax >> 8) & 0xff;
+            msrs.extend(MSR_ARCH_PERFMON_PERFCTR0..MSR_ARCH_PERFMON_PERFCTR0 + gp_counters);
+            msrs.extend(MSR_ARCH_PERFMON_EVENTSEL0..MSR_ARCH_PERFMON_EVENTSEL0 + gp_counters);
+        }
+        // The fixed counters and the global controls were introduced in version 2.
+        if version > 1 {
+            let fixed_counters = leaf_a.edx & 0x1f;
+            msrs.extend(MSR_ARCH_PERFMON_FIXED_CTR0..MSR_ARCH_PERFMON_FIXED_CTR0 + fixed_counters);
+            msrs.extend([
+                MSR_CORE_PERF_FIXED_CTR_CTRL,
+                MSR_CORE_PERF_GLOBAL_STATUS,
+                MSR_CORE_PERF_GLOBAL_CTRL,
+            ]);
+            // Replaced by IA32_PERF_GLOBAL_STATUS_RESET in version 4.
+            if version < 4 {
+                msrs.insert(MSR_CORE_PERF_GLOBAL_OVF_CTRL);
+            }
+        }
+    }
+
     msrs
 }
 
@@ -154,4 +191,29 @@ mod tests {
             Err(GetCpuidError::UnsupportedLeaf(max_leaf_plus_one))
         );
     }
+
+    #[test]
+    fn test_msrs_to_save_by_cpuid_pmu() {
+        let leaf_a = |eax, edx| {
+            kvm_bindings::CpuId::from_entries(&[kvm_bindings::kvm_cpuid_entry2 {
+                function: 0xA,
+                eax,
+                edx,
+                ..Default::default()
+            }])
+            .unwrap()
+        };
+
+        // No performance monitoring MSRs when the PMU is hidden.
+        assert!(msrs_to_save_by_cpuid(&leaf_a(0, 0)).is_empty());
+
+        // Version 2 with 8 general-purpose counters and 3 fixed counters.
+        let msrs = msrs_to_save_by_cpuid(&leaf_a(0x0830_0802, 0x603));
+        assert_eq!(msrs.len(), 8 + 8 + 3 + 4);
+        assert!(msrs.contains(&0xc8)); // IA32_PMC7
+        assert!(msrs.contains(&0x18d)); // IA32_PERFEVTSEL7
+        assert!(msrs.contains(&0x30b)); // IA32_FIXED_CTR2
+        assert!(!msrs.contains(&0x30c));
+        assert!(msrs.contains(&0x390)); // IA32_PERF_GLOBAL_OVF_CTRL
+    }
 }
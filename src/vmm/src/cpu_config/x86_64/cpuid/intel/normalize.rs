@@ -20,6 +20,8 @@ pub enum NormalizeCpuidError {
     MissingLeaf7,
     /// Leaf 0xA is missing from CPUID.
     MissingLeafA,
+    /// Leaf 0xA reports no performance monitoring, the host doesn't virtualize the PMU.
+    PmuNotSupported,
     /// Failed to get brand string: {0}
     GetBrandString(DefaultBrandStringError),
     /// Failed to set brand string: {0}
@@ -68,11 +70,17 @@ impl super::IntelCpuid {
         cpu_count: u8,
         // The number of logical CPUs per core.
         cpus_per_core: u8,
+        // Whether the virtual PMU is exposed to the guest.
+        pmu: bool,
     ) -> Result<(), NormalizeCpuidError> {
         self.update_deterministic_cache_entry(cpu_count, cpus_per_core)?;
         self.update_power_management_entry()?;
         self.update_extended_feature_flags_entry()?;
-        self.update_performance_monitoring_entry()?;
+        if pmu {
+            self.check_performance_monitoring_entry()?;
+        } else {
+            self.update_performance_monitoring_entry()?;
+        }
         self.update_brand_string_entry()?;
 
         Ok(())
@@ -214,6 +222,21 @@ impl super::IntelCpuid {
         Ok(())
     }
 
+    /// Checks that the performance monitoring entry, as reported by KVM and adjusted by the CPU
+    /// template, describes a PMU.
+    fn check_performance_monitoring_entry(&self) -> Result<(), NormalizeCpuidError> {
+        let leaf_a = self
+            .get(&CpuidKey::leaf(0xA))
+            .ok_or(NormalizeCpuidError::MissingLeafA)?;
+        // Version ID of architectural performance monitoring.
+        //
+        // version_id: 0..8
+        if get_range(leaf_a.result.eax, 0..8) == 0 {
+            return Err(NormalizeCpuidError::PmuNotSupported);
+        }
+        Ok(())
+    }
+
     fn update_brand_string_entry(&mut self) -> Result<(), NormalizeCpuidError> {
         // Get host brand string.
         let host_brand_string: [u8; BRAND_STRING_LENGTH] = host_brand_string();
@@ -398,4 +421,41 @@ mod tests {
         assert!((leaf_7_0.result.ebx & (1 << 6)) > 0);
         assert!((leaf_7_0.result.ebx & (1 << 13)) > 0);
     }
+
+    #[test]
+    fn test_performance_monitoring_entry() {
+        let mut cpuid =
+            crate::cpu_config::x86_64::cpuid::IntelCpuid(std::collections::BTreeMap::from([(
+                CpuidKey::leaf(0xA),
+                crate::cpu_config::x86_64::cpuid::CpuidEntry {
+                    result: CpuidRegisters {
+                        eax: 0x0830_0802,
+                        ebx: 0,
+                        ecx: 0,
+                        edx: 0x603,
+                    },
+                    ..Default::default()
+                },
+            )]));
+
+        // The entry reported by KVM is kept when the PMU is exposed.
+        cpuid.check_performance_monitoring_entry().unwrap();
+        assert_eq!(
+            cpuid.get(&CpuidKey::leaf(0xA)).unwrap().result.eax,
+            0x0830_0802
+        );
+
+        // Otherwise, the PMU is hidden from the guest.
+        cpuid.update_performance_monitoring_entry().unwrap();
+        assert_eq!(
+            cpuid.check_performance_monitoring_entry(),
+            Err(NormalizeCpuidError::PmuNotSupported)
+        );
+
+        cpuid.0.clear();
+        assert_eq!(
+            cpuid.check_performance_monitoring_entry(),
+            Err(NormalizeCpuidError::MissingLeafA)
+        );
+    }
 }
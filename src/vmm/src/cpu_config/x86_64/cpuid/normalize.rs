@@ -177,6 +177,8 @@ impl super::Cpuid {
         cpu_count: u8,
         // The number of bits needed to enumerate logical CPUs per core.
         cpu_bits: u8,
+        // Whether the virtual PMU is exposed to the guest.
+        pmu: bool,
     ) -> Result<(), NormalizeCpuidError> {
         let cpus_per_core = 1u8
             .checked_shl(u32::from(cpu_bits))
@@ -190,7 +192,7 @@ impl super::Cpuid {
         match self {
             // Apply Intel specific modifications.
             Self::Intel(intel_cpuid) => {
-                intel_cpuid.normalize(cpu_index, cpu_count, cpus_per_core)?;
+                intel_cpuid.normalize(cpu_index, cpu_count, cpus_per_core, pmu)?;
             }
             // Apply AMD specific modifications.
             Self::Amd(amd_cpuid) => amd_cpuid.normalize(cpu_index, cpu_count, cpus_per_core)?,
@@ -11,11 +11,12 @@ use std::sync::{Arc, Mutex};
 
 #[cfg(target_arch = "x86_64")]
 use acpi_tables::{aml, Aml};
+use event_manager::{MutEventSubscriber, SubscriberId, SubscriberOps};
 use kvm_ioctls::{IoEventAddress, VmFd};
 use linux_loader::cmdline as kernel_cmdline;
 #[cfg(target_arch = "x86_64")]
 use log::debug;
-use log::info;
+use log::{error, info};
 use serde::{Deserialize, Serialize};
 use vm_allocator::AllocPolicy;
 
@@ -27,15 +28,21 @@ use crate::arch::DeviceType::Virtio;
 #[cfg(target_arch = "aarch64")]
 use crate::devices::legacy::RTCDevice;
 use crate::devices::pseudo::BootTimer;
+#[cfg(target_arch = "x86_64")]
+use crate::devices::tpm::{TpmBackend, TpmCrb};
 use crate::devices::virtio::balloon::Balloon;
 use crate::devices::virtio::block::device::Block;
+use crate::devices::virtio::crypto::Crypto;
 use crate::devices::virtio::device::VirtioDevice;
-use crate::devices::virtio::mmio::MmioTransport;
+use crate::devices::virtio::features::device_type_name;
+use crate::devices::virtio::mmio::{ActivationBarrier, MmioTransport};
 use crate::devices::virtio::net::Net;
+use crate::devices::virtio::queue_metrics::QueueMetricsPerDevice;
 use crate::devices::virtio::rng::Entropy;
 use crate::devices::virtio::vsock::TYPE_VSOCK;
-use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_NET, TYPE_RNG};
-use crate::devices::BusDevice;
+use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_CRYPTO, TYPE_NET, TYPE_RNG};
+use crate::devices::{BusDevice, BusRegion};
+use crate::event_loop::add_timed_subscriber;
 #[cfg(target_arch = "x86_64")]
 use crate::vstate::memory::GuestAddress;
 
@@ -60,6 +67,12 @@ pub enum MmioError {
     RegisterIoEvent(kvm_ioctls::Error),
     /// Failed to register irqfd: {0}
     RegisterIrqFd(kvm_ioctls::Error),
+    /// Failed to unregister IO event: {0}
+    UnregisterIoEvent(kvm_ioctls::Error),
+    /// Failed to unregister irqfd: {0}
+    UnregisterIrqFd(kvm_ioctls::Error),
+    /// Failed to remove the device from the event manager: {0}
+    RemoveSubscriber(event_manager::Error),
 }
 
 /// This represents the size of the mmio device specified to the kernel through ACPI and as a
@@ -123,6 +136,10 @@ pub struct MMIODeviceManager {
     // devices in the order they were added.
     #[cfg(target_arch = "x86_64")]
     pub(crate) dsdt_data: Vec<u8>,
+    // Notifies once the guest drivers activated all the virtio devices.
+    pub(crate) activation_barrier: Arc<ActivationBarrier>,
+    // Event manager subscribers of the virtio devices, removed when the devices are replaced.
+    subscriber_ids: HashMap<(DeviceType, String), SubscriberId>,
 }
 
 impl MMIODeviceManager {
@@ -133,6 +150,8 @@ impl MMIODeviceManager {
             id_to_dev_info: HashMap::new(),
             #[cfg(target_arch = "x86_64")]
             dsdt_data: vec![],
+            activation_barrier: Arc::new(ActivationBarrier::default()),
+            subscriber_ids: HashMap::new(),
         }
     }
 
@@ -174,7 +193,7 @@ impl MMIODeviceManager {
         &mut self,
         vm: &VmFd,
         device_id: String,
-        mmio_device: MmioTransport,
+        mut mmio_device: MmioTransport,
         device_info: &MMIODeviceInfo,
     ) -> Result<(), MmioError> {
         // Our virtio devices are currently hardcoded to use a single IRQ.
@@ -184,18 +203,19 @@ impl MMIODeviceManager {
         }
         let identifier;
         {
-            let locked_device = mmio_device.locked_device();
-            identifier = (DeviceType::Virtio(locked_device.device_type()), device_id);
-            for (i, queue_evt) in locked_device.queue_events().iter().enumerate() {
-                let io_addr = IoEventAddress::Mmio(
-                    device_info.addr + u64::from(crate::devices::virtio::NOTIFY_REG_OFFSET),
-                );
-                vm.register_ioevent(queue_evt, &io_addr, u32::try_from(i).unwrap())
-                    .map_err(MmioError::RegisterIoEvent)?;
+            let mut locked_device = mmio_device.locked_device();
+            let device_type = locked_device.device_type();
+            let queue_metrics = QueueMetricsPerDevice::alloc(
+                format!("{}_{}", device_type_name(device_type), device_id),
+                locked_device.queues().len(),
+            );
+            for (queue, metrics) in locked_device.queues_mut().iter_mut().zip(queue_metrics) {
+                queue.set_metrics(Some(metrics));
             }
-            vm.register_irqfd(locked_device.interrupt_evt(), device_info.irqs[0])
-                .map_err(MmioError::RegisterIrqFd)?;
+            identifier = (DeviceType::Virtio(device_type), device_id);
+            Self::register_virtio_fds(vm, &*locked_device, device_info)?;
         }
+        mmio_device.set_activation_barrier(self.activation_barrier.clone());
 
         self.register_mmio_device(
             identifier,
@@ -204,6 +224,122 @@ impl MMIODeviceManager {
         )
     }
 
+    fn register_virtio_fds(
+        vm: &VmFd,
+        device: &dyn VirtioDevice,
+        device_info: &MMIODeviceInfo,
+    ) -> Result<(), MmioError> {
+        let io_addr = IoEventAddress::Mmio(
+            device_info.addr + u64::from(crate::devices::virtio::NOTIFY_REG_OFFSET),
+        );
+        for (i, queue_evt) in device.queue_events().iter().enumerate() {
+            vm.register_ioevent(queue_evt, &io_addr, u32::try_from(i).unwrap())
+                .map_err(MmioError::RegisterIoEvent)?;
+        }
+        vm.register_irqfd(device.interrupt_evt(), device_info.irqs[0])
+            .map_err(MmioError::RegisterIrqFd)
+    }
+
+    fn unregister_virtio_fds(
+        vm: &VmFd,
+        device: &dyn VirtioDevice,
+        device_info: &MMIODeviceInfo,
+    ) -> Result<(), MmioError> {
+        let io_addr = IoEventAddress::Mmio(
+            device_info.addr + u64::from(crate::devices::virtio::NOTIFY_REG_OFFSET),
+        );
+        for (i, queue_evt) in device.queue_events().iter().enumerate() {
+            vm.unregister_ioevent(queue_evt, &io_addr, u32::try_from(i).unwrap())
+                .map_err(MmioError::UnregisterIoEvent)?;
+        }
+        vm.unregister_irqfd(device.interrupt_evt(), device_info.irqs[0])
+            .map_err(MmioError::UnregisterIrqFd)
+    }
+
+    /// Registers `subscriber`, handling the events of the virtio device of type `device_type`
+    /// registered with `device_id`, with the event manager.
+    pub fn add_virtio_subscriber(
+        &mut self,
+        event_manager: &mut crate::EventManager,
+        device_type: u32,
+        device_id: String,
+        subscriber: Arc<Mutex<dyn MutEventSubscriber>>,
+    ) {
+        let subscriber_id = add_timed_subscriber(event_manager, device_id.clone(), subscriber);
+        self.subscriber_ids
+            .insert((DeviceType::Virtio(device_type), device_id), subscriber_id);
+    }
+
+    /// Replaces the virtio device registered with `device_id` by `mmio_device`, whose events are
+    /// handled by `subscriber`.
+    ///
+    /// The new device takes over the MMIO range and IRQ of the old one, so the device layout seen
+    /// by the guest does not change. This allows swapping out a device whose backend could not be
+    /// set up, e.g. while restoring from a snapshot. Both devices must be of the same type. The old
+    /// device is removed from the event manager, and is left in place if the new one can't be
+    /// registered.
+    pub fn replace_mmio_virtio(
+        &mut self,
+        vm: &VmFd,
+        event_manager: &mut crate::EventManager,
+        device_id: &str,
+        mmio_device: MmioTransport,
+        subscriber: Arc<Mutex<dyn MutEventSubscriber>>,
+    ) -> Result<(), MmioError> {
+        let device_type = mmio_device.locked_device().device_type();
+        let identifier = (DeviceType::Virtio(device_type), device_id.to_string());
+        let device_info = self
+            .id_to_dev_info
+            .get(&identifier)
+            .ok_or(MmioError::DeviceNotFound)?;
+        let (_, bus_device) = self
+            .bus
+            .get_device(device_info.addr)
+            .ok_or(MmioError::DeviceNotFound)?;
+
+        let mut bus_device = bus_device.lock().expect("Poisoned lock");
+        let old_device = bus_device
+            .mmio_transport_ref()
+            .ok_or(MmioError::InvalidDeviceType)?;
+        let old_subscriber = self
+            .subscriber_ids
+            .remove(&identifier)
+            .map(|subscriber_id| event_manager.remove_subscriber(subscriber_id))
+            .transpose()
+            .map_err(MmioError::RemoveSubscriber)?;
+
+        let old_locked = old_device.locked_device();
+        let new_locked = mmio_device.locked_device();
+        let result = Self::unregister_virtio_fds(vm, &*old_locked, device_info)
+            .and_then(|()| Self::register_virtio_fds(vm, &*new_locked, device_info));
+        if result.is_err() {
+            // The guest keeps the old device.
+            let _ = Self::unregister_virtio_fds(vm, &*new_locked, device_info);
+            if let Err(err) = Self::register_virtio_fds(vm, &*old_locked, device_info) {
+                error!("Cannot restore the device {}: {}", device_id, err);
+            }
+        }
+        drop(new_locked);
+        drop(old_locked);
+        if let Err(err) = result {
+            if let Some(old_subscriber) = old_subscriber {
+                self.subscriber_ids
+                    .insert(identifier, event_manager.add_subscriber(old_subscriber));
+            }
+            return Err(err);
+        }
+
+        *bus_device = BusDevice::MmioTransport(mmio_device);
+        drop(bus_device);
+        self.add_virtio_subscriber(
+            event_manager,
+            device_type,
+            device_id.to_string(),
+            subscriber,
+        );
+        Ok(())
+    }
+
     /// Append a registered virtio-over-MMIO device to the kernel cmdline.
     #[cfg(target_arch = "x86_64")]
     pub fn add_virtio_device_to_cmdline(
@@ -346,11 +482,73 @@ impl MMIODeviceManager {
         )
     }
 
+    /// Register a TPM device, forwarding the commands of the guest to `backend`.
+    #[cfg(target_arch = "x86_64")]
+    pub fn register_mmio_tpm(
+        &mut self,
+        resource_allocator: &mut ResourceAllocator,
+        backend: Box<dyn TpmBackend>,
+    ) -> Result<Arc<Mutex<BusDevice>>, MmioError> {
+        let device_info = self.allocate_mmio_resources(resource_allocator, 0)?;
+        let tpm = TpmCrb::new(device_info.addr, backend)
+            .map_err(|err| MmioError::InternalDeviceError(err.to_string()))?;
+
+        debug!(
+            "acpi: Building AML for TPM device _SB_.TPM0. memory range: {:#010x}:{}",
+            device_info.addr, device_info.len
+        );
+        aml::Device::new(
+            "TPM0".into(),
+            vec![
+                &aml::Name::new("_HID".into(), &"MSFT0101"),
+                &aml::Name::new("_STA".into(), &0x0fu8),
+                &aml::Name::new(
+                    "_CRS".into(),
+                    &aml::ResourceTemplate::new(vec![&aml::Memory32Fixed::new(
+                        true,
+                        device_info.addr.try_into().unwrap(),
+                        device_info.len.try_into().unwrap(),
+                    )]),
+                ),
+            ],
+        )
+        .append_aml_bytes(&mut self.dsdt_data);
+
+        let device = Arc::new(Mutex::new(BusDevice::Tpm(tpm)));
+        let identifier = (DeviceType::Tpm, DeviceType::Tpm.to_string());
+        self.register_mmio_device(identifier, device_info, device.clone())?;
+        Ok(device)
+    }
+
     /// Gets the information of the devices registered up to some point in time.
     pub fn get_device_info(&self) -> &HashMap<(DeviceType, String), MMIODeviceInfo> {
         &self.id_to_dev_info
     }
 
+    /// Returns the address ranges registered on the MMIO bus. Virtio devices are named after
+    /// their type and id, e.g. `virtio-block/rootfs`, and report their activation status.
+    pub fn bus_regions(&self) -> Vec<BusRegion> {
+        let mut regions = self.bus.regions();
+        for ((device_type, id), device_info) in self.id_to_dev_info.iter() {
+            if let Virtio(_) = device_type {
+                if let Some(region) = regions.iter_mut().find(|r| r.base == device_info.addr) {
+                    region.device = format!("{}/{}", region.device, id);
+                    region.activation =
+                        self.bus
+                            .get_device(device_info.addr)
+                            .and_then(|(_, device)| {
+                                device
+                                    .lock()
+                                    .expect("Poisoned lock")
+                                    .mmio_transport_ref()
+                                    .map(|transport| transport.activation().clone())
+                            });
+                }
+            }
+        }
+        regions
+    }
+
     /// Gets the specified device.
     pub fn get_device(
         &self,
@@ -383,6 +581,24 @@ impl MMIODeviceManager {
         Ok(())
     }
 
+    /// Starts logging the register accesses of the virtio devices with the given IDs, and stops
+    /// logging the ones of the other virtio devices. Unknown IDs are ignored.
+    pub fn set_mmio_trace(&self, device_ids: &[String]) {
+        let _ = self.for_each_device(|device_type, device_id, _, bus_device| {
+            if let Virtio(_) = device_type {
+                let mut bus_device = bus_device.lock().expect("Poisoned lock");
+                let name = device_ids
+                    .contains(device_id)
+                    .then(|| format!("{}/{}", bus_device.name(), device_id));
+                bus_device
+                    .mmio_transport_mut()
+                    .expect("Unexpected device type")
+                    .set_trace(name);
+            }
+            Ok::<(), MmioError>(())
+        });
+    }
+
     /// Run fn for each registered virtio device.
     pub fn for_each_virtio_device<F, E: Debug>(&self, mut f: F) -> Result<(), E>
     where
@@ -491,6 +707,13 @@ impl MMIODeviceManager {
                             entropy.process_virtio_queues();
                         }
                     }
+                    TYPE_CRYPTO => {
+                        let crypto = virtio.as_mut_any().downcast_mut::<Crypto>().unwrap();
+                        if crypto.is_activated() {
+                            info!("kick crypto {id}.");
+                            crypto.process_virtio_queues();
+                        }
+                    }
                     _ => (),
                 }
                 Ok(())
@@ -513,18 +736,21 @@ impl DeviceInfoForFDT for MMIODeviceInfo {
 
 #[cfg(test)]
 mod tests {
+    use std::os::unix::io::AsRawFd;
     use std::sync::atomic::AtomicU32;
     use std::sync::Arc;
 
+    use event_manager::{EventOps, EventSet, Events};
     use utils::eventfd::EventFd;
 
     use super::*;
     use crate::devices::virtio::device::VirtioDevice;
+    use crate::devices::virtio::mmio::DeviceActivation;
     use crate::devices::virtio::queue::Queue;
     use crate::devices::virtio::ActivateError;
     use crate::utilities::test_utils::multi_region_mem;
     use crate::vstate::memory::{GuestAddress, GuestMemoryMmap};
-    use crate::{builder, Vm};
+    use crate::{builder, EventManager, Vm};
 
     const QUEUE_SIZES: &[u16] = &[64];
 
@@ -567,6 +793,8 @@ mod tests {
         queues: Vec<Queue>,
         queue_evts: [EventFd; 1],
         interrupt_evt: EventFd,
+        // Number of queue events processed.
+        processed: u32,
     }
 
     impl DummyDevice {
@@ -576,15 +804,30 @@ mod tests {
                 queues: QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect(),
                 queue_evts: [EventFd::new(libc::EFD_NONBLOCK).expect("cannot create eventFD")],
                 interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).expect("cannot create eventFD"),
+                processed: 0,
             }
         }
     }
 
+    impl MutEventSubscriber for DummyDevice {
+        fn process(&mut self, _: Events, _: &mut EventOps) {
+            self.queue_evts[0].read().unwrap();
+            self.processed += 1;
+        }
+
+        fn init(&mut self, ops: &mut EventOps) {
+            ops.add(Events::new(&self.queue_evts[0], EventSet::IN))
+                .unwrap();
+        }
+    }
+
     impl VirtioDevice for DummyDevice {
         fn avail_features(&self) -> u64 {
             0
         }
 
+        fn set_avail_features(&mut self, _: u64) {}
+
         fn acked_features(&self) -> u64 {
             0
         }
@@ -666,6 +909,22 @@ mod tests {
                 "dummy",
             )
             .unwrap();
+
+        let is_traced = |device_manager: &MMIODeviceManager| {
+            device_manager
+                .get_device(DeviceType::Virtio(0), "dummy")
+                .unwrap()
+                .lock()
+                .unwrap()
+                .mmio_transport_ref()
+                .unwrap()
+                .is_traced()
+        };
+        assert!(!is_traced(&device_manager));
+        device_manager.set_mmio_trace(&["dummy".to_string()]);
+        assert!(is_traced(&device_manager));
+        device_manager.set_mmio_trace(&[]);
+        assert!(!is_traced(&device_manager));
     }
 
     #[test]
@@ -722,6 +981,135 @@ mod tests {
         assert_eq!(dummy.queues().len(), QUEUE_SIZES.len());
     }
 
+    #[test]
+    fn test_replace_device() {
+        let start_addr1 = GuestAddress(0x0);
+        let start_addr2 = GuestAddress(0x1000);
+        let guest_mem = multi_region_mem(&[(start_addr1, 0x1000), (start_addr2, 0x1000)]);
+        let mut vm = Vm::new(vec![]).unwrap();
+        vm.memory_init(&guest_mem, false).unwrap();
+
+        #[cfg(target_arch = "x86_64")]
+        builder::setup_interrupt_controller(&mut vm).unwrap();
+        #[cfg(target_arch = "aarch64")]
+        builder::setup_interrupt_controller(&mut vm, 1).unwrap();
+
+        let mut device_manager = MMIODeviceManager::new();
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let mut cmdline = kernel_cmdline::Cmdline::new(4096).unwrap();
+        let mut event_manager = EventManager::new().unwrap();
+        let mut register = |device_manager: &mut MMIODeviceManager, id: &str| {
+            let dummy = Arc::new(Mutex::new(DummyDevice::new()));
+            device_manager
+                .register_virtio_test_device(
+                    vm.fd(),
+                    guest_mem.clone(),
+                    &mut resource_allocator,
+                    dummy.clone(),
+                    &mut cmdline,
+                    id,
+                )
+                .unwrap();
+            device_manager.add_virtio_subscriber(
+                &mut event_manager,
+                0,
+                id.to_string(),
+                dummy.clone(),
+            );
+            dummy
+        };
+        let dummy = register(&mut device_manager, "foo");
+        let other_dummy = register(&mut device_manager, "bar");
+        let identifier = (DeviceType::Virtio(0), String::from("foo"));
+        let device_info = device_manager.id_to_dev_info[&identifier].clone();
+
+        // Unknown devices cannot be replaced.
+        let new_dummy = Arc::new(Mutex::new(DummyDevice::new()));
+        let mmio_device = MmioTransport::new(guest_mem.clone(), new_dummy.clone(), false);
+        assert!(matches!(
+            device_manager.replace_mmio_virtio(
+                vm.fd(),
+                &mut event_manager,
+                "baz",
+                mmio_device,
+                new_dummy
+            ),
+            Err(MmioError::DeviceNotFound)
+        ));
+
+        // The old device is kept when the new one can't be registered, here because its
+        // interrupt is already used by another device.
+        let mut bad_dummy = DummyDevice::new();
+        bad_dummy.interrupt_evt = other_dummy
+            .lock()
+            .unwrap()
+            .interrupt_evt
+            .try_clone()
+            .unwrap();
+        let bad_dummy = Arc::new(Mutex::new(bad_dummy));
+        let mmio_device = MmioTransport::new(guest_mem.clone(), bad_dummy.clone(), false);
+        assert!(matches!(
+            device_manager.replace_mmio_virtio(
+                vm.fd(),
+                &mut event_manager,
+                "foo",
+                mmio_device,
+                bad_dummy.clone()
+            ),
+            Err(MmioError::RegisterIrqFd(_))
+        ));
+        let current_irq_fd = |device_manager: &MMIODeviceManager| -> i32 {
+            let irq_fd = device_manager
+                .get_device(DeviceType::Virtio(0), "foo")
+                .unwrap()
+                .lock()
+                .unwrap()
+                .mmio_transport_ref()
+                .unwrap()
+                .locked_device()
+                .interrupt_evt()
+                .as_raw_fd();
+            irq_fd
+        };
+        let irq_fd = current_irq_fd(&device_manager);
+        assert_eq!(irq_fd, dummy.lock().unwrap().interrupt_evt.as_raw_fd());
+        dummy.lock().unwrap().queue_evts[0].write(1).unwrap();
+        bad_dummy.lock().unwrap().queue_evts[0].write(1).unwrap();
+        event_manager.run_with_timeout(0).unwrap();
+        assert_eq!(dummy.lock().unwrap().processed, 1);
+        assert_eq!(bad_dummy.lock().unwrap().processed, 0);
+        // The fds of the old device are still registered with KVM.
+        vm.fd()
+            .register_irqfd(&dummy.lock().unwrap().interrupt_evt, device_info.irqs[0])
+            .unwrap_err();
+
+        let new_dummy = Arc::new(Mutex::new(DummyDevice::new()));
+        let mmio_device = MmioTransport::new(guest_mem, new_dummy.clone(), false);
+        device_manager
+            .replace_mmio_virtio(
+                vm.fd(),
+                &mut event_manager,
+                "foo",
+                mmio_device,
+                new_dummy.clone(),
+            )
+            .unwrap();
+
+        // The new device sits in the same slot, with the same IRQ.
+        assert_eq!(device_manager.id_to_dev_info[&identifier], device_info);
+        let irq_fd = current_irq_fd(&device_manager);
+        assert_eq!(irq_fd, new_dummy.lock().unwrap().interrupt_evt.as_raw_fd());
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(device_manager.used_irqs_count(), 2);
+
+        // The events are handled by the new device only.
+        dummy.lock().unwrap().queue_evts[0].write(1).unwrap();
+        new_dummy.lock().unwrap().queue_evts[0].write(1).unwrap();
+        event_manager.run_with_timeout(0).unwrap();
+        assert_eq!(dummy.lock().unwrap().processed, 1);
+        assert_eq!(new_dummy.lock().unwrap().processed, 1);
+    }
+
     #[test]
     fn test_device_info() {
         let start_addr1 = GuestAddress(0x0);
@@ -797,6 +1185,14 @@ mod tests {
         assert_eq!(count, 3);
         #[cfg(target_arch = "x86_64")]
         assert_eq!(device_manager.used_irqs_count(), 2);
+
+        let regions = device_manager.bus_regions();
+        assert_eq!(regions.len(), 2);
+        let region = regions.iter().find(|r| r.base == addr).unwrap();
+        assert_eq!(region.len, MMIO_LEN);
+        assert_eq!(region.device, format!("virtio-{type_id}/foo"));
+        assert_eq!(region.activation, Some(DeviceActivation::Inactive));
+        assert_eq!(device_manager.activation_barrier.pending(), 2);
     }
 
     #[test]
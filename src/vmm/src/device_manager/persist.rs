@@ -6,7 +6,7 @@
 use std::fmt::{self, Debug};
 use std::sync::{Arc, Mutex};
 
-use event_manager::{MutEventSubscriber, SubscriberOps};
+use event_manager::MutEventSubscriber;
 use kvm_ioctls::VmFd;
 use log::{error, warn};
 use serde::{Deserialize, Serialize};
@@ -25,13 +25,20 @@ use crate::devices::virtio::balloon::{Balloon, BalloonError};
 use crate::devices::virtio::block::device::Block;
 use crate::devices::virtio::block::persist::{BlockConstructorArgs, BlockState};
 use crate::devices::virtio::block::BlockError;
+use crate::devices::virtio::crypto::persist::{
+    CryptoConstructorArgs, CryptoPersistError as CryptoError, CryptoState,
+};
+use crate::devices::virtio::crypto::Crypto;
 use crate::devices::virtio::device::VirtioDevice;
 use crate::devices::virtio::mmio::MmioTransport;
 use crate::devices::virtio::net::persist::{
     NetConstructorArgs, NetPersistError as NetError, NetState,
 };
 use crate::devices::virtio::net::Net;
-use crate::devices::virtio::persist::{MmioTransportConstructorArgs, MmioTransportState};
+use crate::devices::virtio::persist::{
+    MmioTransportConstructorArgs, MmioTransportState, SnapshotFeature,
+};
+use crate::devices::virtio::remote::RemoteDevice;
 use crate::devices::virtio::rng::persist::{
     EntropyConstructorArgs, EntropyPersistError as EntropyError, EntropyState,
 };
@@ -42,7 +49,7 @@ use crate::devices::virtio::vsock::persist::{
 use crate::devices::virtio::vsock::{
     Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError, TYPE_VSOCK,
 };
-use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_NET, TYPE_RNG};
+use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_CRYPTO, TYPE_NET, TYPE_RNG};
 use crate::mmds::data_store::MmdsVersion;
 use crate::resources::{ResourcesError, VmResources};
 use crate::snapshot::Persist;
@@ -74,8 +81,12 @@ pub enum DevicePersistError {
     MmdsConfig(#[from] MmdsConfigError),
     /// Entropy: {0}
     Entropy(#[from] EntropyError),
+    /// Crypto: {0}
+    Crypto(#[from] CryptoError),
     /// Resource misconfiguration: {0}. Is the snapshot file corrupted?
     ResourcesError(#[from] ResourcesError),
+    /// Device {0} negotiated virtio features denied by the feature policy: {1:#x}
+    DeniedVirtioFeatures(String, u64),
 }
 
 /// Holds the state of a balloon device connected to the MMIO space.
@@ -130,6 +141,19 @@ pub struct ConnectedVsockState {
     pub device_info: MMIODeviceInfo,
 }
 
+/// Holds the state of a crypto device connected to the MMIO space.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectedCryptoState {
+    /// Device identifier.
+    pub device_id: String,
+    /// Device state.
+    pub device_state: CryptoState,
+    /// Mmio transport state.
+    pub transport_state: MmioTransportState,
+    /// VmmResources.
+    pub device_info: MMIODeviceInfo,
+}
+
 /// Holds the state of an entropy device connected to the MMIO space.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConnectedEntropyState {
@@ -188,14 +212,93 @@ pub struct DeviceStates {
     pub block_devices: Vec<ConnectedBlockState>,
     /// Net device states.
     pub net_devices: Vec<ConnectedNetState>,
-    /// Vsock device state.
-    pub vsock_device: Option<ConnectedVsockState>,
+    /// Vsock device states.
+    pub vsock_devices: Vec<ConnectedVsockState>,
     /// Balloon device state.
     pub balloon_device: Option<ConnectedBalloonState>,
     /// Mmds version.
     pub mmds_version: Option<MmdsVersionState>,
     /// Entropy device state.
     pub entropy_device: Option<ConnectedEntropyState>,
+    /// Crypto device state.
+    pub crypto_device: Option<ConnectedCryptoState>,
+}
+
+impl DeviceStates {
+    /// IDs and types of the devices.
+    pub fn devices(&self) -> Vec<(String, &'static str)> {
+        let block = self
+            .block_devices
+            .iter()
+            .map(|dev| (&dev.device_id, "block"));
+        let net = self.net_devices.iter().map(|dev| (&dev.device_id, "net"));
+        let vsock = self
+            .vsock_devices
+            .iter()
+            .map(|dev| (&dev.device_id, "vsock"));
+        let balloon = self
+            .balloon_device
+            .iter()
+            .map(|dev| (&dev.device_id, "balloon"));
+        let entropy = self
+            .entropy_device
+            .iter()
+            .map(|dev| (&dev.device_id, "entropy"));
+        let crypto = self
+            .crypto_device
+            .iter()
+            .map(|dev| (&dev.device_id, "crypto"));
+
+        block
+            .chain(net)
+            .chain(vsock)
+            .chain(balloon)
+            .chain(entropy)
+            .chain(crypto)
+            .map(|(id, kind)| (id.clone(), kind))
+            .collect()
+    }
+
+    /// Snapshot features used by the devices, along with the IDs of the devices using them.
+    pub fn snapshot_features(&self) -> Vec<(String, SnapshotFeature)> {
+        let block = self
+            .block_devices
+            .iter()
+            .map(|dev| (&dev.device_id, dev.device_state.snapshot_features()));
+        let net = self
+            .net_devices
+            .iter()
+            .map(|dev| (&dev.device_id, dev.device_state.snapshot_features()));
+        let vsock = self
+            .vsock_devices
+            .iter()
+            .map(|dev| (&dev.device_id, dev.device_state.snapshot_features()));
+        let balloon = self
+            .balloon_device
+            .iter()
+            .map(|dev| (&dev.device_id, dev.device_state.snapshot_features()));
+        let entropy = self
+            .entropy_device
+            .iter()
+            .map(|dev| (&dev.device_id, dev.device_state.snapshot_features()));
+        let crypto = self
+            .crypto_device
+            .iter()
+            .map(|dev| (&dev.device_id, dev.device_state.snapshot_features()));
+
+        block
+            .chain(net)
+            .chain(vsock)
+            .chain(balloon)
+            .chain(entropy)
+            .chain(crypto)
+            .flat_map(|(id, features)| {
+                features
+                    .into_iter()
+                    .map(move |feature| (id.clone(), feature))
+            })
+            .collect()
+    }
 }
 
 /// A type used to extract the concrete `Arc<Mutex<T>>` for each of the device
@@ -207,6 +310,7 @@ pub enum SharedDeviceType {
     Balloon(Arc<Mutex<Balloon>>),
     Vsock(Arc<Mutex<Vsock<VsockUnixBackend>>>),
     Entropy(Arc<Mutex<Entropy>>),
+    Crypto(Arc<Mutex<Crypto>>),
 }
 
 pub struct MMIODevManagerConstructorArgs<'a> {
@@ -295,6 +399,11 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                 // No need to save BootTimer state.
                 return Ok(());
             }
+            #[cfg(target_arch = "x86_64")]
+            if *devtype == crate::arch::DeviceType::Tpm {
+                // The TPM state is held by its emulator, snapshots of it are rejected.
+                return Ok(());
+            }
 
             #[cfg(target_arch = "aarch64")]
             {
@@ -350,6 +459,14 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                 }
                 TYPE_NET => {
                     let net = locked_device.as_any().downcast_ref::<Net>().unwrap();
+                    if net.is_worker() {
+                        warn!(
+                            "Skipping net device {}. Net devices emulated by a worker process do \
+                             not support snapshotting yet",
+                            devid
+                        );
+                        return Ok(());
+                    }
                     if let (Some(mmds_ns), None) =
                         (net.mmds_ns.as_ref(), states.mmds_version.as_ref())
                     {
@@ -384,7 +501,7 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                         });
                     }
 
-                    states.vsock_device = Some(ConnectedVsockState {
+                    states.vsock_devices.push(ConnectedVsockState {
                         device_id: devid.clone(),
                         device_state: vsock_state,
                         transport_state,
@@ -404,6 +521,22 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                         device_info: device_info.clone(),
                     });
                 }
+                TYPE_CRYPTO => {
+                    let crypto = locked_device.as_mut_any().downcast_mut::<Crypto>().unwrap();
+
+                    states.crypto_device = Some(ConnectedCryptoState {
+                        device_id: devid.clone(),
+                        device_state: crypto.save(),
+                        transport_state,
+                        device_info: device_info.clone(),
+                    });
+                }
+                _ if locked_device.as_any().is::<RemoteDevice>() => {
+                    warn!(
+                        "Skipping remote device {}. Remote devices do not support snapshotting yet",
+                        devid
+                    );
+                }
                 _ => unreachable!(),
             };
 
@@ -471,6 +604,11 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             }
         }
 
+        let virtio_feature_policy = constructor_args
+            .vm_resources
+            .vm_config
+            .virtio_feature_policy
+            .clone();
         let mut restore_helper = |device: Arc<Mutex<dyn VirtioDevice>>,
                                   is_vhost_user: bool,
                                   as_subscriber: Arc<Mutex<dyn MutEventSubscriber>>,
@@ -479,6 +617,23 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                                   device_info: &MMIODeviceInfo,
                                   event_manager: &mut EventManager|
          -> Result<(), Self::Error> {
+            let device_type;
+            {
+                let mut locked = device.lock().expect("Poisoned lock");
+                device_type = locked.device_type();
+                let denied_features = virtio_feature_policy.denied_features(id);
+                // The features negotiated by the driver cannot be withdrawn from the guest.
+                let denied_acked_features = locked.acked_features() & denied_features;
+                if denied_acked_features != 0 {
+                    return Err(DevicePersistError::DeniedVirtioFeatures(
+                        id.clone(),
+                        denied_acked_features,
+                    ));
+                }
+                let avail_features = locked.avail_features() & !denied_features;
+                locked.set_avail_features(avail_features);
+            }
+
             let restore_args = MmioTransportConstructorArgs {
                 mem: mem.clone(),
                 device,
@@ -510,7 +665,12 @@ impl<'a> Persist<'a> for MMIODeviceManager {
 
             dev_manager.register_mmio_virtio(vm, id.clone(), mmio_transport, device_info)?;
 
-            event_manager.add_subscriber(as_subscriber);
+            dev_manager.add_virtio_subscriber(
+                event_manager,
+                device_type,
+                id.clone(),
+                as_subscriber,
+            );
             Ok(())
         };
 
@@ -600,8 +760,10 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                 constructor_args.event_manager,
             )?;
         }
+        // Peers can only be linked once all the network devices are restored.
+        constructor_args.vm_resources.net_builder.link_peers();
 
-        if let Some(vsock_state) = &state.vsock_device {
+        for vsock_state in &state.vsock_devices {
             let ctor_args = VsockUdsConstructorArgs {
                 cid: vsock_state.device_state.frontend.cid,
             };
@@ -613,6 +775,10 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                 },
                 &vsock_state.device_state.frontend,
             )?));
+            device
+                .lock()
+                .expect("Poisoned lock")
+                .set_id(vsock_state.device_id.clone());
 
             constructor_args
                 .vm_resources
@@ -652,6 +818,29 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             )?;
         }
 
+        if let Some(crypto_state) = &state.crypto_device {
+            let ctor_args = CryptoConstructorArgs::new(mem.clone());
+
+            let device = Arc::new(Mutex::new(Crypto::restore(
+                ctor_args,
+                &crypto_state.device_state,
+            )?));
+
+            constructor_args
+                .vm_resources
+                .update_from_restored_device(SharedDeviceType::Crypto(device.clone()))?;
+
+            restore_helper(
+                device.clone(),
+                false,
+                device,
+                &crypto_state.device_id,
+                &crypto_state.transport_state,
+                &crypto_state.device_info,
+                constructor_args.event_manager,
+            )?;
+        }
+
         Ok(dev_manager)
     }
 }
@@ -663,9 +852,11 @@ mod tests {
     use super::*;
     use crate::builder::tests::*;
     use crate::devices::virtio::block::CacheType;
+    use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
     use crate::resources::VmmConfig;
     use crate::snapshot::Snapshot;
     use crate::vmm_config::balloon::BalloonDeviceConfig;
+    use crate::vmm_config::crypto::CryptoDeviceConfig;
     use crate::vmm_config::entropy::EntropyDeviceConfig;
     use crate::vmm_config::net::NetworkInterfaceConfig;
     use crate::vmm_config::vsock::VsockDeviceConfig;
@@ -703,7 +894,7 @@ mod tests {
             self.balloon_device == other.balloon_device
                 && self.block_devices == other.block_devices
                 && self.net_devices == other.net_devices
-                && self.vsock_device == other.vsock_device
+                && self.vsock_devices == other.vsock_devices
         }
     }
 
@@ -740,8 +931,10 @@ mod tests {
         // These need to survive so the restored blocks find them.
         let _block_files;
         let mut tmp_sock_file = TempFile::new().unwrap();
+        let mut other_tmp_sock_file = TempFile::new().unwrap();
         let mut resource_allocator = ResourceAllocator::new().unwrap();
         tmp_sock_file.remove().unwrap();
+        other_tmp_sock_file.remove().unwrap();
         // Set up a vmm with one of each device, and get the serialized DeviceStates.
         let original_mmio_device_manager = {
             let mut event_manager = EventManager::new().expect("Unable to create EventManager");
@@ -766,6 +959,12 @@ mod tests {
             )];
             _block_files =
                 insert_block_devices(&mut vmm, &mut cmdline, &mut event_manager, block_configs);
+            vmm.mmio_device_manager
+                .with_virtio_device_with_id(TYPE_BLOCK, "root", |block: &mut Block| {
+                    block.set_acked_features(1 << VIRTIO_RING_F_EVENT_IDX);
+                    Ok(())
+                })
+                .unwrap();
             // Add a net device.
             let network_interface = NetworkInterfaceConfig {
                 iface_id: String::from("netif"),
@@ -773,6 +972,13 @@ mod tests {
                 guest_mac: None,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                peer: None,
+                dma_ranges: None,
+                rx_interrupt_coalescing: None,
+                tx_interrupt_coalescing: None,
+                worker: false,
+                dhcp: None,
+                xdp: None,
             };
             insert_net_device_with_mmds(
                 &mut vmm,
@@ -787,11 +993,23 @@ mod tests {
                 vsock_id: Some(vsock_dev_id.to_string()),
                 guest_cid: 3,
                 uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+                dma_ranges: None,
+            };
+            insert_vsock_device(&mut vmm, &mut cmdline, &mut event_manager, vsock_config);
+            // Add a second vsock device.
+            let vsock_config = VsockDeviceConfig {
+                vsock_id: Some(String::from("vsock1")),
+                guest_cid: 4,
+                uds_path: other_tmp_sock_file.as_path().to_str().unwrap().to_string(),
+                dma_ranges: None,
             };
             insert_vsock_device(&mut vmm, &mut cmdline, &mut event_manager, vsock_config);
             // Add an entropy device.
             let entropy_config = EntropyDeviceConfig::default();
             insert_entropy_device(&mut vmm, &mut cmdline, &mut event_manager, entropy_config);
+            // Add a crypto device.
+            let crypto_config = CryptoDeviceConfig::default();
+            insert_crypto_device(&mut vmm, &mut cmdline, &mut event_manager, crypto_config);
 
             Snapshot::serialize(&mut buf.as_mut_slice(), &vmm.mmio_device_manager.save()).unwrap();
 
@@ -799,6 +1017,7 @@ mod tests {
             vmm.mmio_device_manager.soft_clone()
         };
         tmp_sock_file.remove().unwrap();
+        other_tmp_sock_file.remove().unwrap();
 
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
         let vmm = default_vmm();
@@ -832,13 +1051,17 @@ mod tests {
       "path_on_host": "{}",
       "rate_limiter": null,
       "io_engine": "Sync",
-      "socket": null
+      "overlay_path": null,
+      "socket": null,
+      "reconnect": null,
+      "worker": false
     }}
   ],
   "boot-source": {{
     "kernel_image_path": "",
     "initrd_path": null,
-    "boot_args": null
+    "boot_args": null,
+    "crash_kernel_size_mib": null
   }},
   "cpu-config": null,
   "logger": null,
@@ -863,19 +1086,39 @@ mod tests {
       "host_dev_name": "hostname",
       "guest_mac": null,
       "rx_rate_limiter": null,
-      "tx_rate_limiter": null
+      "tx_rate_limiter": null,
+      "peer": null,
+      "dma_ranges": null,
+      "rx_interrupt_coalescing": null,
+      "tx_interrupt_coalescing": null,
+      "worker": false,
+      "dhcp": null,
+      "xdp": null
     }}
   ],
   "vsock": {{
     "guest_cid": 3,
     "uds_path": "{}"
   }},
+  "vsock-devices": [
+    {{
+      "vsock_id": "vsock1",
+      "guest_cid": 4,
+      "uds_path": "{}"
+    }}
+  ],
   "entropy": {{
+    "rate_limiter": null,
+    "deterministic_seed": null,
+    "dma_ranges": null
+  }},
+  "crypto": {{
     "rate_limiter": null
   }}
 }}"#,
             _block_files.last().unwrap().as_path().to_str().unwrap(),
-            tmp_sock_file.as_path().to_str().unwrap()
+            tmp_sock_file.as_path().to_str().unwrap(),
+            other_tmp_sock_file.as_path().to_str().unwrap()
         );
 
         assert_eq!(
@@ -888,12 +1131,39 @@ mod tests {
                 .version(),
             MmdsVersion::V2
         );
-        assert_eq!(device_states.mmds_version.unwrap(), MmdsVersion::V2.into());
+        assert_eq!(
+            device_states.mmds_version.clone().unwrap(),
+            MmdsVersion::V2.into()
+        );
 
         assert_eq!(restored_dev_manager, original_mmio_device_manager);
         assert_eq!(
             expected_vm_resources,
             serde_json::to_string_pretty(&VmmConfig::from(&*vm_resources)).unwrap()
         );
+
+        // The features negotiated by a driver cannot be denied to the restored device.
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let vmm = default_vmm();
+        let vm_resources = &mut VmResources::default();
+        vm_resources
+            .vm_config
+            .virtio_feature_policy
+            .devices
+            .insert(String::from("root"), vec![VIRTIO_RING_F_EVENT_IDX]);
+        let restore_args = MMIODevManagerConstructorArgs {
+            mem: vmm.guest_memory(),
+            vm: vmm.vm.fd(),
+            event_manager: &mut event_manager,
+            resource_allocator: &mut resource_allocator,
+            vm_resources,
+            instance_id: "microvm-id",
+        };
+        assert!(matches!(
+            MMIODeviceManager::restore(restore_args, &device_states),
+            Err(DevicePersistError::DeniedVirtioFeatures(id, features))
+                if id == "root" && features == 1 << VIRTIO_RING_F_EVENT_IDX
+        ));
     }
 }
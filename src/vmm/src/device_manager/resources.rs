@@ -5,6 +5,9 @@ pub use vm_allocator::AllocPolicy;
 use vm_allocator::{AddressAllocator, IdAllocator};
 
 use crate::arch;
+use crate::device_manager::mmio::MMIO_LEN;
+use crate::vmm_config::machine_config::MemoryLayoutConfig;
+use crate::vstate::memory::{GuestMemory, GuestMemoryMmap};
 
 /// A resource manager for (de)allocating interrupt lines (GSIs) and guest memory
 ///
@@ -13,12 +16,15 @@ use crate::arch;
 /// * GSIs for legacy x86_64 devices
 /// * GSIs for MMIO devicecs
 /// * Memory allocations in the MMIO address space
+/// * Memory allocations in the 64-bit MMIO address space, if the memory layout has one
 #[derive(Debug)]
 pub struct ResourceAllocator {
     // Allocator for device interrupt lines
     gsi_allocator: IdAllocator,
     // Allocator for memory in the MMIO address space
     mmio_memory: AddressAllocator,
+    // Allocator for memory in the 64-bit MMIO address space above guest memory
+    mmio64_memory: Option<AddressAllocator>,
     // Memory allocator for system data
     #[cfg(target_arch = "x86_64")]
     system_memory: AddressAllocator,
@@ -27,9 +33,29 @@ pub struct ResourceAllocator {
 impl ResourceAllocator {
     /// Create a new resource allocator for Firecracker devices
     pub fn new() -> Result<Self, vm_allocator::Error> {
+        Self::with_mmio_windows((arch::MMIO_MEM_START, arch::MMIO_MEM_SIZE), None)
+    }
+
+    /// Create a new resource allocator for Firecracker devices, handing out the MMIO windows of
+    /// `memory_layout` around `guest_memory`
+    pub fn with_memory_layout(
+        memory_layout: &MemoryLayoutConfig,
+        guest_memory: &GuestMemoryMmap,
+    ) -> Result<Self, vm_allocator::Error> {
+        let (mmio, mmio64) = arch::mmio_windows(memory_layout, guest_memory.last_addr());
+        Self::with_mmio_windows(mmio, mmio64)
+    }
+
+    fn with_mmio_windows(
+        (mmio_start, mmio_size): (u64, u64),
+        mmio64: Option<(u64, u64)>,
+    ) -> Result<Self, vm_allocator::Error> {
         Ok(Self {
             gsi_allocator: IdAllocator::new(arch::IRQ_BASE, arch::IRQ_MAX)?,
-            mmio_memory: AddressAllocator::new(arch::MMIO_MEM_START, arch::MMIO_MEM_SIZE)?,
+            mmio_memory: AddressAllocator::new(mmio_start, mmio_size)?,
+            mmio64_memory: mmio64
+                .map(|(start, size)| AddressAllocator::new(start, size))
+                .transpose()?,
             #[cfg(target_arch = "x86_64")]
             system_memory: AddressAllocator::new(arch::SYSTEM_MEM_START, arch::SYSTEM_MEM_SIZE)?,
         })
@@ -77,6 +103,29 @@ impl ResourceAllocator {
         Ok(self.mmio_memory.allocate(size, alignment, policy)?.start())
     }
 
+    /// Allocate a memory range in the 64-bit MMIO address space
+    ///
+    /// If it succeeds, it returns the first address of the allocated range. Fails if the memory
+    /// layout has no 64-bit MMIO window.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - The size in bytes of the memory to allocate
+    /// * `alignment` - The alignment of the address of the first byte
+    /// * `policy` - A [`vm_allocator::AllocPolicy`] variant for determining the allocation policy
+    pub fn allocate_mmio64_memory(
+        &mut self,
+        size: u64,
+        alignment: u64,
+        policy: AllocPolicy,
+    ) -> Result<u64, vm_allocator::Error> {
+        let mmio64_memory = self
+            .mmio64_memory
+            .as_mut()
+            .ok_or(vm_allocator::Error::ResourceNotAvailable)?;
+        Ok(mmio64_memory.allocate(size, alignment, policy)?.start())
+    }
+
     /// Allocate a memory range for system data
     ///
     /// If it succeeds, it returns the first address of the allocated range
@@ -100,12 +149,37 @@ impl ResourceAllocator {
     }
 }
 
+/// Interrupt lines and MMIO address space needed by the devices of a microVM
+///
+/// It is computed from the device list before the microVM is built, so that a configuration
+/// needing more resources than available fails before any of them is allocated.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceRequirements {
+    /// Number of interrupt lines
+    pub gsis: u32,
+    /// Size in bytes of the MMIO address space below 4 GiB
+    pub mmio: u64,
+    /// Size in bytes of the 64-bit MMIO address space
+    pub mmio64: u64,
+}
+
+impl ResourceRequirements {
+    /// Number of interrupt lines available to the devices
+    pub const MAX_GSIS: u32 = arch::IRQ_MAX - arch::IRQ_BASE + 1;
+
+    /// Accounts for a device registered on the MMIO bus, using `irq_count` interrupt lines
+    pub fn add_mmio_device(&mut self, irq_count: u32) {
+        self.gsis += irq_count;
+        self.mmio += MMIO_LEN;
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::ResourceAllocator;
+    use super::{AllocPolicy, ResourceAllocator, ResourceRequirements};
     use crate::arch;
 
-    const MAX_IRQS: u32 = arch::IRQ_MAX - arch::IRQ_BASE + 1;
+    const MAX_IRQS: u32 = ResourceRequirements::MAX_GSIS;
 
     #[test]
     fn test_allocate_gsi() {
@@ -145,4 +219,42 @@ mod tests {
             assert_eq!(allocator.allocate_gsi(1), Ok(vec![i]));
         }
     }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_allocate_mmio_memory_with_layout() {
+        use crate::utilities::test_utils::arch_mem;
+        use crate::vmm_config::machine_config::MemoryLayoutConfig;
+
+        let guest_memory = arch_mem(128 << 20);
+        let mut allocator = ResourceAllocator::new().unwrap();
+        assert_eq!(
+            allocator.allocate_mmio_memory(0x1000, 0x1000, AllocPolicy::FirstMatch),
+            Ok(arch::MMIO_MEM_START)
+        );
+        assert_eq!(
+            allocator.allocate_mmio64_memory(0x1000, 0x1000, AllocPolicy::FirstMatch),
+            Err(vm_allocator::Error::ResourceNotAvailable)
+        );
+
+        let layout = MemoryLayoutConfig {
+            mmio_gap_start: 2 << 30,
+            mmio_gap_size: 1 << 30,
+            mmio64_size: 1 << 30,
+            ipa_size: None,
+        };
+        let mut allocator = ResourceAllocator::with_memory_layout(&layout, &guest_memory).unwrap();
+        assert_eq!(
+            allocator.allocate_mmio_memory(0x1000, 0x1000, AllocPolicy::FirstMatch),
+            Ok(2 << 30)
+        );
+        assert_eq!(
+            allocator.allocate_mmio64_memory(1 << 30, 1 << 30, AllocPolicy::FirstMatch),
+            Ok(4 << 30)
+        );
+        assert_eq!(
+            allocator.allocate_mmio64_memory(0x1000, 0x1000, AllocPolicy::FirstMatch),
+            Err(vm_allocator::Error::ResourceNotAvailable)
+        );
+    }
 }
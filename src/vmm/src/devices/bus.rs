@@ -11,11 +11,47 @@ use std::cmp::{Ord, Ordering, PartialEq, PartialOrd};
 use std::collections::btree_map::BTreeMap;
 use std::sync::{Arc, Mutex};
 
+use serde::Serialize;
+
+use super::virtio::mmio::DeviceActivation;
+
 /// Errors triggered during bus operations.
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 pub enum BusError {
-    /// New device overlaps with an old device.
-    Overlap,
+    /// New device has an empty address range.
+    EmptyRange,
+    /// New device overlaps with device {device} registered at {base:#x} (length {len:#x}).
+    Overlap {
+        /// Base address of the conflicting device.
+        base: u64,
+        /// Length of the address range of the conflicting device.
+        len: u64,
+        /// Name of the conflicting device.
+        device: String,
+    },
+}
+
+/// Address range registered on a [`Bus`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BusRegion {
+    /// Base address of the range.
+    pub base: u64,
+    /// Length of the range.
+    pub len: u64,
+    /// Name of the device owning the range.
+    pub device: String,
+    /// Activation status of the device, for virtio devices.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub activation: Option<DeviceActivation>,
+}
+
+/// Address ranges of the devices registered on the guest buses.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DeviceRegions {
+    /// Ranges registered on the MMIO bus.
+    pub mmio: Vec<BusRegion>,
+    /// Ranges registered on the port IO bus. Always empty on aarch64.
+    pub pio: Vec<BusRegion>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -56,7 +92,10 @@ use event_manager::{EventOps, Events, MutEventSubscriber};
 use super::legacy::RTCDevice;
 use super::legacy::{I8042Device, SerialDevice};
 use super::pseudo::BootTimer;
+use super::tpm::TpmCrb;
 use super::virtio::mmio::MmioTransport;
+use super::virtio::vsock::TYPE_VSOCK;
+use super::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_CRYPTO, TYPE_NET, TYPE_RNG};
 
 #[derive(Debug)]
 pub enum BusDevice {
@@ -66,6 +105,7 @@ pub enum BusDevice {
     BootTimer(BootTimer),
     MmioTransport(MmioTransport),
     Serial(SerialDevice<std::io::Stdin>),
+    Tpm(TpmCrb),
     #[cfg(test)]
     Dummy(DummyDevice),
     #[cfg(test)]
@@ -133,6 +173,12 @@ impl BusDevice {
             _ => None,
         }
     }
+    pub fn tpm_ref(&self) -> Option<&TpmCrb> {
+        match self {
+            Self::Tpm(x) => Some(x),
+            _ => None,
+        }
+    }
 
     pub fn i8042_device_mut(&mut self) -> Option<&mut I8042Device> {
         match self {
@@ -165,6 +211,40 @@ impl BusDevice {
             _ => None,
         }
     }
+    pub fn tpm_mut(&mut self) -> Option<&mut TpmCrb> {
+        match self {
+            Self::Tpm(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// Returns a short name describing the device.
+    pub fn name(&self) -> String {
+        match self {
+            Self::I8042Device(_) => "i8042".to_string(),
+            #[cfg(target_arch = "aarch64")]
+            Self::RTCDevice(_) => "rtc".to_string(),
+            Self::BootTimer(_) => "boot_timer".to_string(),
+            Self::MmioTransport(x) => {
+                let device_type = x.locked_device().device_type();
+                match device_type {
+                    TYPE_NET => "virtio-net".to_string(),
+                    TYPE_BLOCK => "virtio-block".to_string(),
+                    TYPE_RNG => "virtio-rng".to_string(),
+                    TYPE_BALLOON => "virtio-balloon".to_string(),
+                    TYPE_VSOCK => "virtio-vsock".to_string(),
+                    TYPE_CRYPTO => "virtio-crypto".to_string(),
+                    _ => format!("virtio-{device_type}"),
+                }
+            }
+            Self::Serial(_) => "serial".to_string(),
+            Self::Tpm(_) => "tpm".to_string(),
+            #[cfg(test)]
+            Self::Dummy(_) => "dummy".to_string(),
+            #[cfg(test)]
+            Self::Constant(_) => "constant".to_string(),
+        }
+    }
 
     pub fn read(&mut self, offset: u64, data: &mut [u8]) {
         match self {
@@ -174,6 +254,7 @@ impl BusDevice {
             Self::BootTimer(x) => x.bus_read(offset, data),
             Self::MmioTransport(x) => x.bus_read(offset, data),
             Self::Serial(x) => x.bus_read(offset, data),
+            Self::Tpm(x) => x.bus_read(offset, data),
             #[cfg(test)]
             Self::Dummy(x) => x.bus_read(offset, data),
             #[cfg(test)]
@@ -189,6 +270,7 @@ impl BusDevice {
             Self::BootTimer(x) => x.bus_write(offset, data),
             Self::MmioTransport(x) => x.bus_write(offset, data),
             Self::Serial(x) => x.bus_write(offset, data),
+            Self::Tpm(x) => x.bus_write(offset, data),
             #[cfg(test)]
             Self::Dummy(x) => x.bus_write(offset, data),
             #[cfg(test)]
@@ -201,12 +283,14 @@ impl MutEventSubscriber for BusDevice {
     fn process(&mut self, event: Events, ops: &mut EventOps) {
         match self {
             Self::Serial(serial) => serial.process(event, ops),
+            Self::Tpm(tpm) => tpm.process(event, ops),
             _ => panic!(),
         }
     }
     fn init(&mut self, ops: &mut EventOps) {
         match self {
             Self::Serial(serial) => serial.init(ops),
+            Self::Tpm(tpm) => tpm.init(ops),
             _ => panic!(),
         }
     }
@@ -249,33 +333,52 @@ impl Bus {
         len: u64,
     ) -> Result<(), BusError> {
         if len == 0 {
-            return Err(BusError::Overlap);
+            return Err(BusError::EmptyRange);
         }
 
         // Reject all cases where the new device's base is within an old device's range.
-        if self.get_device(base).is_some() {
-            return Err(BusError::Overlap);
+        if let Some((range, dev)) = self.first_before(base).filter(|(r, _)| base - r.0 < r.1) {
+            return Err(Self::overlap_error(range, dev));
         }
 
         // The above check will miss an overlap in which the new device's base address is before the
         // range of another device. To catch that case, we search for a device with a range before
         // the new device's range's end. If there is no existing device in that range that starts
         // after the new device, then there will be no overlap.
-        if let Some((BusRange(start, _), _)) = self.first_before(base + len - 1) {
+        if let Some((range, dev)) = self.first_before(base + len - 1) {
             // Such a device only conflicts with the new device if it also starts after the new
-            // device because of our initial `get_device` check above.
-            if start >= base {
-                return Err(BusError::Overlap);
+            // device because of our initial check above.
+            if range.0 >= base {
+                return Err(Self::overlap_error(range, dev));
             }
         }
 
-        if self.devices.insert(BusRange(base, len), device).is_some() {
-            return Err(BusError::Overlap);
-        }
+        self.devices.insert(BusRange(base, len), device);
 
         Ok(())
     }
 
+    fn overlap_error(range: BusRange, device: &Mutex<BusDevice>) -> BusError {
+        BusError::Overlap {
+            base: range.0,
+            len: range.1,
+            device: device.lock().expect("Poisoned lock").name(),
+        }
+    }
+
+    /// Returns the address ranges registered on the bus, sorted by base address.
+    pub fn regions(&self) -> Vec<BusRegion> {
+        self.devices
+            .iter()
+            .map(|(range, device)| BusRegion {
+                base: range.0,
+                len: range.1,
+                device: device.lock().expect("Poisoned lock").name(),
+                activation: None,
+            })
+            .collect()
+    }
+
     /// Reads data from the device that owns the range containing `addr` and puts it into `data`.
     ///
     /// Returns true on success, otherwise `data` is untouched.
@@ -316,12 +419,20 @@ mod tests {
         let mut bus = Bus::new();
         let dummy = Arc::new(Mutex::new(BusDevice::Dummy(DummyDevice)));
         // Insert len should not be 0.
-        bus.insert(dummy.clone(), 0x10, 0).unwrap_err();
+        let result = bus.insert(dummy.clone(), 0x10, 0);
+        assert!(matches!(result, Err(BusError::EmptyRange)), "{:?}", result);
         bus.insert(dummy.clone(), 0x10, 0x10).unwrap();
 
         let result = bus.insert(dummy.clone(), 0x0f, 0x10);
         // This overlaps the address space of the existing bus device at 0x10.
-        assert!(matches!(result, Err(BusError::Overlap)), "{:?}", result);
+        assert!(
+            matches!(
+                result,
+                Err(BusError::Overlap { base: 0x10, len: 0x10, ref device }) if device == "dummy"
+            ),
+            "{:?}",
+            result
+        );
 
         // This overlaps the address space of the existing bus device at 0x10.
         bus.insert(dummy.clone(), 0x10, 0x10).unwrap_err();
@@ -338,6 +449,37 @@ mod tests {
         bus.insert(dummy, 0x0, 0x10).unwrap();
     }
 
+    #[test]
+    fn bus_regions() {
+        let mut bus = Bus::new();
+        assert!(bus.regions().is_empty());
+
+        let dummy = Arc::new(Mutex::new(BusDevice::Dummy(DummyDevice)));
+        let constant = Arc::new(Mutex::new(BusDevice::Constant(ConstantDevice)));
+        bus.insert(constant.clone(), 0x20, 0x8).unwrap();
+        bus.insert(dummy, 0x10, 0x10).unwrap();
+        // Failed inserts do not show up in the listing.
+        bus.insert(constant, 0x1f, 0x2).unwrap_err();
+
+        assert_eq!(
+            bus.regions(),
+            vec![
+                BusRegion {
+                    base: 0x10,
+                    len: 0x10,
+                    device: "dummy".to_string(),
+                    activation: None,
+                },
+                BusRegion {
+                    base: 0x20,
+                    len: 0x8,
+                    device: "constant".to_string(),
+                    activation: None,
+                },
+            ]
+        );
+    }
+
     #[test]
     fn bus_read_write() {
         let mut bus = Bus::new();
@@ -397,8 +539,19 @@ mod tests {
     #[test]
     fn test_display_error() {
         assert_eq!(
-            format!("{}", BusError::Overlap),
-            "New device overlaps with an old device."
+            format!("{}", BusError::EmptyRange),
+            "New device has an empty address range."
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                BusError::Overlap {
+                    base: 0x3f8,
+                    len: 0x8,
+                    device: "serial".to_string(),
+                }
+            ),
+            "New device overlaps with device serial registered at 0x3f8 (length 0x8)."
         );
     }
 }
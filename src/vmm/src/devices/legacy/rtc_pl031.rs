@@ -1,13 +1,17 @@
 // Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::convert::TryInto;
+use std::convert::{Infallible, TryInto};
 
 use serde::Serialize;
 use vm_superio::rtc_pl031::RtcEvents;
 
+use crate::devices::replay::{self, ReplaySource};
 use crate::logger::{warn, IncMetric, SharedIncMetric};
 
+// Offset of the data register, holding the current time.
+const RTCDR_OFFSET: u16 = 0x000;
+
 /// Metrics specific to the RTC device.
 #[derive(Debug, Serialize)]
 pub struct RTCDeviceMetrics {
@@ -81,7 +85,16 @@ impl RTCDevice {
         if let (Ok(offset), 4) = (u16::try_from(offset), data.len()) {
             // read() function from RTC implementation expects a slice of
             // len 4, and we just validated that this is the data lengt
-            self.read(offset, data.try_into().unwrap())
+            if offset == RTCDR_OFFSET {
+                // The time read by the guest is an input of the replay log.
+                replay::replay_or_fill(&ReplaySource::Clock, data, |data| {
+                    self.read(offset, data.try_into().unwrap());
+                    Ok::<_, Infallible>(())
+                })
+                .unwrap();
+            } else {
+                self.read(offset, data.try_into().unwrap())
+            }
         } else {
             warn!(
                 "Found invalid data offset/length while trying to read from the RTC: {}, {}",
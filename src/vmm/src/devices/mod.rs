@@ -14,9 +14,12 @@ pub mod acpi;
 pub mod bus;
 pub mod legacy;
 pub mod pseudo;
+pub mod replay;
+pub mod timer;
+pub mod tpm;
 pub mod virtio;
 
-pub use bus::{Bus, BusDevice, BusError};
+pub use bus::{Bus, BusDevice, BusError, BusRegion, DeviceRegions};
 use log::error;
 
 use crate::devices::virtio::net::metrics::NetDeviceMetrics;
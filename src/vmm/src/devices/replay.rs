@@ -0,0 +1,371 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Record and replay of the nondeterministic inputs of the devices.
+//!
+//! In record mode, the frames read by the network devices from their taps, the random bytes handed
+//! to the guest (by the entropy device, or as the boot seed of the kernel) and the wall clock times
+//! read by the guest from the RTC are appended to a log, along with the time elapsed since the
+//! microVM was built. In replay mode, the devices take these inputs from the log instead of the
+//! host, in the order they were recorded, and each frame is received by the network device no
+//! earlier than during the recording. Frames sent by the guest to the taps are dropped.
+//!
+//! The vCPU is not stopped at the same instruction as during the recording when an input is
+//! delivered, so the replay only reproduces the guest behaviours that depend on the contents and
+//! the order of the inputs. This is also why only microVMs with a single vCPU are supported.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use utils::time::{get_time_us, ClockType};
+
+use crate::logger::{error, warn};
+use crate::vmm_config::replay::{ReplayConfig, ReplayMode};
+
+/// Inputs of the devices that are recorded and replayed.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum ReplaySource {
+    /// Frames read from the tap of the network device with this ID.
+    NetRx(String),
+    /// Random bytes of the entropy device and of the boot seed.
+    Entropy,
+    /// Values of the data register of the RTC.
+    Clock,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ReplayEvent<'a> {
+    // Time of the input, in microseconds since the microVM was built.
+    timestamp_us: u64,
+    source: Cow<'a, ReplaySource>,
+    data: Cow<'a, [u8]>,
+}
+
+/// Errors associated with recording and replaying the inputs of the devices.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum ReplayError {
+    /// Recording or replaying the inputs requires a microVM with a single vCPU.
+    MultipleVcpus,
+    /// Recording or replaying the inputs is not supported by worker devices: {0}
+    WorkerDevice(String),
+    /// Cannot open the replay log: {0}
+    OpenLog(io::Error),
+    /// Cannot parse the replay log: {0}
+    ParseLog(String),
+    /// The inputs are already recorded or replayed.
+    AlreadyStarted,
+}
+
+#[derive(Debug)]
+enum ReplayLog {
+    Record(File),
+    Replay {
+        events: HashMap<ReplaySource, VecDeque<ReplayEvent<'static>>>,
+        // Whether the guest was already reported to diverge from the recording.
+        diverged: bool,
+    },
+}
+
+/// Records the inputs of the devices to a log, or replays them from it.
+#[derive(Debug)]
+pub struct Replayer {
+    mode: ReplayMode,
+    start_us: u64,
+    log: Mutex<ReplayLog>,
+}
+
+impl Replayer {
+    /// Creates the log in record mode, or reads it in replay mode.
+    pub fn new(config: &ReplayConfig) -> Result<Self, ReplayError> {
+        let log = match config.mode {
+            ReplayMode::Record => ReplayLog::Record(
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(&config.log_path)
+                    .map_err(ReplayError::OpenLog)?,
+            ),
+            ReplayMode::Replay => {
+                let bytes = std::fs::read(&config.log_path).map_err(ReplayError::OpenLog)?;
+                let mut reader = bytes.as_slice();
+                let mut events: HashMap<_, VecDeque<_>> = HashMap::new();
+                while !reader.is_empty() {
+                    let event: ReplayEvent = bincode::DefaultOptions::new()
+                        .with_limit(bytes.len() as u64)
+                        .with_fixint_encoding()
+                        .allow_trailing_bytes()
+                        .deserialize_from(&mut reader)
+                        .map_err(|err| ReplayError::ParseLog(err.to_string()))?;
+                    events
+                        .entry(event.source.clone().into_owned())
+                        .or_default()
+                        .push_back(event);
+                }
+                ReplayLog::Replay {
+                    events,
+                    diverged: false,
+                }
+            }
+        };
+
+        Ok(Replayer {
+            mode: config.mode,
+            start_us: get_time_us(ClockType::Monotonic),
+            log: Mutex::new(log),
+        })
+    }
+
+    fn elapsed_us(&self) -> u64 {
+        get_time_us(ClockType::Monotonic).saturating_sub(self.start_us)
+    }
+
+    fn record(&self, source: &ReplaySource, data: &[u8]) {
+        let mut log = self.log.lock().expect("Poisoned lock");
+        let ReplayLog::Record(file) = &mut *log else {
+            return;
+        };
+        let event = ReplayEvent {
+            timestamp_us: self.elapsed_us(),
+            source: Cow::Borrowed(source),
+            data: Cow::Borrowed(data),
+        };
+        // Each event is written at once, so that the log is usable even if Firecracker crashes.
+        let res = bincode::serialize(&event)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+            .and_then(|bytes| file.write_all(&bytes));
+        if let Err(err) = res {
+            error!("replay: cannot write to the log: {}", err);
+        }
+    }
+
+    // Pops the next recorded input of `source`. With `elapsed_us`, the input is only popped if it
+    // was received no later than `elapsed_us` after the microVM was built.
+    fn pop_input(&self, source: &ReplaySource, elapsed_us: Option<u64>) -> Option<Vec<u8>> {
+        let mut log = self.log.lock().expect("Poisoned lock");
+        let ReplayLog::Replay { events, .. } = &mut *log else {
+            return None;
+        };
+        let queue = events.get_mut(source)?;
+        let front_us = queue.front()?.timestamp_us;
+        if elapsed_us.is_some_and(|elapsed_us| front_us > elapsed_us) {
+            return None;
+        }
+        queue.pop_front().map(|event| event.data.into_owned())
+    }
+
+    fn report_divergence(&self, source: &ReplaySource) {
+        let mut log = self.log.lock().expect("Poisoned lock");
+        if let ReplayLog::Replay { diverged, .. } = &mut *log {
+            if !*diverged {
+                *diverged = true;
+                warn!(
+                    "replay: the guest diverged from the recording on {:?}, the missing inputs \
+                     are taken from the host",
+                    source
+                );
+            }
+        }
+    }
+
+    fn replay_or_fill<E>(
+        &self,
+        source: &ReplaySource,
+        buf: &mut [u8],
+        fill: impl FnOnce(&mut [u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        match self.mode {
+            ReplayMode::Record => {
+                fill(buf)?;
+                self.record(source, buf);
+                Ok(())
+            }
+            ReplayMode::Replay => match self.pop_input(source, None) {
+                Some(data) if data.len() == buf.len() => {
+                    buf.copy_from_slice(&data);
+                    Ok(())
+                }
+                _ => {
+                    self.report_divergence(source);
+                    fill(buf)
+                }
+            },
+        }
+    }
+
+    fn next_due_input(&self, source: &ReplaySource) -> Option<Vec<u8>> {
+        self.pop_input(source, Some(self.elapsed_us()))
+    }
+
+    fn time_to_next_input(&self, source: &ReplaySource) -> Option<Duration> {
+        let log = self.log.lock().expect("Poisoned lock");
+        let ReplayLog::Replay { events, .. } = &*log else {
+            return None;
+        };
+        let event = events.get(source)?.front()?;
+        Some(Duration::from_micros(
+            event.timestamp_us.saturating_sub(self.elapsed_us()),
+        ))
+    }
+}
+
+static REPLAYER: OnceLock<Replayer> = OnceLock::new();
+
+/// Starts recording or replaying the inputs of the devices, for the rest of the process lifetime.
+pub fn start(config: &ReplayConfig) -> Result<(), ReplayError> {
+    REPLAYER
+        .set(Replayer::new(config)?)
+        .map_err(|_| ReplayError::AlreadyStarted)
+}
+
+/// Returns whether the inputs of the devices are recorded.
+pub fn is_recording() -> bool {
+    REPLAYER
+        .get()
+        .is_some_and(|replayer| replayer.mode == ReplayMode::Record)
+}
+
+/// Returns whether the inputs of the devices are replayed.
+pub fn is_replaying() -> bool {
+    REPLAYER
+        .get()
+        .is_some_and(|replayer| replayer.mode == ReplayMode::Replay)
+}
+
+/// Appends an input taken from the host to the log, if the inputs are recorded.
+pub fn record(source: &ReplaySource, data: &[u8]) {
+    if let Some(replayer) = REPLAYER.get() {
+        replayer.record(source, data);
+    }
+}
+
+/// Fills `buf` with the next recorded input of `source` if the inputs are replayed, or with
+/// `fill` otherwise. The input is recorded if the inputs are recorded.
+///
+/// `fill` is also used when replaying if the next recorded input is missing or has another size,
+/// which means that the guest diverged from the recording.
+pub fn replay_or_fill<E>(
+    source: &ReplaySource,
+    buf: &mut [u8],
+    fill: impl FnOnce(&mut [u8]) -> Result<(), E>,
+) -> Result<(), E> {
+    match REPLAYER.get() {
+        Some(replayer) => replayer.replay_or_fill(source, buf, fill),
+        None => fill(buf),
+    }
+}
+
+/// Pops the next recorded input of `source` if the inputs are replayed and it is due, which is
+/// when as much time as during the recording elapsed since the microVM was built.
+pub fn next_due_input(source: &ReplaySource) -> Option<Vec<u8>> {
+    REPLAYER
+        .get()
+        .and_then(|replayer| replayer.next_due_input(source))
+}
+
+/// Returns the time left until the next recorded input of `source` is due, if the inputs are
+/// replayed and there is one.
+pub fn time_to_next_input(source: &ReplaySource) -> Option<Duration> {
+    REPLAYER
+        .get()
+        .and_then(|replayer| replayer.time_to_next_input(source))
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_record_and_replay() {
+        let log = TempFile::new().unwrap();
+        let mut config = ReplayConfig {
+            mode: ReplayMode::Record,
+            log_path: log.as_path().to_path_buf(),
+        };
+        let eth0 = ReplaySource::NetRx("eth0".to_string());
+
+        let recorder = Replayer::new(&config).unwrap();
+        let mut seed = [0u8; 4];
+        recorder
+            .replay_or_fill(&ReplaySource::Entropy, &mut seed, |buf| {
+                buf.fill(1);
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+        assert_eq!(seed, [1; 4]);
+        recorder.record(&eth0, &[2; 60]);
+        recorder.record(&ReplaySource::Clock, &[3; 4]);
+        recorder.record(&eth0, &[4; 60]);
+        drop(recorder);
+
+        config.mode = ReplayMode::Replay;
+        let mut replayer = Replayer::new(&config).unwrap();
+        replayer
+            .replay_or_fill(&ReplaySource::Entropy, &mut seed, |_| Err(()))
+            .unwrap();
+        assert_eq!(seed, [1; 4]);
+        // The clock reads do not have to be due.
+        let mut time = [0u8; 4];
+        replayer
+            .replay_or_fill(&ReplaySource::Clock, &mut time, |_| Err(()))
+            .unwrap();
+        assert_eq!(time, [3; 4]);
+
+        // The frames are due once as much time as during the recording elapsed.
+        replayer.start_us = get_time_us(ClockType::Monotonic) + 1_000_000;
+        assert!(replayer.time_to_next_input(&eth0).unwrap() > Duration::ZERO);
+        assert_eq!(replayer.next_due_input(&eth0), None);
+        replayer.start_us = 0;
+        assert_eq!(replayer.time_to_next_input(&eth0), Some(Duration::ZERO));
+        assert_eq!(replayer.next_due_input(&eth0), Some(vec![2; 60]));
+        assert_eq!(replayer.next_due_input(&eth0), Some(vec![4; 60]));
+        assert_eq!(replayer.next_due_input(&eth0), None);
+        assert_eq!(replayer.time_to_next_input(&eth0), None);
+
+        // The host inputs are used once the guest diverged from the recording.
+        replayer
+            .replay_or_fill(&ReplaySource::Entropy, &mut seed, |buf| {
+                buf.fill(5);
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+        assert_eq!(seed, [5; 4]);
+        replayer.record(&eth0, &[6; 60]);
+        assert_eq!(replayer.next_due_input(&eth0), None);
+    }
+
+    #[test]
+    fn test_replay_log_errors() {
+        let log = TempFile::new().unwrap();
+        let mut config = ReplayConfig {
+            mode: ReplayMode::Replay,
+            log_path: log.as_path().join("missing"),
+        };
+        assert!(matches!(
+            Replayer::new(&config),
+            Err(ReplayError::OpenLog(_))
+        ));
+
+        config.mode = ReplayMode::Record;
+        config.log_path = log.as_path().to_path_buf();
+        Replayer::new(&config)
+            .unwrap()
+            .record(&ReplaySource::Clock, &[0; 4]);
+        let len = log.as_file().metadata().unwrap().len();
+        log.as_file().set_len(len - 1).unwrap();
+
+        config.mode = ReplayMode::Replay;
+        assert!(matches!(
+            Replayer::new(&config),
+            Err(ReplayError::ParseLog(_))
+        ));
+    }
+}
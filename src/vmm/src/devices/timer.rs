@@ -0,0 +1,161 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Timer for the devices that have to do some work after a delay or periodically.
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use event_manager::{EventOps, Events};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::EventSet;
+
+/// Monotonic timer of a device, which becomes readable in the event loop when it expires.
+///
+/// The device registers the timer with the event manager through `register()`, and calls
+/// `read_expirations()` when the timer event is received.
+#[derive(Debug)]
+pub struct DeviceTimer {
+    timer_fd: TimerFd,
+}
+
+impl DeviceTimer {
+    /// Creates a disarmed timer.
+    pub fn new() -> io::Result<Self> {
+        Ok(DeviceTimer {
+            timer_fd: TimerFd::new_custom(ClockId::Monotonic, true, true)?,
+        })
+    }
+
+    /// Arms the timer to expire once, after `delay`. A zero `delay` disarms the timer.
+    pub fn arm_oneshot(&mut self, delay: Duration) {
+        self.timer_fd
+            .set_state(TimerState::Oneshot(delay), SetTimeFlags::Default);
+    }
+
+    /// Arms the timer to expire every `interval`, starting one `interval` from now. A zero
+    /// `interval` disarms the timer.
+    pub fn arm_periodic(&mut self, interval: Duration) {
+        self.timer_fd.set_state(
+            TimerState::Periodic {
+                current: interval,
+                interval,
+            },
+            SetTimeFlags::Default,
+        );
+    }
+
+    /// Disarms the timer. Expirations that were not read yet are discarded.
+    pub fn disarm(&mut self) {
+        self.timer_fd
+            .set_state(TimerState::Disarmed, SetTimeFlags::Default);
+    }
+
+    /// Returns whether the timer is going to expire.
+    pub fn is_armed(&self) -> bool {
+        self.timer_fd.get_state() != TimerState::Disarmed
+    }
+
+    /// Consumes the expirations of the timer, and returns their number since the last call. Zero
+    /// is returned if the timer did not expire.
+    pub fn read_expirations(&self) -> u64 {
+        self.timer_fd.read()
+    }
+
+    /// Registers the timer with the event manager, so that the subscriber receives an event with
+    /// `data` when the timer expires.
+    pub fn register(&self, ops: &mut EventOps, data: u32) -> Result<(), event_manager::Error> {
+        ops.add(Events::with_data(&self.timer_fd, data, EventSet::IN))
+    }
+
+    /// Stops the subscriber from receiving the events of the timer registered with `data`.
+    pub fn unregister(&self, ops: &mut EventOps, data: u32) -> Result<(), event_manager::Error> {
+        ops.remove(Events::with_data(&self.timer_fd, data, EventSet::IN))
+    }
+}
+
+impl AsRawFd for DeviceTimer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.timer_fd.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use event_manager::{EventManager, MutEventSubscriber, SubscriberOps};
+
+    use super::*;
+
+    #[test]
+    fn test_arm_and_disarm() {
+        let mut timer = DeviceTimer::new().unwrap();
+        assert!(!timer.is_armed());
+        assert_eq!(timer.read_expirations(), 0);
+
+        timer.arm_oneshot(Duration::from_millis(10));
+        assert!(timer.is_armed());
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!timer.is_armed());
+        assert_eq!(timer.read_expirations(), 1);
+        assert_eq!(timer.read_expirations(), 0);
+
+        timer.arm_periodic(Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(35));
+        assert!(timer.is_armed());
+        assert!(timer.read_expirations() >= 3);
+
+        timer.disarm();
+        assert!(!timer.is_armed());
+        assert_eq!(timer.read_expirations(), 0);
+
+        timer.arm_periodic(Duration::ZERO);
+        assert!(!timer.is_armed());
+    }
+
+    #[derive(Debug)]
+    struct TimerSubscriber {
+        timer: DeviceTimer,
+        expirations: u64,
+    }
+
+    impl TimerSubscriber {
+        const PROCESS_TIMER: u32 = 7;
+    }
+
+    impl MutEventSubscriber for TimerSubscriber {
+        fn process(&mut self, event: Events, ops: &mut EventOps) {
+            assert_eq!(event.data(), Self::PROCESS_TIMER);
+            self.expirations += self.timer.read_expirations();
+            if self.expirations >= 2 {
+                self.timer.unregister(ops, Self::PROCESS_TIMER).unwrap();
+            }
+        }
+
+        fn init(&mut self, ops: &mut EventOps) {
+            self.timer.register(ops, Self::PROCESS_TIMER).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_event_manager() {
+        let mut event_manager = EventManager::<Arc<Mutex<dyn MutEventSubscriber>>>::new().unwrap();
+        let mut timer = DeviceTimer::new().unwrap();
+        timer.arm_periodic(Duration::from_millis(10));
+        let subscriber = Arc::new(Mutex::new(TimerSubscriber {
+            timer,
+            expirations: 0,
+        }));
+        event_manager.add_subscriber(subscriber.clone());
+
+        while subscriber.lock().unwrap().expirations < 2 {
+            assert_eq!(event_manager.run_with_timeout(1000).unwrap(), 1);
+        }
+        // The timer keeps expiring, but the subscriber does not receive its events anymore.
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 0);
+        assert!(subscriber.lock().unwrap().timer.read_expirations() > 0);
+    }
+}
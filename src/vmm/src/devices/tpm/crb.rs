@@ -0,0 +1,607 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use log::{error, warn};
+use utils::epoll::EventSet;
+use utils::eventfd::EventFd;
+
+use super::metrics::METRICS;
+use super::{message_size, TpmBackend, TpmError, TPM_HEADER_SIZE};
+use crate::devices::timer::DeviceTimer;
+use crate::logger::IncMetric;
+
+/// Offset of the control area in the MMIO region, following the locality registers.
+pub const TPM_CRB_CTRL_AREA_OFFSET: u64 = 0x40;
+
+// Register offsets, as defined by the TCG PC Client Platform TPM Profile specification.
+const CRB_LOC_STATE: u64 = 0x00;
+const CRB_LOC_CTRL: u64 = 0x08;
+const CRB_LOC_STS: u64 = 0x0C;
+const CRB_INTF_ID: u64 = 0x30;
+const CRB_CTRL_REQ: u64 = 0x40;
+const CRB_CTRL_STS: u64 = 0x44;
+const CRB_CTRL_CANCEL: u64 = 0x48;
+const CRB_CTRL_START: u64 = 0x4C;
+const CRB_INT_ENABLE: u64 = 0x50;
+const CRB_INT_STS: u64 = 0x54;
+const CRB_CTRL_CMD_SIZE: u64 = 0x58;
+const CRB_CTRL_CMD_LADDR: u64 = 0x5C;
+const CRB_CTRL_CMD_HADDR: u64 = 0x60;
+const CRB_CTRL_RSP_SIZE: u64 = 0x64;
+const CRB_CTRL_RSP_ADDR: u64 = 0x68;
+const CRB_CTRL_RSP_ADDR_HIGH: u64 = 0x6C;
+const CRB_DATA_BUFFER: u64 = 0x80;
+
+/// Size of the command and response buffer, which fills the rest of the 4 KiB MMIO region of
+/// locality 0, the only one implemented.
+const CRB_DATA_BUFFER_SIZE: usize = 0xF80;
+
+const LOC_STATE_LOC_ASSIGNED: u32 = 1 << 1;
+const LOC_STATE_REG_VALID_STS: u32 = 1 << 7;
+const LOC_CTRL_REQUEST_ACCESS: u32 = 1 << 0;
+const LOC_CTRL_RELINQUISH: u32 = 1 << 1;
+const LOC_STS_GRANTED: u32 = 1 << 0;
+const CTRL_REQ_CMD_READY: u32 = 1 << 0;
+const CTRL_REQ_GO_IDLE: u32 = 1 << 1;
+const CTRL_STS_TPM_IDLE: u32 = 1 << 1;
+const CTRL_START_INVOKE: u32 = 1 << 0;
+
+/// Interface identifier: an active CRB interface of version 1, transferring up to 64 bytes per
+/// access and without support for other localities.
+const INTF_ID: u32 = 0x1 | 0x1 << 4 | 0x3 << 11 | 0x1 << 14 | 0x1 << 17;
+
+/// Response code of a TPM which failed to execute a command.
+const TPM_RC_FAILURE: u32 = 0x101;
+/// Tag of a response without sessions.
+const TPM_ST_NO_SESSIONS: u16 = 0x8001;
+/// Time after which a command the backend did not answer fails.
+const TPM_COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// TPM 2.0 device with the Command Response Buffer interface.
+///
+/// The guest writes a command in the data buffer and sets the start register, which signals
+/// the start event. The command is then sent to the backend from the VMM thread, whose event loop
+/// receives the response when the backend connection is readable, so that a slow emulator blocks
+/// neither the VMM thread nor the vCPUs. The start register is cleared once the response is in the
+/// data buffer, or once the command failed because it was not answered in time. The guest polls
+/// the start register, as the device has no interrupt.
+#[derive(Debug)]
+pub struct TpmCrb {
+    base: u64,
+    loc_state: u32,
+    loc_sts: u32,
+    ctrl_sts: u32,
+    ctrl_cancel: u32,
+    ctrl_start: u32,
+    int_enable: u32,
+    int_sts: u32,
+    data: Vec<u8>,
+    start_evt: EventFd,
+    backend: Box<dyn TpmBackend>,
+    // Connection of the backend registered with the event manager.
+    backend_fd: Option<RawFd>,
+    // Set while a command sent to the backend waits for its response.
+    in_flight: bool,
+    timer: DeviceTimer,
+    timeout: Duration,
+}
+
+impl TpmCrb {
+    /// Creates an idle TPM whose MMIO region is at guest address `base`.
+    pub fn new(base: u64, backend: Box<dyn TpmBackend>) -> Result<Self, TpmError> {
+        Ok(Self {
+            base,
+            loc_state: LOC_STATE_REG_VALID_STS,
+            loc_sts: 0,
+            ctrl_sts: CTRL_STS_TPM_IDLE,
+            ctrl_cancel: 0,
+            ctrl_start: 0,
+            int_enable: 0,
+            int_sts: 0,
+            data: vec![0; CRB_DATA_BUFFER_SIZE],
+            start_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(TpmError::EventFd)?,
+            backend,
+            backend_fd: None,
+            in_flight: false,
+            timer: DeviceTimer::new().map_err(TpmError::Timer)?,
+            timeout: TPM_COMMAND_TIMEOUT,
+        })
+    }
+
+    /// Returns the value of the control register at `offset`.
+    fn register(&self, offset: u64) -> u32 {
+        let data_buffer = self.base + CRB_DATA_BUFFER;
+        let (low, high) = (data_buffer & 0xffff_ffff, data_buffer >> 32);
+        match offset {
+            CRB_LOC_STATE => self.loc_state,
+            CRB_LOC_STS => self.loc_sts,
+            CRB_INTF_ID => INTF_ID,
+            CRB_CTRL_STS => self.ctrl_sts,
+            CRB_CTRL_CANCEL => self.ctrl_cancel,
+            CRB_CTRL_START => self.ctrl_start,
+            CRB_INT_ENABLE => self.int_enable,
+            CRB_INT_STS => self.int_sts,
+            CRB_CTRL_CMD_SIZE | CRB_CTRL_RSP_SIZE => u32::try_from(CRB_DATA_BUFFER_SIZE).unwrap(),
+            // The command and response buffers are the same data buffer.
+            CRB_CTRL_CMD_LADDR | CRB_CTRL_RSP_ADDR => u32::try_from(low).unwrap(),
+            CRB_CTRL_CMD_HADDR | CRB_CTRL_RSP_ADDR_HIGH => u32::try_from(high).unwrap(),
+            _ => 0,
+        }
+    }
+
+    pub fn bus_read(&mut self, offset: u64, data: &mut [u8]) {
+        for (offset, byte) in (offset..).zip(data.iter_mut()) {
+            *byte = match offset {
+                CRB_DATA_BUFFER.. => self
+                    .data
+                    .get(usize::try_from(offset - CRB_DATA_BUFFER).unwrap())
+                    .copied()
+                    .unwrap_or_default(),
+                _ => {
+                    let register = self.register(offset & !0x3);
+                    register.to_le_bytes()[usize::try_from(offset & 0x3).unwrap()]
+                }
+            };
+        }
+    }
+
+    pub fn bus_write(&mut self, offset: u64, data: &[u8]) {
+        if offset >= CRB_DATA_BUFFER {
+            let start = usize::try_from(offset - CRB_DATA_BUFFER).unwrap();
+            match self.data.get_mut(start..start + data.len()) {
+                Some(buffer) => buffer.copy_from_slice(data),
+                None => METRICS.invalid_access_count.inc(),
+            }
+            return;
+        }
+
+        // Control registers are only written with aligned 32 bits accesses.
+        if data.len() != 4 || offset & 0x3 != 0 {
+            warn!(
+                "tpm: Unsupported write of {} bytes at {offset:#x}",
+                data.len()
+            );
+            METRICS.invalid_access_count.inc();
+            return;
+        }
+        let value = u32::from_le_bytes(data.try_into().unwrap());
+        match offset {
+            CRB_LOC_CTRL => {
+                if value & LOC_CTRL_RELINQUISH != 0 {
+                    self.loc_state &= !LOC_STATE_LOC_ASSIGNED;
+                    self.loc_sts &= !LOC_STS_GRANTED;
+                }
+                if value & LOC_CTRL_REQUEST_ACCESS != 0 {
+                    self.loc_state |= LOC_STATE_LOC_ASSIGNED;
+                    self.loc_sts |= LOC_STS_GRANTED;
+                }
+            }
+            CRB_CTRL_REQ => {
+                // Requests complete immediately, so the register always reads as zero.
+                if value & CTRL_REQ_CMD_READY != 0 {
+                    self.ctrl_sts &= !CTRL_STS_TPM_IDLE;
+                }
+                if value & CTRL_REQ_GO_IDLE != 0 {
+                    self.ctrl_sts |= CTRL_STS_TPM_IDLE;
+                }
+            }
+            // Commands cannot be canceled, the register is only kept for the guest to read.
+            CRB_CTRL_CANCEL => self.ctrl_cancel = value,
+            CRB_CTRL_START
+                if value & CTRL_START_INVOKE != 0 && self.ctrl_start & CTRL_START_INVOKE == 0 =>
+            {
+                self.ctrl_start = CTRL_START_INVOKE;
+                if let Err(err) = self.start_evt.write(1) {
+                    error!("tpm: Failed to signal the start event: {err}");
+                    METRICS.event_fails.inc();
+                    self.complete_command(Self::failure_response());
+                }
+            }
+            CRB_INT_ENABLE => self.int_enable = value,
+            CRB_INT_STS => self.int_sts &= !value,
+            _ => {}
+        }
+    }
+
+    /// Response of a TPM which failed to execute a command.
+    fn failure_response() -> Vec<u8> {
+        let mut response = Vec::with_capacity(TPM_HEADER_SIZE);
+        response.extend_from_slice(&TPM_ST_NO_SESSIONS.to_be_bytes());
+        response.extend_from_slice(&u32::try_from(TPM_HEADER_SIZE).unwrap().to_be_bytes());
+        response.extend_from_slice(&TPM_RC_FAILURE.to_be_bytes());
+        response
+    }
+
+    /// Writes `response` in the data buffer and signals the guest that the command completed.
+    fn complete_command(&mut self, response: Vec<u8>) {
+        self.data[..response.len()].copy_from_slice(&response);
+        self.ctrl_start = 0;
+        self.in_flight = false;
+        self.timer.disarm();
+    }
+
+    fn fail_command(&mut self) {
+        METRICS.command_fails.inc();
+        self.complete_command(Self::failure_response());
+    }
+
+    /// Registers the connection of the backend with the event manager, if not done yet.
+    fn watch_backend(&mut self, ops: &mut EventOps) {
+        if self.backend_fd.is_some() {
+            return;
+        }
+        if let Some(fd) = self.backend.fd() {
+            match ops.add(Events::new_raw(fd, EventSet::IN)) {
+                Ok(()) => self.backend_fd = Some(fd),
+                Err(err) => {
+                    error!("tpm: Failed to register the backend connection: {err}");
+                    METRICS.event_fails.inc();
+                }
+            }
+        }
+    }
+
+    /// Drops the connection of the backend, after removing it from the event manager.
+    fn reset_backend(&mut self, ops: &mut EventOps) {
+        if let Some(fd) = self.backend_fd.take() {
+            if let Err(err) = ops.remove(Events::new_raw(fd, EventSet::IN)) {
+                error!("tpm: Failed to unregister the backend connection: {err}");
+                METRICS.event_fails.inc();
+            }
+        }
+        self.backend.reset();
+    }
+
+    /// Sends the command in the data buffer to the backend, if the guest started one.
+    fn start_command(&mut self, ops: &mut EventOps) {
+        if self.ctrl_start & CTRL_START_INVOKE == 0 || self.in_flight {
+            return;
+        }
+
+        METRICS.command_count.inc();
+        match message_size(&self.data) {
+            Some(size) if (TPM_HEADER_SIZE..=CRB_DATA_BUFFER_SIZE).contains(&size) => {
+                if let Err(err) = self.backend.send(&self.data[..size]) {
+                    error!("tpm: Failed to send command: {err}");
+                    METRICS.backend_fails.inc();
+                    self.reset_backend(ops);
+                    self.fail_command();
+                    return;
+                }
+            }
+            size => {
+                warn!("tpm: Invalid command size: {size:?}");
+                self.fail_command();
+                return;
+            }
+        }
+        self.watch_backend(ops);
+        self.in_flight = true;
+        self.timer.arm_oneshot(self.timeout);
+    }
+
+    /// Receives the response to the command in flight, once the backend has all of it.
+    fn receive_response(&mut self, ops: &mut EventOps) {
+        if !self.in_flight {
+            // No response is expected, the emulator closed the connection or is misbehaving.
+            warn!("tpm: Unexpected event on the backend connection");
+            self.reset_backend(ops);
+            return;
+        }
+
+        match self.backend.receive() {
+            Ok(None) => {}
+            Ok(Some(response)) if response.len() <= CRB_DATA_BUFFER_SIZE => {
+                self.complete_command(response)
+            }
+            Ok(Some(response)) => {
+                error!("tpm: Response of {} bytes is too large", response.len());
+                self.fail_command();
+            }
+            Err(err) => {
+                error!("tpm: Failed to receive the response: {err}");
+                METRICS.backend_fails.inc();
+                self.reset_backend(ops);
+                self.fail_command();
+            }
+        }
+    }
+
+    /// Fails the command in flight if the backend did not answer it in time.
+    fn process_timeout(&mut self, ops: &mut EventOps) {
+        self.timer.read_expirations();
+        if self.in_flight {
+            error!("tpm: The backend did not answer the command in time");
+            METRICS.backend_fails.inc();
+            self.reset_backend(ops);
+            self.fail_command();
+        }
+    }
+}
+
+impl MutEventSubscriber for TpmCrb {
+    fn process(&mut self, events: Events, ops: &mut EventOps) {
+        let fd = events.fd();
+        let event_set = events.event_set();
+        if Some(fd) == self.backend_fd {
+            // Hang ups are handled like readable data, the receive reports them.
+            self.receive_response(ops);
+        } else if !event_set.contains(EventSet::IN) {
+            warn!("tpm: Received unknown event: {event_set:?}");
+        } else if fd == self.start_evt.as_raw_fd() {
+            if let Err(err) = self.start_evt.read() {
+                error!("tpm: Failed to consume the start event: {err}");
+                METRICS.event_fails.inc();
+            }
+            self.start_command(ops);
+        } else if fd == self.timer.as_raw_fd() {
+            self.process_timeout(ops);
+        } else {
+            warn!("tpm: Received unknown event: {event_set:?}");
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(&self.start_evt, EventSet::IN)) {
+            error!("tpm: Failed to register the start event: {err}");
+        }
+        if let Err(err) = ops.add(Events::new(&self.timer, EventSet::IN)) {
+            error!("tpm: Failed to register the command timer: {err}");
+        }
+        self.watch_backend(ops);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use event_manager::{EventManager, SubscriberOps};
+
+    use super::*;
+
+    const BASE: u64 = 0xd000_0000;
+
+    // Answers the commands with `response`, signaling `ready` when it can be received. Sending
+    // fails without a response.
+    #[derive(Debug)]
+    struct MockBackend {
+        commands: Arc<Mutex<Vec<Vec<u8>>>>,
+        resets: Arc<AtomicUsize>,
+        response: Option<Vec<u8>>,
+        // Whether the commands are answered, or left waiting for the timeout.
+        answer: bool,
+        ready: EventFd,
+    }
+
+    impl MockBackend {
+        fn new(response: Option<Vec<u8>>) -> Self {
+            MockBackend {
+                commands: Arc::default(),
+                resets: Arc::default(),
+                response,
+                answer: true,
+                ready: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            }
+        }
+    }
+
+    impl TpmBackend for MockBackend {
+        fn send(&mut self, command: &[u8]) -> Result<(), TpmError> {
+            self.commands.lock().unwrap().push(command.to_vec());
+            if self.response.is_none() {
+                return Err(TpmError::Send(std::io::Error::from_raw_os_error(
+                    libc::EPIPE,
+                )));
+            }
+            if self.answer {
+                self.ready.write(1).unwrap();
+            }
+            Ok(())
+        }
+
+        fn receive(&mut self) -> Result<Option<Vec<u8>>, TpmError> {
+            self.ready.read().unwrap();
+            Ok(self.response.clone())
+        }
+
+        fn fd(&self) -> Option<RawFd> {
+            Some(self.ready.as_raw_fd())
+        }
+
+        fn reset(&mut self) {
+            self.resets.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn read_u32(tpm: &mut TpmCrb, offset: u64) -> u32 {
+        let mut data = [0u8; 4];
+        tpm.bus_read(offset, &mut data);
+        u32::from_le_bytes(data)
+    }
+
+    fn write_u32(tpm: &mut TpmCrb, offset: u64, value: u32) {
+        tpm.bus_write(offset, &value.to_le_bytes());
+    }
+
+    fn event_manager(tpm: &Arc<Mutex<TpmCrb>>) -> EventManager<Arc<Mutex<dyn MutEventSubscriber>>> {
+        let mut event_manager: EventManager<Arc<Mutex<dyn MutEventSubscriber>>> =
+            EventManager::new().unwrap();
+        event_manager.add_subscriber(tpm.clone());
+        event_manager
+    }
+
+    // Writes `command` in the data buffer, starts it and runs the event loop until it completes.
+    fn run_command(
+        tpm: &Arc<Mutex<TpmCrb>>,
+        event_manager: &mut EventManager<Arc<Mutex<dyn MutEventSubscriber>>>,
+        command: &[u8],
+    ) -> Vec<u8> {
+        {
+            let mut tpm = tpm.lock().unwrap();
+            tpm.bus_write(CRB_DATA_BUFFER, command);
+            write_u32(&mut tpm, CRB_CTRL_START, CTRL_START_INVOKE);
+            assert_eq!(read_u32(&mut tpm, CRB_CTRL_START), CTRL_START_INVOKE);
+        }
+        while read_u32(&mut tpm.lock().unwrap(), CRB_CTRL_START) != 0 {
+            assert!(event_manager.run_with_timeout(1000).unwrap() > 0);
+        }
+
+        let mut tpm = tpm.lock().unwrap();
+        let mut header = [0u8; TPM_HEADER_SIZE];
+        tpm.bus_read(CRB_DATA_BUFFER, &mut header);
+        let mut response = vec![0u8; message_size(&header).unwrap()];
+        tpm.bus_read(CRB_DATA_BUFFER, &mut response);
+        response
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut tpm = TpmCrb::new(BASE, Box::new(MockBackend::new(None))).unwrap();
+
+        assert_eq!(read_u32(&mut tpm, CRB_INTF_ID), 0x25811);
+        assert_eq!(read_u32(&mut tpm, CRB_CTRL_CMD_SIZE), 0xf80);
+        assert_eq!(read_u32(&mut tpm, CRB_CTRL_CMD_LADDR), 0xd000_0080);
+        assert_eq!(read_u32(&mut tpm, CRB_CTRL_CMD_LADDR + 4), 0);
+        let mut rsp_addr = [0u8; 8];
+        tpm.bus_read(CRB_CTRL_RSP_ADDR, &mut rsp_addr);
+        assert_eq!(u64::from_le_bytes(rsp_addr), 0xd000_0080);
+
+        // Locality 0 is granted on request.
+        assert_eq!(read_u32(&mut tpm, CRB_LOC_STATE), LOC_STATE_REG_VALID_STS);
+        write_u32(&mut tpm, CRB_LOC_CTRL, LOC_CTRL_REQUEST_ACCESS);
+        assert_eq!(
+            read_u32(&mut tpm, CRB_LOC_STATE),
+            LOC_STATE_REG_VALID_STS | LOC_STATE_LOC_ASSIGNED
+        );
+        assert_eq!(read_u32(&mut tpm, CRB_LOC_STS), LOC_STS_GRANTED);
+        write_u32(&mut tpm, CRB_LOC_CTRL, LOC_CTRL_RELINQUISH);
+        assert_eq!(read_u32(&mut tpm, CRB_LOC_STATE), LOC_STATE_REG_VALID_STS);
+        assert_eq!(read_u32(&mut tpm, CRB_LOC_STS), 0);
+
+        // Idle state transitions complete immediately.
+        assert_eq!(read_u32(&mut tpm, CRB_CTRL_STS), CTRL_STS_TPM_IDLE);
+        write_u32(&mut tpm, CRB_CTRL_REQ, CTRL_REQ_CMD_READY);
+        assert_eq!(read_u32(&mut tpm, CRB_CTRL_REQ), 0);
+        assert_eq!(read_u32(&mut tpm, CRB_CTRL_STS), 0);
+        write_u32(&mut tpm, CRB_CTRL_REQ, CTRL_REQ_GO_IDLE);
+        assert_eq!(read_u32(&mut tpm, CRB_CTRL_STS), CTRL_STS_TPM_IDLE);
+
+        // Unaligned and partial register writes are ignored.
+        let invalid_accesses = METRICS.invalid_access_count.count();
+        tpm.bus_write(CRB_INT_ENABLE, &[1]);
+        tpm.bus_write(CRB_INT_ENABLE + 1, &[1, 0, 0, 0]);
+        assert_eq!(read_u32(&mut tpm, CRB_INT_ENABLE), 0);
+        assert!(METRICS.invalid_access_count.count() >= invalid_accesses + 2);
+        write_u32(&mut tpm, CRB_INT_ENABLE, 1);
+        assert_eq!(read_u32(&mut tpm, CRB_INT_ENABLE), 1);
+
+        // Writes past the data buffer are ignored.
+        tpm.bus_write(0x1000 - 2, &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_command() {
+        let backend = MockBackend::new(Some(vec![0x80, 0x01, 0, 0, 0, 12, 0, 0, 0, 0, 0xab, 0xcd]));
+        let commands = backend.commands.clone();
+        let resets = backend.resets.clone();
+        let tpm = Arc::new(Mutex::new(TpmCrb::new(BASE, Box::new(backend)).unwrap()));
+        let mut event_manager = event_manager(&tpm);
+
+        // The command is read up to its size, ignoring the rest of the buffer.
+        let mut command = vec![0x80, 0x01, 0, 0, 0, 12, 0, 0, 0x01, 0x7b, 0, 8];
+        command.extend_from_slice(&[0xff; 4]);
+        assert_eq!(
+            run_command(&tpm, &mut event_manager, &command),
+            vec![0x80, 0x01, 0, 0, 0, 12, 0, 0, 0, 0, 0xab, 0xcd]
+        );
+        assert_eq!(*commands.lock().unwrap(), vec![command[..12].to_vec()]);
+        assert!(!tpm.lock().unwrap().timer.is_armed());
+
+        // No command is sent without the start register set.
+        tpm.lock().unwrap().start_evt.write(1).unwrap();
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 1);
+        assert_eq!(commands.lock().unwrap().len(), 1);
+
+        // The connection becoming readable without a command in flight resets it.
+        let fd = tpm.lock().unwrap().backend_fd.unwrap();
+        // SAFETY: The file descriptor is the eventfd of the mock backend.
+        let written = unsafe { libc::write(fd, 1u64.to_ne_bytes().as_ptr().cast(), 8) };
+        assert_eq!(written, 8);
+        assert_eq!(event_manager.run_with_timeout(0).unwrap(), 1);
+        assert_eq!(resets.load(Ordering::SeqCst), 1);
+        assert!(tpm.lock().unwrap().backend_fd.is_none());
+    }
+
+    #[test]
+    fn test_command_failure() {
+        let backend = MockBackend::new(None);
+        let commands = backend.commands.clone();
+        let resets = backend.resets.clone();
+        let tpm = Arc::new(Mutex::new(TpmCrb::new(BASE, Box::new(backend)).unwrap()));
+        let mut event_manager = event_manager(&tpm);
+        let failure = TpmCrb::failure_response();
+        assert_eq!(failure, vec![0x80, 0x01, 0, 0, 0, 10, 0, 0, 0x01, 0x01]);
+
+        // The backend failed, and is reset.
+        assert_eq!(
+            run_command(
+                &tpm,
+                &mut event_manager,
+                &[0x80, 0x01, 0, 0, 0, 10, 0, 0, 0x01, 0x7b]
+            ),
+            failure
+        );
+        assert_eq!(commands.lock().unwrap().len(), 1);
+        assert_eq!(resets.load(Ordering::SeqCst), 1);
+
+        // Commands with an invalid size are not forwarded to the backend.
+        assert_eq!(
+            run_command(
+                &tpm,
+                &mut event_manager,
+                &[0x80, 0x01, 0, 0, 0, 9, 0, 0, 0x01, 0x7b]
+            ),
+            failure
+        );
+        assert_eq!(
+            run_command(
+                &tpm,
+                &mut event_manager,
+                &[0x80, 0x01, 0, 0, 0x10, 0, 0, 0, 0x01, 0x7b]
+            ),
+            failure
+        );
+        assert_eq!(commands.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_command_timeout() {
+        let mut backend = MockBackend::new(Some(vec![0x80, 0x01, 0, 0, 0, 10, 0, 0, 0, 0]));
+        backend.answer = false;
+        let resets = backend.resets.clone();
+        let mut tpm = TpmCrb::new(BASE, Box::new(backend)).unwrap();
+        tpm.timeout = Duration::from_millis(10);
+        let tpm = Arc::new(Mutex::new(tpm));
+        let mut event_manager = event_manager(&tpm);
+
+        // The backend never answers, the command fails once the timer expires.
+        assert_eq!(
+            run_command(
+                &tpm,
+                &mut event_manager,
+                &[0x80, 0x01, 0, 0, 0, 10, 0, 0, 0x01, 0x7b]
+            ),
+            TpmCrb::failure_response()
+        );
+        assert_eq!(resets.load(Ordering::SeqCst), 1);
+        let tpm = tpm.lock().unwrap();
+        assert!(!tpm.in_flight);
+        assert!(tpm.backend_fd.is_none());
+    }
+}
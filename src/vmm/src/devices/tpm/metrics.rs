@@ -0,0 +1,82 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the metrics system for the TPM device.
+//!
+//! # Metrics format
+//! The metrics are flushed in JSON when requested by vmm::logger::metrics::METRICS.write().
+//!
+//! ## JSON example with metrics:
+//! ```json
+//!  "tpm": {
+//!     "command_count": "SharedIncMetric",
+//!     "command_fails": "SharedIncMetric",
+//!     "backend_fails": "SharedIncMetric",
+//!     ...
+//!  }
+//! }
+//! ```
+//! Each `tpm` field in the example above is a serializable `TpmDeviceMetrics` structure
+//! collecting metrics such as `command_count`, `backend_fails` etc. for the TPM device.
+//! Since there is at most one TPM device, `tpm` represents the aggregate TPM metrics.
+
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+
+use crate::logger::SharedIncMetric;
+
+/// Stores aggregated TPM metrics
+pub(super) static METRICS: TpmDeviceMetrics = TpmDeviceMetrics::new();
+
+/// Called by METRICS.flush(), this function facilitates serialization of TPM device metrics.
+pub fn flush_metrics<S: Serializer>(serializer: S) -> Result<S::Ok, S::Error> {
+    let mut seq = serializer.serialize_map(Some(1))?;
+    seq.serialize_entry("tpm", &METRICS)?;
+    seq.end()
+}
+
+#[derive(Debug, Serialize)]
+pub(super) struct TpmDeviceMetrics {
+    /// Number of commands executed
+    pub command_count: SharedIncMetric,
+    /// Number of commands answered with a failure because they were malformed or the TPM
+    /// emulator could not execute them
+    pub command_fails: SharedIncMetric,
+    /// Number of errors while communicating with the TPM emulator
+    pub backend_fails: SharedIncMetric,
+    /// Number of unsupported register accesses
+    pub invalid_access_count: SharedIncMetric,
+    /// Number of start event handling failures
+    pub event_fails: SharedIncMetric,
+}
+impl TpmDeviceMetrics {
+    /// Const default construction.
+    const fn new() -> Self {
+        Self {
+            command_count: SharedIncMetric::new(),
+            command_fails: SharedIncMetric::new(),
+            backend_fails: SharedIncMetric::new(),
+            invalid_access_count: SharedIncMetric::new(),
+            event_fails: SharedIncMetric::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::logger::IncMetric;
+
+    #[test]
+    fn test_tpm_dev_metrics() {
+        let tpm_metrics: TpmDeviceMetrics = TpmDeviceMetrics::new();
+        let tpm_metrics_local: String = serde_json::to_string(&tpm_metrics).unwrap();
+        // the 1st serialize flushes the metrics and resets values to 0 so that
+        // we can compare the values with local metrics.
+        serde_json::to_string(&METRICS).unwrap();
+        let tpm_metrics_global: String = serde_json::to_string(&METRICS).unwrap();
+        assert_eq!(tpm_metrics_local, tpm_metrics_global);
+        tpm_metrics.command_count.inc();
+        assert_eq!(tpm_metrics.command_count.count(), 1);
+    }
+}
@@ -0,0 +1,60 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements a TPM 2.0 device with the Command Response Buffer (CRB) interface, forwarding the
+//! commands of the guest to a TPM emulator on the host.
+
+mod crb;
+pub mod metrics;
+mod swtpm;
+
+use std::fmt::Debug;
+use std::io;
+use std::os::unix::io::RawFd;
+
+pub use self::crb::{TpmCrb, TPM_CRB_CTRL_AREA_OFFSET};
+pub use self::swtpm::SwtpmBackend;
+
+/// Size of the header of TPM commands and responses: a 2 bytes tag, a 4 bytes size and a 4 bytes
+/// command or response code, all big endian.
+pub const TPM_HEADER_SIZE: usize = 10;
+
+/// Errors of the TPM device.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum TpmError {
+    /// Cannot connect to the TPM emulator: {0}
+    Connect(io::Error),
+    /// Cannot send the command to the TPM emulator: {0}
+    Send(io::Error),
+    /// Cannot receive the response of the TPM emulator: {0}
+    Receive(io::Error),
+    /// Invalid response size of the TPM emulator: {0}
+    ResponseSize(usize),
+    /// Cannot create the start event: {0}
+    EventFd(io::Error),
+    /// Cannot create the command timer: {0}
+    Timer(io::Error),
+}
+
+/// Executes the TPM commands of the guest without blocking the event loop.
+///
+/// A command is sent with `send()`, then `receive()` is called each time the file descriptor
+/// returned by `fd()` is readable, until it returns the response. After an error, `reset()` must be
+/// called before sending the next command.
+pub trait TpmBackend: Debug + Send {
+    /// Sends `command` to the TPM.
+    fn send(&mut self, command: &[u8]) -> Result<(), TpmError>;
+    /// Reads the available part of the response to the last command, and returns the response
+    /// once it is complete.
+    fn receive(&mut self) -> Result<Option<Vec<u8>>, TpmError>;
+    /// Returns the file descriptor which becomes readable when a response can be received.
+    fn fd(&self) -> Option<RawFd>;
+    /// Drops the connection to the TPM along with the command in flight, if any.
+    fn reset(&mut self);
+}
+
+/// Returns the size of the TPM command or response starting with `header`.
+fn message_size(header: &[u8]) -> Option<usize> {
+    let size = header.get(2..6)?;
+    usize::try_from(u32::from_be_bytes(size.try_into().unwrap())).ok()
+}
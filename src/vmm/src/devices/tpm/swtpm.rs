@@ -0,0 +1,251 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+use super::{message_size, TpmBackend, TpmError, TPM_HEADER_SIZE};
+
+/// Largest response accepted from the TPM emulator.
+const MAX_RESPONSE_SIZE: usize = 4096;
+
+/// Backend forwarding the TPM commands to the data socket of a `swtpm` process.
+///
+/// `swtpm` must be started without a control channel, e.g. with
+/// `swtpm socket --tpm2 --server type=unixio,path=<socket> --flags not-need-init`.
+/// The socket is nonblocking, the response is read as it arrives. The connection is reopened
+/// after a reset, so that the device recovers from a restarted emulator.
+#[derive(Debug)]
+pub struct SwtpmBackend {
+    path: PathBuf,
+    stream: Option<UnixStream>,
+    // Part of the response received so far.
+    response: Vec<u8>,
+}
+
+impl SwtpmBackend {
+    /// Connects to the `swtpm` data socket at `path`.
+    pub fn new(path: PathBuf) -> Result<Self, TpmError> {
+        let stream = Self::connect(&path)?;
+        Ok(Self {
+            path,
+            stream: Some(stream),
+            response: Vec::new(),
+        })
+    }
+
+    fn connect(path: &Path) -> Result<UnixStream, TpmError> {
+        let stream = UnixStream::connect(path).map_err(TpmError::Connect)?;
+        stream.set_nonblocking(true).map_err(TpmError::Connect)?;
+        Ok(stream)
+    }
+
+    // Size of the response, or of its header while it is not fully received.
+    fn expected_size(response: &[u8]) -> Result<usize, TpmError> {
+        if response.len() < TPM_HEADER_SIZE {
+            return Ok(TPM_HEADER_SIZE);
+        }
+        let size = message_size(response).unwrap_or_default();
+        if !(TPM_HEADER_SIZE..=MAX_RESPONSE_SIZE).contains(&size) {
+            return Err(TpmError::ResponseSize(size));
+        }
+        Ok(size)
+    }
+}
+
+impl TpmBackend for SwtpmBackend {
+    fn send(&mut self, command: &[u8]) -> Result<(), TpmError> {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => self.stream.insert(Self::connect(&self.path)?),
+        };
+        self.response.clear();
+        // Commands are smaller than the socket buffer, so they are not expected to block.
+        stream.write_all(command).map_err(TpmError::Send)
+    }
+
+    fn receive(&mut self) -> Result<Option<Vec<u8>>, TpmError> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Err(TpmError::Receive(io::Error::from(
+                io::ErrorKind::NotConnected,
+            )));
+        };
+        loop {
+            let size = Self::expected_size(&self.response)?;
+            if self.response.len() == size {
+                return Ok(Some(std::mem::take(&mut self.response)));
+            }
+
+            let len = self.response.len();
+            self.response.resize(size, 0);
+            match stream.read(&mut self.response[len..]) {
+                Ok(0) => {
+                    self.response.truncate(len);
+                    return Err(TpmError::Receive(io::Error::from(
+                        io::ErrorKind::UnexpectedEof,
+                    )));
+                }
+                Ok(count) => self.response.truncate(len + count),
+                Err(err) => {
+                    self.response.truncate(len);
+                    match err.kind() {
+                        io::ErrorKind::WouldBlock => return Ok(None),
+                        io::ErrorKind::Interrupted => {}
+                        _ => return Err(TpmError::Receive(err)),
+                    }
+                }
+            }
+        }
+    }
+
+    fn fd(&self) -> Option<RawFd> {
+        self.stream.as_ref().map(AsRawFd::as_raw_fd)
+    }
+
+    fn reset(&mut self) {
+        if self.stream.take().is_some() {
+            // The stream may hold part of a message, start over with a new connection.
+            warn!("tpm: Dropping the connection to swtpm");
+        }
+        self.response.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use utils::tempdir::TempDir;
+
+    use super::*;
+
+    // Answers each command with a response echoing its code, and closes the connection after
+    // `count` commands.
+    fn serve(listener: UnixListener, count: usize) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            for _ in 0..count {
+                let mut command = [0u8; TPM_HEADER_SIZE];
+                stream.read_exact(&mut command).unwrap();
+                let mut response = vec![0x80, 0x01, 0, 0, 0, 12, 0, 0, 0, 0];
+                response.extend_from_slice(&command[8..10]);
+                stream.write_all(&response).unwrap();
+            }
+        })
+    }
+
+    // Sends `command` and polls the backend until the response is received.
+    fn execute(backend: &mut SwtpmBackend, command: &[u8]) -> Result<Vec<u8>, TpmError> {
+        backend.send(command)?;
+        loop {
+            if let Some(response) = backend.receive()? {
+                return Ok(response);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_swtpm_backend() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("swtpm.sock");
+        assert!(matches!(
+            SwtpmBackend::new(path.clone()),
+            Err(TpmError::Connect(_))
+        ));
+
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = serve(listener.try_clone().unwrap(), 1);
+        let mut backend = SwtpmBackend::new(path).unwrap();
+        assert!(backend.fd().is_some());
+        let command = [0x80, 0x01, 0, 0, 0, 10, 0, 0, 0x01, 0x7b];
+        assert_eq!(
+            execute(&mut backend, &command).unwrap(),
+            vec![0x80, 0x01, 0, 0, 0, 12, 0, 0, 0, 0, 0x01, 0x7b]
+        );
+        server.join().unwrap();
+
+        // The server closed the connection, which is reopened on the next command after a reset.
+        execute(&mut backend, &command).unwrap_err();
+        backend.reset();
+        assert!(backend.fd().is_none());
+        assert!(matches!(
+            backend.receive(),
+            Err(TpmError::Receive(err)) if err.kind() == io::ErrorKind::NotConnected
+        ));
+        let server = serve(listener, 1);
+        assert_eq!(execute(&mut backend, &command).unwrap().len(), 12);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_swtpm_partial_response() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("swtpm.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let (tx, rx) = mpsc::channel();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut command = [0u8; TPM_HEADER_SIZE];
+            stream.read_exact(&mut command).unwrap();
+            let response = [0x80, 0x01, 0, 0, 0, 12, 0, 0, 0, 0, 0xab, 0xcd];
+            stream.write_all(&response[..5]).unwrap();
+            rx.recv().unwrap();
+            stream.write_all(&response[5..]).unwrap();
+        });
+
+        let mut backend = SwtpmBackend::new(path).unwrap();
+        backend
+            .send(&[0x80, 0x01, 0, 0, 0, 10, 0, 0, 0x01, 0x7b])
+            .unwrap();
+        // The receive does not block while the response is incomplete.
+        while backend.response.len() < 5 {
+            assert!(backend.receive().unwrap().is_none());
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(backend.receive().unwrap().is_none());
+
+        tx.send(()).unwrap();
+        let response = loop {
+            if let Some(response) = backend.receive().unwrap() {
+                break response;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(
+            response,
+            vec![0x80, 0x01, 0, 0, 0, 12, 0, 0, 0, 0, 0xab, 0xcd]
+        );
+        assert!(backend.response.is_empty());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_swtpm_bad_response_size() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("swtpm.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut command = [0u8; TPM_HEADER_SIZE];
+            stream.read_exact(&mut command).unwrap();
+            stream
+                .write_all(&[0x80, 0x01, 0, 0x01, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+        });
+
+        let mut backend = SwtpmBackend::new(path).unwrap();
+        assert!(matches!(
+            execute(&mut backend, &[0x80, 0x01, 0, 0, 0, 10, 0, 0, 0x01, 0x7b]),
+            Err(TpmError::ResponseSize(0x1_0000))
+        ));
+        server.join().unwrap();
+    }
+}
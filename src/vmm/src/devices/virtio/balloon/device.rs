@@ -1,35 +1,38 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::fmt;
+use std::collections::VecDeque;
+use std::fs::File;
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
 use std::time::Duration;
 
 use log::error;
 use serde::Serialize;
-use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 use utils::eventfd::EventFd;
+use utils::time::{get_time_us, ClockType};
 use utils::u64_to_usize;
 
 use super::super::device::{DeviceState, VirtioDevice};
 use super::super::queue::Queue;
 use super::super::{ActivateError, TYPE_BALLOON};
 use super::metrics::METRICS;
-use super::util::{compact_page_frame_numbers, remove_range};
+use super::util::{compact_page_frame_numbers, ranges_overlap, remove_range};
 use super::{
-    BALLOON_DEV_ID, BALLOON_NUM_QUEUES, BALLOON_QUEUE_SIZES, DEFLATE_INDEX, INFLATE_INDEX,
-    MAX_PAGES_IN_DESC, MAX_PAGE_COMPACT_BUFFER, MIB_TO_4K_PAGES, STATS_INDEX,
-    VIRTIO_BALLOON_F_DEFLATE_ON_OOM, VIRTIO_BALLOON_F_STATS_VQ, VIRTIO_BALLOON_PFN_SHIFT,
-    VIRTIO_BALLOON_S_AVAIL, VIRTIO_BALLOON_S_CACHES, VIRTIO_BALLOON_S_HTLB_PGALLOC,
-    VIRTIO_BALLOON_S_HTLB_PGFAIL, VIRTIO_BALLOON_S_MAJFLT, VIRTIO_BALLOON_S_MEMFREE,
-    VIRTIO_BALLOON_S_MEMTOT, VIRTIO_BALLOON_S_MINFLT, VIRTIO_BALLOON_S_SWAP_IN,
-    VIRTIO_BALLOON_S_SWAP_OUT,
+    memory_pressure_trigger, BALLOON_DEV_ID, BALLOON_NUM_QUEUES, BALLOON_QUEUE_SIZES,
+    BALLOON_STATS_HISTORY_LEN, DEFLATE_INDEX, INFLATE_INDEX, MAX_PAGES_IN_DESC,
+    MAX_PAGE_COMPACT_BUFFER, MIB_TO_4K_PAGES, STATS_INDEX, VIRTIO_BALLOON_F_DEFLATE_ON_OOM,
+    VIRTIO_BALLOON_F_STATS_VQ, VIRTIO_BALLOON_PFN_SHIFT, VIRTIO_BALLOON_S_AVAIL,
+    VIRTIO_BALLOON_S_CACHES, VIRTIO_BALLOON_S_HTLB_PGALLOC, VIRTIO_BALLOON_S_HTLB_PGFAIL,
+    VIRTIO_BALLOON_S_MAJFLT, VIRTIO_BALLOON_S_MEMFREE, VIRTIO_BALLOON_S_MEMTOT,
+    VIRTIO_BALLOON_S_MINFLT, VIRTIO_BALLOON_S_SWAP_IN, VIRTIO_BALLOON_S_SWAP_OUT,
 };
+use crate::devices::timer::DeviceTimer;
 use crate::devices::virtio::balloon::BalloonError;
 use crate::devices::virtio::device::{IrqTrigger, IrqType};
 use crate::devices::virtio::gen::virtio_blk::VIRTIO_F_VERSION_1;
 use crate::logger::IncMetric;
+use crate::vmm_config::balloon::MemoryPressurePolicyConfig;
 use crate::vstate::memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemoryMmap};
 
 const SIZE_OF_U32: usize = std::mem::size_of::<u32>();
@@ -128,6 +131,16 @@ pub struct BalloonStats {
     pub hugetlb_failures: Option<u64>,
 }
 
+/// A balloon statistics report, as kept in the statistics history.
+#[derive(Clone, Default, Debug, PartialEq, Eq, Serialize)]
+pub struct BalloonStatsSample {
+    /// Monotonic time, in microseconds, at which the report was received from the guest.
+    pub timestamp_us: u64,
+    /// The statistics as of this report.
+    #[serde(flatten)]
+    pub stats: BalloonStats,
+}
+
 impl BalloonStats {
     fn update_with_stat(&mut self, stat: &BalloonStat) -> Result<(), BalloonError> {
         let val = Some(stat.val);
@@ -152,6 +165,7 @@ impl BalloonStats {
 }
 
 /// Virtio balloon device.
+#[derive(Debug)]
 pub struct Balloon {
     // Virtio fields.
     pub(crate) avail_features: u64,
@@ -168,36 +182,29 @@ pub struct Balloon {
     // Implementation specific fields.
     pub(crate) restored: bool,
     pub(crate) stats_polling_interval_s: u16,
-    pub(crate) stats_timer: TimerFd,
+    pub(crate) stats_timer: DeviceTimer,
     // The index of the previous stats descriptor is saved because
     // it is acknowledged after the stats queue is processed.
     pub(crate) stats_desc_index: Option<u16>,
     pub(crate) latest_stats: BalloonStats,
+    // The most recent statistics reports, oldest first.
+    pub(crate) stats_history: VecDeque<BalloonStatsSample>,
     // A buffer used as pfn accumulator during descriptor processing.
     pub(crate) pfn_buffer: [u32; MAX_PAGE_COMPACT_BUFFER],
+    // Inflates the balloon when the host is under memory pressure.
+    pub(crate) pressure_monitor: Option<PressureMonitor>,
 }
 
-// TODO Use `#[derive(Debug)]` when a new release of
-// [rust-timerfd](https://github.com/main--/rust-timerfd) is published that includes
-// https://github.com/main--/rust-timerfd/pull/12.
-impl fmt::Debug for Balloon {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Balloon")
-            .field("avail_features", &self.avail_features)
-            .field("acked_features", &self.acked_features)
-            .field("config_space", &self.config_space)
-            .field("activate_evt", &self.activate_evt)
-            .field("queues", &self.queues)
-            .field("queue_evts", &self.queue_evts)
-            .field("device_state", &self.device_state)
-            .field("irq_trigger", &self.irq_trigger)
-            .field("restored", &self.restored)
-            .field("stats_polling_interval_s", &self.stats_polling_interval_s)
-            .field("stats_desc_index", &self.stats_desc_index)
-            .field("latest_stats", &self.latest_stats)
-            .field("pfn_buffer", &self.pfn_buffer)
-            .finish()
-    }
+/// Host memory pressure trigger, along with the policy it drives.
+#[derive(Debug)]
+pub(crate) struct PressureMonitor {
+    // PSI trigger, which becomes ready for `EPOLLPRI` when the stall threshold is exceeded.
+    pub(crate) trigger: File,
+    // Deflates the balloon by one step each time it expires without pressure events.
+    pub(crate) deflate_timer: DeviceTimer,
+    pub(crate) policy: MemoryPressurePolicyConfig,
+    // Amount the policy inflated the balloon by, in MiB, which it deflates back.
+    pub(crate) inflated_mib: u32,
 }
 
 impl Balloon {
@@ -232,8 +239,7 @@ impl Balloon {
             let _ = queues.remove(STATS_INDEX);
         }
 
-        let stats_timer =
-            TimerFd::new_custom(ClockId::Monotonic, true, true).map_err(BalloonError::Timer)?;
+        let stats_timer = DeviceTimer::new().map_err(BalloonError::Timer)?;
 
         Ok(Balloon {
             avail_features,
@@ -252,41 +258,100 @@ impl Balloon {
             stats_timer,
             stats_desc_index: None,
             latest_stats: BalloonStats::default(),
+            stats_history: VecDeque::with_capacity(BALLOON_STATS_HISTORY_LEN),
             pfn_buffer: [0u32; MAX_PAGE_COMPACT_BUFFER],
+            pressure_monitor: None,
         })
     }
 
     pub(crate) fn process_inflate_queue_event(&mut self) -> Result<(), BalloonError> {
-        self.queue_evts[INFLATE_INDEX]
-            .read()
+        self.queues[INFLATE_INDEX]
+            .read_kicks(&self.queue_evts[INFLATE_INDEX])
             .map_err(BalloonError::EventFd)?;
         self.process_inflate()
     }
 
     pub(crate) fn process_deflate_queue_event(&mut self) -> Result<(), BalloonError> {
-        self.queue_evts[DEFLATE_INDEX]
-            .read()
+        self.queues[DEFLATE_INDEX]
+            .read_kicks(&self.queue_evts[DEFLATE_INDEX])
             .map_err(BalloonError::EventFd)?;
         self.process_deflate_queue()
     }
 
     pub(crate) fn process_stats_queue_event(&mut self) -> Result<(), BalloonError> {
-        self.queue_evts[STATS_INDEX]
-            .read()
+        self.queues[STATS_INDEX]
+            .read_kicks(&self.queue_evts[STATS_INDEX])
             .map_err(BalloonError::EventFd)?;
         self.process_stats_queue()
     }
 
     pub(crate) fn process_stats_timer_event(&mut self) -> Result<(), BalloonError> {
-        self.stats_timer.read();
+        self.stats_timer.read_expirations();
         self.trigger_stats_update()
     }
 
+    pub(crate) fn process_pressure_event(&mut self) -> Result<(), BalloonError> {
+        let Some(policy) = self.pressure_policy() else {
+            return Ok(());
+        };
+        let size_mib = self.size_mb();
+        let target_mib = size_mib
+            .saturating_add(policy.inflate_step_mib)
+            .min(policy.max_balloon_mib);
+        if target_mib > size_mib {
+            METRICS.pressure_inflate_count.inc();
+            self.set_target_size(target_mib)?;
+        }
+
+        if let Some(monitor) = self.pressure_monitor.as_mut() {
+            monitor.inflated_mib += target_mib.saturating_sub(size_mib);
+            // The deflation waits for a whole interval without pressure events.
+            if monitor.inflated_mib > 0 {
+                monitor
+                    .deflate_timer
+                    .arm_periodic(Duration::from_secs(u64::from(policy.deflate_interval_s)));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn process_pressure_timer_event(&mut self) -> Result<(), BalloonError> {
+        let size_mib = self.size_mb();
+        let Some(monitor) = self.pressure_monitor.as_mut() else {
+            return Ok(());
+        };
+        monitor.deflate_timer.read_expirations();
+        let step_mib = monitor
+            .policy
+            .inflate_step_mib
+            .min(monitor.inflated_mib)
+            .min(size_mib);
+        monitor.inflated_mib -= step_mib;
+        if monitor.inflated_mib == 0 {
+            monitor.deflate_timer.disarm();
+        }
+        if step_mib == 0 {
+            return Ok(());
+        }
+
+        METRICS.pressure_deflate_count.inc();
+        self.set_target_size(size_mib - step_mib)
+    }
+
     pub(crate) fn process_inflate(&mut self) -> Result<(), BalloonError> {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
         METRICS.inflate_count.inc();
 
+        // Removing the pages that hold the rings of a queue would zero them under the device, so
+        // those pages are kept even if the driver asks for them.
+        let ring_ranges: Vec<_> = self
+            .queues
+            .iter()
+            .filter(|queue| queue.ready)
+            .flat_map(Queue::ring_ranges)
+            .collect();
+
         let queue = &mut self.queues[INFLATE_INDEX];
         // The pfn buffer index used during descriptor processing.
         let mut pfn_buffer_idx = 0;
@@ -355,12 +420,22 @@ impl Balloon {
             for (page_frame_number, range_len) in page_ranges {
                 let guest_addr =
                     GuestAddress(u64::from(page_frame_number) << VIRTIO_BALLOON_PFN_SHIFT);
+                let range_len = u64::from(range_len) << VIRTIO_BALLOON_PFN_SHIFT;
+
+                if ring_ranges
+                    .iter()
+                    .any(|&ring| ranges_overlap(ring, (guest_addr, range_len)))
+                {
+                    error!(
+                        "Inflate range at {:#x} of size {:#x} holds a virtio queue ring, skipping.",
+                        guest_addr.raw_value(),
+                        range_len
+                    );
+                    METRICS.inflate_ring_overlaps.inc();
+                    continue;
+                }
 
-                if let Err(err) = remove_range(
-                    mem,
-                    (guest_addr, u64::from(range_len) << VIRTIO_BALLOON_PFN_SHIFT),
-                    self.restored,
-                ) {
+                if let Err(err) = remove_range(mem, (guest_addr, range_len), self.restored) {
                     error!("Error removing memory range: {:?}", err);
                 }
             }
@@ -399,6 +474,7 @@ impl Balloon {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
         METRICS.stats_updates_count.inc();
+        let mut stats_received = false;
 
         while let Some(head) = self.queues[STATS_INDEX].pop(mem) {
             if let Some(prev_stats_desc) = self.stats_desc_index {
@@ -428,11 +504,28 @@ impl Balloon {
             }
 
             self.stats_desc_index = Some(head.index);
+            stats_received = true;
+        }
+
+        if stats_received {
+            self.record_stats_sample();
         }
 
         Ok(())
     }
 
+    // Appends the latest statistics to the history, evicting the oldest sample if it is full.
+    fn record_stats_sample(&mut self) {
+        if self.stats_history.len() == BALLOON_STATS_HISTORY_LEN {
+            self.stats_history.pop_front();
+        }
+        self.update_size_stats();
+        self.stats_history.push_back(BalloonStatsSample {
+            timestamp_us: get_time_us(ClockType::Monotonic),
+            stats: self.latest_stats.clone(),
+        });
+    }
+
     pub(crate) fn signal_used_queue(&self) -> Result<(), BalloonError> {
         self.irq_trigger.trigger_irq(IrqType::Vring).map_err(|err| {
             METRICS.event_fails.inc();
@@ -470,10 +563,19 @@ impl Balloon {
 
     /// Update the target size of the balloon.
     pub fn update_size(&mut self, amount_mib: u32) -> Result<(), BalloonError> {
+        // The memory pressure policy deflates the balloon back to the size set by the user.
+        if let Some(monitor) = self.pressure_monitor.as_mut() {
+            monitor.inflated_mib = 0;
+            monitor.deflate_timer.disarm();
+        }
+        self.set_target_size(amount_mib)
+    }
+
+    fn set_target_size(&mut self, amount_mib: u32) -> Result<(), BalloonError> {
         if self.is_activated() {
             self.config_space.num_pages = mib_to_pages(amount_mib)?;
             self.irq_trigger
-                .trigger_irq(IrqType::Config)
+                .signal_config_update()
                 .map_err(BalloonError::InterruptError)
         } else {
             Err(BalloonError::DeviceNotActive)
@@ -498,12 +600,29 @@ impl Balloon {
     }
 
     pub fn update_timer_state(&mut self) {
-        let timer_state = TimerState::Periodic {
-            current: Duration::from_secs(u64::from(self.stats_polling_interval_s)),
-            interval: Duration::from_secs(u64::from(self.stats_polling_interval_s)),
-        };
-        self.stats_timer
-            .set_state(timer_state, SetTimeFlags::Default);
+        self.stats_timer.arm_periodic(Duration::from_secs(u64::from(
+            self.stats_polling_interval_s,
+        )));
+    }
+
+    /// Sizes the balloon according to the given policy, based on the notifications of the memory
+    /// pressure stall trigger passed to Firecracker. The policy replaces any previous one.
+    pub fn set_pressure_policy(
+        &mut self,
+        policy: MemoryPressurePolicyConfig,
+    ) -> Result<(), BalloonError> {
+        self.pressure_monitor = Some(PressureMonitor {
+            trigger: memory_pressure_trigger()?,
+            deflate_timer: DeviceTimer::new().map_err(BalloonError::Timer)?,
+            policy,
+            inflated_mib: 0,
+        });
+        Ok(())
+    }
+
+    /// Returns the host memory pressure policy, if one is set.
+    pub fn pressure_policy(&self) -> Option<MemoryPressurePolicyConfig> {
+        self.pressure_monitor.as_ref().map(|monitor| monitor.policy)
     }
 
     /// Obtain the number of 4K pages the device is currently holding.
@@ -524,19 +643,33 @@ impl Balloon {
         self.stats_polling_interval_s
     }
 
+    fn update_size_stats(&mut self) {
+        self.latest_stats.target_pages = self.config_space.num_pages;
+        self.latest_stats.actual_pages = self.config_space.actual_pages;
+        self.latest_stats.target_mib = pages_to_mib(self.latest_stats.target_pages);
+        self.latest_stats.actual_mib = pages_to_mib(self.latest_stats.actual_pages);
+    }
+
     /// Retrieve latest stats for the balloon device.
     pub fn latest_stats(&mut self) -> Option<&BalloonStats> {
         if self.stats_enabled() {
-            self.latest_stats.target_pages = self.config_space.num_pages;
-            self.latest_stats.actual_pages = self.config_space.actual_pages;
-            self.latest_stats.target_mib = pages_to_mib(self.latest_stats.target_pages);
-            self.latest_stats.actual_mib = pages_to_mib(self.latest_stats.actual_pages);
+            self.update_size_stats();
             Some(&self.latest_stats)
         } else {
             None
         }
     }
 
+    /// Retrieve up to `count` of the most recent statistics reports, oldest first.
+    pub fn stats_history(&self, count: usize) -> Option<Vec<BalloonStatsSample>> {
+        if self.stats_enabled() {
+            let skip = self.stats_history.len().saturating_sub(count);
+            Some(self.stats_history.iter().skip(skip).cloned().collect())
+        } else {
+            None
+        }
+    }
+
     /// Return the config of the balloon device.
     pub fn config(&self) -> BalloonConfig {
         BalloonConfig {
@@ -560,6 +693,10 @@ impl VirtioDevice for Balloon {
         self.avail_features
     }
 
+    fn set_avail_features(&mut self, avail_features: u64) {
+        self.avail_features = avail_features;
+    }
+
     fn acked_features(&self) -> u64 {
         self.acked_features
     }
@@ -641,13 +778,15 @@ impl VirtioDevice for Balloon {
 pub(crate) mod tests {
     use std::u32;
 
+    use utils::tempfile::TempFile;
+
     use super::super::BALLOON_CONFIG_SPACE_SIZE;
     use super::*;
     use crate::check_metric_after_block;
-    use crate::devices::virtio::balloon::report_balloon_event_fail;
     use crate::devices::virtio::balloon::test_utils::{
         check_request_completion, invoke_handler_for_queue_event, set_request,
     };
+    use crate::devices::virtio::balloon::{report_balloon_event_fail, set_memory_pressure_trigger};
     use crate::devices::virtio::queue::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
     use crate::devices::virtio::test_utils::{default_mem, VirtQueue};
     use crate::utilities::test_utils::single_region_mem;
@@ -949,6 +1088,28 @@ pub(crate) mod tests {
                 assert_eq!(mem.read_obj::<u8>(GuestAddress((1 << 12) + i)).unwrap(), 0);
             }
         }
+
+        // The page holding the rings of the inflate queue is kept.
+        {
+            // Past the end of the rings, which take up the start of the first page.
+            let page_addr = 0x800;
+            mem.write_obj::<u32>(0x0, GuestAddress(page_addr)).unwrap();
+            set_request(
+                &infq,
+                1,
+                page_addr,
+                SIZE_OF_U32.try_into().unwrap(),
+                VIRTQ_DESC_F_NEXT,
+            );
+
+            check_metric_after_block!(
+                METRICS.inflate_ring_overlaps,
+                1,
+                invoke_handler_for_queue_event(&mut balloon, INFLATE_INDEX)
+            );
+            // The used ring would read as zeroed if the page was removed.
+            check_request_completion(&infq, 1);
+        }
     }
 
     #[test]
@@ -1070,6 +1231,9 @@ pub(crate) mod tests {
                 ..BalloonStats::default()
             };
             assert_eq!(stats, &expected_stats);
+            let history = balloon.stats_history(BALLOON_STATS_HISTORY_LEN).unwrap();
+            assert_eq!(history.len(), 1);
+            assert_eq!(history[0].stats, expected_stats);
 
             // Wait for the timer to expire, although as it is non-blocking
             // we could just process the timer event and it would not
@@ -1086,6 +1250,39 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_stats_history() {
+        let balloon = Balloon::new(0, true, 0, false).unwrap();
+        assert!(balloon.stats_history(1).is_none());
+
+        let mut balloon = Balloon::new(0, true, 1, false).unwrap();
+        assert!(balloon.stats_history(1).unwrap().is_empty());
+
+        for i in 0..BALLOON_STATS_HISTORY_LEN + 2 {
+            balloon.latest_stats.free_memory = Some(i as u64);
+            balloon.record_stats_sample();
+        }
+        // The oldest samples were evicted.
+        let history = balloon.stats_history(usize::MAX).unwrap();
+        assert_eq!(history.len(), BALLOON_STATS_HISTORY_LEN);
+        assert_eq!(history[0].stats.free_memory, Some(2));
+        assert!(history
+            .windows(2)
+            .all(|pair| pair[0].timestamp_us <= pair[1].timestamp_us));
+
+        // Only the most recent samples are returned, oldest first.
+        let history = balloon.stats_history(2).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(
+            history[0].stats.free_memory,
+            Some(BALLOON_STATS_HISTORY_LEN as u64)
+        );
+        assert_eq!(
+            history[1].stats.free_memory,
+            Some(BALLOON_STATS_HISTORY_LEN as u64 + 1)
+        );
+    }
+
     #[test]
     fn test_process_balloon_queues() {
         let mut balloon = Balloon::new(0x10, true, 0, false).unwrap();
@@ -1157,4 +1354,70 @@ pub(crate) mod tests {
         assert_eq!(balloon.num_pages(), 0x1122_3344);
         assert_eq!(balloon.actual_pages(), 0x1234_5678);
     }
+
+    #[test]
+    fn test_process_pressure_event() {
+        let mut balloon = Balloon::new(4, true, 0, false).unwrap();
+        balloon.device_state = DeviceState::Activated(single_region_mem(0x1));
+
+        // Without a policy, pressure events are ignored.
+        balloon.process_pressure_event().unwrap();
+        balloon.process_pressure_timer_event().unwrap();
+        assert_eq!(balloon.size_mb(), 4);
+
+        let policy = MemoryPressurePolicyConfig {
+            inflate_step_mib: 16,
+            max_balloon_mib: 40,
+            deflate_interval_s: 10,
+        };
+        set_memory_pressure_trigger(TempFile::new().unwrap().into_file());
+        balloon.set_pressure_policy(policy).unwrap();
+        assert_eq!(balloon.pressure_policy(), Some(policy));
+
+        // The balloon is inflated in steps, up to the maximum size.
+        check_metric_after_block!(
+            METRICS.pressure_inflate_count,
+            3,
+            for _ in 0..4 {
+                balloon.process_pressure_event().unwrap();
+            }
+        );
+        assert_eq!(balloon.size_mb(), 40);
+        assert!(balloon
+            .pressure_monitor
+            .as_ref()
+            .unwrap()
+            .deflate_timer
+            .is_armed());
+
+        // Once the pressure is gone, the balloon is deflated in steps, back to its initial size.
+        check_metric_after_block!(
+            METRICS.pressure_deflate_count,
+            3,
+            for _ in 0..4 {
+                balloon.process_pressure_timer_event().unwrap();
+            }
+        );
+        assert_eq!(balloon.size_mb(), 4);
+        assert!(!balloon
+            .pressure_monitor
+            .as_ref()
+            .unwrap()
+            .deflate_timer
+            .is_armed());
+
+        // A size set by the user becomes the size the policy deflates back to.
+        balloon.process_pressure_event().unwrap();
+        assert_eq!(balloon.size_mb(), 20);
+        balloon.update_size(8).unwrap();
+        assert!(!balloon
+            .pressure_monitor
+            .as_ref()
+            .unwrap()
+            .deflate_timer
+            .is_armed());
+        balloon.process_pressure_event().unwrap();
+        balloon.process_pressure_timer_event().unwrap();
+        assert_eq!(balloon.size_mb(), 8);
+    }
 }
@@ -15,6 +15,8 @@ impl Balloon {
     const PROCESS_VIRTQ_DEFLATE: u32 = 2;
     const PROCESS_VIRTQ_STATS: u32 = 3;
     const PROCESS_STATS_TIMER: u32 = 4;
+    const PROCESS_PRESSURE: u32 = 5;
+    const PROCESS_PRESSURE_TIMER: u32 = 6;
 
     fn register_runtime_events(&self, ops: &mut EventOps) {
         if let Err(err) = ops.add(Events::with_data(
@@ -39,12 +41,23 @@ impl Balloon {
             )) {
                 error!("Failed to register stats queue event: {}", err);
             }
+            if let Err(err) = self.stats_timer.register(ops, Self::PROCESS_STATS_TIMER) {
+                error!("Failed to register stats timerfd event: {}", err);
+            }
+        }
+        if let Some(monitor) = self.pressure_monitor.as_ref() {
             if let Err(err) = ops.add(Events::with_data(
-                &self.stats_timer,
-                Self::PROCESS_STATS_TIMER,
-                EventSet::IN,
+                &monitor.trigger,
+                Self::PROCESS_PRESSURE,
+                EventSet::PRIORITY,
             )) {
-                error!("Failed to register stats timerfd event: {}", err);
+                error!("Failed to register memory pressure event: {}", err);
+            }
+            if let Err(err) = monitor
+                .deflate_timer
+                .register(ops, Self::PROCESS_PRESSURE_TIMER)
+            {
+                error!("Failed to register memory pressure timerfd event: {}", err);
             }
         }
     }
@@ -78,7 +91,7 @@ impl MutEventSubscriber for Balloon {
     fn process(&mut self, event: Events, ops: &mut EventOps) {
         let source = event.data();
         let event_set = event.event_set();
-        let supported_events = EventSet::IN;
+        let supported_events = EventSet::IN | EventSet::PRIORITY;
 
         if !supported_events.contains(event_set) {
             warn!(
@@ -103,6 +116,12 @@ impl MutEventSubscriber for Balloon {
                 Self::PROCESS_STATS_TIMER => self
                     .process_stats_timer_event()
                     .unwrap_or_else(report_balloon_event_fail),
+                Self::PROCESS_PRESSURE => self
+                    .process_pressure_event()
+                    .unwrap_or_else(report_balloon_event_fail),
+                Self::PROCESS_PRESSURE_TIMER => self
+                    .process_pressure_timer_event()
+                    .unwrap_or_else(report_balloon_event_fail),
                 _ => {
                     warn!("Balloon: Spurious event received: {:?}", source);
                 }
@@ -63,6 +63,12 @@ pub(super) struct BalloonDeviceMetrics {
     pub deflate_count: SharedIncMetric,
     /// Number of times when handling events on a balloon device failed.
     pub event_fails: SharedIncMetric,
+    /// Number of balloon inflations requested because of host memory pressure.
+    pub pressure_inflate_count: SharedIncMetric,
+    /// Number of balloon deflations requested once the host memory pressure is gone.
+    pub pressure_deflate_count: SharedIncMetric,
+    /// Number of inflated page ranges kept because they hold a virtio queue ring.
+    pub inflate_ring_overlaps: SharedIncMetric,
 }
 impl BalloonDeviceMetrics {
     /// Const default construction.
@@ -74,6 +80,9 @@ impl BalloonDeviceMetrics {
             stats_update_fails: SharedIncMetric::new(),
             deflate_count: SharedIncMetric::new(),
             event_fails: SharedIncMetric::new(),
+            pressure_inflate_count: SharedIncMetric::new(),
+            pressure_deflate_count: SharedIncMetric::new(),
+            inflate_ring_overlaps: SharedIncMetric::new(),
         }
     }
 }
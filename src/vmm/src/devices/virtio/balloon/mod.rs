@@ -10,10 +10,13 @@ pub mod persist;
 pub mod test_utils;
 mod util;
 
+use std::fs::File;
+use std::sync::Mutex;
+
 use log::error;
 use vm_memory::GuestMemoryError;
 
-pub use self::device::{Balloon, BalloonConfig, BalloonStats};
+pub use self::device::{Balloon, BalloonConfig, BalloonStats, BalloonStatsSample};
 use super::queue::QueueError;
 use crate::devices::virtio::balloon::metrics::METRICS;
 use crate::devices::virtio::queue::FIRECRACKER_MAX_QUEUE_SIZE;
@@ -48,6 +51,29 @@ pub const INFLATE_INDEX: usize = 0;
 pub const DEFLATE_INDEX: usize = 1;
 /// The index of the deflate queue from Balloon device queues/queues_evts vector.
 pub const STATS_INDEX: usize = 2;
+/// The number of statistics samples kept in the balloon statistics history.
+pub const BALLOON_STATS_HISTORY_LEN: usize = 64;
+
+/// Memory pressure stall trigger inherited by Firecracker, which drives the memory pressure
+/// policy of the balloon device.
+static MEMORY_PRESSURE_TRIGGER: Mutex<Option<File>> = Mutex::new(None);
+
+/// Sets the memory pressure stall trigger used by the memory pressure policy of the balloon
+/// device. The trigger is installed by the jailer, on the cgroup of the microVM.
+pub fn set_memory_pressure_trigger(trigger: File) {
+    *MEMORY_PRESSURE_TRIGGER.lock().expect("Poisoned lock") = Some(trigger);
+}
+
+// Returns a new handle to the memory pressure stall trigger.
+fn memory_pressure_trigger() -> Result<File, BalloonError> {
+    MEMORY_PRESSURE_TRIGGER
+        .lock()
+        .expect("Poisoned lock")
+        .as_ref()
+        .ok_or(BalloonError::NoPressureTrigger)?
+        .try_clone()
+        .map_err(BalloonError::PressureTrigger)
+}
 
 // The feature bitmap for virtio balloon.
 const VIRTIO_BALLOON_F_STATS_VQ: u32 = 1; // Enable statistics.
@@ -98,6 +124,10 @@ pub enum BalloonError {
     RemoveMemoryRegion(RemoveRegionError),
     /// Error creating the statistics timer: {0}
     Timer(std::io::Error),
+    /// No memory pressure stall trigger was passed to Firecracker.
+    NoPressureTrigger,
+    /// Error setting up the host memory pressure trigger: {0}
+    PressureTrigger(std::io::Error),
 }
 
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
@@ -5,15 +5,13 @@
 
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
-use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
-use timerfd::{SetTimeFlags, TimerState};
 
 use super::*;
 use crate::devices::virtio::balloon::device::{BalloonStats, ConfigSpace};
 use crate::devices::virtio::device::DeviceState;
-use crate::devices::virtio::persist::VirtioDeviceState;
+use crate::devices::virtio::persist::{SnapshotFeature, VirtioDeviceState};
 use crate::devices::virtio::queue::FIRECRACKER_MAX_QUEUE_SIZE;
 use crate::devices::virtio::TYPE_BALLOON;
 use crate::snapshot::Persist;
@@ -90,6 +88,13 @@ pub struct BalloonState {
     virtio_state: VirtioDeviceState,
 }
 
+impl BalloonState {
+    /// Snapshot features used by the device.
+    pub fn snapshot_features(&self) -> Vec<SnapshotFeature> {
+        self.virtio_state.snapshot_features()
+    }
+}
+
 /// Auxiliary structure for creating a device when resuming from a snapshot.
 #[derive(Debug)]
 pub struct BalloonConstructorArgs {
@@ -156,13 +161,7 @@ impl Persist<'_> for Balloon {
                 balloon.set_stats_desc_index(state.stats_desc_index);
 
                 // Restart timer if needed.
-                let timer_state = TimerState::Periodic {
-                    current: Duration::from_secs(u64::from(state.stats_polling_interval_s)),
-                    interval: Duration::from_secs(u64::from(state.stats_polling_interval_s)),
-                };
-                balloon
-                    .stats_timer
-                    .set_state(timer_state, SetTimeFlags::Default);
+                balloon.update_timer_state();
             }
         }
 
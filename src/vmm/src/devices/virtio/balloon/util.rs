@@ -66,6 +66,16 @@ pub(crate) fn compact_page_frame_numbers(v: &mut [u32]) -> Vec<(u32, u32)> {
     result
 }
 
+/// Checks whether two ranges of guest physical addresses, given by start and length, overlap.
+pub(crate) fn ranges_overlap(a: (GuestAddress, u64), b: (GuestAddress, u64)) -> bool {
+    let (GuestAddress(a_start), a_len) = a;
+    let (GuestAddress(b_start), b_len) = b;
+    a_len != 0
+        && b_len != 0
+        && a_start < b_start.saturating_add(b_len)
+        && b_start < a_start.saturating_add(a_len)
+}
+
 pub(crate) fn remove_range(
     guest_memory: &GuestMemoryMmap,
     range: (GuestAddress, u64),
@@ -177,6 +187,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_ranges_overlap() {
+        let range = (GuestAddress(0x1000), 0x1000);
+        assert!(ranges_overlap(range, range));
+        assert!(ranges_overlap(range, (GuestAddress(0x1fff), 0x10)));
+        assert!(ranges_overlap(range, (GuestAddress(0), 0x1001)));
+        assert!(ranges_overlap(range, (GuestAddress(0x1800), 0x10)));
+        assert!(!ranges_overlap(range, (GuestAddress(0x2000), 0x10)));
+        assert!(!ranges_overlap(range, (GuestAddress(0), 0x1000)));
+        assert!(!ranges_overlap(range, (GuestAddress(0x1800), 0)));
+        assert!(ranges_overlap(range, (GuestAddress(0x10), u64::MAX)));
+    }
+
     #[test]
     fn test_remove_range() {
         let page_size: usize = 0x1000;
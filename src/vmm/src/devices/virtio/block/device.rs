@@ -9,10 +9,11 @@ use utils::eventfd::EventFd;
 
 use super::persist::{BlockConstructorArgs, BlockState};
 use super::vhost_user::device::{VhostUserBlock, VhostUserBlockConfig};
-use super::virtio::device::{VirtioBlock, VirtioBlockConfig};
+use super::virtio::device::{FileEngineType, VirtioBlock, VirtioBlockConfig};
 use super::BlockError;
 use crate::devices::virtio::device::VirtioDevice;
 use crate::devices::virtio::queue::Queue;
+use crate::devices::virtio::worker::block::{create_block_worker, BlockWorkerConfig};
 use crate::devices::virtio::{ActivateError, TYPE_BLOCK};
 use crate::rate_limiter::BucketUpdate;
 use crate::snapshot::Persist;
@@ -29,7 +30,12 @@ pub enum Block {
 
 impl Block {
     pub fn new(config: BlockDeviceConfig) -> Result<Block, BlockError> {
-        if let Ok(config) = VirtioBlockConfig::try_from(&config) {
+        if config.worker {
+            let config = BlockWorkerConfig::try_from(&config).map_err(BlockError::Worker)?;
+            Ok(Self::VhostUser(
+                create_block_worker(config).map_err(BlockError::Worker)?,
+            ))
+        } else if let Ok(config) = VirtioBlockConfig::try_from(&config) {
             Ok(Self::Virtio(
                 VirtioBlock::new(config).map_err(BlockError::VirtioBackend)?,
             ))
@@ -45,7 +51,10 @@ impl Block {
     pub fn config(&self) -> BlockDeviceConfig {
         match self {
             Self::Virtio(b) => b.config().into(),
-            Self::VhostUser(b) => b.config().into(),
+            Self::VhostUser(b) => match &b.worker {
+                Some(worker) => worker.config.clone().into(),
+                None => b.config().into(),
+            },
         }
     }
 
@@ -72,6 +81,41 @@ impl Block {
         }
     }
 
+    pub fn update_file_engine(
+        &mut self,
+        file_engine_type: FileEngineType,
+    ) -> Result<(), BlockError> {
+        match self {
+            Self::Virtio(b) => b
+                .update_file_engine(file_engine_type)
+                .map_err(BlockError::VirtioBackend),
+            Self::VhostUser(_) => Err(BlockError::InvalidBlockBackend),
+        }
+    }
+
+    /// Makes the requests accessing the disk fail with an I/O error, while `enabled`.
+    pub fn set_io_fault(&mut self, enabled: bool) -> Result<(), BlockError> {
+        match self {
+            Self::Virtio(b) => {
+                b.disk.io_fault = enabled;
+                Ok(())
+            }
+            Self::VhostUser(_) => Err(BlockError::InvalidBlockBackend),
+        }
+    }
+
+    /// Drops the interrupts of the device instead of delivering them to the guest, while
+    /// `enabled`.
+    pub fn set_irq_fault(&mut self, enabled: bool) -> Result<(), BlockError> {
+        match self {
+            Self::Virtio(b) => {
+                b.irq_trigger.drop_irqs = enabled;
+                Ok(())
+            }
+            Self::VhostUser(_) => Err(BlockError::InvalidBlockBackend),
+        }
+    }
+
     pub fn update_config(&mut self) -> Result<(), BlockError> {
         match self {
             Self::Virtio(_) => Err(BlockError::InvalidBlockBackend),
@@ -86,6 +130,30 @@ impl Block {
         }
     }
 
+    /// Stop accessing the guest memory until `unquiesce` is called.
+    pub fn quiesce(&mut self) -> Result<(), BlockError> {
+        match self {
+            Self::Virtio(b) => {
+                b.drain();
+                Ok(())
+            }
+            Self::VhostUser(b) => b
+                .set_queues_enabled(false)
+                .map_err(BlockError::VhostUserBackend),
+        }
+    }
+
+    /// Resume accessing the guest memory after `quiesce`.
+    pub fn unquiesce(&mut self) -> Result<(), BlockError> {
+        match self {
+            // The queues are processed again once the device is kicked.
+            Self::Virtio(_) => Ok(()),
+            Self::VhostUser(b) => b
+                .set_queues_enabled(true)
+                .map_err(BlockError::VhostUserBackend),
+        }
+    }
+
     pub fn process_virtio_queues(&mut self) {
         match self {
             Self::Virtio(b) => b.process_virtio_queues(),
@@ -137,6 +205,13 @@ impl VirtioDevice for Block {
         }
     }
 
+    fn set_avail_features(&mut self, avail_features: u64) {
+        match self {
+            Self::Virtio(b) => b.avail_features = avail_features,
+            Self::VhostUser(b) => b.avail_features = avail_features,
+        }
+    }
+
     fn acked_features(&self) -> u64 {
         match self {
             Self::Virtio(b) => b.acked_features,
@@ -190,6 +265,13 @@ impl VirtioDevice for Block {
         }
     }
 
+    fn needs_reset(&self) -> bool {
+        match self {
+            Self::Virtio(b) => b.irq_trigger.needs_reset(),
+            Self::VhostUser(b) => b.irq_trigger.needs_reset(),
+        }
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         match self {
             Self::Virtio(b) => b.read_config(offset, data),
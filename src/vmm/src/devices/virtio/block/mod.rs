@@ -4,6 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use self::vhost_user::VhostUserBlockError;
 use self::virtio::VirtioBlockError;
+use crate::devices::virtio::worker::block::BlockWorkerError;
 
 pub mod device;
 pub mod persist;
@@ -20,6 +21,10 @@ pub enum CacheType {
     /// flush requests coming from the guest will be performed using
     /// `fsync`.
     Writeback,
+    /// Flushing mechanic will not be advertised to the guest driver and
+    /// writes complete only once they reach the backing storage, using
+    /// `O_DSYNC`.
+    Writethrough,
 }
 
 /// Errors the block device can trigger.
@@ -35,4 +40,6 @@ pub enum BlockError {
     VirtioBackend(VirtioBlockError),
     /// Vhost user backend error: {0}
     VhostUserBackend(VhostUserBlockError),
+    /// Worker backend error: {0}
+    Worker(BlockWorkerError),
 }
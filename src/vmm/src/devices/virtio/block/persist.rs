@@ -5,6 +5,7 @@ use serde::{Deserialize, Serialize};
 
 use super::vhost_user::persist::VhostUserBlockState;
 use super::virtio::persist::VirtioBlockState;
+use crate::devices::virtio::persist::SnapshotFeature;
 use crate::vstate::memory::GuestMemoryMmap;
 
 /// Block device state.
@@ -14,6 +15,16 @@ pub enum BlockState {
     VhostUser(VhostUserBlockState),
 }
 
+impl BlockState {
+    /// Snapshot features used by the device.
+    pub fn snapshot_features(&self) -> Vec<SnapshotFeature> {
+        match self {
+            BlockState::Virtio(state) => state.snapshot_features(),
+            BlockState::VhostUser(state) => state.snapshot_features(),
+        }
+    }
+}
+
 /// Auxiliary structure for creating a device when resuming from a snapshot.
 #[derive(Debug)]
 pub struct BlockConstructorArgs {
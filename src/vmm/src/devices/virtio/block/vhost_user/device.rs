@@ -6,8 +6,10 @@
 
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
+use std::time::Duration;
 
-use log::error;
+use log::{error, info, warn};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 use utils::eventfd::EventFd;
 use utils::u64_to_usize;
 use vhost::vhost_user::message::*;
@@ -15,19 +17,22 @@ use vhost::vhost_user::Frontend;
 
 use super::{VhostUserBlockError, NUM_QUEUES, QUEUE_SIZE};
 use crate::devices::virtio::block::CacheType;
-use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, VirtioDevice};
+use crate::devices::virtio::device::{DeviceState, IrqTrigger, VirtioDevice};
 use crate::devices::virtio::gen::virtio_blk::{
     VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_RO, VIRTIO_F_VERSION_1,
 };
 use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
 use crate::devices::virtio::queue::Queue;
-use crate::devices::virtio::vhost_user::{VhostUserHandleBackend, VhostUserHandleImpl};
+use crate::devices::virtio::vhost_user::{
+    InflightRegion, VhostUserHandleBackend, VhostUserHandleImpl,
+};
 use crate::devices::virtio::vhost_user_metrics::{
     VhostUserDeviceMetrics, VhostUserMetricsPerDevice,
 };
+use crate::devices::virtio::worker::block::BlockWorker;
 use crate::devices::virtio::{ActivateError, TYPE_BLOCK};
 use crate::logger::{log_dev_preview_warning, IncMetric, StoreMetric};
-use crate::vmm_config::drive::BlockDeviceConfig;
+use crate::vmm_config::drive::{BlockDeviceConfig, VhostUserReconnectConfig};
 use crate::vstate::memory::GuestMemoryMmap;
 
 /// Block device config space size in bytes.
@@ -60,17 +65,27 @@ pub struct VhostUserBlockConfig {
 
     /// Socket path of the vhost-user process
     pub socket: String,
+    /// Policy for reconnecting to the vhost-user process if it goes away.
+    pub reconnect: Option<VhostUserReconnectConfig>,
 }
 
 impl TryFrom<&BlockDeviceConfig> for VhostUserBlockConfig {
     type Error = VhostUserBlockError;
 
     fn try_from(value: &BlockDeviceConfig) -> Result<Self, Self::Error> {
+        // The backing file is opened by the vhost-user backend, so its cache mode can't be
+        // enforced.
         if value.socket.is_some()
+            && value.cache_type != CacheType::Writethrough
             && value.is_read_only.is_none()
             && value.path_on_host.is_none()
             && value.rate_limiter.is_none()
             && value.file_engine_type.is_none()
+            && value.overlay_path.is_none()
+            && value
+                .reconnect
+                .iter()
+                .all(VhostUserReconnectConfig::is_valid)
         {
             Ok(Self {
                 drive_id: value.drive_id.clone(),
@@ -79,6 +94,7 @@ impl TryFrom<&BlockDeviceConfig> for VhostUserBlockConfig {
                 cache_type: value.cache_type,
 
                 socket: value.socket.as_ref().unwrap().clone(),
+                reconnect: value.reconnect,
             })
         } else {
             Err(VhostUserBlockError::Config)
@@ -98,8 +114,12 @@ impl From<VhostUserBlockConfig> for BlockDeviceConfig {
             path_on_host: None,
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: Some(value.socket),
+            reconnect: value.reconnect,
+
+            worker: false,
         }
     }
 }
@@ -131,6 +151,16 @@ pub struct VhostUserBlockImpl<T: VhostUserHandleBackend> {
     pub vu_handle: VhostUserHandleImpl<T>,
     pub vu_acked_protocol_features: u64,
     pub metrics: Arc<VhostUserDeviceMetrics>,
+
+    // Reconnection to the vhost user backend
+    pub reconnect: Option<VhostUserReconnectConfig>,
+    pub reconnect_timer: TimerFd,
+    pub reconnect_attempts: u32,
+    pub disconnected: bool,
+    pub inflight: Option<InflightRegion>,
+
+    // Worker process emulating the device, if it is not provided by an external backend.
+    pub worker: Option<BlockWorker>,
 }
 
 // Need custom implementation because otherwise `Debug` is required for `vhost::Master`
@@ -156,6 +186,11 @@ impl<T: VhostUserHandleBackend> std::fmt::Debug for VhostUserBlockImpl<T> {
                 &self.vu_acked_protocol_features,
             )
             .field("metrics", &self.metrics)
+            .field("reconnect", &self.reconnect)
+            .field("reconnect_attempts", &self.reconnect_attempts)
+            .field("disconnected", &self.disconnected)
+            .field("inflight", &self.inflight)
+            .field("worker", &self.worker)
             .finish()
     }
 }
@@ -163,6 +198,16 @@ impl<T: VhostUserHandleBackend> std::fmt::Debug for VhostUserBlockImpl<T> {
 impl<T: VhostUserHandleBackend> VhostUserBlockImpl<T> {
     pub fn new(config: VhostUserBlockConfig) -> Result<Self, VhostUserBlockError> {
         log_dev_preview_warning("vhost-user-blk device", Option::None);
+        let vu_handle = VhostUserHandleImpl::<T>::new(&config.socket, NUM_QUEUES)
+            .map_err(VhostUserBlockError::VhostUser)?;
+        Self::with_handle(config, vu_handle)
+    }
+
+    /// Create the device on top of a session with the backend which is already owned.
+    pub fn with_handle(
+        config: VhostUserBlockConfig,
+        mut vu_handle: VhostUserHandleImpl<T>,
+    ) -> Result<Self, VhostUserBlockError> {
         let start_time = utils::time::get_time_us(utils::time::ClockType::Monotonic);
         let mut requested_features = AVAILABLE_FEATURES;
 
@@ -170,10 +215,14 @@ impl<T: VhostUserHandleBackend> VhostUserBlockImpl<T> {
             requested_features |= 1 << VIRTIO_BLK_F_FLUSH;
         }
 
-        let requested_protocol_features = VhostUserProtocolFeatures::CONFIG;
+        let mut requested_protocol_features = VhostUserProtocolFeatures::CONFIG;
+
+        // The inflight buffer lets a new backend instance resubmit the requests the previous
+        // one did not complete.
+        if config.reconnect.is_some() {
+            requested_protocol_features |= VhostUserProtocolFeatures::INFLIGHT_SHMFD;
+        }
 
-        let mut vu_handle = VhostUserHandleImpl::<T>::new(&config.socket, NUM_QUEUES)
-            .map_err(VhostUserBlockError::VhostUser)?;
         let (acked_features, acked_protocol_features) = vu_handle
             .negotiate_features(requested_features, requested_protocol_features)
             .map_err(VhostUserBlockError::VhostUser)?;
@@ -205,6 +254,8 @@ impl<T: VhostUserHandleBackend> VhostUserBlockImpl<T> {
             u64_to_usize(NUM_QUEUES)];
         let device_state = DeviceState::Inactive;
         let irq_trigger = IrqTrigger::new().map_err(VhostUserBlockError::IrqTrigger)?;
+        let reconnect_timer = TimerFd::new_custom(ClockId::Monotonic, true, true)
+            .map_err(VhostUserBlockError::Timer)?;
 
         // We negotiated features with backend. Now these acked_features
         // are available for guest driver to choose from.
@@ -237,6 +288,14 @@ impl<T: VhostUserHandleBackend> VhostUserBlockImpl<T> {
             vu_handle,
             vu_acked_protocol_features: acked_protocol_features,
             metrics,
+
+            reconnect: config.reconnect,
+            reconnect_timer,
+            reconnect_attempts: 0,
+            disconnected: false,
+            inflight: None,
+
+            worker: None,
         })
     }
 
@@ -245,6 +304,18 @@ impl<T: VhostUserHandleBackend> VhostUserBlockImpl<T> {
         unimplemented!("VhostUserBlock does not support snapshotting yet");
     }
 
+    /// Ask the backend to start or stop processing the queues.
+    pub fn set_queues_enabled(&mut self, enable: bool) -> Result<(), VhostUserBlockError> {
+        // A disconnected backend does not process the queues, and the one replacing it gets
+        // the vrings enabled when the device is resumed.
+        if !self.device_state.is_activated() || self.disconnected {
+            return Ok(());
+        }
+        self.vu_handle
+            .set_vrings_enabled(u64_to_usize(NUM_QUEUES), enable)
+            .map_err(VhostUserBlockError::VhostUser)
+    }
+
     pub fn config(&self) -> VhostUserBlockConfig {
         VhostUserBlockConfig {
             drive_id: self.id.clone(),
@@ -252,9 +323,113 @@ impl<T: VhostUserHandleBackend> VhostUserBlockImpl<T> {
             is_root_device: self.root_device,
             cache_type: self.cache_type,
             socket: self.vu_handle.socket_path.clone(),
+            reconnect: self.reconnect,
+        }
+    }
+
+    /// Handle the vhost-user backend going away. The requests of the guest wait in the queue
+    /// until the device reconnects to a new backend, if a reconnection policy is configured.
+    pub fn handle_backend_disconnect(&mut self) {
+        self.metrics.backend_disconnects.inc();
+        self.disconnected = true;
+        self.reconnect_attempts = 0;
+        if self.reconnect.is_some() {
+            warn!(
+                "vhost-user block {}: backend disconnected, reconnecting",
+                self.id
+            );
+            self.arm_reconnect_timer();
+        } else {
+            error!(
+                "vhost-user block {}: backend disconnected and reconnection is disabled",
+                self.id
+            );
         }
     }
 
+    /// Attempt to reconnect to the vhost-user backend. Schedules the next attempt if this one
+    /// fails and the reconnection policy allows it.
+    pub fn try_reconnect(&mut self) -> Result<(), VhostUserBlockError> {
+        let result = self.reconnect_backend();
+        match &result {
+            Ok(()) => {
+                info!("vhost-user block {}: backend reconnected", self.id);
+                self.metrics.reconnects.inc();
+                self.disconnected = false;
+                self.reconnect_attempts = 0;
+            }
+            Err(err) => {
+                self.metrics.reconnect_fails.inc();
+                self.reconnect_attempts += 1;
+                // Safe to unwrap because reconnecting is only attempted with a policy.
+                if self
+                    .reconnect
+                    .unwrap()
+                    .is_exhausted(self.reconnect_attempts)
+                {
+                    error!(
+                        "vhost-user block {}: giving up reconnecting after {} attempts: {}",
+                        self.id, self.reconnect_attempts, err
+                    );
+                } else {
+                    warn!("vhost-user block {}: failed to reconnect: {}", self.id, err);
+                    self.arm_reconnect_timer();
+                }
+            }
+        }
+        result
+    }
+
+    fn arm_reconnect_timer(&mut self) {
+        if let Some(reconnect) = self.reconnect {
+            let delay = Duration::from_millis(reconnect.backoff_ms(self.reconnect_attempts));
+            self.reconnect_timer
+                .set_state(TimerState::Oneshot(delay), SetTimeFlags::Default);
+        }
+    }
+
+    // Connect to a new instance of the backend and restore the state of the previous one.
+    fn reconnect_backend(&mut self) -> Result<(), VhostUserBlockError> {
+        let DeviceState::Activated(mem) = &self.device_state else {
+            unreachable!("Reconnecting an inactive vhost-user block device");
+        };
+
+        let mut vu_handle = VhostUserHandleImpl::<T>::new(&self.vu_handle.socket_path, NUM_QUEUES)
+            .map_err(VhostUserBlockError::VhostUser)?;
+        let (acked_features, acked_protocol_features) = vu_handle
+            .negotiate_features(
+                self.avail_features,
+                VhostUserProtocolFeatures::from_bits_truncate(self.vu_acked_protocol_features),
+            )
+            .map_err(VhostUserBlockError::VhostUser)?;
+        // The guest driver already negotiated the features, so the new backend has to support
+        // all of them.
+        if acked_features & self.acked_features != self.acked_features
+            || acked_protocol_features != self.vu_acked_protocol_features
+        {
+            return Err(VhostUserBlockError::ReconnectFeatures);
+        }
+
+        vu_handle
+            .set_features(self.acked_features)
+            .map_err(VhostUserBlockError::VhostUser)?;
+        if let Some(inflight) = &self.inflight {
+            vu_handle
+                .set_inflight(inflight)
+                .map_err(VhostUserBlockError::VhostUser)?;
+        }
+        vu_handle
+            .resume_backend(
+                mem,
+                &[(0, &self.queues[0], &self.queue_evts[0])],
+                &self.irq_trigger,
+            )
+            .map_err(VhostUserBlockError::VhostUser)?;
+
+        self.vu_handle = vu_handle;
+        Ok(())
+    }
+
     pub fn config_update(&mut self) -> Result<(), VhostUserBlockError> {
         let start_time = utils::time::get_time_us(utils::time::ClockType::Monotonic);
 
@@ -272,7 +447,7 @@ impl<T: VhostUserHandleBackend> VhostUserBlockImpl<T> {
             .map_err(VhostUserBlockError::Vhost)?;
         self.config_space = new_config_space;
         self.irq_trigger
-            .trigger_irq(IrqType::Config)
+            .signal_config_update()
             .map_err(VhostUserBlockError::IrqTrigger)?;
 
         let delta_us = utils::time::get_time_us(utils::time::ClockType::Monotonic) - start_time;
@@ -287,6 +462,10 @@ impl<T: VhostUserHandleBackend + Send + 'static> VirtioDevice for VhostUserBlock
         self.avail_features
     }
 
+    fn set_avail_features(&mut self, avail_features: u64) {
+        self.avail_features = avail_features;
+    }
+
     fn acked_features(&self) -> u64 {
         self.acked_features
     }
@@ -343,6 +522,17 @@ impl<T: VhostUserHandleBackend + Send + 'static> VirtioDevice for VhostUserBlock
         self.vu_handle
             .set_features(self.acked_features)
             .map_err(ActivateError::VhostUser)?;
+        if self.vu_acked_protocol_features & VhostUserProtocolFeatures::INFLIGHT_SHMFD.bits() != 0 {
+            self.inflight = Some(
+                self.vu_handle
+                    // Safe to unwrap since the device has a single queue.
+                    .setup_inflight(u16::try_from(NUM_QUEUES).unwrap(), QUEUE_SIZE)
+                    .map_err(|err| {
+                        self.metrics.activate_fails.inc();
+                        ActivateError::VhostUser(err)
+                    })?,
+            );
+        }
         self.vu_handle
             .setup_backend(
                 &mem,
@@ -368,9 +558,12 @@ impl<T: VhostUserHandleBackend + Send + 'static> VirtioDevice for VhostUserBlock
 mod tests {
     #![allow(clippy::undocumented_unsafe_blocks)]
 
-    use std::os::unix::net::UnixStream;
-    use std::sync::atomic::Ordering;
+    use std::fs::File;
+    use std::os::fd::RawFd;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::atomic::{AtomicU64, Ordering};
 
+    use utils::tempdir::TempDir;
     use utils::tempfile::TempFile;
     use vhost::{VhostUserMemoryRegionInfo, VringConfigData};
 
@@ -378,7 +571,7 @@ mod tests {
     use crate::devices::virtio::block::virtio::device::FileEngineType;
     use crate::devices::virtio::mmio::VIRTIO_MMIO_INT_CONFIG;
     use crate::utilities::test_utils::create_tmp_socket;
-    use crate::vstate::memory::{FileOffset, GuestAddress, GuestMemoryExtension};
+    use crate::vstate::memory::{Bytes, FileOffset, GuestAddress, GuestMemoryExtension};
 
     #[test]
     fn test_from_config() {
@@ -392,11 +585,33 @@ mod tests {
             path_on_host: None,
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: Some("sock".to_string()),
+            reconnect: None,
+            worker: false,
         };
         VhostUserBlockConfig::try_from(&block_config).unwrap();
 
+        // The cache mode of the backing file can't be enforced.
+        let block_config = BlockDeviceConfig {
+            drive_id: "".to_string(),
+            partuuid: None,
+            is_root_device: false,
+            cache_type: CacheType::Writethrough,
+
+            is_read_only: None,
+            path_on_host: None,
+            rate_limiter: None,
+            file_engine_type: None,
+            overlay_path: None,
+
+            socket: Some("sock".to_string()),
+            reconnect: None,
+            worker: false,
+        };
+        VhostUserBlockConfig::try_from(&block_config).unwrap_err();
+
         let block_config = BlockDeviceConfig {
             drive_id: "".to_string(),
             partuuid: None,
@@ -407,8 +622,11 @@ mod tests {
             path_on_host: Some("path".to_string()),
             rate_limiter: None,
             file_engine_type: Some(FileEngineType::Sync),
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
         VhostUserBlockConfig::try_from(&block_config).unwrap_err();
 
@@ -422,8 +640,11 @@ mod tests {
             path_on_host: Some("path".to_string()),
             rate_limiter: None,
             file_engine_type: Some(FileEngineType::Sync),
+            overlay_path: None,
 
             socket: Some("sock".to_string()),
+            reconnect: None,
+            worker: false,
         };
         VhostUserBlockConfig::try_from(&block_config).unwrap_err();
     }
@@ -485,6 +706,7 @@ mod tests {
             is_root_device: false,
             cache_type: CacheType::Unsafe,
             socket: tmp_socket_path.clone(),
+            reconnect: None,
         };
         let vhost_block = VhostUserBlockImpl::<MockMaster>::new(vhost_block_config).unwrap();
 
@@ -585,6 +807,7 @@ mod tests {
             is_root_device: false,
             cache_type: CacheType::Writeback,
             socket: tmp_socket_path.clone(),
+            reconnect: None,
         };
         let mut vhost_block = VhostUserBlockImpl::<MockMaster>::new(vhost_block_config).unwrap();
 
@@ -771,6 +994,7 @@ mod tests {
             is_root_device: false,
             cache_type: CacheType::Writeback,
             socket: tmp_socket_path,
+            reconnect: None,
         };
         let mut vhost_block = VhostUserBlockImpl::<MockMaster>::new(vhost_block_config).unwrap();
 
@@ -792,4 +1016,203 @@ mod tests {
         assert!(unsafe { *vhost_block.vu_handle.vu.vring_enabled.get() });
         assert!(vhost_block.is_activated());
     }
+
+    #[test]
+    fn test_reconnect() {
+        static BACKEND_FEATURES: AtomicU64 = AtomicU64::new(
+            (1 << VIRTIO_F_VERSION_1) | VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits(),
+        );
+
+        struct MockMaster {
+            vring_base: std::cell::UnsafeCell<Option<u16>>,
+            inflight_is_set: std::cell::UnsafeCell<bool>,
+        }
+
+        impl VhostUserHandleBackend for MockMaster {
+            fn from_stream(_sock: UnixStream, _max_queue_num: u64) -> Self {
+                Self {
+                    vring_base: std::cell::UnsafeCell::new(None),
+                    inflight_is_set: std::cell::UnsafeCell::new(false),
+                }
+            }
+
+            fn set_owner(&self) -> Result<(), vhost::Error> {
+                Ok(())
+            }
+
+            fn set_hdr_flags(&self, _flags: VhostUserHeaderFlag) {}
+
+            fn get_features(&self) -> Result<u64, vhost::Error> {
+                Ok(BACKEND_FEATURES.load(Ordering::SeqCst))
+            }
+
+            fn get_protocol_features(&mut self) -> Result<VhostUserProtocolFeatures, vhost::Error> {
+                Ok(VhostUserProtocolFeatures::CONFIG | VhostUserProtocolFeatures::INFLIGHT_SHMFD)
+            }
+
+            fn set_protocol_features(
+                &mut self,
+                _features: VhostUserProtocolFeatures,
+            ) -> Result<(), vhost::Error> {
+                Ok(())
+            }
+
+            fn get_config(
+                &mut self,
+                _offset: u32,
+                _size: u32,
+                _flags: VhostUserConfigFlags,
+                _buf: &[u8],
+            ) -> Result<(VhostUserConfig, VhostUserConfigPayload), vhost::Error> {
+                Ok((VhostUserConfig::default(), vec![]))
+            }
+
+            fn get_inflight_fd(
+                &mut self,
+                inflight: &VhostUserInflight,
+            ) -> Result<(VhostUserInflight, File), vhost::Error> {
+                let info =
+                    VhostUserInflight::new(0x1000, 0, inflight.num_queues, inflight.queue_size);
+                Ok((info, TempFile::new().unwrap().into_file()))
+            }
+
+            fn set_inflight_fd(
+                &mut self,
+                _inflight: &VhostUserInflight,
+                _fd: RawFd,
+            ) -> Result<(), vhost::Error> {
+                unsafe { (*self.inflight_is_set.get()) = true };
+                Ok(())
+            }
+
+            fn set_features(&self, _features: u64) -> Result<(), vhost::Error> {
+                Ok(())
+            }
+
+            fn set_mem_table(
+                &self,
+                _regions: &[VhostUserMemoryRegionInfo],
+            ) -> Result<(), vhost::Error> {
+                Ok(())
+            }
+
+            fn set_vring_num(&self, _queue_index: usize, _num: u16) -> Result<(), vhost::Error> {
+                Ok(())
+            }
+
+            fn set_vring_addr(
+                &self,
+                _queue_index: usize,
+                _config_data: &VringConfigData,
+            ) -> Result<(), vhost::Error> {
+                Ok(())
+            }
+
+            fn set_vring_base(&self, _queue_index: usize, base: u16) -> Result<(), vhost::Error> {
+                unsafe { (*self.vring_base.get()) = Some(base) };
+                Ok(())
+            }
+
+            fn set_vring_call(
+                &self,
+                _queue_index: usize,
+                _fd: &EventFd,
+            ) -> Result<(), vhost::Error> {
+                Ok(())
+            }
+
+            fn set_vring_kick(
+                &self,
+                _queue_index: usize,
+                _fd: &EventFd,
+            ) -> Result<(), vhost::Error> {
+                Ok(())
+            }
+
+            fn set_vring_enable(
+                &mut self,
+                _queue_index: usize,
+                _enable: bool,
+            ) -> Result<(), vhost::Error> {
+                Ok(())
+            }
+        }
+
+        // Block creation. Every connection to the backend is accepted, as the device connects
+        // several times.
+        let tmp_dir = TempDir::new().unwrap();
+        let tmp_socket_path = format!("{}/tmp_socket", tmp_dir.as_path().to_str().unwrap());
+        let listener = UnixListener::bind(&tmp_socket_path).unwrap();
+        let vhost_block_config = VhostUserBlockConfig {
+            drive_id: "test_drive".to_string(),
+            partuuid: None,
+            is_root_device: false,
+            cache_type: CacheType::Unsafe,
+            socket: tmp_socket_path,
+            reconnect: Some(VhostUserReconnectConfig::default()),
+        };
+        let mut vhost_block = VhostUserBlockImpl::<MockMaster>::new(vhost_block_config).unwrap();
+        listener.accept().unwrap();
+        assert_ne!(
+            vhost_block.vu_acked_protocol_features
+                & VhostUserProtocolFeatures::INFLIGHT_SHMFD.bits(),
+            0
+        );
+        vhost_block.acked_features = vhost_block.avail_features;
+
+        // Memory creation
+        let region_size = 0x10000;
+        let file = TempFile::new().unwrap().into_file();
+        file.set_len(region_size as u64).unwrap();
+        let regions = vec![(
+            FileOffset::new(file.try_clone().unwrap(), 0x0),
+            GuestAddress(0x0),
+            region_size,
+        )];
+        let guest_memory = GuestMemoryMmap::from_raw_regions_file(regions, false, false).unwrap();
+
+        // The backend starts processing the queue from the available descriptors.
+        vhost_block.queues[0].avail_ring = GuestAddress(0x1000);
+        vhost_block.queues[0].used_ring = GuestAddress(0x2000);
+        guest_memory.write_obj(7u16, GuestAddress(0x1002)).unwrap();
+        guest_memory.write_obj(5u16, GuestAddress(0x2002)).unwrap();
+        vhost_block.activate(guest_memory).unwrap();
+        assert!(vhost_block.inflight.is_some());
+        assert!(unsafe { *vhost_block.vu_handle.vu.inflight_is_set.get() });
+        assert_eq!(
+            unsafe { *vhost_block.vu_handle.vu.vring_base.get() },
+            Some(7)
+        );
+
+        vhost_block.handle_backend_disconnect();
+        assert!(vhost_block.disconnected);
+        assert_eq!(vhost_block.metrics.backend_disconnects.count(), 1);
+
+        // A backend not supporting the negotiated features is rejected.
+        BACKEND_FEATURES.store(1 << VIRTIO_F_VERSION_1, Ordering::SeqCst);
+        assert!(matches!(
+            vhost_block.try_reconnect(),
+            Err(VhostUserBlockError::ReconnectFeatures)
+        ));
+        listener.accept().unwrap();
+        assert!(vhost_block.disconnected);
+        assert_eq!(vhost_block.reconnect_attempts, 1);
+        assert_eq!(vhost_block.metrics.reconnect_fails.count(), 1);
+
+        // The new backend resumes processing the queue from the used descriptors, the inflight
+        // ones being resubmitted from the inflight buffer.
+        BACKEND_FEATURES.store(
+            (1 << VIRTIO_F_VERSION_1) | VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits(),
+            Ordering::SeqCst,
+        );
+        vhost_block.try_reconnect().unwrap();
+        assert!(!vhost_block.disconnected);
+        assert_eq!(vhost_block.reconnect_attempts, 0);
+        assert_eq!(vhost_block.metrics.reconnects.count(), 1);
+        assert!(unsafe { *vhost_block.vu_handle.vu.inflight_is_set.get() });
+        assert_eq!(
+            unsafe { *vhost_block.vu_handle.vu.vring_base.get() },
+            Some(5)
+        );
+    }
 }
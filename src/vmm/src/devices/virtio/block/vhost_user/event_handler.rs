@@ -1,14 +1,40 @@
 // Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
+use std::os::fd::AsRawFd;
+
 use event_manager::{EventOps, Events, MutEventSubscriber};
 use utils::epoll::EventSet;
 
 use super::VhostUserBlock;
 use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::vhost_user::VhostUserHandleBackend;
 use crate::logger::{error, warn};
 
 impl VhostUserBlock {
     const PROCESS_ACTIVATE: u32 = 0;
+    const PROCESS_BACKEND: u32 = 1;
+    const PROCESS_RECONNECT: u32 = 2;
+
+    fn backend_events(&self) -> Events {
+        Events::with_data_raw(
+            self.vu_handle.vu.socket_fd(),
+            Self::PROCESS_BACKEND,
+            EventSet::IN | EventSet::READ_HANG_UP,
+        )
+    }
+
+    fn register_runtime_events(&self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(self.backend_events()) {
+            error!("Failed to register vhost-user backend event: {}", err);
+        }
+        if let Err(err) = ops.add(Events::with_data(
+            &self.reconnect_timer,
+            Self::PROCESS_RECONNECT,
+            EventSet::IN,
+        )) {
+            error!("Failed to register vhost-user reconnect event: {}", err);
+        }
+    }
 
     fn register_activate_event(&self, ops: &mut EventOps) {
         if let Err(err) = ops.add(Events::with_data(
@@ -24,6 +50,7 @@ impl VhostUserBlock {
         if let Err(err) = self.activate_evt.read() {
             error!("Failed to consume block activate event: {:?}", err);
         }
+        self.register_runtime_events(ops);
         if let Err(err) = ops.remove(Events::with_data(
             &self.activate_evt,
             Self::PROCESS_ACTIVATE,
@@ -32,6 +59,40 @@ impl VhostUserBlock {
             error!("Failed to un-register activate event: {}", err);
         }
     }
+
+    fn process_backend_event(&mut self, event_set: EventSet, ops: &mut EventOps) {
+        // The frontend only reads the replies of the backend synchronously, so the socket
+        // becoming readable means the backend closed it.
+        if !event_set.intersects(EventSet::READ_HANG_UP | EventSet::HANG_UP | EventSet::ERROR) {
+            warn!(
+                "BlockVhost: Unexpected message from the backend: {:?}",
+                event_set
+            );
+            return;
+        }
+        if let Err(err) = ops.remove(self.backend_events()) {
+            error!("Failed to un-register vhost-user backend event: {}", err);
+        }
+        self.handle_backend_disconnect();
+    }
+
+    fn process_reconnect_event(&mut self, ops: &mut EventOps) {
+        // The timer is non-blocking and only armed once at a time, so the number of
+        // expirations can be ignored.
+        let _ = self.reconnect_timer.read();
+        if !self.disconnected {
+            warn!(
+                "BlockVhost: Spurious reconnect event, fd: {}",
+                self.reconnect_timer.as_raw_fd()
+            );
+            return;
+        }
+        if self.try_reconnect().is_ok() {
+            if let Err(err) = ops.add(self.backend_events()) {
+                error!("Failed to register vhost-user backend event: {}", err);
+            }
+        }
+    }
 }
 
 impl MutEventSubscriber for VhostUserBlock {
@@ -39,8 +100,13 @@ impl MutEventSubscriber for VhostUserBlock {
     fn process(&mut self, event: Events, ops: &mut EventOps) {
         let source = event.data();
         let event_set = event.event_set();
-        let supported_events = EventSet::IN;
 
+        if self.is_activated() && Self::PROCESS_BACKEND == source {
+            self.process_backend_event(event_set, ops);
+            return;
+        }
+
+        let supported_events = EventSet::IN;
         if !supported_events.contains(event_set) {
             warn!(
                 "Received unknown event: {:?} from source: {:?}",
@@ -50,10 +116,10 @@ impl MutEventSubscriber for VhostUserBlock {
         }
 
         if self.is_activated() {
-            if Self::PROCESS_ACTIVATE == source {
-                self.process_activate_event(ops)
-            } else {
-                warn!("BlockVhost: Spurious event received: {:?}", source)
+            match source {
+                Self::PROCESS_ACTIVATE => self.process_activate_event(ops),
+                Self::PROCESS_RECONNECT => self.process_reconnect_event(ops),
+                _ => warn!("BlockVhost: Spurious event received: {:?}", source),
             }
         } else {
             warn!(
@@ -29,4 +29,8 @@ pub enum VhostUserBlockError {
     EventFd(std::io::Error),
     /// Error creating irqfd: {0}
     IrqTrigger(std::io::Error),
+    /// The vhost-user backend no longer supports the negotiated features
+    ReconnectFeatures,
+    /// Error creating the reconnection timer: {0}
+    Timer(std::io::Error),
 }
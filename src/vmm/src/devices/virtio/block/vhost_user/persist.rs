@@ -9,7 +9,7 @@ use super::device::VhostUserBlock;
 use super::VhostUserBlockError;
 use crate::devices::virtio::block::persist::BlockConstructorArgs;
 use crate::devices::virtio::block::CacheType;
-use crate::devices::virtio::persist::VirtioDeviceState;
+use crate::devices::virtio::persist::{SnapshotFeature, VirtioDeviceState};
 use crate::snapshot::Persist;
 
 /// vhost-user block device state.
@@ -25,6 +25,13 @@ pub struct VhostUserBlockState {
     virtio_state: VirtioDeviceState,
 }
 
+impl VhostUserBlockState {
+    /// Snapshot features used by the device.
+    pub fn snapshot_features(&self) -> Vec<SnapshotFeature> {
+        self.virtio_state.snapshot_features()
+    }
+}
+
 impl Persist<'_> for VhostUserBlock {
     type State = VhostUserBlockState;
     type ConstructorArgs = BlockConstructorArgs;
@@ -10,6 +10,7 @@ use std::convert::From;
 use std::fs::{File, OpenOptions};
 use std::io::{Seek, SeekFrom, Write};
 use std::os::linux::fs::MetadataExt;
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
@@ -20,17 +21,18 @@ use utils::eventfd::EventFd;
 use utils::kernel_version::{min_kernel_version_for_io_uring, KernelVersion};
 use utils::u64_to_usize;
 
-use super::io::async_io;
+use super::io::{async_io, CowOverlay, SyncFileEngine};
 use super::request::*;
 use super::{
-    io as block_io, VirtioBlockError, BLOCK_CONFIG_SPACE_SIZE, BLOCK_QUEUE_SIZES, SECTOR_SHIFT,
-    SECTOR_SIZE,
+    io as block_io, VirtioBlockError, BLOCK_CONFIG_SPACE_SIZE, BLOCK_CONFIG_WRITEBACK_OFFSET,
+    BLOCK_QUEUE_SIZES, SECTOR_SHIFT, SECTOR_SIZE,
 };
 use crate::devices::virtio::block::virtio::metrics::{BlockDeviceMetrics, BlockMetricsPerDevice};
 use crate::devices::virtio::block::CacheType;
 use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, VirtioDevice};
 use crate::devices::virtio::gen::virtio_blk::{
-    VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_RO, VIRTIO_BLK_ID_BYTES, VIRTIO_F_VERSION_1,
+    VIRTIO_BLK_F_CONFIG_WCE, VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_RO, VIRTIO_BLK_ID_BYTES,
+    VIRTIO_F_VERSION_1,
 };
 use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
 use crate::devices::virtio::queue::Queue;
@@ -65,17 +67,30 @@ impl FileEngineType {
 #[derive(Debug)]
 pub struct DiskProperties {
     pub file_path: String,
+    pub overlay_path: Option<String>,
     pub file_engine: FileEngine<PendingRequest>,
+    // Signals the completion of the requests of an Async engine. It outlives the engine, so that
+    // the engine can be replaced without registering a new event with the event manager.
+    pub completion_evt: EventFd,
     pub nsectors: u64,
     pub image_id: [u8; VIRTIO_BLK_ID_BYTES as usize],
+    // Whether the requests accessing the disk fail with an I/O error, to inject faults.
+    pub io_fault: bool,
 }
 
 impl DiskProperties {
     // Helper function that opens the file with the proper access permissions
-    fn open_file(disk_image_path: &str, is_disk_read_only: bool) -> Result<File, VirtioBlockError> {
-        OpenOptions::new()
-            .read(true)
-            .write(!is_disk_read_only)
+    fn open_file(
+        disk_image_path: &str,
+        is_disk_read_only: bool,
+        cache_type: CacheType,
+    ) -> Result<File, VirtioBlockError> {
+        let mut options = OpenOptions::new();
+        options.read(true).write(!is_disk_read_only);
+        if cache_type == CacheType::Writethrough {
+            options.custom_flags(libc::O_DSYNC);
+        }
+        options
             .open(PathBuf::from(&disk_image_path))
             .map_err(|x| VirtioBlockError::BackingFile(x, disk_image_path.to_string()))
     }
@@ -100,21 +115,64 @@ impl DiskProperties {
     }
 
     /// Create a new file for the block device using a FileEngine
+    ///
+    /// When `overlay_path` is set, the disk image is opened read-only and the guest writes are
+    /// stored in the copy-on-write overlay at that path.
     pub fn new(
         disk_image_path: String,
+        overlay_path: Option<String>,
         is_disk_read_only: bool,
+        cache_type: CacheType,
+        file_engine_type: FileEngineType,
+    ) -> Result<Self, VirtioBlockError> {
+        let Some(overlay_path) = overlay_path else {
+            let disk_image = Self::open_file(&disk_image_path, is_disk_read_only, cache_type)?;
+            return Self::from_file(disk_image_path, disk_image, file_engine_type);
+        };
+
+        let completion_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(VirtioBlockError::EventFd)?;
+        if is_disk_read_only || file_engine_type != FileEngineType::Sync {
+            return Err(VirtioBlockError::OverlayConfig);
+        }
+        let mut disk_image = Self::open_file(&disk_image_path, true, cache_type)?;
+        let disk_size = Self::file_size(&disk_image_path, &mut disk_image)?;
+        let overlay_file = Self::open_file(&overlay_path, false, cache_type)?;
+        // The overlay holds the guest visible state of the disk.
+        let image_id = Self::build_disk_image_id(&overlay_file);
+        let overlay = CowOverlay::new(overlay_file, disk_size)
+            .map_err(|x| VirtioBlockError::BackingFile(x, overlay_path.clone()))?;
+
+        Ok(Self {
+            file_path: disk_image_path,
+            overlay_path: Some(overlay_path),
+            file_engine: FileEngine::Sync(SyncFileEngine::with_overlay(disk_image, overlay)),
+            completion_evt,
+            nsectors: disk_size >> SECTOR_SHIFT,
+            image_id,
+            io_fault: false,
+        })
+    }
+
+    /// Create a new file for the block device using a FileEngine, from the disk image already
+    /// opened as `disk_image`. `disk_image_path` is only used to identify the disk image.
+    pub fn from_file(
+        disk_image_path: String,
+        mut disk_image: File,
         file_engine_type: FileEngineType,
     ) -> Result<Self, VirtioBlockError> {
-        let mut disk_image = Self::open_file(&disk_image_path, is_disk_read_only)?;
+        let completion_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(VirtioBlockError::EventFd)?;
         let disk_size = Self::file_size(&disk_image_path, &mut disk_image)?;
         let image_id = Self::build_disk_image_id(&disk_image);
 
         Ok(Self {
             file_path: disk_image_path,
-            file_engine: FileEngine::from_file(disk_image, file_engine_type)
+            overlay_path: None,
+            file_engine: FileEngine::from_file(disk_image, file_engine_type, &completion_evt)
                 .map_err(VirtioBlockError::FileEngine)?,
+            completion_evt,
             nsectors: disk_size >> SECTOR_SHIFT,
             image_id,
+            io_fault: false,
         })
     }
 
@@ -123,8 +181,12 @@ impl DiskProperties {
         &mut self,
         disk_image_path: String,
         is_disk_read_only: bool,
+        cache_type: CacheType,
     ) -> Result<(), VirtioBlockError> {
-        let mut disk_image = Self::open_file(&disk_image_path, is_disk_read_only)?;
+        if self.overlay_path.is_some() {
+            return Err(VirtioBlockError::OverlayUpdate);
+        }
+        let mut disk_image = Self::open_file(&disk_image_path, is_disk_read_only, cache_type)?;
         let disk_size = Self::file_size(&disk_image_path, &mut disk_image)?;
 
         self.image_id = Self::build_disk_image_id(&disk_image);
@@ -137,6 +199,25 @@ impl DiskProperties {
         Ok(())
     }
 
+    /// Replaces the IO engine of the backing file with one of type `file_engine_type`. The
+    /// requests in flight on the current engine are dropped, so they must have completed.
+    pub fn update_file_engine(
+        &mut self,
+        file_engine_type: FileEngineType,
+    ) -> Result<(), VirtioBlockError> {
+        if self.overlay_path.is_some() && file_engine_type != FileEngineType::Sync {
+            return Err(VirtioBlockError::OverlayConfig);
+        }
+        let file = self
+            .file_engine
+            .file()
+            .try_clone()
+            .map_err(|x| VirtioBlockError::BackingFile(x, self.file_path.clone()))?;
+        self.file_engine = FileEngine::from_file(file, file_engine_type, &self.completion_evt)
+            .map_err(VirtioBlockError::FileEngine)?;
+        Ok(())
+    }
+
     fn build_device_id(disk_file: &File) -> Result<String, VirtioBlockError> {
         let blk_metadata = disk_file
             .metadata()
@@ -194,8 +275,8 @@ pub struct VirtioBlockConfig {
     /// Setting this flag to true will mount the block device in the
     /// guest under /dev/vda unless the partuuid is present.
     pub is_root_device: bool,
-    /// If set to true, the drive will ignore flush requests coming from
-    /// the guest driver.
+    /// Caching strategy of the drive, which decides whether flush requests
+    /// are advertised to and honored for the guest driver.
     #[serde(default)]
     pub cache_type: CacheType,
 
@@ -210,13 +291,16 @@ pub struct VirtioBlockConfig {
     #[serde(default)]
     #[serde(rename = "io_engine")]
     pub file_engine_type: FileEngineType,
+    /// Path of the copy-on-write overlay of the backing file on the host.
+    #[serde(default)]
+    pub overlay_path: Option<String>,
 }
 
 impl TryFrom<&BlockDeviceConfig> for VirtioBlockConfig {
     type Error = VirtioBlockError;
 
     fn try_from(value: &BlockDeviceConfig) -> Result<Self, Self::Error> {
-        if value.path_on_host.is_some() && value.socket.is_none() {
+        if value.path_on_host.is_some() && value.socket.is_none() && value.reconnect.is_none() {
             Ok(Self {
                 drive_id: value.drive_id.clone(),
                 partuuid: value.partuuid.clone(),
@@ -227,6 +311,7 @@ impl TryFrom<&BlockDeviceConfig> for VirtioBlockConfig {
                 path_on_host: value.path_on_host.as_ref().unwrap().clone(),
                 rate_limiter: value.rate_limiter,
                 file_engine_type: value.file_engine_type.unwrap_or_default(),
+                overlay_path: value.overlay_path.clone(),
             })
         } else {
             Err(VirtioBlockError::Config)
@@ -246,8 +331,12 @@ impl From<VirtioBlockConfig> for BlockDeviceConfig {
             path_on_host: Some(value.path_on_host),
             rate_limiter: value.rate_limiter,
             file_engine_type: Some(value.file_engine_type),
+            overlay_path: value.overlay_path,
 
             socket: None,
+            reconnect: None,
+
+            worker: false,
         }
     }
 }
@@ -271,6 +360,9 @@ pub struct VirtioBlock {
     pub id: String,
     pub partuuid: Option<String>,
     pub cache_type: CacheType,
+    /// Whether the write cache is enabled, which the guest driver can toggle through the
+    /// `writeback` field of the config space on devices with the `Writeback` cache type.
+    pub writeback: bool,
     pub root_device: bool,
     pub read_only: bool,
 
@@ -278,6 +370,9 @@ pub struct VirtioBlock {
     pub disk: DiskProperties,
     pub rate_limiter: RateLimiter,
     pub is_io_engine_throttled: bool,
+    /// Descriptor chain heads of the requests that were in flight when the snapshot the device
+    /// was restored from was taken. They are submitted again before processing the queue.
+    pub replay_requests: Vec<u16>,
     pub metrics: Arc<BlockDeviceMetrics>,
 }
 
@@ -300,7 +395,9 @@ impl VirtioBlock {
     pub fn new(config: VirtioBlockConfig) -> Result<VirtioBlock, VirtioBlockError> {
         let disk_properties = DiskProperties::new(
             config.path_on_host,
+            config.overlay_path,
             config.is_read_only,
+            config.cache_type,
             config.file_engine_type,
         )?;
 
@@ -314,7 +411,7 @@ impl VirtioBlock {
         let mut avail_features = (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_RING_F_EVENT_IDX);
 
         if config.cache_type == CacheType::Writeback {
-            avail_features |= 1u64 << VIRTIO_BLK_F_FLUSH;
+            avail_features |= (1u64 << VIRTIO_BLK_F_FLUSH) | (1u64 << VIRTIO_BLK_F_CONFIG_WCE);
         }
 
         if config.is_read_only {
@@ -325,10 +422,11 @@ impl VirtioBlock {
 
         let queues = BLOCK_QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
 
+        let writeback = config.cache_type == CacheType::Writeback;
         Ok(VirtioBlock {
             avail_features,
             acked_features: 0u64,
-            config_space: disk_properties.virtio_block_config_space(),
+            config_space: Self::build_config_space(&disk_properties, avail_features, writeback),
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(VirtioBlockError::EventFd)?,
 
             queues,
@@ -339,16 +437,51 @@ impl VirtioBlock {
             id: config.drive_id.clone(),
             partuuid: config.partuuid,
             cache_type: config.cache_type,
+            writeback,
             root_device: config.is_root_device,
             read_only: config.is_read_only,
 
             disk: disk_properties,
             rate_limiter,
             is_io_engine_throttled: false,
+            replay_requests: Vec::new(),
             metrics: BlockMetricsPerDevice::alloc(config.drive_id),
         })
     }
 
+    /// Builds the config space of the device: the capacity of the disk, followed by the
+    /// `writeback` field when the device offers `VIRTIO_BLK_F_CONFIG_WCE`.
+    pub(crate) fn build_config_space(
+        disk: &DiskProperties,
+        avail_features: u64,
+        writeback: bool,
+    ) -> Vec<u8> {
+        let mut config = disk.virtio_block_config_space();
+        if avail_features & (1u64 << VIRTIO_BLK_F_CONFIG_WCE) != 0 {
+            config.resize(BLOCK_CONFIG_WRITEBACK_OFFSET + 1, 0);
+            config[BLOCK_CONFIG_WRITEBACK_OFFSET] = u8::from(writeback);
+        }
+        config
+    }
+
+    // Writes are synced to the host storage when the guest disabled the write cache it was
+    // offered.
+    fn sync_writes(&self) -> bool {
+        self.cache_type == CacheType::Writeback && !self.writeback
+    }
+
+    // Enables or disables the write cache. The data written while the cache was enabled is
+    // flushed before disabling it.
+    fn set_writeback(&mut self, writeback: bool) {
+        if self.writeback && !writeback {
+            if let Err(err) = self.disk.file_engine.sync() {
+                error!("Failed to flush block data: {:?}", err);
+            }
+        }
+        self.writeback = writeback;
+        self.config_space[BLOCK_CONFIG_WRITEBACK_OFFSET] = u8::from(writeback);
+    }
+
     /// Returns a copy of a device config
     pub fn config(&self) -> VirtioBlockConfig {
         let rl: RateLimiterConfig = (&self.rate_limiter).into();
@@ -361,6 +494,7 @@ impl VirtioBlock {
             cache_type: self.cache_type,
             rate_limiter: rl.into_option(),
             file_engine_type: self.file_engine_type(),
+            overlay_path: self.disk.overlay_path.clone(),
         }
     }
 
@@ -370,7 +504,7 @@ impl VirtioBlock {
     /// about new buffers in the queue.
     pub(crate) fn process_queue_event(&mut self) {
         self.metrics.queue_event_count.inc();
-        if let Err(err) = self.queue_evts[0].read() {
+        if let Err(err) = self.queues[0].read_kicks(&self.queue_evts[0]) {
             error!("Failed to get queue event: {:?}", err);
             self.metrics.event_fails.inc();
         } else if self.rate_limiter.is_blocked() {
@@ -396,16 +530,24 @@ impl VirtioBlock {
         }
     }
 
-    fn add_used_descriptor(
+    // Publishes the finished requests, as `(descriptor head, bytes written)` pairs, to the
+    // guest all at once and notifies it if needed.
+    fn add_used_descriptors(
         queue: &mut Queue,
-        index: u16,
-        len: u32,
+        used: &[(u16, u32)],
         mem: &GuestMemoryMmap,
         irq_trigger: &IrqTrigger,
         block_metrics: &BlockDeviceMetrics,
     ) {
-        queue.add_used(mem, index, len).unwrap_or_else(|err| {
-            error!("Failed to add available descriptor head {}: {}", index, err)
+        if used.is_empty() {
+            return;
+        }
+
+        queue.add_used_batch(mem, used).unwrap_or_else(|err| {
+            error!(
+                "Failed to add available descriptor heads {:?}: {}",
+                used, err
+            )
         });
 
         if queue.prepare_kick(mem) {
@@ -415,13 +557,90 @@ impl VirtioBlock {
         }
     }
 
+    // Submits again the requests that were in flight when the snapshot the device was restored
+    // from was taken, as the guest still waits for them. Returns whether all of them were
+    // submitted, the remaining ones being replayed once the IO engine has room for them.
+    fn replay_inflight_requests(&mut self) -> bool {
+        if self.replay_requests.is_empty() {
+            return true;
+        }
+
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+        let sync_writes = self.sync_writes();
+        let queue = &mut self.queues[0];
+        let mut used = Vec::new();
+        let mut replayed = 0;
+
+        for &desc_idx in &self.replay_requests {
+            replayed += 1;
+            let Some(head) = queue.descriptor_chain(mem, desc_idx) else {
+                error!(
+                    "Failed to replay in-flight block request: {}",
+                    VirtioBlockError::InvalidDescriptorHead(desc_idx)
+                );
+                self.metrics.execute_fails.inc();
+                continue;
+            };
+            let processing_result = match Request::parse(&head, mem, self.disk.nsectors) {
+                Ok(request) => request.process(
+                    &mut self.disk,
+                    self.cache_type,
+                    sync_writes,
+                    desc_idx,
+                    mem,
+                    &self.metrics,
+                ),
+                Err(err) => {
+                    error!("Failed to replay in-flight block request: {:?}", err);
+                    self.metrics.execute_fails.inc();
+                    ProcessingResult::Executed(FinishedRequest {
+                        num_bytes_to_mem: 0,
+                        desc_idx,
+                    })
+                }
+            };
+
+            match processing_result {
+                ProcessingResult::Submitted => {}
+                ProcessingResult::Throttled => {
+                    self.is_io_engine_throttled = true;
+                    replayed -= 1;
+                    break;
+                }
+                ProcessingResult::Executed(finished) => {
+                    used.push((desc_idx, finished.num_bytes_to_mem));
+                }
+            }
+        }
+        self.replay_requests.drain(..replayed);
+
+        Self::add_used_descriptors(queue, &used, mem, &self.irq_trigger, &self.metrics);
+
+        if let FileEngine::Async(ref mut engine) = self.disk.file_engine {
+            if let Err(err) = engine.kick_submission_queue() {
+                error!("BlockError submitting replayed block requests: {:?}", err);
+            }
+        }
+
+        self.replay_requests.is_empty()
+    }
+
     /// Device specific function for peaking inside a queue and processing descriptors.
     pub fn process_queue(&mut self, queue_index: usize) {
+        if self.irq_trigger.needs_reset() || !self.replay_inflight_requests() {
+            return;
+        }
+
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
 
+        let sync_writes = self.sync_writes();
         let queue = &mut self.queues[queue_index];
         let mut used_any = false;
+        let mut used = Vec::new();
+        let mut failed = false;
+        self.metrics.queue_depth_hist.record(queue.len(mem).into());
 
         while let Some(head) = queue.pop_or_enable_notification(mem) {
             self.metrics.remaining_reqs_count.add(queue.len(mem).into());
@@ -436,7 +655,21 @@ impl VirtioBlock {
                     }
 
                     used_any = true;
-                    request.process(&mut self.disk, head.index, mem, &self.metrics)
+                    queue.trace_backend(head.index);
+                    request.process(
+                        &mut self.disk,
+                        self.cache_type,
+                        sync_writes,
+                        head.index,
+                        mem,
+                        &self.metrics,
+                    )
+                }
+                Err(err @ VirtioBlockError::GuestMemory(_)) => {
+                    error!("Malformed descriptor chain, failing the device: {:?}", err);
+                    self.metrics.execute_fails.inc();
+                    failed = true;
+                    break;
                 }
                 Err(err) => {
                     error!("Failed to parse available descriptor chain: {:?}", err);
@@ -456,18 +689,13 @@ impl VirtioBlock {
                     break;
                 }
                 ProcessingResult::Executed(finished) => {
-                    Self::add_used_descriptor(
-                        queue,
-                        head.index,
-                        finished.num_bytes_to_mem,
-                        mem,
-                        &self.irq_trigger,
-                        &self.metrics,
-                    );
+                    used.push((head.index, finished.num_bytes_to_mem));
                 }
             }
         }
 
+        Self::add_used_descriptors(queue, &used, mem, &self.irq_trigger, &self.metrics);
+
         if let FileEngine::Async(ref mut engine) = self.disk.file_engine {
             if let Err(err) = engine.kick_submission_queue() {
                 error!("BlockError submitting pending block requests: {:?}", err);
@@ -477,14 +705,30 @@ impl VirtioBlock {
         if !used_any {
             self.metrics.no_avail_buffer.inc();
         }
+
+        if failed {
+            self.fail_device();
+        }
+    }
+
+    // Stops processing the requests of a driver that made a descriptor chain the device cannot
+    // use, until the driver resets the device.
+    fn fail_device(&self) {
+        if let Err(err) = self.irq_trigger.signal_device_failure() {
+            error!("Failed to signal the block device failure: {:?}", err);
+        }
     }
 
     fn process_async_completion_queue(&mut self) {
+        let sync_writes = self.sync_writes();
         let engine = unwrap_async_file_engine_or_return!(&mut self.disk.file_engine);
+        // A single sync covers all the writes completed so far.
+        let mut synced = false;
 
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
         let queue = &mut self.queues[0];
+        let mut used = Vec::new();
 
         loop {
             match engine.pop(mem) {
@@ -498,6 +742,22 @@ impl VirtioBlock {
                     let user_data = cqe.user_data();
 
                     let (pending, res) = match res {
+                        Ok(count)
+                            if sync_writes
+                                && !synced
+                                && user_data.request_type() == RequestType::Out =>
+                        {
+                            match engine.sync() {
+                                Ok(()) => {
+                                    synced = true;
+                                    (user_data, Ok(count))
+                                }
+                                Err(error) => (
+                                    user_data,
+                                    Err(IoErr::FileEngine(block_io::BlockIoError::Async(error))),
+                                ),
+                            }
+                        }
                         Ok(count) => (user_data, Ok(count)),
                         Err(error) => (
                             user_data,
@@ -507,26 +767,20 @@ impl VirtioBlock {
                         ),
                     };
                     let finished = pending.finish(mem, res, &self.metrics);
-
-                    Self::add_used_descriptor(
-                        queue,
-                        finished.desc_idx,
-                        finished.num_bytes_to_mem,
-                        mem,
-                        &self.irq_trigger,
-                        &self.metrics,
-                    );
+                    used.push((finished.desc_idx, finished.num_bytes_to_mem));
                 }
             }
         }
+
+        Self::add_used_descriptors(queue, &used, mem, &self.irq_trigger, &self.metrics);
     }
 
     pub fn process_async_completion_event(&mut self) {
-        let engine = unwrap_async_file_engine_or_return!(&mut self.disk.file_engine);
-
-        if let Err(err) = engine.completion_evt().read() {
+        // The event is consumed even if the device switched to the Sync engine in the meantime,
+        // as the completions it signals were processed when switching.
+        if let Err(err) = self.disk.completion_evt.read() {
             error!("Failed to get async completion event: {:?}", err);
-        } else {
+        } else if let FileEngine::Async(_) = self.disk.file_engine {
             self.process_async_completion_queue();
 
             if self.is_io_engine_throttled {
@@ -538,11 +792,15 @@ impl VirtioBlock {
 
     /// Update the backing file and the config space of the block device.
     pub fn update_disk_image(&mut self, disk_image_path: String) -> Result<(), VirtioBlockError> {
-        self.disk.update(disk_image_path, self.read_only)?;
-        self.config_space = self.disk.virtio_block_config_space();
+        self.disk
+            .update(disk_image_path, self.read_only, self.cache_type)?;
+        self.config_space =
+            Self::build_config_space(&self.disk, self.avail_features, self.writeback);
 
         // Kick the driver to pick up the changes.
-        self.irq_trigger.trigger_irq(IrqType::Config).unwrap();
+        self.irq_trigger
+            .signal_config_update()
+            .map_err(VirtioBlockError::IrqTrigger)?;
 
         self.metrics.update_count.inc();
         Ok(())
@@ -553,6 +811,25 @@ impl VirtioBlock {
         self.rate_limiter.update_buckets(bytes, ops);
     }
 
+    /// Switches the device to an IO engine of type `file_engine_type`, once the requests in
+    /// flight on the current engine completed.
+    pub fn update_file_engine(
+        &mut self,
+        file_engine_type: FileEngineType,
+    ) -> Result<(), VirtioBlockError> {
+        if file_engine_type == self.file_engine_type() {
+            return Ok(());
+        }
+
+        self.drain();
+        self.disk.update_file_engine(file_engine_type)?;
+        // Submit to the new engine the requests the previous one had no room for.
+        if std::mem::take(&mut self.is_io_engine_throttled) {
+            self.process_queue(0);
+        }
+        Ok(())
+    }
+
     /// Retrieve the file engine type.
     pub fn file_engine_type(&self) -> FileEngineType {
         match self.disk.file_engine {
@@ -567,6 +844,31 @@ impl VirtioBlock {
         }
     }
 
+    /// Complete the inflight requests, so that the device stops writing to the guest memory
+    /// until the guest submits new ones.
+    pub fn drain(&mut self) {
+        if !self.is_activated() {
+            return;
+        }
+
+        if let Err(err) = self.disk.file_engine.drain(false) {
+            error!("Failed to drain block ops: {:?}", err);
+        }
+        if let FileEngine::Async(ref _engine) = self.disk.file_engine {
+            self.process_async_completion_queue();
+        }
+    }
+
+    /// Descriptor chain heads of the requests the guest is still waiting for: the ones submitted
+    /// to the IO engine and not completed yet, and the ones not replayed yet after a restore.
+    pub fn inflight_requests(&self) -> Vec<u16> {
+        let mut requests = self.replay_requests.clone();
+        if let FileEngine::Async(ref engine) = self.disk.file_engine {
+            requests.extend(engine.inflight().map(PendingRequest::desc_idx));
+        }
+        requests
+    }
+
     /// Prepare device for being snapshotted.
     pub fn prepare_save(&mut self) {
         if !self.is_activated() {
@@ -585,6 +887,10 @@ impl VirtioDevice for VirtioBlock {
         self.avail_features
     }
 
+    fn set_avail_features(&mut self, avail_features: u64) {
+        self.avail_features = avail_features;
+    }
+
     fn acked_features(&self) -> u64 {
         self.acked_features
     }
@@ -618,6 +924,10 @@ impl VirtioDevice for VirtioBlock {
         self.irq_trigger.irq_status.clone()
     }
 
+    fn needs_reset(&self) -> bool {
+        self.irq_trigger.needs_reset()
+    }
+
     fn read_config(&self, offset: u64, mut data: &mut [u8]) {
         let config_len = self.config_space.len() as u64;
         if offset >= config_len {
@@ -635,18 +945,24 @@ impl VirtioDevice for VirtioBlock {
     }
 
     fn write_config(&mut self, offset: u64, data: &[u8]) {
-        let start = usize::try_from(offset).ok();
-        let end = start.and_then(|s| s.checked_add(data.len()));
-        let Some(dst) = start
-            .zip(end)
-            .and_then(|(start, end)| self.config_space.get_mut(start..end))
-        else {
-            error!("Failed to write config space");
-            self.metrics.cfg_fails.inc();
-            return;
-        };
-
-        dst.copy_from_slice(data);
+        // The `writeback` field is the only writable field, once the driver negotiated
+        // `VIRTIO_BLK_F_CONFIG_WCE`.
+        let wce = self.has_feature(u64::from(VIRTIO_BLK_F_CONFIG_WCE));
+        match (offset, data) {
+            (offset, [writeback @ (0 | 1)])
+                if wce && offset == BLOCK_CONFIG_WRITEBACK_OFFSET as u64 =>
+            {
+                self.set_writeback(*writeback == 1);
+            }
+            _ => {
+                error!(
+                    "Failed to write config space (offset={:#x}, len={:#x})",
+                    offset,
+                    data.len()
+                );
+                self.metrics.cfg_fails.inc();
+            }
+        }
     }
 
     fn activate(&mut self, mem: GuestMemoryMmap) -> Result<(), ActivateError> {
@@ -673,7 +989,7 @@ impl VirtioDevice for VirtioBlock {
 impl Drop for VirtioBlock {
     fn drop(&mut self) {
         match self.cache_type {
-            CacheType::Unsafe => {
+            CacheType::Unsafe | CacheType::Writethrough => {
                 if let Err(err) = self.disk.file_engine.drain(true) {
                     error!("Failed to drain ops on drop: {:?}", err);
                 }
@@ -690,6 +1006,8 @@ mod tests {
     use std::fs::metadata;
     use std::io::Read;
     use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::FileExt;
+    use std::os::unix::io::AsRawFd;
     use std::time::Duration;
     use std::{thread, u32};
 
@@ -699,8 +1017,8 @@ mod tests {
     use super::*;
     use crate::check_metric_after_block;
     use crate::devices::virtio::block::virtio::test_utils::{
-        default_block, default_engine_type_for_kv, read_blk_req_descriptors, set_queue,
-        set_rate_limiter, simulate_async_completion_event,
+        default_block, default_block_with_path, default_engine_type_for_kv,
+        read_blk_req_descriptors, set_queue, set_rate_limiter, simulate_async_completion_event,
         simulate_queue_and_async_completion_events, simulate_queue_event,
     };
     use crate::devices::virtio::block::virtio::IO_URING_NUM_ENTRIES;
@@ -721,8 +1039,11 @@ mod tests {
             path_on_host: Some("path".to_string()),
             rate_limiter: None,
             file_engine_type: Default::default(),
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
         VirtioBlockConfig::try_from(&block_config).unwrap();
 
@@ -736,8 +1057,11 @@ mod tests {
             path_on_host: None,
             rate_limiter: None,
             file_engine_type: Default::default(),
+            overlay_path: None,
 
             socket: Some("sock".to_string()),
+            reconnect: None,
+            worker: false,
         };
         VirtioBlockConfig::try_from(&block_config).unwrap_err();
 
@@ -751,8 +1075,11 @@ mod tests {
             path_on_host: Some("path".to_string()),
             rate_limiter: None,
             file_engine_type: Default::default(),
+            overlay_path: None,
 
             socket: Some("sock".to_string()),
+            reconnect: None,
+            worker: false,
         };
         VirtioBlockConfig::try_from(&block_config).unwrap_err();
     }
@@ -766,7 +1093,9 @@ mod tests {
 
         let disk_properties = DiskProperties::new(
             String::from(f.as_path().to_str().unwrap()),
+            None,
             true,
+            CacheType::Unsafe,
             default_engine_type_for_kv(),
         )
         .unwrap();
@@ -783,7 +1112,9 @@ mod tests {
 
         let res = DiskProperties::new(
             "invalid-disk-path".to_string(),
+            None,
             true,
+            CacheType::Unsafe,
             default_engine_type_for_kv(),
         );
         assert!(
@@ -793,6 +1124,114 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_disk_cache_type() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(u64::from(SECTOR_SIZE)).unwrap();
+        let path = String::from(f.as_path().to_str().unwrap());
+        let open_flags = |disk: &DiskProperties| {
+            // SAFETY: The file descriptor is valid.
+            unsafe { libc::fcntl(disk.file_engine.file().as_raw_fd(), libc::F_GETFL) }
+        };
+
+        let mut disk = DiskProperties::new(
+            path.clone(),
+            None,
+            false,
+            CacheType::Writeback,
+            FileEngineType::Sync,
+        )
+        .unwrap();
+        assert_eq!(open_flags(&disk) & libc::O_DSYNC, 0);
+
+        // Writes only complete once on the backing storage in `Writethrough` mode.
+        disk.update(path.clone(), false, CacheType::Writethrough)
+            .unwrap();
+        assert_eq!(open_flags(&disk) & libc::O_DSYNC, libc::O_DSYNC);
+
+        let disk = DiskProperties::new(
+            path,
+            None,
+            false,
+            CacheType::Writethrough,
+            FileEngineType::Sync,
+        )
+        .unwrap();
+        assert_eq!(open_flags(&disk) & libc::O_DSYNC, libc::O_DSYNC);
+    }
+
+    #[test]
+    fn test_disk_overlay() {
+        let base = TempFile::new().unwrap();
+        base.as_file().write_all(&[0xaa; 0x1000]).unwrap();
+        let base_path = String::from(base.as_path().to_str().unwrap());
+        let overlay = TempFile::new().unwrap();
+        let overlay_path = String::from(overlay.as_path().to_str().unwrap());
+
+        // Overlays need a writable drive and the Sync engine.
+        for (read_only, engine) in [(true, FileEngineType::Sync), (false, FileEngineType::Async)] {
+            let res = DiskProperties::new(
+                base_path.clone(),
+                Some(overlay_path.clone()),
+                read_only,
+                CacheType::Unsafe,
+                engine,
+            );
+            assert!(
+                matches!(res, Err(VirtioBlockError::OverlayConfig)),
+                "{:?}",
+                res
+            );
+        }
+
+        let config = VirtioBlockConfig {
+            drive_id: "test".to_string(),
+            partuuid: None,
+            is_root_device: false,
+            cache_type: CacheType::Unsafe,
+            is_read_only: false,
+            path_on_host: base_path.clone(),
+            rate_limiter: None,
+            file_engine_type: FileEngineType::Sync,
+            overlay_path: Some(overlay_path.clone()),
+        };
+        let mut block = VirtioBlock::new(config).unwrap();
+        assert_eq!(block.config().overlay_path, Some(overlay_path.clone()));
+        assert_eq!(block.disk.nsectors, 0x1000 >> SECTOR_SHIFT);
+        // The base image is only opened for reading.
+        // SAFETY: The file descriptor is valid.
+        let flags =
+            unsafe { libc::fcntl(block.disk.file_engine.file().as_raw_fd(), libc::F_GETFL) };
+        assert_eq!(flags & libc::O_ACCMODE, libc::O_RDONLY);
+        // The new overlay only holds its metadata, after the clusters.
+        assert_eq!(overlay.as_file().metadata().unwrap().len(), 0x1000 + 24 + 8);
+
+        let mem = default_mem();
+        let FileEngine::Sync(ref mut engine) = block.disk.file_engine else {
+            panic!("Overlays use the Sync engine");
+        };
+        assert!(engine.has_overlay());
+        mem.write_slice(&[0x55; 0x200], GuestAddress(0)).unwrap();
+        engine.write(0x200, &mem, GuestAddress(0), 0x200).unwrap();
+        engine.flush().unwrap();
+        engine.read(0, &mem, GuestAddress(0x1000), 0x1000).unwrap();
+        let mut data = [0u8; 0x1000];
+        mem.read_slice(&mut data, GuestAddress(0x1000)).unwrap();
+        assert!(data[..0x200].iter().all(|&b| b == 0xaa));
+        assert!(data[0x200..0x400].iter().all(|&b| b == 0x55));
+        assert!(data[0x400..].iter().all(|&b| b == 0xaa));
+
+        // The guest writes don't reach the base image.
+        let mut base_data = [0u8; 0x1000];
+        base.as_file().read_exact_at(&mut base_data, 0).unwrap();
+        assert!(base_data.iter().all(|&b| b == 0xaa));
+
+        assert!(matches!(
+            block.update_disk_image(base_path),
+            Err(VirtioBlockError::OverlayUpdate)
+        ));
+    }
+
     #[test]
     fn test_virtio_features() {
         let mut block = default_block(default_engine_type_for_kv());
@@ -843,35 +1282,70 @@ mod tests {
     #[test]
     fn test_virtio_write_config() {
         let mut block = default_block(default_engine_type_for_kv());
+        for i in 0..10 {
+            block.ack_features_by_page(i, u32::MAX);
+        }
 
-        let expected_config_space: [u8; BLOCK_CONFIG_SPACE_SIZE] =
-            [0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
-        block.write_config(0, &expected_config_space);
+        // The capacity is read-only.
+        let config_space = block.config_space.clone();
+        block.write_config(0, &[0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        block.write_config(1, &[0x11]);
+        assert_eq!(block.config_space, config_space);
 
-        let mut actual_config_space = [0u8; BLOCK_CONFIG_SPACE_SIZE];
-        block.read_config(0, &mut actual_config_space);
-        assert_eq!(actual_config_space, expected_config_space);
+        // The device doesn't offer `VIRTIO_BLK_F_CONFIG_WCE` without a write cache.
+        block.write_config(BLOCK_CONFIG_WRITEBACK_OFFSET as u64, &[0]);
+        assert_eq!(block.config_space, config_space);
+        assert!(!block.writeback);
 
-        // If priviledged user writes to `/dev/mem`, in block config space - byte by byte.
-        let expected_config_space = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x00, 0x11];
-        for i in 0..expected_config_space.len() {
-            block.write_config(i as u64, &expected_config_space[i..=i]);
-        }
-        block.read_config(0, &mut actual_config_space);
-        assert_eq!(actual_config_space, expected_config_space);
+        // Large offset that may cause an overflow.
+        block.write_config(u64::MAX, &[0]);
+        assert_eq!(block.config_space, config_space);
+    }
 
-        // Invalid write.
-        let new_config_space = [0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
-        block.write_config(5, &new_config_space);
-        // Make sure nothing got written.
-        block.read_config(0, &mut actual_config_space);
-        assert_eq!(actual_config_space, expected_config_space);
+    #[test]
+    fn test_virtio_write_config_writeback() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let mut config = default_block_with_path(
+            f.as_path().to_str().unwrap().to_string(),
+            default_engine_type_for_kv(),
+        )
+        .config();
+        config.cache_type = CacheType::Writeback;
+        let mut block = VirtioBlock::new(config).unwrap();
 
-        // Large offset that may cause an overflow.
-        block.write_config(u64::MAX, &new_config_space);
-        // Make sure nothing got written.
-        block.read_config(0, &mut actual_config_space);
-        assert_eq!(actual_config_space, expected_config_space);
+        assert_ne!(block.avail_features & (1u64 << VIRTIO_BLK_F_CONFIG_WCE), 0);
+        assert!(block.writeback);
+        let mut writeback = [0u8];
+        block.read_config(BLOCK_CONFIG_WRITEBACK_OFFSET as u64, &mut writeback);
+        assert_eq!(writeback, [1]);
+
+        // The `writeback` field is read-only until the driver acks `VIRTIO_BLK_F_CONFIG_WCE`.
+        block.write_config(BLOCK_CONFIG_WRITEBACK_OFFSET as u64, &[0]);
+        assert!(block.writeback);
+
+        for i in 0..10 {
+            block.ack_features_by_page(i, u32::MAX);
+        }
+
+        // The driver disables the write cache, so that writes are synced to the disk.
+        block.write_config(BLOCK_CONFIG_WRITEBACK_OFFSET as u64, &[0]);
+        assert!(!block.writeback);
+        assert!(block.sync_writes());
+        block.read_config(BLOCK_CONFIG_WRITEBACK_OFFSET as u64, &mut writeback);
+        assert_eq!(writeback, [0]);
+
+        // Invalid values and sizes are rejected.
+        block.write_config(BLOCK_CONFIG_WRITEBACK_OFFSET as u64, &[2]);
+        block.write_config(BLOCK_CONFIG_WRITEBACK_OFFSET as u64, &[1, 0]);
+        assert!(!block.writeback);
+
+        // The driver enables the write cache again.
+        block.write_config(BLOCK_CONFIG_WRITEBACK_OFFSET as u64, &[1]);
+        assert!(block.writeback);
+        assert!(!block.sync_writes());
+        block.read_config(BLOCK_CONFIG_WRITEBACK_OFFSET as u64, &mut writeback);
+        assert_eq!(writeback, [1]);
     }
 
     #[test]
@@ -961,6 +1435,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_header_out_of_bounds() {
+        let mut block = default_block(default_engine_type_for_kv());
+        // Default mem size is 0x10000
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        read_blk_req_descriptors(&vq);
+
+        // The request header is outside of the guest memory.
+        vq.avail.idx.set(1);
+        vq.dtable[0].set(0x20000, 0x1000, VIRTQ_DESC_F_NEXT, 1);
+        simulate_queue_event(&mut block, None);
+
+        // Check that the device failed, without using the descriptor chain.
+        assert!(block.needs_reset());
+        assert!(block.irq_trigger.has_pending_irq(IrqType::Config));
+        assert_eq!(vq.used.idx.get(), 0);
+
+        // The device ignores the requests until the driver resets it.
+        read_blk_req_descriptors(&vq);
+        vq.avail.ring[1].set(0);
+        vq.avail.idx.set(2);
+        simulate_queue_event(&mut block, Some(false));
+        assert_eq!(vq.used.idx.get(), 0);
+    }
+
     #[test]
     fn test_request_parse_failures() {
         let mut block = default_block(default_engine_type_for_kv());
@@ -1398,6 +1900,7 @@ mod tests {
     #[test]
     fn test_flush() {
         let mut block = default_block(default_engine_type_for_kv());
+        block.cache_type = CacheType::Writeback;
         let mem = default_mem();
         let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
         set_queue(&mut block, 0, vq.create_queue());
@@ -1439,6 +1942,68 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_flush_without_write_cache() {
+        for cache_type in [CacheType::Unsafe, CacheType::Writethrough] {
+            let mut block = default_block(default_engine_type_for_kv());
+            block.cache_type = cache_type;
+            let mem = default_mem();
+            let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+            set_queue(&mut block, 0, vq.create_queue());
+            block.activate(mem.clone()).unwrap();
+            read_blk_req_descriptors(&vq);
+
+            let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+            let status_addr = GuestAddress(vq.dtable[2].addr.get());
+            vq.dtable[0].next.set(2);
+            mem.write_obj::<u32>(VIRTIO_BLK_T_FLUSH, request_type_addr)
+                .unwrap();
+
+            // The flush is completed right away, without going through the IO engine.
+            simulate_queue_event(&mut block, Some(true));
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().id, 0);
+            assert_eq!(vq.used.ring[0].get().len, 1);
+            assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+        }
+    }
+
+    #[test]
+    fn test_io_fault() {
+        let mut block = default_block(default_engine_type_for_kv());
+        block.cache_type = CacheType::Writeback;
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        read_blk_req_descriptors(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        vq.dtable[0].next.set(2);
+        mem.write_obj::<u32>(VIRTIO_BLK_T_FLUSH, request_type_addr)
+            .unwrap();
+
+        // The requests fail without going through the IO engine while the fault is injected.
+        block.disk.io_fault = true;
+        simulate_queue_event(&mut block, Some(true));
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(vq.used.ring[0].get().id, 0);
+        assert_eq!(vq.used.ring[0].get().len, 1);
+        assert_eq!(
+            mem.read_obj::<u32>(status_addr).unwrap(),
+            VIRTIO_BLK_S_IOERR
+        );
+
+        // The requests succeed again once the fault is no longer injected.
+        block.disk.io_fault = false;
+        vq.used.idx.set(0);
+        set_queue(&mut block, 0, vq.create_queue());
+        simulate_queue_and_async_completion_events(&mut block, true);
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+    }
+
     #[test]
     fn test_get_device_id() {
         let mut block = default_block(default_engine_type_for_kv());
@@ -1502,6 +2067,8 @@ mod tests {
     }
 
     fn add_flush_requests_batch(block: &mut VirtioBlock, vq: &VirtQueue, count: u16) {
+        // Flush requests only reach the IO engine in `Writeback` mode.
+        block.cache_type = CacheType::Writeback;
         let mem = vq.memory();
         vq.avail.idx.set(0);
         vq.used.idx.set(0);
@@ -1625,6 +2192,45 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_update_file_engine() {
+        skip_if_io_uring_unsupported!();
+
+        let mut block = default_block(FileEngineType::Sync);
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, IO_URING_NUM_ENTRIES * 4);
+        block.activate(mem.clone()).unwrap();
+
+        // Switching to the current engine is a no-op.
+        block.update_file_engine(FileEngineType::Sync).unwrap();
+        assert_eq!(block.file_engine_type(), FileEngineType::Sync);
+
+        block.update_file_engine(FileEngineType::Async).unwrap();
+        assert_eq!(block.file_engine_type(), FileEngineType::Async);
+        add_flush_requests_batch(&mut block, &vq, IO_URING_NUM_ENTRIES + 10);
+        simulate_queue_event(&mut block, Some(false));
+        assert!(block.is_io_engine_throttled);
+
+        // The requests in flight on the Async engine complete, and the ones it had no room for
+        // are processed by the Sync engine.
+        block.update_file_engine(FileEngineType::Sync).unwrap();
+        assert_eq!(block.file_engine_type(), FileEngineType::Sync);
+        assert!(!block.is_io_engine_throttled);
+        check_flush_requests_batch(IO_URING_NUM_ENTRIES + 10, &vq);
+        // The pending completion event of the previous engine is consumed without effect.
+        block.process_async_completion_event();
+        block.disk.completion_evt.read().unwrap_err();
+        check_flush_requests_batch(IO_URING_NUM_ENTRIES + 10, &vq);
+
+        // A drive with an overlay can only use the Sync engine.
+        block.disk.overlay_path = Some(String::from("overlay"));
+        assert!(matches!(
+            block.update_file_engine(FileEngineType::Async),
+            Err(VirtioBlockError::OverlayConfig)
+        ));
+        assert_eq!(block.file_engine_type(), FileEngineType::Sync);
+    }
+
     #[test]
     fn test_prepare_save() {
         let mut block = default_block(default_engine_type_for_kv());
@@ -1640,6 +2246,36 @@ mod tests {
 
         // Check that all the pending flush requests were processed during `prepare_save()`.
         check_flush_requests_batch(5, &vq);
+        assert!(block.inflight_requests().is_empty());
+    }
+
+    #[test]
+    fn test_replay_inflight_requests() {
+        let mut block = default_block(default_engine_type_for_kv());
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        read_blk_req_descriptors(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        mem.write_obj::<u32>(VIRTIO_BLK_T_IN, request_type_addr)
+            .unwrap();
+        mem.write_obj::<u32>(0xff, status_addr).unwrap();
+
+        // The request was popped from the avail ring before the snapshot was taken, whereas the
+        // invalid head is discarded.
+        vq.avail.idx.set(0);
+        block.replay_requests = vec![0, 16];
+        assert_eq!(block.inflight_requests(), vec![0, 16]);
+        simulate_queue_and_async_completion_events(&mut block, true);
+
+        assert!(block.replay_requests.is_empty());
+        assert!(block.inflight_requests().is_empty());
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(vq.used.ring[0].get().id, 0);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
     }
 
     #[test]
@@ -1810,5 +2446,13 @@ mod tests {
             mdata.st_ino()
         );
         assert_eq!(block.disk.image_id, id.as_slice());
+
+        // Failing to notify the driver is reported instead of panicking.
+        block.irq_trigger.irq_evt.read().unwrap();
+        block.irq_trigger.irq_evt.write(u64::MAX - 1).unwrap();
+        assert!(matches!(
+            block.update_disk_image(String::from(path.to_str().unwrap())),
+            Err(VirtioBlockError::IrqTrigger(_))
+        ));
     }
 }
@@ -3,7 +3,6 @@
 use event_manager::{EventOps, Events, MutEventSubscriber};
 use utils::epoll::EventSet;
 
-use super::io::FileEngine;
 use crate::devices::virtio::block::virtio::device::VirtioBlock;
 use crate::devices::virtio::device::VirtioDevice;
 use crate::logger::{error, warn};
@@ -29,14 +28,14 @@ impl VirtioBlock {
         )) {
             error!("Failed to register ratelimiter event: {}", err);
         }
-        if let FileEngine::Async(ref engine) = self.disk.file_engine {
-            if let Err(err) = ops.add(Events::with_data(
-                engine.completion_evt(),
-                Self::PROCESS_ASYNC_COMPLETION,
-                EventSet::IN,
-            )) {
-                error!("Failed to register IO engine completion event: {}", err);
-            }
+        // The completion event is registered whatever the IO engine is, since the engine can be
+        // switched at runtime.
+        if let Err(err) = ops.add(Events::with_data(
+            &self.disk.completion_evt,
+            Self::PROCESS_ASYNC_COMPLETION,
+            EventSet::IN,
+        )) {
+            error!("Failed to register IO engine completion event: {}", err);
         }
     }
 
@@ -90,10 +90,14 @@ impl<T: Debug> AsyncFileEngine<T> {
         )
     }
 
-    pub fn from_file(file: File) -> Result<AsyncFileEngine<T>, AsyncIoError> {
+    /// Creates an engine for `file`, which signals the completion of requests through
+    /// `completion_evt`.
+    pub fn from_file(
+        file: File,
+        completion_evt: EventFd,
+    ) -> Result<AsyncFileEngine<T>, AsyncIoError> {
         log_dev_preview_warning("Async file IO", Option::None);
 
-        let completion_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(AsyncIoError::EventFd)?;
         let ring =
             Self::new_ring(&file, completion_evt.as_raw_fd()).map_err(AsyncIoError::IoUring)?;
 
@@ -113,15 +117,10 @@ impl<T: Debug> AsyncFileEngine<T> {
         Ok(())
     }
 
-    #[cfg(test)]
     pub fn file(&self) -> &File {
         &self.file
     }
 
-    pub fn completion_evt(&self) -> &EventFd {
-        &self.completion_evt
-    }
-
     pub fn push_read(
         &mut self,
         offset: u64,
@@ -233,6 +232,18 @@ impl<T: Debug> AsyncFileEngine<T> {
         Ok(())
     }
 
+    pub fn sync(&self) -> Result<(), AsyncIoError> {
+        // Only the data of the completed ops is synced, the ops still in flight are not waited for.
+        self.file.sync_all().map_err(AsyncIoError::SyncAll)
+    }
+
+    /// Returns the user data of the requests pushed to the engine and not completed yet.
+    pub fn inflight(&self) -> impl Iterator<Item = &T> {
+        self.ring
+            .pending_user_data()
+            .map(|wrapped_user_data| &wrapped_user_data.user_data)
+    }
+
     fn do_pop(&mut self) -> Result<Option<Cqe<WrappedUserData<T>>>, AsyncIoError> {
         self.ring.pop().map_err(AsyncIoError::IoUring)
     }
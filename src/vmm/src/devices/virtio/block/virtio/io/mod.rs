@@ -2,12 +2,16 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod async_io;
+pub mod overlay;
 pub mod sync_io;
 
 use std::fmt::Debug;
 use std::fs::File;
 
+use utils::eventfd::EventFd;
+
 pub use self::async_io::{AsyncFileEngine, AsyncIoError};
+pub use self::overlay::CowOverlay;
 pub use self::sync_io::{SyncFileEngine, SyncIoError};
 use crate::devices::virtio::block::virtio::device::FileEngineType;
 use crate::vstate::memory::{GuestAddress, GuestMemoryMmap};
@@ -34,6 +38,8 @@ pub enum BlockIoError {
     UnsupportedEngine(FileEngineType),
     /// Could not get kernel version: {0}
     GetKernelVersion(utils::kernel_version::KernelVersionError),
+    /// Injected I/O error
+    InjectedFault,
 }
 
 impl BlockIoError {
@@ -60,9 +66,12 @@ pub enum FileEngine<T> {
 }
 
 impl<T: Debug> FileEngine<T> {
+    /// Creates an engine of type `engine_type` for `file`. An Async engine signals the completion
+    /// of requests through `completion_evt`.
     pub fn from_file(
         file: File,
         engine_type: FileEngineType,
+        completion_evt: &EventFd,
     ) -> Result<FileEngine<T>, BlockIoError> {
         if !engine_type
             .is_supported()
@@ -71,9 +80,15 @@ impl<T: Debug> FileEngine<T> {
             return Err(BlockIoError::UnsupportedEngine(engine_type));
         }
         match engine_type {
-            FileEngineType::Async => Ok(FileEngine::Async(
-                AsyncFileEngine::from_file(file).map_err(BlockIoError::Async)?,
-            )),
+            FileEngineType::Async => {
+                let completion_evt = completion_evt
+                    .try_clone()
+                    .map_err(|err| BlockIoError::Async(AsyncIoError::EventFd(err)))?;
+                Ok(FileEngine::Async(
+                    AsyncFileEngine::from_file(file, completion_evt)
+                        .map_err(BlockIoError::Async)?,
+                ))
+            }
             FileEngineType::Sync => Ok(FileEngine::Sync(SyncFileEngine::from_file(file))),
         }
     }
@@ -87,7 +102,6 @@ impl<T: Debug> FileEngine<T> {
         Ok(())
     }
 
-    #[cfg(test)]
     pub fn file(&self) -> &File {
         match self {
             FileEngine::Async(engine) => engine.file(),
@@ -176,6 +190,14 @@ impl<T: Debug> FileEngine<T> {
         }
     }
 
+    /// Synchronously writes the data of the completed write requests out to the host storage.
+    pub fn sync(&mut self) -> Result<(), BlockIoError> {
+        match self {
+            FileEngine::Async(engine) => engine.sync().map_err(BlockIoError::Async),
+            FileEngine::Sync(engine) => engine.flush().map_err(BlockIoError::Sync),
+        }
+    }
+
     pub fn drain(&mut self, discard: bool) -> Result<(), BlockIoError> {
         match self {
             FileEngine::Async(engine) => engine.drain(discard).map_err(BlockIoError::Async),
@@ -282,7 +304,8 @@ pub mod tests {
         assert!(matches!(
             FileEngine::<PendingRequest>::from_file(
                 TempFile::new().unwrap().into_file(),
-                FileEngineType::Async
+                FileEngineType::Async,
+                &EventFd::new(libc::EFD_NONBLOCK).unwrap()
             ),
             Err(BlockIoError::UnsupportedEngine(FileEngineType::Async))
         ));
@@ -290,10 +313,12 @@ pub mod tests {
 
     #[test]
     fn test_sync() {
+        let completion_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
         // Check invalid file
         let mem = create_mem();
         let file = unsafe { File::from_raw_fd(-2) };
-        let mut engine = FileEngine::from_file(file, FileEngineType::Sync).unwrap();
+        let mut engine =
+            FileEngine::from_file(file, FileEngineType::Sync, &completion_evt).unwrap();
         let res = engine.read(0, &mem, GuestAddress(0), 0, ());
         assert_err!(res, BlockIoError::Sync(sync_io::SyncIoError::Seek(_e)));
         let res = engine.write(0, &mem, GuestAddress(0), 0, ());
@@ -303,7 +328,8 @@ pub mod tests {
 
         // Create backing file.
         let file = TempFile::new().unwrap().into_file();
-        let mut engine = FileEngine::from_file(file, FileEngineType::Sync).unwrap();
+        let mut engine =
+            FileEngine::from_file(file, FileEngineType::Sync, &completion_evt).unwrap();
 
         let data = utils::rand::rand_alphanumerics(FILE_LEN as usize)
             .as_bytes()
@@ -369,13 +395,15 @@ pub mod tests {
     fn test_async() {
         skip_if_io_uring_unsupported!();
 
+        let completion_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
         // Check invalid file
         let file = unsafe { File::from_raw_fd(-2) };
-        FileEngine::<()>::from_file(file, FileEngineType::Async).unwrap_err();
+        FileEngine::<()>::from_file(file, FileEngineType::Async, &completion_evt).unwrap_err();
 
         // Create backing file.
         let file = TempFile::new().unwrap().into_file();
-        let mut engine = FileEngine::<()>::from_file(file, FileEngineType::Async).unwrap();
+        let mut engine =
+            FileEngine::<()>::from_file(file, FileEngineType::Async, &completion_evt).unwrap();
 
         let data = utils::rand::rand_alphanumerics(FILE_LEN as usize)
             .as_bytes()
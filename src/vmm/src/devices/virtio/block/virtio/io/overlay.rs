@@ -0,0 +1,411 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use utils::u64_to_usize;
+use vm_memory::bitmap::BitmapSlice;
+use vm_memory::{ReadVolatile, VolatileSlice, WriteVolatile};
+
+use crate::logger::error;
+
+/// Size of the unit copied from the base image to the overlay on the first write to it.
+pub const OVERLAY_CLUSTER_SIZE: u64 = 64 << 10;
+
+// Identifies the metadata of an overlay file.
+const OVERLAY_MAGIC: [u8; 8] = *b"FCOVRLY1";
+// Size of the metadata header: the magic, the size of the disk and the size of the clusters.
+const OVERLAY_HEADER_SIZE: usize = 24;
+// Alignment of the metadata in the overlay file.
+const OVERLAY_METADATA_ALIGNMENT: u64 = 4096;
+
+/// Copy-on-write overlay of a read-only base image.
+///
+/// The overlay is a sparse raw file holding the clusters written by the guest at their offset in
+/// the disk, the others are read from the base image. Which clusters are stored in the overlay is
+/// tracked by a bitmap, stored after the clusters along with a header identifying the overlay. The
+/// bitmap is written to the file when the overlay is synced and when it is dropped.
+#[derive(Debug)]
+pub struct CowOverlay {
+    file: File,
+    disk_size: u64,
+    // One bit per cluster, set when the cluster is stored in the overlay file.
+    bitmap: Vec<u64>,
+    // Set when clusters were stored since the bitmap was last written to the file.
+    bitmap_dirty: bool,
+}
+
+impl CowOverlay {
+    /// Uses `file` as the overlay of a base image of `disk_size` bytes. An empty file is
+    /// initialized as a new overlay, other files must be overlays of a disk of the same size.
+    pub fn new(file: File, disk_size: u64) -> io::Result<Self> {
+        let clusters = disk_size.div_ceil(OVERLAY_CLUSTER_SIZE);
+        let bitmap_len = u64_to_usize(clusters.div_ceil(64));
+        let mut overlay = CowOverlay {
+            file,
+            disk_size,
+            bitmap: vec![0; bitmap_len],
+            bitmap_dirty: false,
+        };
+        if overlay.file.metadata()?.len() == 0 {
+            overlay.store_bitmap()?;
+        } else {
+            overlay.load_bitmap()?;
+        }
+        Ok(overlay)
+    }
+
+    #[cfg(test)]
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    // Offset of the metadata in the overlay file, right after the clusters.
+    fn metadata_offset(&self) -> u64 {
+        self.disk_size.next_multiple_of(OVERLAY_METADATA_ALIGNMENT)
+    }
+
+    fn header(&self) -> [u8; OVERLAY_HEADER_SIZE] {
+        let mut header = [0; OVERLAY_HEADER_SIZE];
+        header[..8].copy_from_slice(&OVERLAY_MAGIC);
+        header[8..16].copy_from_slice(&self.disk_size.to_le_bytes());
+        header[16..].copy_from_slice(&OVERLAY_CLUSTER_SIZE.to_le_bytes());
+        header
+    }
+
+    // Reads the bitmap from the overlay file, after checking it is an overlay of this disk.
+    fn load_bitmap(&mut self) -> io::Result<()> {
+        let mut metadata = vec![0; OVERLAY_HEADER_SIZE + self.bitmap.len() * 8];
+        self.file.seek(SeekFrom::Start(self.metadata_offset()))?;
+        self.file
+            .read_exact(&mut metadata)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => invalid_overlay(),
+                _ => err,
+            })?;
+        let (header, bitmap) = metadata.split_at(OVERLAY_HEADER_SIZE);
+        if header != self.header() {
+            return Err(invalid_overlay());
+        }
+        for (word, bytes) in self.bitmap.iter_mut().zip(bitmap.chunks_exact(8)) {
+            *word = u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+        Ok(())
+    }
+
+    // Writes the header and the bitmap to the overlay file.
+    fn store_bitmap(&mut self) -> io::Result<()> {
+        let mut metadata = self.header().to_vec();
+        for word in &self.bitmap {
+            metadata.extend_from_slice(&word.to_le_bytes());
+        }
+        self.file.seek(SeekFrom::Start(self.metadata_offset()))?;
+        self.file.write_all(&metadata)?;
+        self.bitmap_dirty = false;
+        Ok(())
+    }
+
+    fn is_present(&self, cluster: u64) -> bool {
+        self.bitmap[u64_to_usize(cluster / 64)] & (1 << (cluster % 64)) != 0
+    }
+
+    fn set_present(&mut self, cluster: u64) {
+        if !self.is_present(cluster) {
+            self.bitmap[u64_to_usize(cluster / 64)] |= 1 << (cluster % 64);
+            self.bitmap_dirty = true;
+        }
+    }
+
+    fn cluster_len(&self, cluster: u64) -> u64 {
+        OVERLAY_CLUSTER_SIZE.min(self.disk_size - cluster * OVERLAY_CLUSTER_SIZE)
+    }
+
+    // Calls `f` with each part of `[offset, offset + len)` lying in a single cluster, as
+    // `(cluster, offset, position in the range, length)`.
+    fn for_each_cluster<F>(offset: u64, len: usize, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(u64, u64, usize, usize) -> io::Result<()>,
+    {
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let left_in_cluster = OVERLAY_CLUSTER_SIZE - pos % OVERLAY_CLUSTER_SIZE;
+            let chunk = (len - done).min(u64_to_usize(left_in_cluster));
+            f(pos / OVERLAY_CLUSTER_SIZE, pos, done, chunk)?;
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// Reads `slice.len()` bytes at `offset` of the disk, from the overlay or the base image.
+    pub fn read<B: BitmapSlice>(
+        &mut self,
+        base: &mut File,
+        offset: u64,
+        slice: &VolatileSlice<B>,
+    ) -> io::Result<()> {
+        Self::for_each_cluster(offset, slice.len(), |cluster, pos, done, chunk| {
+            let file = if self.is_present(cluster) {
+                &mut self.file
+            } else {
+                &mut *base
+            };
+            let mut chunk_slice = slice.subslice(done, chunk).map_err(io::Error::other)?;
+            file.seek(SeekFrom::Start(pos))?;
+            file.read_exact_volatile(&mut chunk_slice)
+                .map_err(io::Error::other)
+        })
+    }
+
+    /// Writes `slice` at `offset` of the disk to the overlay, copying the clusters it partially
+    /// covers from the base image first.
+    pub fn write<B: BitmapSlice>(
+        &mut self,
+        base: &File,
+        offset: u64,
+        slice: &VolatileSlice<B>,
+    ) -> io::Result<()> {
+        Self::for_each_cluster(offset, slice.len(), |cluster, pos, done, chunk| {
+            if !self.is_present(cluster) && (chunk as u64) < self.cluster_len(cluster) {
+                self.copy_up(base, cluster)?;
+            }
+            let chunk_slice = slice.subslice(done, chunk).map_err(io::Error::other)?;
+            self.file.seek(SeekFrom::Start(pos))?;
+            self.file
+                .write_all_volatile(&chunk_slice)
+                .map_err(io::Error::other)?;
+            self.set_present(cluster);
+            Ok(())
+        })
+    }
+
+    fn copy_up(&mut self, mut base: &File, cluster: u64) -> io::Result<()> {
+        let start = cluster * OVERLAY_CLUSTER_SIZE;
+        let mut buf = vec![0; u64_to_usize(self.cluster_len(cluster))];
+        base.seek(SeekFrom::Start(start))?;
+        base.read_exact(&mut buf)?;
+        self.file.seek(SeekFrom::Start(start))?;
+        self.file.write_all(&buf)
+    }
+
+    /// Commits the data written to the overlay, and the bitmap of the clusters it stores, to the
+    /// host storage.
+    pub fn sync(&mut self) -> io::Result<()> {
+        if self.bitmap_dirty {
+            // The clusters reach the host storage before the bitmap marking them as stored.
+            self.file.sync_all()?;
+            self.store_bitmap()?;
+        }
+        self.file.sync_all()
+    }
+}
+
+impl Drop for CowOverlay {
+    fn drop(&mut self) {
+        // Without a sync, e.g. when flush requests are ignored, the bitmap is still written to the
+        // file, but not committed to the host storage.
+        if self.bitmap_dirty {
+            if let Err(err) = self.store_bitmap() {
+                error!("Cannot store the bitmap of the overlay: {}", err);
+            }
+        }
+    }
+}
+
+fn invalid_overlay() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "The file is not a copy-on-write overlay of this disk",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::FileExt;
+    use std::os::unix::io::AsRawFd;
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    const DISK_SIZE: u64 = 4 * OVERLAY_CLUSTER_SIZE + 512;
+
+    fn base_image() -> TempFile {
+        let base = TempFile::new().unwrap();
+        let data: Vec<u8> = (0..DISK_SIZE).map(|i| (i % 251) as u8).collect();
+        base.as_file().write_all_at(&data, 0).unwrap();
+        base
+    }
+
+    fn read(overlay: &mut CowOverlay, base: &mut File, offset: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        overlay
+            .read(base, offset, &VolatileSlice::from(buf.as_mut_slice()))
+            .unwrap();
+        buf
+    }
+
+    fn write(overlay: &mut CowOverlay, base: &File, offset: u64, data: &[u8]) {
+        let mut buf = data.to_vec();
+        overlay
+            .write(base, offset, &VolatileSlice::from(buf.as_mut_slice()))
+            .unwrap();
+    }
+
+    fn expected_base(offset: u64, len: usize) -> Vec<u8> {
+        (offset..offset + len as u64)
+            .map(|i| (i % 251) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn test_overlay_new() {
+        let overlay_file = TempFile::new().unwrap();
+        let overlay = CowOverlay::new(overlay_file.into_file(), DISK_SIZE).unwrap();
+        // The metadata follows the clusters.
+        assert_eq!(overlay.metadata_offset(), 4 * OVERLAY_CLUSTER_SIZE + 4096);
+        assert_eq!(
+            overlay.file().metadata().unwrap().len(),
+            overlay.metadata_offset() + 24 + 8
+        );
+        assert_eq!(overlay.bitmap, vec![0]);
+        assert_eq!(overlay.cluster_len(4), 512);
+    }
+
+    #[test]
+    fn test_overlay_invalid() {
+        // A file which is not an overlay.
+        let overlay_file = TempFile::new().unwrap();
+        overlay_file
+            .as_file()
+            .write_all_at(&[0xaa; 4096], 0)
+            .unwrap();
+        let err = CowOverlay::new(overlay_file.into_file(), DISK_SIZE).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // The overlay of a disk of another size.
+        let overlay_file = TempFile::new().unwrap();
+        CowOverlay::new(overlay_file.as_file().try_clone().unwrap(), DISK_SIZE).unwrap();
+        let err = CowOverlay::new(overlay_file.into_file(), DISK_SIZE + 512).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_overlay_fully_allocated() {
+        let base = base_image();
+        let mut base_file = base.as_file().try_clone().unwrap();
+        let overlay_file = TempFile::new().unwrap();
+        let mut overlay =
+            CowOverlay::new(overlay_file.as_file().try_clone().unwrap(), DISK_SIZE).unwrap();
+        write(&mut overlay, &base_file, OVERLAY_CLUSTER_SIZE, &[0xff; 512]);
+        overlay.sync().unwrap();
+        drop(overlay);
+
+        // Allocate the whole file, as when it is copied without preserving its holes or stored on
+        // a filesystem without sparse files.
+        let len = overlay_file.as_file().metadata().unwrap().len();
+        // SAFETY: Safe because the file descriptor is valid and we check the return value.
+        let ret = unsafe {
+            libc::fallocate(
+                overlay_file.as_file().as_raw_fd(),
+                0,
+                0,
+                i64::try_from(len).unwrap(),
+            )
+        };
+        assert_eq!(ret, 0);
+
+        // Only the cluster written by the guest is read from the overlay.
+        let mut overlay =
+            CowOverlay::new(overlay_file.as_file().try_clone().unwrap(), DISK_SIZE).unwrap();
+        assert_eq!(overlay.bitmap, vec![0b10]);
+        let len = u64_to_usize(DISK_SIZE);
+        let mut expected = expected_base(0, len);
+        expected[u64_to_usize(OVERLAY_CLUSTER_SIZE)..][..512].fill(0xff);
+        assert_eq!(read(&mut overlay, &mut base_file, 0, len), expected);
+    }
+
+    #[test]
+    fn test_overlay_drop() {
+        let base = base_image();
+        let base_file = base.as_file().try_clone().unwrap();
+        let overlay_file = TempFile::new().unwrap();
+        let mut overlay =
+            CowOverlay::new(overlay_file.as_file().try_clone().unwrap(), DISK_SIZE).unwrap();
+        write(&mut overlay, &base_file, 0, &[0xff; 512]);
+        assert!(overlay.bitmap_dirty);
+        drop(overlay);
+
+        // The bitmap is written when the overlay is dropped without a sync.
+        let overlay =
+            CowOverlay::new(overlay_file.as_file().try_clone().unwrap(), DISK_SIZE).unwrap();
+        assert_eq!(overlay.bitmap, vec![0b1]);
+        assert!(!overlay.bitmap_dirty);
+    }
+
+    #[test]
+    fn test_overlay_read_write() {
+        let base = base_image();
+        let mut base_file = base.as_file().try_clone().unwrap();
+        let overlay_file = TempFile::new().unwrap();
+        let mut overlay =
+            CowOverlay::new(overlay_file.as_file().try_clone().unwrap(), DISK_SIZE).unwrap();
+
+        // Reads are served from the base image.
+        let offset = 2 * OVERLAY_CLUSTER_SIZE - 100;
+        assert_eq!(
+            read(&mut overlay, &mut base_file, offset, 200),
+            expected_base(offset, 200)
+        );
+
+        // A write spanning two clusters copies both of them to the overlay.
+        write(&mut overlay, &base_file, offset, &[0xff; 200]);
+        assert!(!overlay.is_present(0));
+        assert!(overlay.is_present(1));
+        assert!(overlay.is_present(2));
+        assert!(!overlay.is_present(3));
+        let len = u64_to_usize(2 * OVERLAY_CLUSTER_SIZE);
+        let mut expected = expected_base(OVERLAY_CLUSTER_SIZE, len);
+        expected[len / 2 - 100..len / 2 + 100].fill(0xff);
+        assert_eq!(
+            read(&mut overlay, &mut base_file, OVERLAY_CLUSTER_SIZE, len),
+            expected
+        );
+        let len_0 = u64_to_usize(OVERLAY_CLUSTER_SIZE);
+        assert_eq!(
+            read(&mut overlay, &mut base_file, 0, len_0),
+            expected_base(0, len_0)
+        );
+
+        // A write covering the whole last, partial, cluster doesn't need a copy.
+        write(
+            &mut overlay,
+            &base_file,
+            4 * OVERLAY_CLUSTER_SIZE,
+            &[0xee; 512],
+        );
+        assert_eq!(
+            read(&mut overlay, &mut base_file, 4 * OVERLAY_CLUSTER_SIZE, 512),
+            vec![0xee; 512]
+        );
+
+        // The base image is left untouched.
+        let mut base_data = vec![0; u64_to_usize(DISK_SIZE)];
+        base.as_file().read_exact_at(&mut base_data, 0).unwrap();
+        assert_eq!(base_data, expected_base(0, u64_to_usize(DISK_SIZE)));
+
+        // The clusters stored in the overlay are found when reopening it.
+        overlay.sync().unwrap();
+        let mut overlay =
+            CowOverlay::new(overlay_file.as_file().try_clone().unwrap(), DISK_SIZE).unwrap();
+        assert!(!overlay.is_present(0));
+        assert!(overlay.is_present(1));
+        assert!(overlay.is_present(2));
+        assert!(overlay.is_present(4));
+        assert_eq!(
+            read(&mut overlay, &mut base_file, OVERLAY_CLUSTER_SIZE, len),
+            expected
+        );
+    }
+}
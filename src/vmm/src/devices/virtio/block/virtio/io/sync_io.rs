@@ -6,12 +6,15 @@ use std::io::{Seek, SeekFrom, Write};
 
 use vm_memory::{GuestMemoryError, ReadVolatile, WriteVolatile};
 
+use super::overlay::CowOverlay;
 use crate::vstate::memory::{GuestAddress, GuestMemory, GuestMemoryMmap};
 
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 pub enum SyncIoError {
     /// Flush: {0}
     Flush(std::io::Error),
+    /// Overlay: {0}
+    Overlay(std::io::Error),
     /// Seek: {0}
     Seek(std::io::Error),
     /// SyncAll: {0}
@@ -23,6 +26,7 @@ pub enum SyncIoError {
 #[derive(Debug)]
 pub struct SyncFileEngine {
     file: File,
+    overlay: Option<CowOverlay>,
 }
 
 // SAFETY: `File` is send and ultimately a POD.
@@ -30,10 +34,24 @@ unsafe impl Send for SyncFileEngine {}
 
 impl SyncFileEngine {
     pub fn from_file(file: File) -> SyncFileEngine {
-        SyncFileEngine { file }
+        SyncFileEngine {
+            file,
+            overlay: None,
+        }
+    }
+
+    /// Creates an engine writing to `overlay` instead of the read-only `base` file.
+    pub fn with_overlay(base: File, overlay: CowOverlay) -> SyncFileEngine {
+        SyncFileEngine {
+            file: base,
+            overlay: Some(overlay),
+        }
+    }
+
+    pub fn has_overlay(&self) -> bool {
+        self.overlay.is_some()
     }
 
-    #[cfg(test)]
     pub fn file(&self) -> &File {
         &self.file
     }
@@ -50,6 +68,16 @@ impl SyncFileEngine {
         addr: GuestAddress,
         count: u32,
     ) -> Result<u32, SyncIoError> {
+        if let Some(overlay) = self.overlay.as_mut() {
+            let slice = mem
+                .get_slice(addr, count as usize)
+                .map_err(SyncIoError::Transfer)?;
+            overlay
+                .read(&mut self.file, offset, &slice)
+                .map_err(SyncIoError::Overlay)?;
+            return Ok(count);
+        }
+
         self.file
             .seek(SeekFrom::Start(offset))
             .map_err(SyncIoError::Seek)?;
@@ -66,6 +94,16 @@ impl SyncFileEngine {
         addr: GuestAddress,
         count: u32,
     ) -> Result<u32, SyncIoError> {
+        if let Some(overlay) = self.overlay.as_mut() {
+            let slice = mem
+                .get_slice(addr, count as usize)
+                .map_err(SyncIoError::Transfer)?;
+            overlay
+                .write(&self.file, offset, &slice)
+                .map_err(SyncIoError::Overlay)?;
+            return Ok(count);
+        }
+
         self.file
             .seek(SeekFrom::Start(offset))
             .map_err(SyncIoError::Seek)?;
@@ -76,6 +114,11 @@ impl SyncFileEngine {
     }
 
     pub fn flush(&mut self) -> Result<(), SyncIoError> {
+        if let Some(overlay) = self.overlay.as_mut() {
+            // The base file is read-only.
+            return overlay.sync().map_err(SyncIoError::Overlay);
+        }
+
         // flush() first to force any cached data out of rust buffers.
         self.file.flush().map_err(SyncIoError::Flush)?;
         // Sync data out to physical media on host.
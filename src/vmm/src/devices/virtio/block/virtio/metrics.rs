@@ -83,7 +83,10 @@ use std::sync::{Arc, RwLock};
 use serde::ser::SerializeMap;
 use serde::{Serialize, Serializer};
 
-use crate::logger::{IncMetric, LatencyAggregateMetrics, SharedIncMetric};
+use crate::logger::{
+    IncMetric, LatencyAggregateMetrics, LatencyBuckets, QueueDepthBuckets, SharedHistogramMetric,
+    SharedIncMetric,
+};
 
 /// map of block drive id and metrics
 /// this should be protected by a lock before accessing.
@@ -183,6 +186,12 @@ pub struct BlockDeviceMetrics {
     pub io_engine_throttled_events: SharedIncMetric,
     /// Number of remaining requests in the queue.
     pub remaining_reqs_count: SharedIncMetric,
+    /// Histogram of the durations of the read operations, in microseconds.
+    pub read_latency_hist: SharedHistogramMetric<LatencyBuckets>,
+    /// Histogram of the durations of the write operations, in microseconds.
+    pub write_latency_hist: SharedHistogramMetric<LatencyBuckets>,
+    /// Histogram of the number of requests pending in the queue when it is processed.
+    pub queue_depth_hist: SharedHistogramMetric<QueueDepthBuckets>,
 }
 
 impl BlockDeviceMetrics {
@@ -229,6 +238,9 @@ impl BlockDeviceMetrics {
             .add(other.io_engine_throttled_events.fetch_diff());
         self.remaining_reqs_count
             .add(other.remaining_reqs_count.fetch_diff());
+        self.read_latency_hist.aggregate(&other.read_latency_hist);
+        self.write_latency_hist.aggregate(&other.write_latency_hist);
+        self.queue_depth_hist.aggregate(&other.queue_depth_hist);
     }
 }
 
@@ -20,6 +20,9 @@ use crate::devices::virtio::queue::FIRECRACKER_MAX_QUEUE_SIZE;
 
 /// Size of config space for block device.
 pub const BLOCK_CONFIG_SPACE_SIZE: usize = 8;
+/// Offset of the `writeback` field in the config space of block devices offering
+/// `VIRTIO_BLK_F_CONFIG_WCE`.
+pub const BLOCK_CONFIG_WRITEBACK_OFFSET: usize = 32;
 /// Sector shift for block device.
 pub const SECTOR_SHIFT: u8 = 9;
 /// Size of block sector.
@@ -45,6 +48,8 @@ pub enum VirtioBlockError {
     GetFileMetadata(std::io::Error),
     /// Guest gave us bad memory addresses.
     GuestMemory(GuestMemoryError),
+    /// Descriptor chain head {0} is not a valid descriptor index.
+    InvalidDescriptorHead(u16),
     /// The data length is invalid.
     InvalidDataLength,
     /// The requested operation would cause a seek beyond disk end.
@@ -63,6 +68,10 @@ pub enum VirtioBlockError {
     IrqTrigger(std::io::Error),
     /// Error coming from the rate limiter: {0}
     RateLimiter(std::io::Error),
+    /// Copy-on-write overlays require a writable drive using the Sync IO engine.
+    OverlayConfig,
+    /// Cannot update the backing file of a drive with a copy-on-write overlay.
+    OverlayUpdate,
     /// Persistence error: {0}
     Persist(crate::devices::virtio::persist::PersistError),
 }
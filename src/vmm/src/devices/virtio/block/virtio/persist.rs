@@ -16,7 +16,7 @@ use crate::devices::virtio::block::virtio::device::FileEngineType;
 use crate::devices::virtio::block::virtio::metrics::BlockMetricsPerDevice;
 use crate::devices::virtio::device::{DeviceState, IrqTrigger};
 use crate::devices::virtio::gen::virtio_blk::VIRTIO_BLK_F_RO;
-use crate::devices::virtio::persist::VirtioDeviceState;
+use crate::devices::virtio::persist::{SnapshotFeature, VirtioDeviceState};
 use crate::devices::virtio::TYPE_BLOCK;
 use crate::logger::warn;
 use crate::rate_limiter::persist::RateLimiterState;
@@ -59,11 +59,44 @@ pub struct VirtioBlockState {
     id: String,
     partuuid: Option<String>,
     cache_type: CacheType,
+    writeback: bool,
     root_device: bool,
     disk_path: String,
+    /// Size of the disk seen by the guest, in sectors.
+    nsectors: u64,
     virtio_state: VirtioDeviceState,
     rate_limiter_state: RateLimiterState,
     file_engine_type: FileEngineTypeState,
+    overlay_path: Option<String>,
+    /// Descriptor chain heads of the requests in flight when the snapshot was taken.
+    inflight_requests: Vec<u16>,
+}
+
+impl VirtioBlockState {
+    /// Snapshot features used by the device.
+    pub fn snapshot_features(&self) -> Vec<SnapshotFeature> {
+        let mut features = self.virtio_state.snapshot_features();
+        if self.overlay_path.is_some() {
+            features.push(SnapshotFeature::BlockOverlay);
+        }
+        if self.cache_type == CacheType::Writeback && !self.writeback {
+            features.push(SnapshotFeature::BlockWriteCacheToggle);
+        }
+        if !self.inflight_requests.is_empty() {
+            features.push(SnapshotFeature::BlockInflightRequests);
+        }
+        features
+    }
+
+    /// Size of the disk seen by the guest, in sectors.
+    pub fn nsectors(&self) -> u64 {
+        self.nsectors
+    }
+
+    /// Replaces the path of the disk image backing the device.
+    pub fn set_disk_path(&mut self, disk_path: String) {
+        self.disk_path = disk_path;
+    }
 }
 
 impl Persist<'_> for VirtioBlock {
@@ -77,11 +110,15 @@ impl Persist<'_> for VirtioBlock {
             id: self.id.clone(),
             partuuid: self.partuuid.clone(),
             cache_type: self.cache_type,
+            writeback: self.writeback,
             root_device: self.root_device,
             disk_path: self.disk.file_path.clone(),
+            nsectors: self.disk.nsectors,
             virtio_state: VirtioDeviceState::from_device(self),
             rate_limiter_state: self.rate_limiter.save(),
             file_engine_type: FileEngineTypeState::from(self.file_engine_type()),
+            overlay_path: self.disk.overlay_path.clone(),
+            inflight_requests: self.inflight_requests(),
         }
     }
 
@@ -95,7 +132,9 @@ impl Persist<'_> for VirtioBlock {
 
         let disk_properties = DiskProperties::new(
             state.disk_path.clone(),
+            state.overlay_path.clone(),
             is_read_only,
+            state.cache_type,
             state.file_engine_type.into(),
         )
         .or_else(|err| match err {
@@ -108,7 +147,13 @@ impl Persist<'_> for VirtioBlock {
                      Defaulting to \"Sync\" mode.",
                     utils::kernel_version::min_kernel_version_for_io_uring()
                 );
-                DiskProperties::new(state.disk_path.clone(), is_read_only, FileEngineType::Sync)
+                DiskProperties::new(
+                    state.disk_path.clone(),
+                    state.overlay_path.clone(),
+                    is_read_only,
+                    state.cache_type,
+                    FileEngineType::Sync,
+                )
             }
             other => Err(other),
         })?;
@@ -140,7 +185,11 @@ impl Persist<'_> for VirtioBlock {
         Ok(VirtioBlock {
             avail_features,
             acked_features,
-            config_space: disk_properties.virtio_block_config_space(),
+            config_space: VirtioBlock::build_config_space(
+                &disk_properties,
+                avail_features,
+                state.writeback,
+            ),
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(VirtioBlockError::EventFd)?,
 
             queues,
@@ -151,12 +200,14 @@ impl Persist<'_> for VirtioBlock {
             id: state.id.clone(),
             partuuid: state.partuuid.clone(),
             cache_type: state.cache_type,
+            writeback: state.writeback,
             root_device: state.root_device,
             read_only: is_read_only,
 
             disk: disk_properties,
             rate_limiter,
             is_io_engine_throttled: false,
+            replay_requests: state.inflight_requests.clone(),
             metrics: BlockMetricsPerDevice::alloc(state.id.clone()),
         })
     }
@@ -189,6 +240,7 @@ mod tests {
             cache_type: CacheType::Writeback,
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            overlay_path: None,
         };
 
         let block = VirtioBlock::new(config).unwrap();
@@ -233,6 +285,7 @@ mod tests {
                 // Need to use Sync because it will otherwise return an error.
                 // We'll overwrite the state instead.
                 file_engine_type: FileEngineType::Sync,
+                overlay_path: None,
             };
 
             let block = VirtioBlock::new(config).unwrap();
@@ -274,6 +327,7 @@ mod tests {
             cache_type: CacheType::Unsafe,
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            overlay_path: None,
         };
 
         let block = VirtioBlock::new(config).unwrap();
@@ -305,4 +359,82 @@ mod tests {
         // Test that block specific fields are the same.
         assert_eq!(restored_block.disk.file_path, block.disk.file_path);
     }
+
+    #[test]
+    fn test_persistence_writeback() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+
+        let config = VirtioBlockConfig {
+            drive_id: "test".to_string(),
+            path_on_host: f.as_path().to_str().unwrap().to_string(),
+            is_root_device: false,
+            partuuid: None,
+            is_read_only: false,
+            cache_type: CacheType::Writeback,
+            rate_limiter: None,
+            file_engine_type: FileEngineType::default(),
+            overlay_path: None,
+        };
+
+        let mut block = VirtioBlock::new(config).unwrap();
+        // The guest driver disables the write cache.
+        block.set_acked_features(block.avail_features());
+        block.write_config(BLOCK_CONFIG_WRITEBACK_OFFSET as u64, &[0]);
+
+        let mut mem = vec![0; 4096];
+        Snapshot::serialize(&mut mem.as_mut_slice(), &block.save()).unwrap();
+
+        let restored_block = VirtioBlock::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &Snapshot::deserialize(&mut mem.as_slice()).unwrap(),
+        )
+        .unwrap();
+
+        assert!(!restored_block.writeback);
+        let mut writeback = [1u8];
+        restored_block.read_config(BLOCK_CONFIG_WRITEBACK_OFFSET as u64, &mut writeback);
+        assert_eq!(writeback, [0]);
+    }
+
+    #[test]
+    fn test_persistence_inflight_requests() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+
+        let config = VirtioBlockConfig {
+            drive_id: "test".to_string(),
+            path_on_host: f.as_path().to_str().unwrap().to_string(),
+            is_root_device: false,
+            partuuid: None,
+            is_read_only: false,
+            cache_type: CacheType::Unsafe,
+            rate_limiter: None,
+            file_engine_type: FileEngineType::default(),
+            overlay_path: None,
+        };
+
+        let mut block = VirtioBlock::new(config).unwrap();
+        assert!(!block
+            .save()
+            .snapshot_features()
+            .contains(&SnapshotFeature::BlockInflightRequests));
+
+        // Requests restored from a previous snapshot and not replayed yet are still in flight.
+        block.replay_requests = vec![3, 7];
+        let state = block.save();
+        assert_eq!(state.inflight_requests, vec![3, 7]);
+        assert!(state
+            .snapshot_features()
+            .contains(&SnapshotFeature::BlockInflightRequests));
+
+        let mut mem = vec![0; 4096];
+        Snapshot::serialize(&mut mem.as_mut_slice(), &state).unwrap();
+        let restored_block = VirtioBlock::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &Snapshot::deserialize(&mut mem.as_slice()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.replay_requests, vec![3, 7]);
+    }
 }
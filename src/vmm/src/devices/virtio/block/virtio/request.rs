@@ -12,6 +12,7 @@ use vm_memory::GuestMemoryError;
 use super::{io as block_io, VirtioBlockError, SECTOR_SHIFT, SECTOR_SIZE};
 use crate::devices::virtio::block::virtio::device::DiskProperties;
 use crate::devices::virtio::block::virtio::metrics::BlockDeviceMetrics;
+use crate::devices::virtio::block::CacheType;
 pub use crate::devices::virtio::gen::virtio_blk::{
     VIRTIO_BLK_ID_BYTES, VIRTIO_BLK_S_IOERR, VIRTIO_BLK_S_OK, VIRTIO_BLK_S_UNSUPP,
     VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
@@ -20,12 +21,14 @@ use crate::devices::virtio::queue::DescriptorChain;
 use crate::logger::{error, IncMetric};
 use crate::rate_limiter::{RateLimiter, TokenType};
 use crate::vstate::memory::{ByteValued, Bytes, GuestAddress, GuestMemoryMmap};
+use crate::vstate::memory_fault::{guarded, GuestMemoryFault};
 
 #[derive(Debug, derive_more::From)]
 pub enum IoErr {
     GetId(GuestMemoryError),
     PartialTransfer { completed: u32, expected: u32 },
     FileEngine(block_io::BlockIoError),
+    GuestMemoryFault(GuestMemoryFault),
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -98,6 +101,14 @@ pub struct PendingRequest {
 }
 
 impl PendingRequest {
+    pub fn request_type(&self) -> RequestType {
+        self.r#type
+    }
+
+    pub fn desc_idx(&self) -> u16 {
+        self.desc_idx
+    }
+
     fn write_status_and_finish(
         self,
         status: &Status,
@@ -366,28 +377,82 @@ impl Request {
     pub(crate) fn process(
         self,
         disk: &mut DiskProperties,
+        cache_type: CacheType,
+        sync_writes: bool,
         desc_idx: u16,
         mem: &GuestMemoryMmap,
         block_metrics: &BlockDeviceMetrics,
     ) -> ProcessingResult {
         let pending = self.to_pending_request(desc_idx);
-        let res = match self.r#type {
+        // The injected faults fail the requests accessing the disk, as a host I/O error would.
+        if disk.io_fault
+            && matches!(
+                self.r#type,
+                RequestType::In | RequestType::Out | RequestType::Flush
+            )
+        {
+            return ProcessingResult::Executed(pending.finish(
+                mem,
+                Err(IoErr::FileEngine(block_io::BlockIoError::InjectedFault)),
+                block_metrics,
+            ));
+        }
+        // The guest memory faults raised while transferring the data fail the request.
+        let (res, fault) = match self.r#type {
             RequestType::In => {
                 let _metric = block_metrics.read_agg.record_latency_metrics();
-                disk.file_engine
-                    .read(self.offset(), mem, self.data_addr, self.data_len, pending)
+                let _hist = block_metrics.read_latency_hist.record_latency();
+                guarded(mem, || {
+                    disk.file_engine.read(
+                        self.offset(),
+                        mem,
+                        self.data_addr,
+                        self.data_len,
+                        pending,
+                    )
+                })
             }
             RequestType::Out => {
                 let _metric = block_metrics.write_agg.record_latency_metrics();
-                disk.file_engine
-                    .write(self.offset(), mem, self.data_addr, self.data_len, pending)
+                let _hist = block_metrics.write_latency_hist.record_latency();
+                let (res, fault) = guarded(mem, || {
+                    disk.file_engine.write(
+                        self.offset(),
+                        mem,
+                        self.data_addr,
+                        self.data_len,
+                        pending,
+                    )
+                });
+                // With the write cache disabled by the guest, the data has to reach the host
+                // storage before the request completes.
+                let res = match res {
+                    Ok(block_io::FileEngineOk::Executed(ok)) if sync_writes && fault.is_none() => {
+                        match disk.file_engine.sync() {
+                            Ok(()) => Ok(block_io::FileEngineOk::Executed(ok)),
+                            Err(error) => Err(block_io::UserDataError {
+                                user_data: ok.user_data,
+                                error,
+                            }),
+                        }
+                    }
+                    res => res,
+                };
+                (res, fault)
+            }
+            RequestType::Flush if cache_type == CacheType::Writeback => {
+                (disk.file_engine.flush(pending), None)
+            }
+            // Without a write cache advertised to the guest, there is nothing to flush.
+            RequestType::Flush => {
+                return ProcessingResult::Executed(pending.finish(mem, Ok(0), block_metrics));
             }
-            RequestType::Flush => disk.file_engine.flush(pending),
             RequestType::GetDeviceID => {
-                let res = mem
-                    .write_slice(&disk.image_id, self.data_addr)
-                    .map(|_| VIRTIO_BLK_ID_BYTES)
-                    .map_err(IoErr::GetId);
+                let (res, fault) = guarded(mem, || mem.write_slice(&disk.image_id, self.data_addr));
+                let res = match fault {
+                    Some(fault) => Err(IoErr::GuestMemoryFault(fault)),
+                    None => res.map(|_| VIRTIO_BLK_ID_BYTES).map_err(IoErr::GetId),
+                };
                 return ProcessingResult::Executed(pending.finish(mem, res, block_metrics));
             }
             RequestType::Unsupported(_) => {
@@ -398,7 +463,11 @@ impl Request {
         match res {
             Ok(block_io::FileEngineOk::Submitted) => ProcessingResult::Submitted,
             Ok(block_io::FileEngineOk::Executed(res)) => {
-                ProcessingResult::Executed(res.user_data.finish(mem, Ok(res.count), block_metrics))
+                let count = match fault {
+                    Some(fault) => Err(IoErr::GuestMemoryFault(fault)),
+                    None => Ok(res.count),
+                };
+                ProcessingResult::Executed(res.user_data.finish(mem, count, block_metrics))
             }
             Err(err) => {
                 if err.error.is_throttling_err() {
@@ -66,6 +66,7 @@ pub fn default_block_with_path(path: String, file_engine_type: FileEngineType) -
             }),
         }),
         file_engine_type,
+        overlay_path: None,
     };
 
     // The default block device is read-write and non-root.
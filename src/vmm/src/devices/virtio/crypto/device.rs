@@ -0,0 +1,757 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{self, Write};
+use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+
+use utils::eventfd::EventFd;
+use utils::u64_to_usize;
+use vm_memory::ByteValued;
+use zeroize::Zeroize;
+
+use super::metrics::METRICS;
+use super::request::{
+    ControlRequest, DataRequest, RequestBuffers, RequestError, CTRL_REQ_LEN, DATA_REQ_LEN,
+    MAX_REQUEST_SIZE, SESSION_INPUT_LEN, VIRTIO_CRYPTO_AEAD_GCM, VIRTIO_CRYPTO_CIPHER_AES_CTR,
+    VIRTIO_CRYPTO_MAC_HMAC_SHA_256, VIRTIO_CRYPTO_MAC_HMAC_SHA_384, VIRTIO_CRYPTO_MAC_HMAC_SHA_512,
+    VIRTIO_CRYPTO_OK, VIRTIO_CRYPTO_SERVICE_AEAD, VIRTIO_CRYPTO_SERVICE_CIPHER,
+    VIRTIO_CRYPTO_SERVICE_MAC,
+};
+use super::session::{Session, SessionTable, MAX_AUTH_KEY_LEN, MAX_CIPHER_KEY_LEN};
+use super::{CRYPTO_NUM_QUEUES, CTRL_QUEUE, DATA_QUEUE};
+use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, VirtioDevice};
+use crate::devices::virtio::gen::virtio_rng::VIRTIO_F_VERSION_1;
+use crate::devices::virtio::queue::{Queue, FIRECRACKER_MAX_QUEUE_SIZE};
+use crate::devices::virtio::{ActivateError, TYPE_CRYPTO};
+use crate::devices::DeviceError;
+use crate::logger::{debug, error, IncMetric};
+use crate::rate_limiter::{BucketUpdate, RateLimiter};
+use crate::vstate::memory::GuestMemoryMmap;
+
+pub const CRYPTO_DEV_ID: &str = "crypto";
+
+/// The device is ready to process requests.
+const VIRTIO_CRYPTO_S_HW_READY: u32 = 1;
+
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum CryptoError {
+    /// Error while handling an Event file descriptor: {0}
+    EventFd(#[from] io::Error),
+}
+
+/// Layout of `struct virtio_crypto_config`.
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct ConfigSpace {
+    pub status: u32,
+    pub max_dataqueues: u32,
+    pub crypto_services: u32,
+    pub cipher_algo_l: u32,
+    pub cipher_algo_h: u32,
+    pub hash_algo: u32,
+    pub mac_algo_l: u32,
+    pub mac_algo_h: u32,
+    pub aead_algo: u32,
+    pub max_cipher_key_len: u32,
+    pub max_auth_key_len: u32,
+    pub akcipher_algo: u32,
+    pub max_size: u64,
+}
+
+// SAFETY: `ConfigSpace` contains only PODs in `repr(C)`, without padding.
+unsafe impl ByteValued for ConfigSpace {}
+
+impl ConfigSpace {
+    fn new() -> Self {
+        Self {
+            status: VIRTIO_CRYPTO_S_HW_READY,
+            max_dataqueues: 1,
+            crypto_services: (1 << VIRTIO_CRYPTO_SERVICE_CIPHER)
+                | (1 << VIRTIO_CRYPTO_SERVICE_MAC)
+                | (1 << VIRTIO_CRYPTO_SERVICE_AEAD),
+            cipher_algo_l: 1 << VIRTIO_CRYPTO_CIPHER_AES_CTR,
+            mac_algo_l: (1 << VIRTIO_CRYPTO_MAC_HMAC_SHA_256)
+                | (1 << VIRTIO_CRYPTO_MAC_HMAC_SHA_384)
+                | (1 << VIRTIO_CRYPTO_MAC_HMAC_SHA_512),
+            aead_algo: 1 << VIRTIO_CRYPTO_AEAD_GCM,
+            max_cipher_key_len: u32::try_from(MAX_CIPHER_KEY_LEN).unwrap(),
+            max_auth_key_len: u32::try_from(MAX_AUTH_KEY_LEN).unwrap(),
+            max_size: MAX_REQUEST_SIZE as u64,
+            ..Default::default()
+        }
+    }
+}
+
+/// Virtio crypto device, performing AES-CTR, AES-GCM and HMAC operations for the guest with
+/// aws-lc-rs.
+#[derive(Debug)]
+pub struct Crypto {
+    // VirtIO fields
+    avail_features: u64,
+    acked_features: u64,
+    activate_event: EventFd,
+
+    // Transport fields
+    device_state: DeviceState,
+    queues: Vec<Queue>,
+    queue_events: Vec<EventFd>,
+    irq_trigger: IrqTrigger,
+
+    // Device specific fields
+    config_space: ConfigSpace,
+    rate_limiter: RateLimiter,
+    sessions: SessionTable,
+}
+
+impl Crypto {
+    pub fn new(rate_limiter: RateLimiter) -> Result<Self, CryptoError> {
+        let queues = vec![Queue::new(FIRECRACKER_MAX_QUEUE_SIZE); CRYPTO_NUM_QUEUES];
+        Self::new_with_queues(queues, rate_limiter)
+    }
+
+    pub fn new_with_queues(
+        queues: Vec<Queue>,
+        rate_limiter: RateLimiter,
+    ) -> Result<Self, CryptoError> {
+        let activate_event = EventFd::new(libc::EFD_NONBLOCK)?;
+        let queue_events = (0..CRYPTO_NUM_QUEUES)
+            .map(|_| EventFd::new(libc::EFD_NONBLOCK))
+            .collect::<Result<Vec<EventFd>, io::Error>>()?;
+        let irq_trigger = IrqTrigger::new()?;
+
+        Ok(Self {
+            avail_features: 1 << VIRTIO_F_VERSION_1,
+            acked_features: 0u64,
+            activate_event,
+            device_state: DeviceState::Inactive,
+            queues,
+            queue_events,
+            irq_trigger,
+            config_space: ConfigSpace::new(),
+            rate_limiter,
+            sessions: SessionTable::default(),
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        CRYPTO_DEV_ID
+    }
+
+    fn signal_used_queue(&self) -> Result<(), DeviceError> {
+        self.irq_trigger
+            .trigger_irq(IrqType::Vring)
+            .map_err(DeviceError::FailedSignalingIrq)
+    }
+
+    // Handles a request of the control queue, returning the number of bytes written to the guest
+    // buffers.
+    fn handle_ctrl_request(
+        sessions: &mut SessionTable,
+        mem: &GuestMemoryMmap,
+        buffers: &RequestBuffers,
+    ) -> u32 {
+        let result = ControlRequest::parse(&buffers.input).and_then(|request| match request {
+            ControlRequest::CreateSession { kind, key_len } => {
+                let key = CTRL_REQ_LEN
+                    .checked_add(key_len)
+                    .and_then(|end| buffers.input.get(CTRL_REQ_LEN..end))
+                    .ok_or(RequestError::InvalidLength("key", key_len))?;
+                let id = sessions.insert(Session::new(kind, key)?)?;
+                debug!(
+                    "crypto: created session {id}: {kind:?}, {} open",
+                    sessions.len()
+                );
+                METRICS.sessions_created.inc();
+                Ok(id)
+            }
+            ControlRequest::DestroySession {
+                service,
+                session_id,
+            } => {
+                sessions.remove(service, session_id)?;
+                debug!("crypto: destroyed session {session_id}");
+                METRICS.sessions_destroyed.inc();
+                Ok(session_id)
+            }
+        });
+        let (session_id, status) = match result {
+            Ok(session_id) => (session_id, VIRTIO_CRYPTO_OK),
+            Err(err) => {
+                error!("crypto: control request failed: {err}");
+                METRICS.session_fails.inc();
+                (0, err.status())
+            }
+        };
+
+        // Session creation requests are answered with a `struct virtio_crypto_session_input`,
+        // session destruction requests with a `struct virtio_crypto_inhdr`.
+        let written = if buffers.output_len() >= SESSION_INPUT_LEN {
+            let mut input = [0u8; SESSION_INPUT_LEN];
+            input[..8].copy_from_slice(&session_id.to_le_bytes());
+            input[8..12].copy_from_slice(&u32::from(status).to_le_bytes());
+            buffers.write_output(mem, 0, &input)
+        } else {
+            buffers.write_status(mem, status)
+        };
+        match written {
+            Ok(()) => u32::try_from(buffers.output_len()).unwrap(),
+            Err(err) => {
+                error!("crypto: could not write control request status: {err}");
+                METRICS.session_fails.inc();
+                0
+            }
+        }
+    }
+
+    // Handles a request of the data queue, returning the number of bytes written to the guest
+    // buffers.
+    fn handle_data_request(
+        sessions: &SessionTable,
+        mem: &GuestMemoryMmap,
+        buffers: &RequestBuffers,
+    ) -> u32 {
+        let result = DataRequest::parse(&buffers.input).and_then(|request| {
+            let payload = buffers
+                .input
+                .get(DATA_REQ_LEN..DATA_REQ_LEN + request.payload_len())
+                .ok_or(RequestError::InvalidLength("request", buffers.input.len()))?;
+            // The destination data is followed by the status.
+            if buffers.output_len() <= request.result_len() {
+                return Err(RequestError::DescriptorChainTooSmall);
+            }
+            let output = sessions
+                .get(request.session_id)?
+                .process(&request.op, payload)?;
+            buffers.write_output(mem, 0, &output)?;
+            METRICS.crypto_bytes.add(payload.len() as u64);
+            Ok(())
+        });
+        let status = match result {
+            Ok(()) => VIRTIO_CRYPTO_OK,
+            Err(err) => {
+                error!("crypto: data request failed: {err}");
+                METRICS.crypto_request_fails.inc();
+                err.status()
+            }
+        };
+
+        match buffers.write_status(mem, status) {
+            Ok(()) => u32::try_from(buffers.output_len()).unwrap(),
+            Err(err) => {
+                error!("crypto: could not write data request status: {err}");
+                METRICS.crypto_request_fails.inc();
+                0
+            }
+        }
+    }
+
+    fn add_used(&mut self, queue: usize, used: &[(u16, u32)]) {
+        if used.is_empty() {
+            return;
+        }
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+        if let Err(err) = self.queues[queue].add_used_batch(mem, used) {
+            error!("crypto: Could not add used descriptors to queue: {err}");
+            METRICS.event_fails.inc();
+            return;
+        }
+        self.signal_used_queue().unwrap_or_else(|err| {
+            error!("crypto: {err:?}");
+            METRICS.event_fails.inc()
+        });
+    }
+
+    fn process_ctrl_queue(&mut self) {
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+
+        let mut used = Vec::new();
+        while let Some(head) = self.queues[CTRL_QUEUE].pop(mem) {
+            let index = head.index;
+            let len = match RequestBuffers::parse(mem, head) {
+                Ok(mut buffers) => {
+                    let len = Self::handle_ctrl_request(&mut self.sessions, mem, &buffers);
+                    // The request holds the session key.
+                    buffers.input.zeroize();
+                    len
+                }
+                Err(err) => {
+                    error!("crypto: Could not parse control request: {err}");
+                    METRICS.session_fails.inc();
+                    0
+                }
+            };
+            used.push((index, len));
+        }
+
+        self.add_used(CTRL_QUEUE, &used);
+    }
+
+    fn process_data_queue(&mut self) {
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+
+        let mut used = Vec::new();
+        METRICS
+            .queue_depth_hist
+            .record(self.queues[DATA_QUEUE].len(mem).into());
+        while let Some(head) = self.queues[DATA_QUEUE].pop(mem) {
+            let index = head.index;
+            METRICS.crypto_request_count.inc();
+
+            let len = match RequestBuffers::parse(mem, head) {
+                Ok(mut buffers) => {
+                    // Check for available rate limiting budget.
+                    // If not enough budget is available, leave the request descriptor in the queue
+                    // to handle once we do have budget.
+                    let bytes = buffers.input.len() as u64;
+                    if !self.rate_limiter.consume_op(bytes) {
+                        debug!("crypto: throttling data queue");
+                        METRICS.crypto_rate_limiter_throttled.inc();
+                        buffers.input.zeroize();
+                        self.queues[DATA_QUEUE].undo_pop();
+                        break;
+                    }
+
+                    let _hist = METRICS.crypto_latency_hist.record_latency();
+                    let len = Self::handle_data_request(&self.sessions, mem, &buffers);
+                    // The request holds the plaintext of the guest.
+                    buffers.input.zeroize();
+                    len
+                }
+                Err(err) => {
+                    error!("crypto: Could not parse data request: {err}");
+                    METRICS.crypto_request_fails.inc();
+                    0
+                }
+            };
+            used.push((index, len));
+        }
+
+        self.add_used(DATA_QUEUE, &used);
+    }
+
+    pub(crate) fn process_ctrl_queue_event(&mut self) {
+        if let Err(err) = self.queues[CTRL_QUEUE].read_kicks(&self.queue_events[CTRL_QUEUE]) {
+            error!("Failed to read crypto control queue event: {err}");
+            METRICS.event_fails.inc();
+        } else {
+            self.process_ctrl_queue();
+        }
+    }
+
+    pub(crate) fn process_data_queue_event(&mut self) {
+        if let Err(err) = self.queues[DATA_QUEUE].read_kicks(&self.queue_events[DATA_QUEUE]) {
+            error!("Failed to read crypto data queue event: {err}");
+            METRICS.event_fails.inc();
+        } else if !self.rate_limiter.is_blocked() {
+            // We are not throttled, handle the data queue
+            self.process_data_queue();
+        } else {
+            METRICS.rate_limiter_event_count.inc();
+        }
+    }
+
+    pub(crate) fn process_rate_limiter_event(&mut self) {
+        METRICS.rate_limiter_event_count.inc();
+        match self.rate_limiter.event_handler() {
+            Ok(_) => {
+                // There might be enough budget now to process data requests.
+                self.process_data_queue();
+            }
+            Err(err) => {
+                error!("crypto: Failed to handle rate-limiter event: {err:?}");
+                METRICS.event_fails.inc();
+            }
+        }
+    }
+
+    pub fn process_virtio_queues(&mut self) {
+        self.process_ctrl_queue();
+        self.process_data_queue();
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    /// Updates the parameters of the rate limiter.
+    pub fn update_rate_limiter(&mut self, bytes: BucketUpdate, ops: BucketUpdate) {
+        self.rate_limiter.update_buckets(bytes, ops);
+    }
+
+    pub(crate) fn sessions(&self) -> &SessionTable {
+        &self.sessions
+    }
+
+    pub(crate) fn sessions_mut(&mut self) -> &mut SessionTable {
+        &mut self.sessions
+    }
+
+    pub(crate) fn set_acked_features(&mut self, features: u64) {
+        self.acked_features = features;
+    }
+
+    pub(crate) fn set_irq_status(&mut self, status: u32) {
+        self.irq_trigger.irq_status = Arc::new(AtomicU32::new(status));
+    }
+
+    pub(crate) fn set_activated(&mut self, mem: GuestMemoryMmap) {
+        self.device_state = DeviceState::Activated(mem);
+    }
+
+    pub(crate) fn activate_event(&self) -> &EventFd {
+        &self.activate_event
+    }
+}
+
+impl VirtioDevice for Crypto {
+    fn device_type(&self) -> u32 {
+        TYPE_CRYPTO
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_events
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.irq_trigger.irq_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicU32> {
+        self.irq_trigger.irq_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn set_avail_features(&mut self, avail_features: u64) {
+        self.avail_features = avail_features;
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_space = self.config_space.as_slice();
+        let config_len = config_space.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read config space");
+            METRICS.cfg_fails.inc();
+            return;
+        }
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(&config_space[u64_to_usize(offset)..u64_to_usize(end.min(config_len))])
+                .unwrap();
+        }
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: &[u8]) {
+        // The config space of the device is read-only.
+        error!("crypto: Guest attempted to write config");
+        METRICS.cfg_fails.inc();
+    }
+
+    fn is_activated(&self) -> bool {
+        self.device_state.is_activated()
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> Result<(), ActivateError> {
+        self.activate_event.write(1).map_err(|err| {
+            error!("crypto: Cannot write to activate_evt: {err}");
+            METRICS.activate_fails.inc();
+            super::super::ActivateError::BadActivate
+        })?;
+        self.device_state = DeviceState::Activated(mem);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::cast_possible_truncation)]
+
+    use std::time::Duration;
+
+    use super::*;
+    use crate::check_metric_after_block;
+    use crate::devices::virtio::crypto::request::tests::{ctrl_request, data_request};
+    use crate::devices::virtio::crypto::request::{
+        VIRTIO_CRYPTO_AEAD_CREATE_SESSION, VIRTIO_CRYPTO_BADMSG,
+        VIRTIO_CRYPTO_CIPHER_CREATE_SESSION, VIRTIO_CRYPTO_CIPHER_DESTROY_SESSION,
+        VIRTIO_CRYPTO_CIPHER_ENCRYPT, VIRTIO_CRYPTO_INVSESS, VIRTIO_CRYPTO_MAC,
+        VIRTIO_CRYPTO_MAC_CREATE_SESSION, VIRTIO_CRYPTO_NOTSUPP,
+    };
+    use crate::devices::virtio::queue::VIRTQ_DESC_F_WRITE;
+    use crate::devices::virtio::test_utils::test::{
+        create_virtio_mem, VirtioTestDevice, VirtioTestHelper,
+    };
+    use crate::vstate::memory::{Bytes, GuestAddress};
+
+    impl VirtioTestDevice for Crypto {
+        fn set_queues(&mut self, queues: Vec<Queue>) {
+            self.queues = queues;
+        }
+
+        fn num_queues() -> usize {
+            CRYPTO_NUM_QUEUES
+        }
+    }
+
+    fn default_crypto() -> Crypto {
+        Crypto::new(RateLimiter::default()).unwrap()
+    }
+
+    fn desc_addr(th: &VirtioTestHelper<Crypto>, queue: usize, index: usize) -> GuestAddress {
+        GuestAddress(th.virtqueue(queue).dtable[index].addr.get())
+    }
+
+    // Creates a session through the control queue, returning its identifier and status.
+    fn create_session(
+        th: &mut VirtioTestHelper<Crypto>,
+        mem: &GuestMemoryMmap,
+        request: &[u8],
+        key: &[u8],
+    ) -> (u64, u32) {
+        th.add_desc_chain(
+            CTRL_QUEUE,
+            0,
+            &[
+                (0, CTRL_REQ_LEN as u32, 0),
+                (1, key.len() as u32, 0),
+                (2, SESSION_INPUT_LEN as u32, VIRTQ_DESC_F_WRITE),
+            ],
+        );
+        mem.write_slice(request, desc_addr(th, CTRL_QUEUE, 0))
+            .unwrap();
+        mem.write_slice(key, desc_addr(th, CTRL_QUEUE, 1)).unwrap();
+        th.emulate_for_msec(100).unwrap();
+
+        let input: [u8; SESSION_INPUT_LEN] = mem.read_obj(desc_addr(th, CTRL_QUEUE, 2)).unwrap();
+        (
+            u64::from_le_bytes(input[..8].try_into().unwrap()),
+            u32::from_le_bytes(input[8..12].try_into().unwrap()),
+        )
+    }
+
+    // Posts a data request, returning the destination data and the status.
+    fn data_op(
+        th: &mut VirtioTestHelper<Crypto>,
+        mem: &GuestMemoryMmap,
+        request: &[u8],
+        payload: &[u8],
+        dst_len: usize,
+    ) -> (Vec<u8>, u8) {
+        th.add_desc_chain(
+            DATA_QUEUE,
+            0,
+            &[
+                (0, DATA_REQ_LEN as u32, 0),
+                (1, payload.len() as u32, 0),
+                (2, dst_len as u32, VIRTQ_DESC_F_WRITE),
+                (3, 1, VIRTQ_DESC_F_WRITE),
+            ],
+        );
+        mem.write_slice(request, desc_addr(th, DATA_QUEUE, 0))
+            .unwrap();
+        mem.write_slice(payload, desc_addr(th, DATA_QUEUE, 1))
+            .unwrap();
+        th.emulate_for_msec(100).unwrap();
+
+        let mut dst = vec![0u8; dst_len];
+        mem.read_slice(&mut dst, desc_addr(th, DATA_QUEUE, 2))
+            .unwrap();
+        let status: u8 = mem.read_obj(desc_addr(th, DATA_QUEUE, 3)).unwrap();
+        (dst, status)
+    }
+
+    #[test]
+    fn test_new() {
+        let crypto = default_crypto();
+
+        assert_eq!(crypto.avail_features(), 1 << VIRTIO_F_VERSION_1);
+        assert_eq!(crypto.acked_features(), 0);
+        assert!(!crypto.is_activated());
+        assert_eq!(crypto.id(), CRYPTO_DEV_ID);
+        assert_eq!(crypto.device_type(), TYPE_CRYPTO);
+    }
+
+    #[test]
+    fn test_config_space() {
+        let mut crypto = default_crypto();
+        assert_eq!(std::mem::size_of::<ConfigSpace>(), 56);
+
+        let mut data = [0u8; 4];
+        crypto.read_config(0, &mut data);
+        assert_eq!(u32::from_le_bytes(data), VIRTIO_CRYPTO_S_HW_READY);
+        crypto.read_config(8, &mut data);
+        assert_eq!(u32::from_le_bytes(data), 0b1101);
+        let mut data = [0u8; 8];
+        crypto.read_config(48, &mut data);
+        assert_eq!(u64::from_le_bytes(data), MAX_REQUEST_SIZE as u64);
+
+        // Out of bounds reads and writes fail.
+        let cfg_fails = METRICS.cfg_fails.count();
+        crypto.read_config(56, &mut data);
+        crypto.write_config(0, &data);
+        assert_eq!(METRICS.cfg_fails.count(), cfg_fails + 2);
+        let mut data = [0u8; 4];
+        crypto.read_config(0, &mut data);
+        assert_eq!(u32::from_le_bytes(data), VIRTIO_CRYPTO_S_HW_READY);
+    }
+
+    #[test]
+    fn test_cipher_session() {
+        let mem = create_virtio_mem();
+        let mut th = VirtioTestHelper::<Crypto>::new(&mem, default_crypto());
+        th.activate_device(&mem);
+
+        let key = [0x11; 16];
+        let request = ctrl_request(
+            VIRTIO_CRYPTO_CIPHER_CREATE_SESSION,
+            &[VIRTIO_CRYPTO_CIPHER_AES_CTR, 16, 1],
+        );
+        let (session_id, status) = create_session(&mut th, &mem, &request, &key);
+        assert_eq!(status, u32::from(VIRTIO_CRYPTO_OK));
+        assert_eq!(th.device().sessions().len(), 1);
+
+        // Encrypting twice gets back the plaintext.
+        let iv = [0x22; 16];
+        let plaintext = b"firecracker crypto".to_vec();
+        let len = plaintext.len() as u32;
+        let request = data_request(VIRTIO_CRYPTO_CIPHER_ENCRYPT, session_id, &[16, len, len]);
+        let payload = [iv.as_slice(), &plaintext].concat();
+        let (ciphertext, status) = data_op(&mut th, &mem, &request, &payload, plaintext.len());
+        assert_eq!(status, VIRTIO_CRYPTO_OK);
+        assert_ne!(ciphertext, plaintext);
+        let payload = [iv.as_slice(), &ciphertext].concat();
+        let (decrypted, status) = data_op(&mut th, &mem, &request, &payload, plaintext.len());
+        assert_eq!(status, VIRTIO_CRYPTO_OK);
+        assert_eq!(decrypted, plaintext);
+
+        // The session can't be used for MACs.
+        let request = data_request(VIRTIO_CRYPTO_MAC, session_id, &[len, 32]);
+        let (_, status) = data_op(&mut th, &mem, &request, &plaintext, 32);
+        assert_eq!(status, VIRTIO_CRYPTO_BADMSG);
+
+        // Destroy the session.
+        let mut request = ctrl_request(VIRTIO_CRYPTO_CIPHER_DESTROY_SESSION, &[]);
+        request[16..24].copy_from_slice(&session_id.to_le_bytes());
+        th.add_desc_chain(
+            CTRL_QUEUE,
+            0,
+            &[(0, CTRL_REQ_LEN as u32, 0), (1, 1, VIRTQ_DESC_F_WRITE)],
+        );
+        mem.write_slice(&request, desc_addr(&th, CTRL_QUEUE, 0))
+            .unwrap();
+        th.emulate_for_msec(100).unwrap();
+        let status: u8 = mem.read_obj(desc_addr(&th, CTRL_QUEUE, 1)).unwrap();
+        assert_eq!(status, VIRTIO_CRYPTO_OK);
+        assert_eq!(th.device().sessions().len(), 0);
+
+        let request = data_request(VIRTIO_CRYPTO_CIPHER_ENCRYPT, session_id, &[16, len, len]);
+        let (_, status) = data_op(&mut th, &mem, &request, &payload, plaintext.len());
+        assert_eq!(status, VIRTIO_CRYPTO_INVSESS);
+    }
+
+    #[test]
+    fn test_mac_and_aead_sessions() {
+        let mem = create_virtio_mem();
+        let mut th = VirtioTestHelper::<Crypto>::new(&mem, default_crypto());
+        th.activate_device(&mem);
+
+        let request = ctrl_request(
+            VIRTIO_CRYPTO_MAC_CREATE_SESSION,
+            &[VIRTIO_CRYPTO_MAC_HMAC_SHA_256, 32, 4],
+        );
+        let (mac_session, status) = create_session(&mut th, &mem, &request, b"Jefe");
+        assert_eq!(status, u32::from(VIRTIO_CRYPTO_OK));
+        let data = b"what do ya want for nothing?";
+        let request = data_request(VIRTIO_CRYPTO_MAC, mac_session, &[data.len() as u32, 32]);
+        let (mac, status) = data_op(&mut th, &mem, &request, data, 32);
+        assert_eq!(status, VIRTIO_CRYPTO_OK);
+        assert_eq!(mac[..4], [0x5b, 0xdc, 0xc1, 0x46], "RFC 4231 test case 2");
+
+        let request = ctrl_request(
+            VIRTIO_CRYPTO_AEAD_CREATE_SESSION,
+            &[VIRTIO_CRYPTO_AEAD_GCM, 16, 16, 0, 1],
+        );
+        let (aead_session, status) = create_session(&mut th, &mem, &request, &[0x33; 16]);
+        assert_eq!(status, u32::from(VIRTIO_CRYPTO_OK));
+        assert_ne!(aead_session, mac_session);
+        assert_eq!(th.device().sessions().len(), 2);
+
+        // Unsupported algorithms are reported to the guest.
+        let request = ctrl_request(VIRTIO_CRYPTO_AEAD_CREATE_SESSION, &[2, 16, 16, 0, 1]);
+        let session_fails = METRICS.session_fails.count();
+        let (_, status) = create_session(&mut th, &mem, &request, &[0x33; 16]);
+        assert_eq!(status, u32::from(VIRTIO_CRYPTO_NOTSUPP));
+        assert_eq!(METRICS.session_fails.count(), session_fails + 1);
+    }
+
+    #[test]
+    fn test_bad_data_request() {
+        let mem = create_virtio_mem();
+        let mut th = VirtioTestHelper::<Crypto>::new(&mem, default_crypto());
+        th.activate_device(&mem);
+
+        // A descriptor chain without writable descriptors cannot get a status.
+        th.add_desc_chain(DATA_QUEUE, 0, &[(0, DATA_REQ_LEN as u32, 0)]);
+        check_metric_after_block!(
+            METRICS.crypto_request_fails,
+            1,
+            th.emulate_for_msec(100).unwrap()
+        );
+        th.virtqueue(DATA_QUEUE).check_used_elem(0, 0, 0);
+
+        // The payload is shorter than announced by the request.
+        let request = data_request(VIRTIO_CRYPTO_MAC, 0, &[64, 32]);
+        let (_, status) = data_op(&mut th, &mem, &request, &[0; 16], 32);
+        assert_eq!(status, VIRTIO_CRYPTO_BADMSG);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mem = create_virtio_mem();
+        // One operation per 100ms.
+        let rate_limiter = RateLimiter::new(0, 0, 0, 1, 0, 100).unwrap();
+        let mut th = VirtioTestHelper::<Crypto>::new(&mem, Crypto::new(rate_limiter).unwrap());
+        th.activate_device(&mem);
+
+        let request = data_request(VIRTIO_CRYPTO_MAC, 0, &[16, 32]);
+        let (_, status) = data_op(&mut th, &mem, &request, &[0; 16], 32);
+        assert_eq!(status, VIRTIO_CRYPTO_INVSESS);
+
+        // The second request is throttled until the rate limiter is replenished.
+        check_metric_after_block!(
+            METRICS.crypto_rate_limiter_throttled,
+            1,
+            data_op(&mut th, &mem, &request, &[0; 16], 32)
+        );
+        assert!(th.device().rate_limiter().is_blocked());
+        std::thread::sleep(Duration::from_millis(200));
+        check_metric_after_block!(
+            METRICS.rate_limiter_event_count,
+            1,
+            th.emulate_for_msec(100).unwrap()
+        );
+        th.virtqueue(DATA_QUEUE).check_used_elem(1, 0, 33);
+    }
+}
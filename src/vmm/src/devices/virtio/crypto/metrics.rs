@@ -0,0 +1,113 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the metrics system for crypto devices.
+//!
+//! # Metrics format
+//! The metrics are flushed in JSON when requested by vmm::logger::metrics::METRICS.write().
+//!
+//! ## JSON example with metrics:
+//! ```json
+//!  "crypto": {
+//!     "activate_fails": "SharedIncMetric",
+//!     "sessions_created": "SharedIncMetric",
+//!     "crypto_request_count": "SharedIncMetric",
+//!     ...
+//!  }
+//! }
+//! ```
+//! Each `crypto` field in the example above is a serializable `CryptoDeviceMetrics` structure
+//! collecting metrics such as `activate_fails`, `sessions_created` etc. for the crypto device.
+//! Since crypto doesn't support multiple devices, there is no per device metrics and
+//! `crypto` represents the aggregate crypto metrics.
+//!
+//! # Design
+//! The system implements 2 types of metrics:
+//! * Shared Incremental Metrics (SharedIncMetrics) - dedicated for the metrics which need a counter
+//!   (i.e the number of times an API request failed). These metrics are reset upon flush.
+//! * Shared Histogram Metrics (SharedHistogramMetrics) - count the recorded values falling in each
+//!   of a fixed set of buckets. These metrics are reset upon flush.
+
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+
+use crate::logger::{LatencyBuckets, QueueDepthBuckets, SharedHistogramMetric, SharedIncMetric};
+
+/// Stores aggregated crypto metrics
+pub(super) static METRICS: CryptoDeviceMetrics = CryptoDeviceMetrics::new();
+
+/// Called by METRICS.flush(), this function facilitates serialization of crypto device metrics.
+pub fn flush_metrics<S: Serializer>(serializer: S) -> Result<S::Ok, S::Error> {
+    let mut seq = serializer.serialize_map(Some(1))?;
+    seq.serialize_entry("crypto", &METRICS)?;
+    seq.end()
+}
+
+#[derive(Debug, Serialize)]
+pub(super) struct CryptoDeviceMetrics {
+    /// Number of device activation failures
+    pub activate_fails: SharedIncMetric,
+    /// Number of invalid config space accesses
+    pub cfg_fails: SharedIncMetric,
+    /// Number of queue event handling failures
+    pub event_fails: SharedIncMetric,
+    /// Number of sessions created by the guest
+    pub sessions_created: SharedIncMetric,
+    /// Number of sessions destroyed by the guest
+    pub sessions_destroyed: SharedIncMetric,
+    /// Number of failed control requests
+    pub session_fails: SharedIncMetric,
+    /// Number of data requests handled
+    pub crypto_request_count: SharedIncMetric,
+    /// Number of failed data requests
+    pub crypto_request_fails: SharedIncMetric,
+    /// Number of source bytes processed for the guest
+    pub crypto_bytes: SharedIncMetric,
+    /// Number of times a data request was rate limited
+    pub crypto_rate_limiter_throttled: SharedIncMetric,
+    /// Number of events associated with the rate limiter
+    pub rate_limiter_event_count: SharedIncMetric,
+    /// Histogram of the durations of the data requests, in microseconds
+    pub crypto_latency_hist: SharedHistogramMetric<LatencyBuckets>,
+    /// Histogram of the number of requests pending in the data queue when it is processed
+    pub queue_depth_hist: SharedHistogramMetric<QueueDepthBuckets>,
+}
+impl CryptoDeviceMetrics {
+    /// Const default construction.
+    const fn new() -> Self {
+        Self {
+            activate_fails: SharedIncMetric::new(),
+            cfg_fails: SharedIncMetric::new(),
+            event_fails: SharedIncMetric::new(),
+            sessions_created: SharedIncMetric::new(),
+            sessions_destroyed: SharedIncMetric::new(),
+            session_fails: SharedIncMetric::new(),
+            crypto_request_count: SharedIncMetric::new(),
+            crypto_request_fails: SharedIncMetric::new(),
+            crypto_bytes: SharedIncMetric::new(),
+            crypto_rate_limiter_throttled: SharedIncMetric::new(),
+            rate_limiter_event_count: SharedIncMetric::new(),
+            crypto_latency_hist: SharedHistogramMetric::new(),
+            queue_depth_hist: SharedHistogramMetric::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::logger::IncMetric;
+
+    #[test]
+    fn test_crypto_dev_metrics() {
+        let crypto_metrics: CryptoDeviceMetrics = CryptoDeviceMetrics::new();
+        let crypto_metrics_local: String = serde_json::to_string(&crypto_metrics).unwrap();
+        // the 1st serialize flushes the metrics and resets values to 0 so that
+        // we can compare the values with local metrics.
+        serde_json::to_string(&METRICS).unwrap();
+        let crypto_metrics_global: String = serde_json::to_string(&METRICS).unwrap();
+        assert_eq!(crypto_metrics_local, crypto_metrics_global);
+        crypto_metrics.sessions_created.inc();
+        assert_eq!(crypto_metrics.sessions_created.count(), 1);
+    }
+}
@@ -0,0 +1,16 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod device;
+mod event_handler;
+pub mod metrics;
+pub mod persist;
+mod request;
+mod session;
+
+pub use self::device::{Crypto, CryptoError};
+
+pub(crate) const CRYPTO_NUM_QUEUES: usize = 2;
+
+pub(crate) const DATA_QUEUE: usize = 0;
+pub(crate) const CTRL_QUEUE: usize = 1;
@@ -0,0 +1,184 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structures needed for saving/restoring crypto devices.
+
+use serde::{Deserialize, Serialize};
+
+use super::request::RequestError;
+use super::session::{Session, SessionKind};
+use crate::devices::virtio::crypto::{Crypto, CryptoError, CRYPTO_NUM_QUEUES};
+use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::persist::{
+    PersistError as VirtioStateError, SnapshotFeature, VirtioDeviceState,
+};
+use crate::devices::virtio::queue::FIRECRACKER_MAX_QUEUE_SIZE;
+use crate::devices::virtio::TYPE_CRYPTO;
+use crate::rate_limiter::persist::RateLimiterState;
+use crate::rate_limiter::RateLimiter;
+use crate::snapshot::Persist;
+use crate::vstate::memory::GuestMemoryMmap;
+
+/// Session of a crypto device, with its key.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CryptoSessionState {
+    id: u64,
+    kind: SessionKind,
+    key: Vec<u8>,
+}
+
+impl std::fmt::Debug for CryptoSessionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CryptoSessionState")
+            .field("id", &self.id)
+            .field("kind", &self.kind)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CryptoState {
+    virtio_state: VirtioDeviceState,
+    rate_limiter_state: RateLimiterState,
+    sessions: Vec<CryptoSessionState>,
+    next_session_id: u64,
+}
+
+impl CryptoState {
+    /// Snapshot features used by the device.
+    pub fn snapshot_features(&self) -> Vec<SnapshotFeature> {
+        let mut features = self.virtio_state.snapshot_features();
+        features.push(SnapshotFeature::CryptoDevice);
+        features
+    }
+}
+
+#[derive(Debug)]
+pub struct CryptoConstructorArgs(GuestMemoryMmap);
+
+impl CryptoConstructorArgs {
+    pub fn new(mem: GuestMemoryMmap) -> Self {
+        Self(mem)
+    }
+}
+
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum CryptoPersistError {
+    /// Create crypto device: {0}
+    CreateCrypto(#[from] CryptoError),
+    /// Virtio state: {0}
+    VirtioState(#[from] VirtioStateError),
+    /// Restore rate limiter: {0}
+    RestoreRateLimiter(#[from] std::io::Error),
+    /// Restore session {0}: {1}
+    RestoreSession(u64, RequestError),
+}
+
+impl Persist<'_> for Crypto {
+    type State = CryptoState;
+    type ConstructorArgs = CryptoConstructorArgs;
+    type Error = CryptoPersistError;
+
+    fn save(&self) -> Self::State {
+        CryptoState {
+            virtio_state: VirtioDeviceState::from_device(self),
+            rate_limiter_state: self.rate_limiter().save(),
+            sessions: self
+                .sessions()
+                .iter()
+                .map(|(id, session)| CryptoSessionState {
+                    id,
+                    kind: session.kind(),
+                    key: session.key_bytes().to_vec(),
+                })
+                .collect(),
+            next_session_id: self.sessions().next_id(),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> Result<Self, Self::Error> {
+        let queues = state.virtio_state.build_queues_checked(
+            &constructor_args.0,
+            TYPE_CRYPTO,
+            CRYPTO_NUM_QUEUES,
+            FIRECRACKER_MAX_QUEUE_SIZE,
+        )?;
+
+        let rate_limiter = RateLimiter::restore((), &state.rate_limiter_state)?;
+        let mut crypto = Crypto::new_with_queues(queues, rate_limiter)?;
+        for session in &state.sessions {
+            let restored = Session::new(session.kind, &session.key)
+                .map_err(|err| CryptoPersistError::RestoreSession(session.id, err))?;
+            crypto
+                .sessions_mut()
+                .restore(session.id, restored, state.next_session_id);
+        }
+        crypto.set_avail_features(state.virtio_state.avail_features);
+        crypto.set_acked_features(state.virtio_state.acked_features);
+        crypto.set_irq_status(state.virtio_state.interrupt_status);
+        if state.virtio_state.activated {
+            crypto.set_activated(constructor_args.0);
+        }
+
+        Ok(crypto)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::devices::virtio::crypto::device::CRYPTO_DEV_ID;
+    use crate::devices::virtio::crypto::request::{
+        VIRTIO_CRYPTO_MAC_HMAC_SHA_256, VIRTIO_CRYPTO_SERVICE_CIPHER,
+    };
+    use crate::devices::virtio::test_utils::test::create_virtio_mem;
+    use crate::snapshot::Snapshot;
+
+    #[test]
+    fn test_persistence() {
+        let mut mem = vec![0u8; 4096];
+        let mut crypto = Crypto::new(RateLimiter::default()).unwrap();
+        let sessions = crypto.sessions_mut();
+        sessions
+            .insert(Session::new(SessionKind::AesCtr, &[0x11; 16]).unwrap())
+            .unwrap();
+        let kind = SessionKind::hmac(VIRTIO_CRYPTO_MAC_HMAC_SHA_256, 32).unwrap();
+        let id = sessions
+            .insert(Session::new(kind, b"key").unwrap())
+            .unwrap();
+        sessions.remove(VIRTIO_CRYPTO_SERVICE_CIPHER, 0).unwrap();
+
+        let state = crypto.save();
+        assert_eq!(
+            state.snapshot_features(),
+            vec![SnapshotFeature::CryptoDevice]
+        );
+        Snapshot::serialize(&mut mem.as_mut_slice(), &state).unwrap();
+
+        let guest_mem = create_virtio_mem();
+        let restored = Crypto::restore(
+            CryptoConstructorArgs(guest_mem),
+            &Snapshot::deserialize(&mut mem.as_slice()).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored.device_type(), TYPE_CRYPTO);
+        assert_eq!(restored.id(), CRYPTO_DEV_ID);
+        assert_eq!(restored.is_activated(), crypto.is_activated());
+        assert_eq!(restored.avail_features(), crypto.avail_features());
+        assert_eq!(restored.acked_features(), crypto.acked_features());
+        assert_eq!(
+            restored.interrupt_status().load(Ordering::Relaxed),
+            crypto.interrupt_status().load(Ordering::Relaxed)
+        );
+        assert_eq!(restored.sessions().len(), 1);
+        assert_eq!(restored.sessions().get(id).unwrap().kind(), kind);
+        assert_eq!(restored.sessions().get(id).unwrap().key_bytes(), b"key");
+        assert_eq!(restored.sessions().next_id(), 2);
+    }
+}
@@ -0,0 +1,554 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Requests of the control and data queues of the crypto device, as laid out in
+//! linux/virtio_crypto.h.
+
+use vm_memory::GuestMemoryError;
+
+use super::session::SessionKind;
+use crate::devices::virtio::queue::DescriptorChain;
+use crate::vstate::memory::{Address, Bytes, GuestAddress, GuestMemoryMmap};
+
+pub const VIRTIO_CRYPTO_SERVICE_CIPHER: u32 = 0;
+pub const VIRTIO_CRYPTO_SERVICE_MAC: u32 = 2;
+pub const VIRTIO_CRYPTO_SERVICE_AEAD: u32 = 3;
+
+const fn opcode(service: u32, op: u32) -> u32 {
+    (service << 8) | op
+}
+
+pub const VIRTIO_CRYPTO_CIPHER_CREATE_SESSION: u32 = opcode(VIRTIO_CRYPTO_SERVICE_CIPHER, 0x02);
+pub const VIRTIO_CRYPTO_CIPHER_DESTROY_SESSION: u32 = opcode(VIRTIO_CRYPTO_SERVICE_CIPHER, 0x03);
+pub const VIRTIO_CRYPTO_MAC_CREATE_SESSION: u32 = opcode(VIRTIO_CRYPTO_SERVICE_MAC, 0x02);
+pub const VIRTIO_CRYPTO_MAC_DESTROY_SESSION: u32 = opcode(VIRTIO_CRYPTO_SERVICE_MAC, 0x03);
+pub const VIRTIO_CRYPTO_AEAD_CREATE_SESSION: u32 = opcode(VIRTIO_CRYPTO_SERVICE_AEAD, 0x02);
+pub const VIRTIO_CRYPTO_AEAD_DESTROY_SESSION: u32 = opcode(VIRTIO_CRYPTO_SERVICE_AEAD, 0x03);
+
+pub const VIRTIO_CRYPTO_CIPHER_ENCRYPT: u32 = opcode(VIRTIO_CRYPTO_SERVICE_CIPHER, 0x00);
+pub const VIRTIO_CRYPTO_CIPHER_DECRYPT: u32 = opcode(VIRTIO_CRYPTO_SERVICE_CIPHER, 0x01);
+pub const VIRTIO_CRYPTO_MAC: u32 = opcode(VIRTIO_CRYPTO_SERVICE_MAC, 0x00);
+pub const VIRTIO_CRYPTO_AEAD_ENCRYPT: u32 = opcode(VIRTIO_CRYPTO_SERVICE_AEAD, 0x00);
+pub const VIRTIO_CRYPTO_AEAD_DECRYPT: u32 = opcode(VIRTIO_CRYPTO_SERVICE_AEAD, 0x01);
+
+pub const VIRTIO_CRYPTO_CIPHER_AES_CTR: u32 = 4;
+pub const VIRTIO_CRYPTO_MAC_HMAC_SHA_256: u32 = 4;
+pub const VIRTIO_CRYPTO_MAC_HMAC_SHA_384: u32 = 5;
+pub const VIRTIO_CRYPTO_MAC_HMAC_SHA_512: u32 = 6;
+pub const VIRTIO_CRYPTO_AEAD_GCM: u32 = 1;
+
+pub const VIRTIO_CRYPTO_SYM_OP_CIPHER: u32 = 1;
+
+pub const VIRTIO_CRYPTO_OK: u8 = 0;
+pub const VIRTIO_CRYPTO_ERR: u8 = 1;
+pub const VIRTIO_CRYPTO_BADMSG: u8 = 2;
+pub const VIRTIO_CRYPTO_NOTSUPP: u8 = 3;
+pub const VIRTIO_CRYPTO_INVSESS: u8 = 4;
+pub const VIRTIO_CRYPTO_NOSPC: u8 = 5;
+
+/// Size of `struct virtio_crypto_op_ctrl_req`.
+pub const CTRL_REQ_LEN: usize = 72;
+/// Size of `struct virtio_crypto_op_data_req`.
+pub const DATA_REQ_LEN: usize = 72;
+/// Size of `struct virtio_crypto_session_input`.
+pub const SESSION_INPUT_LEN: usize = 16;
+
+/// Offset of the service specific part of the control requests, after
+/// `struct virtio_crypto_ctrl_header`.
+const CTRL_PARA_OFFSET: usize = 16;
+/// Offset of `op_type` in `struct virtio_crypto_sym_create_session_req`.
+const CTRL_SYM_OP_TYPE_OFFSET: usize = CTRL_PARA_OFFSET + 48;
+/// Offset of the service specific part of the data requests, after
+/// `struct virtio_crypto_op_header`.
+const DATA_PARA_OFFSET: usize = 24;
+/// Offset of `op_type` in `struct virtio_crypto_sym_data_req`.
+const DATA_SYM_OP_TYPE_OFFSET: usize = DATA_PARA_OFFSET + 40;
+
+/// Maximum size of the data of a request: its IV, additional authenticated data and source data.
+pub const MAX_REQUEST_SIZE: usize = 1 << 20;
+
+/// Error while handling a crypto request.
+#[derive(Debug, thiserror::Error, displaydoc::Display, PartialEq, Eq)]
+pub enum RequestError {
+    /// Guest memory error: {0}
+    GuestMemory(String),
+    /// The request is larger than the maximum request size.
+    TooLarge,
+    /// The descriptor chain is too small for the request.
+    DescriptorChainTooSmall,
+    /// Unsupported opcode {0:#x}.
+    UnsupportedOpcode(u32),
+    /// Unsupported algorithm {0}.
+    UnsupportedAlgorithm(u32),
+    /// Invalid length of {0}: {1}.
+    InvalidLength(&'static str, usize),
+    /// The opcode does not match the session.
+    SessionMismatch,
+    /// Invalid session {0}.
+    InvalidSession(u64),
+    /// No free session.
+    NoSpace,
+    /// Authentication of the data failed.
+    Authentication,
+    /// The crypto operation failed.
+    Crypto,
+}
+
+impl RequestError {
+    /// Status reported to the guest for the error.
+    pub fn status(&self) -> u8 {
+        match self {
+            RequestError::UnsupportedOpcode(_) | RequestError::UnsupportedAlgorithm(_) => {
+                VIRTIO_CRYPTO_NOTSUPP
+            }
+            RequestError::InvalidSession(_) => VIRTIO_CRYPTO_INVSESS,
+            RequestError::NoSpace => VIRTIO_CRYPTO_NOSPC,
+            RequestError::TooLarge
+            | RequestError::InvalidLength(..)
+            | RequestError::SessionMismatch
+            | RequestError::Authentication => VIRTIO_CRYPTO_BADMSG,
+            RequestError::GuestMemory(_)
+            | RequestError::DescriptorChainTooSmall
+            | RequestError::Crypto => VIRTIO_CRYPTO_ERR,
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_len(bytes: &[u8], offset: usize) -> usize {
+    read_u32(bytes, offset) as usize
+}
+
+/// Descriptor chain of a request, split in the bytes provided by the guest and the guest memory
+/// ranges the device writes to.
+#[derive(Debug)]
+pub struct RequestBuffers {
+    /// Contents of the device-readable descriptors.
+    pub input: Vec<u8>,
+    output: Vec<(GuestAddress, usize)>,
+}
+
+impl RequestBuffers {
+    /// Reads the request in the descriptor chain starting at `head`.
+    pub fn parse(mem: &GuestMemoryMmap, head: DescriptorChain) -> Result<Self, RequestError> {
+        let mut input = Vec::new();
+        let mut output = Vec::new();
+
+        for descriptor in head {
+            let len = descriptor.len as usize;
+            if descriptor.is_write_only() {
+                output.push((descriptor.addr, len));
+                continue;
+            }
+            // The device-readable descriptors come first.
+            if !output.is_empty() {
+                return Err(RequestError::DescriptorChainTooSmall);
+            }
+            let start = input.len();
+            let end = start + len;
+            if end > DATA_REQ_LEN + MAX_REQUEST_SIZE {
+                return Err(RequestError::TooLarge);
+            }
+            input.resize(end, 0);
+            mem.read_slice(&mut input[start..], descriptor.addr)
+                .map_err(|err: GuestMemoryError| RequestError::GuestMemory(err.to_string()))?;
+        }
+
+        // There is no room for the status of the request.
+        if output.is_empty() {
+            return Err(RequestError::DescriptorChainTooSmall);
+        }
+        Ok(Self { input, output })
+    }
+
+    /// Total size of the device-writable descriptors.
+    pub fn output_len(&self) -> usize {
+        self.output.iter().map(|&(_, len)| len).sum()
+    }
+
+    /// Writes `data` at `offset` in the device-writable descriptors.
+    pub fn write_output(
+        &self,
+        mem: &GuestMemoryMmap,
+        mut offset: usize,
+        mut data: &[u8],
+    ) -> Result<(), RequestError> {
+        for &(addr, len) in &self.output {
+            if data.is_empty() {
+                break;
+            }
+            if offset >= len {
+                offset -= len;
+                continue;
+            }
+            let count = data.len().min(len - offset);
+            // The range was checked when the descriptor chain was popped from the queue.
+            mem.write_slice(&data[..count], addr.unchecked_add(offset as u64))
+                .map_err(|err| RequestError::GuestMemory(err.to_string()))?;
+            data = &data[count..];
+            offset = 0;
+        }
+
+        if !data.is_empty() {
+            return Err(RequestError::DescriptorChainTooSmall);
+        }
+        Ok(())
+    }
+
+    /// Writes the status of a request to the last byte of the device-writable descriptors.
+    pub fn write_status(&self, mem: &GuestMemoryMmap, status: u8) -> Result<(), RequestError> {
+        match self.output_len().checked_sub(1) {
+            Some(offset) => self.write_output(mem, offset, &[status]),
+            None => Err(RequestError::DescriptorChainTooSmall),
+        }
+    }
+}
+
+/// Request of the control queue.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ControlRequest {
+    /// Creates a session, whose key follows the request.
+    CreateSession {
+        /// Algorithm of the session.
+        kind: SessionKind,
+        /// Length of the key.
+        key_len: usize,
+    },
+    /// Destroys a session.
+    DestroySession {
+        /// Service the session belongs to.
+        service: u32,
+        /// Identifier of the session.
+        session_id: u64,
+    },
+}
+
+impl ControlRequest {
+    /// Parses `struct virtio_crypto_op_ctrl_req`.
+    pub fn parse(input: &[u8]) -> Result<Self, RequestError> {
+        if input.len() < CTRL_REQ_LEN {
+            return Err(RequestError::InvalidLength("request", input.len()));
+        }
+        let opcode = read_u32(input, 0);
+        let para = CTRL_PARA_OFFSET;
+
+        let (kind, key_len) = match opcode {
+            VIRTIO_CRYPTO_CIPHER_CREATE_SESSION => {
+                let op_type = read_u32(input, CTRL_SYM_OP_TYPE_OFFSET);
+                if op_type != VIRTIO_CRYPTO_SYM_OP_CIPHER {
+                    return Err(RequestError::UnsupportedOpcode(op_type));
+                }
+                let algo = read_u32(input, para);
+                if algo != VIRTIO_CRYPTO_CIPHER_AES_CTR {
+                    return Err(RequestError::UnsupportedAlgorithm(algo));
+                }
+                (SessionKind::AesCtr, read_len(input, para + 4))
+            }
+            VIRTIO_CRYPTO_MAC_CREATE_SESSION => {
+                let algo = read_u32(input, para);
+                let kind = SessionKind::hmac(algo, read_len(input, para + 4))?;
+                (kind, read_len(input, para + 8))
+            }
+            VIRTIO_CRYPTO_AEAD_CREATE_SESSION => {
+                let algo = read_u32(input, para);
+                if algo != VIRTIO_CRYPTO_AEAD_GCM {
+                    return Err(RequestError::UnsupportedAlgorithm(algo));
+                }
+                let kind = SessionKind::aes_gcm(read_len(input, para + 8))?;
+                (kind, read_len(input, para + 4))
+            }
+            VIRTIO_CRYPTO_CIPHER_DESTROY_SESSION
+            | VIRTIO_CRYPTO_MAC_DESTROY_SESSION
+            | VIRTIO_CRYPTO_AEAD_DESTROY_SESSION => {
+                return Ok(ControlRequest::DestroySession {
+                    service: opcode >> 8,
+                    session_id: read_u64(input, para),
+                });
+            }
+            _ => return Err(RequestError::UnsupportedOpcode(opcode)),
+        };
+
+        Ok(ControlRequest::CreateSession { kind, key_len })
+    }
+}
+
+/// Operation of a request of the data queue.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DataOp {
+    /// Encrypts or decrypts the source data with a cipher.
+    Cipher {
+        /// Whether the data is encrypted.
+        encrypt: bool,
+        /// Length of the IV.
+        iv_len: usize,
+        /// Length of the source data.
+        src_len: usize,
+        /// Length of the destination data.
+        dst_len: usize,
+    },
+    /// Computes the MAC of the source data.
+    Mac {
+        /// Length of the source data.
+        src_len: usize,
+        /// Length of the MAC.
+        result_len: usize,
+    },
+    /// Encrypts or decrypts the source data with an AEAD algorithm.
+    Aead {
+        /// Whether the data is encrypted.
+        encrypt: bool,
+        /// Length of the IV.
+        iv_len: usize,
+        /// Length of the additional authenticated data.
+        aad_len: usize,
+        /// Length of the source data, including the tag when decrypting.
+        src_len: usize,
+        /// Length of the destination data, including the tag when encrypting.
+        dst_len: usize,
+    },
+}
+
+/// Request of the data queue.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DataRequest {
+    /// Session the request uses.
+    pub session_id: u64,
+    /// Requested operation.
+    pub op: DataOp,
+}
+
+impl DataRequest {
+    /// Parses `struct virtio_crypto_op_data_req`.
+    pub fn parse(input: &[u8]) -> Result<Self, RequestError> {
+        if input.len() < DATA_REQ_LEN {
+            return Err(RequestError::InvalidLength("request", input.len()));
+        }
+        let opcode = read_u32(input, 0);
+        let session_id = read_u64(input, 8);
+        let para = DATA_PARA_OFFSET;
+
+        let op = match opcode {
+            VIRTIO_CRYPTO_CIPHER_ENCRYPT | VIRTIO_CRYPTO_CIPHER_DECRYPT => {
+                let op_type = read_u32(input, DATA_SYM_OP_TYPE_OFFSET);
+                if op_type != VIRTIO_CRYPTO_SYM_OP_CIPHER {
+                    return Err(RequestError::UnsupportedOpcode(op_type));
+                }
+                DataOp::Cipher {
+                    encrypt: opcode == VIRTIO_CRYPTO_CIPHER_ENCRYPT,
+                    iv_len: read_len(input, para),
+                    src_len: read_len(input, para + 4),
+                    dst_len: read_len(input, para + 8),
+                }
+            }
+            VIRTIO_CRYPTO_MAC => DataOp::Mac {
+                src_len: read_len(input, para),
+                result_len: read_len(input, para + 4),
+            },
+            VIRTIO_CRYPTO_AEAD_ENCRYPT | VIRTIO_CRYPTO_AEAD_DECRYPT => DataOp::Aead {
+                encrypt: opcode == VIRTIO_CRYPTO_AEAD_ENCRYPT,
+                iv_len: read_len(input, para),
+                aad_len: read_len(input, para + 4),
+                src_len: read_len(input, para + 8),
+                dst_len: read_len(input, para + 12),
+            },
+            _ => return Err(RequestError::UnsupportedOpcode(opcode)),
+        };
+
+        let request = Self { session_id, op };
+        if request.payload_len() > MAX_REQUEST_SIZE {
+            return Err(RequestError::TooLarge);
+        }
+        Ok(request)
+    }
+
+    /// Length of the data following the request: its IV, additional authenticated data and
+    /// source data.
+    pub fn payload_len(&self) -> usize {
+        match self.op {
+            DataOp::Cipher {
+                iv_len, src_len, ..
+            } => iv_len.saturating_add(src_len),
+            DataOp::Mac { src_len, .. } => src_len,
+            DataOp::Aead {
+                iv_len,
+                aad_len,
+                src_len,
+                ..
+            } => iv_len.saturating_add(aad_len).saturating_add(src_len),
+        }
+    }
+
+    /// Length of the data the device writes back: the destination data or the MAC.
+    pub fn result_len(&self) -> usize {
+        match self.op {
+            DataOp::Cipher { dst_len, .. } | DataOp::Aead { dst_len, .. } => dst_len,
+            DataOp::Mac { result_len, .. } => result_len,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Builds a control request with the given opcode and service specific fields.
+    pub(crate) fn ctrl_request(opcode: u32, para: &[u32]) -> Vec<u8> {
+        let mut req = vec![0u8; CTRL_REQ_LEN];
+        req[..4].copy_from_slice(&opcode.to_le_bytes());
+        for (i, value) in para.iter().enumerate() {
+            let offset = CTRL_PARA_OFFSET + 4 * i;
+            req[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        if opcode == VIRTIO_CRYPTO_CIPHER_CREATE_SESSION {
+            req[CTRL_SYM_OP_TYPE_OFFSET..CTRL_SYM_OP_TYPE_OFFSET + 4]
+                .copy_from_slice(&VIRTIO_CRYPTO_SYM_OP_CIPHER.to_le_bytes());
+        }
+        req
+    }
+
+    /// Builds a data request with the given opcode, session and service specific fields.
+    pub(crate) fn data_request(opcode: u32, session_id: u64, para: &[u32]) -> Vec<u8> {
+        let mut req = vec![0u8; DATA_REQ_LEN];
+        req[..4].copy_from_slice(&opcode.to_le_bytes());
+        req[8..16].copy_from_slice(&session_id.to_le_bytes());
+        for (i, value) in para.iter().enumerate() {
+            let offset = DATA_PARA_OFFSET + 4 * i;
+            req[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        if opcode >> 8 == VIRTIO_CRYPTO_SERVICE_CIPHER {
+            req[DATA_SYM_OP_TYPE_OFFSET..DATA_SYM_OP_TYPE_OFFSET + 4]
+                .copy_from_slice(&VIRTIO_CRYPTO_SYM_OP_CIPHER.to_le_bytes());
+        }
+        req
+    }
+
+    #[test]
+    fn test_parse_ctrl_request() {
+        assert_eq!(
+            ControlRequest::parse(&[0u8; 16]),
+            Err(RequestError::InvalidLength("request", 16))
+        );
+
+        let req = ctrl_request(
+            VIRTIO_CRYPTO_CIPHER_CREATE_SESSION,
+            &[VIRTIO_CRYPTO_CIPHER_AES_CTR, 16, 1],
+        );
+        assert_eq!(
+            ControlRequest::parse(&req).unwrap(),
+            ControlRequest::CreateSession {
+                kind: SessionKind::AesCtr,
+                key_len: 16
+            }
+        );
+        // Only AES-CTR is supported.
+        let req = ctrl_request(VIRTIO_CRYPTO_CIPHER_CREATE_SESSION, &[3, 16, 1]);
+        assert_eq!(
+            ControlRequest::parse(&req),
+            Err(RequestError::UnsupportedAlgorithm(3))
+        );
+
+        let req = ctrl_request(
+            VIRTIO_CRYPTO_MAC_CREATE_SESSION,
+            &[VIRTIO_CRYPTO_MAC_HMAC_SHA_256, 32, 64],
+        );
+        assert_eq!(
+            ControlRequest::parse(&req).unwrap(),
+            ControlRequest::CreateSession {
+                kind: SessionKind::Hmac {
+                    algo: VIRTIO_CRYPTO_MAC_HMAC_SHA_256,
+                    result_len: 32
+                },
+                key_len: 64
+            }
+        );
+
+        let req = ctrl_request(
+            VIRTIO_CRYPTO_AEAD_CREATE_SESSION,
+            &[VIRTIO_CRYPTO_AEAD_GCM, 32, 16, 0, 1],
+        );
+        assert_eq!(
+            ControlRequest::parse(&req).unwrap(),
+            ControlRequest::CreateSession {
+                kind: SessionKind::AesGcm,
+                key_len: 32
+            }
+        );
+
+        let mut req = ctrl_request(VIRTIO_CRYPTO_MAC_DESTROY_SESSION, &[]);
+        req[CTRL_PARA_OFFSET..CTRL_PARA_OFFSET + 8].copy_from_slice(&42u64.to_le_bytes());
+        assert_eq!(
+            ControlRequest::parse(&req).unwrap(),
+            ControlRequest::DestroySession {
+                service: VIRTIO_CRYPTO_SERVICE_MAC,
+                session_id: 42
+            }
+        );
+
+        let req = ctrl_request(0x0402, &[]);
+        assert_eq!(
+            ControlRequest::parse(&req),
+            Err(RequestError::UnsupportedOpcode(0x0402))
+        );
+    }
+
+    #[test]
+    fn test_parse_data_request() {
+        let req = data_request(VIRTIO_CRYPTO_CIPHER_DECRYPT, 3, &[16, 100, 100]);
+        let req = DataRequest::parse(&req).unwrap();
+        assert_eq!(
+            req,
+            DataRequest {
+                session_id: 3,
+                op: DataOp::Cipher {
+                    encrypt: false,
+                    iv_len: 16,
+                    src_len: 100,
+                    dst_len: 100
+                }
+            }
+        );
+        assert_eq!(req.payload_len(), 116);
+        assert_eq!(req.result_len(), 100);
+
+        let req = data_request(VIRTIO_CRYPTO_MAC, 1, &[10, 32]);
+        let req = DataRequest::parse(&req).unwrap();
+        assert_eq!(req.payload_len(), 10);
+        assert_eq!(req.result_len(), 32);
+
+        let req = data_request(VIRTIO_CRYPTO_AEAD_ENCRYPT, 1, &[12, 8, 20, 36]);
+        let req = DataRequest::parse(&req).unwrap();
+        assert_eq!(req.payload_len(), 40);
+        assert_eq!(req.result_len(), 36);
+
+        let req = data_request(VIRTIO_CRYPTO_MAC, 1, &[u32::MAX, 32]);
+        assert_eq!(DataRequest::parse(&req), Err(RequestError::TooLarge));
+
+        let req = data_request(0x0100, 1, &[]);
+        assert_eq!(
+            DataRequest::parse(&req),
+            Err(RequestError::UnsupportedOpcode(0x0100))
+        );
+    }
+
+    #[test]
+    fn test_error_status() {
+        assert_eq!(
+            RequestError::UnsupportedAlgorithm(1).status(),
+            VIRTIO_CRYPTO_NOTSUPP
+        );
+        assert_eq!(
+            RequestError::InvalidSession(1).status(),
+            VIRTIO_CRYPTO_INVSESS
+        );
+        assert_eq!(RequestError::NoSpace.status(), VIRTIO_CRYPTO_NOSPC);
+        assert_eq!(RequestError::Authentication.status(), VIRTIO_CRYPTO_BADMSG);
+        assert_eq!(RequestError::Crypto.status(), VIRTIO_CRYPTO_ERR);
+    }
+}
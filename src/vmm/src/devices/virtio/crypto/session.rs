@@ -0,0 +1,471 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sessions of the crypto device, holding the keys configured by the guest.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use aws_lc_rs::{aead, cipher, hmac};
+use serde::{Deserialize, Serialize};
+
+use super::request::{
+    DataOp, RequestError, VIRTIO_CRYPTO_MAC_HMAC_SHA_256, VIRTIO_CRYPTO_MAC_HMAC_SHA_384,
+    VIRTIO_CRYPTO_MAC_HMAC_SHA_512, VIRTIO_CRYPTO_SERVICE_AEAD, VIRTIO_CRYPTO_SERVICE_CIPHER,
+    VIRTIO_CRYPTO_SERVICE_MAC,
+};
+use crate::secret::SecretBuffer;
+
+/// Maximum number of sessions open at the same time.
+pub const MAX_SESSIONS: usize = 256;
+/// Maximum length of the cipher keys.
+pub const MAX_CIPHER_KEY_LEN: usize = cipher::AES_256_KEY_LEN;
+/// Maximum length of the MAC keys.
+pub const MAX_AUTH_KEY_LEN: usize = 128;
+
+/// Length of the AES-GCM tags.
+const GCM_TAG_LEN: usize = 16;
+
+/// Algorithm of a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SessionKind {
+    /// AES in counter mode.
+    AesCtr,
+    /// HMAC, producing MACs of `result_len` bytes.
+    Hmac {
+        /// Virtio identifier of the HMAC algorithm.
+        algo: u32,
+        /// Length of the MACs, which may be truncated.
+        result_len: usize,
+    },
+    /// AES in Galois/Counter mode.
+    AesGcm,
+}
+
+impl SessionKind {
+    /// HMAC session with the virtio algorithm `algo`.
+    pub fn hmac(algo: u32, result_len: usize) -> Result<Self, RequestError> {
+        let kind = SessionKind::Hmac { algo, result_len };
+        let digest_len = kind.hmac_algorithm()?.digest_algorithm().output_len;
+        if result_len == 0 || result_len > digest_len {
+            return Err(RequestError::InvalidLength("MAC", result_len));
+        }
+        Ok(kind)
+    }
+
+    /// AES-GCM session producing tags of `tag_len` bytes.
+    pub fn aes_gcm(tag_len: usize) -> Result<Self, RequestError> {
+        if tag_len != GCM_TAG_LEN {
+            return Err(RequestError::InvalidLength("tag", tag_len));
+        }
+        Ok(SessionKind::AesGcm)
+    }
+
+    /// Virtio service of the session.
+    pub fn service(&self) -> u32 {
+        match self {
+            SessionKind::AesCtr => VIRTIO_CRYPTO_SERVICE_CIPHER,
+            SessionKind::Hmac { .. } => VIRTIO_CRYPTO_SERVICE_MAC,
+            SessionKind::AesGcm => VIRTIO_CRYPTO_SERVICE_AEAD,
+        }
+    }
+
+    fn hmac_algorithm(&self) -> Result<hmac::Algorithm, RequestError> {
+        match self {
+            SessionKind::Hmac { algo, .. } => match *algo {
+                VIRTIO_CRYPTO_MAC_HMAC_SHA_256 => Ok(hmac::HMAC_SHA256),
+                VIRTIO_CRYPTO_MAC_HMAC_SHA_384 => Ok(hmac::HMAC_SHA384),
+                VIRTIO_CRYPTO_MAC_HMAC_SHA_512 => Ok(hmac::HMAC_SHA512),
+                algo => Err(RequestError::UnsupportedAlgorithm(algo)),
+            },
+            _ => Err(RequestError::SessionMismatch),
+        }
+    }
+
+    fn aes_algorithms(
+        key_len: usize,
+    ) -> Option<(&'static cipher::Algorithm, &'static aead::Algorithm)> {
+        match key_len {
+            cipher::AES_128_KEY_LEN => Some((&cipher::AES_128, &aead::AES_128_GCM)),
+            cipher::AES_256_KEY_LEN => Some((&cipher::AES_256, &aead::AES_256_GCM)),
+            _ => None,
+        }
+    }
+}
+
+enum SessionKey {
+    Cipher(cipher::EncryptingKey),
+    Mac(hmac::Key),
+    Aead(aead::LessSafeKey),
+}
+
+/// Session created by the guest.
+pub struct Session {
+    kind: SessionKind,
+    // Kept to save the session in snapshots.
+    key_bytes: SecretBuffer,
+    key: SessionKey,
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("kind", &self.kind)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Session {
+    /// Creates a session of the given kind with `key_bytes`.
+    pub fn new(kind: SessionKind, key_bytes: &[u8]) -> Result<Self, RequestError> {
+        let invalid_key = || RequestError::InvalidLength("key", key_bytes.len());
+        let key = match kind {
+            SessionKind::AesCtr => {
+                let (algorithm, _) =
+                    SessionKind::aes_algorithms(key_bytes.len()).ok_or_else(invalid_key)?;
+                let key = cipher::UnboundCipherKey::new(algorithm, key_bytes)
+                    .map_err(|_| invalid_key())?;
+                SessionKey::Cipher(cipher::EncryptingKey::ctr(key).map_err(|_| invalid_key())?)
+            }
+            SessionKind::Hmac { .. } => {
+                if key_bytes.len() > MAX_AUTH_KEY_LEN {
+                    return Err(invalid_key());
+                }
+                SessionKey::Mac(hmac::Key::new(kind.hmac_algorithm()?, key_bytes))
+            }
+            SessionKind::AesGcm => {
+                let (_, algorithm) =
+                    SessionKind::aes_algorithms(key_bytes.len()).ok_or_else(invalid_key)?;
+                let key = aead::UnboundKey::new(algorithm, key_bytes).map_err(|_| invalid_key())?;
+                SessionKey::Aead(aead::LessSafeKey::new(key))
+            }
+        };
+
+        let mut stored_key = SecretBuffer::new(key_bytes.len());
+        stored_key.copy_from_slice(key_bytes);
+        Ok(Self {
+            kind,
+            key_bytes: stored_key,
+            key,
+        })
+    }
+
+    /// Algorithm of the session.
+    pub fn kind(&self) -> SessionKind {
+        self.kind
+    }
+
+    /// Key of the session.
+    pub fn key_bytes(&self) -> &[u8] {
+        &self.key_bytes
+    }
+
+    /// Performs `op` on `payload`, the IV, additional authenticated data and source data of the
+    /// request, returning the data to write back to the guest.
+    pub fn process(&self, op: &DataOp, payload: &[u8]) -> Result<SecretBuffer, RequestError> {
+        match (op, &self.key) {
+            (
+                &DataOp::Cipher {
+                    iv_len,
+                    src_len,
+                    dst_len,
+                    ..
+                },
+                SessionKey::Cipher(key),
+            ) => {
+                // Encryption and decryption are the same operation in counter mode.
+                if dst_len != src_len {
+                    return Err(RequestError::InvalidLength("destination", dst_len));
+                }
+                let (iv, src) = payload.split_at(iv_len);
+                let iv: [u8; cipher::AES_CTR_IV_LEN] = iv
+                    .try_into()
+                    .map_err(|_| RequestError::InvalidLength("IV", iv_len))?;
+                let mut output = SecretBuffer::new(src.len());
+                output.copy_from_slice(src);
+                key.less_safe_encrypt(&mut output, cipher::EncryptionContext::Iv128(iv.into()))
+                    .map_err(|_| RequestError::Crypto)?;
+                Ok(output)
+            }
+            (&DataOp::Mac { result_len, .. }, SessionKey::Mac(key)) => {
+                let SessionKind::Hmac {
+                    result_len: session_len,
+                    ..
+                } = self.kind
+                else {
+                    return Err(RequestError::SessionMismatch);
+                };
+                if result_len != session_len {
+                    return Err(RequestError::InvalidLength("MAC", result_len));
+                }
+                let tag = hmac::sign(key, payload);
+                let mut output = SecretBuffer::new(result_len);
+                output.copy_from_slice(&tag.as_ref()[..result_len]);
+                Ok(output)
+            }
+            (
+                &DataOp::Aead {
+                    encrypt,
+                    iv_len,
+                    aad_len,
+                    src_len,
+                    dst_len,
+                },
+                SessionKey::Aead(key),
+            ) => {
+                let (iv, rest) = payload.split_at(iv_len);
+                let (aad, src) = rest.split_at(aad_len);
+                let nonce = aead::Nonce::try_assume_unique_for_key(iv)
+                    .map_err(|_| RequestError::InvalidLength("IV", iv_len))?;
+                let aad = aead::Aad::from(aad);
+
+                if encrypt {
+                    if dst_len != src_len + GCM_TAG_LEN {
+                        return Err(RequestError::InvalidLength("destination", dst_len));
+                    }
+                    let mut output = SecretBuffer::new(dst_len);
+                    output[..src_len].copy_from_slice(src);
+                    let tag = key
+                        .seal_in_place_separate_tag(nonce, aad, &mut output[..src_len])
+                        .map_err(|_| RequestError::Crypto)?;
+                    output[src_len..].copy_from_slice(tag.as_ref());
+                    Ok(output)
+                } else {
+                    if src_len < GCM_TAG_LEN || dst_len != src_len - GCM_TAG_LEN {
+                        return Err(RequestError::InvalidLength("destination", dst_len));
+                    }
+                    let mut buffer = SecretBuffer::new(src_len);
+                    buffer.copy_from_slice(src);
+                    key.open_in_place(nonce, aad, &mut buffer)
+                        .map_err(|_| RequestError::Authentication)?;
+                    let mut output = SecretBuffer::new(dst_len);
+                    output.copy_from_slice(&buffer[..dst_len]);
+                    buffer.zeroize(0..src_len);
+                    Ok(output)
+                }
+            }
+            _ => Err(RequestError::SessionMismatch),
+        }
+    }
+}
+
+/// Sessions open on the device, by identifier.
+#[derive(Debug, Default)]
+pub struct SessionTable {
+    sessions: BTreeMap<u64, Session>,
+    next_id: u64,
+}
+
+impl SessionTable {
+    /// Adds `session` to the table, returning its identifier.
+    pub fn insert(&mut self, session: Session) -> Result<u64, RequestError> {
+        if self.sessions.len() >= MAX_SESSIONS {
+            return Err(RequestError::NoSpace);
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.sessions.insert(id, session);
+        Ok(id)
+    }
+
+    /// Removes the session `id` of `service`.
+    pub fn remove(&mut self, service: u32, id: u64) -> Result<(), RequestError> {
+        match self.sessions.get(&id) {
+            Some(session) if session.kind().service() == service => {
+                self.sessions.remove(&id);
+                Ok(())
+            }
+            _ => Err(RequestError::InvalidSession(id)),
+        }
+    }
+
+    /// Session `id`.
+    pub fn get(&self, id: u64) -> Result<&Session, RequestError> {
+        self.sessions
+            .get(&id)
+            .ok_or(RequestError::InvalidSession(id))
+    }
+
+    /// Number of open sessions.
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Iterates over the open sessions.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &Session)> {
+        self.sessions.iter().map(|(&id, session)| (id, session))
+    }
+
+    /// Identifier of the next session.
+    pub fn next_id(&self) -> u64 {
+        self.next_id
+    }
+
+    /// Restores a session saved in a snapshot.
+    pub fn restore(&mut self, id: u64, session: Session, next_id: u64) {
+        self.sessions.insert(id, session);
+        self.next_id = next_id;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::virtio::crypto::request::VIRTIO_CRYPTO_SERVICE_MAC;
+
+    #[test]
+    fn test_aes_ctr() {
+        // NIST SP 800-38A F.5.1 CTR-AES128.Encrypt, first block.
+        let key = [
+            0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf,
+            0x4f, 0x3c,
+        ];
+        let iv = [
+            0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa, 0xfb, 0xfc, 0xfd,
+            0xfe, 0xff,
+        ];
+        let plaintext = [
+            0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93,
+            0x17, 0x2a,
+        ];
+        let ciphertext = [
+            0x87, 0x4d, 0x61, 0x91, 0xb6, 0x20, 0xe3, 0x26, 0x1b, 0xef, 0x68, 0x64, 0x99, 0x0d,
+            0xb6, 0xce,
+        ];
+
+        let session = Session::new(SessionKind::AesCtr, &key).unwrap();
+        let op = |encrypt| DataOp::Cipher {
+            encrypt,
+            iv_len: 16,
+            src_len: 16,
+            dst_len: 16,
+        };
+        let payload = [iv.as_slice(), plaintext.as_slice()].concat();
+        assert_eq!(&*session.process(&op(true), &payload).unwrap(), ciphertext);
+        let payload = [iv.as_slice(), ciphertext.as_slice()].concat();
+        assert_eq!(&*session.process(&op(false), &payload).unwrap(), plaintext);
+
+        // Invalid IV length.
+        let op = DataOp::Cipher {
+            encrypt: true,
+            iv_len: 12,
+            src_len: 20,
+            dst_len: 20,
+        };
+        assert_eq!(
+            session.process(&op, &payload[..32]).unwrap_err(),
+            RequestError::InvalidLength("IV", 12)
+        );
+
+        // Invalid key length.
+        assert_eq!(
+            Session::new(SessionKind::AesCtr, &[0; 24]).unwrap_err(),
+            RequestError::InvalidLength("key", 24)
+        );
+    }
+
+    #[test]
+    fn test_hmac() {
+        // RFC 4231 test case 2.
+        let kind = SessionKind::hmac(VIRTIO_CRYPTO_MAC_HMAC_SHA_256, 16).unwrap();
+        let session = Session::new(kind, b"Jefe").unwrap();
+        let op = DataOp::Mac {
+            src_len: 28,
+            result_len: 16,
+        };
+        let mac = session
+            .process(&op, b"what do ya want for nothing?")
+            .unwrap();
+        assert_eq!(
+            &*mac,
+            [
+                0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95,
+                0x75, 0xc7
+            ]
+        );
+
+        assert_eq!(
+            SessionKind::hmac(VIRTIO_CRYPTO_MAC_HMAC_SHA_256, 33),
+            Err(RequestError::InvalidLength("MAC", 33))
+        );
+        assert_eq!(
+            SessionKind::hmac(1, 16),
+            Err(RequestError::UnsupportedAlgorithm(1))
+        );
+        // The MAC length is fixed by the session.
+        let op = DataOp::Mac {
+            src_len: 28,
+            result_len: 32,
+        };
+        assert_eq!(
+            session
+                .process(&op, b"what do ya want for nothing?")
+                .unwrap_err(),
+            RequestError::InvalidLength("MAC", 32)
+        );
+    }
+
+    #[test]
+    fn test_aes_gcm() {
+        let kind = SessionKind::aes_gcm(16).unwrap();
+        let session = Session::new(kind, &[0x42; 32]).unwrap();
+        let iv = [0x24; 12];
+        let aad = b"header";
+        let plaintext = b"secret message";
+
+        let op = DataOp::Aead {
+            encrypt: true,
+            iv_len: 12,
+            aad_len: aad.len(),
+            src_len: plaintext.len(),
+            dst_len: plaintext.len() + 16,
+        };
+        let payload = [iv.as_slice(), aad, plaintext].concat();
+        let ciphertext = session.process(&op, &payload).unwrap();
+        assert_eq!(ciphertext.len(), plaintext.len() + 16);
+
+        let op = DataOp::Aead {
+            encrypt: false,
+            iv_len: 12,
+            aad_len: aad.len(),
+            src_len: ciphertext.len(),
+            dst_len: plaintext.len(),
+        };
+        let mut payload = [iv.as_slice(), aad, &ciphertext].concat();
+        assert_eq!(&*session.process(&op, &payload).unwrap(), plaintext);
+
+        // Tampered ciphertext.
+        *payload.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            session.process(&op, &payload).unwrap_err(),
+            RequestError::Authentication
+        );
+
+        assert_eq!(
+            SessionKind::aes_gcm(12),
+            Err(RequestError::InvalidLength("tag", 12))
+        );
+    }
+
+    #[test]
+    fn test_session_table() {
+        let mut table = SessionTable::default();
+        let session = || Session::new(SessionKind::AesCtr, &[0; 16]).unwrap();
+
+        let id = table.insert(session()).unwrap();
+        assert_eq!(table.get(id).unwrap().kind(), SessionKind::AesCtr);
+        // Sessions are destroyed through the service they belong to.
+        assert_eq!(
+            table.remove(VIRTIO_CRYPTO_SERVICE_MAC, id),
+            Err(RequestError::InvalidSession(id))
+        );
+        table.remove(VIRTIO_CRYPTO_SERVICE_CIPHER, id).unwrap();
+        assert_eq!(table.get(id).unwrap_err(), RequestError::InvalidSession(id));
+
+        for _ in 0..MAX_SESSIONS {
+            table.insert(session()).unwrap();
+        }
+        assert_eq!(table.insert(session()), Err(RequestError::NoSpace));
+        assert_eq!(table.len(), MAX_SESSIONS);
+    }
+}
@@ -6,11 +6,12 @@
 // found in the THIRD-PARTY file.
 
 use std::fmt;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 
 use utils::eventfd::EventFd;
 
+use super::dma::{DmaRange, DmaRanges};
 use super::mmio::{VIRTIO_MMIO_INT_CONFIG, VIRTIO_MMIO_INT_VRING};
 use super::queue::Queue;
 use super::ActivateError;
@@ -58,6 +59,10 @@ pub enum IrqType {
 pub struct IrqTrigger {
     pub(crate) irq_status: Arc<AtomicU32>,
     pub(crate) irq_evt: EventFd,
+    // Whether the interrupts are dropped instead of delivered to the guest, to inject faults.
+    pub(crate) drop_irqs: bool,
+    // Set when the device failed, until the driver resets it.
+    needs_reset: AtomicBool,
 }
 
 impl IrqTrigger {
@@ -65,6 +70,8 @@ impl IrqTrigger {
         Ok(Self {
             irq_status: Arc::new(AtomicU32::new(0)),
             irq_evt: EventFd::new(libc::EFD_NONBLOCK)?,
+            drop_irqs: false,
+            needs_reset: AtomicBool::new(false),
         })
     }
 
@@ -75,6 +82,10 @@ impl IrqTrigger {
         };
         self.irq_status.fetch_or(irq, Ordering::SeqCst);
 
+        if self.drop_irqs {
+            return Ok(());
+        }
+
         self.irq_evt.write(1).map_err(|err| {
             error!("Failed to send irq to the guest: {:?}", err);
             err
@@ -82,6 +93,24 @@ impl IrqTrigger {
 
         Ok(())
     }
+
+    /// Notifies the driver that the device configuration space changed.
+    pub fn signal_config_update(&self) -> Result<(), std::io::Error> {
+        self.trigger_irq(IrqType::Config)
+    }
+
+    /// Marks the device as failed, e.g. after the driver made a descriptor chain it cannot
+    /// process. The device status then has the DEVICE_NEEDS_RESET bit set, which the driver is
+    /// notified of through a configuration change interrupt.
+    pub fn signal_device_failure(&self) -> Result<(), std::io::Error> {
+        self.needs_reset.store(true, Ordering::SeqCst);
+        self.trigger_irq(IrqType::Config)
+    }
+
+    /// Returns whether the device failed and must be reset by the driver.
+    pub fn needs_reset(&self) -> bool {
+        self.needs_reset.load(Ordering::SeqCst)
+    }
 }
 
 /// Trait for virtio devices to be driven by a virtio transport.
@@ -94,6 +123,9 @@ pub trait VirtioDevice: AsAny + Send {
     /// Get the available features offered by device.
     fn avail_features(&self) -> u64;
 
+    /// Set the available features offered by device, before it is attached to the transport.
+    fn set_avail_features(&mut self, avail_features: u64);
+
     /// Get acknowledged features of the driver.
     fn acked_features(&self) -> u64;
 
@@ -116,6 +148,21 @@ pub trait VirtioDevice: AsAny + Send {
     /// Returns a mutable reference to the device queues.
     fn queues_mut(&mut self) -> &mut [Queue];
 
+    /// Restricts the guest memory all the device queues can access to the given ranges.
+    fn set_dma_ranges(&mut self, dma_ranges: Option<Arc<DmaRanges>>) {
+        for queue in self.queues_mut() {
+            queue.set_dma_ranges(dma_ranges.clone());
+        }
+    }
+
+    /// The guest memory ranges the device is restricted to, if any.
+    fn dma_ranges(&self) -> Option<Vec<DmaRange>> {
+        self.queues()
+            .first()
+            .and_then(Queue::dma_ranges)
+            .map(|ranges| ranges.ranges().to_vec())
+    }
+
     /// Returns the device queues event fds.
     fn queue_events(&self) -> &[EventFd];
 
@@ -125,6 +172,12 @@ pub trait VirtioDevice: AsAny + Send {
     /// Returns the current device interrupt status.
     fn interrupt_status(&self) -> Arc<AtomicU32>;
 
+    /// Returns whether the device failed, and must be reset by the driver before it is used
+    /// again.
+    fn needs_reset(&self) -> bool {
+        false
+    }
+
     /// The set of feature bits shifted by `page * 32`.
     fn avail_features_by_page(&self, page: u32) -> u32 {
         let avail_features = self.avail_features();
@@ -212,7 +265,7 @@ pub(crate) mod tests {
 
     #[test]
     fn irq_trigger() {
-        let irq_trigger = IrqTrigger::new().unwrap();
+        let mut irq_trigger = IrqTrigger::new().unwrap();
         assert_eq!(irq_trigger.irq_status.load(Ordering::SeqCst), 0);
 
         // Check that there are no pending irqs.
@@ -225,6 +278,24 @@ pub(crate) mod tests {
         irq_trigger.irq_status.store(0, Ordering::SeqCst);
         irq_trigger.trigger_irq(IrqType::Vring).unwrap();
         assert!(irq_trigger.has_pending_irq(IrqType::Vring));
+        irq_trigger.irq_status.store(0, Ordering::SeqCst);
+        irq_trigger.signal_config_update().unwrap();
+        assert!(irq_trigger.has_pending_irq(IrqType::Config));
+        assert!(!irq_trigger.has_pending_irq(IrqType::Vring));
+
+        // Check that the irqfd is not written to while the interrupts are dropped.
+        irq_trigger.drop_irqs = true;
+        irq_trigger.trigger_irq(IrqType::Vring).unwrap();
+        assert!(!irq_trigger.has_pending_irq(IrqType::Vring));
+        irq_trigger.drop_irqs = false;
+        irq_trigger.irq_status.store(0, Ordering::SeqCst);
+
+        // Check that a device failure is signaled with a configuration change interrupt.
+        assert!(!irq_trigger.needs_reset());
+        irq_trigger.signal_device_failure().unwrap();
+        assert!(irq_trigger.needs_reset());
+        assert!(irq_trigger.has_pending_irq(IrqType::Config));
+        irq_trigger.irq_status.store(0, Ordering::SeqCst);
 
         // Check trigger_irq() failure case (irq_evt is full).
         irq_trigger.irq_evt.write(u64::MAX - 1).unwrap();
@@ -242,6 +313,10 @@ pub(crate) mod tests {
             todo!()
         }
 
+        fn set_avail_features(&mut self, _avail_features: u64) {
+            todo!()
+        }
+
         fn acked_features(&self) -> u64 {
             self.acked_features
         }
@@ -0,0 +1,179 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::logger::{error, IncMetric, METRICS};
+use crate::vstate::memory::GuestAddress;
+
+/// A range of guest physical addresses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DmaRange {
+    /// Guest physical address of the first byte of the range.
+    pub base: u64,
+    /// Size of the range, in bytes.
+    pub size: u64,
+}
+
+impl DmaRange {
+    // Address of the first byte after the range, if it does not overflow.
+    fn end(&self) -> Option<u64> {
+        self.base.checked_add(self.size)
+    }
+}
+
+/// Errors associated with the DMA ranges of a device.
+#[derive(Debug, thiserror::Error, displaydoc::Display, PartialEq, Eq)]
+pub enum DmaRangesError {
+    /// The list of DMA ranges is empty.
+    Empty,
+    /// Invalid DMA range: base {0:#x}, size {1:#x}. The range must be non-empty and below 2^64.
+    InvalidRange(u64, u64),
+}
+
+/// The guest physical address ranges a device is allowed to access through its queues,
+/// i.e. a static, software IOTLB.
+///
+/// A device that accesses guest memory outside of its ranges is failed: its queues stop yielding
+/// descriptor chains until the driver resets it.
+#[derive(Debug)]
+pub struct DmaRanges {
+    // Sorted, non-overlapping and non-adjacent ranges.
+    ranges: Vec<DmaRange>,
+    violated: AtomicBool,
+}
+
+impl DmaRanges {
+    /// Builds the table of the given ranges. Overlapping and adjacent ranges are merged.
+    pub fn new(ranges: &[DmaRange]) -> Result<Self, DmaRangesError> {
+        if ranges.is_empty() {
+            return Err(DmaRangesError::Empty);
+        }
+        if let Some(range) = ranges.iter().find(|r| r.size == 0 || r.end().is_none()) {
+            return Err(DmaRangesError::InvalidRange(range.base, range.size));
+        }
+
+        let mut sorted = ranges.to_vec();
+        sorted.sort_unstable_by_key(|r| r.base);
+        let mut merged: Vec<DmaRange> = Vec::with_capacity(sorted.len());
+        for range in sorted {
+            match merged.last_mut() {
+                // The ranges were validated above, so their ends cannot overflow.
+                Some(last) if range.base <= last.base + last.size => {
+                    let end = (last.base + last.size).max(range.base + range.size);
+                    last.size = end - last.base;
+                }
+                _ => merged.push(range),
+            }
+        }
+
+        Ok(DmaRanges {
+            ranges: merged,
+            violated: AtomicBool::new(false),
+        })
+    }
+
+    /// The allowed ranges, sorted by address.
+    pub fn ranges(&self) -> &[DmaRange] {
+        &self.ranges
+    }
+
+    /// Checks that `[addr, addr + len)` lies within one of the allowed ranges. If not, the
+    /// violation is recorded and the device is failed.
+    pub fn check(&self, addr: GuestAddress, len: u32) -> bool {
+        let allowed = addr.0.checked_add(u64::from(len)).is_some_and(|end| {
+            // Find the last range starting at or before `addr`.
+            let idx = self.ranges.partition_point(|r| r.base <= addr.0);
+            idx > 0 && self.ranges[idx - 1].end().is_some_and(|r_end| end <= r_end)
+        });
+        if !allowed {
+            error!(
+                "Virtio device accessed guest memory outside of its DMA ranges: addr {:#x}, len \
+                 {:#x}",
+                addr.0, len
+            );
+            METRICS.vmm.dma_range_violations.inc();
+            self.violated.store(true, Ordering::Release);
+        }
+        allowed
+    }
+
+    /// Whether the device accessed guest memory outside of its ranges since the last reset.
+    pub fn is_violated(&self) -> bool {
+        self.violated.load(Ordering::Acquire)
+    }
+
+    /// Clears the failure of the device, when it is reset.
+    pub fn reset(&self) {
+        self.violated.store(false, Ordering::Release);
+    }
+}
+
+impl PartialEq for DmaRanges {
+    fn eq(&self, other: &Self) -> bool {
+        self.ranges == other.ranges
+    }
+}
+
+impl Eq for DmaRanges {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(base: u64, size: u64) -> DmaRange {
+        DmaRange { base, size }
+    }
+
+    #[test]
+    fn test_dma_ranges_new() {
+        assert_eq!(DmaRanges::new(&[]).unwrap_err(), DmaRangesError::Empty);
+        assert_eq!(
+            DmaRanges::new(&[range(0x1000, 0)]).unwrap_err(),
+            DmaRangesError::InvalidRange(0x1000, 0)
+        );
+        assert_eq!(
+            DmaRanges::new(&[range(u64::MAX, 2)]).unwrap_err(),
+            DmaRangesError::InvalidRange(u64::MAX, 2)
+        );
+
+        // Overlapping and adjacent ranges are merged, the others are kept apart.
+        let ranges = DmaRanges::new(&[
+            range(0x8000, 0x1000),
+            range(0x1000, 0x1000),
+            range(0x1800, 0x1000),
+            range(0x2800, 0x800),
+        ])
+        .unwrap();
+        assert_eq!(
+            ranges.ranges(),
+            &[range(0x1000, 0x2000), range(0x8000, 0x1000)]
+        );
+    }
+
+    #[test]
+    fn test_dma_ranges_check() {
+        let ranges = DmaRanges::new(&[range(0x1000, 0x1000), range(0x8000, 0x1000)]).unwrap();
+
+        assert!(ranges.check(GuestAddress(0x1000), 0x1000));
+        assert!(ranges.check(GuestAddress(0x8800), 0x800));
+        assert!(ranges.check(GuestAddress(0x1fff), 1));
+        assert!(!ranges.is_violated());
+
+        let violations = METRICS.vmm.dma_range_violations.count();
+        // Before, across the end of, between and after the ranges.
+        assert!(!ranges.check(GuestAddress(0x0fff), 2));
+        assert!(!ranges.check(GuestAddress(0x1800), 0x1000));
+        assert!(!ranges.check(GuestAddress(0x4000), 1));
+        assert!(!ranges.check(GuestAddress(0x9000), 1));
+        assert!(!ranges.check(GuestAddress(u64::MAX), 2));
+        assert_eq!(METRICS.vmm.dma_range_violations.count(), violations + 5);
+        assert!(ranges.is_violated());
+
+        ranges.reset();
+        assert!(!ranges.is_violated());
+    }
+}
@@ -0,0 +1,186 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Human-readable reporting of the features negotiated by virtio devices.
+
+use serde::Serialize;
+
+use super::device::VirtioDevice;
+use super::vsock::TYPE_VSOCK;
+use super::{TYPE_BALLOON, TYPE_BLOCK, TYPE_CRYPTO, TYPE_NET, TYPE_RNG};
+
+// Feature bits not specific to a device type, see section 6 of the virtio 1.2 specification.
+const COMMON_FEATURES: &[(u32, &str)] = &[
+    (24, "VIRTIO_F_NOTIFY_ON_EMPTY"),
+    (27, "VIRTIO_F_ANY_LAYOUT"),
+    (28, "VIRTIO_RING_F_INDIRECT_DESC"),
+    (29, "VIRTIO_RING_F_EVENT_IDX"),
+    (32, "VIRTIO_F_VERSION_1"),
+    (33, "VIRTIO_F_ACCESS_PLATFORM"),
+    (34, "VIRTIO_F_RING_PACKED"),
+    (35, "VIRTIO_F_IN_ORDER"),
+    (36, "VIRTIO_F_ORDER_PLATFORM"),
+    (37, "VIRTIO_F_SR_IOV"),
+    (38, "VIRTIO_F_NOTIFICATION_DATA"),
+];
+
+const NET_FEATURES: &[(u32, &str)] = &[
+    (0, "VIRTIO_NET_F_CSUM"),
+    (1, "VIRTIO_NET_F_GUEST_CSUM"),
+    (2, "VIRTIO_NET_F_CTRL_GUEST_OFFLOADS"),
+    (3, "VIRTIO_NET_F_MTU"),
+    (5, "VIRTIO_NET_F_MAC"),
+    (6, "VIRTIO_NET_F_GSO"),
+    (7, "VIRTIO_NET_F_GUEST_TSO4"),
+    (8, "VIRTIO_NET_F_GUEST_TSO6"),
+    (9, "VIRTIO_NET_F_GUEST_ECN"),
+    (10, "VIRTIO_NET_F_GUEST_UFO"),
+    (11, "VIRTIO_NET_F_HOST_TSO4"),
+    (12, "VIRTIO_NET_F_HOST_TSO6"),
+    (13, "VIRTIO_NET_F_HOST_ECN"),
+    (14, "VIRTIO_NET_F_HOST_UFO"),
+    (15, "VIRTIO_NET_F_MRG_RXBUF"),
+    (16, "VIRTIO_NET_F_STATUS"),
+    (17, "VIRTIO_NET_F_CTRL_VQ"),
+    (18, "VIRTIO_NET_F_CTRL_RX"),
+    (19, "VIRTIO_NET_F_CTRL_VLAN"),
+    (20, "VIRTIO_NET_F_CTRL_RX_EXTRA"),
+    (21, "VIRTIO_NET_F_GUEST_ANNOUNCE"),
+    (22, "VIRTIO_NET_F_MQ"),
+    (23, "VIRTIO_NET_F_CTRL_MAC_ADDR"),
+];
+
+const BLOCK_FEATURES: &[(u32, &str)] = &[
+    (0, "VIRTIO_BLK_F_BARRIER"),
+    (1, "VIRTIO_BLK_F_SIZE_MAX"),
+    (2, "VIRTIO_BLK_F_SEG_MAX"),
+    (4, "VIRTIO_BLK_F_GEOMETRY"),
+    (5, "VIRTIO_BLK_F_RO"),
+    (6, "VIRTIO_BLK_F_BLK_SIZE"),
+    (7, "VIRTIO_BLK_F_SCSI"),
+    (9, "VIRTIO_BLK_F_FLUSH"),
+    (10, "VIRTIO_BLK_F_TOPOLOGY"),
+    (11, "VIRTIO_BLK_F_CONFIG_WCE"),
+    (12, "VIRTIO_BLK_F_MQ"),
+    (13, "VIRTIO_BLK_F_DISCARD"),
+    (14, "VIRTIO_BLK_F_WRITE_ZEROES"),
+];
+
+const BALLOON_FEATURES: &[(u32, &str)] = &[
+    (0, "VIRTIO_BALLOON_F_MUST_TELL_HOST"),
+    (1, "VIRTIO_BALLOON_F_STATS_VQ"),
+    (2, "VIRTIO_BALLOON_F_DEFLATE_ON_OOM"),
+    (3, "VIRTIO_BALLOON_F_FREE_PAGE_HINT"),
+    (4, "VIRTIO_BALLOON_F_PAGE_POISON"),
+    (5, "VIRTIO_BALLOON_F_REPORTING"),
+];
+
+const VSOCK_FEATURES: &[(u32, &str)] = &[
+    (0, "VIRTIO_VSOCK_F_STREAM"),
+    (1, "VIRTIO_VSOCK_F_SEQPACKET"),
+];
+
+/// Returns the name of the virtio device type `device_type`.
+pub fn device_type_name(device_type: u32) -> &'static str {
+    match device_type {
+        TYPE_NET => "net",
+        TYPE_BLOCK => "block",
+        TYPE_RNG => "rng",
+        TYPE_BALLOON => "balloon",
+        TYPE_VSOCK => "vsock",
+        TYPE_CRYPTO => "crypto",
+        _ => "unknown",
+    }
+}
+
+/// Decodes the feature bits set in `features` for a device of type `device_type`. Bits without a
+/// known name are reported as `FEATURE_<bit>`.
+pub fn feature_names(device_type: u32, features: u64) -> Vec<String> {
+    let device_features = match device_type {
+        TYPE_NET => NET_FEATURES,
+        TYPE_BLOCK => BLOCK_FEATURES,
+        TYPE_BALLOON => BALLOON_FEATURES,
+        TYPE_VSOCK => VSOCK_FEATURES,
+        _ => &[],
+    };
+
+    (0..u64::BITS)
+        .filter(|bit| features & (1 << bit) != 0)
+        .map(|bit| {
+            device_features
+                .iter()
+                .chain(COMMON_FEATURES)
+                .find(|(feature, _)| *feature == bit)
+                .map_or_else(|| format!("FEATURE_{bit}"), |(_, name)| name.to_string())
+        })
+        .collect()
+}
+
+/// Features offered by a virtio device and acknowledged by the guest driver.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct VirtioDeviceFeatures {
+    /// Identifier of the device.
+    pub id: String,
+    /// Type of the device.
+    pub device_type: String,
+    /// Whether the guest driver finished initializing the device, after which the acknowledged
+    /// features no longer change.
+    pub negotiated: bool,
+    /// Feature bits offered by the device.
+    pub avail_features: u64,
+    /// Feature bits acknowledged by the guest driver.
+    pub acked_features: u64,
+    /// Names of the feature bits acknowledged by the guest driver.
+    pub acked_feature_names: Vec<String>,
+}
+
+impl VirtioDeviceFeatures {
+    /// Reads the current features of `device`, of type `device_type`.
+    pub fn new(id: &str, device_type: u32, device: &dyn VirtioDevice) -> Self {
+        VirtioDeviceFeatures {
+            id: id.to_string(),
+            device_type: device_type_name(device_type).to_string(),
+            negotiated: device.is_activated(),
+            avail_features: device.avail_features(),
+            acked_features: device.acked_features(),
+            acked_feature_names: feature_names(device_type, device.acked_features()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_names() {
+        assert!(feature_names(TYPE_NET, 0).is_empty());
+        assert_eq!(
+            feature_names(TYPE_NET, (1 << 11) | (1 << 15) | (1 << 32)),
+            vec![
+                "VIRTIO_NET_F_HOST_TSO4",
+                "VIRTIO_NET_F_MRG_RXBUF",
+                "VIRTIO_F_VERSION_1"
+            ]
+        );
+        // The same bit has a different meaning for each device type.
+        assert_eq!(feature_names(TYPE_BLOCK, 1 << 5), vec!["VIRTIO_BLK_F_RO"]);
+        assert_eq!(feature_names(TYPE_NET, 1 << 5), vec!["VIRTIO_NET_F_MAC"]);
+        assert_eq!(
+            feature_names(TYPE_RNG, (1 << 5) | (1 << 29)),
+            vec!["FEATURE_5", "VIRTIO_RING_F_EVENT_IDX"]
+        );
+        assert_eq!(feature_names(TYPE_BLOCK, 1 << 63), vec!["FEATURE_63"]);
+    }
+
+    #[test]
+    fn test_device_type_name() {
+        assert_eq!(device_type_name(TYPE_NET), "net");
+        assert_eq!(device_type_name(TYPE_BLOCK), "block");
+        assert_eq!(device_type_name(TYPE_RNG), "rng");
+        assert_eq!(device_type_name(TYPE_BALLOON), "balloon");
+        assert_eq!(device_type_name(TYPE_VSOCK), "vsock");
+        assert_eq!(device_type_name(TYPE_CRYPTO), "crypto");
+        assert_eq!(device_type_name(0), "unknown");
+    }
+}
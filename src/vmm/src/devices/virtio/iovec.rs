@@ -10,7 +10,7 @@ use vm_memory::{
 };
 
 use crate::devices::virtio::queue::DescriptorChain;
-use crate::vstate::memory::{Bitmap, GuestMemory};
+use crate::vstate::memory::{Bitmap, ByteValued, GuestMemory};
 
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 pub enum IoVecError {
@@ -20,10 +20,22 @@ pub enum IoVecError {
     ReadOnlyDescriptor,
     /// Tried to create an `IoVec` or `IoVecMut` from a descriptor chain that was too large
     OverflowedDescriptor,
+    /// Descriptor buffer at {0:#x} of length {1:#x} is outside of the DMA ranges of the device
+    DmaRange(u64, u32),
     /// Guest memory error: {0}
     GuestMemory(#[from] GuestMemoryError),
 }
 
+// Checks the buffer of a descriptor against the DMA ranges of the device, if restricted.
+fn check_dma_ranges(desc: &DescriptorChain) -> Result<(), IoVecError> {
+    match &desc.dma_ranges {
+        Some(dma_ranges) if !dma_ranges.check(desc.addr, desc.len) => {
+            Err(IoVecError::DmaRange(desc.addr.0, desc.len))
+        }
+        _ => Ok(()),
+    }
+}
+
 // Using SmallVec in the kani proofs causes kani to use unbounded amounts of memory
 // during post-processing, and then crash.
 // TODO: remove new-type once kani performance regression are resolved
@@ -56,6 +68,7 @@ impl IoVecBuffer {
             if desc.is_write_only() {
                 return Err(IoVecError::WriteOnlyDescriptor);
             }
+            check_dma_ranges(&desc)?;
 
             // We use get_slice instead of `get_host_address` here in order to have the whole
             // range of the descriptor chain checked, i.e. [addr, addr + len) is a valid memory
@@ -128,6 +141,18 @@ impl IoVecBuffer {
         }
     }
 
+    /// Reads an object of type `T` from the `IoVecBuffer` starting at the given offset. The object
+    /// may span several of the memory regions of the buffer.
+    ///
+    /// Fails like [`read_exact_volatile_at`](Self::read_exact_volatile_at) if the buffer is too
+    /// short to hold the object.
+    pub fn read_obj<T: ByteValued>(&self, offset: usize) -> Result<T, VolatileMemoryError> {
+        // SAFETY: `ByteValued` types are valid for any byte pattern, including all zeroes.
+        let mut obj: T = unsafe { std::mem::zeroed() };
+        self.read_exact_volatile_at(obj.as_mut_slice(), offset)?;
+        Ok(obj)
+    }
+
     /// Reads up to `len` bytes from the `IoVecBuffer` starting at the given offset.
     ///
     /// This will try to write to the given [`WriteVolatile`].
@@ -205,6 +230,7 @@ impl IoVecBufferMut {
             if !desc.is_write_only() {
                 return Err(IoVecError::ReadOnlyDescriptor);
             }
+            check_dma_ranges(&desc)?;
 
             // We use get_slice instead of `get_host_address` here in order to have the whole
             // range of the descriptor chain checked, i.e. [addr, addr + len) is a valid memory
@@ -268,6 +294,19 @@ impl IoVecBufferMut {
         }
     }
 
+    /// Writes an object of type `T` into the `IoVecBufferMut` starting at the given offset. The
+    /// object may span several of the memory regions of the buffer.
+    ///
+    /// Fails like [`write_all_volatile_at`](Self::write_all_volatile_at) if the buffer is too
+    /// short to hold the object, in which case only part of it may have been written.
+    pub fn write_obj<T: ByteValued>(
+        &mut self,
+        val: T,
+        offset: usize,
+    ) -> Result<(), VolatileMemoryError> {
+        self.write_all_volatile_at(val.as_slice(), offset)
+    }
+
     /// Writes up to `len` bytes into the `IoVecBuffer` starting at the given offset.
     ///
     /// This will try to write to the given [`WriteVolatile`].
@@ -324,10 +363,13 @@ impl IoVecBufferMut {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use libc::{c_void, iovec};
     use vm_memory::VolatileMemoryError;
 
-    use super::{IoVecBuffer, IoVecBufferMut};
+    use super::{IoVecBuffer, IoVecBufferMut, IoVecError};
+    use crate::devices::virtio::dma::{DmaRange, DmaRanges};
     use crate::devices::virtio::queue::{Queue, VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
     use crate::devices::virtio::test_utils::VirtQueue;
     use crate::utilities::test_utils::multi_region_mem;
@@ -443,6 +485,49 @@ mod tests {
         IoVecBufferMut::from_descriptor_chain(head).unwrap();
     }
 
+    #[test]
+    fn test_dma_ranges() {
+        let mem = default_mem();
+
+        // The whole chain lies within the DMA ranges.
+        let (mut q, _) = read_only_chain(&mem);
+        let dma_ranges = Arc::new(
+            DmaRanges::new(&[DmaRange {
+                base: 0x20000,
+                size: 0x100,
+            }])
+            .unwrap(),
+        );
+        q.set_dma_ranges(Some(dma_ranges.clone()));
+        let head = q.pop(&mem).unwrap();
+        IoVecBuffer::from_descriptor_chain(head).unwrap();
+        assert!(!dma_ranges.is_violated());
+
+        // The last descriptor of the chain lies outside of the DMA ranges.
+        let (mut q, vq) = write_only_chain(&mem);
+        let dma_ranges = Arc::new(
+            DmaRanges::new(&[DmaRange {
+                base: 0x20000,
+                size: 0xc0,
+            }])
+            .unwrap(),
+        );
+        q.set_dma_ranges(Some(dma_ranges.clone()));
+        vq.avail.ring[1].set(0);
+        vq.avail.idx.set(2);
+        let head = q.pop(&mem).unwrap();
+        assert!(matches!(
+            IoVecBufferMut::from_descriptor_chain(head),
+            Err(IoVecError::DmaRange(0x200c0, 64))
+        ));
+
+        // The device is failed, so the queue doesn't yield the next chain until it is reset.
+        assert!(dma_ranges.is_violated());
+        assert!(q.pop(&mem).is_none());
+        dma_ranges.reset();
+        q.pop(&mem).unwrap();
+    }
+
     #[test]
     fn test_iovec_length() {
         let mem = default_mem();
@@ -768,4 +853,78 @@ mod verification {
             buf.len().min(iov_mut.len().saturating_sub(offset) as usize)
         );
     }
+
+    #[repr(C, packed)]
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    struct PackedHeader {
+        kind: u8,
+        sector: u64,
+        len: u32,
+    }
+
+    // SAFETY: `PackedHeader` only contains plain data, without padding.
+    unsafe impl ByteValued for PackedHeader {}
+
+    #[test]
+    fn test_iovec_read_obj() {
+        let mem = default_mem();
+        let (mut q, _) = read_only_chain(&mem);
+        let iovec = IoVecBuffer::from_descriptor_chain(q.pop(&mem).unwrap()).unwrap();
+
+        // Within a single memory region, then spanning the first two of them.
+        assert_eq!(
+            iovec.read_obj::<u32>(4).unwrap(),
+            u32::from_le_bytes([4, 5, 6, 7])
+        );
+        let header: PackedHeader = iovec.read_obj(58).unwrap();
+        assert_eq!(
+            header.as_slice(),
+            (58..58 + std::mem::size_of::<PackedHeader>() as u8).collect::<Vec<_>>()
+        );
+
+        assert!(matches!(
+            iovec.read_obj::<u64>(252),
+            Err(VolatileMemoryError::PartialBuffer {
+                expected: 8,
+                completed: 4
+            })
+        ));
+        assert!(matches!(
+            iovec.read_obj::<u8>(256),
+            Err(VolatileMemoryError::OutOfBounds { addr: 256 })
+        ));
+    }
+
+    #[test]
+    fn test_iovec_mut_write_obj() {
+        let mem = default_mem();
+        let (mut q, vq) = write_only_chain(&mem);
+        let mut iovec = IoVecBufferMut::from_descriptor_chain(q.pop(&mem).unwrap()).unwrap();
+
+        // Spanning the second and third memory regions.
+        let header = PackedHeader {
+            kind: 1,
+            sector: 0x0102_0304_0506_0708,
+            len: 0x1122_3344,
+        };
+        iovec.write_obj(header, 120).unwrap();
+        let mut expected = vec![0u8; 64];
+        expected[56..].copy_from_slice(&header.as_slice()[..8]);
+        vq.dtable[1].check_data(&expected);
+        let mut expected = vec![0u8; 64];
+        expected[..5].copy_from_slice(&header.as_slice()[8..]);
+        vq.dtable[2].check_data(&expected);
+
+        assert!(matches!(
+            iovec.write_obj(0u64, 252),
+            Err(VolatileMemoryError::PartialBuffer {
+                expected: 8,
+                completed: 4
+            })
+        ));
+        assert!(matches!(
+            iovec.write_obj(0u8, 256),
+            Err(VolatileMemoryError::OutOfBounds { addr: 256 })
+        ));
+    }
 }
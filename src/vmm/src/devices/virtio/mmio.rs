@@ -6,15 +6,18 @@
 // found in the THIRD-PARTY file.
 
 use std::fmt::Debug;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
 
+use serde::Serialize;
 use utils::byte_order;
+use utils::time::{get_time_us, ClockType};
 
 use crate::devices::virtio::device::VirtioDevice;
 use crate::devices::virtio::device_status;
 use crate::devices::virtio::queue::Queue;
-use crate::logger::warn;
+use crate::lifecycle::{notify_lifecycle_event, LifecycleEvent};
+use crate::logger::{error, info, warn};
 use crate::vstate::memory::{GuestAddress, GuestMemoryMmap};
 
 // TODO crosvm uses 0 here, but IIRC virtio specified some other vendor id that should be used
@@ -31,6 +34,85 @@ const MMIO_MAGIC_VALUE: u32 = 0x7472_6976;
 // current version specified by the mmio standard (legacy devices used 1 here)
 const MMIO_VERSION: u32 = 2;
 
+/// Returns the name given by the virtio specification to the MMIO register at `offset`.
+fn register_name(offset: u64) -> &'static str {
+    match offset {
+        0x00 => "MagicValue",
+        0x04 => "Version",
+        0x08 => "DeviceID",
+        0x0c => "VendorID",
+        0x10 => "DeviceFeatures",
+        0x14 => "DeviceFeaturesSel",
+        0x20 => "DriverFeatures",
+        0x24 => "DriverFeaturesSel",
+        0x30 => "QueueSel",
+        0x34 => "QueueNumMax",
+        0x38 => "QueueNum",
+        0x44 => "QueueReady",
+        0x50 => "QueueNotify",
+        0x60 => "InterruptStatus",
+        0x64 => "InterruptACK",
+        0x70 => "Status",
+        0x80 => "QueueDescLow",
+        0x84 => "QueueDescHigh",
+        0x90 => "QueueDriverLow",
+        0x94 => "QueueDriverHigh",
+        0xa0 => "QueueDeviceLow",
+        0xa4 => "QueueDeviceHigh",
+        0xfc => "ConfigGeneration",
+        0x100..=0xfff => "Config",
+        _ => "Unknown",
+    }
+}
+
+/// Activation status of a virtio device, as reported by the API.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DeviceActivation {
+    /// The guest driver did not complete the initialization of the device yet.
+    #[default]
+    Inactive,
+    /// The device was activated at `timestamp_us`, in microseconds of the real time clock.
+    Activated {
+        /// Time of the activation.
+        timestamp_us: u64,
+    },
+    /// The activation of the device failed at `timestamp_us`.
+    Failed {
+        /// Time of the failure.
+        timestamp_us: u64,
+        /// Cause of the failure.
+        reason: String,
+    },
+}
+
+/// Counts the virtio devices that were never activated, and notifies the lifecycle sinks when
+/// the guest drivers have activated all of them.
+#[derive(Debug, Default)]
+pub struct ActivationBarrier {
+    pending: AtomicUsize,
+}
+
+impl ActivationBarrier {
+    /// Adds a device to wait for.
+    pub fn add(&self) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Records the first activation of a device.
+    pub fn arrive(&self) {
+        if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            info!("All the virtio devices were activated");
+            notify_lifecycle_event(LifecycleEvent::DevicesActivated);
+        }
+    }
+
+    /// Number of devices that were never activated.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+}
+
 /// Implements the
 /// [MMIO](http://docs.oasis-open.org/virtio/virtio/v1.0/cs04/virtio-v1.0-cs04.html#x1-1090002)
 /// transport for virtio devices.
@@ -58,6 +140,11 @@ pub struct MmioTransport {
     mem: GuestMemoryMmap,
     pub(crate) interrupt_status: Arc<AtomicU32>,
     pub is_vhost_user: bool,
+    // Name of the device in the logs of the register accesses, when they are traced.
+    trace_name: Option<String>,
+    activation: DeviceActivation,
+    // Barrier to arrive at on the first activation of the device.
+    activation_barrier: Option<Arc<ActivationBarrier>>,
 }
 
 impl MmioTransport {
@@ -79,6 +166,67 @@ impl MmioTransport {
             mem,
             interrupt_status,
             is_vhost_user,
+            trace_name: None,
+            activation: DeviceActivation::Inactive,
+            activation_barrier: None,
+        }
+    }
+
+    /// Activation status of the device.
+    pub fn activation(&self) -> &DeviceActivation {
+        &self.activation
+    }
+
+    /// Makes the first activation of the device arrive at `barrier`.
+    ///
+    /// A device that is already activated, such as one restored from a snapshot, is reported as
+    /// activated now and doesn't wait on the barrier.
+    pub fn set_activation_barrier(&mut self, barrier: Arc<ActivationBarrier>) {
+        if self.locked_device().is_activated() {
+            self.set_activation(DeviceActivation::Activated {
+                timestamp_us: get_time_us(ClockType::Real),
+            });
+        } else {
+            barrier.add();
+            self.activation_barrier = Some(barrier);
+        }
+    }
+
+    fn set_activation(&mut self, activation: DeviceActivation) {
+        if matches!(activation, DeviceActivation::Activated { .. }) {
+            if let Some(barrier) = self.activation_barrier.take() {
+                barrier.arrive();
+            }
+        }
+        self.activation = activation;
+    }
+
+    /// Starts logging the accesses to the registers of the device under `name`, or stops it when
+    /// `name` is `None`.
+    pub fn set_trace(&mut self, name: Option<String>) {
+        self.trace_name = name;
+    }
+
+    /// Whether the accesses to the registers of the device are logged.
+    pub fn is_traced(&self) -> bool {
+        self.trace_name.is_some()
+    }
+
+    fn trace_access(&self, access: &str, offset: u64, data: &[u8]) {
+        let Some(name) = self.trace_name.as_ref() else {
+            return;
+        };
+        let register = register_name(offset);
+        match offset {
+            0x00..=0xff if data.len() == 4 => info!(
+                "mmio trace {name}: {access} {register} (0x{offset:x}) = 0x{:x}",
+                byte_order::read_le_u32(data)
+            ),
+            0x100..=0xfff => info!(
+                "mmio trace {name}: {access} {register}+0x{:x} = {data:02x?}",
+                offset - 0x100
+            ),
+            _ => info!("mmio trace {name}: {access} {register} (0x{offset:x}) = {data:02x?}"),
         }
     }
 
@@ -154,11 +302,21 @@ impl MmioTransport {
         self.queue_select = 0;
         self.interrupt_status.store(0, Ordering::SeqCst);
         self.device_status = device_status::INIT;
+        self.activation = DeviceActivation::Inactive;
         // . Keep interrupt_evt and queue_evts as is. There may be pending notifications in those
         //   eventfds, but nothing will happen other than supurious wakeups.
         // . Do not reset config_generation and keep it monotonically increasing
+        // . Keep the DMA ranges of the queues, but clear a failure caused by a violation.
+        // . Keep the metrics of the queues.
         for queue in self.locked_device().queues_mut() {
+            let dma_ranges = queue.dma_ranges.take();
+            if let Some(ranges) = &dma_ranges {
+                ranges.reset();
+            }
+            let metrics = queue.metrics.take();
             *queue = Queue::new(queue.get_max_size());
+            queue.set_dma_ranges(dma_ranges);
+            queue.metrics = metrics;
         }
     }
 
@@ -186,10 +344,31 @@ impl MmioTransport {
             DRIVER_OK if self.device_status == (ACKNOWLEDGE | DRIVER | FEATURES_OK) => {
                 self.device_status = status;
                 let device_activated = self.locked_device().is_activated();
-                if !device_activated && self.are_queues_valid() {
+                if device_activated {
+                    return;
+                }
+                let timestamp_us = get_time_us(ClockType::Real);
+                let result = if self.are_queues_valid() {
                     self.locked_device()
                         .activate(self.mem.clone())
-                        .expect("Failed to activate device");
+                        .map_err(|err| err.to_string())
+                } else {
+                    Err(String::from("The queues are invalid"))
+                };
+                match result {
+                    Ok(()) => self.set_activation(DeviceActivation::Activated { timestamp_us }),
+                    Err(reason) => {
+                        error!(
+                            "Failed to activate the virtio device of type {}: {reason}",
+                            self.locked_device().device_type()
+                        );
+                        // The driver must reset the device before using it again.
+                        self.device_status |= DEVICE_NEEDS_RESET;
+                        self.set_activation(DeviceActivation::Failed {
+                            timestamp_us,
+                            reason,
+                        });
+                    }
                 }
             }
             _ if (status & FAILED) != 0 => {
@@ -227,6 +406,16 @@ impl MmioTransport {
 
 impl MmioTransport {
     pub fn bus_read(&mut self, offset: u64, data: &mut [u8]) {
+        self.read_register(offset, data);
+        self.trace_access("read", offset, data);
+    }
+
+    pub fn bus_write(&mut self, offset: u64, data: &[u8]) {
+        self.trace_access("write", offset, data);
+        self.write_register(offset, data);
+    }
+
+    fn read_register(&mut self, offset: u64, data: &mut [u8]) {
         match offset {
             0x00..=0xff if data.len() == 4 => {
                 let v = match offset {
@@ -269,7 +458,13 @@ impl MmioTransport {
                             VIRTIO_MMIO_INT_VRING
                         }
                     }
-                    0x70 => self.device_status,
+                    0x70 => {
+                        if self.locked_device().needs_reset() {
+                            self.device_status | device_status::DEVICE_NEEDS_RESET
+                        } else {
+                            self.device_status
+                        }
+                    }
                     0xfc => self.config_generation,
                     _ => {
                         warn!("unknown virtio mmio register read: 0x{:x}", offset);
@@ -289,7 +484,7 @@ impl MmioTransport {
         };
     }
 
-    pub fn bus_write(&mut self, offset: u64, data: &[u8]) {
+    fn write_register(&mut self, offset: u64, data: &[u8]) {
         fn hi(v: &mut GuestAddress, x: u32) {
             *v = (*v & 0xffff_ffff) | (u64::from(x) << 32)
         }
@@ -377,6 +572,7 @@ pub(crate) mod tests {
         queues: Vec<Queue>,
         device_activated: bool,
         config_bytes: [u8; 0xeff],
+        needs_reset: bool,
     }
 
     impl DummyDevice {
@@ -393,12 +589,9 @@ pub(crate) mod tests {
                 queues: vec![Queue::new(16), Queue::new(32)],
                 device_activated: false,
                 config_bytes: [0; 0xeff],
+                needs_reset: false,
             }
         }
-
-        fn set_avail_features(&mut self, avail_features: u64) {
-            self.avail_features = avail_features;
-        }
     }
 
     impl VirtioDevice for DummyDevice {
@@ -406,6 +599,10 @@ pub(crate) mod tests {
             self.avail_features
         }
 
+        fn set_avail_features(&mut self, avail_features: u64) {
+            self.avail_features = avail_features;
+        }
+
         fn acked_features(&self) -> u64 {
             self.acked_features
         }
@@ -456,6 +653,10 @@ pub(crate) mod tests {
         fn is_activated(&self) -> bool {
             self.device_activated
         }
+
+        fn needs_reset(&self) -> bool {
+            self.needs_reset
+        }
     }
 
     fn set_device_status(d: &mut MmioTransport, status: u32) {
@@ -886,6 +1087,81 @@ pub(crate) mod tests {
         assert!(d.locked_device().is_activated());
     }
 
+    #[test]
+    fn test_device_needs_reset() {
+        let m = single_region_mem(0x1000);
+        let dummy = Arc::new(Mutex::new(DummyDevice::new()));
+        let mut d = MmioTransport::new(m, dummy.clone(), false);
+        activate_device(&mut d);
+        let mut buf = [0; 4];
+        d.bus_read(0x70, &mut buf);
+        assert_eq!(read_le_u32(&buf), d.device_status);
+        assert_eq!(d.device_status & device_status::DEVICE_NEEDS_RESET, 0);
+
+        // The device status of a failed device has the DEVICE_NEEDS_RESET bit set.
+        dummy.lock().unwrap().needs_reset = true;
+        d.bus_read(0x70, &mut buf);
+        assert_eq!(
+            read_le_u32(&buf),
+            d.device_status | device_status::DEVICE_NEEDS_RESET
+        );
+    }
+
+    #[test]
+    fn test_device_activation() {
+        let m = single_region_mem(0x1000);
+        let mut d = MmioTransport::new(m, Arc::new(Mutex::new(DummyDevice::new())), false);
+        let barrier = Arc::new(ActivationBarrier::default());
+        d.set_activation_barrier(barrier.clone());
+        assert_eq!(barrier.pending(), 1);
+        assert_eq!(d.activation(), &DeviceActivation::Inactive);
+
+        // The activation fails when the queues are not set up.
+        set_device_status(&mut d, device_status::ACKNOWLEDGE);
+        set_device_status(&mut d, device_status::ACKNOWLEDGE | device_status::DRIVER);
+        set_device_status(
+            &mut d,
+            device_status::ACKNOWLEDGE | device_status::DRIVER | device_status::FEATURES_OK,
+        );
+        set_device_status(
+            &mut d,
+            device_status::ACKNOWLEDGE
+                | device_status::DRIVER
+                | device_status::FEATURES_OK
+                | device_status::DRIVER_OK,
+        );
+        assert!(!d.locked_device().is_activated());
+        assert_ne!(d.device_status & device_status::DEVICE_NEEDS_RESET, 0);
+        assert!(matches!(
+            d.activation(),
+            DeviceActivation::Failed { reason, .. } if reason == "The queues are invalid"
+        ));
+        assert_eq!(barrier.pending(), 1);
+
+        // A reset lets the driver try again.
+        set_device_status(&mut d, 0);
+        assert_eq!(d.activation(), &DeviceActivation::Inactive);
+        activate_device(&mut d);
+        assert!(matches!(
+            d.activation(),
+            DeviceActivation::Activated { timestamp_us } if *timestamp_us > 0
+        ));
+        assert_eq!(barrier.pending(), 0);
+        assert_eq!(
+            serde_json::to_value(d.activation()).unwrap()["status"],
+            "activated"
+        );
+
+        // Devices that are already activated don't wait on the barrier.
+        let mut restored = MmioTransport::new(d.mem.clone(), d.device(), false);
+        restored.set_activation_barrier(barrier.clone());
+        assert_eq!(barrier.pending(), 0);
+        assert!(matches!(
+            restored.activation(),
+            DeviceActivation::Activated { .. }
+        ));
+    }
+
     #[test]
     fn test_get_avail_features() {
         let dummy_dev = DummyDevice::new();
@@ -915,4 +1191,26 @@ pub(crate) mod tests {
         dummy_dev.ack_features_by_page(0, 8);
         assert_eq!(dummy_dev.acked_features(), 24);
     }
+
+    #[test]
+    fn test_bus_trace() {
+        assert_eq!(register_name(0x70), "Status");
+        assert_eq!(register_name(0x104), "Config");
+        assert_eq!(register_name(0x1000), "Unknown");
+
+        // Tracing the register accesses doesn't change their outcome.
+        let m = single_region_mem(0x1000);
+        let mut d = MmioTransport::new(m, Arc::new(Mutex::new(DummyDevice::new())), false);
+        d.set_trace(Some("virtio-dummy/dummy".to_string()));
+        assert!(d.is_traced());
+        let mut buf = [0; 4];
+        d.bus_read(0x00, &mut buf[..]);
+        assert_eq!(read_le_u32(&buf[..]), MMIO_MAGIC_VALUE);
+        activate_device(&mut d);
+        assert!(d.locked_device().is_activated());
+        d.bus_read(0x1000, &mut buf[..2]);
+
+        d.set_trace(None);
+        assert!(!d.is_traced());
+    }
 }
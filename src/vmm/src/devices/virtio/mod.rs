@@ -12,18 +12,25 @@ use std::io::Error as IOError;
 
 pub mod balloon;
 pub mod block;
+pub mod crypto;
 pub mod device;
+pub mod dma;
+pub mod features;
 pub mod gen;
 pub mod iovec;
 pub mod mmio;
 pub mod net;
 pub mod persist;
 pub mod queue;
+pub mod queue_metrics;
+pub mod remote;
 pub mod rng;
 pub mod test_utils;
+pub mod trace;
 pub mod vhost_user;
 pub mod vhost_user_metrics;
 pub mod vsock;
+pub mod worker;
 
 /// When the driver initializes the device, it lets the device know about the
 /// completed stages using the Device Status Field.
@@ -40,6 +47,7 @@ mod device_status {
     pub const FAILED: u32 = 128;
     pub const FEATURES_OK: u32 = 8;
     pub const DRIVER_OK: u32 = 4;
+    pub const DEVICE_NEEDS_RESET: u32 = 64;
 }
 
 /// Types taken from linux/virtio_ids.h.
@@ -52,6 +60,8 @@ pub const TYPE_BLOCK: u32 = 2;
 pub const TYPE_RNG: u32 = 4;
 /// Virtio balloon device ID.
 pub const TYPE_BALLOON: u32 = 5;
+/// Virtio crypto device ID.
+pub const TYPE_CRYPTO: u32 = 20;
 
 /// Offset from the base MMIO address of a virtio device used by the guest to notify the device of
 /// queue events.
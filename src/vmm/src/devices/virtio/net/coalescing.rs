@@ -0,0 +1,234 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Moderates the interrupts signaling used descriptors to the guest driver.
+//!
+//! Instead of signaling the driver every time the device uses descriptors of a queue, the
+//! signal is delayed until either enough descriptors are used or a timer expires, similarly to
+//! the interrupt coalescing of physical network cards.
+
+use std::fmt;
+use std::num::Wrapping;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+
+/// Interrupt coalescing parameters of a queue.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InterruptCoalescingConfig {
+    /// Number of used descriptors after which the driver is signaled without waiting for the
+    /// timer. If 0, the driver is only signaled when the timer expires.
+    #[serde(default)]
+    pub max_frames: u16,
+    /// Maximum time, in microseconds, between the device using a descriptor and the driver
+    /// being signaled. If 0, interrupt coalescing is disabled.
+    #[serde(default)]
+    pub max_usecs: u64,
+}
+
+impl InterruptCoalescingConfig {
+    /// Returns whether the interrupts are coalesced.
+    pub fn is_enabled(&self) -> bool {
+        self.max_usecs != 0
+    }
+
+    /// Returns `None` if interrupt coalescing is disabled, or `Some(self)` otherwise.
+    pub fn into_option(self) -> Option<Self> {
+        if self.is_enabled() {
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+
+/// Errors of the interrupt coalescer.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum InterruptCoalescerError {
+    /// Interrupt coalescer event handler called without an armed timer
+    SpuriousEvent,
+}
+
+/// Decides when the driver is signaled about the descriptors used by the device in a queue.
+///
+/// The coalescer generates events on the FD provided by its `AsRawFd` implementation when the
+/// driver has to be signaled. The user must call `event_handler()` on such events.
+pub struct InterruptCoalescer {
+    config: InterruptCoalescingConfig,
+    timer_fd: TimerFd,
+    timer_armed: bool,
+    // Index of the used ring when the driver was last signaled.
+    signaled_used: Wrapping<u16>,
+}
+
+impl fmt::Debug for InterruptCoalescer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InterruptCoalescer")
+            .field("config", &self.config)
+            .field("timer_armed", &self.timer_armed)
+            .field("signaled_used", &self.signaled_used)
+            .finish()
+    }
+}
+
+impl InterruptCoalescer {
+    /// Creates a coalescer with the given parameters.
+    pub fn new(config: InterruptCoalescingConfig) -> std::io::Result<Self> {
+        Ok(InterruptCoalescer {
+            config,
+            timer_fd: TimerFd::new_custom(ClockId::Monotonic, true, true)?,
+            timer_armed: false,
+            signaled_used: Wrapping(0),
+        })
+    }
+
+    /// Provides the parameters of the coalescer.
+    pub fn config(&self) -> InterruptCoalescingConfig {
+        self.config
+    }
+
+    /// Updates the parameters of the coalescer. If coalescing gets disabled, the pending signal
+    /// is cancelled and `true` is returned, so that the caller signals the driver instead.
+    pub fn update(&mut self, config: InterruptCoalescingConfig) -> bool {
+        self.config = config;
+        if !config.is_enabled() && self.timer_armed {
+            self.timer_fd
+                .set_state(TimerState::Disarmed, SetTimeFlags::Default);
+            self.timer_armed = false;
+            return true;
+        }
+        false
+    }
+
+    /// Returns whether the driver has to be signaled now, given the index `next_used` of the
+    /// next entry of the used ring. Otherwise, the timer is armed to signal it later.
+    pub fn should_signal(&mut self, next_used: Wrapping<u16>) -> bool {
+        if !self.config.is_enabled() {
+            self.signaled_used = next_used;
+            return true;
+        }
+
+        let pending = (next_used - self.signaled_used).0;
+        if pending == 0 {
+            return false;
+        }
+        if self.config.max_frames != 0 && pending >= self.config.max_frames {
+            self.timer_fd
+                .set_state(TimerState::Disarmed, SetTimeFlags::Default);
+            self.timer_armed = false;
+            self.signaled_used = next_used;
+            return true;
+        }
+        if !self.timer_armed {
+            self.schedule_signal();
+        }
+        false
+    }
+
+    /// Arms the timer, if interrupt coalescing is enabled, so that the driver is signaled about
+    /// the pending used descriptors once it expires.
+    pub fn schedule_signal(&mut self) {
+        if self.config.is_enabled() {
+            let delay = Duration::from_micros(self.config.max_usecs);
+            self.timer_fd
+                .set_state(TimerState::Oneshot(delay), SetTimeFlags::Default);
+            self.timer_armed = true;
+        }
+    }
+
+    /// Handles the expiration of the timer, given the index `next_used` of the next entry of the
+    /// used ring. Returns whether the driver has to be signaled.
+    pub fn event_handler(
+        &mut self,
+        next_used: Wrapping<u16>,
+    ) -> Result<bool, InterruptCoalescerError> {
+        if self.timer_fd.read() == 0 {
+            return Err(InterruptCoalescerError::SpuriousEvent);
+        }
+        self.timer_armed = false;
+        let pending = next_used != self.signaled_used;
+        self.signaled_used = next_used;
+        Ok(pending)
+    }
+}
+
+impl AsRawFd for InterruptCoalescer {
+    /// Provides a FD which needs to be monitored for POLLIN events.
+    ///
+    /// This object's `event_handler()` method must be called on such events.
+    fn as_raw_fd(&self) -> RawFd {
+        self.timer_fd.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled() {
+        let mut coalescer = InterruptCoalescer::new(InterruptCoalescingConfig::default()).unwrap();
+        assert!(coalescer.should_signal(Wrapping(0)));
+        assert!(coalescer.should_signal(Wrapping(1)));
+        assert!(!coalescer.timer_armed);
+        assert!(matches!(
+            coalescer.event_handler(Wrapping(1)),
+            Err(InterruptCoalescerError::SpuriousEvent)
+        ));
+        assert_eq!(InterruptCoalescingConfig::default().into_option(), None);
+    }
+
+    #[test]
+    fn test_max_frames() {
+        let config = InterruptCoalescingConfig {
+            max_frames: 3,
+            max_usecs: 1_000_000,
+        };
+        let mut coalescer = InterruptCoalescer::new(config).unwrap();
+        assert_eq!(config.into_option(), Some(config));
+
+        // No descriptor was used since the driver was last signaled.
+        assert!(!coalescer.should_signal(Wrapping(0)));
+        assert!(!coalescer.timer_armed);
+
+        assert!(!coalescer.should_signal(Wrapping(2)));
+        assert!(coalescer.timer_armed);
+        assert!(coalescer.should_signal(Wrapping(3)));
+        assert!(!coalescer.timer_armed);
+
+        // The used ring index wraps around.
+        coalescer.signaled_used = Wrapping(u16::MAX);
+        assert!(!coalescer.should_signal(Wrapping(1)));
+        assert!(coalescer.should_signal(Wrapping(2)));
+    }
+
+    #[test]
+    fn test_timer() {
+        let config = InterruptCoalescingConfig {
+            max_frames: 0,
+            max_usecs: 1000,
+        };
+        let mut coalescer = InterruptCoalescer::new(config).unwrap();
+
+        assert!(!coalescer.should_signal(Wrapping(100)));
+        assert!(coalescer.timer_armed);
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(coalescer.event_handler(Wrapping(101)).unwrap());
+        assert!(!coalescer.timer_armed);
+        assert!(!coalescer.should_signal(Wrapping(101)));
+
+        // Disabling coalescing cancels the timer.
+        coalescer.schedule_signal();
+        assert!(coalescer.update(InterruptCoalescingConfig::default()));
+        assert!(!coalescer.timer_armed);
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(matches!(
+            coalescer.event_handler(Wrapping(101)),
+            Err(InterruptCoalescerError::SpuriousEvent)
+        ));
+        assert!(coalescer.should_signal(Wrapping(102)));
+    }
+}
@@ -0,0 +1,394 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements the commands of the virtio-net control virtqueue, which the guest driver uses to
+//! configure the receive filters of the device (see section 5.1.6.5 of the virtio 1.2
+//! specification).
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
+
+/// Class of the commands controlling the receive mode.
+pub const VIRTIO_NET_CTRL_RX: u8 = 0;
+/// Enables or disables promiscuous mode.
+pub const VIRTIO_NET_CTRL_RX_PROMISC: u8 = 0;
+/// Enables or disables receiving all multicast frames.
+pub const VIRTIO_NET_CTRL_RX_ALLMULTI: u8 = 1;
+/// Class of the commands controlling MAC address filtering.
+pub const VIRTIO_NET_CTRL_MAC: u8 = 1;
+/// Sets the unicast and multicast MAC filtering tables.
+pub const VIRTIO_NET_CTRL_MAC_TABLE_SET: u8 = 0;
+/// Sets the MAC address of the device.
+pub const VIRTIO_NET_CTRL_MAC_ADDR_SET: u8 = 1;
+/// Class of the commands controlling VLAN filtering.
+pub const VIRTIO_NET_CTRL_VLAN: u8 = 2;
+/// Adds a VLAN ID to the VLAN filter.
+pub const VIRTIO_NET_CTRL_VLAN_ADD: u8 = 0;
+/// Removes a VLAN ID from the VLAN filter.
+pub const VIRTIO_NET_CTRL_VLAN_DEL: u8 = 1;
+
+/// Status written back to the guest for a successful command.
+pub const VIRTIO_NET_OK: u8 = 0;
+/// Status written back to the guest for a failed command.
+pub const VIRTIO_NET_ERR: u8 = 1;
+
+/// Maximum number of entries of each of the unicast and multicast MAC filtering tables. Tables
+/// with more entries make the device accept all frames of the corresponding kind.
+pub const MAC_TABLE_MAX_ENTRIES: usize = 64;
+
+const ETH_HEADER_LEN: usize = 14;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const VLAN_ID_MASK: u16 = 0x0fff;
+const MAX_VLAN_ID: u16 = 4095;
+
+/// Errors triggered by control commands.
+#[derive(Debug, thiserror::Error, displaydoc::Display, PartialEq, Eq)]
+pub enum CtrlError {
+    /// Unsupported control command: class {0}, command {1}
+    UnsupportedCommand(u8, u8),
+    /// Malformed control command
+    MalformedCommand,
+    /// Invalid VLAN ID: {0}
+    InvalidVlanId(u16),
+}
+
+/// A single MAC filtering table.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MacTable {
+    /// The MAC addresses in the table.
+    pub entries: Vec<MacAddr>,
+    /// Whether the guest configured more than `MAC_TABLE_MAX_ENTRIES` entries, in which case all
+    /// addresses are accepted.
+    pub overflow: bool,
+}
+
+impl MacTable {
+    // Parses a table, i.e. a le32 number of entries followed by the entries, returning the bytes
+    // following it.
+    fn parse(data: &[u8]) -> Result<(Self, &[u8]), CtrlError> {
+        let count: [u8; 4] = data
+            .get(..4)
+            .and_then(|count| count.try_into().ok())
+            .ok_or(CtrlError::MalformedCommand)?;
+        let len = usize::try_from(u32::from_le_bytes(count))
+            .ok()
+            .and_then(|count| count.checked_mul(MAC_ADDR_LEN as usize))
+            .ok_or(CtrlError::MalformedCommand)?;
+        let data = &data[4..];
+        if data.len() < len {
+            return Err(CtrlError::MalformedCommand);
+        }
+        let (entries, data) = data.split_at(len);
+
+        let entries: Vec<_> = entries
+            .chunks_exact(MAC_ADDR_LEN as usize)
+            .map(MacAddr::from_bytes_unchecked)
+            .collect();
+        let table = if entries.len() > MAC_TABLE_MAX_ENTRIES {
+            MacTable {
+                entries: Vec::new(),
+                overflow: true,
+            }
+        } else {
+            MacTable {
+                entries,
+                overflow: false,
+            }
+        };
+        Ok((table, data))
+    }
+
+    fn contains(&self, mac: &[u8]) -> bool {
+        self.overflow || self.entries.iter().any(|entry| entry.get_bytes() == mac)
+    }
+}
+
+/// Receive filters configured by the guest driver through the control virtqueue.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RxFilter {
+    /// Whether all frames are accepted.
+    pub promisc: bool,
+    /// Whether all multicast frames are accepted.
+    pub allmulti: bool,
+    /// Additional unicast addresses accepted.
+    pub unicast: MacTable,
+    /// Multicast addresses accepted.
+    pub multicast: MacTable,
+    /// VLAN IDs whose tagged frames are accepted.
+    pub vlans: BTreeSet<u16>,
+}
+
+impl Default for RxFilter {
+    // Until the guest driver configures the filters, all frames are accepted.
+    fn default() -> Self {
+        RxFilter {
+            promisc: true,
+            allmulti: false,
+            unicast: MacTable::default(),
+            multicast: MacTable::default(),
+            vlans: BTreeSet::new(),
+        }
+    }
+}
+
+impl RxFilter {
+    /// Applies the command `cmd` of class `class`, with `data` as its command-specific data.
+    ///
+    /// `VIRTIO_NET_CTRL_MAC_ADDR_SET` changes the device config space, so it is handled by the
+    /// device itself.
+    pub fn apply(&mut self, class: u8, cmd: u8, data: &[u8]) -> Result<(), CtrlError> {
+        match (class, cmd) {
+            (VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_PROMISC) => {
+                self.promisc = Self::parse_on_off(data)?;
+            }
+            (VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_ALLMULTI) => {
+                self.allmulti = Self::parse_on_off(data)?;
+            }
+            (VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_TABLE_SET) => {
+                let (unicast, data) = MacTable::parse(data)?;
+                let (multicast, data) = MacTable::parse(data)?;
+                if !data.is_empty() {
+                    return Err(CtrlError::MalformedCommand);
+                }
+                self.unicast = unicast;
+                self.multicast = multicast;
+            }
+            (VIRTIO_NET_CTRL_VLAN, VIRTIO_NET_CTRL_VLAN_ADD) => {
+                self.vlans.insert(Self::parse_vlan_id(data)?);
+            }
+            (VIRTIO_NET_CTRL_VLAN, VIRTIO_NET_CTRL_VLAN_DEL) => {
+                self.vlans.remove(&Self::parse_vlan_id(data)?);
+            }
+            _ => return Err(CtrlError::UnsupportedCommand(class, cmd)),
+        }
+        Ok(())
+    }
+
+    /// Returns whether `frame`, an ethernet frame received from the host, passes the filters.
+    ///
+    /// Frames addressed to `guest_mac` are always accepted, as are all unicast frames when the
+    /// guest MAC is unknown. Tagged frames are only filtered by VLAN ID if `vlan_filtering` is set.
+    pub fn accepts(&self, frame: &[u8], guest_mac: Option<&MacAddr>, vlan_filtering: bool) -> bool {
+        if self.promisc || frame.len() < ETH_HEADER_LEN {
+            return true;
+        }
+
+        let ethertype = u16::from_be_bytes([frame[12], frame[13]]);
+        if vlan_filtering && ethertype == ETHERTYPE_VLAN {
+            let Some(tci) = frame.get(ETH_HEADER_LEN..ETH_HEADER_LEN + 2) else {
+                return false;
+            };
+            let vlan_id = u16::from_be_bytes([tci[0], tci[1]]) & VLAN_ID_MASK;
+            if !self.vlans.contains(&vlan_id) {
+                return false;
+            }
+        }
+
+        let dst_mac = &frame[..MAC_ADDR_LEN as usize];
+        if dst_mac.iter().all(|byte| *byte == 0xff) {
+            // Broadcast.
+            true
+        } else if dst_mac[0] & 0x01 != 0 {
+            // Multicast.
+            self.allmulti || self.multicast.contains(dst_mac)
+        } else {
+            let to_guest = match guest_mac {
+                Some(mac) => mac.get_bytes() == dst_mac,
+                None => true,
+            };
+            to_guest || self.unicast.contains(dst_mac)
+        }
+    }
+
+    fn parse_on_off(data: &[u8]) -> Result<bool, CtrlError> {
+        match data {
+            [on] => Ok(*on != 0),
+            _ => Err(CtrlError::MalformedCommand),
+        }
+    }
+
+    fn parse_vlan_id(data: &[u8]) -> Result<u16, CtrlError> {
+        let vlan_id: [u8; 2] = data.try_into().map_err(|_| CtrlError::MalformedCommand)?;
+        let vlan_id = u16::from_le_bytes(vlan_id);
+        if vlan_id > MAX_VLAN_ID {
+            return Err(CtrlError::InvalidVlanId(vlan_id));
+        }
+        Ok(vlan_id)
+    }
+}
+
+/// Parses the MAC address of a `VIRTIO_NET_CTRL_MAC_ADDR_SET` command.
+pub fn parse_mac_addr(data: &[u8]) -> Result<MacAddr, CtrlError> {
+    if data.len() != MAC_ADDR_LEN as usize {
+        return Err(CtrlError::MalformedCommand);
+    }
+    Ok(MacAddr::from_bytes_unchecked(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn frame(dst_mac: &str, vlan_id: Option<u16>) -> Vec<u8> {
+        let mut frame = MacAddr::from_str(dst_mac).unwrap().get_bytes().to_vec();
+        frame.extend_from_slice(&[0x06, 0, 0, 0, 0, 1]);
+        if let Some(vlan_id) = vlan_id {
+            frame.extend_from_slice(&ETHERTYPE_VLAN.to_be_bytes());
+            frame.extend_from_slice(&vlan_id.to_be_bytes());
+        }
+        frame.extend_from_slice(&0x0800u16.to_be_bytes());
+        frame.extend_from_slice(&[0u8; 32]);
+        frame
+    }
+
+    fn mac_table(macs: &[&str]) -> Vec<u8> {
+        let mut table = u32::try_from(macs.len()).unwrap().to_le_bytes().to_vec();
+        for mac in macs {
+            table.extend_from_slice(MacAddr::from_str(mac).unwrap().get_bytes());
+        }
+        table
+    }
+
+    #[test]
+    fn test_rx_mode() {
+        let guest_mac = MacAddr::from_str("06:00:00:00:00:02").unwrap();
+        let mut filter = RxFilter::default();
+        assert!(filter.accepts(&frame("06:00:00:00:00:03", None), Some(&guest_mac), true));
+
+        filter
+            .apply(VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_PROMISC, &[0])
+            .unwrap();
+        assert!(!filter.promisc);
+        assert!(!filter.accepts(&frame("06:00:00:00:00:03", None), Some(&guest_mac), true));
+        assert!(filter.accepts(&frame("06:00:00:00:00:02", None), Some(&guest_mac), true));
+        assert!(filter.accepts(&frame("ff:ff:ff:ff:ff:ff", None), Some(&guest_mac), true));
+        // Unicast frames are not filtered if the guest MAC is unknown.
+        assert!(filter.accepts(&frame("06:00:00:00:00:03", None), None, true));
+
+        assert!(!filter.accepts(&frame("01:00:5e:00:00:01", None), Some(&guest_mac), true));
+        filter
+            .apply(VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_ALLMULTI, &[1])
+            .unwrap();
+        assert!(filter.accepts(&frame("01:00:5e:00:00:01", None), Some(&guest_mac), true));
+
+        assert_eq!(
+            filter.apply(VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_PROMISC, &[0, 1]),
+            Err(CtrlError::MalformedCommand)
+        );
+        assert_eq!(
+            filter.apply(VIRTIO_NET_CTRL_RX, 5, &[1]),
+            Err(CtrlError::UnsupportedCommand(VIRTIO_NET_CTRL_RX, 5))
+        );
+    }
+
+    #[test]
+    fn test_mac_table() {
+        let guest_mac = MacAddr::from_str("06:00:00:00:00:02").unwrap();
+        let mut filter = RxFilter::default();
+        filter
+            .apply(VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_PROMISC, &[0])
+            .unwrap();
+
+        let mut data = mac_table(&["06:00:00:00:00:03"]);
+        data.extend(mac_table(&["01:00:5e:00:00:01"]));
+        filter
+            .apply(VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_TABLE_SET, &data)
+            .unwrap();
+        assert!(filter.accepts(&frame("06:00:00:00:00:03", None), Some(&guest_mac), true));
+        assert!(!filter.accepts(&frame("06:00:00:00:00:04", None), Some(&guest_mac), true));
+        assert!(filter.accepts(&frame("01:00:5e:00:00:01", None), Some(&guest_mac), true));
+        assert!(!filter.accepts(&frame("01:00:5e:00:00:02", None), Some(&guest_mac), true));
+
+        // Too many unicast entries make the device accept all unicast frames.
+        let macs: Vec<_> = (0..=MAC_TABLE_MAX_ENTRIES)
+            .map(|i| format!("06:00:00:00:01:{:02x}", i))
+            .collect();
+        let mut data = mac_table(&macs.iter().map(String::as_str).collect::<Vec<_>>());
+        data.extend(mac_table(&[]));
+        filter
+            .apply(VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_TABLE_SET, &data)
+            .unwrap();
+        assert!(filter.unicast.overflow);
+        assert!(filter.accepts(&frame("06:00:00:00:00:04", None), Some(&guest_mac), true));
+        assert!(!filter.accepts(&frame("01:00:5e:00:00:01", None), Some(&guest_mac), true));
+
+        // The multicast table is missing.
+        let data = mac_table(&["06:00:00:00:00:03"]);
+        assert_eq!(
+            filter.apply(VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_TABLE_SET, &data),
+            Err(CtrlError::MalformedCommand)
+        );
+        // Table shorter than its number of entries.
+        let mut data = 2u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&[0u8; MAC_ADDR_LEN as usize]);
+        assert_eq!(
+            filter.apply(VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_TABLE_SET, &data),
+            Err(CtrlError::MalformedCommand)
+        );
+        // Failed commands leave the filters unchanged.
+        assert!(filter.unicast.overflow);
+
+        assert_eq!(
+            parse_mac_addr(&[6, 0, 0, 0, 0, 5]).unwrap(),
+            MacAddr::from_str("06:00:00:00:00:05").unwrap()
+        );
+        assert_eq!(parse_mac_addr(&[6, 0]), Err(CtrlError::MalformedCommand));
+    }
+
+    #[test]
+    fn test_vlan_filter() {
+        let guest_mac = MacAddr::from_str("06:00:00:00:00:02").unwrap();
+        let mut filter = RxFilter::default();
+        filter
+            .apply(VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_PROMISC, &[0])
+            .unwrap();
+
+        let tagged = frame("06:00:00:00:00:02", Some(100));
+        assert!(!filter.accepts(&tagged, Some(&guest_mac), true));
+        // Tagged frames are not filtered without VLAN filtering.
+        assert!(filter.accepts(&tagged, Some(&guest_mac), false));
+        // Untagged frames are not filtered by VLAN.
+        assert!(filter.accepts(&frame("06:00:00:00:00:02", None), Some(&guest_mac), true));
+
+        filter
+            .apply(
+                VIRTIO_NET_CTRL_VLAN,
+                VIRTIO_NET_CTRL_VLAN_ADD,
+                &100u16.to_le_bytes(),
+            )
+            .unwrap();
+        assert!(filter.accepts(&tagged, Some(&guest_mac), true));
+        // The VLAN filter applies on top of the MAC filter.
+        assert!(!filter.accepts(
+            &frame("06:00:00:00:00:03", Some(100)),
+            Some(&guest_mac),
+            true
+        ));
+
+        filter
+            .apply(
+                VIRTIO_NET_CTRL_VLAN,
+                VIRTIO_NET_CTRL_VLAN_DEL,
+                &100u16.to_le_bytes(),
+            )
+            .unwrap();
+        assert!(!filter.accepts(&tagged, Some(&guest_mac), true));
+
+        assert_eq!(
+            filter.apply(
+                VIRTIO_NET_CTRL_VLAN,
+                VIRTIO_NET_CTRL_VLAN_ADD,
+                &4096u16.to_le_bytes()
+            ),
+            Err(CtrlError::InvalidVlanId(4096))
+        );
+        assert_eq!(
+            filter.apply(VIRTIO_NET_CTRL_VLAN, VIRTIO_NET_CTRL_VLAN_ADD, &[1]),
+            Err(CtrlError::MalformedCommand)
+        );
+    }
+}
@@ -7,54 +7,75 @@
 
 #[cfg(not(test))]
 use std::io::Read;
-use std::mem;
 use std::net::Ipv4Addr;
 use std::sync::atomic::AtomicU32;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{io, mem};
 
-use libc::EAGAIN;
+use libc::{EAGAIN, ENOBUFS};
 use log::{error, warn};
 use utils::eventfd::EventFd;
-use utils::net::mac::MacAddr;
+use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
 use utils::u64_to_usize;
+use vhost::vhost_user::VhostUserVirtioFeatures;
 use vm_memory::GuestMemoryError;
 
+use crate::devices::replay::{self, ReplaySource};
+use crate::devices::timer::DeviceTimer;
 use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, VirtioDevice};
 use crate::devices::virtio::gen::virtio_blk::VIRTIO_F_VERSION_1;
 use crate::devices::virtio::gen::virtio_net::{
-    virtio_net_hdr_v1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_TSO4,
+    virtio_net_hdr_v1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_CTRL_MAC_ADDR, VIRTIO_NET_F_CTRL_RX,
+    VIRTIO_NET_F_CTRL_VLAN, VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_TSO4,
     VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC,
+    VIRTIO_NET_F_STATUS, VIRTIO_NET_HDR_GSO_ECN, VIRTIO_NET_HDR_GSO_TCPV4,
+    VIRTIO_NET_HDR_GSO_TCPV6,
 };
 use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
-use crate::devices::virtio::iovec::IoVecBuffer;
+use crate::devices::virtio::iovec::{IoVecBuffer, IoVecError};
+use crate::devices::virtio::net::coalescing::{InterruptCoalescer, InterruptCoalescingConfig};
+use crate::devices::virtio::net::ctrl::{
+    parse_mac_addr, CtrlError, RxFilter, VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_ADDR_SET,
+    VIRTIO_NET_ERR, VIRTIO_NET_OK,
+};
+use crate::devices::virtio::net::dhcp::{DhcpConfig, DhcpServer};
 use crate::devices::virtio::net::metrics::{NetDeviceMetrics, NetMetricsPerDevice};
+use crate::devices::virtio::net::peer::PeerInbox;
+use crate::devices::virtio::net::port_forward::{PortForwardError, PortForwarder};
 use crate::devices::virtio::net::tap::Tap;
+use crate::devices::virtio::net::xdp::{XdpConfig, XdpSocket};
 use crate::devices::virtio::net::{
-    gen, NetError, NetQueue, MAX_BUFFER_SIZE, NET_QUEUE_SIZES, RX_INDEX, TX_INDEX,
+    gen, NetError, NetQueue, CTRL_INDEX, MAX_BUFFER_SIZE, NET_QUEUE_SIZES, RX_INDEX, TX_INDEX,
+};
+use crate::devices::virtio::queue::{DescriptorChain, Queue, UsedBatch};
+use crate::devices::virtio::worker::net::{
+    NetWorker, NetWorkerError, NUM_QUEUES as WORKER_NUM_QUEUES,
 };
-use crate::devices::virtio::queue::{DescriptorChain, Queue};
 use crate::devices::virtio::{ActivateError, TYPE_NET};
 use crate::devices::{report_net_event_fail, DeviceError};
 use crate::dumbo::pdu::arp::ETH_IPV4_FRAME_LEN;
 use crate::dumbo::pdu::ethernet::{EthernetFrame, PAYLOAD_OFFSET};
-use crate::logger::{IncMetric, METRICS};
+use crate::logger::{IncMetric, SharedIncMetric, METRICS};
 use crate::mmds::data_store::Mmds;
 use crate::mmds::ns::MmdsNetworkStack;
-use crate::rate_limiter::{BucketUpdate, RateLimiter, TokenType};
-use crate::vstate::memory::{ByteValued, Bytes, GuestMemoryMmap};
+use crate::rate_limiter::{BucketUpdate, RateLimiter};
+use crate::vmm_config::port_forward::PortForwardConfig;
+use crate::vstate::memory::{ByteValued, Bytes, GuestAddress, GuestMemoryMmap};
+use crate::vstate::memory_fault::{guarded, GuestMemoryFault};
 
 const FRAME_HEADER_MAX_LEN: usize = PAYLOAD_OFFSET + ETH_IPV4_FRAME_LEN;
 
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 enum FrontendError {
-    /// Add user.
-    AddUsed,
     /// Descriptor chain too mall.
     DescriptorChainTooSmall,
     /// Empty queue.
     EmptyQueue,
     /// Guest memory error: {0}
     GuestMemory(GuestMemoryError),
+    /// Guest memory fault: {0}
+    GuestMemoryFault(GuestMemoryFault),
     /// Read only descriptor.
     ReadOnlyDescriptor,
 }
@@ -94,10 +115,30 @@ fn init_vnet_hdr(buf: &mut [u8]) {
     buf[0..vnet_hdr_len()].fill(0);
 }
 
+// Offset of `gso_type` in `virtio_net_hdr_v1`.
+const VNET_HDR_GSO_TYPE_OFFSET: usize = 1;
+
+// Accounts the frame starting with the VNET hdr in `buf` in `tso4` or `tso6` if the hdr requests
+// TCP segmentation offload for it.
+fn account_tso_frame(buf: &[u8], tso4: &SharedIncMetric, tso6: &SharedIncMetric) {
+    let Some(gso_type) = buf.get(VNET_HDR_GSO_TYPE_OFFSET) else {
+        return;
+    };
+    match u32::from(*gso_type) & !VIRTIO_NET_HDR_GSO_ECN {
+        VIRTIO_NET_HDR_GSO_TCPV4 => tso4.inc(),
+        VIRTIO_NET_HDR_GSO_TCPV6 => tso6.inc(),
+        _ => (),
+    }
+}
+
+/// Bit of the `status` config field announcing that the link is up.
+const VIRTIO_NET_S_LINK_UP: u16 = 1;
+
 #[derive(Debug, Default, Clone, Copy)]
 #[repr(C)]
 pub struct ConfigSpace {
     pub guest_mac: MacAddr,
+    pub status: u16,
 }
 
 // SAFETY: `ConfigSpace` contains only PODs in `repr(C)` or `repr(transparent)`, without padding.
@@ -106,13 +147,15 @@ unsafe impl ByteValued for ConfigSpace {}
 /// VirtIO network device.
 ///
 /// It emulates a network device able to exchange L2 frames between the guest
-/// and a host-side tap device.
+/// and a host-side tap device, or an AF_XDP socket.
 #[derive(Debug)]
 pub struct Net {
     pub(crate) id: String,
 
     /// The backend for this device: a tap.
     pub tap: Tap,
+    /// The AF_XDP socket exchanging the frames instead of the tap, if any.
+    pub(crate) xdp: Option<XdpSocket>,
 
     pub(crate) avail_features: u64,
     pub(crate) acked_features: u64,
@@ -124,9 +167,14 @@ pub struct Net {
     pub(crate) tx_rate_limiter: RateLimiter,
 
     pub(crate) rx_deferred_frame: bool,
+    // The RX descriptor chains used since the driver was last signaled.
+    rx_used: UsedBatch,
+
+    pub(crate) rx_coalescer: InterruptCoalescer,
+    pub(crate) tx_coalescer: InterruptCoalescer,
 
-    rx_bytes_read: usize,
-    rx_frame_buf: [u8; MAX_BUFFER_SIZE],
+    pub(crate) rx_bytes_read: usize,
+    pub(crate) rx_frame_buf: [u8; MAX_BUFFER_SIZE],
 
     tx_frame_headers: [u8; frame_hdr_len()],
 
@@ -134,6 +182,14 @@ pub struct Net {
 
     pub(crate) config_space: ConfigSpace,
     pub(crate) guest_mac: Option<MacAddr>,
+    pub(crate) rx_filter: RxFilter,
+
+    /// ID of the network device configured as peer of this one.
+    pub(crate) peer_id: Option<String>,
+    /// Frames sent to this device by its peer.
+    pub(crate) peer_inbox: Arc<PeerInbox>,
+    /// Inbox of the peer of this device, if linked.
+    pub(crate) peer: Option<Arc<PeerInbox>>,
 
     pub(crate) device_state: DeviceState,
     pub(crate) activate_evt: EventFd,
@@ -141,7 +197,16 @@ pub struct Net {
     /// The MMDS stack corresponding to this interface.
     /// Only if MMDS transport has been associated with it.
     pub mmds_ns: Option<MmdsNetworkStack>,
+    /// The DHCP responder configuring the guest interface, if enabled.
+    pub(crate) dhcp: Option<DhcpServer>,
+    /// The proxy forwarding host ports to the guest, if any.
+    pub(crate) port_forwarder: Option<PortForwarder>,
+    /// Timer expiring when the next frame of the replay log is due, if the inputs are replayed.
+    pub(crate) replay_timer: Option<DeviceTimer>,
     pub(crate) metrics: Arc<NetDeviceMetrics>,
+
+    /// Worker process processing the RX and TX queues, if they are not processed by the VMM.
+    pub(crate) worker: Option<NetWorker>,
 }
 
 impl Net {
@@ -159,10 +224,18 @@ impl Net {
             | 1 << VIRTIO_NET_F_GUEST_UFO
             | 1 << VIRTIO_NET_F_HOST_TSO4
             | 1 << VIRTIO_NET_F_HOST_UFO
+            | 1 << VIRTIO_NET_F_CTRL_VQ
+            | 1 << VIRTIO_NET_F_CTRL_RX
+            | 1 << VIRTIO_NET_F_CTRL_VLAN
+            | 1 << VIRTIO_NET_F_CTRL_MAC_ADDR
+            | 1 << VIRTIO_NET_F_STATUS
             | 1 << VIRTIO_F_VERSION_1
             | 1 << VIRTIO_RING_F_EVENT_IDX;
 
-        let mut config_space = ConfigSpace::default();
+        let mut config_space = ConfigSpace {
+            status: VIRTIO_NET_S_LINK_UP,
+            ..Default::default()
+        };
         if let Some(mac) = guest_mac {
             config_space.guest_mac = mac;
             // Enabling feature for MAC address configuration
@@ -180,6 +253,7 @@ impl Net {
         Ok(Net {
             id: id.clone(),
             tap,
+            xdp: None,
             avail_features,
             acked_features: 0u64,
             queues,
@@ -187,16 +261,29 @@ impl Net {
             rx_rate_limiter,
             tx_rate_limiter,
             rx_deferred_frame: false,
+            rx_used: UsedBatch::default(),
+            rx_coalescer: InterruptCoalescer::new(InterruptCoalescingConfig::default())
+                .map_err(NetError::CoalescingTimer)?,
+            tx_coalescer: InterruptCoalescer::new(InterruptCoalescingConfig::default())
+                .map_err(NetError::CoalescingTimer)?,
             rx_bytes_read: 0,
             rx_frame_buf: [0u8; MAX_BUFFER_SIZE],
             tx_frame_headers: [0u8; frame_hdr_len()],
             irq_trigger: IrqTrigger::new().map_err(NetError::EventFd)?,
             config_space,
             guest_mac,
+            rx_filter: RxFilter::default(),
+            peer_id: None,
+            peer_inbox: Arc::new(PeerInbox::new(guest_mac).map_err(NetError::EventFd)?),
+            peer: None,
             device_state: DeviceState::Inactive,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(NetError::EventFd)?,
             mmds_ns: None,
+            dhcp: None,
+            port_forwarder: None,
+            replay_timer: None,
             metrics: NetMetricsPerDevice::alloc(id),
+            worker: None,
         })
     }
 
@@ -221,6 +308,32 @@ impl Net {
         Self::new_with_tap(id, tap, guest_mac, rx_rate_limiter, tx_rate_limiter)
     }
 
+    /// Hands the processing of the RX and TX queues to a sandboxed worker process. The guest
+    /// is no longer offered the control queue.
+    pub fn start_worker(&mut self) -> Result<(), NetError> {
+        let worker = NetWorker::new(&self.tap, self.avail_features).map_err(NetError::Worker)?;
+        self.avail_features = worker.features;
+        self.worker = Some(worker);
+        Ok(())
+    }
+
+    /// Whether the queues of this net device are processed by a worker process.
+    pub fn is_worker(&self) -> bool {
+        self.worker.is_some()
+    }
+
+    /// Asks the worker process to start or stop processing the queues. Has no effect if the
+    /// queues are processed by the VMM.
+    pub fn set_queues_enabled(&mut self, enable: bool) -> Result<(), NetError> {
+        match self.worker.as_mut() {
+            Some(worker) if self.device_state.is_activated() => worker
+                .vu_handle
+                .set_vrings_enabled(WORKER_NUM_QUEUES, enable)
+                .map_err(|err| NetError::Worker(NetWorkerError::VhostUser(err))),
+            _ => Ok(()),
+        }
+    }
+
     /// Provides the ID of this net device.
     pub fn id(&self) -> &String {
         &self.id
@@ -231,6 +344,31 @@ impl Net {
         self.guest_mac.as_ref()
     }
 
+    /// Whether the link of this net device is announced as up to the guest.
+    pub fn link_up(&self) -> bool {
+        self.config_space.status & VIRTIO_NET_S_LINK_UP != 0
+    }
+
+    /// Provides the ID of the network device configured as peer of this one.
+    pub fn peer_id(&self) -> Option<&String> {
+        self.peer_id.as_ref()
+    }
+
+    /// Sets the ID of the network device configured as peer of this one.
+    pub fn set_peer_id(&mut self, peer_id: Option<String>) {
+        self.peer_id = peer_id;
+    }
+
+    /// Provides the inbox receiving the frames sent to this device by its peer.
+    pub fn peer_inbox(&self) -> Arc<PeerInbox> {
+        self.peer_inbox.clone()
+    }
+
+    /// Links this device to the peer owning `peer_inbox`, or unlinks it if `None`.
+    pub fn set_peer(&mut self, peer_inbox: Option<Arc<PeerInbox>>) {
+        self.peer = peer_inbox;
+    }
+
     /// Provides the host IFACE name of this net device.
     pub fn iface_name(&self) -> String {
         self.tap.if_name_as_str().to_string()
@@ -251,11 +389,108 @@ impl Net {
         }
     }
 
+    /// Sets the MMDS data store namespace exposed to the guest through this device. Has no effect
+    /// if the device does not support MMDS.
+    pub fn set_mmds_namespace(&mut self, namespace: Option<String>) {
+        if let Some(mmds_ns) = self.mmds_ns.as_mut() {
+            mmds_ns.set_namespace(namespace);
+        }
+    }
+
+    /// Makes the writes of the frames sent by the guest to the tap fail, while `enabled`.
+    pub fn set_tx_fault(&mut self, enabled: bool) {
+        self.tap.write_fault = enabled;
+    }
+
+    /// Drops the interrupts of the device instead of delivering them to the guest, while
+    /// `enabled`.
+    pub fn set_irq_fault(&mut self, enabled: bool) {
+        self.irq_trigger.drop_irqs = enabled;
+    }
+
     /// Disables the `MmdsNetworkStack` to prevent device to forward MMDS requests.
     pub fn disable_mmds_network_stack(&mut self) {
         self.mmds_ns = None
     }
 
+    /// Returns the network configuration handed to the guest through DHCP, if enabled.
+    pub fn dhcp_config(&self) -> Option<&DhcpConfig> {
+        self.dhcp.as_ref().map(DhcpServer::config)
+    }
+
+    /// Answers the DHCP requests of the guest with `config`, or lets them through if `None`.
+    pub fn set_dhcp_config(&mut self, config: Option<DhcpConfig>) {
+        self.dhcp = config.map(DhcpServer::new);
+    }
+
+    /// Returns the host ports forwarded to the guest.
+    pub fn port_forwards(&self) -> &[PortForwardConfig] {
+        self.port_forwarder
+            .as_ref()
+            .map_or(&[], PortForwarder::configs)
+    }
+
+    /// Forwards the TCP connections accepted on the host addresses of `configs` to the guest,
+    /// replacing the previous port forwards.
+    pub fn set_port_forwards(
+        &mut self,
+        configs: Vec<PortForwardConfig>,
+    ) -> Result<(), PortForwardError> {
+        // The previous listeners are closed first, in case the same host ports are forwarded.
+        self.port_forwarder = None;
+        if !configs.is_empty() {
+            self.port_forwarder = Some(PortForwarder::new(configs)?);
+        }
+        Ok(())
+    }
+
+    /// Returns the configuration of the AF_XDP socket exchanging the frames, if any.
+    pub fn xdp_config(&self) -> Option<&XdpConfig> {
+        self.xdp.as_ref().map(XdpSocket::config)
+    }
+
+    /// Exchanges the frames through an AF_XDP socket set up from `config` instead of the tap.
+    /// Keeps using the tap if the socket cannot be set up.
+    pub fn set_xdp_config(&mut self, config: XdpConfig) {
+        match XdpSocket::new(config) {
+            Ok(xdp) => {
+                // The frames leave the host interface as the guest sends them, so they must be
+                // checksummed and segmented by the guest.
+                self.avail_features &= !(1 << VIRTIO_NET_F_CSUM
+                    | 1 << VIRTIO_NET_F_HOST_TSO4
+                    | 1 << VIRTIO_NET_F_HOST_UFO);
+                self.xdp = Some(xdp);
+            }
+            Err(err) => {
+                warn!(
+                    "Net device {} falls back to its tap device: {}",
+                    self.id, err
+                );
+                self.metrics.xdp_fallbacks.inc();
+            }
+        }
+    }
+
+    /// Prepares the device to receive the frames of the replay log instead of the tap frames.
+    pub fn enable_replay(&mut self) -> Result<(), NetError> {
+        if self.is_worker() {
+            return Err(NetError::WorkerUnsupported);
+        }
+        self.replay_timer = Some(DeviceTimer::new().map_err(NetError::IO)?);
+        Ok(())
+    }
+
+    // Arms the replay timer to expire when the next frame of the replay log is due.
+    fn arm_replay_timer(&mut self) {
+        if let Some(timer) = self.replay_timer.as_mut() {
+            match replay::time_to_next_input(&ReplaySource::NetRx(self.id.clone())) {
+                // A zero delay would disarm the timer.
+                Some(delay) => timer.arm_oneshot(delay.max(Duration::from_micros(1))),
+                None => timer.disarm(),
+            }
+        }
+    }
+
     /// Provides a reference to the configured RX rate limiter.
     pub fn rx_rate_limiter(&self) -> &RateLimiter {
         &self.rx_rate_limiter
@@ -266,13 +501,70 @@ impl Net {
         &self.tx_rate_limiter
     }
 
+    /// Provides the interrupt coalescing parameters of the RX queue.
+    pub fn rx_interrupt_coalescing(&self) -> InterruptCoalescingConfig {
+        self.rx_coalescer.config()
+    }
+
+    /// Provides the interrupt coalescing parameters of the TX queue.
+    pub fn tx_interrupt_coalescing(&self) -> InterruptCoalescingConfig {
+        self.tx_coalescer.config()
+    }
+
+    /// Updates the interrupt coalescing parameters of the RX and TX queues. Only the provided
+    /// parameters are updated.
+    pub fn set_interrupt_coalescing(
+        &mut self,
+        rx: Option<InterruptCoalescingConfig>,
+        tx: Option<InterruptCoalescingConfig>,
+    ) {
+        let rx_cancelled = rx.is_some_and(|rx| self.rx_coalescer.update(rx));
+        let tx_cancelled = tx.is_some_and(|tx| self.tx_coalescer.update(tx));
+        // The descriptors used while the interrupts were delayed must not wait for the cancelled
+        // signals.
+        if rx_cancelled {
+            self.signal_used_queue(NetQueue::Rx)
+                .unwrap_or_else(|err| report_net_event_fail(&self.metrics, err));
+        }
+        if tx_cancelled {
+            self.signal_used_queue(NetQueue::Tx)
+                .unwrap_or_else(|err| report_net_event_fail(&self.metrics, err));
+        }
+    }
+
+    // Stops processing the queues of a driver that made a descriptor chain the device cannot
+    // use, until the driver resets the device.
+    fn fail_device(&self) {
+        if let Err(err) = self.irq_trigger.signal_device_failure() {
+            error!("net: Failed to signal the device failure: {err}");
+        }
+    }
+
     fn signal_used_queue(&mut self, queue_type: NetQueue) -> Result<(), DeviceError> {
+        let (queue, coalescer) = match queue_type {
+            NetQueue::Rx => (&self.queues[RX_INDEX], Some(&mut self.rx_coalescer)),
+            NetQueue::Tx => (&self.queues[TX_INDEX], Some(&mut self.tx_coalescer)),
+            NetQueue::Ctrl => (&self.queues[CTRL_INDEX], None),
+        };
+        if let Some(coalescer) = coalescer {
+            if !coalescer.should_signal(queue.next_used) {
+                return Ok(());
+            }
+        }
+
+        self.kick_used_queue(queue_type)
+    }
+
+    // Signals the driver about the used descriptors of a queue, unless it suppressed the
+    // notifications.
+    fn kick_used_queue(&mut self, queue_type: NetQueue) -> Result<(), DeviceError> {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
 
         let queue = match queue_type {
             NetQueue::Rx => &mut self.queues[RX_INDEX],
             NetQueue::Tx => &mut self.queues[TX_INDEX],
+            NetQueue::Ctrl => &mut self.queues[CTRL_INDEX],
         };
 
         if queue.prepare_kick(mem) {
@@ -287,31 +579,11 @@ impl Net {
         Ok(())
     }
 
-    // Helper function to consume one op with `size` bytes from a rate limiter
-    fn rate_limiter_consume_op(rate_limiter: &mut RateLimiter, size: u64) -> bool {
-        if !rate_limiter.consume(1, TokenType::Ops) {
-            return false;
-        }
-
-        if !rate_limiter.consume(size, TokenType::Bytes) {
-            rate_limiter.manual_replenish(1, TokenType::Ops);
-            return false;
-        }
-
-        true
-    }
-
-    // Helper function to replenish one operation with `size` bytes from a rate limiter
-    fn rate_limiter_replenish_op(rate_limiter: &mut RateLimiter, size: u64) {
-        rate_limiter.manual_replenish(1, TokenType::Ops);
-        rate_limiter.manual_replenish(size, TokenType::Bytes);
-    }
-
     // Attempts to copy a single frame into the guest if there is enough
     // rate limiting budget.
     // Returns true on successful frame delivery.
     fn rate_limited_rx_single_frame(&mut self) -> bool {
-        if !Self::rate_limiter_consume_op(&mut self.rx_rate_limiter, self.rx_bytes_read as u64) {
+        if !self.rx_rate_limiter.consume_op(self.rx_bytes_read as u64) {
             self.metrics.rx_rate_limiter_throttled.inc();
             return false;
         }
@@ -322,7 +594,7 @@ impl Net {
         // Undo the tokens consumption if guest delivery failed.
         if !success {
             // revert the rate limiting budget consumption
-            Self::rate_limiter_replenish_op(&mut self.rx_rate_limiter, self.rx_bytes_read as u64);
+            self.rx_rate_limiter.replenish_op(self.rx_bytes_read as u64);
         }
 
         success
@@ -390,28 +662,50 @@ impl Net {
         })?;
         let head_index = head_descriptor.index;
 
-        let result = Self::write_to_descriptor_chain(
-            mem,
-            &self.rx_frame_buf[..self.rx_bytes_read],
-            head_descriptor,
-            &self.metrics,
-        );
+        let (result, fault) = guarded(mem, || {
+            Self::write_to_descriptor_chain(
+                mem,
+                &self.rx_frame_buf[..self.rx_bytes_read],
+                head_descriptor,
+                &self.metrics,
+            )
+        });
+        let result = match fault {
+            Some(fault) => Err(FrontendError::GuestMemoryFault(fault)),
+            None => result,
+        };
         // Mark the descriptor chain as used. If an error occurred, skip the descriptor chain.
+        // The used descriptor chains are published once the whole batch of frames is processed.
         let used_len = if result.is_err() {
             self.metrics.rx_fails.inc();
             0
         } else {
+            account_tso_frame(
+                &self.rx_frame_buf[..self.rx_bytes_read],
+                &self.metrics.rx_tso4_frames,
+                &self.metrics.rx_tso6_frames,
+            );
             // Safe to unwrap because a frame must be smaller than 2^16 bytes.
             u32::try_from(self.rx_bytes_read).unwrap()
         };
-        queue.add_used(mem, head_index, used_len).map_err(|err| {
-            error!("Failed to add available descriptor {}: {}", head_index, err);
-            FrontendError::AddUsed
-        })?;
+        self.rx_used.push(head_index, used_len);
 
         result
     }
 
+    // Publishes the RX descriptor chains used since the last call.
+    fn flush_rx_used(&mut self) -> Result<(), DeviceError> {
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+        self.rx_used
+            .flush(&mut self.queues[RX_INDEX], mem)
+            .map(|_| ())
+            .map_err(|err| {
+                error!("Failed to add used RX descriptors: {}", err);
+                DeviceError::QueueError(err)
+            })
+    }
+
     // Copies a single frame from `self.rx_frame_buf` into the guest. In case of an error retries
     // the operation if possible. Returns true if the operation was successfull.
     fn write_frame_to_guest(&mut self) -> bool {
@@ -419,7 +713,7 @@ impl Net {
         for _ in 0..max_iterations {
             match self.do_write_frame_to_guest() {
                 Ok(()) => return true,
-                Err(FrontendError::EmptyQueue) | Err(FrontendError::AddUsed) => {
+                Err(FrontendError::EmptyQueue) => {
                     return false;
                 }
                 Err(_) => {
@@ -432,15 +726,22 @@ impl Net {
         false
     }
 
-    // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it on the host TAP.
+    // Tries to detour the frame to MMDS, the DHCP responder or the port forwarding proxy and if
+    // they don't accept it, sends it to the peer device if addressed to it, or on the host TAP
+    // otherwise.
     //
-    // Returns whether MMDS consumed the frame.
+    // Returns whether MMDS, the DHCP responder or the port forwarding proxy consumed the frame.
+    #[allow(clippy::too_many_arguments)]
     fn write_to_mmds_or_tap(
         mmds_ns: Option<&mut MmdsNetworkStack>,
+        dhcp: Option<&mut DhcpServer>,
+        port_forwarder: Option<&mut PortForwarder>,
         rate_limiter: &mut RateLimiter,
         headers: &mut [u8],
         frame_iovec: &IoVecBuffer,
         tap: &mut Tap,
+        xdp: Option<&mut XdpSocket>,
+        peer: Option<&PeerInbox>,
         guest_mac: Option<MacAddr>,
         net_metrics: &NetDeviceMetrics,
     ) -> Result<bool, NetError> {
@@ -454,7 +755,8 @@ impl Net {
                 NetError::VnetHeaderMissing
             })?;
 
-        let headers = frame_bytes_from_buf(&headers[..header_len]).map_err(|e| {
+        let vnet_hdr_and_headers = &headers[..header_len];
+        let headers = frame_bytes_from_buf(vnet_hdr_and_headers).map_err(|e| {
             error!("VNET headers missing in TX frame");
             net_metrics.tx_malformed_frames.inc();
             e
@@ -463,23 +765,66 @@ impl Net {
         if let Some(ns) = mmds_ns {
             if ns.is_mmds_frame(headers) {
                 let mut frame = vec![0u8; frame_iovec.len() as usize - vnet_hdr_len()];
-                // Ok to unwrap here, because we are passing a buffer that has the exact size
-                // of the `IoVecBuffer` minus the VNET headers.
                 frame_iovec
                     .read_exact_volatile_at(&mut frame, vnet_hdr_len())
-                    .unwrap();
+                    .map_err(|err| {
+                        error!("Received malformed TX buffer: {:?}", err);
+                        net_metrics.tx_malformed_frames.inc();
+                        NetError::VnetHeaderMissing
+                    })?;
                 let _ = ns.detour_frame(&frame);
                 METRICS.mmds.rx_accepted.inc();
+                net_metrics.tx_mmds_intercepted_frames.inc();
 
                 // MMDS frames are not accounted by the rate limiter.
-                Self::rate_limiter_replenish_op(rate_limiter, u64::from(frame_iovec.len()));
+                rate_limiter.replenish_op(u64::from(frame_iovec.len()));
 
                 // MMDS consumed the frame.
                 return Ok(true);
             }
         }
 
-        // This frame goes to the TAP.
+        if let Some(dhcp) = dhcp.filter(|_| DhcpServer::is_dhcp_frame(headers)) {
+            let mut frame = vec![0u8; frame_iovec.len() as usize - vnet_hdr_len()];
+            frame_iovec
+                .read_exact_volatile_at(&mut frame, vnet_hdr_len())
+                .map_err(|err| {
+                    error!("Received malformed TX buffer: {:?}", err);
+                    net_metrics.tx_malformed_frames.inc();
+                    NetError::VnetHeaderMissing
+                })?;
+            dhcp.detour_frame(&frame);
+            net_metrics.tx_dhcp_intercepted_frames.inc();
+
+            // DHCP frames are not accounted by the rate limiter.
+            rate_limiter.replenish_op(u64::from(frame_iovec.len()));
+
+            // The DHCP responder consumed the frame.
+            return Ok(true);
+        }
+
+        if let Some(port_forwarder) =
+            port_forwarder.filter(|_| PortForwarder::is_port_forward_frame(headers))
+        {
+            let mut frame = vec![0u8; frame_iovec.len() as usize - vnet_hdr_len()];
+            frame_iovec
+                .read_exact_volatile_at(&mut frame, vnet_hdr_len())
+                .map_err(|err| {
+                    error!("Received malformed TX buffer: {:?}", err);
+                    net_metrics.tx_malformed_frames.inc();
+                    NetError::VnetHeaderMissing
+                })?;
+            port_forwarder.detour_frame(&frame);
+            net_metrics.tx_port_forward_intercepted_frames.inc();
+
+            // The frames of the forwarded connections are not accounted by the rate limiter.
+            rate_limiter.replenish_op(u64::from(frame_iovec.len()));
+
+            // The port forwarding proxy consumed the frame.
+            return Ok(true);
+        }
+
+        // This frame goes to the peer device or the TAP.
 
         // Check for guest MAC spoofing.
         if let Some(guest_mac) = guest_mac {
@@ -490,17 +835,55 @@ impl Net {
             });
         }
 
+        if let Some(peer) = peer.filter(|peer| peer.is_addressed_to(headers)) {
+            let mut frame = vec![0u8; frame_iovec.len() as usize];
+            frame_iovec
+                .read_exact_volatile_at(&mut frame, 0)
+                .map_err(|err| {
+                    error!("Received malformed TX buffer: {:?}", err);
+                    net_metrics.tx_malformed_frames.inc();
+                    NetError::VnetHeaderMissing
+                })?;
+            if peer.push(frame) {
+                net_metrics.tx_bytes_count.add(u64::from(frame_iovec.len()));
+                net_metrics.tx_packets_count.inc();
+                net_metrics.tx_count.inc();
+                account_tso_frame(
+                    vnet_hdr_and_headers,
+                    &net_metrics.tx_tso4_frames,
+                    &net_metrics.tx_tso6_frames,
+                );
+            } else {
+                net_metrics.tx_peer_dropped_frames.inc();
+            }
+            return Ok(false);
+        }
+
+        // The answers of the host network to the frames sent by the guest are in the replay log.
+        if replay::is_replaying() {
+            return Ok(false);
+        }
+
         let _metric = net_metrics.tap_write_agg.record_latency_metrics();
-        match Self::write_tap(tap, frame_iovec) {
+        let _hist = net_metrics.tap_write_latency_hist.record_latency();
+        match Self::write_tap(tap, xdp, frame_iovec) {
             Ok(_) => {
                 let len = u64::from(frame_iovec.len());
                 net_metrics.tx_bytes_count.add(len);
                 net_metrics.tx_packets_count.inc();
                 net_metrics.tx_count.inc();
+                account_tso_frame(
+                    vnet_hdr_and_headers,
+                    &net_metrics.tx_tso4_frames,
+                    &net_metrics.tx_tso6_frames,
+                );
             }
             Err(err) => {
                 error!("Failed to write to tap: {:?}", err);
                 net_metrics.tap_write_fails.inc();
+                if matches!(err.raw_os_error(), Some(ENOBUFS) | Some(EAGAIN)) {
+                    net_metrics.tx_dropped_tap_full.inc();
+                }
             }
         };
         Ok(false)
@@ -520,16 +903,97 @@ impl Net {
             }
         }
 
-        self.read_tap().map_err(NetError::IO)
+        if let Some(dhcp) = self.dhcp.as_mut() {
+            if let Some(len) =
+                dhcp.write_next_frame(frame_bytes_from_buf_mut(&mut self.rx_frame_buf)?)
+            {
+                self.metrics.rx_dhcp_frames.inc();
+                init_vnet_hdr(&mut self.rx_frame_buf);
+                return Ok(vnet_hdr_len() + len.get());
+            }
+        }
+
+        if let Some(port_forwarder) = self.port_forwarder.as_mut() {
+            if let Some(len) =
+                port_forwarder.write_next_frame(frame_bytes_from_buf_mut(&mut self.rx_frame_buf)?)
+            {
+                self.metrics.rx_port_forward_frames.inc();
+                init_vnet_hdr(&mut self.rx_frame_buf);
+                return Ok(vnet_hdr_len() + len.get());
+            }
+        }
+
+        if let Some(len) = self.peer_inbox.pop(&mut self.rx_frame_buf) {
+            return Ok(len);
+        }
+
+        if self.replay_timer.is_some() {
+            return match replay::next_due_input(&ReplaySource::NetRx(self.id.clone())) {
+                Some(frame) if frame.len() <= self.rx_frame_buf.len() => {
+                    self.rx_frame_buf[..frame.len()].copy_from_slice(&frame);
+                    Ok(frame.len())
+                }
+                _ => Err(NetError::IO(io::Error::from_raw_os_error(EAGAIN))),
+            };
+        }
+
+        let len = self.read_tap().map_err(NetError::IO)?;
+        if replay::is_recording() {
+            replay::record(
+                &ReplaySource::NetRx(self.id.clone()),
+                &self.rx_frame_buf[..len],
+            );
+        }
+        Ok(len)
+    }
+
+    // Returns whether the frame in `self.rx_frame_buf` passes the receive filters configured by the
+    // guest driver through the control queue.
+    fn rx_filter_accepts(&self) -> bool {
+        if !self.has_feature(u64::from(VIRTIO_NET_F_CTRL_RX)) {
+            return true;
+        }
+
+        frame_bytes_from_buf(&self.rx_frame_buf[..self.rx_bytes_read]).map_or(true, |frame| {
+            self.rx_filter.accepts(
+                frame,
+                self.guest_mac.as_ref(),
+                self.has_feature(u64::from(VIRTIO_NET_F_CTRL_VLAN)),
+            )
+        })
     }
 
     fn process_rx(&mut self) -> Result<(), DeviceError> {
-        // Read as many frames as possible.
+        if self.irq_trigger.needs_reset() {
+            return Ok(());
+        }
+        let result = self.read_rx_frames();
+        // Publish the frames written to the guest, even if reading the next one failed.
+        self.flush_rx_used()?;
+        result?;
+
+        // The next frame of the replay log is received when it is due, or once the guest makes
+        // room for the deferred one.
+        if !self.rx_deferred_frame {
+            self.arm_replay_timer();
+        }
+
+        // At this point we processed as many Rx frames as possible.
+        // We have to wake the guest if at least one descriptor chain has been used.
+        self.signal_used_queue(NetQueue::Rx)
+    }
+
+    // Writes as many frames as possible to the RX queue, without publishing them.
+    fn read_rx_frames(&mut self) -> Result<(), DeviceError> {
         loop {
             match self.read_from_mmds_or_tap() {
                 Ok(count) => {
                     self.rx_bytes_read = count;
                     self.metrics.rx_count.inc();
+                    if !self.rx_filter_accepts() {
+                        self.metrics.rx_filtered_frames.inc();
+                        continue;
+                    }
                     if !self.rate_limited_rx_single_frame() {
                         self.rx_deferred_frame = true;
                         break;
@@ -554,9 +1018,7 @@ impl Net {
             }
         }
 
-        // At this point we processed as many Rx frames as possible.
-        // We have to wake the guest if at least one descriptor chain has been used.
-        self.signal_used_queue(NetQueue::Rx)
+        Ok(())
     }
 
     // Process the deferred frame first, then continue reading from tap.
@@ -568,6 +1030,7 @@ impl Net {
             return self.process_rx();
         }
 
+        self.flush_rx_used()?;
         self.signal_used_queue(NetQueue::Rx)
     }
 
@@ -580,6 +1043,9 @@ impl Net {
     }
 
     fn process_tx(&mut self) -> Result<(), DeviceError> {
+        if self.irq_trigger.needs_reset() {
+            return Ok(());
+        }
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
 
@@ -588,8 +1054,13 @@ impl Net {
         // trigger a process_rx() which checks if there are any new frames to be sent, starting
         // with the MMDS network stack.
         let mut process_rx_for_mmds = false;
-        let mut used_any = false;
+        // The used descriptors are published all at once, after draining the queue.
+        let mut used = Vec::new();
+        let mut failed = false;
         let tx_queue = &mut self.queues[TX_INDEX];
+        self.metrics
+            .tx_queue_depth_hist
+            .record(tx_queue.len(mem).into());
 
         while let Some(head) = tx_queue.pop_or_enable_notification(mem) {
             self.metrics
@@ -599,11 +1070,17 @@ impl Net {
             // Parse IoVecBuffer from descriptor head
             let buffer = match IoVecBuffer::from_descriptor_chain(head) {
                 Ok(buffer) => buffer,
+                Err(err @ (IoVecError::GuestMemory(_) | IoVecError::OverflowedDescriptor)) => {
+                    error!("net: Malformed TX descriptor chain, failing the device: {err}");
+                    self.metrics.tx_fails.inc();
+                    self.metrics.tx_dropped_malformed.inc();
+                    failed = true;
+                    break;
+                }
                 Err(_) => {
                     self.metrics.tx_fails.inc();
-                    tx_queue
-                        .add_used(mem, head_index, 0)
-                        .map_err(DeviceError::QueueError)?;
+                    self.metrics.tx_dropped_malformed.inc();
+                    used.push((head_index, 0));
                     continue;
                 }
             };
@@ -612,42 +1089,65 @@ impl Net {
             if buffer.len() as usize > MAX_BUFFER_SIZE {
                 error!("net: received too big frame from driver");
                 self.metrics.tx_malformed_frames.inc();
-                tx_queue
-                    .add_used(mem, head_index, 0)
-                    .map_err(DeviceError::QueueError)?;
+                self.metrics.tx_dropped_malformed.inc();
+                used.push((head_index, 0));
                 continue;
             }
 
-            if !Self::rate_limiter_consume_op(&mut self.tx_rate_limiter, u64::from(buffer.len())) {
+            if !self.tx_rate_limiter.consume_op(u64::from(buffer.len())) {
                 tx_queue.undo_pop();
                 self.metrics.tx_rate_limiter_throttled.inc();
                 break;
             }
 
-            let frame_consumed_by_mmds = Self::write_to_mmds_or_tap(
-                self.mmds_ns.as_mut(),
-                &mut self.tx_rate_limiter,
-                &mut self.tx_frame_headers,
-                &buffer,
-                &mut self.tap,
-                self.guest_mac,
-                &self.metrics,
-            )
-            .unwrap_or(false);
+            tx_queue.trace_backend(head_index);
+            let (res, fault) = guarded(mem, || {
+                Self::write_to_mmds_or_tap(
+                    self.mmds_ns.as_mut(),
+                    self.dhcp.as_mut(),
+                    self.port_forwarder.as_mut(),
+                    &mut self.tx_rate_limiter,
+                    &mut self.tx_frame_headers,
+                    &buffer,
+                    &mut self.tap,
+                    self.xdp.as_mut(),
+                    self.peer.as_deref(),
+                    self.guest_mac,
+                    &self.metrics,
+                )
+            });
+            if fault.is_some() || res.is_err() {
+                self.metrics.tx_dropped_malformed.inc();
+            }
+            if let Some(fault) = fault {
+                error!("net: failed to read TX frame: {fault}");
+                self.metrics.tx_fails.inc();
+            }
+            let frame_consumed_by_mmds = res.unwrap_or(false);
             if frame_consumed_by_mmds && !self.rx_deferred_frame {
-                // MMDS consumed this frame/request, let's also try to process the response.
+                // MMDS or the DHCP responder consumed this frame/request, let's also try to
+                // process the response.
                 process_rx_for_mmds = true;
             }
 
-            tx_queue
-                .add_used(mem, head_index, 0)
-                .map_err(DeviceError::QueueError)?;
-            used_any = true;
+            used.push((head_index, 0));
         }
 
-        if !used_any {
+        if used.is_empty() {
             self.metrics.no_tx_avail_buffer.inc();
         }
+        tx_queue
+            .add_used_batch(mem, &used)
+            .map_err(DeviceError::QueueError)?;
+
+        if failed {
+            // Only the frames sent before the malformed one need a used queue notification.
+            if !used.is_empty() {
+                self.signal_used_queue(NetQueue::Tx)?;
+            }
+            self.fail_device();
+            return Ok(());
+        }
 
         self.signal_used_queue(NetQueue::Tx)?;
 
@@ -659,6 +1159,91 @@ impl Net {
         }
     }
 
+    // Reads the command in the descriptor chain starting at `head`, returning it along with the
+    // address of the descriptor in which its status has to be written.
+    fn read_ctrl_command(
+        mem: &GuestMemoryMmap,
+        head: DescriptorChain,
+    ) -> Result<(Vec<u8>, GuestAddress), FrontendError> {
+        let mut command = Vec::new();
+        let mut next_descriptor = Some(head);
+
+        while let Some(descriptor) = next_descriptor {
+            if descriptor.is_write_only() {
+                return Ok((command, descriptor.addr));
+            }
+
+            let start = command.len();
+            let end = start + descriptor.len as usize;
+            if end > MAX_BUFFER_SIZE {
+                return Err(FrontendError::DescriptorChainTooSmall);
+            }
+            command.resize(end, 0);
+            mem.read_slice(&mut command[start..], descriptor.addr)
+                .map_err(FrontendError::GuestMemory)?;
+
+            next_descriptor = descriptor.next_descriptor();
+        }
+
+        Err(FrontendError::DescriptorChainTooSmall)
+    }
+
+    fn process_ctrl(&mut self) -> Result<(), DeviceError> {
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+        let ctrl_queue = &mut self.queues[CTRL_INDEX];
+
+        while let Some(head) = ctrl_queue.pop_or_enable_notification(mem) {
+            let head_index = head.index;
+            let (command, status_addr) = match Self::read_ctrl_command(mem, head) {
+                Ok(command) => command,
+                Err(err) => {
+                    error!("Net: invalid control queue descriptor chain: {:?}", err);
+                    self.metrics.ctrl_fails.inc();
+                    ctrl_queue
+                        .add_used(mem, head_index, 0)
+                        .map_err(DeviceError::QueueError)?;
+                    continue;
+                }
+            };
+
+            let result = match command.as_slice() {
+                [VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_ADDR_SET, data @ ..] => {
+                    parse_mac_addr(data).map(|mac| {
+                        self.config_space.guest_mac = mac;
+                        self.guest_mac = Some(mac);
+                        self.peer_inbox.set_guest_mac(self.guest_mac);
+                        self.metrics.mac_address_updates.inc();
+                    })
+                }
+                [class, cmd, data @ ..] => self.rx_filter.apply(*class, *cmd, data),
+                _ => Err(CtrlError::MalformedCommand),
+            };
+            let status = match result {
+                Ok(()) => VIRTIO_NET_OK,
+                Err(err) => {
+                    error!("Net: failed to handle control command: {}", err);
+                    self.metrics.ctrl_fails.inc();
+                    VIRTIO_NET_ERR
+                }
+            };
+
+            let used_len = match mem.write_obj(status, status_addr) {
+                Ok(()) => 1,
+                Err(err) => {
+                    error!("Net: failed to write control command status: {:?}", err);
+                    self.metrics.ctrl_fails.inc();
+                    0
+                }
+            };
+            ctrl_queue
+                .add_used(mem, head_index, used_len)
+                .map_err(DeviceError::QueueError)?;
+        }
+
+        self.signal_used_queue(NetQueue::Ctrl)
+    }
+
     /// Updates the parameters for the rate limiters
     pub fn patch_rate_limiters(
         &mut self,
@@ -671,14 +1256,68 @@ impl Net {
         self.tx_rate_limiter.update_buckets(tx_bytes, tx_ops);
     }
 
+    /// Updates the MAC address exposed to the guest through the device config space. If the device
+    /// is activated, the guest driver is notified of the change through a config interrupt.
+    ///
+    /// Only devices created with a guest MAC offer `VIRTIO_NET_F_MAC`, so the MAC of devices
+    /// without one cannot be changed.
+    pub fn set_guest_mac(&mut self, mac: MacAddr) -> Result<(), NetError> {
+        if self.avail_features & (1 << VIRTIO_NET_F_MAC) == 0 {
+            return Err(NetError::GuestMacNotConfigured);
+        }
+
+        self.config_space.guest_mac = mac;
+        self.guest_mac = Some(mac);
+        self.peer_inbox.set_guest_mac(self.guest_mac);
+        self.metrics.mac_address_updates.inc();
+
+        if self.is_activated() {
+            self.irq_trigger
+                .signal_config_update()
+                .map_err(NetError::EventFd)?;
+        }
+        Ok(())
+    }
+
+    /// Updates the link state announced to the guest through the `status` config field. If the
+    /// device is activated, the guest driver is notified of the change through a config interrupt.
+    ///
+    /// The link state only informs the guest, frames keep being exchanged while the link is down.
+    pub fn set_link_up(&mut self, up: bool) -> Result<(), NetError> {
+        if self.link_up() == up {
+            return Ok(());
+        }
+
+        self.config_space.status ^= VIRTIO_NET_S_LINK_UP;
+        self.metrics.link_state_updates.inc();
+
+        if self.is_activated() {
+            self.irq_trigger
+                .signal_config_update()
+                .map_err(NetError::EventFd)?;
+        }
+        Ok(())
+    }
+
     #[cfg(not(test))]
     fn read_tap(&mut self) -> std::io::Result<usize> {
-        self.tap.read(&mut self.rx_frame_buf)
+        match self.xdp.as_mut() {
+            Some(xdp) => xdp.read(&mut self.rx_frame_buf),
+            None => self.tap.read(&mut self.rx_frame_buf),
+        }
     }
 
     #[cfg(not(test))]
-    fn write_tap(tap: &mut Tap, buf: &IoVecBuffer) -> std::io::Result<usize> {
-        tap.write_iovec(buf)
+    fn write_tap(
+        tap: &mut Tap,
+        xdp: Option<&mut XdpSocket>,
+        buf: &IoVecBuffer,
+    ) -> std::io::Result<usize> {
+        match xdp {
+            // The faults injected in the tap writes also apply to the socket.
+            Some(xdp) if !tap.write_fault => xdp.write_iovec(buf),
+            _ => tap.write_iovec(buf),
+        }
     }
 
     /// Process a single RX queue event.
@@ -688,7 +1327,7 @@ impl Net {
     pub fn process_rx_queue_event(&mut self) {
         self.metrics.rx_queue_event_count.inc();
 
-        if let Err(err) = self.queue_evts[RX_INDEX].read() {
+        if let Err(err) = self.queues[RX_INDEX].read_kicks(&self.queue_evts[RX_INDEX]) {
             // rate limiters present but with _very high_ allowed rate
             error!("Failed to get rx queue event: {:?}", err);
             self.metrics.event_fails.inc();
@@ -702,9 +1341,47 @@ impl Net {
     }
 
     pub fn process_tap_rx_event(&mut self) {
+        self.metrics.rx_tap_event_count.inc();
+        self.process_rx_event();
+    }
+
+    /// Process a single peer RX event.
+    ///
+    /// This is called by the event manager responding to the peer device sending
+    /// a frame to this one.
+    pub fn process_peer_rx_event(&mut self) {
+        if let Err(err) = self.peer_inbox.evt.read() {
+            error!("Failed to get peer rx event: {:?}", err);
+            self.metrics.event_fails.inc();
+        } else {
+            self.process_rx_event();
+        }
+    }
+
+    /// Process the events of the host sockets of the port forwarding proxy.
+    ///
+    /// This is called by the event manager when a forwarded connection is accepted, or has data
+    /// to send to the guest.
+    pub fn process_port_forward_event(&mut self) {
+        if let Some(port_forwarder) = self.port_forwarder.as_mut() {
+            port_forwarder.process_host_events();
+            self.process_rx_event();
+        }
+    }
+
+    /// Process the expiration of the replay timer.
+    ///
+    /// This is called by the event manager when the next frame of the replay log is due.
+    pub fn process_replay_timer_event(&mut self) {
+        if let Some(timer) = self.replay_timer.as_ref() {
+            timer.read_expirations();
+            self.process_rx_event();
+        }
+    }
+
+    fn process_rx_event(&mut self) {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
-        self.metrics.rx_tap_event_count.inc();
 
         // While there are no available RX queue buffers and there's a deferred_frame
         // don't process any more incoming. Otherwise start processing a frame. In the
@@ -739,7 +1416,7 @@ impl Net {
     /// buffer in the TX queue.
     pub fn process_tx_queue_event(&mut self) {
         self.metrics.tx_queue_event_count.inc();
-        if let Err(err) = self.queue_evts[TX_INDEX].read() {
+        if let Err(err) = self.queues[TX_INDEX].read_kicks(&self.queue_evts[TX_INDEX]) {
             error!("Failed to get tx queue event: {:?}", err);
             self.metrics.event_fails.inc();
         } else if !self.tx_rate_limiter.is_blocked()
@@ -752,6 +1429,21 @@ impl Net {
         }
     }
 
+    /// Process a single control queue event.
+    ///
+    /// This is called by the event manager responding to the guest adding a new
+    /// command in the control queue.
+    pub fn process_ctrl_queue_event(&mut self) {
+        self.metrics.ctrl_queue_event_count.inc();
+        if let Err(err) = self.queues[CTRL_INDEX].read_kicks(&self.queue_evts[CTRL_INDEX]) {
+            error!("Failed to get control queue event: {:?}", err);
+            self.metrics.event_fails.inc();
+        } else {
+            self.process_ctrl()
+                .unwrap_or_else(|err| report_net_event_fail(&self.metrics, err));
+        }
+    }
+
     pub fn process_rx_rate_limiter_event(&mut self) {
         self.metrics.rx_event_rate_limiter_count.inc();
         // Upon rate limiter event, call the rate limiter handler
@@ -787,10 +1479,45 @@ impl Net {
         }
     }
 
+    /// Process the expiration of the RX interrupt coalescing timer.
+    pub fn process_rx_coalescing_event(&mut self) {
+        match self
+            .rx_coalescer
+            .event_handler(self.queues[RX_INDEX].next_used)
+        {
+            Ok(true) => self
+                .kick_used_queue(NetQueue::Rx)
+                .unwrap_or_else(|err| report_net_event_fail(&self.metrics, err)),
+            Ok(false) => (),
+            Err(err) => {
+                error!("Failed to get rx interrupt coalescing event: {:?}", err);
+                self.metrics.event_fails.inc();
+            }
+        }
+    }
+
+    /// Process the expiration of the TX interrupt coalescing timer.
+    pub fn process_tx_coalescing_event(&mut self) {
+        match self
+            .tx_coalescer
+            .event_handler(self.queues[TX_INDEX].next_used)
+        {
+            Ok(true) => self
+                .kick_used_queue(NetQueue::Tx)
+                .unwrap_or_else(|err| report_net_event_fail(&self.metrics, err)),
+            Ok(false) => (),
+            Err(err) => {
+                error!("Failed to get tx interrupt coalescing event: {:?}", err);
+                self.metrics.event_fails.inc();
+            }
+        }
+    }
+
     /// Process device virtio queue(s).
     pub fn process_virtio_queues(&mut self) {
         let _ = self.resume_rx();
         let _ = self.process_tx();
+        let _ = self.process_ctrl();
     }
 }
 
@@ -799,6 +1526,10 @@ impl VirtioDevice for Net {
         self.avail_features
     }
 
+    fn set_avail_features(&mut self, avail_features: u64) {
+        self.avail_features = avail_features;
+    }
+
     fn acked_features(&self) -> u64 {
         self.acked_features
     }
@@ -831,6 +1562,10 @@ impl VirtioDevice for Net {
         self.irq_trigger.irq_status.clone()
     }
 
+    fn needs_reset(&self) -> bool {
+        self.irq_trigger.needs_reset()
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         if let Some(config_space_bytes) = self.config_space.as_slice().get(u64_to_usize(offset)..) {
             let len = config_space_bytes.len().min(data.len());
@@ -842,7 +1577,18 @@ impl VirtioDevice for Net {
     }
 
     fn write_config(&mut self, offset: u64, data: &[u8]) {
-        let config_space_bytes = self.config_space.as_mut_slice();
+        // The `mac` field is writable only when the device offers `VIRTIO_NET_F_MAC` and the
+        // driver cannot set the MAC through the control queue.
+        if self.avail_features & (1 << VIRTIO_NET_F_MAC) == 0
+            || self.has_feature(u64::from(VIRTIO_NET_F_CTRL_MAC_ADDR))
+        {
+            error!("Failed to write config space: the MAC address is read-only");
+            self.metrics.cfg_fails.inc();
+            return;
+        }
+
+        // The `status` field is read-only.
+        let config_space_bytes = &mut self.config_space.as_mut_slice()[..MAC_ADDR_LEN as usize];
         let start = usize::try_from(offset).ok();
         let end = start.and_then(|s| s.checked_add(data.len()));
         let Some(dst) = start
@@ -856,10 +1602,33 @@ impl VirtioDevice for Net {
 
         dst.copy_from_slice(data);
         self.guest_mac = Some(self.config_space.guest_mac);
+        self.peer_inbox.set_guest_mac(self.guest_mac);
         self.metrics.mac_address_updates.inc();
     }
 
     fn activate(&mut self, mem: GuestMemoryMmap) -> Result<(), ActivateError> {
+        if let Some(worker) = self.worker.as_mut() {
+            worker
+                .vu_handle
+                .set_features(
+                    self.acked_features | VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits(),
+                )
+                .map_err(ActivateError::VhostUser)?;
+            worker
+                .vu_handle
+                .setup_backend(
+                    &mem,
+                    &[
+                        (RX_INDEX, &self.queues[RX_INDEX], &self.queue_evts[RX_INDEX]),
+                        (TX_INDEX, &self.queues[TX_INDEX], &self.queue_evts[TX_INDEX]),
+                    ],
+                    &self.irq_trigger,
+                )
+                .map_err(ActivateError::VhostUser)?;
+            self.device_state = DeviceState::Activated(mem);
+            return Ok(());
+        }
+
         let event_idx = self.has_feature(u64::from(VIRTIO_RING_F_EVENT_IDX));
         if event_idx {
             for queue in &mut self.queues {
@@ -872,6 +1641,7 @@ impl VirtioDevice for Net {
             return Err(super::super::ActivateError::BadActivate);
         }
         self.device_state = DeviceState::Activated(mem);
+        self.arm_replay_timer();
         Ok(())
     }
 
@@ -895,9 +1665,11 @@ pub mod tests {
     use crate::check_metric_after_block;
     use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
     use crate::devices::virtio::iovec::IoVecBuffer;
+    use crate::devices::virtio::net::ctrl::{VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_PROMISC};
     use crate::devices::virtio::net::device::{
         frame_bytes_from_buf, frame_bytes_from_buf_mut, frame_hdr_len, init_vnet_hdr, vnet_hdr_len,
     };
+    use crate::devices::virtio::net::peer::PEER_INBOX_MAX_FRAMES;
     use crate::devices::virtio::net::test_utils::test::TestHelper;
     use crate::devices::virtio::net::test_utils::{
         default_net, if_index, inject_tap_tx_frame, set_mac, NetEvent, NetQueue, ReadTapMock,
@@ -923,13 +1695,23 @@ pub mod tests {
                     io::ErrorKind::Other,
                     "Read tap synthetically failed.",
                 )),
-                ReadTapMock::TapFrame => self.tap.read(&mut self.rx_frame_buf),
+                ReadTapMock::TapFrame => match self.xdp.as_mut() {
+                    Some(xdp) => xdp.read(&mut self.rx_frame_buf),
+                    None => self.tap.read(&mut self.rx_frame_buf),
+                },
             }
         }
 
-        pub(crate) fn write_tap(tap: &mut Tap, buf: &IoVecBuffer) -> io::Result<usize> {
+        pub(crate) fn write_tap(
+            tap: &mut Tap,
+            xdp: Option<&mut XdpSocket>,
+            buf: &IoVecBuffer,
+        ) -> io::Result<usize> {
             match tap.mocks.write_tap {
-                WriteTapMock::Success => tap.write_iovec(buf),
+                WriteTapMock::Success => match xdp {
+                    Some(xdp) if !tap.write_fault => xdp.write_iovec(buf),
+                    _ => tap.write_iovec(buf),
+                },
                 WriteTapMock::Failure => Err(io::Error::new(
                     io::ErrorKind::Other,
                     "Write tap mock failure.",
@@ -985,6 +1767,11 @@ pub mod tests {
             | 1 << VIRTIO_NET_F_GUEST_UFO
             | 1 << VIRTIO_NET_F_HOST_TSO4
             | 1 << VIRTIO_NET_F_HOST_UFO
+            | 1 << VIRTIO_NET_F_CTRL_VQ
+            | 1 << VIRTIO_NET_F_CTRL_RX
+            | 1 << VIRTIO_NET_F_CTRL_VLAN
+            | 1 << VIRTIO_NET_F_CTRL_MAC_ADDR
+            | 1 << VIRTIO_NET_F_STATUS
             | 1 << VIRTIO_F_VERSION_1
             | 1 << VIRTIO_RING_F_EVENT_IDX;
 
@@ -1015,9 +1802,14 @@ pub mod tests {
         net.read_config(0, &mut config_mac);
         assert_eq!(&config_mac, mac.get_bytes());
 
+        // The link is announced as up.
+        let mut status = [0u8; 2];
+        net.read_config(u64::from(MAC_ADDR_LEN), &mut status);
+        assert_eq!(u16::from_le_bytes(status), VIRTIO_NET_S_LINK_UP);
+
         // Invalid read.
         config_mac = [0u8; MAC_ADDR_LEN as usize];
-        net.read_config(u64::from(MAC_ADDR_LEN), &mut config_mac);
+        net.read_config(std::mem::size_of::<ConfigSpace>() as u64, &mut config_mac);
         assert_eq!(config_mac, [0u8, 0u8, 0u8, 0u8, 0u8, 0u8]);
     }
 
@@ -1052,12 +1844,197 @@ pub mod tests {
         net.read_config(0, &mut new_config_read);
         assert_eq!(new_config, new_config_read);
 
+        // The link status is read-only.
+        net.write_config(u64::from(MAC_ADDR_LEN), &[0, 0]);
+        assert!(net.link_up());
+
         // Large offset that may cause an overflow.
         net.write_config(u64::MAX, &new_config);
         // Verify old config was untouched.
         new_config_read = [0u8; MAC_ADDR_LEN as usize];
         net.read_config(0, &mut new_config_read);
         assert_eq!(new_config, new_config_read);
+
+        // Drivers that negotiated `VIRTIO_NET_F_CTRL_MAC_ADDR` set the MAC through the control
+        // queue, so the config space is read-only.
+        net.acked_features = 1 << VIRTIO_NET_F_CTRL_MAC_ADDR;
+        net.write_config(0, &[0x66, 0x55, 0x44, 0x33, 0x22, 0x11]);
+        net.read_config(0, &mut new_config_read);
+        assert_eq!(new_config, new_config_read);
+        assert_eq!(net.guest_mac.unwrap().get_bytes(), &new_config);
+
+        // Devices created without a MAC do not offer `VIRTIO_NET_F_MAC`.
+        let mut net = Net::new(
+            "net-no-mac-config".to_string(),
+            "net-device%d",
+            None,
+            RateLimiter::default(),
+            RateLimiter::default(),
+        )
+        .unwrap();
+        net.write_config(0, &new_config);
+        assert!(net.guest_mac().is_none());
+        net.read_config(0, &mut new_config_read);
+        assert_eq!(new_config_read, [0u8; MAC_ADDR_LEN as usize]);
+    }
+
+    #[test]
+    fn test_set_guest_mac() {
+        let mut th = TestHelper::get_default();
+        let mac = MacAddr::from_str("11:22:33:44:55:66").unwrap();
+
+        // Before activation, only the config space is updated.
+        th.net().set_guest_mac(mac).unwrap();
+        let mut config_mac = [0u8; MAC_ADDR_LEN as usize];
+        th.net().read_config(0, &mut config_mac);
+        assert_eq!(&config_mac, mac.get_bytes());
+        assert_eq!(th.net().guest_mac(), Some(&mac));
+        assert!(!th.net().irq_trigger.has_pending_irq(IrqType::Config));
+
+        // Once activated, the guest is notified of the change.
+        th.activate_net();
+        let mac = MacAddr::from_str("66:55:44:33:22:11").unwrap();
+        th.net().set_guest_mac(mac).unwrap();
+        th.net().read_config(0, &mut config_mac);
+        assert_eq!(&config_mac, mac.get_bytes());
+        assert!(th.net().irq_trigger.has_pending_irq(IrqType::Config));
+        assert_eq!(th.net().metrics.mac_address_updates.count(), 2);
+
+        // Devices created without a MAC do not offer `VIRTIO_NET_F_MAC`.
+        let mut net = Net::new(
+            "net-no-mac".to_string(),
+            "net-device%d",
+            None,
+            RateLimiter::default(),
+            RateLimiter::default(),
+        )
+        .unwrap();
+        assert!(matches!(
+            net.set_guest_mac(mac),
+            Err(NetError::GuestMacNotConfigured)
+        ));
+        assert!(net.guest_mac().is_none());
+    }
+
+    #[test]
+    fn test_set_link_up() {
+        let mut th = TestHelper::get_default();
+        assert!(th.net().link_up());
+
+        // Before activation, only the config space is updated.
+        th.net().set_link_up(false).unwrap();
+        let mut status = [0u8; 2];
+        th.net().read_config(u64::from(MAC_ADDR_LEN), &mut status);
+        assert_eq!(u16::from_le_bytes(status), 0);
+        assert!(!th.net().link_up());
+        assert!(!th.net().irq_trigger.has_pending_irq(IrqType::Config));
+
+        // Once activated, the guest is notified of the change.
+        th.activate_net();
+        th.net().set_link_up(true).unwrap();
+        th.net().read_config(u64::from(MAC_ADDR_LEN), &mut status);
+        assert_eq!(u16::from_le_bytes(status), VIRTIO_NET_S_LINK_UP);
+        assert!(th.net().irq_trigger.has_pending_irq(IrqType::Config));
+        assert_eq!(th.net().metrics.link_state_updates.count(), 2);
+
+        // Setting the current state again is a no-op.
+        th.net().set_link_up(true).unwrap();
+        assert_eq!(th.net().metrics.link_state_updates.count(), 2);
+    }
+
+    #[test]
+    fn test_ctrl_queue() {
+        let mut th = TestHelper::get_default();
+        th.net()
+            .set_acked_features(1 << VIRTIO_NET_F_CTRL_VQ | 1 << VIRTIO_NET_F_CTRL_RX);
+        th.activate_net();
+
+        // Disable promiscuous mode.
+        th.add_desc_chain(
+            NetQueue::Ctrl,
+            0,
+            &[(0, 2, 0), (1, 1, 0), (2, 1, VIRTQ_DESC_F_WRITE)],
+        );
+        th.ctrlq.dtable[0].set_data(&[VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_PROMISC]);
+        th.ctrlq.dtable[1].set_data(&[0]);
+        th.ctrlq.dtable[2].set_data(&[0xff]);
+        th.simulate_event(NetEvent::CtrlQueue);
+        th.ctrlq.check_used_elem(0, 0, 1);
+        th.ctrlq.dtable[2].check_data(&[VIRTIO_NET_OK]);
+        assert!(!th.net().rx_filter.promisc);
+        assert!(th.net().irq_trigger.has_pending_irq(IrqType::Vring));
+
+        // Unsupported commands are rejected.
+        th.add_desc_chain(
+            NetQueue::Ctrl,
+            100,
+            &[(3, 2, 0), (4, 1, VIRTQ_DESC_F_WRITE)],
+        );
+        th.ctrlq.dtable[3].set_data(&[VIRTIO_NET_CTRL_RX, 5]);
+        th.simulate_event(NetEvent::CtrlQueue);
+        th.ctrlq.check_used_elem(1, 3, 1);
+        th.ctrlq.dtable[4].check_data(&[VIRTIO_NET_ERR]);
+        assert_eq!(th.net().metrics.ctrl_fails.count(), 1);
+
+        // Set the MAC address.
+        let mac = MacAddr::from_str("06:00:00:00:00:05").unwrap();
+        th.add_desc_chain(
+            NetQueue::Ctrl,
+            200,
+            &[(5, 2, 0), (6, 6, 0), (7, 1, VIRTQ_DESC_F_WRITE)],
+        );
+        th.ctrlq.dtable[5].set_data(&[VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_ADDR_SET]);
+        th.ctrlq.dtable[6].set_data(mac.get_bytes());
+        th.simulate_event(NetEvent::CtrlQueue);
+        th.ctrlq.check_used_elem(2, 5, 1);
+        th.ctrlq.dtable[7].check_data(&[VIRTIO_NET_OK]);
+        assert_eq!(th.net().guest_mac(), Some(&mac));
+        let mut config_mac = [0u8; MAC_ADDR_LEN as usize];
+        th.net().read_config(0, &mut config_mac);
+        assert_eq!(&config_mac, mac.get_bytes());
+
+        // Chains without a status descriptor are discarded.
+        th.add_desc_chain(NetQueue::Ctrl, 300, &[(8, 2, 0)]);
+        th.simulate_event(NetEvent::CtrlQueue);
+        th.ctrlq.check_used_elem(3, 8, 0);
+        assert_eq!(th.net().metrics.ctrl_fails.count(), 2);
+    }
+
+    #[test]
+    fn test_rx_filter() {
+        let mut net = default_net();
+        let guest_mac = MacAddr::from_str("06:00:00:00:00:02").unwrap();
+        set_mac(&mut net, guest_mac);
+        let other_mac = MacAddr::from_str("06:00:00:00:00:09").unwrap();
+        let set_frame = |net: &mut Net, dst_mac: &MacAddr| {
+            let frame = frame_bytes_from_buf_mut(&mut net.rx_frame_buf).unwrap();
+            frame[..MAC_ADDR_LEN as usize].copy_from_slice(dst_mac.get_bytes());
+            frame[MAC_ADDR_LEN as usize..2 * MAC_ADDR_LEN as usize]
+                .copy_from_slice(other_mac.get_bytes());
+            frame[12..14].copy_from_slice(&[0x08, 0x00]);
+            net.rx_bytes_read = vnet_hdr_len() + 64;
+        };
+        net.rx_filter.promisc = false;
+
+        // Filters are only enforced once negotiated.
+        set_frame(&mut net, &other_mac);
+        assert!(net.rx_filter_accepts());
+
+        net.set_acked_features(1 << VIRTIO_NET_F_CTRL_VQ | 1 << VIRTIO_NET_F_CTRL_RX);
+        assert!(!net.rx_filter_accepts());
+        set_frame(&mut net, &guest_mac);
+        assert!(net.rx_filter_accepts());
+
+        // VLAN filtering is only enforced once negotiated.
+        let frame = frame_bytes_from_buf_mut(&mut net.rx_frame_buf).unwrap();
+        frame[12..18].copy_from_slice(&[0x81, 0x00, 0x00, 0x64, 0x08, 0x00]);
+        assert!(net.rx_filter_accepts());
+        net.set_acked_features(
+            1 << VIRTIO_NET_F_CTRL_VQ | 1 << VIRTIO_NET_F_CTRL_RX | 1 << VIRTIO_NET_F_CTRL_VLAN,
+        );
+        assert!(!net.rx_filter_accepts());
+        net.rx_filter.vlans.insert(100);
+        assert!(net.rx_filter_accepts());
     }
 
     #[test]
@@ -1251,9 +2228,11 @@ pub mod tests {
 
         // Check that the frames weren't deferred.
         assert!(!th.net().rx_deferred_frame);
-        // Check that the used queue has advanced.
+        // Check that the used queue has advanced, and that both frames were published with a
+        // single interrupt.
+        assert!(th.net().rx_used.is_empty());
         assert_eq!(th.rxq.used.idx.get(), 2);
-        assert!(&th.net().irq_trigger.has_pending_irq(IrqType::Vring));
+        assert_eq!(th.net().irq_trigger.irq_evt.read().unwrap(), 1);
         // Check that the 1st frame was written successfully to the 1st Rx descriptor chain.
         th.rxq
             .check_used_elem(0, 0, frame_1.len().try_into().unwrap());
@@ -1314,9 +2293,13 @@ pub mod tests {
         // Send an invalid frame (too small, VNET header missing).
         th.add_desc_chain(NetQueue::Tx, 0, &[(0, 1, 0)]);
         check_metric_after_block!(
-            th.net().metrics.tx_malformed_frames,
+            th.net().metrics.tx_dropped_malformed,
             1,
-            th.event_manager.run_with_timeout(100)
+            check_metric_after_block!(
+                th.net().metrics.tx_malformed_frames,
+                1,
+                th.event_manager.run_with_timeout(100)
+            )
         );
 
         // Check that the used queue advanced.
@@ -1375,6 +2358,35 @@ pub mod tests {
         assert!(!tap_traffic_simulator.pop_rx_packet(&mut []));
     }
 
+    #[test]
+    fn test_tx_malformed_chain() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+
+        // Send a frame pointing outside of the guest memory.
+        let out_of_range = th.mem.last_addr().raw_value() + 1 - th.data_addr();
+        th.add_desc_chain(NetQueue::Tx, out_of_range, &[(0, 100, 0)]);
+        check_metric_after_block!(
+            th.net().metrics.tx_dropped_malformed,
+            1,
+            th.event_manager.run_with_timeout(100)
+        );
+
+        // Check that the device failed, without using the descriptor chain.
+        assert!(th.net().needs_reset());
+        assert!(&th.net().irq_trigger.has_pending_irq(IrqType::Config));
+        assert_eq!(th.txq.used.idx.get(), 0);
+
+        // The device ignores the frames until the driver resets it.
+        let desc_list = [(1, 1000, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        th.write_tx_frame(&desc_list, 1000);
+        th.event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(th.txq.used.idx.get(), 0);
+        assert!(!tap_traffic_simulator.pop_rx_packet(&mut [0; 1000]));
+    }
+
     #[test]
     fn test_tx_retry() {
         let mut th = TestHelper::get_default();
@@ -1387,10 +2399,8 @@ pub mod tests {
             0,
             &[(0, 100, 0), (1, 100, VIRTQ_DESC_F_WRITE), (2, 500, 0)],
         );
-        // Add invalid descriptor chain - invalid memory.
-        th.add_desc_chain(NetQueue::Tx, th.mem.last_addr().raw_value(), &[(3, 100, 0)]);
         // Add invalid descriptor chain - too short.
-        th.add_desc_chain(NetQueue::Tx, 700, &[(0, 1, 0)]);
+        th.add_desc_chain(NetQueue::Tx, 700, &[(3, 1, 0)]);
 
         // Add valid descriptor chain
         let desc_list = [(4, 1000, 0)];
@@ -1398,17 +2408,17 @@ pub mod tests {
         let frame = th.write_tx_frame(&desc_list, 1000);
 
         // One frame is valid, one will not be handled because it includes write-only memory
-        // so that leaves us with 2 malformed (no vnet header) frames.
+        // so that leaves us with 1 malformed (no vnet header) frame.
         check_metric_after_block!(
             th.net().metrics.tx_malformed_frames,
-            2,
+            1,
             th.event_manager.run_with_timeout(100)
         );
 
         // Check that the used queue advanced.
-        assert_eq!(th.txq.used.idx.get(), 4);
+        assert_eq!(th.txq.used.idx.get(), 3);
         assert!(&th.net().irq_trigger.has_pending_irq(IrqType::Vring));
-        th.txq.check_used_elem(3, 4, 0);
+        th.txq.check_used_elem(2, 4, 0);
         // Check that the valid frame was sent to the tap.
         let mut buf = vec![0; 1000];
         assert!(tap_traffic_simulator.pop_rx_packet(&mut buf[vnet_hdr_len()..]));
@@ -1467,6 +2477,27 @@ pub mod tests {
         th.txq.check_used_elem(0, 0, 0);
     }
 
+    #[test]
+    fn test_tso_accounting() {
+        let tso4 = SharedIncMetric::default();
+        let tso6 = SharedIncMetric::default();
+        let mut frame_buf = vec![0u8; vnet_hdr_len() + 100];
+
+        // Frames without a complete VNET header or not requesting TSO are not accounted.
+        account_tso_frame(&frame_buf[..1], &tso4, &tso6);
+        account_tso_frame(&frame_buf, &tso4, &tso6);
+        frame_buf[VNET_HDR_GSO_TYPE_OFFSET] = 3; // VIRTIO_NET_HDR_GSO_UDP
+        account_tso_frame(&frame_buf, &tso4, &tso6);
+        assert_eq!((tso4.count(), tso6.count()), (0, 0));
+
+        frame_buf[VNET_HDR_GSO_TYPE_OFFSET] = u8::try_from(VIRTIO_NET_HDR_GSO_TCPV4).unwrap();
+        account_tso_frame(&frame_buf, &tso4, &tso6);
+        frame_buf[VNET_HDR_GSO_TYPE_OFFSET] =
+            u8::try_from(VIRTIO_NET_HDR_GSO_TCPV6 | VIRTIO_NET_HDR_GSO_ECN).unwrap();
+        account_tso_frame(&frame_buf, &tso4, &tso6);
+        assert_eq!((tso4.count(), tso6.count()), (1, 1));
+    }
+
     #[test]
     fn test_tx_multiple_frame() {
         let mut th = TestHelper::get_default();
@@ -1503,6 +2534,63 @@ pub mod tests {
         assert_eq!(&buf[..600], &frame_2[..600]);
     }
 
+    #[test]
+    fn test_tx_interrupt_coalescing() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        th.net().set_interrupt_coalescing(
+            None,
+            Some(InterruptCoalescingConfig {
+                max_frames: 2,
+                max_usecs: 10_000_000,
+            }),
+        );
+
+        // The driver is not signaled until two descriptors are used.
+        th.add_desc_chain(NetQueue::Tx, 0, &[(0, 0, 0)]);
+        th.event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(th.txq.used.idx.get(), 1);
+        assert!(!th.net().irq_trigger.has_pending_irq(IrqType::Vring));
+        th.add_desc_chain(NetQueue::Tx, 0, &[(1, 0, 0)]);
+        th.event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(th.txq.used.idx.get(), 2);
+        assert!(th.net().irq_trigger.has_pending_irq(IrqType::Vring));
+
+        // The driver is signaled once the timer expires.
+        th.net().set_interrupt_coalescing(
+            None,
+            Some(InterruptCoalescingConfig {
+                max_frames: 0,
+                max_usecs: 1000,
+            }),
+        );
+        th.add_desc_chain(NetQueue::Tx, 0, &[(2, 0, 0)]);
+        th.event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(th.txq.used.idx.get(), 3);
+        assert!(!th.net().irq_trigger.has_pending_irq(IrqType::Vring));
+        th.event_manager.run_with_timeout(100).unwrap();
+        assert!(th.net().irq_trigger.has_pending_irq(IrqType::Vring));
+
+        // Disabling the coalescing signals the pending used descriptors right away.
+        th.net().set_interrupt_coalescing(
+            None,
+            Some(InterruptCoalescingConfig {
+                max_frames: 0,
+                max_usecs: 10_000_000,
+            }),
+        );
+        th.add_desc_chain(NetQueue::Tx, 0, &[(3, 0, 0)]);
+        th.event_manager.run_with_timeout(100).unwrap();
+        assert!(!th.net().irq_trigger.has_pending_irq(IrqType::Vring));
+        th.net()
+            .set_interrupt_coalescing(None, Some(InterruptCoalescingConfig::default()));
+        assert!(th.net().irq_trigger.has_pending_irq(IrqType::Vring));
+        assert_eq!(
+            th.net().tx_interrupt_coalescing(),
+            InterruptCoalescingConfig::default()
+        );
+    }
+
     fn create_arp_request(
         src_mac: MacAddr,
         src_ip: Ipv4Addr,
@@ -1553,18 +2641,26 @@ pub mod tests {
         // Call the code which sends the packet to the host or MMDS.
         // Validate the frame was consumed by MMDS and that the metrics reflect that.
         check_metric_after_block!(
-            &METRICS.mmds.rx_accepted,
+            net.metrics.tx_mmds_intercepted_frames,
             1,
-            assert!(Net::write_to_mmds_or_tap(
-                net.mmds_ns.as_mut(),
-                &mut net.tx_rate_limiter,
-                &mut headers,
-                &buffer,
-                &mut net.tap,
-                Some(src_mac),
-                &net.metrics,
+            check_metric_after_block!(
+                &METRICS.mmds.rx_accepted,
+                1,
+                assert!(Net::write_to_mmds_or_tap(
+                    net.mmds_ns.as_mut(),
+                    None,
+                    None,
+                    &mut net.tx_rate_limiter,
+                    &mut headers,
+                    &buffer,
+                    &mut net.tap,
+                    None,
+                    None,
+                    Some(src_mac),
+                    &net.metrics,
+                )
+                .unwrap())
             )
-            .unwrap())
         );
 
         // Validate that MMDS has a response and we can retrieve it.
@@ -1595,10 +2691,14 @@ pub mod tests {
             0,
             Net::write_to_mmds_or_tap(
                 net.mmds_ns.as_mut(),
+                None,
+                None,
                 &mut net.tx_rate_limiter,
                 &mut headers,
                 &buffer,
                 &mut net.tap,
+                None,
+                None,
                 Some(guest_mac),
                 &net.metrics,
             )
@@ -1610,16 +2710,85 @@ pub mod tests {
             1,
             Net::write_to_mmds_or_tap(
                 net.mmds_ns.as_mut(),
+                None,
+                None,
                 &mut net.tx_rate_limiter,
                 &mut headers,
                 &buffer,
                 &mut net.tap,
+                None,
+                None,
                 Some(not_guest_mac),
                 &net.metrics,
             )
         );
     }
 
+    #[test]
+    fn test_write_to_peer() {
+        let mut net = default_net();
+
+        let guest_mac = MacAddr::from_str("06:00:00:00:00:01").unwrap();
+        let guest_ip = Ipv4Addr::new(10, 1, 2, 3);
+        let peer_mac = MacAddr::from_str("06:00:00:00:00:02").unwrap();
+        let peer_ip = Ipv4Addr::new(10, 1, 2, 4);
+        let peer = PeerInbox::new(Some(peer_mac)).unwrap();
+
+        // Frames addressed to the peer MAC are moved to its inbox, virtio-net header included.
+        let (frame_buf, frame_len) = create_arp_request(guest_mac, guest_ip, peer_mac, peer_ip);
+        let buffer = IoVecBuffer::from(&frame_buf[..frame_len]);
+        let mut headers = vec![0; frame_hdr_len()];
+        check_metric_after_block!(
+            net.metrics.tx_packets_count,
+            1,
+            assert!(!Net::write_to_mmds_or_tap(
+                net.mmds_ns.as_mut(),
+                None,
+                None,
+                &mut net.tx_rate_limiter,
+                &mut headers,
+                &buffer,
+                &mut net.tap,
+                None,
+                Some(&peer),
+                Some(guest_mac),
+                &net.metrics,
+            )
+            .unwrap())
+        );
+        let mut rx_buf = [0u8; MAX_BUFFER_SIZE];
+        assert_eq!(peer.pop(&mut rx_buf), Some(frame_len));
+        assert_eq!(rx_buf[..frame_len], frame_buf[..frame_len]);
+
+        // Frames dropped when the inbox is full are accounted for.
+        for _ in 0..PEER_INBOX_MAX_FRAMES {
+            assert!(peer.push(vec![0]));
+        }
+        check_metric_after_block!(
+            net.metrics.tx_peer_dropped_frames,
+            1,
+            Net::write_to_mmds_or_tap(
+                net.mmds_ns.as_mut(),
+                None,
+                None,
+                &mut net.tx_rate_limiter,
+                &mut headers,
+                &buffer,
+                &mut net.tap,
+                None,
+                Some(&peer),
+                Some(guest_mac),
+                &net.metrics,
+            )
+            .unwrap()
+        );
+
+        // Frames received from the peer are read before the ones on the tap.
+        net.peer_inbox.push(frame_buf[..frame_len].to_vec());
+        assert_eq!(net.read_from_mmds_or_tap().unwrap(), frame_len);
+        assert_eq!(net.rx_frame_buf[..frame_len], frame_buf[..frame_len]);
+    }
+
     #[test]
     fn test_process_error_cases() {
         let mut th = TestHelper::get_default();
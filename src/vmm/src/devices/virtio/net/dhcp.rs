@@ -0,0 +1,612 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Answers the DHCP requests of the guest with a static configuration.
+//!
+//! The DHCP frames sent by the guest are intercepted by the network device, like the MMDS
+//! frames, so that the guest interface can be configured by a regular DHCP client without any
+//! DHCP server on the host. Only the messages needed to lease the configured address are
+//! implemented, and the lease never expires.
+
+use std::net::Ipv4Addr;
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
+
+use crate::dumbo::pdu::ethernet::{EthernetFrame, ETHERTYPE_IPV4, PAYLOAD_OFFSET};
+use crate::dumbo::pdu::ipv4::{IPv4Packet, PROTOCOL_UDP};
+use crate::dumbo::pdu::udp::UdpDatagram;
+
+/// UDP port of DHCP servers.
+pub const DHCP_SERVER_PORT: u16 = 67;
+/// UDP port of DHCP clients.
+pub const DHCP_CLIENT_PORT: u16 = 68;
+/// Maximum number of DNS servers handed to the guest.
+pub const MAX_NAMESERVERS: usize = 8;
+
+// Source MAC address of the replies.
+const SERVER_MAC_ADDR: &str = "06:01:23:45:67:02";
+// Server identifier of the replies when no gateway is configured.
+const DEFAULT_SERVER_ADDR: Ipv4Addr = Ipv4Addr::new(169, 254, 169, 253);
+
+// Offsets of the fields of BOOTP messages, from https://www.rfc-editor.org/rfc/rfc2131#section-2
+const OP_OFFSET: usize = 0;
+const HTYPE_OFFSET: usize = 1;
+const HLEN_OFFSET: usize = 2;
+const XID_OFFSET: usize = 4;
+const FLAGS_OFFSET: usize = 10;
+const CIADDR_OFFSET: usize = 12;
+const YIADDR_OFFSET: usize = 16;
+const CHADDR_OFFSET: usize = 28;
+const CHADDR_LEN: usize = MAC_ADDR_LEN as usize;
+const MAGIC_COOKIE_OFFSET: usize = 236;
+const OPTIONS_OFFSET: usize = 240;
+// Replies are padded to the minimum size of BOOTP messages.
+const MIN_MESSAGE_SIZE: usize = 300;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+// Flag asking for the replies to be broadcast.
+const FLAG_BROADCAST: u16 = 0x8000;
+
+// Options, from https://www.rfc-editor.org/rfc/rfc2132
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_ADDR: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+// Lease time of the address, which never expires.
+const INFINITE_LEASE: u32 = u32::MAX;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+
+/// Network configuration handed to the guest through DHCP.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DhcpConfig {
+    /// IPv4 address leased to the guest.
+    pub ip_address: Ipv4Addr,
+    /// Length of the prefix of the network of the guest.
+    pub prefix_length: u8,
+    /// Default gateway of the guest.
+    #[serde(default)]
+    pub gateway: Option<Ipv4Addr>,
+    /// DNS servers of the guest.
+    #[serde(default)]
+    pub nameservers: Vec<Ipv4Addr>,
+}
+
+/// Errors of the DHCP configuration.
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum DhcpConfigError {
+    /// Invalid IPv4 address leased to the guest: {0}
+    InvalidAddress(Ipv4Addr),
+    /// Invalid prefix length: {0}
+    InvalidPrefixLength(u8),
+    /// Too many DNS servers, the maximum is 8.
+    TooManyNameservers,
+}
+
+impl DhcpConfig {
+    /// Checks that the configuration can be handed to the guest.
+    pub fn validate(&self) -> Result<(), DhcpConfigError> {
+        let addr = self.ip_address;
+        if addr.is_unspecified() || addr.is_broadcast() || addr.is_multicast() {
+            return Err(DhcpConfigError::InvalidAddress(addr));
+        }
+        if self.prefix_length > 32 {
+            return Err(DhcpConfigError::InvalidPrefixLength(self.prefix_length));
+        }
+        if self.nameservers.len() > MAX_NAMESERVERS {
+            return Err(DhcpConfigError::TooManyNameservers);
+        }
+        Ok(())
+    }
+
+    fn subnet_mask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(
+            u32::MAX
+                .checked_shl(32 - u32::from(self.prefix_length))
+                .unwrap_or(0),
+        )
+    }
+
+    fn server_addr(&self) -> Ipv4Addr {
+        self.gateway.unwrap_or(DEFAULT_SERVER_ADDR)
+    }
+}
+
+// Reply to the last request of the guest, which has not been sent yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PendingReply {
+    message_type: u8,
+    xid: [u8; 4],
+    flags: u16,
+    ciaddr: Ipv4Addr,
+    chaddr: MacAddr,
+}
+
+/// DHCP responder of a network device.
+#[derive(Debug)]
+pub struct DhcpServer {
+    config: DhcpConfig,
+    mac_addr: MacAddr,
+    pending_reply: Option<PendingReply>,
+}
+
+impl DhcpServer {
+    /// Creates a responder handing `config` to the guest.
+    pub fn new(config: DhcpConfig) -> Self {
+        DhcpServer {
+            config,
+            mac_addr: MacAddr::from_str(SERVER_MAC_ADDR).unwrap(),
+            pending_reply: None,
+        }
+    }
+
+    /// Returns the configuration handed to the guest.
+    pub fn config(&self) -> &DhcpConfig {
+        &self.config
+    }
+
+    /// Returns whether `src` is an IPv4 frame sent to the DHCP server port. It does not consume
+    /// the frame, and only needs its headers.
+    pub fn is_dhcp_frame(src: &[u8]) -> bool {
+        let Ok(eth) = EthernetFrame::from_bytes(src) else {
+            return false;
+        };
+        if eth.ethertype() != ETHERTYPE_IPV4 {
+            return false;
+        }
+        let packet = &src[PAYLOAD_OFFSET..];
+        let (Some(version_and_ihl), Some(&protocol)) = (packet.first(), packet.get(9)) else {
+            return false;
+        };
+        let header_len = usize::from(version_and_ihl & 0xf) * 4;
+        protocol == PROTOCOL_UDP
+            && packet.get(header_len + 2..header_len + 4)
+                == Some(DHCP_SERVER_PORT.to_be_bytes().as_slice())
+    }
+
+    /// Handles a frame for which `is_dhcp_frame` returned true, and prepares the reply to send
+    /// to the guest.
+    ///
+    /// Returns whether the frame holds a request the server answers.
+    pub fn detour_frame(&mut self, src: &[u8]) -> bool {
+        let Ok(eth) = EthernetFrame::from_bytes(src) else {
+            return false;
+        };
+        let Ok(packet) = IPv4Packet::from_bytes(eth.payload(), false) else {
+            return false;
+        };
+        // The guest may offload the computation of the checksum.
+        let Ok(datagram) = UdpDatagram::from_bytes(packet.payload(), None) else {
+            return false;
+        };
+        self.pending_reply = self.handle_message(datagram.payload());
+        self.pending_reply.is_some()
+    }
+
+    fn handle_message(&self, message: &[u8]) -> Option<PendingReply> {
+        if message.len() < OPTIONS_OFFSET
+            || message[OP_OFFSET] != BOOTREQUEST
+            || message[HTYPE_OFFSET] != HTYPE_ETHERNET
+            || message[HLEN_OFFSET] != MAC_ADDR_LEN
+            || message[MAGIC_COOKIE_OFFSET..OPTIONS_OFFSET] != MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        let mut message_type = None;
+        let mut requested_addr = None;
+        let mut server_id = None;
+        let mut options = &message[OPTIONS_OFFSET..];
+        while let Some((&code, rest)) = options.split_first() {
+            match code {
+                OPTION_PAD => {
+                    options = rest;
+                    continue;
+                }
+                OPTION_END => break,
+                _ => (),
+            }
+            let (&len, rest) = rest.split_first()?;
+            let value = rest.get(..usize::from(len))?;
+            match (code, value) {
+                (OPTION_MESSAGE_TYPE, &[value]) => message_type = Some(value),
+                (OPTION_REQUESTED_ADDR, &[a, b, c, d]) => {
+                    requested_addr = Some(Ipv4Addr::new(a, b, c, d))
+                }
+                (OPTION_SERVER_ID, &[a, b, c, d]) => server_id = Some(Ipv4Addr::new(a, b, c, d)),
+                _ => (),
+            }
+            options = &rest[usize::from(len)..];
+        }
+
+        let ciaddr = ipv4_addr_at(message, CIADDR_OFFSET);
+        let message_type = match message_type? {
+            DHCPDISCOVER => DHCPOFFER,
+            // The guest chose the offer of another server.
+            DHCPREQUEST if server_id.is_some_and(|id| id != self.config.server_addr()) => {
+                return None
+            }
+            DHCPREQUEST => {
+                let addr = requested_addr.unwrap_or(ciaddr);
+                if addr == self.config.ip_address {
+                    DHCPACK
+                } else {
+                    DHCPNAK
+                }
+            }
+            _ => return None,
+        };
+
+        Some(PendingReply {
+            message_type,
+            xid: message[XID_OFFSET..XID_OFFSET + 4].try_into().unwrap(),
+            flags: u16::from_be_bytes([message[FLAGS_OFFSET], message[FLAGS_OFFSET + 1]]),
+            ciaddr,
+            chaddr: MacAddr::from_bytes_unchecked(
+                &message[CHADDR_OFFSET..CHADDR_OFFSET + CHADDR_LEN],
+            ),
+        })
+    }
+
+    /// Writes the pending reply to the guest in `buf`, if any, and returns the length of the
+    /// frame.
+    pub fn write_next_frame(&mut self, buf: &mut [u8]) -> Option<NonZeroUsize> {
+        let reply = self.pending_reply.take()?;
+        let message = self.write_message(&reply);
+
+        // Replies are broadcast unless the guest already has an address.
+        let (dst_mac, dst_addr) = if reply.message_type != DHCPNAK
+            && reply.flags & FLAG_BROADCAST == 0
+            && !reply.ciaddr.is_unspecified()
+        {
+            (reply.chaddr, reply.ciaddr)
+        } else {
+            (
+                MacAddr::from_bytes_unchecked(&[0xff; CHADDR_LEN]),
+                Ipv4Addr::BROADCAST,
+            )
+        };
+        let src_addr = self.config.server_addr();
+
+        let mut eth =
+            EthernetFrame::write_incomplete(buf, dst_mac, self.mac_addr, ETHERTYPE_IPV4).ok()?;
+        let mut packet = IPv4Packet::write_header(
+            eth.inner_mut().payload_mut(),
+            PROTOCOL_UDP,
+            src_addr,
+            dst_addr,
+        )
+        .ok()?;
+        let datagram_len =
+            UdpDatagram::write_incomplete_datagram(packet.inner_mut().payload_mut(), &message)
+                .ok()?
+                .finalize(
+                    DHCP_SERVER_PORT,
+                    DHCP_CLIENT_PORT,
+                    Some((src_addr, dst_addr)),
+                )
+                .len();
+        let packet_len = packet.with_payload_len_unchecked(datagram_len, true).len();
+        NonZeroUsize::new(eth.with_payload_len_unchecked(packet_len).len())
+    }
+
+    fn write_message(&self, reply: &PendingReply) -> Vec<u8> {
+        let mut message = vec![0u8; OPTIONS_OFFSET];
+        message[OP_OFFSET] = BOOTREPLY;
+        message[HTYPE_OFFSET] = HTYPE_ETHERNET;
+        message[HLEN_OFFSET] = MAC_ADDR_LEN;
+        message[XID_OFFSET..XID_OFFSET + 4].copy_from_slice(&reply.xid);
+        message[FLAGS_OFFSET..FLAGS_OFFSET + 2].copy_from_slice(&reply.flags.to_be_bytes());
+        message[CIADDR_OFFSET..CIADDR_OFFSET + 4].copy_from_slice(&reply.ciaddr.octets());
+        if reply.message_type != DHCPNAK {
+            message[YIADDR_OFFSET..YIADDR_OFFSET + 4]
+                .copy_from_slice(&self.config.ip_address.octets());
+        }
+        message[CHADDR_OFFSET..CHADDR_OFFSET + CHADDR_LEN]
+            .copy_from_slice(reply.chaddr.get_bytes());
+        message[MAGIC_COOKIE_OFFSET..OPTIONS_OFFSET].copy_from_slice(&MAGIC_COOKIE);
+
+        let mut push_option = |code: u8, value: &[u8]| {
+            message.push(code);
+            message.push(u8::try_from(value.len()).unwrap());
+            message.extend_from_slice(value);
+        };
+        push_option(OPTION_MESSAGE_TYPE, &[reply.message_type]);
+        push_option(OPTION_SERVER_ID, &self.config.server_addr().octets());
+        if reply.message_type != DHCPNAK {
+            push_option(OPTION_LEASE_TIME, &INFINITE_LEASE.to_be_bytes());
+            push_option(OPTION_SUBNET_MASK, &self.config.subnet_mask().octets());
+            if let Some(gateway) = self.config.gateway {
+                push_option(OPTION_ROUTER, &gateway.octets());
+            }
+            if !self.config.nameservers.is_empty() {
+                let nameservers = self
+                    .config
+                    .nameservers
+                    .iter()
+                    .flat_map(Ipv4Addr::octets)
+                    .collect::<Vec<_>>();
+                push_option(OPTION_DNS, &nameservers);
+            }
+        }
+        message.push(OPTION_END);
+        if message.len() < MIN_MESSAGE_SIZE {
+            message.resize(MIN_MESSAGE_SIZE, OPTION_PAD);
+        }
+        message
+    }
+}
+
+fn ipv4_addr_at(message: &[u8], offset: usize) -> Ipv4Addr {
+    let octets: [u8; 4] = message[offset..offset + 4].try_into().unwrap();
+    Ipv4Addr::from(octets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUEST_MAC: &str = "12:34:56:78:9a:bc";
+
+    fn config() -> DhcpConfig {
+        DhcpConfig {
+            ip_address: Ipv4Addr::new(192, 168, 0, 2),
+            prefix_length: 24,
+            gateway: Some(Ipv4Addr::new(192, 168, 0, 1)),
+            nameservers: vec![Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(1, 1, 1, 1)],
+        }
+    }
+
+    // Writes a frame holding a request of the guest, with the given options after the message
+    // type, and returns its length.
+    fn write_request(buf: &mut [u8], message_type: u8, ciaddr: Ipv4Addr, options: &[u8]) -> usize {
+        let mut message = vec![0u8; OPTIONS_OFFSET];
+        message[OP_OFFSET] = BOOTREQUEST;
+        message[HTYPE_OFFSET] = HTYPE_ETHERNET;
+        message[HLEN_OFFSET] = 6;
+        message[XID_OFFSET..XID_OFFSET + 4].copy_from_slice(&[1, 2, 3, 4]);
+        message[CIADDR_OFFSET..CIADDR_OFFSET + 4].copy_from_slice(&ciaddr.octets());
+        let guest_mac = MacAddr::from_str(GUEST_MAC).unwrap();
+        message[CHADDR_OFFSET..CHADDR_OFFSET + 6].copy_from_slice(guest_mac.get_bytes());
+        message[MAGIC_COOKIE_OFFSET..OPTIONS_OFFSET].copy_from_slice(&MAGIC_COOKIE);
+        message.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, message_type, OPTION_PAD]);
+        message.extend_from_slice(options);
+        message.push(OPTION_END);
+
+        let mut eth = EthernetFrame::write_incomplete(
+            buf,
+            MacAddr::from_bytes_unchecked(&[0xff; 6]),
+            guest_mac,
+            ETHERTYPE_IPV4,
+        )
+        .unwrap();
+        let mut packet = IPv4Packet::write_header(
+            eth.inner_mut().payload_mut(),
+            PROTOCOL_UDP,
+            ciaddr,
+            Ipv4Addr::BROADCAST,
+        )
+        .unwrap();
+        let datagram_len =
+            UdpDatagram::write_incomplete_datagram(packet.inner_mut().payload_mut(), &message)
+                .unwrap()
+                .finalize(DHCP_CLIENT_PORT, DHCP_SERVER_PORT, None)
+                .len();
+        let packet_len = packet.with_payload_len_unchecked(datagram_len, true).len();
+        eth.with_payload_len_unchecked(packet_len).len()
+    }
+
+    // Returns the destination MAC, destination address, `yiaddr` and options of a reply.
+    fn parse_reply(frame: &[u8]) -> (MacAddr, Ipv4Addr, Ipv4Addr, Vec<(u8, Vec<u8>)>) {
+        let eth = EthernetFrame::from_bytes(frame).unwrap();
+        let packet = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+        let datagram = UdpDatagram::from_bytes(
+            packet.payload(),
+            Some((packet.source_address(), packet.destination_address())),
+        )
+        .unwrap();
+        assert_eq!(datagram.source_port(), DHCP_SERVER_PORT);
+        assert_eq!(datagram.destination_port(), DHCP_CLIENT_PORT);
+
+        let message = datagram.payload();
+        assert!(message.len() >= MIN_MESSAGE_SIZE);
+        assert_eq!(message[OP_OFFSET], BOOTREPLY);
+        assert_eq!(message[XID_OFFSET..XID_OFFSET + 4], [1, 2, 3, 4]);
+        let mut options = vec![];
+        let mut rest = &message[OPTIONS_OFFSET..];
+        while rest[0] != OPTION_END {
+            let len = usize::from(rest[1]);
+            options.push((rest[0], rest[2..2 + len].to_vec()));
+            rest = &rest[2 + len..];
+        }
+        (
+            eth.dst_mac(),
+            packet.destination_address(),
+            ipv4_addr_at(message, YIADDR_OFFSET),
+            options,
+        )
+    }
+
+    #[test]
+    fn test_validate() {
+        config().validate().unwrap();
+
+        let mut invalid = config();
+        invalid.ip_address = Ipv4Addr::BROADCAST;
+        assert_eq!(
+            invalid.validate(),
+            Err(DhcpConfigError::InvalidAddress(Ipv4Addr::BROADCAST))
+        );
+        let mut invalid = config();
+        invalid.prefix_length = 33;
+        assert_eq!(
+            invalid.validate(),
+            Err(DhcpConfigError::InvalidPrefixLength(33))
+        );
+        let mut invalid = config();
+        invalid.nameservers = vec![Ipv4Addr::LOCALHOST; MAX_NAMESERVERS + 1];
+        assert_eq!(invalid.validate(), Err(DhcpConfigError::TooManyNameservers));
+
+        let mut config = config();
+        assert_eq!(config.subnet_mask(), Ipv4Addr::new(255, 255, 255, 0));
+        config.prefix_length = 0;
+        assert_eq!(config.subnet_mask(), Ipv4Addr::UNSPECIFIED);
+        config.prefix_length = 32;
+        assert_eq!(config.subnet_mask(), Ipv4Addr::BROADCAST);
+    }
+
+    #[test]
+    fn test_is_dhcp_frame() {
+        let mut buf = [0u8; 1000];
+        let len = write_request(&mut buf, DHCPDISCOVER, Ipv4Addr::UNSPECIFIED, &[]);
+        assert!(DhcpServer::is_dhcp_frame(&buf[..len]));
+        // Only the headers are needed.
+        assert!(DhcpServer::is_dhcp_frame(&buf[..PAYLOAD_OFFSET + 24]));
+        assert!(!DhcpServer::is_dhcp_frame(&buf[..PAYLOAD_OFFSET + 20]));
+
+        // Sent to the client port.
+        buf[PAYLOAD_OFFSET + 23] = 68;
+        assert!(!DhcpServer::is_dhcp_frame(&buf[..len]));
+        // Not UDP.
+        buf[PAYLOAD_OFFSET + 23] = 67;
+        buf[PAYLOAD_OFFSET + 9] = 6;
+        assert!(!DhcpServer::is_dhcp_frame(&buf[..len]));
+    }
+
+    #[test]
+    fn test_discover_and_request() {
+        let mut server = DhcpServer::new(config());
+        let mut buf = [0u8; 1000];
+        assert!(server.write_next_frame(&mut buf).is_none());
+
+        let len = write_request(&mut buf, DHCPDISCOVER, Ipv4Addr::UNSPECIFIED, &[]);
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        assert!(server.write_next_frame(&mut buf).is_none());
+        let (dst_mac, dst_addr, yiaddr, options) = parse_reply(&buf[..len]);
+        assert_eq!(dst_mac, MacAddr::from_bytes_unchecked(&[0xff; 6]));
+        assert_eq!(dst_addr, Ipv4Addr::BROADCAST);
+        assert_eq!(yiaddr, Ipv4Addr::new(192, 168, 0, 2));
+        assert_eq!(
+            options,
+            vec![
+                (OPTION_MESSAGE_TYPE, vec![DHCPOFFER]),
+                (OPTION_SERVER_ID, vec![192, 168, 0, 1]),
+                (OPTION_LEASE_TIME, vec![0xff; 4]),
+                (OPTION_SUBNET_MASK, vec![255, 255, 255, 0]),
+                (OPTION_ROUTER, vec![192, 168, 0, 1]),
+                (OPTION_DNS, vec![8, 8, 8, 8, 1, 1, 1, 1]),
+            ]
+        );
+
+        let len = write_request(
+            &mut buf,
+            DHCPREQUEST,
+            Ipv4Addr::UNSPECIFIED,
+            &[
+                OPTION_REQUESTED_ADDR,
+                4,
+                192,
+                168,
+                0,
+                2,
+                OPTION_SERVER_ID,
+                4,
+                192,
+                168,
+                0,
+                1,
+            ],
+        );
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        let (_, _, yiaddr, options) = parse_reply(&buf[..len]);
+        assert_eq!(yiaddr, Ipv4Addr::new(192, 168, 0, 2));
+        assert_eq!(options[0], (OPTION_MESSAGE_TYPE, vec![DHCPACK]));
+
+        // Renewal of the lease, unicast to the guest.
+        let len = write_request(&mut buf, DHCPREQUEST, Ipv4Addr::new(192, 168, 0, 2), &[]);
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        let (dst_mac, dst_addr, _, options) = parse_reply(&buf[..len]);
+        assert_eq!(dst_mac, MacAddr::from_str(GUEST_MAC).unwrap());
+        assert_eq!(dst_addr, Ipv4Addr::new(192, 168, 0, 2));
+        assert_eq!(options[0], (OPTION_MESSAGE_TYPE, vec![DHCPACK]));
+    }
+
+    #[test]
+    fn test_request_rejected() {
+        let mut server = DhcpServer::new(DhcpConfig {
+            gateway: None,
+            nameservers: vec![],
+            ..config()
+        });
+        let mut buf = [0u8; 1000];
+
+        // Another address is requested.
+        let len = write_request(
+            &mut buf,
+            DHCPREQUEST,
+            Ipv4Addr::UNSPECIFIED,
+            &[OPTION_REQUESTED_ADDR, 4, 192, 168, 0, 3],
+        );
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        let (_, dst_addr, yiaddr, options) = parse_reply(&buf[..len]);
+        assert_eq!(dst_addr, Ipv4Addr::BROADCAST);
+        assert_eq!(yiaddr, Ipv4Addr::UNSPECIFIED);
+        assert_eq!(
+            options,
+            vec![
+                (OPTION_MESSAGE_TYPE, vec![DHCPNAK]),
+                (OPTION_SERVER_ID, DEFAULT_SERVER_ADDR.octets().to_vec()),
+            ]
+        );
+
+        // The offer of another server is accepted.
+        let len = write_request(
+            &mut buf,
+            DHCPREQUEST,
+            Ipv4Addr::UNSPECIFIED,
+            &[
+                OPTION_REQUESTED_ADDR,
+                4,
+                192,
+                168,
+                0,
+                2,
+                OPTION_SERVER_ID,
+                4,
+                192,
+                168,
+                0,
+                1,
+            ],
+        );
+        assert!(!server.detour_frame(&buf[..len]));
+        assert!(server.write_next_frame(&mut buf).is_none());
+
+        // Malformed or unsupported messages.
+        let len = write_request(&mut buf, 8, Ipv4Addr::UNSPECIFIED, &[]);
+        assert!(!server.detour_frame(&buf[..len]));
+        let len = write_request(&mut buf, DHCPDISCOVER, Ipv4Addr::UNSPECIFIED, &[50, 10, 1]);
+        assert!(!server.detour_frame(&buf[..len]));
+        assert!(!server.detour_frame(&buf[..PAYLOAD_OFFSET + 30]));
+    }
+}
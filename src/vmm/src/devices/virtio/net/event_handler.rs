@@ -6,7 +6,7 @@ use utils::epoll::EventSet;
 
 use crate::devices::virtio::device::VirtioDevice;
 use crate::devices::virtio::net::device::Net;
-use crate::devices::virtio::net::{RX_INDEX, TX_INDEX};
+use crate::devices::virtio::net::{CTRL_INDEX, RX_INDEX, TX_INDEX};
 use crate::logger::{error, warn, IncMetric};
 
 impl Net {
@@ -16,6 +16,12 @@ impl Net {
     const PROCESS_TAP_RX: u32 = 3;
     const PROCESS_RX_RATE_LIMITER: u32 = 4;
     const PROCESS_TX_RATE_LIMITER: u32 = 5;
+    const PROCESS_VIRTQ_CTRL: u32 = 6;
+    const PROCESS_PEER_RX: u32 = 7;
+    const PROCESS_RX_COALESCING: u32 = 8;
+    const PROCESS_TX_COALESCING: u32 = 9;
+    const PROCESS_PORT_FORWARD: u32 = 10;
+    const PROCESS_REPLAY_TIMER: u32 = 11;
 
     fn register_runtime_events(&self, ops: &mut EventOps) {
         if let Err(err) = ops.add(Events::with_data(
@@ -32,6 +38,13 @@ impl Net {
         )) {
             error!("Failed to register tx queue event: {}", err);
         }
+        if let Err(err) = ops.add(Events::with_data(
+            &self.queue_evts[CTRL_INDEX],
+            Self::PROCESS_VIRTQ_CTRL,
+            EventSet::IN,
+        )) {
+            error!("Failed to register control queue event: {}", err);
+        }
         if let Err(err) = ops.add(Events::with_data(
             &self.rx_rate_limiter,
             Self::PROCESS_RX_RATE_LIMITER,
@@ -46,12 +59,56 @@ impl Net {
         )) {
             error!("Failed to register tx queue event: {}", err);
         }
+        // The frames are received on the AF_XDP socket instead of the tap, if any.
+        let rx_result = match self.xdp.as_ref() {
+            Some(xdp) => ops.add(Events::with_data(
+                xdp,
+                Self::PROCESS_TAP_RX,
+                EventSet::IN | EventSet::EDGE_TRIGGERED,
+            )),
+            None => ops.add(Events::with_data(
+                &self.tap,
+                Self::PROCESS_TAP_RX,
+                EventSet::IN | EventSet::EDGE_TRIGGERED,
+            )),
+        };
+        if let Err(err) = rx_result {
+            error!("Failed to register tap event: {}", err);
+        }
         if let Err(err) = ops.add(Events::with_data(
-            &self.tap,
-            Self::PROCESS_TAP_RX,
-            EventSet::IN | EventSet::EDGE_TRIGGERED,
+            &self.peer_inbox.evt,
+            Self::PROCESS_PEER_RX,
+            EventSet::IN,
         )) {
-            error!("Failed to register tap event: {}", err);
+            error!("Failed to register peer rx event: {}", err);
+        }
+        if let Err(err) = ops.add(Events::with_data(
+            &self.rx_coalescer,
+            Self::PROCESS_RX_COALESCING,
+            EventSet::IN,
+        )) {
+            error!("Failed to register rx interrupt coalescing event: {}", err);
+        }
+        if let Err(err) = ops.add(Events::with_data(
+            &self.tx_coalescer,
+            Self::PROCESS_TX_COALESCING,
+            EventSet::IN,
+        )) {
+            error!("Failed to register tx interrupt coalescing event: {}", err);
+        }
+        if let Some(port_forwarder) = self.port_forwarder.as_ref() {
+            if let Err(err) = ops.add(Events::with_data(
+                port_forwarder,
+                Self::PROCESS_PORT_FORWARD,
+                EventSet::IN,
+            )) {
+                error!("Failed to register port forward event: {}", err);
+            }
+        }
+        if let Some(replay_timer) = self.replay_timer.as_ref() {
+            if let Err(err) = replay_timer.register(ops, Self::PROCESS_REPLAY_TIMER) {
+                error!("Failed to register replay timer event: {}", err);
+            }
         }
     }
 
@@ -101,9 +158,15 @@ impl MutEventSubscriber for Net {
                 Self::PROCESS_ACTIVATE => self.process_activate_event(ops),
                 Self::PROCESS_VIRTQ_RX => self.process_rx_queue_event(),
                 Self::PROCESS_VIRTQ_TX => self.process_tx_queue_event(),
+                Self::PROCESS_VIRTQ_CTRL => self.process_ctrl_queue_event(),
                 Self::PROCESS_TAP_RX => self.process_tap_rx_event(),
+                Self::PROCESS_PEER_RX => self.process_peer_rx_event(),
                 Self::PROCESS_RX_RATE_LIMITER => self.process_rx_rate_limiter_event(),
                 Self::PROCESS_TX_RATE_LIMITER => self.process_tx_rate_limiter_event(),
+                Self::PROCESS_RX_COALESCING => self.process_rx_coalescing_event(),
+                Self::PROCESS_TX_COALESCING => self.process_tx_coalescing_event(),
+                Self::PROCESS_PORT_FORWARD => self.process_port_forward_event(),
+                Self::PROCESS_REPLAY_TIMER => self.process_replay_timer_event(),
                 _ => {
                     warn!("Net: Spurious event received: {:?}", source);
                     self.metrics.event_fails.inc();
@@ -122,6 +185,10 @@ impl MutEventSubscriber for Net {
         //  - shortly after device creation,
         //  - on device activation (is-activated already true at this point),
         //  - on device restore from snapshot.
+        // The queues and the tap of a device emulated by a worker are only polled by the worker.
+        if self.is_worker() {
+            return;
+        }
         if self.is_activated() {
             self.register_runtime_events(ops);
         } else {
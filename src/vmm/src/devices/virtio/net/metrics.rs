@@ -85,7 +85,10 @@ use std::sync::{Arc, RwLock};
 use serde::ser::SerializeMap;
 use serde::{Serialize, Serializer};
 
-use crate::logger::{IncMetric, LatencyAggregateMetrics, SharedIncMetric};
+use crate::logger::{
+    IncMetric, LatencyAggregateMetrics, LatencyBuckets, QueueDepthBuckets, SharedHistogramMetric,
+    SharedIncMetric,
+};
 
 /// map of network interface id and metrics
 /// this should be protected by a lock before accessing.
@@ -141,6 +144,15 @@ pub fn flush_metrics<S: Serializer>(serializer: S) -> Result<S::Ok, S::Error> {
     seq.end()
 }
 
+/// Discards what the network devices counted since the metrics were last flushed, so that the
+/// next flush only reports what happens after the reset.
+pub fn reset_metrics() {
+    for metrics in METRICS.read().unwrap().metrics.values() {
+        // Serializing the metrics resets them.
+        let _ = serde_json::to_value(metrics.as_ref());
+    }
+}
+
 /// Network-related metrics.
 #[derive(Default, Debug, Serialize)]
 pub struct NetDeviceMetrics {
@@ -150,6 +162,12 @@ pub struct NetDeviceMetrics {
     pub cfg_fails: SharedIncMetric,
     /// Number of times the mac address was updated through the config space.
     pub mac_address_updates: SharedIncMetric,
+    /// Number of times the link state announced to the guest was updated.
+    pub link_state_updates: SharedIncMetric,
+    /// Number of events associated with the control queue.
+    pub ctrl_queue_event_count: SharedIncMetric,
+    /// Number of control commands that failed.
+    pub ctrl_fails: SharedIncMetric,
     /// No available buffer for the net device rx queue.
     pub no_rx_avail_buffer: SharedIncMetric,
     /// No available buffer for the net device tx queue.
@@ -174,6 +192,8 @@ pub struct NetDeviceMetrics {
     pub rx_fails: SharedIncMetric,
     /// Number of successful read operations while receiving data.
     pub rx_count: SharedIncMetric,
+    /// Number of received frames dropped by the receive filters configured by the guest.
+    pub rx_filtered_frames: SharedIncMetric,
     /// Number of times reading from TAP failed.
     pub tap_read_fails: SharedIncMetric,
     /// Number of times writing to TAP failed.
@@ -200,8 +220,41 @@ pub struct NetDeviceMetrics {
     pub tx_rate_limiter_throttled: SharedIncMetric,
     /// Number of packets with a spoofed mac, sent by the guest.
     pub tx_spoofed_mac_count: SharedIncMetric,
+    /// Number of frames addressed to the peer device dropped because its inbox was full.
+    pub tx_peer_dropped_frames: SharedIncMetric,
+    /// Number of TX frames dropped because they were malformed or could not be read from the
+    /// guest memory.
+    pub tx_dropped_malformed: SharedIncMetric,
+    /// Number of TX frames dropped because the TAP could not take them in.
+    pub tx_dropped_tap_full: SharedIncMetric,
+    /// Number of TX frames intercepted by MMDS instead of being sent on the TAP.
+    pub tx_mmds_intercepted_frames: SharedIncMetric,
+    /// Number of TX frames intercepted by the DHCP responder instead of being sent on the TAP.
+    pub tx_dhcp_intercepted_frames: SharedIncMetric,
+    /// Number of DHCP replies delivered to the guest.
+    pub rx_dhcp_frames: SharedIncMetric,
+    /// Number of TX frames intercepted by the port forwarding proxy instead of being sent on the
+    /// TAP.
+    pub tx_port_forward_intercepted_frames: SharedIncMetric,
+    /// Number of frames of forwarded connections delivered to the guest.
+    pub rx_port_forward_frames: SharedIncMetric,
+    /// Number of received TCPv4 segmentation offload frames.
+    pub rx_tso4_frames: SharedIncMetric,
+    /// Number of received TCPv6 segmentation offload frames.
+    pub rx_tso6_frames: SharedIncMetric,
+    /// Number of transmitted TCPv4 segmentation offload frames.
+    pub tx_tso4_frames: SharedIncMetric,
+    /// Number of transmitted TCPv6 segmentation offload frames.
+    pub tx_tso6_frames: SharedIncMetric,
     /// Number of remaining requests in the TX queue.
     pub tx_remaining_reqs_count: SharedIncMetric,
+    /// Number of times the device kept using its tap because the AF_XDP socket could not be set
+    /// up.
+    pub xdp_fallbacks: SharedIncMetric,
+    /// Histogram of the durations of the tap writes, in microseconds.
+    pub tap_write_latency_hist: SharedHistogramMetric<LatencyBuckets>,
+    /// Histogram of the number of requests pending in the TX queue when it is processed.
+    pub tx_queue_depth_hist: SharedHistogramMetric<QueueDepthBuckets>,
 }
 
 impl NetDeviceMetrics {
@@ -223,6 +276,11 @@ impl NetDeviceMetrics {
         self.cfg_fails.add(other.cfg_fails.fetch_diff());
         self.mac_address_updates
             .add(other.mac_address_updates.fetch_diff());
+        self.link_state_updates
+            .add(other.link_state_updates.fetch_diff());
+        self.ctrl_queue_event_count
+            .add(other.ctrl_queue_event_count.fetch_diff());
+        self.ctrl_fails.add(other.ctrl_fails.fetch_diff());
         self.no_rx_avail_buffer
             .add(other.no_rx_avail_buffer.fetch_diff());
         self.no_tx_avail_buffer
@@ -243,6 +301,8 @@ impl NetDeviceMetrics {
             .add(other.rx_packets_count.fetch_diff());
         self.rx_fails.add(other.rx_fails.fetch_diff());
         self.rx_count.add(other.rx_count.fetch_diff());
+        self.rx_filtered_frames
+            .add(other.rx_filtered_frames.fetch_diff());
         self.tap_read_fails.add(other.tap_read_fails.fetch_diff());
         self.tap_write_fails.add(other.tap_write_fails.fetch_diff());
         self.tap_write_agg
@@ -265,8 +325,32 @@ impl NetDeviceMetrics {
             .add(other.tx_rate_limiter_throttled.fetch_diff());
         self.tx_spoofed_mac_count
             .add(other.tx_spoofed_mac_count.fetch_diff());
+        self.tx_peer_dropped_frames
+            .add(other.tx_peer_dropped_frames.fetch_diff());
+        self.tx_dropped_malformed
+            .add(other.tx_dropped_malformed.fetch_diff());
+        self.tx_dropped_tap_full
+            .add(other.tx_dropped_tap_full.fetch_diff());
+        self.tx_mmds_intercepted_frames
+            .add(other.tx_mmds_intercepted_frames.fetch_diff());
+        self.tx_dhcp_intercepted_frames
+            .add(other.tx_dhcp_intercepted_frames.fetch_diff());
+        self.rx_dhcp_frames.add(other.rx_dhcp_frames.fetch_diff());
+        self.tx_port_forward_intercepted_frames
+            .add(other.tx_port_forward_intercepted_frames.fetch_diff());
+        self.rx_port_forward_frames
+            .add(other.rx_port_forward_frames.fetch_diff());
+        self.rx_tso4_frames.add(other.rx_tso4_frames.fetch_diff());
+        self.rx_tso6_frames.add(other.rx_tso6_frames.fetch_diff());
+        self.tx_tso4_frames.add(other.tx_tso4_frames.fetch_diff());
+        self.tx_tso6_frames.add(other.tx_tso6_frames.fetch_diff());
         self.tx_remaining_reqs_count
             .add(other.tx_remaining_reqs_count.fetch_diff());
+        self.xdp_fallbacks.add(other.xdp_fallbacks.fetch_diff());
+        self.tap_write_latency_hist
+            .aggregate(&other.tap_write_latency_hist);
+        self.tx_queue_depth_hist
+            .aggregate(&other.tx_queue_depth_hist);
     }
 }
 
@@ -442,4 +526,19 @@ pub mod tests {
                 >= 5
         );
     }
+
+    #[test]
+    fn test_reset_net_dev_metrics() {
+        let metrics = NetMetricsPerDevice::alloc(String::from("reset_test"));
+        metrics.tx_dropped_tap_full.add(3);
+        metrics.rx_tso4_frames.inc();
+
+        reset_metrics();
+        metrics.tx_dropped_tap_full.inc();
+        // Only what was counted after the reset is reported, while the counters keep going.
+        let serialized = serde_json::to_value(metrics.as_ref()).unwrap();
+        assert_eq!(serialized["tx_dropped_tap_full"], 1);
+        assert_eq!(serialized["rx_tso4_frames"], 0);
+        assert_eq!(metrics.tx_dropped_tap_full.count(), 4);
+    }
 }
@@ -10,19 +10,27 @@ use crate::devices::virtio::queue::FIRECRACKER_MAX_QUEUE_SIZE;
 /// Maximum size of the frame buffers handled by this device.
 pub const MAX_BUFFER_SIZE: usize = 65562;
 /// The number of queues of the network device.
-pub const NET_NUM_QUEUES: usize = 2;
+pub const NET_NUM_QUEUES: usize = 3;
 pub const NET_QUEUE_SIZES: [u16; NET_NUM_QUEUES] = [FIRECRACKER_MAX_QUEUE_SIZE; NET_NUM_QUEUES];
 /// The index of the rx queue from Net device queues/queues_evts vector.
 pub const RX_INDEX: usize = 0;
 /// The index of the tx queue from Net device queues/queues_evts vector.
 pub const TX_INDEX: usize = 1;
+/// The index of the control queue from Net device queues/queues_evts vector.
+pub const CTRL_INDEX: usize = 2;
 
+pub mod coalescing;
+pub mod ctrl;
 pub mod device;
+pub mod dhcp;
 mod event_handler;
 pub mod metrics;
+pub mod peer;
 pub mod persist;
+pub mod port_forward;
 mod tap;
 pub mod test_utils;
+pub mod xdp;
 
 mod gen;
 
@@ -37,6 +45,8 @@ pub enum NetQueue {
     Rx,
     /// The TX queue
     Tx,
+    /// The control queue
+    Ctrl,
 }
 
 /// Errors the network device can trigger.
@@ -50,8 +60,18 @@ pub enum NetError {
     TapSetVnetHdrSize(TapError),
     /// EventFd error: {0}
     EventFd(io::Error),
+    /// Creating the interrupt coalescing timer failed: {0}
+    CoalescingTimer(io::Error),
     /// IO error: {0}
     IO(io::Error),
     /// The VNET header is missing from the frame
     VnetHeaderMissing,
+    /// The device was not configured with a guest MAC address
+    GuestMacNotConfigured,
+    /// Worker process error: {0}
+    Worker(crate::devices::virtio::worker::net::NetWorkerError),
+    /// The operation is not supported by network devices emulated by a worker process
+    WorkerUnsupported,
+    /// AF_XDP socket error: {0}
+    Xdp(xdp::XdpError),
 }
@@ -0,0 +1,109 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements the direct link between two network devices of the same microVM, which exchange
+//! the frames addressed to each other without going through their tap devices.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Mutex;
+
+use log::error;
+use utils::eventfd::EventFd;
+use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
+
+/// Maximum number of frames waiting to be received by a device from its peer.
+pub const PEER_INBOX_MAX_FRAMES: usize = 256;
+
+/// Frames sent to a network device by its peer.
+#[derive(Debug)]
+pub struct PeerInbox {
+    frames: Mutex<VecDeque<Vec<u8>>>,
+    guest_mac: Mutex<Option<MacAddr>>,
+    /// Signaled when a frame is added to the inbox.
+    pub evt: EventFd,
+}
+
+impl PeerInbox {
+    /// Creates an empty inbox for a device with the MAC address `guest_mac`.
+    pub fn new(guest_mac: Option<MacAddr>) -> Result<Self, io::Error> {
+        Ok(PeerInbox {
+            frames: Mutex::new(VecDeque::new()),
+            guest_mac: Mutex::new(guest_mac),
+            evt: EventFd::new(libc::EFD_NONBLOCK)?,
+        })
+    }
+
+    /// Updates the MAC address of the device owning the inbox.
+    pub fn set_guest_mac(&self, guest_mac: Option<MacAddr>) {
+        *self.guest_mac.lock().expect("Poisoned lock") = guest_mac;
+    }
+
+    /// Returns whether `frame`, an ethernet frame, is addressed to the device owning the inbox.
+    pub fn is_addressed_to(&self, frame: &[u8]) -> bool {
+        let guest_mac = self.guest_mac.lock().expect("Poisoned lock");
+        match (guest_mac.as_ref(), frame.get(..MAC_ADDR_LEN as usize)) {
+            (Some(guest_mac), Some(dst_mac)) => guest_mac.get_bytes() == dst_mac,
+            _ => false,
+        }
+    }
+
+    /// Adds `frame`, including its virtio-net header, to the inbox. Returns `false` if the inbox
+    /// is full, in which case the frame is dropped.
+    pub fn push(&self, frame: Vec<u8>) -> bool {
+        let mut frames = self.frames.lock().expect("Poisoned lock");
+        if frames.len() >= PEER_INBOX_MAX_FRAMES {
+            return false;
+        }
+        frames.push_back(frame);
+        drop(frames);
+
+        if let Err(err) = self.evt.write(1) {
+            error!("Failed to signal peer inbox: {:?}", err);
+        }
+        true
+    }
+
+    /// Moves the oldest frame of the inbox to `buf`, returning its length, or `None` if the
+    /// inbox is empty. Frames larger than `buf` are truncated.
+    pub fn pop(&self, buf: &mut [u8]) -> Option<usize> {
+        let frame = self.frames.lock().expect("Poisoned lock").pop_front()?;
+        let len = frame.len().min(buf.len());
+        buf[..len].copy_from_slice(&frame[..len]);
+        Some(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_peer_inbox() {
+        let mac = MacAddr::from_str("06:00:00:00:00:02").unwrap();
+        let inbox = PeerInbox::new(None).unwrap();
+        assert!(!inbox.is_addressed_to(mac.get_bytes()));
+        inbox.set_guest_mac(Some(mac));
+        assert!(inbox.is_addressed_to(mac.get_bytes()));
+        assert!(!inbox.is_addressed_to(&[6, 0, 0, 0, 0, 3]));
+        assert!(!inbox.is_addressed_to(&[6, 0]));
+
+        let mut buf = [0u8; 4];
+        assert!(inbox.pop(&mut buf).is_none());
+        assert!(inbox.push(vec![1, 2, 3]));
+        assert!(inbox.push(vec![4, 5, 6, 7, 8]));
+        assert_eq!(inbox.evt.read().unwrap(), 2);
+        assert_eq!(inbox.pop(&mut buf), Some(3));
+        assert_eq!(&buf[..3], &[1, 2, 3]);
+        assert_eq!(inbox.pop(&mut buf), Some(4));
+        assert_eq!(buf, [4, 5, 6, 7]);
+        assert!(inbox.pop(&mut buf).is_none());
+
+        for _ in 0..PEER_INBOX_MAX_FRAMES {
+            assert!(inbox.push(vec![0]));
+        }
+        assert!(!inbox.push(vec![0]));
+    }
+}
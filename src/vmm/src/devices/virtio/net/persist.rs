@@ -10,10 +10,18 @@ use std::sync::{Arc, Mutex};
 use serde::{Deserialize, Serialize};
 use utils::net::mac::MacAddr;
 
+use super::coalescing::InterruptCoalescingConfig;
+use super::ctrl::RxFilter;
 use super::device::Net;
+use super::dhcp::DhcpConfig;
+use super::port_forward::PortForwardError;
+use super::xdp::XdpConfig;
 use super::NET_NUM_QUEUES;
 use crate::devices::virtio::device::DeviceState;
-use crate::devices::virtio::persist::{PersistError as VirtioStateError, VirtioDeviceState};
+use crate::devices::virtio::gen::virtio_net::VIRTIO_NET_F_CTRL_VQ;
+use crate::devices::virtio::persist::{
+    PersistError as VirtioStateError, SnapshotFeature, VirtioDeviceState,
+};
 use crate::devices::virtio::queue::FIRECRACKER_MAX_QUEUE_SIZE;
 use crate::devices::virtio::TYPE_NET;
 use crate::mmds::data_store::Mmds;
@@ -22,6 +30,7 @@ use crate::mmds::persist::MmdsNetworkStackState;
 use crate::rate_limiter::persist::RateLimiterState;
 use crate::rate_limiter::RateLimiter;
 use crate::snapshot::Persist;
+use crate::vmm_config::port_forward::PortForwardConfig;
 use crate::vstate::memory::GuestMemoryMmap;
 
 /// Information about the network config's that are saved
@@ -29,6 +38,8 @@ use crate::vstate::memory::GuestMemoryMmap;
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct NetConfigSpaceState {
     guest_mac: Option<MacAddr>,
+    /// Whether the link is announced as up to the guest.
+    link_up: bool,
 }
 
 /// Information about the network device that are saved
@@ -42,9 +53,69 @@ pub struct NetState {
     /// The associated MMDS network stack.
     pub mmds_ns: Option<MmdsNetworkStackState>,
     config_space: NetConfigSpaceState,
+    /// The receive filters configured through the control queue.
+    rx_filter: RxFilter,
+    /// ID of the network device configured as peer of this one.
+    peer_id: Option<String>,
+    /// Interrupt coalescing parameters of the RX queue.
+    rx_interrupt_coalescing: InterruptCoalescingConfig,
+    /// Interrupt coalescing parameters of the TX queue.
+    tx_interrupt_coalescing: InterruptCoalescingConfig,
+    /// Frame received by the device and not delivered to the guest yet, with its vnet header.
+    rx_deferred_frame: Option<Vec<u8>>,
+    /// Network configuration handed to the guest through DHCP.
+    dhcp: Option<DhcpConfig>,
+    /// Host ports forwarded to the guest.
+    port_forwards: Vec<PortForwardConfig>,
+    /// AF_XDP socket exchanging the frames instead of the tap.
+    xdp: Option<XdpConfig>,
     virtio_state: VirtioDeviceState,
 }
 
+impl NetState {
+    /// Replaces the name of the tap device backing the device.
+    pub fn set_tap_if_name(&mut self, tap_if_name: String) {
+        self.tap_if_name = tap_if_name;
+    }
+
+    /// Snapshot features used by the device.
+    pub fn snapshot_features(&self) -> Vec<SnapshotFeature> {
+        let mut features = self.virtio_state.snapshot_features();
+        if self.virtio_state.acked_features & (1 << VIRTIO_NET_F_CTRL_VQ) != 0 {
+            features.push(SnapshotFeature::NetControlQueue);
+        }
+        if self.peer_id.is_some() {
+            features.push(SnapshotFeature::NetPeer);
+        }
+        if self.rx_interrupt_coalescing.is_enabled() || self.tx_interrupt_coalescing.is_enabled() {
+            features.push(SnapshotFeature::NetInterruptCoalescing);
+        }
+        if self
+            .mmds_ns
+            .as_ref()
+            .is_some_and(|mmds_ns| mmds_ns.namespace.is_some())
+        {
+            features.push(SnapshotFeature::MmdsNamespace);
+        }
+        if self.rx_deferred_frame.is_some() {
+            features.push(SnapshotFeature::NetRxDeferredFrame);
+        }
+        if self.dhcp.is_some() {
+            features.push(SnapshotFeature::NetDhcp);
+        }
+        if !self.port_forwards.is_empty() {
+            features.push(SnapshotFeature::NetPortForward);
+        }
+        if !self.config_space.link_up {
+            features.push(SnapshotFeature::NetLinkDown);
+        }
+        if self.xdp.is_some() {
+            features.push(SnapshotFeature::NetXdp);
+        }
+        features
+    }
+}
+
 /// Auxiliary structure for creating a device when resuming from a snapshot.
 #[derive(Debug)]
 pub struct NetConstructorArgs {
@@ -65,6 +136,10 @@ pub enum NetPersistError {
     VirtioState(#[from] VirtioStateError),
     /// Indicator that no MMDS is associated with this device.
     NoMmdsDataStore,
+    /// Deferred RX frame of {0} bytes does not fit in the RX buffer.
+    DeferredFrameTooBig(usize),
+    /// Failed to forward the host ports to the guest: {0}
+    PortForward(#[from] PortForwardError),
 }
 
 impl Persist<'_> for Net {
@@ -81,7 +156,18 @@ impl Persist<'_> for Net {
             mmds_ns: self.mmds_ns.as_ref().map(|mmds| mmds.save()),
             config_space: NetConfigSpaceState {
                 guest_mac: self.guest_mac,
+                link_up: self.link_up(),
             },
+            rx_filter: self.rx_filter.clone(),
+            peer_id: self.peer_id.clone(),
+            rx_interrupt_coalescing: self.rx_coalescer.config(),
+            tx_interrupt_coalescing: self.tx_coalescer.config(),
+            rx_deferred_frame: self
+                .rx_deferred_frame
+                .then(|| self.rx_frame_buf[..self.rx_bytes_read].to_vec()),
+            dhcp: self.dhcp_config().cloned(),
+            port_forwards: self.port_forwards().to_vec(),
+            xdp: self.xdp_config().cloned(),
             virtio_state: VirtioDeviceState::from_device(self),
         }
     }
@@ -125,11 +211,40 @@ impl Persist<'_> for Net {
             FIRECRACKER_MAX_QUEUE_SIZE,
         )?;
         net.irq_trigger.irq_status = Arc::new(AtomicU32::new(state.virtio_state.interrupt_status));
+        net.rx_filter = state.rx_filter.clone();
+        net.peer_id = state.peer_id.clone();
+        net.set_interrupt_coalescing(
+            Some(state.rx_interrupt_coalescing),
+            Some(state.tx_interrupt_coalescing),
+        );
+        if let Some(frame) = &state.rx_deferred_frame {
+            net.rx_frame_buf
+                .get_mut(..frame.len())
+                .ok_or(NetPersistError::DeferredFrameTooBig(frame.len()))?
+                .copy_from_slice(frame);
+            net.rx_bytes_read = frame.len();
+            // The frame is delivered once the device is kicked after the restore.
+            net.rx_deferred_frame = true;
+        }
+        // The reply to a request in flight is lost, the DHCP client of the guest retries.
+        net.set_dhcp_config(state.dhcp.clone());
+        // The forwarded connections are lost, only the host ports are listened on again.
+        net.set_port_forwards(state.port_forwards.clone())?;
+        // The guest is not notified, as the device is not activated yet.
+        net.set_link_up(state.config_space.link_up)?;
+        // The frames in the rings of the socket are lost. The device keeps using its tap if the
+        // socket cannot be set up again, the offloads offered to the guest being unchanged.
+        if let Some(config) = &state.xdp {
+            net.set_xdp_config(config.clone());
+        }
         net.avail_features = state.virtio_state.avail_features;
         net.acked_features = state.virtio_state.acked_features;
 
         if state.virtio_state.activated {
             net.device_state = DeviceState::Activated(constructor_args.mem);
+            // The driver may not have been signaled about the last used descriptors yet.
+            net.rx_coalescer.schedule_signal();
+            net.tx_coalescer.schedule_signal();
         }
 
         Ok(net)
@@ -138,6 +253,7 @@ impl Persist<'_> for Net {
 
 #[cfg(test)]
 mod tests {
+    use std::net::Ipv4Addr;
     use std::sync::atomic::Ordering;
 
     use super::*;
@@ -154,6 +270,13 @@ mod tests {
         let tap_if_name;
         let has_mmds_ns;
         let allow_mmds_requests;
+        let rx_filter;
+        let peer_id;
+        let rx_interrupt_coalescing;
+        let rx_deferred_frame;
+        let dhcp;
+        let port_forwards;
+        let link_up;
         let virtio_state;
 
         // Create and save the net device.
@@ -165,6 +288,15 @@ mod tests {
             tap_if_name = net.iface_name();
             has_mmds_ns = net.mmds_ns.is_some();
             allow_mmds_requests = has_mmds_ns && mmds_ds.is_some();
+            rx_filter = net.rx_filter.clone();
+            peer_id = net.peer_id.clone();
+            rx_interrupt_coalescing = net.rx_interrupt_coalescing();
+            rx_deferred_frame = net
+                .rx_deferred_frame
+                .then(|| net.rx_frame_buf[..net.rx_bytes_read].to_vec());
+            dhcp = net.dhcp_config().cloned();
+            port_forwards = net.port_forwards().to_vec();
+            link_up = net.link_up();
             virtio_state = VirtioDeviceState::from_device(&net);
         }
 
@@ -197,6 +329,23 @@ mod tests {
                     assert_eq!(restored_net.mmds_ns.is_some(), allow_mmds_requests);
                     assert_eq!(restored_net.rx_rate_limiter, RateLimiter::default());
                     assert_eq!(restored_net.tx_rate_limiter, RateLimiter::default());
+                    assert_eq!(restored_net.rx_filter, rx_filter);
+                    assert_eq!(restored_net.peer_id, peer_id);
+                    assert_eq!(
+                        restored_net.rx_interrupt_coalescing(),
+                        rx_interrupt_coalescing
+                    );
+                    assert_eq!(
+                        restored_net
+                            .rx_deferred_frame
+                            .then(
+                                || restored_net.rx_frame_buf[..restored_net.rx_bytes_read].to_vec()
+                            ),
+                        rx_deferred_frame
+                    );
+                    assert_eq!(restored_net.dhcp_config(), dhcp.as_ref());
+                    assert_eq!(restored_net.port_forwards(), port_forwards);
+                    assert_eq!(restored_net.link_up(), link_up);
                 }
                 Err(NetPersistError::NoMmdsDataStore) => {
                     assert!(has_mmds_ns && !allow_mmds_requests)
@@ -210,7 +359,43 @@ mod tests {
     fn test_persistence() {
         let mmds = Some(Arc::new(Mutex::new(Mmds::default())));
         validate_save_and_restore(default_net(), mmds.as_ref().cloned());
-        validate_save_and_restore(default_net_no_mmds(), None);
+        let mut net = default_net_no_mmds();
+        net.rx_filter.promisc = false;
+        net.rx_filter.vlans.insert(100);
+        net.peer_id = Some("peer".to_string());
+        net.set_interrupt_coalescing(
+            Some(InterruptCoalescingConfig {
+                max_frames: 8,
+                max_usecs: 100,
+            }),
+            None,
+        );
+        // A frame read from the TAP is waiting for RX buffers.
+        net.rx_frame_buf[..4].copy_from_slice(&[1, 2, 3, 4]);
+        net.rx_bytes_read = 4;
+        net.rx_deferred_frame = true;
+        net.set_dhcp_config(Some(DhcpConfig {
+            ip_address: Ipv4Addr::new(192, 168, 0, 2),
+            prefix_length: 24,
+            gateway: None,
+            nameservers: vec![],
+        }));
+        // The restored device listens on another ephemeral port.
+        net.set_port_forwards(vec![PortForwardConfig {
+            iface_id: "net-id".to_string(),
+            host_address: Ipv4Addr::LOCALHOST,
+            host_port: 0,
+            guest_address: Ipv4Addr::new(192, 168, 0, 2),
+            guest_port: 80,
+        }])
+        .unwrap();
+        net.set_link_up(false).unwrap();
+        let features = net.save().snapshot_features();
+        assert!(features.contains(&SnapshotFeature::NetRxDeferredFrame));
+        assert!(features.contains(&SnapshotFeature::NetDhcp));
+        assert!(features.contains(&SnapshotFeature::NetPortForward));
+        assert!(features.contains(&SnapshotFeature::NetLinkDown));
+        validate_save_and_restore(net, None);
 
         // Check what happens if the MMIODeviceManager gives us the reference to the MMDS
         // data store even if this device does not have mmds ns configured.
@@ -0,0 +1,856 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Forwards the TCP connections accepted on host ports to ports of the guest.
+//!
+//! Each connection accepted on a host socket is proxied through a TCP connection which the dumbo
+//! stack opens with the guest. The frames of these connections are exchanged with the guest
+//! through the network device, like the MMDS frames, so the host doesn't need any NAT or routing
+//! configuration. The guest sees the connections coming from a link local address, which must be
+//! routed through the network interface.
+
+use std::cmp::min;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddrV4, TcpListener, TcpStream};
+use std::num::{NonZeroU16, NonZeroU64, NonZeroUsize, Wrapping};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::str::FromStr;
+use std::time::Duration;
+
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
+use utils::net::mac::MacAddr;
+use utils::time::timestamp_cycles;
+
+use crate::dumbo::pdu::arp::{EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN};
+use crate::dumbo::pdu::ethernet::{EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use crate::dumbo::pdu::ipv4::{IPv4Packet, PROTOCOL_TCP};
+use crate::dumbo::pdu::tcp::TcpSegment;
+use crate::dumbo::tcp::connection::Connection;
+use crate::dumbo::tcp::seq_after;
+use crate::logger::warn;
+use crate::vmm_config::port_forward::PortForwardConfig;
+
+/// Source address of the connections forwarded to the guest.
+pub const PROXY_IPV4_ADDR: Ipv4Addr = Ipv4Addr::new(169, 254, 169, 252);
+/// Maximum number of connections forwarded at the same time by a network device.
+pub const MAX_CONNECTIONS: usize = 64;
+
+// Source MAC address of the frames of the proxy.
+const PROXY_MAC_ADDR: &str = "06:01:23:45:67:03";
+// Size of the buffers of the connections in each direction, which is also the receive window
+// advertised to the guest.
+const BUF_SIZE: usize = u16::MAX as usize;
+// Maximum segment size of the connections with the guest.
+const MSS: u16 = 1460;
+// The guest sees the connections coming from the ephemeral ports.
+const FIRST_LOCAL_PORT: u16 = 49152;
+// Retransmission settings of the connections, which are the ones of the MMDS.
+const RTO_PERIOD: u64 = 1_200_000_000;
+const RTO_COUNT_MAX: u16 = 15;
+// While connections are open, the proxy wakes up periodically to retransmit the lost segments
+// and ARP requests.
+const TICK_PERIOD: Duration = Duration::from_millis(200);
+// Number of ticks after which the connections to a guest address which doesn't answer the ARP
+// requests are dropped.
+const ARP_TICKS_MAX: u32 = 25;
+const EPOLL_EVENTS_LEN: usize = 32;
+
+/// Errors of the port forwarding proxy.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum PortForwardError {
+    /// Cannot listen on {0}: {1}
+    Listen(SocketAddrV4, io::Error),
+    /// Cannot create the epoll instance of the proxy: {0}
+    Epoll(io::Error),
+    /// Cannot create the timer of the proxy: {0}
+    Timer(io::Error),
+}
+
+// File descriptors polled by the proxy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Timer,
+    Listener(usize),
+    Connection(u16),
+}
+
+// A host connection, and the connection with the guest it is forwarded to.
+#[derive(Debug)]
+struct ForwardedConnection {
+    stream: TcpStream,
+    guest_addr: SocketAddrV4,
+    connection: Connection,
+    // Bytes read from the host which have not been acknowledged by the guest yet.
+    to_guest: Vec<u8>,
+    // Sequence number of the first byte of `to_guest`.
+    to_guest_seq: Wrapping<u32>,
+    // Bytes received from the guest which have not been written to the host yet.
+    to_host: Box<[u8]>,
+    to_host_len: usize,
+    // The host stream is polled in edge triggered mode, so we have to remember whether it can be
+    // read or written without blocking.
+    host_readable: bool,
+    host_writable: bool,
+    host_eof: bool,
+    host_shut_down: bool,
+}
+
+impl ForwardedConnection {
+    fn new(stream: TcpStream, guest_addr: SocketAddrV4) -> Self {
+        // The unwraps are safe because the values are not zero.
+        let connection = Connection::active_open(
+            NonZeroU16::new(MSS).unwrap(),
+            u32::from(u16::MAX),
+            NonZeroU64::new(RTO_PERIOD).unwrap(),
+            NonZeroU16::new(RTO_COUNT_MAX).unwrap(),
+        );
+        ForwardedConnection {
+            stream,
+            guest_addr,
+            to_guest_seq: connection.first_not_sent(),
+            connection,
+            to_guest: Vec::with_capacity(BUF_SIZE),
+            to_host: vec![0u8; BUF_SIZE].into_boxed_slice(),
+            to_host_len: 0,
+            host_readable: false,
+            host_writable: false,
+            host_eof: false,
+            host_shut_down: false,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.connection.is_done()
+    }
+
+    // Reads from the host as much as the buffer of the bytes sent to the guest can hold.
+    fn read_from_host(&mut self) {
+        while self.host_readable && !self.host_eof && self.to_guest.len() < BUF_SIZE {
+            let len = self.to_guest.len();
+            self.to_guest.resize(BUF_SIZE, 0);
+            let res = self.stream.read(&mut self.to_guest[len..]);
+            match res {
+                Ok(0) => {
+                    self.to_guest.truncate(len);
+                    self.host_eof = true;
+                }
+                Ok(count) => self.to_guest.truncate(len + count),
+                Err(err) => {
+                    self.to_guest.truncate(len);
+                    self.host_readable = false;
+                    if err.kind() != io::ErrorKind::WouldBlock {
+                        warn!("port forward: cannot read from the host: {}", err);
+                        self.connection.reset();
+                    }
+                }
+            }
+        }
+    }
+
+    // Writes to the host the bytes received from the guest, which opens the receive window of the
+    // connection with the guest.
+    fn flush_to_host(&mut self) {
+        while self.host_writable && self.to_host_len > 0 {
+            match self.stream.write(&self.to_host[..self.to_host_len]) {
+                Ok(count) => {
+                    self.to_host.copy_within(count..self.to_host_len, 0);
+                    self.to_host_len -= count;
+                    // The unwrap is safe because count is at most BUF_SIZE.
+                    self.connection
+                        .advance_local_rwnd_edge(u32::try_from(count).unwrap());
+                }
+                Err(err) => {
+                    self.host_writable = false;
+                    if err.kind() != io::ErrorKind::WouldBlock {
+                        warn!("port forward: cannot write to the host: {}", err);
+                        self.connection.reset();
+                    }
+                }
+            }
+        }
+
+        // The guest closed its half of the connection, and everything it sent reached the host.
+        if self.connection.fin_received() && self.to_host_len == 0 && !self.host_shut_down {
+            let _ = self.stream.shutdown(Shutdown::Write);
+            self.host_shut_down = true;
+        }
+    }
+
+    fn receive_segment(&mut self, s: &TcpSegment<&[u8]>) {
+        let res = self.connection.receive_segment(
+            s,
+            &mut self.to_host[self.to_host_len..],
+            timestamp_cycles(),
+        );
+        if let Ok((Some(len), _)) = res {
+            self.to_host_len += len.get();
+        }
+
+        // Forget the bytes acknowledged by the guest.
+        let highest_ack = self.connection.highest_ack_received();
+        if seq_after(highest_ack, self.to_guest_seq) {
+            let acked = min(
+                (highest_ack - self.to_guest_seq).0 as usize,
+                self.to_guest.len(),
+            );
+            self.to_guest.drain(..acked);
+            // The unwrap is safe because acked is at most BUF_SIZE.
+            self.to_guest_seq += Wrapping(u32::try_from(acked).unwrap());
+        }
+
+        self.flush_to_host();
+        self.read_from_host();
+    }
+
+    // Writes the next segment to the guest in `buf`, which holds the payload of an IPv4 packet,
+    // and returns its length.
+    fn write_next_segment(&mut self, buf: &mut [u8], local_port: u16) -> Option<u16> {
+        // The FIN follows the last byte read from the host.
+        // The unwrap is safe because the buffer holds at most BUF_SIZE bytes.
+        let to_guest_end =
+            self.to_guest_seq + Wrapping(u32::try_from(self.to_guest.len()).unwrap());
+        if self.host_eof
+            && self.connection.is_established()
+            && self.connection.first_not_sent() == to_guest_end
+        {
+            self.connection.close();
+        }
+
+        let payload_src = if self.to_guest.is_empty() {
+            None
+        } else {
+            Some((self.to_guest.as_slice(), self.to_guest_seq))
+        };
+        let segment =
+            match self
+                .connection
+                .write_next_segment(buf, 0, payload_src, timestamp_cycles())
+            {
+                Ok(segment) => segment?,
+                Err(err) => {
+                    if !self.is_done() {
+                        warn!("port forward: cannot write a segment: {}", err);
+                        self.connection.reset();
+                    }
+                    return None;
+                }
+            };
+        let segment_len = segment
+            .finalize(
+                local_port,
+                self.guest_addr.port(),
+                Some((PROXY_IPV4_ADDR, *self.guest_addr.ip())),
+            )
+            .len();
+        Some(segment_len)
+    }
+}
+
+/// Proxy forwarding the TCP connections accepted on host ports to the guest of a network device.
+#[derive(Debug)]
+pub struct PortForwarder {
+    configs: Vec<PortForwardConfig>,
+    listeners: Vec<TcpListener>,
+    // Forwarded connections, by the port they use on the side of the proxy.
+    connections: BTreeMap<u16, ForwardedConnection>,
+    next_local_port: u16,
+    epoll: Epoll,
+    sources: HashMap<RawFd, Source>,
+    timer: TimerFd,
+    timer_armed: bool,
+    mac_addr: MacAddr,
+    // MAC addresses of the guest addresses.
+    neighbors: HashMap<Ipv4Addr, MacAddr>,
+    pending_arp_requests: BTreeSet<Ipv4Addr>,
+    pending_arp_reply: Option<(MacAddr, Ipv4Addr)>,
+    arp_ticks: u32,
+}
+
+impl PortForwarder {
+    /// Creates a proxy listening on the host addresses of `configs`.
+    pub fn new(configs: Vec<PortForwardConfig>) -> Result<Self, PortForwardError> {
+        let epoll = Epoll::new().map_err(PortForwardError::Epoll)?;
+        let timer =
+            TimerFd::new_custom(ClockId::Monotonic, true, true).map_err(PortForwardError::Timer)?;
+        let mut forwarder = PortForwarder {
+            configs: Vec::new(),
+            listeners: Vec::new(),
+            connections: BTreeMap::new(),
+            next_local_port: FIRST_LOCAL_PORT,
+            epoll,
+            sources: HashMap::new(),
+            timer,
+            timer_armed: false,
+            mac_addr: MacAddr::from_str(PROXY_MAC_ADDR).unwrap(),
+            neighbors: HashMap::new(),
+            pending_arp_requests: BTreeSet::new(),
+            pending_arp_reply: None,
+            arp_ticks: 0,
+        };
+        forwarder
+            .add_source(forwarder.timer.as_raw_fd(), Source::Timer, EventSet::IN)
+            .map_err(PortForwardError::Epoll)?;
+
+        for config in configs.iter() {
+            let addr = config.host_socket_addr();
+            let listener = TcpListener::bind(addr)
+                .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+                .map_err(|err| PortForwardError::Listen(addr, err))?;
+            forwarder
+                .add_source(
+                    listener.as_raw_fd(),
+                    Source::Listener(forwarder.listeners.len()),
+                    EventSet::IN,
+                )
+                .map_err(PortForwardError::Epoll)?;
+            forwarder.listeners.push(listener);
+        }
+        forwarder.configs = configs;
+        Ok(forwarder)
+    }
+
+    /// Returns the forwarded ports.
+    pub fn configs(&self) -> &[PortForwardConfig] {
+        &self.configs
+    }
+
+    fn add_source(&mut self, fd: RawFd, source: Source, evset: EventSet) -> io::Result<()> {
+        self.epoll.ctl(
+            ControlOperation::Add,
+            fd,
+            EpollEvent::new(evset, u64::try_from(fd).unwrap()),
+        )?;
+        self.sources.insert(fd, source);
+        Ok(())
+    }
+
+    fn remove_connection(&mut self, local_port: u16) {
+        if let Some(conn) = self.connections.remove(&local_port) {
+            let fd = conn.stream.as_raw_fd();
+            self.sources.remove(&fd);
+            if let Err(err) = self
+                .epoll
+                .ctl(ControlOperation::Delete, fd, EpollEvent::default())
+            {
+                warn!("port forward: cannot stop polling a connection: {}", err);
+            }
+        }
+    }
+
+    /// Handles the events of the host sockets and of the timer of the proxy, which is polled
+    /// through the nested epoll file descriptor returned by `as_raw_fd`.
+    pub fn process_host_events(&mut self) {
+        let mut events = [EpollEvent::default(); EPOLL_EVENTS_LEN];
+        loop {
+            let count = match self.epoll.wait(0, &mut events) {
+                Ok(count) => count,
+                Err(err) => {
+                    warn!("port forward: cannot poll the host sockets: {}", err);
+                    return;
+                }
+            };
+            for event in &events[..count] {
+                // The unwrap is safe because the events are filled in by the kernel.
+                let evset = EventSet::from_bits(event.events).unwrap();
+                match self.sources.get(&event.fd()).copied() {
+                    Some(Source::Timer) => self.process_tick(),
+                    Some(Source::Listener(index)) => self.accept_connections(index),
+                    Some(Source::Connection(local_port)) => {
+                        self.process_connection_event(local_port, evset)
+                    }
+                    None => (),
+                }
+            }
+            if count < EPOLL_EVENTS_LEN {
+                return;
+            }
+        }
+    }
+
+    fn accept_connections(&mut self, index: usize) {
+        loop {
+            let stream = match self.listeners[index].accept() {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    if err.kind() != io::ErrorKind::WouldBlock {
+                        warn!("port forward: cannot accept a connection: {}", err);
+                    }
+                    return;
+                }
+            };
+            if self.connections.len() >= MAX_CONNECTIONS {
+                warn!("port forward: too many connections, dropping a new one");
+                continue;
+            }
+            if let Err(err) = stream.set_nonblocking(true) {
+                warn!("port forward: cannot set up a connection: {}", err);
+                continue;
+            }
+
+            let local_port = self.next_free_local_port();
+            let evset =
+                EventSet::IN | EventSet::OUT | EventSet::READ_HANG_UP | EventSet::EDGE_TRIGGERED;
+            if let Err(err) =
+                self.add_source(stream.as_raw_fd(), Source::Connection(local_port), evset)
+            {
+                warn!("port forward: cannot poll a connection: {}", err);
+                continue;
+            }
+            let guest_addr = self.configs[index].guest_socket_addr();
+            self.connections
+                .insert(local_port, ForwardedConnection::new(stream, guest_addr));
+            if !self.neighbors.contains_key(guest_addr.ip()) {
+                self.pending_arp_requests.insert(*guest_addr.ip());
+            }
+            self.arm_timer();
+        }
+    }
+
+    fn next_free_local_port(&mut self) -> u16 {
+        // There is always a free port because the number of connections is limited.
+        while self.connections.contains_key(&self.next_local_port) {
+            self.next_local_port = self
+                .next_local_port
+                .checked_add(1)
+                .unwrap_or(FIRST_LOCAL_PORT);
+        }
+        let local_port = self.next_local_port;
+        self.next_local_port = local_port.checked_add(1).unwrap_or(FIRST_LOCAL_PORT);
+        local_port
+    }
+
+    fn process_connection_event(&mut self, local_port: u16, evset: EventSet) {
+        let Some(conn) = self.connections.get_mut(&local_port) else {
+            return;
+        };
+        if evset.intersects(EventSet::ERROR) {
+            conn.connection.reset();
+            return;
+        }
+        if evset.intersects(EventSet::IN | EventSet::READ_HANG_UP | EventSet::HANG_UP) {
+            conn.host_readable = true;
+        }
+        if evset.contains(EventSet::OUT) {
+            conn.host_writable = true;
+        }
+        conn.flush_to_host();
+        conn.read_from_host();
+    }
+
+    fn arm_timer(&mut self) {
+        if !self.timer_armed {
+            self.timer.set_state(
+                TimerState::Periodic {
+                    current: TICK_PERIOD,
+                    interval: TICK_PERIOD,
+                },
+                SetTimeFlags::Default,
+            );
+            self.timer_armed = true;
+        }
+    }
+
+    fn process_tick(&mut self) {
+        self.timer.read();
+        if self.connections.is_empty() {
+            self.timer
+                .set_state(TimerState::Disarmed, SetTimeFlags::Default);
+            self.timer_armed = false;
+            return;
+        }
+
+        // Ask again for the MAC addresses of the guest which are still unknown, and give up on
+        // the connections to them after a while.
+        let unresolved: Vec<(u16, Ipv4Addr)> = self
+            .connections
+            .iter()
+            .map(|(local_port, conn)| (*local_port, *conn.guest_addr.ip()))
+            .filter(|(_, addr)| !self.neighbors.contains_key(addr))
+            .collect();
+        if unresolved.is_empty() {
+            self.arp_ticks = 0;
+            return;
+        }
+        self.arp_ticks += 1;
+        for (local_port, addr) in unresolved {
+            if self.arp_ticks > ARP_TICKS_MAX {
+                warn!(
+                    "port forward: {} does not answer, dropping a connection",
+                    addr
+                );
+                self.remove_connection(local_port);
+            } else {
+                self.pending_arp_requests.insert(addr);
+            }
+        }
+        if self.arp_ticks > ARP_TICKS_MAX {
+            self.arp_ticks = 0;
+        }
+    }
+
+    /// Returns whether `src` is an ARP or IPv4 frame addressed to the proxy. It does not consume
+    /// the frame, and only needs its headers.
+    pub fn is_port_forward_frame(src: &[u8]) -> bool {
+        let Ok(eth) = EthernetFrame::from_bytes(src) else {
+            return false;
+        };
+        let payload = eth.payload();
+        match eth.ethertype() {
+            ETHERTYPE_ARP => {
+                payload.len() >= ETH_IPV4_FRAME_LEN
+                    && EthIPv4ArpFrame::from_bytes_unchecked(payload).tpa() == PROXY_IPV4_ADDR
+            }
+            ETHERTYPE_IPV4 => payload.get(16..20) == Some(PROXY_IPV4_ADDR.octets().as_slice()),
+            _ => false,
+        }
+    }
+
+    /// Handles a frame for which `is_port_forward_frame` returned true.
+    ///
+    /// Returns whether the frame was accepted by the proxy.
+    pub fn detour_frame(&mut self, src: &[u8]) -> bool {
+        let Ok(eth) = EthernetFrame::from_bytes(src) else {
+            return false;
+        };
+        match eth.ethertype() {
+            ETHERTYPE_ARP => self.detour_arp(&eth),
+            ETHERTYPE_IPV4 => self.detour_ipv4(&eth),
+            _ => false,
+        }
+    }
+
+    fn detour_arp(&mut self, eth: &EthernetFrame<&[u8]>) -> bool {
+        let Some(bytes) = eth.payload().get(..ETH_IPV4_FRAME_LEN) else {
+            return false;
+        };
+        let (arp, is_request) = match EthIPv4ArpFrame::request_from_bytes(bytes) {
+            Ok(arp) => (arp, true),
+            Err(_) => match EthIPv4ArpFrame::reply_from_bytes(bytes) {
+                Ok(arp) => (arp, false),
+                Err(_) => return false,
+            },
+        };
+        if arp.tpa() != PROXY_IPV4_ADDR {
+            return false;
+        }
+        if self.configs.iter().any(|c| c.guest_address == arp.spa()) {
+            self.neighbors.insert(arp.spa(), arp.sha());
+        }
+        if is_request {
+            self.pending_arp_reply = Some((arp.sha(), arp.spa()));
+        }
+        true
+    }
+
+    fn detour_ipv4(&mut self, eth: &EthernetFrame<&[u8]>) -> bool {
+        // The checksums are not verified, because the guest may offload their computation.
+        let Ok(packet) = IPv4Packet::from_bytes(eth.payload(), false) else {
+            return false;
+        };
+        if packet.protocol() != PROTOCOL_TCP {
+            return false;
+        }
+        let Ok(segment) = TcpSegment::from_bytes(packet.payload(), None) else {
+            return false;
+        };
+        let guest_addr = SocketAddrV4::new(packet.source_address(), segment.source_port());
+        let Some(conn) = self
+            .connections
+            .get_mut(&segment.destination_port())
+            .filter(|conn| conn.guest_addr == guest_addr)
+        else {
+            return false;
+        };
+        conn.receive_segment(&segment);
+        self.neighbors.insert(*guest_addr.ip(), eth.src_mac());
+        true
+    }
+
+    /// Writes the next frame to send to the guest in `buf`, if any, and returns its length.
+    pub fn write_next_frame(&mut self, buf: &mut [u8]) -> Option<NonZeroUsize> {
+        if let Some((dst_mac, dst_addr)) = self.pending_arp_reply.take() {
+            return self.write_arp_frame(buf, dst_mac, dst_addr, true);
+        }
+        if let Some(dst_addr) = self.pending_arp_requests.pop_first() {
+            let broadcast = MacAddr::from_bytes_unchecked(&[0xff; 6]);
+            return self.write_arp_frame(buf, broadcast, dst_addr, false);
+        }
+
+        let local_ports: Vec<u16> = self.connections.keys().copied().collect();
+        for local_port in local_ports {
+            // The unwrap is safe because the port is a key of the map.
+            let conn = self.connections.get_mut(&local_port).unwrap();
+            let Some(&dst_mac) = self.neighbors.get(conn.guest_addr.ip()) else {
+                continue;
+            };
+            let dst_addr = *conn.guest_addr.ip();
+            let len =
+                EthernetFrame::write_incomplete(&mut *buf, dst_mac, self.mac_addr, ETHERTYPE_IPV4)
+                    .ok()
+                    .and_then(|mut eth| {
+                        let mut packet = IPv4Packet::write_header(
+                            eth.inner_mut().payload_mut(),
+                            PROTOCOL_TCP,
+                            PROXY_IPV4_ADDR,
+                            dst_addr,
+                        )
+                        .ok()?;
+                        let segment_len =
+                            conn.write_next_segment(packet.inner_mut().payload_mut(), local_port)?;
+                        let packet_len = packet.with_payload_len_unchecked(segment_len, true).len();
+                        NonZeroUsize::new(eth.with_payload_len_unchecked(packet_len).len())
+                    });
+            if conn.is_done() {
+                self.remove_connection(local_port);
+            }
+            if len.is_some() {
+                return len;
+            }
+        }
+        None
+    }
+
+    fn write_arp_frame(
+        &self,
+        buf: &mut [u8],
+        dst_mac: MacAddr,
+        dst_addr: Ipv4Addr,
+        is_reply: bool,
+    ) -> Option<NonZeroUsize> {
+        let mut eth =
+            EthernetFrame::write_incomplete(buf, dst_mac, self.mac_addr, ETHERTYPE_ARP).ok()?;
+        let arp_buf = eth
+            .inner_mut()
+            .payload_mut()
+            .get_mut(..ETH_IPV4_FRAME_LEN)?;
+        let arp_len = if is_reply {
+            EthIPv4ArpFrame::write_reply(arp_buf, self.mac_addr, PROXY_IPV4_ADDR, dst_mac, dst_addr)
+        } else {
+            let unknown = MacAddr::from_bytes_unchecked(&[0; 6]);
+            EthIPv4ArpFrame::write_request(
+                arp_buf,
+                self.mac_addr,
+                PROXY_IPV4_ADDR,
+                unknown,
+                dst_addr,
+            )
+        }
+        .ok()?
+        .len();
+        NonZeroUsize::new(eth.with_payload_len_unchecked(arp_len).len())
+    }
+}
+
+impl AsRawFd for PortForwarder {
+    fn as_raw_fd(&self) -> RawFd {
+        self.epoll.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::dumbo::pdu::tcp::Flags as TcpFlags;
+
+    const GUEST_MAC_ADDR: &str = "06:00:00:00:00:01";
+    const GUEST_ADDR: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 2);
+    const GUEST_PORT: u16 = 80;
+
+    fn write_guest_segment(
+        buf: &mut [u8],
+        dst_port: u16,
+        seq: u32,
+        ack: u32,
+        flags: TcpFlags,
+        payload: &[u8],
+    ) -> usize {
+        let guest_mac = MacAddr::from_str(GUEST_MAC_ADDR).unwrap();
+        let proxy_mac = MacAddr::from_str(PROXY_MAC_ADDR).unwrap();
+        let mut eth =
+            EthernetFrame::write_incomplete(buf, proxy_mac, guest_mac, ETHERTYPE_IPV4).unwrap();
+        let mut packet = IPv4Packet::write_header(
+            eth.inner_mut().payload_mut(),
+            PROTOCOL_TCP,
+            GUEST_ADDR,
+            PROXY_IPV4_ADDR,
+        )
+        .unwrap();
+        let segment_len = TcpSegment::write_segment(
+            packet.inner_mut().payload_mut(),
+            GUEST_PORT,
+            dst_port,
+            seq,
+            ack,
+            flags,
+            10000,
+            if flags.contains(TcpFlags::SYN) {
+                Some(MSS)
+            } else {
+                None
+            },
+            MSS,
+            (!payload.is_empty()).then_some((payload, payload.len())),
+            Some((GUEST_ADDR, PROXY_IPV4_ADDR)),
+        )
+        .unwrap()
+        .len();
+        let packet_len = packet.with_payload_len_unchecked(segment_len, true).len();
+        eth.with_payload_len_unchecked(packet_len).len()
+    }
+
+    fn read_segment(frame: &[u8]) -> (u16, u32, u32, TcpFlags, Vec<u8>) {
+        let eth = EthernetFrame::from_bytes(frame).unwrap();
+        assert_eq!(eth.dst_mac(), MacAddr::from_str(GUEST_MAC_ADDR).unwrap());
+        let packet = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+        assert_eq!(packet.source_address(), PROXY_IPV4_ADDR);
+        assert_eq!(packet.destination_address(), GUEST_ADDR);
+        let segment =
+            TcpSegment::from_bytes(packet.payload(), Some((PROXY_IPV4_ADDR, GUEST_ADDR))).unwrap();
+        assert_eq!(segment.destination_port(), GUEST_PORT);
+        (
+            segment.source_port(),
+            segment.sequence_number(),
+            segment.ack_number(),
+            segment.flags_after_ns(),
+            segment.payload().to_vec(),
+        )
+    }
+
+    fn next_frame(forwarder: &mut PortForwarder, buf: &mut [u8]) -> usize {
+        forwarder.write_next_frame(buf).unwrap().get()
+    }
+
+    #[test]
+    fn test_port_forward() {
+        let config = PortForwardConfig {
+            iface_id: "eth0".to_string(),
+            host_address: Ipv4Addr::LOCALHOST,
+            host_port: 0,
+            guest_address: GUEST_ADDR,
+            guest_port: GUEST_PORT,
+        };
+        let mut forwarder = PortForwarder::new(vec![config.clone()]).unwrap();
+        assert_eq!(forwarder.configs(), &[config]);
+        let host_addr = forwarder.listeners[0].local_addr().unwrap();
+        let mut buf = [0u8; 2000];
+        let mut frame = [0u8; 2000];
+        assert!(forwarder.write_next_frame(&mut buf).is_none());
+
+        // The guest asks for the MAC address of the proxy.
+        let guest_mac = MacAddr::from_str(GUEST_MAC_ADDR).unwrap();
+        let len = {
+            let mut eth = EthernetFrame::write_incomplete(
+                frame.as_mut(),
+                MacAddr::from_bytes_unchecked(&[0xff; 6]),
+                guest_mac,
+                ETHERTYPE_ARP,
+            )
+            .unwrap();
+            let arp_len = EthIPv4ArpFrame::write_request(
+                &mut eth.inner_mut().payload_mut()[..ETH_IPV4_FRAME_LEN],
+                guest_mac,
+                GUEST_ADDR,
+                MacAddr::from_bytes_unchecked(&[0; 6]),
+                PROXY_IPV4_ADDR,
+            )
+            .unwrap()
+            .len();
+            eth.with_payload_len_unchecked(arp_len).len()
+        };
+        assert!(PortForwarder::is_port_forward_frame(&frame[..len]));
+        assert!(forwarder.detour_frame(&frame[..len]));
+        let len = next_frame(&mut forwarder, &mut buf);
+        let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+        let arp = EthIPv4ArpFrame::reply_from_bytes(eth.payload()).unwrap();
+        assert_eq!(arp.spa(), PROXY_IPV4_ADDR);
+        assert_eq!(arp.tha(), guest_mac);
+        assert_eq!(arp.tpa(), GUEST_ADDR);
+
+        // A host connection makes the proxy open a connection with the guest.
+        let mut host = TcpStream::connect(host_addr).unwrap();
+        host.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        forwarder.process_host_events();
+        assert_eq!(forwarder.connections.len(), 1);
+        let len = next_frame(&mut forwarder, &mut buf);
+        let (local_port, isn, _, flags, _) = read_segment(&buf[..len]);
+        assert_eq!(flags, TcpFlags::SYN);
+        assert!(forwarder.write_next_frame(&mut buf).is_none());
+
+        let guest_isn = 1000;
+        let len = write_guest_segment(
+            &mut frame,
+            local_port,
+            guest_isn,
+            isn.wrapping_add(1),
+            TcpFlags::SYN | TcpFlags::ACK,
+            &[],
+        );
+        assert!(PortForwarder::is_port_forward_frame(&frame[..len]));
+        assert!(forwarder.detour_frame(&frame[..len]));
+        let len = next_frame(&mut forwarder, &mut buf);
+        let (_, _, ack, flags, _) = read_segment(&buf[..len]);
+        assert_eq!(flags, TcpFlags::ACK);
+        assert_eq!(ack, guest_isn + 1);
+
+        // The bytes sent by the host reach the guest.
+        host.write_all(b"hello").unwrap();
+        // Wait for the bytes to be readable.
+        std::thread::sleep(Duration::from_millis(50));
+        forwarder.process_host_events();
+        let len = next_frame(&mut forwarder, &mut buf);
+        let (_, seq, _, _, payload) = read_segment(&buf[..len]);
+        assert_eq!(seq, isn.wrapping_add(1));
+        assert_eq!(payload, b"hello");
+
+        // The bytes sent by the guest reach the host.
+        let len = write_guest_segment(
+            &mut frame,
+            local_port,
+            guest_isn + 1,
+            isn.wrapping_add(6),
+            TcpFlags::ACK,
+            b"world",
+        );
+        assert!(forwarder.detour_frame(&frame[..len]));
+        let mut received = [0u8; 5];
+        host.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"world");
+        assert!(forwarder.connections[&local_port].to_guest.is_empty());
+
+        // A reset from the guest closes the host connection.
+        let len = write_guest_segment(&mut frame, local_port, guest_isn + 6, 0, TcpFlags::RST, &[]);
+        assert!(forwarder.detour_frame(&frame[..len]));
+        while forwarder.write_next_frame(&mut buf).is_some() {}
+        assert!(forwarder.connections.is_empty());
+        assert_eq!(host.read(&mut received).unwrap(), 0);
+
+        // Frames which are not addressed to the proxy go through.
+        let len = write_guest_segment(&mut frame, local_port, 0, 0, TcpFlags::SYN, &[]);
+        frame[30..34].copy_from_slice(&[192, 168, 0, 1]);
+        assert!(!PortForwarder::is_port_forward_frame(&frame[..len]));
+    }
+
+    #[test]
+    fn test_port_forward_listen_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = match listener.local_addr().unwrap() {
+            std::net::SocketAddr::V4(addr) => addr,
+            std::net::SocketAddr::V6(_) => unreachable!(),
+        };
+        let config = PortForwardConfig {
+            iface_id: "eth0".to_string(),
+            host_address: *addr.ip(),
+            host_port: addr.port(),
+            guest_address: GUEST_ADDR,
+            guest_port: GUEST_PORT,
+        };
+        assert!(matches!(
+            PortForwarder::new(vec![config]),
+            Err(PortForwardError::Listen(listen_addr, _)) if listen_addr == addr
+        ));
+    }
+}
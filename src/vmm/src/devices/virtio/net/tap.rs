@@ -53,6 +53,8 @@ ioctl_iow_nr!(TUNSETVNETHDRSZ, TUNTAP, 216, ::std::os::raw::c_int);
 pub struct Tap {
     tap_file: File,
     pub(crate) if_name: [u8; IFACE_NAME_MAX_LEN],
+    // Whether the writes to the tap fail, to inject faults.
+    pub(crate) write_fault: bool,
 
     #[cfg(test)]
     pub(crate) mocks: Mocks,
@@ -149,12 +151,26 @@ impl Tap {
             tap_file: tuntap,
             // SAFETY: Safe since only the name is accessed, and it's cloned out.
             if_name: unsafe { ifreq.ifr_ifrn.ifrn_name },
+            write_fault: false,
 
             #[cfg(test)]
             mocks: Mocks::default(),
         })
     }
 
+    /// Create a handle to the TUN/TAP device already opened as `tap_file`. The name of the
+    /// interface is left empty.
+    pub(crate) fn from_file(tap_file: File) -> Tap {
+        Tap {
+            tap_file,
+            if_name: [0; IFACE_NAME_MAX_LEN],
+            write_fault: false,
+
+            #[cfg(test)]
+            mocks: Mocks::default(),
+        }
+    }
+
     /// Retrieve the interface's name as a str.
     pub fn if_name_as_str(&self) -> &str {
         let len = self
@@ -187,6 +203,10 @@ impl Tap {
 
     /// Write an `IoVecBuffer` to tap
     pub(crate) fn write_iovec(&mut self, buffer: &IoVecBuffer) -> Result<usize, IoError> {
+        if self.write_fault {
+            return Err(IoError::from_raw_os_error(libc::EIO));
+        }
+
         let iovcnt = i32::try_from(buffer.iovec_count()).unwrap();
         let iov = buffer.as_iovec_ptr();
 
@@ -288,6 +308,7 @@ pub mod tests {
         let faulty_tap = Tap {
             tap_file: unsafe { File::from_raw_fd(-2) },
             if_name: [0x01; 16],
+            write_fault: false,
             mocks: Default::default(),
         };
         assert_eq!(
@@ -378,4 +399,23 @@ pub mod tests {
             fragment3
         );
     }
+
+    #[test]
+    fn test_write_iovec_fault() {
+        let mut tap = Tap::open_named("").unwrap();
+        enable(&tap);
+
+        let mut fragment = utils::rand::rand_bytes(PAYLOAD_SIZE);
+        fragment.as_mut_slice()[..gen::ETH_HLEN as usize]
+            .copy_from_slice(&[0; gen::ETH_HLEN as usize]);
+        let buffer = IoVecBuffer::from(vec![fragment.as_slice()]);
+
+        tap.write_fault = true;
+        assert_eq!(
+            tap.write_iovec(&buffer).unwrap_err().raw_os_error(),
+            Some(libc::EIO)
+        );
+        tap.write_fault = false;
+        tap.write_iovec(&buffer).unwrap();
+    }
 }
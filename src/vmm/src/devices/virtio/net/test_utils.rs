@@ -131,6 +131,7 @@ impl Default for Mocks {
 pub enum NetQueue {
     Rx,
     Tx,
+    Ctrl,
 }
 
 #[derive(Debug)]
@@ -140,6 +141,7 @@ pub enum NetEvent {
     Tap,
     TxQueue,
     TxRateLimiter,
+    CtrlQueue,
 }
 
 #[derive(Debug)]
@@ -338,10 +340,11 @@ pub fn set_mac(net: &mut Net, mac: MacAddr) {
 }
 
 // Assigns "guest virtio driver" activated queues to the net device.
-pub fn assign_queues(net: &mut Net, rxq: Queue, txq: Queue) {
+pub fn assign_queues(net: &mut Net, rxq: Queue, txq: Queue, ctrlq: Queue) {
     net.queues.clear();
     net.queues.push(rxq);
     net.queues.push(txq);
+    net.queues.push(ctrlq);
 }
 
 #[cfg(test)]
@@ -360,7 +363,7 @@ pub mod test {
     use crate::devices::virtio::net::test_utils::{
         assign_queues, default_net, inject_tap_tx_frame, NetEvent, NetQueue, ReadTapMock,
     };
-    use crate::devices::virtio::net::{Net, MAX_BUFFER_SIZE, RX_INDEX, TX_INDEX};
+    use crate::devices::virtio::net::{Net, CTRL_INDEX, MAX_BUFFER_SIZE, RX_INDEX, TX_INDEX};
     use crate::devices::virtio::queue::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
     use crate::devices::virtio::test_utils::{VirtQueue, VirtqDesc};
     use crate::logger::IncMetric;
@@ -374,6 +377,7 @@ pub mod test {
         pub mem: GuestMemoryMmap,
         pub rxq: VirtQueue<'a>,
         pub txq: VirtQueue<'a>,
+        pub ctrlq: VirtQueue<'a>,
     }
 
     impl fmt::Debug for TestHelper<'_> {
@@ -385,6 +389,7 @@ pub mod test {
                 .field("mem", &self.mem)
                 .field("rxq", &self.rxq)
                 .field("txq", &self.txq)
+                .field("ctrlq", &self.ctrlq)
                 .finish()
         }
     }
@@ -406,7 +411,17 @@ pub mod test {
                 mem_ref,
                 Self::QUEUE_SIZE,
             );
-            assign_queues(&mut net, rxq.create_queue(), txq.create_queue());
+            let ctrlq = VirtQueue::new(
+                txq.end().unchecked_align_up(VirtqDesc::ALIGNMENT),
+                mem_ref,
+                Self::QUEUE_SIZE,
+            );
+            assign_queues(
+                &mut net,
+                rxq.create_queue(),
+                txq.create_queue(),
+                ctrlq.create_queue(),
+            );
 
             let net = Arc::new(Mutex::new(net));
             let subscriber_id = event_manager.add_subscriber(net.clone());
@@ -418,6 +433,7 @@ pub mod test {
                 mem,
                 rxq,
                 txq,
+                ctrlq,
             }
         }
 
@@ -439,11 +455,12 @@ pub mod test {
                 NetEvent::Tap => self.net().process_tap_rx_event(),
                 NetEvent::TxQueue => self.net().process_tx_queue_event(),
                 NetEvent::TxRateLimiter => self.net().process_tx_rate_limiter_event(),
+                NetEvent::CtrlQueue => self.net().process_ctrl_queue_event(),
             };
         }
 
         pub fn data_addr(&self) -> u64 {
-            self.txq.end().raw_value()
+            self.ctrlq.end().raw_value()
         }
 
         pub fn add_desc_chain(
@@ -457,6 +474,7 @@ pub mod test {
             let (queue, event_fd) = match queue {
                 NetQueue::Rx => (&self.rxq, &net.queue_evts[RX_INDEX]),
                 NetQueue::Tx => (&self.txq, &net.queue_evts[TX_INDEX]),
+                NetQueue::Ctrl => (&self.ctrlq, &net.queue_evts[CTRL_INDEX]),
             };
 
             // Create the descriptor chain.
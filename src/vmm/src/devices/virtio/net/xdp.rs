@@ -0,0 +1,660 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Exchanges the frames of a network device through an AF_XDP socket bound to a queue of a host
+//! interface, instead of a tap device.
+//!
+//! The socket only receives the frames an XDP program attached to the host interface redirects
+//! to it. Firecracker does not load the program: it inserts the socket in the XSKMAP of the
+//! program, pinned in a BPF filesystem by the host.
+//!
+//! The frames go through a UMEM allocated by Firecracker, as the buffers of the virtio queues
+//! are neither contiguous nor aligned on UMEM chunks. The socket is bound in zero-copy mode if
+//! the driver of the host interface supports it, so the frames are copied once, between the UMEM
+//! and the guest memory, as with a tap device.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::{io, mem};
+
+use serde::{Deserialize, Serialize};
+use utils::u64_to_usize;
+
+use crate::devices::virtio::iovec::IoVecBuffer;
+use crate::devices::virtio::net::device::vnet_hdr_len;
+
+/// Size of the UMEM chunks, which hold one frame each.
+pub const FRAME_SIZE: usize = 4096;
+// Number of descriptors of each ring of the socket.
+const RING_SIZE: u32 = 256;
+// The UMEM holds the frames of the fill and RX rings, then those of the TX and completion rings.
+const NUM_FRAMES: usize = 2 * RING_SIZE as usize;
+
+// Commands of the `bpf` syscall.
+const BPF_MAP_UPDATE_ELEM: libc::c_int = 2;
+const BPF_OBJ_GET: libc::c_int = 7;
+// Flag of `BPF_MAP_UPDATE_ELEM` creating or replacing the element.
+const BPF_ANY: u64 = 0;
+
+/// Configuration of the AF_XDP socket exchanging the frames of a network device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct XdpConfig {
+    /// Name of the host interface the socket is bound to.
+    pub if_name: String,
+    /// Queue of the host interface the socket is bound to.
+    #[serde(default)]
+    pub queue_id: u32,
+    /// Path of the XSKMAP of the XDP program redirecting the frames of the queue, pinned in a BPF
+    /// filesystem. The socket is inserted at index `queue_id`.
+    pub xsks_map: String,
+}
+
+/// Errors associated with the AF_XDP socket of a network device.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum XdpError {
+    /// Invalid host interface name: {0}
+    InvalidIfname(String),
+    /// Cannot find the host interface {0}: {1}
+    UnknownInterface(String, io::Error),
+    /// Cannot create the AF_XDP socket: {0}
+    Socket(io::Error),
+    /// Cannot register the UMEM of the AF_XDP socket: {0}
+    Umem(io::Error),
+    /// Cannot set up the rings of the AF_XDP socket: {0}
+    Rings(io::Error),
+    /// Cannot bind the AF_XDP socket to queue {1} of {0}: {2}
+    Bind(String, u32, io::Error),
+    /// Cannot open the XSKMAP {0}: {1}
+    OpenMap(String, io::Error),
+    /// Cannot insert the AF_XDP socket in the XSKMAP {0}: {1}
+    UpdateMap(String, io::Error),
+}
+
+// Attributes of the `BPF_OBJ_GET` command.
+#[repr(C)]
+struct BpfObjGetAttr {
+    pathname: u64,
+    bpf_fd: u32,
+    file_flags: u32,
+}
+
+// Attributes of the `BPF_MAP_UPDATE_ELEM` command.
+#[repr(C)]
+struct BpfMapUpdateAttr {
+    map_fd: u32,
+    _pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+// Calls the `bpf` syscall with the attributes `attr`.
+fn bpf<T>(cmd: libc::c_int, attr: &T) -> io::Result<libc::c_long> {
+    // SAFETY: `attr` is a valid attribute structure of `cmd`, whose size is passed along.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *const T,
+            u32::try_from(mem::size_of::<T>()).unwrap(),
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+fn setsockopt<T>(fd: &OwnedFd, name: libc::c_int, value: &T) -> io::Result<()> {
+    // SAFETY: `value` is valid for the size passed along and the result is checked.
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_XDP,
+            name,
+            (value as *const T).cast(),
+            u32::try_from(mem::size_of::<T>()).unwrap(),
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// A memory mapping, unmapped on drop.
+#[derive(Debug)]
+struct Mapping {
+    addr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(len: usize, flags: libc::c_int, fd: RawFd, offset: libc::off_t) -> io::Result<Self> {
+        // SAFETY: A new mapping is created, the result is checked.
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags,
+                fd,
+                offset,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            addr: addr.cast(),
+            len,
+        })
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: The mapping was created by `Mapping::new` and is not referenced anymore.
+        unsafe { libc::munmap(self.addr.cast(), self.len) };
+    }
+}
+
+// A single producer, single consumer ring shared with the kernel. Firecracker produces the
+// descriptors of the fill and TX rings, and consumes those of the RX and completion rings.
+#[derive(Debug)]
+struct Ring<T> {
+    // The ring is unmapped when dropped.
+    _mapping: Mapping,
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    flags: *const AtomicU32,
+    descs: *mut T,
+}
+
+impl<T: Copy> Ring<T> {
+    fn map(fd: &OwnedFd, offset: u64, ring: &libc::xdp_ring_offset) -> io::Result<Self> {
+        let len = u64_to_usize(ring.desc) + RING_SIZE as usize * mem::size_of::<T>();
+        let mapping = Mapping::new(
+            len,
+            libc::MAP_SHARED | libc::MAP_POPULATE,
+            fd.as_raw_fd(),
+            libc::off_t::try_from(offset).unwrap(),
+        )?;
+        // SAFETY: The kernel provides the offsets of the fields of the ring, inside the mapping.
+        unsafe {
+            Ok(Ring {
+                producer: mapping.addr.add(u64_to_usize(ring.producer)).cast(),
+                consumer: mapping.addr.add(u64_to_usize(ring.consumer)).cast(),
+                flags: mapping.addr.add(u64_to_usize(ring.flags)).cast(),
+                descs: mapping.addr.add(u64_to_usize(ring.desc)).cast(),
+                _mapping: mapping,
+            })
+        }
+    }
+
+    fn producer(&self) -> &AtomicU32 {
+        // SAFETY: The index is aligned and lives as long as the mapping.
+        unsafe { &*self.producer }
+    }
+
+    fn consumer(&self) -> &AtomicU32 {
+        // SAFETY: The index is aligned and lives as long as the mapping.
+        unsafe { &*self.consumer }
+    }
+
+    // Whether the kernel must be woken up to process the ring.
+    fn needs_wakeup(&self) -> bool {
+        // SAFETY: The flags are aligned and live as long as the mapping.
+        let flags = unsafe { &*self.flags };
+        flags.load(Ordering::Relaxed) & libc::XDP_RING_NEED_WAKEUP != 0
+    }
+
+    // Produces `desc`, unless the ring is full.
+    fn push(&mut self, desc: T) -> bool {
+        let producer = self.producer().load(Ordering::Relaxed);
+        let consumer = self.consumer().load(Ordering::Acquire);
+        if producer.wrapping_sub(consumer) == RING_SIZE {
+            return false;
+        }
+        // SAFETY: The index is masked to stay inside the descriptors of the ring.
+        unsafe {
+            self.descs
+                .add((producer & (RING_SIZE - 1)) as usize)
+                .write_volatile(desc)
+        };
+        self.producer()
+            .store(producer.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    // Consumes the next descriptor, if any.
+    fn pop(&mut self) -> Option<T> {
+        let consumer = self.consumer().load(Ordering::Relaxed);
+        let producer = self.producer().load(Ordering::Acquire);
+        if producer == consumer {
+            return None;
+        }
+        // SAFETY: The index is masked to stay inside the descriptors of the ring.
+        let desc = unsafe {
+            self.descs
+                .add((consumer & (RING_SIZE - 1)) as usize)
+                .read_volatile()
+        };
+        self.consumer()
+            .store(consumer.wrapping_add(1), Ordering::Release);
+        Some(desc)
+    }
+}
+
+/// AF_XDP socket bound to a queue of a host interface, exchanging frames prefixed by a virtio-net
+/// header like those of a tap device.
+#[derive(Debug)]
+pub struct XdpSocket {
+    config: XdpConfig,
+    zero_copy: bool,
+    // The rings are declared before the socket and the UMEM, so that they are unmapped first.
+    fill: Ring<u64>,
+    completion: Ring<u64>,
+    rx: Ring<libc::xdp_desc>,
+    tx: Ring<libc::xdp_desc>,
+    // Addresses of the TX frames owned by Firecracker.
+    tx_frames: Vec<u64>,
+    fd: OwnedFd,
+    umem: Mapping,
+}
+
+// SAFETY: The rings and the UMEM are only accessed through `&mut self`, the accesses of the
+// kernel being synchronized by the producer and consumer indices of the rings.
+unsafe impl Send for XdpSocket {}
+
+impl XdpSocket {
+    /// Binds a socket to the queue of the host interface in `config`, and inserts it in the
+    /// XSKMAP of the XDP program redirecting the frames of the queue.
+    pub fn new(config: XdpConfig) -> Result<Self, XdpError> {
+        let socket = Self::bind(config)?;
+        socket.insert_in_map()?;
+        Ok(socket)
+    }
+
+    fn bind(config: XdpConfig) -> Result<Self, XdpError> {
+        let if_name = CString::new(config.if_name.as_str())
+            .map_err(|_| XdpError::InvalidIfname(config.if_name.clone()))?;
+        // SAFETY: `if_name` is a valid null-terminated string.
+        let if_index = unsafe { libc::if_nametoindex(if_name.as_ptr()) };
+        if if_index == 0 {
+            return Err(XdpError::UnknownInterface(
+                config.if_name,
+                io::Error::last_os_error(),
+            ));
+        }
+
+        // SAFETY: A new socket is created, the result is checked.
+        let fd = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(XdpError::Socket(io::Error::last_os_error()));
+        }
+        // SAFETY: We just checked that the fd is valid.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let umem = Mapping::new(
+            NUM_FRAMES * FRAME_SIZE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+        .map_err(XdpError::Umem)?;
+        // SAFETY: `xdp_umem_reg` is a POD, zeroing its padding as the kernel expects.
+        let mut umem_reg: libc::xdp_umem_reg = unsafe { mem::zeroed() };
+        umem_reg.addr = umem.addr as u64;
+        umem_reg.len = umem.len as u64;
+        umem_reg.chunk_size = u32::try_from(FRAME_SIZE).unwrap();
+        setsockopt(&fd, libc::XDP_UMEM_REG, &umem_reg).map_err(XdpError::Umem)?;
+
+        for ring in [
+            libc::XDP_UMEM_FILL_RING,
+            libc::XDP_UMEM_COMPLETION_RING,
+            libc::XDP_RX_RING,
+            libc::XDP_TX_RING,
+        ] {
+            setsockopt(&fd, ring, &RING_SIZE).map_err(XdpError::Rings)?;
+        }
+        // SAFETY: `xdp_mmap_offsets` is a POD.
+        let mut offsets: libc::xdp_mmap_offsets = unsafe { mem::zeroed() };
+        let mut len = u32::try_from(mem::size_of_val(&offsets)).unwrap();
+        // SAFETY: `offsets` is valid for `len` bytes and the result is checked.
+        let ret = unsafe {
+            libc::getsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_XDP,
+                libc::XDP_MMAP_OFFSETS,
+                (&mut offsets as *mut libc::xdp_mmap_offsets).cast(),
+                &mut len,
+            )
+        };
+        if ret < 0 {
+            return Err(XdpError::Rings(io::Error::last_os_error()));
+        }
+        // Kernels older than 5.4 don't have the flags of the rings.
+        if len as usize != mem::size_of_val(&offsets) {
+            return Err(XdpError::Rings(io::Error::from_raw_os_error(
+                libc::EOPNOTSUPP,
+            )));
+        }
+
+        let mut fill =
+            Ring::map(&fd, libc::XDP_UMEM_PGOFF_FILL_RING, &offsets.fr).map_err(XdpError::Rings)?;
+        let completion = Ring::map(&fd, libc::XDP_UMEM_PGOFF_COMPLETION_RING, &offsets.cr)
+            .map_err(XdpError::Rings)?;
+        let rx =
+            Ring::map(&fd, libc::XDP_PGOFF_RX_RING as u64, &offsets.rx).map_err(XdpError::Rings)?;
+        let tx =
+            Ring::map(&fd, libc::XDP_PGOFF_TX_RING as u64, &offsets.tx).map_err(XdpError::Rings)?;
+
+        // The fill ring holds all the RX frames, so it always has room for the ones consumed.
+        for frame in 0..RING_SIZE as usize {
+            fill.push((frame * FRAME_SIZE) as u64);
+        }
+        let tx_frames = (RING_SIZE as usize..NUM_FRAMES)
+            .map(|frame| (frame * FRAME_SIZE) as u64)
+            .collect();
+
+        // Zero-copy needs the support of the driver of the interface, copy mode works on any.
+        let mut zero_copy = true;
+        for mode in [libc::XDP_ZEROCOPY, libc::XDP_COPY] {
+            #[allow(clippy::cast_possible_truncation)]
+            let addr = libc::sockaddr_xdp {
+                sxdp_family: libc::AF_XDP as u16,
+                sxdp_flags: mode | libc::XDP_USE_NEED_WAKEUP,
+                sxdp_ifindex: if_index,
+                sxdp_queue_id: config.queue_id,
+                sxdp_shared_umem_fd: 0,
+            };
+            // SAFETY: `addr` is a valid `sockaddr_xdp` and the result is checked.
+            let ret = unsafe {
+                libc::bind(
+                    fd.as_raw_fd(),
+                    (&addr as *const libc::sockaddr_xdp).cast(),
+                    u32::try_from(mem::size_of_val(&addr)).unwrap(),
+                )
+            };
+            if ret == 0 {
+                break;
+            }
+            if mode == libc::XDP_COPY {
+                return Err(XdpError::Bind(
+                    config.if_name,
+                    config.queue_id,
+                    io::Error::last_os_error(),
+                ));
+            }
+            zero_copy = false;
+        }
+
+        Ok(XdpSocket {
+            config,
+            zero_copy,
+            fill,
+            completion,
+            rx,
+            tx,
+            tx_frames,
+            fd,
+            umem,
+        })
+    }
+
+    // Inserts the socket in the XSKMAP of the configuration, at the index of its queue.
+    fn insert_in_map(&self) -> Result<(), XdpError> {
+        let path = &self.config.xsks_map;
+        let map_err = |err| XdpError::OpenMap(path.clone(), err);
+        let pathname = CString::new(path.as_str())
+            .map_err(|_| map_err(io::Error::from_raw_os_error(libc::EINVAL)))?;
+        let map_fd = bpf(
+            BPF_OBJ_GET,
+            &BpfObjGetAttr {
+                pathname: pathname.as_ptr() as u64,
+                bpf_fd: 0,
+                file_flags: 0,
+            },
+        )
+        .map_err(map_err)?;
+        // SAFETY: The fd was just returned by the kernel.
+        let map = unsafe { File::from_raw_fd(RawFd::try_from(map_fd).unwrap()) };
+
+        let key = self.config.queue_id;
+        let value = u32::try_from(self.fd.as_raw_fd()).unwrap();
+        bpf(
+            BPF_MAP_UPDATE_ELEM,
+            &BpfMapUpdateAttr {
+                map_fd: u32::try_from(map.as_raw_fd()).unwrap(),
+                _pad: 0,
+                key: &key as *const u32 as u64,
+                value: &value as *const u32 as u64,
+                flags: BPF_ANY,
+            },
+        )
+        .map_err(|err| XdpError::UpdateMap(path.clone(), err))?;
+        Ok(())
+    }
+
+    /// Returns the configuration of the socket.
+    pub fn config(&self) -> &XdpConfig {
+        &self.config
+    }
+
+    /// Whether the frames are exchanged with the host interface without being copied.
+    pub fn is_zero_copy(&self) -> bool {
+        self.zero_copy
+    }
+
+    // Returns the bytes of the UMEM starting at `addr`, if the range is inside a single frame.
+    fn frame(&mut self, addr: u64, len: usize) -> Option<&mut [u8]> {
+        let offset = u64_to_usize(addr);
+        let frame_end = (offset / FRAME_SIZE + 1) * FRAME_SIZE;
+        if offset.checked_add(len)? > frame_end.min(self.umem.len) {
+            return None;
+        }
+        // SAFETY: The range is inside the UMEM, in a frame owned by Firecracker.
+        Some(unsafe { std::slice::from_raw_parts_mut(self.umem.addr.add(offset), len) })
+    }
+
+    /// Reads the next frame received on the queue into `buf`, after a zeroed virtio-net header.
+    /// Fails with `EAGAIN` if there is none.
+    ///
+    /// `buf` must have room for a frame of `FRAME_SIZE` bytes after the header.
+    pub fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(desc) = self.rx.pop() else {
+            if self.fill.needs_wakeup() {
+                // SAFETY: The socket is valid; a zero-length read only wakes up the driver.
+                unsafe {
+                    libc::recvfrom(
+                        self.fd.as_raw_fd(),
+                        std::ptr::null_mut(),
+                        0,
+                        libc::MSG_DONTWAIT,
+                        std::ptr::null_mut(),
+                        std::ptr::null_mut(),
+                    )
+                };
+            }
+            return Err(io::Error::from_raw_os_error(libc::EAGAIN));
+        };
+
+        let len = desc.len as usize;
+        let result = match self.frame(desc.addr, len) {
+            Some(frame) => {
+                let (hdr, payload) = buf[..vnet_hdr_len() + len].split_at_mut(vnet_hdr_len());
+                hdr.fill(0);
+                payload.copy_from_slice(frame);
+                Ok(vnet_hdr_len() + len)
+            }
+            None => Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        };
+        // The frame is handed back to the kernel, which receives at its start plus a headroom.
+        let pushed = self.fill.push(desc.addr & !(FRAME_SIZE as u64 - 1));
+        debug_assert!(pushed);
+        result
+    }
+
+    /// Sends the frame in `buf`, skipping its virtio-net header.
+    ///
+    /// Fails with `ENOBUFS` if the frames sent before are still in flight, and with `EMSGSIZE`
+    /// if the frame doesn't fit in a UMEM chunk.
+    pub fn write_iovec(&mut self, buf: &IoVecBuffer) -> io::Result<usize> {
+        while let Some(addr) = self.completion.pop() {
+            self.tx_frames.push(addr);
+        }
+
+        let len = buf.len() as usize;
+        let frame_len = match len.checked_sub(vnet_hdr_len()) {
+            Some(0) | None => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+            Some(frame_len) if frame_len > FRAME_SIZE => {
+                return Err(io::Error::from_raw_os_error(libc::EMSGSIZE))
+            }
+            Some(frame_len) => frame_len,
+        };
+        let addr = self
+            .tx_frames
+            .pop()
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOBUFS))?;
+
+        let frame = self.frame(addr, frame_len).unwrap();
+        if let Err(err) = buf.read_exact_volatile_at(frame, vnet_hdr_len()) {
+            self.tx_frames.push(addr);
+            return Err(io::Error::other(err));
+        }
+        // The TX ring has room for all the TX frames.
+        let pushed = self.tx.push(libc::xdp_desc {
+            addr,
+            len: u32::try_from(frame_len).unwrap(),
+            options: 0,
+        });
+        debug_assert!(pushed);
+
+        if self.tx.needs_wakeup() {
+            // SAFETY: The socket is valid; a zero-length write only kicks the transmission. It
+            // fails if the previous frames are still being sent, this one is sent along.
+            unsafe {
+                libc::sendto(
+                    self.fd.as_raw_fd(),
+                    std::ptr::null(),
+                    0,
+                    libc::MSG_DONTWAIT,
+                    std::ptr::null(),
+                    0,
+                )
+            };
+        }
+        Ok(len)
+    }
+}
+
+impl AsRawFd for XdpSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::devices::virtio::net::test_utils::enable;
+    use crate::devices::virtio::net::Tap;
+
+    fn xdp_config(if_name: &str) -> XdpConfig {
+        XdpConfig {
+            if_name: if_name.to_string(),
+            queue_id: 0,
+            xsks_map: String::from("/sys/fs/bpf/firecracker-missing-map"),
+        }
+    }
+
+    #[test]
+    fn test_xdp_config() {
+        let config: XdpConfig =
+            serde_json::from_str(r#"{"if_name": "eth1", "xsks_map": "/sys/fs/bpf/xsks"}"#).unwrap();
+        assert_eq!(config.queue_id, 0);
+        serde_json::from_str::<XdpConfig>(r#"{"if_name": "eth1"}"#).unwrap_err();
+    }
+
+    #[test]
+    fn test_xdp_socket_errors() {
+        assert!(matches!(
+            XdpSocket::new(xdp_config("nonexistent0")),
+            Err(XdpError::UnknownInterface(..))
+        ));
+        assert!(matches!(
+            XdpSocket::new(xdp_config("eth\0")),
+            Err(XdpError::InvalidIfname(_))
+        ));
+        // The socket can be bound, but there is no XSKMAP to redirect the frames to it.
+        let err = XdpSocket::new(xdp_config("lo")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cannot open the XSKMAP /sys/fs/bpf/firecracker-missing-map: No such file or \
+             directory (os error 2)"
+        );
+    }
+
+    #[test]
+    fn test_xdp_socket_tx() {
+        let mut tap = Tap::open_named("xdptx%d").unwrap();
+        tap.set_vnet_hdr_size(i32::try_from(vnet_hdr_len()).unwrap())
+            .unwrap();
+        enable(&tap);
+        // The frames sent on the socket are received by the tap, as it is the host interface.
+        let mut socket = XdpSocket::bind(xdp_config(tap.if_name_as_str())).unwrap();
+        assert_eq!(socket.config().queue_id, 0);
+        // Tap devices only support the copy mode.
+        assert!(!socket.is_zero_copy());
+
+        let mut frame = vec![0u8; vnet_hdr_len() + 60];
+        frame[vnet_hdr_len()..vnet_hdr_len() + 6].copy_from_slice(&[0xff; 6]);
+        frame[vnet_hdr_len() + 12..vnet_hdr_len() + 14].copy_from_slice(&[0x08, 0x06]);
+        frame[vnet_hdr_len() + 14..].fill(0x42);
+        assert_eq!(
+            socket
+                .write_iovec(&IoVecBuffer::from(frame.as_slice()))
+                .unwrap(),
+            frame.len()
+        );
+
+        // The host may send its own frames on the tap, e.g. IPv6 router solicitations.
+        let mut buf = [0u8; 2048];
+        let mut received = false;
+        while let Ok(len) = tap.read(&mut buf) {
+            received |= &buf[..len] == frame.as_slice();
+        }
+        assert!(received);
+
+        // Frames too short or too long to be sent.
+        let short = vec![0u8; vnet_hdr_len()];
+        assert_eq!(
+            socket
+                .write_iovec(&IoVecBuffer::from(short.as_slice()))
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EINVAL)
+        );
+        let long = vec![0u8; vnet_hdr_len() + FRAME_SIZE + 1];
+        assert_eq!(
+            socket
+                .write_iovec(&IoVecBuffer::from(long.as_slice()))
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EMSGSIZE)
+        );
+
+        // Nothing is redirected to the socket without an XDP program.
+        let mut buf = [0u8; vnet_hdr_len() + FRAME_SIZE];
+        assert_eq!(
+            socket.read(&mut buf).unwrap_err().raw_os_error(),
+            Some(libc::EAGAIN)
+        );
+    }
+}
@@ -3,13 +3,16 @@
 
 //! Defines the structures needed for saving/restoring Virtio primitives.
 
+use std::fmt;
 use std::num::Wrapping;
 use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 
+use semver::Version;
 use serde::{Deserialize, Serialize};
 
 use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::dma::{DmaRange, DmaRanges};
 use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
 use crate::devices::virtio::mmio::MmioTransport;
 use crate::devices::virtio::queue::Queue;
@@ -23,6 +26,93 @@ pub enum PersistError {
     InvalidInput,
 }
 
+/// Device capabilities whose state can only be restored by the snapshot data versions that
+/// introduced them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotFeature {
+    /// Guest memory ranges a virtio device is restricted to.
+    DmaRanges,
+    /// Copy-on-write overlay of a block device.
+    BlockOverlay,
+    /// Write cache of a block device disabled by the guest driver.
+    BlockWriteCacheToggle,
+    /// Receive filters of a network device configured through the control queue.
+    NetControlQueue,
+    /// Peer link between two network devices.
+    NetPeer,
+    /// Interrupt coalescing of the queues of a network device.
+    NetInterruptCoalescing,
+    /// MMDS data store namespace exposed through a network device.
+    MmdsNamespace,
+    /// Requests of a block device in flight when the snapshot was taken.
+    BlockInflightRequests,
+    /// Frame received by a network device and not delivered to the guest yet.
+    NetRxDeferredFrame,
+    /// Sessions of a crypto device.
+    CryptoDevice,
+    /// DHCP responder of a network device.
+    NetDhcp,
+    /// Host ports forwarded to the guest of a network device.
+    NetPortForward,
+    /// Link of a network device announced as down to the guest.
+    NetLinkDown,
+    /// AF_XDP socket exchanging the frames of a network device.
+    NetXdp,
+}
+
+impl SnapshotFeature {
+    /// Snapshot data version that introduced the feature.
+    pub fn introduced_in(self) -> Version {
+        match self {
+            SnapshotFeature::DmaRanges
+            | SnapshotFeature::BlockOverlay
+            | SnapshotFeature::BlockWriteCacheToggle
+            | SnapshotFeature::NetControlQueue
+            | SnapshotFeature::NetPeer
+            | SnapshotFeature::NetInterruptCoalescing
+            | SnapshotFeature::MmdsNamespace
+            | SnapshotFeature::BlockInflightRequests
+            | SnapshotFeature::NetRxDeferredFrame
+            | SnapshotFeature::CryptoDevice
+            | SnapshotFeature::NetDhcp
+            | SnapshotFeature::NetPortForward
+            | SnapshotFeature::NetLinkDown
+            | SnapshotFeature::NetXdp => Version::new(2, 1, 0),
+        }
+    }
+
+    /// Name of the feature.
+    pub fn name(self) -> &'static str {
+        match self {
+            SnapshotFeature::DmaRanges => "DMA ranges",
+            SnapshotFeature::BlockOverlay => "block overlay",
+            SnapshotFeature::BlockWriteCacheToggle => "block write cache toggle",
+            SnapshotFeature::NetControlQueue => "net control queue",
+            SnapshotFeature::NetPeer => "net peer",
+            SnapshotFeature::NetInterruptCoalescing => "net interrupt coalescing",
+            SnapshotFeature::MmdsNamespace => "MMDS namespace",
+            SnapshotFeature::BlockInflightRequests => "block in-flight requests",
+            SnapshotFeature::NetRxDeferredFrame => "net deferred RX frame",
+            SnapshotFeature::CryptoDevice => "crypto device",
+            SnapshotFeature::NetDhcp => "net DHCP responder",
+            SnapshotFeature::NetPortForward => "net port forwarding",
+            SnapshotFeature::NetLinkDown => "net link down",
+            SnapshotFeature::NetXdp => "net AF_XDP socket",
+        }
+    }
+}
+
+impl fmt::Display for SnapshotFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (since snapshot version {})",
+            self.name(),
+            self.introduced_in()
+        )
+    }
+}
+
 /// Queue information saved in snapshot.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct QueueState {
@@ -82,6 +172,8 @@ impl Persist<'_> for Queue {
             next_used: state.next_used,
             uses_notif_suppression: false,
             num_added: state.num_added,
+            dma_ranges: None,
+            metrics: None,
         })
     }
 }
@@ -101,6 +193,9 @@ pub struct VirtioDeviceState {
     pub interrupt_status: u32,
     /// Flag for activated status.
     pub activated: bool,
+    /// Guest memory ranges the device is restricted to, if any.
+    #[serde(default)]
+    pub dma_ranges: Option<Vec<DmaRange>>,
 }
 
 impl VirtioDeviceState {
@@ -113,7 +208,17 @@ impl VirtioDeviceState {
             queues: device.queues().iter().map(Persist::save).collect(),
             interrupt_status: device.interrupt_status().load(Ordering::Relaxed),
             activated: device.is_activated(),
+            dma_ranges: device.dma_ranges(),
+        }
+    }
+
+    /// Snapshot features used by the virtio state of the device.
+    pub fn snapshot_features(&self) -> Vec<SnapshotFeature> {
+        let mut features = Vec::new();
+        if self.dma_ranges.is_some() {
+            features.push(SnapshotFeature::DmaRanges);
         }
+        features
     }
 
     /// Does sanity checking on the `self` state against expected values
@@ -137,6 +242,13 @@ impl VirtioDeviceState {
         }
 
         let uses_notif_suppression = (self.acked_features & 1u64 << VIRTIO_RING_F_EVENT_IDX) != 0;
+        let dma_ranges = self
+            .dma_ranges
+            .as_deref()
+            .map(DmaRanges::new)
+            .transpose()
+            .map_err(|_| PersistError::InvalidInput)?
+            .map(Arc::new);
         let queues: Vec<Queue> = self
             .queues
             .iter()
@@ -146,6 +258,7 @@ impl VirtioDeviceState {
                 if uses_notif_suppression {
                     queue.enable_notif_suppression();
                 }
+                queue.set_dma_ranges(dma_ranges.clone());
                 queue
             })
             .collect();
@@ -8,8 +8,13 @@
 use std::cmp::min;
 use std::num::Wrapping;
 use std::sync::atomic::{fence, Ordering};
+use std::sync::Arc;
 
-use crate::logger::error;
+use utils::eventfd::EventFd;
+
+use crate::devices::virtio::dma::DmaRanges;
+use crate::devices::virtio::queue_metrics::QueueMetrics;
+use crate::logger::{error, IncMetric, StoreMetric};
 use crate::vstate::memory::{
     Address, ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap,
 };
@@ -74,6 +79,9 @@ pub struct DescriptorChain<'a, M: GuestMemory = GuestMemoryMmap> {
     /// Index into the descriptor table of the next descriptor if flags has
     /// the next bit set
     pub next: u16,
+
+    /// Guest memory the device is allowed to access through this chain, if restricted
+    pub dma_ranges: Option<Arc<DmaRanges>>,
 }
 
 impl<'a, M: GuestMemory> DescriptorChain<'a, M> {
@@ -111,6 +119,7 @@ impl<'a, M: GuestMemory> DescriptorChain<'a, M> {
             len: desc.len,
             flags: desc.flags,
             next: desc.next,
+            dma_ranges: None,
         };
 
         if chain.is_valid() {
@@ -146,6 +155,7 @@ impl<'a, M: GuestMemory> DescriptorChain<'a, M> {
             DescriptorChain::checked_new(self.mem, self.desc_table, self.queue_size, self.next).map(
                 |mut c| {
                     c.ttl = self.ttl - 1;
+                    c.dma_ranges = self.dma_ranges.clone();
                     c
                 },
             )
@@ -206,8 +216,27 @@ pub struct Queue {
     pub(crate) uses_notif_suppression: bool,
     /// The number of added used buffers since last guest kick
     pub(crate) num_added: Wrapping<u16>,
+
+    /// Guest memory the device is allowed to access through this queue, if restricted
+    pub(crate) dma_ranges: Option<Arc<DmaRanges>>,
+
+    /// Statistics of the queue, if collected
+    pub(crate) metrics: Option<QueueMetricsRef>,
+}
+
+/// Shared reference to the metrics of a queue. Two references are equal if they point to the same
+/// metrics, so that the queues of a restored device compare equal to the original ones.
+#[derive(Clone, Debug)]
+pub struct QueueMetricsRef(pub Arc<QueueMetrics>);
+
+impl PartialEq for QueueMetricsRef {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
 }
 
+impl Eq for QueueMetricsRef {}
+
 #[allow(clippy::len_without_is_empty)]
 impl Queue {
     /// Constructs an empty virtio queue with the given `max_size`.
@@ -223,9 +252,60 @@ impl Queue {
             next_used: Wrapping(0),
             uses_notif_suppression: false,
             num_added: Wrapping(0),
+            dma_ranges: None,
+            metrics: None,
+        }
+    }
+
+    /// Restricts the guest memory the device can access through the descriptor chains of this
+    /// queue.
+    pub fn set_dma_ranges(&mut self, dma_ranges: Option<Arc<DmaRanges>>) {
+        self.dma_ranges = dma_ranges;
+    }
+
+    /// Guest memory the device is allowed to access through this queue, if restricted.
+    pub fn dma_ranges(&self) -> Option<&Arc<DmaRanges>> {
+        self.dma_ranges.as_ref()
+    }
+
+    /// Collects the statistics of the queue into `metrics`.
+    pub fn set_metrics(&mut self, metrics: Option<Arc<QueueMetrics>>) {
+        self.metrics = metrics.map(QueueMetricsRef);
+    }
+
+    /// Statistics of the queue, if collected.
+    pub fn metrics(&self) -> Option<&Arc<QueueMetrics>> {
+        self.metrics.as_ref().map(|metrics| &metrics.0)
+    }
+
+    /// Consumes the notifications the driver sent for this queue through `queue_evt`, and
+    /// returns their number.
+    pub fn read_kicks(&self, queue_evt: &EventFd) -> Result<u64, std::io::Error> {
+        let kicks = queue_evt.read()?;
+        if let Some(metrics) = self.metrics() {
+            metrics.kicks.add(kicks);
+        }
+        Ok(kicks)
+    }
+
+    // Records the number of descriptor chains the driver made available and the device did not
+    // return yet.
+    fn update_backlog<M: GuestMemory>(&self, mem: &M) {
+        if let Some(metrics) = self.metrics() {
+            metrics
+                .backlog
+                .store(u64::from((self.avail_idx(mem) - self.next_used).0));
         }
     }
 
+    // A device that accessed guest memory outside of its DMA ranges is failed, so its queues
+    // stop yielding descriptor chains until it is reset.
+    fn is_failed(&self) -> bool {
+        self.dma_ranges
+            .as_ref()
+            .is_some_and(|dma_ranges| dma_ranges.is_violated())
+    }
+
     /// Maximum size of the queue.
     pub fn get_max_size(&self) -> u16 {
         self.max_size
@@ -290,6 +370,17 @@ impl Queue {
         }
     }
 
+    /// Returns the guest physical address and size in bytes of the descriptor table, the
+    /// available ring and the used ring, in this order.
+    pub fn ring_ranges(&self) -> [(GuestAddress, u64); 3] {
+        let queue_size = u64::from(self.actual_size());
+        [
+            (self.desc_table, 16 * queue_size),
+            (self.avail_ring, 6 + 2 * queue_size),
+            (self.used_ring, 6 + 8 * queue_size),
+        ]
+    }
+
     /// Validates that the queue's representation is correct.
     pub fn is_valid<M: GuestMemory>(&self, mem: &M) -> bool {
         if !self.is_layout_valid(mem) {
@@ -335,8 +426,9 @@ impl Queue {
             // logging and potentially clogging the microVM through the log system.
             panic!("The number of available virtio descriptors is greater than queue size!");
         }
+        self.update_backlog(mem);
 
-        if len == 0 {
+        if len == 0 || self.is_failed() {
             return None;
         }
 
@@ -353,7 +445,7 @@ impl Queue {
             return self.pop(mem);
         }
 
-        if self.try_enable_notification(mem) {
+        if self.try_enable_notification(mem) || self.is_failed() {
             return None;
         }
 
@@ -405,8 +497,33 @@ impl Queue {
             .unwrap();
 
         DescriptorChain::checked_new(mem, self.desc_table, self.actual_size(), desc_index).map(
-            |dc| {
+            |mut dc| {
                 self.next_avail += Wrapping(1);
+                dc.dma_ranges = self.dma_ranges.clone();
+                #[cfg(feature = "virtio-trace")]
+                super::trace::record_pop(self.avail_ring.0, dc.index);
+                dc
+            },
+        )
+    }
+
+    /// Records that the descriptor chain with head `desc_index` was handed over to the device
+    /// backend, when the virtio datapath is traced.
+    pub fn trace_backend(&self, _desc_index: u16) {
+        #[cfg(feature = "virtio-trace")]
+        super::trace::record_backend(self.avail_ring.0, _desc_index);
+    }
+
+    /// Returns the descriptor chain with head `index`, which was popped from the avail ring
+    /// before, e.g. to process again a request that was in flight when a snapshot was taken.
+    pub fn descriptor_chain<'b, M: GuestMemory>(
+        &self,
+        mem: &'b M,
+        index: u16,
+    ) -> Option<DescriptorChain<'b, M>> {
+        DescriptorChain::checked_new(mem, self.desc_table, self.actual_size(), index).map(
+            |mut dc| {
+                dc.dma_ranges = self.dma_ranges.clone();
                 dc
             },
         )
@@ -424,10 +541,24 @@ impl Queue {
         mem: &M,
         desc_index: u16,
         len: u32,
+    ) -> Result<(), QueueError> {
+        self.add_used_batch(mem, &[(desc_index, len)])
+    }
+
+    /// Puts several available descriptor heads, along with the number of bytes written to each
+    /// of them, into the used ring for use by the guest.
+    ///
+    /// The used ring index is only published once, after all the elements are written, so that
+    /// the driver observes the whole batch at once. Nothing is added if any of the descriptor
+    /// indices is out of bounds.
+    pub fn add_used_batch<M: GuestMemory>(
+        &mut self,
+        mem: &M,
+        items: &[(u16, u32)],
     ) -> Result<(), QueueError> {
         debug_assert!(self.is_layout_valid(mem));
 
-        if desc_index >= self.actual_size() {
+        if let Some(&(desc_index, _)) = items.iter().find(|(idx, _)| *idx >= self.actual_size()) {
             error!(
                 "attempted to add out of bounds descriptor to used ring: {}",
                 desc_index
@@ -435,9 +566,31 @@ impl Queue {
             return Err(QueueError::DescIndexOutOfBounds(desc_index));
         }
 
-        let used_ring = self.used_ring;
+        let result = items
+            .iter()
+            .try_for_each(|&(desc_index, len)| self.write_used_element(mem, desc_index, len));
+
+        // Publish the elements written so far, even if one of them could not be.
+        // This fence ensures all descriptor writes are visible before the index update is.
+        fence(Ordering::Release);
+
+        let next_used_addr = self.used_ring.unchecked_add(2);
+        mem.write_obj(self.next_used.0, next_used_addr)
+            .map_err(QueueError::UsedRing)?;
+        self.update_backlog(mem);
+
+        result
+    }
+
+    // Writes the used element at the next free position of the used ring, without publishing it.
+    fn write_used_element<M: GuestMemory>(
+        &mut self,
+        mem: &M,
+        desc_index: u16,
+        len: u32,
+    ) -> Result<(), QueueError> {
         let next_used = u64::from(self.next_used.0 % self.actual_size());
-        let used_elem = used_ring.unchecked_add(4 + next_used * 8);
+        let used_elem = self.used_ring.unchecked_add(4 + next_used * 8);
 
         mem.write_obj(u32::from(desc_index), used_elem)?;
 
@@ -446,13 +599,13 @@ impl Queue {
 
         self.num_added += Wrapping(1);
         self.next_used += Wrapping(1);
+        if let Some(metrics) = self.metrics() {
+            metrics.descriptors.inc();
+        }
 
-        // This fence ensures all descriptor writes are visible before the index update is.
-        fence(Ordering::Release);
-
-        let next_used_addr = used_ring.unchecked_add(2);
-        mem.write_obj(self.next_used.0, next_used_addr)
-            .map_err(QueueError::UsedRing)
+        #[cfg(feature = "virtio-trace")]
+        super::trace::record_used(self.avail_ring.0, desc_index);
+        Ok(())
     }
 
     /// Fetch the available ring index (`virtq_avail->idx`) from guest memory.
@@ -468,6 +621,16 @@ impl Queue {
         Wrapping(mem.read_obj::<u16>(addr).unwrap())
     }
 
+    /// Fetch the used ring index (`virtq_used->idx`) from guest memory.
+    /// This is written by the device, to indicate the next slot that will be filled in the used
+    /// ring.
+    pub fn used_idx<M: GuestMemory>(&self, mem: &M) -> Wrapping<u16> {
+        // Bound checks for queue inner data have already been performed, at device activation time,
+        // via `self.is_valid()`, so it's safe to unwrap and use unchecked offsets here.
+        let addr = self.used_ring.unchecked_add(2);
+        Wrapping(mem.read_obj::<u16>(addr).unwrap())
+    }
+
     /// Get the value of the used event field of the avail ring.
     #[inline(always)]
     pub fn used_event<M: GuestMemory>(&self, mem: &M) -> Wrapping<u16> {
@@ -543,6 +706,15 @@ impl Queue {
     ///
     /// This is similar to the `vring_need_event()` method implemented by the Linux kernel.
     pub fn prepare_kick<M: GuestMemory>(&mut self, mem: &M) -> bool {
+        let kick = self.needs_kick(mem);
+        #[cfg(feature = "virtio-trace")]
+        if kick {
+            super::trace::record_irq(self.avail_ring.0);
+        }
+        kick
+    }
+
+    fn needs_kick<M: GuestMemory>(&mut self, mem: &M) -> bool {
         debug_assert!(self.is_layout_valid(mem));
 
         // If the device doesn't use notification suppression, always return true
@@ -563,6 +735,54 @@ impl Queue {
     }
 }
 
+/// Accumulates the descriptor chains used during a processing batch, along with the number of
+/// bytes written to each of them, so that they are published to the driver all at once.
+///
+/// Devices completing their descriptor chains one at a time push them here instead of calling
+/// `Queue::add_used()` for each of them, and flush the batch before signaling the driver, so that
+/// the used ring index is written once and a single interrupt covers the whole batch. The
+/// storage is kept across batches.
+#[derive(Debug, Default)]
+pub struct UsedBatch {
+    items: Vec<(u16, u32)>,
+}
+
+impl UsedBatch {
+    /// Records that `len` bytes were written to the descriptor chain starting at `head_index`.
+    pub fn push(&mut self, head_index: u16, len: u32) {
+        self.items.push((head_index, len));
+    }
+
+    /// Returns the number of descriptor chains waiting to be published.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns whether no descriptor chain is waiting to be published.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Puts the accumulated descriptor chains into the used ring of `queue`, publishing the
+    /// used ring index once, and empties the batch. Returns whether any descriptor chain was
+    /// published.
+    ///
+    /// The batch is emptied even if the descriptor chains could not be published, as they
+    /// cannot be retried.
+    pub fn flush<M: GuestMemory>(
+        &mut self,
+        queue: &mut Queue,
+        mem: &M,
+    ) -> Result<bool, QueueError> {
+        if self.items.is_empty() {
+            return Ok(false);
+        }
+        let result = queue.add_used_batch(mem, &self.items);
+        self.items.clear();
+        result.map(|()| true)
+    }
+}
+
 #[cfg(kani)]
 #[allow(dead_code)]
 mod verification {
@@ -1391,6 +1611,113 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_add_used_batch() {
+        let m = &default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), m, 16);
+
+        let mut q = vq.create_queue();
+        q.add_used(m, 1, 0x1000).unwrap();
+
+        // Nothing is added if one of the descriptors is out of bounds.
+        match q.add_used_batch(m, &[(2, 0x10), (16, 0x20)]) {
+            Err(DescIndexOutOfBounds(16)) => (),
+            _ => unreachable!(),
+        }
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(q.next_used, Wrapping(1));
+
+        q.add_used_batch(m, &[(2, 0x10), (5, 0x20), (3, 0)])
+            .unwrap();
+        assert_eq!(vq.used.idx.get(), 4);
+        assert_eq!(q.num_added, Wrapping(4));
+        for (i, (id, len)) in [(1, 0x1000), (2, 0x10), (5, 0x20), (3, 0)]
+            .into_iter()
+            .enumerate()
+        {
+            let elem = vq.used.ring[i].get();
+            assert_eq!((elem.id, elem.len), (id, len));
+        }
+
+        // An empty batch leaves the used ring untouched.
+        q.add_used_batch(m, &[]).unwrap();
+        assert_eq!(vq.used.idx.get(), 4);
+    }
+
+    #[test]
+    fn test_used_batch() {
+        let m = &default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), m, 16);
+
+        let mut q = vq.create_queue();
+        let mut batch = UsedBatch::default();
+        assert!(!batch.flush(&mut q, m).unwrap());
+
+        // The descriptor chains are only published when the batch is flushed.
+        batch.push(3, 0x100);
+        batch.push(1, 0x200);
+        assert_eq!(batch.len(), 2);
+        assert_eq!(vq.used.idx.get(), 0);
+        assert!(batch.flush(&mut q, m).unwrap());
+        assert!(batch.is_empty());
+        assert_eq!(vq.used.idx.get(), 2);
+        assert_eq!(q.num_added, Wrapping(2));
+        for (i, (id, len)) in [(3, 0x100), (1, 0x200)].into_iter().enumerate() {
+            let elem = vq.used.ring[i].get();
+            assert_eq!((elem.id, elem.len), (id, len));
+        }
+
+        // A batch which cannot be published is dropped.
+        batch.push(16, 0);
+        match batch.flush(&mut q, m) {
+            Err(DescIndexOutOfBounds(16)) => (),
+            _ => unreachable!(),
+        }
+        assert!(batch.is_empty());
+        assert_eq!(vq.used.idx.get(), 2);
+    }
+
+    #[test]
+    fn test_queue_metrics() {
+        let m = &default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), m, 16);
+        let mut q = vq.create_queue();
+        let metrics = Arc::new(QueueMetrics::default());
+        q.set_metrics(Some(metrics.clone()));
+
+        let queue_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        queue_evt.write(2).unwrap();
+        assert_eq!(q.read_kicks(&queue_evt).unwrap(), 2);
+        assert_eq!(metrics.kicks.count(), 2);
+        q.read_kicks(&queue_evt).unwrap_err();
+        assert_eq!(metrics.kicks.count(), 2);
+
+        for i in 0..3 {
+            vq.dtable[i].set(0x1000, 0x1000, 0, 0);
+            vq.avail.ring[i].set(u16::try_from(i).unwrap());
+        }
+        vq.avail.idx.set(3);
+
+        // The backlog accounts for the chains made available, popped or not.
+        let index = q.pop(m).unwrap().index;
+        assert_eq!(metrics.backlog.fetch(), 3);
+        q.add_used(m, index, 0).unwrap();
+        assert_eq!(metrics.backlog.fetch(), 2);
+        assert_eq!(metrics.descriptors.count(), 1);
+
+        q.add_used_batch(m, &[(1, 0), (2, 0)]).unwrap();
+        assert_eq!(metrics.backlog.fetch(), 0);
+        assert_eq!(metrics.descriptors.count(), 3);
+
+        // Metrics are kept across a queue being cloned and compared by identity.
+        let other = q.clone();
+        assert_eq!(other, q);
+        let mut other = vq.create_queue();
+        other.set_metrics(Some(Arc::new(QueueMetrics::default())));
+        q.set_metrics(other.metrics().cloned());
+        assert_eq!(q.metrics, other.metrics);
+    }
+
     #[test]
     fn test_used_event() {
         let m = &default_mem();
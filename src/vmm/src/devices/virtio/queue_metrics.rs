@@ -0,0 +1,165 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the metrics system for the queues of virtio devices.
+//!
+//! # Metrics format
+//! The metrics are flushed in JSON when requested by vmm::logger::metrics::METRICS.write().
+//!
+//! ## JSON example with metrics:
+//! ```json
+//! {
+//!  "queues_block_rootfs": {
+//!     "q0": {
+//!         "kicks": "SharedIncMetric",
+//!         "descriptors": "SharedIncMetric",
+//!         "backlog": "SharedStoreMetric",
+//!     }
+//!  }
+//!  "queues_net_eth0": {
+//!     "q0": { ... },
+//!     "q1": { ... },
+//!  }
+//!  ...
+//! }
+//! ```
+//! Each `qN` field in the example above is a serializable `QueueMetrics` structure collecting
+//! the metrics of the N-th queue of the device:
+//! * `kicks` is the number of notifications received from the driver for the queue.
+//! * `descriptors` is the number of descriptor chains the device returned to the driver.
+//! * `backlog` is the number of descriptor chains the driver made available that were not yet
+//!   returned to it (avail idx - used idx), as last observed by the device. A backlog that does not
+//!   go down while `kicks` keeps increasing points at a stalled device, whereas a zero backlog with
+//!   no kicks points at a stalled guest driver.
+//!
+//! For a virtio device of type `{type}` having id `{id}` the emitted metrics are
+//! `queues_{type}_{id}`, e.g. `queues_block_rootfs` or `queues_vsock_vsock`.
+//!
+//! # Design
+//! * Follow the design of the vhost-user device metrics and use a map of device name and
+//!   corresponding metrics. Aggregate metrics are not emitted, as they can easily be obtained in
+//!   typical observability tools.
+//! * The metrics are attached to the queues of a device when it is registered on the MMIO bus, so
+//!   that they are collected for every device without each device having to know about them.
+//! * The queues of vhost-user devices are processed by the backend, which receives the guest
+//!   notifications directly, so their metrics stay at 0.
+//!
+//! We add QueueMetrics entries from queue_metrics::METRICS into the queues instead of the queues
+//! having individual separate QueueMetrics entries because the devices are not accessible from
+//! signal handlers to flush metrics and queue_metrics::METRICS is.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+
+use crate::logger::{SharedIncMetric, SharedStoreMetric};
+
+/// map of virtio device name and the metrics of its queues
+/// this should be protected by a lock before accessing.
+#[allow(missing_debug_implementations)]
+pub struct QueueMetricsPerDevice {
+    /// used to access the per queue metrics of a virtio device
+    pub metrics: BTreeMap<String, Vec<Arc<QueueMetrics>>>,
+}
+
+impl QueueMetricsPerDevice {
+    /// Allocate `QueueMetrics` for the `num_queues` queues of the virtio device having name
+    /// `dev_name`. Already allocated entries are reused to avoid overwriting previously allocated
+    /// data. lock is always initialized so it is safe the unwrap the lock without a check.
+    pub fn alloc(dev_name: String, num_queues: usize) -> Vec<Arc<QueueMetrics>> {
+        let mut metrics = METRICS.write().unwrap();
+        let queues = metrics.metrics.entry(dev_name).or_default();
+        if queues.len() < num_queues {
+            queues.resize_with(num_queues, || Arc::new(QueueMetrics::default()));
+        }
+        queues.iter().take(num_queues).cloned().collect()
+    }
+}
+
+/// Pool of queue-related metrics per device behind a lock to
+/// keep things thread safe. Since the lock is initialized here
+/// it is safe to unwrap it without any check.
+static METRICS: RwLock<QueueMetricsPerDevice> = RwLock::new(QueueMetricsPerDevice {
+    metrics: BTreeMap::new(),
+});
+
+// Serializes the metrics of the queues of one device as a map keyed by the queue index.
+struct DeviceQueueMetrics<'a>(&'a [Arc<QueueMetrics>]);
+
+impl Serialize for DeviceQueueMetrics<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_map(Some(self.0.len()))?;
+        for (idx, metrics) in self.0.iter().enumerate() {
+            seq.serialize_entry(&format!("q{}", idx), metrics)?;
+        }
+        seq.end()
+    }
+}
+
+/// This function facilitates serialization of the virtio queue metrics.
+pub fn flush_metrics<S: Serializer>(serializer: S) -> Result<S::Ok, S::Error> {
+    let queue_metrics = METRICS.read().unwrap();
+    let metrics_len = queue_metrics.metrics.len();
+    let mut seq = serializer.serialize_map(Some(metrics_len))?;
+
+    for (name, metrics) in queue_metrics.metrics.iter() {
+        let devn = format!("queues_{}", name);
+        seq.serialize_entry(&devn, &DeviceQueueMetrics(metrics))?;
+    }
+    seq.end()
+}
+
+/// Virtio queue associated metrics.
+#[derive(Debug, Default, Serialize)]
+pub struct QueueMetrics {
+    /// Number of notifications received from the driver for the queue.
+    pub kicks: SharedIncMetric,
+    /// Number of descriptor chains returned to the driver through the used ring.
+    pub descriptors: SharedIncMetric,
+    /// Number of descriptor chains made available by the driver and not yet returned to it.
+    pub backlog: SharedStoreMetric,
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::logger::{IncMetric, StoreMetric};
+
+    #[test]
+    fn test_queue_metrics_alloc() {
+        let dev_name = String::from("block_alloc_test");
+        let metrics = QueueMetricsPerDevice::alloc(dev_name.clone(), 1);
+        assert_eq!(metrics.len(), 1);
+        metrics[0].kicks.add(3);
+
+        // Allocating again reuses the metrics of the queues that already exist.
+        let more_metrics = QueueMetricsPerDevice::alloc(dev_name.clone(), 2);
+        assert_eq!(more_metrics.len(), 2);
+        assert!(Arc::ptr_eq(&metrics[0], &more_metrics[0]));
+        assert_eq!(more_metrics[0].kicks.count(), 3);
+        assert_eq!(METRICS.read().unwrap().metrics[&dev_name].len(), 2);
+    }
+
+    #[test]
+    fn test_queue_metrics_serialize() {
+        let dev_name = String::from("net_serialize_test");
+        let metrics = QueueMetricsPerDevice::alloc(dev_name.clone(), 2);
+        metrics[0].kicks.inc();
+        metrics[1].descriptors.add(2);
+        metrics[1].backlog.store(5);
+
+        let serialized = serde_json::to_value(DeviceQueueMetrics(
+            &METRICS.read().unwrap().metrics[&dev_name],
+        ))
+        .unwrap();
+        assert_eq!(
+            serialized,
+            serde_json::json!({
+                "q0": {"kicks": 1, "descriptors": 0, "backlog": 0},
+                "q1": {"kicks": 0, "descriptors": 2, "backlog": 5},
+            })
+        );
+    }
+}
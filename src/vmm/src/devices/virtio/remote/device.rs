@@ -0,0 +1,599 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+
+use log::error;
+use utils::eventfd::EventFd;
+use utils::u64_to_usize;
+use vhost::vhost_user::message::*;
+use vhost::vhost_user::Frontend;
+
+use super::{RemoteDeviceError, MAX_CONFIG_SIZE, MAX_QUEUES, MAX_QUEUE_SIZE};
+use crate::devices::virtio::device::{DeviceState, IrqTrigger, VirtioDevice};
+use crate::devices::virtio::gen::virtio_blk::VIRTIO_F_VERSION_1;
+use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
+use crate::devices::virtio::queue::Queue;
+use crate::devices::virtio::vhost_user::{VhostUserHandleBackend, VhostUserHandleImpl};
+use crate::devices::virtio::vhost_user_metrics::{
+    VhostUserDeviceMetrics, VhostUserMetricsPerDevice,
+};
+use crate::devices::virtio::vsock::TYPE_VSOCK;
+use crate::devices::virtio::{
+    ActivateError, TYPE_BALLOON, TYPE_BLOCK, TYPE_CRYPTO, TYPE_NET, TYPE_RNG,
+};
+use crate::logger::{log_dev_preview_warning, IncMetric, StoreMetric};
+use crate::vmm_config::remote_device::RemoteDeviceConfig;
+use crate::vstate::memory::GuestMemoryMmap;
+
+// The device specific feature bits are offered to the driver as long as the backend supports
+// them.
+const DEVICE_FEATURES: u64 = (1 << 24) - 1;
+
+const AVAILABLE_FEATURES: u64 = DEVICE_FEATURES
+    | (1 << VIRTIO_F_VERSION_1)
+    | (1 << VIRTIO_RING_F_EVENT_IDX)
+    // vhost-user specific bit. Not defined in standart virtio spec.
+    // Specifies ability of frontend to negotiate protocol features.
+    | VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits();
+
+// Devices emulated by Firecracker itself, which are looked up by their type.
+const EMULATED_DEVICE_TYPES: [u32; 6] = [
+    TYPE_NET,
+    TYPE_BLOCK,
+    TYPE_RNG,
+    TYPE_BALLOON,
+    TYPE_VSOCK,
+    TYPE_CRYPTO,
+];
+
+pub type RemoteDevice = RemoteDeviceImpl<Frontend>;
+
+/// Virtio device emulated by a vhost-user backend process.
+pub struct RemoteDeviceImpl<T: VhostUserHandleBackend> {
+    // Virtio fields.
+    pub avail_features: u64,
+    pub acked_features: u64,
+    pub config_space: Vec<u8>,
+    pub activate_evt: EventFd,
+
+    // Transport related fields.
+    pub queues: Vec<Queue>,
+    pub queue_evts: Vec<EventFd>,
+    pub device_state: DeviceState,
+    pub irq_trigger: IrqTrigger,
+
+    // Implementation specific fields.
+    pub id: String,
+    pub device_type: u32,
+
+    // Vhost user protocol handle
+    pub vu_handle: VhostUserHandleImpl<T>,
+    pub vu_acked_protocol_features: u64,
+    pub metrics: Arc<VhostUserDeviceMetrics>,
+}
+
+// Need custom implementation because otherwise `Debug` is required for `vhost::Master`
+impl<T: VhostUserHandleBackend> std::fmt::Debug for RemoteDeviceImpl<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RemoteDeviceImpl")
+            .field("avail_features", &self.avail_features)
+            .field("acked_features", &self.acked_features)
+            .field("config_space", &self.config_space)
+            .field("activate_evt", &self.activate_evt)
+            .field("queues", &self.queues)
+            .field("queue_evts", &self.queue_evts)
+            .field("device_state", &self.device_state)
+            .field("irq_trigger", &self.irq_trigger)
+            .field("id", &self.id)
+            .field("device_type", &self.device_type)
+            .field("vu_handle", &self.vu_handle)
+            .field(
+                "vu_acked_protocol_features",
+                &self.vu_acked_protocol_features,
+            )
+            .field("metrics", &self.metrics)
+            .finish()
+    }
+}
+
+impl<T: VhostUserHandleBackend> RemoteDeviceImpl<T> {
+    /// Connects to the backend of the device and negotiates the features with it.
+    pub fn new(config: RemoteDeviceConfig) -> Result<Self, RemoteDeviceError> {
+        if config.device_type == 0 || EMULATED_DEVICE_TYPES.contains(&config.device_type) {
+            return Err(RemoteDeviceError::DeviceType(config.device_type));
+        }
+        if !(1..=MAX_QUEUES).contains(&config.num_queues) {
+            return Err(RemoteDeviceError::NumQueues(config.num_queues));
+        }
+        if !config.queue_size.is_power_of_two() || config.queue_size > MAX_QUEUE_SIZE {
+            return Err(RemoteDeviceError::QueueSize(config.queue_size));
+        }
+        if config.config_size > MAX_CONFIG_SIZE {
+            return Err(RemoteDeviceError::ConfigSize(config.config_size));
+        }
+
+        log_dev_preview_warning("Remote devices", Option::None);
+        let start_time = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+
+        let mut vu_handle =
+            VhostUserHandleImpl::<T>::new(&config.socket, u64::from(config.num_queues))
+                .map_err(RemoteDeviceError::VhostUser)?;
+        let (acked_features, acked_protocol_features) = vu_handle
+            .negotiate_features(AVAILABLE_FEATURES, VhostUserProtocolFeatures::CONFIG)
+            .map_err(RemoteDeviceError::VhostUser)?;
+        // The queues can only be enabled through the protocol features.
+        if acked_features & VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits() == 0 {
+            return Err(RemoteDeviceError::ProtocolFeatures);
+        }
+
+        let config_space = if config.config_size == 0 {
+            vec![]
+        } else if acked_protocol_features & VhostUserProtocolFeatures::CONFIG.bits() != 0 {
+            // This buffer is used for config size check in vhost crate.
+            let buffer = vec![0u8; u64_to_usize(u64::from(config.config_size))];
+            let (_, config_space) = vu_handle
+                .vu
+                .get_config(
+                    0,
+                    config.config_size,
+                    VhostUserConfigFlags::WRITABLE,
+                    &buffer,
+                )
+                .map_err(RemoteDeviceError::Vhost)?;
+            config_space
+        } else {
+            return Err(RemoteDeviceError::Config);
+        };
+
+        let activate_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(RemoteDeviceError::EventFd)?;
+        let queues = vec![Queue::new(config.queue_size); usize::from(config.num_queues)];
+        let queue_evts = (0..config.num_queues)
+            .map(|_| EventFd::new(libc::EFD_NONBLOCK))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(RemoteDeviceError::EventFd)?;
+        let irq_trigger = IrqTrigger::new().map_err(RemoteDeviceError::IrqTrigger)?;
+
+        // We negotiated features with backend. Now these acked_features
+        // are available for guest driver to choose from.
+        let avail_features = acked_features;
+        let acked_features = acked_features & VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits();
+
+        let metrics = VhostUserMetricsPerDevice::alloc(format!("remote_{}", config.id));
+        let delta_us = utils::time::get_time_us(utils::time::ClockType::Monotonic) - start_time;
+        metrics.init_time_us.store(delta_us);
+
+        Ok(Self {
+            avail_features,
+            acked_features,
+            config_space,
+            activate_evt,
+
+            queues,
+            queue_evts,
+            device_state: DeviceState::Inactive,
+            irq_trigger,
+
+            id: config.id,
+            device_type: config.device_type,
+
+            vu_handle,
+            vu_acked_protocol_features: acked_protocol_features,
+            metrics,
+        })
+    }
+
+    /// Provides the ID of this device.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns the configuration of the device.
+    pub fn config(&self) -> RemoteDeviceConfig {
+        RemoteDeviceConfig {
+            id: self.id.clone(),
+            device_type: self.device_type,
+            socket: self.vu_handle.socket_path.clone(),
+            // The number of queues is bounded when the device is created.
+            num_queues: u16::try_from(self.queues.len()).unwrap(),
+            queue_size: self.queues[0].get_max_size(),
+            // The config space is no larger than `MAX_CONFIG_SIZE`.
+            config_size: u32::try_from(self.config_space.len()).unwrap(),
+        }
+    }
+
+    /// Ask the backend to start or stop processing the queues.
+    pub fn set_queues_enabled(&mut self, enable: bool) -> Result<(), RemoteDeviceError> {
+        if !self.device_state.is_activated() {
+            return Ok(());
+        }
+        self.vu_handle
+            .set_vrings_enabled(self.queues.len(), enable)
+            .map_err(RemoteDeviceError::VhostUser)
+    }
+
+    /// Handle the backend going away. The device stops working, as the queues are only
+    /// processed by the backend.
+    pub fn handle_backend_disconnect(&mut self) {
+        self.metrics.backend_disconnects.inc();
+        error!("Remote device {}: backend disconnected", self.id);
+    }
+}
+
+impl<T: VhostUserHandleBackend + Send + 'static> VirtioDevice for RemoteDeviceImpl<T> {
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn set_avail_features(&mut self, avail_features: u64) {
+        self.avail_features = avail_features;
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn device_type(&self) -> u32 {
+        self.device_type
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.irq_trigger.irq_evt
+    }
+
+    /// Returns the current device interrupt status.
+    fn interrupt_status(&self) -> Arc<AtomicU32> {
+        self.irq_trigger.irq_status.clone()
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        if let Some(config_space_bytes) = self.config_space.as_slice().get(u64_to_usize(offset)..) {
+            let len = config_space_bytes.len().min(data.len());
+            data[..len].copy_from_slice(&config_space_bytes[..len]);
+        } else {
+            error!("Failed to read config space");
+            self.metrics.cfg_fails.inc();
+        }
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        let config_len = self.config_space.len();
+        let Some(end) = u64_to_usize(offset)
+            .checked_add(data.len())
+            .filter(|end| *end <= config_len)
+        else {
+            error!("Failed to write config space");
+            self.metrics.cfg_fails.inc();
+            return;
+        };
+        self.config_space[u64_to_usize(offset)..end].copy_from_slice(data);
+
+        // The offset is within the config space, which is no larger than `MAX_CONFIG_SIZE`.
+        let offset = u32::try_from(offset).unwrap();
+        if let Err(err) = self
+            .vu_handle
+            .vu
+            .set_config(offset, VhostUserConfigFlags::WRITABLE, data)
+        {
+            error!("Failed to write config space of the backend: {}", err);
+            self.metrics.cfg_fails.inc();
+        }
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> Result<(), ActivateError> {
+        let start_time = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+        // Setting features again, because now we negotiated them
+        // with guest driver as well.
+        self.vu_handle
+            .set_features(self.acked_features)
+            .map_err(ActivateError::VhostUser)?;
+        let queues = self
+            .queues
+            .iter()
+            .zip(self.queue_evts.iter())
+            .enumerate()
+            .map(|(index, (queue, queue_evt))| (index, queue, queue_evt))
+            .collect::<Vec<_>>();
+        self.vu_handle
+            .setup_backend(&mem, &queues, &self.irq_trigger)
+            .map_err(|err| {
+                self.metrics.activate_fails.inc();
+                ActivateError::VhostUser(err)
+            })?;
+        self.device_state = DeviceState::Activated(mem);
+        let delta_us = utils::time::get_time_us(utils::time::ClockType::Monotonic) - start_time;
+        self.metrics.activate_time_us.store(delta_us);
+        Ok(())
+    }
+
+    fn is_activated(&self) -> bool {
+        self.device_state.is_activated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::undocumented_unsafe_blocks)]
+
+    use std::cell::UnsafeCell;
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Mutex;
+
+    use utils::tempfile::TempFile;
+    use vhost::{VhostUserMemoryRegionInfo, VringConfigData};
+
+    use super::*;
+    use crate::utilities::test_utils::create_tmp_socket;
+    use crate::vstate::memory::{FileOffset, GuestAddress, GuestMemoryExtension};
+
+    static BACKEND_FEATURES: AtomicU64 = AtomicU64::new(0);
+    static BACKEND_PROTOCOL_FEATURES: AtomicU64 = AtomicU64::new(0);
+    // Serializes the tests, as the backend features are shared between them.
+    static BACKEND_LOCK: Mutex<()> = Mutex::new(());
+
+    struct MockFrontend {
+        max_queue_num: u64,
+        config: Vec<u8>,
+        features: UnsafeCell<u64>,
+        memory_is_set: UnsafeCell<bool>,
+        enabled_vrings: UnsafeCell<Vec<usize>>,
+    }
+
+    impl VhostUserHandleBackend for MockFrontend {
+        fn from_stream(_sock: UnixStream, max_queue_num: u64) -> Self {
+            Self {
+                max_queue_num,
+                config: vec![0x69; 8],
+                features: UnsafeCell::new(0),
+                memory_is_set: UnsafeCell::new(false),
+                enabled_vrings: UnsafeCell::new(vec![]),
+            }
+        }
+
+        fn set_owner(&self) -> Result<(), vhost::Error> {
+            Ok(())
+        }
+
+        fn set_hdr_flags(&self, _flags: VhostUserHeaderFlag) {}
+
+        fn get_features(&self) -> Result<u64, vhost::Error> {
+            Ok(BACKEND_FEATURES.load(Ordering::SeqCst))
+        }
+
+        fn set_features(&self, features: u64) -> Result<(), vhost::Error> {
+            unsafe { *self.features.get() = features };
+            Ok(())
+        }
+
+        fn get_protocol_features(&mut self) -> Result<VhostUserProtocolFeatures, vhost::Error> {
+            Ok(VhostUserProtocolFeatures::from_bits_truncate(
+                BACKEND_PROTOCOL_FEATURES.load(Ordering::SeqCst),
+            ))
+        }
+
+        fn set_protocol_features(
+            &mut self,
+            _features: VhostUserProtocolFeatures,
+        ) -> Result<(), vhost::Error> {
+            Ok(())
+        }
+
+        fn get_config(
+            &mut self,
+            offset: u32,
+            size: u32,
+            _flags: VhostUserConfigFlags,
+            _buf: &[u8],
+        ) -> Result<(VhostUserConfig, VhostUserConfigPayload), vhost::Error> {
+            let range = u64_to_usize(u64::from(offset))..u64_to_usize(u64::from(offset + size));
+            Ok((VhostUserConfig::default(), self.config[range].to_vec()))
+        }
+
+        fn set_config(
+            &mut self,
+            offset: u32,
+            _flags: VhostUserConfigFlags,
+            buf: &[u8],
+        ) -> Result<(), vhost::Error> {
+            let offset = u64_to_usize(u64::from(offset));
+            self.config[offset..offset + buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn set_mem_table(
+            &self,
+            _regions: &[VhostUserMemoryRegionInfo],
+        ) -> Result<(), vhost::Error> {
+            unsafe { *self.memory_is_set.get() = true };
+            Ok(())
+        }
+
+        fn set_vring_num(&self, _queue_index: usize, _num: u16) -> Result<(), vhost::Error> {
+            Ok(())
+        }
+
+        fn set_vring_addr(
+            &self,
+            _queue_index: usize,
+            _config_data: &VringConfigData,
+        ) -> Result<(), vhost::Error> {
+            Ok(())
+        }
+
+        fn set_vring_base(&self, _queue_index: usize, _base: u16) -> Result<(), vhost::Error> {
+            Ok(())
+        }
+
+        fn set_vring_call(&self, _queue_index: usize, _fd: &EventFd) -> Result<(), vhost::Error> {
+            Ok(())
+        }
+
+        fn set_vring_kick(&self, _queue_index: usize, _fd: &EventFd) -> Result<(), vhost::Error> {
+            Ok(())
+        }
+
+        fn set_vring_enable(
+            &mut self,
+            queue_index: usize,
+            enable: bool,
+        ) -> Result<(), vhost::Error> {
+            let enabled_vrings = self.enabled_vrings.get_mut();
+            if enable {
+                enabled_vrings.push(queue_index);
+            } else {
+                enabled_vrings.retain(|index| *index != queue_index);
+            }
+            Ok(())
+        }
+    }
+
+    fn remote_config(socket: String) -> RemoteDeviceConfig {
+        RemoteDeviceConfig {
+            id: "remote0".to_string(),
+            device_type: 3,
+            socket,
+            num_queues: 2,
+            queue_size: 64,
+            config_size: 4,
+        }
+    }
+
+    #[test]
+    fn test_new() {
+        let _guard = BACKEND_LOCK.lock().unwrap();
+
+        // The queues of the backend can only be enabled through the protocol features.
+        BACKEND_FEATURES.store(AVAILABLE_FEATURES, Ordering::SeqCst);
+        BACKEND_FEATURES.fetch_and(
+            !VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits(),
+            Ordering::SeqCst,
+        );
+        let (_tmp_dir, tmp_socket_path) = create_tmp_socket();
+        assert!(matches!(
+            RemoteDeviceImpl::<MockFrontend>::new(remote_config(tmp_socket_path)),
+            Err(RemoteDeviceError::ProtocolFeatures)
+        ));
+
+        // The config space can't be fetched without the `CONFIG` protocol feature.
+        BACKEND_FEATURES.store(u64::MAX, Ordering::SeqCst);
+        BACKEND_PROTOCOL_FEATURES.store(0, Ordering::SeqCst);
+        let (_tmp_dir, tmp_socket_path) = create_tmp_socket();
+        assert!(matches!(
+            RemoteDeviceImpl::<MockFrontend>::new(remote_config(tmp_socket_path)),
+            Err(RemoteDeviceError::Config)
+        ));
+
+        BACKEND_PROTOCOL_FEATURES.store(VhostUserProtocolFeatures::all().bits(), Ordering::SeqCst);
+        let (_tmp_dir, tmp_socket_path) = create_tmp_socket();
+        let remote =
+            RemoteDeviceImpl::<MockFrontend>::new(remote_config(tmp_socket_path.clone())).unwrap();
+        assert_eq!(remote.vu_handle.vu.max_queue_num, 2);
+        // Only the device specific and the supported transport features are offered.
+        assert_eq!(remote.avail_features(), AVAILABLE_FEATURES);
+        assert_eq!(
+            remote.acked_features(),
+            VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits()
+        );
+        assert_eq!(
+            remote.vu_acked_protocol_features,
+            VhostUserProtocolFeatures::CONFIG.bits()
+        );
+        assert_eq!(remote.device_type(), 3);
+        assert_eq!(remote.queues().len(), 2);
+        assert_eq!(remote.queue_events().len(), 2);
+        assert_eq!(remote.queues()[1].get_max_size(), 64);
+        assert_eq!(remote.config_space, vec![0x69; 4]);
+        assert_eq!(remote.config(), remote_config(tmp_socket_path));
+    }
+
+    #[test]
+    fn test_config_space() {
+        let _guard = BACKEND_LOCK.lock().unwrap();
+        let (_tmp_dir, tmp_socket_path) = create_tmp_socket();
+        BACKEND_FEATURES.store(u64::MAX, Ordering::SeqCst);
+        BACKEND_PROTOCOL_FEATURES.store(VhostUserProtocolFeatures::all().bits(), Ordering::SeqCst);
+        let mut remote =
+            RemoteDeviceImpl::<MockFrontend>::new(remote_config(tmp_socket_path)).unwrap();
+
+        let mut data = [0u8; 2];
+        remote.read_config(3, &mut data);
+        assert_eq!(data, [0x69, 0]);
+
+        // Writes are forwarded to the backend.
+        remote.write_config(1, &[1, 2]);
+        assert_eq!(remote.config_space, vec![0x69, 1, 2, 0x69]);
+        assert_eq!(remote.vu_handle.vu.config[..4], [0x69, 1, 2, 0x69]);
+
+        // Out of bounds accesses are ignored.
+        let cfg_fails = remote.metrics.cfg_fails.count();
+        remote.write_config(3, &[3, 4]);
+        let mut data = [0u8; 1];
+        remote.read_config(5, &mut data);
+        assert_eq!(remote.config_space, vec![0x69, 1, 2, 0x69]);
+        assert_eq!(remote.metrics.cfg_fails.count(), cfg_fails + 2);
+    }
+
+    #[test]
+    fn test_activate() {
+        let _guard = BACKEND_LOCK.lock().unwrap();
+        let (_tmp_dir, tmp_socket_path) = create_tmp_socket();
+        BACKEND_FEATURES.store(u64::MAX, Ordering::SeqCst);
+        BACKEND_PROTOCOL_FEATURES.store(VhostUserProtocolFeatures::all().bits(), Ordering::SeqCst);
+        let mut remote =
+            RemoteDeviceImpl::<MockFrontend>::new(remote_config(tmp_socket_path)).unwrap();
+        remote.set_acked_features(remote.avail_features());
+
+        let region_size = 0x10000;
+        let file = TempFile::new().unwrap().into_file();
+        file.set_len(region_size as u64).unwrap();
+        let regions = vec![(
+            FileOffset::new(file.try_clone().unwrap(), 0x0),
+            GuestAddress(0x0),
+            region_size,
+        )];
+        let guest_memory = GuestMemoryMmap::from_raw_regions_file(regions, false, false).unwrap();
+
+        // The queues of an inactive device are not touched.
+        remote.set_queues_enabled(false).unwrap();
+
+        // All the queues are handed over to the backend.
+        remote.activate(guest_memory).unwrap();
+        assert_eq!(
+            unsafe { *remote.vu_handle.vu.features.get() },
+            AVAILABLE_FEATURES
+        );
+        assert!(unsafe { *remote.vu_handle.vu.memory_is_set.get() });
+        assert_eq!(
+            unsafe { &*remote.vu_handle.vu.enabled_vrings.get() },
+            &[0, 1]
+        );
+        assert!(remote.is_activated());
+
+        // The backend stops and restarts processing the queues.
+        remote.set_queues_enabled(false).unwrap();
+        assert!(unsafe { &*remote.vu_handle.vu.enabled_vrings.get() }.is_empty());
+        remote.set_queues_enabled(true).unwrap();
+        assert_eq!(
+            unsafe { &*remote.vu_handle.vu.enabled_vrings.get() },
+            &[0, 1]
+        );
+    }
+}
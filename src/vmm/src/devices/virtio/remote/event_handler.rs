@@ -0,0 +1,103 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use utils::epoll::EventSet;
+
+use super::RemoteDevice;
+use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::vhost_user::VhostUserHandleBackend;
+use crate::logger::{error, warn};
+
+impl RemoteDevice {
+    const PROCESS_ACTIVATE: u32 = 0;
+    const PROCESS_BACKEND: u32 = 1;
+
+    fn backend_events(&self) -> Events {
+        Events::with_data_raw(
+            self.vu_handle.vu.socket_fd(),
+            Self::PROCESS_BACKEND,
+            EventSet::IN | EventSet::READ_HANG_UP,
+        )
+    }
+
+    fn register_activate_event(&self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::with_data(
+            &self.activate_evt,
+            Self::PROCESS_ACTIVATE,
+            EventSet::IN,
+        )) {
+            error!("Failed to register activate event: {}", err);
+        }
+    }
+
+    fn process_activate_event(&self, ops: &mut EventOps) {
+        if let Err(err) = self.activate_evt.read() {
+            error!("Failed to consume remote device activate event: {:?}", err);
+        }
+        if let Err(err) = ops.add(self.backend_events()) {
+            error!("Failed to register remote device backend event: {}", err);
+        }
+        if let Err(err) = ops.remove(Events::with_data(
+            &self.activate_evt,
+            Self::PROCESS_ACTIVATE,
+            EventSet::IN,
+        )) {
+            error!("Failed to un-register activate event: {}", err);
+        }
+    }
+
+    fn process_backend_event(&mut self, event_set: EventSet, ops: &mut EventOps) {
+        // The frontend only reads the replies of the backend synchronously, so the socket
+        // becoming readable means the backend closed it.
+        if !event_set.intersects(EventSet::READ_HANG_UP | EventSet::HANG_UP | EventSet::ERROR) {
+            warn!(
+                "Remote device: Unexpected message from the backend: {:?}",
+                event_set
+            );
+            return;
+        }
+        if let Err(err) = ops.remove(self.backend_events()) {
+            error!("Failed to un-register remote device backend event: {}", err);
+        }
+        self.handle_backend_disconnect();
+    }
+}
+
+impl MutEventSubscriber for RemoteDevice {
+    fn process(&mut self, event: Events, ops: &mut EventOps) {
+        let source = event.data();
+        let event_set = event.event_set();
+
+        if self.is_activated() && Self::PROCESS_BACKEND == source {
+            self.process_backend_event(event_set, ops);
+            return;
+        }
+
+        let supported_events = EventSet::IN;
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if self.is_activated() && Self::PROCESS_ACTIVATE == source {
+            self.process_activate_event(ops);
+        } else {
+            warn!("Remote device: Spurious event received: {:?}", source);
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        // This function can be called during different points in the device lifetime:
+        //  - shortly after device creation,
+        //  - on device activation (is-activated already true at this point).
+        if self.is_activated() {
+            warn!("Remote device: unexpected init event");
+        } else {
+            self.register_activate_event(ops);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Virtio devices emulated out of process.
+//!
+//! A remote device only implements the virtio transport in Firecracker. The device itself is
+//! emulated by a backend process, which Firecracker connects to over a Unix domain socket
+//! using the vhost-user protocol. The backend maps the guest memory, gets notified of the queue
+//! events and notifies the guest of the used buffers directly through the file descriptors
+//! shared over the socket, so the data path doesn't go through Firecracker.
+
+pub mod device;
+pub mod event_handler;
+
+pub use self::device::{RemoteDevice, RemoteDeviceImpl};
+use crate::devices::virtio::vhost_user::VhostUserError;
+use crate::vmm_config::device_id::DeviceIdError;
+
+/// Maximum number of queues of a remote device.
+pub const MAX_QUEUES: u16 = 64;
+
+/// Maximum size of the queues of a remote device.
+pub const MAX_QUEUE_SIZE: u16 = 32768;
+
+/// Maximum size of the config space of a remote device, i.e. the size of the config space
+/// window of the MMIO transport.
+pub const MAX_CONFIG_SIZE: u32 = 0xf00;
+
+/// Remote device error.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum RemoteDeviceError {
+    /// Device type {0} is invalid or emulated by Firecracker itself.
+    DeviceType(u32),
+    /// Invalid number of queues {0}, it must be between 1 and 64.
+    NumQueues(u16),
+    /// Invalid queue size {0}, it must be a power of 2 no larger than 32768.
+    QueueSize(u16),
+    /// Invalid config space size {0}, it must be no larger than 3840 bytes.
+    ConfigSize(u32),
+    /// The backend does not support the vhost-user protocol features.
+    ProtocolFeatures,
+    /// The backend does not support exposing the config space of the device.
+    Config,
+    /// Vhost-user error: {0}
+    VhostUser(VhostUserError),
+    /// Vhost error: {0}
+    Vhost(vhost::Error),
+    /// Error opening eventfd: {0}
+    EventFd(std::io::Error),
+    /// Error creating irqfd: {0}
+    IrqTrigger(std::io::Error),
+    /// Invalid remote device ID: {0}
+    DeviceId(DeviceIdError),
+}
@@ -5,21 +5,24 @@ use std::io;
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
 
-use aws_lc_rs::rand;
+use aws_lc_rs::{digest, rand};
 use utils::eventfd::EventFd;
-use vm_memory::GuestMemoryError;
+use vm_memory::{GuestMemoryError, VolatileMemoryError};
 
 use super::metrics::METRICS;
 use super::{RNG_NUM_QUEUES, RNG_QUEUE};
+use crate::devices::replay::{self, ReplaySource};
 use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, VirtioDevice};
 use crate::devices::virtio::gen::virtio_rng::VIRTIO_F_VERSION_1;
-use crate::devices::virtio::iovec::IoVecBufferMut;
+use crate::devices::virtio::iovec::{IoVecBufferMut, IoVecError};
 use crate::devices::virtio::queue::{Queue, FIRECRACKER_MAX_QUEUE_SIZE};
 use crate::devices::virtio::{ActivateError, TYPE_RNG};
 use crate::devices::DeviceError;
 use crate::logger::{debug, error, IncMetric};
-use crate::rate_limiter::{RateLimiter, TokenType};
+use crate::rate_limiter::{BucketUpdate, RateLimiter};
+use crate::secret::SecretBuffer;
 use crate::vstate::memory::GuestMemoryMmap;
+use crate::vstate::memory_fault::{guarded, GuestMemoryFault};
 
 pub const ENTROPY_DEV_ID: &str = "rng";
 
@@ -31,6 +34,30 @@ pub enum EntropyError {
     GuestMemory(#[from] GuestMemoryError),
     /// Could not get random bytes: {0}
     Random(#[from] aws_lc_rs::error::Unspecified),
+    /// Could not write random bytes to the guest buffer: {0}
+    WriteBuffer(#[from] VolatileMemoryError),
+    /// Could not write random bytes to the guest buffer: {0}
+    GuestMemoryFault(GuestMemoryFault),
+}
+
+/// Deterministic random bit generator, producing the SHA-256 digests of a seed followed by an
+/// incrementing counter. Only meant for reproducible testing.
+#[derive(Debug)]
+struct DeterministicRng {
+    seed: u64,
+    counter: u64,
+}
+
+impl DeterministicRng {
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(digest::SHA256_OUTPUT_LEN) {
+            let mut ctx = digest::Context::new(&digest::SHA256);
+            ctx.update(&self.seed.to_le_bytes());
+            ctx.update(&self.counter.to_le_bytes());
+            chunk.copy_from_slice(&ctx.finish().as_ref()[..chunk.len()]);
+            self.counter += 1;
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -48,6 +75,9 @@ pub struct Entropy {
 
     // Device specific fields
     rate_limiter: RateLimiter,
+    deterministic_rng: Option<DeterministicRng>,
+    // Whether getting random bytes from the host fails, to inject faults.
+    source_fault: bool,
 }
 
 impl Entropy {
@@ -75,9 +105,32 @@ impl Entropy {
             queue_events,
             irq_trigger,
             rate_limiter,
+            deterministic_rng: None,
+            source_fault: false,
         })
     }
 
+    /// Replaces the host entropy source with a deterministic generator seeded with `seed`.
+    pub fn set_deterministic_seed(&mut self, seed: u64) {
+        self.deterministic_rng = Some(DeterministicRng { seed, counter: 0 });
+    }
+
+    /// Returns the seed of the deterministic generator, if the device uses one.
+    pub fn deterministic_seed(&self) -> Option<u64> {
+        self.deterministic_rng.as_ref().map(|rng| rng.seed)
+    }
+
+    /// Makes getting random bytes from the host entropy source fail, while `enabled`.
+    pub fn set_source_fault(&mut self, enabled: bool) {
+        self.source_fault = enabled;
+    }
+
+    /// Drops the interrupts of the device instead of delivering them to the guest, while
+    /// `enabled`.
+    pub fn set_irq_fault(&mut self, enabled: bool) {
+        self.irq_trigger.drop_irqs = enabled;
+    }
+
     pub fn id(&self) -> &str {
         ENTROPY_DEV_ID
     }
@@ -88,46 +141,60 @@ impl Entropy {
             .map_err(DeviceError::FailedSignalingIrq)
     }
 
-    fn rate_limit_request(rate_limiter: &mut RateLimiter, bytes: u64) -> bool {
-        if !rate_limiter.consume(1, TokenType::Ops) {
-            return false;
-        }
-
-        if !rate_limiter.consume(bytes, TokenType::Bytes) {
-            rate_limiter.manual_replenish(1, TokenType::Ops);
-            return false;
+    // Stops processing the requests of a driver that made a descriptor chain the device cannot
+    // use, until the driver resets the device.
+    fn fail_device(&self) {
+        if let Err(err) = self.irq_trigger.signal_device_failure() {
+            error!("entropy: Failed to signal the device failure: {err}");
         }
-
-        true
     }
 
-    fn rate_limit_replenish_request(rate_limiter: &mut RateLimiter, bytes: u64) {
-        rate_limiter.manual_replenish(1, TokenType::Ops);
-        rate_limiter.manual_replenish(bytes, TokenType::Bytes);
-    }
-
-    fn handle_one(&self, iovec: &mut IoVecBufferMut) -> Result<u32, EntropyError> {
+    fn handle_one(
+        mem: &GuestMemoryMmap,
+        deterministic_rng: Option<&mut DeterministicRng>,
+        source_fault: bool,
+        iovec: &mut IoVecBufferMut,
+    ) -> Result<u32, EntropyError> {
         // If guest provided us with an empty buffer just return directly
         if iovec.len() == 0 {
             return Ok(0);
         }
 
-        let mut rand_bytes = vec![0; iovec.len() as usize];
-        rand::fill(&mut rand_bytes).map_err(|err| {
+        if source_fault {
             METRICS.host_rng_fails.inc();
-            err
-        })?;
+            return Err(EntropyError::Random(aws_lc_rs::error::Unspecified));
+        }
+
+        // Zeroized when dropped, if the secret hardening is enabled.
+        let mut rand_bytes = SecretBuffer::new(iovec.len() as usize);
+        match deterministic_rng {
+            Some(rng) => rng.fill(&mut rand_bytes),
+            None => replay::replay_or_fill(&ReplaySource::Entropy, &mut rand_bytes, rand::fill)
+                .map_err(|err| {
+                    METRICS.host_rng_fails.inc();
+                    err
+                })?,
+        }
 
-        // It is ok to unwrap here. We are writing `iovec.len()` bytes at offset 0.
-        iovec.write_all_volatile_at(&rand_bytes, 0).unwrap();
+        let (res, fault) = guarded(mem, || iovec.write_all_volatile_at(&rand_bytes, 0));
+        if let Some(fault) = fault {
+            return Err(EntropyError::GuestMemoryFault(fault));
+        }
+        res?;
         Ok(iovec.len())
     }
 
     fn process_entropy_queue(&mut self) {
+        if self.irq_trigger.needs_reset() {
+            return;
+        }
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
 
-        let mut used_any = false;
+        let mut used = Vec::new();
+        METRICS
+            .queue_depth_hist
+            .record(self.queues[RNG_QUEUE].len(mem).into());
         while let Some(desc) = self.queues[RNG_QUEUE].pop(mem) {
             let index = desc.index;
             METRICS.entropy_event_count.inc();
@@ -142,19 +209,32 @@ impl Entropy {
                     // Check for available rate limiting budget.
                     // If not enough budget is available, leave the request descriptor in the queue
                     // to handle once we do have budget.
-                    if !Self::rate_limit_request(&mut self.rate_limiter, u64::from(iovec.len())) {
+                    if !self.rate_limiter.consume_op(u64::from(iovec.len())) {
                         debug!("entropy: throttling entropy queue");
                         METRICS.entropy_rate_limiter_throttled.inc();
                         self.queues[RNG_QUEUE].undo_pop();
                         break;
                     }
 
-                    self.handle_one(&mut iovec).unwrap_or_else(|err| {
+                    let _hist = METRICS.entropy_latency_hist.record_latency();
+                    Self::handle_one(
+                        mem,
+                        self.deterministic_rng.as_mut(),
+                        self.source_fault,
+                        &mut iovec,
+                    )
+                    .unwrap_or_else(|err| {
                         error!("entropy: {err}");
                         METRICS.entropy_event_fails.inc();
                         0
                     })
                 }
+                Err(err @ (IoVecError::GuestMemory(_) | IoVecError::OverflowedDescriptor)) => {
+                    error!("entropy: Malformed descriptor chain, failing the device: {err}");
+                    METRICS.entropy_event_fails.inc();
+                    self.fail_device();
+                    break;
+                }
                 Err(err) => {
                     error!("entropy: Could not parse descriptor chain: {err}");
                     METRICS.entropy_event_fails.inc();
@@ -162,33 +242,36 @@ impl Entropy {
                 }
             };
 
-            match self.queues[RNG_QUEUE].add_used(mem, index, bytes) {
-                Ok(_) => {
-                    used_any = true;
-                    METRICS.entropy_bytes.add(bytes.into());
-                }
-                Err(err) => {
-                    error!("entropy: Could not add used descriptor to queue: {err}");
-                    Self::rate_limit_replenish_request(&mut self.rate_limiter, bytes.into());
-                    METRICS.entropy_event_fails.inc();
-                    // If we are not able to add a buffer to the used queue, something
-                    // is probably seriously wrong, so just stop processing additional
-                    // buffers
-                    break;
-                }
-            }
+            used.push((index, bytes));
+        }
+
+        if used.is_empty() {
+            return;
         }
 
-        if used_any {
-            self.signal_used_queue().unwrap_or_else(|err| {
-                error!("entropy: {err:?}");
-                METRICS.entropy_event_fails.inc()
-            });
+        let bytes: u64 = used.iter().map(|&(_, bytes)| u64::from(bytes)).sum();
+        match self.queues[RNG_QUEUE].add_used_batch(mem, &used) {
+            Ok(()) => {
+                METRICS.entropy_bytes.add(bytes);
+                self.signal_used_queue().unwrap_or_else(|err| {
+                    error!("entropy: {err:?}");
+                    METRICS.entropy_event_fails.inc()
+                });
+            }
+            Err(err) => {
+                // If we are not able to add the buffers to the used queue, something is probably
+                // seriously wrong, so just give the budget of the requests back.
+                error!("entropy: Could not add used descriptors to queue: {err}");
+                for &(_, bytes) in &used {
+                    self.rate_limiter.replenish_op(bytes.into());
+                }
+                METRICS.entropy_event_fails.inc();
+            }
         }
     }
 
     pub(crate) fn process_entropy_queue_event(&mut self) {
-        if let Err(err) = self.queue_events[RNG_QUEUE].read() {
+        if let Err(err) = self.queues[RNG_QUEUE].read_kicks(&self.queue_events[RNG_QUEUE]) {
             error!("Failed to read entropy queue event: {err}");
             METRICS.entropy_event_fails.inc();
         } else if !self.rate_limiter.is_blocked() {
@@ -221,8 +304,9 @@ impl Entropy {
         &self.rate_limiter
     }
 
-    pub(crate) fn set_avail_features(&mut self, features: u64) {
-        self.avail_features = features;
+    /// Updates the parameters of the rate limiter.
+    pub fn update_rate_limiter(&mut self, bytes: BucketUpdate, ops: BucketUpdate) {
+        self.rate_limiter.update_buckets(bytes, ops);
     }
 
     pub(crate) fn set_acked_features(&mut self, features: u64) {
@@ -267,10 +351,18 @@ impl VirtioDevice for Entropy {
         self.irq_trigger.irq_status.clone()
     }
 
+    fn needs_reset(&self) -> bool {
+        self.irq_trigger.needs_reset()
+    }
+
     fn avail_features(&self) -> u64 {
         self.avail_features
     }
 
+    fn set_avail_features(&mut self, avail_features: u64) {
+        self.avail_features = avail_features;
+    }
+
     fn acked_features(&self) -> u64 {
         self.acked_features
     }
@@ -302,13 +394,23 @@ impl VirtioDevice for Entropy {
 mod tests {
     use std::time::Duration;
 
+    use utils::tempfile::TempFile;
+
     use super::*;
     use crate::check_metric_after_block;
     use crate::devices::virtio::device::VirtioDevice;
+    use crate::devices::virtio::net::MAX_BUFFER_SIZE;
     use crate::devices::virtio::queue::VIRTQ_DESC_F_WRITE;
     use crate::devices::virtio::test_utils::test::{
         create_virtio_mem, VirtioTestDevice, VirtioTestHelper,
     };
+    use crate::rate_limiter::TokenType;
+    use crate::signal_handler::register_signal_handlers;
+    use crate::vmm_config::machine_config::HugePageConfig;
+    use crate::vstate::memory::{
+        Address, Bytes, FileBackend, GuestAddress, GuestMemory, MemfdBackend, MemoryBackend,
+    };
+    use crate::vstate::memory_fault::inject_fault;
 
     impl VirtioTestDevice for Entropy {
         fn set_queues(&mut self, queues: Vec<Queue>) {
@@ -440,7 +542,177 @@ mod tests {
         // This should succeed, we should have one more descriptor
         let desc = entropy_dev.queues_mut()[RNG_QUEUE].pop(&mem).unwrap();
         let mut iovec = IoVecBufferMut::from_descriptor_chain(desc).unwrap();
-        entropy_dev.handle_one(&mut iovec).unwrap();
+        Entropy::handle_one(
+            &mem,
+            entropy_dev.deterministic_rng.as_mut(),
+            false,
+            &mut iovec,
+        )
+        .unwrap();
+
+        // The request fails while the entropy source fault is injected.
+        let err = Entropy::handle_one(
+            &mem,
+            entropy_dev.deterministic_rng.as_mut(),
+            true,
+            &mut iovec,
+        )
+        .unwrap_err();
+        assert!(matches!(err, EntropyError::Random(_)), "{err}");
+    }
+
+    // Guest memory of the size used by the virtio tests, mapped from a file holding zeroes.
+    fn file_backed_mem(shared: bool) -> GuestMemoryMmap {
+        let regions = [(GuestAddress(0), MAX_BUFFER_SIZE)];
+        if shared {
+            return MemfdBackend {
+                huge_pages: HugePageConfig::None,
+            }
+            .create(&regions, false)
+            .unwrap();
+        }
+        let file = TempFile::new().unwrap();
+        file.as_file().set_len(MAX_BUFFER_SIZE as u64).unwrap();
+        FileBackend {
+            file: file.as_file(),
+            shared: false,
+        }
+        .create(&regions, false)
+        .unwrap()
+    }
+
+    #[test]
+    fn test_handle_one_guest_memory_fault() {
+        register_signal_handlers().unwrap();
+        let mem = file_backed_mem(false);
+        let mut th = VirtioTestHelper::<Entropy>::new(&mem, default_entropy());
+        th.activate_device(&mem);
+
+        th.add_desc_chain(RNG_QUEUE, 0, &[(0, 64, VIRTQ_DESC_F_WRITE)]);
+        let mut entropy_dev = th.device();
+        let desc = entropy_dev.queues_mut()[RNG_QUEUE].pop(&mem).unwrap();
+        let addr = desc.addr;
+        let mut iovec = IoVecBufferMut::from_descriptor_chain(desc).unwrap();
+
+        // The fault fails the request instead of killing the process.
+        inject_fault(&mem, addr).unwrap();
+        let err = Entropy::handle_one(
+            &mem,
+            entropy_dev.deterministic_rng.as_mut(),
+            false,
+            &mut iovec,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, EntropyError::GuestMemoryFault(fault) if fault.guest_addr == addr.0),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_handle_one_shared_memory_fault() {
+        register_signal_handlers().unwrap();
+        let mem = file_backed_mem(true);
+        let mut th = VirtioTestHelper::<Entropy>::new(&mem, default_entropy());
+        th.activate_device(&mem);
+
+        th.add_desc_chain(RNG_QUEUE, 0, &[(0, 64, VIRTQ_DESC_F_WRITE)]);
+        let mut entropy_dev = th.device();
+        let desc = entropy_dev.queues_mut()[RNG_QUEUE].pop(&mem).unwrap();
+        let addr = desc.addr;
+        let mut iovec = IoVecBufferMut::from_descriptor_chain(desc).unwrap();
+        inject_fault(&mem, addr).unwrap();
+
+        // The faulting page is shared with the other processes mapping the memfd, so it is not
+        // replaced. Run the access in a child, as the fatal handler only exits outside of tests,
+        // leaving the access faulting until the alarm kills the child.
+        // SAFETY: The child only accesses the memory it inherited and exits.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            // SAFETY: Arming the alarm has no memory safety implications.
+            unsafe { libc::alarm(1) };
+            let res = Entropy::handle_one(
+                &mem,
+                entropy_dev.deterministic_rng.as_mut(),
+                false,
+                &mut iovec,
+            );
+            // SAFETY: Exiting the child has no memory safety implications.
+            unsafe { libc::_exit(i32::from(res.is_err())) };
+        }
+
+        let mut status = 0;
+        // SAFETY: The status is a valid pointer and the result is checked.
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFSIGNALED(status));
+        assert_eq!(libc::WTERMSIG(status), libc::SIGALRM);
+    }
+
+    #[test]
+    fn test_deterministic_rng() {
+        let mut rng = DeterministicRng {
+            seed: 42,
+            counter: 0,
+        };
+        let mut first = vec![0; 40];
+        rng.fill(&mut first);
+        // Each started digest block advances the counter.
+        assert_eq!(rng.counter, 2);
+
+        // The same seed produces the same bytes.
+        let mut rng = DeterministicRng {
+            seed: 42,
+            counter: 0,
+        };
+        let mut second = vec![0; 40];
+        rng.fill(&mut second);
+        assert_eq!(first, second);
+
+        // Later requests get different bytes.
+        rng.fill(&mut second);
+        assert_ne!(first, second);
+
+        let mut rng = DeterministicRng {
+            seed: 43,
+            counter: 0,
+        };
+        rng.fill(&mut second);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_deterministic_entropy() {
+        let mem = create_virtio_mem();
+        let mut entropy = default_entropy();
+        assert_eq!(entropy.deterministic_seed(), None);
+        entropy.set_deterministic_seed(7);
+        assert_eq!(entropy.deterministic_seed(), Some(7));
+        let mut th = VirtioTestHelper::<Entropy>::new(&mem, entropy);
+        th.activate_device(&mem);
+
+        th.add_desc_chain(RNG_QUEUE, 0, &[(0, 64, VIRTQ_DESC_F_WRITE)]);
+        let mut entropy_dev = th.device();
+        let desc = entropy_dev.queues_mut()[RNG_QUEUE].pop(&mem).unwrap();
+        let addr = desc.addr;
+        let mut iovec = IoVecBufferMut::from_descriptor_chain(desc).unwrap();
+        Entropy::handle_one(
+            &mem,
+            entropy_dev.deterministic_rng.as_mut(),
+            false,
+            &mut iovec,
+        )
+        .unwrap();
+
+        let mut expected = vec![0; 64];
+        DeterministicRng {
+            seed: 7,
+            counter: 0,
+        }
+        .fill(&mut expected);
+        let mut written = vec![0; 64];
+        mem.read_slice(&mut written, addr).unwrap();
+        assert_eq!(written, expected);
     }
 
     #[test]
@@ -498,6 +770,31 @@ mod tests {
         assert_eq!(METRICS.host_rng_fails.count(), host_rng_fails);
     }
 
+    #[test]
+    fn test_entropy_event_malformed_chain() {
+        let mem = create_virtio_mem();
+        let mut th = VirtioTestHelper::<Entropy>::new(&mem, default_entropy());
+
+        th.activate_device(&mem);
+
+        // Add a descriptor pointing outside of the guest memory.
+        let out_of_range = mem.last_addr().raw_value() + 1 - th.data_address();
+        th.add_desc_chain(RNG_QUEUE, out_of_range, &[(0, 64, VIRTQ_DESC_F_WRITE)]);
+
+        let entropy_event_fails = METRICS.entropy_event_fails.count();
+        assert_eq!(th.emulate_for_msec(100).unwrap(), 1);
+        assert_eq!(METRICS.entropy_event_fails.count(), entropy_event_fails + 1);
+        assert!(th.device().needs_reset());
+        assert!(th.device().irq_trigger.has_pending_irq(IrqType::Config));
+
+        // The device ignores the requests until the driver resets it.
+        th.add_desc_chain(RNG_QUEUE, 0, &[(1, 10, VIRTQ_DESC_F_WRITE)]);
+        let entropy_bytes = METRICS.entropy_bytes.count();
+        assert_eq!(th.emulate_for_msec(100).unwrap(), 1);
+        assert_eq!(METRICS.entropy_bytes.count(), entropy_bytes);
+        assert_eq!(th.device().queues()[RNG_QUEUE].next_used.0, 0);
+    }
+
     #[test]
     fn test_bad_rate_limiter_event() {
         let mem = create_virtio_mem();
@@ -29,14 +29,16 @@
 //!   FirecrackerDeviceMetrics.
 //! * Rely on `serde` to provide the actual serialization for writing the metrics.
 //!
-//! The system implements 1 type of metrics:
+//! The system implements 2 types of metrics:
 //! * Shared Incremental Metrics (SharedIncMetrics) - dedicated for the metrics which need a counter
 //! (i.e the number of times an API request failed). These metrics are reset upon flush.
+//! * Shared Histogram Metrics (SharedHistogramMetrics) - count the recorded values falling in each
+//!   of a fixed set of buckets. These metrics are reset upon flush.
 
 use serde::ser::SerializeMap;
 use serde::{Serialize, Serializer};
 
-use crate::logger::SharedIncMetric;
+use crate::logger::{LatencyBuckets, QueueDepthBuckets, SharedHistogramMetric, SharedIncMetric};
 
 /// Stores aggregated entropy metrics
 pub(super) static METRICS: EntropyDeviceMetrics = EntropyDeviceMetrics::new();
@@ -64,6 +66,10 @@ pub(super) struct EntropyDeviceMetrics {
     pub entropy_rate_limiter_throttled: SharedIncMetric,
     /// Number of events associated with the rate limiter
     pub rate_limiter_event_count: SharedIncMetric,
+    /// Histogram of the durations of the entropy requests, in microseconds
+    pub entropy_latency_hist: SharedHistogramMetric<LatencyBuckets>,
+    /// Histogram of the number of requests pending in the queue when it is processed
+    pub queue_depth_hist: SharedHistogramMetric<QueueDepthBuckets>,
 }
 impl EntropyDeviceMetrics {
     /// Const default construction.
@@ -76,6 +82,8 @@ impl EntropyDeviceMetrics {
             host_rng_fails: SharedIncMetric::new(),
             entropy_rate_limiter_throttled: SharedIncMetric::new(),
             rate_limiter_event_count: SharedIncMetric::new(),
+            entropy_latency_hist: SharedHistogramMetric::new(),
+            queue_depth_hist: SharedHistogramMetric::new(),
         }
     }
 }
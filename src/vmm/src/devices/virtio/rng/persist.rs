@@ -5,7 +5,10 @@
 
 use serde::{Deserialize, Serialize};
 
-use crate::devices::virtio::persist::{PersistError as VirtioStateError, VirtioDeviceState};
+use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::persist::{
+    PersistError as VirtioStateError, SnapshotFeature, VirtioDeviceState,
+};
 use crate::devices::virtio::queue::FIRECRACKER_MAX_QUEUE_SIZE;
 use crate::devices::virtio::rng::{Entropy, EntropyError, RNG_NUM_QUEUES};
 use crate::devices::virtio::TYPE_RNG;
@@ -20,6 +23,13 @@ pub struct EntropyState {
     rate_limiter_state: RateLimiterState,
 }
 
+impl EntropyState {
+    /// Snapshot features used by the device.
+    pub fn snapshot_features(&self) -> Vec<SnapshotFeature> {
+        self.virtio_state.snapshot_features()
+    }
+}
+
 #[derive(Debug)]
 pub struct EntropyConstructorArgs(GuestMemoryMmap);
 
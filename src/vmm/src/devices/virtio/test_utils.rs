@@ -480,6 +480,11 @@ pub(crate) mod test {
             event_fd.write(1).unwrap();
         }
 
+        /// Get one of the Virtqueues of the device
+        pub fn virtqueue(&self, queue: usize) -> &VirtQueue<'a> {
+            &self.virtqueues[queue]
+        }
+
         /// Emulate the device for a period of time
         ///
         /// # Arguments
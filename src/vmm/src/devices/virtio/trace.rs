@@ -0,0 +1,221 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Lightweight tracing of the virtio datapath.
+//!
+//! When Firecracker is built with the `virtio-trace` feature, each descriptor chain popped from a
+//! virtio queue is assigned a span ID, and the time at which it goes through each stage of its
+//! processing is recorded: pop from the avail ring, handover to the device backend, add to the
+//! used ring and guest notification. The most recent spans are kept in a bounded ring buffer.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use serde::Serialize;
+use utils::time::{get_time_us, ClockType};
+
+/// Whether the virtio datapath is traced in this build.
+pub const VIRTIO_TRACE_ENABLED: bool = cfg!(feature = "virtio-trace");
+
+/// Maximum number of spans kept in the trace ring buffer.
+pub const TRACE_CAPACITY: usize = 4096;
+
+/// Timestamps, in microseconds of monotonic time, of the processing stages of a descriptor chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TraceSpan {
+    /// Unique identifier of the span.
+    pub span_id: u64,
+    /// Guest physical address of the avail ring of the queue the chain was popped from.
+    pub queue_addr: u64,
+    /// Index of the head descriptor of the chain.
+    pub head_index: u16,
+    /// Time the chain was popped from the avail ring.
+    pub pop_us: u64,
+    /// Time the chain was handed over to the device backend.
+    pub backend_us: Option<u64>,
+    /// Time the chain was added to the used ring.
+    pub used_us: Option<u64>,
+    /// Time the guest was notified about the used chain.
+    pub irq_us: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Tracer {
+    next_span_id: u64,
+    // Spans of the chains popped but not yet used, by queue and head index.
+    in_flight: HashMap<(u64, u16), TraceSpan>,
+    // Spans of the used chains the guest was not yet notified about, by queue.
+    awaiting_irq: HashMap<u64, Vec<TraceSpan>>,
+    completed: VecDeque<TraceSpan>,
+}
+
+impl Tracer {
+    fn complete(&mut self, span: TraceSpan) {
+        if self.completed.len() == TRACE_CAPACITY {
+            self.completed.pop_front();
+        }
+        self.completed.push_back(span);
+    }
+
+    fn pop(&mut self, queue_addr: u64, head_index: u16, now_us: u64) {
+        // A chain given back to the avail ring with `undo_pop` keeps its original span.
+        if self.in_flight.contains_key(&(queue_addr, head_index)) {
+            return;
+        }
+        self.next_span_id += 1;
+        let span = TraceSpan {
+            span_id: self.next_span_id,
+            queue_addr,
+            head_index,
+            pop_us: now_us,
+            ..Default::default()
+        };
+        self.in_flight.insert((queue_addr, head_index), span);
+    }
+
+    fn backend(&mut self, queue_addr: u64, head_index: u16, now_us: u64) {
+        if let Some(span) = self.in_flight.get_mut(&(queue_addr, head_index)) {
+            span.backend_us.get_or_insert(now_us);
+        }
+    }
+
+    fn used(&mut self, queue_addr: u64, head_index: u16, now_us: u64) {
+        let Some(mut span) = self.in_flight.remove(&(queue_addr, head_index)) else {
+            return;
+        };
+        span.used_us = Some(now_us);
+
+        let pending = self.awaiting_irq.entry(queue_addr).or_default();
+        // Devices that never notify the guest through `Queue::prepare_kick` would otherwise
+        // accumulate spans forever.
+        let overflow = (pending.len() == TRACE_CAPACITY).then(|| pending.remove(0));
+        pending.push(span);
+        if let Some(span) = overflow {
+            self.complete(span);
+        }
+    }
+
+    fn irq(&mut self, queue_addr: u64, now_us: u64) {
+        for mut span in self.awaiting_irq.remove(&queue_addr).unwrap_or_default() {
+            span.irq_us = Some(now_us);
+            self.complete(span);
+        }
+    }
+
+    fn spans(&self) -> Vec<TraceSpan> {
+        let mut spans: Vec<_> = self
+            .completed
+            .iter()
+            .chain(self.awaiting_irq.values().flatten())
+            .copied()
+            .collect();
+        spans.sort_unstable_by_key(|span| span.span_id);
+        spans
+    }
+}
+
+fn tracer() -> MutexGuard<'static, Tracer> {
+    static TRACER: OnceLock<Mutex<Tracer>> = OnceLock::new();
+    TRACER
+        .get_or_init(|| Mutex::new(Tracer::default()))
+        .lock()
+        .expect("Poisoned lock")
+}
+
+/// Records that the chain with head `head_index` was popped from the queue at `queue_addr`.
+pub fn record_pop(queue_addr: u64, head_index: u16) {
+    tracer().pop(queue_addr, head_index, get_time_us(ClockType::Monotonic));
+}
+
+/// Records that the chain with head `head_index` was handed over to the device backend.
+pub fn record_backend(queue_addr: u64, head_index: u16) {
+    tracer().backend(queue_addr, head_index, get_time_us(ClockType::Monotonic));
+}
+
+/// Records that the chain with head `head_index` was added to the used ring.
+pub fn record_used(queue_addr: u64, head_index: u16) {
+    tracer().used(queue_addr, head_index, get_time_us(ClockType::Monotonic));
+}
+
+/// Records that the guest was notified about the used chains of the queue at `queue_addr`.
+pub fn record_irq(queue_addr: u64) {
+    tracer().irq(queue_addr, get_time_us(ClockType::Monotonic));
+}
+
+/// Returns the traced spans, oldest first. Spans still waiting for a guest notification are
+/// included.
+pub fn dump() -> Vec<TraceSpan> {
+    tracer().spans()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span_stages() {
+        let mut tracer = Tracer::default();
+
+        tracer.pop(0x1000, 3, 10);
+        tracer.backend(0x1000, 3, 20);
+        // Only the first handover to the backend is recorded.
+        tracer.backend(0x1000, 3, 25);
+        // Popping the same chain again after `undo_pop` keeps the span.
+        tracer.pop(0x1000, 3, 15);
+        tracer.pop(0x2000, 3, 30);
+        tracer.used(0x1000, 3, 40);
+        // Unknown chains are ignored.
+        tracer.backend(0x1000, 4, 40);
+        tracer.used(0x1000, 4, 40);
+
+        let spans = tracer.spans();
+        assert_eq!(
+            spans,
+            vec![TraceSpan {
+                span_id: 1,
+                queue_addr: 0x1000,
+                head_index: 3,
+                pop_us: 10,
+                backend_us: Some(20),
+                used_us: Some(40),
+                irq_us: None,
+            }]
+        );
+
+        // Notifying another queue doesn't complete the span.
+        tracer.irq(0x2000, 50);
+        assert_eq!(tracer.spans()[0].irq_us, None);
+        tracer.irq(0x1000, 60);
+        assert_eq!(tracer.spans()[0].irq_us, Some(60));
+        assert_eq!(tracer.completed.len(), 1);
+
+        tracer.used(0x2000, 3, 70);
+        tracer.irq(0x2000, 80);
+        let spans = tracer.spans();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[1].span_id, 2);
+        assert_eq!(spans[1].backend_us, None);
+        assert_eq!(spans[1].irq_us, Some(80));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut tracer = Tracer::default();
+        let count = u16::try_from(TRACE_CAPACITY).unwrap() + 10;
+
+        for head_index in 0..count {
+            tracer.pop(0x1000, head_index, u64::from(head_index));
+            tracer.used(0x1000, head_index, u64::from(head_index));
+        }
+        // Spans waiting for a notification are bounded.
+        assert_eq!(tracer.completed.len(), 10);
+        assert_eq!(tracer.awaiting_irq[&0x1000].len(), TRACE_CAPACITY);
+        assert!(tracer.completed.iter().all(|span| span.irq_us.is_none()));
+
+        tracer.irq(0x1000, 0);
+        assert_eq!(tracer.completed.len(), TRACE_CAPACITY);
+        assert!(tracer.awaiting_irq.is_empty());
+        // The oldest spans were dropped.
+        assert_eq!(tracer.spans()[0].span_id, 11);
+    }
+}
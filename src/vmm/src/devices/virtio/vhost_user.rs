@@ -4,7 +4,9 @@
 // Portions Copyright 2019 Intel Corporation. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::os::fd::AsRawFd;
+use std::fs::File;
+use std::num::Wrapping;
+use std::os::fd::{AsRawFd, RawFd};
 use std::os::unix::net::UnixStream;
 
 use utils::eventfd::EventFd;
@@ -26,6 +28,8 @@ pub enum VhostUserError {
     Connect(#[from] std::io::Error),
     /// Invalid descriptor table address
     DescriptorTableAddress(GuestMemoryError),
+    /// Get inflight fd failed: {0}
+    VhostUserGetInflightFd(VhostError),
     /// Get features failed: {0}
     VhostUserGetFeatures(VhostError),
     /// Get protocol features failed: {0}
@@ -50,6 +54,8 @@ pub enum VhostUserError {
     VhostUserSetVringKick(VhostError),
     /// Set vring enable failed: {0}
     VhostUserSetVringEnable(VhostError),
+    /// Set inflight fd failed: {0}
+    VhostUserSetInflightFd(VhostError),
     /// Failed to read vhost eventfd: {0}
     VhostUserMemoryRegion(MmapError),
     /// Invalid used address
@@ -159,6 +165,28 @@ pub trait VhostUserHandleBackend: Sized {
     ) -> Result<(), vhost::Error> {
         unimplemented!()
     }
+
+    /// Get a shared buffer from the backend to track the inflight I/O.
+    fn get_inflight_fd(
+        &mut self,
+        _inflight: &VhostUserInflight,
+    ) -> Result<(VhostUserInflight, File), vhost::Error> {
+        unimplemented!()
+    }
+
+    /// Set the shared buffer tracking the inflight I/O to the backend.
+    fn set_inflight_fd(
+        &mut self,
+        _inflight: &VhostUserInflight,
+        _fd: RawFd,
+    ) -> Result<(), vhost::Error> {
+        unimplemented!()
+    }
+
+    /// File descriptor of the socket connected to the backend.
+    fn socket_fd(&self) -> RawFd {
+        unimplemented!()
+    }
 }
 
 impl VhostUserHandleBackend for Frontend {
@@ -259,10 +287,51 @@ impl VhostUserHandleBackend for Frontend {
     ) -> Result<(), vhost::Error> {
         <Frontend as VhostUserFrontend>::set_config(self, offset, flags, buf)
     }
+
+    fn get_inflight_fd(
+        &mut self,
+        inflight: &VhostUserInflight,
+    ) -> Result<(VhostUserInflight, File), vhost::Error> {
+        <Frontend as VhostUserFrontend>::get_inflight_fd(self, inflight)
+    }
+
+    fn set_inflight_fd(
+        &mut self,
+        inflight: &VhostUserInflight,
+        fd: RawFd,
+    ) -> Result<(), vhost::Error> {
+        <Frontend as VhostUserFrontend>::set_inflight_fd(self, inflight, fd)
+    }
+
+    fn socket_fd(&self) -> RawFd {
+        self.as_raw_fd()
+    }
 }
 
 pub type VhostUserHandle = VhostUserHandleImpl<Frontend>;
 
+/// Shared buffer used by the backend to track the inflight I/O, so that a new backend
+/// instance can resubmit it after a reconnection.
+pub struct InflightRegion {
+    /// Layout of the buffer, as returned by the backend.
+    pub info: VhostUserInflight,
+    /// File backing the buffer.
+    pub file: File,
+}
+
+// Need custom implementation because `VhostUserInflight` does not implement `Debug`.
+impl std::fmt::Debug for InflightRegion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InflightRegion")
+            .field("mmap_size", &self.info.mmap_size)
+            .field("mmap_offset", &self.info.mmap_offset)
+            .field("num_queues", &self.info.num_queues)
+            .field("queue_size", &self.info.queue_size)
+            .field("file", &self.file)
+            .finish()
+    }
+}
+
 /// vhost-user socket handle
 #[derive(Clone)]
 pub struct VhostUserHandleImpl<T: VhostUserHandleBackend> {
@@ -283,7 +352,16 @@ impl<T: VhostUserHandleBackend> VhostUserHandleImpl<T> {
     /// owner of the session.
     pub fn new(socket_path: &str, num_queues: u64) -> Result<Self, VhostUserError> {
         let stream = UnixStream::connect(socket_path).map_err(VhostUserError::Connect)?;
+        Self::from_stream(stream, socket_path, num_queues)
+    }
 
+    /// Mark self as an owner of the session on an already connected
+    /// vhost-user backend stream.
+    pub fn from_stream(
+        stream: UnixStream,
+        socket_path: &str,
+        num_queues: u64,
+    ) -> Result<Self, VhostUserError> {
         let vu = T::from_stream(stream, num_queues);
         vu.set_owner().map_err(VhostUserError::VhostUserSetOwner)?;
 
@@ -394,6 +472,43 @@ impl<T: VhostUserHandleBackend> VhostUserHandleImpl<T> {
         Ok(())
     }
 
+    /// Get the buffer tracking the inflight I/O from the backend and hand it back to it. The
+    /// backend must have acked `VhostUserProtocolFeatures::INFLIGHT_SHMFD`.
+    pub fn setup_inflight(
+        &mut self,
+        num_queues: u16,
+        queue_size: u16,
+    ) -> Result<InflightRegion, VhostUserError> {
+        let (info, file) = self
+            .vu
+            .get_inflight_fd(&VhostUserInflight::new(0, 0, num_queues, queue_size))
+            .map_err(VhostUserError::VhostUserGetInflightFd)?;
+        let inflight = InflightRegion { info, file };
+        self.set_inflight(&inflight)?;
+        Ok(inflight)
+    }
+
+    /// Hand the buffer tracking the inflight I/O to the backend.
+    pub fn set_inflight(&mut self, inflight: &InflightRegion) -> Result<(), VhostUserError> {
+        self.vu
+            .set_inflight_fd(&inflight.info, inflight.file.as_raw_fd())
+            .map_err(VhostUserError::VhostUserSetInflightFd)
+    }
+
+    /// Ask the backend to start or stop processing the first `num_queues` vrings.
+    pub fn set_vrings_enabled(
+        &mut self,
+        num_queues: usize,
+        enable: bool,
+    ) -> Result<(), VhostUserError> {
+        for queue_index in 0..num_queues {
+            self.vu
+                .set_vring_enable(queue_index, enable)
+                .map_err(VhostUserError::VhostUserSetVringEnable)?;
+        }
+        Ok(())
+    }
+
     /// Set up vhost-user backend. This includes updating memory table,
     /// sending information about virtio rings and enabling them.
     pub fn setup_backend(
@@ -402,6 +517,31 @@ impl<T: VhostUserHandleBackend> VhostUserHandleImpl<T> {
         queues: &[(usize, &Queue, &EventFd)],
         irq_trigger: &IrqTrigger,
     ) -> Result<(), VhostUserError> {
+        self.setup_vrings(mem, queues, irq_trigger, |queue| queue.avail_idx(mem))
+    }
+
+    /// Set up a vhost-user backend replacing one that went away. The backend resumes
+    /// processing the vrings from the last used descriptors; the requests that were
+    /// inflight are resubmitted by the backend from the inflight buffer.
+    pub fn resume_backend(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        queues: &[(usize, &Queue, &EventFd)],
+        irq_trigger: &IrqTrigger,
+    ) -> Result<(), VhostUserError> {
+        self.setup_vrings(mem, queues, irq_trigger, |queue| queue.used_idx(mem))
+    }
+
+    fn setup_vrings<F>(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        queues: &[(usize, &Queue, &EventFd)],
+        irq_trigger: &IrqTrigger,
+        vring_base: F,
+    ) -> Result<(), VhostUserError>
+    where
+        F: Fn(&Queue) -> Wrapping<u16>,
+    {
         // Provide the memory table to the backend.
         self.update_mem_table(mem)?;
 
@@ -436,7 +576,7 @@ impl<T: VhostUserHandleBackend> VhostUserHandleImpl<T> {
                 .set_vring_addr(*queue_index, &config_data)
                 .map_err(VhostUserError::VhostUserSetVringAddr)?;
             self.vu
-                .set_vring_base(*queue_index, queue.avail_idx(mem).0)
+                .set_vring_base(*queue_index, vring_base(queue).0)
                 .map_err(VhostUserError::VhostUserSetVringBase)?;
 
             // No matter the queue, we set irq_evt for signaling the guest that buffers were
@@ -139,6 +139,12 @@ pub struct VhostUserDeviceMetrics {
     pub activate_time_us: SharedStoreMetric,
     // Vhost-user config change time in microseconds.
     pub config_change_time_us: SharedStoreMetric,
+    /// Number of times the vhost-user backend went away.
+    pub backend_disconnects: SharedIncMetric,
+    /// Number of successful reconnections to the vhost-user backend.
+    pub reconnects: SharedIncMetric,
+    /// Number of failed attempts to reconnect to the vhost-user backend.
+    pub reconnect_fails: SharedIncMetric,
 }
 
 #[cfg(test)]
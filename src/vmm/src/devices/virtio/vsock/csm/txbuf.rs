@@ -10,15 +10,17 @@ use utils::wrap_usize_to_u32;
 use vm_memory::{VolatileMemoryError, VolatileSlice, WriteVolatile};
 
 use super::{defs, VsockCsmError};
+use crate::secret::SecretBuffer;
 use crate::vstate::memory::{BitmapSlice, Bytes};
 
 /// A simple ring-buffer implementation, used by vsock connections to buffer TX (guest -> host)
 /// data.  Memory for this buffer is allocated lazily, since buffering will only be needed when
-/// the host can't read fast enough.
+/// the host can't read fast enough. With the secret hardening enabled, the data is zeroized as
+/// soon as it is flushed out.
 #[derive(Debug)]
 pub struct TxBuf {
     /// The actual u8 buffer - only allocated after the first push.
-    data: Option<Box<[u8]>>,
+    data: Option<SecretBuffer>,
     /// Ring-buffer head offset - where new data is pushed to.
     head: Wrapping<u32>,
     /// Ring-buffer tail offset - where data is flushed from.
@@ -56,7 +58,7 @@ impl TxBuf {
 
         let data = self
             .data
-            .get_or_insert_with(|| vec![0u8; Self::SIZE].into_boxed_slice());
+            .get_or_insert_with(|| SecretBuffer::new(Self::SIZE));
 
         // Buffer head, as an offset into the data slice.
         let head_ofs = self.head.0 as usize % Self::SIZE;
@@ -104,13 +106,14 @@ impl TxBuf {
         let len_to_write = std::cmp::min(Self::SIZE - tail_ofs, self.len());
 
         // It's safe to unwrap here, since we've already checked if the buffer was empty.
-        let data = self.data.as_ref().unwrap();
+        let data = self.data.as_mut().unwrap();
 
         // Issue the first write and absorb any `WouldBlock` error (we can just try again
         // later).
         let written = sink
             .write(&data[tail_ofs..(tail_ofs + len_to_write)])
             .map_err(VsockCsmError::TxBufFlush)?;
+        data.zeroize(tail_ofs..(tail_ofs + written));
 
         // Move the buffer tail ahead by the amount (of bytes) we were able to flush out.
         self.tail += wrap_usize_to_u32(written);
@@ -55,6 +55,7 @@ pub(crate) const AVAIL_FEATURES: u64 =
 /// Structure representing the vsock device.
 #[derive(Debug)]
 pub struct Vsock<B> {
+    id: String,
     cid: u64,
     pub(crate) queues: Vec<VirtQueue>,
     pub(crate) queue_events: Vec<EventFd>,
@@ -93,6 +94,7 @@ where
         }
 
         Ok(Vsock {
+            id: defs::VSOCK_DEV_ID.to_owned(),
             cid,
             queues,
             queue_events,
@@ -116,7 +118,12 @@ where
 
     /// Provides the ID of this vsock device as used in MMIO device identification.
     pub fn id(&self) -> &str {
-        defs::VSOCK_DEV_ID
+        &self.id
+    }
+
+    /// Sets the ID of this vsock device, which defaults to `VSOCK_DEV_ID`.
+    pub fn set_id(&mut self, id: String) {
+        self.id = id;
     }
 
     /// Retrieve the cid associated with this vsock device.
@@ -266,6 +273,10 @@ where
         self.avail_features
     }
 
+    fn set_avail_features(&mut self, avail_features: u64) {
+        self.avail_features = avail_features;
+    }
+
     fn acked_features(&self) -> u64 {
         self.acked_features
     }
@@ -119,6 +119,8 @@ pub enum VsockError {
     GuestMemoryMmap(GuestMemoryError),
     /// Bounds check failed on guest memory pointer.
     GuestMemoryBounds,
+    /// Guest memory access outside of the DMA ranges of the device: address {0:#x}, length {1:#x}
+    DmaRange(u64, u32),
     /** The total length of the descriptor chain ({0}) is less than the number of bytes required\
     to hold a vsock packet header.*/
     DescChainTooShortForHeader(usize),
@@ -147,6 +149,7 @@ impl From<IoVecError> for VsockError {
             IoVecError::ReadOnlyDescriptor => VsockError::UnwritableDescriptor,
             IoVecError::GuestMemory(err) => VsockError::GuestMemoryMmap(err),
             IoVecError::OverflowedDescriptor => VsockError::DescChainOverflow,
+            IoVecError::DmaRange(addr, len) => VsockError::DmaRange(addr, len),
         }
     }
 }
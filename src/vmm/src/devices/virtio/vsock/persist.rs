@@ -11,7 +11,7 @@ use serde::{Deserialize, Serialize};
 
 use super::*;
 use crate::devices::virtio::device::DeviceState;
-use crate::devices::virtio::persist::VirtioDeviceState;
+use crate::devices::virtio::persist::{SnapshotFeature, VirtioDeviceState};
 use crate::devices::virtio::queue::FIRECRACKER_MAX_QUEUE_SIZE;
 use crate::devices::virtio::vsock::TYPE_VSOCK;
 use crate::snapshot::Persist;
@@ -26,6 +26,13 @@ pub struct VsockState {
     pub frontend: VsockFrontendState,
 }
 
+impl VsockState {
+    /// Snapshot features used by the device.
+    pub fn snapshot_features(&self) -> Vec<SnapshotFeature> {
+        self.frontend.virtio_state.snapshot_features()
+    }
+}
+
 /// The Vsock frontend serializable state.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VsockFrontendState {
@@ -0,0 +1,399 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Block devices emulated by a worker process.
+
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+
+use serde::{Deserialize, Serialize};
+
+use super::{spawn_worker, WorkerDevice, WorkerError, WorkerProcess, WorkerRequest};
+use crate::devices::virtio::block::vhost_user::device::{VhostUserBlock, VhostUserBlockConfig};
+use crate::devices::virtio::block::vhost_user::{VhostUserBlockError, NUM_QUEUES};
+use crate::devices::virtio::block::virtio::device::{DiskProperties, FileEngineType};
+use crate::devices::virtio::block::virtio::metrics::BlockDeviceMetrics;
+use crate::devices::virtio::block::virtio::{ProcessingResult, Request, VirtioBlockError};
+use crate::devices::virtio::block::CacheType;
+use crate::devices::virtio::gen::virtio_blk::{
+    VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_RO, VIRTIO_F_VERSION_1,
+};
+use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
+use crate::devices::virtio::queue::Queue;
+use crate::devices::virtio::vhost_user::VhostUserHandle;
+use crate::vmm_config::drive::BlockDeviceConfig;
+use crate::vstate::memory::GuestMemoryMmap;
+
+/// Name of the block device worker processes.
+pub(super) const WORKER_NAME: &str = "fc_blk_worker";
+
+/// Block device worker error.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum BlockWorkerError {
+    /// Cannot create config
+    Config,
+    /// Cannot open the backing file: {0}
+    Disk(VirtioBlockError),
+    /// Cannot spawn the worker process: {0}
+    Spawn(WorkerError),
+    /// Cannot connect to the worker process: {0}
+    Frontend(VhostUserBlockError),
+}
+
+/// Use this structure to set up a block device emulated by a worker process.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockWorkerConfig {
+    /// Unique identifier of the drive.
+    pub drive_id: String,
+    /// Part-UUID. Represents the unique id of the boot partition of this device. It is
+    /// optional and it will be used only if the `is_root_device` field is true.
+    pub partuuid: Option<String>,
+    /// If set to true, it makes the current device the root block device.
+    pub is_root_device: bool,
+    /// Caching strategy of the drive.
+    pub cache_type: CacheType,
+    /// If set to true, the drive is opened in read-only mode.
+    pub is_read_only: bool,
+    /// Path of the drive.
+    pub path_on_host: String,
+}
+
+impl TryFrom<&BlockDeviceConfig> for BlockWorkerConfig {
+    type Error = BlockWorkerError;
+
+    fn try_from(value: &BlockDeviceConfig) -> Result<Self, Self::Error> {
+        // The worker only runs the Sync engine, and can't open copy-on-write overlays nor
+        // throttle the guest.
+        match &value.path_on_host {
+            Some(path_on_host)
+                if value.worker
+                    && value.socket.is_none()
+                    && value.reconnect.is_none()
+                    && value.rate_limiter.is_none()
+                    && value.overlay_path.is_none()
+                    && matches!(value.file_engine_type, None | Some(FileEngineType::Sync)) =>
+            {
+                Ok(Self {
+                    drive_id: value.drive_id.clone(),
+                    partuuid: value.partuuid.clone(),
+                    is_root_device: value.is_root_device,
+                    cache_type: value.cache_type,
+                    is_read_only: value.is_read_only.unwrap_or(false),
+                    path_on_host: path_on_host.clone(),
+                })
+            }
+            _ => Err(BlockWorkerError::Config),
+        }
+    }
+}
+
+impl From<BlockWorkerConfig> for BlockDeviceConfig {
+    fn from(value: BlockWorkerConfig) -> Self {
+        Self {
+            drive_id: value.drive_id,
+            partuuid: value.partuuid,
+            is_root_device: value.is_root_device,
+            cache_type: value.cache_type,
+
+            is_read_only: Some(value.is_read_only),
+            path_on_host: Some(value.path_on_host),
+            rate_limiter: None,
+            file_engine_type: Some(FileEngineType::Sync),
+            overlay_path: None,
+
+            socket: None,
+            reconnect: None,
+
+            worker: true,
+        }
+    }
+}
+
+/// Worker process emulating a block device, as seen by the VMM.
+#[derive(Debug)]
+pub struct BlockWorker {
+    /// Handle of the worker process.
+    pub process: WorkerProcess,
+    /// Configuration of the device.
+    pub config: BlockWorkerConfig,
+}
+
+/// Spawns the worker emulating the block device described by `config`, and creates the
+/// vhost-user frontend of the device connected to it.
+pub fn create_block_worker(config: BlockWorkerConfig) -> Result<VhostUserBlock, BlockWorkerError> {
+    // The backing file is opened by the VMM, which reports the errors of the configuration, and
+    // sent to the worker.
+    let disk = DiskProperties::new(
+        config.path_on_host.clone(),
+        None,
+        config.is_read_only,
+        config.cache_type,
+        FileEngineType::Sync,
+    )
+    .map_err(BlockWorkerError::Disk)?;
+    let (process, stream) = spawn_worker(
+        &WorkerRequest::Block(config.clone()),
+        &[disk.file_engine.file().as_raw_fd()],
+    )
+    .map_err(BlockWorkerError::Spawn)?;
+
+    let frontend_config = VhostUserBlockConfig {
+        drive_id: config.drive_id.clone(),
+        partuuid: config.partuuid.clone(),
+        is_root_device: config.is_root_device,
+        cache_type: config.cache_type,
+        socket: String::new(),
+        reconnect: None,
+    };
+    let vu_handle = VhostUserHandle::from_stream(stream, "", NUM_QUEUES)
+        .map_err(|err| BlockWorkerError::Frontend(VhostUserBlockError::VhostUser(err)))?;
+    let mut block = VhostUserBlock::with_handle(frontend_config, vu_handle)
+        .map_err(BlockWorkerError::Frontend)?;
+    block.worker = Some(BlockWorker { process, config });
+    Ok(block)
+}
+
+/// Creates the device emulation of a worker process from the backing file in `files`.
+pub(super) fn worker_device(
+    config: &BlockWorkerConfig,
+    files: Vec<File>,
+) -> io::Result<impl WorkerDevice> {
+    let disk_image = files
+        .into_iter()
+        .next()
+        .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+    let disk = DiskProperties::from_file(
+        config.path_on_host.clone(),
+        disk_image,
+        FileEngineType::Sync,
+    )
+    .map_err(io::Error::other)?;
+    Ok(BlockWorkerDevice::new(config, disk))
+}
+
+// Block device emulation running in the worker process.
+#[derive(Debug)]
+struct BlockWorkerDevice {
+    disk: DiskProperties,
+    cache_type: CacheType,
+    features: u64,
+    config_space: Vec<u8>,
+    // The metrics of the worker are not flushed, they are only needed to process the requests.
+    metrics: BlockDeviceMetrics,
+}
+
+impl BlockWorkerDevice {
+    fn new(config: &BlockWorkerConfig, disk: DiskProperties) -> Self {
+        let mut features = (1u64 << VIRTIO_F_VERSION_1)
+            | (1u64 << VIRTIO_RING_F_EVENT_IDX)
+            | vhost::vhost_user::VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits();
+        if config.cache_type == CacheType::Writeback {
+            features |= 1u64 << VIRTIO_BLK_F_FLUSH;
+        }
+        if config.is_read_only {
+            features |= 1u64 << VIRTIO_BLK_F_RO;
+        }
+
+        Self {
+            config_space: disk.virtio_block_config_space(),
+            disk,
+            cache_type: config.cache_type,
+            features,
+            metrics: BlockDeviceMetrics::new(),
+        }
+    }
+}
+
+impl WorkerDevice for BlockWorkerDevice {
+    fn features(&self) -> u64 {
+        self.features
+    }
+
+    fn config_space(&self) -> &[u8] {
+        &self.config_space
+    }
+
+    fn num_queues(&self) -> usize {
+        1
+    }
+
+    fn host_fds(&self) -> Vec<RawFd> {
+        vec![
+            self.disk.file_engine.file().as_raw_fd(),
+            self.disk.completion_evt.as_raw_fd(),
+        ]
+    }
+
+    fn process_queue(&mut self, _index: usize, queue: &mut Queue, mem: &GuestMemoryMmap) -> bool {
+        let mut used_any = false;
+        while let Some(head) = queue.pop_or_enable_notification(mem) {
+            let num_bytes_to_mem = match Request::parse(&head, mem, self.disk.nsectors) {
+                Ok(request) => match request.process(
+                    &mut self.disk,
+                    self.cache_type,
+                    false,
+                    head.index,
+                    mem,
+                    &self.metrics,
+                ) {
+                    ProcessingResult::Executed(finished) => finished.num_bytes_to_mem,
+                    // The Sync engine executes the requests right away.
+                    ProcessingResult::Submitted | ProcessingResult::Throttled => 0,
+                },
+                Err(_) => 0,
+            };
+            // The descriptor chain was just popped from the queue, so its index is valid.
+            let _ = queue.add_used(mem, head.index, num_bytes_to_mem);
+            used_any = true;
+        }
+        used_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+    use crate::devices::virtio::device::VirtioDevice;
+    use crate::devices::virtio::gen::virtio_blk::VIRTIO_BLK_T_IN;
+    use crate::devices::virtio::queue::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+    use crate::devices::virtio::test_utils::VirtQueue;
+    use crate::devices::virtio::worker::tests::wait_exit;
+    use crate::vmm_config::machine_config::HugePageConfig;
+    use crate::vstate::memory::{Bytes, GuestAddress, MemfdBackend, MemoryBackend};
+
+    fn block_config(path_on_host: &str) -> BlockDeviceConfig {
+        BlockDeviceConfig {
+            drive_id: "block".to_string(),
+            partuuid: None,
+            is_root_device: false,
+            cache_type: CacheType::Unsafe,
+
+            is_read_only: None,
+            path_on_host: Some(path_on_host.to_string()),
+            rate_limiter: None,
+            file_engine_type: None,
+            overlay_path: None,
+
+            socket: None,
+            reconnect: None,
+
+            worker: true,
+        }
+    }
+
+    #[test]
+    fn test_from_config() {
+        let mut config = block_config("path");
+        let worker_config = BlockWorkerConfig::try_from(&config).unwrap();
+        assert_eq!(worker_config.path_on_host, "path");
+        assert!(!worker_config.is_read_only);
+        assert_eq!(
+            BlockDeviceConfig::from(worker_config),
+            BlockDeviceConfig {
+                is_read_only: Some(false),
+                file_engine_type: Some(FileEngineType::Sync),
+                ..block_config("path")
+            }
+        );
+
+        config.file_engine_type = Some(FileEngineType::Async);
+        BlockWorkerConfig::try_from(&config).unwrap_err();
+
+        config.file_engine_type = None;
+        config.overlay_path = Some("overlay".to_string());
+        BlockWorkerConfig::try_from(&config).unwrap_err();
+
+        config.overlay_path = None;
+        config.worker = false;
+        BlockWorkerConfig::try_from(&config).unwrap_err();
+    }
+
+    #[test]
+    fn test_create_block_worker() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let mut config =
+            BlockWorkerConfig::try_from(&block_config(f.as_path().to_str().unwrap())).unwrap();
+        config.is_read_only = true;
+
+        let block = create_block_worker(config.clone()).unwrap();
+        // The features and the config space are those of the worker.
+        assert_ne!(block.avail_features() & (1 << VIRTIO_F_VERSION_1), 0);
+        assert_ne!(block.avail_features() & (1 << VIRTIO_BLK_F_RO), 0);
+        assert_eq!(block.avail_features() & (1 << VIRTIO_BLK_F_FLUSH), 0);
+        assert_eq!(block.config_space[..8], 8u64.to_le_bytes());
+
+        let worker = block.worker.as_ref().unwrap();
+        assert_eq!(worker.config, config);
+        let pid = worker.process.pid();
+        assert!(!wait_exit(pid, 0));
+        // The worker runs in user, mount and network namespaces of its own, and doesn't keep
+        // the privileges of the tests.
+        for ns in ["user", "mnt", "net"] {
+            assert_ne!(
+                std::fs::read_link(format!("/proc/{pid}/ns/{ns}")).unwrap(),
+                std::fs::read_link(format!("/proc/self/ns/{ns}")).unwrap()
+            );
+        }
+        // SAFETY: geteuid cannot fail.
+        if unsafe { libc::geteuid() } == 0 {
+            let status = std::fs::read_to_string(format!("/proc/{pid}/status")).unwrap();
+            assert!(status.contains("\nUid:\t65534\t65534\t65534\t65534\n"));
+        }
+
+        // Dropping the device kills the worker.
+        drop(block);
+        assert!(wait_exit(pid, 5000));
+    }
+
+    #[test]
+    fn test_block_worker_io() {
+        let f = TempFile::new().unwrap();
+        f.as_file().write_all(&[0xab; 512]).unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let config =
+            BlockWorkerConfig::try_from(&block_config(f.as_path().to_str().unwrap())).unwrap();
+        let mut block = create_block_worker(config).unwrap();
+
+        // The worker maps the guest memory, so it has to be shared.
+        let mem = MemfdBackend {
+            huge_pages: HugePageConfig::None,
+        }
+        .create(&[(GuestAddress(0), 0x10000)], false)
+        .unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.queues[0] = vq.create_queue();
+
+        // Read the first sector of the disk.
+        vq.dtable[0].set(0x2000, 16, VIRTQ_DESC_F_NEXT, 1);
+        vq.dtable[1].set(0x3000, 512, VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE, 2);
+        vq.dtable[2].set(0x4000, 1, VIRTQ_DESC_F_WRITE, 0);
+        mem.write_obj(VIRTIO_BLK_T_IN, GuestAddress(0x2000))
+            .unwrap();
+        mem.write_obj(0u64, GuestAddress(0x2008)).unwrap();
+        mem.write_obj(0xffu8, GuestAddress(0x4000)).unwrap();
+        block.activate(mem.clone()).unwrap();
+        vq.avail.ring[0].set(0);
+        vq.avail.idx.set(1);
+        block.queue_evts[0].write(1).unwrap();
+
+        let start = Instant::now();
+        while vq.used.idx.get() == 0 && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        vq.check_used_elem(0, 0, 513);
+        let mut data = [0u8; 512];
+        mem.read_slice(&mut data, GuestAddress(0x3000)).unwrap();
+        assert_eq!(data, [0xab; 512]);
+        assert_eq!(mem.read_obj::<u8>(GuestAddress(0x4000)).unwrap(), 0);
+        // The worker is still alive after processing the request.
+        let pid = block.worker.as_ref().unwrap().process.pid();
+        assert!(!wait_exit(pid, 0));
+    }
+}
@@ -0,0 +1,971 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runs the emulation of virtio devices in sandboxed worker processes.
+//!
+//! Forking a multi-threaded process is only safe if the child restricts itself to
+//! async-signal-safe calls until it execs, which the device emulation can't do. Firecracker thus
+//! forks a spawner process when it starts, while it is still single-threaded, and the workers are
+//! forked from the spawner. When a device is created, the VMM sends the spawner a request
+//! describing the device along with the file descriptors of its host resources. The worker only
+//! keeps these file descriptors open, drops its privileges, installs a seccomp filter restricting
+//! it to the system calls needed to process the queues, and serves the vhost-user protocol on a
+//! socket connected to the device frontend, which stays in the VMM process. A bug in the device
+//! emulation exploited by the guest is then confined to the worker.
+
+pub mod block;
+pub mod net;
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::num::Wrapping;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixDatagram, UnixStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use seccompiler::backend::{
+    FilterError, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter,
+    SeccompRule, SeccompRuleMap,
+};
+use seccompiler::{BpfProgram, BpfProgramRef};
+use serde::{Deserialize, Serialize};
+use utils::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
+use utils::sock_ctrl_msg::ScmSocket;
+use utils::u64_to_usize;
+use vhost::vhost_user::message::{
+    VhostTransferStateDirection, VhostTransferStatePhase, VhostUserConfigFlags, VhostUserInflight,
+    VhostUserLog, VhostUserMemoryRegion, VhostUserSingleMemoryRegion, VhostUserVringAddrFlags,
+    VhostUserVringState,
+};
+use vhost::vhost_user::{
+    BackendReqHandler, Error, Result, VhostUserBackendReqHandlerMut, VhostUserProtocolFeatures,
+};
+
+use self::block::BlockWorkerConfig;
+use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
+use crate::devices::virtio::queue::{Queue, FIRECRACKER_MAX_QUEUE_SIZE};
+use crate::vstate::memory::{FileOffset, GuestAddress, GuestMemoryExtension, GuestMemoryMmap};
+
+/// Epoll data of the vhost-user socket. The kick events use their queue index.
+const SOCKET_EVENT: u64 = u64::MAX;
+/// Epoll data of the host event of the device.
+const HOST_EVENT: u64 = u64::MAX - 1;
+/// Largest config space a worker device can expose.
+const MAX_CONFIG_SIZE: usize = 256;
+/// Name of the spawner process.
+const SPAWNER_NAME: &str = "fc_wrk_spawner";
+/// Largest request the spawner accepts.
+const MAX_REQUEST_SIZE: usize = 64 << 10;
+/// Largest number of file descriptors sent along with a request.
+const MAX_REQUEST_FDS: usize = 8;
+/// User and group the workers run as when Firecracker runs as root.
+const WORKER_ID: libc::uid_t = 65534;
+
+// System calls the workers make to serve the vhost-user protocol and to access the host
+// resources of their device. Opening files, creating sockets or processes and issuing ioctls
+// are not allowed.
+const WORKER_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_brk,
+    libc::SYS_clock_gettime,
+    libc::SYS_close,
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_pwait,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    libc::SYS_fsync,
+    libc::SYS_futex,
+    libc::SYS_lseek,
+    libc::SYS_madvise,
+    libc::SYS_mremap,
+    libc::SYS_munmap,
+    libc::SYS_read,
+    libc::SYS_recvmsg,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sched_yield,
+    libc::SYS_sendmsg,
+    libc::SYS_tkill,
+    libc::SYS_write,
+    libc::SYS_writev,
+];
+
+/// Socket connected to the spawner, set once the spawner is started.
+static SPAWNER: OnceLock<Mutex<UnixDatagram>> = OnceLock::new();
+
+/// Errors associated with the worker processes.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum WorkerError {
+    /// Cannot build the seccomp filter of the worker: {0}
+    SeccompFilter(FilterError),
+    /// Cannot create the socket connected to the worker: {0}
+    Socket(io::Error),
+    /// Cannot fork the spawner process: {0}
+    Fork(io::Error),
+    /// The spawner process was not started
+    NoSpawner,
+    /// Cannot send the request to the spawner process: {0}
+    Spawner(io::Error),
+    /// Cannot start the worker process: {0}
+    Start(io::Error),
+}
+
+/// Device a worker process is asked to emulate.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum WorkerRequest {
+    /// Block device backed by the file sent along with the request.
+    Block(BlockWorkerConfig),
+    /// Network device moving frames to and from the tap device sent along with the request.
+    Net,
+}
+
+/// Device emulation run by a worker process.
+pub trait WorkerDevice {
+    /// Virtio features offered by the device.
+    fn features(&self) -> u64;
+
+    /// Content of the device config space.
+    fn config_space(&self) -> &[u8];
+
+    /// Number of queues of the device.
+    fn num_queues(&self) -> usize;
+
+    /// File descriptors of the host resources of the device, which stay open in the worker.
+    fn host_fds(&self) -> Vec<RawFd>;
+
+    /// File descriptor signaling that the device has host data for the guest, along with the
+    /// index of the queue the data goes to.
+    fn host_event(&self) -> Option<(RawFd, usize)> {
+        None
+    }
+
+    /// Processes the descriptor chains the guest made available in queue `index`. Returns
+    /// whether any of them was used.
+    fn process_queue(&mut self, index: usize, queue: &mut Queue, mem: &GuestMemoryMmap) -> bool;
+}
+
+/// Handle of a worker process. The worker is killed when the handle is dropped.
+#[derive(Debug)]
+pub struct WorkerProcess {
+    pid: libc::pid_t,
+    // The worker is a child of the spawner, so it is signaled through a pidfd, which can't refer
+    // to another process once the worker is reaped.
+    pidfd: File,
+}
+
+impl WorkerProcess {
+    /// Process ID of the worker.
+    pub fn pid(&self) -> libc::pid_t {
+        self.pid
+    }
+}
+
+impl Drop for WorkerProcess {
+    fn drop(&mut self) {
+        // SAFETY: The pidfd refers to the worker, and the spawner reaps it.
+        unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                self.pidfd.as_raw_fd(),
+                libc::SIGKILL,
+                std::ptr::null::<libc::siginfo_t>(),
+                0,
+            );
+        }
+    }
+}
+
+/// Forks the spawner process, from which the worker processes are forked.
+///
+/// # Safety
+///
+/// The process must be single-threaded, as the spawner doesn't restrict itself to
+/// async-signal-safe calls.
+pub unsafe fn start_spawner() -> std::result::Result<(), WorkerError> {
+    let filter = seccomp_filter().map_err(WorkerError::SeccompFilter)?;
+    let (vmm, spawner) = UnixDatagram::pair().map_err(WorkerError::Socket)?;
+
+    // SAFETY: getpid cannot fail.
+    let parent = unsafe { libc::getpid() };
+    // SAFETY: The process is single-threaded, and the child only runs the spawner, exiting
+    // without returning from this function.
+    match unsafe { libc::fork() } {
+        -1 => Err(WorkerError::Fork(io::Error::last_os_error())),
+        0 => {
+            drop(vmm);
+            let code =
+                panic::catch_unwind(AssertUnwindSafe(|| run_spawner(&spawner, parent, &filter)))
+                    .unwrap_or(libc::EXIT_FAILURE);
+            // SAFETY: Exits the spawner without running the destructors of the VMM state.
+            unsafe { libc::_exit(code) }
+        }
+        _ => {
+            // The spawner can only be started once.
+            let _ = SPAWNER.set(Mutex::new(vmm));
+            Ok(())
+        }
+    }
+}
+
+/// Asks the spawner to fork a worker emulating the device described by `request`, whose host
+/// resources are `host_fds`. Returns the handle of the worker and the socket on which it serves
+/// the vhost-user protocol.
+pub fn spawn_worker(
+    request: &WorkerRequest,
+    host_fds: &[RawFd],
+) -> std::result::Result<(WorkerProcess, UnixStream), WorkerError> {
+    let spawner = SPAWNER
+        .get()
+        .ok_or(WorkerError::NoSpawner)?
+        .lock()
+        .expect("Poisoned lock");
+    let (frontend, backend) = UnixStream::pair().map_err(WorkerError::Socket)?;
+    let request = serde_json::to_vec(request).map_err(|err| WorkerError::Spawner(err.into()))?;
+    let mut fds = vec![backend.as_raw_fd()];
+    fds.extend_from_slice(host_fds);
+    spawner
+        .send_with_fds(&[&request[..]], &fds)
+        .map_err(|err| WorkerError::Spawner(err.into()))?;
+    drop(backend);
+
+    // The spawner replies with the PID of the worker and its pidfd, or with an error number.
+    let mut reply = [0u8; 4];
+    let (len, pidfd) = spawner
+        .recv_with_fd(&mut reply)
+        .map_err(|err| WorkerError::Spawner(err.into()))?;
+    match (len, i32::from_ne_bytes(reply), pidfd) {
+        (4, pid, Some(pidfd)) if pid > 0 => Ok((WorkerProcess { pid, pidfd }, frontend)),
+        (4, errno, _) if errno < 0 => Err(WorkerError::Start(io::Error::from_raw_os_error(-errno))),
+        _ => Err(WorkerError::Spawner(io::Error::from_raw_os_error(
+            libc::EPROTO,
+        ))),
+    }
+}
+
+// Runs in the spawner process. Returns the exit code of the spawner.
+fn run_spawner(socket: &UnixDatagram, parent: libc::pid_t, filter: BpfProgramRef) -> i32 {
+    if die_with_parent(parent).is_err() || close_fds_except(&[socket.as_raw_fd()]).is_err() {
+        return libc::EXIT_FAILURE;
+    }
+    set_name(SPAWNER_NAME);
+
+    let mut buf = vec![0u8; MAX_REQUEST_SIZE];
+    loop {
+        let mut fds = [-1; MAX_REQUEST_FDS];
+        let mut iovecs = [libc::iovec {
+            iov_base: buf.as_mut_ptr().cast(),
+            iov_len: buf.len(),
+        }];
+        // SAFETY: The iovec covers `buf`, to which arbitrary data can be written.
+        let (len, num_fds) = match unsafe { socket.recv_with_fds(&mut iovecs, &mut fds) } {
+            Ok(received) => received,
+            Err(err) if err.errno() == libc::EINTR => continue,
+            Err(_) => return libc::EXIT_FAILURE,
+        };
+        let files = fds[..num_fds]
+            .iter()
+            // SAFETY: The file descriptors were just received, so they are owned by nothing else.
+            .map(|&fd| unsafe { File::from_raw_fd(fd) })
+            .collect();
+
+        reap_workers();
+        let sent = match fork_worker(&buf[..len], files, filter) {
+            Ok((pid, pidfd)) => socket.send_with_fd(&pid.to_ne_bytes()[..], pidfd.as_raw_fd()),
+            Err(err) => {
+                let errno = -err.raw_os_error().unwrap_or(libc::EIO);
+                socket.send_with_fds(&[&errno.to_ne_bytes()[..]], &[])
+            }
+        };
+        if sent.is_err() {
+            return libc::EXIT_FAILURE;
+        }
+    }
+}
+
+// Reaps the workers which exited.
+fn reap_workers() {
+    // SAFETY: waitpid is called with valid arguments, and only reaps children of the spawner.
+    while unsafe { libc::waitpid(-1, std::ptr::null_mut(), libc::WNOHANG) } > 0 {}
+}
+
+// Forks a worker serving the request in `request`. The first file in `files` is the vhost-user
+// socket, and the others are the host resources of the device. Returns the PID of the worker and
+// its pidfd once the worker is sandboxed.
+fn fork_worker(
+    request: &[u8],
+    mut files: Vec<File>,
+    filter: BpfProgramRef,
+) -> io::Result<(libc::pid_t, File)> {
+    let request: WorkerRequest = serde_json::from_slice(request)?;
+    if files.is_empty() {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    let socket = UnixStream::from(OwnedFd::from(files.remove(0)));
+    let (mut status_rx, status_tx) = pipe()?;
+
+    // SAFETY: getpid cannot fail.
+    let spawner = unsafe { libc::getpid() };
+    // SAFETY: The spawner is single-threaded, and the child only runs the worker, exiting without
+    // returning from this function.
+    let pid = match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => {
+            drop(status_rx);
+            let code = panic::catch_unwind(AssertUnwindSafe(|| {
+                run_worker(request, socket, files, status_tx, spawner, filter)
+            }))
+            .unwrap_or(libc::EXIT_FAILURE);
+            // SAFETY: Exits the worker without running the destructors of the spawner state.
+            unsafe { libc::_exit(code) }
+        }
+        pid => pid,
+    };
+    drop(status_tx);
+    // The worker isn't reaped before the next request, so its PID can't be reused yet.
+    // SAFETY: pidfd_open is called with valid arguments and we check the return value.
+    let pidfd = match unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) } {
+        -1 => return Err(io::Error::last_os_error()),
+        // SAFETY: The pidfd was just opened, so it is owned by nothing else.
+        fd => unsafe { File::from_raw_fd(RawFd::try_from(fd).unwrap()) },
+    };
+
+    // The worker reports whether it could create its device and enter its sandbox. If it died
+    // before that, the pipe is closed without a status.
+    let mut status = [0u8; 4];
+    status_rx
+        .read_exact(&mut status)
+        .map_err(|_| io::Error::from_raw_os_error(libc::ECHILD))?;
+    match i32::from_ne_bytes(status) {
+        0 => Ok((pid, pidfd)),
+        errno => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+fn pipe() -> io::Result<(File, File)> {
+    let mut fds = [-1; 2];
+    // SAFETY: `fds` has room for the two file descriptors, and we check the return value.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: The file descriptors were just created, so they are owned by nothing else.
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+// Runs in the worker process. Returns the exit code of the worker.
+fn run_worker(
+    request: WorkerRequest,
+    socket: UnixStream,
+    files: Vec<File>,
+    status: File,
+    spawner: libc::pid_t,
+    filter: BpfProgramRef,
+) -> i32 {
+    match request {
+        WorkerRequest::Block(config) => start_worker(
+            block::WORKER_NAME,
+            block::worker_device(&config, files),
+            socket,
+            status,
+            spawner,
+            filter,
+        ),
+        WorkerRequest::Net => start_worker(
+            net::WORKER_NAME,
+            net::worker_device(files),
+            socket,
+            status,
+            spawner,
+            filter,
+        ),
+    }
+}
+
+// Sandboxes the worker running `device`, reports the outcome to the spawner, and serves the
+// vhost-user protocol. Returns the exit code of the worker.
+fn start_worker<D: WorkerDevice>(
+    name: &str,
+    device: io::Result<D>,
+    socket: UnixStream,
+    mut status: File,
+    spawner: libc::pid_t,
+    filter: BpfProgramRef,
+) -> i32 {
+    let device = device.and_then(|device| {
+        let mut keep_fds = device.host_fds();
+        keep_fds.extend([socket.as_raw_fd(), status.as_raw_fd()]);
+        enter_sandbox(spawner, &keep_fds, filter, name)?;
+        Ok(device)
+    });
+    let errno = match &device {
+        Ok(_) => 0,
+        Err(err) => err.raw_os_error().unwrap_or(libc::EIO),
+    };
+    if status.write_all(&errno.to_ne_bytes()).is_err() {
+        return libc::EXIT_FAILURE;
+    }
+    drop(status);
+
+    match device.map(|device| serve(device, socket)) {
+        Ok(Ok(())) => libc::EXIT_SUCCESS,
+        _ => libc::EXIT_FAILURE,
+    }
+}
+
+// Drops the privileges of the worker, makes it die with the spawner, drops all the file
+// descriptors it doesn't need and restricts the system calls it can make.
+fn enter_sandbox(
+    spawner: libc::pid_t,
+    keep_fds: &[RawFd],
+    filter: BpfProgramRef,
+    name: &str,
+) -> io::Result<()> {
+    // Changing the credentials of the process clears its death signal, so this goes first.
+    drop_privileges()?;
+    die_with_parent(spawner)?;
+    set_name(name);
+
+    close_fds_except(keep_fds)?;
+    seccompiler::apply_filter(filter).map_err(|_| io::Error::from_raw_os_error(libc::EPERM))
+}
+
+// Leaves the worker without privileges on the host: it runs as an unprivileged user, in user,
+// mount and network namespaces of its own. It keeps access to the host resources of its device
+// through the file descriptors it was given.
+fn drop_privileges() -> io::Result<()> {
+    // SAFETY: geteuid cannot fail.
+    if unsafe { libc::geteuid() } == 0 {
+        // SAFETY: The calls are made with valid arguments and we check the return values.
+        let dropped = unsafe {
+            libc::setgroups(0, std::ptr::null()) == 0
+                && libc::setresgid(WORKER_ID, WORKER_ID, WORKER_ID) == 0
+                && libc::setresuid(WORKER_ID, WORKER_ID, WORKER_ID) == 0
+        };
+        if !dropped {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    // SAFETY: unshare is called with valid flags and we check the return value.
+    if unsafe { libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWNET) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Makes the process die with `parent`.
+fn die_with_parent(parent: libc::pid_t) -> io::Result<()> {
+    // SAFETY: prctl is called with valid arguments and we check the return value.
+    if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // The parent may have exited before the death signal was armed.
+    // SAFETY: getppid cannot fail.
+    if unsafe { libc::getppid() } != parent {
+        return Err(io::Error::from_raw_os_error(libc::ESRCH));
+    }
+    Ok(())
+}
+
+// Names the process. Names longer than 15 bytes are truncated by the kernel.
+fn set_name(name: &str) {
+    let name = CString::new(name).unwrap_or_default();
+    // SAFETY: `name` is a valid NUL terminated string.
+    unsafe { libc::prctl(libc::PR_SET_NAME, name.as_ptr()) };
+}
+
+// Closes all the file descriptors but the standard streams and `keep_fds`.
+fn close_fds_except(keep_fds: &[RawFd]) -> io::Result<()> {
+    let mut keep = keep_fds
+        .iter()
+        .map(|&fd| libc::c_uint::try_from(fd))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| io::Error::from_raw_os_error(libc::EBADF))?;
+    keep.extend([0, 1, 2]);
+    keep.sort_unstable();
+    keep.dedup();
+
+    let mut first = 0;
+    for fd in keep {
+        if fd > first {
+            close_range(first, fd - 1)?;
+        }
+        first = fd + 1;
+    }
+    close_range(first, libc::c_uint::MAX)
+}
+
+fn close_range(first: libc::c_uint, last: libc::c_uint) -> io::Result<()> {
+    // SAFETY: The worker doesn't use the file descriptors in the range, and we check the return
+    // value.
+    if unsafe { libc::syscall(libc::SYS_close_range, first, last, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Builds the seccomp filter of the worker processes. The system calls missing from the filter
+/// kill the worker.
+pub fn seccomp_filter() -> std::result::Result<BpfProgram, FilterError> {
+    let allow = || vec![SeccompRule::new(vec![], SeccompAction::Allow)];
+    let mut rules: SeccompRuleMap = WORKER_SYSCALLS.iter().map(|&nr| (nr, allow())).collect();
+    #[cfg(target_arch = "x86_64")]
+    rules.insert(libc::SYS_epoll_wait, allow());
+
+    // Neither the guest memory nor the heap may be executable.
+    let prot_exec = u64::try_from(libc::PROT_EXEC).unwrap();
+    let not_exec = SeccompCondition::new(
+        2,
+        SeccompCmpArgLen::Dword,
+        SeccompCmpOp::MaskedEq(prot_exec),
+        0,
+    )?;
+    let allow_not_exec = vec![SeccompRule::new(vec![not_exec], SeccompAction::Allow)];
+    rules.insert(libc::SYS_mmap, allow_not_exec.clone());
+    rules.insert(libc::SYS_mprotect, allow_not_exec);
+
+    SeccompFilter::new(rules, SeccompAction::KillProcess, std::env::consts::ARCH)?.try_into()
+}
+
+// Serves the vhost-user protocol and processes the queues until the frontend goes away.
+fn serve<D: WorkerDevice>(device: D, socket: UnixStream) -> Result<()> {
+    let epoll = Arc::new(Epoll::new().map_err(Error::ReqHandlerError)?);
+    let host_event = device.host_event();
+    let num_events = device.num_queues() + 2;
+    let backend = Arc::new(Mutex::new(WorkerBackend::new(device, epoll.clone())));
+    let mut handler = BackendReqHandler::from_stream(socket, backend.clone());
+
+    epoll
+        .ctl(
+            ControlOperation::Add,
+            handler.as_raw_fd(),
+            EpollEvent::new(EventSet::IN, SOCKET_EVENT),
+        )
+        .map_err(Error::ReqHandlerError)?;
+    if let Some((fd, _)) = host_event {
+        epoll
+            .ctl(
+                ControlOperation::Add,
+                fd,
+                EpollEvent::new(EventSet::IN | EventSet::EDGE_TRIGGERED, HOST_EVENT),
+            )
+            .map_err(Error::ReqHandlerError)?;
+    }
+
+    let mut events = vec![EpollEvent::default(); num_events];
+    loop {
+        let num_events = match epoll.wait(-1, &mut events) {
+            Ok(num_events) => num_events,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(Error::ReqHandlerError(err)),
+        };
+
+        for event in &events[..num_events] {
+            match (event.data(), host_event) {
+                (SOCKET_EVENT, _) => match handler.handle_request() {
+                    Ok(()) => (),
+                    // The device was dropped by the VMM, or the VMM exited.
+                    Err(Error::Disconnected | Error::PartialMessage) => return Ok(()),
+                    Err(err) => return Err(err),
+                },
+                (HOST_EVENT, Some((_, index))) => {
+                    backend.lock().expect("Poisoned lock").process_queue(index)
+                }
+                (index, _) => backend
+                    .lock()
+                    .expect("Poisoned lock")
+                    .process_kick(u64_to_usize(index)),
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Vring {
+    queue: Queue,
+    kick: Option<File>,
+    call: Option<File>,
+    enabled: bool,
+}
+
+/// Memory region of the frontend, used to translate the queue addresses.
+#[derive(Debug)]
+struct MemoryRegion {
+    guest_phys_addr: u64,
+    memory_size: u64,
+    user_addr: u64,
+}
+
+// vhost-user backend handing the queues of the frontend to the device emulation.
+#[derive(Debug)]
+struct WorkerBackend<D> {
+    device: D,
+    epoll: Arc<Epoll>,
+    acked_features: u64,
+    regions: Vec<MemoryRegion>,
+    mem: Option<GuestMemoryMmap>,
+    vrings: Vec<Vring>,
+}
+
+impl<D: WorkerDevice> WorkerBackend<D> {
+    fn new(device: D, epoll: Arc<Epoll>) -> Self {
+        let vrings = (0..device.num_queues())
+            .map(|_| Vring {
+                queue: Queue::new(FIRECRACKER_MAX_QUEUE_SIZE),
+                kick: None,
+                call: None,
+                enabled: false,
+            })
+            .collect();
+        Self {
+            device,
+            epoll,
+            acked_features: 0,
+            regions: vec![],
+            mem: None,
+            vrings,
+        }
+    }
+
+    fn vring(&mut self, index: u32) -> Result<&mut Vring> {
+        self.vrings
+            .get_mut(u64_to_usize(u64::from(index)))
+            .ok_or(Error::InvalidParam)
+    }
+
+    /// Translates an address in the frontend address space to a guest physical address.
+    fn translate(&self, addr: u64) -> Result<GuestAddress> {
+        self.regions
+            .iter()
+            .find(|region| region.user_addr <= addr && addr - region.user_addr < region.memory_size)
+            .map(|region| GuestAddress(region.guest_phys_addr + (addr - region.user_addr)))
+            .ok_or(Error::InvalidParam)
+    }
+
+    fn process_kick(&mut self, index: usize) {
+        let Some(kick) = self
+            .vrings
+            .get_mut(index)
+            .and_then(|vring| vring.kick.as_mut())
+        else {
+            return;
+        };
+        let mut buf = [0u8; 8];
+        // The kick is an eventfd, so it can only fail to be read if it was already consumed.
+        let _ = kick.read_exact(&mut buf);
+        self.process_queue(index);
+    }
+
+    fn process_queue(&mut self, index: usize) {
+        let Self {
+            device,
+            mem,
+            vrings,
+            ..
+        } = self;
+        let (Some(mem), Some(vring)) = (mem.as_ref(), vrings.get_mut(index)) else {
+            return;
+        };
+        if !vring.enabled {
+            return;
+        }
+        if device.process_queue(index, &mut vring.queue, mem) && vring.queue.prepare_kick(mem) {
+            if let Some(call) = vring.call.as_mut() {
+                // The frontend going away is handled on the vhost-user socket.
+                let _ = call.write_all(&1u64.to_ne_bytes());
+            }
+        }
+    }
+}
+
+impl<D: WorkerDevice> VhostUserBackendReqHandlerMut for WorkerBackend<D> {
+    fn set_owner(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn reset_owner(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_features(&mut self) -> Result<u64> {
+        Ok(self.device.features())
+    }
+
+    fn set_features(&mut self, features: u64) -> Result<()> {
+        self.acked_features = features;
+        Ok(())
+    }
+
+    fn set_mem_table(&mut self, ctx: &[VhostUserMemoryRegion], files: Vec<File>) -> Result<()> {
+        let regions = ctx
+            .iter()
+            .zip(files)
+            .map(|(region, file)| {
+                (
+                    FileOffset::new(file, region.mmap_offset),
+                    GuestAddress(region.guest_phys_addr),
+                    u64_to_usize(region.memory_size),
+                )
+            })
+            .collect();
+        let mem = GuestMemoryMmap::from_raw_regions_file(regions, false, true)
+            .map_err(|_| Error::InvalidParam)?;
+        self.regions = ctx
+            .iter()
+            .map(|region| MemoryRegion {
+                guest_phys_addr: region.guest_phys_addr,
+                memory_size: region.memory_size,
+                user_addr: region.user_addr,
+            })
+            .collect();
+        self.mem = Some(mem);
+        Ok(())
+    }
+
+    fn set_vring_num(&mut self, index: u32, num: u32) -> Result<()> {
+        let size = u16::try_from(num).map_err(|_| Error::InvalidParam)?;
+        let queue = &mut self.vring(index)?.queue;
+        if size > queue.get_max_size() {
+            return Err(Error::InvalidParam);
+        }
+        queue.size = size;
+        Ok(())
+    }
+
+    fn set_vring_addr(
+        &mut self,
+        index: u32,
+        _flags: VhostUserVringAddrFlags,
+        descriptor: u64,
+        used: u64,
+        available: u64,
+        _log: u64,
+    ) -> Result<()> {
+        let desc_table = self.translate(descriptor)?;
+        let used_ring = self.translate(used)?;
+        let avail_ring = self.translate(available)?;
+        let queue = &mut self.vring(index)?.queue;
+        queue.desc_table = desc_table;
+        queue.used_ring = used_ring;
+        queue.avail_ring = avail_ring;
+        Ok(())
+    }
+
+    fn set_vring_base(&mut self, index: u32, base: u32) -> Result<()> {
+        let base = u16::try_from(base).map_err(|_| Error::InvalidParam)?;
+        let queue = &mut self.vring(index)?.queue;
+        queue.next_avail = Wrapping(base);
+        queue.next_used = Wrapping(base);
+        Ok(())
+    }
+
+    fn get_vring_base(&mut self, index: u32) -> Result<VhostUserVringState> {
+        let vring = self.vring(index)?;
+        vring.enabled = false;
+        Ok(VhostUserVringState::new(
+            index,
+            u32::from(vring.queue.next_avail.0),
+        ))
+    }
+
+    fn set_vring_kick(&mut self, index: u8, fd: Option<File>) -> Result<()> {
+        // Polling the queues is not supported.
+        let fd = fd.ok_or(Error::InvalidParam)?;
+        self.epoll
+            .ctl(
+                ControlOperation::Add,
+                fd.as_raw_fd(),
+                EpollEvent::new(EventSet::IN, u64::from(index)),
+            )
+            .map_err(Error::ReqHandlerError)?;
+        self.vring(u32::from(index))?.kick = Some(fd);
+        Ok(())
+    }
+
+    fn set_vring_call(&mut self, index: u8, fd: Option<File>) -> Result<()> {
+        self.vring(u32::from(index))?.call = fd;
+        Ok(())
+    }
+
+    fn set_vring_err(&mut self, _index: u8, _fd: Option<File>) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_protocol_features(&mut self) -> Result<VhostUserProtocolFeatures> {
+        Ok(VhostUserProtocolFeatures::CONFIG)
+    }
+
+    fn set_protocol_features(&mut self, _features: u64) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_queue_num(&mut self) -> Result<u64> {
+        Ok(self.vrings.len() as u64)
+    }
+
+    fn set_vring_enable(&mut self, index: u32, enable: bool) -> Result<()> {
+        let event_idx = self.acked_features & (1 << VIRTIO_RING_F_EVENT_IDX) != 0;
+        let vring = self.vring(index)?;
+        vring.enabled = enable;
+        vring.queue.ready = enable;
+        if event_idx {
+            vring.queue.enable_notif_suppression();
+        }
+        // The guest may have made descriptor chains available before the queue was enabled.
+        self.process_queue(u64_to_usize(u64::from(index)));
+        Ok(())
+    }
+
+    fn get_config(
+        &mut self,
+        offset: u32,
+        size: u32,
+        _flags: VhostUserConfigFlags,
+    ) -> Result<Vec<u8>> {
+        let start = u64_to_usize(u64::from(offset));
+        let end = start + u64_to_usize(u64::from(size));
+        if end > MAX_CONFIG_SIZE {
+            return Err(Error::InvalidParam);
+        }
+        // The bytes past the config space of the device read as zero.
+        let mut config = self.device.config_space().to_vec();
+        config.resize(config.len().max(end), 0);
+        Ok(config[start..end].to_vec())
+    }
+
+    fn set_config(
+        &mut self,
+        _offset: u32,
+        _buf: &[u8],
+        _flags: VhostUserConfigFlags,
+    ) -> Result<()> {
+        // The config space of the worker devices is read-only.
+        Ok(())
+    }
+
+    fn get_inflight_fd(
+        &mut self,
+        _inflight: &VhostUserInflight,
+    ) -> Result<(VhostUserInflight, File)> {
+        Err(Error::InvalidOperation("get_inflight_fd"))
+    }
+
+    fn set_inflight_fd(&mut self, _inflight: &VhostUserInflight, _file: File) -> Result<()> {
+        Err(Error::InvalidOperation("set_inflight_fd"))
+    }
+
+    fn get_max_mem_slots(&mut self) -> Result<u64> {
+        Err(Error::InvalidOperation("get_max_mem_slots"))
+    }
+
+    fn add_mem_region(&mut self, _region: &VhostUserSingleMemoryRegion, _fd: File) -> Result<()> {
+        Err(Error::InvalidOperation("add_mem_region"))
+    }
+
+    fn remove_mem_region(&mut self, _region: &VhostUserSingleMemoryRegion) -> Result<()> {
+        Err(Error::InvalidOperation("remove_mem_region"))
+    }
+
+    fn set_device_state_fd(
+        &mut self,
+        _direction: VhostTransferStateDirection,
+        _phase: VhostTransferStatePhase,
+        _fd: File,
+    ) -> Result<Option<File>> {
+        Err(Error::InvalidOperation("set_device_state_fd"))
+    }
+
+    fn check_device_state(&mut self) -> Result<()> {
+        Err(Error::InvalidOperation("check_device_state"))
+    }
+
+    fn set_log_base(&mut self, _log: &VhostUserLog, _file: File) -> Result<()> {
+        Err(Error::InvalidOperation("set_log_base"))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // The workers are forked from the spawner, which has to be forked before the test harness
+    // starts its threads.
+    #[used]
+    #[link_section = ".init_array"]
+    static START_SPAWNER: extern "C" fn() = {
+        extern "C" fn start() {
+            // SAFETY: The constructors run before main, while the process is single-threaded.
+            unsafe { start_spawner() }.unwrap();
+        }
+        start
+    };
+
+    /// Waits up to `timeout_ms` milliseconds for the process `pid` to exit. Returns whether it
+    /// did.
+    pub(crate) fn wait_exit(pid: libc::pid_t, timeout_ms: i32) -> bool {
+        // SAFETY: pidfd_open is called with valid arguments and we check the return value.
+        let pidfd = match unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) } {
+            -1 => return true,
+            // SAFETY: The pidfd was just opened, so it is owned by nothing else.
+            fd => unsafe { File::from_raw_fd(RawFd::try_from(fd).unwrap()) },
+        };
+        let mut pollfd = libc::pollfd {
+            fd: pidfd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `pollfd` is a valid pollfd structure.
+        unsafe { libc::poll(&mut pollfd, 1, timeout_ms) == 1 }
+    }
+
+    #[test]
+    fn test_seccomp_filter() {
+        assert!(!seccomp_filter().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_translate() {
+        struct NoDevice;
+        impl WorkerDevice for NoDevice {
+            fn features(&self) -> u64 {
+                0
+            }
+            fn config_space(&self) -> &[u8] {
+                &[]
+            }
+            fn num_queues(&self) -> usize {
+                1
+            }
+            fn host_fds(&self) -> Vec<RawFd> {
+                vec![]
+            }
+            fn process_queue(&mut self, _: usize, _: &mut Queue, _: &GuestMemoryMmap) -> bool {
+                false
+            }
+        }
+
+        let mut backend = WorkerBackend::new(NoDevice, Arc::new(Epoll::new().unwrap()));
+        backend.regions = vec![MemoryRegion {
+            guest_phys_addr: 0x1000,
+            memory_size: 0x1000,
+            user_addr: 0x7000_0000,
+        }];
+        assert_eq!(
+            backend.translate(0x7000_0010).unwrap(),
+            GuestAddress(0x1010)
+        );
+        backend.translate(0x7000_1000).unwrap_err();
+        backend.translate(0x6fff_ffff).unwrap_err();
+
+        // The config space reads as zero past the one of the device.
+        assert_eq!(
+            backend
+                .get_config(0, 4, VhostUserConfigFlags::empty())
+                .unwrap(),
+            vec![0; 4]
+        );
+        backend
+            .get_config(0, 512, VhostUserConfigFlags::empty())
+            .unwrap_err();
+    }
+}
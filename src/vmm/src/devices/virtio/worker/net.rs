@@ -0,0 +1,186 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Network devices emulated by a worker process.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, RawFd};
+
+use vhost::vhost_user::message::VhostUserProtocolFeatures;
+use vhost::vhost_user::VhostUserVirtioFeatures;
+
+use super::{spawn_worker, WorkerDevice, WorkerError, WorkerProcess, WorkerRequest};
+use crate::devices::virtio::gen::virtio_blk::VIRTIO_F_VERSION_1;
+use crate::devices::virtio::gen::virtio_net::{
+    VIRTIO_NET_F_CSUM, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO,
+    VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC, VIRTIO_NET_F_STATUS,
+};
+use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
+use crate::devices::virtio::iovec::{IoVecBuffer, IoVecBufferMut};
+use crate::devices::virtio::net::{Tap, MAX_BUFFER_SIZE, RX_INDEX, TX_INDEX};
+use crate::devices::virtio::queue::{Queue, UsedBatch};
+use crate::devices::virtio::vhost_user::{VhostUserError, VhostUserHandle};
+use crate::vstate::memory::GuestMemoryMmap;
+
+/// Name of the network device worker processes.
+pub(super) const WORKER_NAME: &str = "fc_net_worker";
+
+/// Number of queues processed by the worker: the RX and TX queues. The control queue is not
+/// offered to the guest.
+pub const NUM_QUEUES: usize = 2;
+
+/// Virtio features the worker supports.
+pub const WORKER_FEATURES: u64 = (1 << VIRTIO_NET_F_GUEST_CSUM)
+    | (1 << VIRTIO_NET_F_CSUM)
+    | (1 << VIRTIO_NET_F_GUEST_TSO4)
+    | (1 << VIRTIO_NET_F_GUEST_UFO)
+    | (1 << VIRTIO_NET_F_HOST_TSO4)
+    | (1 << VIRTIO_NET_F_HOST_UFO)
+    | (1 << VIRTIO_NET_F_MAC)
+    | (1 << VIRTIO_NET_F_STATUS)
+    | (1 << VIRTIO_F_VERSION_1)
+    | (1 << VIRTIO_RING_F_EVENT_IDX);
+
+/// Network device worker error.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum NetWorkerError {
+    /// Cannot spawn the worker process: {0}
+    Spawn(WorkerError),
+    /// Cannot connect to the worker process: {0}
+    VhostUser(VhostUserError),
+}
+
+/// Worker process emulating a network device, as seen by the VMM.
+#[derive(Debug)]
+pub struct NetWorker {
+    /// Handle of the worker process.
+    pub process: WorkerProcess,
+    /// Session with the worker, which processes the RX and TX queues.
+    pub vu_handle: VhostUserHandle,
+    /// Virtio features supported by both the device and the worker.
+    pub features: u64,
+}
+
+impl NetWorker {
+    /// Spawns a worker moving frames between the guest and `tap`, and negotiates the virtio
+    /// features in `avail_features` with it.
+    pub fn new(tap: &Tap, avail_features: u64) -> Result<Self, NetWorkerError> {
+        let (process, stream) =
+            spawn_worker(&WorkerRequest::Net, &[tap.as_raw_fd()]).map_err(NetWorkerError::Spawn)?;
+
+        let mut vu_handle = VhostUserHandle::from_stream(stream, "", NUM_QUEUES as u64)
+            .map_err(NetWorkerError::VhostUser)?;
+        let (features, _) = vu_handle
+            .negotiate_features(
+                avail_features | VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits(),
+                VhostUserProtocolFeatures::empty(),
+            )
+            .map_err(NetWorkerError::VhostUser)?;
+
+        Ok(Self {
+            process,
+            vu_handle,
+            features: features & !VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits(),
+        })
+    }
+}
+
+/// Creates the device emulation of a worker process from the tap device in `files`.
+pub(super) fn worker_device(files: Vec<File>) -> io::Result<impl WorkerDevice> {
+    let tap_file = files
+        .into_iter()
+        .next()
+        .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+    Ok(NetWorkerDevice {
+        tap: Tap::from_file(tap_file),
+        frame_buf: vec![0u8; MAX_BUFFER_SIZE],
+        frame_len: 0,
+        rx_used: UsedBatch::default(),
+    })
+}
+
+// Network device emulation running in the worker process.
+#[derive(Debug)]
+struct NetWorkerDevice {
+    tap: Tap,
+    // Frame read from the tap, including the vnet header, waiting for an RX descriptor chain.
+    frame_buf: Vec<u8>,
+    frame_len: usize,
+    // The RX descriptor chains used while processing the queue, published all at once.
+    rx_used: UsedBatch,
+}
+
+impl NetWorkerDevice {
+    fn process_rx(&mut self, queue: &mut Queue, mem: &GuestMemoryMmap) -> bool {
+        loop {
+            if self.frame_len == 0 {
+                // The tap is non-blocking: stop once there are no more frames to read.
+                match self.tap.read(&mut self.frame_buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(len) => self.frame_len = len,
+                }
+            }
+            // The frame is kept until the guest makes a descriptor chain available.
+            let Some(head) = queue.pop_or_enable_notification(mem) else {
+                break;
+            };
+            let index = head.index;
+            let frame = &self.frame_buf[..self.frame_len];
+            // Frames which don't fit in the descriptor chain are dropped.
+            let len = IoVecBufferMut::from_descriptor_chain(head)
+                .ok()
+                .and_then(|mut buf| buf.write_all_volatile_at(frame, 0).ok())
+                .map_or(0, |()| u32::try_from(frame.len()).unwrap());
+            self.frame_len = 0;
+            self.rx_used.push(index, len);
+        }
+        // The descriptor chains were just popped from the queue, so their indices are valid.
+        self.rx_used.flush(queue, mem).unwrap_or(true)
+    }
+
+    fn process_tx(&mut self, queue: &mut Queue, mem: &GuestMemoryMmap) -> bool {
+        let mut used_any = false;
+        while let Some(head) = queue.pop_or_enable_notification(mem) {
+            let index = head.index;
+            // Frames the tap rejects are dropped, as the guest is not told about them.
+            if let Ok(buf) = IoVecBuffer::from_descriptor_chain(head) {
+                let _ = self.tap.write_iovec(&buf);
+            }
+            let _ = queue.add_used(mem, index, 0);
+            used_any = true;
+        }
+        used_any
+    }
+}
+
+impl WorkerDevice for NetWorkerDevice {
+    fn features(&self) -> u64 {
+        WORKER_FEATURES | VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits()
+    }
+
+    fn config_space(&self) -> &[u8] {
+        // The config space is emulated by the VMM.
+        &[]
+    }
+
+    fn num_queues(&self) -> usize {
+        NUM_QUEUES
+    }
+
+    fn host_fds(&self) -> Vec<RawFd> {
+        vec![self.tap.as_raw_fd()]
+    }
+
+    fn host_event(&self) -> Option<(RawFd, usize)> {
+        Some((self.tap.as_raw_fd(), RX_INDEX))
+    }
+
+    fn process_queue(&mut self, index: usize, queue: &mut Queue, mem: &GuestMemoryMmap) -> bool {
+        match index {
+            RX_INDEX => self.process_rx(queue, mem),
+            TX_INDEX => self.process_tx(queue, mem),
+            _ => false,
+        }
+    }
+}
@@ -91,6 +91,17 @@ impl<'a, T: NetworkBytes + Debug> EthIPv4ArpFrame<'a, T> {
     /// If no error occurs, it guarantees accessor methods (which make use of various `_unchecked`
     /// functions) are safe to call on the result, because all predefined offsets will be valid.
     pub fn request_from_bytes(bytes: T) -> Result<Self, ArpError> {
+        Self::from_bytes_with_operation(bytes, OPER_REQUEST)
+    }
+
+    /// Tries to interpret a byte slice as a valid IPv4 over Ethernet ARP reply.
+    ///
+    /// Offers the same guarantees as `request_from_bytes`.
+    pub fn reply_from_bytes(bytes: T) -> Result<Self, ArpError> {
+        Self::from_bytes_with_operation(bytes, OPER_REPLY)
+    }
+
+    fn from_bytes_with_operation(bytes: T, operation: u16) -> Result<Self, ArpError> {
         // This kind of frame has a fixed length, so we know what to expect.
         if bytes.len() != ETH_IPV4_FRAME_LEN {
             return Err(ArpError::SliceExactLen);
@@ -115,7 +126,7 @@ impl<'a, T: NetworkBytes + Debug> EthIPv4ArpFrame<'a, T> {
             return Err(ArpError::PLen);
         }
 
-        if maybe.operation() != OPER_REQUEST {
+        if maybe.operation() != operation {
             return Err(ArpError::Operation);
         }
 
@@ -402,6 +413,9 @@ mod tests {
             EthIPv4ArpFrame::request_from_bytes(&a[..ETH_IPV4_FRAME_LEN]).unwrap_err(),
             ArpError::Operation
         );
+        let f = EthIPv4ArpFrame::reply_from_bytes(&a[..ETH_IPV4_FRAME_LEN]).unwrap();
+        assert_eq!(f.sha(), sha);
+        assert_eq!(f.tpa(), tpa);
 
         // Various requests
         let requests = [
@@ -13,7 +13,7 @@ use std::net::Ipv4Addr;
 use std::result::Result;
 
 use crate::dumbo::pdu::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
-use crate::dumbo::pdu::{ethernet, Incomplete};
+use crate::dumbo::pdu::{ethernet, ones_complement_sum, Incomplete};
 
 const VERSION_AND_IHL_OFFSET: usize = 0;
 const DSCP_AND_ECN_OFFSET: usize = 1;
@@ -230,17 +230,7 @@ impl<'a, T: NetworkBytes + Debug> IPv4Packet<'a, T> {
     ///
     /// [here]: https://en.wikipedia.org/wiki/IPv4_header_checksum
     pub fn compute_checksum_unchecked(&self, header_len: usize) -> u16 {
-        let mut sum = 0u32;
-        for i in 0..header_len / 2 {
-            sum += u32::from(self.bytes.ntohs_unchecked(i * 2));
-        }
-
-        while sum >> 16 != 0 {
-            sum = (sum & 0xffff) + (sum >> 16);
-        }
-
-        // Safe to unwrap due to the while loop.
-        !u16::try_from(sum).unwrap()
+        !ones_complement_sum(&self.bytes[..header_len])
     }
 
     /// Computes and returns the packet header checksum.
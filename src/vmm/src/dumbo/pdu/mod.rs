@@ -59,6 +59,39 @@ enum ChecksumProto {
     Udp = PROTOCOL_UDP,
 }
 
+/// Computes the 16-bit one's complement sum of `bytes`, read as a sequence of big-endian words.
+///
+/// The bytes are summed as 32-bit words into a 64-bit accumulator, which the compiler can
+/// vectorize, and which cannot overflow for slices shorter than 16 GiB. Since `2^16` is congruent
+/// to 1 modulo `0xffff`, the accumulator folds back into the same 16-bit sum as the one computed
+/// word by word. A trailing odd byte is padded with a zero byte, as described in [RFC 1071].
+///
+/// [RFC 1071]: https://www.rfc-editor.org/rfc/rfc1071
+#[inline]
+pub fn ones_complement_sum(bytes: &[u8]) -> u16 {
+    let mut chunks = bytes.chunks_exact(4);
+    let mut sum: u64 = chunks
+        .by_ref()
+        .map(|chunk| u64::from(u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])))
+        .sum();
+
+    let mut remainder = chunks.remainder();
+    if remainder.len() >= 2 {
+        sum += u64::from(u16::from_be_bytes([remainder[0], remainder[1]]));
+        remainder = &remainder[2..];
+    }
+    if let Some(byte) = remainder.first() {
+        sum += u64::from(*byte) << 8;
+    }
+
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    // Safe to unwrap due to the while loop above.
+    u16::try_from(sum).unwrap()
+}
+
 /// Computes the checksum of a TCP/UDP packet. Since both protocols use
 /// the same algorithm to compute the checksum.
 ///
@@ -89,17 +122,9 @@ fn compute_checksum<T: NetworkBytes + Debug>(
     sum += b & 0xffff;
     sum += b >> 16;
 
-    let len = bytes.len();
     sum += protocol as usize;
-    sum += len;
-
-    for i in 0..len / 2 {
-        sum += usize::from(bytes.ntohs_unchecked(i * 2));
-    }
-
-    if len % 2 != 0 {
-        sum += usize::from(bytes[len - 1]) << 8;
-    }
+    sum += bytes.len();
+    sum += usize::from(ones_complement_sum(bytes));
 
     while sum >> 16 != 0 {
         sum = (sum & 0xffff) + (sum >> 16);
@@ -114,3 +139,58 @@ fn compute_checksum<T: NetworkBytes + Debug>(
 
     csum
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    // Reference implementation, summing the bytes word by word.
+    fn ones_complement_sum_bytewise(bytes: &[u8]) -> u16 {
+        let mut sum = 0u64;
+        for word in bytes.chunks(2) {
+            sum += u64::from(word[0]) << 8;
+            if let Some(byte) = word.get(1) {
+                sum += u64::from(*byte);
+            }
+        }
+        while sum >> 16 != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        u16::try_from(sum).unwrap()
+    }
+
+    #[test]
+    fn test_ones_complement_sum() {
+        assert_eq!(ones_complement_sum(&[]), 0);
+        assert_eq!(ones_complement_sum(&[0x12]), 0x1200);
+        assert_eq!(ones_complement_sum(&[0x12, 0x34]), 0x1234);
+        assert_eq!(ones_complement_sum(&[0x12, 0x34, 0x56]), 0x6834);
+        // The carries are folded back into the sum.
+        assert_eq!(ones_complement_sum(&[0xff; 4]), 0xffff);
+        assert_eq!(ones_complement_sum(&[0xff, 0xff, 0x00, 0x01]), 0x0001);
+        // Example of RFC 1071, section 3.
+        assert_eq!(
+            ones_complement_sum(&[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7]),
+            0xddf2
+        );
+
+        // Every length of the remainder of the 32-bit words.
+        let bytes: Vec<u8> = (0..=255).collect();
+        for len in 0..bytes.len() {
+            assert_eq!(
+                ones_complement_sum(&bytes[..len]),
+                ones_complement_sum_bytewise(&bytes[..len])
+            );
+        }
+    }
+
+    #[test]
+    fn test_ones_complement_sum_random() {
+        let cfg = ProptestConfig::with_cases(1000);
+        proptest!(cfg, |(bytes in prop::collection::vec(any::<u8>(), 0..9000))| {
+            prop_assert_eq!(ones_complement_sum(&bytes), ones_complement_sum_bytewise(&bytes));
+        });
+    }
+}
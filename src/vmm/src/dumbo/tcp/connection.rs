@@ -1,13 +1,14 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-//! This module contains a minimalist TCP [`Connection`] implementation, which only supports
-//! passive open scenarios, and some auxiliary logic and data structures.
+//! This module contains a minimalist TCP [`Connection`] implementation, which supports passive
+//! and active open scenarios, and some auxiliary logic and data structures.
 //!
 //! [`Connection`]: struct.Connection.html
 
 use std::fmt::Debug;
 use std::num::{NonZeroU16, NonZeroU64, NonZeroUsize, Wrapping};
+use std::ops::Index;
 
 use bitflags::bitflags;
 use utils::rand::xor_pseudo_rng_u32;
@@ -36,6 +37,10 @@ bitflags! {
         const FIN_ACKED =           1 << 4;
         // The connection is reset, because we either sent, or received a RST segment.
         const RESET =               1 << 5;
+        // The connection was created via active open, and starts by sending a SYN.
+        const ACTIVE_OPEN =         1 << 6;
+        // At least one SYN segment has been sent for an active open.
+        const SYN_SENT =            1 << 7;
     }
 }
 
@@ -129,8 +134,8 @@ pub enum WriteNextError {
 /// improvements/changes may happen in the future (this also goes for other aspects of the
 /// current implementation).
 ///
-/// A `Connection` object can only be created via passive open, and will not recognize/use any TCP
-/// options except `MSS` during the handshake. The associated state machine is similar to how
+/// A `Connection` object can be created via passive or active open, and will not recognize/use any
+/// TCP options except `MSS` during the handshake. The associated state machine is similar to how
 /// TCP normally functions, but there are some differences:
 ///
 /// * Since only passive opens are supported, a `Connection` can only be instantiated in response to
@@ -142,6 +147,10 @@ pub enum WriteNextError {
 /// * In the `SYNACK_SENT` state, the connection awaits an `ACK` for the `SYNACK`. A retransmission
 ///   of the original `SYN` moves the state back to `SYN_RECEIVED`. A valid `ACK` advances the state
 ///   to `ESTABLISHED`. Any unexpected/invalid segment resets the connection.
+/// * A `Connection` created via active open starts by sending a `SYN`, which is retransmitted until
+///   a `SYNACK` acknowledging it arrives and moves the connection to `ESTABLISHED`. Simultaneous
+///   opens are not supported: segments which don't acknowledge the `SYN` are ignored, or reset the
+///   connection if they carry an invalid `ACK`.
 /// * While `ESTABLISHED`, the connection will only reset if it receives a `RST` or a `SYN`. Invalid
 ///   segments are simply ignored. `FIN` handling is simplifed: when [`close`] is invoked the
 ///   connection records the `FIN` sequence number, and starts setting the `FIN` flag (when
@@ -205,6 +214,32 @@ pub struct Connection {
     status_flags: ConnStatusFlags,
 }
 
+// Exposes the bytes of a payload source starting at `offset`, so that a segment can carry data
+// from the middle of the buffer (when retransmitting, for example).
+#[derive(Debug)]
+struct OffsetBuffer<'a, R: ?Sized> {
+    buf: &'a R,
+    offset: usize,
+}
+
+impl<R: ByteBuffer + ?Sized> Index<usize> for OffsetBuffer<'_, R> {
+    type Output = u8;
+
+    fn index(&self, index: usize) -> &u8 {
+        &self.buf[self.offset + index]
+    }
+}
+
+impl<R: ByteBuffer + ?Sized> ByteBuffer for OffsetBuffer<'_, R> {
+    fn len(&self) -> usize {
+        self.buf.len() - self.offset
+    }
+
+    fn read_to_slice(&self, offset: usize, buf: &mut [u8]) {
+        self.buf.read_to_slice(self.offset + offset, buf)
+    }
+}
+
 fn parse_mss_option<T: NetworkBytes + Debug>(
     segment: &TcpSegment<T>,
 ) -> Result<u16, PassiveOpenError> {
@@ -278,6 +313,50 @@ impl Connection {
         })
     }
 
+    /// Creates a new `Connection` which opens a connection to the other endpoint, by sending a
+    /// `SYN` segment at the first opportunity.
+    ///
+    /// # Arguments
+    ///
+    /// * `mss` - The MSS value advertised to the other endpoint.
+    /// * `local_rwnd_size` - Initial size of the local receive window.
+    /// * `rto_period` - How long the connection waits before a retransmission timeout fires for the
+    ///   first segment which has not been acknowledged yet. This uses an opaque time unit.
+    /// * `rto_count_max` - How many consecutive timeout-based retransmission may occur before the
+    ///   connection resets itself.
+    pub fn active_open(
+        mss: NonZeroU16,
+        local_rwnd_size: u32,
+        rto_period: NonZeroU64,
+        rto_count_max: NonZeroU16,
+    ) -> Self {
+        // Let's pick the initial sequence number, which is sent over the SYN.
+        let isn = Wrapping(xor_pseudo_rng_u32());
+        let first_not_sent = isn + Wrapping(1);
+
+        Connection {
+            // The initial sequence number of the other endpoint is only known after receiving the
+            // SYNACK, so the local receive window is relative to 0 until then.
+            ack_to_send: Wrapping(0),
+            highest_ack_received: isn,
+            first_not_sent,
+            local_rwnd_edge: Wrapping(local_rwnd_size),
+            // Nothing can be sent before the connection reaches the ESTABLISHED state.
+            remote_rwnd_edge: first_not_sent,
+            rto_start: 0,
+            rto_period: rto_period.get(),
+            rto_count: 0,
+            rto_count_max: rto_count_max.get(),
+            fin_received: None,
+            send_fin: None,
+            send_rst: None,
+            mss: mss.get(),
+            pending_ack: false,
+            dup_ack: false,
+            status_flags: ConnStatusFlags::ACTIVE_OPEN,
+        }
+    }
+
     fn flags_intersect(&self, flags: ConnStatusFlags) -> bool {
         self.status_flags.intersects(flags)
     }
@@ -302,6 +381,18 @@ impl Connection {
         self.flags_intersect(ConnStatusFlags::SYNACK_SENT)
     }
 
+    fn is_active_open(&self) -> bool {
+        self.flags_intersect(ConnStatusFlags::ACTIVE_OPEN)
+    }
+
+    fn syn_sent(&self) -> bool {
+        self.flags_intersect(ConnStatusFlags::SYN_SENT)
+    }
+
+    fn syn_pending(&self) -> bool {
+        self.is_active_open() && !self.syn_sent()
+    }
+
     fn is_reset(&self) -> bool {
         self.flags_intersect(ConnStatusFlags::RESET)
     }
@@ -385,7 +476,7 @@ impl Connection {
     /// endpoint to signal the connection should be reset.
     #[inline]
     pub fn make_rst_config(&self) -> RstConfig {
-        if self.is_established() {
+        if self.is_established() || self.is_active_open() {
             RstConfig::Seq(self.first_not_sent.0)
         } else {
             RstConfig::Ack(self.ack_to_send.0)
@@ -483,6 +574,7 @@ impl Connection {
     #[inline]
     pub fn control_segment_or_timeout_status(&self) -> NextSegmentStatus {
         if self.synack_pending()
+            || self.syn_pending()
             || self.rst_pending()
             || self.can_send_first_fin()
             || self.pending_ack
@@ -528,16 +620,16 @@ impl Connection {
             return Err(RecvError::ConnectionReset);
         }
 
-        // TODO: The following logic fully makes sense only for a passive open (which is what we
-        // currently support). Things must change a bit if/when we also implement active opens.
+        // Until it's ESTABLISHED, an actively opened connection only waits for a SYNACK.
+        if self.is_active_open() && !self.is_established() {
+            return self.receive_synack(s, now);
+        }
 
         let segment_flags = s.flags_after_ns();
 
         if segment_flags.intersects(TcpFlags::RST) {
             let seq = Wrapping(s.sequence_number());
             // We accept the RST only if it carries an in-window sequence number.
-            // TODO: If/when we support active opens, we'll also have to accept RST/SYN segments,
-            // which must acknowledge our SYN to be valid.
             if seq_at_or_after(seq, self.ack_to_send) && seq_after(self.local_rwnd_edge, seq) {
                 self.set_flags(ConnStatusFlags::RESET);
                 return Ok((None, RecvStatusFlags::RESET_RECEIVED));
@@ -549,7 +641,15 @@ impl Connection {
         let payload_len = s.len() - u16::from(s.header_len());
         let mut recv_status_flags = RecvStatusFlags::empty();
 
-        if !self.synack_sent() {
+        if self.is_established() {
+            // Reaching this branch means the connection is ESTABLISHED. The only thing we want to
+            // do right now is reset if we get segments which carry the SYN flag, because they are
+            // obviously invalid, and something must be really wrong.
+            // TODO: Is it an overreaction to reset here?
+            if s.flags_after_ns().intersects(TcpFlags::SYN) {
+                return self.reset_for_segment_helper(s, RecvStatusFlags::INVALID_SEGMENT);
+            }
+        } else if !self.synack_sent() {
             // We received another segment before getting the chance to send a SYNACK. It's either
             // a retransmitted SYN, or something that does not make sense.
             if self.is_same_syn(s) {
@@ -571,14 +671,6 @@ impl Connection {
                 // retransmission.
                 return self.reset_for_segment_helper(s, RecvStatusFlags::INVALID_SEGMENT);
             }
-        } else {
-            // Reaching this branch means the connection is ESTABLISHED. The only thing we want to
-            // do right now is reset if we get segments which carry the SYN flag, because they are
-            // obviously invalid, and something must be really wrong.
-            // TODO: Is it an overreaction to reset here?
-            if s.flags_after_ns().intersects(TcpFlags::SYN) {
-                return self.reset_for_segment_helper(s, RecvStatusFlags::INVALID_SEGMENT);
-            }
         }
 
         // The ACK number can only be valid when ACK flag is set. The following logic applies to
@@ -723,6 +815,61 @@ impl Connection {
         Ok((None, recv_status_flags))
     }
 
+    // Handles a segment received by an actively opened connection which is not ESTABLISHED yet.
+    // The only valid segments at this point are a SYNACK or a RST which acknowledge our SYN.
+    fn receive_synack<T: NetworkBytes + Debug>(
+        &mut self,
+        s: &TcpSegment<T>,
+        now: u64,
+    ) -> Result<(Option<NonZeroUsize>, RecvStatusFlags), RecvError> {
+        let segment_flags = s.flags_after_ns();
+        let ack = Wrapping(s.ack_number());
+        let acks_syn = self.syn_sent()
+            && segment_flags.intersects(TcpFlags::ACK)
+            && ack == self.first_not_sent;
+
+        if segment_flags.intersects(TcpFlags::RST) {
+            if acks_syn {
+                self.set_flags(ConnStatusFlags::RESET);
+                return Ok((None, RecvStatusFlags::RESET_RECEIVED));
+            } else {
+                return Ok((None, RecvStatusFlags::INVALID_RST));
+            }
+        }
+
+        if !acks_syn {
+            if segment_flags.intersects(TcpFlags::ACK) {
+                return self.reset_for_segment_helper(s, RecvStatusFlags::INVALID_ACK);
+            }
+            // This includes the SYN segments of simultaneous opens, which we don't support.
+            return Ok((None, RecvStatusFlags::INVALID_SEGMENT));
+        }
+
+        if segment_flags != TcpFlags::SYN | TcpFlags::ACK || s.payload_len() > 0 {
+            return self.reset_for_segment_helper(s, RecvStatusFlags::INVALID_SEGMENT);
+        }
+
+        let mss = match parse_mss_option(s) {
+            Ok(mss) => mss,
+            Err(_) => return self.reset_for_segment_helper(s, RecvStatusFlags::INVALID_SEGMENT),
+        };
+
+        // The local receive window was relative to 0 until now.
+        let local_rwnd_size = self.local_rwnd_edge - self.ack_to_send;
+        self.ack_to_send = Wrapping(s.sequence_number()) + Wrapping(1);
+        self.local_rwnd_edge = self.ack_to_send + local_rwnd_size;
+        self.highest_ack_received = ack;
+        self.remote_rwnd_edge = self.compute_remote_rwnd_edge(ack, s.window_size());
+        self.mss = self.mss.min(mss);
+        self.rto_count = 0;
+        self.rto_start = now;
+        self.set_flags(ConnStatusFlags::ESTABLISHED);
+        // The handshake completes when the other endpoint gets our ACK for the SYNACK.
+        self.enqueue_ack();
+
+        Ok((None, RecvStatusFlags::empty()))
+    }
+
     // The write helper functions return incomplete segments because &self does not have information
     // regarding the identity of the endpoints, such as source and destination ports, or source and
     // destination L3 addresses (which are required for checksum computation). We need this stupid
@@ -737,8 +884,8 @@ impl Connection {
         flags_after_ns: TcpFlags,
         payload: Option<(&R, usize)>,
     ) -> Result<Incomplete<TcpSegment<'a, &'a mut [u8]>>, WriteNextError> {
-        // Write the MSS option on SYNACK segments.
-        let mss_option = if flags_after_ns == TcpFlags::SYN | TcpFlags::ACK {
+        // Write the MSS option on SYN and SYNACK segments.
+        let mss_option = if flags_after_ns.intersects(TcpFlags::SYN) {
             Some(self.mss)
         } else {
             None
@@ -780,9 +927,13 @@ impl Connection {
             ack = Wrapping(t.1);
             flags_after_ns = t.2;
         } else if !self.is_established() {
-            // We can only send SYNACKs on this branch. The ISN should be right before
-            // self.first_not_sent.
-            flags_after_ns |= TcpFlags::SYN | TcpFlags::ACK;
+            // We can only send SYNs (for active opens) or SYNACKs on this branch. The ISN should
+            // be right before self.first_not_sent.
+            flags_after_ns |= if self.is_active_open() {
+                TcpFlags::SYN
+            } else {
+                TcpFlags::SYN | TcpFlags::ACK
+            };
             seq = self.first_not_sent - Wrapping(1);
         } else {
             // If we got to this point, the connection is ESTABLISHED, and we're not sending a RST.
@@ -826,10 +977,6 @@ impl Connection {
         payload_src: PayloadSource<R>,
         now: u64,
     ) -> Result<Option<Incomplete<TcpSegment<'a, &'a mut [u8]>>>, WriteNextError> {
-        // TODO: like receive_segment(), this function is specific in some ways to Connections
-        // created via passive open. When/if we also implement active opens, some things will
-        // have to change.
-
         if self.is_reset() {
             return Err(WriteNextError::ConnectionReset);
         }
@@ -850,8 +997,16 @@ impl Connection {
             return Ok(Some(segment));
         }
 
-        // Resend a SYNACK if the RTO expired. Otherwise, no reason to continue until the connection
-        // becomes ESTABLISHED.
+        // Likewise, an active open starts by sending a SYN.
+        if self.syn_pending() {
+            let segment = self.write_control_segment::<R>(buf, mss_reserved)?;
+            self.set_flags(ConnStatusFlags::SYN_SENT);
+            self.rto_start = now;
+            return Ok(Some(segment));
+        }
+
+        // Resend a SYN or SYNACK if the RTO expired. Otherwise, no reason to continue until the
+        // connection becomes ESTABLISHED.
         if !self.is_established() {
             if self.rto_expired(now) {
                 // If we exceeded the maximum retransmission count, reset the connection and call
@@ -943,6 +1098,12 @@ impl Connection {
                 // We always set the ACK flag for data segments.
                 let tcp_flags = TcpFlags::ACK;
 
+                // The payload starts with the byte that has the sequence number we send.
+                let payload_buf = OffsetBuffer {
+                    buf: read_buf,
+                    offset: (seq_to_send - payload_seq).0 as usize,
+                };
+
                 let ack_to_send = self.ack_to_send;
                 let mut segment = self.write_segment(
                     buf,
@@ -950,7 +1111,7 @@ impl Connection {
                     seq_to_send,
                     ack_to_send,
                     tcp_flags,
-                    Some((read_buf, max_payload_len)),
+                    Some((&payload_buf, max_payload_len)),
                 )?;
 
                 // If self.dup_ack was Some(_), we've just written the retransmission segment,
@@ -1785,4 +1946,112 @@ pub(crate) mod tests {
         // and we don't wait for our FIN to be ACKed.
         assert!(c.is_done());
     }
+
+    #[test]
+    fn test_active_open() {
+        let mut t = ConnectionTester::new();
+        let mut buf1 = [0u8; 100];
+        let mut buf2 = [0u8; 100];
+        let mut buf3 = [0u8; 100];
+        let remote_isn = t.remote_isn;
+        let mss = t.mss;
+
+        let mut c = Connection::active_open(
+            NonZeroU16::new(t.mss).unwrap(),
+            t.local_rwnd_size,
+            NonZeroU64::new(t.rto_period).unwrap(),
+            NonZeroU16::new(t.rto_count_max).unwrap(),
+        );
+        let conn_isn = c.first_not_sent.0.wrapping_sub(1);
+        assert_eq!(
+            c.control_segment_or_timeout_status(),
+            NextSegmentStatus::Available
+        );
+
+        // The first segment is a SYN, which carries the MSS option.
+        {
+            let s = t.write_next_segment(&mut c, None).unwrap().unwrap();
+            check_control_segment(&s, 4, TcpFlags::SYN);
+            assert_eq!(s.sequence_number(), conn_isn);
+            assert_eq!(parse_mss_option(&s).unwrap(), mss);
+        }
+        assert_eq!(
+            c.control_segment_or_timeout_status(),
+            NextSegmentStatus::Timeout(t.rto_period)
+        );
+        assert!(t.write_next_segment(&mut c, None).unwrap().is_none());
+
+        // The SYN is retransmitted when the RTO expires.
+        t.now += t.rto_period;
+        check_control_segment(
+            &t.write_next_segment(&mut c, None).unwrap().unwrap(),
+            4,
+            TcpFlags::SYN,
+        );
+
+        // A SYN from the other endpoint is ignored, because simultaneous opens are not supported.
+        let mut synack = t.write_syn(buf1.as_mut());
+        assert_eq!(
+            t.receive_segment(&mut c, &synack).unwrap(),
+            (None, RecvStatusFlags::INVALID_SEGMENT)
+        );
+
+        // A SYNACK which doesn't acknowledge our SYN resets the connection.
+        synack.set_flags_after_ns(TcpFlags::SYN | TcpFlags::ACK);
+        synack.set_ack_number(conn_isn);
+        t.should_reset_after(
+            &mut c.clone(),
+            &synack,
+            RecvStatusFlags::CONN_RESETTING | RecvStatusFlags::INVALID_ACK,
+            TcpFlags::empty(),
+        );
+
+        // So does a RST which acknowledges our SYN.
+        let mut rst = t.write_ctrl(buf2.as_mut());
+        rst.set_flags_after_ns(TcpFlags::RST | TcpFlags::ACK);
+        rst.set_ack_number(conn_isn.wrapping_add(1));
+        t.should_reset_after(
+            &mut c.clone(),
+            &rst,
+            RecvStatusFlags::RESET_RECEIVED,
+            TcpFlags::empty(),
+        );
+
+        // A valid SYNACK moves the connection to ESTABLISHED, and gets ACKed.
+        synack.set_ack_number(conn_isn.wrapping_add(1));
+        assert_eq!(
+            t.receive_segment(&mut c, &synack).unwrap(),
+            (None, RecvStatusFlags::empty())
+        );
+        assert!(c.is_established());
+        {
+            let s = t.write_next_segment(&mut c, None).unwrap().unwrap();
+            check_control_segment(&s, 0, TcpFlags::ACK);
+            check_acks(&s, remote_isn.wrapping_add(1), TcpFlags::empty());
+            assert_eq!(u32::from(s.window_size()), t.local_rwnd_size);
+        }
+
+        // Data can now flow in both directions.
+        let send_buf = [11u8; 100];
+        let data_seq = c.first_not_sent();
+        {
+            let s = t
+                .write_next_segment(&mut c, Some((send_buf.as_ref(), data_seq)))
+                .unwrap()
+                .unwrap();
+            assert_eq!(s.sequence_number(), data_seq.0);
+            assert_eq!(s.payload(), send_buf.as_ref());
+        }
+
+        let data_buf = [2u8; 50];
+        let mut data = t.write_data(buf3.as_mut(), data_buf.as_ref());
+        data.set_flags_after_ns(TcpFlags::ACK);
+        data.set_sequence_number(remote_isn.wrapping_add(1));
+        data.set_ack_number(data_seq.0.wrapping_add(100));
+        assert_eq!(
+            t.receive_segment(&mut c, &data).unwrap(),
+            (NonZeroUsize::new(50), RecvStatusFlags::empty())
+        );
+        assert_eq!(c.highest_ack_received(), data_seq + Wrapping(100));
+    }
 }
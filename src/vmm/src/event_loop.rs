@@ -0,0 +1,158 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use event_manager::{EventOps, Events, MutEventSubscriber, SubscriberId, SubscriberOps};
+use utils::time::{get_time_us, ClockType};
+
+use crate::logger::{warn, IncMetric, METRICS};
+use crate::EventManager;
+
+/// Dispatch latency budget of an event handler, in microseconds. Zero disables stall detection.
+static EVENT_LOOP_BUDGET_US: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the dispatch latency budget of event handlers, in microseconds.
+///
+/// Handlers running for longer than the budget are logged and counted in the
+/// `vmm.event_loop_stalls` metric. A budget of zero disables stall detection.
+pub fn set_event_loop_budget_us(budget_us: u64) {
+    EVENT_LOOP_BUDGET_US.store(budget_us, Ordering::Relaxed);
+}
+
+/// Returns the dispatch latency budget of event handlers, in microseconds.
+pub fn event_loop_budget_us() -> u64 {
+    EVENT_LOOP_BUDGET_US.load(Ordering::Relaxed)
+}
+
+/// Event subscriber wrapper that measures how long the inner subscriber takes to handle events.
+pub struct TimedSubscriber {
+    name: String,
+    inner: Arc<Mutex<dyn MutEventSubscriber>>,
+}
+
+impl TimedSubscriber {
+    /// Wraps `inner`, reporting its stalls under `name`.
+    pub fn new(name: String, inner: Arc<Mutex<dyn MutEventSubscriber>>) -> Self {
+        TimedSubscriber { name, inner }
+    }
+}
+
+impl Debug for TimedSubscriber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TimedSubscriber")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl MutEventSubscriber for TimedSubscriber {
+    fn process(&mut self, events: Events, ops: &mut EventOps) {
+        let budget_us = event_loop_budget_us();
+        if budget_us == 0 {
+            self.inner
+                .lock()
+                .expect("Poisoned lock")
+                .process(events, ops);
+            return;
+        }
+
+        let start_us = get_time_us(ClockType::Monotonic);
+        self.inner
+            .lock()
+            .expect("Poisoned lock")
+            .process(events, ops);
+        let elapsed_us = get_time_us(ClockType::Monotonic).saturating_sub(start_us);
+        if elapsed_us > budget_us {
+            warn!(
+                "Event handler '{}' stalled the event loop for {} us (budget {} us)",
+                self.name, elapsed_us, budget_us
+            );
+            METRICS.vmm.event_loop_stalls.inc();
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        self.inner.lock().expect("Poisoned lock").init(ops);
+    }
+}
+
+/// Registers `subscriber` with the event manager, measuring its dispatch latency under `name`.
+// The event manager and its subscribers are only used from the thread running the event loop.
+#[allow(clippy::arc_with_non_send_sync)]
+pub fn add_timed_subscriber(
+    event_manager: &mut EventManager,
+    name: impl Into<String>,
+    subscriber: Arc<Mutex<dyn MutEventSubscriber>>,
+) -> SubscriberId {
+    event_manager.add_subscriber(Arc::new(Mutex::new(TimedSubscriber::new(
+        name.into(),
+        subscriber,
+    ))))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use event_manager::EventSet;
+    use utils::eventfd::EventFd;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct SlowSubscriber {
+        evfd: EventFd,
+        delay: Duration,
+        processed: u32,
+    }
+
+    impl MutEventSubscriber for SlowSubscriber {
+        fn process(&mut self, _events: Events, _ops: &mut EventOps) {
+            self.evfd.read().unwrap();
+            std::thread::sleep(self.delay);
+            self.processed += 1;
+        }
+
+        fn init(&mut self, ops: &mut EventOps) {
+            ops.add(Events::new(&self.evfd, EventSet::IN)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_timed_subscriber() {
+        let mut event_manager = EventManager::new().unwrap();
+        let slow = Arc::new(Mutex::new(SlowSubscriber {
+            evfd: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            delay: Duration::from_millis(20),
+            processed: 0,
+        }));
+        add_timed_subscriber(&mut event_manager, "slow", slow.clone());
+
+        // Stall detection is disabled by default.
+        let stalls = METRICS.vmm.event_loop_stalls.count();
+        slow.lock().unwrap().evfd.write(1).unwrap();
+        event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(slow.lock().unwrap().processed, 1);
+        assert_eq!(METRICS.vmm.event_loop_stalls.count(), stalls);
+
+        // The handler runs for longer than the budget.
+        set_event_loop_budget_us(1000);
+        assert_eq!(event_loop_budget_us(), 1000);
+        slow.lock().unwrap().evfd.write(1).unwrap();
+        event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(slow.lock().unwrap().processed, 2);
+        assert!(METRICS.vmm.event_loop_stalls.count() > stalls);
+
+        // The handler runs within the budget.
+        set_event_loop_budget_us(1_000_000);
+        let stalls = METRICS.vmm.event_loop_stalls.count();
+        slow.lock().unwrap().evfd.write(1).unwrap();
+        event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(slow.lock().unwrap().processed, 3);
+        assert_eq!(METRICS.vmm.event_loop_stalls.count(), stalls);
+        set_event_loop_budget_us(0);
+    }
+}
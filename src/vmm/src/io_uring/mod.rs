@@ -246,6 +246,11 @@ impl<T: Debug> IoUring<T> {
         self.num_ops
     }
 
+    /// Returns the user data of the operations pushed to the ring and not popped yet.
+    pub fn pending_user_data(&self) -> impl Iterator<Item = &T> {
+        self.slab.iter().map(|(_, user_data)| user_data)
+    }
+
     fn enable(&mut self) -> Result<(), IoUringError> {
         // SAFETY: Safe because values are valid and we check the return value.
         SyscallReturnCode(unsafe {
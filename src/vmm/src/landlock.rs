@@ -0,0 +1,292 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Filesystem sandboxing with [Landlock](https://docs.kernel.org/userspace-api/landlock.html).
+//!
+//! Once the microVM is built, the VMM thread restricts its filesystem accesses, and the ones of
+//! the threads it spawns afterwards, to the artifacts the microVM was configured with.
+
+use std::fs::File;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use utils::syscall::SyscallReturnCode;
+
+use crate::logger::warn;
+
+static LANDLOCK: AtomicBool = AtomicBool::new(true);
+
+/// Enables the Landlock ruleset applied when the microVM is built.
+pub fn set_landlock(enabled: bool) {
+    LANDLOCK.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether the Landlock ruleset is applied when the microVM is built.
+pub fn landlock() -> bool {
+    LANDLOCK.load(Ordering::Relaxed)
+}
+
+// Definitions from include/uapi/linux/landlock.h.
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+const LANDLOCK_ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const LANDLOCK_ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const LANDLOCK_ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const LANDLOCK_ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const LANDLOCK_ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+const LANDLOCK_ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+// Access rights handled by each ABI version: all the filesystem accesses of the first version,
+// the ability to link or rename files across directories from version 2, and truncating files
+// from version 3.
+const LANDLOCK_ACCESS_FS_V1: u64 = (1 << 13) - 1;
+const LANDLOCK_ACCESS_FS_V2: u64 = LANDLOCK_ACCESS_FS_V1 | (1 << 13);
+const LANDLOCK_ACCESS_FS_V3: u64 = LANDLOCK_ACCESS_FS_V2 | LANDLOCK_ACCESS_FS_TRUNCATE;
+
+// Access rights that can be granted on files, the other ones only apply to directories.
+const LANDLOCK_ACCESS_FS_FILE: u64 = LANDLOCK_ACCESS_FS_EXECUTE
+    | LANDLOCK_ACCESS_FS_WRITE_FILE
+    | LANDLOCK_ACCESS_FS_READ_FILE
+    | LANDLOCK_ACCESS_FS_TRUNCATE;
+
+#[repr(C)]
+struct LandlockRulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct LandlockPathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Landlock error.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum LandlockError {
+    /// Failed to create the Landlock ruleset: {0}
+    CreateRuleset(std::io::Error),
+    /// Failed to open {0:?}: {1}
+    OpenPath(PathBuf, std::io::Error),
+    /// Failed to add the Landlock rule for {0:?}: {1}
+    AddRule(PathBuf, std::io::Error),
+    /// Failed to prevent the thread from gaining new privileges: {0}
+    NoNewPrivs(std::io::Error),
+    /// Failed to restrict the thread with the Landlock ruleset: {0}
+    RestrictSelf(std::io::Error),
+}
+
+/// Filesystem accesses granted beneath a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Reading files and listing directories.
+    Read,
+    /// Reading, writing and truncating files.
+    ReadWrite,
+    /// Creating, writing and truncating regular files, without reading them back.
+    CreateFiles,
+}
+
+impl Access {
+    fn rights(self, handled: u64) -> u64 {
+        let rights = match self {
+            Access::Read => LANDLOCK_ACCESS_FS_READ_FILE | LANDLOCK_ACCESS_FS_READ_DIR,
+            Access::ReadWrite => {
+                LANDLOCK_ACCESS_FS_READ_FILE
+                    | LANDLOCK_ACCESS_FS_WRITE_FILE
+                    | LANDLOCK_ACCESS_FS_TRUNCATE
+            }
+            Access::CreateFiles => {
+                LANDLOCK_ACCESS_FS_MAKE_REG
+                    | LANDLOCK_ACCESS_FS_WRITE_FILE
+                    | LANDLOCK_ACCESS_FS_TRUNCATE
+            }
+        };
+        rights & handled
+    }
+}
+
+/// Set of paths the filesystem accesses of a thread are restricted to.
+#[derive(Debug, Default)]
+pub struct Ruleset {
+    rules: Vec<(PathBuf, Access)>,
+}
+
+impl Ruleset {
+    /// Creates a ruleset denying all the filesystem accesses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Grants `access` beneath `path`.
+    pub fn allow(&mut self, path: impl Into<PathBuf>, access: Access) -> &mut Self {
+        self.rules.push((path.into(), access));
+        self
+    }
+
+    /// Restricts the filesystem accesses of the calling thread, and of the threads it spawns
+    /// afterwards, to the ones granted by the ruleset.
+    ///
+    /// Returns whether the thread was restricted, which is not the case if the kernel doesn't
+    /// support Landlock.
+    pub fn restrict_self(&self) -> Result<bool, LandlockError> {
+        // SAFETY: Querying the ABI version doesn't access any memory.
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<LandlockRulesetAttr>(),
+                0,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        let handled_access_fs = match abi {
+            ..=0 => {
+                warn!(
+                    "Landlock is not supported by the host kernel: {}",
+                    std::io::Error::last_os_error()
+                );
+                return Ok(false);
+            }
+            1 => LANDLOCK_ACCESS_FS_V1,
+            2 => LANDLOCK_ACCESS_FS_V2,
+            _ => LANDLOCK_ACCESS_FS_V3,
+        };
+
+        let attr = LandlockRulesetAttr { handled_access_fs };
+        // SAFETY: The attribute is valid for the duration of the call and we check the return
+        // value.
+        let fd = SyscallReturnCode(unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr,
+                std::mem::size_of::<LandlockRulesetAttr>(),
+                0,
+            )
+        })
+        .into_result()
+        .map_err(LandlockError::CreateRuleset)?;
+        // SAFETY: The fd was just created and is owned by nothing else.
+        let ruleset = unsafe { File::from_raw_fd(i32::try_from(fd).unwrap()) };
+
+        for (path, access) in &self.rules {
+            let file = File::options()
+                .read(true)
+                .custom_flags(libc::O_PATH)
+                .open(path)
+                .map_err(|err| LandlockError::OpenPath(path.clone(), err))?;
+            let mut allowed_access = access.rights(handled_access_fs);
+            if !file
+                .metadata()
+                .map_err(|err| LandlockError::OpenPath(path.clone(), err))?
+                .is_dir()
+            {
+                allowed_access &= LANDLOCK_ACCESS_FS_FILE;
+            }
+            let attr = LandlockPathBeneathAttr {
+                allowed_access,
+                parent_fd: file.as_raw_fd(),
+            };
+            // SAFETY: The ruleset fd and the attribute are valid for the duration of the call
+            // and we check the return value.
+            SyscallReturnCode(unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    ruleset.as_raw_fd(),
+                    LANDLOCK_RULE_PATH_BENEATH,
+                    &attr,
+                    0,
+                )
+            })
+            .into_empty_result()
+            .map_err(|err| LandlockError::AddRule(path.clone(), err))?;
+        }
+
+        // SAFETY: Safe because we provide valid parameters and check the return value.
+        SyscallReturnCode(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })
+            .into_empty_result()
+            .map_err(LandlockError::NoNewPrivs)?;
+
+        // SAFETY: The ruleset fd is valid and we check the return value.
+        SyscallReturnCode(unsafe {
+            libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0)
+        })
+        .into_empty_result()
+        .map_err(LandlockError::RestrictSelf)?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use utils::tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_access_rights() {
+        assert_eq!(
+            Access::Read.rights(LANDLOCK_ACCESS_FS_V3),
+            LANDLOCK_ACCESS_FS_READ_FILE | LANDLOCK_ACCESS_FS_READ_DIR
+        );
+        // Truncating is not handled before the third version, so it must not be granted.
+        assert_eq!(
+            Access::ReadWrite.rights(LANDLOCK_ACCESS_FS_V1),
+            LANDLOCK_ACCESS_FS_READ_FILE | LANDLOCK_ACCESS_FS_WRITE_FILE
+        );
+        assert_eq!(
+            Access::CreateFiles.rights(LANDLOCK_ACCESS_FS_V3),
+            LANDLOCK_ACCESS_FS_MAKE_REG
+                | LANDLOCK_ACCESS_FS_WRITE_FILE
+                | LANDLOCK_ACCESS_FS_TRUNCATE
+        );
+    }
+
+    #[test]
+    fn test_restrict_self() {
+        let dir = TempDir::new().unwrap();
+        let dir = dir.as_path().to_path_buf();
+        let allowed = dir.join("allowed");
+        let denied = dir.join("denied");
+        let output = dir.join("output");
+        fs::create_dir(&output).unwrap();
+        fs::write(&allowed, "allowed").unwrap();
+        fs::write(&denied, "denied").unwrap();
+
+        // Landlock restricts the calling thread, so don't restrict the test harness.
+        std::thread::spawn(move || {
+            let mut ruleset = Ruleset::new();
+            ruleset
+                .allow(&allowed, Access::Read)
+                .allow(&output, Access::CreateFiles);
+            if !ruleset.restrict_self().unwrap() {
+                return;
+            }
+
+            assert_eq!(fs::read_to_string(&allowed).unwrap(), "allowed");
+            fs::write(&allowed, "").unwrap_err();
+            fs::read_to_string(&denied).unwrap_err();
+            fs::write(&denied, "").unwrap_err();
+
+            let created = output.join("created");
+            fs::write(&created, "created").unwrap();
+            fs::read_to_string(&created).unwrap_err();
+            fs::create_dir(output.join("dir")).unwrap_err();
+        })
+        .join()
+        .unwrap();
+
+        let mut ruleset = Ruleset::new();
+        ruleset.allow(dir.join("missing"), Access::Read);
+        std::thread::spawn(move || match ruleset.restrict_self() {
+            Ok(restricted) => assert!(!restricted),
+            Err(err) => assert!(matches!(err, LandlockError::OpenPath(_, _)), "{:?}", err),
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -77,6 +77,11 @@ pub mod rate_limiter;
 /// Currently, we only use ACPI on x86 microVMs.
 #[cfg(target_arch = "x86_64")]
 pub mod acpi;
+/// Machine-readable classification of the errors returned by the API.
+pub mod api_error;
+/// Boot, snapshot and restore time measurement.
+#[cfg(feature = "bench")]
+pub mod bench;
 /// Handles setup and initialization a `Vmm` object.
 pub mod builder;
 /// Types for guest configuration.
@@ -87,6 +92,12 @@ pub(crate) mod device_manager;
 pub mod devices;
 /// minimalist HTTP/TCP/IPv4 stack named DUMBO
 pub mod dumbo;
+/// Event loop instrumentation.
+pub mod event_loop;
+/// Filesystem sandboxing with Landlock.
+pub mod landlock;
+/// Notifications of the microVM state transitions.
+pub mod lifecycle;
 /// Logger
 pub mod logger;
 /// microVM Metadata Service MMDS
@@ -99,12 +110,16 @@ pub mod resources;
 pub mod rpc_interface;
 /// Seccomp filter utilities.
 pub mod seccomp_filters;
+/// Hardened buffers for sensitive data.
+pub mod secret;
 /// Signal handling utilities.
 pub mod signal_handler;
 /// Serialization and deserialization facilities
 pub mod snapshot;
 /// Utility functions for integration and benchmark testing
 pub mod utilities;
+/// Guest memory dumps in the ELF core format.
+pub mod vmcore;
 /// Wrappers over structures used to configure the VMM.
 pub mod vmm_config;
 /// Module with virtual state structs.
@@ -115,7 +130,7 @@ use std::io;
 use std::os::unix::io::AsRawFd;
 use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, Barrier, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 #[cfg(target_arch = "x86_64")]
 use device_manager::acpi::ACPIDeviceManager;
@@ -127,6 +142,7 @@ use seccompiler::BpfProgram;
 use userfaultfd::Uffd;
 use utils::epoll::EventSet;
 use utils::eventfd::EventFd;
+use utils::net::mac::MacAddr;
 use utils::terminal::Terminal;
 use utils::u64_to_usize;
 use vstate::vcpu::{self, KvmVcpuConfigureError, StartThreadedError, VcpuSendEventError};
@@ -135,24 +151,38 @@ use crate::arch::DeviceType;
 use crate::cpu_config::templates::CpuConfiguration;
 #[cfg(target_arch = "x86_64")]
 use crate::device_manager::legacy::PortIODeviceManager;
-use crate::device_manager::mmio::MMIODeviceManager;
+use crate::device_manager::mmio::{MMIODeviceManager, MmioError};
 use crate::devices::legacy::{IER_RDA_BIT, IER_RDA_OFFSET};
 use crate::devices::virtio::balloon::{
-    Balloon, BalloonConfig, BalloonError, BalloonStats, BALLOON_DEV_ID,
+    Balloon, BalloonConfig, BalloonError, BalloonStats, BalloonStatsSample, BALLOON_DEV_ID,
 };
 use crate::devices::virtio::block::device::Block;
-use crate::devices::virtio::net::Net;
-use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_NET};
-use crate::logger::{error, info, warn, MetricsError, METRICS};
+use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::features::VirtioDeviceFeatures;
+use crate::devices::virtio::mmio::MmioTransport;
+use crate::devices::virtio::net::coalescing::InterruptCoalescingConfig;
+use crate::devices::virtio::net::{Net, NetError};
+use crate::devices::virtio::remote::RemoteDevice;
+use crate::devices::virtio::rng::device::ENTROPY_DEV_ID;
+use crate::devices::virtio::rng::Entropy;
+use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_NET, TYPE_RNG};
+use crate::devices::DeviceRegions;
+use crate::lifecycle::{notify_lifecycle_event, LifecycleEvent};
+use crate::logger::{error, info, warn, IncMetric, MetricsError, METRICS};
 use crate::persist::{MicrovmState, MicrovmStateError, VmInfo};
 use crate::rate_limiter::BucketUpdate;
 use crate::snapshot::Persist;
+use crate::vmm_config::drive::FileEngineType;
+use crate::vmm_config::fault_injection::{FaultInjectionConfig, FaultType};
 use crate::vmm_config::instance_info::{InstanceInfo, VmState};
+use crate::vmm_config::mmio_trace::{MmioTraceConfig, MmioTraceError};
+use crate::vmm_config::net::{NetworkInterfaceError, NetworkInterfaceRateLimiterStats};
+use crate::vmm_config::RateLimiterStats;
 use crate::vstate::memory::{
     GuestMemory, GuestMemoryExtension, GuestMemoryMmap, GuestMemoryRegion,
 };
-use crate::vstate::vcpu::VcpuState;
 pub use crate::vstate::vcpu::{Vcpu, VcpuConfig, VcpuEvent, VcpuHandle, VcpuResponse};
+use crate::vstate::vcpu::{VcpuRegisters, VcpuState};
 pub use crate::vstate::vm::Vm;
 
 /// Shorthand type for the EventManager flavour used by Firecracker.
@@ -197,6 +227,9 @@ pub enum FcExitCode {
 /// used to detect a potential vcpu deadlock.
 pub const RECV_TIMEOUT_SEC: Duration = Duration::from_secs(30);
 
+/// Time budget of a graceful shutdown, when none is requested explicitly (e.g. on `SIGTERM`).
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Default byte limit of accepted http requests on API and MMDS servers.
 pub const HTTP_MAX_PAYLOAD_SIZE: usize = 51200;
 
@@ -208,10 +241,14 @@ pub enum VmmError {
     #[cfg(target_arch = "aarch64")]
     /// Invalid command line error.
     Cmdline,
+    /// Cannot quiesce block device: {0}
+    BlockQuiesce(devices::virtio::block::BlockError),
     /// Device manager error: {0}
     DeviceManager(device_manager::mmio::MmioError),
     /// Error getting the KVM dirty bitmap. {0}
     DirtyBitmap(kvm_ioctls::Error),
+    /// Error harvesting the KVM dirty rings: {0}
+    DirtyRing(vstate::dirty_ring::DirtyRingError),
     /// Event fd error: {0}
     EventFd(io::Error),
     /// I8042 error: {0}
@@ -223,6 +260,10 @@ pub enum VmmError {
     LegacyIOBus(device_manager::legacy::LegacyDeviceError),
     /// Metrics error: {0}
     Metrics(MetricsError),
+    /// Cannot quiesce network device: {0}
+    NetQuiesce(devices::virtio::net::NetError),
+    /// Cannot quiesce remote device: {0}
+    RemoteQuiesce(devices::virtio::remote::RemoteDeviceError),
     /// Cannot add a device to the MMIO Bus. {0}
     RegisterMMIODevice(device_manager::mmio::MmioError),
     /// Cannot install seccomp filters: {0}
@@ -300,12 +341,38 @@ pub enum DumpCpuConfigError {
     NotAllowed(String),
 }
 
+/// Error type for [`Vmm::vcpu_registers()`]
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum VcpuRegistersError {
+    /// Invalid vCPU index: {0}
+    InvalidVcpuIndex(u8),
+    /// Failed to send event to vcpu thread: {0}
+    SendEvent(#[from] VcpuSendEventError),
+    /// Got unexpected response from vcpu thread.
+    UnexpectedResponse,
+    /// Failed to save vCPU state: {0}
+    SaveVcpuState(#[from] vcpu::VcpuError),
+    /// Operation not allowed: {0}
+    NotAllowed(String),
+}
+
+/// Error type for [`Vmm::virtio_device_features()`]
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum DeviceFeaturesError {
+    /// No virtio device with ID {0}
+    NotFound(String),
+    /// Several virtio devices have the ID {0}
+    AmbiguousId(String),
+}
+
 /// Contains the state and associated methods required for the Firecracker VMM.
 #[derive(Debug)]
 pub struct Vmm {
     events_observer: Option<std::io::Stdin>,
     instance_info: InstanceInfo,
     shutdown_exit_code: Option<FcExitCode>,
+    // Whether the devices were stopped from accessing the guest memory along with the vCPUs.
+    devices_quiesced: bool,
 
     // Guest VM core resources.
     vm: Vm,
@@ -317,6 +384,8 @@ pub struct Vmm {
     vcpus_handles: Vec<VcpuHandle>,
     // Used by Vcpus and devices to initiate teardown; Vmm should never write here.
     vcpus_exit_evt: EventFd,
+    // Written by the `SIGTERM` handler to initiate a graceful shutdown.
+    shutdown_evt: EventFd,
 
     // Allocator for guest resrouces
     resource_allocator: ResourceAllocator,
@@ -404,6 +473,9 @@ impl Vmm {
 
     /// Sends a resume command to the vCPUs.
     pub fn resume_vm(&mut self) -> Result<(), VmmError> {
+        if self.devices_quiesced {
+            self.set_devices_quiesced(false)?;
+        }
         self.mmio_device_manager.kick_devices();
 
         // Send the events.
@@ -423,9 +495,68 @@ impl Vmm {
         }
 
         self.instance_info.state = VmState::Running;
+        notify_lifecycle_event(LifecycleEvent::Running);
+        Ok(())
+    }
+
+    /// Advances the guest clock by the time elapsed since the vCPUs were paused, or since the
+    /// snapshot the microVM was restored from was created.
+    ///
+    /// Does nothing if the microVM is not paused.
+    #[cfg(target_arch = "x86_64")]
+    pub fn adjust_clock(&mut self) -> Result<(), VmmError> {
+        if self.instance_info.state != VmState::Paused {
+            return Ok(());
+        }
+        self.vm.adjust_clock().map_err(VmmError::Vm)
+    }
+
+    /// Stops the devices from accessing the guest memory until the vCPUs are resumed.
+    ///
+    /// The inflight requests of the block devices are completed, and the vhost-user and remote
+    /// backends are asked to stop processing their queues. Interrupts raised in the meantime
+    /// are only delivered once the vCPUs run again.
+    pub fn quiesce_devices(&mut self) -> Result<(), VmmError> {
+        self.set_devices_quiesced(true)
+    }
+
+    fn set_devices_quiesced(&mut self, quiesce: bool) -> Result<(), VmmError> {
+        // If a device fails to quiesce, the ones that did are still resumed with the vCPUs.
+        self.devices_quiesced |= quiesce;
+        self.mmio_device_manager
+            .for_each_virtio_device(|virtio_type, _, _, device| {
+                let mut locked_device = device.lock().expect("Poisoned lock");
+                if virtio_type == TYPE_BLOCK {
+                    let block = locked_device.as_mut_any().downcast_mut::<Block>().unwrap();
+                    if quiesce {
+                        block.quiesce()
+                    } else {
+                        block.unquiesce()
+                    }
+                    .map_err(VmmError::BlockQuiesce)
+                } else if virtio_type == TYPE_NET {
+                    let net = locked_device.as_mut_any().downcast_mut::<Net>().unwrap();
+                    net.set_queues_enabled(!quiesce)
+                        .map_err(VmmError::NetQuiesce)
+                } else if let Some(remote) =
+                    locked_device.as_mut_any().downcast_mut::<RemoteDevice>()
+                {
+                    remote
+                        .set_queues_enabled(!quiesce)
+                        .map_err(VmmError::RemoteQuiesce)
+                } else {
+                    Ok(())
+                }
+            })?;
+        self.devices_quiesced = quiesce;
         Ok(())
     }
 
+    /// Returns the event that initiates a graceful shutdown of the Vmm when written.
+    pub fn shutdown_evt(&self) -> &EventFd {
+        &self.shutdown_evt
+    }
+
     /// Sends a pause command to the vCPUs.
     pub fn pause_vm(&mut self) -> Result<(), VmmError> {
         // Send the events.
@@ -443,8 +574,11 @@ impl Vmm {
         {
             return Err(VmmError::VcpuMessage);
         }
+        #[cfg(target_arch = "x86_64")]
+        self.vm.record_paused_clock().map_err(VmmError::Vm)?;
 
         self.instance_info.state = VmState::Paused;
+        notify_lifecycle_event(LifecycleEvent::Paused);
         Ok(())
     }
 
@@ -541,6 +675,8 @@ impl Vmm {
             device_states,
             #[cfg(target_arch = "x86_64")]
             acpi_dev_state,
+            // Digests are filled in once the snapshot memory file has been written.
+            digests: Default::default(),
         })
     }
 
@@ -600,8 +736,68 @@ impl Vmm {
         Ok(cpu_configs)
     }
 
+    /// Returns the address ranges of the devices registered on the guest buses.
+    pub fn device_regions(&self) -> DeviceRegions {
+        DeviceRegions {
+            mmio: self.mmio_device_manager.bus_regions(),
+            #[cfg(target_arch = "x86_64")]
+            pio: self.pio_device_manager.io_bus.regions(),
+            #[cfg(target_arch = "aarch64")]
+            pio: Vec::new(),
+        }
+    }
+
+    /// Returns the features offered by the virtio device with the given ID, and those acknowledged
+    /// by the guest driver.
+    pub fn virtio_device_features(
+        &self,
+        id: &str,
+    ) -> Result<VirtioDeviceFeatures, DeviceFeaturesError> {
+        let mut features = None;
+        self.mmio_device_manager
+            .for_each_virtio_device(|virtio_type, device_id, _, device| {
+                if device_id == id {
+                    if features.is_some() {
+                        return Err(DeviceFeaturesError::AmbiguousId(id.to_string()));
+                    }
+                    let device = device.lock().expect("Poisoned lock");
+                    features = Some(VirtioDeviceFeatures::new(id, virtio_type, &*device));
+                }
+                Ok(())
+            })?;
+        features.ok_or_else(|| DeviceFeaturesError::NotFound(id.to_string()))
+    }
+
+    /// Returns the register state of the vCPU with the given index. The vCPU must be paused.
+    pub fn vcpu_registers(&mut self, index: u8) -> Result<VcpuRegisters, VcpuRegistersError> {
+        let handle = self
+            .vcpus_handles
+            .get(usize::from(index))
+            .ok_or(VcpuRegistersError::InvalidVcpuIndex(index))?;
+
+        handle
+            .send_event(VcpuEvent::SaveState)
+            .map_err(VcpuRegistersError::SendEvent)?;
+
+        let response = handle
+            .response_receiver()
+            .recv_timeout(RECV_TIMEOUT_SEC)
+            .map_err(|_| VcpuRegistersError::UnexpectedResponse)?;
+
+        match response {
+            VcpuResponse::SavedState(state) => Ok(VcpuRegisters::from(&*state)),
+            VcpuResponse::Error(err) => Err(VcpuRegistersError::SaveVcpuState(err)),
+            VcpuResponse::NotAllowed(reason) => Err(VcpuRegistersError::NotAllowed(reason)),
+            _ => Err(VcpuRegistersError::UnexpectedResponse),
+        }
+    }
+
     /// Retrieves the KVM dirty bitmap for each of the guest's memory regions.
     pub fn reset_dirty_bitmap(&self) {
+        if let Some(dirty_ring) = self.vm.dirty_ring() {
+            let _ = dirty_ring.take_bitmap();
+            return;
+        }
         self.guest_memory
             .iter()
             .enumerate()
@@ -615,6 +811,9 @@ impl Vmm {
 
     /// Retrieves the KVM dirty bitmap for each of the guest's memory regions.
     pub fn get_dirty_bitmap(&self) -> Result<DirtyBitmap, VmmError> {
+        if let Some(dirty_ring) = self.vm.dirty_ring() {
+            return dirty_ring.take_bitmap().map_err(VmmError::DirtyRing);
+        }
         let mut bitmap: DirtyBitmap = HashMap::new();
         self.guest_memory
             .iter()
@@ -643,6 +842,24 @@ impl Vmm {
             .map_err(VmmError::Vm)
     }
 
+    /// Replaces the virtio device with id `device_id` by `device`, keeping the MMIO range and IRQ
+    /// of the old device. The events of the new device are handled by `event_manager` instead of
+    /// the ones of the old device.
+    pub fn replace_virtio_device<T: 'static + VirtioDevice + MutEventSubscriber>(
+        &mut self,
+        event_manager: &mut EventManager,
+        device_id: &str,
+        device: Arc<Mutex<T>>,
+        is_vhost_user: bool,
+    ) -> Result<(), VmmError> {
+        // The device mutex mustn't be locked here otherwise it will deadlock.
+        let mmio_device =
+            MmioTransport::new(self.guest_memory.clone(), device.clone(), is_vhost_user);
+        self.mmio_device_manager
+            .replace_mmio_virtio(self.vm.fd(), event_manager, device_id, mmio_device, device)
+            .map_err(VmmError::DeviceManager)
+    }
+
     /// Updates the path of the host file backing the emulated block device with id `drive_id`.
     /// We update the disk image on the device and its virtio configuration.
     pub fn update_block_device_path(
@@ -659,6 +876,21 @@ impl Vmm {
             .map_err(VmmError::DeviceManager)
     }
 
+    /// Switches the block device with `drive_id` id to an IO engine of type `file_engine_type`.
+    pub fn update_block_file_engine(
+        &mut self,
+        drive_id: &str,
+        file_engine_type: FileEngineType,
+    ) -> Result<(), VmmError> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                block
+                    .update_file_engine(file_engine_type)
+                    .map_err(|err| err.to_string())
+            })
+            .map_err(VmmError::DeviceManager)
+    }
+
     /// Updates the rate limiter parameters for block device with `drive_id` id.
     pub fn update_block_rate_limiter(
         &mut self,
@@ -695,12 +927,193 @@ impl Vmm {
     ) -> Result<(), VmmError> {
         self.mmio_device_manager
             .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                if net.is_worker() {
+                    return Err(NetError::WorkerUnsupported.to_string());
+                }
                 net.patch_rate_limiters(rx_bytes, rx_ops, tx_bytes, tx_ops);
                 Ok(())
             })
             .map_err(VmmError::DeviceManager)
     }
 
+    /// Returns the live state of the rate limiters of net device with `net_id` id.
+    pub fn net_rate_limiter_stats(
+        &mut self,
+        net_id: &str,
+    ) -> Result<NetworkInterfaceRateLimiterStats, VmmError> {
+        let mut stats = None;
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                if net.is_worker() {
+                    return Err(NetError::WorkerUnsupported.to_string());
+                }
+                // Account for the tokens generated since the buckets were last used.
+                net.rx_rate_limiter.refresh();
+                net.tx_rate_limiter.refresh();
+                stats = Some(NetworkInterfaceRateLimiterStats {
+                    rx_rate_limiter: RateLimiterStats::from(net.rx_rate_limiter()),
+                    tx_rate_limiter: RateLimiterStats::from(net.tx_rate_limiter()),
+                });
+                Ok(())
+            })
+            .map_err(VmmError::DeviceManager)?;
+        Ok(stats.unwrap_or_default())
+    }
+
+    /// Updates the rate limiter parameters for the entropy device.
+    pub fn update_entropy_rate_limiter(
+        &mut self,
+        bytes: BucketUpdate,
+        ops: BucketUpdate,
+    ) -> Result<(), VmmError> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_RNG, ENTROPY_DEV_ID, |entropy: &mut Entropy| {
+                entropy.update_rate_limiter(bytes, ops);
+                Ok(())
+            })
+            .map_err(VmmError::DeviceManager)
+    }
+
+    /// Starts or stops injecting the fault described by `config` into the backend of the device
+    /// it targets.
+    pub fn inject_device_fault(&mut self, config: &FaultInjectionConfig) -> Result<(), VmmError> {
+        let id = config.device_id.as_str();
+        let enabled = config.enabled;
+        let manager = &self.mmio_device_manager;
+        match config.fault_type {
+            FaultType::BlockIo => {
+                manager.with_virtio_device_with_id(TYPE_BLOCK, id, |block: &mut Block| {
+                    block.set_io_fault(enabled).map_err(|err| err.to_string())
+                })
+            }
+            FaultType::NetTx => {
+                manager.with_virtio_device_with_id(TYPE_NET, id, |net: &mut Net| {
+                    if net.is_worker() {
+                        return Err(NetError::WorkerUnsupported.to_string());
+                    }
+                    net.set_tx_fault(enabled);
+                    Ok(())
+                })
+            }
+            FaultType::EntropySource => {
+                manager.with_virtio_device_with_id(TYPE_RNG, id, |entropy: &mut Entropy| {
+                    entropy.set_source_fault(enabled);
+                    Ok(())
+                })
+            }
+            FaultType::IrqDrop => {
+                let virtio_type =
+                    manager
+                        .get_device_info()
+                        .keys()
+                        .find_map(|(device_type, device_id)| match device_type {
+                            DeviceType::Virtio(virtio_type) if device_id == id => {
+                                Some(*virtio_type)
+                            }
+                            _ => None,
+                        });
+                match virtio_type {
+                    Some(TYPE_BLOCK) => {
+                        manager.with_virtio_device_with_id(TYPE_BLOCK, id, |block: &mut Block| {
+                            block.set_irq_fault(enabled).map_err(|err| err.to_string())
+                        })
+                    }
+                    Some(TYPE_NET) => {
+                        manager.with_virtio_device_with_id(TYPE_NET, id, |net: &mut Net| {
+                            if net.is_worker() {
+                                return Err(NetError::WorkerUnsupported.to_string());
+                            }
+                            net.set_irq_fault(enabled);
+                            Ok(())
+                        })
+                    }
+                    Some(TYPE_RNG) => {
+                        manager.with_virtio_device_with_id(TYPE_RNG, id, |entropy: &mut Entropy| {
+                            entropy.set_irq_fault(enabled);
+                            Ok(())
+                        })
+                    }
+                    Some(_) => Err(MmioError::InvalidDeviceType),
+                    None => Err(MmioError::DeviceNotFound),
+                }
+            }
+        }
+        .map_err(VmmError::DeviceManager)
+    }
+
+    /// Updates the interrupt coalescing parameters for net device with `net_id` id.
+    pub fn update_net_interrupt_coalescing(
+        &mut self,
+        net_id: &str,
+        rx: Option<InterruptCoalescingConfig>,
+        tx: Option<InterruptCoalescingConfig>,
+    ) -> Result<(), VmmError> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                if net.is_worker() {
+                    return Err(NetError::WorkerUnsupported.to_string());
+                }
+                net.set_interrupt_coalescing(rx, tx);
+                Ok(())
+            })
+            .map_err(VmmError::DeviceManager)
+    }
+
+    /// Updates the guest MAC address of the net device with `net_id` id.
+    pub fn update_net_guest_mac(
+        &mut self,
+        net_id: &str,
+        guest_mac: MacAddr,
+    ) -> Result<(), NetworkInterfaceError> {
+        let mut mac_in_use = false;
+        self.mmio_device_manager
+            .for_each_virtio_device(|virtio_type, device_id, _, device| {
+                if virtio_type == TYPE_NET && device_id != net_id {
+                    let device = device.lock().expect("Poisoned lock");
+                    let net = device.as_any().downcast_ref::<Net>().unwrap();
+                    mac_in_use |= net.guest_mac() == Some(&guest_mac);
+                }
+                Ok::<(), VmmError>(())
+            })?;
+        if mac_in_use {
+            return Err(NetworkInterfaceError::GuestMacAddressInUse(
+                guest_mac.to_string(),
+            ));
+        }
+
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                net.set_guest_mac(guest_mac).map_err(|err| err.to_string())
+            })
+            .map_err(VmmError::DeviceManager)
+            .map_err(NetworkInterfaceError::DeviceUpdate)
+    }
+
+    /// Logs the register accesses of the virtio devices listed in `config`, and stops logging the
+    /// ones of the other virtio devices.
+    pub fn set_mmio_trace(&mut self, config: &MmioTraceConfig) -> Result<(), MmioTraceError> {
+        let device_info = self.mmio_device_manager.get_device_info();
+        if let Some(id) = config.device_ids.iter().find(|id| {
+            !device_info.keys().any(|(device_type, device_id)| {
+                matches!(device_type, DeviceType::Virtio(_)) && device_id == *id
+            })
+        }) {
+            return Err(MmioTraceError::UnknownDevice(id.clone()));
+        }
+        self.mmio_device_manager.set_mmio_trace(&config.device_ids);
+        Ok(())
+    }
+
+    /// Updates the link state announced to the guest by the net device with `net_id` id.
+    pub fn update_net_link(&mut self, net_id: &str, up: bool) -> Result<(), NetworkInterfaceError> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                net.set_link_up(up).map_err(|err| err.to_string())
+            })
+            .map_err(VmmError::DeviceManager)
+            .map_err(NetworkInterfaceError::DeviceUpdate)
+    }
+
     /// Returns a reference to the balloon device if present.
     pub fn balloon_config(&self) -> Result<BalloonConfig, BalloonError> {
         if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
@@ -753,6 +1166,35 @@ impl Vmm {
         }
     }
 
+    /// Returns up to `count` of the most recent balloon statistics reports, if they are enabled.
+    pub fn balloon_stats_history(
+        &self,
+        count: usize,
+    ) -> Result<Vec<BalloonStatsSample>, BalloonError> {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
+        {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned lock")
+                .mmio_transport_ref()
+                .expect("Unexpected device type")
+                .device();
+
+            let history = virtio_device
+                .lock()
+                .expect("Poisoned lock")
+                .as_mut_any()
+                .downcast_mut::<Balloon>()
+                .unwrap()
+                .stats_history(count)
+                .ok_or(BalloonError::StatisticsDisabled)?;
+
+            Ok(history)
+        } else {
+            Err(BalloonError::DeviceNotFound)
+        }
+    }
+
     /// Updates configuration for the balloon device target size.
     pub fn update_balloon_config(&mut self, amount_mib: u32) -> Result<(), BalloonError> {
         // The balloon cannot have a target size greater than the size of
@@ -853,6 +1295,57 @@ impl Vmm {
         // Break the main event loop, propagating the Vmm exit-code.
         self.shutdown_exit_code = Some(exit_code);
     }
+
+    /// Drains the devices and syncs the logger and metrics, then signals Vmm to stop and exit.
+    ///
+    /// The vCPUs are paused first, so that the guest cannot submit new requests. Each block
+    /// device then completes its in-flight requests and flushes its backing file. Block devices
+    /// that were not reached before `timeout` elapsed are skipped.
+    pub fn graceful_shutdown(&mut self, timeout: Duration) {
+        info!("Vmm is draining the devices before stopping.");
+        let deadline = Instant::now() + timeout;
+
+        if self.instance_info.state == VmState::Running {
+            if let Err(err) = self.pause_vm() {
+                error!(
+                    "Failed to pause the vCPUs before draining the devices: {}",
+                    err
+                );
+            }
+        }
+        self.drain_block_devices(deadline);
+
+        log::logger().flush();
+        if let Err(err) = METRICS.write() {
+            error!("Failed to write metrics while stopping: {}", err);
+        }
+        self.stop(FcExitCode::Ok);
+    }
+
+    fn drain_block_devices(&self, deadline: Instant) {
+        let _ = self.mmio_device_manager.for_each_virtio_device::<_, ()>(
+            |virtio_type, id, _, device| {
+                if virtio_type != TYPE_BLOCK {
+                    return Ok(());
+                }
+                if Instant::now() >= deadline {
+                    warn!(
+                        "Shutdown timeout elapsed, block device {} was not drained.",
+                        id
+                    );
+                    METRICS.vmm.shutdown_drain_timeouts.inc();
+                    return Ok(());
+                }
+                let mut locked_device = device.lock().expect("Poisoned lock");
+                let block = locked_device.as_mut_any().downcast_mut::<Block>().unwrap();
+                // The backing file of a vhost-user block device is owned by its backend.
+                if !block.is_vhost_user() {
+                    block.prepare_save();
+                }
+                Ok(())
+            },
+        );
+    }
 }
 
 /// Process the content of the MPIDR_EL1 register in order to be able to pass it to KVM
@@ -955,6 +1448,9 @@ impl MutEventSubscriber for Vmm {
                 FcExitCode::Ok
             };
             self.stop(exit_code);
+        } else if source == self.shutdown_evt.as_raw_fd() && event_set == EventSet::IN {
+            let _ = self.shutdown_evt.read();
+            self.graceful_shutdown(DEFAULT_SHUTDOWN_TIMEOUT);
         } else {
             error!("Spurious EventManager event for handler: Vmm");
         }
@@ -964,5 +1460,8 @@ impl MutEventSubscriber for Vmm {
         if let Err(err) = ops.add(Events::new(&self.vcpus_exit_evt, EventSet::IN)) {
             error!("Failed to register vmm exit event: {}", err);
         }
+        if let Err(err) = ops.add(Events::new(&self.shutdown_evt, EventSet::IN)) {
+            error!("Failed to register vmm shutdown event: {}", err);
+        }
     }
 }
@@ -0,0 +1,198 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Notifications of the microVM state transitions.
+//!
+//! Instead of polling the metrics, an orchestrator can register notification sinks that receive a
+//! line of JSON every time the microVM starts running, gets paused, starts being snapshotted or
+//! exits, and once the guest drivers activated all the virtio devices. A sink is either a Unix
+//! socket Firecracker connects to, or a file descriptor inherited from its parent, such as the
+//! pipe to the hook runner of the jailer.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::Serialize;
+use utils::syscall::SyscallReturnCode;
+use utils::time::{get_time_us, ClockType};
+
+use crate::logger::{warn, IncMetric, INSTANCE_ID, METRICS};
+use crate::FcExitCode;
+
+static SINKS: Mutex<Vec<File>> = Mutex::new(Vec::new());
+
+/// Lifecycle notification error.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum LifecycleError {
+    /// Failed to connect to the notification socket {0:?}: {1}
+    Connect(PathBuf, io::Error),
+    /// Failed to make the notification sink non-blocking: {0}
+    NonBlocking(io::Error),
+}
+
+/// State the microVM transitioned to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    /// The vCPUs were started or resumed.
+    Running,
+    /// The vCPUs were paused.
+    Paused,
+    /// The creation of a snapshot started.
+    Snapshotting,
+    /// The guest drivers activated all the virtio devices.
+    DevicesActivated,
+    /// Firecracker is exiting with the given code.
+    Exit(FcExitCode),
+}
+
+#[derive(Debug, Serialize)]
+struct Notification<'a> {
+    id: &'a str,
+    state: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<u8>,
+    timestamp_us: u64,
+}
+
+impl LifecycleEvent {
+    fn to_json(self) -> String {
+        let (state, exit_code) = match self {
+            LifecycleEvent::Running => ("Running", None),
+            LifecycleEvent::Paused => ("Paused", None),
+            LifecycleEvent::Snapshotting => ("Snapshotting", None),
+            LifecycleEvent::DevicesActivated => ("DevicesActivated", None),
+            LifecycleEvent::Exit(code) => ("Exit", Some(code as u8)),
+        };
+        let notification = Notification {
+            id: INSTANCE_ID
+                .get()
+                .map(String::as_str)
+                .unwrap_or(crate::logger::DEFAULT_INSTANCE_ID),
+            state,
+            exit_code,
+            timestamp_us: get_time_us(ClockType::Real),
+        };
+        // Serializing a struct of strings and integers cannot fail.
+        serde_json::to_string(&notification).unwrap()
+    }
+}
+
+/// Connects to the Unix socket at `path` and notifies it of the microVM state transitions.
+pub fn add_lifecycle_socket(path: &Path) -> Result<(), LifecycleError> {
+    let sink = connect_sink(path)?;
+    SINKS.lock().expect("Poisoned lock").push(sink);
+    Ok(())
+}
+
+/// Notifies the file descriptor `fd` of the microVM state transitions.
+///
+/// # Safety
+///
+/// `fd` must be an open file descriptor, owned by nothing else.
+pub unsafe fn add_lifecycle_fd(fd: RawFd) -> Result<(), LifecycleError> {
+    let sink = nonblocking_sink(File::from_raw_fd(fd))?;
+    SINKS.lock().expect("Poisoned lock").push(sink);
+    Ok(())
+}
+
+fn connect_sink(path: &Path) -> Result<File, LifecycleError> {
+    let stream =
+        UnixStream::connect(path).map_err(|err| LifecycleError::Connect(path.to_owned(), err))?;
+    nonblocking_sink(OwnedFd::from(stream).into())
+}
+
+fn nonblocking_sink(sink: File) -> Result<File, LifecycleError> {
+    // A slow listener must not stall the VMM thread, the notifications are dropped instead.
+    // SAFETY: Safe because the fd is valid and we check the return value.
+    let flags = SyscallReturnCode(unsafe { libc::fcntl(sink.as_raw_fd(), libc::F_GETFL) })
+        .into_result()
+        .map_err(LifecycleError::NonBlocking)?;
+    // SAFETY: Safe because the fd is valid and we check the return value.
+    SyscallReturnCode(unsafe {
+        libc::fcntl(sink.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK)
+    })
+    .into_empty_result()
+    .map_err(LifecycleError::NonBlocking)?;
+    Ok(sink)
+}
+
+/// Notifies the registered sinks that the microVM transitioned to the state of `event`.
+pub fn notify_lifecycle_event(event: LifecycleEvent) {
+    notify_sinks(&mut SINKS.lock().expect("Poisoned lock"), event);
+}
+
+fn notify_sinks(sinks: &mut [File], event: LifecycleEvent) {
+    if sinks.is_empty() {
+        return;
+    }
+    let mut line = event.to_json();
+    line.push('\n');
+    for sink in sinks.iter_mut() {
+        if let Err(err) = sink.write_all(line.as_bytes()) {
+            warn!("Failed to notify the lifecycle event {:?}: {}", event, err);
+            METRICS.vmm.lifecycle_notify_fails.inc();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+
+    use utils::tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_lifecycle_notifications() {
+        let event = serde_json::from_str::<serde_json::Value>(
+            &LifecycleEvent::Exit(FcExitCode::BadConfiguration).to_json(),
+        )
+        .unwrap();
+        assert_eq!(event["state"], "Exit");
+        assert_eq!(event["exit_code"], 152);
+        let event =
+            serde_json::from_str::<serde_json::Value>(&LifecycleEvent::Paused.to_json()).unwrap();
+        assert_eq!(event["state"], "Paused");
+        assert!(event.get("exit_code").is_none());
+        let event =
+            serde_json::from_str::<serde_json::Value>(&LifecycleEvent::DevicesActivated.to_json())
+                .unwrap();
+        assert_eq!(event["state"], "DevicesActivated");
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("lifecycle.sock");
+        assert!(matches!(
+            connect_sink(&path),
+            Err(LifecycleError::Connect(_, _))
+        ));
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut sinks = vec![connect_sink(&path).unwrap()];
+        let (socket, _) = listener.accept().unwrap();
+
+        let mut fds: [libc::c_int; 2] = [0; 2];
+        // SAFETY: Safe because the array can hold the two fds.
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        // SAFETY: The fds were just created and are owned by nothing else.
+        let pipe_read = unsafe { File::from_raw_fd(fds[0]) };
+        // SAFETY: Same as above.
+        sinks.push(nonblocking_sink(unsafe { File::from_raw_fd(fds[1]) }).unwrap());
+
+        notify_sinks(&mut sinks, LifecycleEvent::Running);
+        for reader in [
+            &mut BufReader::new(socket) as &mut dyn BufRead,
+            &mut BufReader::new(pipe_read),
+        ] {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let event = serde_json::from_str::<serde_json::Value>(&line).unwrap();
+            assert_eq!(event["state"], "Running");
+            assert!(event["timestamp_us"].as_u64().unwrap() > 0);
+        }
+    }
+}
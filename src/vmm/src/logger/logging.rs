@@ -177,7 +177,17 @@ impl Log for Logger {
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        let mut guard = self.0.lock().unwrap();
+        let result = if let Some(file) = &mut guard.target {
+            file.flush()
+        } else {
+            std::io::stdout().flush()
+        };
+        if result.is_err() {
+            METRICS.logger.missed_log_count.inc();
+        }
+    }
 }
 
 /// Strongly typed structure used to describe the logger.
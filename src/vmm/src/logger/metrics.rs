@@ -44,12 +44,15 @@
 //! * Since all metrics start at 0, we implement the `Default` trait via derive for all of them, to
 //!   avoid having to initialize everything by hand.
 //!
-//! The system implements 2 types of metrics:
+//! The system implements 3 types of metrics:
 //! * Shared Incremental Metrics (SharedIncMetrics) - dedicated for the metrics which need a counter
 //! (i.e the number of times an API request failed). These metrics are reset upon flush.
 //! * Shared Store Metrics (SharedStoreMetrics) - are targeted at keeping a persistent value, it is
 //!   not
 //! intended to act as a counter (i.e for measure the process start up time for example).
+//! * Shared Histogram Metrics (SharedHistogramMetrics) - count the recorded values falling in each
+//!   of a fixed set of buckets (i.e the latencies of the block requests), serialized as a JSON
+//!   object keyed by the upper bound of each bucket. These metrics are reset upon flush.
 //!
 //! The current approach for the `SharedIncMetrics` type is to store two values (current and
 //! previous) and compute the delta between them each time we do a flush (i.e by serialization).
@@ -63,20 +66,24 @@
 
 use std::fmt::Debug;
 use std::io::Write;
+use std::marker::PhantomData;
 use std::ops::Deref;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Mutex, OnceLock};
 
+use serde::ser::SerializeMap;
 use serde::{Serialize, Serializer};
 
 use super::FcLineWriter;
 use crate::devices::legacy;
+use crate::devices::tpm::metrics as tpm_metrics;
 use crate::devices::virtio::balloon::metrics as balloon_metrics;
 use crate::devices::virtio::block::virtio::metrics as block_metrics;
+use crate::devices::virtio::crypto::metrics as crypto_metrics;
 use crate::devices::virtio::net::metrics as net_metrics;
 use crate::devices::virtio::rng::metrics as entropy_metrics;
-use crate::devices::virtio::vhost_user_metrics;
 use crate::devices::virtio::vsock::metrics as vsock_metrics;
+use crate::devices::virtio::{queue_metrics, vhost_user_metrics};
 
 /// Static instance used for handling metrics.
 pub static METRICS: Metrics<FirecrackerMetrics, FcLineWriter> =
@@ -570,6 +577,14 @@ pub struct MmdsMetrics {
     pub connections_created: SharedIncMetric,
     /// The number of connections cleaned up by the MMDS TCP handler.
     pub connections_destroyed: SharedIncMetric,
+    /// The number of times the data store was reloaded from the data file.
+    pub data_file_reloads: SharedIncMetric,
+    /// The number of data file reloads that failed because the file was too large.
+    pub data_file_too_large: SharedIncMetric,
+    /// The number of data file reloads that failed because the file was not valid JSON.
+    pub data_file_parse_errors: SharedIncMetric,
+    /// The number of data file reloads that failed for other reasons, e.g. I/O errors.
+    pub data_file_fails: SharedIncMetric,
 }
 impl MmdsMetrics {
     /// Const default construction.
@@ -586,6 +601,10 @@ impl MmdsMetrics {
             tx_frames: SharedIncMetric::new(),
             connections_created: SharedIncMetric::new(),
             connections_destroyed: SharedIncMetric::new(),
+            data_file_reloads: SharedIncMetric::new(),
+            data_file_too_large: SharedIncMetric::new(),
+            data_file_parse_errors: SharedIncMetric::new(),
+            data_file_fails: SharedIncMetric::new(),
         }
     }
 }
@@ -674,6 +693,8 @@ pub struct SignalMetrics {
     pub sighup: SharedStoreMetric,
     /// Number of times that SIGILL was handled.
     pub sigill: SharedStoreMetric,
+    /// Number of times that SIGTERM was handled.
+    pub sigterm: SharedIncMetric,
 }
 impl SignalMetrics {
     /// Const default construction.
@@ -686,6 +707,7 @@ impl SignalMetrics {
             sigpipe: SharedIncMetric::new(),
             sighup: SharedStoreMetric::new(),
             sigill: SharedStoreMetric::new(),
+            sigterm: SharedIncMetric::new(),
         }
     }
 }
@@ -757,6 +779,140 @@ impl LatencyAggregateMetrics {
     }
 }
 
+/// Maximum number of buckets of a [`SharedHistogramMetric`].
+pub const HISTOGRAM_MAX_BUCKETS: usize = 20;
+
+/// Inclusive upper bounds of the buckets of a [`SharedHistogramMetric`].
+pub trait HistogramBuckets {
+    /// Bounds in increasing order. The last one must be `u64::MAX`, so that the last bucket
+    /// counts all the values greater than the previous bound.
+    const BOUNDS: &'static [u64];
+}
+
+/// Buckets for latencies, in microseconds.
+#[derive(Debug)]
+pub struct LatencyBuckets;
+impl HistogramBuckets for LatencyBuckets {
+    const BOUNDS: &'static [u64] = &[
+        1,
+        2,
+        5,
+        10,
+        20,
+        50,
+        100,
+        200,
+        500,
+        1000,
+        2000,
+        5000,
+        10000,
+        20000,
+        50000,
+        100000,
+        u64::MAX,
+    ];
+}
+
+/// Buckets for the number of requests pending in a virtio queue.
+#[derive(Debug)]
+pub struct QueueDepthBuckets;
+impl HistogramBuckets for QueueDepthBuckets {
+    const BOUNDS: &'static [u64] = &[0, 1, 2, 4, 8, 16, 32, 64, 128, 256, u64::MAX];
+}
+
+/// Representation of a histogram, which counts the recorded values falling in each of the
+/// fixed buckets of `B`. The count of each bucket is a [`SharedIncMetric`], so recording a value
+/// is lockless and the counts are reset upon flush.
+#[derive(Debug)]
+pub struct SharedHistogramMetric<B> {
+    counts: [SharedIncMetric; HISTOGRAM_MAX_BUCKETS],
+    buckets: PhantomData<B>,
+}
+
+impl<B: HistogramBuckets> SharedHistogramMetric<B> {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        // Only used to initialize the counts, each one being a copy of it.
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: SharedIncMetric = SharedIncMetric::new();
+        assert!(B::BOUNDS.len() <= HISTOGRAM_MAX_BUCKETS);
+        Self {
+            counts: [ZERO; HISTOGRAM_MAX_BUCKETS],
+            buckets: PhantomData,
+        }
+    }
+
+    /// Counts `value` in the bucket with the lowest bound greater than or equal to it.
+    pub fn record(&self, value: u64) {
+        let bucket = B::BOUNDS.partition_point(|&bound| bound < value);
+        self.counts[bucket].inc();
+    }
+
+    /// Returns the current counts of the buckets.
+    pub fn counts(&self) -> Vec<u64> {
+        self.counts[..B::BOUNDS.len()]
+            .iter()
+            .map(IncMetric::count)
+            .collect()
+    }
+
+    /// Adds the counts of `other` since the last flush to the counts of the histogram.
+    /// Mostly used in process of aggregating per device metrics.
+    pub fn aggregate(&self, other: &Self) {
+        for (count, other) in self.counts.iter().zip(other.counts.iter()) {
+            count.add(other.fetch_diff());
+        }
+    }
+}
+
+impl SharedHistogramMetric<LatencyBuckets> {
+    /// Returns a recorder which counts the time elapsed until it is dropped, in microseconds.
+    pub fn record_latency(&self) -> HistogramRecorder<'_> {
+        HistogramRecorder {
+            start_time: utils::time::get_time_us(utils::time::ClockType::Monotonic),
+            metric: self,
+        }
+    }
+}
+
+impl<B: HistogramBuckets> Default for SharedHistogramMetric<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: HistogramBuckets> Serialize for SharedHistogramMetric<B> {
+    /// Serializes the counts of the buckets keyed by their upper bound, the last one being
+    /// `inf`. As for `SharedIncMetric`, this resets the counts.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(B::BOUNDS.len()))?;
+        for (bound, count) in B::BOUNDS.iter().zip(self.counts.iter()) {
+            if *bound == u64::MAX {
+                map.serialize_entry("inf", count)?;
+            } else {
+                map.serialize_entry(&bound.to_string(), count)?;
+            }
+        }
+        map.end()
+    }
+}
+
+/// Records the latency of an operation in a latency histogram when dropped.
+#[derive(Debug)]
+pub struct HistogramRecorder<'a> {
+    start_time: u64,
+    metric: &'a SharedHistogramMetric<LatencyBuckets>,
+}
+
+impl<'a> Drop for HistogramRecorder<'a> {
+    fn drop(&mut self) {
+        let delta_us =
+            utils::time::get_time_us(utils::time::ClockType::Monotonic) - self.start_time;
+        self.metric.record(delta_us);
+    }
+}
+
 /// Structure provides Metrics specific to VCPUs' mode of functioning.
 /// Sample_count or number of kvm exits for IO and MMIO VM exits are covered by:
 /// `exit_io_in`, `exit_io_out`, `exit_mmio_read` and , `exit_mmio_write`.
@@ -778,6 +934,8 @@ pub struct VcpuMetrics {
     pub exit_mmio_read: SharedIncMetric,
     /// Number of KVM exits for handling MMIO writes.
     pub exit_mmio_write: SharedIncMetric,
+    /// Number of KVM exits for harvesting a full dirty ring.
+    pub exit_dirty_ring_full: SharedIncMetric,
     /// Number of errors during this VCPU's run.
     pub failures: SharedIncMetric,
     /// Provides Min/max/sum for KVM exits handling input IO.
@@ -797,6 +955,7 @@ impl VcpuMetrics {
             exit_io_out: SharedIncMetric::new(),
             exit_mmio_read: SharedIncMetric::new(),
             exit_mmio_write: SharedIncMetric::new(),
+            exit_dirty_ring_full: SharedIncMetric::new(),
             failures: SharedIncMetric::new(),
             exit_io_in_agg: LatencyAggregateMetrics::new(),
             exit_io_out_agg: LatencyAggregateMetrics::new(),
@@ -813,6 +972,24 @@ pub struct VmmMetrics {
     pub device_events: SharedIncMetric,
     /// Metric for signaling a panic has occurred.
     pub panic_count: SharedStoreMetric,
+    /// Number of guest memory pages currently deduplicated by KSM.
+    pub ksm_merging_pages: SharedStoreMetric,
+    /// Number of guest memory pages KSM is tracking for deduplication.
+    pub ksm_rmap_items: SharedStoreMetric,
+    /// Number of event handlers that ran for longer than the event loop budget.
+    pub event_loop_stalls: SharedIncMetric,
+    /// Number of block devices left undrained because the shutdown timeout elapsed.
+    pub shutdown_drain_timeouts: SharedIncMetric,
+    /// Number of guest memory accesses of virtio devices outside of their DMA ranges.
+    pub dma_range_violations: SharedIncMetric,
+    /// Number of secret buffers that could not be locked in memory or excluded from core dumps.
+    pub secret_hardening_fails: SharedIncMetric,
+    /// Number of cgroup pressure stall notifications received.
+    pub cgroup_pressure_events: SharedIncMetric,
+    /// Number of faults on guest memory accesses the devices recovered from.
+    pub guest_memory_faults: SharedIncMetric,
+    /// Number of lifecycle notifications that could not be written to their sinks.
+    pub lifecycle_notify_fails: SharedIncMetric,
 }
 impl VmmMetrics {
     /// Const default construction.
@@ -820,6 +997,15 @@ impl VmmMetrics {
         Self {
             device_events: SharedIncMetric::new(),
             panic_count: SharedStoreMetric::new(),
+            ksm_merging_pages: SharedStoreMetric::new(),
+            ksm_rmap_items: SharedStoreMetric::new(),
+            event_loop_stalls: SharedIncMetric::new(),
+            shutdown_drain_timeouts: SharedIncMetric::new(),
+            dma_range_violations: SharedIncMetric::new(),
+            secret_hardening_fails: SharedIncMetric::new(),
+            cgroup_pressure_events: SharedIncMetric::new(),
+            guest_memory_faults: SharedIncMetric::new(),
+            lifecycle_notify_fails: SharedIncMetric::new(),
         }
     }
 }
@@ -866,10 +1052,13 @@ macro_rules! create_serialize_proxy {
 create_serialize_proxy!(BlockMetricsSerializeProxy, block_metrics);
 create_serialize_proxy!(NetMetricsSerializeProxy, net_metrics);
 create_serialize_proxy!(VhostUserMetricsSerializeProxy, vhost_user_metrics);
+create_serialize_proxy!(QueueMetricsSerializeProxy, queue_metrics);
 create_serialize_proxy!(BalloonMetricsSerializeProxy, balloon_metrics);
 create_serialize_proxy!(EntropyMetricsSerializeProxy, entropy_metrics);
+create_serialize_proxy!(CryptoMetricsSerializeProxy, crypto_metrics);
 create_serialize_proxy!(VsockMetricsSerializeProxy, vsock_metrics);
 create_serialize_proxy!(LegacyDevMetricsSerializeProxy, legacy);
+create_serialize_proxy!(TpmMetricsSerializeProxy, tpm_metrics);
 
 /// Structure storing all metrics while enforcing serialization support on them.
 #[derive(Debug, Default, Serialize)]
@@ -918,8 +1107,17 @@ pub struct FirecrackerMetrics {
     /// Metrics related to virtio-rng entropy device.
     pub entropy_ser: EntropyMetricsSerializeProxy,
     #[serde(flatten)]
+    /// Metrics related to the virtio-crypto device.
+    pub crypto_ser: CryptoMetricsSerializeProxy,
+    #[serde(flatten)]
+    /// Metrics related to the TPM device.
+    pub tpm_ser: TpmMetricsSerializeProxy,
+    #[serde(flatten)]
     /// Vhost-user device related metrics.
     pub vhost_user_ser: VhostUserMetricsSerializeProxy,
+    #[serde(flatten)]
+    /// Virtio queue related metrics.
+    pub queue_ser: QueueMetricsSerializeProxy,
 }
 impl FirecrackerMetrics {
     /// Const default construction.
@@ -944,7 +1142,10 @@ impl FirecrackerMetrics {
             signals: SignalMetrics::new(),
             vsock_ser: VsockMetricsSerializeProxy {},
             entropy_ser: EntropyMetricsSerializeProxy {},
+            crypto_ser: CryptoMetricsSerializeProxy {},
+            tpm_ser: TpmMetricsSerializeProxy {},
             vhost_user_ser: VhostUserMetricsSerializeProxy {},
+            queue_ser: QueueMetricsSerializeProxy {},
         }
     }
 }
@@ -1028,6 +1229,36 @@ mod tests {
         assert_eq!(1, m1.fetch());
     }
 
+    #[test]
+    fn test_shared_histogram_metric() {
+        let m1 = SharedHistogramMetric::<QueueDepthBuckets>::new();
+        for value in [0, 1, 3, 4, 256, 1000] {
+            m1.record(value);
+        }
+        assert_eq!(m1.counts(), [1, 1, 0, 2, 0, 0, 0, 0, 0, 1, 1]);
+
+        let m2 = SharedHistogramMetric::<QueueDepthBuckets>::new();
+        m2.record(2);
+        m2.aggregate(&m1);
+        assert_eq!(m2.counts(), [1, 1, 1, 2, 0, 0, 0, 0, 0, 1, 1]);
+
+        // Serializing resets the counts.
+        assert_eq!(
+            serde_json::to_string(&m2).unwrap(),
+            "{\"0\":1,\"1\":1,\"2\":1,\"4\":2,\"8\":0,\"16\":0,\"32\":0,\"64\":0,\"128\":0,\"256\"\
+             :1,\"inf\":1}"
+        );
+        assert_eq!(
+            serde_json::to_string(&m2).unwrap(),
+            "{\"0\":0,\"1\":0,\"2\":0,\"4\":0,\"8\":0,\"16\":0,\"32\":0,\"64\":0,\"128\":0,\"256\"\
+             :0,\"inf\":0}"
+        );
+
+        let m3 = SharedHistogramMetric::<LatencyBuckets>::new();
+        drop(m3.record_latency());
+        assert_eq!(m3.counts().iter().sum::<u64>(), 1);
+    }
+
     #[test]
     fn test_serialize() {
         let s = serde_json::to_string(&FirecrackerMetrics::default());
@@ -15,8 +15,9 @@ pub use logging::{
     DEFAULT_INSTANCE_ID, DEFAULT_LEVEL, INSTANCE_ID, LOGGER,
 };
 pub use metrics::{
-    IncMetric, LatencyAggregateMetrics, MetricsError, ProcessTimeReporter, SharedIncMetric,
-    SharedStoreMetric, StoreMetric, METRICS,
+    IncMetric, LatencyAggregateMetrics, LatencyBuckets, MetricsError, ProcessTimeReporter,
+    QueueDepthBuckets, SharedHistogramMetric, SharedIncMetric, SharedStoreMetric, StoreMetric,
+    METRICS,
 };
 
 /// Alias for `std::io::LineWriter<std::fs::File>`.
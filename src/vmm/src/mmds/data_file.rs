@@ -0,0 +1,257 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ffi::{CString, OsString};
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::FromRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use utils::epoll::EventSet;
+
+use crate::logger::{error, warn, IncMetric, METRICS};
+use crate::mmds::data_store::{Mmds, MmdsDatastoreError};
+
+/// Errors associated with the MMDS data file.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum MmdsDataFileError {
+    /// The MMDS data file path does not name a file: {0:?}
+    InvalidPath(PathBuf),
+    /// Failed to watch the MMDS data file: {0}
+    Watch(io::Error),
+    /// Failed to read the MMDS data file: {0}
+    Read(io::Error),
+    /// The MMDS data file is larger than the data store limit of {0} bytes.
+    TooLarge(usize),
+    /// The MMDS data file is not valid JSON: {0}
+    Parse(serde_json::Error),
+    /// Failed to store the content of the MMDS data file: {0}
+    DataStore(MmdsDatastoreError),
+}
+
+/// Size of the `struct inotify_event` header preceding the name of each event.
+const INOTIFY_EVENT_SIZE: usize = std::mem::size_of::<libc::inotify_event>();
+
+/// Reloads the MMDS data store from a JSON file whenever the file is written or replaced.
+///
+/// The directory of the file is watched rather than the file itself, so that the file can be
+/// replaced atomically by renaming another file over it.
+#[derive(Debug)]
+pub struct MmdsDataFile {
+    path: PathBuf,
+    file_name: OsString,
+    inotify: File,
+    mmds: Arc<Mutex<Mmds>>,
+}
+
+impl MmdsDataFile {
+    /// Starts watching the file at `path`, and loads its content in the data store if it exists.
+    pub fn new(path: PathBuf, mmds: Arc<Mutex<Mmds>>) -> Result<Self, MmdsDataFileError> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| MmdsDataFileError::InvalidPath(path.clone()))?
+            .to_owned();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|_| MmdsDataFileError::InvalidPath(path.clone()))?;
+
+        // SAFETY: Safe because the call has no memory side effects.
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(MmdsDataFileError::Watch(io::Error::last_os_error()));
+        }
+        // SAFETY: The file descriptor was just created and is owned by nothing else.
+        let inotify = unsafe { File::from_raw_fd(fd) };
+        // SAFETY: Safe because the path is a valid C string.
+        let ret = unsafe {
+            libc::inotify_add_watch(
+                inotify.as_raw_fd(),
+                dir.as_ptr(),
+                libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO,
+            )
+        };
+        if ret < 0 {
+            return Err(MmdsDataFileError::Watch(io::Error::last_os_error()));
+        }
+
+        let data_file = Self {
+            path,
+            file_name,
+            inotify,
+            mmds,
+        };
+        // The file may be created later, once the microVM runs.
+        match data_file.reload() {
+            Err(MmdsDataFileError::Read(err)) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }?;
+        Ok(data_file)
+    }
+
+    /// Replaces the content of the data store by the content of the file.
+    fn reload(&self) -> Result<(), MmdsDataFileError> {
+        let limit = self.mmds.lock().expect("Poisoned lock").data_store_limit();
+        let mut content = Vec::new();
+        File::open(&self.path)
+            .and_then(|file| {
+                // One byte more than the limit is read to detect larger files.
+                file.take(u64::try_from(limit).unwrap_or(u64::MAX).saturating_add(1))
+                    .read_to_end(&mut content)
+            })
+            .map_err(MmdsDataFileError::Read)?;
+        if content.len() > limit {
+            return Err(MmdsDataFileError::TooLarge(limit));
+        }
+        let data = serde_json::from_slice(&content).map_err(MmdsDataFileError::Parse)?;
+        self.mmds
+            .lock()
+            .expect("Poisoned lock")
+            .put_data(data)
+            .map_err(MmdsDataFileError::DataStore)
+    }
+
+    /// Drains the pending inotify events, and returns whether one of them concerns the file.
+    fn file_changed(&mut self) -> bool {
+        let mut changed = false;
+        // Large enough for at least one event with the longest file name.
+        let mut buf = [0u8; 4096];
+        loop {
+            let len = match self.inotify.read(&mut buf) {
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    error!("mmds: Failed to read the data file events: {err}");
+                    METRICS.mmds.data_file_fails.inc();
+                    break;
+                }
+            };
+            let mut offset = 0;
+            while offset + INOTIFY_EVENT_SIZE <= len {
+                // SAFETY: The kernel writes whole events, and the header fits in the buffer.
+                let event = unsafe {
+                    std::ptr::read_unaligned(buf[offset..].as_ptr().cast::<libc::inotify_event>())
+                };
+                let name_start = offset + INOTIFY_EVENT_SIZE;
+                let name_end = (name_start + event.len as usize).min(len);
+                // The name is padded with null bytes.
+                let name = buf[name_start..name_end].split(|&b| b == 0).next();
+                changed |= name == Some(self.file_name.as_bytes());
+                offset = name_end;
+            }
+        }
+        changed
+    }
+
+    fn process_file_change(&mut self) {
+        if !self.file_changed() {
+            return;
+        }
+        match self.reload() {
+            Ok(()) => METRICS.mmds.data_file_reloads.inc(),
+            Err(err) => {
+                match err {
+                    MmdsDataFileError::TooLarge(_)
+                    | MmdsDataFileError::DataStore(MmdsDatastoreError::DataStoreLimitExceeded) => {
+                        METRICS.mmds.data_file_too_large.inc()
+                    }
+                    MmdsDataFileError::Parse(_) => METRICS.mmds.data_file_parse_errors.inc(),
+                    _ => METRICS.mmds.data_file_fails.inc(),
+                }
+                warn!("mmds: Failed to reload the data file, keeping the previous data: {err}");
+            }
+        }
+    }
+}
+
+impl MutEventSubscriber for MmdsDataFile {
+    fn process(&mut self, events: Events, _ops: &mut EventOps) {
+        let event_set = events.event_set();
+        if !event_set.contains(EventSet::IN) || events.fd() != self.inotify.as_raw_fd() {
+            warn!("mmds: Received unknown event: {event_set:?}");
+            return;
+        }
+        self.process_file_change();
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(&self.inotify, EventSet::IN)) {
+            error!("mmds: Failed to register the data file event: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use utils::tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_mmds_data_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("metadata.json");
+        let mmds = Arc::new(Mutex::new(Mmds::default_with_limit(64)));
+
+        // The file doesn't need to exist when the watch starts.
+        let mut data_file = MmdsDataFile::new(path.clone(), mmds.clone()).unwrap();
+        assert!(!data_file.file_changed());
+        assert_eq!(mmds.lock().unwrap().data_store_value(), json!(null));
+
+        // Files written in place, or renamed over the watched file, are reloaded.
+        std::fs::write(&path, r#"{"key": "value"}"#).unwrap();
+        data_file.process_file_change();
+        assert_eq!(
+            mmds.lock().unwrap().data_store_value(),
+            json!({"key": "value"})
+        );
+        let tmp_path = dir.as_path().join("metadata.json.tmp");
+        std::fs::write(&tmp_path, r#"{"key": "other"}"#).unwrap();
+        std::fs::rename(&tmp_path, &path).unwrap();
+        let reloads = METRICS.mmds.data_file_reloads.count();
+        data_file.process_file_change();
+        assert_eq!(METRICS.mmds.data_file_reloads.count(), reloads + 1);
+        assert_eq!(
+            mmds.lock().unwrap().data_store_value(),
+            json!({"key": "other"})
+        );
+
+        // Other files of the directory are ignored.
+        std::fs::write(dir.as_path().join("other.json"), "{}").unwrap();
+        assert!(!data_file.file_changed());
+
+        // Invalid or too large files leave the data store untouched.
+        let parse_errors = METRICS.mmds.data_file_parse_errors.count();
+        std::fs::write(&path, r#"{"key": "#).unwrap();
+        data_file.process_file_change();
+        assert_eq!(
+            METRICS.mmds.data_file_parse_errors.count(),
+            parse_errors + 1
+        );
+        let too_large = METRICS.mmds.data_file_too_large.count();
+        std::fs::write(&path, format!(r#"{{"key": "{}"}}"#, "a".repeat(64))).unwrap();
+        data_file.process_file_change();
+        assert_eq!(METRICS.mmds.data_file_too_large.count(), too_large + 1);
+        assert_eq!(
+            mmds.lock().unwrap().data_store_value(),
+            json!({"key": "other"})
+        );
+
+        // An invalid file fails the watch when it starts.
+        assert!(matches!(
+            MmdsDataFile::new(path, mmds.clone()),
+            Err(MmdsDataFileError::TooLarge(64))
+        ));
+        assert!(matches!(
+            MmdsDataFile::new(PathBuf::from("/"), mmds),
+            Err(MmdsDataFileError::InvalidPath(_))
+        ));
+    }
+}
@@ -140,6 +140,11 @@ impl Mmds {
             .and_then(|ta| ta.generate_token_secret(ttl_seconds))
     }
 
+    /// Returns the MMDS data store limit.
+    pub fn data_store_limit(&self) -> usize {
+        self.data_store_limit
+    }
+
     /// set MMDS data store limit to `data_store_limit`
     pub fn set_data_store_limit(&mut self, data_store_limit: usize) {
         self.data_store_limit = data_store_limit;
@@ -1,6 +1,8 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+/// MMDS data file
+pub mod data_file;
 /// MMDS data store
 pub mod data_store;
 /// MMDS network stack
@@ -98,8 +100,13 @@ fn sanitize_uri(mut uri: String) -> String {
     uri
 }
 
-/// Build a response for `request` and return response based on MMDS version
-pub fn convert_to_response(mmds: Arc<Mutex<Mmds>>, request: Request) -> Response {
+/// Build a response for `request` and return response based on MMDS version. The data is looked up
+/// in the value of the `namespace` top-level key of the data store, if given.
+pub fn convert_to_response(
+    mmds: Arc<Mutex<Mmds>>,
+    namespace: Option<&str>,
+    request: Request,
+) -> Response {
     let uri = request.uri().get_abs_path();
     if uri.is_empty() {
         return build_response(
@@ -112,15 +119,15 @@ pub fn convert_to_response(mmds: Arc<Mutex<Mmds>>, request: Request) -> Response
     let mut mmds_guard = mmds.lock().expect("Poisoned lock");
 
     match mmds_guard.version() {
-        MmdsVersion::V1 => respond_to_request_mmdsv1(&mmds_guard, request),
-        MmdsVersion::V2 => respond_to_request_mmdsv2(&mut mmds_guard, request),
+        MmdsVersion::V1 => respond_to_request_mmdsv1(&mmds_guard, namespace, request),
+        MmdsVersion::V2 => respond_to_request_mmdsv2(&mut mmds_guard, namespace, request),
     }
 }
 
-fn respond_to_request_mmdsv1(mmds: &Mmds, request: Request) -> Response {
+fn respond_to_request_mmdsv1(mmds: &Mmds, namespace: Option<&str>, request: Request) -> Response {
     // Allow only GET requests.
     match request.method() {
-        Method::Get => respond_to_get_request_unchecked(mmds, request),
+        Method::Get => respond_to_get_request_unchecked(mmds, namespace, request),
         _ => {
             let mut response = build_response(
                 request.http_version(),
@@ -133,7 +140,11 @@ fn respond_to_request_mmdsv1(mmds: &Mmds, request: Request) -> Response {
     }
 }
 
-fn respond_to_request_mmdsv2(mmds: &mut Mmds, request: Request) -> Response {
+fn respond_to_request_mmdsv2(
+    mmds: &mut Mmds,
+    namespace: Option<&str>,
+    request: Request,
+) -> Response {
     // Fetch custom headers from request.
     let token_headers = match TokenHeaders::try_from(request.headers.custom_entries()) {
         Ok(token_headers) => token_headers,
@@ -148,7 +159,7 @@ fn respond_to_request_mmdsv2(mmds: &mut Mmds, request: Request) -> Response {
 
     // Allow only GET and PUT requests.
     match request.method() {
-        Method::Get => respond_to_get_request_checked(mmds, request, token_headers),
+        Method::Get => respond_to_get_request_checked(mmds, namespace, request, token_headers),
         Method::Put => respond_to_put_request(mmds, request, token_headers),
         _ => {
             let mut response = build_response(
@@ -165,6 +176,7 @@ fn respond_to_request_mmdsv2(mmds: &mut Mmds, request: Request) -> Response {
 
 fn respond_to_get_request_checked(
     mmds: &Mmds,
+    namespace: Option<&str>,
     request: Request,
     token_headers: TokenHeaders,
 ) -> Response {
@@ -183,7 +195,7 @@ fn respond_to_get_request_checked(
 
     // Validate MMDS token.
     match mmds.is_valid_token(token) {
-        Ok(true) => respond_to_get_request_unchecked(mmds, request),
+        Ok(true) => respond_to_get_request_unchecked(mmds, namespace, request),
         Ok(false) => build_response(
             request.http_version(),
             StatusCode::Unauthorized,
@@ -193,12 +205,19 @@ fn respond_to_get_request_checked(
     }
 }
 
-fn respond_to_get_request_unchecked(mmds: &Mmds, request: Request) -> Response {
+fn respond_to_get_request_unchecked(
+    mmds: &Mmds,
+    namespace: Option<&str>,
+    request: Request,
+) -> Response {
     let uri = request.uri().get_abs_path();
 
     // The data store expects a strict json path, so we need to
     // sanitize the URI.
-    let json_path = sanitize_uri(uri.to_string());
+    let json_path = match namespace {
+        Some(namespace) => sanitize_uri(format!("/{}{}", namespace, uri)),
+        None => sanitize_uri(uri.to_string()),
+    };
 
     match mmds.get_value(json_path, request.headers.accept().into()) {
         Ok(response_body) => build_response(
@@ -384,7 +403,7 @@ mod tests {
         expected_response.set_body(Body::new(
             VmmMmdsError::ResourceNotFound(String::from("/invalid")).to_string(),
         ));
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response, expected_response);
 
         // Test NotImplemented.
@@ -393,7 +412,7 @@ mod tests {
         let mut expected_response = Response::new(Version::Http11, StatusCode::NotImplemented);
         let body = "Cannot retrieve value. The value has an unsupported type.".to_string();
         expected_response.set_body(Body::new(body));
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response, expected_response);
 
         // Test not allowed HTTP Method.
@@ -405,7 +424,7 @@ mod tests {
                 Response::new(Version::Http10, StatusCode::MethodNotAllowed);
             expected_response.set_body(Body::new(VmmMmdsError::MethodNotAllowed.to_string()));
             expected_response.allow_method(Method::Get);
-            let actual_response = convert_to_response(mmds.clone(), request);
+            let actual_response = convert_to_response(mmds.clone(), None, request);
             assert_eq!(actual_response, expected_response);
         }
 
@@ -414,7 +433,7 @@ mod tests {
         let request = Request::try_from(request_bytes, None).unwrap();
         let mut expected_response = Response::new(Version::Http10, StatusCode::BadRequest);
         expected_response.set_body(Body::new(VmmMmdsError::InvalidURI.to_string()));
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response, expected_response);
 
         // Test invalid custom header value is ignored when V1 is configured.
@@ -424,7 +443,7 @@ mod tests {
         let request = Request::try_from(request_bytes, None).unwrap();
         let mut expected_response = Response::new(Version::Http10, StatusCode::OK);
         expected_response.set_body(Body::new("\"John\""));
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response, expected_response);
 
         // Test Ok path.
@@ -435,8 +454,48 @@ mod tests {
         let mut body = get_json_data().to_string();
         body.retain(|c| !c.is_whitespace());
         expected_response.set_body(Body::new(body));
-        let actual_response = convert_to_response(mmds, request);
+        let actual_response = convert_to_response(mmds, None, request);
+        assert_eq!(actual_response, expected_response);
+    }
+
+    #[test]
+    fn test_respond_to_request_in_namespace() {
+        let mmds = populate_mmds();
+        mmds.lock()
+            .expect("Poisoned lock")
+            .set_version(MmdsVersion::V1)
+            .unwrap();
+
+        // The value of the namespace key is the root of the data.
+        let request_bytes = b"GET http://169.254.169.254/first HTTP/1.0\r\n\r\n";
+        let request = Request::try_from(request_bytes, None).unwrap();
+        let mut expected_response = Response::new(Version::Http10, StatusCode::OK);
+        expected_response.set_body(Body::new("John"));
+        let actual_response = convert_to_response(mmds.clone(), Some("name"), request);
+        assert_eq!(actual_response, expected_response);
+
+        let request_bytes = b"GET http://169.254.169.254/ HTTP/1.0\r\n\r\n";
+        let request = Request::try_from(request_bytes, None).unwrap();
+        let mut expected_response = Response::new(Version::Http10, StatusCode::OK);
+        expected_response.set_body(Body::new("first\nsecond"));
+        let actual_response = convert_to_response(mmds.clone(), Some("name"), request);
         assert_eq!(actual_response, expected_response);
+
+        // The data outside of the namespace is not reachable.
+        let request_bytes = b"GET http://169.254.169.254/age HTTP/1.0\r\n\r\n";
+        let request = Request::try_from(request_bytes, None).unwrap();
+        let mut expected_response = Response::new(Version::Http10, StatusCode::NotFound);
+        expected_response.set_body(Body::new(
+            VmmMmdsError::ResourceNotFound(String::from("/age")).to_string(),
+        ));
+        let actual_response = convert_to_response(mmds.clone(), Some("name"), request);
+        assert_eq!(actual_response, expected_response);
+
+        // A namespace missing from the data store has no data.
+        let request_bytes = b"GET http://169.254.169.254/ HTTP/1.0\r\n\r\n";
+        let request = Request::try_from(request_bytes, None).unwrap();
+        let actual_response = convert_to_response(mmds, Some("tenant"), request);
+        assert_eq!(actual_response.status(), StatusCode::NotFound);
     }
 
     #[test]
@@ -461,7 +520,7 @@ mod tests {
         expected_response.set_body(Body::new(VmmMmdsError::MethodNotAllowed.to_string()));
         expected_response.allow_method(Method::Get);
         expected_response.allow_method(Method::Put);
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response, expected_response);
 
         // Test invalid value for custom header.
@@ -475,7 +534,7 @@ mod tests {
              Value:application/json"
                 .to_string(),
         ));
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response, expected_response);
 
         // Test PUT requests.
@@ -487,7 +546,7 @@ mod tests {
         expected_response.set_body(Body::new(
             "Invalid header. Reason: Unsupported header name. Key: X-Forwarded-For".to_string(),
         ));
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response, expected_response);
 
         // Test invalid path.
@@ -498,7 +557,7 @@ mod tests {
         expected_response.set_body(Body::new(
             VmmMmdsError::ResourceNotFound(String::from("/token")).to_string(),
         ));
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response, expected_response);
 
         // Test invalid lifetime values for token.
@@ -517,7 +576,7 @@ mod tests {
                 invalid_value, MIN_TOKEN_TTL_SECONDS, MAX_TOKEN_TTL_SECONDS
             );
             expected_response.set_body(Body::new(error_msg));
-            let actual_response = convert_to_response(mmds.clone(), request);
+            let actual_response = convert_to_response(mmds.clone(), None, request);
             assert_eq!(actual_response, expected_response);
         }
 
@@ -526,14 +585,14 @@ mod tests {
         let request = Request::try_from(request_bytes, None).unwrap();
         let mut expected_response = Response::new(Version::Http10, StatusCode::BadRequest);
         expected_response.set_body(Body::new(VmmMmdsError::NoTtlProvided.to_string()));
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response, expected_response);
 
         // Test valid PUT.
         let request_bytes = b"PUT http://169.254.169.254/latest/api/token HTTP/1.0\r\n\
                                     X-metadata-token-ttl-seconds: 60\r\n\r\n";
         let request = Request::try_from(request_bytes, None).unwrap();
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response.status(), StatusCode::OK);
         assert_eq!(actual_response.content_type(), MediaType::PlainText);
 
@@ -549,7 +608,7 @@ mod tests {
         let mut body = get_json_data().to_string();
         body.retain(|c| !c.is_whitespace());
         expected_response.set_body(Body::new(body));
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response, expected_response);
 
         // Test GET request towards unsupported value type.
@@ -561,7 +620,7 @@ mod tests {
         let mut expected_response = Response::new(Version::Http11, StatusCode::NotImplemented);
         let body = "Cannot retrieve value. The value has an unsupported type.".to_string();
         expected_response.set_body(Body::new(body));
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response, expected_response);
 
         // Test GET request towards invalid resource.
@@ -574,7 +633,7 @@ mod tests {
         expected_response.set_body(Body::new(
             VmmMmdsError::ResourceNotFound(String::from("/invalid")).to_string(),
         ));
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response, expected_response);
 
         // Test GET request without token should return Unauthorized status code.
@@ -582,7 +641,7 @@ mod tests {
         let request = Request::try_from(request_bytes, None).unwrap();
         let mut expected_response = Response::new(Version::Http10, StatusCode::Unauthorized);
         expected_response.set_body(Body::new(VmmMmdsError::NoTokenProvided.to_string()));
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response, expected_response);
 
         // Test GET request with invalid token should return Unauthorized status code.
@@ -591,14 +650,14 @@ mod tests {
         let request = Request::try_from(request_bytes, None).unwrap();
         let mut expected_response = Response::new(Version::Http10, StatusCode::Unauthorized);
         expected_response.set_body(Body::new(VmmMmdsError::InvalidToken.to_string()));
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response, expected_response);
 
         // Create a new MMDS token that expires in one second.
         let request_bytes = b"PUT http://169.254.169.254/latest/api/token HTTP/1.0\r\n\
                                     X-metadata-token-ttl-seconds: 1\r\n\r\n";
         let request = Request::try_from(request_bytes, None).unwrap();
-        let actual_response = convert_to_response(mmds.clone(), request);
+        let actual_response = convert_to_response(mmds.clone(), None, request);
         assert_eq!(actual_response.status(), StatusCode::OK);
         assert_eq!(actual_response.content_type(), MediaType::PlainText);
 
@@ -615,7 +674,7 @@ mod tests {
             let request = Request::try_from(request_bytes.as_bytes(), None).unwrap();
             let mut expected_response = Response::new(Version::Http10, StatusCode::Unauthorized);
             expected_response.set_body(Body::new(VmmMmdsError::InvalidToken.to_string()));
-            let actual_response = convert_to_response(mmds.clone(), request);
+            let actual_response = convert_to_response(mmds.clone(), None, request);
             assert_eq!(actual_response, expected_response);
 
             // Wait for the second token to expire.
@@ -74,6 +74,9 @@ pub struct MmdsNetworkStack {
     pub(crate) tcp_handler: TcpIPv4Handler,
     // Data store reference shared across all MmdsNetworkStack instances.
     pub mmds: Arc<Mutex<Mmds>>,
+    // Top-level key of the data store whose value is served as the root of the data, if the
+    // network device only gets a view of the data store.
+    pub namespace: Option<String>,
 }
 
 impl MmdsNetworkStack {
@@ -97,6 +100,7 @@ impl MmdsNetworkStack {
                 max_pending_resets,
             ),
             mmds,
+            namespace: None,
         }
     }
 
@@ -124,6 +128,14 @@ impl MmdsNetworkStack {
         self.ipv4_addr
     }
 
+    pub fn set_namespace(&mut self, namespace: Option<String>) {
+        self.namespace = namespace;
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
     pub fn default_ipv4_addr() -> Ipv4Addr {
         Ipv4Addr::from(DEFAULT_IPV4_ADDR)
     }
@@ -188,8 +200,9 @@ impl MmdsNetworkStack {
                 // each MmdsNetworkStack routes packets for only one network device.
                 self.remote_mac_addr = eth.src_mac();
                 let mmds_instance = self.mmds.clone();
+                let namespace = self.namespace.as_deref();
                 match &mut self.tcp_handler.receive_packet(&ip, move |request| {
-                    super::convert_to_response(mmds_instance, request)
+                    super::convert_to_response(mmds_instance, namespace, request)
                 }) {
                     Ok(event) => {
                         METRICS.mmds.rx_count.inc();
@@ -21,6 +21,8 @@ pub struct MmdsNetworkStackState {
     tcp_port: u16,
     max_connections: usize,
     max_pending_resets: usize,
+    /// Namespace of the data store exposed through the network stack.
+    pub namespace: Option<String>,
 }
 
 impl Persist<'_> for MmdsNetworkStack {
@@ -38,6 +40,7 @@ impl Persist<'_> for MmdsNetworkStack {
             tcp_port: self.tcp_handler.local_port(),
             max_connections: self.tcp_handler.max_connections(),
             max_pending_resets: self.tcp_handler.max_pending_resets(),
+            namespace: self.namespace.clone(),
         }
     }
 
@@ -45,14 +48,16 @@ impl Persist<'_> for MmdsNetworkStack {
         mmds: Self::ConstructorArgs,
         state: &Self::State,
     ) -> std::result::Result<Self, Self::Error> {
-        Ok(MmdsNetworkStack::new(
+        let mut ns = MmdsNetworkStack::new(
             MacAddr::from_bytes_unchecked(&state.mac_addr),
             Ipv4Addr::from(state.ipv4_addr),
             state.tcp_port,
             std::num::NonZeroUsize::new(state.max_connections).unwrap(),
             std::num::NonZeroUsize::new(state.max_pending_resets).unwrap(),
             mmds,
-        ))
+        );
+        ns.set_namespace(state.namespace.clone());
+        Ok(ns)
     }
 }
 
@@ -64,7 +69,9 @@ mod tests {
 
     #[test]
     fn test_persistence() {
-        let ns = MmdsNetworkStack::new_with_defaults(None, Arc::new(Mutex::new(Mmds::default())));
+        let mut ns =
+            MmdsNetworkStack::new_with_defaults(None, Arc::new(Mutex::new(Mmds::default())));
+        ns.set_namespace(Some("tenant".to_string()));
 
         let mut mem = vec![0; 4096];
 
@@ -78,6 +85,7 @@ mod tests {
 
         assert_eq!(restored_ns.mac_addr, ns.mac_addr);
         assert_eq!(restored_ns.ipv4_addr, ns.ipv4_addr);
+        assert_eq!(restored_ns.namespace(), Some("tenant"));
         assert_eq!(
             restored_ns.tcp_handler.local_port(),
             ns.tcp_handler.local_port()
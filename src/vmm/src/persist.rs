@@ -3,25 +3,30 @@
 
 //! Defines state structures for saving/restoring a Firecracker microVM.
 
+use std::collections::HashSet;
 use std::fmt::Debug;
 use std::fs::{File, OpenOptions};
-use std::io::{self, Write};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::os::unix::io::AsRawFd;
 use std::os::unix::net::UnixStream;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 
+use aws_lc_rs::digest;
 use seccompiler::BpfThreadMap;
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use userfaultfd::{FeatureFlags, Uffd, UffdBuilder};
 use utils::sock_ctrl_msg::ScmSocket;
 use utils::u64_to_usize;
+use vm_memory::bitmap::BitmapSlice;
+use vm_memory::{VolatileMemoryError, VolatileSlice, WriteVolatile};
 
 #[cfg(target_arch = "aarch64")]
 use crate::arch::aarch64::vcpu::{get_manufacturer_id_from_host, get_manufacturer_id_from_state};
 use crate::builder::{self, BuildMicrovmFromSnapshotError};
-use crate::cpu_config::templates::StaticCpuTemplate;
+use crate::cpu_config::fingerprint::{CpuFingerprint, CpuFingerprintError};
+use crate::cpu_config::templates::{CpuTemplateType, StaticCpuTemplate};
 #[cfg(target_arch = "x86_64")]
 use crate::cpu_config::x86_64::cpuid::common::get_vendor_id_from_host;
 #[cfg(target_arch = "x86_64")]
@@ -29,17 +34,36 @@ use crate::cpu_config::x86_64::cpuid::CpuidTrait;
 #[cfg(target_arch = "x86_64")]
 use crate::device_manager::persist::ACPIDeviceManagerState;
 use crate::device_manager::persist::{DevicePersistError, DeviceStates};
+use crate::devices::replay;
+use crate::devices::virtio::block::persist::BlockState;
+use crate::devices::virtio::block::virtio::SECTOR_SHIFT;
+use crate::devices::virtio::rng::device::ENTROPY_DEV_ID;
+use crate::devices::virtio::rng::Entropy;
+use crate::devices::virtio::vsock::persist::VsockBackendState;
+use crate::devices::virtio::vsock::VSOCK_DEV_ID;
+use crate::devices::virtio::TYPE_RNG;
 use crate::logger::{info, warn};
 use crate::resources::VmResources;
-use crate::snapshot::Snapshot;
+use crate::snapshot::encryption::{
+    is_sealed_state, FdKeyProvider, OpeningReader, SealingWriter, SnapshotEncryptionError,
+    SnapshotKey, SnapshotKeyProvider, CHUNK_SIZE,
+};
+use crate::snapshot::manifest::{PageHashWriter, PageManifest};
+use crate::snapshot::sparse::SparseWriter;
+use crate::snapshot::{Snapshot, SnapshotError};
 use crate::vmm_config::boot_source::BootSourceConfig;
 use crate::vmm_config::instance_info::InstanceInfo;
-use crate::vmm_config::machine_config::{HugePageConfig, MachineConfigUpdate, VmConfigError};
+use crate::vmm_config::machine_config::{
+    HugePageConfig, MachineConfigUpdate, MemoryLayoutConfig, VcpuThreadConfig, VirtioFeaturePolicy,
+    VmConfigError,
+};
 use crate::vmm_config::snapshot::{
-    CreateSnapshotParams, LoadSnapshotParams, MemBackendType, SnapshotType,
+    CreateSnapshotParams, LoadSnapshotParams, MemBackendType, SnapshotEncryptionConfig,
+    SnapshotOverrides, SnapshotType,
 };
 use crate::vstate::memory::{
-    GuestMemory, GuestMemoryExtension, GuestMemoryMmap, GuestMemoryState, MemoryError,
+    Bytes, GuestAddress, GuestMemory, GuestMemoryExtension, GuestMemoryMmap,
+    GuestMemoryRegionState, GuestMemoryState, MemoryError,
 };
 use crate::vstate::vcpu::{VcpuSendEventError, VcpuState};
 use crate::vstate::vm::VmState;
@@ -58,6 +82,20 @@ pub struct VmInfo {
     pub boot_source: BootSourceConfig,
     /// Huge page configuration
     pub huge_pages: HugePageConfig,
+    /// Layout of the MMIO address space
+    pub memory_layout: MemoryLayoutConfig,
+    /// Virtio features withheld from the guest drivers
+    pub virtio_feature_policy: VirtioFeaturePolicy,
+    /// Whether a custom CPU template was applied
+    pub custom_cpu_template: bool,
+    /// Fingerprint of the host CPU
+    pub host_cpu: CpuFingerprint,
+    /// Host scheduling settings of the vCPU threads
+    pub vcpu_threads: Vec<VcpuThreadConfig>,
+    /// Whether a virtual PMU is exposed to the guest
+    pub pmu: bool,
+    /// Version of the Firecracker that created the snapshot
+    pub firecracker_version: String,
 }
 
 impl From<&VmResources> for VmInfo {
@@ -68,6 +106,16 @@ impl From<&VmResources> for VmInfo {
             cpu_template: StaticCpuTemplate::from(&value.vm_config.cpu_template),
             boot_source: value.boot_source_config().clone(),
             huge_pages: value.vm_config.huge_pages,
+            memory_layout: value.vm_config.memory_layout,
+            virtio_feature_policy: value.vm_config.virtio_feature_policy.clone(),
+            custom_cpu_template: matches!(
+                value.vm_config.cpu_template,
+                Some(CpuTemplateType::Custom(_))
+            ),
+            host_cpu: CpuFingerprint::host(),
+            vcpu_threads: value.vm_config.vcpu_threads.clone(),
+            pmu: value.vm_config.pmu,
+            firecracker_version: String::new(),
         }
     }
 }
@@ -88,6 +136,148 @@ pub struct MicrovmState {
     /// ACPI devices state.
     #[cfg(target_arch = "x86_64")]
     pub acpi_dev_state: ACPIDeviceManagerState,
+    /// Integrity digests of the snapshot sections.
+    pub digests: SnapshotDigests,
+}
+
+/// SHA-256 digest.
+pub type Sha256Digest = [u8; digest::SHA256_OUTPUT_LEN];
+
+/// SHA-256 digest of a guest memory region, as stored in the memory file.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct MemoryRegionDigest {
+    /// Offset of the region in the memory file.
+    pub offset: u64,
+    /// Region size.
+    pub size: u64,
+    /// Digest of the region contents.
+    pub digest: Sha256Digest,
+}
+
+/// SHA-256 digests of the snapshot sections, checked when loading a snapshot with `verify` set.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct SnapshotDigests {
+    /// Digest of the serialized microVM state, computed while this field is zeroed.
+    pub vmstate: Sha256Digest,
+    /// Digests of the guest memory regions in the memory file, only computed for full
+    /// snapshots that are not encrypted.
+    pub memory: Option<Vec<MemoryRegionDigest>>,
+}
+
+/// Writer that feeds everything written to it into a SHA-256 digest.
+struct Sha256Writer(digest::Context);
+
+impl Sha256Writer {
+    fn new() -> Self {
+        Sha256Writer(digest::Context::new(&digest::SHA256))
+    }
+
+    fn finish(self) -> Sha256Digest {
+        let mut out = Sha256Digest::default();
+        out.copy_from_slice(self.0.finish().as_ref());
+        out
+    }
+}
+
+impl Write for Sha256Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Computes the digest of the serialized microVM state, excluding its own stored digest.
+fn vmstate_digest(microvm_state: &mut MicrovmState) -> Result<Sha256Digest, SnapshotError> {
+    let stored = std::mem::take(&mut microvm_state.digests.vmstate);
+    let mut writer = Sha256Writer::new();
+    let res = Snapshot::serialize(&mut writer, microvm_state);
+    microvm_state.digests.vmstate = stored;
+    res.map(|()| writer.finish())
+}
+
+/// Computes the digest of `size` bytes of `file`, starting at `offset`.
+fn file_range_digest(file: &mut File, offset: u64, size: u64) -> io::Result<Sha256Digest> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut writer = Sha256Writer::new();
+    let copied = io::copy(&mut io::Read::take(&mut *file, size), &mut writer)?;
+    if copied != size {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    Ok(writer.finish())
+}
+
+/// Writer computing the digests of the guest memory regions written to it.
+///
+/// The regions are hashed while they are dumped, so computing their digests does not require
+/// reading the memory file back. They have to be written in order, without seeking.
+struct MemoryDigestWriter<'a, W> {
+    writer: &'a mut W,
+    regions: &'a [GuestMemoryRegionState],
+    region: Sha256Writer,
+    region_len: u64,
+    buf: Vec<u8>,
+    digests: Vec<MemoryRegionDigest>,
+}
+
+impl<'a, W: WriteVolatile> MemoryDigestWriter<'a, W> {
+    const BUF_SIZE: usize = 64 << 10;
+
+    fn new(writer: &'a mut W, regions: &'a [GuestMemoryRegionState]) -> Self {
+        MemoryDigestWriter {
+            writer,
+            regions,
+            region: Sha256Writer::new(),
+            region_len: 0,
+            buf: vec![0; Self::BUF_SIZE],
+            digests: Vec::with_capacity(regions.len()),
+        }
+    }
+
+    /// Returns the digests of the regions, which must all have been written.
+    fn finish(self) -> io::Result<Vec<MemoryRegionDigest>> {
+        if self.digests.len() != self.regions.len() {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        Ok(self.digests)
+    }
+}
+
+impl<W: WriteVolatile> WriteVolatile for MemoryDigestWriter<'_, W> {
+    fn write_volatile<B: BitmapSlice>(
+        &mut self,
+        buf: &VolatileSlice<B>,
+    ) -> Result<usize, VolatileMemoryError> {
+        let count = self.writer.write_volatile(buf)?;
+        let mut written = buf.subslice(0, count)?;
+        while !written.is_empty() {
+            let Some(region) = self.regions.get(self.digests.len()) else {
+                // Anything past the last region is not part of the guest memory.
+                break;
+            };
+            let region_size = region.size as u64;
+            let len = u64_to_usize(region_size - self.region_len)
+                .min(written.len())
+                .min(Self::BUF_SIZE);
+            written.subslice(0, len)?.copy_to(&mut self.buf[..len]);
+            self.region.0.update(&self.buf[..len]);
+            written = written.offset(len)?;
+            self.region_len += len as u64;
+            if self.region_len == region_size {
+                let region_digest = std::mem::replace(&mut self.region, Sha256Writer::new());
+                self.digests.push(MemoryRegionDigest {
+                    offset: region.offset,
+                    size: region_size,
+                    digest: region_digest.finish(),
+                });
+                self.region_len = 0;
+            }
+        }
+        Ok(count)
+    }
 }
 
 /// This describes the mapping between Firecracker base virtual address and
@@ -142,9 +332,10 @@ pub enum MicrovmStateError {
 pub enum CreateSnapshotError {
     /// Cannot get dirty bitmap: {0}
     DirtyBitmap(VmmError),
-    #[rustfmt::skip]
-    /// Cannot translate microVM version to snapshot data version
-    UnsupportedVersion,
+    /// Cannot create a snapshot with unsupported data version {0}
+    UnsupportedVersion(Version),
+    /// Devices use features that snapshot data version {0} does not support: {1}
+    IncompatibleDeviceFeatures(Version, String),
     /// Cannot write memory file: {0}
     Memory(MemoryError),
     /// Cannot perform {0} on the memory backing file: {1}
@@ -157,10 +348,30 @@ pub enum CreateSnapshotError {
     SnapshotBackingFile(&'static str, io::Error),
     /// Size mismatch when writing diff snapshot on top of base layer: base layer size is {0} but diff layer is size {1}.
     SnapshotBackingFileLengthMismatch(u64, u64),
+    /// Cannot encrypt the snapshot: {0}
+    Encryption(SnapshotEncryptionError),
+    /// Encrypted diff snapshots are not supported.
+    EncryptedDiffSnapshot,
+    /// Page manifests are not supported for encrypted snapshots.
+    EncryptedManifest,
+    /// Sparse memory files are not supported for encrypted snapshots.
+    EncryptedSparseSnapshot,
+    /// Cannot perform {0} on the page manifest file: {1}
+    ManifestFile(&'static str, io::Error),
+    /// Cannot snapshot a microVM whose entropy device uses a deterministic generator.
+    DeterministicEntropy,
+    /// Cannot snapshot a confidential microVM.
+    Confidential,
+    /// Cannot snapshot a microVM with a TPM device.
+    Tpm,
+    /// Cannot snapshot a microVM whose device inputs are recorded or replayed.
+    Replay,
+    /// Cannot quiesce the devices: {0}
+    QuiesceDevices(VmmError),
 }
 
 /// Snapshot version
-pub const SNAPSHOT_VERSION: Version = Version::new(2, 0, 0);
+pub const SNAPSHOT_VERSION: Version = Version::new(3, 0, 0);
 
 /// Creates a Microvm snapshot.
 pub fn create_snapshot(
@@ -168,20 +379,148 @@ pub fn create_snapshot(
     vm_info: &VmInfo,
     params: &CreateSnapshotParams,
 ) -> Result<(), CreateSnapshotError> {
-    let microvm_state = vmm
+    // The host cannot read the memory and state of a confidential guest.
+    if vmm.vm.is_confidential() {
+        return Err(CreateSnapshotError::Confidential);
+    }
+
+    // The guest of a restored snapshot would keep getting predictable random bytes.
+    let mut deterministic_entropy = false;
+    // This only fails when there is no entropy device.
+    let _ = vmm.mmio_device_manager.with_virtio_device_with_id(
+        TYPE_RNG,
+        ENTROPY_DEV_ID,
+        |entropy: &mut Entropy| {
+            deterministic_entropy = entropy.deterministic_seed().is_some();
+            Ok(())
+        },
+    );
+    if deterministic_entropy {
+        return Err(CreateSnapshotError::DeterministicEntropy);
+    }
+
+    // A restored microVM would not record or replay its inputs anymore.
+    if replay::is_recording() || replay::is_replaying() {
+        return Err(CreateSnapshotError::Replay);
+    }
+
+    // The state of the TPM is held by the emulator, out of the snapshot.
+    #[cfg(target_arch = "x86_64")]
+    if vmm
+        .mmio_device_manager
+        .get_device(crate::arch::DeviceType::Tpm, "Tpm")
+        .is_some()
+    {
+        return Err(CreateSnapshotError::Tpm);
+    }
+
+    let key = params
+        .encryption
+        .as_ref()
+        .map(snapshot_key)
+        .transpose()
+        .map_err(CreateSnapshotError::Encryption)?;
+    if key.is_some() && params.snapshot_type == SnapshotType::Diff {
+        return Err(CreateSnapshotError::EncryptedDiffSnapshot);
+    }
+    // The hashes of the plaintext pages would leak which pages of guest memory are identical.
+    if key.is_some() && params.manifest_path.is_some() {
+        return Err(CreateSnapshotError::EncryptedManifest);
+    }
+    // The ciphertext of zero pages is not zero.
+    if key.is_some() && params.sparse {
+        return Err(CreateSnapshotError::EncryptedSparseSnapshot);
+    }
+
+    // The devices complete the requests they can and stop accessing the guest memory, so that the
+    // ones still in flight are recorded in their state, to be replayed on restore. The devices
+    // resume along with the vCPUs.
+    vmm.quiesce_devices()
+        .map_err(CreateSnapshotError::QuiesceDevices)?;
+
+    let mut microvm_state = vmm
         .save_state(vm_info)
         .map_err(CreateSnapshotError::MicrovmState)?;
+    microvm_state.vm_info.firecracker_version = vmm.instance_info().vmm_version;
+    let version = match params.snapshot_version.as_ref() {
+        Some(version) => {
+            check_snapshot_version(&microvm_state, version)?;
+            version.clone()
+        }
+        None => SNAPSHOT_VERSION,
+    };
 
-    snapshot_state_to_file(&microvm_state, &params.snapshot_path)?;
+    // The memory file is written first, so that the digests of its contents can be
+    // embedded in the state file. The contents of an encrypted memory file are already
+    // authenticated by AES-GCM, so no digests are computed for it.
+    microvm_state.digests.memory = match key.as_ref() {
+        Some(key) => {
+            snapshot_memory_to_encrypted_file(vmm, &params.mem_file_path, key)?;
+            None
+        }
+        None => snapshot_memory_to_file(
+            vmm,
+            &params.mem_file_path,
+            &microvm_state.memory_state,
+            params.snapshot_type,
+            params.manifest_path.as_deref(),
+            params.sparse,
+        )?,
+    };
+    microvm_state.digests.vmstate =
+        vmstate_digest(&mut microvm_state).map_err(CreateSnapshotError::SerializeMicrovmState)?;
+
+    snapshot_state_to_file(
+        &microvm_state,
+        &params.snapshot_path,
+        &version,
+        key.as_ref(),
+    )
+}
 
-    snapshot_memory_to_file(vmm, &params.mem_file_path, params.snapshot_type)?;
+/// Checks that a snapshot of the microVM state can be created with the data version `version`.
+///
+/// The state is always serialized with the layout of [`SNAPSHOT_VERSION`], so no other version
+/// can be requested, and none of the devices may use a feature introduced by a later version.
+fn check_snapshot_version(
+    microvm_state: &MicrovmState,
+    version: &Version,
+) -> Result<(), CreateSnapshotError> {
+    if *version != SNAPSHOT_VERSION {
+        return Err(CreateSnapshotError::UnsupportedVersion(version.clone()));
+    }
 
+    let incompatible_features = incompatible_snapshot_features(microvm_state, version);
+    if !incompatible_features.is_empty() {
+        return Err(CreateSnapshotError::IncompatibleDeviceFeatures(
+            version.clone(),
+            incompatible_features.join(", "),
+        ));
+    }
     Ok(())
 }
 
+/// Lists the device features of the microVM state introduced after the data version `version`.
+fn incompatible_snapshot_features(microvm_state: &MicrovmState, version: &Version) -> Vec<String> {
+    microvm_state
+        .device_states
+        .snapshot_features()
+        .into_iter()
+        .filter(|(_, feature)| feature.introduced_in() > *version)
+        .map(|(id, feature)| format!("{}: {}", id, feature))
+        .collect()
+}
+
+/// Obtains the key described by the snapshot encryption configuration.
+fn snapshot_key(config: &SnapshotEncryptionConfig) -> Result<SnapshotKey, SnapshotEncryptionError> {
+    FdKeyProvider(config.key_fd).snapshot_key()
+}
+
 fn snapshot_state_to_file(
     microvm_state: &MicrovmState,
     snapshot_path: &Path,
+    version: &Version,
+    key: Option<&SnapshotKey>,
 ) -> Result<(), CreateSnapshotError> {
     use self::CreateSnapshotError::*;
     let mut snapshot_file = OpenOptions::new()
@@ -191,10 +530,22 @@ fn snapshot_state_to_file(
         .open(snapshot_path)
         .map_err(|err| SnapshotBackingFile("open", err))?;
 
-    let snapshot = Snapshot::new(SNAPSHOT_VERSION);
-    snapshot
-        .save(&mut snapshot_file, microvm_state)
-        .map_err(SerializeMicrovmState)?;
+    let snapshot = Snapshot::new(version.clone());
+    match key {
+        Some(key) => {
+            let mut contents = Vec::new();
+            snapshot
+                .save(&mut contents, microvm_state)
+                .map_err(SerializeMicrovmState)?;
+            let sealed = key.seal(contents).map_err(Encryption)?;
+            snapshot_file
+                .write_all(&sealed)
+                .map_err(|err| SnapshotBackingFile("write", err))?;
+        }
+        None => snapshot
+            .save(&mut snapshot_file, microvm_state)
+            .map_err(SerializeMicrovmState)?,
+    }
     snapshot_file
         .flush()
         .map_err(|err| SnapshotBackingFile("flush", err))?;
@@ -204,7 +555,7 @@ fn snapshot_state_to_file(
 }
 
 /// Takes a snapshot of the virtual machine running inside the given [`Vmm`] and saves it to
-/// `mem_file_path`.
+/// `mem_file_path`, returning the digests of the regions of `mem_state` for full snapshots.
 ///
 /// If `snapshot_type` is [`SnapshotType::Diff`], and `mem_file_path` exists and is a snapshot file
 /// of matching size, then the diff snapshot will be directly merged into the existing snapshot.
@@ -212,8 +563,11 @@ fn snapshot_state_to_file(
 fn snapshot_memory_to_file(
     vmm: &Vmm,
     mem_file_path: &Path,
+    mem_state: &GuestMemoryState,
     snapshot_type: SnapshotType,
-) -> Result<(), CreateSnapshotError> {
+    manifest_path: Option<&Path>,
+    sparse: bool,
+) -> Result<Option<Vec<MemoryRegionDigest>>, CreateSnapshotError> {
     use self::CreateSnapshotError::*;
 
     // Need to check this here, as we create the file in the line below
@@ -229,6 +583,9 @@ fn snapshot_memory_to_file(
     let mem_size_mib = mem_size_mib(vmm.guest_memory());
     let expected_size = mem_size_mib * 1024 * 1024;
 
+    // The zero pages skipped by sparse writes have to be deallocated when the file is reused, as
+    // they may contain data written before.
+    let mut punch_holes = false;
     if file_existed {
         let file_size = file
             .metadata()
@@ -245,6 +602,8 @@ fn snapshot_memory_to_file(
         if file_size != expected_size {
             file.set_len(0)
                 .map_err(|err| MemoryBackingFile("truncate", err))?;
+        } else {
+            punch_holes = true;
         }
     }
 
@@ -252,25 +611,135 @@ fn snapshot_memory_to_file(
     file.set_len(expected_size)
         .map_err(|e| MemoryBackingFile("set_length", e))?;
 
+    let (manifest, digests) = if sparse {
+        let mut writer = SparseWriter::new(&mut file, punch_holes);
+        let dumped =
+            dump_memory_with_manifest(vmm, &mut writer, mem_state, snapshot_type, manifest_path)?;
+        writer
+            .finish()
+            .map_err(|err| MemoryBackingFile("punch_hole", err))?;
+        dumped
+    } else {
+        dump_memory_with_manifest(vmm, &mut file, mem_state, snapshot_type, manifest_path)?
+    };
+    file.flush()
+        .map_err(|err| MemoryBackingFile("flush", err))?;
+    file.sync_all()
+        .map_err(|err| MemoryBackingFile("sync_all", err))?;
+
+    if let (Some(manifest), Some(manifest_path)) = (manifest, manifest_path) {
+        snapshot_manifest_to_file(&manifest, manifest_path)?;
+    }
+    Ok(digests)
+}
+
+/// Dumps the guest memory pages required by `snapshot_type` to `writer`, returning their page
+/// manifest if `manifest_path` is set, and the digests of the regions for full snapshots.
+fn dump_memory_with_manifest<T: WriteVolatile + Seek>(
+    vmm: &Vmm,
+    writer: &mut T,
+    mem_state: &GuestMemoryState,
+    snapshot_type: SnapshotType,
+    manifest_path: Option<&Path>,
+) -> Result<(Option<PageManifest>, Option<Vec<MemoryRegionDigest>>), CreateSnapshotError> {
+    // The pages are hashed while they are dumped, so the memory file is never read back.
+    match manifest_path {
+        Some(_) => {
+            let mut writer = PageHashWriter::new(writer);
+            let digests = dump_memory(vmm, &mut writer, mem_state, snapshot_type)?;
+            Ok((Some(writer.finish()), digests))
+        }
+        None => Ok((None, dump_memory(vmm, writer, mem_state, snapshot_type)?)),
+    }
+}
+
+/// Dumps the guest memory pages required by `snapshot_type` to `writer`, returning the digests
+/// of the regions of `mem_state` for full snapshots.
+///
+/// A diff snapshot only holds the dirty pages, so the digests of the memory file it is merged
+/// into are not known.
+fn dump_memory<T: WriteVolatile + Seek>(
+    vmm: &Vmm,
+    writer: &mut T,
+    mem_state: &GuestMemoryState,
+    snapshot_type: SnapshotType,
+) -> Result<Option<Vec<MemoryRegionDigest>>, CreateSnapshotError> {
+    use self::CreateSnapshotError::*;
+
     match snapshot_type {
         SnapshotType::Diff => {
             let dirty_bitmap = vmm.get_dirty_bitmap().map_err(DirtyBitmap)?;
             vmm.guest_memory()
-                .dump_dirty(&mut file, &dirty_bitmap)
-                .map_err(Memory)
+                .dump_dirty(writer, &dirty_bitmap)
+                .map_err(Memory)?;
+            Ok(None)
         }
         SnapshotType::Full => {
-            let dump_res = vmm.guest_memory().dump(&mut file).map_err(Memory);
-            if dump_res.is_ok() {
-                vmm.reset_dirty_bitmap();
-                vmm.guest_memory().reset_dirty();
-            }
+            let mut writer = MemoryDigestWriter::new(writer, &mem_state.regions);
+            vmm.guest_memory().dump(&mut writer).map_err(Memory)?;
+            let digests = writer
+                .finish()
+                .map_err(|err| MemoryBackingFile("digest", err))?;
+            vmm.reset_dirty_bitmap();
+            vmm.guest_memory().reset_dirty();
+            Ok(Some(digests))
+        }
+    }
+}
+
+fn snapshot_manifest_to_file(
+    manifest: &PageManifest,
+    manifest_path: &Path,
+) -> Result<(), CreateSnapshotError> {
+    use self::CreateSnapshotError::*;
+    let mut manifest_file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(manifest_path)
+        .map_err(|err| ManifestFile("open", err))?;
+    let mut writer = io::BufWriter::new(&mut manifest_file);
+    manifest
+        .save(&mut writer)
+        .map_err(|err| ManifestFile("write", err))?;
+    writer.flush().map_err(|err| ManifestFile("flush", err))?;
+    drop(writer);
+    manifest_file
+        .sync_all()
+        .map_err(|err| ManifestFile("sync_all", err))
+}
 
-            dump_res
+/// Takes a full snapshot of the guest memory of the given [`Vmm`] and saves it, encrypted with
+/// `key`, to `mem_file_path`.
+fn snapshot_memory_to_encrypted_file(
+    vmm: &Vmm,
+    mem_file_path: &Path,
+    key: &SnapshotKey,
+) -> Result<(), CreateSnapshotError> {
+    use self::CreateSnapshotError::*;
+
+    // The microVM may have been restored from this very file, with its guest memory mapped from
+    // it. Unlinking the old file instead of overwriting it keeps the mapped contents intact.
+    match std::fs::remove_file(mem_file_path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            return Err(MemoryBackingFile("remove", err))
         }
-    }?;
-    file.flush()
-        .map_err(|err| MemoryBackingFile("flush", err))?;
+        _ => {}
+    }
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(mem_file_path)
+        .map_err(|err| MemoryBackingFile("open", err))?;
+
+    let mut writer = SealingWriter::new(key, file).map_err(Encryption)?;
+    vmm.guest_memory().dump(&mut writer).map_err(Memory)?;
+    let file = writer
+        .finish()
+        .map_err(|err| MemoryBackingFile("write", err))?;
+    vmm.reset_dirty_bitmap();
+    vmm.guest_memory().reset_dirty();
+
     file.sync_all()
         .map_err(|err| MemoryBackingFile("sync_all", err))
 }
@@ -370,6 +839,111 @@ pub fn snapshot_state_sanity_check(
     Ok(())
 }
 
+/// Checks that the host CPU can run the guest of the snapshot, which requires the features of
+/// the snapshot host CPU that were exposed to the guest.
+pub fn check_cpu_compatibility(microvm_state: &MicrovmState) -> Result<(), CpuFingerprintError> {
+    let vm_info = &microvm_state.vm_info;
+    // Snapshots which don't record the host CPU cannot be checked.
+    if vm_info.host_cpu.vendor.is_empty() {
+        return Ok(());
+    }
+    info!(
+        "Snapshot host CPU: vendor {}, model {:#x}, CPU template {:?}{}",
+        vm_info.host_cpu.vendor,
+        vm_info.host_cpu.model,
+        vm_info.cpu_template,
+        if vm_info.custom_cpu_template {
+            " (custom)"
+        } else {
+            ""
+        }
+    );
+
+    #[allow(unused_mut)]
+    let mut required = vm_info.host_cpu.clone();
+    // The CPU template of the guest may hide features of the host CPU.
+    #[cfg(target_arch = "x86_64")]
+    if let Some(vcpu_state) = microvm_state.vcpu_states.first() {
+        let guest_features = CpuFingerprint::feature_words(|leaf, subleaf| {
+            vcpu_state
+                .cpuid
+                .as_slice()
+                .iter()
+                .find(|entry| entry.function == leaf && entry.index == subleaf)
+                .map(|entry| [entry.eax, entry.ebx, entry.ecx, entry.edx])
+        });
+        required.mask_features(&guest_features);
+    }
+
+    let host = CpuFingerprint::host();
+    if host.model != required.model {
+        warn!(
+            "Host CPU model {:#x} differs from the snapshotted one",
+            host.model
+        );
+    }
+    host.check_compatible(&required)
+}
+
+/// Error type for [`verify_snapshot_digests`].
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum SnapshotVerificationError {
+    /// Failed to compute the digest of the microVM state: {0}
+    VmStateDigest(SnapshotError),
+    /// The microVM state section of the snapshot is corrupted: digest mismatch.
+    VmStateMismatch,
+    /// The snapshot has no memory digests, as it is a diff snapshot.
+    NoMemoryDigests,
+    /// The memory digests stored in the snapshot do not match its memory layout.
+    MemoryLayout,
+    /// Failed to read the memory file: {0}
+    MemoryFile(io::Error),
+    /// The memory file range at offset {0:#x} of size {1:#x} is corrupted: digest mismatch.
+    MemoryMismatch(u64, u64),
+}
+
+/// Checks the snapshot sections against the digests stored in the microVM state. Guest memory
+/// is only verified when `mem_file_path` is provided.
+pub fn verify_snapshot_digests(
+    microvm_state: &mut MicrovmState,
+    mem_file_path: Option<&Path>,
+) -> Result<(), SnapshotVerificationError> {
+    use self::SnapshotVerificationError::*;
+
+    if vmstate_digest(microvm_state).map_err(VmStateDigest)? != microvm_state.digests.vmstate {
+        return Err(VmStateMismatch);
+    }
+
+    let Some(mem_file_path) = mem_file_path else {
+        return Ok(());
+    };
+
+    let regions = &microvm_state.memory_state.regions;
+    let digests = microvm_state
+        .digests
+        .memory
+        .as_ref()
+        .ok_or(NoMemoryDigests)?;
+    if regions.len() != digests.len()
+        || regions.iter().zip(digests.iter()).any(|(region, digest)| {
+            region.offset != digest.offset || region.size as u64 != digest.size
+        })
+    {
+        return Err(MemoryLayout);
+    }
+
+    let mut mem_file = File::open(mem_file_path).map_err(MemoryFile)?;
+    for expected in digests {
+        let actual =
+            file_range_digest(&mut mem_file, expected.offset, expected.size).map_err(MemoryFile)?;
+        if actual != expected.digest {
+            return Err(MemoryMismatch(expected.offset, expected.size));
+        }
+    }
+
+    Ok(())
+}
+
 /// Error type for [`restore_from_snapshot`].
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 pub enum RestoreFromSnapshotError {
@@ -377,10 +951,20 @@ pub enum RestoreFromSnapshotError {
     File(#[from] SnapshotStateFromFileError),
     /// Invalid snapshot state: {0}
     Invalid(#[from] SnapShotStateSanityCheckError),
+    /// Host CPU is incompatible with the snapshot: {0}
+    CpuMismatch(CpuFingerprintError),
+    /// Snapshot integrity verification failed: {0}
+    Verify(#[from] SnapshotVerificationError),
+    /// Failed to obtain the snapshot key: {0}
+    Key(SnapshotEncryptionError),
+    /// Encrypted snapshots cannot be loaded with the Uffd memory backend.
+    EncryptedUffd,
     /// Failed to load guest memory: {0}
     GuestMemory(#[from] RestoreFromSnapshotGuestMemoryError),
     /// Failed to build microVM from snapshot: {0}
     Build(#[from] BuildMicrovmFromSnapshotError),
+    /// Invalid device overrides: {0}
+    Overrides(#[from] SnapshotOverrideError),
 }
 /// Sub-Error type for [`restore_from_snapshot`] to contain either [`GuestMemoryFromFileError`] or
 /// [`GuestMemoryFromUffdError`] within [`RestoreFromSnapshotError`].
@@ -390,6 +974,100 @@ pub enum RestoreFromSnapshotGuestMemoryError {
     File(#[from] GuestMemoryFromFileError),
     /// Error creating guest memory from uffd: {0}
     Uffd(#[from] GuestMemoryFromUffdError),
+    /// Error pre-faulting guest memory: {0}
+    Prefault(#[from] MemoryError),
+    /// Error marking guest memory as mergeable: {0}
+    Mergeable(MemoryError),
+}
+
+/// Error type for [`apply_snapshot_overrides`].
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum SnapshotOverrideError {
+    /// Device {0} is overridden more than once.
+    Duplicate(String),
+    /// The snapshot has no block device {0}.
+    UnknownDrive(String),
+    /// Block device {0} is not backed by a disk image.
+    VhostUserDrive(String),
+    /// Cannot open the disk image of block device {0}: {1}
+    DriveFile(String, io::Error),
+    /// The disk image of block device {0} has {1} sectors instead of {2}.
+    DriveSize(String, u64, u64),
+    /// The snapshot has no network interface {0}.
+    UnknownNetworkInterface(String),
+    /// The snapshot has no vsock device {0}.
+    UnknownVsock(String),
+}
+
+/// Substitutes the backends of the devices of the microVM state described by `overrides`.
+///
+/// The guest keeps seeing the snapshotted disk size, so a disk image must hold as many sectors
+/// as the one it replaces.
+fn apply_snapshot_overrides(
+    microvm_state: &mut MicrovmState,
+    overrides: &SnapshotOverrides,
+) -> Result<(), SnapshotOverrideError> {
+    use self::SnapshotOverrideError::*;
+    let device_states = &mut microvm_state.device_states;
+    // The IDs of all the device types share a single namespace.
+    let mut overridden = HashSet::new();
+
+    for drive in &overrides.drives {
+        let id = &drive.drive_id;
+        if !overridden.insert(id.as_str()) {
+            return Err(Duplicate(id.clone()));
+        }
+        let dev = device_states
+            .block_devices
+            .iter_mut()
+            .find(|dev| dev.device_id == *id)
+            .ok_or_else(|| UnknownDrive(id.clone()))?;
+        let BlockState::Virtio(state) = &mut dev.device_state else {
+            return Err(VhostUserDrive(id.clone()));
+        };
+        // Block special files report a null size in their metadata.
+        let size = File::open(&drive.path_on_host)
+            .and_then(|mut file| file.seek(SeekFrom::End(0)))
+            .map_err(|err| DriveFile(id.clone(), err))?;
+        if size >> SECTOR_SHIFT != state.nsectors() {
+            return Err(DriveSize(
+                id.clone(),
+                size >> SECTOR_SHIFT,
+                state.nsectors(),
+            ));
+        }
+        state.set_disk_path(drive.path_on_host.clone());
+    }
+
+    for iface in &overrides.network_interfaces {
+        let id = &iface.iface_id;
+        if !overridden.insert(id.as_str()) {
+            return Err(Duplicate(id.clone()));
+        }
+        device_states
+            .net_devices
+            .iter_mut()
+            .find(|dev| dev.device_id == *id)
+            .ok_or_else(|| UnknownNetworkInterface(id.clone()))?
+            .device_state
+            .set_tap_if_name(iface.host_dev_name.clone());
+    }
+
+    for vsock in &overrides.vsock_devices {
+        let id = vsock.vsock_id.as_deref().unwrap_or(VSOCK_DEV_ID);
+        if !overridden.insert(id) {
+            return Err(Duplicate(id.to_string()));
+        }
+        let dev = device_states
+            .vsock_devices
+            .iter_mut()
+            .find(|dev| dev.device_id == id)
+            .ok_or_else(|| UnknownVsock(id.to_string()))?;
+        let VsockBackendState::Uds(uds) = &mut dev.device_state.backend;
+        uds.path = vsock.uds_path.clone();
+    }
+
+    Ok(())
 }
 
 /// Loads a Microvm snapshot producing a 'paused' Microvm.
@@ -400,9 +1078,36 @@ pub fn restore_from_snapshot(
     params: &LoadSnapshotParams,
     vm_resources: &mut VmResources,
 ) -> Result<Arc<Mutex<Vmm>>, RestoreFromSnapshotError> {
-    let microvm_state = snapshot_state_from_file(&params.snapshot_path)?;
+    let key = params
+        .encryption
+        .as_ref()
+        .map(snapshot_key)
+        .transpose()
+        .map_err(RestoreFromSnapshotError::Key)?;
+    if key.is_some() && params.mem_backend.backend_type == MemBackendType::Uffd {
+        return Err(RestoreFromSnapshotError::EncryptedUffd);
+    }
+
+    let mut microvm_state = snapshot_state_from_file(&params.snapshot_path, key.as_ref())?;
     let track_dirty_pages = params.enable_diff_snapshots;
 
+    if params.verify {
+        let mem_file_path = match params.mem_backend.backend_type {
+            // Encrypted guest memory is authenticated by AES-GCM while it is decrypted.
+            MemBackendType::File if key.is_some() => None,
+            MemBackendType::File => Some(params.mem_backend.backend_path.as_path()),
+            MemBackendType::Uffd => {
+                warn!(
+                    "Guest memory served through UFFD cannot be verified, only the microVM state \
+                     will be checked."
+                );
+                None
+            }
+        };
+        verify_snapshot_digests(&mut microvm_state, mem_file_path)?;
+    }
+    apply_snapshot_overrides(&mut microvm_state, &params.overrides)?;
+
     let vcpu_count = microvm_state
         .vcpu_states
         .len()
@@ -417,24 +1122,50 @@ pub fn restore_from_snapshot(
             smt: Some(microvm_state.vm_info.smt),
             cpu_template: Some(microvm_state.vm_info.cpu_template),
             track_dirty_pages: Some(track_dirty_pages),
+            dirty_tracking_mode: None,
             huge_pages: Some(microvm_state.vm_info.huge_pages),
+            memory_backend: None,
+            mem_populate: None,
+            mem_prefault_ranges: None,
+            mem_mergeable: None,
+            mem_mergeable_ranges: None,
+            confidential: None,
+            memory_layout: Some(microvm_state.vm_info.memory_layout),
+            virtio_feature_policy: Some(microvm_state.vm_info.virtio_feature_policy.clone()),
+            vcpu_threads: Some(microvm_state.vm_info.vcpu_threads.clone()),
+            pmu: Some(microvm_state.vm_info.pmu),
         })
         .map_err(BuildMicrovmFromSnapshotError::VmUpdateConfig)?;
 
     // Some sanity checks before building the microvm.
     snapshot_state_sanity_check(&microvm_state)?;
+    if let Err(err) = check_cpu_compatibility(&microvm_state) {
+        if !params.allow_cpu_mismatch {
+            return Err(RestoreFromSnapshotError::CpuMismatch(err));
+        }
+        warn!("Loading the snapshot on an incompatible host CPU: {err}");
+    }
 
     let mem_backend_path = &params.mem_backend.backend_path;
     let mem_state = &microvm_state.memory_state;
 
     let (guest_memory, uffd) = match params.mem_backend.backend_type {
         MemBackendType::File => (
-            guest_memory_from_file(
-                mem_backend_path,
-                mem_state,
-                track_dirty_pages,
-                vm_resources.vm_config.huge_pages,
-            )
+            match key.as_ref() {
+                Some(key) => guest_memory_from_encrypted_file(
+                    mem_backend_path,
+                    mem_state,
+                    track_dirty_pages,
+                    vm_resources.vm_config.huge_pages,
+                    key,
+                ),
+                None => guest_memory_from_file(
+                    mem_backend_path,
+                    mem_state,
+                    track_dirty_pages,
+                    vm_resources.vm_config.huge_pages,
+                ),
+            }
             .map_err(RestoreFromSnapshotGuestMemoryError::File)?,
             None,
         ),
@@ -449,6 +1180,10 @@ pub fn restore_from_snapshot(
         )
         .map_err(RestoreFromSnapshotGuestMemoryError::Uffd)?,
     };
+    builder::mark_guest_memory_mergeable(&guest_memory, &vm_resources.vm_config)
+        .map_err(RestoreFromSnapshotGuestMemoryError::Mergeable)?;
+    builder::prefault_guest_memory(&guest_memory, &vm_resources.vm_config)
+        .map_err(RestoreFromSnapshotGuestMemoryError::Prefault)?;
     builder::build_microvm_from_snapshot(
         instance_info,
         event_manager,
@@ -466,26 +1201,158 @@ pub fn restore_from_snapshot(
 pub enum SnapshotStateFromFileError {
     /// Failed to open snapshot file: {0}
     Open(std::io::Error),
-    /// Failed to read snapshot file metadata: {0}
-    Meta(std::io::Error),
+    /// Failed to read snapshot file: {0}
+    Read(std::io::Error),
+    /// Failed to decrypt snapshot file: {0}
+    Decrypt(SnapshotEncryptionError),
+    /// Snapshot file is encrypted, but no key was provided.
+    Encrypted,
     /// Failed to load snapshot state from file: {0}
     Load(#[from] crate::snapshot::SnapshotError),
 }
 
 fn snapshot_state_from_file(
     snapshot_path: &Path,
+    key: Option<&SnapshotKey>,
 ) -> Result<MicrovmState, SnapshotStateFromFileError> {
     let snapshot = Snapshot::new(SNAPSHOT_VERSION);
-    let mut snapshot_reader =
-        File::open(snapshot_path).map_err(SnapshotStateFromFileError::Open)?;
-    let metadata = std::fs::metadata(snapshot_path).map_err(SnapshotStateFromFileError::Meta)?;
-    let snapshot_len = u64_to_usize(metadata.len());
+    let mut contents = Vec::new();
+    File::open(snapshot_path)
+        .map_err(SnapshotStateFromFileError::Open)?
+        .read_to_end(&mut contents)
+        .map_err(SnapshotStateFromFileError::Read)?;
+    match key {
+        Some(key) => {
+            contents = key
+                .open(contents)
+                .map_err(SnapshotStateFromFileError::Decrypt)?
+        }
+        None if is_sealed_state(&contents) => return Err(SnapshotStateFromFileError::Encrypted),
+        None => (),
+    }
+    let snapshot_len = contents.len();
     let state: MicrovmState = snapshot
-        .load_with_version_check(&mut snapshot_reader, snapshot_len)
+        .load_with_version_check(&mut contents.as_slice(), snapshot_len)
         .map_err(SnapshotStateFromFileError::Load)?;
     Ok(state)
 }
 
+/// Device of a snapshot, as listed by [`describe_snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SnapshotDeviceDescription {
+    /// Device ID.
+    pub id: String,
+    /// Device type.
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+/// Snapshot feature used by a device, as listed by [`describe_snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SnapshotFeatureDescription {
+    /// ID of the device using the feature.
+    pub device_id: String,
+    /// Feature name.
+    pub feature: String,
+    /// First snapshot data version supporting the feature.
+    pub introduced_in: Version,
+}
+
+/// Summary of a snapshot, returned by [`describe_snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SnapshotDescription {
+    /// Snapshot data version.
+    pub snapshot_version: Version,
+    /// Version of the Firecracker that created the snapshot, empty if unknown.
+    pub firecracker_version: String,
+    /// Number of vCPUs.
+    pub vcpu_count: usize,
+    /// Guest memory size.
+    pub mem_size_mib: u64,
+    /// Whether SMT is enabled.
+    pub smt: bool,
+    /// Static CPU template.
+    pub cpu_template: StaticCpuTemplate,
+    /// Whether a custom CPU template was applied.
+    pub custom_cpu_template: bool,
+    /// Huge page configuration of the guest memory.
+    pub huge_pages: HugePageConfig,
+    /// Whether a virtual PMU is exposed to the guest.
+    pub pmu: bool,
+    /// Fingerprint of the CPU of the host that created the snapshot.
+    pub host_cpu: CpuFingerprint,
+    /// Devices of the microVM.
+    pub devices: Vec<SnapshotDeviceDescription>,
+    /// Snapshot features the devices depend on.
+    pub features: Vec<SnapshotFeatureDescription>,
+}
+
+impl SnapshotDescription {
+    fn new(microvm_state: &MicrovmState, snapshot_version: Version) -> Self {
+        let vm_info = &microvm_state.vm_info;
+        let devices = microvm_state
+            .device_states
+            .devices()
+            .into_iter()
+            .map(|(id, kind)| SnapshotDeviceDescription {
+                id,
+                kind: kind.to_string(),
+            })
+            .collect();
+        let features = microvm_state
+            .device_states
+            .snapshot_features()
+            .into_iter()
+            .map(|(device_id, feature)| SnapshotFeatureDescription {
+                device_id,
+                feature: feature.name().to_string(),
+                introduced_in: feature.introduced_in(),
+            })
+            .collect();
+        SnapshotDescription {
+            snapshot_version,
+            firecracker_version: vm_info.firecracker_version.clone(),
+            vcpu_count: microvm_state.vcpu_states.len(),
+            mem_size_mib: vm_info.mem_size_mib,
+            smt: vm_info.smt,
+            cpu_template: vm_info.cpu_template,
+            custom_cpu_template: vm_info.custom_cpu_template,
+            huge_pages: vm_info.huge_pages,
+            pmu: vm_info.pmu,
+            host_cpu: vm_info.host_cpu.clone(),
+            devices,
+            features,
+        }
+    }
+}
+
+/// Describes the snapshot whose microVM state is stored at `snapshot_path`.
+///
+/// Only the state file is read: neither the guest memory nor any KVM or device resource is
+/// touched, so orchestrators can use it to pick a host able to restore the snapshot. The data
+/// version is checked from the header before deserializing the rest of the state.
+pub fn describe_snapshot(
+    snapshot_path: &Path,
+) -> Result<SnapshotDescription, SnapshotStateFromFileError> {
+    let mut contents = Vec::new();
+    File::open(snapshot_path)
+        .map_err(SnapshotStateFromFileError::Open)?
+        .read_to_end(&mut contents)
+        .map_err(SnapshotStateFromFileError::Read)?;
+    if is_sealed_state(&contents) {
+        return Err(SnapshotStateFromFileError::Encrypted);
+    }
+
+    let version = Snapshot::get_format_version(&mut contents.as_slice())?;
+    if version.major != SNAPSHOT_VERSION.major || version.minor > SNAPSHOT_VERSION.minor {
+        return Err(SnapshotError::InvalidFormatVersion(version).into());
+    }
+    let snapshot_len = contents.len();
+    let (microvm_state, version): (MicrovmState, _) =
+        Snapshot::load(&mut contents.as_slice(), snapshot_len)?;
+    Ok(SnapshotDescription::new(&microvm_state, version))
+}
+
 /// Error type for [`guest_memory_from_file`].
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 pub enum GuestMemoryFromFileError {
@@ -493,6 +1360,10 @@ pub enum GuestMemoryFromFileError {
     File(#[from] std::io::Error),
     /// Failed to restore guest memory: {0}
     Restore(#[from] MemoryError),
+    /// Failed to decrypt guest memory: {0}
+    Decrypt(SnapshotEncryptionError),
+    /// Failed to write guest memory: {0}
+    Write(vm_memory::GuestMemoryError),
 }
 
 fn guest_memory_from_file(
@@ -507,6 +1378,45 @@ fn guest_memory_from_file(
     Ok(guest_mem)
 }
 
+fn guest_memory_from_encrypted_file(
+    mem_file_path: &Path,
+    mem_state: &GuestMemoryState,
+    track_dirty_pages: bool,
+    huge_pages: HugePageConfig,
+    key: &SnapshotKey,
+) -> Result<GuestMemoryMmap, GuestMemoryFromFileError> {
+    let mem_file = File::open(mem_file_path)?;
+    let guest_mem = GuestMemoryMmap::from_state(None, mem_state, track_dirty_pages, huge_pages)?;
+
+    // The memory file holds the regions back to back, in the order they are described.
+    let len = mem_state
+        .regions
+        .iter()
+        .map(|region| region.size as u64)
+        .sum();
+    let mut reader = OpeningReader::new(key, io::BufReader::new(mem_file), len)
+        .map_err(GuestMemoryFromFileError::Decrypt)?;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    for region in mem_state.regions.iter() {
+        let mut offset = 0;
+        while offset < region.size {
+            let count = CHUNK_SIZE.min(region.size - offset);
+            reader.read_exact(&mut buf[..count])?;
+            guest_mem
+                .write_slice(
+                    &buf[..count],
+                    GuestAddress(region.base_address + offset as u64),
+                )
+                .map_err(GuestMemoryFromFileError::Write)?;
+            offset += count;
+        }
+    }
+    // Populating guest memory is not a guest write, so it must not show up in diff snapshots.
+    guest_mem.reset_dirty();
+
+    Ok(guest_mem)
+}
+
 /// Error type for [`guest_memory_from_uffd`]
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 pub enum GuestMemoryFromUffdError {
@@ -626,25 +1536,31 @@ fn send_uffd_handshake(
 
 #[cfg(test)]
 mod tests {
+    use std::os::unix::fs::FileExt;
     use std::os::unix::net::UnixListener;
 
     use utils::tempfile::TempFile;
 
     use super::*;
-    #[cfg(target_arch = "x86_64")]
-    use crate::builder::tests::insert_vmgenid_device;
     use crate::builder::tests::{
         default_kernel_cmdline, default_vmm, insert_balloon_device, insert_block_devices,
-        insert_net_device, insert_vsock_device, CustomBlockConfig,
+        insert_entropy_device, insert_net_device, insert_vsock_device, CustomBlockConfig,
     };
+    #[cfg(target_arch = "x86_64")]
+    use crate::builder::tests::{insert_tpm_device, insert_vmgenid_device};
     #[cfg(target_arch = "aarch64")]
     use crate::construct_kvm_mpidrs;
     use crate::devices::virtio::block::CacheType;
+    use crate::devices::virtio::device::VirtioDevice;
+    use crate::devices::virtio::gen::virtio_net::VIRTIO_NET_F_CTRL_VQ;
+    use crate::devices::virtio::net::Net;
+    use crate::devices::virtio::TYPE_NET;
     use crate::snapshot::Persist;
     use crate::vmm_config::balloon::BalloonDeviceConfig;
+    use crate::vmm_config::entropy::EntropyDeviceConfig;
     use crate::vmm_config::net::NetworkInterfaceConfig;
+    use crate::vmm_config::snapshot::{DriveOverride, NetworkInterfaceOverride, VsockOverride};
     use crate::vmm_config::vsock::tests::default_config;
-    use crate::vstate::memory::GuestMemoryRegionState;
     use crate::Vmm;
 
     fn default_vmm_with_devices() -> Vmm {
@@ -678,6 +1594,13 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            peer: None,
+            dma_ranges: None,
+            rx_interrupt_coalescing: None,
+            tx_interrupt_coalescing: None,
+            worker: false,
+            dhcp: None,
+            xdp: None,
         };
         insert_net_device(
             &mut vmm,
@@ -708,7 +1631,7 @@ mod tests {
         // is tested by that device's tests.
         assert_eq!(states.block_devices.len(), 1);
         assert_eq!(states.net_devices.len(), 1);
-        assert!(states.vsock_device.is_some());
+        assert_eq!(states.vsock_devices.len(), 1);
         assert!(states.balloon_device.is_some());
 
         let memory_state = vmm.guest_memory().describe();
@@ -729,6 +1652,7 @@ mod tests {
             vm_state: vmm.vm.save_state().unwrap(),
             #[cfg(target_arch = "x86_64")]
             acpi_dev_state: vmm.acpi_device_manager.save(),
+            digests: SnapshotDigests::default(),
         };
 
         let mut buf = vec![0; 10000];
@@ -744,6 +1668,675 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_snapshot_memory_sparse() {
+        use std::os::unix::fs::MetadataExt;
+
+        use crate::vstate::memory::Bytes;
+
+        let vmm = default_vmm();
+        vmm.guest_memory()
+            .write_obj(0xAAu8, GuestAddress(0x1000))
+            .unwrap();
+        let mem_state = vmm.guest_memory().describe();
+        let snapshot_memory = |path: &Path, sparse| {
+            snapshot_memory_to_file(&vmm, path, &mem_state, SnapshotType::Full, None, sparse)
+                .unwrap()
+                .unwrap()
+        };
+        let dense_file = TempFile::new().unwrap();
+        let sparse_file = TempFile::new().unwrap();
+        let dense_digests = snapshot_memory(dense_file.as_path(), false);
+        let sparse_digests = snapshot_memory(sparse_file.as_path(), true);
+        // The skipped zero pages are hashed as well.
+        assert_eq!(dense_digests, sparse_digests);
+
+        let dense = dense_file.as_file().metadata().unwrap();
+        let sparse = sparse_file.as_file().metadata().unwrap();
+        assert_eq!(sparse.len(), dense.len());
+        assert!(sparse.blocks() < dense.blocks());
+        assert!(
+            std::fs::read(dense_file.as_path()).unwrap()
+                == std::fs::read(sparse_file.as_path()).unwrap()
+        );
+
+        // Reusing the dense file deallocates its zero pages.
+        snapshot_memory(dense_file.as_path(), true);
+        let reused = dense_file.as_file().metadata().unwrap();
+        assert!(reused.blocks() < dense.blocks());
+    }
+
+    #[test]
+    fn test_snapshot_deterministic_entropy() {
+        let mut event_manager = EventManager::new().unwrap();
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+        let entropy_config = EntropyDeviceConfig {
+            rate_limiter: None,
+            deterministic_seed: Some(42),
+            dma_ranges: None,
+        };
+        insert_entropy_device(&mut vmm, &mut cmdline, &mut event_manager, entropy_config);
+
+        let snapshot_file = TempFile::new().unwrap();
+        let mem_file = TempFile::new().unwrap();
+        let params = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: snapshot_file.as_path().to_path_buf(),
+            mem_file_path: mem_file.as_path().to_path_buf(),
+            encryption: None,
+            manifest_path: None,
+            sparse: false,
+            snapshot_version: None,
+        };
+        assert!(matches!(
+            create_snapshot(&mut vmm, &VmInfo::default(), &params),
+            Err(CreateSnapshotError::DeterministicEntropy)
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_landlock() {
+        use crate::landlock::Access;
+
+        let dir = utils::tempdir::TempDir::new().unwrap();
+        let dir = dir.as_path().to_path_buf();
+        let params = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: dir.join("vmstate"),
+            mem_file_path: dir.join("memory"),
+            encryption: None,
+            manifest_path: Some(dir.join("manifest")),
+            sparse: true,
+            snapshot_version: None,
+        };
+
+        // Landlock restricts the calling thread, so don't restrict the test harness.
+        std::thread::spawn(move || {
+            let mut vmm = default_vmm();
+            // The snapshot files are created in the working directory of Firecracker, which is
+            // granted the same accesses here.
+            let mut ruleset = builder::landlock_ruleset(&VmResources::default());
+            ruleset.allow(&dir, Access::CreateFiles);
+            if !ruleset.restrict_self().unwrap() {
+                return;
+            }
+
+            create_snapshot(&mut vmm, &VmInfo::default(), &params).unwrap();
+            // The memory file is never read back.
+            assert_eq!(
+                std::fs::read(&params.mem_file_path).unwrap_err().kind(),
+                io::ErrorKind::PermissionDenied
+            );
+            // The existing files are reused by the next snapshot.
+            create_snapshot(&mut vmm, &VmInfo::default(), &params).unwrap();
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_snapshot_twice() {
+        use std::os::unix::io::IntoRawFd;
+
+        use crate::snapshot::encryption::add_key_fd;
+
+        let key_file = TempFile::new().unwrap();
+        key_file.as_file().write_all(&[0x42; 32]).unwrap();
+        let key_fd = File::open(key_file.as_path()).unwrap().into_raw_fd();
+        // SAFETY: The file descriptor was just opened and is owned by nothing else.
+        unsafe { add_key_fd(key_fd) }.unwrap();
+
+        let mut vmm = default_vmm();
+        let snapshot_file = TempFile::new().unwrap();
+        let mem_file = TempFile::new().unwrap();
+        let params = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: snapshot_file.as_path().to_path_buf(),
+            mem_file_path: mem_file.as_path().to_path_buf(),
+            encryption: Some(SnapshotEncryptionConfig { key_fd }),
+            manifest_path: None,
+            sparse: false,
+            snapshot_version: None,
+        };
+        // The key is read from the start of the file every time.
+        for _ in 0..2 {
+            create_snapshot(&mut vmm, &VmInfo::default(), &params).unwrap();
+            let key = SnapshotKey::new(&[0x42; 32]).unwrap();
+            snapshot_state_from_file(snapshot_file.as_path(), Some(&key)).unwrap();
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_snapshot_tpm() {
+        let mut event_manager = EventManager::new().unwrap();
+        let mut vmm = default_vmm();
+        let dir = utils::tempdir::TempDir::new().unwrap();
+        let socket = dir.as_path().join("swtpm.sock");
+        let _listener = UnixListener::bind(&socket).unwrap();
+        insert_tpm_device(&mut vmm, &mut event_manager, &socket);
+
+        let snapshot_file = TempFile::new().unwrap();
+        let mem_file = TempFile::new().unwrap();
+        let params = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: snapshot_file.as_path().to_path_buf(),
+            mem_file_path: mem_file.as_path().to_path_buf(),
+            encryption: None,
+            manifest_path: None,
+            sparse: false,
+            snapshot_version: None,
+        };
+        assert!(matches!(
+            create_snapshot(&mut vmm, &VmInfo::default(), &params),
+            Err(CreateSnapshotError::Tpm)
+        ));
+    }
+
+    #[test]
+    fn test_check_snapshot_version() {
+        let mut event_manager = EventManager::new().unwrap();
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+        let network_interface = NetworkInterfaceConfig {
+            iface_id: String::from("netif"),
+            host_dev_name: String::from("net-device%d"),
+            guest_mac: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            peer: None,
+            dma_ranges: None,
+            rx_interrupt_coalescing: None,
+            tx_interrupt_coalescing: None,
+            worker: false,
+            dhcp: None,
+            xdp: None,
+        };
+        insert_net_device(
+            &mut vmm,
+            &mut cmdline,
+            &mut event_manager,
+            network_interface,
+        );
+
+        let microvm_state = MicrovmState {
+            device_states: vmm.mmio_device_manager.save(),
+            ..Default::default()
+        };
+        check_snapshot_version(&microvm_state, &SNAPSHOT_VERSION).unwrap();
+        // The state is only serialized with the layout of the current version.
+        for version in [
+            Version::new(2, 0, 0),
+            Version::new(2, 1, 0),
+            Version::new(3, 0, 1),
+            Version::new(3, 1, 0),
+        ] {
+            assert!(matches!(
+                check_snapshot_version(&microvm_state, &version),
+                Err(CreateSnapshotError::UnsupportedVersion(_))
+            ));
+        }
+        assert!(incompatible_snapshot_features(&microvm_state, &Version::new(2, 0, 0)).is_empty());
+
+        // The guest driver negotiated the control queue, which older versions cannot restore.
+        vmm.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, "netif", |net: &mut Net| {
+                net.set_acked_features(1 << VIRTIO_NET_F_CTRL_VQ);
+                Ok(())
+            })
+            .unwrap();
+        let microvm_state = MicrovmState {
+            device_states: vmm.mmio_device_manager.save(),
+            ..Default::default()
+        };
+        check_snapshot_version(&microvm_state, &SNAPSHOT_VERSION).unwrap();
+        assert_eq!(
+            incompatible_snapshot_features(&microvm_state, &Version::new(2, 0, 0)),
+            ["netif: net control queue (since snapshot version 2.1.0)"]
+        );
+    }
+
+    #[test]
+    fn test_snapshot_version_round_trip() {
+        let vmm = default_vmm_with_devices();
+        let microvm_state = MicrovmState {
+            device_states: vmm.mmio_device_manager.save(),
+            ..Default::default()
+        };
+        let state_file = TempFile::new().unwrap();
+
+        // A state saved with the current version is loaded back.
+        snapshot_state_to_file(
+            &microvm_state,
+            state_file.as_path(),
+            &SNAPSHOT_VERSION,
+            None,
+        )
+        .unwrap();
+        let restored_state = snapshot_state_from_file(state_file.as_path(), None).unwrap();
+        assert_eq!(restored_state.device_states, microvm_state.device_states);
+
+        // The layout of the state of version 2.0.0 differs, so a file labeled with it is rejected
+        // before its contents are deserialized.
+        snapshot_state_to_file(
+            &microvm_state,
+            state_file.as_path(),
+            &Version::new(2, 0, 0),
+            None,
+        )
+        .unwrap();
+        assert!(matches!(
+            snapshot_state_from_file(state_file.as_path(), None),
+            Err(SnapshotStateFromFileError::Load(
+                SnapshotError::InvalidFormatVersion(Version { major: 2, .. })
+            ))
+        ));
+
+        // Creating a snapshot with that version is refused instead of producing such a file.
+        let mut vmm = default_vmm();
+        let snapshot_file = TempFile::new().unwrap();
+        let mem_file = TempFile::new().unwrap();
+        let params = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: snapshot_file.as_path().to_path_buf(),
+            mem_file_path: mem_file.as_path().to_path_buf(),
+            encryption: None,
+            manifest_path: None,
+            sparse: false,
+            snapshot_version: Some(Version::new(2, 0, 0)),
+        };
+        assert!(matches!(
+            create_snapshot(&mut vmm, &VmInfo::default(), &params),
+            Err(CreateSnapshotError::UnsupportedVersion(_))
+        ));
+        assert_eq!(snapshot_file.as_file().metadata().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_describe_snapshot() {
+        let vmm = default_vmm_with_devices();
+        let mut microvm_state = MicrovmState {
+            device_states: vmm.mmio_device_manager.save(),
+            vcpu_states: vec![VcpuState::default(), VcpuState::default()],
+            ..Default::default()
+        };
+        microvm_state.vm_info.mem_size_mib = 128;
+        microvm_state.vm_info.firecracker_version = String::from("1.9.0");
+        let state_file = TempFile::new().unwrap();
+        snapshot_state_to_file(
+            &microvm_state,
+            state_file.as_path(),
+            &SNAPSHOT_VERSION,
+            None,
+        )
+        .unwrap();
+
+        let description = describe_snapshot(state_file.as_path()).unwrap();
+        assert_eq!(description.snapshot_version, SNAPSHOT_VERSION);
+        assert_eq!(description.firecracker_version, "1.9.0");
+        assert_eq!(description.vcpu_count, 2);
+        assert_eq!(description.mem_size_mib, 128);
+        let devices: Vec<_> = description
+            .devices
+            .iter()
+            .map(|dev| (dev.id.as_str(), dev.kind.as_str()))
+            .collect();
+        assert_eq!(
+            devices,
+            [
+                ("root", "block"),
+                ("netif", "net"),
+                ("vsock", "vsock"),
+                ("balloon", "balloon")
+            ]
+        );
+        assert!(description.features.is_empty());
+        let json = serde_json::to_value(&description).unwrap();
+        assert_eq!(json["snapshot_version"], "3.0.0");
+        assert_eq!(json["devices"][0]["type"], "block");
+
+        // The state of a snapshot with an incompatible layout is not deserialized.
+        for version in [Version::new(2, 1, 0), Version::new(4, 0, 0)] {
+            snapshot_state_to_file(&microvm_state, state_file.as_path(), &version, None).unwrap();
+            assert!(matches!(
+                describe_snapshot(state_file.as_path()),
+                Err(SnapshotStateFromFileError::Load(
+                    SnapshotError::InvalidFormatVersion(_)
+                ))
+            ));
+        }
+
+        let key = SnapshotKey::new(&[0x42; 32]).unwrap();
+        snapshot_state_to_file(
+            &microvm_state,
+            state_file.as_path(),
+            &SNAPSHOT_VERSION,
+            Some(&key),
+        )
+        .unwrap();
+        assert!(matches!(
+            describe_snapshot(state_file.as_path()),
+            Err(SnapshotStateFromFileError::Encrypted)
+        ));
+    }
+
+    #[test]
+    fn test_apply_snapshot_overrides() {
+        let vmm = default_vmm_with_devices();
+        let microvm_state = || MicrovmState {
+            device_states: vmm.mmio_device_manager.save(),
+            ..Default::default()
+        };
+        let mut state = microvm_state();
+        let BlockState::Virtio(block_state) = &state.device_states.block_devices[0].device_state
+        else {
+            panic!("unexpected block device type");
+        };
+        let nsectors = block_state.nsectors();
+        let disk_image = TempFile::new().unwrap();
+        disk_image
+            .as_file()
+            .set_len(nsectors << SECTOR_SHIFT)
+            .unwrap();
+        let disk_path = disk_image.as_path().to_str().unwrap().to_string();
+
+        let overrides = SnapshotOverrides {
+            drives: vec![DriveOverride {
+                drive_id: String::from("root"),
+                path_on_host: disk_path.clone(),
+            }],
+            network_interfaces: vec![NetworkInterfaceOverride {
+                iface_id: String::from("netif"),
+                host_dev_name: String::from("tap-override"),
+            }],
+            vsock_devices: vec![VsockOverride {
+                vsock_id: None,
+                uds_path: String::from("/tmp/override.sock"),
+            }],
+        };
+        apply_snapshot_overrides(&mut state, &overrides).unwrap();
+        let device_states = &state.device_states;
+        assert!(format!("{:?}", device_states.block_devices[0].device_state).contains(&disk_path));
+        assert!(format!("{:?}", device_states.net_devices[0].device_state).contains("tap-override"));
+        let VsockBackendState::Uds(uds) = &device_states.vsock_devices[0].device_state.backend;
+        assert_eq!(uds.path, "/tmp/override.sock");
+
+        // The guest would see the disk size change.
+        disk_image
+            .as_file()
+            .set_len((nsectors + 1) << SECTOR_SHIFT)
+            .unwrap();
+        let mut state = microvm_state();
+        assert!(matches!(
+            apply_snapshot_overrides(&mut state, &overrides),
+            Err(SnapshotOverrideError::DriveSize(_, size, expected))
+                if size == nsectors + 1 && expected == nsectors
+        ));
+
+        let unknown = SnapshotOverrides {
+            drives: vec![DriveOverride {
+                drive_id: String::from("netif"),
+                path_on_host: disk_path.clone(),
+            }],
+            ..Default::default()
+        };
+        assert!(matches!(
+            apply_snapshot_overrides(&mut state, &unknown),
+            Err(SnapshotOverrideError::UnknownDrive(id)) if id == "netif"
+        ));
+        let unknown = SnapshotOverrides {
+            network_interfaces: vec![NetworkInterfaceOverride {
+                iface_id: String::from("root"),
+                host_dev_name: String::from("tap-override"),
+            }],
+            ..Default::default()
+        };
+        assert!(matches!(
+            apply_snapshot_overrides(&mut state, &unknown),
+            Err(SnapshotOverrideError::UnknownNetworkInterface(id)) if id == "root"
+        ));
+        let unknown = SnapshotOverrides {
+            vsock_devices: vec![VsockOverride {
+                vsock_id: Some(String::from("vsock1")),
+                uds_path: String::from("/tmp/override.sock"),
+            }],
+            ..Default::default()
+        };
+        assert!(matches!(
+            apply_snapshot_overrides(&mut state, &unknown),
+            Err(SnapshotOverrideError::UnknownVsock(id)) if id == "vsock1"
+        ));
+
+        let duplicate = SnapshotOverrides {
+            network_interfaces: vec![overrides.network_interfaces[0].clone(); 2],
+            ..Default::default()
+        };
+        assert!(matches!(
+            apply_snapshot_overrides(&mut state, &duplicate),
+            Err(SnapshotOverrideError::Duplicate(id)) if id == "netif"
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_digests() {
+        let mem_file = TempFile::new().unwrap();
+        let mut microvm_state = MicrovmState {
+            memory_state: GuestMemoryState {
+                regions: vec![
+                    GuestMemoryRegionState {
+                        base_address: 0,
+                        size: 0x1000,
+                        offset: 0,
+                    },
+                    GuestMemoryRegionState {
+                        base_address: 0x10000,
+                        size: 0x2000,
+                        offset: 0x1000,
+                    },
+                ],
+            },
+            ..Default::default()
+        };
+
+        // A snapshot without digests fails verification.
+        assert!(matches!(
+            verify_snapshot_digests(&mut microvm_state, None),
+            Err(SnapshotVerificationError::VmStateMismatch)
+        ));
+
+        // Without memory digests, only the state can be verified.
+        microvm_state.digests.vmstate = vmstate_digest(&mut microvm_state).unwrap();
+        verify_snapshot_digests(&mut microvm_state, None).unwrap();
+        assert!(matches!(
+            verify_snapshot_digests(&mut microvm_state, Some(mem_file.as_path())),
+            Err(SnapshotVerificationError::NoMemoryDigests)
+        ));
+
+        // All the regions have to be written.
+        let mut file = TempFile::new().unwrap().into_file();
+        let mut writer = MemoryDigestWriter::new(&mut file, &microvm_state.memory_state.regions);
+        writer
+            .write_all_volatile(&VolatileSlice::from(&mut [0xAA; 0x2000][..]))
+            .unwrap();
+        assert_eq!(
+            writer.finish().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+
+        // The memory regions are hashed while they are written, in uneven pieces.
+        let mut file = mem_file.as_file().try_clone().unwrap();
+        let mut writer = MemoryDigestWriter::new(&mut file, &microvm_state.memory_state.regions);
+        for len in [0x800, 0x1000, 0x1800] {
+            writer
+                .write_all_volatile(&VolatileSlice::from(&mut vec![0xAA; len][..]))
+                .unwrap();
+        }
+        let digests = writer.finish().unwrap();
+        assert_eq!(digests.len(), 2);
+        assert_eq!(digests[1].offset, 0x1000);
+        assert_eq!(digests[1].size, 0x2000);
+        assert_eq!(
+            digests[1].digest,
+            file_range_digest(&mut mem_file.as_file().try_clone().unwrap(), 0x1000, 0x2000)
+                .unwrap()
+        );
+        microvm_state.digests.memory = Some(digests);
+        microvm_state.digests.vmstate = vmstate_digest(&mut microvm_state).unwrap();
+        verify_snapshot_digests(&mut microvm_state, Some(mem_file.as_path())).unwrap();
+
+        // The digests survive a serialization round trip.
+        let mut buf = Vec::new();
+        Snapshot::serialize(&mut buf, &microvm_state).unwrap();
+        let mut restored_state: MicrovmState = Snapshot::deserialize(&mut buf.as_slice()).unwrap();
+        verify_snapshot_digests(&mut restored_state, Some(mem_file.as_path())).unwrap();
+
+        // Corrupt the second memory region.
+        mem_file.as_file().write_all_at(&[0xBB], 0x2000).unwrap();
+        assert!(matches!(
+            verify_snapshot_digests(&mut microvm_state, Some(mem_file.as_path())),
+            Err(SnapshotVerificationError::MemoryMismatch(0x1000, 0x2000))
+        ));
+        // Without a memory file, only the state is checked.
+        verify_snapshot_digests(&mut microvm_state, None).unwrap();
+
+        // Truncated memory file.
+        mem_file.as_file().set_len(0x2000).unwrap();
+        assert!(matches!(
+            verify_snapshot_digests(&mut microvm_state, Some(mem_file.as_path())),
+            Err(SnapshotVerificationError::MemoryFile(_))
+        ));
+
+        // Memory layout not matching the digests.
+        microvm_state.digests.memory.as_mut().unwrap().pop();
+        microvm_state.digests.vmstate = vmstate_digest(&mut microvm_state).unwrap();
+        assert!(matches!(
+            verify_snapshot_digests(&mut microvm_state, Some(mem_file.as_path())),
+            Err(SnapshotVerificationError::MemoryLayout)
+        ));
+
+        // Corrupt the microVM state.
+        microvm_state.vm_info.mem_size_mib += 1;
+        assert!(matches!(
+            verify_snapshot_digests(&mut microvm_state, None),
+            Err(SnapshotVerificationError::VmStateMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_check_cpu_compatibility() {
+        // Snapshots without a host CPU fingerprint are not checked.
+        let mut microvm_state = MicrovmState::default();
+        check_cpu_compatibility(&microvm_state).unwrap();
+
+        let host = CpuFingerprint::host();
+        microvm_state.vm_info.host_cpu = host.clone();
+        check_cpu_compatibility(&microvm_state).unwrap();
+
+        // The snapshot host CPU has features this host lacks.
+        microvm_state.vm_info.host_cpu.features = host
+            .features
+            .iter()
+            .map(|word| !word & u64::from(u32::MAX))
+            .collect();
+        assert!(matches!(
+            check_cpu_compatibility(&microvm_state),
+            Err(CpuFingerprintError::MissingFeatures(_))
+        ));
+        // Features hidden from the guest are not required.
+        #[cfg(target_arch = "x86_64")]
+        {
+            microvm_state.vcpu_states.push(VcpuState::default());
+            check_cpu_compatibility(&microvm_state).unwrap();
+        }
+
+        microvm_state.vm_info.host_cpu = CpuFingerprint {
+            vendor: "other".to_string(),
+            ..host
+        };
+        assert!(matches!(
+            check_cpu_compatibility(&microvm_state),
+            Err(CpuFingerprintError::Vendor { .. })
+        ));
+    }
+
+    #[test]
+    fn test_encrypted_snapshot_files() {
+        let key = SnapshotKey::new(&[0x42; 32]).unwrap();
+        let other_key = SnapshotKey::new(&[0x24; 32]).unwrap();
+
+        // State file round trip.
+        let microvm_state = MicrovmState::default();
+        let state_file = TempFile::new().unwrap();
+        snapshot_state_to_file(
+            &microvm_state,
+            state_file.as_path(),
+            &SNAPSHOT_VERSION,
+            Some(&key),
+        )
+        .unwrap();
+        let restored_state = snapshot_state_from_file(state_file.as_path(), Some(&key)).unwrap();
+        assert_eq!(restored_state.vm_info, microvm_state.vm_info);
+        assert!(matches!(
+            snapshot_state_from_file(state_file.as_path(), None),
+            Err(SnapshotStateFromFileError::Encrypted)
+        ));
+        assert!(matches!(
+            snapshot_state_from_file(state_file.as_path(), Some(&other_key)),
+            Err(SnapshotStateFromFileError::Decrypt(_))
+        ));
+
+        // Memory file round trip, with a region larger than a single chunk.
+        let mem_state = GuestMemoryState {
+            regions: vec![
+                GuestMemoryRegionState {
+                    base_address: 0,
+                    size: 0x1000,
+                    offset: 0,
+                },
+                GuestMemoryRegionState {
+                    base_address: 0x10_0000,
+                    size: CHUNK_SIZE + 0x1000,
+                    offset: 0x1000,
+                },
+            ],
+        };
+        let contents: Vec<u8> = (0..CHUNK_SIZE + 0x2000)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect();
+        let mem_file = TempFile::new().unwrap();
+        let mut writer = SealingWriter::new(&key, mem_file.as_file()).unwrap();
+        writer.write_all(&contents).unwrap();
+        writer.finish().unwrap();
+
+        let guest_mem = guest_memory_from_encrypted_file(
+            mem_file.as_path(),
+            &mem_state,
+            false,
+            HugePageConfig::None,
+            &key,
+        )
+        .unwrap();
+        let mut restored = vec![0u8; CHUNK_SIZE + 0x1000];
+        guest_mem
+            .read_slice(&mut restored[..0x1000], GuestAddress(0))
+            .unwrap();
+        assert_eq!(restored[..0x1000], contents[..0x1000]);
+        guest_mem
+            .read_slice(&mut restored, GuestAddress(0x10_0000))
+            .unwrap();
+        assert_eq!(restored, contents[0x1000..]);
+
+        guest_memory_from_encrypted_file(
+            mem_file.as_path(),
+            &mem_state,
+            false,
+            HugePageConfig::None,
+            &other_key,
+        )
+        .unwrap_err();
+    }
+
     #[test]
     fn test_create_guest_memory() {
         let mem_state = GuestMemoryState {
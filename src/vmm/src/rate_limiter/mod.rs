@@ -455,6 +455,42 @@ impl RateLimiter {
         }
     }
 
+    /// Attempts to consume the tokens of one operation of `bytes` bytes, and returns whether that
+    /// is possible.
+    ///
+    /// The tokens are consumed from both buckets or from none: the ops token is given back when
+    /// the bandwidth budget is exhausted.
+    pub fn consume_op(&mut self, bytes: u64) -> bool {
+        if !self.consume(1, TokenType::Ops) {
+            return false;
+        }
+
+        if !self.consume(bytes, TokenType::Bytes) {
+            self.manual_replenish(1, TokenType::Ops);
+            return false;
+        }
+
+        true
+    }
+
+    /// Gives back the tokens of one operation of `bytes` bytes, consumed for an operation that
+    /// could not be completed.
+    pub fn replenish_op(&mut self, bytes: u64) {
+        self.manual_replenish(1, TokenType::Ops);
+        self.manual_replenish(bytes, TokenType::Bytes);
+    }
+
+    /// Refills the buckets according to the time elapsed since they were last used, so that
+    /// their budgets are current.
+    pub fn refresh(&mut self) {
+        for bucket in [self.bandwidth.as_mut(), self.ops.as_mut()]
+            .into_iter()
+            .flatten()
+        {
+            bucket.auto_replenish();
+        }
+    }
+
     /// Returns whether this rate limiter is blocked.
     ///
     /// The limiter 'blocks' when a `consume()` operation fails because there was not enough
@@ -991,6 +1027,33 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_rate_limiter_op() {
+        // rate limiter with limit of 1000 bytes/s and 10 ops/s
+        let mut l = RateLimiter::new(1000, 0, 1000, 10, 0, 1000).unwrap();
+
+        assert!(l.consume_op(600));
+        assert_eq!(l.bandwidth().unwrap().budget(), 400);
+        assert_eq!(l.ops().unwrap().budget(), 9);
+        // The ops token is given back when the bandwidth budget is exhausted.
+        assert!(!l.consume_op(600));
+        assert_eq!(l.bandwidth().unwrap().budget(), 400);
+        assert_eq!(l.ops().unwrap().budget(), 9);
+        assert!(l.is_blocked());
+
+        l.replenish_op(600);
+        assert_eq!(l.bandwidth().unwrap().budget(), 1000);
+        assert_eq!(l.ops().unwrap().budget(), 10);
+
+        // The budgets are only refilled when the buckets are used or refreshed.
+        let mut l = RateLimiter::new(1000, 0, 1000, 0, 0, 0).unwrap();
+        assert!(l.consume(500, TokenType::Bytes));
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(l.bandwidth().unwrap().budget(), 500);
+        l.refresh();
+        assert!(l.bandwidth().unwrap().budget() >= 700);
+    }
+
     #[test]
     fn test_rate_limiter_bandwidth() {
         // rate limiter with limit of 1000 bytes/s
@@ -8,8 +8,12 @@ use std::sync::{Arc, Mutex, MutexGuard};
 use serde::{Deserialize, Serialize};
 use utils::net::ipv4addr::is_link_local_valid;
 
+use crate::arch::MemoryMap;
 use crate::cpu_config::templates::CustomCpuTemplate;
 use crate::device_manager::persist::SharedDeviceType;
+use crate::device_manager::resources::ResourceRequirements;
+use crate::devices::virtio::net::dhcp::DhcpConfig;
+use crate::devices::virtio::remote::RemoteDeviceError;
 use crate::logger::{info, log_dev_preview_warning};
 use crate::mmds;
 use crate::mmds::data_store::{Mmds, MmdsVersion};
@@ -18,15 +22,23 @@ use crate::vmm_config::balloon::*;
 use crate::vmm_config::boot_source::{
     BootConfig, BootSource, BootSourceConfig, BootSourceConfigError,
 };
+use crate::vmm_config::config_batch::{ConfigBatch, ConfigBatchError, DeviceConfig};
+use crate::vmm_config::crypto::*;
+use crate::vmm_config::device_id::{DeviceIdRegistry, DeviceKind};
 use crate::vmm_config::drive::*;
 use crate::vmm_config::entropy::*;
 use crate::vmm_config::instance_info::InstanceInfo;
 use crate::vmm_config::machine_config::{
-    HugePageConfig, MachineConfig, MachineConfigUpdate, VmConfig, VmConfigError,
+    HugePageConfig, MachineConfig, MachineConfigUpdate, MemoryLayoutConfig, VmConfig, VmConfigError,
 };
 use crate::vmm_config::metrics::{init_metrics, MetricsConfig, MetricsConfigError};
-use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
+use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError, MmdsInterfaceConfig};
+use crate::vmm_config::mmio_trace::MmioTraceConfig;
 use crate::vmm_config::net::*;
+use crate::vmm_config::port_forward::{PortForwardConfig, PortForwardConfigError};
+use crate::vmm_config::remote_device::{RemoteDeviceBuilder, RemoteDeviceConfig};
+use crate::vmm_config::replay::ReplayConfig;
+use crate::vmm_config::tpm::{TpmConfig, TpmConfigError};
 use crate::vmm_config::vsock::*;
 
 /// Errors encountered when configuring microVM resources.
@@ -58,6 +70,14 @@ pub enum ResourcesError {
     VsockDevice(#[from] VsockConfigError),
     /// Entropy device error: {0}
     EntropyDevice(#[from] EntropyDeviceError),
+    /// Crypto device error: {0}
+    CryptoDevice(#[from] CryptoDeviceError),
+    /// Remote device error: {0}
+    RemoteDevice(#[from] RemoteDeviceError),
+    /// TPM device error: {0}
+    Tpm(#[from] TpmConfigError),
+    /// Port forwarding error: {0}
+    PortForward(#[from] PortForwardConfigError),
 }
 
 /// Used for configuring a vmm from one single json passed to the Firecracker process.
@@ -65,7 +85,7 @@ pub enum ResourcesError {
 pub struct VmmConfig {
     #[serde(rename = "balloon")]
     balloon_device: Option<BalloonDeviceConfig>,
-    #[serde(rename = "drives")]
+    #[serde(rename = "drives", default)]
     block_devices: Vec<BlockDeviceConfig>,
     #[serde(rename = "boot-source")]
     boot_source: BootSourceConfig,
@@ -83,8 +103,38 @@ pub struct VmmConfig {
     net_devices: Vec<NetworkInterfaceConfig>,
     #[serde(rename = "vsock")]
     vsock_device: Option<VsockDeviceConfig>,
+    #[serde(
+        rename = "vsock-devices",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    vsock_devices: Vec<VsockDeviceConfig>,
     #[serde(rename = "entropy")]
     entropy_device: Option<EntropyDeviceConfig>,
+    #[serde(rename = "crypto", default, skip_serializing_if = "Option::is_none")]
+    crypto_device: Option<CryptoDeviceConfig>,
+    #[serde(
+        rename = "remote-devices",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    remote_devices: Vec<RemoteDeviceConfig>,
+    #[serde(rename = "tpm", default, skip_serializing_if = "Option::is_none")]
+    tpm: Option<TpmConfig>,
+    #[serde(
+        rename = "port-forwards",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    port_forwards: Vec<PortForwardConfig>,
+    #[serde(rename = "replay", default, skip_serializing_if = "Option::is_none")]
+    replay: Option<ReplayConfig>,
+    #[serde(
+        rename = "mmio-trace",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    mmio_trace: Option<MmioTraceConfig>,
 }
 
 /// A data structure that encapsulates the device configurations
@@ -97,7 +147,7 @@ pub struct VmResources {
     pub boot_source: BootSource,
     /// The block devices.
     pub block: BlockBuilder,
-    /// The vsock device.
+    /// The vsock devices.
     pub vsock: VsockBuilder,
     /// The balloon device.
     pub balloon: BalloonBuilder,
@@ -105,14 +155,26 @@ pub struct VmResources {
     pub net_builder: NetBuilder,
     /// The entropy device builder.
     pub entropy: EntropyDeviceBuilder,
+    /// The crypto device builder.
+    pub crypto: CryptoDeviceBuilder,
+    /// The remote devices builder.
+    pub remote_devices: RemoteDeviceBuilder,
     /// The optional Mmds data store.
     // This is initialised on demand (if ever used), so that we don't allocate it unless it's
     // actually used.
     pub mmds: Option<Arc<Mutex<Mmds>>>,
     /// Data store limit for the mmds.
     pub mmds_size_limit: usize,
+    /// JSON file the mmds data store is reloaded from whenever it changes.
+    pub mmds_data_file: Option<PathBuf>,
     /// Whether or not to load boot timer device.
     pub boot_timer: bool,
+    /// The TPM device configuration.
+    pub tpm: Option<TpmConfig>,
+    /// The configuration of the record or the replay of the inputs of the devices.
+    pub replay: Option<ReplayConfig>,
+    /// The virtio devices whose MMIO register accesses are logged once the VM starts.
+    pub mmio_trace: MmioTraceConfig,
 }
 
 impl VmResources {
@@ -159,7 +221,19 @@ impl VmResources {
             resources.build_net_device(net_config)?;
         }
 
+        if !vmm_config.port_forwards.is_empty() {
+            resources.set_port_forwards(vmm_config.port_forwards)?;
+        }
+
         if let Some(vsock_config) = vmm_config.vsock_device {
+            // The deprecated `vsock_id` does not name the default vsock device.
+            resources.set_vsock_device(VsockDeviceConfig {
+                vsock_id: None,
+                ..vsock_config
+            })?;
+        }
+
+        for vsock_config in vmm_config.vsock_devices.into_iter() {
             resources.set_vsock_device(vsock_config)?;
         }
 
@@ -183,6 +257,26 @@ impl VmResources {
             resources.build_entropy_device(entropy_device_config)?;
         }
 
+        if let Some(crypto_device_config) = vmm_config.crypto_device {
+            resources.build_crypto_device(crypto_device_config)?;
+        }
+
+        for remote_device_config in vmm_config.remote_devices.into_iter() {
+            resources.build_remote_device(remote_device_config)?;
+        }
+
+        if let Some(tpm_config) = vmm_config.tpm {
+            resources.set_tpm(tpm_config)?;
+        }
+
+        if let Some(replay_config) = vmm_config.replay {
+            resources.set_replay_config(replay_config);
+        }
+
+        if let Some(mmio_trace_config) = vmm_config.mmio_trace {
+            resources.set_mmio_trace(mmio_trace_config);
+        }
+
         Ok(resources)
     }
 
@@ -229,11 +323,36 @@ impl VmResources {
             SharedDeviceType::Entropy(entropy) => {
                 self.entropy.set_device(entropy);
             }
+            SharedDeviceType::Crypto(crypto) => {
+                self.crypto.set_device(crypto);
+            }
         }
 
         Ok(())
     }
 
+    /// Returns the IDs of the configured block, network, vsock and remote devices, which share a
+    /// single namespace.
+    pub fn device_ids(&self) -> DeviceIdRegistry {
+        let blocks = self.block.devices.iter().map(|dev| {
+            let id = dev.lock().expect("Poisoned lock").id().to_string();
+            (id, DeviceKind::Block)
+        });
+        let nets = self.net_builder.iter().map(|dev| {
+            let id = dev.lock().expect("Poisoned lock").id().clone();
+            (id, DeviceKind::Net)
+        });
+        let vsocks = self.vsock.iter().map(|dev| {
+            let id = dev.lock().expect("Poisoned lock").id().to_string();
+            (id, DeviceKind::Vsock)
+        });
+        let remotes = self.remote_devices.iter().map(|dev| {
+            let id = dev.lock().expect("Poisoned lock").id().to_string();
+            (id, DeviceKind::Remote)
+        });
+        blocks.chain(nets).chain(vsocks).chain(remotes).collect()
+    }
+
     /// Returns whether dirty page tracking is enabled or not.
     pub fn track_dirty_pages(&self) -> bool {
         self.vm_config.track_dirty_pages
@@ -270,6 +389,10 @@ impl VmResources {
             return Err(VmConfigError::BalloonAndHugePages);
         }
 
+        if self.balloon.get().is_some() && updated.confidential.is_some() {
+            return Err(VmConfigError::ConfidentialAndBalloon);
+        }
+
         if self.boot_source.config.initrd_path.is_some()
             && updated.huge_pages != HugePageConfig::None
         {
@@ -281,6 +404,75 @@ impl VmResources {
         Ok(())
     }
 
+    /// Computes the interrupt lines and MMIO address space needed by the configured devices.
+    pub fn resource_requirements(&self) -> ResourceRequirements {
+        let mut requirements = ResourceRequirements::default();
+        if self.boot_timer {
+            requirements.add_mmio_device(0);
+        }
+        if self.tpm.is_some() {
+            requirements.add_mmio_device(0);
+        }
+        let virtio_devices = usize::from(self.balloon.get().is_some())
+            + self.block.devices.len()
+            + self.net_builder.iter().count()
+            + self.vsock.iter().count()
+            + usize::from(self.entropy.get().is_some())
+            + usize::from(self.crypto.get().is_some())
+            + self.remote_devices.iter().count();
+        for _ in 0..virtio_devices {
+            requirements.add_mmio_device(1);
+        }
+        // The VMGenID device.
+        #[cfg(target_arch = "x86_64")]
+        {
+            requirements.gsis += 1;
+        }
+        // The RTC, and the serial console if the kernel command line uses it.
+        #[cfg(target_arch = "aarch64")]
+        {
+            requirements.add_mmio_device(1);
+            let console = self.boot_source_builder().is_some_and(|boot_config| {
+                boot_config
+                    .cmdline
+                    .as_cstring()
+                    .is_ok_and(|cmdline| cmdline.to_string_lossy().contains("console="))
+            });
+            if console {
+                requirements.add_mmio_device(1);
+            }
+        }
+        requirements
+    }
+
+    /// Sizes the MMIO windows of the memory layout for the configured devices. Fails if the
+    /// devices need more interrupt lines or MMIO address space than the microVM provides.
+    pub fn fit_memory_layout(&mut self) -> Result<(), VmConfigError> {
+        self.vm_config.memory_layout = self.fitted_memory_layout()?;
+        Ok(())
+    }
+
+    fn fitted_memory_layout(&self) -> Result<MemoryLayoutConfig, VmConfigError> {
+        let requirements = self.resource_requirements();
+        if requirements.gsis > ResourceRequirements::MAX_GSIS {
+            return Err(VmConfigError::TooManyDeviceInterrupts(
+                requirements.gsis,
+                ResourceRequirements::MAX_GSIS,
+            ));
+        }
+        self.vm_config.memory_layout.fit(&requirements)
+    }
+
+    /// Returns the layout of the guest physical address space of the microVM, with the MMIO
+    /// windows sized for the configured devices.
+    pub fn memory_map(&self) -> Result<MemoryMap, VmConfigError> {
+        let memory_layout = self.fitted_memory_layout()?;
+        Ok(crate::arch::memory_map(
+            self.vm_config.mem_size_mib << 20,
+            &memory_layout,
+        ))
+    }
+
     // Repopulate the MmdsConfig based on information from the data store
     // and the associated net devices.
     fn mmds_config(&self) -> Option<MmdsConfig> {
@@ -300,16 +492,28 @@ impl VmResources {
                 version: mmds.lock().expect("Poisoned lock").version(),
                 network_interfaces: vec![],
                 ipv4_address: None,
+                interfaces: vec![],
+                data_file: self.mmds_data_file.clone(),
             };
 
             for net_dev in net_devs_with_mmds {
                 let net = net_dev.lock().unwrap();
+                // Safe to unwrap the mmds_ns as the filter() explicitly checks for
+                // its existence.
+                let mmds_ns = net.mmds_ns().unwrap();
                 inner_mmds_config.network_interfaces.push(net.id().clone());
-                // Only need to get one ip address, as they will all be equal.
-                if inner_mmds_config.ipv4_address.is_none() {
-                    // Safe to unwrap the mmds_ns as the filter() explicitly checks for
-                    // its existence.
-                    inner_mmds_config.ipv4_address = Some(net.mmds_ns().unwrap().ipv4_addr());
+                // The address of the first interface is the default one, the interfaces using
+                // another address or a namespace get their own settings.
+                let default_ipv4_addr = *inner_mmds_config
+                    .ipv4_address
+                    .get_or_insert(mmds_ns.ipv4_addr());
+                if mmds_ns.ipv4_addr() != default_ipv4_addr || mmds_ns.namespace().is_some() {
+                    inner_mmds_config.interfaces.push(MmdsInterfaceConfig {
+                        iface_id: net.id().clone(),
+                        ipv4_address: Some(mmds_ns.ipv4_addr())
+                            .filter(|ipv4_addr| *ipv4_addr != default_ipv4_addr),
+                        namespace: mmds_ns.namespace().map(str::to_string),
+                    });
                 }
             }
 
@@ -329,6 +533,14 @@ impl VmResources {
         self.boot_source.builder.as_ref()
     }
 
+    /// Sets the host memory pressure policy of the balloon device.
+    pub fn set_memory_pressure_policy(
+        &mut self,
+        config: MemoryPressurePolicyConfig,
+    ) -> Result<(), BalloonConfigError> {
+        self.balloon.set_pressure_policy(config)
+    }
+
     /// Sets a balloon device to be attached when the VM starts.
     pub fn set_balloon_device(
         &mut self,
@@ -344,6 +556,10 @@ impl VmResources {
             return Err(BalloonConfigError::HugePages);
         }
 
+        if self.vm_config.confidential.is_some() {
+            return Err(BalloonConfigError::Confidential);
+        }
+
         self.balloon.set(config)
     }
 
@@ -375,6 +591,9 @@ impl VmResources {
         &mut self,
         block_device_config: BlockDeviceConfig,
     ) -> Result<(), DriveError> {
+        self.device_ids()
+            .register(&block_device_config.drive_id, DeviceKind::Block)
+            .map_err(DriveError::DeviceId)?;
         self.block.insert(block_device_config)
     }
 
@@ -383,12 +602,78 @@ impl VmResources {
         &mut self,
         body: NetworkInterfaceConfig,
     ) -> Result<(), NetworkInterfaceError> {
+        self.device_ids()
+            .register(&body.iface_id, DeviceKind::Net)?;
         let _ = self.net_builder.build(body)?;
         Ok(())
     }
 
-    /// Sets a vsock device to be attached when the VM starts.
+    /// Sets the configuration handed to the guest by the DHCP responder of a network device.
+    pub fn set_net_dhcp_config(
+        &mut self,
+        iface_id: &str,
+        config: DhcpConfig,
+    ) -> Result<(), NetworkInterfaceError> {
+        self.net_builder.set_dhcp_config(iface_id, config)
+    }
+
+    /// Forwards host ports to the guest through the network devices, replacing the previous port
+    /// forwards.
+    pub fn set_port_forwards(
+        &mut self,
+        configs: Vec<PortForwardConfig>,
+    ) -> Result<(), PortForwardConfigError> {
+        for (index, config) in configs.iter().enumerate() {
+            config.validate()?;
+            let host_addr = config.host_socket_addr();
+            if configs[..index]
+                .iter()
+                .any(|other| other.host_socket_addr() == host_addr)
+            {
+                return Err(PortForwardConfigError::DuplicateHostAddress(host_addr));
+            }
+            let net = self
+                .net_builder
+                .iter()
+                .find(|net| net.lock().expect("Poisoned lock").id() == &config.iface_id)
+                .ok_or_else(|| PortForwardConfigError::UnknownInterface(config.iface_id.clone()))?;
+            if net.lock().expect("Poisoned lock").is_worker() {
+                return Err(PortForwardConfigError::WorkerInterface(
+                    config.iface_id.clone(),
+                ));
+            }
+        }
+
+        // Close all the previous listeners first, in case a host port moves to another device.
+        for net in self.net_builder.iter() {
+            net.lock()
+                .expect("Poisoned lock")
+                .set_port_forwards(Vec::new())?;
+        }
+        for net in self.net_builder.iter() {
+            let mut net = net.lock().expect("Poisoned lock");
+            let net_configs: Vec<_> = configs
+                .iter()
+                .filter(|config| &config.iface_id == net.id())
+                .cloned()
+                .collect();
+            net.set_port_forwards(net_configs)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the host ports forwarded to the guest.
+    pub fn port_forwards(&self) -> Vec<PortForwardConfig> {
+        self.net_builder
+            .iter()
+            .flat_map(|net| net.lock().expect("Poisoned lock").port_forwards().to_vec())
+            .collect()
+    }
+
+    /// Sets a vsock device to be attached when the VM starts. The device replaces the one with
+    /// the same ID, if any.
     pub fn set_vsock_device(&mut self, config: VsockDeviceConfig) -> Result<(), VsockConfigError> {
+        self.device_ids().register(config.id(), DeviceKind::Vsock)?;
         self.vsock.insert(config)
     }
 
@@ -400,14 +685,112 @@ impl VmResources {
         self.entropy.insert(body)
     }
 
+    /// Builds a crypto device to be attached when the VM starts.
+    pub fn build_crypto_device(
+        &mut self,
+        body: CryptoDeviceConfig,
+    ) -> Result<(), CryptoDeviceError> {
+        self.crypto.insert(body)
+    }
+
+    /// Sets the TPM device to be attached when the VM starts.
+    pub fn set_tpm(&mut self, config: TpmConfig) -> Result<(), TpmConfigError> {
+        config.validate()?;
+        self.tpm = Some(config);
+        Ok(())
+    }
+
+    /// Sets whether the inputs of the devices are recorded or replayed once the VM starts.
+    pub fn set_replay_config(&mut self, config: ReplayConfig) {
+        self.replay = Some(config);
+    }
+
+    /// Sets the virtio devices whose MMIO register accesses are logged once the VM starts.
+    pub fn set_mmio_trace(&mut self, config: MmioTraceConfig) {
+        self.mmio_trace = config;
+    }
+
+    /// Builds a remote device, connected to its backend, to be attached when the VM starts.
+    pub fn build_remote_device(
+        &mut self,
+        body: RemoteDeviceConfig,
+    ) -> Result<(), RemoteDeviceError> {
+        self.device_ids()
+            .register(&body.id, DeviceKind::Remote)
+            .map_err(RemoteDeviceError::DeviceId)?;
+        let _ = self.remote_devices.build(body)?;
+        Ok(())
+    }
+
+    /// Applies the device configurations of `batch` in order. If one of them fails, the devices
+    /// configured before the batch are restored, so that none of the configurations is applied.
+    pub fn apply_config_batch(&mut self, batch: ConfigBatch) -> Result<(), ConfigBatchError> {
+        batch.validate(&self.device_ids())?;
+
+        // The builders only hold references to the devices, so saving them is cheap.
+        let saved = (
+            self.block.clone(),
+            self.net_builder.clone(),
+            self.vsock.clone(),
+            self.balloon.clone(),
+            self.entropy.clone(),
+            self.crypto.clone(),
+            self.remote_devices.clone(),
+            self.tpm.clone(),
+        );
+        let result = batch
+            .devices
+            .into_iter()
+            .enumerate()
+            .try_for_each(|(index, device)| {
+                self.apply_device_config(device)
+                    .map_err(|err| ConfigBatchError::Device(index, err))
+            });
+        if result.is_err() {
+            (
+                self.block,
+                self.net_builder,
+                self.vsock,
+                self.balloon,
+                self.entropy,
+                self.crypto,
+                self.remote_devices,
+                self.tpm,
+            ) = saved;
+            // The peers of the restored network devices may have been linked to discarded ones.
+            self.net_builder.link_peers();
+        }
+        result
+    }
+
+    fn apply_device_config(&mut self, device: DeviceConfig) -> Result<(), ResourcesError> {
+        match device {
+            DeviceConfig::Drive(config) => self.set_block_device(config)?,
+            DeviceConfig::NetworkInterface(config) => self.build_net_device(config)?,
+            DeviceConfig::Vsock(config) => self.set_vsock_device(config)?,
+            DeviceConfig::Balloon(config) => self.set_balloon_device(config)?,
+            DeviceConfig::Entropy(config) => self.build_entropy_device(config)?,
+            DeviceConfig::Crypto(config) => self.build_crypto_device(config)?,
+            DeviceConfig::RemoteDevice(config) => self.build_remote_device(config)?,
+            DeviceConfig::Tpm(config) => self.set_tpm(config)?,
+        }
+        Ok(())
+    }
+
     /// Setter for mmds config.
     pub fn set_mmds_config(
         &mut self,
         config: MmdsConfig,
         instance_id: &str,
     ) -> Result<(), MmdsConfigError> {
+        if let Some(path) = config.data_file.as_ref() {
+            if path.file_name().is_none() {
+                return Err(MmdsConfigError::InvalidDataFile(path.clone()));
+            }
+        }
         self.set_mmds_network_stack_config(&config)?;
         self.set_mmds_version(config.version, instance_id)?;
+        self.mmds_data_file = config.data_file;
 
         Ok(())
     }
@@ -455,6 +838,36 @@ impl VmResources {
         }) {
             return Err(MmdsConfigError::InvalidNetworkInterfaceId);
         }
+        if let Some(net_device) = self.net_builder.iter().find(|device| {
+            let device = device.lock().expect("Poisoned lock");
+            device.is_worker() && network_interfaces.contains(device.id())
+        }) {
+            let iface_id = net_device.lock().expect("Poisoned lock").id().clone();
+            return Err(MmdsConfigError::WorkerInterface(iface_id));
+        }
+
+        // Check the settings specific to the network interfaces.
+        for (index, iface_config) in config.interfaces.iter().enumerate() {
+            let iface_id = &iface_config.iface_id;
+            if !network_interfaces.contains(iface_id)
+                || config.interfaces[..index]
+                    .iter()
+                    .any(|other| &other.iface_id == iface_id)
+            {
+                return Err(MmdsConfigError::InvalidInterfaceConfig(iface_id.clone()));
+            }
+            if iface_config
+                .ipv4_address
+                .is_some_and(|ipv4_addr| !is_link_local_valid(ipv4_addr))
+            {
+                return Err(MmdsConfigError::InvalidIpv4Addr);
+            }
+            if let Some(namespace) = iface_config.namespace.as_ref() {
+                if namespace.is_empty() || namespace.contains(['/', '~']) {
+                    return Err(MmdsConfigError::InvalidNamespace(namespace.clone()));
+                }
+            }
+        }
 
         // Safe to unwrap because we've just made sure that it's initialised.
         let mmds = self.mmds_or_default().clone();
@@ -465,7 +878,14 @@ impl VmResources {
         for net_device in self.net_builder.iter_mut() {
             let mut net_device_lock = net_device.lock().expect("Poisoned lock");
             if network_interfaces.contains(net_device_lock.id()) {
-                net_device_lock.configure_mmds_network_stack(ipv4_addr, mmds.clone());
+                let iface_config = config.interface_config(net_device_lock.id());
+                let iface_ipv4_addr = iface_config
+                    .and_then(|iface_config| iface_config.ipv4_address)
+                    .unwrap_or(ipv4_addr);
+                let namespace =
+                    iface_config.and_then(|iface_config| iface_config.namespace.clone());
+                net_device_lock.configure_mmds_network_stack(iface_ipv4_addr, mmds.clone());
+                net_device_lock.set_mmds_namespace(namespace);
             } else {
                 net_device_lock.disable_mmds_network_stack();
             }
@@ -488,7 +908,20 @@ impl From<&VmResources> for VmmConfig {
             mmds_config: resources.mmds_config(),
             net_devices: resources.net_builder.configs(),
             vsock_device: resources.vsock.config(),
+            vsock_devices: resources
+                .vsock
+                .configs()
+                .into_iter()
+                .filter(|config| config.vsock_id.is_some())
+                .collect(),
             entropy_device: resources.entropy.config(),
+            crypto_device: resources.crypto.config(),
+            remote_devices: resources.remote_devices.configs(),
+            tpm: resources.tpm.clone(),
+            port_forwards: resources.port_forwards(),
+            replay: resources.replay.clone(),
+            mmio_trace: (!resources.mmio_trace.device_ids.is_empty())
+                .then(|| resources.mmio_trace.clone()),
         }
     }
 }
@@ -497,6 +930,7 @@ impl From<&VmResources> for VmmConfig {
 mod tests {
     use std::fs::File;
     use std::io::Write;
+    use std::net::Ipv4Addr;
     use std::os::linux::fs::MetadataExt;
     use std::str::FromStr;
 
@@ -506,7 +940,9 @@ mod tests {
     use utils::tempfile::TempFile;
 
     use super::*;
+    use crate::arch::MemoryRegionKind;
     use crate::cpu_config::templates::{CpuTemplateType, StaticCpuTemplate};
+    use crate::device_manager::mmio::MMIO_LEN;
     use crate::devices::virtio::balloon::Balloon;
     use crate::devices::virtio::block::virtio::VirtioBlockError;
     use crate::devices::virtio::block::{BlockError, CacheType};
@@ -515,11 +951,16 @@ mod tests {
     use crate::vmm_config::boot_source::{
         BootConfig, BootSource, BootSourceConfig, DEFAULT_KERNEL_CMDLINE,
     };
+    use crate::vmm_config::device_id::DeviceIdError;
     use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig};
-    use crate::vmm_config::machine_config::{HugePageConfig, MachineConfig, VmConfigError};
+    use crate::vmm_config::machine_config::{
+        DirtyTrackingMode, GuestMemoryRange, HugePageConfig, MachineConfig, MemoryBackendType,
+        MemoryLayoutConfig, VirtioFeaturePolicy, VmConfigError,
+    };
     use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vmm_config::RateLimiterConfig;
+    use crate::vstate::confidential::{ConfidentialConfig, ConfidentialTechnology};
     use crate::HTTP_MAX_PAYLOAD_SIZE;
 
     fn default_net_cfg() -> NetworkInterfaceConfig {
@@ -536,6 +977,13 @@ mod tests {
             guest_mac: Some(MacAddr::from_str("01:23:45:67:89:0a").unwrap()),
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
+            peer: None,
+            dma_ranges: None,
+            rx_interrupt_coalescing: None,
+            tx_interrupt_coalescing: None,
+            worker: false,
+            dhcp: None,
+            xdp: None,
         }
     }
 
@@ -559,8 +1007,11 @@ mod tests {
                 path_on_host: Some(tmp_file.as_path().to_str().unwrap().to_string()),
                 rate_limiter: Some(RateLimiterConfig::default()),
                 file_engine_type: None,
+                overlay_path: None,
 
                 socket: None,
+                reconnect: None,
+                worker: false,
             },
             tmp_file,
         )
@@ -583,6 +1034,7 @@ mod tests {
                 cmdline: kernel_cmdline,
                 kernel_file: File::open(tmp_file.as_path()).unwrap(),
                 initrd_file: Some(File::open(tmp_file.as_path()).unwrap()),
+                crash_kernel_size_mib: None,
             }),
         }
     }
@@ -598,7 +1050,13 @@ mod tests {
             mmds: None,
             boot_timer: false,
             mmds_size_limit: HTTP_MAX_PAYLOAD_SIZE,
+            mmds_data_file: None,
             entropy: Default::default(),
+            crypto: Default::default(),
+            remote_devices: Default::default(),
+            tpm: None,
+            replay: None,
+            mmio_trace: Default::default(),
         }
     }
 
@@ -935,6 +1393,50 @@ mod tests {
             error
         );
 
+        // Remote devices can't take the type of a device emulated by Firecracker.
+        json = format!(
+            r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}",
+                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                    }},
+                    "drives": [
+                        {{
+                            "drive_id": "rootfs",
+                            "path_on_host": "{}",
+                            "is_root_device": true,
+                            "is_read_only": false
+                        }}
+                    ],
+                    "remote-devices": [
+                        {{
+                            "id": "remote0",
+                            "device_type": 2,
+                            "socket": "/invalid/sock"
+                        }}
+                    ]
+            }}"#,
+            kernel_file.as_path().to_str().unwrap(),
+            rootfs_file.as_path().to_str().unwrap()
+        );
+
+        let error = VmResources::from_json(
+            json.as_str(),
+            &default_instance_info,
+            HTTP_MAX_PAYLOAD_SIZE,
+            None,
+        )
+        .unwrap_err();
+
+        assert!(
+            matches!(
+                error,
+                ResourcesError::RemoteDevice(RemoteDeviceError::DeviceType(2))
+            ),
+            "{:?}",
+            error
+        );
+
         // Let's try now passing a valid configuration. We won't include any logger
         // or metrics configuration because these were already initialized in other
         // tests of this module and the reinitialization of them will cause crashing.
@@ -1036,6 +1538,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_from_json_without_drives() {
+        // The kernel and its initramfs can be the entire system.
+        let kernel_file = TempFile::new().unwrap();
+        let initrd_file = TempFile::new().unwrap();
+        let json = format!(
+            r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}",
+                        "initrd_path": "{}",
+                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                    }}
+            }}"#,
+            kernel_file.as_path().to_str().unwrap(),
+            initrd_file.as_path().to_str().unwrap(),
+        );
+
+        let vm_resources = VmResources::from_json(
+            json.as_str(),
+            &InstanceInfo::default(),
+            HTTP_MAX_PAYLOAD_SIZE,
+            None,
+        )
+        .unwrap();
+        assert!(vm_resources.block.devices.is_empty());
+        assert!(!vm_resources.block.has_root_device());
+    }
+
     #[test]
     fn test_cpu_config_from_invalid_json() {
         // Invalid cpu config file path.
@@ -1162,7 +1692,8 @@ mod tests {
                         "mem_size_mib": 1024,
                         "smt": false
                     }},
-                    "entropy": {{}}
+                    "entropy": {{}},
+                    "crypto": {{}}
             }}"#,
                 kernel_file.as_path().to_str().unwrap(),
                 rootfs_file.as_path().to_str().unwrap(),
@@ -1317,6 +1848,73 @@ mod tests {
             let vmm_config: VmmConfig = (&resources).into();
             assert_eq!(initial_vmm_config, vmm_config);
         }
+
+        // Interfaces with their own MMDS settings.
+        {
+            let kernel_file = TempFile::new().unwrap();
+            let rootfs_file = TempFile::new().unwrap();
+            let json = format!(
+                r#"{{
+                    "balloon": {{
+                        "amount_mib": 0,
+                        "deflate_on_oom": false,
+                        "stats_polling_interval_s": 0
+                    }},
+                    "boot-source": {{
+                        "kernel_image_path": "{}",
+                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                    }},
+                    "drives": [
+                        {{
+                            "drive_id": "rootfs",
+                            "path_on_host": "{}",
+                            "is_root_device": true,
+                            "is_read_only": false,
+                            "io_engine": "Sync"
+                        }}
+                    ],
+                    "network-interfaces": [
+                        {{
+                            "iface_id": "netif1",
+                            "host_dev_name": "hostname9"
+                        }},
+                        {{
+                            "iface_id": "netif2",
+                            "host_dev_name": "hostname10"
+                        }}
+                    ],
+                    "machine-config": {{
+                        "vcpu_count": 2,
+                        "mem_size_mib": 1024,
+                        "smt": false
+                    }},
+                    "mmds-config": {{
+                        "network_interfaces": ["netif1", "netif2"],
+                        "ipv4_address": "169.254.1.1",
+                        "interfaces": [
+                            {{
+                                "iface_id": "netif2",
+                                "ipv4_address": "169.254.1.2",
+                                "namespace": "tenant"
+                            }}
+                        ]
+                    }}
+            }}"#,
+                kernel_file.as_path().to_str().unwrap(),
+                rootfs_file.as_path().to_str().unwrap(),
+            );
+            let resources = VmResources::from_json(
+                json.as_str(),
+                &InstanceInfo::default(),
+                HTTP_MAX_PAYLOAD_SIZE,
+                None,
+            )
+            .unwrap();
+
+            let initial_vmm_config = serde_json::from_str::<VmmConfig>(&json).unwrap();
+            let vmm_config: VmmConfig = (&resources).into();
+            assert_eq!(initial_vmm_config, vmm_config);
+        }
     }
 
     #[test]
@@ -1331,7 +1929,24 @@ mod tests {
             #[cfg(target_arch = "aarch64")]
             cpu_template: Some(StaticCpuTemplate::V1N1),
             track_dirty_pages: Some(false),
+            dirty_tracking_mode: Some(DirtyTrackingMode::Auto),
             huge_pages: Some(HugePageConfig::None),
+            memory_backend: Some(MemoryBackendType::Auto),
+            mem_populate: Some(true),
+            mem_prefault_ranges: Some(vec![GuestMemoryRange {
+                guest_addr: 0,
+                len: 4096,
+            }]),
+            mem_mergeable: Some(true),
+            mem_mergeable_ranges: Some(vec![GuestMemoryRange {
+                guest_addr: 4096,
+                len: 4096,
+            }]),
+            confidential: None,
+            memory_layout: Some(MemoryLayoutConfig::default()),
+            virtio_feature_policy: Some(VirtioFeaturePolicy::default()),
+            vcpu_threads: Some(Vec::new()),
+            pmu: Some(false),
         };
 
         assert_ne!(
@@ -1344,6 +1959,48 @@ mod tests {
             aux_vm_config
         );
 
+        // Invalid pre-fault ranges.
+        aux_vm_config.mem_prefault_ranges = Some(vec![GuestMemoryRange {
+            guest_addr: 0,
+            len: 0,
+        }]);
+        assert_eq!(
+            vm_resources.update_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidMemoryRange)
+        );
+        aux_vm_config.mem_prefault_ranges = Some(vec![GuestMemoryRange {
+            guest_addr: u64::MAX,
+            len: 2,
+        }]);
+        assert_eq!(
+            vm_resources.update_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidMemoryRange)
+        );
+        aux_vm_config.mem_prefault_ranges = None;
+        aux_vm_config.mem_mergeable_ranges = Some(vec![GuestMemoryRange {
+            guest_addr: 0,
+            len: 0,
+        }]);
+        assert_eq!(
+            vm_resources.update_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidMemoryRange)
+        );
+        aux_vm_config.mem_mergeable_ranges = None;
+
+        // KSM does not merge shared or huge page backed memory.
+        aux_vm_config.memory_backend = Some(MemoryBackendType::Memfd);
+        assert_eq!(
+            vm_resources.update_vm_config(&aux_vm_config),
+            Err(VmConfigError::MergeableSharedMemory)
+        );
+        aux_vm_config.memory_backend = Some(MemoryBackendType::Auto);
+        aux_vm_config.huge_pages = Some(HugePageConfig::Hugetlbfs2M);
+        assert_eq!(
+            vm_resources.update_vm_config(&aux_vm_config),
+            Err(VmConfigError::MergeableSharedMemory)
+        );
+        aux_vm_config.huge_pages = Some(HugePageConfig::None);
+
         // Invalid vcpu count.
         aux_vm_config.vcpu_count = Some(0);
         assert_eq!(
@@ -1402,6 +2059,8 @@ mod tests {
         vm_resources.update_vm_config(&aux_vm_config).unwrap();
 
         // mem_size_mib incompatible with huge pages configuration
+        aux_vm_config.mem_mergeable = Some(false);
+        aux_vm_config.mem_mergeable_ranges = Some(Vec::new());
         aux_vm_config.mem_size_mib = Some(129);
         aux_vm_config.huge_pages = Some(HugePageConfig::Hugetlbfs2M);
         assert_eq!(
@@ -1452,6 +2111,45 @@ mod tests {
             .unwrap_err();
     }
 
+    #[test]
+    fn test_confidential_balloon_device() {
+        let balloon_cfg = BalloonDeviceConfig {
+            amount_mib: 100,
+            deflate_on_oom: false,
+            stats_polling_interval_s: 0,
+        };
+
+        let mut vm_resources = default_vm_resources();
+        vm_resources.balloon = BalloonBuilder::new();
+        vm_resources.vm_config.confidential = Some(ConfidentialConfig {
+            technology: ConfidentialTechnology::SevSnp,
+            policy: 0,
+        });
+        assert!(matches!(
+            vm_resources.set_balloon_device(balloon_cfg.clone()),
+            Err(BalloonConfigError::Confidential)
+        ));
+        assert!(vm_resources.balloon.get().is_none());
+
+        let mut vm_resources = default_vm_resources();
+        vm_resources.balloon = BalloonBuilder::new();
+        vm_resources.set_balloon_device(balloon_cfg).unwrap();
+        if ConfidentialTechnology::SevSnp.is_supported() {
+            assert_eq!(
+                vm_resources
+                    .update_vm_config(&MachineConfigUpdate {
+                        confidential: Some(ConfidentialConfig {
+                            technology: ConfidentialTechnology::SevSnp,
+                            policy: 0,
+                        }),
+                        ..Default::default()
+                    })
+                    .unwrap_err(),
+                VmConfigError::ConfidentialAndBalloon
+            );
+        }
+    }
+
     #[test]
     fn test_negative_restore_balloon_device_with_huge_pages() {
         if KernelVersion::get().unwrap() >= KernelVersion::new(4, 16, 0) {
@@ -1494,6 +2192,19 @@ mod tests {
         assert_eq!(actual_entropy_cfg, entropy_device_cfg);
     }
 
+    #[test]
+    fn test_set_crypto_device() {
+        let mut vm_resources = default_vm_resources();
+        let crypto_device_cfg = CryptoDeviceConfig::default();
+
+        assert!(vm_resources.crypto.get().is_none());
+        vm_resources
+            .build_crypto_device(crypto_device_cfg.clone())
+            .unwrap();
+
+        assert_eq!(vm_resources.crypto.config().unwrap(), crypto_device_cfg);
+    }
+
     #[test]
     fn test_boot_config() {
         let vm_resources = default_vm_resources();
@@ -1511,6 +2222,7 @@ mod tests {
             kernel_image_path: String::from(tmp_file.as_path().to_str().unwrap()),
             initrd_path: Some(String::from(tmp_file.as_path().to_str().unwrap())),
             boot_args: Some(cmdline.to_string()),
+            crash_kernel_size_mib: None,
         };
 
         let mut vm_resources = default_vm_resources();
@@ -1566,6 +2278,72 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_fit_memory_layout() {
+        let mut vm_resources = default_vm_resources();
+        // The balloon, block and net devices, plus the VMGenID device.
+        let requirements = vm_resources.resource_requirements();
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            requirements,
+            ResourceRequirements {
+                gsis: 4,
+                mmio: 3 * MMIO_LEN,
+                mmio64: 0,
+            }
+        );
+        assert_eq!(requirements.mmio64, 0);
+        vm_resources.fit_memory_layout().unwrap();
+        assert_eq!(
+            vm_resources.vm_config.memory_layout,
+            MemoryLayoutConfig::default()
+        );
+
+        // Add block devices until they need more interrupt lines than available.
+        let mut files = Vec::new();
+        while vm_resources.resource_requirements().gsis <= ResourceRequirements::MAX_GSIS {
+            let (mut block_cfg, file) = default_block_cfg();
+            block_cfg.drive_id = format!("block{}", files.len() + 2);
+            vm_resources.set_block_device(block_cfg).unwrap();
+            files.push(file);
+        }
+        assert_eq!(
+            vm_resources.fit_memory_layout().unwrap_err(),
+            VmConfigError::TooManyDeviceInterrupts(
+                ResourceRequirements::MAX_GSIS + 1,
+                ResourceRequirements::MAX_GSIS
+            )
+        );
+        assert_eq!(
+            vm_resources.memory_map().unwrap_err(),
+            VmConfigError::TooManyDeviceInterrupts(
+                ResourceRequirements::MAX_GSIS + 1,
+                ResourceRequirements::MAX_GSIS
+            )
+        );
+    }
+
+    #[test]
+    fn test_memory_map() {
+        let mut vm_resources = default_vm_resources();
+        let map = vm_resources.memory_map().unwrap();
+        let ram: u64 = map
+            .regions
+            .iter()
+            .filter(|region| region.kind == MemoryRegionKind::Ram)
+            .map(|region| region.len)
+            .sum();
+        assert_eq!(ram, (vm_resources.vm_config.mem_size_mib as u64) << 20);
+        assert!(map
+            .regions
+            .windows(2)
+            .all(|pair| pair[0].base <= pair[1].base));
+
+        // The map does not depend on whether the memory layout was fitted already.
+        vm_resources.fit_memory_layout().unwrap();
+        assert_eq!(vm_resources.memory_map().unwrap(), map);
+    }
+
     #[test]
     fn test_set_block_device() {
         let mut vm_resources = default_vm_resources();
@@ -1578,15 +2356,59 @@ mod tests {
         assert_eq!(vm_resources.block.devices.len(), 2);
     }
 
+    #[test]
+    fn test_apply_config_batch() {
+        let mut vm_resources = default_vm_resources();
+        let (mut block_cfg, _file) = default_block_cfg();
+        block_cfg.drive_id = "block2".to_string();
+        let mut invalid_block_cfg = block_cfg.clone();
+        invalid_block_cfg.drive_id = "block3".to_string();
+        invalid_block_cfg.path_on_host = Some("/invalid/path".to_string());
+
+        // A failed device restores the devices configured before the batch.
+        let batch = ConfigBatch {
+            devices: vec![
+                DeviceConfig::Drive(block_cfg.clone()),
+                DeviceConfig::Entropy(EntropyDeviceConfig::default()),
+                DeviceConfig::Drive(invalid_block_cfg),
+            ],
+        };
+        assert!(matches!(
+            vm_resources.apply_config_batch(batch),
+            Err(ConfigBatchError::Device(2, ResourcesError::BlockDevice(_)))
+        ));
+        assert_eq!(vm_resources.block.devices.len(), 1);
+        assert!(vm_resources.entropy.get().is_none());
+
+        // The configured network devices cannot be replaced in a batch.
+        let batch = ConfigBatch {
+            devices: vec![DeviceConfig::NetworkInterface(default_net_cfg())],
+        };
+        assert!(matches!(
+            vm_resources.apply_config_batch(batch),
+            Err(ConfigBatchError::Replace(0, DeviceKind::Net, _))
+        ));
+
+        let batch = ConfigBatch {
+            devices: vec![
+                DeviceConfig::Drive(block_cfg),
+                DeviceConfig::Entropy(EntropyDeviceConfig::default()),
+            ],
+        };
+        vm_resources.apply_config_batch(batch).unwrap();
+        assert_eq!(vm_resources.block.devices.len(), 2);
+        assert!(vm_resources.entropy.get().is_some());
+    }
+
     #[test]
     fn test_set_vsock_device() {
         let mut vm_resources = default_vm_resources();
         let mut tmp_sock_file = TempFile::new().unwrap();
         tmp_sock_file.remove().unwrap();
         let new_vsock_cfg = default_config(&tmp_sock_file);
-        assert!(vm_resources.vsock.get().is_none());
+        assert!(vm_resources.vsock.iter().next().is_none());
         vm_resources.set_vsock_device(new_vsock_cfg).unwrap();
-        let actual_vsock_cfg = vm_resources.vsock.get().unwrap();
+        let actual_vsock_cfg = vm_resources.vsock.iter().next().unwrap();
         assert_eq!(actual_vsock_cfg.lock().unwrap().id(), VSOCK_DEV_ID);
     }
 
@@ -1604,4 +2426,188 @@ mod tests {
         vm_resources.build_net_device(new_net_device_cfg).unwrap();
         assert_eq!(vm_resources.net_builder.len(), 2);
     }
+
+    #[test]
+    fn test_device_ids() {
+        let mut vm_resources = default_vm_resources();
+        let device_ids = vm_resources.device_ids();
+        assert_eq!(device_ids.get("block1"), Some(DeviceKind::Block));
+        assert_eq!(device_ids.get("net_if1"), Some(DeviceKind::Net));
+        assert_eq!(device_ids.get(VSOCK_DEV_ID), None);
+
+        // The IDs are unique across the kinds of devices.
+        let (mut block_cfg, _file) = default_block_cfg();
+        block_cfg.drive_id = "net_if1".to_string();
+        assert_eq!(
+            vm_resources
+                .set_block_device(block_cfg)
+                .unwrap_err()
+                .to_string(),
+            "Invalid drive ID: The device ID net_if1 is already used by a network device."
+        );
+        let mut net_cfg = default_net_cfg();
+        net_cfg.iface_id = "block1".to_string();
+        assert!(matches!(
+            vm_resources.build_net_device(net_cfg),
+            Err(NetworkInterfaceError::DeviceId(DeviceIdError::InUse(
+                _,
+                DeviceKind::Block
+            )))
+        ));
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let mut vsock_cfg = default_config(&tmp_sock_file);
+        vsock_cfg.vsock_id = Some("block1".to_string());
+        assert!(matches!(
+            vm_resources.set_vsock_device(vsock_cfg),
+            Err(VsockConfigError::DeviceId(DeviceIdError::InUse(
+                _,
+                DeviceKind::Block
+            )))
+        ));
+
+        // The IDs follow the same rules whatever the kind of device.
+        let (mut block_cfg, _file) = default_block_cfg();
+        block_cfg.drive_id = "block-2".to_string();
+        assert!(matches!(
+            vm_resources.set_block_device(block_cfg),
+            Err(DriveError::DeviceId(DeviceIdError::InvalidCharacters(_)))
+        ));
+        assert_eq!(vm_resources.block.devices.len(), 1);
+        assert_eq!(vm_resources.net_builder.len(), 1);
+        assert!(vm_resources.vsock.iter().next().is_none());
+    }
+
+    #[test]
+    fn test_set_mmds_interface_config() {
+        let mut vm_resources = default_vm_resources();
+        let mut net_cfg = default_net_cfg();
+        net_cfg.iface_id = "net_if2".to_string();
+        net_cfg.host_dev_name = "if_name2".to_string();
+        net_cfg.guest_mac = None;
+        vm_resources.build_net_device(net_cfg).unwrap();
+
+        let mut mmds_config = MmdsConfig {
+            version: MmdsVersion::V2,
+            network_interfaces: vec!["net_if1".to_string(), "net_if2".to_string()],
+            ipv4_address: None,
+            interfaces: vec![MmdsInterfaceConfig {
+                iface_id: "net_if2".to_string(),
+                ipv4_address: Some(Ipv4Addr::new(169, 254, 1, 2)),
+                namespace: Some("tenant".to_string()),
+            }],
+            data_file: Some(PathBuf::from("/run/metadata.json")),
+        };
+        vm_resources
+            .set_mmds_config(mmds_config.clone(), "instance")
+            .unwrap();
+        for net in vm_resources.net_builder.iter() {
+            let net = net.lock().unwrap();
+            let mmds_ns = net.mmds_ns().unwrap();
+            if net.id() == "net_if1" {
+                assert_eq!(mmds_ns.ipv4_addr(), MmdsNetworkStack::default_ipv4_addr());
+                assert_eq!(mmds_ns.namespace(), None);
+            } else {
+                assert_eq!(mmds_ns.ipv4_addr(), Ipv4Addr::new(169, 254, 1, 2));
+                assert_eq!(mmds_ns.namespace(), Some("tenant"));
+            }
+        }
+        mmds_config.ipv4_address = Some(MmdsNetworkStack::default_ipv4_addr());
+        assert_eq!(vm_resources.mmds_config(), Some(mmds_config.clone()));
+
+        // The settings of an interface are given only once, for an interface using MMDS.
+        let mut invalid_config = mmds_config.clone();
+        invalid_config
+            .interfaces
+            .push(invalid_config.interfaces[0].clone());
+        assert!(matches!(
+            vm_resources.set_mmds_config(invalid_config, "instance"),
+            Err(MmdsConfigError::InvalidInterfaceConfig(id)) if id == "net_if2"
+        ));
+        let mut invalid_config = mmds_config.clone();
+        invalid_config.network_interfaces = vec!["net_if1".to_string()];
+        assert!(matches!(
+            vm_resources.set_mmds_config(invalid_config, "instance"),
+            Err(MmdsConfigError::InvalidInterfaceConfig(id)) if id == "net_if2"
+        ));
+
+        let mut invalid_config = mmds_config.clone();
+        invalid_config.interfaces[0].ipv4_address = Some(Ipv4Addr::new(10, 0, 0, 1));
+        assert!(matches!(
+            vm_resources.set_mmds_config(invalid_config, "instance"),
+            Err(MmdsConfigError::InvalidIpv4Addr)
+        ));
+
+        let mut invalid_config = mmds_config.clone();
+        invalid_config.data_file = Some(PathBuf::from("/"));
+        assert!(matches!(
+            vm_resources.set_mmds_config(invalid_config, "instance"),
+            Err(MmdsConfigError::InvalidDataFile(_))
+        ));
+
+        for namespace in ["", "a/b", "a~b"] {
+            let mut invalid_config = mmds_config.clone();
+            invalid_config.interfaces[0].namespace = Some(namespace.to_string());
+            assert!(matches!(
+                vm_resources.set_mmds_config(invalid_config, "instance"),
+                Err(MmdsConfigError::InvalidNamespace(ns)) if ns == namespace
+            ));
+        }
+
+        // Without its own settings, an interface gets the default ones back.
+        mmds_config.interfaces.clear();
+        vm_resources
+            .set_mmds_config(mmds_config, "instance")
+            .unwrap();
+        for net in vm_resources.net_builder.iter() {
+            let net = net.lock().unwrap();
+            let mmds_ns = net.mmds_ns().unwrap();
+            assert_eq!(mmds_ns.ipv4_addr(), MmdsNetworkStack::default_ipv4_addr());
+            assert_eq!(mmds_ns.namespace(), None);
+        }
+    }
+
+    #[test]
+    fn test_set_port_forwards() {
+        let mut vm_resources = default_vm_resources();
+        let host_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = PortForwardConfig {
+            iface_id: "net_if1".to_string(),
+            host_address: Ipv4Addr::LOCALHOST,
+            host_port,
+            guest_address: Ipv4Addr::new(192, 168, 0, 2),
+            guest_port: 80,
+        };
+        vm_resources
+            .set_port_forwards(vec![config.clone()])
+            .unwrap();
+        assert_eq!(vm_resources.port_forwards(), vec![config.clone()]);
+        assert_eq!(
+            VmmConfig::from(&vm_resources).port_forwards,
+            vec![config.clone()]
+        );
+        // The same ports can be forwarded again.
+        vm_resources
+            .set_port_forwards(vec![config.clone()])
+            .unwrap();
+
+        let mut unknown_iface = config.clone();
+        unknown_iface.iface_id = "net_if2".to_string();
+        assert!(matches!(
+            vm_resources.set_port_forwards(vec![unknown_iface]),
+            Err(PortForwardConfigError::UnknownInterface(id)) if id == "net_if2"
+        ));
+        assert!(matches!(
+            vm_resources.set_port_forwards(vec![config.clone(), config.clone()]),
+            Err(PortForwardConfigError::DuplicateHostAddress(_))
+        ));
+        assert_eq!(vm_resources.port_forwards(), vec![config]);
+
+        vm_resources.set_port_forwards(Vec::new()).unwrap();
+        assert!(vm_resources.port_forwards().is_empty());
+    }
 }
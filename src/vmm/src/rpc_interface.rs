@@ -3,50 +3,80 @@
 
 use std::fmt::{self, Debug};
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 
 use seccompiler::BpfThreadMap;
 use serde_json::Value;
 #[cfg(test)]
 use tests::{
-    build_and_boot_microvm, create_snapshot, restore_from_snapshot, MockVmRes as VmResources,
-    MockVmm as Vmm,
+    build_and_boot_microvm, create_snapshot, create_vmcore, restore_from_snapshot,
+    MockVmRes as VmResources, MockVmm as Vmm,
 };
 
 use super::VmmError;
 #[cfg(not(test))]
 use super::{
     builder::build_and_boot_microvm, persist::create_snapshot, persist::restore_from_snapshot,
-    resources::VmResources, Vmm,
+    resources::VmResources, vmcore::create_vmcore, Vmm,
 };
+use crate::arch::MemoryMap;
 use crate::builder::StartMicrovmError;
 use crate::cpu_config::templates::{CustomCpuTemplate, GuestConfigError};
+use crate::devices::virtio::features::VirtioDeviceFeatures;
+use crate::devices::virtio::net::dhcp::DhcpConfig;
+use crate::devices::virtio::remote::RemoteDeviceError;
+use crate::devices::virtio::trace::{self, TraceSpan};
+use crate::devices::DeviceRegions;
+use crate::lifecycle::{notify_lifecycle_event, LifecycleEvent};
 use crate::logger::{info, warn, LoggerConfig, *};
 use crate::mmds::data_store::{self, Mmds};
 use crate::persist::{CreateSnapshotError, RestoreFromSnapshotError, VmInfo};
 use crate::resources::VmmConfig;
+use crate::vmcore::CreateVmcoreError;
 use crate::vmm_config::balloon::{
-    BalloonConfigError, BalloonDeviceConfig, BalloonStats, BalloonUpdateConfig,
-    BalloonUpdateStatsConfig,
+    BalloonConfigError, BalloonDeviceConfig, BalloonStats, BalloonStatsSample, BalloonUpdateConfig,
+    BalloonUpdateStatsConfig, MemoryPressurePolicyConfig,
 };
 use crate::vmm_config::boot_source::{BootSourceConfig, BootSourceConfigError};
+use crate::vmm_config::config_batch::{ConfigBatch, ConfigBatchError};
+use crate::vmm_config::crypto::{CryptoDeviceConfig, CryptoDeviceError};
 use crate::vmm_config::drive::{BlockDeviceConfig, BlockDeviceUpdateConfig, DriveError};
-use crate::vmm_config::entropy::{EntropyDeviceConfig, EntropyDeviceError};
-use crate::vmm_config::instance_info::InstanceInfo;
+use crate::vmm_config::entropy::{
+    EntropyDeviceConfig, EntropyDeviceError, EntropyDeviceUpdateConfig,
+};
+use crate::vmm_config::fault_injection::{
+    FaultInjectionConfig, FaultInjectionError, FAULT_INJECTION_ENABLED,
+};
+use crate::vmm_config::instance_info::{InstanceInfo, VmState};
 use crate::vmm_config::machine_config::{MachineConfig, MachineConfigUpdate, VmConfigError};
 use crate::vmm_config::metrics::{MetricsConfig, MetricsConfigError};
 use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
+use crate::vmm_config::mmio_trace::{MmioTraceConfig, MmioTraceError};
 use crate::vmm_config::net::{
-    NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceUpdateConfig,
+    LinkState, NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceLinkConfig,
+    NetworkInterfaceRateLimiterStats, NetworkInterfaceUpdateConfig,
 };
-use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
+use crate::vmm_config::port_forward::{PortForwardConfig, PortForwardConfigError};
+use crate::vmm_config::remote_device::RemoteDeviceConfig;
+use crate::vmm_config::replay::ReplayConfig;
+use crate::vmm_config::snapshot::{
+    CreateSnapshotParams, LoadSnapshotParams, PauseMode, ResumeTimeHandling, SnapshotType,
+};
+use crate::vmm_config::tpm::{TpmConfig, TpmConfigError};
+use crate::vmm_config::vmcore::CreateVmcoreParams;
+use crate::vmm_config::vmm_info::VmmInfo;
 use crate::vmm_config::vsock::{VsockConfigError, VsockDeviceConfig};
 use crate::vmm_config::{self, RateLimiterUpdate};
-use crate::EventManager;
+use crate::vstate::vcpu::VcpuRegisters;
+use crate::{DeviceFeaturesError, EventManager, VcpuRegistersError};
 
 /// This enum represents the public interface of the VMM. Each action contains various
 /// bits of information (ids, paths, etc.).
 #[derive(Debug, PartialEq, Eq)]
 pub enum VmmAction {
+    /// Apply the device configurations of the `ConfigBatch` together, or none of them if one
+    /// fails. This action can only be called before the microVM has booted.
+    ApplyConfigBatch(ConfigBatch),
     /// Configure the boot source of the microVM using as input the `ConfigureBootSource`. This
     /// action can only be called before the microVM has booted.
     ConfigureBootSource(BootSourceConfig),
@@ -59,22 +89,53 @@ pub enum VmmAction {
     /// Create a snapshot using as input the `CreateSnapshotParams`. This action can only be called
     /// after the microVM has booted and only when the microVM is in `Paused` state.
     CreateSnapshot(CreateSnapshotParams),
+    /// Write the guest memory to an ELF core file using as input the `CreateVmcoreParams`. This
+    /// action can only be called after the microVM has booted and only when the microVM is in
+    /// `Paused` state.
+    CreateVmcore(CreateVmcoreParams),
+    /// Get the traced spans of the virtio datapath. This action can only be called after the
+    /// microVM has booted, on Firecracker built with the `virtio-trace` feature.
+    DumpVirtioTrace,
+    /// Start or stop injecting a host fault into the backend of a device. This action can only be
+    /// called after the microVM has booted, on Firecracker built with the `fault-injection`
+    /// feature.
+    InjectFault(FaultInjectionConfig),
     /// Get the balloon device configuration.
     GetBalloonConfig,
     /// Get the ballon device latest statistics.
     GetBalloonStats,
+    /// Get up to the given number of the most recent balloon device statistics reports.
+    GetBalloonStatsHistory(usize),
+    /// Get the address ranges of the devices registered on the guest buses.
+    GetDevices,
+    /// Get the features offered by the virtio device with the given ID and acknowledged by the
+    /// guest driver. This action can only be called after the microVM has booted.
+    GetDeviceFeatures(String),
     /// Get complete microVM configuration in JSON format.
     GetFullVmConfig,
     /// Get MMDS contents.
     GetMMDS,
+    /// Get the live state of the RX and TX rate limiters of the network interface with the given
+    /// ID. This action can only be called after the microVM has booted.
+    GetNetworkInterfaceRateLimiters(String),
     /// Get the machine configuration of the microVM.
     GetVmMachineConfig,
+    /// Get the layout of the guest physical address space of the microVM.
+    GetMemoryMap,
     /// Get microVM instance information.
     GetVmInstanceInfo,
     /// Get microVM version.
     GetVmmVersion,
+    /// Get information about the VMM process.
+    GetVmmInfo,
+    /// Get the register state of the vCPU with the given index. This action can only be called
+    /// after the microVM has booted and only when the microVM is in `Paused` state.
+    GetVcpuRegisters(u8),
     /// Flush the metrics. This action can only be called after the logger has been configured.
     FlushMetrics,
+    /// Discard what the network devices counted since the metrics were last flushed. This action
+    /// can only be called after the microVM has booted.
+    ResetNetMetrics,
     /// Add a new block device or update one that already exists using the `BlockDeviceConfig` as
     /// input. This action can only be called before the microVM has booted.
     InsertBlockDevice(BlockDeviceConfig),
@@ -82,26 +143,50 @@ pub enum VmmAction {
     /// `NetworkInterfaceConfig` as input. This action can only be called before the microVM has
     /// booted.
     InsertNetworkDevice(NetworkInterfaceConfig),
+    /// Add a new remote device or replace one that already exists using the
+    /// `RemoteDeviceConfig` as input. This action can only be called before the microVM has
+    /// booted.
+    InsertRemoteDevice(RemoteDeviceConfig),
     /// Load the microVM state using as input the `LoadSnapshotParams`. This action can only be
     /// called before the microVM has booted. If this action is successful, the loaded microVM will
     /// be in `Paused` state. Should change this state to `Resumed` for the microVM to run.
     LoadSnapshot(LoadSnapshotParams),
     /// Partial update of the MMDS contents.
     PatchMMDS(Value),
-    /// Pause the guest, by pausing the microVM VCPUs.
-    Pause,
+    /// Pause the guest, by pausing the microVM VCPUs and, depending on the mode, quiescing the
+    /// devices.
+    Pause(PauseMode),
     /// Repopulate the MMDS contents.
     PutMMDS(Value),
     /// Configure the guest vCPU features.
     PutCpuConfiguration(CustomCpuTemplate),
-    /// Resume the guest, by resuming the microVM VCPUs.
-    Resume,
+    /// Resume the guest, by resuming the microVM VCPUs after handling the guest clock as
+    /// requested.
+    Resume(ResumeTimeHandling),
+    /// Drain the devices, sync the logger and metrics, then stop the microVM, within the given
+    /// time budget. This action can only be called after the microVM has booted.
+    Shutdown(Duration),
     /// Set the balloon device or update the one that already exists using the
     /// `BalloonDeviceConfig` as input. This action can only be called before the microVM
     /// has booted.
     SetBalloonDevice(BalloonDeviceConfig),
+    /// Set the host memory pressure policy of the balloon device. This action can only be called
+    /// before the microVM has booted.
+    SetMemoryPressurePolicy(MemoryPressurePolicyConfig),
     /// Set the MMDS configuration.
     SetMmdsConfiguration(MmdsConfig),
+    /// Set the virtio devices whose MMIO register accesses are logged, replacing the previous
+    /// ones.
+    SetMmioTrace(MmioTraceConfig),
+    /// Set the configuration handed by the DHCP responder of the network interface with the given
+    /// ID. This action can only be called before the microVM has booted.
+    SetNetworkInterfaceDhcp(String, DhcpConfig),
+    /// Set the host ports forwarded to the guest, replacing the previous ones. This action can
+    /// only be called before the microVM has booted.
+    SetPortForwards(Vec<PortForwardConfig>),
+    /// Set whether the inputs of the devices are recorded or replayed once the microVM starts.
+    /// This action can only be called before the microVM has booted.
+    SetReplay(ReplayConfig),
     /// Set the vsock device or update the one that already exists using the
     /// `VsockDeviceConfig` as input. This action can only be called before the microVM has
     /// booted.
@@ -109,6 +194,12 @@ pub enum VmmAction {
     /// Set the entropy device using `EntropyDeviceConfig` as input. This action can only be called
     /// before the microVM has booted.
     SetEntropyDevice(EntropyDeviceConfig),
+    /// Set the crypto device using `CryptoDeviceConfig` as input. This action can only be called
+    /// before the microVM has booted.
+    SetCryptoDevice(CryptoDeviceConfig),
+    /// Set the TPM device using `TpmConfig` as input. This action can only be called before the
+    /// microVM has booted.
+    SetTpm(TpmConfig),
     /// Launch the microVM. This action can only be called before the microVM has booted.
     StartMicroVm,
     /// Send CTRL+ALT+DEL to the microVM, using the i8042 keyboard function. If an AT-keyboard
@@ -121,9 +212,15 @@ pub enum VmmAction {
     UpdateBalloonStatistics(BalloonUpdateStatsConfig),
     /// Update existing block device properties such as `path_on_host` or `rate_limiter`.
     UpdateBlockDevice(BlockDeviceUpdateConfig),
+    /// Update the entropy device, after microVM start. Currently, the only updatable property is
+    /// the rate limiter.
+    UpdateEntropyDevice(EntropyDeviceUpdateConfig),
     /// Update a network interface, after microVM start. Currently, the only updatable properties
     /// are the RX and TX rate limiters.
     UpdateNetworkInterface(NetworkInterfaceUpdateConfig),
+    /// Set the link state announced to the guest by the network interface with the given ID,
+    /// after microVM start.
+    UpdateNetworkInterfaceLink(String, NetworkInterfaceLinkConfig),
     /// Update the microVM configuration (memory & vcpu) using `VmUpdateConfig` as input. This
     /// action can only be called before the microVM has booted.
     UpdateVmConfiguration(MachineConfigUpdate),
@@ -136,14 +233,24 @@ pub enum VmmActionError {
     BalloonConfig(#[from] BalloonConfigError),
     /// Boot source error: {0}
     BootSource(#[from] BootSourceConfigError),
+    /// Config batch error: {0}
+    ConfigBatch(#[from] ConfigBatchError),
     /// Create snapshot error: {0}
     CreateSnapshot(#[from] CreateSnapshotError),
+    /// Create vmcore error: {0}
+    CreateVmcore(#[from] CreateVmcoreError),
+    /// Device features error: {0}
+    DeviceFeatures(#[from] DeviceFeaturesError),
     /// Configure CPU error: {0}
     ConfigureCpu(#[from] GuestConfigError),
+    /// Crypto device error: {0}
+    CryptoDevice(#[from] CryptoDeviceError),
     /// Drive config error: {0}
     DriveConfig(#[from] DriveError),
     /// Entropy device error: {0}
     EntropyDevice(#[from] EntropyDeviceError),
+    /// Fault injection error: {0}
+    FaultInjection(#[from] FaultInjectionError),
     /// Internal VMM error: {0}
     InternalVmm(#[from] VmmError),
     /// Load snapshot error: {0}
@@ -159,6 +266,8 @@ pub enum VmmActionError {
     Mmds(#[from] data_store::MmdsDatastoreError),
     /// MMMDS config error: {0}
     MmdsConfig(#[from] MmdsConfigError),
+    /// MMIO trace error: {0}
+    MmioTrace(#[from] MmioTraceError),
     #[from(ignore)]
     /// MMDS limit exceeded error: {0}
     MmdsLimitExceeded(data_store::MmdsDatastoreError),
@@ -170,12 +279,53 @@ pub enum VmmActionError {
     OperationNotSupportedPostBoot,
     /// The requested operation is not supported before starting the microVM.
     OperationNotSupportedPreBoot,
+    /// Port forwarding error: {0}
+    PortForward(#[from] PortForwardConfigError),
+    /// Remote device error: {0}
+    RemoteDevice(#[from] RemoteDeviceError),
     /// Start microvm error: {0}
     StartMicrovm(#[from] StartMicrovmError),
+    /// TPM device error: {0}
+    Tpm(#[from] TpmConfigError),
+    /// vCPU registers error: {0}
+    VcpuRegisters(#[from] VcpuRegistersError),
     /// Vsock config error: {0}
     VsockConfig(#[from] VsockConfigError),
 }
 
+crate::impl_api_error!(VmmActionError {
+    BalloonConfig => "balloon",
+    BootSource => "boot_source",
+    ConfigBatch => "config_batch",
+    CreateSnapshot => "snapshot",
+    CreateVmcore => "vmcore",
+    DeviceFeatures => "device",
+    ConfigureCpu => "cpu_config",
+    CryptoDevice => "crypto",
+    DriveConfig => "block",
+    EntropyDevice => "entropy",
+    FaultInjection => "fault_injection",
+    InternalVmm => "vmm",
+    LoadSnapshot => "snapshot",
+    Logger => "logger",
+    MachineConfig => "machine_config",
+    Metrics => "metrics",
+    Mmds => "mmds",
+    MmdsConfig => "mmds",
+    MmdsLimitExceeded => "mmds",
+    MmioTrace => "mmio_trace",
+    NetworkConfig => "net",
+    NotSupported => "vmm",
+    OperationNotSupportedPostBoot => "vmm",
+    OperationNotSupportedPreBoot => "vmm",
+    PortForward => "port_forward",
+    RemoteDevice => "remote_device",
+    StartMicrovm => "vmm",
+    Tpm => "tpm",
+    VcpuRegisters => "vcpu",
+    VsockConfig => "vsock",
+});
+
 /// The enum represents the response sent by the VMM in case of success. The response is either
 /// empty, when no data needs to be sent, or an internal VMM structure.
 #[allow(clippy::large_enum_variant)]
@@ -185,18 +335,34 @@ pub enum VmmData {
     BalloonConfig(BalloonDeviceConfig),
     /// The latest balloon device statistics.
     BalloonStats(BalloonStats),
+    /// The most recent balloon device statistics reports, oldest first.
+    BalloonStatsHistory(Vec<BalloonStatsSample>),
+    /// The features offered by a virtio device and acknowledged by the guest driver.
+    DeviceFeatures(VirtioDeviceFeatures),
+    /// The address ranges of the devices registered on the guest buses.
+    DeviceRegions(DeviceRegions),
     /// No data is sent on the channel.
     Empty,
     /// The complete microVM configuration in JSON format.
     FullVmConfig(VmmConfig),
     /// The microVM configuration represented by `VmConfig`.
     MachineConfiguration(MachineConfig),
+    /// The layout of the guest physical address space.
+    MemoryMap(MemoryMap),
     /// Mmds contents.
     MmdsValue(serde_json::Value),
+    /// The live state of the rate limiters of a network interface.
+    NetworkInterfaceRateLimiters(NetworkInterfaceRateLimiterStats),
     /// The microVM instance information.
     InstanceInformation(InstanceInfo),
+    /// The register state of a vCPU.
+    VcpuRegisters(VcpuRegisters),
+    /// The traced spans of the virtio datapath, oldest first.
+    VirtioTrace(Vec<TraceSpan>),
     /// The microVM version.
     VmmVersion(String),
+    /// The information about the VMM process.
+    VmmInfo(VmmInfo),
 }
 
 /// Trait used for deduplicating the MMDS request handling across the two ApiControllers.
@@ -408,6 +574,7 @@ impl<'a> PrebootApiController<'a> {
                 .update(logger_cfg)
                 .map(|()| VmmData::Empty)
                 .map_err(VmmActionError::Logger),
+            ApplyConfigBatch(batch) => self.apply_config_batch(batch),
             ConfigureMetrics(metrics_cfg) => vmm_config::metrics::init_metrics(metrics_cfg)
                 .map(|()| VmmData::Empty)
                 .map_err(VmmActionError::Metrics),
@@ -423,10 +590,17 @@ impl<'a> PrebootApiController<'a> {
             GetVmMachineConfig => Ok(VmmData::MachineConfiguration(MachineConfig::from(
                 &self.vm_resources.vm_config,
             ))),
+            GetMemoryMap => self
+                .vm_resources
+                .memory_map()
+                .map(VmmData::MemoryMap)
+                .map_err(VmmActionError::MachineConfig),
             GetVmInstanceInfo => Ok(VmmData::InstanceInformation(self.instance_info.clone())),
             GetVmmVersion => Ok(VmmData::VmmVersion(self.instance_info.vmm_version.clone())),
+            GetVmmInfo => Ok(VmmData::VmmInfo(VmmInfo::collect())),
             InsertBlockDevice(config) => self.insert_block_device(config),
             InsertNetworkDevice(config) => self.insert_net_device(config),
+            InsertRemoteDevice(config) => self.insert_remote_device(config),
             LoadSnapshot(config) => self
                 .load_snapshot(&config)
                 .map_err(VmmActionError::LoadSnapshot),
@@ -436,21 +610,42 @@ impl<'a> PrebootApiController<'a> {
             }
             PutMMDS(value) => self.put_mmds(value),
             SetBalloonDevice(config) => self.set_balloon_device(config),
+            SetMemoryPressurePolicy(config) => self.set_memory_pressure_policy(config),
             SetVsockDevice(config) => self.set_vsock_device(config),
             SetMmdsConfiguration(config) => self.set_mmds_config(config),
+            SetMmioTrace(config) => self.set_mmio_trace(config),
+            SetNetworkInterfaceDhcp(iface_id, config) => {
+                self.set_net_dhcp_config(&iface_id, config)
+            }
+            SetPortForwards(configs) => self.set_port_forwards(configs),
+            SetReplay(config) => self.set_replay_config(config),
             StartMicroVm => self.start_microvm(),
             UpdateVmConfiguration(config) => self.update_vm_config(config),
             SetEntropyDevice(config) => self.set_entropy_device(config),
+            SetCryptoDevice(config) => self.set_crypto_device(config),
+            SetTpm(config) => self.set_tpm(config),
             // Operations not allowed pre-boot.
             CreateSnapshot(_)
+            | CreateVmcore(_)
+            | DumpVirtioTrace
+            | InjectFault(_)
             | FlushMetrics
-            | Pause
-            | Resume
+            | ResetNetMetrics
+            | Pause(_)
+            | Resume(_)
+            | Shutdown(_)
             | GetBalloonStats
+            | GetBalloonStatsHistory(_)
+            | GetDevices
+            | GetDeviceFeatures(_)
+            | GetNetworkInterfaceRateLimiters(_)
+            | GetVcpuRegisters(_)
             | UpdateBalloon(_)
             | UpdateBalloonStatistics(_)
             | UpdateBlockDevice(_)
-            | UpdateNetworkInterface(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
+            | UpdateEntropyDevice(_)
+            | UpdateNetworkInterface(_)
+            | UpdateNetworkInterfaceLink(_, _) => Err(VmmActionError::OperationNotSupportedPreBoot),
             #[cfg(target_arch = "x86_64")]
             SendCtrlAltDel => Err(VmmActionError::OperationNotSupportedPreBoot),
         }
@@ -464,6 +659,12 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::BalloonConfig)
     }
 
+    fn apply_config_batch(&mut self, batch: ConfigBatch) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources.apply_config_batch(batch)?;
+        Ok(VmmData::Empty)
+    }
+
     fn insert_block_device(&mut self, cfg: BlockDeviceConfig) -> Result<VmmData, VmmActionError> {
         self.boot_path = true;
         self.vm_resources
@@ -483,6 +684,33 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::NetworkConfig)
     }
 
+    fn set_net_dhcp_config(
+        &mut self,
+        iface_id: &str,
+        config: DhcpConfig,
+    ) -> Result<VmmData, VmmActionError> {
+        self.vm_resources
+            .set_net_dhcp_config(iface_id, config)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::NetworkConfig)
+    }
+
+    fn set_port_forwards(
+        &mut self,
+        configs: Vec<PortForwardConfig>,
+    ) -> Result<VmmData, VmmActionError> {
+        self.vm_resources
+            .set_port_forwards(configs)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::PortForward)
+    }
+
+    fn insert_remote_device(&mut self, cfg: RemoteDeviceConfig) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources.build_remote_device(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
     fn set_balloon_device(&mut self, cfg: BalloonDeviceConfig) -> Result<VmmData, VmmActionError> {
         self.boot_path = true;
         self.vm_resources
@@ -491,6 +719,17 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::BalloonConfig)
     }
 
+    fn set_memory_pressure_policy(
+        &mut self,
+        cfg: MemoryPressurePolicyConfig,
+    ) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources
+            .set_memory_pressure_policy(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::BalloonConfig)
+    }
+
     fn set_boot_source(&mut self, cfg: BootSourceConfig) -> Result<VmmData, VmmActionError> {
         self.boot_path = true;
         self.vm_resources
@@ -537,6 +776,30 @@ impl<'a> PrebootApiController<'a> {
         Ok(VmmData::Empty)
     }
 
+    fn set_crypto_device(&mut self, cfg: CryptoDeviceConfig) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources.build_crypto_device(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    fn set_tpm(&mut self, cfg: TpmConfig) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources.set_tpm(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    fn set_replay_config(&mut self, cfg: ReplayConfig) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources.set_replay_config(cfg);
+        Ok(VmmData::Empty)
+    }
+
+    fn set_mmio_trace(&mut self, cfg: MmioTraceConfig) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources.set_mmio_trace(cfg);
+        Ok(VmmData::Empty)
+    }
+
     // On success, this command will end the pre-boot stage and this controller
     // will be replaced by a runtime controller.
     fn start_microvm(&mut self) -> Result<VmmData, VmmActionError> {
@@ -631,7 +894,25 @@ impl RuntimeApiController {
         match request {
             // Supported operations allowed post-boot.
             CreateSnapshot(snapshot_create_cfg) => self.create_snapshot(&snapshot_create_cfg),
+            CreateVmcore(vmcore_create_cfg) => {
+                create_vmcore(&self.vmm.lock().expect("Poisoned lock"), &vmcore_create_cfg)
+                    .map(|()| VmmData::Empty)
+                    .map_err(VmmActionError::CreateVmcore)
+            }
+            DumpVirtioTrace => Self::dump_virtio_trace(),
+            InjectFault(config) => self.inject_fault(&config),
+            SetMmioTrace(config) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .set_mmio_trace(&config)
+                .map(|()| VmmData::Empty)
+                .map_err(VmmActionError::MmioTrace),
             FlushMetrics => self.flush_metrics(),
+            ResetNetMetrics => {
+                crate::devices::virtio::net::metrics::reset_metrics();
+                Ok(VmmData::Empty)
+            }
             GetBalloonConfig => self
                 .vmm
                 .lock()
@@ -646,23 +927,68 @@ impl RuntimeApiController {
                 .latest_balloon_stats()
                 .map(VmmData::BalloonStats)
                 .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
+            GetBalloonStatsHistory(count) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .balloon_stats_history(count)
+                .map(VmmData::BalloonStatsHistory)
+                .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
+            GetDevices => Ok(VmmData::DeviceRegions(
+                self.vmm.lock().expect("Poisoned lock").device_regions(),
+            )),
+            GetDeviceFeatures(id) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .virtio_device_features(&id)
+                .map(VmmData::DeviceFeatures)
+                .map_err(VmmActionError::DeviceFeatures),
             GetFullVmConfig => Ok(VmmData::FullVmConfig((&self.vm_resources).into())),
             GetMMDS => self.get_mmds(),
+            GetNetworkInterfaceRateLimiters(iface_id) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .net_rate_limiter_stats(&iface_id)
+                .map(VmmData::NetworkInterfaceRateLimiters)
+                .map_err(NetworkInterfaceError::DeviceUpdate)
+                .map_err(VmmActionError::NetworkConfig),
             GetVmMachineConfig => Ok(VmmData::MachineConfiguration(MachineConfig::from(
                 &self.vm_resources.vm_config,
             ))),
+            GetMemoryMap => self
+                .vm_resources
+                .memory_map()
+                .map(VmmData::MemoryMap)
+                .map_err(VmmActionError::MachineConfig),
             GetVmInstanceInfo => Ok(VmmData::InstanceInformation(
                 self.vmm.lock().expect("Poisoned lock").instance_info(),
             )),
             GetVmmVersion => Ok(VmmData::VmmVersion(
                 self.vmm.lock().expect("Poisoned lock").version(),
             )),
+            GetVmmInfo => Ok(VmmData::VmmInfo(VmmInfo::collect())),
+            GetVcpuRegisters(index) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .vcpu_registers(index)
+                .map(VmmData::VcpuRegisters)
+                .map_err(VmmActionError::VcpuRegisters),
             PatchMMDS(value) => self.patch_mmds(value),
-            Pause => self.pause(),
+            Pause(mode) => self.pause(mode),
             PutMMDS(value) => self.put_mmds(value),
-            Resume => self.resume(),
+            Resume(time_handling) => self.resume(time_handling),
             #[cfg(target_arch = "x86_64")]
             SendCtrlAltDel => self.send_ctrl_alt_del(),
+            Shutdown(timeout) => {
+                self.vmm
+                    .lock()
+                    .expect("Poisoned lock")
+                    .graceful_shutdown(timeout);
+                Ok(VmmData::Empty)
+            }
             UpdateBalloon(balloon_update) => self
                 .vmm
                 .lock()
@@ -678,20 +1004,36 @@ impl RuntimeApiController {
                 .map(|_| VmmData::Empty)
                 .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
             UpdateBlockDevice(new_cfg) => self.update_block_device(new_cfg),
-            UpdateNetworkInterface(netif_update) => self.update_net_rate_limiters(netif_update),
+            UpdateEntropyDevice(new_cfg) => self.update_entropy_device(new_cfg),
+            UpdateNetworkInterface(netif_update) => self.update_net_device(netif_update),
+            UpdateNetworkInterfaceLink(iface_id, config) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .update_net_link(&iface_id, config.state == LinkState::Up)
+                .map(|()| VmmData::Empty)
+                .map_err(VmmActionError::NetworkConfig),
 
             // Operations not allowed post-boot.
-            ConfigureBootSource(_)
+            ApplyConfigBatch(_)
+            | ConfigureBootSource(_)
             | ConfigureLogger(_)
             | ConfigureMetrics(_)
             | InsertBlockDevice(_)
             | InsertNetworkDevice(_)
+            | InsertRemoteDevice(_)
             | LoadSnapshot(_)
             | PutCpuConfiguration(_)
             | SetBalloonDevice(_)
+            | SetMemoryPressurePolicy(_)
             | SetVsockDevice(_)
             | SetMmdsConfiguration(_)
+            | SetNetworkInterfaceDhcp(_, _)
+            | SetPortForwards(_)
+            | SetReplay(_)
             | SetEntropyDevice(_)
+            | SetCryptoDevice(_)
+            | SetTpm(_)
             | StartMicroVm
             | UpdateVmConfiguration(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
         }
@@ -702,11 +1044,16 @@ impl RuntimeApiController {
         Self { vmm, vm_resources }
     }
 
-    /// Pauses the microVM by pausing the vCPUs.
-    pub fn pause(&mut self) -> Result<VmmData, VmmActionError> {
+    /// Pauses the microVM by pausing the vCPUs, and the devices if requested by `mode`.
+    pub fn pause(&mut self, mode: PauseMode) -> Result<VmmData, VmmActionError> {
         let pause_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
 
-        self.vmm.lock().expect("Poisoned lock").pause_vm()?;
+        let mut locked_vmm = self.vmm.lock().expect("Poisoned lock");
+        locked_vmm.pause_vm()?;
+        if mode == PauseMode::VcpusAndDevices {
+            locked_vmm.quiesce_devices()?;
+        }
+        drop(locked_vmm);
 
         let elapsed_time_us =
             update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_pause_vm, pause_start_us);
@@ -715,11 +1062,25 @@ impl RuntimeApiController {
         Ok(VmmData::Empty)
     }
 
-    /// Resumes the microVM by resuming the vCPUs.
-    pub fn resume(&mut self) -> Result<VmmData, VmmActionError> {
+    /// Resumes the microVM by resuming the vCPUs, after advancing the guest clock if requested
+    /// by `time_handling`.
+    pub fn resume(&mut self, time_handling: ResumeTimeHandling) -> Result<VmmData, VmmActionError> {
         let resume_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
 
-        self.vmm.lock().expect("Poisoned lock").resume_vm()?;
+        #[cfg(target_arch = "aarch64")]
+        if time_handling == ResumeTimeHandling::Adjust {
+            return Err(VmmActionError::NotSupported(
+                "Adjusting the guest clock on resume is only supported on x86_64.".to_string(),
+            ));
+        }
+
+        let mut locked_vmm = self.vmm.lock().expect("Poisoned lock");
+        #[cfg(target_arch = "x86_64")]
+        if time_handling == ResumeTimeHandling::Adjust {
+            locked_vmm.adjust_clock()?;
+        }
+        locked_vmm.resume_vm()?;
+        drop(locked_vmm);
 
         let elapsed_time_us =
             update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_resume_vm, resume_start_us);
@@ -733,6 +1094,30 @@ impl RuntimeApiController {
     /// Defer to inner Vmm. We'll move to a variant where the Vmm simply exposes functionality like
     /// getting the dirty pages, and then we'll have the metrics flushing logic entirely on the
     /// outside.
+    fn dump_virtio_trace() -> Result<VmmData, VmmActionError> {
+        if !trace::VIRTIO_TRACE_ENABLED {
+            return Err(VmmActionError::NotSupported(
+                "Firecracker was built without the virtio-trace feature.".to_string(),
+            ));
+        }
+        Ok(VmmData::VirtioTrace(trace::dump()))
+    }
+
+    fn inject_fault(&mut self, config: &FaultInjectionConfig) -> Result<VmmData, VmmActionError> {
+        if !FAULT_INJECTION_ENABLED {
+            return Err(VmmActionError::NotSupported(
+                "Firecracker was built without the fault-injection feature.".to_string(),
+            ));
+        }
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .inject_device_fault(config)
+            .map(|()| VmmData::Empty)
+            .map_err(FaultInjectionError::DeviceUpdate)
+            .map_err(VmmActionError::FaultInjection)
+    }
+
     fn flush_metrics(&mut self) -> Result<VmmData, VmmActionError> {
         // FIXME: we're losing the bool saying whether metrics were actually written.
         METRICS
@@ -772,7 +1157,11 @@ impl RuntimeApiController {
         let vm_info = VmInfo::from(&self.vm_resources);
         let create_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
 
-        create_snapshot(&mut locked_vmm, &vm_info, create_params)?;
+        notify_lifecycle_event(LifecycleEvent::Snapshotting);
+        let result = create_snapshot(&mut locked_vmm, &vm_info, create_params);
+        // The microVM is back to the paused state, whether the snapshot was created or not.
+        notify_lifecycle_event(LifecycleEvent::Paused);
+        result?;
 
         match create_params.snapshot_type {
             SnapshotType::Full => {
@@ -802,7 +1191,8 @@ impl RuntimeApiController {
     /// Updates block device properties:
     ///  - path of the host file backing the emulated block device, update the disk image on the
     ///    device and its virtio configuration
-    ///  - rate limiter configuration.
+    ///  - rate limiter configuration
+    ///  - IO engine, on a paused microVM.
     fn update_block_device(
         &mut self,
         new_cfg: BlockDeviceUpdateConfig,
@@ -810,7 +1200,10 @@ impl RuntimeApiController {
         let mut vmm = self.vmm.lock().expect("Poisoned lock");
 
         // vhost-user-block updates
-        if new_cfg.path_on_host.is_none() && new_cfg.rate_limiter.is_none() {
+        if new_cfg.path_on_host.is_none()
+            && new_cfg.rate_limiter.is_none()
+            && new_cfg.file_engine_type.is_none()
+        {
             vmm.update_vhost_user_block_config(&new_cfg.drive_id)
                 .map(|()| VmmData::Empty)
                 .map_err(DriveError::DeviceUpdate)?;
@@ -831,27 +1224,60 @@ impl RuntimeApiController {
             .map(|()| VmmData::Empty)
             .map_err(DriveError::DeviceUpdate)?;
         }
+        if let Some(file_engine_type) = new_cfg.file_engine_type {
+            if vmm.instance_info().state != VmState::Paused {
+                return Err(DriveError::UpdateEngineNotPaused.into());
+            }
+            vmm.update_block_file_engine(&new_cfg.drive_id, file_engine_type)
+                .map_err(DriveError::DeviceUpdate)?;
+        }
         Ok(VmmData::Empty)
     }
 
-    /// Updates configuration for an emulated net device as described in `new_cfg`.
-    fn update_net_rate_limiters(
+    /// Updates configuration for the entropy device as described in `new_cfg`.
+    fn update_entropy_device(
         &mut self,
-        new_cfg: NetworkInterfaceUpdateConfig,
+        new_cfg: EntropyDeviceUpdateConfig,
     ) -> Result<VmmData, VmmActionError> {
+        let rate_limiter = RateLimiterUpdate::from(new_cfg.rate_limiter);
         self.vmm
             .lock()
             .expect("Poisoned lock")
-            .update_net_rate_limiters(
+            .update_entropy_rate_limiter(rate_limiter.bandwidth, rate_limiter.ops)
+            .map(|()| VmmData::Empty)
+            .map_err(EntropyDeviceError::DeviceUpdate)
+            .map_err(VmmActionError::EntropyDevice)
+    }
+
+    /// Updates configuration for an emulated net device as described in `new_cfg`.
+    fn update_net_device(
+        &mut self,
+        new_cfg: NetworkInterfaceUpdateConfig,
+    ) -> Result<VmmData, VmmActionError> {
+        let mut vmm = self.vmm.lock().expect("Poisoned lock");
+        vmm.update_net_rate_limiters(
+            &new_cfg.iface_id,
+            RateLimiterUpdate::from(new_cfg.rx_rate_limiter).bandwidth,
+            RateLimiterUpdate::from(new_cfg.rx_rate_limiter).ops,
+            RateLimiterUpdate::from(new_cfg.tx_rate_limiter).bandwidth,
+            RateLimiterUpdate::from(new_cfg.tx_rate_limiter).ops,
+        )
+        .map_err(NetworkInterfaceError::DeviceUpdate)
+        .map_err(VmmActionError::NetworkConfig)?;
+        if new_cfg.rx_interrupt_coalescing.is_some() || new_cfg.tx_interrupt_coalescing.is_some() {
+            vmm.update_net_interrupt_coalescing(
                 &new_cfg.iface_id,
-                RateLimiterUpdate::from(new_cfg.rx_rate_limiter).bandwidth,
-                RateLimiterUpdate::from(new_cfg.rx_rate_limiter).ops,
-                RateLimiterUpdate::from(new_cfg.tx_rate_limiter).bandwidth,
-                RateLimiterUpdate::from(new_cfg.tx_rate_limiter).ops,
+                new_cfg.rx_interrupt_coalescing,
+                new_cfg.tx_interrupt_coalescing,
             )
-            .map(|()| VmmData::Empty)
             .map_err(NetworkInterfaceError::DeviceUpdate)
-            .map_err(VmmActionError::NetworkConfig)
+            .map_err(VmmActionError::NetworkConfig)?;
+        }
+        if let Some(guest_mac) = new_cfg.guest_mac {
+            vmm.update_net_guest_mac(&new_cfg.iface_id, guest_mac)
+                .map_err(VmmActionError::NetworkConfig)?;
+        }
+        Ok(VmmData::Empty)
     }
 }
 
@@ -863,15 +1289,22 @@ mod tests {
     use seccompiler::BpfThreadMap;
 
     use super::*;
+    use crate::cpu_config::fingerprint::CpuFingerprint;
     use crate::cpu_config::templates::test_utils::build_test_template;
     use crate::cpu_config::templates::{CpuTemplateType, StaticCpuTemplate};
     use crate::devices::virtio::balloon::{BalloonConfig, BalloonError};
     use crate::devices::virtio::block::CacheType;
+    use crate::devices::virtio::crypto::CryptoError;
+    use crate::devices::virtio::net::coalescing::InterruptCoalescingConfig;
     use crate::devices::virtio::rng::EntropyError;
     use crate::devices::virtio::vsock::VsockError;
     use crate::mmds::data_store::MmdsVersion;
     use crate::vmm_config::balloon::BalloonBuilder;
+    use crate::vmm_config::config_batch::DeviceConfig;
+    use crate::vmm_config::drive::FileEngineType;
+    use crate::vmm_config::fault_injection::FaultType;
     use crate::vmm_config::machine_config::VmConfig;
+    use crate::vmm_config::replay::ReplayMode;
     use crate::vmm_config::snapshot::{MemBackendConfig, MemBackendType};
     use crate::vmm_config::vsock::VsockBuilder;
     use crate::HTTP_MAX_PAYLOAD_SIZE;
@@ -884,6 +1317,9 @@ mod tests {
                 (BalloonConfig(_), BalloonConfig(_))
                     | (BootSource(_), BootSource(_))
                     | (CreateSnapshot(_), CreateSnapshot(_))
+                    | (CreateVmcore(_), CreateVmcore(_))
+                    | (ConfigBatch(_), ConfigBatch(_))
+                    | (DeviceFeatures(_), DeviceFeatures(_))
                     | (DriveConfig(_), DriveConfig(_))
                     | (InternalVmm(_), InternalVmm(_))
                     | (LoadSnapshot(_), LoadSnapshot(_))
@@ -892,13 +1328,20 @@ mod tests {
                     | (Mmds(_), Mmds(_))
                     | (MmdsLimitExceeded(_), MmdsLimitExceeded(_))
                     | (MmdsConfig(_), MmdsConfig(_))
+                    | (MmioTrace(_), MmioTrace(_))
                     | (NetworkConfig(_), NetworkConfig(_))
                     | (NotSupported(_), NotSupported(_))
                     | (OperationNotSupportedPostBoot, OperationNotSupportedPostBoot)
                     | (OperationNotSupportedPreBoot, OperationNotSupportedPreBoot)
+                    | (PortForward(_), PortForward(_))
+                    | (RemoteDevice(_), RemoteDevice(_))
                     | (StartMicrovm(_), StartMicrovm(_))
+                    | (Tpm(_), Tpm(_))
+                    | (VcpuRegisters(_), VcpuRegisters(_))
                     | (VsockConfig(_), VsockConfig(_))
                     | (EntropyDevice(_), EntropyDevice(_))
+                    | (CryptoDevice(_), CryptoDevice(_))
+                    | (FaultInjection(_), FaultInjection(_))
             )
         }
     }
@@ -911,12 +1354,21 @@ mod tests {
         pub vsock: VsockBuilder,
         balloon_config_called: bool,
         balloon_set: bool,
+        pressure_policy_set: bool,
         boot_src: BootSourceConfig,
         boot_cfg_set: bool,
         block_set: bool,
         vsock_set: bool,
         net_set: bool,
+        net_dhcp_set: bool,
+        port_forwards_set: bool,
         entropy_set: bool,
+        crypto_set: bool,
+        tpm_set: bool,
+        replay_set: bool,
+        mmio_trace_set: bool,
+        remote_device_set: bool,
+        config_batch_set: bool,
         pub mmds: Option<Arc<Mutex<Mmds>>>,
         pub mmds_size_limit: usize,
         pub boot_timer: bool,
@@ -925,6 +1377,16 @@ mod tests {
     }
 
     impl MockVmRes {
+        pub fn memory_map(&self) -> Result<MemoryMap, VmConfigError> {
+            if self.force_errors {
+                return Err(VmConfigError::InvalidMemoryLayout);
+            }
+            Ok(crate::arch::memory_map(
+                self.vm_config.mem_size_mib << 20,
+                &self.vm_config.memory_layout,
+            ))
+        }
+
         pub fn balloon_config(&mut self) -> Result<BalloonConfig, BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -965,6 +1427,17 @@ mod tests {
             Ok(())
         }
 
+        pub fn set_memory_pressure_policy(
+            &mut self,
+            _: MemoryPressurePolicyConfig,
+        ) -> Result<(), BalloonConfigError> {
+            if self.force_errors {
+                return Err(BalloonConfigError::DeviceNotFound);
+            }
+            self.pressure_policy_set = true;
+            Ok(())
+        }
+
         pub fn build_boot_source(
             &mut self,
             boot_source: BootSourceConfig,
@@ -1002,6 +1475,31 @@ mod tests {
             Ok(())
         }
 
+        pub fn set_net_dhcp_config(
+            &mut self,
+            iface_id: &str,
+            _: DhcpConfig,
+        ) -> Result<(), NetworkInterfaceError> {
+            if self.force_errors {
+                return Err(NetworkInterfaceError::UnknownInterface(
+                    iface_id.to_string(),
+                ));
+            }
+            self.net_dhcp_set = true;
+            Ok(())
+        }
+
+        pub fn set_port_forwards(
+            &mut self,
+            _: Vec<PortForwardConfig>,
+        ) -> Result<(), PortForwardConfigError> {
+            if self.force_errors {
+                return Err(PortForwardConfigError::InvalidPort);
+            }
+            self.port_forwards_set = true;
+            Ok(())
+        }
+
         pub fn set_vsock_device(&mut self, _: VsockDeviceConfig) -> Result<(), VsockConfigError> {
             if self.force_errors {
                 return Err(VsockConfigError::CreateVsockDevice(
@@ -1025,6 +1523,54 @@ mod tests {
             Ok(())
         }
 
+        pub fn build_crypto_device(
+            &mut self,
+            _: CryptoDeviceConfig,
+        ) -> Result<(), CryptoDeviceError> {
+            if self.force_errors {
+                return Err(CryptoDeviceError::CreateDevice(CryptoError::EventFd(
+                    io::Error::from_raw_os_error(0),
+                )));
+            }
+            self.crypto_set = true;
+            Ok(())
+        }
+
+        pub fn set_tpm(&mut self, _: TpmConfig) -> Result<(), TpmConfigError> {
+            if self.force_errors {
+                return Err(TpmConfigError::UnsupportedArch);
+            }
+            self.tpm_set = true;
+            Ok(())
+        }
+
+        pub fn set_replay_config(&mut self, _: ReplayConfig) {
+            self.replay_set = true;
+        }
+
+        pub fn set_mmio_trace(&mut self, _: MmioTraceConfig) {
+            self.mmio_trace_set = true;
+        }
+
+        pub fn apply_config_batch(&mut self, _: ConfigBatch) -> Result<(), ConfigBatchError> {
+            if self.force_errors {
+                return Err(ConfigBatchError::Empty);
+            }
+            self.config_batch_set = true;
+            Ok(())
+        }
+
+        pub fn build_remote_device(
+            &mut self,
+            _: RemoteDeviceConfig,
+        ) -> Result<(), RemoteDeviceError> {
+            if self.force_errors {
+                return Err(RemoteDeviceError::ProtocolFeatures);
+            }
+            self.remote_device_set = true;
+            Ok(())
+        }
+
         pub fn set_mmds_config(
             &mut self,
             mmds_config: MmdsConfig,
@@ -1074,6 +1620,16 @@ mod tests {
                 cpu_template: StaticCpuTemplate::from(&value.vm_config.cpu_template),
                 boot_source: value.boot_source_config().clone(),
                 huge_pages: value.vm_config.huge_pages,
+                memory_layout: value.vm_config.memory_layout,
+                virtio_feature_policy: value.vm_config.virtio_feature_policy.clone(),
+                custom_cpu_template: matches!(
+                    value.vm_config.cpu_template,
+                    Some(CpuTemplateType::Custom(_))
+                ),
+                host_cpu: CpuFingerprint::host(),
+                vcpu_threads: value.vm_config.vcpu_threads.clone(),
+                pmu: value.vm_config.pmu,
+                firecracker_version: String::new(),
             }
         }
     }
@@ -1083,7 +1639,11 @@ mod tests {
     pub struct MockVmm {
         pub balloon_config_called: bool,
         pub latest_balloon_stats_called: bool,
+        pub balloon_stats_history_called: bool,
         pub pause_called: bool,
+        pub quiesce_devices_called: bool,
+        #[cfg(target_arch = "x86_64")]
+        pub adjust_clock_called: bool,
         pub resume_called: bool,
         #[cfg(target_arch = "x86_64")]
         pub send_ctrl_alt_del_called: bool,
@@ -1091,9 +1651,23 @@ mod tests {
         pub update_balloon_stats_config_called: bool,
         pub update_block_device_path_called: bool,
         pub update_block_device_vhost_user_config_called: bool,
+        pub update_block_file_engine_called: bool,
+        pub update_entropy_rate_limiter_called: bool,
+        pub inject_device_fault_called: bool,
         pub update_net_rate_limiters_called: bool,
+        pub net_rate_limiter_stats_called: bool,
+        pub update_net_interrupt_coalescing_called: bool,
+        pub update_net_guest_mac_called: bool,
+        pub update_net_link_called: bool,
+        pub set_mmio_trace_called: bool,
+        pub vcpu_registers_called: bool,
+        pub graceful_shutdown_called: bool,
+        pub device_regions_called: bool,
+        pub virtio_device_features_called: bool,
         // when `true`, all self methods are forced to fail
         pub force_errors: bool,
+        // state reported by `instance_info()`
+        pub state: VmState,
     }
 
     impl MockVmm {
@@ -1105,6 +1679,15 @@ mod tests {
             Ok(())
         }
 
+        #[cfg(target_arch = "x86_64")]
+        pub fn adjust_clock(&mut self) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::VcpuResume);
+            }
+            self.adjust_clock_called = true;
+            Ok(())
+        }
+
         pub fn pause_vm(&mut self) -> Result<(), VmmError> {
             if self.force_errors {
                 return Err(VmmError::VcpuPause);
@@ -1113,6 +1696,14 @@ mod tests {
             Ok(())
         }
 
+        pub fn quiesce_devices(&mut self) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::VcpuPause);
+            }
+            self.quiesce_devices_called = true;
+            Ok(())
+        }
+
         #[cfg(target_arch = "x86_64")]
         pub fn send_ctrl_alt_del(&mut self) -> Result<(), VmmError> {
             if self.force_errors {
@@ -1124,6 +1715,10 @@ mod tests {
             Ok(())
         }
 
+        pub fn graceful_shutdown(&mut self, _timeout: Duration) {
+            self.graceful_shutdown_called = true;
+        }
+
         pub fn balloon_config(&mut self) -> Result<BalloonConfig, BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -1132,6 +1727,30 @@ mod tests {
             Ok(BalloonConfig::default())
         }
 
+        pub fn device_regions(&mut self) -> DeviceRegions {
+            self.device_regions_called = true;
+            DeviceRegions::default()
+        }
+
+        pub fn virtio_device_features(
+            &mut self,
+            id: &str,
+        ) -> Result<VirtioDeviceFeatures, DeviceFeaturesError> {
+            if self.force_errors {
+                return Err(DeviceFeaturesError::NotFound(id.to_string()));
+            }
+            self.virtio_device_features_called = true;
+            Ok(VirtioDeviceFeatures::default())
+        }
+
+        pub fn vcpu_registers(&mut self, index: u8) -> Result<VcpuRegisters, VcpuRegistersError> {
+            if self.force_errors {
+                return Err(VcpuRegistersError::InvalidVcpuIndex(index));
+            }
+            self.vcpu_registers_called = true;
+            Ok(VcpuRegisters::default())
+        }
+
         pub fn latest_balloon_stats(&mut self) -> Result<BalloonStats, BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -1140,6 +1759,17 @@ mod tests {
             Ok(BalloonStats::default())
         }
 
+        pub fn balloon_stats_history(
+            &mut self,
+            _: usize,
+        ) -> Result<Vec<BalloonStatsSample>, BalloonError> {
+            if self.force_errors {
+                return Err(BalloonError::DeviceNotFound);
+            }
+            self.balloon_stats_history_called = true;
+            Ok(Vec::new())
+        }
+
         pub fn update_balloon_config(&mut self, _: u32) -> Result<(), BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -1175,6 +1805,20 @@ mod tests {
             Ok(())
         }
 
+        pub fn update_block_file_engine(
+            &mut self,
+            _: &str,
+            _: FileEngineType,
+        ) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::MmioError::InvalidDeviceType,
+                ));
+            }
+            self.update_block_file_engine_called = true;
+            Ok(())
+        }
+
         pub fn update_vhost_user_block_config(&mut self, _: &str) -> Result<(), VmmError> {
             if self.force_errors {
                 return Err(VmmError::DeviceManager(
@@ -1185,6 +1829,30 @@ mod tests {
             Ok(())
         }
 
+        pub fn update_entropy_rate_limiter(
+            &mut self,
+            _: crate::rate_limiter::BucketUpdate,
+            _: crate::rate_limiter::BucketUpdate,
+        ) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::MmioError::InvalidDeviceType,
+                ));
+            }
+            self.update_entropy_rate_limiter_called = true;
+            Ok(())
+        }
+
+        pub fn inject_device_fault(&mut self, _: &FaultInjectionConfig) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::MmioError::DeviceNotFound,
+                ));
+            }
+            self.inject_device_fault_called = true;
+            Ok(())
+        }
+
         pub fn update_net_rate_limiters(
             &mut self,
             _: &str,
@@ -1202,21 +1870,84 @@ mod tests {
             Ok(())
         }
 
-        pub fn instance_info(&self) -> InstanceInfo {
-            InstanceInfo::default()
-        }
-
-        pub fn version(&self) -> String {
-            String::default()
+        pub fn net_rate_limiter_stats(
+            &mut self,
+            _: &str,
+        ) -> Result<NetworkInterfaceRateLimiterStats, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::MmioError::InvalidDeviceType,
+                ));
+            }
+            self.net_rate_limiter_stats_called = true;
+            Ok(NetworkInterfaceRateLimiterStats::default())
         }
-    }
 
-    // Need to redefine this since the non-test one uses real VmResources
-    // and real Vmm instead of our mocks.
-    pub fn build_and_boot_microvm(
-        _: &InstanceInfo,
-        _: &VmResources,
-        _: &mut EventManager,
+        pub fn update_net_interrupt_coalescing(
+            &mut self,
+            _: &str,
+            _: Option<InterruptCoalescingConfig>,
+            _: Option<InterruptCoalescingConfig>,
+        ) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::MmioError::InvalidDeviceType,
+                ));
+            }
+            self.update_net_interrupt_coalescing_called = true;
+            Ok(())
+        }
+
+        pub fn update_net_guest_mac(
+            &mut self,
+            _: &str,
+            _: utils::net::mac::MacAddr,
+        ) -> Result<(), NetworkInterfaceError> {
+            if self.force_errors {
+                return Err(NetworkInterfaceError::GuestMacAddressInUse(String::new()));
+            }
+            self.update_net_guest_mac_called = true;
+            Ok(())
+        }
+
+        pub fn update_net_link(&mut self, _: &str, _: bool) -> Result<(), NetworkInterfaceError> {
+            if self.force_errors {
+                return Err(NetworkInterfaceError::DeviceUpdate(
+                    VmmError::DeviceManager(
+                        crate::device_manager::mmio::MmioError::InvalidDeviceType,
+                    ),
+                ));
+            }
+            self.update_net_link_called = true;
+            Ok(())
+        }
+
+        pub fn set_mmio_trace(&mut self, _: &MmioTraceConfig) -> Result<(), MmioTraceError> {
+            if self.force_errors {
+                return Err(MmioTraceError::UnknownDevice(String::new()));
+            }
+            self.set_mmio_trace_called = true;
+            Ok(())
+        }
+
+        pub fn instance_info(&self) -> InstanceInfo {
+            InstanceInfo {
+                state: self.state.clone(),
+                ..Default::default()
+            }
+        }
+
+        pub fn version(&self) -> String {
+            String::default()
+        }
+    }
+
+    // Need to redefine this since the non-test one uses real VmResources
+    // and real Vmm instead of our mocks.
+    pub fn build_and_boot_microvm(
+        _: &InstanceInfo,
+        _: &mut VmResources,
+        _: &mut EventManager,
         _: &BpfThreadMap,
     ) -> Result<Arc<Mutex<Vmm>>, StartMicrovmError> {
         Ok(Arc::new(Mutex::new(MockVmm::default())))
@@ -1232,6 +1963,12 @@ mod tests {
         Ok(())
     }
 
+    // Need to redefine this since the non-test one uses real Vmm
+    // instead of our mocks.
+    pub fn create_vmcore(_: &Vmm, _: &CreateVmcoreParams) -> Result<(), CreateVmcoreError> {
+        Ok(())
+    }
+
     // Need to redefine this since the non-test one uses real Vmm
     // instead of our mocks.
     pub fn restore_from_snapshot(
@@ -1305,6 +2042,20 @@ mod tests {
             assert!(vm_res.boot_cfg_set)
         });
 
+        let req = VmmAction::GetMemoryMap;
+        check_preboot_request(req, |result, _| {
+            assert_eq!(
+                result,
+                Ok(VmmData::MemoryMap(
+                    VmResources::default().memory_map().unwrap()
+                ))
+            );
+        });
+        check_preboot_request_err(
+            VmmAction::GetMemoryMap,
+            VmmActionError::MachineConfig(VmConfigError::InvalidMemoryLayout),
+        );
+
         let req = VmmAction::ConfigureBootSource(BootSourceConfig::default());
         check_preboot_request_err(
             req,
@@ -1314,6 +2065,13 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preboot_get_vmm_info() {
+        check_preboot_request(VmmAction::GetVmmInfo, |result, _| {
+            assert!(matches!(result, Ok(VmmData::VmmInfo(info)) if info.pid == std::process::id()));
+        });
+    }
+
     #[test]
     fn test_preboot_get_vm_config() {
         let req = VmmAction::GetVmMachineConfig;
@@ -1386,6 +2144,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preboot_set_memory_pressure_policy() {
+        let req = VmmAction::SetMemoryPressurePolicy(MemoryPressurePolicyConfig::default());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.pressure_policy_set)
+        });
+
+        let req = VmmAction::SetMemoryPressurePolicy(MemoryPressurePolicyConfig::default());
+        check_preboot_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+        );
+    }
+
     #[test]
     fn test_preboot_insert_block_dev() {
         let config = BlockDeviceConfig {
@@ -1398,8 +2171,11 @@ mod tests {
             path_on_host: Some(String::new()),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
         let req = VmmAction::InsertBlockDevice(config.clone());
         check_preboot_request(req, |result, vm_res| {
@@ -1422,6 +2198,13 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            peer: None,
+            dma_ranges: None,
+            rx_interrupt_coalescing: None,
+            tx_interrupt_coalescing: None,
+            worker: false,
+            dhcp: None,
+            xdp: None,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1434,6 +2217,13 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            peer: None,
+            dma_ranges: None,
+            rx_interrupt_coalescing: None,
+            tx_interrupt_coalescing: None,
+            worker: false,
+            dhcp: None,
+            xdp: None,
         });
         check_preboot_request_err(
             req,
@@ -1443,12 +2233,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preboot_set_net_dhcp() {
+        let config = DhcpConfig {
+            ip_address: "192.168.0.2".parse().unwrap(),
+            prefix_length: 24,
+            gateway: None,
+            nameservers: vec![],
+        };
+        let req = VmmAction::SetNetworkInterfaceDhcp(String::from("eth0"), config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.net_dhcp_set)
+        });
+
+        let req = VmmAction::SetNetworkInterfaceDhcp(String::from("eth0"), config);
+        check_preboot_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::UnknownInterface(String::from(
+                "eth0",
+            ))),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_port_forwards() {
+        let configs = vec![PortForwardConfig {
+            iface_id: String::from("eth0"),
+            host_address: "127.0.0.1".parse().unwrap(),
+            host_port: 8080,
+            guest_address: "192.168.0.2".parse().unwrap(),
+            guest_port: 80,
+        }];
+        let req = VmmAction::SetPortForwards(configs.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.port_forwards_set)
+        });
+
+        let req = VmmAction::SetPortForwards(configs);
+        check_preboot_request_err(
+            req,
+            VmmActionError::PortForward(PortForwardConfigError::InvalidPort),
+        );
+    }
+
     #[test]
     fn test_preboot_set_vsock_dev() {
         let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
             vsock_id: Some(String::new()),
             guest_cid: 0,
             uds_path: String::new(),
+            dma_ranges: None,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1459,6 +2295,7 @@ mod tests {
             vsock_id: Some(String::new()),
             guest_cid: 0,
             uds_path: String::new(),
+            dma_ranges: None,
         });
         check_preboot_request_err(
             req,
@@ -1477,12 +2314,107 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_preboot_set_crypto_device() {
+        let req = VmmAction::SetCryptoDevice(CryptoDeviceConfig::default());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.crypto_set);
+        });
+
+        let req = VmmAction::SetCryptoDevice(CryptoDeviceConfig::default());
+        check_preboot_request_err(
+            req,
+            VmmActionError::CryptoDevice(CryptoDeviceError::CreateDevice(CryptoError::EventFd(
+                io::Error::from_raw_os_error(0),
+            ))),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_tpm() {
+        let config = TpmConfig {
+            socket: String::from("swtpm.sock"),
+        };
+        let req = VmmAction::SetTpm(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.tpm_set);
+        });
+
+        let req = VmmAction::SetTpm(config);
+        check_preboot_request_err(req, VmmActionError::Tpm(TpmConfigError::UnsupportedArch));
+    }
+
+    #[test]
+    fn test_preboot_apply_config_batch() {
+        let batch = || ConfigBatch {
+            devices: vec![DeviceConfig::Entropy(EntropyDeviceConfig::default())],
+        };
+        let req = VmmAction::ApplyConfigBatch(batch());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.config_batch_set);
+        });
+
+        let req = VmmAction::ApplyConfigBatch(batch());
+        check_preboot_request_err(req, VmmActionError::ConfigBatch(ConfigBatchError::Empty));
+    }
+
+    #[test]
+    fn test_preboot_set_replay() {
+        let req = VmmAction::SetReplay(ReplayConfig {
+            mode: ReplayMode::Record,
+            log_path: PathBuf::from("replay.log"),
+        });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.replay_set);
+        });
+    }
+
+    #[test]
+    fn test_preboot_set_mmio_trace() {
+        let req = VmmAction::SetMmioTrace(MmioTraceConfig {
+            device_ids: vec![String::from("rootfs")],
+        });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.mmio_trace_set);
+        });
+    }
+
+    #[test]
+    fn test_preboot_insert_remote_device() {
+        let config = RemoteDeviceConfig {
+            id: String::from("remote0"),
+            device_type: 3,
+            socket: String::new(),
+            num_queues: 1,
+            queue_size: 256,
+            config_size: 0,
+        };
+        let req = VmmAction::InsertRemoteDevice(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.remote_device_set)
+        });
+
+        let req = VmmAction::InsertRemoteDevice(config);
+        check_preboot_request_err(
+            req,
+            VmmActionError::RemoteDevice(RemoteDeviceError::ProtocolFeatures),
+        );
+    }
+
     #[test]
     fn test_preboot_set_mmds_config() {
         let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
             ipv4_address: None,
             version: MmdsVersion::V2,
             network_interfaces: Vec::new(),
+            interfaces: Vec::new(),
+            data_file: None,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1496,6 +2428,8 @@ mod tests {
             ipv4_address: None,
             version: MmdsVersion::default(),
             network_interfaces: Vec::new(),
+            interfaces: Vec::new(),
+            data_file: None,
         });
         check_preboot_request_err(
             req,
@@ -1737,6 +2671,10 @@ mod tests {
             },
             enable_diff_snapshots: false,
             resume_vm: false,
+            verify: false,
+            encryption: None,
+            allow_cpu_mismatch: false,
+            overrides: Default::default(),
         });
         // Request should succeed.
         preboot.handle_preboot_request(req).unwrap();
@@ -1753,6 +2691,10 @@ mod tests {
             },
             enable_diff_snapshots: false,
             resume_vm: true,
+            verify: false,
+            encryption: None,
+            allow_cpu_mismatch: false,
+            overrides: Default::default(),
         });
         // Request should succeed.
         preboot.handle_preboot_request(req).unwrap();
@@ -1770,17 +2712,53 @@ mod tests {
             VmmActionError::OperationNotSupportedPreBoot,
         );
         check_preboot_request_err(
-            VmmAction::Pause,
+            VmmAction::ResetNetMetrics,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::DumpVirtioTrace,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::InjectFault(FaultInjectionConfig {
+                device_id: String::from("rootfs"),
+                fault_type: FaultType::BlockIo,
+                enabled: true,
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::Pause(PauseMode::Vcpus),
             VmmActionError::OperationNotSupportedPreBoot,
         );
         check_preboot_request_err(
-            VmmAction::Resume,
+            VmmAction::Resume(ResumeTimeHandling::None),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::Shutdown(crate::DEFAULT_SHUTDOWN_TIMEOUT),
             VmmActionError::OperationNotSupportedPreBoot,
         );
         check_preboot_request_err(
             VmmAction::GetBalloonStats,
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        check_preboot_request_err(
+            VmmAction::GetBalloonStatsHistory(1),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetDevices,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetDeviceFeatures(String::from("rootfs")),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetVcpuRegisters(0),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
         check_preboot_request_err(
             VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 }),
             VmmActionError::OperationNotSupportedPreBoot,
@@ -1795,19 +2773,49 @@ mod tests {
             VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig::default()),
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        check_preboot_request_err(
+            VmmAction::UpdateEntropyDevice(EntropyDeviceUpdateConfig { rate_limiter: None }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
         check_preboot_request_err(
             VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
                 iface_id: String::new(),
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                guest_mac: None,
+                rx_interrupt_coalescing: None,
+                tx_interrupt_coalescing: None,
             }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        check_preboot_request_err(
+            VmmAction::UpdateNetworkInterfaceLink(
+                String::new(),
+                NetworkInterfaceLinkConfig {
+                    state: LinkState::Down,
+                },
+            ),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetNetworkInterfaceRateLimiters(String::new()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
         check_preboot_request_err(
             VmmAction::CreateSnapshot(CreateSnapshotParams {
                 snapshot_type: SnapshotType::Full,
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
+                encryption: None,
+                manifest_path: None,
+                sparse: false,
+                snapshot_version: None,
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::CreateVmcore(CreateVmcoreParams {
+                vmcore_path: PathBuf::new(),
             }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
@@ -1856,6 +2864,13 @@ mod tests {
         assert_eq!(err, expected_err);
     }
 
+    #[test]
+    fn test_runtime_get_vmm_info() {
+        check_runtime_request(VmmAction::GetVmmInfo, |result, _| {
+            assert!(matches!(result, Ok(VmmData::VmmInfo(info)) if info.pid == std::process::id()));
+        });
+    }
+
     #[test]
     fn test_runtime_get_vm_config() {
         let req = VmmAction::GetVmMachineConfig;
@@ -1867,30 +2882,174 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_runtime_get_memory_map() {
+        let req = VmmAction::GetMemoryMap;
+        check_runtime_request(req, |result, _| {
+            assert_eq!(
+                result,
+                Ok(VmmData::MemoryMap(
+                    VmResources::default().memory_map().unwrap()
+                ))
+            );
+        });
+    }
+
     #[test]
     fn test_runtime_pause() {
-        let req = VmmAction::Pause;
+        let req = VmmAction::Pause(PauseMode::Vcpus);
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vmm.pause_called)
+            assert!(vmm.pause_called);
+            assert!(!vmm.quiesce_devices_called);
         });
 
-        let req = VmmAction::Pause;
+        let req = VmmAction::Pause(PauseMode::VcpusAndDevices);
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.pause_called);
+            assert!(vmm.quiesce_devices_called);
+        });
+
+        let req = VmmAction::Pause(PauseMode::Vcpus);
         check_runtime_request_err(req, VmmActionError::InternalVmm(VmmError::VcpuPause));
     }
 
+    #[test]
+    fn test_runtime_shutdown() {
+        let req = VmmAction::Shutdown(Duration::from_millis(100));
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.graceful_shutdown_called)
+        });
+    }
+
     #[test]
     fn test_runtime_resume() {
-        let req = VmmAction::Resume;
+        let req = VmmAction::Resume(ResumeTimeHandling::None);
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.resume_called);
+            #[cfg(target_arch = "x86_64")]
+            assert!(!vmm.adjust_clock_called);
+        });
+
+        let req = VmmAction::Resume(ResumeTimeHandling::Adjust);
+        #[cfg(target_arch = "x86_64")]
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vmm.resume_called)
+            assert!(vmm.adjust_clock_called);
+            assert!(vmm.resume_called);
         });
+        #[cfg(target_arch = "aarch64")]
+        check_runtime_request_err(
+            req,
+            VmmActionError::NotSupported(
+                "Adjusting the guest clock on resume is only supported on x86_64.".to_string(),
+            ),
+        );
 
-        let req = VmmAction::Resume;
+        let req = VmmAction::Resume(ResumeTimeHandling::None);
         check_runtime_request_err(req, VmmActionError::InternalVmm(VmmError::VcpuResume));
     }
 
+    #[test]
+    fn test_runtime_get_devices() {
+        let req = VmmAction::GetDevices;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::DeviceRegions(DeviceRegions::default())));
+            assert!(vmm.device_regions_called)
+        });
+    }
+
+    #[test]
+    fn test_runtime_create_vmcore() {
+        let req = VmmAction::CreateVmcore(CreateVmcoreParams {
+            vmcore_path: PathBuf::from("/tmp/vmcore"),
+        });
+        check_runtime_request(req, |result, _| {
+            assert_eq!(result, Ok(VmmData::Empty));
+        });
+    }
+
+    #[test]
+    fn test_runtime_dump_virtio_trace() {
+        check_runtime_request(VmmAction::DumpVirtioTrace, |result, _| {
+            if trace::VIRTIO_TRACE_ENABLED {
+                assert!(matches!(result, Ok(VmmData::VirtioTrace(_))));
+            } else {
+                assert!(matches!(result, Err(VmmActionError::NotSupported(_))));
+            }
+        });
+    }
+
+    #[test]
+    fn test_runtime_inject_fault() {
+        let config = FaultInjectionConfig {
+            device_id: String::from("rootfs"),
+            fault_type: FaultType::BlockIo,
+            enabled: true,
+        };
+        check_runtime_request(VmmAction::InjectFault(config.clone()), |result, vmm| {
+            if FAULT_INJECTION_ENABLED {
+                assert_eq!(result, Ok(VmmData::Empty));
+                assert!(vmm.inject_device_fault_called);
+            } else {
+                assert!(matches!(result, Err(VmmActionError::NotSupported(_))));
+                assert!(!vmm.inject_device_fault_called);
+            }
+        });
+
+        if FAULT_INJECTION_ENABLED {
+            check_runtime_request_err(
+                VmmAction::InjectFault(config),
+                VmmActionError::FaultInjection(FaultInjectionError::DeviceUpdate(
+                    VmmError::DeviceManager(crate::device_manager::mmio::MmioError::DeviceNotFound),
+                )),
+            );
+        }
+    }
+
+    #[test]
+    fn test_runtime_reset_net_metrics() {
+        check_runtime_request(VmmAction::ResetNetMetrics, |result, _| {
+            assert_eq!(result, Ok(VmmData::Empty));
+        });
+    }
+
+    #[test]
+    fn test_runtime_get_device_features() {
+        let req = VmmAction::GetDeviceFeatures(String::from("rootfs"));
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::DeviceFeatures(VirtioDeviceFeatures::default()))
+            );
+            assert!(vmm.virtio_device_features_called)
+        });
+
+        let req = VmmAction::GetDeviceFeatures(String::from("rootfs"));
+        check_runtime_request_err(
+            req,
+            VmmActionError::DeviceFeatures(DeviceFeaturesError::NotFound(String::from("rootfs"))),
+        );
+    }
+
+    #[test]
+    fn test_runtime_get_vcpu_registers() {
+        let req = VmmAction::GetVcpuRegisters(0);
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::VcpuRegisters(VcpuRegisters::default())));
+            assert!(vmm.vcpu_registers_called)
+        });
+
+        let req = VmmAction::GetVcpuRegisters(0);
+        check_runtime_request_err(
+            req,
+            VmmActionError::VcpuRegisters(VcpuRegistersError::InvalidVcpuIndex(0)),
+        );
+    }
+
     #[cfg(target_arch = "x86_64")]
     #[test]
     fn test_runtime_ctrl_alt_del() {
@@ -1942,6 +3101,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_balloon_stats_history() {
+        let req = VmmAction::GetBalloonStatsHistory(1);
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::BalloonStatsHistory(Vec::new())));
+            assert!(vmm.balloon_stats_history_called)
+        });
+
+        let req = VmmAction::GetBalloonStatsHistory(1);
+        check_runtime_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+        );
+    }
+
     #[test]
     fn test_runtime_update_balloon_config() {
         let req = VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 });
@@ -1999,6 +3173,51 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_update_block_file_engine() {
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            file_engine_type: Some(FileEngineType::Async),
+            ..Default::default()
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Err(VmmActionError::DriveConfig(
+                    DriveError::UpdateEngineNotPaused
+                ))
+            );
+            assert!(!vmm.update_block_file_engine_called);
+            assert!(!vmm.update_block_device_vhost_user_config_called);
+        });
+
+        for force_errors in [false, true] {
+            let vmm = Arc::new(Mutex::new(MockVmm {
+                state: VmState::Paused,
+                force_errors,
+                ..Default::default()
+            }));
+            let mut runtime = RuntimeApiController::new(MockVmRes::default(), vmm.clone());
+            let res =
+                runtime.handle_request(VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+                    file_engine_type: Some(FileEngineType::Sync),
+                    ..Default::default()
+                }));
+            if force_errors {
+                assert_eq!(
+                    res,
+                    Err(VmmActionError::DriveConfig(DriveError::DeviceUpdate(
+                        VmmError::DeviceManager(
+                            crate::device_manager::mmio::MmioError::InvalidDeviceType
+                        )
+                    )))
+                );
+            } else {
+                assert_eq!(res, Ok(VmmData::Empty));
+                assert!(vmm.lock().unwrap().update_block_file_engine_called);
+            }
+        }
+    }
+
     #[test]
     fn test_runtime_update_block_device_vhost_user_config() {
         let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
@@ -2020,12 +3239,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_get_net_rate_limiters() {
+        let req = VmmAction::GetNetworkInterfaceRateLimiters(String::new());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::NetworkInterfaceRateLimiters(
+                    NetworkInterfaceRateLimiterStats::default()
+                ))
+            );
+            assert!(vmm.net_rate_limiter_stats_called)
+        });
+
+        let req = VmmAction::GetNetworkInterfaceRateLimiters(String::new());
+        check_runtime_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceUpdate(
+                VmmError::DeviceManager(crate::device_manager::mmio::MmioError::InvalidDeviceType),
+            )),
+        );
+    }
+
     #[test]
     fn test_runtime_update_net_rate_limiters() {
         let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
             iface_id: String::new(),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            guest_mac: None,
+            rx_interrupt_coalescing: None,
+            tx_interrupt_coalescing: None,
         });
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -2036,6 +3280,9 @@ mod tests {
             iface_id: String::new(),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            guest_mac: None,
+            rx_interrupt_coalescing: None,
+            tx_interrupt_coalescing: None,
         });
         check_runtime_request_err(
             req,
@@ -2045,8 +3292,117 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_update_entropy_device() {
+        let req = VmmAction::UpdateEntropyDevice(EntropyDeviceUpdateConfig { rate_limiter: None });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_entropy_rate_limiter_called)
+        });
+
+        let req = VmmAction::UpdateEntropyDevice(EntropyDeviceUpdateConfig { rate_limiter: None });
+        check_runtime_request_err(
+            req,
+            VmmActionError::EntropyDevice(EntropyDeviceError::DeviceUpdate(
+                VmmError::DeviceManager(crate::device_manager::mmio::MmioError::InvalidDeviceType),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_runtime_update_net_guest_mac() {
+        let guest_mac = utils::net::mac::MacAddr::from_bytes_unchecked(&[1, 2, 3, 4, 5, 6]);
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            guest_mac: Some(guest_mac),
+            rx_interrupt_coalescing: None,
+            tx_interrupt_coalescing: None,
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_net_rate_limiters_called);
+            assert!(vmm.update_net_guest_mac_called);
+        });
+
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            guest_mac: None,
+            rx_interrupt_coalescing: None,
+            tx_interrupt_coalescing: None,
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(!vmm.update_net_guest_mac_called);
+            assert!(!vmm.update_net_interrupt_coalescing_called);
+        });
+    }
+
+    #[test]
+    fn test_runtime_update_net_link() {
+        let config = NetworkInterfaceLinkConfig {
+            state: LinkState::Down,
+        };
+        let req = VmmAction::UpdateNetworkInterfaceLink(String::new(), config);
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_net_link_called);
+        });
+
+        let req = VmmAction::UpdateNetworkInterfaceLink(String::new(), config);
+        check_runtime_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceUpdate(
+                VmmError::DeviceManager(crate::device_manager::mmio::MmioError::InvalidDeviceType),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_runtime_set_mmio_trace() {
+        let config = MmioTraceConfig {
+            device_ids: vec![String::from("rootfs")],
+        };
+        check_runtime_request(VmmAction::SetMmioTrace(config.clone()), |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.set_mmio_trace_called);
+        });
+        check_runtime_request_err(
+            VmmAction::SetMmioTrace(config),
+            VmmActionError::MmioTrace(MmioTraceError::UnknownDevice(String::from("rootfs"))),
+        );
+    }
+
+    #[test]
+    fn test_runtime_update_net_interrupt_coalescing() {
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            guest_mac: None,
+            rx_interrupt_coalescing: Some(InterruptCoalescingConfig {
+                max_frames: 16,
+                max_usecs: 50,
+            }),
+            tx_interrupt_coalescing: None,
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_net_interrupt_coalescing_called);
+        });
+    }
+
     #[test]
     fn test_runtime_disallowed() {
+        check_runtime_request_err(
+            VmmAction::ApplyConfigBatch(ConfigBatch {
+                devices: vec![DeviceConfig::Entropy(EntropyDeviceConfig::default())],
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
         check_runtime_request_err(
             VmmAction::ConfigureBootSource(BootSourceConfig::default()),
             VmmActionError::OperationNotSupportedPostBoot,
@@ -2078,8 +3434,11 @@ mod tests {
                 path_on_host: Some(String::new()),
                 rate_limiter: None,
                 file_engine_type: None,
+                overlay_path: None,
 
                 socket: None,
+                reconnect: None,
+                worker: false,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2090,6 +3449,13 @@ mod tests {
                 guest_mac: None,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                peer: None,
+                dma_ranges: None,
+                rx_interrupt_coalescing: None,
+                tx_interrupt_coalescing: None,
+                worker: false,
+                dhcp: None,
+                xdp: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2098,6 +3464,7 @@ mod tests {
                 vsock_id: Some(String::new()),
                 guest_cid: 0,
                 uds_path: String::new(),
+                dma_ranges: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2105,11 +3472,16 @@ mod tests {
             VmmAction::SetBalloonDevice(BalloonDeviceConfig::default()),
             VmmActionError::OperationNotSupportedPostBoot,
         );
+        check_runtime_request_err(
+            VmmAction::SetMemoryPressurePolicy(MemoryPressurePolicyConfig::default()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
         check_runtime_request_err(
             VmmAction::SetVsockDevice(VsockDeviceConfig {
                 vsock_id: Some(String::new()),
                 guest_cid: 0,
                 uds_path: String::new(),
+                dma_ranges: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2118,9 +3490,27 @@ mod tests {
                 ipv4_address: None,
                 version: MmdsVersion::default(),
                 network_interfaces: Vec::new(),
+                interfaces: Vec::new(),
+                data_file: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
+        check_runtime_request_err(
+            VmmAction::SetNetworkInterfaceDhcp(
+                String::new(),
+                DhcpConfig {
+                    ip_address: "192.168.0.2".parse().unwrap(),
+                    prefix_length: 24,
+                    gateway: None,
+                    nameservers: vec![],
+                },
+            ),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetPortForwards(Vec::new()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
         check_runtime_request_err(
             VmmAction::UpdateVmConfiguration(MachineConfigUpdate::from(MachineConfig::default())),
             VmmActionError::OperationNotSupportedPostBoot,
@@ -2134,6 +3524,10 @@ mod tests {
                 },
                 enable_diff_snapshots: false,
                 resume_vm: false,
+                verify: false,
+                encryption: None,
+                allow_cpu_mismatch: false,
+                overrides: Default::default(),
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2141,6 +3535,34 @@ mod tests {
             VmmAction::SetEntropyDevice(EntropyDeviceConfig::default()),
             VmmActionError::OperationNotSupportedPostBoot,
         );
+        check_runtime_request_err(
+            VmmAction::SetCryptoDevice(CryptoDeviceConfig::default()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetTpm(TpmConfig {
+                socket: String::from("swtpm.sock"),
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetReplay(ReplayConfig {
+                mode: ReplayMode::Replay,
+                log_path: PathBuf::from("replay.log"),
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::InsertRemoteDevice(RemoteDeviceConfig {
+                id: String::from("remote0"),
+                device_type: 3,
+                socket: String::new(),
+                num_queues: 1,
+                queue_size: 256,
+                config_size: 0,
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
     }
 
     fn verify_load_snap_disallowed_after_boot_resources(res: VmmAction, res_name: &str) {
@@ -2160,6 +3582,10 @@ mod tests {
             },
             enable_diff_snapshots: false,
             resume_vm: false,
+            verify: false,
+            encryption: None,
+            allow_cpu_mismatch: false,
+            overrides: Default::default(),
         });
         let err = preboot.handle_preboot_request(req);
         assert_eq!(
@@ -2188,8 +3614,11 @@ mod tests {
             path_on_host: Some(String::new()),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         let req = VmmAction::InsertBlockDevice(config);
@@ -2201,16 +3630,27 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            peer: None,
+            dma_ranges: None,
+            rx_interrupt_coalescing: None,
+            tx_interrupt_coalescing: None,
+            worker: false,
+            dhcp: None,
+            xdp: None,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "InsertNetworkDevice");
 
         let req = VmmAction::SetBalloonDevice(BalloonDeviceConfig::default());
         verify_load_snap_disallowed_after_boot_resources(req, "SetBalloonDevice");
 
+        let req = VmmAction::SetMemoryPressurePolicy(MemoryPressurePolicyConfig::default());
+        verify_load_snap_disallowed_after_boot_resources(req, "SetMemoryPressurePolicy");
+
         let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
             vsock_id: Some(String::new()),
             guest_cid: 0,
             uds_path: String::new(),
+            dma_ranges: None,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "SetVsockDevice");
 
@@ -2222,6 +3662,8 @@ mod tests {
             ipv4_address: None,
             version: MmdsVersion::default(),
             network_interfaces: Vec::new(),
+            interfaces: Vec::new(),
+            data_file: None,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "SetMmdsConfiguration");
     }
@@ -0,0 +1,176 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::alloc::{self, Layout};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use zeroize::Zeroize;
+
+use crate::logger::{error, IncMetric, METRICS};
+
+static SECRET_HARDENING: AtomicBool = AtomicBool::new(false);
+
+/// Enables the hardening of the secret buffers allocated from now on.
+pub fn set_secret_hardening(enabled: bool) {
+    SECRET_HARDENING.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether the secret buffers are hardened.
+pub fn secret_hardening() -> bool {
+    SECRET_HARDENING.load(Ordering::Relaxed)
+}
+
+/// A heap buffer staging data that must not leak out of Firecracker, such as the random bytes
+/// handed to the guest or the plaintext of the vsock connections.
+///
+/// When the secret hardening is enabled, the buffer is locked in memory so that it never gets
+/// swapped out, it is excluded from core dumps and it is zeroized when dropped. Its users should
+/// also [`zeroize`](SecretBuffer::zeroize) the data they are done with.
+#[derive(Debug)]
+pub struct SecretBuffer {
+    ptr: NonNull<u8>,
+    len: usize,
+    layout: Layout,
+    hardened: bool,
+}
+
+// SAFETY: The buffer exclusively owns its allocation.
+unsafe impl Send for SecretBuffer {}
+
+impl SecretBuffer {
+    /// Allocates a zero-filled buffer of `len` bytes.
+    pub fn new(len: usize) -> Self {
+        Self::with_hardening(len, secret_hardening())
+    }
+
+    fn with_hardening(len: usize, hardened: bool) -> Self {
+        // Hardened buffers own whole pages, so that locking them and excluding them from core
+        // dumps doesn't affect any other allocation.
+        let layout = if hardened {
+            let page_size = utils::get_page_size().expect("Cannot retrieve page size.");
+            Layout::from_size_align(len.max(1).next_multiple_of(page_size), page_size)
+        } else {
+            Layout::from_size_align(len.max(1), 1)
+        }
+        .expect("Invalid secret buffer size");
+
+        // SAFETY: The size of the layout is non-zero.
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        let Some(ptr) = NonNull::new(ptr) else {
+            alloc::handle_alloc_error(layout);
+        };
+
+        if hardened {
+            // SAFETY: The address and length are those of the allocation made above.
+            let ret =
+                unsafe { libc::madvise(ptr.as_ptr().cast(), layout.size(), libc::MADV_DONTDUMP) };
+            if ret < 0 {
+                error!(
+                    "Failed to exclude secret buffer from core dumps: {}",
+                    std::io::Error::last_os_error()
+                );
+                METRICS.vmm.secret_hardening_fails.inc();
+            }
+            // SAFETY: The address and length are those of the allocation made above.
+            if unsafe { libc::mlock(ptr.as_ptr().cast(), layout.size()) } < 0 {
+                error!(
+                    "Failed to lock secret buffer in memory: {}",
+                    std::io::Error::last_os_error()
+                );
+                METRICS.vmm.secret_hardening_fails.inc();
+            }
+        }
+
+        SecretBuffer {
+            ptr,
+            len,
+            layout,
+            hardened,
+        }
+    }
+
+    /// Whether the buffer is locked in memory and excluded from core dumps.
+    pub fn is_hardened(&self) -> bool {
+        self.hardened
+    }
+
+    /// Zeroizes `range` of the buffer if it is hardened.
+    pub fn zeroize(&mut self, range: std::ops::Range<usize>) {
+        if self.hardened {
+            self[range].zeroize();
+        }
+    }
+}
+
+impl Deref for SecretBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: The allocation is at least `len` bytes long and initialized.
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for SecretBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: The allocation is at least `len` bytes long, initialized, and exclusively
+        // borrowed.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for SecretBuffer {
+    fn drop(&mut self) {
+        if self.hardened {
+            let len = self.len;
+            self.zeroize(0..len);
+            // SAFETY: The address and length are those of the allocation of the buffer. Failures
+            // are ignored, as the memory is released right after.
+            unsafe {
+                libc::munlock(self.ptr.as_ptr().cast(), self.layout.size());
+                libc::madvise(
+                    self.ptr.as_ptr().cast(),
+                    self.layout.size(),
+                    libc::MADV_DODUMP,
+                );
+            }
+        }
+        // SAFETY: The buffer was allocated with this layout.
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_buffer() {
+        let mut buf = SecretBuffer::with_hardening(100, false);
+        assert!(!buf.is_hardened());
+        assert_eq!(buf.len(), 100);
+        assert!(buf.iter().all(|b| *b == 0));
+        buf.fill(0xaa);
+        // Plain buffers are left as they are.
+        buf.zeroize(0..10);
+        assert!(buf.iter().all(|b| *b == 0xaa));
+
+        assert_eq!(SecretBuffer::with_hardening(0, false).len(), 0);
+        assert_eq!(SecretBuffer::with_hardening(0, true).len(), 0);
+    }
+
+    #[test]
+    fn test_hardened_secret_buffer() {
+        let mut buf = SecretBuffer::with_hardening(5000, true);
+        assert!(buf.is_hardened());
+        assert_eq!(buf.len(), 5000);
+        assert_eq!(buf.as_ptr() as usize % utils::get_page_size().unwrap(), 0);
+        buf.fill(0xaa);
+        buf.zeroize(10..20);
+        assert!(buf[..10].iter().all(|b| *b == 0xaa));
+        assert!(buf[10..20].iter().all(|b| *b == 0));
+        assert!(buf[20..].iter().all(|b| *b == 0xaa));
+    }
+}
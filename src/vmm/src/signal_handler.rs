@@ -1,13 +1,19 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+
 use libc::{
-    c_int, c_void, siginfo_t, SIGBUS, SIGHUP, SIGILL, SIGPIPE, SIGSEGV, SIGSYS, SIGXCPU, SIGXFSZ,
+    c_int, c_void, siginfo_t, SIGBUS, SIGHUP, SIGILL, SIGPIPE, SIGSEGV, SIGSYS, SIGTERM, SIGXCPU,
+    SIGXFSZ,
 };
 use log::error;
+use utils::eventfd::EventFd;
 use utils::signal::register_signal_handler;
 
 use crate::logger::{IncMetric, StoreMetric, METRICS};
+use crate::vstate::memory_fault;
 use crate::FcExitCode;
 
 // The offset of `si_syscall` (offending syscall identifier) within the siginfo structure
@@ -19,6 +25,10 @@ const SI_OFF_SYSCALL: isize = 6;
 
 const SYS_SECCOMP_CODE: i32 = 1;
 
+// File descriptor of the event that the `SIGTERM` handler writes to, in order to request a
+// graceful shutdown from the Vmm.
+static SHUTDOWN_EVT_FD: AtomicI32 = AtomicI32::new(-1);
+
 #[inline]
 fn exit_with_code(exit_code: FcExitCode) {
     // Write the metrics before exiting.
@@ -93,7 +103,7 @@ generate_handler!(
 );
 
 generate_handler!(
-    sigbus_handler,
+    sigbus_fatal_handler,
     SIGBUS,
     SIGBUS,
     METRICS.signals.sigbus,
@@ -101,13 +111,28 @@ generate_handler!(
 );
 
 generate_handler!(
-    sigsegv_handler,
+    sigsegv_fatal_handler,
     SIGSEGV,
     SIGSEGV,
     METRICS.signals.sigsegv,
     empty_fn
 );
 
+// Faults on guest memory raised by the devices are recovered from, the other ones are fatal.
+#[inline(always)]
+extern "C" fn sigbus_handler(num: c_int, info: *mut siginfo_t, unused: *mut c_void) {
+    if num != SIGBUS || !memory_fault::recover(num, info) {
+        sigbus_fatal_handler(num, info, unused);
+    }
+}
+
+#[inline(always)]
+extern "C" fn sigsegv_handler(num: c_int, info: *mut siginfo_t, unused: *mut c_void) {
+    if num != SIGSEGV || !memory_fault::recover(num, info) {
+        sigsegv_fatal_handler(num, info, unused);
+    }
+}
+
 generate_handler!(
     sigsys_handler,
     SIGSYS,
@@ -151,6 +176,42 @@ extern "C" fn sigpipe_handler(num: c_int, info: *mut siginfo_t, _unused: *mut c_
     error!("Received signal {}, code {}.", si_signo, si_code);
 }
 
+#[inline(always)]
+extern "C" fn sigterm_handler(num: c_int, info: *mut siginfo_t, _unused: *mut c_void) {
+    // Only async-signal-safe operations are allowed here, so the actual shutdown is deferred to
+    // the Vmm event loop.
+
+    // SAFETY: Safe because we're just reading some fields from a supposedly valid argument.
+    let si_signo = unsafe { (*info).si_signo };
+    if num != si_signo || num != SIGTERM {
+        return;
+    }
+    METRICS.signals.sigterm.inc();
+
+    let fd = SHUTDOWN_EVT_FD.load(Ordering::Relaxed);
+    if fd < 0 {
+        exit_with_code(FcExitCode::Ok);
+        return;
+    }
+    let val: u64 = 1;
+    // SAFETY: `write` is async-signal-safe and `val` outlives the call.
+    unsafe {
+        libc::write(
+            fd,
+            std::ptr::addr_of!(val).cast(),
+            std::mem::size_of::<u64>(),
+        )
+    };
+}
+
+/// Makes `SIGTERM` initiate a graceful shutdown of the Vmm, by writing to `shutdown_evt`.
+///
+/// The event must stay open for as long as the process runs.
+pub fn register_shutdown_signal_handler(shutdown_evt: &EventFd) -> utils::errno::Result<()> {
+    SHUTDOWN_EVT_FD.store(shutdown_evt.as_raw_fd(), Ordering::Relaxed);
+    register_signal_handler(SIGTERM, sigterm_handler)
+}
+
 /// Registers all the required signal handlers.
 ///
 /// Custom handlers are installed for: `SIGBUS`, `SIGSEGV`, `SIGSYS`
@@ -248,6 +309,20 @@ mod tests {
         assert!(METRICS.signals.sigill.fetch() >= 1);
     }
 
+    #[test]
+    fn test_shutdown_signal_handler() {
+        let shutdown_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        register_shutdown_signal_handler(&shutdown_evt).unwrap();
+
+        let sigterm_count = METRICS.signals.sigterm.count();
+        unsafe {
+            syscall(libc::SYS_kill, process::id(), SIGTERM);
+        }
+
+        assert_eq!(shutdown_evt.read().unwrap(), 1);
+        assert_eq!(METRICS.signals.sigterm.count(), sigterm_count + 1);
+    }
+
     fn make_test_seccomp_bpf_filter() -> Vec<sock_filter> {
         // Create seccomp filter that allows all syscalls, except for `SYS_mkdirat`.
         // For some reason, directly calling `SYS_kill` with SIGSYS, like we do with the
@@ -0,0 +1,521 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements authenticated encryption (AES-256-GCM) of snapshot files.
+//!
+//! The microVM state file is sealed as a single message:
+//!
+//!  |-----------------------------|
+//!  |    64 bit encryption magic  |
+//!  |-----------------------------|
+//!  |        96 bit nonce         |
+//!  |-----------------------------|
+//!  |   ciphertext + 128 bit tag  |
+//!  |-----------------------------|
+//!
+//! The guest memory file is sealed as a stream of fixed size chunks, so that it never needs to be
+//! held in memory as a whole:
+//!
+//!  |-----------------------------|
+//!  |    64 bit encryption magic  |
+//!  |-----------------------------|
+//!  |     64 bit nonce prefix     |
+//!  |-----------------------------|
+//!  |  chunk 0 ciphertext + tag   |
+//!  |-----------------------------|
+//!  |             ...             |
+//!  |-----------------------------|
+//!
+//! The nonce of every chunk is made of the random nonce prefix followed by the 32 bit chunk index.
+//! The last chunk may be shorter than [`CHUNK_SIZE`]. Truncation of the stream is detected by the
+//! reader, which always knows how many bytes it expects.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::Mutex;
+
+use aws_lc_rs::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, MAX_TAG_LEN, NONCE_LEN};
+use aws_lc_rs::rand;
+use vm_memory::bitmap::BitmapSlice;
+use vm_memory::{VolatileMemoryError, VolatileSlice, WriteVolatile};
+
+/// Magic value identifying an encrypted microVM state file.
+pub const STATE_ENCRYPTION_MAGIC: u64 = 0x0710_1984_EC57_0001u64;
+/// Magic value identifying an encrypted guest memory file.
+pub const MEMORY_ENCRYPTION_MAGIC: u64 = 0x0710_1984_EC57_0002u64;
+/// Size in bytes of an encryption key.
+pub const KEY_LEN: usize = 32;
+/// Size in bytes of the plaintext of a guest memory file chunk.
+pub const CHUNK_SIZE: usize = 0x10_0000;
+/// Size in bytes of the random nonce prefix of a chunked stream.
+const NONCE_PREFIX_LEN: usize = 8;
+
+/// Errors related to snapshot encryption.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum SnapshotEncryptionError {
+    /// Invalid key file descriptor: {0}
+    InvalidKeyFd(RawFd),
+    /// File descriptor {0} was not passed to Firecracker with `--snapshot-key-fd`.
+    UnknownKeyFd(RawFd),
+    /// Failed to read the encryption key: {0}
+    ReadKey(io::Error),
+    /// Invalid encryption key.
+    InvalidKey,
+    /// Failed to generate a random nonce.
+    Nonce,
+    /// Failed to encrypt snapshot data.
+    Seal,
+    /// Failed to decrypt snapshot data: wrong key or corrupted data.
+    Open,
+    /// The file is not an encrypted snapshot file.
+    NotEncrypted,
+    /// The encrypted file is truncated.
+    Truncated,
+    /// The encrypted file is too large.
+    TooLarge,
+    /// An IO error occurred: {0}
+    Io(io::Error),
+}
+
+/// Key used to encrypt and decrypt snapshot files.
+#[derive(Debug)]
+pub struct SnapshotKey(LessSafeKey);
+
+impl SnapshotKey {
+    /// Creates a key from raw AES-256 key material.
+    pub fn new(key: &[u8; KEY_LEN]) -> Result<Self, SnapshotEncryptionError> {
+        let unbound =
+            UnboundKey::new(&AES_256_GCM, key).map_err(|_| SnapshotEncryptionError::InvalidKey)?;
+        Ok(SnapshotKey(LessSafeKey::new(unbound)))
+    }
+
+    /// Encrypts `plaintext` into a self-contained sealed message.
+    pub fn seal(&self, mut plaintext: Vec<u8>) -> Result<Vec<u8>, SnapshotEncryptionError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::fill(&mut nonce).map_err(|_| SnapshotEncryptionError::Nonce)?;
+        let tag = self
+            .0
+            .seal_in_place_separate_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(STATE_ENCRYPTION_MAGIC.to_le_bytes()),
+                &mut plaintext,
+            )
+            .map_err(|_| SnapshotEncryptionError::Seal)?;
+
+        let mut sealed = Vec::with_capacity(
+            std::mem::size_of::<u64>() + NONCE_LEN + plaintext.len() + MAX_TAG_LEN,
+        );
+        sealed.extend_from_slice(&STATE_ENCRYPTION_MAGIC.to_le_bytes());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&plaintext);
+        sealed.extend_from_slice(tag.as_ref());
+        Ok(sealed)
+    }
+
+    /// Decrypts a message produced by [`SnapshotKey::seal`].
+    pub fn open(&self, mut sealed: Vec<u8>) -> Result<Vec<u8>, SnapshotEncryptionError> {
+        if !is_sealed_state(&sealed) {
+            return Err(SnapshotEncryptionError::NotEncrypted);
+        }
+        let header_len = std::mem::size_of::<u64>() + NONCE_LEN;
+        if sealed.len() < header_len + MAX_TAG_LEN {
+            return Err(SnapshotEncryptionError::Truncated);
+        }
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&sealed[std::mem::size_of::<u64>()..header_len]);
+        let plaintext_len = self
+            .0
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(STATE_ENCRYPTION_MAGIC.to_le_bytes()),
+                &mut sealed[header_len..],
+            )
+            .map_err(|_| SnapshotEncryptionError::Open)?
+            .len();
+        sealed.drain(..header_len);
+        sealed.truncate(plaintext_len);
+        Ok(sealed)
+    }
+}
+
+/// Returns whether `contents` look like a microVM state file sealed by [`SnapshotKey::seal`].
+pub fn is_sealed_state(contents: &[u8]) -> bool {
+    contents.len() >= std::mem::size_of::<u64>()
+        && contents[..std::mem::size_of::<u64>()] == STATE_ENCRYPTION_MAGIC.to_le_bytes()
+}
+
+/// Interface for obtaining the key used to encrypt or decrypt a snapshot.
+///
+/// Firecracker reads keys from a file descriptor (see [`FdKeyProvider`]). Embedders that fetch
+/// keys from a key management service can provide their own implementation.
+pub trait SnapshotKeyProvider: Debug {
+    /// Returns the snapshot key.
+    fn snapshot_key(&self) -> Result<SnapshotKey, SnapshotEncryptionError>;
+}
+
+/// Key files inherited by Firecracker, by file descriptor.
+static KEY_FILES: Mutex<BTreeMap<RawFd, File>> = Mutex::new(BTreeMap::new());
+
+/// Allows [`FdKeyProvider`] to read keys from the file descriptor `fd`.
+///
+/// # Safety
+///
+/// `fd` must be an open file descriptor, owned by nothing else.
+pub unsafe fn add_key_fd(fd: RawFd) -> Result<(), SnapshotEncryptionError> {
+    if fd < 0 {
+        return Err(SnapshotEncryptionError::InvalidKeyFd(fd));
+    }
+    KEY_FILES
+        .lock()
+        .expect("Poisoned lock")
+        .insert(fd, File::from_raw_fd(fd));
+    Ok(())
+}
+
+/// Reads the raw key material from a file descriptor registered with [`add_key_fd`].
+///
+/// The key of a regular file is read at its start, so it can be read again for every snapshot.
+/// Other files, such as pipes, are consumed by the read.
+#[derive(Debug)]
+pub struct FdKeyProvider(pub RawFd);
+
+impl SnapshotKeyProvider for FdKeyProvider {
+    fn snapshot_key(&self) -> Result<SnapshotKey, SnapshotEncryptionError> {
+        let key_files = KEY_FILES.lock().expect("Poisoned lock");
+        let mut file = key_files
+            .get(&self.0)
+            .ok_or(SnapshotEncryptionError::UnknownKeyFd(self.0))?;
+        let metadata = file.metadata().map_err(SnapshotEncryptionError::ReadKey)?;
+        if metadata.is_file() {
+            file.seek(SeekFrom::Start(0))
+                .map_err(SnapshotEncryptionError::ReadKey)?;
+        }
+        let mut key = [0u8; KEY_LEN];
+        file.read_exact(&mut key)
+            .map_err(SnapshotEncryptionError::ReadKey)?;
+        SnapshotKey::new(&key)
+    }
+}
+
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_LEN], index: u32) -> Nonce {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..].copy_from_slice(&index.to_le_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
+
+fn io_error(err: SnapshotEncryptionError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// Writer that encrypts everything written to it as a stream of chunks.
+///
+/// [`SealingWriter::finish`] must be called once all data has been written.
+#[derive(Debug)]
+pub struct SealingWriter<'a, W: Write> {
+    key: &'a SnapshotKey,
+    writer: W,
+    prefix: [u8; NONCE_PREFIX_LEN],
+    index: u32,
+    buf: Vec<u8>,
+}
+
+impl<'a, W: Write> SealingWriter<'a, W> {
+    /// Creates a new writer and writes the stream header.
+    pub fn new(key: &'a SnapshotKey, mut writer: W) -> Result<Self, SnapshotEncryptionError> {
+        let mut prefix = [0u8; NONCE_PREFIX_LEN];
+        rand::fill(&mut prefix).map_err(|_| SnapshotEncryptionError::Nonce)?;
+        writer
+            .write_all(&MEMORY_ENCRYPTION_MAGIC.to_le_bytes())
+            .and_then(|()| writer.write_all(&prefix))
+            .map_err(SnapshotEncryptionError::Io)?;
+        Ok(SealingWriter {
+            key,
+            writer,
+            prefix,
+            index: 0,
+            buf: Vec::with_capacity(CHUNK_SIZE + MAX_TAG_LEN),
+        })
+    }
+
+    fn seal_chunk(&mut self) -> io::Result<()> {
+        let tag = self
+            .key
+            .0
+            .seal_in_place_separate_tag(
+                chunk_nonce(&self.prefix, self.index),
+                Aad::from(MEMORY_ENCRYPTION_MAGIC.to_le_bytes()),
+                &mut self.buf,
+            )
+            .map_err(|_| io_error(SnapshotEncryptionError::Seal))?;
+        self.index = self
+            .index
+            .checked_add(1)
+            .ok_or_else(|| io_error(SnapshotEncryptionError::TooLarge))?;
+        self.writer.write_all(&self.buf)?;
+        self.writer.write_all(tag.as_ref())?;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// Seals the last, possibly partial, chunk and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.buf.is_empty() {
+            self.seal_chunk()?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for SealingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(CHUNK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&buf[..len]);
+        if self.buf.len() == CHUNK_SIZE {
+            self.seal_chunk()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> WriteVolatile for SealingWriter<'_, W> {
+    fn write_volatile<B: BitmapSlice>(
+        &mut self,
+        buf: &VolatileSlice<B>,
+    ) -> Result<usize, VolatileMemoryError> {
+        let start = self.buf.len();
+        let len = buf.len().min(CHUNK_SIZE - start);
+        self.buf.resize(start + len, 0);
+        buf.subslice(0, len)?.copy_to(&mut self.buf[start..]);
+        if self.buf.len() == CHUNK_SIZE {
+            self.seal_chunk().map_err(VolatileMemoryError::IOError)?;
+        }
+        Ok(len)
+    }
+}
+
+/// Reader that decrypts a stream of chunks produced by a [`SealingWriter`].
+#[derive(Debug)]
+pub struct OpeningReader<'a, R: Read> {
+    key: &'a SnapshotKey,
+    reader: R,
+    prefix: [u8; NONCE_PREFIX_LEN],
+    index: u32,
+    remaining: u64,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<'a, R: Read> OpeningReader<'a, R> {
+    /// Creates a new reader, checking the stream header. `len` is the size in bytes of the
+    /// plaintext stored in the stream.
+    pub fn new(
+        key: &'a SnapshotKey,
+        mut reader: R,
+        len: u64,
+    ) -> Result<Self, SnapshotEncryptionError> {
+        let mut magic = [0u8; std::mem::size_of::<u64>()];
+        let mut prefix = [0u8; NONCE_PREFIX_LEN];
+        reader
+            .read_exact(&mut magic)
+            .and_then(|()| reader.read_exact(&mut prefix))
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => SnapshotEncryptionError::Truncated,
+                _ => SnapshotEncryptionError::Io(err),
+            })?;
+        if u64::from_le_bytes(magic) != MEMORY_ENCRYPTION_MAGIC {
+            return Err(SnapshotEncryptionError::NotEncrypted);
+        }
+        Ok(OpeningReader {
+            key,
+            reader,
+            prefix,
+            index: 0,
+            remaining: len,
+            buf: Vec::with_capacity(CHUNK_SIZE + MAX_TAG_LEN),
+            pos: 0,
+        })
+    }
+
+    fn open_chunk(&mut self) -> io::Result<()> {
+        let plaintext_len = usize::try_from(self.remaining)
+            .unwrap_or(CHUNK_SIZE)
+            .min(CHUNK_SIZE);
+        self.buf.resize(plaintext_len + MAX_TAG_LEN, 0);
+        self.reader.read_exact(&mut self.buf).map_err(|err| {
+            if err.kind() == io::ErrorKind::UnexpectedEof {
+                io_error(SnapshotEncryptionError::Truncated)
+            } else {
+                err
+            }
+        })?;
+        self.key
+            .0
+            .open_in_place(
+                chunk_nonce(&self.prefix, self.index),
+                Aad::from(MEMORY_ENCRYPTION_MAGIC.to_le_bytes()),
+                &mut self.buf,
+            )
+            .map_err(|_| io_error(SnapshotEncryptionError::Open))?;
+        self.buf.truncate(plaintext_len);
+        self.index = self
+            .index
+            .checked_add(1)
+            .ok_or_else(|| io_error(SnapshotEncryptionError::TooLarge))?;
+        self.remaining -= plaintext_len as u64;
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for OpeningReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buf.len() {
+            if self.remaining == 0 {
+                return Ok(0);
+            }
+            self.open_chunk()?;
+        }
+        let len = buf.len().min(self.buf.len() - self.pos);
+        buf[..len].copy_from_slice(&self.buf[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key() -> SnapshotKey {
+        SnapshotKey::new(&[0x42; KEY_LEN]).unwrap()
+    }
+
+    #[test]
+    fn test_seal_open() {
+        let key = test_key();
+        let plaintext = b"microVM state".to_vec();
+
+        let sealed = key.seal(plaintext.clone()).unwrap();
+        assert!(is_sealed_state(&sealed));
+        assert!(!is_sealed_state(&plaintext));
+        assert_eq!(key.open(sealed.clone()).unwrap(), plaintext);
+
+        // Wrong key.
+        let other_key = SnapshotKey::new(&[0x43; KEY_LEN]).unwrap();
+        assert!(matches!(
+            other_key.open(sealed.clone()),
+            Err(SnapshotEncryptionError::Open)
+        ));
+
+        // Tampered ciphertext.
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            key.open(tampered),
+            Err(SnapshotEncryptionError::Open)
+        ));
+
+        // Truncated message.
+        assert!(matches!(
+            key.open(sealed[..20].to_vec()),
+            Err(SnapshotEncryptionError::Truncated)
+        ));
+
+        // Plaintext input.
+        assert!(matches!(
+            key.open(plaintext),
+            Err(SnapshotEncryptionError::NotEncrypted)
+        ));
+    }
+
+    #[test]
+    fn test_chunked_stream() {
+        let key = test_key();
+        let plaintext: Vec<u8> = (0..CHUNK_SIZE * 2 + 100)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect();
+
+        let mut writer = SealingWriter::new(&key, Vec::new()).unwrap();
+        writer.write_all(&plaintext).unwrap();
+        let sealed = writer.finish().unwrap();
+        assert_eq!(
+            sealed.len(),
+            std::mem::size_of::<u64>() + NONCE_PREFIX_LEN + plaintext.len() + 3 * MAX_TAG_LEN
+        );
+
+        let mut reader =
+            OpeningReader::new(&key, sealed.as_slice(), plaintext.len() as u64).unwrap();
+        let mut opened = Vec::new();
+        reader.read_to_end(&mut opened).unwrap();
+        assert_eq!(opened, plaintext);
+
+        // Truncated stream.
+        let truncated = &sealed[..sealed.len() - 1];
+        let mut reader = OpeningReader::new(&key, truncated, plaintext.len() as u64).unwrap();
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Swapped chunks are rejected.
+        let header_len = std::mem::size_of::<u64>() + NONCE_PREFIX_LEN;
+        let chunk_len = CHUNK_SIZE + MAX_TAG_LEN;
+        let mut swapped = sealed.clone();
+        swapped[header_len..header_len + 2 * chunk_len].rotate_left(chunk_len);
+        let mut reader =
+            OpeningReader::new(&key, swapped.as_slice(), plaintext.len() as u64).unwrap();
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Unencrypted stream.
+        assert!(matches!(
+            OpeningReader::new(&key, plaintext.as_slice(), plaintext.len() as u64),
+            Err(SnapshotEncryptionError::NotEncrypted)
+        ));
+    }
+
+    #[test]
+    fn test_fd_key_provider() {
+        use std::os::unix::io::IntoRawFd;
+
+        let key_file = utils::tempfile::TempFile::new().unwrap();
+        key_file.as_file().write_all(&[0x42; KEY_LEN]).unwrap();
+        let fd = File::open(key_file.as_path()).unwrap().into_raw_fd();
+
+        // Only registered file descriptors are read.
+        assert!(matches!(
+            FdKeyProvider(fd).snapshot_key(),
+            Err(SnapshotEncryptionError::UnknownKeyFd(_))
+        ));
+        // SAFETY: The file descriptor was just opened and is owned by nothing else.
+        unsafe { add_key_fd(fd) }.unwrap();
+
+        // The key is read again for every snapshot.
+        for _ in 0..2 {
+            let key = FdKeyProvider(fd).snapshot_key().unwrap();
+            let sealed = key.seal(vec![1, 2, 3]).unwrap();
+            assert_eq!(test_key().open(sealed).unwrap(), vec![1, 2, 3]);
+        }
+
+        // The key file is shorter than a key.
+        key_file.as_file().set_len(KEY_LEN as u64 - 1).unwrap();
+        assert!(matches!(
+            FdKeyProvider(fd).snapshot_key(),
+            Err(SnapshotEncryptionError::ReadKey(_))
+        ));
+
+        // SAFETY: -1 is rejected without being used.
+        let res = unsafe { add_key_fd(-1) };
+        assert!(matches!(
+            res,
+            Err(SnapshotEncryptionError::InvalidKeyFd(-1))
+        ));
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements a page-granular hash manifest of the guest memory file.
+//!
+//! The manifest lists the xxh3 hash of every [`MANIFEST_PAGE_SIZE`] page written to the memory
+//! file while a snapshot is taken. For full snapshots this covers the whole file, while for diff
+//! snapshots only the dirty pages are listed. Since entries are keyed by their offset in the
+//! memory file, the manifest of a diff snapshot can be merged on top of the manifest of its base.
+//!
+//! The manifest is stored as a sequence of little-endian integers:
+//!
+//!  |-----------------------------|
+//!  |    64 bit manifest magic    |
+//!  |-----------------------------|
+//!  |      64 bit page size       |
+//!  |-----------------------------|
+//!  |    64 bit number of pages   |
+//!  |-----------------------------|
+//!  | 64 bit offset, 64 bit hash  |
+//!  |-----------------------------|
+//!  |             ...             |
+//!  |-----------------------------|
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use vm_memory::bitmap::BitmapSlice;
+use vm_memory::{VolatileMemoryError, VolatileSlice, WriteVolatile};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Magic value identifying a page manifest file.
+pub const MANIFEST_MAGIC: u64 = 0x0710_1984_4A54_0001u64;
+/// Granularity at which the memory file is hashed.
+pub const MANIFEST_PAGE_SIZE: usize = 4096;
+
+/// Hash of a single page of the memory file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageHash {
+    /// Offset of the page in the memory file.
+    pub offset: u64,
+    /// xxh3 hash of the page contents.
+    pub hash: u64,
+}
+
+/// Page-granular hash manifest of a memory file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PageManifest {
+    /// Pages listed in the order they were written.
+    pub pages: Vec<PageHash>,
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; std::mem::size_of::<u64>()];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+impl PageManifest {
+    /// Writes the manifest to `writer`.
+    pub fn save<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&MANIFEST_MAGIC.to_le_bytes())?;
+        writer.write_all(&(MANIFEST_PAGE_SIZE as u64).to_le_bytes())?;
+        writer.write_all(&(self.pages.len() as u64).to_le_bytes())?;
+        for page in self.pages.iter() {
+            writer.write_all(&page.offset.to_le_bytes())?;
+            writer.write_all(&page.hash.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Reads a manifest previously written with [`PageManifest::save`].
+    pub fn load<R: Read>(reader: &mut R) -> io::Result<Self> {
+        if read_u64(reader)? != MANIFEST_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid page manifest magic",
+            ));
+        }
+        if read_u64(reader)? != MANIFEST_PAGE_SIZE as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported page manifest page size",
+            ));
+        }
+        let count = read_u64(reader)?;
+        let pages = (0..count)
+            .map(|_| {
+                Ok(PageHash {
+                    offset: read_u64(reader)?,
+                    hash: read_u64(reader)?,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(PageManifest { pages })
+    }
+}
+
+/// Writer that hashes every page going through it to a [`PageManifest`].
+///
+/// Pages are hashed as they are written, so building the manifest does not require reading the
+/// memory file back. Seeking finishes the current page, which is hashed even if it is partial.
+#[derive(Debug)]
+pub struct PageHashWriter<'a, W> {
+    writer: &'a mut W,
+    offset: u64,
+    page_offset: u64,
+    page: Vec<u8>,
+    manifest: PageManifest,
+}
+
+impl<'a, W: WriteVolatile + Seek> PageHashWriter<'a, W> {
+    /// Creates a new writer, positioned at the start of `writer`.
+    pub fn new(writer: &'a mut W) -> Self {
+        PageHashWriter {
+            writer,
+            offset: 0,
+            page_offset: 0,
+            page: Vec::with_capacity(MANIFEST_PAGE_SIZE),
+            manifest: PageManifest::default(),
+        }
+    }
+
+    fn finish_page(&mut self) {
+        if !self.page.is_empty() {
+            self.manifest.pages.push(PageHash {
+                offset: self.page_offset,
+                hash: xxh3_64(&self.page),
+            });
+            self.page.clear();
+        }
+    }
+
+    /// Hashes the last page, if partial, and returns the manifest of the written pages.
+    pub fn finish(mut self) -> PageManifest {
+        self.finish_page();
+        self.manifest
+    }
+}
+
+impl<W: WriteVolatile + Seek> WriteVolatile for PageHashWriter<'_, W> {
+    fn write_volatile<B: BitmapSlice>(
+        &mut self,
+        buf: &VolatileSlice<B>,
+    ) -> Result<usize, VolatileMemoryError> {
+        let count = self.writer.write_volatile(buf)?;
+        let mut written = buf.subslice(0, count)?;
+        while !written.is_empty() {
+            if self.page.is_empty() {
+                self.page_offset = self.offset;
+            }
+            let start = self.page.len();
+            let len = written.len().min(MANIFEST_PAGE_SIZE - start);
+            self.page.resize(start + len, 0);
+            written.subslice(0, len)?.copy_to(&mut self.page[start..]);
+            written = written.offset(len)?;
+            self.offset += len as u64;
+            if self.page.len() == MANIFEST_PAGE_SIZE {
+                self.finish_page();
+            }
+        }
+        Ok(count)
+    }
+}
+
+impl<W: WriteVolatile + Seek> Seek for PageHashWriter<'_, W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.finish_page();
+        self.offset = self.writer.seek(pos)?;
+        Ok(self.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_page_hash_writer() {
+        let contents: Vec<u8> = (0..MANIFEST_PAGE_SIZE * 4)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect();
+        let page = |i: usize| &contents[i * MANIFEST_PAGE_SIZE..(i + 1) * MANIFEST_PAGE_SIZE];
+
+        let mut file = utils::tempfile::TempFile::new().unwrap().into_file();
+        let mut writer = PageHashWriter::new(&mut file);
+        // Two pages written in uneven pieces, then one page further away.
+        writer
+            .write_all_volatile(&VolatileSlice::from(&mut contents.clone()[..100]))
+            .unwrap();
+        writer
+            .write_all_volatile(&VolatileSlice::from(
+                &mut contents.clone()[100..MANIFEST_PAGE_SIZE * 2],
+            ))
+            .unwrap();
+        writer
+            .seek(SeekFrom::Start(3 * MANIFEST_PAGE_SIZE as u64))
+            .unwrap();
+        writer
+            .write_all_volatile(&VolatileSlice::from(&mut page(3).to_vec()[..]))
+            .unwrap();
+        let manifest = writer.finish();
+
+        assert_eq!(
+            manifest.pages,
+            vec![
+                PageHash {
+                    offset: 0,
+                    hash: xxh3_64(page(0)),
+                },
+                PageHash {
+                    offset: MANIFEST_PAGE_SIZE as u64,
+                    hash: xxh3_64(page(1)),
+                },
+                PageHash {
+                    offset: 3 * MANIFEST_PAGE_SIZE as u64,
+                    hash: xxh3_64(page(3)),
+                },
+            ]
+        );
+        assert_eq!(
+            file.metadata().unwrap().len(),
+            4 * MANIFEST_PAGE_SIZE as u64
+        );
+
+        // Identical pages hash to the same value, regardless of their offset.
+        let mut file = utils::tempfile::TempFile::new().unwrap().into_file();
+        let mut writer = PageHashWriter::new(&mut file);
+        writer
+            .write_all_volatile(&VolatileSlice::from(&mut [0u8; MANIFEST_PAGE_SIZE * 2][..]))
+            .unwrap();
+        let manifest = writer.finish();
+        assert_eq!(manifest.pages[0].hash, manifest.pages[1].hash);
+    }
+
+    #[test]
+    fn test_manifest_save_load() {
+        let manifest = PageManifest {
+            pages: vec![
+                PageHash {
+                    offset: 0,
+                    hash: 0xdead_beef,
+                },
+                PageHash {
+                    offset: 0x3000,
+                    hash: 0xcafe_babe,
+                },
+            ],
+        };
+        let mut buf = Vec::new();
+        manifest.save(&mut buf).unwrap();
+        assert_eq!(buf.len(), 3 * 8 + 2 * 16);
+        assert_eq!(PageManifest::load(&mut buf.as_slice()).unwrap(), manifest);
+
+        // Truncated manifest.
+        PageManifest::load(&mut &buf[..buf.len() - 1]).unwrap_err();
+        // Invalid magic.
+        buf[0] ^= 0xff;
+        PageManifest::load(&mut Cursor::new(&buf)).unwrap_err();
+    }
+}
@@ -26,7 +26,10 @@
 //! The snapshot format uses a version value in the form of `MAJOR.MINOR.PATCH`. The version is
 //! provided by the library clients (it is not tied to this crate).
 pub mod crc;
+pub mod encryption;
+pub mod manifest;
 mod persist;
+pub mod sparse;
 use std::fmt::Debug;
 use std::io::{Read, Write};
 
@@ -153,6 +156,15 @@ impl Snapshot {
     where
         T: Read + Debug,
         O: DeserializeOwned + Debug,
+    {
+        let snapshot = Self::read_checked(reader, snapshot_len)?;
+        Snapshot::unchecked_load::<_, O>(&mut snapshot.as_slice())
+    }
+
+    /// Reads a snapshot from a reader and validates its CRC, returning it without the CRC.
+    fn read_checked<T>(reader: &mut T, snapshot_len: usize) -> Result<Vec<u8>, SnapshotError>
+    where
+        T: Read + Debug,
     {
         let mut crc_reader = CRC64Reader::new(reader);
 
@@ -176,8 +188,7 @@ impl Snapshot {
             return Err(SnapshotError::Crc64(computed_checksum));
         }
 
-        let mut snapshot_slice: &[u8] = snapshot.as_mut_slice();
-        Snapshot::unchecked_load::<_, O>(&mut snapshot_slice)
+        Ok(snapshot)
     }
 
     /// Load a snapshot from a reader object and perform a snapshot version check
@@ -190,12 +201,15 @@ impl Snapshot {
         T: Read + Debug,
         O: DeserializeOwned + Debug,
     {
-        let (data, version) = Snapshot::load::<_, O>(reader, snapshot_len)?;
+        let snapshot = Self::read_checked(reader, snapshot_len)?;
+        // The version is checked before the data, as the layout of the data may differ between
+        // incompatible versions.
+        let version = Snapshot::get_format_version(&mut snapshot.as_slice())?;
         if version.major != self.version.major || version.minor > self.version.minor {
-            Err(SnapshotError::InvalidFormatVersion(version))
-        } else {
-            Ok(data)
+            return Err(SnapshotError::InvalidFormatVersion(version));
         }
+        let (data, _) = Snapshot::unchecked_load::<_, O>(&mut snapshot.as_slice())?;
+        Ok(data)
     }
 
     /// Saves a snapshot and include a CRC64 checksum.
@@ -386,4 +400,27 @@ mod tests {
             .load_with_version_check::<_, u8>(&mut data.as_slice(), data.len())
             .unwrap();
     }
+
+    #[test]
+    fn test_version_checked_before_data() {
+        // The data of a snapshot with an unsupported version is not deserialized, so a layout
+        // change between major versions is reported as a version mismatch.
+        let mut data = Vec::new();
+        Snapshot::new(Version::new(1, 0, 0))
+            .save(&mut data, &42u8)
+            .unwrap();
+
+        let snapshot = Snapshot::new(Version::new(2, 0, 0));
+        assert!(matches!(
+            snapshot.load_with_version_check::<_, (u64, u64)>(&mut data.as_slice(), data.len()),
+            Err(SnapshotError::InvalidFormatVersion(Version {
+                major: 1,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            Snapshot::load::<_, (u64, u64)>(&mut data.as_slice(), data.len()),
+            Err(SnapshotError::Serde(_))
+        ));
+    }
 }
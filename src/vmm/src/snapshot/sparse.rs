@@ -0,0 +1,190 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements sparse writes of the guest memory file.
+//!
+//! Most of the guest memory of a freshly booted or lightly used microVM is zero. Instead of
+//! writing these pages, the [`SparseWriter`] leaves holes in the memory file, which read back as
+//! zeros, so that the file takes less space and less time to write.
+
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::os::unix::io::AsRawFd;
+
+use vm_memory::bitmap::BitmapSlice;
+use vm_memory::{VolatileMemoryError, VolatileSlice, WriteVolatile};
+
+/// Granularity at which zero pages are detected.
+pub const SPARSE_PAGE_SIZE: usize = 4096;
+
+/// Writer that skips the zero pages going through it, leaving holes in the file.
+///
+/// The file must already have its final size, since skipped pages at the end of the file do not
+/// extend it.
+#[derive(Debug)]
+pub struct SparseWriter<'a> {
+    file: &'a mut File,
+    punch_holes: bool,
+    offset: u64,
+    hole: Option<Range<u64>>,
+    page: Vec<u8>,
+}
+
+impl<'a> SparseWriter<'a> {
+    /// Creates a new writer, positioned at the start of `file`.
+    ///
+    /// If `punch_holes` is set, the skipped pages are deallocated from the file, which is needed
+    /// when they may contain data written before.
+    pub fn new(file: &'a mut File, punch_holes: bool) -> Self {
+        SparseWriter {
+            file,
+            punch_holes,
+            offset: 0,
+            hole: None,
+            page: Vec::with_capacity(SPARSE_PAGE_SIZE),
+        }
+    }
+
+    // Deallocates the pending hole, if requested, and moves the file position past it.
+    fn flush_hole(&mut self) -> io::Result<()> {
+        let Some(hole) = self.hole.take() else {
+            return Ok(());
+        };
+        if self.punch_holes {
+            // SAFETY: The file descriptor is valid and the result is checked.
+            let ret = unsafe {
+                libc::fallocate(
+                    self.file.as_raw_fd(),
+                    libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                    i64::try_from(hole.start).map_err(io::Error::other)?,
+                    i64::try_from(hole.end - hole.start).map_err(io::Error::other)?,
+                )
+            };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        self.file.seek(SeekFrom::Start(hole.end))?;
+        Ok(())
+    }
+
+    /// Completes the trailing hole, if any.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush_hole()
+    }
+}
+
+impl WriteVolatile for SparseWriter<'_> {
+    fn write_volatile<B: BitmapSlice>(
+        &mut self,
+        buf: &VolatileSlice<B>,
+    ) -> Result<usize, VolatileMemoryError> {
+        let mut remaining = buf.offset(0)?;
+        while !remaining.is_empty() {
+            // Pages are aligned on their offset in the file.
+            let page_offset = usize::try_from(self.offset % SPARSE_PAGE_SIZE as u64).unwrap();
+            let len = remaining.len().min(SPARSE_PAGE_SIZE - page_offset);
+            self.page.resize(len, 0);
+            remaining.subslice(0, len)?.copy_to(&mut self.page[..]);
+
+            if self.page.iter().all(|&byte| byte == 0) {
+                self.hole.get_or_insert(self.offset..self.offset).end += len as u64;
+            } else {
+                self.flush_hole().map_err(VolatileMemoryError::IOError)?;
+                self.file
+                    .write_all(&self.page)
+                    .map_err(VolatileMemoryError::IOError)?;
+            }
+            self.offset += len as u64;
+            remaining = remaining.offset(len)?;
+        }
+        Ok(buf.len())
+    }
+}
+
+impl Seek for SparseWriter<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.flush_hole()?;
+        self.offset = self.file.seek(pos)?;
+        Ok(self.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::os::unix::fs::MetadataExt;
+
+    use utils::seek_hole::SeekHole;
+
+    use super::*;
+
+    const FILE_SIZE: usize = SPARSE_PAGE_SIZE * 8;
+
+    fn contents() -> Vec<u8> {
+        let mut contents = vec![0u8; FILE_SIZE];
+        // One data page, followed by three zero pages, a partially zero page and zero pages.
+        contents[..SPARSE_PAGE_SIZE].fill(0xAA);
+        contents[SPARSE_PAGE_SIZE * 4 + 100] = 0xBB;
+        contents
+    }
+
+    fn write_sparse(file: &mut File, contents: &[u8], punch_holes: bool) {
+        let mut writer = SparseWriter::new(file, punch_holes);
+        // Written in uneven pieces, with a seek over a page.
+        writer
+            .write_all_volatile(&VolatileSlice::from(&mut contents.to_vec()[..1000]))
+            .unwrap();
+        writer
+            .write_all_volatile(&VolatileSlice::from(
+                &mut contents.to_vec()[1000..SPARSE_PAGE_SIZE * 5],
+            ))
+            .unwrap();
+        writer
+            .seek(SeekFrom::Start((SPARSE_PAGE_SIZE * 6) as u64))
+            .unwrap();
+        writer
+            .write_all_volatile(&VolatileSlice::from(
+                &mut contents.to_vec()[SPARSE_PAGE_SIZE * 6..],
+            ))
+            .unwrap();
+        writer.finish().unwrap();
+    }
+
+    fn read_all(file: &mut File) -> Vec<u8> {
+        let mut read = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut read).unwrap();
+        read
+    }
+
+    #[test]
+    fn test_sparse_writer() {
+        let contents = contents();
+        let mut file = utils::tempfile::TempFile::new().unwrap().into_file();
+        file.set_len(FILE_SIZE as u64).unwrap();
+        write_sparse(&mut file, &contents, false);
+
+        assert_eq!(read_all(&mut file), contents);
+        // The zero pages following the first one are not allocated.
+        assert_eq!(file.seek_hole(0).unwrap(), Some(SPARSE_PAGE_SIZE as u64));
+        assert!(file.metadata().unwrap().blocks() * 512 < FILE_SIZE as u64);
+    }
+
+    #[test]
+    fn test_sparse_writer_punch_holes() {
+        let contents = contents();
+        let mut file = utils::tempfile::TempFile::new().unwrap().into_file();
+        file.write_all(&[0xCC; FILE_SIZE]).unwrap();
+        let blocks = file.metadata().unwrap().blocks();
+        file.seek(SeekFrom::Start(0)).unwrap();
+
+        // The data written before is deallocated, except for the page skipped by the seek.
+        write_sparse(&mut file, &contents, true);
+        let mut expected = contents.clone();
+        expected[SPARSE_PAGE_SIZE * 5..SPARSE_PAGE_SIZE * 6].fill(0xCC);
+        assert_eq!(read_all(&mut file), expected);
+        assert!(file.metadata().unwrap().blocks() < blocks);
+    }
+}
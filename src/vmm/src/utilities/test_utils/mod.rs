@@ -8,12 +8,13 @@ use utils::tempdir::TempDir;
 use vm_memory::GuestAddress;
 
 use crate::builder::build_microvm_for_boot;
+use crate::landlock::set_landlock;
 use crate::resources::VmResources;
 use crate::seccomp_filters::get_empty_filters;
 use crate::utilities::mock_resources::{MockBootSourceConfig, MockVmConfig, MockVmResources};
 use crate::vmm_config::boot_source::BootSourceConfig;
 use crate::vmm_config::instance_info::InstanceInfo;
-use crate::vmm_config::machine_config::HugePageConfig;
+use crate::vmm_config::machine_config::{HugePageConfig, MemoryLayoutConfig};
 use crate::vstate::memory::{GuestMemoryExtension, GuestMemoryMmap};
 use crate::{EventManager, Vmm};
 
@@ -38,7 +39,10 @@ pub fn multi_region_mem(regions: &[(GuestAddress, usize)]) -> GuestMemoryMmap {
 /// Creates a [`GuestMemoryMmap`] of the given size with the contained regions laid out in
 /// accordance with the requirements of the architecture on which the tests are being run.
 pub fn arch_mem(mem_size_bytes: usize) -> GuestMemoryMmap {
-    multi_region_mem(&crate::arch::arch_memory_regions(mem_size_bytes))
+    multi_region_mem(&crate::arch::arch_memory_regions(
+        mem_size_bytes,
+        &MemoryLayoutConfig::default(),
+    ))
 }
 
 pub fn create_vmm(
@@ -48,6 +52,9 @@ pub fn create_vmm(
 ) -> (Arc<Mutex<Vmm>>, EventManager) {
     let mut event_manager = EventManager::new().unwrap();
     let empty_seccomp_filters = get_empty_filters();
+    // The snapshots of the tests are created in temporary files, outside of the working
+    // directory.
+    set_landlock(false);
 
     let boot_source_cfg = MockBootSourceConfig::new().with_default_boot_args();
     #[cfg(target_arch = "aarch64")]
@@ -58,7 +65,7 @@ pub fn create_vmm(
         None => boot_source_cfg.into(),
     };
     let mock_vm_res = MockVmResources::new().with_boot_source(boot_source_cfg);
-    let resources: VmResources = if is_diff {
+    let mut resources: VmResources = if is_diff {
         mock_vm_res
             .with_vm_config(MockVmConfig::new().with_dirty_page_tracking().into())
             .into()
@@ -68,7 +75,7 @@ pub fn create_vmm(
 
     let vmm = build_microvm_for_boot(
         &InstanceInfo::default(),
-        &resources,
+        &mut resources,
         &mut event_manager,
         &empty_seccomp_filters,
     )
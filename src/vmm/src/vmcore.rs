@@ -0,0 +1,179 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Writes the guest memory of a microVM as an ELF core file, in the format of the `/proc/vmcore`
+//! file exposed by a Linux crash kernel, so that it can be analyzed with tools such as `crash`.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+use vm_memory::WriteVolatile;
+
+use crate::arch::PAGE_SIZE;
+use crate::vmm_config::instance_info::VmState;
+use crate::vmm_config::vmcore::CreateVmcoreParams;
+use crate::vstate::memory::{
+    Address, GuestMemory, GuestMemoryExtension, GuestMemoryMmap, GuestMemoryRegion, MemoryError,
+};
+use crate::Vmm;
+
+const ELF_HEADER_SIZE: u16 = 64;
+const ELF_PHDR_SIZE: u16 = 56;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_CORE: u16 = 4;
+#[cfg(target_arch = "x86_64")]
+const EM_MACHINE: u16 = 62; // EM_X86_64
+#[cfg(target_arch = "aarch64")]
+const EM_MACHINE: u16 = 183; // EM_AARCH64
+const PT_LOAD: u32 = 1;
+// PF_X | PF_W | PF_R
+const PF_RWX: u32 = 7;
+
+/// Errors associated with creating a vmcore file.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum CreateVmcoreError {
+    /// Cannot create a vmcore of a microVM that is not paused.
+    InvalidVmState,
+    /// Cannot create a vmcore of a confidential microVM.
+    Confidential,
+    /// Cannot open the vmcore file: {0}
+    OpenFile(io::Error),
+    /// Cannot write the ELF headers of the vmcore file: {0}
+    WriteHeaders(io::Error),
+    /// Cannot write the guest memory to the vmcore file: {0}
+    WriteMemory(MemoryError),
+}
+
+/// Writes the guest memory of the paused microVM to the ELF core file of `params`.
+pub fn create_vmcore(vmm: &Vmm, params: &CreateVmcoreParams) -> Result<(), CreateVmcoreError> {
+    if vmm.instance_info().state != VmState::Paused {
+        return Err(CreateVmcoreError::InvalidVmState);
+    }
+    // The host cannot read the memory of a confidential guest.
+    if vmm.vm.is_confidential() {
+        return Err(CreateVmcoreError::Confidential);
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&params.vmcore_path)
+        .map_err(CreateVmcoreError::OpenFile)?;
+    write_vmcore(vmm.guest_memory(), &mut file)
+}
+
+/// Writes the ELF core headers describing the guest memory regions, followed by their contents,
+/// to `writer`.
+fn write_vmcore<T: Write + WriteVolatile>(
+    guest_memory: &GuestMemoryMmap,
+    writer: &mut T,
+) -> Result<(), CreateVmcoreError> {
+    let headers = elf_headers(guest_memory);
+    writer
+        .write_all(&headers)
+        .map_err(CreateVmcoreError::WriteHeaders)?;
+    guest_memory
+        .dump(writer)
+        .map_err(CreateVmcoreError::WriteMemory)
+}
+
+// Builds the ELF header and the program headers of the vmcore, padded to the page boundary where
+// the contents of the guest memory regions start.
+fn elf_headers(guest_memory: &GuestMemoryMmap) -> Vec<u8> {
+    let phnum = u16::try_from(guest_memory.num_regions()).unwrap();
+    let headers_size = u64::from(ELF_HEADER_SIZE) + u64::from(ELF_PHDR_SIZE) * u64::from(phnum);
+    let data_offset = headers_size.next_multiple_of(PAGE_SIZE as u64);
+
+    let mut headers = Vec::with_capacity(usize::try_from(data_offset).unwrap());
+    headers.extend_from_slice(&[0x7f, b'E', b'L', b'F', ELFCLASS64, ELFDATA2LSB, EV_CURRENT]);
+    headers.resize(16, 0);
+    headers.extend_from_slice(&ET_CORE.to_le_bytes());
+    headers.extend_from_slice(&EM_MACHINE.to_le_bytes());
+    headers.extend_from_slice(&u32::from(EV_CURRENT).to_le_bytes());
+    // e_entry
+    headers.extend_from_slice(&0u64.to_le_bytes());
+    // e_phoff
+    headers.extend_from_slice(&u64::from(ELF_HEADER_SIZE).to_le_bytes());
+    // e_shoff
+    headers.extend_from_slice(&0u64.to_le_bytes());
+    // e_flags
+    headers.extend_from_slice(&0u32.to_le_bytes());
+    headers.extend_from_slice(&ELF_HEADER_SIZE.to_le_bytes());
+    headers.extend_from_slice(&ELF_PHDR_SIZE.to_le_bytes());
+    headers.extend_from_slice(&phnum.to_le_bytes());
+    // e_shentsize, e_shnum and e_shstrndx
+    headers.resize(usize::from(ELF_HEADER_SIZE), 0);
+
+    let mut offset = data_offset;
+    for region in guest_memory.iter() {
+        let address = region.start_addr().raw_value();
+        let size = region.len();
+        headers.extend_from_slice(&PT_LOAD.to_le_bytes());
+        headers.extend_from_slice(&PF_RWX.to_le_bytes());
+        headers.extend_from_slice(&offset.to_le_bytes());
+        // The guest memory is described by its physical addresses only.
+        headers.extend_from_slice(&address.to_le_bytes());
+        headers.extend_from_slice(&address.to_le_bytes());
+        headers.extend_from_slice(&size.to_le_bytes());
+        headers.extend_from_slice(&size.to_le_bytes());
+        headers.extend_from_slice(&(PAGE_SIZE as u64).to_le_bytes());
+        offset += size;
+    }
+    headers.resize(usize::try_from(data_offset).unwrap(), 0);
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::u64_to_usize;
+
+    use super::*;
+    use crate::utilities::test_utils::multi_region_mem;
+    use crate::vstate::memory::{Bytes, GuestAddress};
+
+    fn read_u64(buf: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_write_vmcore() {
+        let regions = [(GuestAddress(0), 0x2000), (GuestAddress(0x10000), 0x1000)];
+        let guest_memory = multi_region_mem(&regions);
+        guest_memory
+            .write_slice(&[0xaa; 16], GuestAddress(0x1000))
+            .unwrap();
+        guest_memory
+            .write_slice(&[0xbb; 16], GuestAddress(0x10000))
+            .unwrap();
+
+        let mut vmcore = Vec::new();
+        write_vmcore(&guest_memory, &mut vmcore).unwrap();
+
+        // The headers are padded to a page, followed by the contents of both regions.
+        assert_eq!(vmcore.len(), PAGE_SIZE + 0x3000);
+        assert_eq!(&vmcore[..4], b"\x7fELF");
+        assert_eq!(u16::from_le_bytes([vmcore[16], vmcore[17]]), ET_CORE);
+        assert_eq!(u16::from_le_bytes([vmcore[18], vmcore[19]]), EM_MACHINE);
+        assert_eq!(u16::from_le_bytes([vmcore[56], vmcore[57]]), 2);
+
+        for (i, (address, size)) in regions.iter().enumerate() {
+            let phdr = usize::from(ELF_HEADER_SIZE) + i * usize::from(ELF_PHDR_SIZE);
+            assert_eq!(
+                u32::from_le_bytes(vmcore[phdr..phdr + 4].try_into().unwrap()),
+                PT_LOAD
+            );
+            assert_eq!(read_u64(&vmcore, phdr + 24), address.raw_value());
+            assert_eq!(read_u64(&vmcore, phdr + 32), *size as u64);
+        }
+        let second_offset = u64_to_usize(read_u64(
+            &vmcore,
+            usize::from(ELF_HEADER_SIZE + ELF_PHDR_SIZE) + 8,
+        ));
+        assert_eq!(second_offset, PAGE_SIZE + 0x2000);
+        assert_eq!(&vmcore[PAGE_SIZE + 0x1000..PAGE_SIZE + 0x1010], &[0xaa; 16]);
+        assert_eq!(&vmcore[second_offset..second_offset + 16], &[0xbb; 16]);
+    }
+}
@@ -5,7 +5,7 @@ use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 
-pub use crate::devices::virtio::balloon::device::BalloonStats;
+pub use crate::devices::virtio::balloon::device::{BalloonStats, BalloonStatsSample};
 pub use crate::devices::virtio::balloon::BALLOON_DEV_ID;
 use crate::devices::virtio::balloon::{Balloon, BalloonConfig};
 
@@ -30,6 +30,13 @@ pub enum BalloonConfigError {
     UpdateFailure(std::io::Error),
     /// Firecracker's huge pages support is incompatible with memory ballooning.
     HugePages,
+    /// Confidential microVMs don't support memory ballooning.
+    Confidential,
+    /// Invalid memory pressure policy: the inflate step and deflate interval must be non-zero.
+    InvalidPressurePolicy,
+    #[from(ignore)]
+    /// Error setting the memory pressure policy: {0}
+    PressurePolicy(crate::devices::virtio::balloon::BalloonError),
 }
 
 /// This struct represents the strongly typed equivalent of the json body
@@ -76,9 +83,31 @@ pub struct BalloonUpdateStatsConfig {
     pub stats_polling_interval_s: u16,
 }
 
+/// The data fed into a memory pressure policy request. Each time the memory pressure stall
+/// trigger installed by the jailer fires, the balloon is inflated by `inflate_step_mib`, up to
+/// `max_balloon_mib`. It is deflated by the same step every `deflate_interval_s` without
+/// notifications, back to the size set by the user.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryPressurePolicyConfig {
+    /// Amount the balloon is inflated or deflated by on each step, in MiB.
+    pub inflate_step_mib: u32,
+    /// Maximum balloon size the policy inflates to, in MiB.
+    pub max_balloon_mib: u32,
+    /// Time without memory pressure notifications after which the balloon is deflated by one
+    /// step, in seconds.
+    pub deflate_interval_s: u32,
+}
+
+impl MemoryPressurePolicyConfig {
+    fn is_valid(&self) -> bool {
+        self.inflate_step_mib > 0 && self.deflate_interval_s > 0
+    }
+}
+
 /// A builder for `Balloon` devices from 'BalloonDeviceConfig'.
 #[cfg_attr(not(test), derive(Default))]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BalloonBuilder {
     inner: Option<MutexBalloon>,
 }
@@ -92,18 +121,39 @@ impl BalloonBuilder {
     /// Inserts a Balloon device in the store.
     /// If an entry already exists, it will overwrite it.
     pub fn set(&mut self, cfg: BalloonDeviceConfig) -> Result<(), BalloonConfigError> {
-        self.inner = Some(Arc::new(Mutex::new(Balloon::new(
+        let mut balloon = Balloon::new(
             cfg.amount_mib,
             cfg.deflate_on_oom,
             cfg.stats_polling_interval_s,
             // `restored` flag is false because this code path
             // is never called by snapshot restore functionality.
             false,
-        )?)));
+        )?;
+        // Keep the memory pressure policy of the device being replaced.
+        if let Some(old) = self.inner.take() {
+            balloon.pressure_monitor = old.lock().expect("Poisoned lock").pressure_monitor.take();
+        }
+        self.inner = Some(Arc::new(Mutex::new(balloon)));
 
         Ok(())
     }
 
+    /// Sets the host memory pressure policy of the balloon device.
+    pub fn set_pressure_policy(
+        &self,
+        policy: MemoryPressurePolicyConfig,
+    ) -> Result<(), BalloonConfigError> {
+        let balloon = self.get().ok_or(BalloonConfigError::DeviceNotFound)?;
+        if !policy.is_valid() {
+            return Err(BalloonConfigError::InvalidPressurePolicy);
+        }
+        balloon
+            .lock()
+            .expect("Poisoned lock")
+            .set_pressure_policy(policy)
+            .map_err(BalloonConfigError::PressurePolicy)
+    }
+
     /// Inserts an existing balloon device.
     pub fn set_device(&mut self, balloon: MutexBalloon) {
         self.inner = Some(balloon);
@@ -134,7 +184,10 @@ impl Default for BalloonBuilder {
 
 #[cfg(test)]
 pub(crate) mod tests {
+    use utils::tempfile::TempFile;
+
     use super::*;
+    use crate::devices::virtio::balloon::set_memory_pressure_trigger;
 
     pub(crate) fn default_config() -> BalloonDeviceConfig {
         BalloonDeviceConfig {
@@ -183,6 +236,46 @@ pub(crate) mod tests {
         assert_eq!(expected_balloon_config, actual_balloon_config);
     }
 
+    #[test]
+    fn test_set_pressure_policy() {
+        let policy = MemoryPressurePolicyConfig {
+            inflate_step_mib: 64,
+            max_balloon_mib: 512,
+            deflate_interval_s: 10,
+        };
+        let mut builder = BalloonBuilder::new();
+        assert!(matches!(
+            builder.set_pressure_policy(policy),
+            Err(BalloonConfigError::DeviceNotFound)
+        ));
+
+        builder.set(default_config()).unwrap();
+        for invalid_policy in [
+            MemoryPressurePolicyConfig {
+                inflate_step_mib: 0,
+                ..policy
+            },
+            MemoryPressurePolicyConfig {
+                deflate_interval_s: 0,
+                ..policy
+            },
+        ] {
+            assert!(matches!(
+                builder.set_pressure_policy(invalid_policy),
+                Err(BalloonConfigError::InvalidPressurePolicy)
+            ));
+        }
+
+        set_memory_pressure_trigger(TempFile::new().unwrap().into_file());
+        builder.set_pressure_policy(policy).unwrap();
+        // The policy is kept when the balloon device is reconfigured.
+        builder.set(default_config()).unwrap();
+        assert_eq!(
+            builder.get().unwrap().lock().unwrap().pressure_policy(),
+            Some(policy)
+        );
+    }
+
     #[test]
     fn test_set_device() {
         let mut builder = BalloonBuilder::new();
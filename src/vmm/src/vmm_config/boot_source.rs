@@ -31,6 +31,10 @@ pub struct BootSourceConfig {
     /// The boot arguments to pass to the kernel. If this field is uninitialized,
     /// DEFAULT_KERNEL_CMDLINE is used.
     pub boot_args: Option<String>,
+    /// Size in MiB of the guest memory region reserved for a crash kernel. The region is
+    /// advertised to the guest through the `crashkernel` boot argument.
+    #[serde(default)]
+    pub crash_kernel_size_mib: Option<u32>,
 }
 
 /// Errors associated with actions on `BootSourceConfig`.
@@ -44,6 +48,8 @@ pub enum BootSourceConfigError {
     InvalidKernelCommandLine(String),
     /// Firecracker's huge pages support is incompatible with initrds.
     HugePagesAndInitRd,
+    /// The crash kernel region size must be greater than zero.
+    InvalidCrashKernelSize,
 }
 
 /// Holds the kernel specification (both configuration as well as runtime details).
@@ -65,15 +71,21 @@ pub struct BootConfig {
     pub kernel_file: File,
     /// The descriptor to the initrd file, if there is one.
     pub initrd_file: Option<File>,
+    /// Size in MiB of the crash kernel region, if one should be reserved.
+    pub crash_kernel_size_mib: Option<u32>,
 }
 
 impl BootConfig {
     /// Creates the BootConfig based on a given configuration.
     pub fn new(cfg: &BootSourceConfig) -> Result<Self, BootSourceConfigError> {
         use self::BootSourceConfigError::{
-            InvalidInitrdPath, InvalidKernelCommandLine, InvalidKernelPath,
+            InvalidCrashKernelSize, InvalidInitrdPath, InvalidKernelCommandLine, InvalidKernelPath,
         };
 
+        if cfg.crash_kernel_size_mib == Some(0) {
+            return Err(InvalidCrashKernelSize);
+        }
+
         // Validate boot source config.
         let kernel_file = File::open(&cfg.kernel_image_path).map_err(InvalidKernelPath)?;
         let initrd_file: Option<File> = match &cfg.initrd_path {
@@ -93,6 +105,7 @@ impl BootConfig {
             cmdline,
             kernel_file,
             initrd_file,
+            crash_kernel_size_mib: cfg.crash_kernel_size_mib,
         })
     }
 }
@@ -113,22 +126,43 @@ pub(crate) mod tests {
             boot_args: None,
             initrd_path: None,
             kernel_image_path: kernel_path,
+            crash_kernel_size_mib: None,
         };
 
         let boot_cfg = BootConfig::new(&boot_src_cfg).unwrap();
         assert!(boot_cfg.initrd_file.is_none());
+        assert!(boot_cfg.crash_kernel_size_mib.is_none());
         assert_eq!(
             boot_cfg.cmdline.as_cstring().unwrap().as_bytes_with_nul(),
             [DEFAULT_KERNEL_CMDLINE.as_bytes(), &[b'\0']].concat()
         );
     }
 
+    #[test]
+    fn test_crash_kernel_size() {
+        let kernel_file = TempFile::new().unwrap();
+        let mut boot_src_cfg = BootSourceConfig {
+            kernel_image_path: kernel_file.as_path().to_str().unwrap().to_string(),
+            crash_kernel_size_mib: Some(0),
+            ..Default::default()
+        };
+        assert!(matches!(
+            BootConfig::new(&boot_src_cfg),
+            Err(BootSourceConfigError::InvalidCrashKernelSize)
+        ));
+
+        boot_src_cfg.crash_kernel_size_mib = Some(64);
+        let boot_cfg = BootConfig::new(&boot_src_cfg).unwrap();
+        assert_eq!(boot_cfg.crash_kernel_size_mib, Some(64));
+    }
+
     #[test]
     fn test_serde() {
         let boot_src_cfg = BootSourceConfig {
             boot_args: Some(DEFAULT_KERNEL_CMDLINE.to_string()),
             initrd_path: Some("/tmp/initrd".to_string()),
             kernel_image_path: "./vmlinux.bin".to_string(),
+            crash_kernel_size_mib: Some(128),
         };
 
         let mut snapshot_data = vec![0u8; 1000];
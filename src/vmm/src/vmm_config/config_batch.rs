@@ -0,0 +1,126 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::Deserialize;
+
+use super::balloon::BalloonDeviceConfig;
+use super::crypto::CryptoDeviceConfig;
+use super::device_id::{DeviceIdRegistry, DeviceKind};
+use super::drive::BlockDeviceConfig;
+use super::entropy::EntropyDeviceConfig;
+use super::net::NetworkInterfaceConfig;
+use super::remote_device::RemoteDeviceConfig;
+use super::tpm::TpmConfig;
+use super::vsock::VsockDeviceConfig;
+use crate::resources::ResourcesError;
+
+/// Errors associated with applying a batch of device configurations.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum ConfigBatchError {
+    /// The batch does not configure any device.
+    Empty,
+    /// Device {0} of the batch replaces the {1} device {2}, which needs its own request.
+    Replace(usize, DeviceKind, String),
+    /// Device {0} of the batch cannot be configured, no change was applied: {1}
+    Device(usize, ResourcesError),
+}
+
+/// The configuration of a device, as accepted by the `PUT` request of its endpoint.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeviceConfig {
+    /// Inserts or replaces a block device.
+    Drive(BlockDeviceConfig),
+    /// Inserts a network device.
+    NetworkInterface(NetworkInterfaceConfig),
+    /// Inserts a vsock device.
+    Vsock(VsockDeviceConfig),
+    /// Sets the balloon device.
+    Balloon(BalloonDeviceConfig),
+    /// Sets the entropy device.
+    Entropy(EntropyDeviceConfig),
+    /// Sets the crypto device.
+    Crypto(CryptoDeviceConfig),
+    /// Inserts a remote device.
+    RemoteDevice(RemoteDeviceConfig),
+    /// Sets the TPM device.
+    Tpm(TpmConfig),
+}
+
+/// Device configurations applied together before the microVM boots, or not at all.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigBatch {
+    /// The device configurations, applied in order.
+    pub devices: Vec<DeviceConfig>,
+}
+
+impl ConfigBatch {
+    /// Checks that the batch can be rolled back, given the IDs of the devices configured before.
+    ///
+    /// The network, vsock and remote devices release their tap device, socket or backend
+    /// connection when they are replaced, so the configured ones cannot be restored if the
+    /// batch fails. They can still be replaced by their own `PUT` request.
+    pub fn validate(&self, configured: &DeviceIdRegistry) -> Result<(), ConfigBatchError> {
+        if self.devices.is_empty() {
+            return Err(ConfigBatchError::Empty);
+        }
+        for (index, device) in self.devices.iter().enumerate() {
+            let (kind, id) = match device {
+                DeviceConfig::NetworkInterface(config) => {
+                    (DeviceKind::Net, config.iface_id.as_str())
+                }
+                DeviceConfig::Vsock(config) => (DeviceKind::Vsock, config.id()),
+                DeviceConfig::RemoteDevice(config) => (DeviceKind::Remote, config.id.as_str()),
+                _ => continue,
+            };
+            if configured.get(id) == Some(kind) {
+                return Err(ConfigBatchError::Replace(index, kind, id.to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_batch() {
+        let batch: ConfigBatch = serde_json::from_str(
+            r#"{
+                "devices": [
+                    {"drive": {"drive_id": "rootfs", "path_on_host": "/tmp/rootfs", "is_root_device": true}},
+                    {"network-interface": {"iface_id": "eth0", "host_dev_name": "tap0"}},
+                    {"vsock": {"guest_cid": 3, "uds_path": "/tmp/v.sock"}},
+                    {"entropy": {}}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(batch.devices.len(), 4);
+        assert!(matches!(
+            batch.devices[1],
+            DeviceConfig::NetworkInterface(_)
+        ));
+        serde_json::from_str::<ConfigBatch>(r#"{"devices": [{"cpu": {}}]}"#).unwrap_err();
+
+        batch.validate(&DeviceIdRegistry::default()).unwrap();
+        let configured: DeviceIdRegistry = [
+            (String::from("rootfs"), DeviceKind::Block),
+            (String::from("eth0"), DeviceKind::Net),
+        ]
+        .into_iter()
+        .collect();
+        // Drives can be replaced, network interfaces can't.
+        assert_eq!(
+            batch.validate(&configured).unwrap_err().to_string(),
+            "Device 1 of the batch replaces the network device eth0, which needs its own request."
+        );
+        assert!(matches!(
+            ConfigBatch { devices: vec![] }.validate(&configured),
+            Err(ConfigBatchError::Empty)
+        ));
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use super::RateLimiterConfig;
+use crate::devices::virtio::crypto::{Crypto, CryptoError};
+
+/// This struct represents the strongly typed equivalent of the json body from crypto device
+/// related requests.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CryptoDeviceConfig {
+    /// Configuration for RateLimiter of the data queue of the crypto device
+    pub rate_limiter: Option<RateLimiterConfig>,
+}
+
+impl From<&Crypto> for CryptoDeviceConfig {
+    fn from(dev: &Crypto) -> Self {
+        let rate_limiter: RateLimiterConfig = dev.rate_limiter().into();
+        CryptoDeviceConfig {
+            rate_limiter: rate_limiter.into_option(),
+        }
+    }
+}
+
+/// Errors that can occur while handling configuration for
+/// a crypto device
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum CryptoDeviceError {
+    /// Could not create crypto device: {0}
+    CreateDevice(#[from] CryptoError),
+    /// Could not create RateLimiter from configuration: {0}
+    CreateRateLimiter(#[from] std::io::Error),
+}
+
+/// A builder type used to construct a crypto device
+#[derive(Debug, Default, Clone)]
+pub struct CryptoDeviceBuilder(Option<Arc<Mutex<Crypto>>>);
+
+impl CryptoDeviceBuilder {
+    /// Create a new instance for the builder
+    pub fn new() -> Self {
+        Self(None)
+    }
+
+    /// Build a crypto device and return a (counted) reference to it protected by a mutex
+    pub fn build(
+        &mut self,
+        config: CryptoDeviceConfig,
+    ) -> Result<Arc<Mutex<Crypto>>, CryptoDeviceError> {
+        let rate_limiter = config
+            .rate_limiter
+            .map(RateLimiterConfig::try_into)
+            .transpose()?;
+        let dev = Arc::new(Mutex::new(Crypto::new(rate_limiter.unwrap_or_default())?));
+        self.0 = Some(dev.clone());
+
+        Ok(dev)
+    }
+
+    /// Insert a new crypto device from a configuration object
+    pub fn insert(&mut self, config: CryptoDeviceConfig) -> Result<(), CryptoDeviceError> {
+        let _ = self.build(config)?;
+        Ok(())
+    }
+
+    /// Get a reference to the crypto device, if present
+    pub fn get(&self) -> Option<&Arc<Mutex<Crypto>>> {
+        self.0.as_ref()
+    }
+
+    /// Get the configuration of the crypto device (if any)
+    pub fn config(&self) -> Option<CryptoDeviceConfig> {
+        self.0
+            .as_ref()
+            .map(|dev| CryptoDeviceConfig::from(dev.lock().unwrap().deref()))
+    }
+
+    /// Set the crypto device from an already created object
+    pub fn set_device(&mut self, device: Arc<Mutex<Crypto>>) {
+        self.0 = Some(device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limiter::RateLimiter;
+    use crate::vmm_config::TokenBucketConfig;
+
+    #[test]
+    fn test_crypto_device_create() {
+        let config = CryptoDeviceConfig::default();
+        let mut builder = CryptoDeviceBuilder::new();
+        assert!(builder.get().is_none());
+
+        builder.insert(config.clone()).unwrap();
+        assert!(builder.get().is_some());
+        assert_eq!(builder.config().unwrap(), config);
+
+        let config = CryptoDeviceConfig {
+            rate_limiter: Some(RateLimiterConfig {
+                bandwidth: None,
+                ops: Some(TokenBucketConfig {
+                    size: 100,
+                    one_time_burst: None,
+                    refill_time: 1000,
+                }),
+            }),
+        };
+        builder.insert(config.clone()).unwrap();
+        assert_eq!(builder.config().unwrap(), config);
+    }
+
+    #[test]
+    fn test_set_device() {
+        let mut builder = CryptoDeviceBuilder::new();
+        let device = Crypto::new(RateLimiter::default()).unwrap();
+        assert!(builder.0.is_none());
+        builder.set_device(Arc::new(Mutex::new(device)));
+        assert!(builder.0.is_some());
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::BTreeMap;
+
+/// Maximum length of a device ID, in characters.
+pub const MAX_DEVICE_ID_LEN: usize = 64;
+
+/// Errors associated with the IDs of the devices.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum DeviceIdError {
+    /// The device ID cannot be empty.
+    Empty,
+    /// The device ID {0} is longer than 64 characters.
+    TooLong(String),
+    /// The device ID {0} contains characters other than alphanumeric characters and underscores.
+    InvalidCharacters(String),
+    /// The device ID {0} is already used by a {1} device.
+    InUse(String, DeviceKind),
+}
+
+/// Kinds of the devices having an ID chosen by the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, displaydoc::Display)]
+pub enum DeviceKind {
+    /// block
+    Block,
+    /// network
+    Net,
+    /// vsock
+    Vsock,
+    /// remote
+    Remote,
+}
+
+/// Checks that `id` is a valid device ID: a non-empty string of at most `MAX_DEVICE_ID_LEN`
+/// alphanumeric characters and underscores.
+pub fn validate_device_id(id: &str) -> Result<(), DeviceIdError> {
+    if id.is_empty() {
+        return Err(DeviceIdError::Empty);
+    }
+    if id.chars().count() > MAX_DEVICE_ID_LEN {
+        return Err(DeviceIdError::TooLong(id.to_string()));
+    }
+    if !id.chars().all(|c| c == '_' || c.is_alphanumeric()) {
+        return Err(DeviceIdError::InvalidCharacters(id.to_string()));
+    }
+    Ok(())
+}
+
+/// IDs of the configured devices. The IDs of all the kinds of devices share a single namespace, so
+/// that an ID identifies a device without the need to know its kind.
+#[derive(Debug, Default)]
+pub struct DeviceIdRegistry {
+    ids: BTreeMap<String, DeviceKind>,
+}
+
+impl DeviceIdRegistry {
+    /// Records the ID of a device of the given kind. A device using an ID already recorded for a
+    /// device of the same kind replaces it, whereas an ID used by another kind of device is
+    /// rejected.
+    pub fn register(&mut self, id: &str, kind: DeviceKind) -> Result<(), DeviceIdError> {
+        validate_device_id(id)?;
+        match self.ids.get(id) {
+            Some(other) if *other != kind => Err(DeviceIdError::InUse(id.to_string(), *other)),
+            _ => {
+                self.ids.insert(id.to_string(), kind);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the kind of the device using `id`, if any.
+    pub fn get(&self, id: &str) -> Option<DeviceKind> {
+        self.ids.get(id).copied()
+    }
+}
+
+impl FromIterator<(String, DeviceKind)> for DeviceIdRegistry {
+    /// Records the IDs of devices that already exist, e.g. restored from a snapshot, without
+    /// validating them again.
+    fn from_iter<T: IntoIterator<Item = (String, DeviceKind)>>(iter: T) -> Self {
+        Self {
+            ids: iter.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_device_id() {
+        validate_device_id("rootfs").unwrap();
+        validate_device_id("net_1").unwrap();
+        validate_device_id(&"a".repeat(MAX_DEVICE_ID_LEN)).unwrap();
+
+        assert_eq!(validate_device_id(""), Err(DeviceIdError::Empty));
+        let long_id = "a".repeat(MAX_DEVICE_ID_LEN + 1);
+        assert_eq!(
+            validate_device_id(&long_id),
+            Err(DeviceIdError::TooLong(long_id))
+        );
+        assert_eq!(
+            validate_device_id("net-1").unwrap_err().to_string(),
+            "The device ID net-1 contains characters other than alphanumeric characters and \
+             underscores."
+        );
+    }
+
+    #[test]
+    fn test_device_id_registry() {
+        let mut registry: DeviceIdRegistry = [("rootfs".to_string(), DeviceKind::Block)]
+            .into_iter()
+            .collect();
+        assert_eq!(registry.get("rootfs"), Some(DeviceKind::Block));
+        assert_eq!(registry.get("eth0"), None);
+
+        // Devices of the same kind are replaced, other kinds cannot reuse the ID.
+        registry.register("rootfs", DeviceKind::Block).unwrap();
+        registry.register("eth0", DeviceKind::Net).unwrap();
+        assert_eq!(
+            registry.register("rootfs", DeviceKind::Net),
+            Err(DeviceIdError::InUse(
+                "rootfs".to_string(),
+                DeviceKind::Block
+            ))
+        );
+        assert_eq!(
+            registry
+                .register("eth0", DeviceKind::Vsock)
+                .unwrap_err()
+                .to_string(),
+            "The device ID eth0 is already used by a network device."
+        );
+        assert_eq!(
+            registry.register("eth-0", DeviceKind::Net),
+            Err(DeviceIdError::InvalidCharacters("eth-0".to_string()))
+        );
+        assert_eq!(registry.get("eth0"), Some(DeviceKind::Net));
+    }
+}
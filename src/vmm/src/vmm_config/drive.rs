@@ -7,6 +7,7 @@ use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 
+use super::device_id::DeviceIdError;
 use super::RateLimiterConfig;
 use crate::devices::virtio::block::device::Block;
 pub use crate::devices::virtio::block::virtio::device::FileEngineType;
@@ -22,8 +23,12 @@ pub enum DriveError {
     CreateRateLimiter(io::Error),
     /// Unable to patch the block device: {0} Please verify the request arguments.
     DeviceUpdate(VmmError),
+    /// Invalid drive ID: {0}
+    DeviceId(DeviceIdError),
     /// A root block device already exists!
     RootBlockDeviceAlreadyAdded,
+    /// The IO engine of a drive can only be changed while the microVM is paused.
+    UpdateEngineNotPaused,
 }
 
 /// Use this structure to set up the Block Device before booting the kernel.
@@ -39,8 +44,8 @@ pub struct BlockDeviceConfig {
     /// Setting this flag to true will mount the block device in the
     /// guest under /dev/vda unless the partuuid is present.
     pub is_root_device: bool,
-    /// If set to true, the drive will ignore flush requests coming from
-    /// the guest driver.
+    /// Caching strategy of the drive, which decides whether flush requests
+    /// are advertised to and honored for the guest driver.
     #[serde(default)]
     pub cache_type: CacheType,
 
@@ -58,10 +63,66 @@ pub struct BlockDeviceConfig {
     // pub file_engine_type: FileEngineType,
     #[serde(rename = "io_engine")]
     pub file_engine_type: Option<FileEngineType>,
+    /// Path of a copy-on-write overlay of the drive. When set, the drive is opened
+    /// read-only and the guest writes are stored in the overlay.
+    pub overlay_path: Option<String>,
 
     // VhostUserBlock specific fields
     /// Path to the vhost-user socket.
     pub socket: Option<String>,
+    /// Policy for reconnecting to the vhost-user backend if it goes away. If not set, the
+    /// device stops working when the backend goes away.
+    pub reconnect: Option<VhostUserReconnectConfig>,
+
+    /// If set to true, the drive is emulated by a sandboxed worker process instead of the
+    /// VMM process.
+    #[serde(default)]
+    pub worker: bool,
+}
+
+/// Policy for reconnecting a vhost-user device to its backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VhostUserReconnectConfig {
+    /// Delay before the first reconnection attempt, in milliseconds. The delay doubles after
+    /// each failed attempt.
+    pub initial_backoff_ms: u64,
+    /// Maximum delay between two reconnection attempts, in milliseconds.
+    pub max_backoff_ms: u64,
+    /// Number of failed attempts after which the device stops trying to reconnect. `0` means
+    /// the device keeps trying forever.
+    pub max_attempts: u32,
+}
+
+impl Default for VhostUserReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff_ms: 100,
+            max_backoff_ms: 5000,
+            max_attempts: 0,
+        }
+    }
+}
+
+impl VhostUserReconnectConfig {
+    /// Returns whether the policy describes a usable backoff.
+    pub fn is_valid(&self) -> bool {
+        self.initial_backoff_ms > 0 && self.initial_backoff_ms <= self.max_backoff_ms
+    }
+
+    /// Delay before the reconnection attempt following `failed_attempts` failed ones, in
+    /// milliseconds.
+    pub fn backoff_ms(&self, failed_attempts: u32) -> u64 {
+        let factor = 1u64.checked_shl(failed_attempts).unwrap_or(u64::MAX);
+        self.initial_backoff_ms
+            .saturating_mul(factor)
+            .min(self.max_backoff_ms)
+    }
+
+    /// Returns whether the device gives up reconnecting after `failed_attempts` failed ones.
+    pub fn is_exhausted(&self, failed_attempts: u32) -> bool {
+        self.max_attempts != 0 && failed_attempts >= self.max_attempts
+    }
 }
 
 /// Only provided fields will be updated. I.e. if any optional fields
@@ -77,10 +138,13 @@ pub struct BlockDeviceUpdateConfig {
     pub path_on_host: Option<String>,
     /// New rate limiter config.
     pub rate_limiter: Option<RateLimiterConfig>,
+    /// New type of IO engine. The microVM must be paused to change it.
+    #[serde(rename = "io_engine")]
+    pub file_engine_type: Option<FileEngineType>,
 }
 
 /// Wrapper for the collection that holds all the Block Devices
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct BlockBuilder {
     /// The list of block devices.
     /// There can be at most one root block device and it would be the first in the list.
@@ -99,7 +163,7 @@ impl BlockBuilder {
     }
 
     /// Specifies whether there is a root block device already present in the list.
-    fn has_root_device(&self) -> bool {
+    pub fn has_root_device(&self) -> bool {
         // If there is a root device, it would be at the top of the list.
         if let Some(block) = self.devices.front() {
             block.lock().expect("Poisoned lock").root_device()
@@ -202,8 +266,11 @@ mod tests {
                 path_on_host: self.path_on_host.clone(),
                 rate_limiter: self.rate_limiter,
                 file_engine_type: self.file_engine_type,
+                overlay_path: self.overlay_path.clone(),
 
                 socket: self.socket.clone(),
+                reconnect: self.reconnect,
+                worker: false,
             }
         }
     }
@@ -229,8 +296,11 @@ mod tests {
             path_on_host: Some(dummy_path),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -261,8 +331,11 @@ mod tests {
             path_on_host: Some(dummy_path),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -290,8 +363,11 @@ mod tests {
             path_on_host: Some(dummy_path_1),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -306,8 +382,11 @@ mod tests {
             path_on_host: Some(dummy_path_2),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -333,8 +412,11 @@ mod tests {
             path_on_host: Some(dummy_path_1),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -349,8 +431,11 @@ mod tests {
             path_on_host: Some(dummy_path_2),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         let dummy_file_3 = TempFile::new().unwrap();
@@ -365,8 +450,11 @@ mod tests {
             path_on_host: Some(dummy_path_3),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -406,8 +494,11 @@ mod tests {
             path_on_host: Some(dummy_path_1),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -422,8 +513,11 @@ mod tests {
             path_on_host: Some(dummy_path_2),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         let dummy_file_3 = TempFile::new().unwrap();
@@ -438,8 +532,11 @@ mod tests {
             path_on_host: Some(dummy_path_3),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -480,8 +577,11 @@ mod tests {
             path_on_host: Some(dummy_path_1.clone()),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -496,8 +596,11 @@ mod tests {
             path_on_host: Some(dummy_path_2.clone()),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -560,8 +663,11 @@ mod tests {
             path_on_host: Some(dummy_path_1),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
         // Switch roots and add a PARTUUID for the new one.
         let mut root_block_device_old = root_block_device;
@@ -576,8 +682,11 @@ mod tests {
             path_on_host: Some(dummy_path_2),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         block_devs.insert(root_block_device_old).unwrap();
@@ -602,8 +711,11 @@ mod tests {
             path_on_host: Some(dummy_file.as_path().to_str().unwrap().to_string()),
             rate_limiter: None,
             file_engine_type: Some(FileEngineType::Sync),
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -630,8 +742,11 @@ mod tests {
             path_on_host: Some(backing_file.as_path().to_str().unwrap().to_string()),
             rate_limiter: None,
             file_engine_type: None,
+            overlay_path: None,
 
             socket: None,
+            reconnect: None,
+            worker: false,
         };
 
         let block = Block::new(config).unwrap();
@@ -643,4 +758,43 @@ mod tests {
             block_id
         );
     }
+
+    #[test]
+    fn test_reconnect_config() {
+        let reconnect: VhostUserReconnectConfig =
+            serde_json::from_str(r#"{"max_attempts": 3}"#).unwrap();
+        assert_eq!(
+            reconnect,
+            VhostUserReconnectConfig {
+                initial_backoff_ms: 100,
+                max_backoff_ms: 5000,
+                max_attempts: 3,
+            }
+        );
+        assert!(reconnect.is_valid());
+
+        // The delay doubles until reaching the maximum.
+        assert_eq!(reconnect.backoff_ms(0), 100);
+        assert_eq!(reconnect.backoff_ms(1), 200);
+        assert_eq!(reconnect.backoff_ms(5), 3200);
+        assert_eq!(reconnect.backoff_ms(6), 5000);
+        assert_eq!(reconnect.backoff_ms(100), 5000);
+
+        assert!(!reconnect.is_exhausted(2));
+        assert!(reconnect.is_exhausted(3));
+        let forever = VhostUserReconnectConfig::default();
+        assert!(!forever.is_exhausted(u32::MAX));
+
+        let invalid = VhostUserReconnectConfig {
+            initial_backoff_ms: 0,
+            ..Default::default()
+        };
+        assert!(!invalid.is_valid());
+        let invalid = VhostUserReconnectConfig {
+            initial_backoff_ms: 200,
+            max_backoff_ms: 100,
+            max_attempts: 0,
+        };
+        assert!(!invalid.is_valid());
+    }
 }
@@ -7,7 +7,11 @@ use std::sync::{Arc, Mutex};
 use serde::{Deserialize, Serialize};
 
 use super::RateLimiterConfig;
+use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::dma::{DmaRange, DmaRanges, DmaRangesError};
 use crate::devices::virtio::rng::{Entropy, EntropyError};
+use crate::logger::warn;
+use crate::VmmError;
 
 /// This struct represents the strongly typed equivalent of the json body from entropy device
 /// related requests.
@@ -16,6 +20,13 @@ use crate::devices::virtio::rng::{Entropy, EntropyError};
 pub struct EntropyDeviceConfig {
     /// Configuration for RateLimiter of Entropy device
     pub rate_limiter: Option<RateLimiterConfig>,
+    /// Seed of a deterministic random generator used instead of the host entropy source.
+    /// Only meant for reproducible testing, as the guest gets predictable random bytes.
+    #[serde(default)]
+    pub deterministic_seed: Option<u64>,
+    /// Guest memory ranges the device is allowed to access. Unrestricted if missing.
+    #[serde(default)]
+    pub dma_ranges: Option<Vec<DmaRange>>,
 }
 
 impl From<&Entropy> for EntropyDeviceConfig {
@@ -23,10 +34,22 @@ impl From<&Entropy> for EntropyDeviceConfig {
         let rate_limiter: RateLimiterConfig = dev.rate_limiter().into();
         EntropyDeviceConfig {
             rate_limiter: rate_limiter.into_option(),
+            deterministic_seed: dev.deterministic_seed(),
+            dma_ranges: dev.dma_ranges(),
         }
     }
 }
 
+/// The data fed into an entropy device update request. Currently, only the rate limiter can be
+/// updated.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EntropyDeviceUpdateConfig {
+    /// New rate limiter config. Only provided data will be updated. I.e. if any optional data
+    /// is missing, it will not be nullified, but left unchanged.
+    pub rate_limiter: Option<RateLimiterConfig>,
+}
+
 /// Errors that can occur while handling configuration for
 /// an entropy device
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
@@ -35,10 +58,14 @@ pub enum EntropyDeviceError {
     CreateDevice(#[from] EntropyError),
     /// Could not create RateLimiter from configuration: {0}
     CreateRateLimiter(#[from] std::io::Error),
+    /// Invalid DMA ranges: {0}
+    DmaRanges(#[from] DmaRangesError),
+    /// Could not update the entropy device: {0}
+    DeviceUpdate(VmmError),
 }
 
 /// A builder type used to construct an Entropy device
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct EntropyDeviceBuilder(Option<Arc<Mutex<Entropy>>>);
 
 impl EntropyDeviceBuilder {
@@ -56,7 +83,19 @@ impl EntropyDeviceBuilder {
             .rate_limiter
             .map(RateLimiterConfig::try_into)
             .transpose()?;
-        let dev = Arc::new(Mutex::new(Entropy::new(rate_limiter.unwrap_or_default())?));
+        let dma_ranges = config
+            .dma_ranges
+            .as_deref()
+            .map(DmaRanges::new)
+            .transpose()?
+            .map(Arc::new);
+        let mut entropy = Entropy::new(rate_limiter.unwrap_or_default())?;
+        entropy.set_dma_ranges(dma_ranges);
+        if let Some(seed) = config.deterministic_seed {
+            warn!("The entropy device uses a deterministic generator, only meant for testing");
+            entropy.set_deterministic_seed(seed);
+        }
+        let dev = Arc::new(Mutex::new(entropy));
         self.0 = Some(dev.clone());
 
         Ok(dev)
@@ -100,6 +139,24 @@ mod tests {
         builder.insert(config.clone()).unwrap();
         assert!(builder.get().is_some());
         assert_eq!(builder.config().unwrap(), config);
+
+        let config = EntropyDeviceConfig {
+            rate_limiter: None,
+            deterministic_seed: Some(42),
+            dma_ranges: None,
+        };
+        builder.insert(config.clone()).unwrap();
+        assert_eq!(builder.config().unwrap(), config);
+
+        let config = EntropyDeviceConfig {
+            dma_ranges: Some(vec![DmaRange {
+                base: 0x1000,
+                size: 0x1000,
+            }]),
+            ..Default::default()
+        };
+        builder.insert(config.clone()).unwrap();
+        assert_eq!(builder.config().unwrap(), config);
     }
 
     #[test]
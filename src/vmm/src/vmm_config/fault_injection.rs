@@ -0,0 +1,72 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+use crate::VmmError;
+
+/// Whether host faults can be injected into the device backends in this build.
+pub const FAULT_INJECTION_ENABLED: bool = cfg!(feature = "fault-injection");
+
+/// The host faults that can be injected into the device backends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FaultType {
+    /// The reads, writes and flushes of a virtio block device fail with EIO.
+    BlockIo,
+    /// The frames sent by a network device to its tap are dropped, as if the write failed.
+    NetTx,
+    /// The entropy device fails to get random bytes from the host.
+    EntropySource,
+    /// The interrupts of a device are not delivered to the guest.
+    IrqDrop,
+}
+
+/// This struct represents the strongly typed equivalent of the json body from fault injection
+/// related requests.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FaultInjectionConfig {
+    /// ID of the device the fault is injected into. The entropy device ID is `rng`.
+    pub device_id: String,
+    /// The fault to inject.
+    pub fault_type: FaultType,
+    /// Whether the fault is injected from now on, or stops being injected.
+    pub enabled: bool,
+}
+
+/// Errors associated with injecting faults into the device backends.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum FaultInjectionError {
+    /// Unable to inject the fault: {0}
+    DeviceUpdate(VmmError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fault_injection_config_deserialize() {
+        let config: FaultInjectionConfig = serde_json::from_str(
+            r#"{"device_id": "rootfs", "fault_type": "BlockIo", "enabled": true}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            FaultInjectionConfig {
+                device_id: "rootfs".to_string(),
+                fault_type: FaultType::BlockIo,
+                enabled: true,
+            }
+        );
+
+        serde_json::from_str::<FaultInjectionConfig>(
+            r#"{"device_id": "rootfs", "fault_type": "Disk", "enabled": true}"#,
+        )
+        .unwrap_err();
+        serde_json::from_str::<FaultInjectionConfig>(
+            r#"{"device_id": "rootfs", "fault_type": "BlockIo"}"#,
+        )
+        .unwrap_err();
+    }
+}
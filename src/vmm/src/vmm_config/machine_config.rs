@@ -1,5 +1,6 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 
 use serde::{Deserialize, Serialize};
@@ -7,6 +8,8 @@ use utils::kernel_version;
 use utils::kernel_version::KernelVersion;
 
 use crate::cpu_config::templates::{CpuTemplateType, CustomCpuTemplate, StaticCpuTemplate};
+use crate::device_manager::resources::ResourceRequirements;
+use crate::vstate::confidential::{ConfidentialConfig, ConfidentialTechnology};
 
 /// The default memory size of the VM, in MiB.
 pub const DEFAULT_MEM_SIZE_MIB: usize = 128;
@@ -37,6 +40,42 @@ pub enum VmConfigError {
     BalloonAndHugePages,
     /// Firecracker's huge pages support is incompatible with initrds.
     InitrdAndHugePages,
+    /// Guest memory ranges must have a non-zero length and must not overflow the address space.
+    InvalidMemoryRange,
+    /// KSM does not merge guest memory backed by huge pages or by a memfd.
+    MergeableSharedMemory,
+    /// {0:?} is not supported by this build of Firecracker.
+    ConfidentialNotSupported(ConfidentialTechnology),
+    /// Confidential microVMs don't support dirty page tracking.
+    ConfidentialAndDirtyPageTracking,
+    /// Confidential microVMs don't support memory ballooning.
+    ConfidentialAndBalloon,
+    /// The MMIO gap must start at or above 1 GiB and end at or below 4 GiB, with a size of at least 256 MiB, both aligned to 2 MiB, and the 64-bit MMIO window size must be a multiple of 1 GiB of at most 1 TiB.
+    InvalidMemoryLayout,
+    /// Configuring the MMIO layout is not supported on aarch64.
+    #[cfg(target_arch = "aarch64")]
+    MemoryLayoutNotSupported,
+    /// Configuring the guest physical address space size is only supported on aarch64.
+    #[cfg(target_arch = "x86_64")]
+    IpaSizeNotSupported,
+    /// The guest physical address space size must be between 32 and 52 bits.
+    #[cfg(target_arch = "aarch64")]
+    InvalidIpaSize,
+    /// The memory size doesn't fit in the guest physical address space, whose size must be increased.
+    #[cfg(target_arch = "aarch64")]
+    MemoryExceedsIpaSize,
+    /// Virtio feature bit {0} is invalid, feature bits must be lower than 64.
+    InvalidVirtioFeature(u32),
+    /// The devices need {0} interrupt lines, more than the {1} available.
+    TooManyDeviceInterrupts(u32, u32),
+    /// The devices need {0:#x} bytes of MMIO address space below 4 GiB, more than the {1:#x} bytes of the MMIO gap.
+    MmioGapTooSmall(u64, u64),
+    /// The devices need {0:#x} bytes of 64-bit MMIO address space, more than the {1:#x} bytes of the 64-bit MMIO window.
+    Mmio64WindowTooSmall(u64, u64),
+    /// The thread settings refer to vCPU {0}, which doesn't exist or is configured more than once.
+    InvalidVcpuThreadIndex(u8),
+    /// The thread settings of vCPU {0} are invalid: host CPUs must be lower than 1024, the nice level between -20 and 19, the SCHED_FIFO priority between 1 and 99 and not set along with a nice level, and the utilization clamps at most 1024 with the minimum not above the maximum.
+    InvalidVcpuThreadConfig(u8),
 }
 
 // We cannot do a `KernelVersion(kernel_version::Error)` variant because `kernel_version::Error`
@@ -103,6 +142,278 @@ impl From<HugePageConfig> for Option<memfd::HugetlbSize> {
     }
 }
 
+/// Describes how KVM reports the guest memory pages dirtied by the vCPUs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DirtyTrackingMode {
+    /// Use the dirty rings if KVM supports them, the dirty bitmaps otherwise.
+    #[default]
+    Auto,
+    /// Use the dirty bitmaps of the guest memory slots.
+    Bitmap,
+    /// Use the per-vCPU dirty rings.
+    Ring,
+}
+
+impl DirtyTrackingMode {
+    fn is_auto(&self) -> bool {
+        *self == DirtyTrackingMode::Auto
+    }
+}
+
+/// Describes the host memory backing the guest memory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemoryBackendType {
+    /// Use a memfd if a device shares the guest memory with another process (vhost-user, remote
+    /// devices), private anonymous memory otherwise.
+    #[default]
+    Auto,
+    /// Use private anonymous memory.
+    Anonymous,
+    /// Use a memfd, which other processes can map.
+    Memfd,
+}
+
+impl MemoryBackendType {
+    fn is_auto(&self) -> bool {
+        *self == MemoryBackendType::Auto
+    }
+}
+
+/// Guest physical memory range that a machine configuration option applies to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GuestMemoryRange {
+    /// Guest physical address where the range starts.
+    pub guest_addr: u64,
+    /// Length of the range, in bytes.
+    pub len: u64,
+}
+
+impl GuestMemoryRange {
+    fn is_valid(&self) -> bool {
+        self.len > 0 && self.guest_addr.checked_add(self.len).is_some()
+    }
+}
+
+/// Guest physical layout of the MMIO address space the guest memory is split around.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemoryLayoutConfig {
+    /// Guest physical address where the MMIO gap below 4 GiB starts, which is also where the
+    /// guest memory below 4 GiB ends.
+    pub mmio_gap_start: u64,
+    /// Size of the MMIO gap below 4 GiB, in bytes.
+    pub mmio_gap_size: u64,
+    /// Size of the 64-bit MMIO window placed above the guest memory, in bytes. There is no such
+    /// window when zero.
+    pub mmio64_size: u64,
+    /// Size of the guest physical address space, in bits, on aarch64. When unset, it is the
+    /// largest size supported by the host, and the guest memory is laid out within 40 bits.
+    pub ipa_size: Option<u8>,
+}
+
+impl Default for MemoryLayoutConfig {
+    fn default() -> Self {
+        Self {
+            mmio_gap_start: crate::arch::MMIO_MEM_START,
+            mmio_gap_size: crate::arch::MMIO_MEM_SIZE,
+            mmio64_size: 0,
+            ipa_size: None,
+        }
+    }
+}
+
+impl MemoryLayoutConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns the layout with MMIO windows large enough for devices needing `requirements`. The
+    /// 64-bit MMIO window is sized for the devices if it was not configured, whereas the MMIO gap
+    /// and a configured 64-bit window have to be large enough already.
+    pub fn fit(&self, requirements: &ResourceRequirements) -> Result<Self, VmConfigError> {
+        if requirements.mmio > self.mmio_gap_size {
+            return Err(VmConfigError::MmioGapTooSmall(
+                requirements.mmio,
+                self.mmio_gap_size,
+            ));
+        }
+
+        #[allow(unused_mut)]
+        let mut layout = *self;
+        #[cfg(target_arch = "x86_64")]
+        {
+            use crate::arch::x86_64::layout::{MAX_MMIO64_SIZE, MMIO64_ALIGNMENT};
+
+            if layout.mmio64_size == 0 {
+                layout.mmio64_size = requirements.mmio64.next_multiple_of(MMIO64_ALIGNMENT);
+            }
+            if layout.mmio64_size > MAX_MMIO64_SIZE {
+                return Err(VmConfigError::Mmio64WindowTooSmall(
+                    requirements.mmio64,
+                    MAX_MMIO64_SIZE,
+                ));
+            }
+        }
+        if requirements.mmio64 > layout.mmio64_size {
+            return Err(VmConfigError::Mmio64WindowTooSmall(
+                requirements.mmio64,
+                layout.mmio64_size,
+            ));
+        }
+        Ok(layout)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn validate(&self, _mem_size_mib: usize) -> Result<(), VmConfigError> {
+        use crate::arch::x86_64::layout::{
+            MAX_MMIO64_SIZE, MIN_MMIO_GAP_SIZE, MIN_MMIO_GAP_START, MMIO64_ALIGNMENT,
+            MMIO_GAP_ALIGNMENT,
+        };
+
+        if self.ipa_size.is_some() {
+            return Err(VmConfigError::IpaSizeNotSupported);
+        }
+        let valid = self.mmio_gap_start >= MIN_MMIO_GAP_START
+            && self.mmio_gap_start & (MMIO_GAP_ALIGNMENT - 1) == 0
+            && self.mmio_gap_size >= MIN_MMIO_GAP_SIZE
+            && self.mmio_gap_size & (MMIO_GAP_ALIGNMENT - 1) == 0
+            && self.mmio_gap_start + self.mmio_gap_size <= 1 << 32
+            && self.mmio64_size <= MAX_MMIO64_SIZE
+            && self.mmio64_size & (MMIO64_ALIGNMENT - 1) == 0;
+        if !valid {
+            return Err(VmConfigError::InvalidMemoryLayout);
+        }
+        Ok(())
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn validate(&self, mem_size_mib: usize) -> Result<(), VmConfigError> {
+        use crate::arch::aarch64::dram_mem_max_size;
+        use crate::arch::aarch64::layout::{DEFAULT_IPA_SIZE, MAX_IPA_SIZE, MIN_IPA_SIZE};
+
+        let mmio_layout = Self {
+            ipa_size: None,
+            ..*self
+        };
+        if !mmio_layout.is_default() {
+            return Err(VmConfigError::MemoryLayoutNotSupported);
+        }
+        let ipa_size = self.ipa_size.unwrap_or(DEFAULT_IPA_SIZE);
+        if !(MIN_IPA_SIZE..=MAX_IPA_SIZE).contains(&ipa_size) {
+            return Err(VmConfigError::InvalidIpaSize);
+        }
+        if mem_size_mib << 20 > dram_mem_max_size(ipa_size) {
+            return Err(VmConfigError::MemoryExceedsIpaSize);
+        }
+        Ok(())
+    }
+}
+
+/// Virtio features withheld from the guest drivers, whatever the devices support.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtioFeaturePolicy {
+    /// Feature bits that no device offers.
+    #[serde(default)]
+    pub denied_features: Vec<u32>,
+    /// Feature bits that the devices with the given IDs don't offer, in addition to
+    /// `denied_features`.
+    #[serde(default)]
+    pub devices: BTreeMap<String, Vec<u32>>,
+}
+
+impl VirtioFeaturePolicy {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn validate(&self) -> Result<(), VmConfigError> {
+        match self
+            .denied_features
+            .iter()
+            .chain(self.devices.values().flatten())
+            .find(|&&bit| bit >= u64::BITS)
+        {
+            Some(&bit) => Err(VmConfigError::InvalidVirtioFeature(bit)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the mask of the feature bits the device with the given ID must not offer.
+    pub fn denied_features(&self, device_id: &str) -> u64 {
+        self.denied_features
+            .iter()
+            .chain(self.devices.get(device_id).into_iter().flatten())
+            .fold(0, |mask, bit| mask | 1u64 << bit)
+    }
+}
+
+/// Host scheduling settings of a vCPU thread, applied when the thread is spawned.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VcpuThreadConfig {
+    /// Index of the vCPU.
+    pub vcpu: u8,
+    /// Host CPUs the thread is pinned to. The thread runs on any host CPU when empty.
+    #[serde(default)]
+    pub cpus: Vec<usize>,
+    /// Nice level of the thread, under the default scheduling policy.
+    #[serde(default)]
+    pub nice: Option<i8>,
+    /// Priority of the thread, under the SCHED_FIFO real-time scheduling policy.
+    #[serde(default)]
+    pub fifo_priority: Option<u8>,
+    /// Minimum utilization clamp of the thread, out of 1024.
+    #[serde(default)]
+    pub uclamp_min: Option<u16>,
+    /// Maximum utilization clamp of the thread, out of 1024.
+    #[serde(default)]
+    pub uclamp_max: Option<u16>,
+}
+
+impl VcpuThreadConfig {
+    /// Highest utilization clamp value.
+    pub const UCLAMP_SCALE: u16 = 1024;
+
+    fn is_valid(&self) -> bool {
+        // `CPU_SETSIZE` is a positive constant.
+        #[allow(clippy::cast_sign_loss)]
+        let cpus_valid = self
+            .cpus
+            .iter()
+            .all(|&cpu| cpu < libc::CPU_SETSIZE as usize);
+        let nice_valid = self.nice.iter().all(|nice| (-20..=19).contains(nice));
+        let fifo_valid = self
+            .fifo_priority
+            .iter()
+            .all(|priority| (1..=99).contains(priority) && self.nice.is_none());
+        let uclamp_valid = match (self.uclamp_min, self.uclamp_max) {
+            (Some(min), Some(max)) => min <= max && max <= Self::UCLAMP_SCALE,
+            (Some(value), None) | (None, Some(value)) => value <= Self::UCLAMP_SCALE,
+            (None, None) => true,
+        };
+        cpus_valid && nice_valid && fifo_valid && uclamp_valid
+    }
+}
+
+fn validate_vcpu_threads(
+    vcpu_threads: &[VcpuThreadConfig],
+    vcpu_count: u8,
+) -> Result<(), VmConfigError> {
+    let mut configured = 0u64;
+    for config in vcpu_threads {
+        if config.vcpu >= vcpu_count || configured & (1 << config.vcpu) != 0 {
+            return Err(VmConfigError::InvalidVcpuThreadIndex(config.vcpu));
+        }
+        configured |= 1 << config.vcpu;
+        if !config.is_valid() {
+            return Err(VmConfigError::InvalidVcpuThreadConfig(config.vcpu));
+        }
+    }
+    Ok(())
+}
+
 /// Struct used in PUT `/machine-config` API call.
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
@@ -120,9 +431,42 @@ pub struct MachineConfig {
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     #[serde(default)]
     pub track_dirty_pages: bool,
+    /// How KVM reports the dirty pages, when dirty page tracking is enabled.
+    #[serde(default, skip_serializing_if = "DirtyTrackingMode::is_auto")]
+    pub dirty_tracking_mode: DirtyTrackingMode,
     /// Configures what page size Firecracker should use to back guest memory.
     #[serde(default)]
     pub huge_pages: HugePageConfig,
+    /// Host memory backing the guest memory.
+    #[serde(default, skip_serializing_if = "MemoryBackendType::is_auto")]
+    pub memory_backend: MemoryBackendType,
+    /// Pre-faults all guest memory before the microVM starts running.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mem_populate: bool,
+    /// Guest memory ranges to pre-fault before the microVM starts running.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mem_prefault_ranges: Vec<GuestMemoryRange>,
+    /// Marks all guest memory as mergeable by KSM.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mem_mergeable: bool,
+    /// Guest memory ranges to mark as mergeable by KSM.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mem_mergeable_ranges: Vec<GuestMemoryRange>,
+    /// Protects the guest memory and state from the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidential: Option<ConfidentialConfig>,
+    /// Layout of the MMIO address space.
+    #[serde(default, skip_serializing_if = "MemoryLayoutConfig::is_default")]
+    pub memory_layout: MemoryLayoutConfig,
+    /// Virtio features withheld from the guest drivers.
+    #[serde(default, skip_serializing_if = "VirtioFeaturePolicy::is_default")]
+    pub virtio_feature_policy: VirtioFeaturePolicy,
+    /// Host scheduling settings of the vCPU threads.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vcpu_threads: Vec<VcpuThreadConfig>,
+    /// Exposes the virtual PMU of KVM to the guest.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pmu: bool,
 }
 
 impl Default for MachineConfig {
@@ -155,9 +499,42 @@ pub struct MachineConfigUpdate {
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub track_dirty_pages: Option<bool>,
+    /// How KVM reports the dirty pages, when dirty page tracking is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dirty_tracking_mode: Option<DirtyTrackingMode>,
     /// Configures what page size Firecracker should use to back guest memory.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub huge_pages: Option<HugePageConfig>,
+    /// Host memory backing the guest memory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_backend: Option<MemoryBackendType>,
+    /// Pre-faults all guest memory before the microVM starts running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_populate: Option<bool>,
+    /// Guest memory ranges to pre-fault before the microVM starts running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_prefault_ranges: Option<Vec<GuestMemoryRange>>,
+    /// Marks all guest memory as mergeable by KSM.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_mergeable: Option<bool>,
+    /// Guest memory ranges to mark as mergeable by KSM.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_mergeable_ranges: Option<Vec<GuestMemoryRange>>,
+    /// Protects the guest memory and state from the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidential: Option<ConfidentialConfig>,
+    /// Layout of the MMIO address space.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_layout: Option<MemoryLayoutConfig>,
+    /// Virtio features withheld from the guest drivers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub virtio_feature_policy: Option<VirtioFeaturePolicy>,
+    /// Host scheduling settings of the vCPU threads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vcpu_threads: Option<Vec<VcpuThreadConfig>>,
+    /// Exposes the virtual PMU of KVM to the guest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pmu: Option<bool>,
 }
 
 impl MachineConfigUpdate {
@@ -177,7 +554,18 @@ impl From<MachineConfig> for MachineConfigUpdate {
             smt: Some(cfg.smt),
             cpu_template: cfg.cpu_template,
             track_dirty_pages: Some(cfg.track_dirty_pages),
+            dirty_tracking_mode: Some(cfg.dirty_tracking_mode),
             huge_pages: Some(cfg.huge_pages),
+            memory_backend: Some(cfg.memory_backend),
+            mem_populate: Some(cfg.mem_populate),
+            mem_prefault_ranges: Some(cfg.mem_prefault_ranges),
+            mem_mergeable: Some(cfg.mem_mergeable),
+            mem_mergeable_ranges: Some(cfg.mem_mergeable_ranges),
+            confidential: cfg.confidential,
+            memory_layout: Some(cfg.memory_layout),
+            virtio_feature_policy: Some(cfg.virtio_feature_policy),
+            vcpu_threads: Some(cfg.vcpu_threads),
+            pmu: Some(cfg.pmu),
         }
     }
 }
@@ -195,8 +583,30 @@ pub struct VmConfig {
     pub cpu_template: Option<CpuTemplateType>,
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     pub track_dirty_pages: bool,
+    /// How KVM reports the dirty pages, when dirty page tracking is enabled.
+    pub dirty_tracking_mode: DirtyTrackingMode,
     /// Configures what page size Firecracker should use to back guest memory.
     pub huge_pages: HugePageConfig,
+    /// Host memory backing the guest memory.
+    pub memory_backend: MemoryBackendType,
+    /// Pre-faults all guest memory before the microVM starts running.
+    pub mem_populate: bool,
+    /// Guest memory ranges to pre-fault before the microVM starts running.
+    pub mem_prefault_ranges: Vec<GuestMemoryRange>,
+    /// Marks all guest memory as mergeable by KSM.
+    pub mem_mergeable: bool,
+    /// Guest memory ranges to mark as mergeable by KSM.
+    pub mem_mergeable_ranges: Vec<GuestMemoryRange>,
+    /// Protects the guest memory and state from the host.
+    pub confidential: Option<ConfidentialConfig>,
+    /// Layout of the MMIO address space.
+    pub memory_layout: MemoryLayoutConfig,
+    /// Virtio features withheld from the guest drivers.
+    pub virtio_feature_policy: VirtioFeaturePolicy,
+    /// Host scheduling settings of the vCPU threads.
+    pub vcpu_threads: Vec<VcpuThreadConfig>,
+    /// Exposes the virtual PMU of KVM to the guest.
+    pub pmu: bool,
 }
 
 impl VmConfig {
@@ -247,13 +657,78 @@ impl VmConfig {
             return Err(VmConfigError::HugetlbfsNotSupported);
         }
 
+        let mem_prefault_ranges = update
+            .mem_prefault_ranges
+            .clone()
+            .unwrap_or_else(|| self.mem_prefault_ranges.clone());
+        let mem_mergeable_ranges = update
+            .mem_mergeable_ranges
+            .clone()
+            .unwrap_or_else(|| self.mem_mergeable_ranges.clone());
+        if !mem_prefault_ranges
+            .iter()
+            .chain(&mem_mergeable_ranges)
+            .all(GuestMemoryRange::is_valid)
+        {
+            return Err(VmConfigError::InvalidMemoryRange);
+        }
+
+        let memory_backend = update.memory_backend.unwrap_or(self.memory_backend);
+        let mem_mergeable = update.mem_mergeable.unwrap_or(self.mem_mergeable);
+        if (mem_mergeable || !mem_mergeable_ranges.is_empty())
+            && (page_config.is_hugetlbfs() || memory_backend == MemoryBackendType::Memfd)
+        {
+            return Err(VmConfigError::MergeableSharedMemory);
+        }
+
+        let track_dirty_pages = update.track_dirty_pages.unwrap_or(self.track_dirty_pages);
+        let confidential = update.confidential.or(self.confidential);
+        if let Some(confidential) = confidential {
+            if !confidential.technology.is_supported() {
+                return Err(VmConfigError::ConfidentialNotSupported(
+                    confidential.technology,
+                ));
+            }
+            if track_dirty_pages {
+                return Err(VmConfigError::ConfidentialAndDirtyPageTracking);
+            }
+        }
+
+        let memory_layout = update.memory_layout.unwrap_or(self.memory_layout);
+        memory_layout.validate(mem_size_mib)?;
+
+        let virtio_feature_policy = update
+            .virtio_feature_policy
+            .clone()
+            .unwrap_or_else(|| self.virtio_feature_policy.clone());
+        virtio_feature_policy.validate()?;
+
+        let vcpu_threads = update
+            .vcpu_threads
+            .clone()
+            .unwrap_or_else(|| self.vcpu_threads.clone());
+        validate_vcpu_threads(&vcpu_threads, vcpu_count)?;
+
         Ok(VmConfig {
             vcpu_count,
             mem_size_mib,
             smt,
             cpu_template,
-            track_dirty_pages: update.track_dirty_pages.unwrap_or(self.track_dirty_pages),
+            track_dirty_pages,
+            dirty_tracking_mode: update
+                .dirty_tracking_mode
+                .unwrap_or(self.dirty_tracking_mode),
             huge_pages: page_config,
+            memory_backend,
+            mem_populate: update.mem_populate.unwrap_or(self.mem_populate),
+            mem_prefault_ranges,
+            mem_mergeable,
+            mem_mergeable_ranges,
+            confidential,
+            memory_layout,
+            virtio_feature_policy,
+            vcpu_threads,
+            pmu: update.pmu.unwrap_or(self.pmu),
         })
     }
 }
@@ -266,7 +741,18 @@ impl Default for VmConfig {
             smt: false,
             cpu_template: None,
             track_dirty_pages: false,
+            dirty_tracking_mode: DirtyTrackingMode::Auto,
             huge_pages: HugePageConfig::None,
+            memory_backend: MemoryBackendType::Auto,
+            mem_populate: false,
+            mem_prefault_ranges: Vec::new(),
+            mem_mergeable: false,
+            mem_mergeable_ranges: Vec::new(),
+            confidential: None,
+            memory_layout: MemoryLayoutConfig::default(),
+            virtio_feature_policy: VirtioFeaturePolicy::default(),
+            vcpu_threads: Vec::new(),
+            pmu: false,
         }
     }
 }
@@ -279,7 +765,18 @@ impl From<&VmConfig> for MachineConfig {
             smt: value.smt,
             cpu_template: value.cpu_template.as_ref().map(|template| template.into()),
             track_dirty_pages: value.track_dirty_pages,
+            dirty_tracking_mode: value.dirty_tracking_mode,
             huge_pages: value.huge_pages,
+            memory_backend: value.memory_backend,
+            mem_populate: value.mem_populate,
+            mem_prefault_ranges: value.mem_prefault_ranges.clone(),
+            mem_mergeable: value.mem_mergeable,
+            mem_mergeable_ranges: value.mem_mergeable_ranges.clone(),
+            confidential: value.confidential,
+            memory_layout: value.memory_layout,
+            virtio_feature_policy: value.virtio_feature_policy.clone(),
+            vcpu_threads: value.vcpu_threads.clone(),
+            pmu: value.pmu,
         }
     }
 }
@@ -288,9 +785,12 @@ impl From<&VmConfig> for MachineConfig {
 mod tests {
     use utils::kernel_version::KernelVersion;
 
+    use crate::device_manager::resources::ResourceRequirements;
     use crate::vmm_config::machine_config::{
-        HugePageConfig, MachineConfigUpdate, VmConfig, VmConfigError,
+        DirtyTrackingMode, HugePageConfig, MachineConfig, MachineConfigUpdate, MemoryBackendType,
+        MemoryLayoutConfig, VcpuThreadConfig, VirtioFeaturePolicy, VmConfig, VmConfigError,
     };
+    use crate::vstate::confidential::{ConfidentialConfig, ConfidentialTechnology};
 
     #[test]
     fn test_hugetlbfs_not_supported_4_14() {
@@ -306,4 +806,412 @@ mod tests {
             assert_eq!(err, VmConfigError::HugetlbfsNotSupported)
         }
     }
+
+    #[test]
+    fn test_confidential() {
+        let base_config = VmConfig::default();
+        let config = ConfidentialConfig {
+            technology: ConfidentialTechnology::SevSnp,
+            policy: 0x30000,
+        };
+        let update = MachineConfigUpdate {
+            confidential: Some(config),
+            ..Default::default()
+        };
+
+        if !config.technology.is_supported() {
+            assert_eq!(
+                base_config.update(&update).unwrap_err(),
+                VmConfigError::ConfidentialNotSupported(ConfidentialTechnology::SevSnp)
+            );
+            return;
+        }
+
+        let updated = base_config.update(&update).unwrap();
+        assert_eq!(updated.confidential, Some(config));
+        assert_eq!(
+            updated
+                .update(&MachineConfigUpdate {
+                    track_dirty_pages: Some(true),
+                    ..Default::default()
+                })
+                .unwrap_err(),
+            VmConfigError::ConfidentialAndDirtyPageTracking
+        );
+    }
+
+    #[test]
+    fn test_dirty_tracking_mode() {
+        let config: MachineConfig = serde_json::from_str(
+            r#"{"vcpu_count": 1, "mem_size_mib": 128, "dirty_tracking_mode": "Ring"}"#,
+        )
+        .unwrap();
+        assert_eq!(config.dirty_tracking_mode, DirtyTrackingMode::Ring);
+
+        let updated = VmConfig::default()
+            .update(&MachineConfigUpdate::from(config))
+            .unwrap();
+        assert_eq!(updated.dirty_tracking_mode, DirtyTrackingMode::Ring);
+        let updated = updated
+            .update(&MachineConfigUpdate {
+                vcpu_count: Some(2),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(updated.dirty_tracking_mode, DirtyTrackingMode::Ring);
+
+        // The default mode is left out of the serialized configuration.
+        let json = serde_json::to_string(&MachineConfig::default()).unwrap();
+        assert!(!json.contains("dirty_tracking_mode"));
+    }
+
+    #[test]
+    fn test_memory_backend() {
+        let config: MachineConfig = serde_json::from_str(
+            r#"{"vcpu_count": 1, "mem_size_mib": 128, "memory_backend": "Memfd"}"#,
+        )
+        .unwrap();
+        assert_eq!(config.memory_backend, MemoryBackendType::Memfd);
+
+        let updated = VmConfig::default()
+            .update(&MachineConfigUpdate::from(config))
+            .unwrap();
+        assert_eq!(updated.memory_backend, MemoryBackendType::Memfd);
+        let updated = updated
+            .update(&MachineConfigUpdate {
+                vcpu_count: Some(2),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(updated.memory_backend, MemoryBackendType::Memfd);
+
+        // The default backend is left out of the serialized configuration.
+        let json = serde_json::to_string(&MachineConfig::default()).unwrap();
+        assert!(!json.contains("memory_backend"));
+    }
+
+    #[test]
+    fn test_memory_layout() {
+        let config: MachineConfig = serde_json::from_str(
+            r#"{
+                "vcpu_count": 1,
+                "mem_size_mib": 128,
+                "memory_layout": {"mmio_gap_start": 2147483648, "mmio_gap_size": 2147483648}
+            }"#,
+        )
+        .unwrap();
+        let layout = MemoryLayoutConfig {
+            mmio_gap_start: 2 << 30,
+            mmio_gap_size: 2 << 30,
+            mmio64_size: 0,
+            ipa_size: None,
+        };
+        assert_eq!(config.memory_layout, layout);
+
+        // The default layout is left out of the serialized configuration.
+        let json = serde_json::to_string(&MachineConfig::default()).unwrap();
+        assert!(!json.contains("memory_layout"));
+
+        let update = MachineConfigUpdate {
+            memory_layout: Some(layout),
+            ..Default::default()
+        };
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            VmConfig::default().update(&update).unwrap_err(),
+            VmConfigError::MemoryLayoutNotSupported
+        );
+        #[cfg(target_arch = "x86_64")]
+        {
+            let updated = VmConfig::default().update(&update).unwrap();
+            assert_eq!(updated.memory_layout, layout);
+
+            let invalid_layouts = [
+                // Starts below 1 GiB.
+                MemoryLayoutConfig {
+                    mmio_gap_start: 512 << 20,
+                    ..layout
+                },
+                // Ends above 4 GiB.
+                MemoryLayoutConfig {
+                    mmio_gap_size: (2 << 30) + (2 << 20),
+                    ..layout
+                },
+                // Smaller than 256 MiB.
+                MemoryLayoutConfig {
+                    mmio_gap_size: 128 << 20,
+                    ..layout
+                },
+                // Misaligned.
+                MemoryLayoutConfig {
+                    mmio_gap_start: (2 << 30) + 4096,
+                    mmio_gap_size: (1 << 30) + 4096,
+                    ..layout
+                },
+                MemoryLayoutConfig {
+                    mmio64_size: 3 << 29,
+                    ..layout
+                },
+                // Larger than 1 TiB.
+                MemoryLayoutConfig {
+                    mmio64_size: 2 << 40,
+                    ..layout
+                },
+            ];
+            for memory_layout in invalid_layouts {
+                assert_eq!(
+                    updated
+                        .update(&MachineConfigUpdate {
+                            memory_layout: Some(memory_layout),
+                            ..Default::default()
+                        })
+                        .unwrap_err(),
+                    VmConfigError::InvalidMemoryLayout
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_fit_memory_layout() {
+        let layout = MemoryLayoutConfig::default();
+        let requirements = ResourceRequirements {
+            gsis: 2,
+            mmio: 0x2000,
+            mmio64: 0,
+        };
+        assert_eq!(layout.fit(&requirements).unwrap(), layout);
+
+        let too_much_mmio = ResourceRequirements {
+            mmio: layout.mmio_gap_size + 0x1000,
+            ..requirements
+        };
+        assert_eq!(
+            layout.fit(&too_much_mmio).unwrap_err(),
+            VmConfigError::MmioGapTooSmall(layout.mmio_gap_size + 0x1000, layout.mmio_gap_size)
+        );
+
+        let requirements = ResourceRequirements {
+            mmio64: (1 << 30) + 0x1000,
+            ..requirements
+        };
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            layout.fit(&requirements).unwrap_err(),
+            VmConfigError::Mmio64WindowTooSmall((1 << 30) + 0x1000, 0)
+        );
+        #[cfg(target_arch = "x86_64")]
+        {
+            // A 64-bit window that was not configured is sized for the devices.
+            assert_eq!(layout.fit(&requirements).unwrap().mmio64_size, 2 << 30);
+
+            let configured = MemoryLayoutConfig {
+                mmio64_size: 1 << 30,
+                ..layout
+            };
+            assert_eq!(
+                configured.fit(&requirements).unwrap_err(),
+                VmConfigError::Mmio64WindowTooSmall((1 << 30) + 0x1000, 1 << 30)
+            );
+
+            let too_much_mmio64 = ResourceRequirements {
+                mmio64: 2 << 40,
+                ..requirements
+            };
+            assert_eq!(
+                layout.fit(&too_much_mmio64).unwrap_err(),
+                VmConfigError::Mmio64WindowTooSmall(2 << 40, 1 << 40)
+            );
+        }
+    }
+
+    #[test]
+    fn test_ipa_size() {
+        let update = |ipa_size, mem_size_mib| MachineConfigUpdate {
+            mem_size_mib: Some(mem_size_mib),
+            memory_layout: Some(MemoryLayoutConfig {
+                ipa_size,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            VmConfig::default()
+                .update(&update(Some(44), 128))
+                .unwrap_err(),
+            VmConfigError::IpaSizeNotSupported
+        );
+        #[cfg(target_arch = "aarch64")]
+        {
+            // The guest memory is laid out within 40 bits by default.
+            assert_eq!(
+                VmConfig::default()
+                    .update(&update(None, 1024 << 10))
+                    .unwrap_err(),
+                VmConfigError::MemoryExceedsIpaSize
+            );
+            let updated = VmConfig::default()
+                .update(&update(Some(44), 1024 << 10))
+                .unwrap();
+            assert_eq!(updated.memory_layout.ipa_size, Some(44));
+            assert_eq!(
+                VmConfig::default()
+                    .update(&update(Some(31), 128))
+                    .unwrap_err(),
+                VmConfigError::InvalidIpaSize
+            );
+            assert_eq!(
+                VmConfig::default()
+                    .update(&update(Some(53), 128))
+                    .unwrap_err(),
+                VmConfigError::InvalidIpaSize
+            );
+        }
+    }
+
+    #[test]
+    fn test_virtio_feature_policy() {
+        let config: MachineConfig = serde_json::from_str(
+            r#"{
+                "vcpu_count": 1,
+                "mem_size_mib": 128,
+                "virtio_feature_policy": {
+                    "denied_features": [28],
+                    "devices": {"net0": [0, 11]}
+                }
+            }"#,
+        )
+        .unwrap();
+        let updated = VmConfig::default()
+            .update(&MachineConfigUpdate::from(config))
+            .unwrap();
+        let policy = &updated.virtio_feature_policy;
+        assert_eq!(policy.denied_features("net0"), 1 << 28 | 1 << 11 | 1);
+        assert_eq!(policy.denied_features("rootfs"), 1 << 28);
+
+        // The policy is kept by updates that don't set it.
+        let updated = updated
+            .update(&MachineConfigUpdate {
+                vcpu_count: Some(2),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(policy, &updated.virtio_feature_policy);
+
+        let mut policy = VirtioFeaturePolicy::default();
+        policy.devices.insert("net0".to_string(), vec![64]);
+        assert_eq!(
+            updated
+                .update(&MachineConfigUpdate {
+                    virtio_feature_policy: Some(policy),
+                    ..Default::default()
+                })
+                .unwrap_err(),
+            VmConfigError::InvalidVirtioFeature(64)
+        );
+
+        // An empty policy is left out of the serialized configuration.
+        let json = serde_json::to_string(&MachineConfig::default()).unwrap();
+        assert!(!json.contains("virtio_feature_policy"));
+    }
+
+    #[test]
+    fn test_pmu() {
+        let update = MachineConfigUpdate {
+            pmu: Some(true),
+            ..Default::default()
+        };
+        assert!(VmConfig::default().update(&update).unwrap().pmu);
+        // The PMU is not exposed by default, and omitted from the configuration when disabled.
+        let config = MachineConfig::default();
+        assert!(!config.pmu);
+        assert!(!serde_json::to_string(&config).unwrap().contains("pmu"));
+    }
+
+    #[test]
+    fn test_vcpu_threads() {
+        let config: MachineConfig = serde_json::from_str(
+            r#"{
+                "vcpu_count": 2,
+                "mem_size_mib": 128,
+                "vcpu_threads": [
+                    {"vcpu": 0, "cpus": [2, 3], "nice": -5},
+                    {"vcpu": 1, "fifo_priority": 10, "uclamp_min": 512, "uclamp_max": 1024}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let updated = VmConfig::default()
+            .update(&MachineConfigUpdate::from(config))
+            .unwrap();
+        assert_eq!(updated.vcpu_threads.len(), 2);
+        assert_eq!(updated.vcpu_threads[0].cpus, vec![2, 3]);
+        assert_eq!(updated.vcpu_threads[1].fifo_priority, Some(10));
+
+        // Reducing the number of vCPUs below a configured thread is rejected.
+        assert_eq!(
+            updated
+                .update(&MachineConfigUpdate {
+                    vcpu_count: Some(1),
+                    ..Default::default()
+                })
+                .unwrap_err(),
+            VmConfigError::InvalidVcpuThreadIndex(1)
+        );
+
+        let update = |vcpu_threads| MachineConfigUpdate {
+            vcpu_threads: Some(vcpu_threads),
+            ..Default::default()
+        };
+        let thread = |vcpu| VcpuThreadConfig {
+            vcpu,
+            ..Default::default()
+        };
+        assert_eq!(
+            updated
+                .update(&update(vec![thread(0), thread(0)]))
+                .unwrap_err(),
+            VmConfigError::InvalidVcpuThreadIndex(0)
+        );
+        let invalid_configs = [
+            VcpuThreadConfig {
+                cpus: vec![1024],
+                ..thread(1)
+            },
+            VcpuThreadConfig {
+                nice: Some(20),
+                ..thread(1)
+            },
+            VcpuThreadConfig {
+                fifo_priority: Some(0),
+                ..thread(1)
+            },
+            VcpuThreadConfig {
+                nice: Some(0),
+                fifo_priority: Some(1),
+                ..thread(1)
+            },
+            VcpuThreadConfig {
+                uclamp_max: Some(1025),
+                ..thread(1)
+            },
+            VcpuThreadConfig {
+                uclamp_min: Some(512),
+                uclamp_max: Some(256),
+                ..thread(1)
+            },
+        ];
+        for config in invalid_configs {
+            assert_eq!(
+                updated.update(&update(vec![config])).unwrap_err(),
+                VmConfigError::InvalidVcpuThreadConfig(1)
+            );
+        }
+
+        // Unset thread settings are left out of the serialized configuration.
+        let json = serde_json::to_string(&MachineConfig::default()).unwrap();
+        assert!(!json.contains("vcpu_threads"));
+    }
 }
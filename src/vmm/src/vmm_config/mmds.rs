@@ -1,6 +1,7 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 use std::net::Ipv4Addr;
+use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
@@ -18,6 +19,27 @@ pub struct MmdsConfig {
     pub network_interfaces: Vec<String>,
     /// MMDS IPv4 configured address.
     pub ipv4_address: Option<Ipv4Addr>,
+    /// Settings of the network interfaces that differ from the ones above.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub interfaces: Vec<MmdsInterfaceConfig>,
+    /// JSON file whose content replaces the data store whenever the file is written or replaced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_file: Option<PathBuf>,
+}
+
+/// MMDS settings specific to one of the network interfaces that allow forwarding packets to MMDS.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MmdsInterfaceConfig {
+    /// ID of the network interface.
+    pub iface_id: String,
+    /// MMDS IPv4 address on the network interface, instead of the one of the MMDS config.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv4_address: Option<Ipv4Addr>,
+    /// Top-level key of the MMDS data store whose value is exposed as the root of the data
+    /// through the network interface, instead of the whole data store.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
 }
 
 impl MmdsConfig {
@@ -36,6 +58,13 @@ impl MmdsConfig {
     pub fn ipv4_addr(&self) -> Option<Ipv4Addr> {
         self.ipv4_address
     }
+
+    /// Returns the settings specific to the network interface `iface_id`, if any.
+    pub fn interface_config(&self, iface_id: &str) -> Option<&MmdsInterfaceConfig> {
+        self.interfaces
+            .iter()
+            .find(|config| config.iface_id == iface_id)
+    }
 }
 
 /// MMDS configuration related errors.
@@ -50,4 +79,12 @@ pub enum MmdsConfigError {
     InvalidNetworkInterfaceId,
     /// The MMDS could not be configured to version {0}: {1}
     MmdsVersion(MmdsVersion, data_store::MmdsDatastoreError),
+    /// The MMDS settings of network interface {0} are given more than once, or for an interface that does not forward packets to MMDS.
+    InvalidInterfaceConfig(String),
+    /// The MMDS namespace {0} is not a valid top-level key of the data store: it must be non-empty and cannot contain '/' or '~'.
+    InvalidNamespace(String),
+    /// The network interface {0} is emulated by a worker process, which cannot forward packets to MMDS.
+    WorkerInterface(String),
+    /// The MMDS data file path {0:?} does not name a file.
+    InvalidDataFile(PathBuf),
 }
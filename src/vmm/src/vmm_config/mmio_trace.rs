@@ -0,0 +1,39 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+/// Selects the virtio devices whose MMIO register accesses are logged, to debug the feature
+/// negotiation and queue setup of guest drivers.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MmioTraceConfig {
+    /// IDs of the traced devices. The devices missing from the list stop being traced.
+    pub device_ids: Vec<String>,
+}
+
+/// Errors associated with tracing the MMIO register accesses.
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum MmioTraceError {
+    /// No virtio device with this ID: {0}
+    UnknownDevice(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mmio_trace_config_deserialize() {
+        let config: MmioTraceConfig =
+            serde_json::from_str(r#"{"device_ids": ["rootfs", "eth0"]}"#).unwrap();
+        assert_eq!(
+            config.device_ids,
+            vec!["rootfs".to_string(), "eth0".to_string()]
+        );
+
+        serde_json::from_str::<MmioTraceConfig>(r#"{"device_ids": "rootfs"}"#).unwrap_err();
+        serde_json::from_str::<MmioTraceConfig>(r#"{"device_ids": [], "level": "Info"}"#)
+            .unwrap_err();
+    }
+}
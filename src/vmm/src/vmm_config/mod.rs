@@ -16,10 +16,18 @@ use crate::rate_limiter::{BucketUpdate, RateLimiter, TokenBucket};
 pub mod balloon;
 /// Wrapper for configuring the microVM boot source.
 pub mod boot_source;
+/// Wrapper for applying batches of device configurations atomically.
+pub mod config_batch;
+/// Wrapper for configuring the crypto device attached to the microVM.
+pub mod crypto;
+/// Wrapper for validating the IDs of the devices attached to the microVM.
+pub mod device_id;
 /// Wrapper for configuring the block devices.
 pub mod drive;
 /// Wrapper for configuring the entropy device attached to the microVM.
 pub mod entropy;
+/// Wrapper for injecting host faults into the device backends.
+pub mod fault_injection;
 /// Wrapper over the microVM general information attached to the microVM.
 pub mod instance_info;
 /// Wrapper for configuring the memory and CPU of the microVM.
@@ -28,10 +36,24 @@ pub mod machine_config;
 pub mod metrics;
 /// Wrapper for configuring the MMDS.
 pub mod mmds;
+/// Wrapper for configuring the tracing of the MMIO register accesses of the virtio devices.
+pub mod mmio_trace;
 /// Wrapper for configuring the network devices attached to the microVM.
 pub mod net;
+/// Wrapper for configuring the forwarding of host ports to the guest.
+pub mod port_forward;
+/// Wrapper for configuring the remote devices attached to the microVM.
+pub mod remote_device;
+/// Wrapper for configuring the record and replay of the inputs of the devices.
+pub mod replay;
 /// Wrapper for configuring microVM snapshots and the microVM state.
 pub mod snapshot;
+/// Wrapper for configuring the TPM device attached to the microVM.
+pub mod tpm;
+/// Wrapper for configuring the extraction of the guest memory to a vmcore file.
+pub mod vmcore;
+/// Information about the VMM process.
+pub mod vmm_info;
 /// Wrapper for configuring the vsock devices attached to the microVM.
 pub mod vsock;
 
@@ -164,6 +186,51 @@ impl RateLimiterConfig {
     }
 }
 
+/// The live state of a TokenBucket, as reported at runtime.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TokenBucketStats {
+    /// See TokenBucket::size.
+    pub size: u64,
+    /// The part of the initial one time burst which has not been consumed yet.
+    pub one_time_burst: u64,
+    /// See TokenBucket::refill_time.
+    pub refill_time: u64,
+    /// The number of tokens currently available in the bucket.
+    pub budget: u64,
+}
+
+impl From<&TokenBucket> for TokenBucketStats {
+    fn from(tb: &TokenBucket) -> Self {
+        TokenBucketStats {
+            size: tb.capacity(),
+            one_time_burst: tb.one_time_burst(),
+            refill_time: tb.refill_time_ms(),
+            budget: tb.budget(),
+        }
+    }
+}
+
+/// The live state of a RateLimiter, as reported at runtime.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RateLimiterStats {
+    /// State of the RateLimiter::bandwidth bucket.
+    pub bandwidth: Option<TokenBucketStats>,
+    /// State of the RateLimiter::ops bucket.
+    pub ops: Option<TokenBucketStats>,
+    /// Whether the rate limiter is currently throttling the device.
+    pub blocked: bool,
+}
+
+impl From<&RateLimiter> for RateLimiterStats {
+    fn from(rl: &RateLimiter) -> Self {
+        RateLimiterStats {
+            bandwidth: rl.bandwidth().map(TokenBucketStats::from),
+            ops: rl.ops().map(TokenBucketStats::from),
+            blocked: rl.is_blocked(),
+        }
+    }
+}
+
 /// Create and opens a File for writing to it.
 /// In case we open a FIFO, in order to not block the instance if nobody is consuming the message
 /// that is flushed to the two pipes, we are opening it with `O_NONBLOCK` flag.
@@ -179,6 +246,7 @@ fn open_file_nonblock(path: &Path) -> Result<File, std::io::Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rate_limiter::TokenType;
 
     const SIZE: u64 = 1024 * 1024;
     const ONE_TIME_BURST: u64 = 1024;
@@ -227,4 +295,28 @@ mod tests {
         assert_eq!(generated_rl_conf, rl_conf);
         assert_eq!(generated_rl_conf.into_option(), Some(rl_conf));
     }
+
+    #[test]
+    fn test_rate_limiter_stats() {
+        let mut rl = RateLimiter::new(SIZE, ONE_TIME_BURST, REFILL_TIME, 0, 0, 0).unwrap();
+        assert!(rl.consume(ONE_TIME_BURST / 2, TokenType::Bytes));
+
+        let stats = RateLimiterStats::from(&rl);
+        assert_eq!(
+            stats.bandwidth,
+            Some(TokenBucketStats {
+                size: SIZE,
+                one_time_burst: ONE_TIME_BURST / 2,
+                refill_time: REFILL_TIME,
+                budget: SIZE,
+            })
+        );
+        assert_eq!(stats.ops, None);
+        assert!(!stats.blocked);
+
+        // Exhaust the bandwidth budget so the rate limiter blocks.
+        assert!(rl.consume(SIZE, TokenType::Bytes));
+        assert!(!rl.consume(SIZE, TokenType::Bytes));
+        assert!(RateLimiterStats::from(&rl).blocked);
+    }
 }
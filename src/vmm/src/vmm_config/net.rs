@@ -8,7 +8,13 @@ use std::sync::{Arc, Mutex};
 use serde::{Deserialize, Serialize};
 use utils::net::mac::MacAddr;
 
-use super::RateLimiterConfig;
+use super::device_id::DeviceIdError;
+use super::{RateLimiterConfig, RateLimiterStats};
+use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::dma::{DmaRange, DmaRanges, DmaRangesError};
+use crate::devices::virtio::net::coalescing::InterruptCoalescingConfig;
+use crate::devices::virtio::net::dhcp::{DhcpConfig, DhcpConfigError};
+use crate::devices::virtio::net::xdp::XdpConfig;
 use crate::devices::virtio::net::{Net, TapError};
 use crate::VmmError;
 
@@ -27,6 +33,31 @@ pub struct NetworkInterfaceConfig {
     pub rx_rate_limiter: Option<RateLimiterConfig>,
     /// Rate Limiter for transmitted packages.
     pub tx_rate_limiter: Option<RateLimiterConfig>,
+    /// ID of another network interface of the microVM which receives the frames addressed to
+    /// its guest MAC directly, without going through the tap devices.
+    #[serde(default)]
+    pub peer: Option<String>,
+    /// Guest memory ranges the device is allowed to access. Unrestricted if missing.
+    #[serde(default)]
+    pub dma_ranges: Option<Vec<DmaRange>>,
+    /// Interrupt coalescing for the used RX descriptors. Disabled if missing.
+    #[serde(default)]
+    pub rx_interrupt_coalescing: Option<InterruptCoalescingConfig>,
+    /// Interrupt coalescing for the used TX descriptors. Disabled if missing.
+    #[serde(default)]
+    pub tx_interrupt_coalescing: Option<InterruptCoalescingConfig>,
+    /// If set to true, the frames are moved between the guest and the tap by a sandboxed worker
+    /// process instead of the VMM process.
+    #[serde(default)]
+    pub worker: bool,
+    /// Network configuration handed to the guest by the DHCP responder of the device. The DHCP
+    /// requests of the guest go to the tap device if missing.
+    #[serde(default)]
+    pub dhcp: Option<DhcpConfig>,
+    /// AF_XDP socket exchanging the frames instead of the tap device. The tap device is used if
+    /// the socket cannot be set up.
+    #[serde(default)]
+    pub xdp: Option<XdpConfig>,
 }
 
 impl From<&Net> for NetworkInterfaceConfig {
@@ -39,12 +70,19 @@ impl From<&Net> for NetworkInterfaceConfig {
             guest_mac: net.guest_mac().copied(),
             rx_rate_limiter: rx_rl.into_option(),
             tx_rate_limiter: tx_rl.into_option(),
+            peer: net.peer_id().cloned(),
+            dma_ranges: net.dma_ranges(),
+            rx_interrupt_coalescing: net.rx_interrupt_coalescing().into_option(),
+            tx_interrupt_coalescing: net.tx_interrupt_coalescing().into_option(),
+            worker: net.is_worker(),
+            dhcp: net.dhcp_config().cloned(),
+            xdp: net.xdp_config().cloned(),
         }
     }
 }
 
-/// The data fed into a network iface update request. Currently, only the RX and TX rate limiters
-/// can be updated.
+/// The data fed into a network iface update request. Currently, only the RX and TX rate limiters,
+/// the guest MAC address and the interrupt coalescing can be updated.
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct NetworkInterfaceUpdateConfig {
@@ -56,6 +94,41 @@ pub struct NetworkInterfaceUpdateConfig {
     /// New TX rate limiter config. Only provided data will be updated. I.e. if any optional data
     /// is missing, it will not be nullified, but left unchanged.
     pub tx_rate_limiter: Option<RateLimiterConfig>,
+    /// New guest MAC address. The guest is notified of the change through a config interrupt.
+    #[serde(default)]
+    pub guest_mac: Option<MacAddr>,
+    /// New interrupt coalescing parameters for the used RX descriptors.
+    #[serde(default)]
+    pub rx_interrupt_coalescing: Option<InterruptCoalescingConfig>,
+    /// New interrupt coalescing parameters for the used TX descriptors.
+    #[serde(default)]
+    pub tx_interrupt_coalescing: Option<InterruptCoalescingConfig>,
+}
+
+/// The live state of the RX and TX rate limiters of a network iface.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct NetworkInterfaceRateLimiterStats {
+    /// State of the RX rate limiter.
+    pub rx_rate_limiter: RateLimiterStats,
+    /// State of the TX rate limiter.
+    pub tx_rate_limiter: RateLimiterStats,
+}
+
+/// Link state of a network interface, as announced to the guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LinkState {
+    /// The guest driver reports the link as up.
+    Up,
+    /// The guest driver reports the link as down.
+    Down,
+}
+
+/// The data fed into a network iface link request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkInterfaceLinkConfig {
+    /// New link state announced to the guest.
+    pub state: LinkState,
 }
 
 /// Errors associated with the operations allowed on a net device.
@@ -65,16 +138,30 @@ pub enum NetworkInterfaceError {
     CreateNetworkDevice(#[from] crate::devices::virtio::net::NetError),
     /// Cannot create the rate limiter: {0}
     CreateRateLimiter(#[from] std::io::Error),
+    /// Invalid DMA ranges: {0}
+    DmaRanges(#[from] DmaRangesError),
     /// Unable to update the net device: {0}
     DeviceUpdate(#[from] VmmError),
+    /// Invalid network interface ID: {0}
+    DeviceId(#[from] DeviceIdError),
     /// The MAC address is already in use: {0}
     GuestMacAddressInUse(String),
     /// Cannot open/create the tap device: {0}
     OpenTap(#[from] TapError),
+    /// The network interface is already the peer of another interface: {0}
+    PeerAlreadyLinked(String),
+    /// A network interface cannot be its own peer: {0}
+    PeerIsSelf(String),
+    /// Unsupported settings for a network interface emulated by a worker process: {0}
+    WorkerUnsupported(String),
+    /// Invalid DHCP configuration: {0}
+    Dhcp(#[from] DhcpConfigError),
+    /// No network interface with this ID: {0}
+    UnknownInterface(String),
 }
 
 /// Builder for a list of network devices.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct NetBuilder {
     net_devices: Vec<Arc<Mutex<Net>>>,
 }
@@ -125,6 +212,12 @@ impl NetBuilder {
             }
         }
 
+        self.validate_worker(&netif_config)?;
+        self.validate_peer(&netif_config)?;
+        if let Some(dhcp) = netif_config.dhcp.as_ref() {
+            dhcp.validate()?;
+        }
+
         // If this is an update, just remove the old one.
         if let Some(index) = self
             .net_devices
@@ -137,10 +230,102 @@ impl NetBuilder {
         // Add new device.
         let net = Arc::new(Mutex::new(Self::create_net(netif_config)?));
         self.net_devices.push(net.clone());
+        self.link_peers();
 
         Ok(net)
     }
 
+    // Checks that an interface emulated by a worker process doesn't use the features the worker
+    // doesn't implement, and isn't the peer of another interface.
+    fn validate_worker(
+        &self,
+        netif_config: &NetworkInterfaceConfig,
+    ) -> Result<(), NetworkInterfaceError> {
+        let iface_id = &netif_config.iface_id;
+        if netif_config.worker
+            && (netif_config.rx_rate_limiter.is_some()
+                || netif_config.tx_rate_limiter.is_some()
+                || netif_config.peer.is_some()
+                || netif_config.dma_ranges.is_some()
+                || netif_config.rx_interrupt_coalescing.is_some()
+                || netif_config.tx_interrupt_coalescing.is_some()
+                || netif_config.dhcp.is_some()
+                || netif_config.xdp.is_some()
+                || self
+                    .net_devices
+                    .iter()
+                    .any(|net| net.lock().expect("Poisoned lock").peer_id() == Some(iface_id)))
+        {
+            return Err(NetworkInterfaceError::WorkerUnsupported(iface_id.clone()));
+        }
+        if let Some(peer) = netif_config.peer.as_ref() {
+            let peer_is_worker = self.net_devices.iter().any(|net| {
+                let net = net.lock().expect("Poisoned lock");
+                net.id() == peer && net.is_worker()
+            });
+            if peer_is_worker {
+                return Err(NetworkInterfaceError::WorkerUnsupported(peer.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    // Checks that linking the interface described by `netif_config` to its peer doesn't give any
+    // interface more than one peer.
+    fn validate_peer(
+        &self,
+        netif_config: &NetworkInterfaceConfig,
+    ) -> Result<(), NetworkInterfaceError> {
+        let iface_id = &netif_config.iface_id;
+        if netif_config.peer.as_ref() == Some(iface_id) {
+            return Err(NetworkInterfaceError::PeerIsSelf(iface_id.clone()));
+        }
+
+        let Some(peer) = netif_config.peer.as_ref() else {
+            return Ok(());
+        };
+        for net in self.net_devices.iter() {
+            let net = net.lock().expect("Poisoned lock");
+            if net.id() == iface_id {
+                continue;
+            }
+            if net.id() == peer {
+                // The peer is linked to another interface.
+                if net.peer_id().is_some_and(|id| id != iface_id) {
+                    return Err(NetworkInterfaceError::PeerAlreadyLinked(peer.clone()));
+                }
+            } else if net.peer_id() == Some(peer) {
+                // Another interface is linked to the peer.
+                return Err(NetworkInterfaceError::PeerAlreadyLinked(peer.clone()));
+            } else if net.peer_id() == Some(iface_id) {
+                // Another interface is linked to this one.
+                return Err(NetworkInterfaceError::PeerAlreadyLinked(iface_id.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Links every network device to the inbox of its peer: either the device it names as peer,
+    /// or the device naming it as peer.
+    pub fn link_peers(&mut self) {
+        let links: Vec<_> = self
+            .net_devices
+            .iter()
+            .map(|net| {
+                let net = net.lock().expect("Poisoned lock");
+                (net.id().clone(), net.peer_id().cloned(), net.peer_inbox())
+            })
+            .collect();
+
+        for net in self.net_devices.iter() {
+            let mut net = net.lock().expect("Poisoned lock");
+            let partner = links.iter().find(|(id, peer_id, _)| {
+                net.peer_id() == Some(id) || peer_id.as_ref() == Some(net.id())
+            });
+            net.set_peer(partner.map(|(_, _, inbox)| inbox.clone()));
+        }
+    }
+
     /// Creates a Net device from a NetworkInterfaceConfig.
     pub fn create_net(cfg: NetworkInterfaceConfig) -> Result<Net, NetworkInterfaceError> {
         let rx_rate_limiter = cfg
@@ -153,16 +338,56 @@ impl NetBuilder {
             .map(super::RateLimiterConfig::try_into)
             .transpose()
             .map_err(NetworkInterfaceError::CreateRateLimiter)?;
+        let dma_ranges = cfg
+            .dma_ranges
+            .as_deref()
+            .map(DmaRanges::new)
+            .transpose()?
+            .map(Arc::new);
 
         // Create and return the Net device
-        crate::devices::virtio::net::Net::new(
+        let mut net = crate::devices::virtio::net::Net::new(
             cfg.iface_id,
             &cfg.host_dev_name,
             cfg.guest_mac,
             rx_rate_limiter.unwrap_or_default(),
             tx_rate_limiter.unwrap_or_default(),
         )
-        .map_err(NetworkInterfaceError::CreateNetworkDevice)
+        .map_err(NetworkInterfaceError::CreateNetworkDevice)?;
+        net.set_peer_id(cfg.peer);
+        net.set_dma_ranges(dma_ranges);
+        net.set_interrupt_coalescing(cfg.rx_interrupt_coalescing, cfg.tx_interrupt_coalescing);
+        net.set_dhcp_config(cfg.dhcp);
+        if let Some(xdp) = cfg.xdp {
+            net.set_xdp_config(xdp);
+        }
+        if cfg.worker {
+            net.start_worker()
+                .map_err(NetworkInterfaceError::CreateNetworkDevice)?;
+        }
+        Ok(net)
+    }
+
+    /// Answers the DHCP requests of the guest on the interface with `iface_id` id with `config`.
+    pub fn set_dhcp_config(
+        &mut self,
+        iface_id: &str,
+        config: DhcpConfig,
+    ) -> Result<(), NetworkInterfaceError> {
+        config.validate()?;
+        let net = self
+            .net_devices
+            .iter()
+            .find(|net| net.lock().expect("Poisoned lock").id() == iface_id)
+            .ok_or_else(|| NetworkInterfaceError::UnknownInterface(iface_id.to_string()))?;
+        let mut net = net.lock().expect("Poisoned lock");
+        if net.is_worker() {
+            return Err(NetworkInterfaceError::WorkerUnsupported(
+                iface_id.to_string(),
+            ));
+        }
+        net.set_dhcp_config(Some(config));
+        Ok(())
     }
 
     /// Returns a vec with the structures used to configure the net devices.
@@ -180,7 +405,12 @@ mod tests {
     use std::str::FromStr;
 
     use super::*;
+    use crate::devices::virtio::gen::virtio_net::{
+        VIRTIO_NET_F_CSUM, VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_MAC,
+    };
+    use crate::logger::IncMetric;
     use crate::rate_limiter::RateLimiter;
+    use crate::vmm_config::TokenBucketConfig;
 
     impl NetBuilder {
         pub fn len(&self) -> usize {
@@ -199,6 +429,13 @@ mod tests {
             guest_mac: Some(MacAddr::from_str(mac).unwrap()),
             rx_rate_limiter: RateLimiterConfig::default().into_option(),
             tx_rate_limiter: RateLimiterConfig::default().into_option(),
+            peer: None,
+            dma_ranges: None,
+            rx_interrupt_coalescing: None,
+            tx_interrupt_coalescing: None,
+            worker: false,
+            dhcp: None,
+            xdp: None,
         }
     }
 
@@ -210,6 +447,13 @@ mod tests {
                 guest_mac: self.guest_mac,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                peer: self.peer.clone(),
+                dma_ranges: self.dma_ranges.clone(),
+                rx_interrupt_coalescing: None,
+                tx_interrupt_coalescing: None,
+                worker: self.worker,
+                dhcp: self.dhcp.clone(),
+                xdp: self.xdp.clone(),
             }
         }
     }
@@ -359,4 +603,204 @@ mod tests {
             net_id
         );
     }
+
+    #[test]
+    fn test_peers() {
+        let mut net_builder = NetBuilder::new();
+
+        let mut netif_1 = create_netif("id_1", "dev5", "06:00:00:00:00:01");
+        netif_1.peer = Some("id_1".to_string());
+        assert_eq!(
+            net_builder
+                .build(netif_1.clone())
+                .err()
+                .unwrap()
+                .to_string(),
+            NetworkInterfaceError::PeerIsSelf("id_1".to_string()).to_string()
+        );
+
+        // The peer doesn't need to exist yet.
+        netif_1.peer = Some("id_2".to_string());
+        net_builder.build(netif_1).unwrap();
+        let net_1 = net_builder.net_devices[0].clone();
+        assert!(net_1.lock().unwrap().peer.is_none());
+
+        // The peer is linked from either side.
+        let netif_2 = create_netif("id_2", "dev6", "06:00:00:00:00:02");
+        net_builder.build(netif_2).unwrap();
+        let net_2 = net_builder.net_devices[1].clone();
+        assert!(Arc::ptr_eq(
+            net_1.lock().unwrap().peer.as_ref().unwrap(),
+            &net_2.lock().unwrap().peer_inbox
+        ));
+        assert!(Arc::ptr_eq(
+            net_2.lock().unwrap().peer.as_ref().unwrap(),
+            &net_1.lock().unwrap().peer_inbox
+        ));
+
+        // An interface can't have two peers.
+        let mut netif_3 = create_netif("id_3", "dev7", "06:00:00:00:00:03");
+        netif_3.peer = Some("id_1".to_string());
+        assert_eq!(
+            net_builder
+                .build(netif_3.clone())
+                .err()
+                .unwrap()
+                .to_string(),
+            NetworkInterfaceError::PeerAlreadyLinked("id_1".to_string()).to_string()
+        );
+        netif_3.peer = Some("id_2".to_string());
+        assert_eq!(
+            net_builder.build(netif_3).err().unwrap().to_string(),
+            NetworkInterfaceError::PeerAlreadyLinked("id_2".to_string()).to_string()
+        );
+        let mut netif_2 = create_netif("id_2", "dev6", "06:00:00:00:00:02");
+        netif_2.peer = Some("id_3".to_string());
+        assert_eq!(
+            net_builder.build(netif_2).err().unwrap().to_string(),
+            NetworkInterfaceError::PeerAlreadyLinked("id_2".to_string()).to_string()
+        );
+        assert_eq!(net_builder.net_devices.len(), 2);
+
+        // Unlinking an interface unlinks its peer too.
+        drop(net_1);
+        let netif_1 = create_netif("id_1", "dev5", "06:00:00:00:00:01");
+        net_builder.build(netif_1).unwrap();
+        assert!(net_2.lock().unwrap().peer.is_none());
+        assert_eq!(net_builder.configs()[0].iface_id, "id_2");
+        assert_eq!(net_builder.configs()[0].peer, None);
+    }
+
+    #[test]
+    fn test_dma_ranges() {
+        let mut net_builder = NetBuilder::new();
+
+        let mut netif = create_netif("id_1", "dev8", "06:00:00:00:00:08");
+        netif.dma_ranges = Some(vec![]);
+        assert_eq!(
+            net_builder.build(netif.clone()).err().unwrap().to_string(),
+            NetworkInterfaceError::DmaRanges(DmaRangesError::Empty).to_string()
+        );
+
+        let ranges = vec![DmaRange {
+            base: 0x1000,
+            size: 0x1000,
+        }];
+        netif.dma_ranges = Some(ranges.clone());
+        let net = net_builder.build(netif).unwrap();
+        assert!(net
+            .lock()
+            .unwrap()
+            .queues()
+            .iter()
+            .all(|q| q.dma_ranges().unwrap().ranges() == ranges.as_slice()));
+        assert_eq!(net_builder.configs()[0].dma_ranges, Some(ranges));
+    }
+
+    #[test]
+    fn test_worker() {
+        let mut net_builder = NetBuilder::new();
+
+        let mut netif_1 = create_netif("id_1", "dev9", "06:00:00:00:00:09");
+        netif_1.worker = true;
+        netif_1.rx_rate_limiter = Some(RateLimiterConfig {
+            bandwidth: Some(TokenBucketConfig {
+                size: 1000,
+                one_time_burst: None,
+                refill_time: 1000,
+            }),
+            ops: None,
+        });
+        assert_eq!(
+            net_builder.build(netif_1).err().unwrap().to_string(),
+            NetworkInterfaceError::WorkerUnsupported("id_1".to_string()).to_string()
+        );
+
+        let mut netif_1 = create_netif("id_1", "dev9", "06:00:00:00:00:09");
+        netif_1.worker = true;
+        let net_1 = net_builder.build(netif_1).unwrap();
+        {
+            let net_1 = net_1.lock().unwrap();
+            assert!(net_1.is_worker());
+            // The control queue is not offered to the guest.
+            assert_eq!(net_1.avail_features() & (1 << VIRTIO_NET_F_CTRL_VQ), 0);
+            assert_ne!(net_1.avail_features() & (1 << VIRTIO_NET_F_MAC), 0);
+        }
+        assert!(net_builder.configs()[0].worker);
+
+        // An interface emulated by a worker can't be the peer of another interface.
+        let mut netif_2 = create_netif("id_2", "dev10", "06:00:00:00:00:0a");
+        netif_2.peer = Some("id_1".to_string());
+        assert_eq!(
+            net_builder.build(netif_2).err().unwrap().to_string(),
+            NetworkInterfaceError::WorkerUnsupported("id_1".to_string()).to_string()
+        );
+    }
+
+    #[test]
+    fn test_dhcp() {
+        let mut net_builder = NetBuilder::new();
+        let config = DhcpConfig {
+            ip_address: "192.168.0.2".parse().unwrap(),
+            prefix_length: 24,
+            gateway: Some("192.168.0.1".parse().unwrap()),
+            nameservers: vec!["192.168.0.1".parse().unwrap()],
+        };
+
+        assert_eq!(
+            net_builder
+                .set_dhcp_config("id_1", config.clone())
+                .err()
+                .unwrap()
+                .to_string(),
+            NetworkInterfaceError::UnknownInterface("id_1".to_string()).to_string()
+        );
+
+        let mut netif = create_netif("id_1", "dev11", "06:00:00:00:00:0b");
+        netif.dhcp = Some(DhcpConfig {
+            prefix_length: 33,
+            ..config.clone()
+        });
+        assert_eq!(
+            net_builder.build(netif.clone()).err().unwrap().to_string(),
+            NetworkInterfaceError::Dhcp(DhcpConfigError::InvalidPrefixLength(33)).to_string()
+        );
+
+        netif.dhcp = None;
+        let net = net_builder.build(netif).unwrap();
+        assert!(net.lock().unwrap().dhcp_config().is_none());
+
+        net_builder.set_dhcp_config("id_1", config.clone()).unwrap();
+        assert_eq!(net.lock().unwrap().dhcp_config(), Some(&config));
+        assert_eq!(net_builder.configs()[0].dhcp, Some(config));
+    }
+
+    #[test]
+    fn test_xdp() {
+        let mut net_builder = NetBuilder::new();
+        let config = XdpConfig {
+            if_name: String::from("lo"),
+            queue_id: 0,
+            xsks_map: String::from("/sys/fs/bpf/firecracker-missing-map"),
+        };
+
+        let mut netif = create_netif("id_1", "dev12", "06:00:00:00:00:0c");
+        netif.xdp = Some(config.clone());
+        netif.worker = true;
+        assert_eq!(
+            net_builder.build(netif.clone()).err().unwrap().to_string(),
+            NetworkInterfaceError::WorkerUnsupported("id_1".to_string()).to_string()
+        );
+
+        // There is no XSKMAP to insert the socket in, so the device keeps using its tap.
+        netif.worker = false;
+        let net = net_builder.build(netif).unwrap();
+        {
+            let net = net.lock().unwrap();
+            assert!(net.xdp_config().is_none());
+            assert_eq!(net.metrics.xdp_fallbacks.count(), 1);
+            assert_ne!(net.avail_features() & (1 << VIRTIO_NET_F_CSUM), 0);
+        }
+        assert_eq!(net_builder.configs()[0].xdp, None);
+    }
 }
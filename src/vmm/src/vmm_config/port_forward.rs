@@ -0,0 +1,111 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use serde::{Deserialize, Serialize};
+
+use crate::devices::virtio::net::port_forward::PortForwardError;
+
+/// Forwards the TCP connections accepted on a host port to a port of the guest.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortForwardConfig {
+    /// ID of the network interface through which the guest is reached.
+    pub iface_id: String,
+    /// Host address on which connections are accepted.
+    #[serde(default = "PortForwardConfig::default_host_address")]
+    pub host_address: Ipv4Addr,
+    /// Host port on which connections are accepted.
+    pub host_port: u16,
+    /// Guest address to which connections are forwarded.
+    pub guest_address: Ipv4Addr,
+    /// Guest port to which connections are forwarded.
+    pub guest_port: u16,
+}
+
+impl PortForwardConfig {
+    fn default_host_address() -> Ipv4Addr {
+        Ipv4Addr::LOCALHOST
+    }
+
+    /// Returns the host address on which connections are accepted.
+    pub fn host_socket_addr(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(self.host_address, self.host_port)
+    }
+
+    /// Returns the guest address to which connections are forwarded.
+    pub fn guest_socket_addr(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(self.guest_address, self.guest_port)
+    }
+
+    /// Checks that the ports and the guest address can be used to forward connections.
+    pub fn validate(&self) -> Result<(), PortForwardConfigError> {
+        if self.host_port == 0 || self.guest_port == 0 {
+            return Err(PortForwardConfigError::InvalidPort);
+        }
+        let addr = self.guest_address;
+        if addr.is_unspecified() || addr.is_broadcast() || addr.is_multicast() {
+            return Err(PortForwardConfigError::InvalidGuestAddress(addr));
+        }
+        Ok(())
+    }
+}
+
+/// Errors associated with the port forwarding configuration.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum PortForwardConfigError {
+    /// Port 0 cannot be forwarded.
+    InvalidPort,
+    /// Invalid guest address: {0}
+    InvalidGuestAddress(Ipv4Addr),
+    /// Host address {0} is forwarded more than once.
+    DuplicateHostAddress(SocketAddrV4),
+    /// No network interface with this ID: {0}
+    UnknownInterface(String),
+    /// Port forwarding is not supported on a network interface emulated by a worker process: {0}
+    WorkerInterface(String),
+    /// Cannot forward the host ports: {0}
+    PortForward(#[from] PortForwardError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_forward_config() {
+        let config: PortForwardConfig = serde_json::from_str(
+            r#"{"iface_id": "eth0", "host_port": 8080, "guest_address": "192.168.0.2", "guest_port": 80}"#,
+        )
+        .unwrap();
+        assert_eq!(config.host_address, Ipv4Addr::LOCALHOST);
+        assert_eq!(
+            config.host_socket_addr(),
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 8080)
+        );
+        assert_eq!(
+            config.guest_socket_addr(),
+            SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 2), 80)
+        );
+        config.validate().unwrap();
+
+        serde_json::from_str::<PortForwardConfig>(
+            r#"{"iface_id": "eth0", "host_port": 8080, "guest_address": "192.168.0.2", "guest_port": 80, "protocol": "udp"}"#,
+        )
+        .unwrap_err();
+
+        let mut bad_config = config.clone();
+        bad_config.guest_port = 0;
+        assert!(matches!(
+            bad_config.validate(),
+            Err(PortForwardConfigError::InvalidPort)
+        ));
+        let mut bad_config = config;
+        bad_config.guest_address = Ipv4Addr::BROADCAST;
+        assert!(matches!(
+            bad_config.validate(),
+            Err(PortForwardConfigError::InvalidGuestAddress(_))
+        ));
+    }
+}
@@ -0,0 +1,165 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::devices::virtio::remote::{RemoteDevice, RemoteDeviceError};
+
+fn default_num_queues() -> u16 {
+    1
+}
+
+fn default_queue_size() -> u16 {
+    256
+}
+
+/// Configuration of a virtio device emulated by a vhost-user backend process.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteDeviceConfig {
+    /// ID of the device.
+    pub id: String,
+    /// Virtio device type exposed to the guest. The types of the devices emulated by Firecracker
+    /// itself are not allowed.
+    pub device_type: u32,
+    /// Path of the Unix domain socket the backend listens on.
+    pub socket: String,
+    /// Number of queues of the device.
+    #[serde(default = "default_num_queues")]
+    pub num_queues: u16,
+    /// Maximum size of the queues of the device.
+    #[serde(default = "default_queue_size")]
+    pub queue_size: u16,
+    /// Size of the config space of the device, fetched from the backend.
+    #[serde(default)]
+    pub config_size: u32,
+}
+
+/// Builder for the list of remote devices.
+#[derive(Debug, Default, Clone)]
+pub struct RemoteDeviceBuilder {
+    devices: Vec<Arc<Mutex<RemoteDevice>>>,
+}
+
+impl RemoteDeviceBuilder {
+    /// Creates an empty list of remote devices.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns an immutable iterator over the remote devices.
+    pub fn iter(&self) -> std::slice::Iter<'_, Arc<Mutex<RemoteDevice>>> {
+        self.devices.iter()
+    }
+
+    /// Whether no remote device is configured.
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// Connects to the backend of a remote device and keeps a reference to the device. A device
+    /// with the same ID is replaced.
+    pub fn build(
+        &mut self,
+        config: RemoteDeviceConfig,
+    ) -> Result<Arc<Mutex<RemoteDevice>>, RemoteDeviceError> {
+        // Disconnect from the backend of the replaced device first, in case the new one shares it.
+        let id = config.id.clone();
+        self.devices
+            .retain(|dev| dev.lock().expect("Poisoned lock").id() != id);
+
+        let device = Arc::new(Mutex::new(RemoteDevice::new(config)?));
+        self.devices.push(device.clone());
+        Ok(device)
+    }
+
+    /// Returns the configurations of the remote devices.
+    pub fn configs(&self) -> Vec<RemoteDeviceConfig> {
+        self.devices
+            .iter()
+            .map(|dev| dev.lock().expect("Poisoned lock").config())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_device_config() {
+        let config: RemoteDeviceConfig =
+            serde_json::from_str(r#"{"id": "console", "device_type": 3, "socket": "/tmp/sock"}"#)
+                .unwrap();
+        assert_eq!(
+            config,
+            RemoteDeviceConfig {
+                id: "console".to_string(),
+                device_type: 3,
+                socket: "/tmp/sock".to_string(),
+                num_queues: 1,
+                queue_size: 256,
+                config_size: 0,
+            }
+        );
+        serde_json::from_str::<RemoteDeviceConfig>(r#"{"id": "console", "device_type": 3}"#)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_remote_device_builder() {
+        let mut builder = RemoteDeviceBuilder::new();
+        let config = RemoteDeviceConfig {
+            id: "console".to_string(),
+            device_type: 3,
+            socket: "/invalid/sock".to_string(),
+            num_queues: 2,
+            queue_size: 256,
+            config_size: 0,
+        };
+
+        assert!(matches!(
+            builder.build(config.clone()).unwrap_err(),
+            RemoteDeviceError::VhostUser(_)
+        ));
+        assert!(matches!(
+            builder
+                .build(RemoteDeviceConfig {
+                    device_type: 1,
+                    ..config.clone()
+                })
+                .unwrap_err(),
+            RemoteDeviceError::DeviceType(1)
+        ));
+        assert!(matches!(
+            builder
+                .build(RemoteDeviceConfig {
+                    num_queues: 0,
+                    ..config.clone()
+                })
+                .unwrap_err(),
+            RemoteDeviceError::NumQueues(0)
+        ));
+        assert!(matches!(
+            builder
+                .build(RemoteDeviceConfig {
+                    queue_size: 100,
+                    ..config.clone()
+                })
+                .unwrap_err(),
+            RemoteDeviceError::QueueSize(100)
+        ));
+        assert!(matches!(
+            builder
+                .build(RemoteDeviceConfig {
+                    config_size: 0x1000,
+                    ..config
+                })
+                .unwrap_err(),
+            RemoteDeviceError::ConfigSize(0x1000)
+        ));
+        assert!(builder.is_empty());
+    }
+}
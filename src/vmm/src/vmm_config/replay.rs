@@ -0,0 +1,49 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Whether the nondeterministic inputs of the devices are recorded or replayed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ReplayMode {
+    /// The inputs are taken from the host and appended to the log.
+    Record,
+    /// The inputs are taken from the log instead of the host.
+    Replay,
+}
+
+/// Configures the record or the replay of the nondeterministic inputs of the devices.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReplayConfig {
+    /// Whether the inputs are recorded or replayed.
+    pub mode: ReplayMode,
+    /// Path of the log the inputs are recorded to, or replayed from.
+    pub log_path: PathBuf,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_config_deserialize() {
+        let config: ReplayConfig =
+            serde_json::from_str(r#"{"mode": "Record", "log_path": "/tmp/replay.log"}"#).unwrap();
+        assert_eq!(
+            config,
+            ReplayConfig {
+                mode: ReplayMode::Record,
+                log_path: PathBuf::from("/tmp/replay.log"),
+            }
+        );
+
+        serde_json::from_str::<ReplayConfig>(
+            r#"{"mode": "Rewind", "log_path": "/tmp/replay.log"}"#,
+        )
+        .unwrap_err();
+        serde_json::from_str::<ReplayConfig>(r#"{"mode": "Replay"}"#).unwrap_err();
+    }
+}
@@ -3,6 +3,7 @@
 
 //! Configurations used in the snapshotting context.
 
+use std::os::unix::io::RawFd;
 use std::path::PathBuf;
 
 /// For crates that depend on `vmm` we export.
@@ -33,6 +34,61 @@ pub enum MemBackendType {
     Uffd,
 }
 
+/// Stores the configuration used for encrypting or decrypting a snapshot.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotEncryptionConfig {
+    /// File descriptor, inherited by Firecracker, from which the 256-bit AES-GCM key is read.
+    pub key_fd: RawFd,
+}
+
+/// New backing file of a block device of a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DriveOverride {
+    /// ID of the block device.
+    pub drive_id: String,
+    /// Path of the disk image, which must be as large as the snapshotted one.
+    pub path_on_host: String,
+}
+
+/// New tap device of a network interface of a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkInterfaceOverride {
+    /// ID of the network interface.
+    pub iface_id: String,
+    /// Name of the tap device.
+    pub host_dev_name: String,
+}
+
+/// New Unix domain socket of a vsock device of a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VsockOverride {
+    /// ID of the vsock device, the one configured through `PUT /vsock` when missing.
+    #[serde(default)]
+    pub vsock_id: Option<String>,
+    /// Path of the Unix domain socket.
+    pub uds_path: String,
+}
+
+/// Backend substitutions applied to the devices of a snapshot before restoring them, so that a
+/// snapshot can be loaded on a host with a different filesystem or network layout.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotOverrides {
+    /// New backing files of block devices.
+    #[serde(default)]
+    pub drives: Vec<DriveOverride>,
+    /// New tap devices of network interfaces.
+    #[serde(default)]
+    pub network_interfaces: Vec<NetworkInterfaceOverride>,
+    /// New Unix domain sockets of vsock devices.
+    #[serde(default)]
+    pub vsock_devices: Vec<VsockOverride>,
+}
+
 /// Stores the configuration that will be used for creating a snapshot.
 #[derive(Debug, PartialEq, Eq, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -45,6 +101,19 @@ pub struct CreateSnapshotParams {
     pub snapshot_path: PathBuf,
     /// Path to the file that will contain the guest memory.
     pub mem_file_path: PathBuf,
+    /// When present, the snapshot files are encrypted with the configured key.
+    #[serde(default)]
+    pub encryption: Option<SnapshotEncryptionConfig>,
+    /// Path to the file that will contain the page hash manifest of the guest memory file.
+    #[serde(default)]
+    pub manifest_path: Option<PathBuf>,
+    /// When set to true, the zero pages of guest memory are left as holes in the memory file.
+    #[serde(default)]
+    pub sparse: bool,
+    /// Snapshot data version of the microVM state file. The default value is the latest version
+    /// supported by this Firecracker.
+    #[serde(default)]
+    pub snapshot_version: Option<Version>,
 }
 
 /// Stores the configuration that will be used for loading a snapshot.
@@ -60,6 +129,16 @@ pub struct LoadSnapshotParams {
     /// When set to true, the vm is also resumed if the snapshot load
     /// is successful.
     pub resume_vm: bool,
+    /// When set to true, the snapshot sections are checked against the
+    /// digests embedded in the snapshot before the microVM is restored.
+    pub verify: bool,
+    /// When present, the snapshot files are decrypted with the configured key.
+    pub encryption: Option<SnapshotEncryptionConfig>,
+    /// When set to true, the snapshot is loaded even if the host CPU lacks
+    /// features of the CPU the snapshot was taken on.
+    pub allow_cpu_mismatch: bool,
+    /// Backend substitutions applied to the devices before restoring them.
+    pub overrides: SnapshotOverrides,
 }
 
 /// Stores the configuration for loading a snapshot that is provided by the user.
@@ -82,6 +161,18 @@ pub struct LoadSnapshotConfig {
     /// Whether or not to resume the vm post snapshot load.
     #[serde(default)]
     pub resume_vm: bool,
+    /// Whether or not to verify the snapshot integrity before loading it.
+    #[serde(default)]
+    pub verify: bool,
+    /// Key configuration for loading an encrypted snapshot.
+    #[serde(default)]
+    pub encryption: Option<SnapshotEncryptionConfig>,
+    /// Whether or not to load the snapshot on a host CPU incompatible with the snapshot one.
+    #[serde(default)]
+    pub allow_cpu_mismatch: bool,
+    /// Backend substitutions applied to the devices before restoring them.
+    #[serde(default)]
+    pub overrides: SnapshotOverrides,
 }
 
 /// Stores the configuration used for managing snapshot memory.
@@ -109,4 +200,34 @@ pub enum VmState {
 pub struct Vm {
     /// The microVM state, which can be `paused` or `resumed`.
     pub state: VmState,
+    /// Whether pausing the microVM also stops the devices from accessing the guest memory.
+    #[serde(default)]
+    pub quiesce_devices: bool,
+    /// How the guest clock is handled when resuming the microVM.
+    #[serde(default)]
+    pub resume_time_handling: Option<ResumeTimeHandling>,
+}
+
+/// How the guest clock is handled when resuming the microVM.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResumeTimeHandling {
+    /// The guest clock is left untouched. After a snapshot is loaded, the guest clock resumes
+    /// from its value when the snapshot was created.
+    #[default]
+    None,
+    /// The guest clock is advanced by the host time elapsed since the vCPUs were paused, or
+    /// since the snapshot was created, so that the wall time of the guest doesn't lag behind.
+    Adjust,
+}
+
+/// What pausing the microVM stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseMode {
+    /// Only the vCPUs are paused. The devices can still complete the requests already
+    /// submitted by the guest, and write to the guest memory.
+    Vcpus,
+    /// The devices are also stopped from accessing the guest memory, which makes it
+    /// consistent for an external capture until the microVM is resumed.
+    VcpusAndDevices,
 }
@@ -0,0 +1,83 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::os::unix::fs::FileTypeExt;
+
+use serde::{Deserialize, Serialize};
+
+/// This struct represents the strongly typed equivalent of the json body from TPM device
+/// related requests.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TpmConfig {
+    /// Path of the data socket of the `swtpm` process executing the commands of the guest.
+    pub socket: String,
+}
+
+/// Errors that can occur while handling configuration for a TPM device
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum TpmConfigError {
+    /// TPM devices are only supported on x86_64.
+    UnsupportedArch,
+    /// Cannot access the swtpm socket {0}: {1}
+    Socket(String, std::io::Error),
+    /// {0} is not a socket.
+    NotASocket(String),
+}
+
+impl TpmConfig {
+    /// Checks that the TPM device can be attached, with its socket ready to be connected to.
+    pub fn validate(&self) -> Result<(), TpmConfigError> {
+        if cfg!(not(target_arch = "x86_64")) {
+            return Err(TpmConfigError::UnsupportedArch);
+        }
+
+        let metadata = std::fs::metadata(&self.socket)
+            .map_err(|err| TpmConfigError::Socket(self.socket.clone(), err))?;
+        if !metadata.file_type().is_socket() {
+            return Err(TpmConfigError::NotASocket(self.socket.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use utils::tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let dir = TempDir::new().unwrap();
+        let socket = dir.as_path().join("swtpm.sock");
+        let config = TpmConfig {
+            socket: socket.to_str().unwrap().to_string(),
+        };
+        if cfg!(not(target_arch = "x86_64")) {
+            assert!(matches!(
+                config.validate(),
+                Err(TpmConfigError::UnsupportedArch)
+            ));
+            return;
+        }
+
+        assert!(matches!(
+            config.validate(),
+            Err(TpmConfigError::Socket(_, _))
+        ));
+
+        let _listener = UnixListener::bind(&socket).unwrap();
+        config.validate().unwrap();
+
+        let config = TpmConfig {
+            socket: dir.as_path().to_str().unwrap().to_string(),
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(TpmConfigError::NotASocket(_))
+        ));
+    }
+}
@@ -0,0 +1,16 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Configurations used for extracting the guest memory of a crashed microVM.
+
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+/// Stores the configuration that will be used for creating a vmcore file.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateVmcoreParams {
+    /// Path to the ELF core file that will contain the guest memory.
+    pub vmcore_path: PathBuf,
+}
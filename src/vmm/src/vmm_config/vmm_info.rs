@@ -0,0 +1,164 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::OnceLock;
+
+use serde::Serialize;
+
+const PROC_SELF_CGROUP: &str = "/proc/self/cgroup";
+const PROC_SELF_FD: &str = "/proc/self/fd";
+const PROC_SELF_STATUS: &str = "/proc/self/status";
+
+/// Procfs entries read when collecting the [`VmmInfo`].
+pub const PROC_SELF_ENTRIES: [&str; 3] = [PROC_SELF_CGROUP, PROC_SELF_FD, PROC_SELF_STATUS];
+
+static PROCESS_DETAILS: OnceLock<ProcessDetails> = OnceLock::new();
+
+/// Seccomp filters installed on the threads of the VMM process.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeccompLevel {
+    /// No filters.
+    #[default]
+    None,
+    /// The default filters.
+    Default,
+    /// User provided filters.
+    Custom,
+}
+
+/// Details about the VMM process known when it starts.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProcessDetails {
+    /// Time at which the process started, in milliseconds since the Unix epoch.
+    pub start_time_utc_ms: u64,
+    /// Seccomp filters installed on the threads of the process.
+    pub seccomp_level: SeccompLevel,
+    /// Whether the process was started by the jailer.
+    pub jailed: bool,
+}
+
+/// Records the details of the VMM process reported in the [`VmmInfo`]. Only the first call has
+/// an effect.
+pub fn set_process_details(details: ProcessDetails) {
+    let _ = PROCESS_DETAILS.set(details);
+}
+
+/// Information about the VMM process.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct VmmInfo {
+    /// Process ID.
+    pub pid: u32,
+    /// Time at which the process started, in milliseconds since the Unix epoch.
+    pub start_time_utc_ms: u64,
+    /// Path of the cgroup of the process, relative to the cgroup hierarchy root.
+    pub cgroup: Option<String>,
+    /// Seccomp filters installed on the threads of the process.
+    pub seccomp_level: SeccompLevel,
+    /// Number of open file descriptors.
+    pub open_fds: Option<u64>,
+    /// Resident set size, in KiB.
+    pub rss_kib: Option<u64>,
+    /// Peak resident set size, in KiB.
+    pub max_rss_kib: u64,
+    /// Whether the process was started by the jailer.
+    pub jailed: bool,
+}
+
+impl VmmInfo {
+    /// Collects the information about the calling process. The fields read from procfs are
+    /// `None` when it is not mounted, which is the case inside the jail by default.
+    pub fn collect() -> Self {
+        let details = PROCESS_DETAILS.get().cloned().unwrap_or_default();
+        VmmInfo {
+            pid: std::process::id(),
+            start_time_utc_ms: details.start_time_utc_ms,
+            cgroup: std::fs::read_to_string(PROC_SELF_CGROUP)
+                .ok()
+                .and_then(|cgroups| parse_cgroup(&cgroups)),
+            seccomp_level: details.seccomp_level,
+            // The directory being listed is open too, so don't count it.
+            open_fds: std::fs::read_dir(PROC_SELF_FD)
+                .ok()
+                .map(|entries| (entries.count() as u64).saturating_sub(1)),
+            rss_kib: std::fs::read_to_string(PROC_SELF_STATUS)
+                .ok()
+                .and_then(|status| parse_status_kib(&status, "VmRSS")),
+            max_rss_kib: max_rss_kib(),
+            jailed: details.jailed,
+        }
+    }
+}
+
+// Returns the path in the cgroup v2 hierarchy, or in the first cgroup v1 hierarchy.
+fn parse_cgroup(cgroups: &str) -> Option<String> {
+    let paths = cgroups
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2).map(|path| (line, path)));
+    let mut first = None;
+    for (line, path) in paths {
+        if line.starts_with("0::") {
+            return Some(path.to_string());
+        }
+        first.get_or_insert(path);
+    }
+    first.map(str::to_string)
+}
+
+fn parse_status_kib(status: &str, field: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+        .and_then(|value| value.trim().strip_suffix("kB"))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+fn max_rss_kib() -> u64 {
+    // SAFETY: `rusage` is a plain old data struct, for which all zeroes is a valid value.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    // SAFETY: The pointer is valid for the duration of the call. This can't fail with valid
+    // arguments.
+    unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+    u64::try_from(usage.ru_maxrss).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cgroup() {
+        assert_eq!(
+            parse_cgroup("0::/firecracker/vm0\n").as_deref(),
+            Some("/firecracker/vm0")
+        );
+        assert_eq!(
+            parse_cgroup("12:cpu,cpuacct:/firecracker/vm0\n11:memory:/firecracker/vm1\n")
+                .as_deref(),
+            Some("/firecracker/vm0")
+        );
+        assert_eq!(
+            parse_cgroup("1:name=systemd:/\n0::/firecracker/vm0\n").as_deref(),
+            Some("/firecracker/vm0")
+        );
+        assert_eq!(parse_cgroup(""), None);
+    }
+
+    #[test]
+    fn test_parse_status_kib() {
+        let status = "Name:\tfirecracker\nVmHWM:\t    2048 kB\nVmRSS:\t    1024 kB\n";
+        assert_eq!(parse_status_kib(status, "VmRSS"), Some(1024));
+        assert_eq!(parse_status_kib(status, "VmHWM"), Some(2048));
+        assert_eq!(parse_status_kib(status, "VmSwap"), None);
+    }
+
+    #[test]
+    fn test_collect() {
+        let info = VmmInfo::collect();
+        assert_eq!(info.pid, std::process::id());
+        assert!(info.cgroup.is_some());
+        assert!(info.open_fds.unwrap() >= 3);
+        assert!(info.rss_kib.unwrap() > 0);
+        assert!(info.max_rss_kib > 0);
+    }
+}
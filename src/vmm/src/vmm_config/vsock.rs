@@ -6,7 +6,12 @@ use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 
-use crate::devices::virtio::vsock::{Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError};
+use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::dma::{DmaRange, DmaRanges, DmaRangesError};
+use crate::devices::virtio::vsock::{
+    Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError, VSOCK_DEV_ID,
+};
+use crate::vmm_config::device_id::DeviceIdError;
 
 type MutexVsockUnix = Arc<Mutex<Vsock<VsockUnixBackend>>>;
 
@@ -17,6 +22,16 @@ pub enum VsockConfigError {
     CreateVsockBackend(VsockUnixBackendError),
     /// Cannot create vsock device: {0}
     CreateVsockDevice(VsockError),
+    /// Invalid DMA ranges: {0}
+    DmaRanges(DmaRangesError),
+    /// Invalid vsock ID: {0}
+    DeviceId(DeviceIdError),
+    /// The guest CID {0} is already used by another vsock device.
+    #[from(ignore)]
+    DuplicateCid(u32),
+    /// The unix socket {0} is already used by another vsock device.
+    #[from(ignore)]
+    DuplicateUdsPath(String),
 }
 
 /// This struct represents the strongly typed equivalent of the json body
@@ -26,15 +41,26 @@ pub enum VsockConfigError {
 pub struct VsockDeviceConfig {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    /// ID of the vsock device.
+    /// ID of the vsock device. The default vsock device is used if missing.
     pub vsock_id: Option<String>,
     /// A 32-bit Context Identifier (CID) used to identify the guest.
     pub guest_cid: u32,
     /// Path to local unix socket.
     pub uds_path: String,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Guest memory ranges the device is allowed to access. Unrestricted if missing.
+    pub dma_ranges: Option<Vec<DmaRange>>,
+}
+
+impl VsockDeviceConfig {
+    /// ID of the vsock device the config is for.
+    pub fn id(&self) -> &str {
+        self.vsock_id.as_deref().unwrap_or(VSOCK_DEV_ID)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct VsockAndUnixPath {
     vsock: MutexVsockUnix,
     uds_path: String,
@@ -44,28 +70,31 @@ impl From<&VsockAndUnixPath> for VsockDeviceConfig {
     fn from(vsock: &VsockAndUnixPath) -> Self {
         let vsock_lock = vsock.vsock.lock().unwrap();
         VsockDeviceConfig {
-            vsock_id: None,
+            vsock_id: Some(vsock_lock.id())
+                .filter(|id| *id != VSOCK_DEV_ID)
+                .map(str::to_owned),
             guest_cid: u32::try_from(vsock_lock.cid()).unwrap(),
             uds_path: vsock.uds_path.clone(),
+            dma_ranges: vsock_lock.dma_ranges(),
         }
     }
 }
 
-/// A builder of Vsock with Unix backend from 'VsockDeviceConfig'.
-#[derive(Debug, Default)]
+/// A builder of the Vsock devices with Unix backend from 'VsockDeviceConfig'.
+#[derive(Debug, Default, Clone)]
 pub struct VsockBuilder {
-    inner: Option<VsockAndUnixPath>,
+    inner: Vec<VsockAndUnixPath>,
 }
 
 impl VsockBuilder {
     /// Creates an empty Vsock with Unix backend Store.
     pub fn new() -> Self {
-        Self { inner: None }
+        Self { inner: Vec::new() }
     }
 
     /// Inserts an existing vsock device.
     pub fn set_device(&mut self, device: Arc<Mutex<Vsock<VsockUnixBackend>>>) {
-        self.inner = Some(VsockAndUnixPath {
+        self.inner.push(VsockAndUnixPath {
             uds_path: device
                 .lock()
                 .expect("Poisoned lock")
@@ -77,36 +106,67 @@ impl VsockBuilder {
     }
 
     /// Inserts a Unix backend Vsock in the store.
-    /// If an entry already exists, it will overwrite it.
+    /// If an entry with the same ID already exists, it will overwrite it.
     pub fn insert(&mut self, cfg: VsockDeviceConfig) -> Result<(), VsockConfigError> {
+        let mut existing = None;
+        for (index, entry) in self.inner.iter().enumerate() {
+            let vsock = entry.vsock.lock().expect("Poisoned lock");
+            if vsock.id() == cfg.id() {
+                existing = Some(index);
+            } else if vsock.cid() == u64::from(cfg.guest_cid) {
+                return Err(VsockConfigError::DuplicateCid(cfg.guest_cid));
+            } else if entry.uds_path == cfg.uds_path {
+                return Err(VsockConfigError::DuplicateUdsPath(cfg.uds_path));
+            }
+        }
+
         // Make sure to drop the old one and remove the socket before creating a new one.
-        if let Some(existing) = self.inner.take() {
+        if let Some(index) = existing {
+            let existing = self.inner.remove(index);
             std::fs::remove_file(existing.uds_path).map_err(VsockUnixBackendError::UnixBind)?;
         }
-        self.inner = Some(VsockAndUnixPath {
+        self.inner.push(VsockAndUnixPath {
             uds_path: cfg.uds_path.clone(),
             vsock: Arc::new(Mutex::new(Self::create_unixsock_vsock(cfg)?)),
         });
         Ok(())
     }
 
-    /// Provides a reference to the Vsock if present.
-    pub fn get(&self) -> Option<&MutexVsockUnix> {
-        self.inner.as_ref().map(|pair| &pair.vsock)
+    /// Returns an iterator over the vsock devices.
+    pub fn iter(&self) -> impl Iterator<Item = &MutexVsockUnix> {
+        self.inner.iter().map(|pair| &pair.vsock)
     }
 
     /// Creates a Vsock device from a VsockDeviceConfig.
     pub fn create_unixsock_vsock(
         cfg: VsockDeviceConfig,
     ) -> Result<Vsock<VsockUnixBackend>, VsockConfigError> {
+        let dma_ranges = cfg
+            .dma_ranges
+            .as_deref()
+            .map(DmaRanges::new)
+            .transpose()?
+            .map(Arc::new);
+        let id = cfg.id().to_owned();
         let backend = VsockUnixBackend::new(u64::from(cfg.guest_cid), cfg.uds_path)?;
 
-        Vsock::new(u64::from(cfg.guest_cid), backend).map_err(VsockConfigError::CreateVsockDevice)
+        let mut vsock = Vsock::new(u64::from(cfg.guest_cid), backend)
+            .map_err(VsockConfigError::CreateVsockDevice)?;
+        vsock.set_id(id);
+        vsock.set_dma_ranges(dma_ranges);
+        Ok(vsock)
     }
 
-    /// Returns the structure used to configure the vsock device.
+    /// Returns the structure used to configure the default vsock device.
     pub fn config(&self) -> Option<VsockDeviceConfig> {
-        self.inner.as_ref().map(VsockDeviceConfig::from)
+        self.configs()
+            .into_iter()
+            .find(|cfg| cfg.vsock_id.is_none())
+    }
+
+    /// Returns the structures used to configure the vsock devices.
+    pub fn configs(&self) -> Vec<VsockDeviceConfig> {
+        self.inner.iter().map(VsockDeviceConfig::from).collect()
     }
 }
 
@@ -115,13 +175,13 @@ pub(crate) mod tests {
     use utils::tempfile::TempFile;
 
     use super::*;
-    use crate::devices::virtio::vsock::VSOCK_DEV_ID;
 
     pub(crate) fn default_config(tmp_sock_file: &TempFile) -> VsockDeviceConfig {
         VsockDeviceConfig {
             vsock_id: None,
             guest_cid: 3,
             uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+            dma_ranges: None,
         }
     }
 
@@ -141,14 +201,42 @@ pub(crate) mod tests {
         let mut vsock_config = default_config(&tmp_sock_file);
 
         store.insert(vsock_config.clone()).unwrap();
-        let vsock = store.get().unwrap();
+        let vsock = store.iter().next().unwrap();
         assert_eq!(vsock.lock().unwrap().id(), VSOCK_DEV_ID);
 
         let new_cid = vsock_config.guest_cid + 1;
         vsock_config.guest_cid = new_cid;
-        store.insert(vsock_config).unwrap();
-        let vsock = store.get().unwrap();
+        store.insert(vsock_config.clone()).unwrap();
+        assert_eq!(store.iter().count(), 1);
+        let vsock = store.iter().next().unwrap();
         assert_eq!(vsock.lock().unwrap().cid(), u64::from(new_cid));
+
+        // Another device needs its own CID and socket.
+        let mut other_sock_file = TempFile::new().unwrap();
+        other_sock_file.remove().unwrap();
+        let mut other_config = default_config(&other_sock_file);
+        other_config.vsock_id = Some("vsock1".to_string());
+        other_config.guest_cid = new_cid;
+        assert!(matches!(
+            store.insert(other_config.clone()),
+            Err(VsockConfigError::DuplicateCid(cid)) if cid == new_cid
+        ));
+        other_config.guest_cid = new_cid + 1;
+        other_config.uds_path = vsock_config.uds_path.clone();
+        assert!(matches!(
+            store.insert(other_config.clone()),
+            Err(VsockConfigError::DuplicateUdsPath(_))
+        ));
+        other_config.uds_path = other_sock_file.as_path().to_str().unwrap().to_string();
+        store.insert(other_config.clone()).unwrap();
+
+        let ids = store
+            .iter()
+            .map(|vsock| vsock.lock().unwrap().id().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [VSOCK_DEV_ID, "vsock1"]);
+        assert_eq!(store.configs(), [vsock_config.clone(), other_config]);
+        assert_eq!(store.config().unwrap(), vsock_config);
     }
 
     #[test]
@@ -162,6 +250,24 @@ pub(crate) mod tests {
         let config = vsock_builder.config();
         assert!(config.is_some());
         assert_eq!(config.unwrap(), vsock_config);
+
+        let mut vsock_config = default_config(&tmp_sock_file);
+        vsock_config.dma_ranges = Some(vec![DmaRange {
+            base: 0x1000,
+            size: 0,
+        }]);
+        assert!(matches!(
+            vsock_builder.insert(vsock_config.clone()),
+            Err(VsockConfigError::DmaRanges(DmaRangesError::InvalidRange(
+                0x1000, 0
+            )))
+        ));
+        vsock_config.dma_ranges = Some(vec![DmaRange {
+            base: 0x1000,
+            size: 0x1000,
+        }]);
+        vsock_builder.insert(vsock_config.clone()).unwrap();
+        assert_eq!(vsock_builder.config().unwrap(), vsock_config);
     }
 
     #[test]
@@ -177,9 +283,9 @@ pub(crate) mod tests {
         .unwrap();
 
         vsock_builder.set_device(Arc::new(Mutex::new(vsock)));
-        assert!(vsock_builder.inner.is_some());
+        assert_eq!(vsock_builder.inner.len(), 1);
         assert_eq!(
-            vsock_builder.inner.unwrap().uds_path,
+            vsock_builder.inner[0].uds_path,
             tmp_sock_file.as_path().to_str().unwrap().to_string()
         )
     }
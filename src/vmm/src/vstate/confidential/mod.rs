@@ -0,0 +1,242 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::Debug;
+
+use kvm_ioctls::VmFd;
+use serde::{Deserialize, Serialize};
+
+/// AMD SEV-SNP support.
+#[cfg(all(target_arch = "x86_64", feature = "sev-snp"))]
+mod sev_snp;
+
+/// Technologies protecting the memory and state of a guest from the host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConfidentialTechnology {
+    /// AMD Secure Encrypted Virtualization with Secure Nested Paging.
+    #[serde(rename = "SEV-SNP")]
+    SevSnp,
+}
+
+impl ConfidentialTechnology {
+    /// Whether this build of Firecracker supports the technology.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            ConfidentialTechnology::SevSnp => {
+                cfg!(all(target_arch = "x86_64", feature = "sev-snp"))
+            }
+        }
+    }
+}
+
+/// Confidential computing configuration of a microVM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfidentialConfig {
+    /// Technology protecting the guest.
+    pub technology: ConfidentialTechnology,
+    /// Guest policy enforced by the secure processor.
+    #[serde(default)]
+    pub policy: u64,
+}
+
+impl ConfidentialConfig {
+    /// Creates the confidential context of a microVM with this configuration.
+    pub fn create(&self) -> Result<Box<dyn ConfidentialVm>, ConfidentialError> {
+        match self.technology {
+            #[cfg(all(target_arch = "x86_64", feature = "sev-snp"))]
+            ConfidentialTechnology::SevSnp => Ok(Box::new(sev_snp::SevSnp::new(self.policy)?)),
+            #[allow(unreachable_patterns)]
+            technology => Err(ConfidentialError::NotSupported(technology)),
+        }
+    }
+}
+
+/// Errors associated with confidential microVMs.
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum ConfidentialError {
+    /// {0:?} is not supported by this build of Firecracker.
+    NotSupported(ConfidentialTechnology),
+    /// Cannot open the secure processor device: {0}
+    OpenDevice(i32),
+    /// The secure processor failed command {0}: {1}, firmware error {2:#x}
+    Command(u32, kvm_ioctls::Error, u32),
+    /// Cannot duplicate the VM file descriptor: {0}
+    DuplicateVm(i32),
+    /// Cannot enable the hypercall exits: {0}
+    EnableHypercallExit(kvm_ioctls::Error),
+    /// Cannot create the private memory of a guest memory region: {0}
+    CreateGuestMemfd(kvm_ioctls::Error),
+    /// Cannot set a guest memory region: {0}
+    SetMemoryRegion(kvm_ioctls::Error),
+    /// Cannot set the attributes of the guest memory: {0}
+    SetMemoryAttributes(kvm_ioctls::Error),
+}
+
+/// Number of the hypercall through which a guest converts its memory between private and shared
+/// (`include/uapi/linux/kvm_para.h`).
+pub const KVM_HC_MAP_GPA_RANGE: u64 = 12;
+/// Attribute of the `KVM_HC_MAP_GPA_RANGE` hypercall converting the memory to private.
+pub const KVM_MAP_GPA_RANGE_ENCRYPTED: u64 = 1 << 4;
+
+/// The confidential context of a VM, driving the secure processor through the launch of the
+/// guest.
+///
+/// The launch goes through these steps:
+/// 1. [`init`](ConfidentialVm::init), right after the VM is created;
+/// 2. [`set_memory_region`](ConfidentialVm::set_memory_region), for every guest memory region,
+///    instead of the regular KVM memory slots;
+/// 3. [`launch_update`](ConfidentialVm::launch_update), for every range of the initial guest
+///    payload once it is loaded, encrypting it in place and extending the launch measurement with
+///    it;
+/// 4. [`launch_finish`](ConfidentialVm::launch_finish), once the vCPUs are configured. The guest
+///    memory and state can't be accessed by the host from then on.
+///
+/// The running guest then converts its memory between private and shared through
+/// [`map_gpa_range`](ConfidentialVm::map_gpa_range), to share buffers with the devices.
+pub trait ConfidentialVm: Debug + Send + Sync {
+    /// Technology protecting the guest.
+    fn technology(&self) -> ConfidentialTechnology;
+
+    /// KVM type of the VM to create.
+    fn vm_type(&self) -> u64;
+
+    /// Initializes the confidential context of the VM.
+    fn init(&self, vm_fd: &VmFd) -> Result<(), ConfidentialError>;
+
+    /// Sets the memory slot `slot` of the guest memory region at `guest_addr`, whose shared
+    /// memory is mapped at `host_addr`. The whole region starts as private memory.
+    fn set_memory_region(
+        &self,
+        vm_fd: &VmFd,
+        slot: u32,
+        guest_addr: u64,
+        host_addr: u64,
+        size: u64,
+    ) -> Result<(), ConfidentialError>;
+
+    /// Encrypts the guest memory at `guest_addr`, mapped at `host_addr`, and extends the launch
+    /// measurement with it.
+    fn launch_update(
+        &self,
+        vm_fd: &VmFd,
+        guest_addr: u64,
+        host_addr: u64,
+        size: u64,
+    ) -> Result<(), ConfidentialError>;
+
+    /// Completes the launch of the guest.
+    fn launch_finish(&self, vm_fd: &VmFd) -> Result<(), ConfidentialError>;
+
+    /// Converts the guest memory at `guest_addr` to private memory if `private` is set, and to
+    /// shared memory otherwise. Called from the vCPU threads.
+    fn map_gpa_range(
+        &self,
+        guest_addr: u64,
+        size: u64,
+        private: bool,
+    ) -> Result<(), ConfidentialError>;
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Calls made to a [`MockConfidentialVm`].
+    #[derive(Debug, PartialEq, Eq)]
+    pub(crate) enum MockCall {
+        SetMemoryRegion(u32, u64, u64),
+        LaunchUpdate(u64, u64),
+        LaunchFinish,
+        MapGpaRange(u64, u64, bool),
+    }
+
+    /// Confidential context of a regular VM, recording the calls made to it.
+    #[derive(Debug, Default)]
+    pub(crate) struct MockConfidentialVm {
+        pub(crate) calls: Arc<Mutex<Vec<MockCall>>>,
+    }
+
+    impl MockConfidentialVm {
+        fn record(&self, call: MockCall) -> Result<(), ConfidentialError> {
+            self.calls.lock().unwrap().push(call);
+            Ok(())
+        }
+    }
+
+    impl ConfidentialVm for MockConfidentialVm {
+        fn technology(&self) -> ConfidentialTechnology {
+            ConfidentialTechnology::SevSnp
+        }
+
+        fn vm_type(&self) -> u64 {
+            0
+        }
+
+        fn init(&self, _vm_fd: &VmFd) -> Result<(), ConfidentialError> {
+            Ok(())
+        }
+
+        fn set_memory_region(
+            &self,
+            _vm_fd: &VmFd,
+            slot: u32,
+            guest_addr: u64,
+            _host_addr: u64,
+            size: u64,
+        ) -> Result<(), ConfidentialError> {
+            self.record(MockCall::SetMemoryRegion(slot, guest_addr, size))
+        }
+
+        fn launch_update(
+            &self,
+            _vm_fd: &VmFd,
+            guest_addr: u64,
+            _host_addr: u64,
+            size: u64,
+        ) -> Result<(), ConfidentialError> {
+            self.record(MockCall::LaunchUpdate(guest_addr, size))
+        }
+
+        fn launch_finish(&self, _vm_fd: &VmFd) -> Result<(), ConfidentialError> {
+            self.record(MockCall::LaunchFinish)
+        }
+
+        fn map_gpa_range(
+            &self,
+            guest_addr: u64,
+            size: u64,
+            private: bool,
+        ) -> Result<(), ConfidentialError> {
+            self.record(MockCall::MapGpaRange(guest_addr, size, private))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confidential_config() {
+        let config: ConfidentialConfig =
+            serde_json::from_str(r#"{"technology": "SEV-SNP", "policy": 196608}"#).unwrap();
+        assert_eq!(
+            config,
+            ConfidentialConfig {
+                technology: ConfidentialTechnology::SevSnp,
+                policy: 0x30000,
+            }
+        );
+        serde_json::from_str::<ConfidentialConfig>(r#"{"technology": "TDX"}"#).unwrap_err();
+
+        if !config.technology.is_supported() {
+            assert_eq!(
+                config.create().unwrap_err(),
+                ConfidentialError::NotSupported(ConfidentialTechnology::SevSnp)
+            );
+        }
+    }
+}
@@ -0,0 +1,344 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::{File, OpenOptions};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::sync::OnceLock;
+
+use kvm_bindings::{kvm_enable_cap, kvm_sev_cmd, KVMIO, KVM_CAP_EXIT_HYPERCALL};
+use kvm_ioctls::VmFd;
+use utils::ioctl::ioctl_with_ref;
+use utils::{ioctl_ioc_nr, ioctl_iow_nr, ioctl_iowr_nr};
+
+use super::{ConfidentialError, ConfidentialTechnology, ConfidentialVm, KVM_HC_MAP_GPA_RANGE};
+
+// The SEV-SNP definitions of the KVM API (`arch/x86/include/uapi/asm/kvm.h`), which are not
+// exported by `kvm-bindings` yet.
+const KVM_X86_SNP_VM: u64 = 4;
+const KVM_SEV_INIT2: u32 = 22;
+const KVM_SEV_SNP_LAUNCH_START: u32 = 100;
+const KVM_SEV_SNP_LAUNCH_UPDATE: u32 = 101;
+const KVM_SEV_SNP_LAUNCH_FINISH: u32 = 102;
+const KVM_SEV_SNP_PAGE_TYPE_NORMAL: u8 = 0x1;
+
+// The private memory definitions of the KVM API (`include/uapi/linux/kvm.h`), which are not
+// exported by `kvm-bindings` yet.
+const KVM_MEM_GUEST_MEMFD: u32 = 1 << 2;
+const KVM_MEMORY_ATTRIBUTE_PRIVATE: u64 = 1 << 3;
+ioctl_iowr_nr!(KVM_CREATE_GUEST_MEMFD, KVMIO, 0xd4, kvm_create_guest_memfd);
+ioctl_iow_nr!(
+    KVM_SET_USER_MEMORY_REGION2,
+    KVMIO,
+    0x49,
+    kvm_userspace_memory_region2
+);
+ioctl_iow_nr!(
+    KVM_SET_MEMORY_ATTRIBUTES,
+    KVMIO,
+    0xd2,
+    kvm_memory_attributes
+);
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default)]
+struct kvm_create_guest_memfd {
+    size: u64,
+    flags: u64,
+    reserved: [u64; 6],
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default)]
+struct kvm_userspace_memory_region2 {
+    slot: u32,
+    flags: u32,
+    guest_phys_addr: u64,
+    memory_size: u64,
+    userspace_addr: u64,
+    guest_memfd_offset: u64,
+    guest_memfd: u32,
+    pad1: u32,
+    pad2: [u64; 14],
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default)]
+struct kvm_memory_attributes {
+    address: u64,
+    size: u64,
+    attributes: u64,
+    flags: u64,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default)]
+struct kvm_sev_init {
+    vmsa_features: u64,
+    flags: u32,
+    ghcb_version: u16,
+    pad1: u16,
+    pad2: [u32; 8],
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default)]
+struct kvm_sev_snp_launch_start {
+    policy: u64,
+    gosvw: [u8; 16],
+    flags: u16,
+    pad0: [u8; 6],
+    pad1: [u64; 4],
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default)]
+struct kvm_sev_snp_launch_update {
+    gfn_start: u64,
+    uaddr: u64,
+    len: u64,
+    type_: u8,
+    pad0: u8,
+    flags: u16,
+    pad1: u32,
+    pad2: [u64; 4],
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default)]
+struct kvm_sev_snp_launch_finish {
+    id_block_uaddr: u64,
+    id_auth_uaddr: u64,
+    id_block_en: u8,
+    auth_key_en: u8,
+    vcek_disabled: u8,
+    host_data: [u8; 32],
+    pad0: [u8; 3],
+    flags: u16,
+    pad1: [u64; 4],
+}
+
+const SEV_DEVICE_PATH: &str = "/dev/sev";
+const GUEST_PAGE_SHIFT: u64 = 12;
+
+/// Confidential context of an AMD SEV-SNP guest.
+#[derive(Debug)]
+pub struct SevSnp {
+    sev: File,
+    policy: u64,
+    // Duplicate of the VM file descriptor, through which the vCPU threads convert the guest
+    // memory. Set by `init`.
+    vm: OnceLock<File>,
+}
+
+impl SevSnp {
+    /// Opens the secure processor device for a guest with the given policy.
+    pub fn new(policy: u64) -> Result<Self, ConfidentialError> {
+        let sev = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(SEV_DEVICE_PATH)
+            .map_err(|err| ConfidentialError::OpenDevice(err.raw_os_error().unwrap_or(0)))?;
+        Ok(SevSnp {
+            sev,
+            policy,
+            vm: OnceLock::new(),
+        })
+    }
+
+    fn command<T>(&self, vm_fd: &VmFd, id: u32, data: &mut T) -> Result<(), ConfidentialError> {
+        let mut cmd = kvm_sev_cmd {
+            id,
+            data: data as *mut T as u64,
+            error: 0,
+            // The file descriptor was opened above, so it is non-negative.
+            sev_fd: u32::try_from(self.sev.as_raw_fd()).unwrap(),
+        };
+        vm_fd
+            .encrypt_op_sev(&mut cmd)
+            .map_err(|err| ConfidentialError::Command(id, err, cmd.error))
+    }
+}
+
+fn set_memory_attributes<F: AsRawFd>(
+    vm: &F,
+    guest_addr: u64,
+    size: u64,
+    private: bool,
+) -> Result<(), ConfidentialError> {
+    let attributes = kvm_memory_attributes {
+        address: guest_addr,
+        size,
+        attributes: if private {
+            KVM_MEMORY_ATTRIBUTE_PRIVATE
+        } else {
+            0
+        },
+        flags: 0,
+    };
+    // SAFETY: Safe because the fd is a valid VM file descriptor, the structure matches the
+    // layout expected by KVM and we check the return value.
+    if unsafe { ioctl_with_ref(vm, KVM_SET_MEMORY_ATTRIBUTES(), &attributes) } < 0 {
+        return Err(ConfidentialError::SetMemoryAttributes(
+            kvm_ioctls::Error::last(),
+        ));
+    }
+    Ok(())
+}
+
+impl ConfidentialVm for SevSnp {
+    fn technology(&self) -> ConfidentialTechnology {
+        ConfidentialTechnology::SevSnp
+    }
+
+    fn vm_type(&self) -> u64 {
+        KVM_X86_SNP_VM
+    }
+
+    fn init(&self, vm_fd: &VmFd) -> Result<(), ConfidentialError> {
+        // SAFETY: Safe because the fd is a valid VM file descriptor and we check the return value.
+        let vm = unsafe { libc::fcntl(vm_fd.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 0) };
+        if vm < 0 {
+            return Err(ConfidentialError::DuplicateVm(
+                std::io::Error::last_os_error().raw_os_error().unwrap_or(0),
+            ));
+        }
+        // SAFETY: The file descriptor was just duplicated, so it is valid and owned by nothing
+        // else.
+        let vm = unsafe { File::from_raw_fd(vm) };
+        // `init` is only called once, right after the VM is created.
+        let _ = self.vm.set(vm);
+
+        // The guest asks for the conversions of its memory between private and shared through
+        // the `KVM_HC_MAP_GPA_RANGE` hypercall, which KVM forwards to the vCPU threads.
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_EXIT_HYPERCALL,
+            ..Default::default()
+        };
+        cap.args[0] = 1 << KVM_HC_MAP_GPA_RANGE;
+        vm_fd
+            .enable_cap(&cap)
+            .map_err(ConfidentialError::EnableHypercallExit)?;
+
+        self.command(vm_fd, KVM_SEV_INIT2, &mut kvm_sev_init::default())?;
+        self.command(
+            vm_fd,
+            KVM_SEV_SNP_LAUNCH_START,
+            &mut kvm_sev_snp_launch_start {
+                policy: self.policy,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn set_memory_region(
+        &self,
+        vm_fd: &VmFd,
+        slot: u32,
+        guest_addr: u64,
+        host_addr: u64,
+        size: u64,
+    ) -> Result<(), ConfidentialError> {
+        // The private memory of the region lives in a guest_memfd, which the host can't map.
+        let create = kvm_create_guest_memfd {
+            size,
+            ..Default::default()
+        };
+        // SAFETY: Safe because the fd is a valid VM file descriptor, the structure matches the
+        // layout expected by KVM and we check the return value.
+        let guest_memfd = unsafe { ioctl_with_ref(vm_fd, KVM_CREATE_GUEST_MEMFD(), &create) };
+        if guest_memfd < 0 {
+            return Err(ConfidentialError::CreateGuestMemfd(
+                kvm_ioctls::Error::last(),
+            ));
+        }
+        // SAFETY: KVM just created the file descriptor, so it is valid and owned by nothing else.
+        // The memory slot holds its own reference to the guest_memfd, which can be closed once
+        // the slot is set.
+        let guest_memfd = unsafe { File::from_raw_fd(guest_memfd) };
+
+        let region = kvm_userspace_memory_region2 {
+            slot,
+            flags: KVM_MEM_GUEST_MEMFD,
+            guest_phys_addr: guest_addr,
+            memory_size: size,
+            userspace_addr: host_addr,
+            guest_memfd_offset: 0,
+            // The file descriptor was checked above, so it is non-negative.
+            guest_memfd: u32::try_from(guest_memfd.as_raw_fd()).unwrap(),
+            ..Default::default()
+        };
+        // SAFETY: Safe because the fd is a valid VM file descriptor, the structure matches the
+        // layout expected by KVM, the shared memory of the region stays mapped for the lifetime
+        // of the VM and we check the return value.
+        if unsafe { ioctl_with_ref(vm_fd, KVM_SET_USER_MEMORY_REGION2(), &region) } < 0 {
+            return Err(ConfidentialError::SetMemoryRegion(kvm_ioctls::Error::last()));
+        }
+
+        set_memory_attributes(vm_fd, guest_addr, size, true)
+    }
+
+    fn launch_update(
+        &self,
+        vm_fd: &VmFd,
+        guest_addr: u64,
+        host_addr: u64,
+        size: u64,
+    ) -> Result<(), ConfidentialError> {
+        self.command(
+            vm_fd,
+            KVM_SEV_SNP_LAUNCH_UPDATE,
+            &mut kvm_sev_snp_launch_update {
+                gfn_start: guest_addr >> GUEST_PAGE_SHIFT,
+                uaddr: host_addr,
+                len: size,
+                type_: KVM_SEV_SNP_PAGE_TYPE_NORMAL,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn launch_finish(&self, vm_fd: &VmFd) -> Result<(), ConfidentialError> {
+        self.command(
+            vm_fd,
+            KVM_SEV_SNP_LAUNCH_FINISH,
+            &mut kvm_sev_snp_launch_finish::default(),
+        )
+    }
+
+    fn map_gpa_range(
+        &self,
+        guest_addr: u64,
+        size: u64,
+        private: bool,
+    ) -> Result<(), ConfidentialError> {
+        match self.vm.get() {
+            Some(vm) => set_memory_attributes(vm, guest_addr, size, private),
+            None => Err(ConfidentialError::SetMemoryAttributes(
+                kvm_ioctls::Error::new(libc::EBADF),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bindings_layout() {
+        assert_eq!(std::mem::size_of::<kvm_sev_init>(), 48);
+        assert_eq!(std::mem::size_of::<kvm_sev_snp_launch_start>(), 64);
+        assert_eq!(std::mem::size_of::<kvm_sev_snp_launch_update>(), 64);
+        assert_eq!(std::mem::size_of::<kvm_sev_snp_launch_finish>(), 88);
+        assert_eq!(std::mem::size_of::<kvm_create_guest_memfd>(), 64);
+        assert_eq!(std::mem::size_of::<kvm_userspace_memory_region2>(), 160);
+        assert_eq!(std::mem::size_of::<kvm_memory_attributes>(), 32);
+    }
+}
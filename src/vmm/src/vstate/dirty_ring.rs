@@ -0,0 +1,334 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use kvm_bindings::{kvm_dirty_gfn, KVM_DIRTY_LOG_PAGE_OFFSET};
+use kvm_ioctls::{VcpuFd, VmFd};
+use utils::ioctl::{ioctl, ioctl_expr, _IOC_NONE};
+use utils::{errno, u64_to_usize};
+
+use crate::logger::error;
+use crate::vstate::memory::{GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+use crate::DirtyBitmap;
+
+// The flags of a `kvm_dirty_gfn`, which are not exported by `kvm-bindings`.
+const KVM_DIRTY_GFN_F_DIRTY: u32 = 1 << 0;
+const KVM_DIRTY_GFN_F_RESET: u32 = 1 << 1;
+
+const KVMIO: u32 = 0xAE;
+const KVM_RESET_DIRTY_RINGS: libc::c_ulong = ioctl_expr(_IOC_NONE, KVMIO, 0xc7, 0);
+
+/// Errors associated with the KVM dirty rings.
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum DirtyRingError {
+    /// Cannot duplicate the VM file descriptor: {0}
+    DupVmFd(errno::Error),
+    /// Cannot get the page size: {0}
+    PageSize(errno::Error),
+    /// Cannot map the dirty ring of a vCPU: {0}
+    Map(errno::Error),
+    /// Cannot reset the dirty rings: {0}
+    Reset(errno::Error),
+}
+
+// The dirty ring of a vCPU, shared with KVM. KVM pushes the guest pages the vCPU dirties to the
+// ring, and these entries are harvested in order.
+#[derive(Debug)]
+struct DirtyRing {
+    gfns: NonNull<kvm_dirty_gfn>,
+    // Number of entries of the ring, a power of 2.
+    entries: u32,
+    // Index of the next entry to harvest.
+    next: u32,
+}
+
+// SAFETY: The ring exclusively owns its mapping.
+unsafe impl Send for DirtyRing {}
+
+impl DirtyRing {
+    fn new(vcpu_fd: &VcpuFd, entries: u32, page_size: usize) -> Result<Self, DirtyRingError> {
+        let offset = libc::off_t::from(KVM_DIRTY_LOG_PAGE_OFFSET)
+            * libc::off_t::try_from(page_size).unwrap();
+        // SAFETY: The file descriptor is a valid vCPU one, and KVM exposes a ring of `entries`
+        // entries at this offset once the dirty rings are enabled.
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                Self::len(entries),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                vcpu_fd.as_raw_fd(),
+                offset,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(DirtyRingError::Map(errno::Error::last()));
+        }
+
+        Ok(DirtyRing {
+            // The mapping succeeded, so the address is not null.
+            gfns: NonNull::new(addr.cast()).unwrap(),
+            entries,
+            next: 0,
+        })
+    }
+
+    fn len(entries: u32) -> usize {
+        entries as usize * std::mem::size_of::<kvm_dirty_gfn>()
+    }
+
+    // Hands the slot and page offset of the dirty entries of the ring to `mark_dirty`, and flags
+    // them to be reset by KVM. Returns the number of harvested entries.
+    fn harvest(&mut self, mut mark_dirty: impl FnMut(u32, u64)) -> usize {
+        let mut harvested = 0;
+        loop {
+            let index = (self.next & (self.entries - 1)) as usize;
+            // SAFETY: The index is within the ring.
+            let gfn = unsafe { self.gfns.as_ptr().add(index) };
+            // SAFETY: The flags are aligned, and they are accessed atomically by KVM as well.
+            let flags = unsafe { AtomicU32::from_ptr(std::ptr::addr_of_mut!((*gfn).flags)) };
+            if flags.load(Ordering::Acquire) & KVM_DIRTY_GFN_F_DIRTY == 0 {
+                break;
+            }
+            // SAFETY: KVM doesn't write to the entry again until it is reset.
+            let (slot, offset) = unsafe {
+                (
+                    std::ptr::addr_of!((*gfn).slot).read_volatile(),
+                    std::ptr::addr_of!((*gfn).offset).read_volatile(),
+                )
+            };
+            mark_dirty(slot, offset);
+            flags.store(KVM_DIRTY_GFN_F_RESET, Ordering::Release);
+            self.next = self.next.wrapping_add(1);
+            harvested += 1;
+        }
+        harvested
+    }
+}
+
+impl Drop for DirtyRing {
+    fn drop(&mut self) {
+        // SAFETY: The address and length are those of the mapping of the ring.
+        unsafe { libc::munmap(self.gfns.as_ptr().cast(), Self::len(self.entries)) };
+    }
+}
+
+#[derive(Debug)]
+struct DirtyRingState {
+    rings: Vec<DirtyRing>,
+    bitmap: DirtyBitmap,
+}
+
+/// The KVM dirty rings of the vCPUs of a VM, an alternative to the dirty bitmaps of its memory
+/// slots.
+///
+/// The rings only hold the pages dirtied since they were last harvested, so collecting them
+/// doesn't need to walk a bitmap covering the whole guest memory. The harvested pages are
+/// accumulated in a [`DirtyBitmap`] until it is taken.
+#[derive(Debug)]
+pub struct DirtyRingLog {
+    vm_fd: File,
+    entries: u32,
+    page_size: usize,
+    // Number of pages of each memory slot.
+    slot_pages: Vec<usize>,
+    state: Mutex<DirtyRingState>,
+}
+
+impl DirtyRingLog {
+    /// Creates the log of a VM whose dirty rings have `entries` entries. The rings of its vCPUs
+    /// are added as they are created.
+    pub fn new(
+        vm_fd: &VmFd,
+        entries: u32,
+        guest_mem: &GuestMemoryMmap,
+    ) -> Result<Self, DirtyRingError> {
+        // SAFETY: The file descriptor is valid, and the duplicate is checked below.
+        let fd = unsafe { libc::dup(vm_fd.as_raw_fd()) };
+        if fd < 0 {
+            return Err(DirtyRingError::DupVmFd(errno::Error::last()));
+        }
+        // SAFETY: The file descriptor was just duplicated, so it is exclusively owned.
+        let vm_fd = unsafe { File::from_raw_fd(fd) };
+
+        let page_size = utils::get_page_size().map_err(DirtyRingError::PageSize)?;
+        let slot_pages = guest_mem
+            .iter()
+            .map(|region| u64_to_usize(region.len()) / page_size)
+            .collect();
+        let mut log = DirtyRingLog {
+            vm_fd,
+            entries,
+            page_size,
+            slot_pages,
+            state: Mutex::new(DirtyRingState {
+                rings: Vec::new(),
+                bitmap: HashMap::new(),
+            }),
+        };
+        let bitmap = log.empty_bitmap();
+        log.state.get_mut().unwrap().bitmap = bitmap;
+        Ok(log)
+    }
+
+    fn empty_bitmap(&self) -> DirtyBitmap {
+        self.slot_pages
+            .iter()
+            .enumerate()
+            .map(|(slot, pages)| (slot, vec![0; pages.div_ceil(64)]))
+            .collect()
+    }
+
+    /// Maps the dirty ring of the given vCPU.
+    pub fn add_vcpu(&self, vcpu_fd: &VcpuFd) -> Result<(), DirtyRingError> {
+        let ring = DirtyRing::new(vcpu_fd, self.entries, self.page_size)?;
+        self.state.lock().expect("Poisoned lock").rings.push(ring);
+        Ok(())
+    }
+
+    /// Moves the pages pushed to the rings of the vCPUs to the dirty bitmap, and lets KVM reuse
+    /// their entries.
+    pub fn harvest(&self) -> Result<(), DirtyRingError> {
+        let mut state = self.state.lock().expect("Poisoned lock");
+        let DirtyRingState { rings, bitmap } = &mut *state;
+
+        let mut harvested = 0;
+        for ring in rings.iter_mut() {
+            harvested += ring.harvest(|slot, offset| {
+                // The upper 16 bits of the slot are the address space, which is always 0.
+                let page = usize::try_from(offset).unwrap_or(usize::MAX);
+                match bitmap.get_mut(&(slot as usize)) {
+                    Some(slot_bitmap) if page < self.slot_pages[slot as usize] => {
+                        slot_bitmap[page / 64] |= 1 << (page % 64);
+                    }
+                    _ => error!(
+                        "Invalid dirty ring entry: slot {}, offset {:#x}",
+                        slot, offset
+                    ),
+                }
+            });
+        }
+
+        if harvested > 0 {
+            // SAFETY: The file descriptor is a valid VM one.
+            if unsafe { ioctl(&self.vm_fd, KVM_RESET_DIRTY_RINGS) } < 0 {
+                return Err(DirtyRingError::Reset(errno::Error::last()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Harvests the rings, then returns the pages dirtied since the bitmap was last taken.
+    pub fn take_bitmap(&self) -> Result<DirtyBitmap, DirtyRingError> {
+        self.harvest()?;
+        let empty = self.empty_bitmap();
+        let mut state = self.state.lock().expect("Poisoned lock");
+        Ok(std::mem::replace(&mut state.bitmap, empty))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vstate::memory::{GuestAddress, GuestMemoryExtension};
+    use crate::vstate::vm::Vm;
+
+    // Builds a ring over anonymous memory, standing in for the mapping shared with KVM.
+    fn anonymous_ring(entries: u32) -> DirtyRing {
+        // SAFETY: The parameters describe a new anonymous mapping.
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                DirtyRing::len(entries),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(addr, libc::MAP_FAILED);
+        DirtyRing {
+            gfns: NonNull::new(addr.cast()).unwrap(),
+            entries,
+            next: 0,
+        }
+    }
+
+    fn push(ring: &DirtyRing, index: u32, slot: u32, offset: u64) {
+        // SAFETY: The index is within the ring.
+        unsafe {
+            *ring.gfns.as_ptr().add(index as usize) = kvm_dirty_gfn {
+                flags: KVM_DIRTY_GFN_F_DIRTY,
+                slot,
+                offset,
+            }
+        };
+    }
+
+    fn flags(ring: &DirtyRing, index: u32) -> u32 {
+        // SAFETY: The index is within the ring.
+        unsafe { (*ring.gfns.as_ptr().add(index as usize)).flags }
+    }
+
+    #[test]
+    fn test_dirty_ring_harvest() {
+        let mut ring = anonymous_ring(4);
+        assert_eq!(ring.harvest(|_, _| panic!()), 0);
+
+        push(&ring, 0, 0, 1);
+        push(&ring, 1, 1, 2);
+        push(&ring, 2, 0, 3);
+        let mut pages = Vec::new();
+        assert_eq!(ring.harvest(|slot, offset| pages.push((slot, offset))), 3);
+        assert_eq!(pages, vec![(0, 1), (1, 2), (0, 3)]);
+        assert_eq!(flags(&ring, 0), KVM_DIRTY_GFN_F_RESET);
+        assert_eq!(flags(&ring, 2), KVM_DIRTY_GFN_F_RESET);
+        assert_eq!(ring.next, 3);
+
+        // The harvest resumes where it stopped, wrapping around the ring.
+        push(&ring, 3, 0, 4);
+        push(&ring, 0, 0, 5);
+        pages.clear();
+        assert_eq!(ring.harvest(|slot, offset| pages.push((slot, offset))), 2);
+        assert_eq!(pages, vec![(0, 4), (0, 5)]);
+        assert_eq!(ring.next, 5);
+    }
+
+    #[test]
+    fn test_dirty_ring_log() {
+        let page_size = utils::get_page_size().unwrap();
+        let guest_mem = GuestMemoryMmap::from_raw_regions(
+            &[
+                (GuestAddress(0), 64 * page_size),
+                (GuestAddress(0x1000_0000), 130 * page_size),
+            ],
+            false,
+            crate::vmm_config::machine_config::HugePageConfig::None,
+        )
+        .unwrap();
+        let vm = Vm::new(vec![]).unwrap();
+        let log = DirtyRingLog::new(vm.fd(), 4, &guest_mem).unwrap();
+
+        let ring = anonymous_ring(4);
+        push(&ring, 0, 1, 129);
+        push(&ring, 1, 0, 3);
+        // Out of the memory slots, so ignored.
+        push(&ring, 2, 2, 0);
+        push(&ring, 3, 0, 64);
+        log.state.lock().unwrap().rings.push(ring);
+
+        // KVM refuses to reset the rings, as they are not enabled on the VM. The pages are
+        // harvested nonetheless.
+        assert!(matches!(log.harvest(), Err(DirtyRingError::Reset(_))));
+        let bitmap = log.take_bitmap().unwrap();
+        assert_eq!(bitmap[&0], vec![1 << 3]);
+        assert_eq!(bitmap[&1], vec![0, 0, 1 << 1]);
+        assert_eq!(log.take_bitmap().unwrap(), log.empty_bitmap());
+    }
+}
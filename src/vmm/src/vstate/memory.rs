@@ -19,6 +19,7 @@ pub use vm_memory::{
 };
 use vm_memory::{Error as VmMemoryError, GuestMemoryError, WriteVolatile};
 
+use crate::logger::{StoreMetric, METRICS};
 use crate::vmm_config::machine_config::HugePageConfig;
 use crate::DirtyBitmap;
 
@@ -52,6 +53,12 @@ pub enum MemoryError {
     MemfdSetLen(std::io::Error),
     /// Cannot restore hugetlbfs backed snapshot by mapping the memory file. Please use uffd.
     HugetlbfsSnapshot,
+    /// Cannot pre-fault guest memory: {0}
+    Populate(std::io::Error),
+    /// Cannot mark guest memory as mergeable: {0}
+    Mergeable(std::io::Error),
+    /// KSM only merges private guest memory backed by base pages.
+    MergeableSharedMemory,
 }
 
 /// Defines the interface for snapshotting memory.
@@ -59,13 +66,6 @@ pub trait GuestMemoryExtension
 where
     Self: Sized,
 {
-    /// Creates a GuestMemoryMmap with `size` in MiB backed by a memfd.
-    fn memfd_backed(
-        mem_size_mib: usize,
-        track_dirty_pages: bool,
-        huge_pages: HugePageConfig,
-    ) -> Result<Self, MemoryError>;
-
     /// Creates a GuestMemoryMmap from raw regions.
     fn from_raw_regions(
         regions: &[(GuestAddress, usize)],
@@ -110,6 +110,17 @@ where
 
     /// Store the dirty bitmap in internal store
     fn store_dirty_bitmap(&self, dirty_bitmap: &DirtyBitmap, page_size: usize);
+
+    /// Pre-faults the guest memory pages backing the given range
+    fn populate(&self, addr: GuestAddress, len: u64, page_size: usize) -> Result<(), MemoryError>;
+
+    /// Marks the guest memory pages backing the given range as mergeable by KSM
+    fn mark_mergeable(
+        &self,
+        addr: GuestAddress,
+        len: u64,
+        page_size: usize,
+    ) -> Result<(), MemoryError>;
 }
 
 /// State of a guest memory region saved to file/buffer.
@@ -132,29 +143,103 @@ pub struct GuestMemoryState {
     pub regions: Vec<GuestMemoryRegionState>,
 }
 
-impl GuestMemoryExtension for GuestMemoryMmap {
-    /// Creates a GuestMemoryMmap with `size` in MiB backed by a memfd.
-    fn memfd_backed(
-        mem_size_mib: usize,
+/// Source of the host memory backing the guest memory of a microVM.
+pub trait MemoryBackend: std::fmt::Debug {
+    /// Maps host memory for the guest memory `regions`. The backends using a file lay the regions
+    /// out back to back in the file, in the order of `regions`.
+    fn create(
+        &self,
+        regions: &[(GuestAddress, usize)],
         track_dirty_pages: bool,
-        huge_pages: HugePageConfig,
-    ) -> Result<Self, MemoryError> {
-        let memfd_file = create_memfd(mem_size_mib, huge_pages.into())?.into_file();
+    ) -> Result<GuestMemoryMmap, MemoryError>;
+}
+
+/// Private anonymous memory, backed by 4K pages.
+#[derive(Debug, Default)]
+pub struct AnonymousBackend;
 
+impl MemoryBackend for AnonymousBackend {
+    fn create(
+        &self,
+        regions: &[(GuestAddress, usize)],
+        track_dirty_pages: bool,
+    ) -> Result<GuestMemoryMmap, MemoryError> {
+        GuestMemoryMmap::from_raw_regions(regions, track_dirty_pages, HugePageConfig::None)
+    }
+}
+
+/// Private anonymous memory, backed by hugetlbfs pages.
+#[derive(Debug)]
+pub struct HugetlbfsBackend {
+    /// Size of the hugetlbfs pages.
+    pub huge_pages: HugePageConfig,
+}
+
+impl MemoryBackend for HugetlbfsBackend {
+    fn create(
+        &self,
+        regions: &[(GuestAddress, usize)],
+        track_dirty_pages: bool,
+    ) -> Result<GuestMemoryMmap, MemoryError> {
+        GuestMemoryMmap::from_raw_regions(regions, track_dirty_pages, self.huge_pages)
+    }
+}
+
+/// Shared memory of a memfd, which can be mapped by other processes, e.g. vhost-user backends.
+#[derive(Debug)]
+pub struct MemfdBackend {
+    /// Size of the pages backing the memfd.
+    pub huge_pages: HugePageConfig,
+}
+
+impl MemoryBackend for MemfdBackend {
+    fn create(
+        &self,
+        regions: &[(GuestAddress, usize)],
+        track_dirty_pages: bool,
+    ) -> Result<GuestMemoryMmap, MemoryError> {
+        let size = regions.iter().map(|(_, size)| *size as u64).sum();
+        let memfd_file = create_memfd(size, self.huge_pages.into())?.into_file();
+
+        FileBackend {
+            file: &memfd_file,
+            shared: true,
+        }
+        .create(regions, track_dirty_pages)
+    }
+}
+
+/// Memory mapped from a file, e.g. the memory file of a snapshot.
+#[derive(Debug)]
+pub struct FileBackend<'a> {
+    /// File holding the guest memory.
+    pub file: &'a File,
+    /// Whether the writes of the guest are carried through to the file.
+    pub shared: bool,
+}
+
+impl MemoryBackend for FileBackend<'_> {
+    fn create(
+        &self,
+        regions: &[(GuestAddress, usize)],
+        track_dirty_pages: bool,
+    ) -> Result<GuestMemoryMmap, MemoryError> {
         let mut offset: u64 = 0;
-        let regions = crate::arch::arch_memory_regions(mem_size_mib << 20)
+        let regions = regions
             .iter()
             .map(|(guest_address, region_size)| {
-                let file_clone = memfd_file.try_clone().map_err(MemoryError::FileError)?;
+                let file_clone = self.file.try_clone().map_err(MemoryError::FileError)?;
                 let file_offset = FileOffset::new(file_clone, offset);
                 offset += *region_size as u64;
                 Ok((file_offset, *guest_address, *region_size))
             })
             .collect::<Result<Vec<_>, MemoryError>>()?;
 
-        Self::from_raw_regions_file(regions, track_dirty_pages, true)
+        GuestMemoryMmap::from_raw_regions_file(regions, track_dirty_pages, self.shared)
     }
+}
 
+impl GuestMemoryExtension for GuestMemoryMmap {
     /// Creates a GuestMemoryMmap from raw regions backed by anonymous memory.
     fn from_raw_regions(
         regions: &[(GuestAddress, usize)],
@@ -231,34 +316,27 @@ impl GuestMemoryExtension for GuestMemoryMmap {
         track_dirty_pages: bool,
         huge_pages: HugePageConfig,
     ) -> Result<Self, MemoryError> {
+        let regions = state
+            .regions
+            .iter()
+            .map(|r| (GuestAddress(r.base_address), r.size))
+            .collect::<Vec<_>>();
         match file {
-            Some(f) => {
+            Some(file) => {
                 if huge_pages.is_hugetlbfs() {
                     return Err(MemoryError::HugetlbfsSnapshot);
                 }
-
-                let regions = state
-                    .regions
-                    .iter()
-                    .map(|r| {
-                        f.try_clone().map(|file_clone| {
-                            let offset = FileOffset::new(file_clone, r.offset);
-                            (offset, GuestAddress(r.base_address), r.size)
-                        })
-                    })
-                    .collect::<Result<Vec<_>, std::io::Error>>()
-                    .map_err(MemoryError::FileError)?;
-
-                Self::from_raw_regions_file(regions, track_dirty_pages, false)
+                // The memory file holds the regions back to back, as laid out by `describe()`.
+                FileBackend {
+                    file,
+                    shared: false,
+                }
+                .create(&regions, track_dirty_pages)
             }
-            None => {
-                let regions = state
-                    .regions
-                    .iter()
-                    .map(|r| (GuestAddress(r.base_address), r.size))
-                    .collect::<Vec<_>>();
-                Self::from_raw_regions(&regions, track_dirty_pages, huge_pages)
+            None if huge_pages.is_hugetlbfs() => {
+                HugetlbfsBackend { huge_pages }.create(&regions, track_dirty_pages)
             }
+            None => AnonymousBackend.create(&regions, track_dirty_pages),
         }
     }
 
@@ -385,13 +463,125 @@ impl GuestMemoryExtension for GuestMemoryMmap {
             }
         });
     }
+
+    /// Pre-faults the guest memory pages backing the given range
+    fn populate(&self, addr: GuestAddress, len: u64, page_size: usize) -> Result<(), MemoryError> {
+        for (host_addr, size) in host_page_ranges(self, addr, len, page_size) {
+            // SAFETY: The range is a valid part of a region's mapping.
+            let ret = unsafe { libc::madvise(host_addr.cast(), size, libc::MADV_POPULATE_WRITE) };
+            if ret == 0 {
+                continue;
+            }
+
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EINVAL) {
+                return Err(MemoryError::Populate(err));
+            }
+
+            // Kernels older than 5.14 do not support MADV_POPULATE_WRITE, so touch every page
+            // by writing back its first byte.
+            for page_offset in (0..size).step_by(page_size) {
+                // SAFETY: The page is within the bounds of the region's mapping.
+                unsafe {
+                    let page = host_addr.add(page_offset);
+                    std::ptr::write_volatile(page, std::ptr::read_volatile(page));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Marks the guest memory pages backing the given range as mergeable by KSM
+    fn mark_mergeable(
+        &self,
+        addr: GuestAddress,
+        len: u64,
+        page_size: usize,
+    ) -> Result<(), MemoryError> {
+        // The kernel accepts the hint for shared and hugetlb mappings, but never merges them.
+        let end = addr.0.saturating_add(len);
+        if self.iter().any(|region| {
+            region.start_addr().0 < end
+                && addr.0 < region.start_addr().0 + region.len()
+                && region.flags() & (libc::MAP_SHARED | libc::MAP_HUGETLB) != 0
+        }) {
+            return Err(MemoryError::MergeableSharedMemory);
+        }
+
+        for (host_addr, size) in host_page_ranges(self, addr, len, page_size) {
+            // SAFETY: The range is a valid part of a region's mapping.
+            let ret = unsafe { libc::madvise(host_addr.cast(), size, libc::MADV_MERGEABLE) };
+            if ret != 0 {
+                return Err(MemoryError::Mergeable(std::io::Error::last_os_error()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Updates the KSM metrics from the statistics the kernel keeps for this process.
+///
+/// The statistics are only available when `/proc` is mounted and the host kernel is 6.1 or newer.
+pub fn update_ksm_metrics() {
+    if let Ok(stat) = std::fs::read_to_string("/proc/self/ksm_stat") {
+        for (key, value) in parse_ksm_stat(&stat) {
+            match key {
+                "ksm_merging_pages" => METRICS.vmm.ksm_merging_pages.store(value),
+                "ksm_rmap_items" => METRICS.vmm.ksm_rmap_items.store(value),
+                _ => (),
+            }
+        }
+    }
+}
+
+fn parse_ksm_stat(stat: &str) -> impl Iterator<Item = (&str, u64)> {
+    stat.lines().filter_map(|line| {
+        let (key, value) = line.split_once(' ')?;
+        Some((key, value.trim().parse().ok()?))
+    })
+}
+
+/// Returns the host address and size of the page aligned parts of each region that overlap with
+/// the given guest memory range.
+fn host_page_ranges(
+    guest_memory: &GuestMemoryMmap,
+    addr: GuestAddress,
+    len: u64,
+    page_size: usize,
+) -> Vec<(*mut u8, usize)> {
+    let page_size = page_size as u64;
+    let end = addr.0.saturating_add(len);
+
+    guest_memory
+        .iter()
+        .filter_map(|region| {
+            let region_start = region.start_addr().0;
+            let start = addr.0.max(region_start);
+            let stop = end.min(region_start + region.len());
+            if start >= stop {
+                return None;
+            }
+
+            // Regions are page aligned, so align the range relative to the region start.
+            let offset = (start - region_start) / page_size * page_size;
+            let size = (stop - region_start)
+                .div_ceil(page_size)
+                .saturating_mul(page_size)
+                .min(region.len())
+                - offset;
+            // SAFETY: `offset + size` is within the bounds of the region's mapping.
+            let host_addr = unsafe { region.as_ptr().add(u64_to_usize(offset)) };
+            Some((host_addr, u64_to_usize(size)))
+        })
+        .collect()
 }
 
 fn create_memfd(
-    size: usize,
+    mem_size: u64,
     hugetlb_size: Option<memfd::HugetlbSize>,
 ) -> Result<memfd::Memfd, MemoryError> {
-    let mem_size = size << 20;
     // Create a memfd.
     let opts = memfd::MemfdOptions::default()
         .hugetlb(hugetlb_size)
@@ -401,7 +591,7 @@ fn create_memfd(
     // Resize to guest mem size.
     mem_file
         .as_file()
-        .set_len(mem_size as u64)
+        .set_len(mem_size)
         .map_err(MemoryError::MemfdSetLen)?;
 
     // Add seals to prevent further resizing.
@@ -529,6 +719,56 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_memory_backends() {
+        let region_size = 0x10000;
+        let regions = [
+            (GuestAddress(0), region_size),
+            (GuestAddress(0x100000), region_size),
+        ];
+
+        let guest_memory = AnonymousBackend.create(&regions, true).unwrap();
+        guest_memory.iter().for_each(|region| {
+            assert_eq!(region.size(), region_size);
+            assert!(region.file_offset().is_none());
+            assert!(region.bitmap().is_some());
+        });
+
+        // The memfd holds all the regions, back to back.
+        let backend = MemfdBackend {
+            huge_pages: HugePageConfig::None,
+        };
+        let guest_memory = backend.create(&regions, false).unwrap();
+        let offsets = guest_memory
+            .iter()
+            .map(|region| region.file_offset().unwrap().start())
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, [0, region_size as u64]);
+        assert!(guest_memory.iter().all(|region| region.bitmap().is_none()));
+        let file = guest_memory
+            .find_region(GuestAddress(0))
+            .unwrap()
+            .file_offset()
+            .unwrap()
+            .file();
+        assert_eq!(file.metadata().unwrap().len(), 2 * region_size as u64);
+
+        // The writes to a shared mapping reach the file.
+        let file = TempFile::new().unwrap().into_file();
+        file.set_len(2 * region_size as u64).unwrap();
+        let backend = FileBackend {
+            file: &file,
+            shared: true,
+        };
+        let guest_memory = backend.create(&regions, false).unwrap();
+        guest_memory
+            .write_obj(0xaa_u8, GuestAddress(0x100000))
+            .unwrap();
+        let mut data = vec![0u8; 2 * region_size];
+        (&file).read_exact(&mut data).unwrap();
+        assert_eq!(data[region_size], 0xaa);
+    }
+
     #[test]
     fn test_from_state() {
         let state = GuestMemoryState {
@@ -872,14 +1112,144 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_populate() {
+        let page_size = get_page_size().unwrap();
+
+        // Two regions of three pages each, with a one page gap between them.
+        let region_1_address = GuestAddress(0);
+        let region_2_address = GuestAddress(page_size as u64 * 4);
+        let region_size = page_size * 3;
+        let mem_regions = [
+            (region_1_address, region_size),
+            (region_2_address, region_size),
+        ];
+        let guest_memory =
+            GuestMemoryMmap::from_raw_regions(&mem_regions, true, HugePageConfig::None).unwrap();
+        guest_memory.write_obj(0xabu8, region_2_address).unwrap();
+        guest_memory.reset_dirty();
+
+        let resident_pages = |region: &GuestRegionMmap| {
+            let mut vec = vec![0u8; u64_to_usize(region.len()) / page_size];
+            let ret = unsafe {
+                libc::mincore(
+                    region.as_ptr().cast(),
+                    u64_to_usize(region.len()),
+                    vec.as_mut_ptr(),
+                )
+            };
+            assert_eq!(ret, 0);
+            vec.iter().map(|v| v & 1 == 1).collect::<Vec<_>>()
+        };
+
+        // Unaligned range spanning the end of the first region and the gap.
+        guest_memory
+            .populate(
+                GuestAddress(page_size as u64 + 1),
+                page_size as u64 * 2,
+                page_size,
+            )
+            .unwrap();
+        let regions = guest_memory.iter().collect::<Vec<_>>();
+        assert_eq!(resident_pages(regions[0]), vec![false, true, true]);
+
+        // Whole memory.
+        guest_memory
+            .populate(GuestAddress(0), u64::MAX, page_size)
+            .unwrap();
+        guest_memory.iter().for_each(|r| {
+            assert!(resident_pages(r).iter().all(|v| *v));
+            // Pre-faulting does not dirty the memory.
+            assert!(!r.bitmap().dirty_at(0));
+        });
+        assert_eq!(guest_memory.read_obj::<u8>(region_2_address).unwrap(), 0xab);
+
+        // Range outside guest memory.
+        guest_memory
+            .populate(GuestAddress(page_size as u64 * 8), 1, page_size)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_mark_mergeable() {
+        let page_size = get_page_size().unwrap();
+        let mem_regions = [(GuestAddress(0), page_size * 4)];
+
+        // Shared memory is never merged.
+        let guest_memory = MemfdBackend {
+            huge_pages: HugePageConfig::None,
+        }
+        .create(&mem_regions, false)
+        .unwrap();
+        assert!(matches!(
+            guest_memory.mark_mergeable(GuestAddress(0), u64::MAX, page_size),
+            Err(MemoryError::MergeableSharedMemory)
+        ));
+
+        let guest_memory =
+            GuestMemoryMmap::from_raw_regions(&mem_regions, false, HugePageConfig::None).unwrap();
+
+        let vm_flags = |host_addr: usize| {
+            let smaps = std::fs::read_to_string("/proc/self/smaps").unwrap();
+            let mut in_mapping = false;
+            for line in smaps.lines() {
+                if let Some((range, _)) = line.split_once(' ') {
+                    if let Some((start, _)) = range.split_once('-') {
+                        if let Ok(start) = usize::from_str_radix(start, 16) {
+                            in_mapping = start == host_addr;
+                            continue;
+                        }
+                    }
+                }
+                if let Some(flags) = line.strip_prefix("VmFlags:") {
+                    if in_mapping {
+                        return flags.split_whitespace().map(String::from).collect();
+                    }
+                }
+            }
+            Vec::new()
+        };
+
+        // Only the second page is marked, so the mapping is split in three.
+        let host_addr = guest_memory.get_host_address(GuestAddress(0)).unwrap() as usize;
+        match guest_memory.mark_mergeable(
+            GuestAddress(page_size as u64 + 1),
+            page_size as u64 - 1,
+            page_size,
+        ) {
+            Ok(()) => (),
+            // The host kernel is built without KSM support.
+            Err(MemoryError::Mergeable(err)) if err.raw_os_error() == Some(libc::EINVAL) => return,
+            Err(err) => panic!("{err}"),
+        }
+        assert!(!vm_flags(host_addr).contains(&"mg".to_string()));
+        assert!(vm_flags(host_addr + page_size).contains(&"mg".to_string()));
+        assert!(!vm_flags(host_addr + page_size * 2).contains(&"mg".to_string()));
+    }
+
+    #[test]
+    fn test_parse_ksm_stat() {
+        let stat = "ksm_rmap_items 12\nksm_zero_pages 0\nksm_merging_pages 3\nksm_process_profit \
+                    -4096\nksm_merge_any: no\n";
+        assert_eq!(
+            parse_ksm_stat(stat).collect::<Vec<_>>(),
+            vec![
+                ("ksm_rmap_items", 12),
+                ("ksm_zero_pages", 0),
+                ("ksm_merging_pages", 3)
+            ]
+        );
+
+        update_ksm_metrics();
+    }
+
     #[test]
     fn test_create_memfd() {
-        let size = 1;
-        let size_mb = 1 << 20;
+        let size = 1 << 20;
 
         let memfd = create_memfd(size, None).unwrap();
 
-        assert_eq!(memfd.as_file().metadata().unwrap().len(), size_mb);
+        assert_eq!(memfd.as_file().metadata().unwrap().len(), size);
         memfd.as_file().set_len(0x69).unwrap_err();
 
         let mut seals = memfd::SealsHashSet::new();
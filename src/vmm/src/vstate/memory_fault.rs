@@ -0,0 +1,324 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recovery from the faults raised when the VMM accesses guest memory.
+//!
+//! Guest memory backed by a file raises `SIGBUS` when the VMM accesses a page past the end of the
+//! file, for example after the file was truncated behind Firecracker's back. The virtio devices
+//! run their guest memory accesses through [`guarded`], so that such a fault fails the device
+//! request the access belongs to instead of killing the process. The faulting page is replaced
+//! with an anonymous zero page, which lets the access complete, and the fault is reported to the
+//! caller once the access returns.
+//!
+//! Only the faults on private file mappings, such as the memory file of a snapshot, are recovered
+//! from. Replacing a page of anonymous memory would lose guest data, and replacing a page of a
+//! shared mapping would detach it from the other processes mapping the memory, e.g. vhost-user
+//! backends, so these faults stay fatal.
+
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+use libc::{c_int, c_void, siginfo_t, SIGBUS, SIGSEGV};
+use serde::Serialize;
+use utils::u64_to_usize;
+
+use crate::logger::{error, IncMetric, METRICS};
+use crate::vstate::memory::{
+    Address, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion,
+};
+
+thread_local! {
+    // Guest memory accessed by the `guarded` call running on the thread, if any.
+    static GUARDED_MEMORY: Cell<*const GuestMemoryMmap> = const { Cell::new(std::ptr::null()) };
+    // First fault recovered from while running the `guarded` call.
+    static FAULT: Cell<Option<GuestMemoryFault>> = const { Cell::new(None) };
+}
+
+/// Fault raised by an access of the VMM to guest memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct GuestMemoryFault {
+    /// Signal raised by the fault, `SIGBUS` or `SIGSEGV`.
+    #[serde(serialize_with = "serialize_signal")]
+    pub signal: c_int,
+    /// Guest physical address of the faulting access.
+    pub guest_addr: u64,
+}
+
+fn signal_name(signal: c_int) -> &'static str {
+    match signal {
+        SIGBUS => "SIGBUS",
+        SIGSEGV => "SIGSEGV",
+        _ => "unknown signal",
+    }
+}
+
+fn serialize_signal<S: serde::Serializer>(
+    signal: &c_int,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(signal_name(*signal))
+}
+
+impl fmt::Display for GuestMemoryFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} on guest memory access at {:#x}",
+            signal_name(self.signal),
+            self.guest_addr
+        )
+    }
+}
+
+/// Errors associated with the injection of guest memory faults.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum MemoryFaultError {
+    /// Guest address {0:#x} is not backed by guest memory.
+    InvalidAddress(u64),
+    /// Failed to get the host page size: {0}
+    PageSize(utils::errno::Error),
+    /// Failed to protect the guest memory page: {0}
+    Protect(std::io::Error),
+}
+
+// Structured event logged for each fault recovered from.
+#[derive(Serialize)]
+struct GuestMemoryFaultEvent<'a> {
+    event: &'static str,
+    #[serde(flatten)]
+    fault: &'a GuestMemoryFault,
+}
+
+/// Runs `f`, which accesses `mem`, recovering from the faults raised by these accesses.
+///
+/// Returns the result of `f` along with the first fault recovered from, in which case the data
+/// `f` read from or wrote to the faulting page is lost and the caller must report an error.
+pub fn guarded<T>(mem: &GuestMemoryMmap, f: impl FnOnce() -> T) -> (T, Option<GuestMemoryFault>) {
+    // Restores the previous guest memory, also when `f` unwinds.
+    struct Reset(*const GuestMemoryMmap);
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            compiler_fence(Ordering::SeqCst);
+            GUARDED_MEMORY.with(|guarded| guarded.set(self.0));
+        }
+    }
+
+    let reset = Reset(GUARDED_MEMORY.with(|guarded| guarded.replace(mem)));
+    // The signal handler runs on this thread, so it is enough to prevent the compiler from moving
+    // the accesses of `f` outside of the guarded section.
+    compiler_fence(Ordering::SeqCst);
+    let res = f();
+    drop(reset);
+
+    let fault = FAULT.with(Cell::take);
+    if let Some(fault) = fault.as_ref() {
+        METRICS.vmm.guest_memory_faults.inc();
+        let event = GuestMemoryFaultEvent {
+            event: "guest_memory_fault",
+            fault,
+        };
+        error!(
+            "Recovered from a guest memory fault: {}",
+            serde_json::to_string(&event).unwrap_or_default()
+        );
+    }
+    (res, fault)
+}
+
+/// Recovers from a fault raised while running [`guarded`] on the calling thread, if the faulting
+/// address belongs to a private file mapping of the guarded guest memory.
+///
+/// Called from the `SIGBUS` and `SIGSEGV` handlers, so it only performs async-signal-safe
+/// operations. Returns whether the handler can return to retry the faulting access.
+pub(crate) fn recover(signal: c_int, info: *mut siginfo_t) -> bool {
+    // SAFETY: The kernel passes a valid siginfo structure to the signal handler.
+    let (si_code, addr) = unsafe { ((*info).si_code, (*info).si_addr() as usize) };
+    // Signals sent by processes, rather than raised by faults, have a non-positive code.
+    if si_code <= 0 {
+        return false;
+    }
+
+    let mem = GUARDED_MEMORY.with(Cell::get);
+    if mem.is_null() {
+        return false;
+    }
+    // SAFETY: The pointer is set by `guarded` on this thread, for the duration of the call during
+    // which the guest memory is borrowed, and the signal interrupted that call.
+    let mem = unsafe { &*mem };
+    let Some(region) = mem.iter().find(|region| {
+        let base = region.as_ptr() as usize;
+        (base..base + u64_to_usize(region.len())).contains(&addr)
+    }) else {
+        return false;
+    };
+    if region.file_offset().is_none() || region.flags() & libc::MAP_PRIVATE == 0 {
+        return false;
+    }
+
+    let Ok(page_size) = utils::get_page_size() else {
+        return false;
+    };
+    let page = addr & !(page_size - 1);
+    // SAFETY: The page lies in the guest memory mapping, which stays valid once the page is
+    // replaced, and the result is checked.
+    let ret = unsafe {
+        libc::mmap(
+            page as *mut c_void,
+            page_size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+            -1,
+            0,
+        )
+    };
+    if ret == libc::MAP_FAILED {
+        return false;
+    }
+
+    let fault = GuestMemoryFault {
+        signal,
+        guest_addr: region.start_addr().raw_value() + (addr - region.as_ptr() as usize) as u64,
+    };
+    FAULT.with(|first| {
+        if first.get().is_none() {
+            first.set(Some(fault));
+        }
+    });
+    true
+}
+
+/// Makes the page of guest memory containing `addr` inaccessible, so that the next access of the
+/// VMM to it raises a fault.
+///
+/// Meant to test how the devices report guest memory faults to the guest: the guest itself must
+/// not access the page until a device recovered from the fault, which requires the page to belong
+/// to a private file mapping.
+pub fn inject_fault(mem: &GuestMemoryMmap, addr: GuestAddress) -> Result<(), MemoryFaultError> {
+    let host_addr = mem
+        .get_host_address(addr)
+        .map_err(|_| MemoryFaultError::InvalidAddress(addr.raw_value()))?;
+    let page_size = utils::get_page_size().map_err(MemoryFaultError::PageSize)?;
+    let page = host_addr as usize & !(page_size - 1);
+    // SAFETY: The page lies in the guest memory mapping and the result is checked.
+    let ret = unsafe { libc::mprotect(page as *mut c_void, page_size, libc::PROT_NONE) };
+    if ret != 0 {
+        return Err(MemoryFaultError::Protect(std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+    use crate::signal_handler::register_signal_handlers;
+    use crate::utilities::test_utils::single_region_mem_at;
+    use crate::vstate::memory::{
+        Bytes, FileBackend, FileOffset, GuestMemoryExtension, MemoryBackend,
+    };
+
+    // Guest memory privately mapped from a file holding zeroes.
+    fn private_file_mem(at: u64, size: usize) -> GuestMemoryMmap {
+        let file = TempFile::new().unwrap();
+        file.as_file().set_len(size as u64).unwrap();
+        FileBackend {
+            file: file.as_file(),
+            shared: false,
+        }
+        .create(&[(GuestAddress(at), size)], false)
+        .unwrap()
+    }
+
+    #[test]
+    fn test_guarded_without_fault() {
+        let mem = single_region_mem_at(0x1000, 0x4000);
+        let (res, fault) = guarded(&mem, || mem.write_obj(0xdead_u64, GuestAddress(0x2000)));
+        res.unwrap();
+        assert_eq!(fault, None);
+        assert_eq!(mem.read_obj::<u64>(GuestAddress(0x2000)).unwrap(), 0xdead);
+    }
+
+    #[test]
+    fn test_recover_injected_fault() {
+        register_signal_handlers().unwrap();
+        let mem = private_file_mem(0x1000, 0x4000);
+        mem.write_obj(0xdead_u64, GuestAddress(0x2008)).unwrap();
+        inject_fault(&mem, GuestAddress(0x2008)).unwrap();
+        inject_fault(&mem, GuestAddress(0x6000)).unwrap_err();
+
+        let faults = METRICS.vmm.guest_memory_faults.count();
+        let (res, fault) = guarded(&mem, || mem.read_obj::<u64>(GuestAddress(0x2008)));
+        // The faulting page was replaced with a zero page.
+        assert_eq!(res.unwrap(), 0);
+        let fault = fault.unwrap();
+        assert_eq!(
+            fault,
+            GuestMemoryFault {
+                signal: SIGSEGV,
+                guest_addr: 0x2008
+            }
+        );
+        assert_eq!(
+            fault.to_string(),
+            "SIGSEGV on guest memory access at 0x2008"
+        );
+        assert!(METRICS.vmm.guest_memory_faults.count() > faults);
+
+        // The page is accessible again.
+        let (res, fault) = guarded(&mem, || mem.write_obj(1_u64, GuestAddress(0x2008)));
+        res.unwrap();
+        assert_eq!(fault, None);
+    }
+
+    #[test]
+    fn test_recover_truncated_file() {
+        register_signal_handlers().unwrap();
+        let page_size = utils::get_page_size().unwrap();
+        let file = TempFile::new().unwrap();
+        file.as_file().write_all(&vec![1u8; 2 * page_size]).unwrap();
+        let mem = GuestMemoryMmap::from_raw_regions_file(
+            vec![(
+                FileOffset::new(file.as_file().try_clone().unwrap(), 0),
+                GuestAddress(0),
+                2 * page_size,
+            )],
+            false,
+            false,
+        )
+        .unwrap();
+        file.as_file().set_len(page_size as u64).unwrap();
+
+        let mut buf = vec![0u8; 2 * page_size];
+        let (res, fault) = guarded(&mem, || mem.read_slice(&mut buf, GuestAddress(0)));
+        res.unwrap();
+        assert_eq!(
+            fault,
+            Some(GuestMemoryFault {
+                signal: SIGBUS,
+                guest_addr: page_size as u64
+            })
+        );
+        assert!(buf[..page_size].iter().all(|&byte| byte == 1));
+        assert!(buf[page_size..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_fault_event() {
+        let fault = GuestMemoryFault {
+            signal: SIGBUS,
+            guest_addr: 0x1000,
+        };
+        let event = GuestMemoryFaultEvent {
+            event: "guest_memory_fault",
+            fault: &fault,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"guest_memory_fault","signal":"SIGBUS","guest_addr":4096}"#
+        );
+    }
+}
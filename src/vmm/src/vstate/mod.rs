@@ -1,8 +1,14 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+/// Module with the confidential computing abstraction.
+pub mod confidential;
+/// Module with the KVM dirty rings.
+pub mod dirty_ring;
 /// Module with GuestMemory implementation.
 pub mod memory;
+/// Module with the recovery from faults on guest memory accesses.
+pub mod memory_fault;
 /// Module with Vcpu implementation.
 pub mod vcpu;
 /// Module with Vm implementation.
@@ -8,12 +8,18 @@
 use std::fmt::{Debug, Write};
 
 use kvm_bindings::{
-    kvm_mp_state, kvm_vcpu_init, KVM_ARM_VCPU_POWER_OFF, KVM_ARM_VCPU_PSCI_0_2, KVM_ARM_VCPU_SVE,
+    kvm_device_attr, kvm_mp_state, kvm_regs, kvm_vcpu_init, user_pt_regs, KVM_ARM_VCPU_PMU_V3,
+    KVM_ARM_VCPU_PMU_V3_CTRL, KVM_ARM_VCPU_PMU_V3_INIT, KVM_ARM_VCPU_PMU_V3_IRQ,
+    KVM_ARM_VCPU_POWER_OFF, KVM_ARM_VCPU_PSCI_0_2, KVM_ARM_VCPU_SVE, KVM_REG_ARM64,
+    KVM_REG_ARM_CORE, KVM_REG_SIZE_U64,
 };
 use kvm_ioctls::*;
 use serde::{Deserialize, Serialize};
 
-use crate::arch::aarch64::regs::{Aarch64RegisterVec, KVM_REG_ARM64_SVE_VLS};
+use crate::arch::aarch64::layout::PMU_PPI;
+use crate::arch::aarch64::regs::{
+    arm64_core_reg_id, offset__of, Aarch64RegisterVec, KVM_REG_ARM64_SVE_VLS,
+};
 use crate::arch::aarch64::vcpu::{
     get_all_registers, get_all_registers_ids, get_mpidr, get_mpstate, get_registers, set_mpstate,
     set_register, setup_boot_regs, VcpuError as ArchError,
@@ -39,6 +45,10 @@ pub enum KvmVcpuError {
     GetPreferredTarget(kvm_ioctls::Error),
     /// Error initializing the vcpu: {0}
     Init(kvm_ioctls::Error),
+    /// Error initializing the PMU of the vcpu: {0}
+    InitPmu(kvm_ioctls::Error),
+    /// The host doesn't support virtualizing the PMU.
+    PmuNotSupported,
     /// Error applying template: {0}
     ApplyCpuTemplate(ArchError),
     /// Failed to restore the state of the vcpu: {0}
@@ -68,6 +78,8 @@ pub struct KvmVcpu {
 pub(super) struct Peripherals {
     /// mmio bus.
     pub mmio_bus: Option<crate::devices::Bus>,
+    /// Dirty rings of the VM, if the dirty pages are reported through them.
+    pub dirty_ring: Option<std::sync::Arc<crate::vstate::dirty_ring::DirtyRingLog>>,
 }
 
 impl KvmVcpu {
@@ -135,6 +147,15 @@ impl KvmVcpu {
         Ok(())
     }
 
+    /// Exposes a virtual PMU to the guest. Must be called before [`KvmVcpu::init`].
+    pub fn enable_pmu(&mut self, vm: &Vm) -> Result<(), KvmVcpuError> {
+        if !vm.fd().check_extension(Cap::ArmPmuV3) {
+            return Err(KvmVcpuError::PmuNotSupported);
+        }
+        self.kvi.features[0] |= 1 << KVM_ARM_VCPU_PMU_V3;
+        Ok(())
+    }
+
     /// Initializes an aarch64 specific vcpu for booting Linux.
     ///
     /// # Arguments
@@ -148,6 +169,7 @@ impl KvmVcpu {
 
         self.init_vcpu()?;
         self.finalize_vcpu()?;
+        self.init_pmu()?;
 
         Ok(())
     }
@@ -201,6 +223,8 @@ impl KvmVcpu {
         }
 
         self.finalize_vcpu()?;
+        // The PMU registers saved in the snapshot are restored with the other registers.
+        self.init_pmu()?;
 
         // KVM_REG_ARM64_SVE_VLS needs to be skipped after vcpu is finalized.
         // If it is present it is handled in the code above.
@@ -241,6 +265,34 @@ impl KvmVcpu {
         }
         Ok(())
     }
+
+    /// Sets the overflow interrupt of the PMU and initializes it, if the PMU is exposed to the
+    /// guest. The interrupt controller must be initialized beforehand.
+    fn init_pmu(&self) -> Result<(), KvmVcpuError> {
+        if (self.kvi.features[0] & (1 << KVM_ARM_VCPU_PMU_V3)) == 0 {
+            return Ok(());
+        }
+        // KVM expects the interrupt ID, where PPIs start at 16.
+        let irq: u32 = PMU_PPI + 16;
+        let irq_attr = kvm_device_attr {
+            group: KVM_ARM_VCPU_PMU_V3_CTRL,
+            attr: u64::from(KVM_ARM_VCPU_PMU_V3_IRQ),
+            addr: &irq as *const u32 as u64,
+            flags: 0,
+        };
+        self.fd
+            .set_device_attr(&irq_attr)
+            .map_err(KvmVcpuError::InitPmu)?;
+        let init_attr = kvm_device_attr {
+            group: KVM_ARM_VCPU_PMU_V3_CTRL,
+            attr: u64::from(KVM_ARM_VCPU_PMU_V3_INIT),
+            addr: 0,
+            flags: 0,
+        };
+        self.fd
+            .set_device_attr(&init_attr)
+            .map_err(KvmVcpuError::InitPmu)
+    }
 }
 
 impl Peripherals {
@@ -293,6 +345,55 @@ impl Debug for VcpuState {
     }
 }
 
+/// Number of general-purpose registers (x0 - x30).
+const NUM_GP_REGS: usize = 31;
+
+/// General-purpose and selected system registers of a paused vCPU, in a form that can be
+/// reported through the API.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct VcpuRegisters {
+    /// General-purpose registers x0 - x30.
+    pub regs: Vec<u64>,
+    /// Stack pointer.
+    pub sp: u64,
+    /// Program counter.
+    pub pc: u64,
+    /// Processor state.
+    pub pstate: u64,
+    /// Multiprocessor affinity register.
+    pub mpidr: u64,
+}
+
+impl From<&VcpuState> for VcpuRegisters {
+    fn from(state: &VcpuState) -> Self {
+        let kreg_off = offset__of!(kvm_regs, regs);
+        let regs0 = offset__of!(user_pt_regs, regs) + kreg_off;
+        let sp = offset__of!(user_pt_regs, sp) + kreg_off;
+        let pc = offset__of!(user_pt_regs, pc) + kreg_off;
+        let pstate = offset__of!(user_pt_regs, pstate) + kreg_off;
+
+        let reg_value = |offset: usize| {
+            let id = arm64_core_reg_id!(KVM_REG_SIZE_U64, offset);
+            state
+                .regs
+                .iter()
+                .find(|reg| reg.id == id)
+                .map(|reg| reg.value::<u64, 8>())
+                .unwrap_or_default()
+        };
+
+        VcpuRegisters {
+            regs: (0..NUM_GP_REGS)
+                .map(|i| reg_value(regs0 + i * std::mem::size_of::<u64>()))
+                .collect(),
+            sp: reg_value(sp),
+            pc: reg_value(pc),
+            pstate: reg_value(pstate),
+            mpidr: state.mpidr,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(clippy::undocumented_unsafe_blocks)]
@@ -301,7 +402,7 @@ mod tests {
     use kvm_bindings::{KVM_ARM_VCPU_PSCI_0_2, KVM_REG_SIZE_U64};
 
     use super::*;
-    use crate::arch::aarch64::regs::Aarch64RegisterRef;
+    use crate::arch::aarch64::regs::{Aarch64RegisterRef, PC};
     use crate::cpu_config::aarch64::CpuConfiguration;
     use crate::cpu_config::templates::RegisterValueFilter;
     use crate::vcpu::VcpuConfig;
@@ -338,6 +439,7 @@ mod tests {
         let vcpu_config = VcpuConfig {
             vcpu_count: 1,
             smt: false,
+            pmu: false,
             cpu_config: CpuConfiguration::default(),
         };
         vcpu.configure(
@@ -382,6 +484,25 @@ mod tests {
         assert!((vcpu.kvi.features[0] & (1 << KVM_ARM_VCPU_PSCI_0_2)) == 0)
     }
 
+    #[test]
+    fn test_init_vcpu_pmu() {
+        let (mut vm, _vm_mem) = setup_vm(0x1000);
+        let mut vcpu = KvmVcpu::new(0, &vm).unwrap();
+        vm.setup_irqchip(1).unwrap();
+
+        if !vm.fd().check_extension(Cap::ArmPmuV3) {
+            assert_eq!(vcpu.enable_pmu(&vm), Err(KvmVcpuError::PmuNotSupported));
+            return;
+        }
+        vcpu.enable_pmu(&vm).unwrap();
+        vcpu.init(&[]).unwrap();
+        // The PMU is already initialized.
+        assert!(matches!(vcpu.init_pmu(), Err(KvmVcpuError::InitPmu(_))));
+        // The PMU is set up again from the saved features on restore.
+        let state = vcpu.save_state().unwrap();
+        assert_ne!(state.kvi.features[0] & (1 << KVM_ARM_VCPU_PMU_V3), 0);
+    }
+
     #[test]
     fn test_vcpu_save_restore_state() {
         let (mut vm, _vm_mem) = setup_vm(0x1000);
@@ -422,6 +543,30 @@ mod tests {
             .expect("Cannot restore state of vcpu");
     }
 
+    #[test]
+    fn test_vcpu_registers_from_state() {
+        let mut state = VcpuState {
+            mpidr: 0x8000_0000,
+            ..Default::default()
+        };
+        let pc_value = 0x4008_0000_u64.to_le_bytes();
+        state.regs.push(Aarch64RegisterRef::new(PC, &pc_value));
+        // x1 is the second 64-bit core register.
+        let x1_value = 0x1234_u64.to_le_bytes();
+        state.regs.push(Aarch64RegisterRef::new(
+            arm64_core_reg_id!(KVM_REG_SIZE_U64, 8),
+            &x1_value,
+        ));
+
+        let registers = VcpuRegisters::from(&state);
+        assert_eq!(registers.regs.len(), NUM_GP_REGS);
+        assert_eq!(registers.regs[0], 0);
+        assert_eq!(registers.regs[1], 0x1234);
+        assert_eq!(registers.pc, 0x4008_0000);
+        assert_eq!(registers.sp, 0);
+        assert_eq!(registers.mpidr, 0x8000_0000);
+    }
+
     #[test]
     fn test_dump_cpu_config_before_init() {
         // Test `dump_cpu_config()` before `KVM_VCPU_INIT`.
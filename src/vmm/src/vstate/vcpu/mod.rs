@@ -7,11 +7,11 @@
 
 use std::cell::Cell;
 use std::sync::atomic::{fence, Ordering};
-use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, TryRecvError};
 use std::sync::{Arc, Barrier};
 use std::{fmt, io, thread};
 
-use kvm_bindings::{KVM_SYSTEM_EVENT_RESET, KVM_SYSTEM_EVENT_SHUTDOWN};
+use kvm_bindings::{KVM_EXIT_DIRTY_RING_FULL, KVM_SYSTEM_EVENT_RESET, KVM_SYSTEM_EVENT_SHUTDOWN};
 use kvm_ioctls::VcpuExit;
 use libc::{c_int, c_void, siginfo_t};
 use log::{error, info, warn};
@@ -23,6 +23,8 @@ use utils::sm::StateMachine;
 
 use crate::cpu_config::templates::{CpuConfiguration, GuestConfigError};
 use crate::logger::{IncMetric, METRICS};
+use crate::vmm_config::machine_config::VcpuThreadConfig;
+use crate::vstate::dirty_ring::DirtyRingError;
 use crate::vstate::vm::Vm;
 use crate::FcExitCode;
 
@@ -60,6 +62,8 @@ pub enum VcpuError {
     VcpuTlsInit,
     /// Vcpu not present in TLS
     VcpuTlsNotPresent,
+    /// Dirty ring error: {0}
+    DirtyRing(DirtyRingError),
 }
 
 /// Encapsulates configuration parameters for the guest vCPUS.
@@ -69,6 +73,8 @@ pub struct VcpuConfig {
     pub vcpu_count: u8,
     /// Enable simultaneous multithreading in the CPUID configuration.
     pub smt: bool,
+    /// Expose the virtual PMU of KVM to the guest.
+    pub pmu: bool,
     /// Configuration for vCPU
     pub cpu_config: CpuConfiguration,
 }
@@ -77,9 +83,101 @@ pub struct VcpuConfig {
 type VcpuCell = Cell<Option<*mut Vcpu>>;
 
 /// Error type for [`Vcpu::start_threaded`].
-#[derive(Debug, derive_more::From, thiserror::Error)]
-#[error("Failed to spawn vCPU thread: {0}")]
-pub struct StartThreadedError(std::io::Error);
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum StartThreadedError {
+    /// Failed to spawn vCPU thread: {0}
+    Spawn(#[from] std::io::Error),
+    /// Failed to apply the host scheduling settings of vCPU thread {0}: {1}
+    ThreadConfig(u8, std::io::Error),
+}
+
+// Layout of `struct sched_attr` from `include/uapi/linux/sched/types.h`, which libc doesn't
+// provide.
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SchedAttr {
+    size: u32,
+    sched_policy: u32,
+    sched_flags: u64,
+    sched_nice: i32,
+    sched_priority: u32,
+    sched_runtime: u64,
+    sched_deadline: u64,
+    sched_period: u64,
+    sched_util_min: u32,
+    sched_util_max: u32,
+}
+
+const SCHED_FLAG_KEEP_POLICY: u64 = 0x08;
+const SCHED_FLAG_KEEP_PARAMS: u64 = 0x10;
+const SCHED_FLAG_UTIL_CLAMP_MIN: u64 = 0x20;
+const SCHED_FLAG_UTIL_CLAMP_MAX: u64 = 0x40;
+
+/// Applies the host scheduling settings to the calling thread.
+fn apply_thread_config(config: &VcpuThreadConfig) -> io::Result<()> {
+    if !config.cpus.is_empty() {
+        // SAFETY: `cpu_set_t` is a plain bitmask, valid when zeroed.
+        let mut cpu_set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        for &cpu in &config.cpus {
+            // SAFETY: The CPU indexes are validated to be lower than `CPU_SETSIZE`.
+            unsafe { libc::CPU_SET(cpu, &mut cpu_set) };
+        }
+        // SAFETY: Safe because the set is valid and its size is passed along.
+        let ret =
+            unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &cpu_set) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    if config.uclamp_min.is_some() || config.uclamp_max.is_some() {
+        let mut attr = SchedAttr {
+            // The structure is a few dozen bytes long.
+            #[allow(clippy::cast_possible_truncation)]
+            size: std::mem::size_of::<SchedAttr>() as u32,
+            sched_flags: SCHED_FLAG_KEEP_POLICY | SCHED_FLAG_KEEP_PARAMS,
+            ..Default::default()
+        };
+        if let Some(min) = config.uclamp_min {
+            attr.sched_flags |= SCHED_FLAG_UTIL_CLAMP_MIN;
+            attr.sched_util_min = u32::from(min);
+        }
+        if let Some(max) = config.uclamp_max {
+            attr.sched_flags |= SCHED_FLAG_UTIL_CLAMP_MAX;
+            attr.sched_util_max = u32::from(max);
+        }
+        // SAFETY: Safe because the attributes are valid and their size is passed along.
+        let ret = unsafe { libc::syscall(libc::SYS_sched_setattr, 0, &attr, 0) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    if let Some(nice) = config.nice {
+        // SAFETY: Safe because the call has no memory side effects.
+        let tid = unsafe { libc::syscall(libc::SYS_gettid) };
+        // Nice levels apply to single threads on Linux, which are identified by their TID.
+        // SAFETY: Safe because the call has no memory side effects.
+        #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+        let ret = unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, nice.into()) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    if let Some(priority) = config.fifo_priority {
+        let param = libc::sched_param {
+            sched_priority: priority.into(),
+        };
+        // SAFETY: Safe because the parameters are valid.
+        let ret = unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
 
 /// A wrapper around creating and using a vcpu.
 #[derive(Debug)]
@@ -97,6 +195,8 @@ pub struct Vcpu {
     response_receiver: Option<Receiver<VcpuResponse>>,
     /// The transmitting end of the responses channel owned by the vcpu side.
     response_sender: Sender<VcpuResponse>,
+    /// Host scheduling settings applied to the thread of the vcpu when it is spawned.
+    thread_config: Option<VcpuThreadConfig>,
 }
 
 impl Vcpu {
@@ -192,7 +292,17 @@ impl Vcpu {
     pub fn new(index: u8, vm: &Vm, exit_evt: EventFd) -> Result<Self, VcpuError> {
         let (event_sender, event_receiver) = channel();
         let (response_sender, response_receiver) = channel();
-        let kvm_vcpu = KvmVcpu::new(index, vm).unwrap();
+        let mut kvm_vcpu = KvmVcpu::new(index, vm).unwrap();
+        if let Some(dirty_ring) = vm.dirty_ring() {
+            dirty_ring
+                .add_vcpu(&kvm_vcpu.fd)
+                .map_err(VcpuError::DirtyRing)?;
+            kvm_vcpu.peripherals.dirty_ring = Some(dirty_ring.clone());
+        }
+        #[cfg(target_arch = "x86_64")]
+        {
+            kvm_vcpu.peripherals.confidential = vm.confidential().cloned();
+        }
 
         Ok(Vcpu {
             exit_evt,
@@ -200,6 +310,7 @@ impl Vcpu {
             event_sender: Some(event_sender),
             response_receiver: Some(response_receiver),
             response_sender,
+            thread_config: None,
             kvm_vcpu,
         })
     }
@@ -209,6 +320,11 @@ impl Vcpu {
         self.kvm_vcpu.peripherals.mmio_bus = Some(mmio_bus);
     }
 
+    /// Sets the host scheduling settings of the thread of this vcpu.
+    pub fn set_thread_config(&mut self, thread_config: VcpuThreadConfig) {
+        self.thread_config = Some(thread_config);
+    }
+
     /// Moves the vcpu to its own thread and constructs a VcpuHandle.
     /// The handle can be used to control the remote vcpu.
     pub fn start_threaded(
@@ -218,16 +334,33 @@ impl Vcpu {
     ) -> Result<VcpuHandle, StartThreadedError> {
         let event_sender = self.event_sender.take().expect("vCPU already started");
         let response_receiver = self.response_receiver.take().unwrap();
+        let index = self.kvm_vcpu.index;
+        let (config_result_sender, config_result_receiver) = sync_channel(1);
         let vcpu_thread = thread::Builder::new()
-            .name(format!("fc_vcpu {}", self.kvm_vcpu.index))
+            .name(format!("fc_vcpu {}", index))
             .spawn(move || {
                 let filter = &*seccomp_filter;
+                // The settings are applied before the seccomp filter, which denies the syscalls.
+                let config_result = self
+                    .thread_config
+                    .as_ref()
+                    .map_or(Ok(()), apply_thread_config);
+                let config_failed = config_result.is_err();
+                // The receiver outlives this message.
+                config_result_sender.send(config_result).unwrap();
+                if config_failed {
+                    return;
+                }
                 self.init_thread_local_data()
                     .expect("Cannot cleanly initialize vcpu TLS.");
                 // Synchronization to make sure thread local data is initialized.
                 barrier.wait();
                 self.run(filter);
             })?;
+        // The thread reports the outcome before anything else, unless it panics.
+        if let Ok(Err(err)) = config_result_receiver.recv() {
+            return Err(StartThreadedError::ThreadConfig(index, err));
+        }
 
         Ok(VcpuHandle::new(
             event_sender,
@@ -448,6 +581,9 @@ impl Vcpu {
                 // Notify that this KVM_RUN was interrupted.
                 Ok(VcpuEmulation::Interrupted)
             }
+            // The arguments and result of the hypercall are in the `kvm_run` structure.
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuExit::Hypercall) => self.kvm_vcpu.handle_hypercall(),
             emulation_result => handle_kvm_exit(&mut self.kvm_vcpu.peripherals, emulation_result),
         }
     }
@@ -527,6 +663,19 @@ fn handle_kvm_exit(
                     )))
                 }
             },
+            VcpuExit::Unsupported(KVM_EXIT_DIRTY_RING_FULL) => {
+                // The vCPU cannot run until entries of its dirty ring are harvested and reset.
+                match &peripherals.dirty_ring {
+                    Some(dirty_ring) => {
+                        METRICS.vcpu.exit_dirty_ring_full.inc();
+                        dirty_ring.harvest().map_err(VcpuError::DirtyRing)?;
+                        Ok(VcpuEmulation::Handled)
+                    }
+                    None => Err(VcpuError::UnhandledKvmExit(
+                        "Dirty ring full without dirty rings".to_string(),
+                    )),
+                }
+            }
             arch_specific_reason => {
                 // run specific architecture emulation.
                 peripherals.run_arch_emulation(arch_specific_reason)
@@ -917,6 +1066,7 @@ pub mod tests {
                     &VcpuConfig {
                         vcpu_count: 1,
                         smt: false,
+                        pmu: false,
                         cpu_config: CpuConfiguration {
                             cpuid: Cpuid::try_from(_vm.supported_cpuid().clone()).unwrap(),
                             msrs: std::collections::HashMap::new(),
@@ -934,6 +1084,7 @@ pub mod tests {
                 &VcpuConfig {
                     vcpu_count: 1,
                     smt: false,
+                    pmu: false,
                     cpu_config: crate::cpu_config::aarch64::CpuConfiguration::default(),
                 },
             )
@@ -1180,4 +1331,55 @@ pub mod tests {
     fn test_vcpu_rtsig_offset() {
         validate_signal_num(sigrtmin() + VCPU_RTSIG_OFFSET).unwrap();
     }
+
+    #[test]
+    fn test_apply_thread_config() {
+        // The settings are applied to a thread of its own, to leave the test thread untouched.
+        thread::spawn(|| {
+            // SAFETY: `cpu_set_t` is a plain bitmask, valid when zeroed.
+            let mut cpu_set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            // SAFETY: Safe because the set is valid and its size is passed along.
+            let ret = unsafe {
+                libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut cpu_set)
+            };
+            assert_eq!(ret, 0);
+            // SAFETY: Safe because the set is valid.
+            #[allow(clippy::cast_sign_loss)]
+            let cpu = (0..libc::CPU_SETSIZE as usize)
+                .find(|&cpu| unsafe { libc::CPU_ISSET(cpu, &cpu_set) })
+                .unwrap();
+
+            // Raising the nice level of a thread doesn't require any privilege.
+            apply_thread_config(&VcpuThreadConfig {
+                cpus: vec![cpu],
+                nice: Some(19),
+                ..Default::default()
+            })
+            .unwrap();
+            // SAFETY: Safe because the set is valid and its size is passed along.
+            let ret = unsafe {
+                libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut cpu_set)
+            };
+            assert_eq!(ret, 0);
+            // SAFETY: Safe because the set is valid.
+            assert_eq!(unsafe { libc::CPU_COUNT(&cpu_set) }, 1);
+            // SAFETY: Safe because the set is valid.
+            assert!(unsafe { libc::CPU_ISSET(cpu, &cpu_set) });
+            // SAFETY: Safe because the call has no memory side effects.
+            let tid = unsafe { libc::syscall(libc::SYS_gettid) };
+            // SAFETY: Safe because the call has no memory side effects.
+            #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+            let nice = unsafe { libc::getpriority(libc::PRIO_PROCESS, tid as libc::id_t) };
+            assert_eq!(nice, 19);
+
+            // A thread cannot be pinned to host CPUs that don't exist.
+            apply_thread_config(&VcpuThreadConfig {
+                cpus: vec![1023],
+                ..Default::default()
+            })
+            .unwrap_err();
+        })
+        .join()
+        .unwrap();
+    }
 }
@@ -7,6 +7,7 @@
 
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use kvm_bindings::{
     kvm_debugregs, kvm_lapic_state, kvm_mp_state, kvm_regs, kvm_sregs, kvm_vcpu_events, kvm_xcrs,
@@ -22,6 +23,9 @@ use crate::arch::x86_64::regs::{SetupFpuError, SetupRegistersError, SetupSpecial
 use crate::arch_gen::x86::msr_index::{MSR_IA32_TSC, MSR_IA32_TSC_DEADLINE};
 use crate::cpu_config::x86_64::{cpuid, CpuConfiguration};
 use crate::logger::{IncMetric, METRICS};
+use crate::vstate::confidential::{
+    ConfidentialVm, KVM_HC_MAP_GPA_RANGE, KVM_MAP_GPA_RANGE_ENCRYPTED,
+};
 use crate::vstate::memory::{Address, GuestAddress, GuestMemoryMmap};
 use crate::vstate::vcpu::{VcpuConfig, VcpuEmulation};
 use crate::vstate::vm::Vm;
@@ -33,6 +37,13 @@ use crate::vstate::vm::Vm;
 const TSC_KHZ_TOL_NUMERATOR: i64 = 250;
 const TSC_KHZ_TOL_DENOMINATOR: i64 = 1_000_000;
 
+// Size of the guest pages counted by the `KVM_HC_MAP_GPA_RANGE` hypercall.
+const GUEST_PAGE_SIZE: u64 = 4096;
+// Result of a failed hypercall, i.e. the negated error number as for the hypercalls handled by
+// KVM.
+#[allow(clippy::cast_sign_loss, clippy::cast_lossless)]
+const HYPERCALL_EINVAL: u64 = -(libc::EINVAL as i64) as u64;
+
 /// Errors associated with the wrappers over KVM ioctls.
 #[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
 pub enum KvmVcpuError {
@@ -142,6 +153,10 @@ pub(super) struct Peripherals {
     pub pio_bus: Option<crate::devices::Bus>,
     /// Mmio bus.
     pub mmio_bus: Option<crate::devices::Bus>,
+    /// Dirty rings of the VM, if the dirty pages are reported through them.
+    pub dirty_ring: Option<std::sync::Arc<crate::vstate::dirty_ring::DirtyRingLog>>,
+    /// Confidential context of the VM, converting the guest memory on behalf of the guest.
+    pub confidential: Option<Arc<dyn ConfidentialVm>>,
 }
 
 impl KvmVcpu {
@@ -189,6 +204,8 @@ impl KvmVcpu {
             vcpu_config.vcpu_count,
             // The number of bits needed to enumerate logical CPUs per core.
             u8::from(vcpu_config.vcpu_count > 1 && vcpu_config.smt),
+            // Whether the virtual PMU is exposed to the guest.
+            vcpu_config.pmu,
         )?;
 
         // Set CPUID.
@@ -536,6 +553,47 @@ impl KvmVcpu {
             .map_err(KvmVcpuError::VcpuSetVcpuEvents)?;
         Ok(())
     }
+
+    /// Handles a hypercall KVM forwarded to userspace, i.e. the conversion of the memory of a
+    /// confidential guest between private and shared.
+    pub(super) fn handle_hypercall(&mut self) -> Result<VcpuEmulation, super::VcpuError> {
+        let Some(confidential) = self.peripherals.confidential.clone() else {
+            return Err(super::VcpuError::UnhandledKvmExit(
+                "Hypercall without confidential context".to_string(),
+            ));
+        };
+        // SAFETY: KVM fills the hypercall member of the union on KVM_EXIT_HYPERCALL.
+        let hypercall = unsafe { &mut self.fd.get_kvm_run().__bindgen_anon_1.hypercall };
+        if hypercall.nr != KVM_HC_MAP_GPA_RANGE {
+            return Err(super::VcpuError::UnhandledKvmExit(format!(
+                "Hypercall {}",
+                hypercall.nr
+            )));
+        }
+
+        let [guest_addr, pages, attributes, ..] = hypercall.args;
+        let private = attributes & KVM_MAP_GPA_RANGE_ENCRYPTED != 0;
+        let result = match pages.checked_mul(GUEST_PAGE_SIZE) {
+            Some(size) => confidential
+                .map_gpa_range(guest_addr, size, private)
+                .map_err(|err| err.to_string()),
+            None => Err(format!("{} pages", pages)),
+        };
+        // The guest is told about the failure of the conversion, which it requested.
+        hypercall.ret = match result {
+            Ok(()) => 0,
+            Err(err) => {
+                warn!(
+                    "Cannot convert the guest memory at {:#x} to {}: {}",
+                    guest_addr,
+                    if private { "private" } else { "shared" },
+                    err
+                );
+                HYPERCALL_EINVAL
+            }
+        };
+        Ok(VcpuEmulation::Handled)
+    }
 }
 
 impl Peripherals {
@@ -624,6 +682,123 @@ impl Debug for VcpuState {
     }
 }
 
+/// General-purpose and selected system registers of a paused vCPU, in a form that can be
+/// reported through the API.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct VcpuRegisters {
+    /// RAX register.
+    pub rax: u64,
+    /// RBX register.
+    pub rbx: u64,
+    /// RCX register.
+    pub rcx: u64,
+    /// RDX register.
+    pub rdx: u64,
+    /// RSI register.
+    pub rsi: u64,
+    /// RDI register.
+    pub rdi: u64,
+    /// RSP register.
+    pub rsp: u64,
+    /// RBP register.
+    pub rbp: u64,
+    /// R8 register.
+    pub r8: u64,
+    /// R9 register.
+    pub r9: u64,
+    /// R10 register.
+    pub r10: u64,
+    /// R11 register.
+    pub r11: u64,
+    /// R12 register.
+    pub r12: u64,
+    /// R13 register.
+    pub r13: u64,
+    /// R14 register.
+    pub r14: u64,
+    /// R15 register.
+    pub r15: u64,
+    /// Instruction pointer.
+    pub rip: u64,
+    /// Flags register.
+    pub rflags: u64,
+    /// CR0 control register.
+    pub cr0: u64,
+    /// CR2 control register (page fault linear address).
+    pub cr2: u64,
+    /// CR3 control register (page table base).
+    pub cr3: u64,
+    /// CR4 control register.
+    pub cr4: u64,
+    /// CR8 control register (task priority).
+    pub cr8: u64,
+    /// Extended feature enable register.
+    pub efer: u64,
+    /// Code segment selector.
+    pub cs: u16,
+    /// Data segment selector.
+    pub ds: u16,
+    /// Extra segment selector.
+    pub es: u16,
+    /// FS segment selector.
+    pub fs: u16,
+    /// GS segment selector.
+    pub gs: u16,
+    /// Stack segment selector.
+    pub ss: u16,
+    /// FS segment base address.
+    pub fs_base: u64,
+    /// GS segment base address.
+    pub gs_base: u64,
+    /// Global descriptor table base address.
+    pub gdt_base: u64,
+    /// Interrupt descriptor table base address.
+    pub idt_base: u64,
+}
+
+impl From<&VcpuState> for VcpuRegisters {
+    fn from(state: &VcpuState) -> Self {
+        let regs = &state.regs;
+        let sregs = &state.sregs;
+        VcpuRegisters {
+            rax: regs.rax,
+            rbx: regs.rbx,
+            rcx: regs.rcx,
+            rdx: regs.rdx,
+            rsi: regs.rsi,
+            rdi: regs.rdi,
+            rsp: regs.rsp,
+            rbp: regs.rbp,
+            r8: regs.r8,
+            r9: regs.r9,
+            r10: regs.r10,
+            r11: regs.r11,
+            r12: regs.r12,
+            r13: regs.r13,
+            r14: regs.r14,
+            r15: regs.r15,
+            rip: regs.rip,
+            rflags: regs.rflags,
+            cr0: sregs.cr0,
+            cr2: sregs.cr2,
+            cr3: sregs.cr3,
+            cr4: sregs.cr4,
+            cr8: sregs.cr8,
+            efer: sregs.efer,
+            cs: sregs.cs.selector,
+            ds: sregs.ds.selector,
+            es: sregs.es.selector,
+            fs: sregs.fs.selector,
+            gs: sregs.gs.selector,
+            ss: sregs.ss.selector,
+            fs_base: sregs.fs.base,
+            gs_base: sregs.gs.base,
+            gdt_base: sregs.gdt.base,
+            idt_base: sregs.idt.base,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(clippy::undocumented_unsafe_blocks)]
@@ -640,6 +815,7 @@ mod tests {
         StaticCpuTemplate,
     };
     use crate::cpu_config::x86_64::cpuid::{Cpuid, CpuidEntry, CpuidKey};
+    use crate::vstate::confidential::test_utils::{MockCall, MockConfidentialVm};
     use crate::vstate::vm::tests::setup_vm;
     use crate::vstate::vm::Vm;
 
@@ -694,6 +870,7 @@ mod tests {
         Ok(VcpuConfig {
             vcpu_count: 1,
             smt: false,
+            pmu: false,
             cpu_config,
         })
     }
@@ -807,6 +984,22 @@ mod tests {
         assert!(leaf3.result.eax == 0x1234_5678);
     }
 
+    #[test]
+    fn test_vcpu_registers_from_state() {
+        let (_vm, vcpu, _mem) = setup_vcpu(0x10000);
+        let mut regs = vcpu.fd.get_regs().unwrap();
+        regs.rax = 0x1234;
+        regs.rip = 0x1000;
+        vcpu.fd.set_regs(&regs).unwrap();
+
+        let state = vcpu.save_state().unwrap();
+        let registers = VcpuRegisters::from(&state);
+        assert_eq!(registers.rax, 0x1234);
+        assert_eq!(registers.rip, 0x1000);
+        assert_eq!(registers.cr0, state.sregs.cr0);
+        assert_eq!(registers.cs, state.sregs.cs.selector);
+    }
+
     #[test]
     fn test_empty_cpuid_entries_removed() {
         // Test that `get_cpuid()` removes zeroed empty entries from the `KVM_GET_CPUID2` result.
@@ -814,6 +1007,7 @@ mod tests {
         let vcpu_config = VcpuConfig {
             vcpu_count: 1,
             smt: false,
+            pmu: false,
             cpu_config: CpuConfiguration {
                 cpuid: Cpuid::try_from(vm.supported_cpuid().clone()).unwrap(),
                 msrs: HashMap::new(),
@@ -876,6 +1070,7 @@ mod tests {
         let vcpu_config = VcpuConfig {
             vcpu_count: 1,
             smt: false,
+            pmu: false,
             cpu_config: CpuConfiguration {
                 cpuid: Cpuid::try_from(vm.supported_cpuid().clone()).unwrap(),
                 msrs: HashMap::new(),
@@ -1059,4 +1254,56 @@ mod tests {
             &[(MSR_IA32_TSC_DEADLINE, 1), (MSR_IA32_TSC, 2)],
         );
     }
+
+    // Fills the `kvm_run` structure of the vCPU as KVM does on KVM_EXIT_HYPERCALL, then handles
+    // the exit and returns the result passed to the guest.
+    fn hypercall(
+        vcpu: &mut KvmVcpu,
+        nr: u64,
+        args: [u64; 6],
+    ) -> Result<u64, crate::vstate::vcpu::VcpuError> {
+        // SAFETY: The vCPU doesn't run, so the union is only accessed from here.
+        let hypercall = unsafe { &mut vcpu.fd.get_kvm_run().__bindgen_anon_1.hypercall };
+        hypercall.nr = nr;
+        hypercall.args = args;
+        hypercall.ret = u64::MAX;
+        assert_eq!(vcpu.handle_hypercall()?, VcpuEmulation::Handled);
+        // SAFETY: As above.
+        Ok(unsafe { vcpu.fd.get_kvm_run().__bindgen_anon_1.hypercall.ret })
+    }
+
+    #[test]
+    fn test_handle_hypercall() {
+        let (_vm, mut vcpu, _) = setup_vcpu(0x10000);
+        let map_gpa_range = [0x4000, 2, KVM_MAP_GPA_RANGE_ENCRYPTED, 0, 0, 0];
+        hypercall(&mut vcpu, KVM_HC_MAP_GPA_RANGE, map_gpa_range).unwrap_err();
+
+        let confidential = MockConfidentialVm::default();
+        let calls = confidential.calls.clone();
+        vcpu.peripherals.confidential = Some(Arc::new(confidential));
+        assert_eq!(
+            hypercall(&mut vcpu, KVM_HC_MAP_GPA_RANGE, map_gpa_range).unwrap(),
+            0
+        );
+        assert_eq!(
+            hypercall(&mut vcpu, KVM_HC_MAP_GPA_RANGE, [0x4000, 1, 0, 0, 0, 0]).unwrap(),
+            0
+        );
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                MockCall::MapGpaRange(0x4000, 0x2000, true),
+                MockCall::MapGpaRange(0x4000, 0x1000, false),
+            ]
+        );
+
+        // The guest is told about the invalid requests.
+        assert_eq!(
+            hypercall(&mut vcpu, KVM_HC_MAP_GPA_RANGE, [0, u64::MAX, 0, 0, 0, 0]).unwrap(),
+            HYPERCALL_EINVAL
+        );
+        assert_eq!(calls.lock().unwrap().len(), 2);
+
+        hypercall(&mut vcpu, 1, [0; 6]).unwrap_err();
+    }
 }
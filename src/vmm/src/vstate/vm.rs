@@ -7,6 +7,7 @@
 
 #[cfg(target_arch = "x86_64")]
 use std::fmt;
+use std::sync::Arc;
 
 #[cfg(target_arch = "x86_64")]
 use kvm_bindings::{
@@ -14,10 +15,13 @@ use kvm_bindings::{
     KVM_CLOCK_TSC_STABLE, KVM_IRQCHIP_IOAPIC, KVM_IRQCHIP_PIC_MASTER, KVM_IRQCHIP_PIC_SLAVE,
     KVM_MAX_CPUID_ENTRIES, KVM_PIT_SPEAKER_DUMMY,
 };
-use kvm_bindings::{kvm_userspace_memory_region, KVM_API_VERSION, KVM_MEM_LOG_DIRTY_PAGES};
+use kvm_bindings::{
+    kvm_enable_cap, kvm_userspace_memory_region, KVM_API_VERSION, KVM_MEM_LOG_DIRTY_PAGES,
+};
 use kvm_ioctls::{Kvm, VmFd};
 use serde::{Deserialize, Serialize};
 #[cfg(target_arch = "x86_64")]
+use utils::time::{get_time_ns, ClockType};
 use utils::u64_to_usize;
 
 #[cfg(target_arch = "aarch64")]
@@ -25,7 +29,12 @@ use crate::arch::aarch64::gic::GICDevice;
 #[cfg(target_arch = "aarch64")]
 use crate::arch::aarch64::gic::GicState;
 use crate::cpu_config::templates::KvmCapability;
-use crate::vstate::memory::{Address, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+use crate::vmm_config::machine_config::DirtyTrackingMode;
+use crate::vstate::confidential::{ConfidentialError, ConfidentialVm};
+use crate::vstate::dirty_ring::{DirtyRingError, DirtyRingLog};
+use crate::vstate::memory::{
+    Address, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion,
+};
 
 /// Errors associated with the wrappers over KVM ioctls.
 /// Needs `rustfmt::skip` to make multiline comments work
@@ -46,9 +55,22 @@ pub enum VmError {
     NotEnoughMemorySlots,
     /// Cannot set the memory regions: {0}
     SetUserMemoryRegion(kvm_ioctls::Error),
+    /// Confidential computing error: {0}
+    Confidential(ConfidentialError),
+    /// The launch payload at {0:#x}, of {1} bytes, is not in a single guest memory region.
+    InvalidLaunchPayload(u64, u64),
+    /// The host kernel doesn't support the KVM dirty rings.
+    DirtyRingNotSupported,
+    /// Cannot enable the KVM dirty rings: {0}
+    EnableDirtyRing(kvm_ioctls::Error),
+    /// Dirty ring error: {0}
+    DirtyRing(DirtyRingError),
     #[cfg(target_arch = "aarch64")]
     /// Error creating the global interrupt controller: {0}
     VmCreateGIC(crate::arch::aarch64::gic::GicError),
+    #[cfg(target_arch = "aarch64")]
+    /// The host supports guest physical address spaces of up to {1} bits, {0} bits were requested.
+    IpaSizeNotSupported(u8, u8),
     /// Cannot open the VM file descriptor: {0}
     VmFd(kvm_ioctls::Error),
     #[cfg(target_arch = "x86_64")]
@@ -117,11 +139,22 @@ pub struct Vm {
     /// Additional capabilities that were specified in cpu template.
     pub kvm_cap_modifiers: Vec<KvmCapability>,
 
+    // Protects the guest memory and state from the host, if set.
+    confidential: Option<Arc<dyn ConfidentialVm>>,
+
+    // Maximum size of the dirty ring of a vCPU, in bytes, or 0 if KVM doesn't support them.
+    dirty_ring_max_size: u32,
+    // Set if the dirty pages are reported through the dirty rings.
+    dirty_ring: Option<Arc<DirtyRingLog>>,
+
     // X86 specific fields.
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     supported_cpuid: CpuId,
     #[cfg(target_arch = "x86_64")]
     msrs_to_save: MsrList,
+    // Value of the KVM clock recorded when the vCPUs were paused, or when the state was restored.
+    #[cfg(target_arch = "x86_64")]
+    paused_clock: Option<PausedClock>,
 
     // Arm specific fields.
     // On aarch64 we need to keep around the fd obtained by creating the VGIC device.
@@ -131,8 +164,24 @@ pub struct Vm {
 
 /// Contains Vm functions that are usable across CPU architectures
 impl Vm {
+    // Size of the dirty ring of a vCPU, in bytes, i.e. 4096 entries.
+    const DIRTY_RING_SIZE: u32 = 0x10000;
+
     /// Constructs a new `Vm` using the given `Kvm` instance.
     pub fn new(kvm_cap_modifiers: Vec<KvmCapability>) -> Result<Self, VmError> {
+        Self::with_confidential(kvm_cap_modifiers, None, None)
+    }
+
+    /// Constructs a new `Vm` whose memory and state are protected by the given confidential
+    /// context, if any.
+    ///
+    /// On aarch64, the guest physical address space has `ipa_size` bits if set, and the largest
+    /// size supported by the host otherwise. It is always `None` on x86_64.
+    pub fn with_confidential(
+        kvm_cap_modifiers: Vec<KvmCapability>,
+        confidential: Option<Box<dyn ConfidentialVm>>,
+        ipa_size: Option<u8>,
+    ) -> Result<Self, VmError> {
         let kvm = Kvm::new().map_err(VmError::Kvm)?;
 
         // Check that KVM has the correct version.
@@ -147,8 +196,26 @@ impl Vm {
         Self::check_capabilities(&kvm, &total_caps).map_err(VmError::Capabilities)?;
 
         let max_memslots = kvm.get_nr_memslots();
+        let dirty_ring_max_size =
+            u32::try_from(kvm.check_extension_raw(u64::from(Self::DIRTY_RING_CAP))).unwrap_or(0);
         // Create fd for interacting with kvm-vm specific functions.
-        let vm_fd = kvm.create_vm().map_err(VmError::VmFd)?;
+        let vm_fd = match (&confidential, ipa_size) {
+            (Some(confidential), _) => kvm.create_vm_with_type(confidential.vm_type()),
+            #[cfg(target_arch = "aarch64")]
+            (None, Some(ipa_size)) => {
+                let limit = Self::host_ipa_limit(&kvm);
+                if ipa_size > limit {
+                    return Err(VmError::IpaSizeNotSupported(ipa_size, limit));
+                }
+                kvm.create_vm_with_ipa_size(u32::from(ipa_size))
+            }
+            _ => kvm.create_vm(),
+        }
+        .map_err(VmError::VmFd)?;
+        let confidential: Option<Arc<dyn ConfidentialVm>> = confidential.map(Arc::from);
+        if let Some(confidential) = &confidential {
+            confidential.init(&vm_fd).map_err(VmError::Confidential)?;
+        }
 
         #[cfg(target_arch = "aarch64")]
         {
@@ -156,6 +223,9 @@ impl Vm {
                 fd: vm_fd,
                 max_memslots,
                 kvm_cap_modifiers,
+                confidential,
+                dirty_ring_max_size,
+                dirty_ring: None,
                 irqchip_handle: None,
             })
         }
@@ -171,12 +241,26 @@ impl Vm {
                 fd: vm_fd,
                 max_memslots,
                 kvm_cap_modifiers,
+                confidential,
+                dirty_ring_max_size,
+                dirty_ring: None,
                 supported_cpuid,
                 msrs_to_save,
+                paused_clock: None,
             })
         }
     }
 
+    /// Returns the largest guest physical address space size, in bits, supported by the host.
+    /// Hosts without `KVM_CAP_ARM_VM_IPA_SIZE` only support the default size.
+    #[cfg(target_arch = "aarch64")]
+    pub fn host_ipa_limit(kvm: &Kvm) -> u8 {
+        match u8::try_from(kvm.get_host_ipa_limit()) {
+            Ok(0) | Err(_) => crate::arch::aarch64::layout::DEFAULT_IPA_SIZE,
+            Ok(limit) => limit,
+        }
+    }
+
     fn combine_capabilities(kvm_cap_modifiers: &[KvmCapability]) -> Vec<u32> {
         let mut total_caps = Self::DEFAULT_CAPABILITIES.to_vec();
         for modifier in kvm_cap_modifiers.iter() {
@@ -215,7 +299,24 @@ impl Vm {
         if guest_mem.num_regions() > self.max_memslots {
             return Err(VmError::NotEnoughMemorySlots);
         }
-        self.set_kvm_memory_regions(guest_mem, track_dirty_pages)?;
+        match &self.confidential {
+            // The guest memory of a confidential VM is backed by private memory, in addition to
+            // the shared memory the host maps.
+            Some(confidential) => guest_mem
+                .iter()
+                .zip(0u32..)
+                .try_for_each(|(region, slot)| {
+                    confidential.set_memory_region(
+                        &self.fd,
+                        slot,
+                        region.start_addr().raw_value(),
+                        region.as_ptr() as u64,
+                        region.len(),
+                    )
+                })
+                .map_err(VmError::Confidential)?,
+            None => self.set_kvm_memory_regions(guest_mem, track_dirty_pages)?,
+        }
         #[cfg(target_arch = "x86_64")]
         self.fd
             .set_tss_address(u64_to_usize(crate::arch::x86_64::layout::KVM_TSS_ADDRESS))
@@ -253,14 +354,88 @@ impl Vm {
         Ok(())
     }
 
+    /// Enables the dirty rings of the vCPUs, if the given mode selects them. Must be called
+    /// before the vCPUs are created.
+    pub fn enable_dirty_ring(
+        &mut self,
+        guest_mem: &GuestMemoryMmap,
+        mode: DirtyTrackingMode,
+    ) -> Result<(), VmError> {
+        let supported = self.dirty_ring_max_size > 0;
+        match mode {
+            DirtyTrackingMode::Bitmap => return Ok(()),
+            DirtyTrackingMode::Auto if !supported => return Ok(()),
+            DirtyTrackingMode::Ring if !supported => return Err(VmError::DirtyRingNotSupported),
+            DirtyTrackingMode::Auto | DirtyTrackingMode::Ring => {}
+        }
+
+        let size = self.dirty_ring_max_size.min(Self::DIRTY_RING_SIZE);
+        let mut cap = kvm_enable_cap {
+            cap: Self::DIRTY_RING_CAP,
+            ..Default::default()
+        };
+        cap.args[0] = u64::from(size);
+        self.fd.enable_cap(&cap).map_err(VmError::EnableDirtyRing)?;
+
+        let entries =
+            size / u32::try_from(std::mem::size_of::<kvm_bindings::kvm_dirty_gfn>()).unwrap();
+        let log = DirtyRingLog::new(&self.fd, entries, guest_mem).map_err(VmError::DirtyRing)?;
+        self.dirty_ring = Some(Arc::new(log));
+        Ok(())
+    }
+
+    /// The dirty rings of the vCPUs, if the dirty pages are reported through them.
+    pub fn dirty_ring(&self) -> Option<&Arc<DirtyRingLog>> {
+        self.dirty_ring.as_ref()
+    }
+
     /// Gets a reference to the kvm file descriptor owned by this VM.
     pub fn fd(&self) -> &VmFd {
         &self.fd
     }
+
+    /// Whether the memory and state of the guest are protected from the host.
+    pub fn is_confidential(&self) -> bool {
+        self.confidential.is_some()
+    }
+
+    /// The confidential context of the VM, if the guest is protected from the host.
+    pub fn confidential(&self) -> Option<&Arc<dyn ConfidentialVm>> {
+        self.confidential.as_ref()
+    }
+
+    /// Encrypts and measures the initial guest payload, i.e. the given ranges of guest memory,
+    /// then completes the launch of a confidential guest. The host can't access the guest memory
+    /// and state anymore afterwards. Does nothing for other guests.
+    pub fn confidential_launch(
+        &self,
+        guest_mem: &GuestMemoryMmap,
+        payload: &[(GuestAddress, u64)],
+    ) -> Result<(), VmError> {
+        let Some(confidential) = &self.confidential else {
+            return Ok(());
+        };
+        for &(guest_addr, size) in payload {
+            let host_addr = guest_mem
+                .get_slice(guest_addr, u64_to_usize(size))
+                .map_err(|_| VmError::InvalidLaunchPayload(guest_addr.raw_value(), size))?
+                .ptr_guard()
+                .as_ptr() as u64;
+            confidential
+                .launch_update(&self.fd, guest_addr.raw_value(), host_addr, size)
+                .map_err(VmError::Confidential)?;
+        }
+        confidential
+            .launch_finish(&self.fd)
+            .map_err(VmError::Confidential)
+    }
 }
 
 #[cfg(target_arch = "aarch64")]
 impl Vm {
+    // On aarch64, KVM only offers the dirty rings with acquire/release semantics.
+    const DIRTY_RING_CAP: u32 = kvm_bindings::KVM_CAP_DIRTY_LOG_RING_ACQ_REL;
+
     const DEFAULT_CAPABILITIES: [u32; 7] = [
         kvm_bindings::KVM_CAP_IOEVENTFD,
         kvm_bindings::KVM_CAP_IRQFD,
@@ -325,6 +500,8 @@ pub struct VmState {
 
 #[cfg(target_arch = "x86_64")]
 impl Vm {
+    const DIRTY_RING_CAP: u32 = kvm_bindings::KVM_CAP_DIRTY_LOG_RING;
+
     const DEFAULT_CAPABILITIES: [u32; 14] = [
         kvm_bindings::KVM_CAP_IRQCHIP,
         kvm_bindings::KVM_CAP_IOEVENTFD,
@@ -369,6 +546,11 @@ impl Vm {
         self.fd
             .set_clock(&state.clock)
             .map_err(RestoreStateError::SetClock)?;
+        // Snapshots created by older versions don't record when the clock was saved.
+        self.paused_clock = (state.clock_realtime_ns != 0).then_some(PausedClock {
+            clock_ns: state.clock.clock,
+            realtime_ns: state.clock_realtime_ns,
+        });
         self.fd
             .set_irqchip(&state.pic_master)
             .map_err(RestoreStateError::SetIrqChipPicMaster)?;
@@ -381,6 +563,39 @@ impl Vm {
         Ok(())
     }
 
+    /// Records the value of the KVM clock, so that [`Vm::adjust_clock`] can advance it by the
+    /// time the vCPUs stay paused.
+    pub fn record_paused_clock(&mut self) -> Result<(), VmError> {
+        let clock = self.fd.get_clock().map_err(VmError::VmGetClock)?;
+        self.paused_clock = Some(PausedClock {
+            clock_ns: clock.clock,
+            realtime_ns: get_time_ns(ClockType::Real),
+        });
+        Ok(())
+    }
+
+    /// Advances the KVM clock by the host real time elapsed since it was recorded, so that the
+    /// wall time of the guest doesn't lag behind the host once the vCPUs are resumed.
+    ///
+    /// The KVM clock keeps running while the vCPUs are paused, so it is only advanced if it lags
+    /// behind, e.g. after a snapshot was loaded. It never goes backwards.
+    pub fn adjust_clock(&mut self) -> Result<(), VmError> {
+        let Some(paused_clock) = self.paused_clock.take() else {
+            return Ok(());
+        };
+        let elapsed_ns = get_time_ns(ClockType::Real).saturating_sub(paused_clock.realtime_ns);
+        let adjusted_ns = paused_clock.clock_ns.saturating_add(elapsed_ns);
+        let clock = self.fd.get_clock().map_err(VmError::VmGetClock)?;
+        if adjusted_ns > clock.clock {
+            let clock = kvm_clock_data {
+                clock: adjusted_ns,
+                ..Default::default()
+            };
+            self.fd.set_clock(&clock).map_err(VmError::VmSetClock)?;
+        }
+        Ok(())
+    }
+
     /// Creates the irq chip and an in-kernel device model for the PIT.
     pub fn setup_irqchip(&self) -> Result<(), VmError> {
         self.fd.create_irq_chip().map_err(VmError::VmSetup)?;
@@ -398,6 +613,7 @@ impl Vm {
         let pitstate = self.fd.get_pit2().map_err(VmError::VmGetPit2)?;
 
         let mut clock = self.fd.get_clock().map_err(VmError::VmGetClock)?;
+        let clock_realtime_ns = get_time_ns(ClockType::Real);
         // This bit is not accepted in SET_CLOCK, clear it.
         clock.flags &= !KVM_CLOCK_TSC_STABLE;
 
@@ -428,6 +644,7 @@ impl Vm {
         Ok(VmState {
             pitstate,
             clock,
+            clock_realtime_ns,
             pic_master,
             pic_slave,
             ioapic,
@@ -436,12 +653,22 @@ impl Vm {
     }
 }
 
+/// Value of the KVM clock at a given host real time.
+#[cfg(target_arch = "x86_64")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PausedClock {
+    clock_ns: u64,
+    realtime_ns: u64,
+}
+
 #[cfg(target_arch = "x86_64")]
 #[derive(Default, Deserialize, Serialize)]
 /// Structure holding VM kvm state.
 pub struct VmState {
     pitstate: kvm_pit_state2,
     clock: kvm_clock_data,
+    // Host real time when the clock was saved, in nanoseconds.
+    clock_realtime_ns: u64,
     // TODO: rename this field to adopt inclusive language once Linux updates it, too.
     pic_master: kvm_irqchip,
     // TODO: rename this field to adopt inclusive language once Linux updates it, too.
@@ -458,6 +685,7 @@ impl fmt::Debug for VmState {
         f.debug_struct("VmState")
             .field("pitstate", &self.pitstate)
             .field("clock", &self.clock)
+            .field("clock_realtime_ns", &self.clock_realtime_ns)
             .field("pic_master", &"?")
             .field("pic_slave", &"?")
             .field("ioapic", &"?")
@@ -471,6 +699,7 @@ pub(crate) mod tests {
     #[cfg(target_arch = "x86_64")]
     use crate::snapshot::Snapshot;
     use crate::utilities::test_utils::single_region_mem;
+    use crate::vstate::confidential::test_utils::{MockCall, MockConfidentialVm};
     use crate::vstate::memory::GuestMemoryMmap;
 
     // Auxiliary function being used throughout the tests.
@@ -489,6 +718,18 @@ pub(crate) mod tests {
         Vm::new(vec![]).unwrap();
     }
 
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn test_ipa_size() {
+        let limit = Vm::host_ipa_limit(&Kvm::new().unwrap());
+        assert!(limit >= crate::arch::aarch64::layout::DEFAULT_IPA_SIZE);
+        Vm::with_confidential(vec![], None, Some(limit)).unwrap();
+        assert_eq!(
+            Vm::with_confidential(vec![], None, Some(limit + 1)).unwrap_err(),
+            VmError::IpaSizeNotSupported(limit + 1, limit)
+        );
+    }
+
     #[test]
     fn test_combine_capabilities() {
         // Default caps for x86_64 and aarch64 both have KVM_CAP_IOEVENTFD and don't have
@@ -540,6 +781,39 @@ pub(crate) mod tests {
         vm.setup_irqchip().unwrap();
 
         vm.restore_state(&vm_state).unwrap();
+        assert_eq!(
+            vm.paused_clock,
+            Some(PausedClock {
+                clock_ns: vm_state.clock.clock,
+                realtime_ns: vm_state.clock_realtime_ns,
+            })
+        );
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_adjust_clock() {
+        let (mut vm, _mem) = setup_vm(0x1000);
+        vm.setup_irqchip().unwrap();
+
+        // Without a recorded clock, there is nothing to adjust.
+        vm.adjust_clock().unwrap();
+
+        // The clock kept running while paused, so it is not moved.
+        vm.record_paused_clock().unwrap();
+        let clock_ns = vm.fd.get_clock().unwrap().clock;
+        vm.adjust_clock().unwrap();
+        assert!(vm.fd.get_clock().unwrap().clock >= clock_ns);
+        assert_eq!(vm.paused_clock, None);
+
+        // A clock recorded a minute ago is advanced by a minute.
+        let clock_ns = vm.fd.get_clock().unwrap().clock;
+        vm.paused_clock = Some(PausedClock {
+            clock_ns,
+            realtime_ns: get_time_ns(ClockType::Real) - 60_000_000_000,
+        });
+        vm.adjust_clock().unwrap();
+        assert!(vm.fd.get_clock().unwrap().clock >= clock_ns + 60_000_000_000);
     }
 
     #[cfg(target_arch = "x86_64")]
@@ -602,4 +876,69 @@ pub(crate) mod tests {
             "Cannot set the memory regions: Invalid argument (os error 22)"
         );
     }
+
+    #[test]
+    fn test_confidential_launch() {
+        let confidential = MockConfidentialVm::default();
+        let calls = confidential.calls.clone();
+        let vm = Vm::with_confidential(vec![], Some(Box::new(confidential)), None).unwrap();
+        let gm = single_region_mem(0x10000);
+        vm.memory_init(&gm, false).unwrap();
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [MockCall::SetMemoryRegion(0, 0, 0x10000)]
+        );
+        calls.lock().unwrap().clear();
+
+        // Only the payload is measured, not the whole guest memory.
+        vm.confidential_launch(
+            &gm,
+            &[(GuestAddress(0), 0x1000), (GuestAddress(0x4000), 0x2000)],
+        )
+        .unwrap();
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                MockCall::LaunchUpdate(0, 0x1000),
+                MockCall::LaunchUpdate(0x4000, 0x2000),
+                MockCall::LaunchFinish,
+            ]
+        );
+
+        assert_eq!(
+            vm.confidential_launch(&gm, &[(GuestAddress(0xf000), 0x2000)])
+                .unwrap_err(),
+            VmError::InvalidLaunchPayload(0xf000, 0x2000)
+        );
+    }
+
+    #[test]
+    fn test_enable_dirty_ring() {
+        let gm = single_region_mem(0x10000);
+
+        let mut vm = Vm::new(vec![]).unwrap();
+        vm.enable_dirty_ring(&gm, DirtyTrackingMode::Bitmap)
+            .unwrap();
+        assert!(vm.dirty_ring().is_none());
+
+        let mut vm = Vm::new(vec![]).unwrap();
+        if vm.dirty_ring_max_size == 0 {
+            assert_eq!(
+                vm.enable_dirty_ring(&gm, DirtyTrackingMode::Ring),
+                Err(VmError::DirtyRingNotSupported)
+            );
+            vm.enable_dirty_ring(&gm, DirtyTrackingMode::Auto).unwrap();
+            assert!(vm.dirty_ring().is_none());
+            return;
+        }
+        vm.enable_dirty_ring(&gm, DirtyTrackingMode::Auto).unwrap();
+        vm.memory_init(&gm, true).unwrap();
+        let dirty_ring = vm.dirty_ring().unwrap().clone();
+
+        // The rings of the vCPUs are mapped as they are created.
+        let exit_evt = utils::eventfd::EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let _vcpu = crate::vstate::vcpu::Vcpu::new(0, &vm, exit_evt).unwrap();
+        let bitmap = dirty_ring.take_bitmap().unwrap();
+        assert_eq!(bitmap[&0], vec![0]);
+    }
 }
@@ -1,15 +1,19 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::io::{Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom};
 use std::thread;
 use std::time::Duration;
 
 use utils::tempfile::TempFile;
 use vmm::builder::{build_and_boot_microvm, build_microvm_from_snapshot};
-use vmm::persist::{self, snapshot_state_sanity_check, MicrovmState, MicrovmStateError, VmInfo};
+use vmm::persist::{
+    self, snapshot_state_sanity_check, verify_snapshot_digests, MicrovmState, MicrovmStateError,
+    SnapshotVerificationError, VmInfo,
+};
 use vmm::resources::VmResources;
 use vmm::seccomp_filters::get_empty_filters;
+use vmm::snapshot::manifest::{PageManifest, MANIFEST_PAGE_SIZE};
 use vmm::snapshot::Snapshot;
 use vmm::utilities::mock_resources::{MockVmResources, NOISY_KERNEL_IMAGE};
 #[cfg(target_arch = "x86_64")]
@@ -19,18 +23,19 @@ use vmm::vmm_config::instance_info::{InstanceInfo, VmState};
 use vmm::vmm_config::machine_config::HugePageConfig;
 use vmm::vmm_config::snapshot::{CreateSnapshotParams, SnapshotType};
 use vmm::{DumpCpuConfigError, EventManager, FcExitCode};
+use xxhash_rust::xxh3::xxh3_64;
 
 #[test]
 fn test_build_and_boot_microvm() {
     // Error case: no boot source configured.
     {
-        let resources: VmResources = MockVmResources::new().into();
+        let mut resources: VmResources = MockVmResources::new().into();
         let mut event_manager = EventManager::new().unwrap();
         let empty_seccomp_filters = get_empty_filters();
 
         let vmm_ret = build_and_boot_microvm(
             &InstanceInfo::default(),
-            &resources,
+            &mut resources,
             &mut event_manager,
             &empty_seccomp_filters,
         );
@@ -171,6 +176,7 @@ fn test_disallow_dump_cpu_config_without_pausing() {
 fn verify_create_snapshot(is_diff: bool) -> (TempFile, TempFile) {
     let snapshot_file = TempFile::new().unwrap();
     let memory_file = TempFile::new().unwrap();
+    let manifest_file = TempFile::new().unwrap();
 
     let (vmm, _) = create_vmm(Some(NOISY_KERNEL_IMAGE), is_diff, true);
 
@@ -189,6 +195,10 @@ fn verify_create_snapshot(is_diff: bool) -> (TempFile, TempFile) {
         snapshot_type,
         snapshot_path: snapshot_file.as_path().to_path_buf(),
         mem_file_path: memory_file.as_path().to_path_buf(),
+        encryption: None,
+        manifest_path: Some(manifest_file.as_path().to_path_buf()),
+        sparse: false,
+        snapshot_version: None,
     };
     let vm_info = VmInfo {
         mem_size_mib: 1u64,
@@ -215,13 +225,32 @@ fn verify_create_snapshot(is_diff: bool) -> (TempFile, TempFile) {
     // The default vmm has no devices and one vCPU.
     assert_eq!(restored_microvm_state.device_states.block_devices.len(), 0);
     assert_eq!(restored_microvm_state.device_states.net_devices.len(), 0);
-    assert!(restored_microvm_state.device_states.vsock_device.is_none());
+    assert!(restored_microvm_state
+        .device_states
+        .vsock_devices
+        .is_empty());
     assert_eq!(restored_microvm_state.vcpu_states.len(), 1);
 
+    // The page manifest matches the contents of the memory file.
+    let manifest = PageManifest::load(&mut manifest_file.as_file()).unwrap();
+    let mut memory = Vec::new();
+    memory_file.as_file().read_to_end(&mut memory).unwrap();
+    if !is_diff {
+        assert_eq!(manifest.pages.len(), memory.len() / MANIFEST_PAGE_SIZE);
+    }
+    for page in manifest.pages {
+        let offset = usize::try_from(page.offset).unwrap();
+        assert_eq!(
+            page.hash,
+            xxh3_64(&memory[offset..offset + MANIFEST_PAGE_SIZE])
+        );
+    }
+    memory_file.as_file().seek(SeekFrom::Start(0)).unwrap();
+
     (snapshot_file, memory_file)
 }
 
-fn verify_load_snapshot(snapshot_file: TempFile, memory_file: TempFile) {
+fn verify_load_snapshot(snapshot_file: TempFile, memory_file: TempFile, is_diff: bool) {
     use vmm::vstate::memory::{GuestMemoryExtension, GuestMemoryMmap};
 
     let mut event_manager = EventManager::new().unwrap();
@@ -231,8 +260,19 @@ fn verify_load_snapshot(snapshot_file: TempFile, memory_file: TempFile) {
     let snapshot_file_metadata = snapshot_file.as_file().metadata().unwrap();
     let snapshot_len = snapshot_file_metadata.len() as usize;
     snapshot_file.as_file().seek(SeekFrom::Start(0)).unwrap();
-    let (microvm_state, _) =
+    let (mut microvm_state, _) =
         Snapshot::load::<_, MicrovmState>(&mut snapshot_file.as_file(), snapshot_len).unwrap();
+    // The snapshot sections match the digests embedded at creation time. A diff snapshot only
+    // has the digest of the microVM state.
+    if is_diff {
+        assert!(matches!(
+            verify_snapshot_digests(&mut microvm_state, Some(memory_file.as_path())),
+            Err(SnapshotVerificationError::NoMemoryDigests)
+        ));
+        verify_snapshot_digests(&mut microvm_state, None).unwrap();
+    } else {
+        verify_snapshot_digests(&mut microvm_state, Some(memory_file.as_path())).unwrap();
+    }
     let mem = GuestMemoryMmap::from_state(
         Some(memory_file.as_file()),
         &microvm_state.memory_state,
@@ -266,7 +306,7 @@ fn test_create_and_load_snapshot() {
     // that a microVM can be built with no errors from given snapshot.
     // It does _not_ verify that the guest is actually restored properly. We're using
     // python integration tests for that.
-    verify_load_snapshot(snapshot_file, memory_file);
+    verify_load_snapshot(snapshot_file, memory_file, true);
 
     // Create full snapshot.
     let (snapshot_file, memory_file) = verify_create_snapshot(false);
@@ -274,7 +314,7 @@ fn test_create_and_load_snapshot() {
     // that a microVM can be built with no errors from given snapshot.
     // It does _not_ verify that the guest is actually restored properly. We're using
     // python integration tests for that.
-    verify_load_snapshot(snapshot_file, memory_file);
+    verify_load_snapshot(snapshot_file, memory_file, false);
 }
 
 #[test]
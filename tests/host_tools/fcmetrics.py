@@ -72,6 +72,38 @@ def validate_fc_metrics(metrics):
         "max_us",
         "sum_us",
     ]
+    latency_hist_metrics_fields = [
+        "1",
+        "2",
+        "5",
+        "10",
+        "20",
+        "50",
+        "100",
+        "200",
+        "500",
+        "1000",
+        "2000",
+        "5000",
+        "10000",
+        "20000",
+        "50000",
+        "100000",
+        "inf",
+    ]
+    queue_depth_hist_metrics_fields = [
+        "0",
+        "1",
+        "2",
+        "4",
+        "8",
+        "16",
+        "32",
+        "64",
+        "128",
+        "256",
+        "inf",
+    ]
     block_metrics = [
         "activate_fails",
         "cfg_fails",
@@ -93,11 +125,17 @@ def validate_fc_metrics(metrics):
         "remaining_reqs_count",
         {"read_agg": latency_agg_metrics_fields},
         {"write_agg": latency_agg_metrics_fields},
+        {"read_latency_hist": latency_hist_metrics_fields},
+        {"write_latency_hist": latency_hist_metrics_fields},
+        {"queue_depth_hist": queue_depth_hist_metrics_fields},
     ]
     net_metrics = [
         "activate_fails",
         "cfg_fails",
         "mac_address_updates",
+        "link_state_updates",
+        "ctrl_queue_event_count",
+        "ctrl_fails",
         "no_rx_avail_buffer",
         "no_tx_avail_buffer",
         "event_fails",
@@ -110,6 +148,7 @@ def validate_fc_metrics(metrics):
         "rx_packets_count",
         "rx_fails",
         "rx_count",
+        "rx_filtered_frames",
         "tap_read_fails",
         "tap_write_fails",
         "tx_bytes_count",
@@ -122,8 +161,23 @@ def validate_fc_metrics(metrics):
         "tx_rate_limiter_event_count",
         "tx_rate_limiter_throttled",
         "tx_spoofed_mac_count",
+        "tx_peer_dropped_frames",
+        "tx_dropped_malformed",
+        "tx_dropped_tap_full",
+        "tx_mmds_intercepted_frames",
+        "tx_dhcp_intercepted_frames",
+        "rx_dhcp_frames",
+        "tx_port_forward_intercepted_frames",
+        "rx_port_forward_frames",
+        "rx_tso4_frames",
+        "rx_tso6_frames",
+        "tx_tso4_frames",
+        "tx_tso6_frames",
         "tx_remaining_reqs_count",
+        "xdp_fallbacks",
         {"tap_write_agg": latency_agg_metrics_fields},
+        {"tap_write_latency_hist": latency_hist_metrics_fields},
+        {"tx_queue_depth_hist": queue_depth_hist_metrics_fields},
     ]
     firecracker_metrics = {
         "utc_timestamp_ms": "",
@@ -140,6 +194,9 @@ def validate_fc_metrics(metrics):
             "stats_update_fails",
             "deflate_count",
             "event_fails",
+            "pressure_inflate_count",
+            "pressure_deflate_count",
+            "inflate_ring_overlaps",
         ],
         "block": block_metrics,
         "deprecated_api": [
@@ -190,6 +247,10 @@ def validate_fc_metrics(metrics):
             "tx_frames",
             "connections_created",
             "connections_destroyed",
+            "data_file_reloads",
+            "data_file_too_large",
+            "data_file_parse_errors",
+            "data_file_fails",
         ],
         "net": net_metrics,
         "patch_api_requests": [
@@ -232,6 +293,7 @@ def validate_fc_metrics(metrics):
             "exit_io_out",
             "exit_mmio_read",
             "exit_mmio_write",
+            "exit_dirty_ring_full",
             "failures",
             {"exit_io_in_agg": latency_agg_metrics_fields},
             {"exit_io_out_agg": latency_agg_metrics_fields},
@@ -241,6 +303,15 @@ def validate_fc_metrics(metrics):
         "vmm": [
             "device_events",
             "panic_count",
+            "ksm_merging_pages",
+            "ksm_rmap_items",
+            "event_loop_stalls",
+            "shutdown_drain_timeouts",
+            "dma_range_violations",
+            "secret_hardening_fails",
+            "cgroup_pressure_events",
+            "guest_memory_faults",
+            "lifecycle_notify_fails",
         ],
         "uart": [
             "error_count",
@@ -258,6 +329,7 @@ def validate_fc_metrics(metrics):
             "sigpipe",
             "sighup",
             "sigill",
+            "sigterm",
         ],
         "vsock": [
             "activate_fails",
@@ -289,6 +361,30 @@ def validate_fc_metrics(metrics):
             "host_rng_fails",
             "entropy_rate_limiter_throttled",
             "rate_limiter_event_count",
+            {"entropy_latency_hist": latency_hist_metrics_fields},
+            {"queue_depth_hist": queue_depth_hist_metrics_fields},
+        ],
+        "crypto": [
+            "activate_fails",
+            "cfg_fails",
+            "event_fails",
+            "sessions_created",
+            "sessions_destroyed",
+            "session_fails",
+            "crypto_request_count",
+            "crypto_request_fails",
+            "crypto_bytes",
+            "crypto_rate_limiter_throttled",
+            "rate_limiter_event_count",
+            {"crypto_latency_hist": latency_hist_metrics_fields},
+            {"queue_depth_hist": queue_depth_hist_metrics_fields},
+        ],
+        "tpm": [
+            "command_count",
+            "command_fails",
+            "backend_fails",
+            "invalid_access_count",
+            "event_fails",
         ],
     }
 
@@ -319,12 +415,20 @@ def validate_fc_metrics(metrics):
                 "init_time_us",
                 "activate_time_us",
                 "config_change_time_us",
+                "backend_disconnects",
+                "reconnects",
+                "reconnect_fails",
             ]
             vhost_user_devices.append(metrics_name)
         if metrics_name.startswith("block_"):
             firecracker_metrics[metrics_name] = block_metrics
         if metrics_name.startswith("net_"):
             firecracker_metrics[metrics_name] = net_metrics
+        if metrics_name.startswith("queues_"):
+            firecracker_metrics[metrics_name] = {
+                queue_name: ["kicks", "descriptors", "backlog"]
+                for queue_name in metrics[metrics_name]
+            }
 
     firecracker_metrics_schema = create_metrics_schema_objects(firecracker_metrics)
 
@@ -402,6 +506,16 @@ class FcDeviceMetrics:
                         if metrics_name not in metrics_calculated:
                             metrics_calculated[metrics_name] = 0
                         metrics_calculated[metrics_name] += metric_value
+                    elif (
+                        isinstance(metric_value, dict) and "sum_us" not in metric_value
+                    ):
+                        # this is for SharedHistogramMetric metrics type
+                        if metrics_name not in metrics_calculated:
+                            metrics_calculated[metrics_name] = dict.fromkeys(
+                                metric_value, 0
+                            )
+                        for bucket, count in metric_value.items():
+                            metrics_calculated[metrics_name][bucket] += count
                     elif isinstance(metric_value, dict):
                         # this is for LatencyAggregateMetrics metrics type
                         if metrics_name not in metrics_calculated:
@@ -358,6 +358,49 @@ def test_flush(uvm_plain_rw, io_engine):
     assert fc_metrics["block"]["flush_count"] > 0
 
 
+def test_overlay_flush(uvm_plain_any):
+    """
+    Verify copy-ups to an overlay and its flushes pass the seccomp filter.
+    """
+    test_microvm = uvm_plain_any
+    test_microvm.spawn()
+    test_microvm.basic_config(vcpu_count=1)
+    test_microvm.add_net_iface()
+
+    base = drive_tools.FilesystemFile(
+        os.path.join(test_microvm.fsfiles, "base"), size=2
+    )
+    base_hash = utils.run_cmd(f"md5sum {base.path}").stdout
+    overlay_path = os.path.join(test_microvm.fsfiles, "overlay")
+    open(overlay_path, "wb").close()
+    test_microvm.api.drive.put(
+        drive_id="scratch",
+        path_on_host=test_microvm.create_jailed_resource(base.path),
+        overlay_path=test_microvm.create_jailed_resource(overlay_path),
+        is_root_device=False,
+        is_read_only=False,
+        cache_type="Writeback",
+    )
+    test_microvm.start()
+
+    # Writing a part of a cluster copies it up from the base image first, and
+    # the sync flushes the overlay.
+    cmd = (
+        "dd if=/dev/urandom of=/tmp/data bs=4096 count=1 && "
+        "dd if=/tmp/data of=/dev/vdb bs=4096 seek=1 oflag=direct && sync"
+    )
+    rc, _, stderr = test_microvm.ssh.run(cmd)
+    assert rc == 0, stderr
+    cmd = "dd if=/dev/vdb bs=4096 skip=1 count=1 iflag=direct | cmp - /tmp/data"
+    rc, _, stderr = test_microvm.ssh.run(cmd)
+    assert rc == 0, stderr
+
+    fc_metrics = test_microvm.flush_metrics()
+    assert fc_metrics["block"]["flush_count"] > 0
+    assert os.path.getsize(overlay_path) > 0
+    assert utils.run_cmd(f"md5sum {base.path}").stdout == base_hash
+
+
 def _check_block_size(ssh_connection, dev_path, size):
     _, stdout, stderr = ssh_connection.run("blockdev --getsize64 {}".format(dev_path))
     assert stderr == ""
@@ -2,6 +2,8 @@
 # SPDX-License-Identifier: Apache-2.0
 """Basic tests scenarios for snapshot save/restore."""
 
+import platform
+
 import pytest
 
 
@@ -130,3 +132,27 @@ def test_pause_resume_preboot(uvm_nano):
     # Try to resume microvm when not running, it must fail.
     with pytest.raises(RuntimeError, match=expected_err):
         basevm.api.vm.patch(state="Resumed")
+
+
+@pytest.mark.skipif(
+    platform.machine() != "x86_64",
+    reason="Adjusting the guest clock on resume is only supported on x86_64.",
+)
+def test_pause_resume_adjust_clock(uvm_nano):
+    """
+    Test resuming with the guest clock adjustment while seccomp is enabled.
+    """
+    microvm = uvm_nano
+    microvm.add_net_iface()
+    microvm.start()
+    microvm.wait_for_up()
+
+    microvm.api.vm.patch(state="Paused")
+
+    # The clock adjustment runs on the VMM thread, so it must pass the
+    # default seccomp filter.
+    microvm.api.vm.patch(state="Resumed", resume_time_handling="adjust")
+
+    # Verify guest is active again.
+    microvm.wait_for_up()
+    assert "Running" in microvm.api.describe.get().text
@@ -501,6 +501,49 @@ def test_diff_snapshot_overlay(guest_kernel, rootfs, microvm_factory):
     new_vm.wait_for_up()
 
 
+@pytest.mark.skipif(
+    global_props.host_linux_version_tpl < (6, 1),
+    reason="The KVM dirty rings are only supported on hosts with Linux 6.1 or later.",
+)
+def test_diff_snapshot_dirty_ring(guest_kernel, rootfs, microvm_factory):
+    """
+    Tests that the dirty rings are harvested under the default seccomp filter,
+    both when a vCPU fills its ring and when creating a diff snapshot.
+    """
+    basevm = microvm_factory.build(guest_kernel, rootfs)
+    basevm.spawn()
+    basevm.basic_config(track_dirty_pages=True)
+    basevm.api.machine_config.patch(dirty_tracking_mode="Ring")
+    basevm.add_net_iface()
+    basevm.start()
+    basevm.wait_for_up()
+
+    base_snapshot = basevm.snapshot_diff()
+    basevm.resume()
+    shutil.copyfile(base_snapshot.mem, Path(basevm.chroot()) / "mem.old")
+    basevm.flush_metrics()
+
+    # Dirty more pages than the 4096 entries of a dirty ring, so that the vCPU
+    # exits to Firecracker to harvest it.
+    rc, _, stderr = basevm.ssh.run(
+        "dd if=/dev/urandom of=/dev/shm/dirty bs=1M count=64 && rm /dev/shm/dirty"
+    )
+    assert rc == 0, stderr
+    assert basevm.flush_metrics()["vcpu"]["exit_dirty_ring_full"] > 0
+
+    merged_snapshot = basevm.snapshot_diff()
+    assert not filecmp.cmp(
+        merged_snapshot.mem, Path(basevm.chroot()) / "mem.old", shallow=False
+    )
+
+    new_vm = microvm_factory.build()
+    new_vm.spawn()
+    new_vm.restore_from_snapshot(merged_snapshot, resume=True)
+
+    # Check that the restored VM works
+    new_vm.wait_for_up()
+
+
 def test_snapshot_overwrite_self(guest_kernel, rootfs, microvm_factory):
     """Tests that if we try to take a snapshot that would overwrite the
     very file from which the current VM is stored, nothing happens.